@@ -0,0 +1,4 @@
+// Migrations are embedded with sqlx::migrate!, which cargo does not know to rebuild for
+fn main() {
+    println!("cargo:rerun-if-changed=migrations");
+}
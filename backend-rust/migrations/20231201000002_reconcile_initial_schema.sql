@@ -0,0 +1,76 @@
+-- The initial schema predates the MT5 integration: it still has the original column names and
+-- leaves out columns the next migrations and the API already rely on, so a fresh database could
+-- not migrate past it. Databases that were brought up to date by hand already match, so every
+-- step checks first and this only changes anything on a fresh one.
+CREATE OR REPLACE FUNCTION pg_temp.rename_column(tbl TEXT, old_name TEXT, new_name TEXT) RETURNS VOID AS $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM information_schema.columns WHERE table_name = tbl AND column_name = old_name)
+        AND NOT EXISTS (SELECT 1 FROM information_schema.columns WHERE table_name = tbl AND column_name = new_name)
+    THEN
+        EXECUTE format('ALTER TABLE %I RENAME COLUMN %I TO %I', tbl, old_name, new_name);
+    END IF;
+END;
+$$ LANGUAGE plpgsql;
+
+SELECT pg_temp.rename_column('trades', 'quantity', 'volume');
+SELECT pg_temp.rename_column('subscriptions', 'plan', 'plan_name');
+SELECT pg_temp.rename_column('broker_connections', 'account_id', 'login');
+SELECT pg_temp.rename_column('broker_connections', 'server_url', 'server');
+SELECT pg_temp.rename_column('broker_connections', 'last_connected_at', 'last_test_at');
+
+ALTER TABLE users
+    ALTER COLUMN is_active SET DEFAULT true, ALTER COLUMN is_active SET NOT NULL,
+    ALTER COLUMN is_superuser SET DEFAULT false, ALTER COLUMN is_superuser SET NOT NULL,
+    ALTER COLUMN subscription_plan SET DEFAULT 'free', ALTER COLUMN subscription_plan SET NOT NULL,
+    ALTER COLUMN created_at SET DEFAULT NOW(), ALTER COLUMN created_at SET NOT NULL,
+    ALTER COLUMN updated_at SET DEFAULT NOW(), ALTER COLUMN updated_at SET NOT NULL;
+
+ALTER TABLE subscriptions
+    ADD COLUMN IF NOT EXISTS stripe_customer_id VARCHAR(255),
+    ALTER COLUMN status SET DEFAULT 'active', ALTER COLUMN status SET NOT NULL,
+    ALTER COLUMN current_period_start SET DEFAULT NOW(), ALTER COLUMN current_period_start SET NOT NULL,
+    ALTER COLUMN current_period_end SET DEFAULT NOW(), ALTER COLUMN current_period_end SET NOT NULL,
+    ALTER COLUMN created_at SET DEFAULT NOW(), ALTER COLUMN created_at SET NOT NULL,
+    ALTER COLUMN updated_at SET DEFAULT NOW(), ALTER COLUMN updated_at SET NOT NULL;
+
+-- Connections are now identified by server and login, either of which may be missing
+ALTER TABLE broker_connections
+    ADD COLUMN IF NOT EXISTS name VARCHAR(255) NOT NULL DEFAULT '',
+    ADD COLUMN IF NOT EXISTS is_demo BOOLEAN NOT NULL DEFAULT false,
+    ADD COLUMN IF NOT EXISTS last_test_status VARCHAR(50),
+    ALTER COLUMN login DROP NOT NULL,
+    ALTER COLUMN api_key SET DEFAULT '', ALTER COLUMN api_key SET NOT NULL,
+    ALTER COLUMN api_secret SET DEFAULT '', ALTER COLUMN api_secret SET NOT NULL,
+    ALTER COLUMN is_active SET DEFAULT true, ALTER COLUMN is_active SET NOT NULL,
+    ALTER COLUMN created_at SET DEFAULT NOW(), ALTER COLUMN created_at SET NOT NULL,
+    ALTER COLUMN updated_at SET DEFAULT NOW(), ALTER COLUMN updated_at SET NOT NULL;
+
+-- Robots created without a strategy run the default one
+ALTER TABLE trading_robots
+    ALTER COLUMN strategy DROP NOT NULL,
+    ALTER COLUMN status SET DEFAULT 'inactive', ALTER COLUMN status SET NOT NULL,
+    ALTER COLUMN risk_config SET DEFAULT '{}', ALTER COLUMN risk_config SET NOT NULL,
+    ALTER COLUMN total_trades SET DEFAULT 0, ALTER COLUMN total_trades SET NOT NULL,
+    ALTER COLUMN created_at SET DEFAULT NOW(), ALTER COLUMN created_at SET NOT NULL,
+    ALTER COLUMN updated_at SET DEFAULT NOW(), ALTER COLUMN updated_at SET NOT NULL;
+
+-- The broker's costs and trade id, and the model's confidence in the signal; 0 when unknown
+ALTER TABLE trades
+    ADD COLUMN IF NOT EXISTS commission DECIMAL(18,8) NOT NULL DEFAULT 0,
+    ADD COLUMN IF NOT EXISTS swap DECIMAL(18,8) NOT NULL DEFAULT 0,
+    ADD COLUMN IF NOT EXISTS ai_confidence DECIMAL(18,8) NOT NULL DEFAULT 0,
+    ADD COLUMN IF NOT EXISTS broker_trade_id VARCHAR(255),
+    ALTER COLUMN status SET DEFAULT 'open', ALTER COLUMN status SET NOT NULL,
+    ALTER COLUMN ai_reasoning SET DEFAULT '',
+    ALTER COLUMN opened_at SET DEFAULT NOW(), ALTER COLUMN opened_at SET NOT NULL,
+    ALTER COLUMN created_at SET DEFAULT NOW(), ALTER COLUMN created_at SET NOT NULL,
+    ALTER COLUMN updated_at SET DEFAULT NOW(), ALTER COLUMN updated_at SET NOT NULL;
+
+ALTER TABLE trading_sessions
+    ALTER COLUMN status SET DEFAULT 'active', ALTER COLUMN status SET NOT NULL,
+    ALTER COLUMN total_trades SET DEFAULT 0, ALTER COLUMN total_trades SET NOT NULL,
+    ALTER COLUMN winning_trades SET DEFAULT 0, ALTER COLUMN winning_trades SET NOT NULL,
+    ALTER COLUMN total_profit SET DEFAULT 0, ALTER COLUMN total_profit SET NOT NULL,
+    ALTER COLUMN started_at SET DEFAULT NOW(), ALTER COLUMN started_at SET NOT NULL,
+    ALTER COLUMN created_at SET DEFAULT NOW(), ALTER COLUMN created_at SET NOT NULL,
+    ALTER COLUMN updated_at SET DEFAULT NOW(), ALTER COLUMN updated_at SET NOT NULL;
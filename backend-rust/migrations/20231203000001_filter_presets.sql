@@ -0,0 +1,14 @@
+-- Saved statistics filters per user
+CREATE TABLE filter_presets (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name VARCHAR(100) NOT NULL,
+    filter JSONB NOT NULL DEFAULT '{}',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (user_id, name)
+);
+
+CREATE INDEX idx_filter_presets_user_id ON filter_presets(user_id);
+
+CREATE TRIGGER update_filter_presets_updated_at BEFORE UPDATE ON filter_presets FOR EACH ROW EXECUTE FUNCTION update_updated_at_column();
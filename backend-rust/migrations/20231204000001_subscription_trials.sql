@@ -0,0 +1,8 @@
+-- One free trial per user, ever
+ALTER TABLE users ADD COLUMN trial_used BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE subscriptions ADD COLUMN trial_end TIMESTAMPTZ;
+-- Smallest "days left" reminder already emailed (3, then 1)
+ALTER TABLE subscriptions ADD COLUMN trial_reminder_days INTEGER;
+
+CREATE INDEX idx_subscriptions_trialing ON subscriptions(trial_end) WHERE status = 'trialing';
@@ -0,0 +1,25 @@
+-- Robots trade through a specific broker connection
+ALTER TABLE trading_robots ADD COLUMN broker_connection_id UUID REFERENCES broker_connections(id) ON DELETE SET NULL;
+
+-- Demo trades are kept out of live statistics and plan limits
+ALTER TABLE trades ADD COLUMN is_demo BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Bind existing robots where the owner has exactly one broker connection
+UPDATE trading_robots r
+SET broker_connection_id = c.id
+FROM (
+    SELECT user_id, MIN(id::text)::uuid AS id
+    FROM broker_connections
+    GROUP BY user_id
+    HAVING COUNT(*) = 1
+) c
+WHERE r.user_id = c.user_id AND r.broker_connection_id IS NULL;
+
+-- Backfill trades through their robot's connection
+UPDATE trades t
+SET is_demo = bc.is_demo
+FROM trading_robots r
+JOIN broker_connections bc ON bc.id = r.broker_connection_id
+WHERE t.robot_id = r.id AND bc.is_demo IS NOT NULL;
+
+CREATE INDEX idx_trades_user_live ON trades(user_id, opened_at) WHERE is_demo = FALSE;
@@ -0,0 +1,11 @@
+-- Per-robot activity log (lifecycle events, recovery, broker problems)
+CREATE TABLE robot_logs (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    robot_id UUID NOT NULL REFERENCES trading_robots(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    level VARCHAR(20) NOT NULL DEFAULT 'info',
+    message TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_robot_logs_robot_id ON robot_logs(robot_id, created_at DESC);
@@ -0,0 +1,2 @@
+-- Records who enforced SL/TP for each trade: broker, platform or both
+ALTER TABLE trades ADD COLUMN stop_management VARCHAR(16) NOT NULL DEFAULT 'broker';
@@ -0,0 +1,30 @@
+-- Runtime feature flags: on for everyone, for listed users, or for a stable percentage of users
+CREATE TABLE feature_flags (
+    key VARCHAR(100) PRIMARY KEY,
+    description TEXT NOT NULL DEFAULT '',
+    enabled BOOLEAN NOT NULL DEFAULT FALSE,
+    enabled_user_ids UUID[] NOT NULL DEFAULT '{}',
+    rollout_percentage SMALLINT NOT NULL DEFAULT 0 CHECK (rollout_percentage BETWEEN 0 AND 100),
+    updated_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TRIGGER update_feature_flags_updated_at BEFORE UPDATE ON feature_flags FOR EACH ROW EXECUTE FUNCTION update_updated_at_column();
+
+-- Every admin change, with the flag before and after
+CREATE TABLE feature_flag_audit (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    flag_key VARCHAR(100) NOT NULL,
+    changed_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    previous JSONB,
+    current JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_feature_flag_audit_key ON feature_flag_audit(flag_key, created_at);
+
+-- Features that already shipped stay on; the flags make them switchable at runtime
+INSERT INTO feature_flags (key, description, enabled) VALUES
+    ('pro_trial', 'One-time 14-day Pro trial signup', TRUE),
+    ('batch_close', 'Closing several open trades in one request', TRUE);
@@ -0,0 +1,12 @@
+-- Users can opt out of onboarding emails with the token sent in each of them
+ALTER TABLE users ADD COLUMN onboarding_emails BOOLEAN NOT NULL DEFAULT TRUE;
+ALTER TABLE users ADD COLUMN unsubscribe_token UUID NOT NULL DEFAULT uuid_generate_v4();
+CREATE UNIQUE INDEX idx_users_unsubscribe_token ON users(unsubscribe_token);
+
+-- One row per onboarding email a user has been sent, so restarts never send a step twice
+CREATE TABLE onboarding_emails (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    step VARCHAR(50) NOT NULL,
+    sent_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, step)
+);
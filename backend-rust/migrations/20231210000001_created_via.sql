@@ -0,0 +1,3 @@
+-- Client (X-Client header, e.g. "web/1.4.0") that created the row
+ALTER TABLE trading_robots ADD COLUMN created_via VARCHAR(100) NOT NULL DEFAULT 'unknown';
+ALTER TABLE trades ADD COLUMN created_via VARCHAR(100) NOT NULL DEFAULT 'unknown';
@@ -0,0 +1,17 @@
+-- Admin integrity checks and recalculations of denormalized trade stats, kept for audit
+CREATE TABLE integrity_runs (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    kind VARCHAR(20) NOT NULL CHECK (kind IN ('check', 'recalculate')),
+    -- NULL runs across every user
+    user_id UUID REFERENCES users(id) ON DELETE CASCADE,
+    requested_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    status VARCHAR(20) NOT NULL DEFAULT 'running' CHECK (status IN ('running', 'completed', 'failed')),
+    robots_total INTEGER NOT NULL DEFAULT 0,
+    robots_processed INTEGER NOT NULL DEFAULT 0,
+    report JSONB,
+    error TEXT,
+    started_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    finished_at TIMESTAMPTZ
+);
+
+CREATE INDEX idx_integrity_runs_started_at ON integrity_runs(started_at);
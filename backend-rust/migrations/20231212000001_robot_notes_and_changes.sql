@@ -0,0 +1,15 @@
+-- Free-text notes on robots and a journal of their configuration changes
+ALTER TABLE trading_robots ADD COLUMN notes TEXT;
+
+CREATE TABLE robot_changes (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    robot_id UUID NOT NULL REFERENCES trading_robots(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    actor_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    -- Changed field -> {"old": ..., "new": ...}; risk_config keys appear as "risk_config.<key>"
+    changes JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_robot_changes_robot_id ON robot_changes(robot_id, created_at);
+CREATE INDEX idx_robot_changes_user_id ON robot_changes(user_id, created_at);
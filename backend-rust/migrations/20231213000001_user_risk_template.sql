@@ -0,0 +1,2 @@
+-- Risk settings a user's new robots start from, merged over the platform defaults
+ALTER TABLE users ADD COLUMN risk_template JSONB;
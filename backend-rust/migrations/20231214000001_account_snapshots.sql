@@ -0,0 +1,17 @@
+-- Hourly broker account snapshots, compacted to one row per day after a week
+CREATE TABLE account_snapshots (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    connection_id UUID NOT NULL REFERENCES broker_connections(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    granularity VARCHAR(10) NOT NULL CHECK (granularity IN ('hour', 'day')),
+    balance DOUBLE PRECISION NOT NULL,
+    equity DOUBLE PRECISION NOT NULL,
+    margin DOUBLE PRECISION NOT NULL,
+    free_margin DOUBLE PRECISION NOT NULL,
+    currency VARCHAR(10) NOT NULL,
+    -- Start of the hour or day the snapshot stands for
+    captured_at TIMESTAMPTZ NOT NULL,
+    UNIQUE (connection_id, granularity, captured_at)
+);
+
+CREATE INDEX idx_account_snapshots_granularity_captured_at ON account_snapshots(granularity, captured_at);
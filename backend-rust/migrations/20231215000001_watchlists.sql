@@ -0,0 +1,10 @@
+-- A user's watchlist: symbols shown with live quotes on the dashboard, in display order
+CREATE TABLE watchlist_symbols (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    symbol VARCHAR(20) NOT NULL,
+    position INTEGER NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, symbol)
+);
+
+CREATE INDEX idx_watchlist_symbols_user_id ON watchlist_symbols(user_id, position);
@@ -0,0 +1,6 @@
+-- Case-insensitive substring search over a trade's AI reasoning, symbol and broker ticket.
+-- The indexed expression must match TRADE_SEARCH_DOCUMENT in models/trade.rs exactly.
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX idx_trades_search ON trades
+    USING GIN ((COALESCE(ai_reasoning, '') || ' ' || symbol || ' ' || COALESCE(broker_trade_id, '')) gin_trgm_ops);
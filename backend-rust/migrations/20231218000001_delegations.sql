@@ -0,0 +1,29 @@
+-- Read-only access to a user's trades and statistics granted to another account (e.g. an accountant).
+-- delegate_id is set once the invitation is accepted; revoked rows are kept for the audit trail.
+CREATE TABLE delegations (
+    id UUID PRIMARY KEY,
+    grantor_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    delegate_email VARCHAR(255) NOT NULL,
+    delegate_id UUID REFERENCES users(id) ON DELETE CASCADE,
+    scope VARCHAR(20) NOT NULL DEFAULT 'read_only',
+    invite_token UUID NOT NULL UNIQUE,
+    invite_expires_at TIMESTAMPTZ NOT NULL,
+    accepted_at TIMESTAMPTZ,
+    revoked_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- One open invitation or delegation per grantor and address
+CREATE UNIQUE INDEX idx_delegations_open_email ON delegations(grantor_id, LOWER(delegate_email)) WHERE revoked_at IS NULL;
+CREATE INDEX idx_delegations_delegate_id ON delegations(delegate_id) WHERE revoked_at IS NULL;
+
+-- Every request served to a delegate on the grantor's behalf
+CREATE TABLE delegation_access_log (
+    id UUID PRIMARY KEY,
+    delegation_id UUID NOT NULL REFERENCES delegations(id) ON DELETE CASCADE,
+    method VARCHAR(10) NOT NULL,
+    path TEXT NOT NULL,
+    accessed_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_delegation_access_log_delegation_id ON delegation_access_log(delegation_id, accessed_at DESC);
@@ -0,0 +1,4 @@
+-- Manual re-entries point back at the trade they duplicated
+ALTER TABLE trades ADD COLUMN reentered_from UUID REFERENCES trades(id) ON DELETE SET NULL;
+
+CREATE INDEX idx_trades_reentered_from ON trades(reentered_from) WHERE reentered_from IS NOT NULL;
@@ -0,0 +1,27 @@
+-- One row of platform KPIs per UTC day, for BI exports. Rows are upserted, so snapshots can be retaken.
+-- active_* and the plan breakdown can only be measured live; backfilled days leave them NULL.
+CREATE TABLE platform_stats_daily (
+    stat_date DATE PRIMARY KEY,
+    total_users BIGINT NOT NULL,
+    active_users BIGINT,
+    total_robots BIGINT NOT NULL,
+    active_robots BIGINT,
+    total_trades BIGINT NOT NULL,
+    total_profit NUMERIC(20, 8) NOT NULL,
+    free_users BIGINT,
+    essential_users BIGINT,
+    pro_users BIGINT,
+    elite_users BIGINT,
+    -- live | backfill
+    source VARCHAR(20) NOT NULL,
+    computed_at TIMESTAMPTZ NOT NULL,
+    pushed_at TIMESTAMPTZ
+);
+
+-- Platform-wide settings edited by admins, one JSON document per key
+CREATE TABLE admin_settings (
+    key VARCHAR(100) PRIMARY KEY,
+    value JSONB NOT NULL,
+    updated_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,19 @@
+-- One connection per broker account and user, unless the user saved a duplicate on purpose.
+-- Broker types and servers match trimmed and case-insensitively, logins trimmed.
+ALTER TABLE broker_connections ADD COLUMN allow_duplicate BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Duplicates that already exist keep working; only the oldest connection per account is indexed
+UPDATE broker_connections b
+SET allow_duplicate = TRUE
+WHERE EXISTS (
+    SELECT 1 FROM broker_connections o
+    WHERE o.user_id = b.user_id
+      AND upper(btrim(o.broker_type)) = upper(btrim(b.broker_type))
+      AND lower(btrim(o.server)) = lower(btrim(b.server))
+      AND btrim(o.login) = btrim(b.login)
+      AND (o.created_at, o.id) < (b.created_at, b.id)
+);
+
+CREATE UNIQUE INDEX idx_broker_connections_account
+    ON broker_connections (user_id, upper(btrim(broker_type)), lower(btrim(server)), btrim(login))
+    WHERE NOT allow_duplicate AND server IS NOT NULL AND login IS NOT NULL;
@@ -0,0 +1,16 @@
+-- Incidents shown on the public status page. updates is the timeline, oldest first:
+-- [{"status": ..., "message": ..., "created_at": ...}]
+CREATE TABLE incidents (
+    id UUID PRIMARY KEY,
+    title VARCHAR(200) NOT NULL,
+    -- investigating | identified | monitoring | resolved
+    status VARCHAR(20) NOT NULL,
+    updates JSONB NOT NULL DEFAULT '[]',
+    created_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    resolved_at TIMESTAMPTZ
+);
+
+CREATE INDEX idx_incidents_open ON incidents(created_at DESC) WHERE resolved_at IS NULL;
+CREATE INDEX idx_incidents_resolved_at ON incidents(resolved_at DESC);
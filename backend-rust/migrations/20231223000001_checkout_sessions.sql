@@ -0,0 +1,22 @@
+-- Stripe Checkout sessions started from the app; the checkout.session.completed webhook
+-- completes them and creates the subscription
+CREATE TABLE checkout_sessions (
+    -- Stripe's cs_... id
+    id VARCHAR(255) PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    plan_name VARCHAR(50) NOT NULL,
+    -- open | completed
+    status VARCHAR(20) NOT NULL DEFAULT 'open',
+    subscription_id UUID REFERENCES subscriptions(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    completed_at TIMESTAMPTZ
+);
+
+CREATE INDEX idx_checkout_sessions_user_id ON checkout_sessions(user_id);
+
+-- Webhook events already handled; Stripe delivers at least once
+CREATE TABLE stripe_events (
+    id VARCHAR(255) PRIMARY KEY,
+    event_type VARCHAR(100) NOT NULL,
+    processed_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,5 @@
+-- Set on every successful login; the admin user list sorts by it
+ALTER TABLE users ADD COLUMN last_login_at TIMESTAMPTZ;
+
+CREATE INDEX idx_users_last_login_at ON users(last_login_at DESC NULLS LAST);
+CREATE INDEX idx_users_subscription_plan ON users(subscription_plan);
@@ -0,0 +1,17 @@
+-- Journal entries for closed trades. A row is created as a pending prompt when the trade
+-- closes; reviewed_at is set on the first submission and the entry locks 7 days later.
+CREATE TABLE trade_reviews (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    trade_id UUID NOT NULL UNIQUE REFERENCES trades(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    followed_plan BOOLEAN,
+    -- 1 (poor) to 5 (flawless)
+    execution_rating SMALLINT CHECK (execution_rating BETWEEN 1 AND 5),
+    emotion_tags TEXT[] NOT NULL DEFAULT '{}',
+    notes TEXT,
+    reviewed_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_trade_reviews_pending ON trade_reviews(user_id, created_at) WHERE reviewed_at IS NULL;
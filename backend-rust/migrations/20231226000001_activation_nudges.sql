@@ -0,0 +1,14 @@
+-- Nudges sent to users with idle robots or no active robot. Each idle situation (a robot, or
+-- the user having no active robot) is nudged once; sent_at drives the per-user monthly cap.
+-- Users who opted out of onboarding emails get no nudges either.
+CREATE TABLE activation_nudges (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    kind VARCHAR(50) NOT NULL,
+    robot_id UUID REFERENCES trading_robots(id) ON DELETE CASCADE,
+    sent_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE UNIQUE INDEX idx_activation_nudges_situation
+    ON activation_nudges(user_id, kind, COALESCE(robot_id, '00000000-0000-0000-0000-000000000000'));
+CREATE INDEX idx_activation_nudges_user_sent ON activation_nudges(user_id, sent_at);
@@ -0,0 +1,8 @@
+-- Broker credentials are encrypted with a key from the ENCRYPTION_KEY ring, and the id of that
+-- key is stored next to them so rotated-out keys keep decrypting until the rows are re-encrypted.
+-- Rows saved before encryption have no key id and hold plaintext until the rotation job runs.
+ALTER TABLE broker_connections ADD COLUMN credentials_key_id VARCHAR(50);
+-- Set when the stored credentials can no longer be decrypted; the user has to enter them again
+ALTER TABLE broker_connections ADD COLUMN needs_credentials BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE INDEX idx_broker_connections_credentials_key_id ON broker_connections(credentials_key_id);
@@ -0,0 +1,29 @@
+-- Users opt in to having their robots ranked on the public leaderboard
+ALTER TABLE users ADD COLUMN share_performance_anonymously BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- The anonymous label of a robot ("Trader #4821"), kept once assigned so it is the same every week
+CREATE TABLE leaderboard_labels (
+    robot_id UUID PRIMARY KEY REFERENCES trading_robots(id) ON DELETE CASCADE,
+    label_number INTEGER NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Every qualifying robot of a week, ranked. Only percentages and counts are stored; user_id is
+-- there to find the caller's own rank and is never exposed.
+CREATE TABLE leaderboard_entries (
+    week_start DATE NOT NULL,
+    robot_id UUID NOT NULL REFERENCES trading_robots(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    rank INTEGER NOT NULL,
+    label VARCHAR(50) NOT NULL,
+    strategy VARCHAR(100) NOT NULL,
+    return_pct DOUBLE PRECISION NOT NULL,
+    win_rate DOUBLE PRECISION NOT NULL,
+    trade_count INTEGER NOT NULL,
+    score DOUBLE PRECISION NOT NULL,
+    computed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (week_start, robot_id)
+);
+
+CREATE INDEX idx_leaderboard_entries_week_rank ON leaderboard_entries(week_start, rank);
+CREATE INDEX idx_leaderboard_entries_user ON leaderboard_entries(user_id, week_start);
@@ -0,0 +1,4 @@
+-- A partial close splits the closed volume into a child trade of the original position
+ALTER TABLE trades ADD COLUMN parent_trade_id UUID NULL REFERENCES trades(id) ON DELETE CASCADE;
+
+CREATE INDEX idx_trades_parent_trade_id ON trades(parent_trade_id) WHERE parent_trade_id IS NOT NULL;
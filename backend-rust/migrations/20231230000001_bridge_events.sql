@@ -0,0 +1,20 @@
+-- The token a connection's MT5 bridge pushes events with; only its SHA-256 is stored. One per
+-- connection, replaced when the owner issues a new one.
+CREATE TABLE broker_bridge_tokens (
+    connection_id UUID PRIMARY KEY REFERENCES broker_connections(id) ON DELETE CASCADE,
+    token_hash TEXT NOT NULL UNIQUE,
+    -- When the bridge last pushed anything; the snapshot poll backs off while this is recent
+    last_event_at TIMESTAMPTZ NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Broker deals already applied, so a redelivered fill or close is a no-op
+CREATE TABLE broker_bridge_deals (
+    connection_id UUID NOT NULL REFERENCES broker_connections(id) ON DELETE CASCADE,
+    deal_id BIGINT NOT NULL,
+    kind VARCHAR(20) NOT NULL,
+    received_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (connection_id, deal_id)
+);
+
+CREATE INDEX idx_trades_broker_trade_id ON trades(broker_trade_id) WHERE broker_trade_id IS NOT NULL;
@@ -0,0 +1,108 @@
+-- Editable copy for notification emails. Each save adds a version; the active one per key and
+-- locale is what gets sent, and the compiled defaults are used while none is active.
+CREATE TABLE message_templates (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    key VARCHAR(100) NOT NULL,
+    locale VARCHAR(10) NOT NULL DEFAULT 'en',
+    subject TEXT NOT NULL,
+    body TEXT NOT NULL,
+    version INTEGER NOT NULL,
+    is_active BOOLEAN NOT NULL DEFAULT FALSE,
+    created_by UUID NULL REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (key, locale, version)
+);
+
+CREATE UNIQUE INDEX idx_message_templates_active ON message_templates(key, locale) WHERE is_active;
+
+-- Version 1 of every key is the built-in copy from services/message_templates.rs
+INSERT INTO message_templates (id, key, locale, subject, body, version, is_active) VALUES
+    (uuid_generate_v4(), 'welcome', 'en', 'Welcome to Trading SaaS Platform!', $tpl$<html>
+<body>
+    <h1>Welcome to Trading SaaS Platform, {{name}}!</h1>
+    <p>Thank you for joining our AI-powered trading platform.</p>
+    <p>You can now:</p>
+    <ul>
+        <li>Connect your MT5 broker account</li>
+        <li>Create and configure trading robots</li>
+        <li>Monitor your trading performance in real-time</li>
+        <li>Access advanced AI trading strategies</li>
+    </ul>
+    <p>Get started by logging into your dashboard and setting up your first trading robot.</p>
+    <p>Happy trading!</p>
+    <p>The Trading SaaS Team</p>
+</body>
+</html>$tpl$, 1, TRUE),
+    (uuid_generate_v4(), 'trade_alert', 'en', 'Trading Alert - New Trade Executed', $tpl$<html>
+<body>
+    <h2>Trading Alert</h2>
+    <p>A new trade has been executed on your account:</p>
+    <div style="background-color: #f5f5f5; padding: 10px; border-radius: 5px;">
+        <pre>{{trade_info}}</pre>
+    </div>
+    <p>You can view more details in your trading dashboard.</p>
+    <p>Best regards,<br>Trading SaaS Platform</p>
+</body>
+</html>$tpl$, 1, TRUE),
+    (uuid_generate_v4(), 'robot_status', 'en', 'Robot Status Update - {{robot_name}}', $tpl$<html>
+<body>
+    <h2>Robot Status Update</h2>
+    <p>Your trading robot <strong>{{robot_name}}</strong> status has changed to: <strong>{{status}}</strong></p>
+    <p>Please check your dashboard for more details.</p>
+    <p>Best regards,<br>Trading SaaS Platform</p>
+</body>
+</html>$tpl$, 1, TRUE),
+    (uuid_generate_v4(), 'subscription', 'en', 'Subscription {{action}} - {{plan}}', $tpl$<html>
+<body>
+    <h2>Subscription Update</h2>
+    <p>Your subscription to the <strong>{{plan}}</strong> plan has been <strong>{{action}}</strong>.</p>
+    <p>You can manage your subscription in your account settings.</p>
+    <p>Thank you for using Trading SaaS Platform!</p>
+    <p>Best regards,<br>Trading SaaS Team</p>
+</body>
+</html>$tpl$, 1, TRUE),
+    (uuid_generate_v4(), 'trial_reminder', 'en', 'Your Pro trial ends in {{days_left}}', $tpl$<html>
+<body>
+    <h2>Your Pro trial is almost over</h2>
+    <p>Your free Pro trial ends in <strong>{{days_left}}</strong>.</p>
+    <p>Subscribe before it ends to keep your robots running without interruption.</p>
+    <p>Best regards,<br>Trading SaaS Team</p>
+</body>
+</html>$tpl$, 1, TRUE),
+    (uuid_generate_v4(), 'trial_expired', 'en', 'Your Pro trial has ended', $tpl$<html>
+<body>
+    <h2>Your Pro trial has ended</h2>
+    <p>Your account is now on the <strong>Free</strong> plan.</p>
+    <p>Upgrade to Pro at any time from your account settings to pick up where you left off.</p>
+    <p>Best regards,<br>Trading SaaS Team</p>
+</body>
+</html>$tpl$, 1, TRUE),
+    (uuid_generate_v4(), 'delegate_invitation', 'en', '{{grantor_email}} shared their trading records with you', $tpl$<html>
+<body>
+    <h2>Read-only access to a trading account</h2>
+    <p>{{grantor_email}} invited you to view their trades and statistics on Trading SaaS Platform.</p>
+    <p>Sign in or create an account with this email address and accept the invitation with this code:</p>
+    <p><strong>{{token}}</strong></p>
+    <p>The invitation expires in {{valid_days}} days. You will not be able to change anything on their account.</p>
+    <p>Best regards,<br>Trading SaaS Team</p>
+</body>
+</html>$tpl$, 1, TRUE),
+    (uuid_generate_v4(), 'onboarding', 'en', '{{subject}}', $tpl$<html>
+<body>
+    <h2>{{subject}}</h2>
+    <p>{{message}}</p>
+    <p>Best regards,<br>Trading SaaS Team</p>
+    <p style="font-size: 12px"><a href="{{unsubscribe_url}}">Stop sending me getting-started emails</a></p>
+</body>
+</html>$tpl$, 1, TRUE),
+    (uuid_generate_v4(), 'system_alert', 'en', 'System Alert - Trading SaaS Platform', $tpl$<html>
+<body>
+    <h2 style="color: red;">System Alert</h2>
+    <p><strong>Alert Message:</strong></p>
+    <div style="background-color: #ffe6e6; padding: 10px; border-left: 4px solid #ff0000;">
+        {{alert_message}}
+    </div>
+    <p><strong>Timestamp:</strong> {{timestamp}}</p>
+    <p>Please investigate this issue immediately.</p>
+</body>
+</html>$tpl$, 1, TRUE);
@@ -0,0 +1,36 @@
+-- Monthly statements are stored when first generated and never change afterwards. Corrections to
+-- the trades a statement covered are booked on the next one as adjustments.
+CREATE TABLE monthly_statements (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- First day of the month covered
+    period_start DATE NOT NULL,
+    content JSONB NOT NULL,
+    html TEXT NOT NULL,
+    generated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    notified_at TIMESTAMPTZ NULL,
+    UNIQUE (user_id, period_start)
+);
+
+-- What each statement booked per trade: its net result, or the change to what was booked before
+CREATE TABLE statement_entries (
+    statement_id UUID NOT NULL REFERENCES monthly_statements(id) ON DELETE CASCADE,
+    trade_id UUID NOT NULL REFERENCES trades(id) ON DELETE CASCADE,
+    amount DOUBLE PRECISION NOT NULL,
+    PRIMARY KEY (statement_id, trade_id)
+);
+
+CREATE INDEX idx_statement_entries_trade_id ON statement_entries(trade_id);
+CREATE INDEX idx_trades_user_closed_at ON trades(user_id, closed_at) WHERE status = 'closed' AND is_demo = FALSE;
+
+-- Version 1 of the new email is the built-in copy from services/message_templates.rs
+INSERT INTO message_templates (id, key, locale, subject, body, version, is_active) VALUES
+    (uuid_generate_v4(), 'statement_ready', 'en', 'Your {{period}} statement is ready', $tpl$<html>
+<body>
+    <h2>Your monthly statement for {{period}}</h2>
+    <p>Your statement with the month's closed trades, fees and balances per broker account is ready.</p>
+    <p>Download it from your account, or from <code>{{statement_path}}</code> in the API.</p>
+    <p>Issued statements do not change. Corrections to their trades appear on the next statement as adjustments.</p>
+    <p>Best regards,<br>Trading SaaS Team</p>
+</body>
+</html>$tpl$, 1, TRUE);
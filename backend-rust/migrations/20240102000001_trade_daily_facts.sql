@@ -0,0 +1,33 @@
+-- Closed trades pre-aggregated per robot, symbol and UTC day, so statistics and sparklines do not
+-- scan the trades table. Rows are upserted in the transaction that closes a trade; existing history
+-- is filled in by the trade_facts backfill job after deploy.
+CREATE TABLE trade_daily_facts (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    robot_id UUID NOT NULL REFERENCES trading_robots(id) ON DELETE CASCADE,
+    symbol VARCHAR(20) NOT NULL,
+    is_demo BOOLEAN NOT NULL,
+    -- Statistics filter on when a trade was opened, daily summaries on when it closed
+    opened_on DATE NOT NULL,
+    date DATE NOT NULL,
+    trades INTEGER NOT NULL DEFAULT 0,
+    wins INTEGER NOT NULL DEFAULT 0,
+    gross_profit DOUBLE PRECISION NOT NULL DEFAULT 0,
+    -- Positive: the sum of the losing trades' losses
+    gross_loss DOUBLE PRECISION NOT NULL DEFAULT 0,
+    net_profit DOUBLE PRECISION NOT NULL DEFAULT 0,
+    volume DOUBLE PRECISION NOT NULL DEFAULT 0,
+    PRIMARY KEY (user_id, robot_id, symbol, is_demo, opened_on, date)
+);
+
+CREATE INDEX idx_trade_daily_facts_user_date ON trade_daily_facts(user_id, date);
+CREATE INDEX idx_trade_daily_facts_robot_id ON trade_daily_facts(robot_id);
+
+-- Progress of the one-time backfill, which walks users in id order. Until completed_at is set,
+-- statistics keep reading the trades table.
+CREATE TABLE trade_facts_backfill (
+    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    last_user_id UUID NULL,
+    completed_at TIMESTAMPTZ NULL
+);
+
+INSERT INTO trade_facts_backfill (id) VALUES (TRUE);
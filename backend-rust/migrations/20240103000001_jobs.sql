@@ -0,0 +1,26 @@
+-- Long-running work (optimizations today) registered in one place, so users can list and cancel
+-- their jobs and per-user concurrency limits count rows here rather than in-process slots
+CREATE TABLE jobs (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- export | backtest | import; the class the plan's concurrency limit applies to
+    kind VARCHAR(20) NOT NULL,
+    status VARCHAR(20) NOT NULL DEFAULT 'queued'
+        CHECK (status IN ('queued', 'running', 'completed', 'failed', 'cancelled')),
+    -- Percent complete, and what the worker last saved about how far it got
+    progress DOUBLE PRECISION NOT NULL DEFAULT 0,
+    checkpoint JSONB NOT NULL DEFAULT '{}',
+    -- Where the finished job's output can be read
+    result_ref TEXT NULL,
+    error TEXT NULL,
+    -- Set by the cancel endpoint; the job only becomes cancelled once its worker acknowledges
+    cancel_requested_at TIMESTAMPTZ NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    started_at TIMESTAMPTZ NULL,
+    finished_at TIMESTAMPTZ NULL,
+    -- Bumped on every checkpoint; jobs that stop checkpointing are failed by the stale sweep
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_jobs_user_created ON jobs(user_id, created_at DESC);
+CREATE INDEX idx_jobs_active ON jobs(user_id, kind) WHERE status IN ('queued', 'running');
@@ -0,0 +1,15 @@
+-- What caused each trade, with the raw inbound payload kept for disputes. The retention job
+-- clears old payloads but keeps the row, so the origin type and reference survive.
+CREATE TABLE trade_origins (
+    trade_id UUID PRIMARY KEY REFERENCES trades(id) ON DELETE CASCADE,
+    origin_type VARCHAR(20) NOT NULL CHECK (origin_type IN ('webhook', 'signal', 'manual', 'import')),
+    -- Alert id, signal id, the trade a manual re-entry came from, or the import job
+    reference TEXT NULL,
+    -- {"headers": {...}, "body": ...} with sensitive headers stripped and the body size-capped
+    payload JSONB NULL,
+    received_at TIMESTAMPTZ NOT NULL,
+    source_ip TEXT NULL,
+    payload_pruned_at TIMESTAMPTZ NULL
+);
+
+CREATE INDEX idx_trade_origins_prunable ON trade_origins(received_at) WHERE payload IS NOT NULL;
@@ -0,0 +1,15 @@
+-- Which engine made each AI trade: the ONNX model or the rule-based fallback, and what the
+-- fallback said for the same market data, so the model's overrides can be measured
+CREATE TABLE trade_ai_decisions (
+    trade_id UUID PRIMARY KEY REFERENCES trades(id) ON DELETE CASCADE,
+    source VARCHAR(10) NOT NULL CHECK (source IN ('model', 'fallback')),
+    -- buy | sell | hold; null when the fallback was not evaluated
+    fallback_signal VARCHAR(10) NULL CHECK (fallback_signal IN ('buy', 'sell', 'hold')),
+    decided_at TIMESTAMPTZ NOT NULL
+);
+
+-- Every AI trade so far came from the fallback; the model has never been loaded
+INSERT INTO trade_ai_decisions (trade_id, source, fallback_signal, decided_at)
+SELECT id, 'fallback', trade_type, COALESCE(opened_at, created_at, NOW())
+FROM trades
+WHERE ai_confidence > 0 AND parent_trade_id IS NULL AND trade_type IN ('buy', 'sell');
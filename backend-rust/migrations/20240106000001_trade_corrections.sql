@@ -0,0 +1,44 @@
+-- When each trade row was first written. Reports asked for `as_of` an instant leave out trades
+-- recorded after it, e.g. by a later import, so the column must never change once set.
+ALTER TABLE trades ADD COLUMN recorded_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+-- Existing trades count from when they were created; their updated_at stays as it was
+ALTER TABLE trades DISABLE TRIGGER update_trades_updated_at;
+UPDATE trades SET recorded_at = created_at;
+ALTER TABLE trades ENABLE TRIGGER update_trades_updated_at;
+
+CREATE OR REPLACE FUNCTION keep_trades_recorded_at()
+RETURNS TRIGGER AS $$
+BEGIN
+    NEW.recorded_at = OLD.recorded_at;
+    RETURN NEW;
+END;
+$$ language 'plpgsql';
+
+CREATE TRIGGER keep_trades_recorded_at BEFORE UPDATE ON trades FOR EACH ROW EXECUTE FUNCTION keep_trades_recorded_at();
+
+-- Edits to a closed trade's P/L fields. The trade row keeps the values it was closed with; each
+-- correction holds the full set from its recorded_at on, and reports apply the latest one
+-- recorded by the instant they are asked for. Rows are only ever appended.
+CREATE TABLE trade_corrections (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    trade_id UUID NOT NULL REFERENCES trades(id) ON DELETE CASCADE,
+    entry_price DOUBLE PRECISION NOT NULL,
+    exit_price DOUBLE PRECISION NULL,
+    profit_loss DOUBLE PRECISION NULL,
+    commission DOUBLE PRECISION NULL,
+    swap DOUBLE PRECISION NULL,
+    -- e.g. late_fill | import | support
+    reason VARCHAR(50) NOT NULL,
+    recorded_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_trade_corrections_trade_id ON trade_corrections(trade_id, recorded_at DESC);
+
+CREATE OR REPLACE FUNCTION reject_trade_correction_update()
+RETURNS TRIGGER AS $$
+BEGIN
+    RAISE EXCEPTION 'trade_corrections is append-only; record a new correction instead';
+END;
+$$ language 'plpgsql';
+
+CREATE TRIGGER reject_trade_correction_update BEFORE UPDATE ON trade_corrections FOR EACH ROW EXECUTE FUNCTION reject_trade_correction_update();
@@ -0,0 +1,19 @@
+-- Operator actions taken outside the API, e.g. with admin-cli, and who took them. Rows are only
+-- ever appended.
+CREATE TABLE audit_log (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    -- e.g. cli
+    actor_type VARCHAR(20) NOT NULL,
+    -- The operator's name as the tool reported it
+    actor VARCHAR(255) NOT NULL,
+    -- e.g. user.promote | robot.stop | trade.close
+    action VARCHAR(100) NOT NULL,
+    -- user | robot | trade | platform
+    target_type VARCHAR(50) NOT NULL,
+    target_id UUID NULL,
+    details JSONB NOT NULL DEFAULT '{}',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_audit_log_target ON audit_log(target_type, target_id, created_at DESC);
+CREATE INDEX idx_audit_log_created_at ON audit_log(created_at DESC);
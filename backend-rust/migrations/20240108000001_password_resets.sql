@@ -0,0 +1,25 @@
+-- Password reset links. Only a SHA-256 of the token is stored; the token itself is only ever in
+-- the email. A token works once, until expires_at.
+CREATE TABLE password_resets (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    token_hash VARCHAR(64) NOT NULL UNIQUE,
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_password_resets_user_id ON password_resets(user_id);
+
+-- Version 1 of the new email is the built-in copy from services/message_templates.rs
+INSERT INTO message_templates (id, key, locale, subject, body, version, is_active) VALUES
+    (uuid_generate_v4(), 'password_reset', 'en', 'Reset your Trading SaaS password', $tpl$<html>
+<body>
+    <h2>Reset your password</h2>
+    <p>Someone asked to reset the password of your Trading SaaS Platform account.</p>
+    <p><a href="{{reset_url}}">Choose a new password</a></p>
+    <p>The link works once and expires in {{valid_minutes}} minutes. Setting a new password signs you out on every device.</p>
+    <p>If this was not you, ignore this email; your password stays as it is.</p>
+    <p>Best regards,<br>Trading SaaS Team</p>
+</body>
+</html>$tpl$, 1, TRUE);
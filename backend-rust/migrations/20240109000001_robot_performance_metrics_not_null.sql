@@ -0,0 +1,7 @@
+-- Robots created by TradingRobot::new always carry both counters; older rows may be NULL or miss
+-- keys. NULL becomes an empty object rather than zeros, so readers still see the counters are
+-- missing and take them from the trades table instead of reporting a 0% win rate.
+UPDATE trading_robots SET performance_metrics = '{}'::jsonb WHERE performance_metrics IS NULL;
+ALTER TABLE trading_robots
+    ALTER COLUMN performance_metrics SET DEFAULT '{"total_profit": 0, "winning_trades": 0}'::jsonb,
+    ALTER COLUMN performance_metrics SET NOT NULL;
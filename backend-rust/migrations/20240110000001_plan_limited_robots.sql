@@ -0,0 +1,13 @@
+-- Robots parked by a plan downgrade are in status plan_limited, with the status they had before
+-- in performance_metrics.plan_limited_from. The status column is free text, so only the email
+-- telling the user about it is new. Version 1 is the built-in copy from services/message_templates.rs.
+INSERT INTO message_templates (id, key, locale, subject, body, version, is_active) VALUES
+    (uuid_generate_v4(), 'plan_limited', 'en', 'Robots paused by your {{plan}} plan', $tpl$<html>
+<body>
+    <h2>Some of your robots were paused</h2>
+    <p>Your account is now on the <strong>{{plan}}</strong> plan, which allows {{max_robots}} trading robot(s). These robots were stopped and marked as limited by your plan:</p>
+    <p>{{robots}}</p>
+    <p>They keep their settings, trades and history. Upgrade again and reactivate them from your dashboard in one click.</p>
+    <p>Best regards,<br>Trading SaaS Team</p>
+</body>
+</html>$tpl$, 1, TRUE);
@@ -0,0 +1,11 @@
+-- Failed login counts and locks live in Redis; only the email telling the user their account was
+-- locked is new. Version 1 is the built-in copy from services/message_templates.rs.
+INSERT INTO message_templates (id, key, locale, subject, body, version, is_active) VALUES
+    (uuid_generate_v4(), 'account_locked', 'en', 'Your account was locked after failed logins', $tpl$<html>
+<body>
+    <h2>Your account was locked</h2>
+    <p>There were {{failures}} failed attempts to log in to your account, so logins are blocked for the next {{minutes}} minutes.</p>
+    <p>If this was you, wait and try again. If it was not, someone may be guessing your password: once the lock ends, reset your password from the login page.</p>
+    <p>Best regards,<br>Trading SaaS Team</p>
+</body>
+</html>$tpl$, 1, TRUE);
@@ -0,0 +1,3 @@
+-- The trades list pages newest first on (created_at, id); this serves both the order and the
+-- cursor comparison without a sort.
+CREATE INDEX idx_trades_user_created_id ON trades(user_id, created_at DESC, id DESC);
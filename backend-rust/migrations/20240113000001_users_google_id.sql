@@ -0,0 +1,4 @@
+-- The Google account (the ID token's `sub`) a user signs in with. Emails can change on either
+-- side, so later Google sign-ins match on this rather than the address.
+ALTER TABLE users ADD COLUMN google_id TEXT;
+CREATE UNIQUE INDEX idx_users_google_id ON users(google_id) WHERE google_id IS NOT NULL;
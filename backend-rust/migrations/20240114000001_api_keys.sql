@@ -0,0 +1,16 @@
+-- Keys scripts and bots authenticate with through X-API-Key. Only a SHA-256 of the key is stored;
+-- the key itself is shown once when it is created. `prefix` is its first characters, so a key can
+-- be told apart in the list. Scopes: read, trade.
+CREATE TABLE api_keys (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    key_hash VARCHAR(64) NOT NULL UNIQUE,
+    prefix VARCHAR(16) NOT NULL,
+    label VARCHAR(100) NOT NULL,
+    scopes TEXT[] NOT NULL,
+    last_used_at TIMESTAMPTZ NULL,
+    revoked_at TIMESTAMPTZ NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_api_keys_user_id ON api_keys(user_id, created_at);
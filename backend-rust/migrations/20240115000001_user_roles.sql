@@ -0,0 +1,6 @@
+-- What a user may do beyond their own account: support staff can read the admin API, admins can
+-- also change things. is_superuser is kept for older readers and set together with role, so it
+-- stays true exactly for admins.
+ALTER TABLE users ADD COLUMN role VARCHAR(20) NOT NULL DEFAULT 'user';
+UPDATE users SET role = 'admin' WHERE is_superuser;
+ALTER TABLE users ADD CONSTRAINT users_role_check CHECK (role IN ('user', 'support', 'admin'));
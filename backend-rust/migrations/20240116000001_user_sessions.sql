@@ -0,0 +1,16 @@
+-- One row per token issued at sign-in, so users can see where they are signed in and sign a
+-- device out. `id` is the token's jti. A session also ends when the user's token version moves
+-- past `token_version` ("logout all devices", a password change or reset).
+CREATE TABLE user_sessions (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    token_version INTEGER NOT NULL,
+    user_agent VARCHAR(512) NULL,
+    ip_address VARCHAR(64) NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_seen_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    revoked_at TIMESTAMPTZ NULL
+);
+
+CREATE INDEX idx_user_sessions_user_id ON user_sessions(user_id, created_at);
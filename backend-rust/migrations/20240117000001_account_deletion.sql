@@ -0,0 +1,14 @@
+-- Deleted accounts are anonymized rather than removed, so their trades stay for accounting.
+-- Version 1 of the confirmation email is the built-in copy from services/message_templates.rs.
+ALTER TABLE users ADD COLUMN deleted_at TIMESTAMPTZ;
+
+INSERT INTO message_templates (id, key, locale, subject, body, version, is_active) VALUES
+    (uuid_generate_v4(), 'account_deleted', 'en', 'Your Trading SaaS account was deleted', $tpl$<html>
+<body>
+    <h2>Your account was deleted</h2>
+    <p>As you asked, your Trading SaaS Platform account was deleted. Your email address, password and broker connections were removed and any subscription was cancelled.</p>
+    <p>Records of past trades are kept for accounting, no longer linked to you.</p>
+    <p>If you did not ask for this, reply to this email.</p>
+    <p>Best regards,<br>Trading SaaS Team</p>
+</body>
+</html>$tpl$, 1, TRUE);
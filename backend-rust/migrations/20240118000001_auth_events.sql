@@ -0,0 +1,16 @@
+-- Security-relevant account events: sign-ins and their failures, sign-outs, password changes and
+-- credential edits. Shown to the user as their security history and searchable by support.
+-- `user_id` is NULL for a failed sign-in with an address that has no account.
+CREATE TABLE auth_events (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NULL REFERENCES users(id) ON DELETE CASCADE,
+    event_type VARCHAR(64) NOT NULL,
+    outcome VARCHAR(16) NOT NULL,
+    reason VARCHAR(64) NULL,
+    ip_address VARCHAR(64) NULL,
+    user_agent VARCHAR(512) NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_auth_events_user_id ON auth_events(user_id, created_at);
+CREATE INDEX idx_auth_events_created_at ON auth_events(created_at);
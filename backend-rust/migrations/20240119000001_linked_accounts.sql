@@ -0,0 +1,18 @@
+-- The external accounts (Google, GitHub, Apple) users sign in with, matched on the provider's
+-- account id rather than the email, which can change on either side. A user has at most one
+-- account per provider, and signing in with any of them reaches the same user.
+-- users.google_id is copied here and no longer read.
+CREATE TABLE linked_accounts (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    provider VARCHAR(32) NOT NULL,
+    external_id TEXT NOT NULL,
+    -- The address the provider gave when the account was linked
+    email VARCHAR(255) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (provider, external_id),
+    UNIQUE (user_id, provider)
+);
+
+INSERT INTO linked_accounts (user_id, provider, external_id, email)
+SELECT id, 'google', google_id, email FROM users WHERE google_id IS NOT NULL;
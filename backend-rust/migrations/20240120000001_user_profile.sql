@@ -0,0 +1,9 @@
+-- Optional profile of a user, edited with PATCH /api/v1/users/me. Name and avatar are also filled
+-- in from the sign-in provider when the user has not set them.
+ALTER TABLE users
+    ADD COLUMN full_name VARCHAR(100),
+    ADD COLUMN avatar_url TEXT,
+    -- An IANA name such as Europe/Lisbon
+    ADD COLUMN timezone VARCHAR(64),
+    -- A language tag such as pt-BR
+    ADD COLUMN locale VARCHAR(35);
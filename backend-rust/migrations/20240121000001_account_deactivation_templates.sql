@@ -0,0 +1,19 @@
+-- Emails for accounts an admin deactivates or reactivates. Version 1 is the built-in copy from
+-- services/message_templates.rs.
+INSERT INTO message_templates (id, key, locale, subject, body, version, is_active) VALUES
+    (uuid_generate_v4(), 'account_deactivated', 'en', 'Your Trading SaaS account was deactivated', $tpl$<html>
+<body>
+    <h2>Your account was deactivated</h2>
+    <p>Your Trading SaaS Platform account was deactivated by our team. You are signed out on every device, logins are refused and your running robots were stopped.</p>
+    <p>Your trades, robots and statements are kept. If you think this is a mistake, reply to this email.</p>
+    <p>Best regards,<br>Trading SaaS Team</p>
+</body>
+</html>$tpl$, 1, TRUE),
+    (uuid_generate_v4(), 'account_reactivated', 'en', 'Your Trading SaaS account is active again', $tpl$<html>
+<body>
+    <h2>Your account is active again</h2>
+    <p>Your Trading SaaS Platform account was reactivated and you can log in again.</p>
+    <p>Robots stopped when the account was deactivated stay stopped until you start them from your dashboard.</p>
+    <p>Best regards,<br>Trading SaaS Team</p>
+</body>
+</html>$tpl$, 1, TRUE);
@@ -0,0 +1,15 @@
+-- Deleted robots and broker connections stay until the purge job removes them, so the trades,
+-- snapshots and statements pointing at them keep working. users.deleted_at already exists.
+ALTER TABLE trading_robots ADD COLUMN deleted_at TIMESTAMPTZ;
+ALTER TABLE broker_connections ADD COLUMN deleted_at TIMESTAMPTZ;
+
+-- A deleted connection no longer keeps its broker account from being connected again
+DROP INDEX idx_broker_connections_account;
+CREATE UNIQUE INDEX idx_broker_connections_account
+    ON broker_connections (user_id, upper(btrim(broker_type)), lower(btrim(server)), btrim(login))
+    WHERE NOT allow_duplicate AND server IS NOT NULL AND login IS NOT NULL AND deleted_at IS NULL;
+
+-- What the purge job looks for
+CREATE INDEX idx_users_deleted_at ON users(deleted_at) WHERE deleted_at IS NOT NULL;
+CREATE INDEX idx_trading_robots_deleted_at ON trading_robots(deleted_at) WHERE deleted_at IS NOT NULL;
+CREATE INDEX idx_broker_connections_deleted_at ON broker_connections(deleted_at) WHERE deleted_at IS NOT NULL;
@@ -0,0 +1,2 @@
+-- Plans an admin set by hand, e.g. comped or repaired after a failed Stripe sync
+ALTER TABLE subscriptions ADD COLUMN manual BOOLEAN NOT NULL DEFAULT FALSE;
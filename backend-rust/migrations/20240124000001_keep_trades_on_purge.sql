@@ -0,0 +1,7 @@
+-- Trades are kept for accounting: the purge job leaves users and robots that still have trades,
+-- and deleting one that does is refused rather than taking its trades along
+ALTER TABLE trades
+    DROP CONSTRAINT trades_user_id_fkey,
+    ADD CONSTRAINT trades_user_id_fkey FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE RESTRICT,
+    DROP CONSTRAINT trades_robot_id_fkey,
+    ADD CONSTRAINT trades_robot_id_fkey FOREIGN KEY (robot_id) REFERENCES trading_robots(id) ON DELETE RESTRICT;
@@ -0,0 +1,2 @@
+-- Token versions are unsigned 32-bit and come from the clock, so they outgrow INTEGER
+ALTER TABLE user_sessions ALTER COLUMN token_version TYPE BIGINT;
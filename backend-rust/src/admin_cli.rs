@@ -0,0 +1,441 @@
+// Operator tasks for the admin-cli binary (src/bin/admin-cli.rs), run against the database and
+// broker connections directly instead of through psql or the API with a hand-built token. Every
+// command, reads included, leaves an audit_log entry attributed to the operator.
+use serde::Serialize;
+use serde_json::{json, Value};
+use sqlx::{FromRow, PgPool};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{
+    config::AppEnv,
+    errors::{AppError, DbOp, Result},
+    models::{AuditActor, AuditEntry, BrokerConnection, Role, Trade, TradeCorrection, TradingRobot, User, UserResponse},
+    services::{
+        execution_queue::{on_behalf_of, Requester},
+        robot_recovery::{PgRecoveryEnv, RecoveryEnv},
+        robot_runner::RUNNER_ERROR,
+        trade_close_service::{Mt5PositionCloser, PgClosedTradeStore, TradeCloseService},
+        EventBus, Mt5Service, NotificationService,
+    },
+};
+
+// Who the audit entries are attributed to; the CLI refuses to run without it
+pub const OPERATOR_ENV: &str = "ADMIN_CLI_OPERATOR";
+pub const CONFIRM_PROD_FLAG: &str = "--yes-i-mean-prod";
+
+pub const USAGE: &str = "Usage: admin-cli [--json] [--yes-i-mean-prod] <command>
+
+Commands:
+  user promote <email|id>       Make the user an admin
+  user deactivate <email|id>    Refuse the user's logins and tokens from now on
+  robot stop <robot id>         Mark the robot stopped
+  robot reset-error <robot id>  Move a robot its runner left in error back to stopped
+  trade show <trade id>         Print the trade and its corrections
+  trade close <trade id>        Close an open trade at the broker's current price
+  reconcile --user <email|id>   Compare the user's open trades with their broker positions
+  stats                         Platform counts
+
+ADMIN_CLI_OPERATOR names the operator in the audit log. With APP_ENV=prod, --yes-i-mean-prod is required.";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    PromoteUser(String),
+    DeactivateUser(String),
+    StopRobot(Uuid),
+    ResetRobotError(Uuid),
+    ShowTrade(Uuid),
+    CloseTrade(Uuid),
+    Reconcile(String),
+    Stats,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Table,
+    Json,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Invocation {
+    pub command: Command,
+    pub format: OutputFormat,
+    pub confirm_prod: bool,
+}
+
+impl Invocation {
+    // `args` without the program name; flags may come anywhere
+    pub fn parse(args: &[String]) -> std::result::Result<Invocation, String> {
+        let mut format = OutputFormat::Table;
+        let mut confirm_prod = false;
+        let mut words = Vec::new();
+        for arg in args {
+            match arg.as_str() {
+                "--json" => format = OutputFormat::Json,
+                CONFIRM_PROD_FLAG => confirm_prod = true,
+                _ => words.push(arg.as_str()),
+            }
+        }
+
+        let id = |raw: &str, what: &str| Uuid::parse_str(raw).map_err(|_| format!("'{}' is not a {} id", raw, what));
+        let command = match words.as_slice() {
+            ["user", "promote", user] => Command::PromoteUser(user.to_string()),
+            ["user", "deactivate", user] => Command::DeactivateUser(user.to_string()),
+            ["robot", "stop", robot] => Command::StopRobot(id(robot, "robot")?),
+            ["robot", "reset-error", robot] => Command::ResetRobotError(id(robot, "robot")?),
+            ["trade", "show", trade] => Command::ShowTrade(id(trade, "trade")?),
+            ["trade", "close", trade] => Command::CloseTrade(id(trade, "trade")?),
+            ["reconcile", "--user", user] => Command::Reconcile(user.to_string()),
+            ["stats"] => Command::Stats,
+            [] => return Err("No command given".to_string()),
+            other => return Err(format!("Unknown command '{}'", other.join(" "))),
+        };
+        Ok(Invocation { command, format, confirm_prod })
+    }
+}
+
+pub fn check_environment(app_env: AppEnv, confirm_prod: bool) -> std::result::Result<(), String> {
+    if app_env == AppEnv::Prod && !confirm_prod {
+        return Err(format!("Refusing to run against prod without {}", CONFIRM_PROD_FLAG));
+    }
+    Ok(())
+}
+
+pub fn operator_name(raw: Option<String>) -> std::result::Result<String, String> {
+    raw.map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .ok_or_else(|| format!("Set {} to your name; it is recorded with every action", OPERATOR_ENV))
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReconcileRow {
+    pub robot_id: Uuid,
+    pub robot: String,
+    pub open_trades: usize,
+    // Open trades with no matching broker position; None when the broker could not be reached
+    pub issues: Option<usize>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, FromRow)]
+pub struct PlatformCounts {
+    pub users: i64,
+    pub active_users: i64,
+    pub admins: i64,
+    pub robots: i64,
+    pub active_robots: i64,
+    pub errored_robots: i64,
+    pub open_trades: i64,
+    pub execution_pending_trades: i64,
+}
+
+pub struct AdminCli {
+    pool: PgPool,
+    mt5: Arc<Mt5Service>,
+    notifications: Arc<NotificationService>,
+    actor: AuditActor,
+}
+
+impl AdminCli {
+    pub fn new(pool: PgPool, mt5: Arc<Mt5Service>, notifications: Arc<NotificationService>, operator: &str) -> Self {
+        AdminCli { pool, mt5, notifications, actor: AuditActor::cli(operator) }
+    }
+
+    pub async fn run(&self, command: &Command) -> Result<Value> {
+        match command {
+            Command::PromoteUser(user) => self.promote_user(user).await,
+            Command::DeactivateUser(user) => self.deactivate_user(user).await,
+            Command::StopRobot(robot_id) => self.stop_robot(*robot_id).await,
+            Command::ResetRobotError(robot_id) => self.reset_robot_error(*robot_id).await,
+            Command::ShowTrade(trade_id) => self.show_trade(*trade_id).await,
+            Command::CloseTrade(trade_id) => self.close_trade(*trade_id).await,
+            Command::Reconcile(user) => self.reconcile(user).await,
+            Command::Stats => self.stats().await,
+        }
+    }
+
+    async fn audit(&self, action: &str, target_type: &str, target_id: Option<Uuid>, details: Value) -> Result<()> {
+        AuditEntry::record(&self.pool, &self.actor, action, target_type, target_id, details).await?;
+        Ok(())
+    }
+
+    // By id, or else by email
+    async fn find_user(&self, user: &str) -> Result<User> {
+        let found = match Uuid::parse_str(user) {
+            Ok(id) => User::find_by_id(&self.pool, id).await?,
+            Err(_) => User::find_by_email(&self.pool, user).await?,
+        };
+        found.ok_or_else(|| AppError::NotFound(format!("User {} not found", user)))
+    }
+
+    async fn find_robot(&self, robot_id: Uuid) -> Result<TradingRobot> {
+        let robot = match TradingRobot::owner_id(&self.pool, robot_id).await? {
+            Some(user_id) => TradingRobot::find_by_id(&self.pool, robot_id, user_id).await?,
+            None => None,
+        };
+        robot.ok_or_else(|| AppError::NotFound(format!("Robot {} not found", robot_id)))
+    }
+
+    async fn find_trade(&self, trade_id: Uuid) -> Result<Trade> {
+        let trade = match Trade::owner_id(&self.pool, trade_id).await? {
+            Some(user_id) => Trade::find_by_id(&self.pool, trade_id, user_id).await?,
+            None => None,
+        };
+        trade.ok_or_else(|| AppError::NotFound(format!("Trade {} not found", trade_id)))
+    }
+
+    async fn promote_user(&self, user: &str) -> Result<Value> {
+        let user = self.find_user(user).await?;
+        User::set_role(&self.pool, user.id, Role::Admin).await?;
+        self.audit("user.promote", "user", Some(user.id), json!({ "email": user.email, "previous_role": user.role }))
+            .await?;
+        Ok(json!(UserResponse::from(self.find_user(&user.id.to_string()).await?)))
+    }
+
+    async fn deactivate_user(&self, user: &str) -> Result<Value> {
+        let user = self.find_user(user).await?;
+        User::set_active(&self.pool, user.id, false).await?;
+        self.audit("user.deactivate", "user", Some(user.id), json!({ "email": user.email, "was_active": user.is_active }))
+            .await?;
+        Ok(json!(UserResponse::from(self.find_user(&user.id.to_string()).await?)))
+    }
+
+    // Only the stored status changes. A server running the robot keeps its runner until it is
+    // stopped through the API or the server restarts, when recovery leaves stopped robots alone.
+    async fn stop_robot(&self, robot_id: Uuid) -> Result<Value> {
+        let robot = self.find_robot(robot_id).await?;
+        TradingRobot::update_status(&self.pool, robot.id, robot.user_id, "stopped").await?;
+        self.audit("robot.stop", "robot", Some(robot.id), json!({ "user_id": robot.user_id, "previous_status": robot.status }))
+            .await?;
+        Ok(json!(self.find_robot(robot_id).await?))
+    }
+
+    async fn reset_robot_error(&self, robot_id: Uuid) -> Result<Value> {
+        let robot = self.find_robot(robot_id).await?;
+        if !TradingRobot::transition_status(&self.pool, robot.id, RUNNER_ERROR, "stopped").await? {
+            return Err(AppError::Validation(format!("Robot {} is {}, not {}", robot.id, robot.status, RUNNER_ERROR)));
+        }
+        self.audit("robot.reset_error", "robot", Some(robot.id), json!({ "user_id": robot.user_id })).await?;
+        Ok(json!(self.find_robot(robot_id).await?))
+    }
+
+    async fn show_trade(&self, trade_id: Uuid) -> Result<Value> {
+        let trade = self.find_trade(trade_id).await?;
+        let corrections = TradeCorrection::find_by_trade_id(&self.pool, trade.id).await?;
+        self.audit("trade.show", "trade", Some(trade.id), json!({ "user_id": trade.user_id })).await?;
+
+        let mut shown = json!(trade);
+        shown["corrections"] = json!(corrections);
+        Ok(shown)
+    }
+
+    // Through the close service the API's batch close uses, on the trade's robot's connection
+    async fn close_trade(&self, trade_id: Uuid) -> Result<Value> {
+        let trade = self.find_trade(trade_id).await?;
+        let robot = self.find_robot(trade.robot_id).await?;
+        let owner = self.find_user(&trade.user_id.to_string()).await?;
+        let connection = match robot.broker_connection_id {
+            Some(connection_id) => BrokerConnection::find_by_id(&self.pool, connection_id, trade.user_id).await?,
+            None => None,
+        }
+        .filter(|c| c.is_active)
+        .ok_or_else(|| AppError::Unprocessable("The trade's robot has no active broker connection".to_string()))?;
+
+        let connection_id = connection.id.to_string();
+        if !self.mt5.is_connected(&connection_id) {
+            self.mt5.connect(&connection).await?;
+        }
+        let closer = Mt5PositionCloser::new(self.mt5.clone(), connection_id);
+        let store = PgClosedTradeStore::new(self.pool.clone());
+        // The server's event subscribers are not reachable from here; clients catch up on their
+        // next read
+        let events = EventBus::new();
+        let requester = Requester::new(owner.id, &owner.subscription_plan).with_robot(robot.id);
+        let mut results = on_behalf_of(
+            requester,
+            TradeCloseService::close_batch(&[trade.id], vec![trade.clone()], &closer, &store, &events),
+        )
+        .await;
+        let result = json!(results.remove(0));
+
+        self.audit("trade.close", "trade", Some(trade.id), json!({ "user_id": trade.user_id, "result": result }))
+            .await?;
+        Ok(result)
+    }
+
+    // Robots without open trades have nothing to compare and are not connected to
+    async fn reconcile(&self, user: &str) -> Result<Value> {
+        let user = self.find_user(user).await?;
+        let env = PgRecoveryEnv::new(self.pool.clone(), self.mt5.clone(), self.notifications.clone());
+
+        let mut rows = Vec::new();
+        for robot in TradingRobot::find_by_user_id(&self.pool, user.id).await? {
+            let trades = env.open_trades(&robot).await?;
+            let mut row = ReconcileRow {
+                robot_id: robot.id,
+                robot: robot.name.clone(),
+                open_trades: trades.len(),
+                issues: Some(0),
+                error: None,
+            };
+            if !trades.is_empty() {
+                let requester = Requester::new(user.id, &user.subscription_plan).with_robot(robot.id);
+                let checked = on_behalf_of(requester, async {
+                    let connection_id = env.preflight(&robot).await?;
+                    env.reconcile(&robot, &connection_id, &trades).await
+                })
+                .await;
+                match checked {
+                    Ok(issues) => row.issues = Some(issues),
+                    Err(e) => {
+                        row.issues = None;
+                        row.error = Some(e.to_string());
+                    }
+                }
+            }
+            rows.push(row);
+        }
+
+        let issues: usize = rows.iter().filter_map(|r| r.issues).sum();
+        let failed = rows.iter().filter(|r| r.error.is_some()).count();
+        self.audit("reconcile", "user", Some(user.id), json!({ "robots": rows.len(), "issues": issues, "failed": failed }))
+            .await?;
+        Ok(json!(rows))
+    }
+
+    async fn stats(&self) -> Result<Value> {
+        let counts = sqlx::query_as::<_, PlatformCounts>(&format!(
+            r#"
+            SELECT
+                (SELECT COUNT(*) FROM users) AS users,
+                (SELECT COUNT(*) FROM users WHERE is_active) AS active_users,
+                (SELECT COUNT(*) FROM users WHERE role = 'admin') AS admins,
+                (SELECT COUNT(*) FROM trading_robots) AS robots,
+                (SELECT COUNT(*) FROM trading_robots WHERE status = 'active') AS active_robots,
+                (SELECT COUNT(*) FROM trading_robots WHERE status = '{}') AS errored_robots,
+                (SELECT COUNT(*) FROM trades WHERE status = 'open') AS open_trades,
+                (SELECT COUNT(*) FROM trades WHERE status = 'execution_pending') AS execution_pending_trades
+            "#,
+            RUNNER_ERROR
+        ))
+        .fetch_one(&self.pool)
+        .await
+        .db_op("admin_cli.stats")?;
+
+        self.audit("stats", "platform", None, json!({})).await?;
+        Ok(json!(counts))
+    }
+}
+
+// An object prints as field/value lines, a list of objects as one row each under a header.
+// Nested values are printed as compact JSON.
+pub fn render(output: &Value, format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Json => serde_json::to_string_pretty(output).unwrap_or_default(),
+        OutputFormat::Table => match output {
+            Value::Object(fields) => table(
+                &["field", "value"],
+                fields.iter().map(|(key, value)| vec![key.clone(), cell(value)]).collect(),
+            ),
+            Value::Array(items) if items.is_empty() => "(none)".to_string(),
+            Value::Array(items) => {
+                let columns: Vec<&str> = match &items[0] {
+                    Value::Object(first) => first.keys().map(String::as_str).collect(),
+                    _ => vec!["value"],
+                };
+                let rows = items
+                    .iter()
+                    .map(|item| match item {
+                        Value::Object(fields) => columns.iter().map(|c| fields.get(*c).map(cell).unwrap_or_default()).collect(),
+                        other => vec![cell(other)],
+                    })
+                    .collect();
+                table(&columns, rows)
+            }
+            other => cell(other),
+        },
+    }
+}
+
+fn cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn table(columns: &[&str], rows: Vec<Vec<String>>) -> String {
+    let mut widths: Vec<usize> = columns.iter().map(|c| c.len()).collect();
+    for row in &rows {
+        for (width, value) in widths.iter_mut().zip(row) {
+            *width = (*width).max(value.chars().count());
+        }
+    }
+    let line = |values: Vec<String>| {
+        values
+            .iter()
+            .zip(&widths)
+            .map(|(value, width)| format!("{:<width$}", value, width = width))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+
+    let mut lines = vec![line(columns.iter().map(|c| c.to_uppercase()).collect())];
+    lines.extend(rows.into_iter().map(line));
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_parses_commands_and_flags_in_any_position() {
+        let robot_id = Uuid::new_v4();
+        let invocation = Invocation::parse(&args(&format!("robot --json reset-error {} --yes-i-mean-prod", robot_id))).unwrap();
+        assert_eq!(invocation.command, Command::ResetRobotError(robot_id));
+        assert_eq!(invocation.format, OutputFormat::Json);
+        assert!(invocation.confirm_prod);
+
+        let invocation = Invocation::parse(&args("reconcile --user ops@example.com")).unwrap();
+        assert_eq!(invocation.command, Command::Reconcile("ops@example.com".to_string()));
+        assert_eq!(invocation.format, OutputFormat::Table);
+        assert!(!invocation.confirm_prod);
+
+        assert!(Invocation::parse(&args("trade close 42")).unwrap_err().contains("not a trade id"));
+        assert!(Invocation::parse(&args("user promote")).is_err());
+        assert!(Invocation::parse(&[]).is_err());
+    }
+
+    #[test]
+    fn test_prod_needs_confirmation_and_an_operator() {
+        assert!(check_environment(AppEnv::Prod, false).is_err());
+        assert!(check_environment(AppEnv::Prod, true).is_ok());
+        assert!(check_environment(AppEnv::Dev, false).is_ok());
+
+        assert_eq!(operator_name(Some(" alice ".to_string())).unwrap(), "alice");
+        assert!(operator_name(Some("  ".to_string())).is_err());
+        assert!(operator_name(None).is_err());
+    }
+
+    #[test]
+    fn test_renders_lists_as_aligned_rows() {
+        let output = json!([
+            { "robot": "Trend", "issues": 0, "error": null },
+            { "robot": "Mean reversion", "issues": null, "error": "Broker unavailable" },
+        ]);
+        assert_eq!(
+            render(&output, OutputFormat::Table),
+            "ERROR               ISSUES  ROBOT\n                    0       Trend\nBroker unavailable          Mean reversion"
+        );
+        assert_eq!(render(&json!([]), OutputFormat::Table), "(none)");
+        assert_eq!(render(&json!({ "users": 3 }), OutputFormat::Table), "FIELD  VALUE\nusers  3");
+    }
+}
@@ -5,10 +5,23 @@ use axum::{
     response::Response,
     body::Body,
 };
+use serde::Serialize;
+use schemars::JsonSchema;
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::sync::{Mutex, OnceLock};
+use uuid::Uuid;
 
 use crate::{
-    models::User,
-    services::auth_service::AuthService,
+    models::{Role, User, UserSession, AUTH_EVENT_TOKEN_REJECTED, AUTH_OUTCOME_FAILURE, AUTH_REASON_TOKEN_REVOKED},
+    services::{
+        api_keys::PgApiKeyStore,
+        auth_events,
+        auth_service::{AuthService, Claims},
+        delegation_service::PgDelegationStore,
+        token_revocation,
+        ApiKeyService, DelegationService,
+    },
     errors::AppError,
     AppState,
 };
@@ -18,7 +31,7 @@ pub async fn auth_middleware(
     mut request: Request<Body>,
     next: Next,
 ) -> Result<Response, AppError> {
-    // Extract token from Authorization header
+    // A Bearer token, or for scripts an X-API-Key, which is held to the key's scopes
     let token = request
         .headers()
         .get(AUTHORIZATION)
@@ -29,11 +42,34 @@ pub async fn auth_middleware(
             } else {
                 None
             }
-        })
-        .ok_or_else(|| AppError::Auth("Missing authorization header".to_string()))?;
+        });
+    let api_key = request.headers().get(X_API_KEY).and_then(|header| header.to_str().ok());
 
-    // Verify token and extract user ID
-    let user_id = AuthService::extract_user_id_from_token(token, &state.config.jwt_secret)?;
+    let (user_id, claims, key_access) = match (token, api_key) {
+        (Some(token), _) => {
+            // Verify token and extract user ID, then turn away tokens signed out since they were issued
+            let claims = AuthService::verify_token(token, &state.config.token_settings())?;
+            let user_id = AuthService::user_id(&claims)?;
+            if let Err(e) = token_revocation::check(state.token_revocations.as_ref(), &claims, user_id).await {
+                if matches!(e, AppError::Auth(_)) {
+                    let event = auth_events::event(request.headers(), Some(user_id), AUTH_EVENT_TOKEN_REJECTED, AUTH_OUTCOME_FAILURE);
+                    auth_events::record(state.db.pool(), event.with_reason(AUTH_REASON_TOKEN_REVOKED));
+                }
+                return Err(e);
+            }
+            if let Ok(session_id) = AuthService::token_id(&claims) {
+                UserSession::touch(state.db.pool(), session_id, chrono::Utc::now()).await?;
+            }
+            (user_id, Some(claims), None)
+        }
+        (None, Some(key)) => {
+            let store = PgApiKeyStore::new(state.db.pool().clone());
+            let access =
+                ApiKeyService::authenticate(&store, key, request.method(), request.uri().path(), chrono::Utc::now()).await?;
+            (access.user_id, None, Some(access))
+        }
+        (None, None) => return Err(AppError::Auth("Missing authorization header".to_string())),
+    };
 
     // Fetch user from database
     let user = User::find_by_id(state.db.pool(), user_id)
@@ -45,13 +81,76 @@ pub async fn auth_middleware(
         return Err(AppError::Auth("Account is disabled".to_string()));
     }
 
-    // Add user to request extensions
+    // A delegate reading a grantor's records: handlers see the grantor as the current user
+    let on_behalf_of = request.headers().get(X_ON_BEHALF_OF).map(|v| v.to_str().unwrap_or_default().to_string());
+    let user = match on_behalf_of {
+        Some(grantor) => {
+            let store = PgDelegationStore::new(state.db.pool().clone());
+            let access = DelegationService::authorize(
+                &store,
+                user.id,
+                &grantor,
+                request.method(),
+                request.uri().path(),
+                chrono::Utc::now(),
+            )
+            .await?;
+            let grantor = User::find_by_id(state.db.pool(), access.grantor_id)
+                .await?
+                .filter(|g| g.is_active)
+                .ok_or_else(|| AppError::Forbidden("The delegating account is disabled".to_string()))?;
+            request.extensions_mut().insert(access);
+            grantor
+        }
+        None => user,
+    };
+
+    // Add user to request extensions, with the claims of the token or the key it was authenticated by
     request.extensions_mut().insert(user);
+    if let Some(claims) = claims {
+        if let Some(admin_id) = AuthService::impersonator(&claims)? {
+            request.extensions_mut().insert(Impersonation { admin_id });
+        }
+        request.extensions_mut().insert(claims);
+    }
+    if let Some(access) = key_access {
+        request.extensions_mut().insert(access);
+    }
 
     Ok(next.run(request).await)
 }
 
-pub async fn admin_middleware(
+// Put in the request extensions when the token was minted by an admin impersonating the user
+#[derive(Debug, Clone, Copy)]
+pub struct Impersonation {
+    pub admin_id: Uuid,
+}
+
+// Taken by handlers an impersonating admin must not reach: broker credentials, trading and the
+// account's own security settings. Refuses impersonated requests with 403.
+pub struct NotImpersonated;
+
+#[axum::async_trait]
+impl<S> axum::extract::FromRequestParts<S> for NotImpersonated
+where
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        match parts.extensions.get::<Impersonation>() {
+            Some(_) => Err(AppError::Forbidden("Not available while impersonating a user".to_string())),
+            None => Ok(NotImpersonated),
+        }
+    }
+}
+
+// Layered with the least role a route group needs; roles are ordered, so admins pass support routes
+pub async fn require_role(
+    State(role): State<Role>,
     request: Request<Body>,
     next: Next,
 ) -> Result<Response, AppError> {
@@ -61,9 +160,12 @@ pub async fn admin_middleware(
         .get::<User>()
         .ok_or_else(|| AppError::Auth("Authentication required".to_string()))?;
 
-    // Check if user is admin
-    if !user.is_superuser {
-        return Err(AppError::Forbidden("Admin access required".to_string()));
+    if user.role() < role {
+        let message = match role {
+            Role::Admin => "Admin access required",
+            _ => "Support access required",
+        };
+        return Err(AppError::Forbidden(message.to_string()));
     }
 
     Ok(next.run(request).await)
@@ -88,3 +190,167 @@ where
             .ok_or_else(|| AppError::Auth("Authentication required".to_string()))
     }
 }
+
+#[axum::async_trait]
+impl<S> axum::extract::FromRequestParts<S> for Claims
+where
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<Claims>()
+            .cloned()
+            .ok_or_else(|| AppError::Auth("Authentication required".to_string()))
+    }
+}
+
+pub const X_CLIENT: &str = "x-client";
+pub const X_ON_BEHALF_OF: &str = "x-on-behalf-of";
+pub const X_API_KEY: &str = "x-api-key";
+// Distinct clients tracked in the request counters before the rest are folded into "other"
+const MAX_TRACKED_CLIENTS: usize = 100;
+
+// The X-Client header, e.g. "ios/2.3.1". Anything missing or malformed becomes "unknown".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientInfo(String);
+
+impl ClientInfo {
+    pub const UNKNOWN: &'static str = "unknown";
+
+    pub fn parse(raw: Option<&str>) -> ClientInfo {
+        let valid_part = |part: &str, allowed: fn(char) -> bool| {
+            !part.is_empty() && part.len() <= 32 && part.chars().all(allowed)
+        };
+
+        let parsed = raw.map(str::trim).and_then(|value| {
+            let (name, version) = value.split_once('/')?;
+            let name_ok = valid_part(name, |c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            let version_ok = valid_part(version, |c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '+'));
+            (name_ok && version_ok).then(|| format!("{}/{}", name.to_ascii_lowercase(), version))
+        });
+
+        ClientInfo(parsed.unwrap_or_else(|| Self::UNKNOWN.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Default for ClientInfo {
+    fn default() -> Self {
+        ClientInfo(Self::UNKNOWN.to_string())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct ClientRequestCount {
+    pub client: String,
+    pub count: u64,
+}
+
+fn client_request_counter() -> &'static Mutex<BTreeMap<String, u64>> {
+    static COUNTER: OnceLock<Mutex<BTreeMap<String, u64>>> = OnceLock::new();
+    COUNTER.get_or_init(|| Mutex::new(BTreeMap::new()))
+}
+
+fn record_client_request(client: &ClientInfo) {
+    let mut counts = client_request_counter().lock().unwrap();
+    let label = if counts.contains_key(client.as_str()) || counts.len() < MAX_TRACKED_CLIENTS {
+        client.as_str()
+    } else {
+        "other"
+    };
+    *counts.entry(label.to_string()).or_insert(0) += 1;
+}
+
+// Requests served per client
+pub fn request_counts_by_client() -> Vec<ClientRequestCount> {
+    client_request_counter()
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(client, count)| ClientRequestCount { client: client.clone(), count: *count })
+        .collect()
+}
+
+pub async fn client_middleware(mut request: Request<Body>, next: Next) -> Response {
+    let client = ClientInfo::parse(request.headers().get(X_CLIENT).and_then(|v| v.to_str().ok()));
+    record_client_request(&client);
+    request.extensions_mut().insert(client);
+    next.run(request).await
+}
+
+#[axum::async_trait]
+impl<S> axum::extract::FromRequestParts<S> for ClientInfo
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(parts.extensions.get::<ClientInfo>().cloned().unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{middleware, routing::get, Router};
+    use tower::Service;
+
+    fn count_for(client: &str) -> u64 {
+        request_counts_by_client()
+            .into_iter()
+            .find(|c| c.client == client)
+            .map(|c| c.count)
+            .unwrap_or(0)
+    }
+
+    async fn echo_client(header: Option<&str>) -> String {
+        let mut app = Router::new()
+            .route("/", get(|client: ClientInfo| async move { client.as_str().to_string() }))
+            .layer(middleware::from_fn(client_middleware));
+
+        let mut request = Request::builder().uri("/");
+        if let Some(value) = header {
+            request = request.header(X_CLIENT, value);
+        }
+        // Router is always ready, so it can be called without polling readiness first
+        let response = app.call(request.body(Body::empty()).unwrap()).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_client_header_format() {
+        assert_eq!(ClientInfo::parse(Some("iOS/2.3.1")).as_str(), "ios/2.3.1");
+        assert_eq!(ClientInfo::parse(Some("web/1.0.0-beta+7")).as_str(), "web/1.0.0-beta+7");
+        assert_eq!(ClientInfo::parse(Some("api-python/0.4")).as_str(), "api-python/0.4");
+        assert_eq!(ClientInfo::parse(None).as_str(), "unknown");
+        assert_eq!(ClientInfo::parse(Some("")).as_str(), "unknown");
+        assert_eq!(ClientInfo::parse(Some("web")).as_str(), "unknown");
+        assert_eq!(ClientInfo::parse(Some("web/1.0 <script>")).as_str(), "unknown");
+        assert_eq!(ClientInfo::parse(Some(&format!("web/{}", "1".repeat(40)))).as_str(), "unknown");
+    }
+
+    #[tokio::test]
+    async fn test_header_reaches_handlers_and_request_counter() {
+        let before = count_for("android/5.1.0");
+        assert_eq!(echo_client(Some("android/5.1.0")).await, "android/5.1.0");
+        assert_eq!(count_for("android/5.1.0"), before + 1);
+
+        assert_eq!(echo_client(None).await, "unknown");
+        assert_eq!(echo_client(Some("not a client")).await, "unknown");
+        assert!(count_for("unknown") >= 2);
+    }
+}
@@ -0,0 +1,64 @@
+// Operator tasks from a shell; see `admin_cli::USAGE`. Reads the same configuration as the
+// server, so point it at an environment the way the server is pointed at one.
+use std::sync::Arc;
+
+use trading_saas_backend::{
+    admin_cli::{self, AdminCli, Invocation, OPERATOR_ENV, USAGE},
+    config::Config,
+    database::Database,
+    services::{
+        credential_vault::{KeyRing, PgCredentialStore},
+        CredentialVault, Mt5Service, NotificationService,
+    },
+};
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    // Output goes to stdout; logs, audit lines included, to stderr
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| "warn,audit=info".into()),
+        )
+        .with_writer(std::io::stderr)
+        .init();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+    let invocation = match Invocation::parse(&args) {
+        Ok(invocation) => invocation,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let config = Config::from_env()?;
+    admin_cli::check_environment(config.app_env, invocation.confirm_prod).map_err(anyhow::Error::msg)?;
+    let operator = admin_cli::operator_name(std::env::var(OPERATOR_ENV).ok()).map_err(anyhow::Error::msg)?;
+
+    // Migrations are left to the server or its `--migrate-only` step
+    let db = Database::new(
+        &config.database_url,
+        std::time::Duration::from_millis(config.db_statement_timeout_ms),
+        std::time::Duration::from_millis(config.db_export_statement_timeout_ms),
+    )
+    .await?;
+    let credentials = Arc::new(CredentialVault::new(
+        KeyRing::new(&config.encryption_key_id, &config.encryption_keys)?,
+        Arc::new(PgCredentialStore::new(db.pool().clone())),
+    ));
+    let mt5 = Arc::new(Mt5Service::new().with_credentials(credentials));
+    let notifications = Arc::new(NotificationService::new(
+        config.smtp_host.clone(),
+        config.smtp_user.clone(),
+        config.smtp_password.clone(),
+    ));
+
+    let cli = AdminCli::new(db.pool().clone(), mt5, notifications, &operator);
+    let output = cli.run(&invocation.command).await?;
+    println!("{}", admin_cli::render(&output, invocation.format));
+    Ok(())
+}
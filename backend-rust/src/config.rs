@@ -1,48 +1,731 @@
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::env;
 
+use crate::database::{DEFAULT_EXPORT_STATEMENT_TIMEOUT, DEFAULT_STATEMENT_TIMEOUT};
+use crate::services::activation_nudges::{DEFAULT_IDLE_ROBOT_DAYS, DEFAULT_NUDGE_MONTHLY_CAP};
+use crate::services::auth_service::{
+    TokenSettings, DEFAULT_TOKEN_AUDIENCE, DEFAULT_TOKEN_ISSUER, DEFAULT_TOKEN_LIFETIME_MINUTES,
+};
+use crate::services::broker_throttle::{BrokerRateLimit, DEFAULT_MAX_QUEUE_DEPTH};
+use crate::services::credential_vault::KeyRing;
+use crate::services::deletion_purge::DEFAULT_DELETED_RETENTION_DAYS;
+use crate::services::leaderboard::DEFAULT_LEADERBOARD_MIN_TRADES;
+use crate::services::order_drain::DEFAULT_ORDER_DRAIN_SECONDS;
+use crate::services::password_policy::{
+    PasswordPolicy, DEFAULT_BREACH_CHECK_TIMEOUT_MS, DEFAULT_MAX_PASSWORD_LENGTH, DEFAULT_MIN_PASSWORD_LENGTH, HIBP_RANGE_URL,
+};
+use crate::services::apple_identity::APPLE_KEYS_URL;
+use crate::services::github_identity::GITHUB_API_URL;
+use crate::services::google_identity::GOOGLE_TOKENINFO_URL;
+use crate::services::stripe_service::MOCK_STRIPE_SECRET_KEY;
+use crate::services::login_throttle::LoginThrottlePolicy;
+use crate::services::trade_origins::DEFAULT_ORIGIN_RETENTION_DAYS;
+use crate::services::ws_shedding::DEFAULT_SHED_WATERMARK_PERCENT;
+use crate::telemetry::{TelemetryConfig, DEFAULT_SERVICE_NAME};
+use crate::services::websocket_manager::{
+    DEFAULT_BATCH_WINDOW, DEFAULT_GLOBAL_CHANNEL_CAPACITY, DEFAULT_USER_CHANNEL_CAPACITY,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AppEnv {
+    Dev,
+    Test,
+    Prod,
+}
+
+impl AppEnv {
+    pub fn parse(raw: Option<&str>) -> anyhow::Result<AppEnv> {
+        match raw.map(|v| v.trim().to_lowercase()).as_deref() {
+            None | Some("") | Some("dev") | Some("development") => Ok(AppEnv::Dev),
+            Some("test") => Ok(AppEnv::Test),
+            Some("prod") | Some("production") => Ok(AppEnv::Prod),
+            Some(other) => anyhow::bail!("Invalid APP_ENV '{}', expected dev, test or prod", other),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AppEnv::Dev => "dev",
+            AppEnv::Test => "test",
+            AppEnv::Prod => "prod",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
+    pub app_env: AppEnv,
     pub server_address: String,
+    // Where links in emails point to
+    pub public_base_url: String,
     pub database_url: String,
     pub redis_url: String,
     pub jwt_secret: String,
+    // Lifetime of issued tokens, and the iss/aud they carry and must carry to be accepted
+    pub jwt_expiry_minutes: i64,
+    pub jwt_issuer: String,
+    pub jwt_audience: String,
+    // Refuse tokens issued before iss/aud/jti; turn on once those have expired
+    pub jwt_require_claims: bool,
+    // Broker credentials are encrypted with the key named by encryption_key_id; the others in
+    // encryption_keys (id to hex key) are only used to read rows not yet rotated
+    pub encryption_key_id: String,
+    pub encryption_keys: HashMap<String, String>,
     pub stripe_secret_key: String,
     pub stripe_publishable_key: String,
+    // Signs webhook deliveries (whsec_...)
+    pub stripe_webhook_secret: String,
+    // Plan name -> Stripe price id used by checkout sessions
+    pub stripe_price_ids: HashMap<String, String>,
+    // Where Stripe Checkout sends the customer back to
+    pub checkout_success_url: String,
+    pub checkout_cancel_url: String,
     pub mt5_login: Option<String>,
     pub mt5_password: Option<String>,
     pub mt5_server: Option<String>,
     pub smtp_host: Option<String>,
     pub smtp_user: Option<String>,
     pub smtp_password: Option<String>,
+    // Receives alerts when a critical background task panics
+    pub admin_alert_email: Option<String>,
+    // Last-resort rates API for indicative quotes when no broker session has the symbol
+    pub quote_fallback_url: Option<String>,
+    pub quote_fallback_api_key: Option<String>,
     pub model_path: String,
+    pub broker_rate_limits: HashMap<String, BrokerRateLimit>,
+    pub broker_max_queue_wait_ms: u64,
+    // Calls queued per broker connection before new ones are turned away
+    pub broker_max_queue_depth: usize,
+    pub ws_user_channel_capacity: usize,
+    pub ws_global_channel_capacity: usize,
+    // Bursts of robot_status and trade_update within this window go out as one batch; 0 disables
+    pub ws_batch_window_ms: u64,
+    // Queue depth, as a percentage of channel capacity, past which market data is shed
+    pub ws_shed_watermark_percent: u8,
+    // Days without a start or a signal before a robot counts as idle, and nudges per user per month
+    pub idle_robot_days: i64,
+    pub nudge_monthly_cap: i64,
+    // Live trades a robot needs in a week to be ranked on the public leaderboard
+    pub leaderboard_min_trades: i64,
+    // How long shutdown waits for broker orders in flight before leaving them to restart recovery
+    pub order_drain_seconds: u64,
+    // Postgres statement_timeout for request connections and for the export/job pool
+    pub db_statement_timeout_ms: u64,
+    pub db_export_statement_timeout_ms: u64,
+    // Empty means any origin is allowed, which is refused in prod
+    pub cors_allowed_origins: Vec<String>,
+    // Step the public stats counts are floored to
+    pub public_stats_round_to: i64,
+    // Replicas that leave migrations to a separate `--migrate-only` step
+    pub skip_migrations: bool,
+    // How long a replica waits for another one to finish migrating
+    pub migration_lock_timeout_secs: u64,
+    // Refuse destructive migrations unless applied with `--migrate-only --allow-destructive`
+    pub strict_migrations: bool,
+    pub password_policy: PasswordPolicy,
+    // Range API the breach check asks when the policy enables it, and how long it may take
+    pub password_breach_check_url: String,
+    pub password_breach_check_timeout_ms: u64,
+    // Days the raw payload behind a trade is kept for disputes
+    pub trade_origin_retention_days: i64,
+    // Days deleted users, robots and broker connections are kept, with their trades, before the
+    // purge job removes them
+    pub deleted_retention_days: i64,
+    // OpenTelemetry trace export; off without an endpoint
+    pub telemetry: TelemetryConfig,
+    // Failed logins before a 429, and before the account is locked
+    pub login_throttle: LoginThrottlePolicy,
+    // Google ID tokens must be issued to this OAuth client; without it Google sign-in is off
+    pub google_client_id: Option<String>,
+    pub google_tokeninfo_url: String,
+    // GitHub access tokens must belong to this OAuth app; without both GitHub sign-in is off
+    pub github_client_id: Option<String>,
+    pub github_client_secret: Option<String>,
+    pub github_api_url: String,
+    // Apple identity tokens must carry this services id (or bundle id); unset turns Apple sign-in off
+    pub apple_client_id: Option<String>,
+    pub apple_keys_url: String,
 }
 
+const DEV_JWT_SECRET: &str = "dev-insecure-jwt-secret";
+const DEV_ENCRYPTION_KEY: &str = "6465762d696e7365637572652d656e6372797074696f6e2d6b65792d30303031";
+const MOCK_STRIPE_PUBLISHABLE_KEY: &str = "pk_test_mock";
+const MOCK_STRIPE_WEBHOOK_SECRET: &str = "whsec_mock";
+const MOCK_STRIPE_PRICE_IDS: &str = "essential=price_mock_essential,pro=price_mock_pro,elite=price_mock_elite";
+
 impl Config {
     pub fn from_env() -> anyhow::Result<Self> {
         dotenvy::dotenv().ok();
 
-        Ok(Config {
-            server_address: env::var("SERVER_ADDRESS")
-                .unwrap_or_else(|_| "0.0.0.0:8000".to_string()),
-            database_url: env::var("DATABASE_URL")
-                .expect("DATABASE_URL must be set"),
-            redis_url: env::var("REDIS_URL")
-                .unwrap_or_else(|_| "redis://localhost:6379".to_string()),
-            jwt_secret: env::var("JWT_SECRET_KEY")
-                .expect("JWT_SECRET_KEY must be set"),
-            stripe_secret_key: env::var("STRIPE_SECRET_KEY")
-                .expect("STRIPE_SECRET_KEY must be set"),
-            stripe_publishable_key: env::var("STRIPE_PUBLISHABLE_KEY")
-                .expect("STRIPE_PUBLISHABLE_KEY must be set"),
-            mt5_login: env::var("MT5_LOGIN").ok(),
-            mt5_password: env::var("MT5_PASSWORD").ok(),
-            mt5_server: env::var("MT5_SERVER").ok(),
-            smtp_host: env::var("SMTP_HOST").ok(),
-            smtp_user: env::var("SMTP_USER").ok(),
-            smtp_password: env::var("SMTP_PASSWORD").ok(),
-            model_path: env::var("MODEL_PATH")
-                .unwrap_or_else(|_| "../model/trading_model.onnx".to_string()),
-        })
+        let app_env = AppEnv::parse(env::var("APP_ENV").ok().as_deref())?;
+        Self::from_lookup(app_env, |key| env::var(key).ok())
+    }
+
+    // Test profile defaults only; never reads the process environment or .env
+    pub fn for_tests() -> Self {
+        Self::from_lookup(AppEnv::Test, |_| None).expect("test profile needs no environment")
+    }
+
+    fn from_lookup(app_env: AppEnv, lookup: impl Fn(&str) -> Option<String>) -> anyhow::Result<Self> {
+        let var = |key: &str| lookup(key).filter(|v| !v.trim().is_empty());
+
+        // Values every profile can live without in dev and test, but prod must set explicitly
+        let with_default = |key: &str, dev: &str, test: &str| -> anyhow::Result<String> {
+            match (var(key), app_env) {
+                (Some(value), _) => Ok(value),
+                (None, AppEnv::Dev) => Ok(dev.to_string()),
+                (None, AppEnv::Test) => Ok(test.to_string()),
+                (None, AppEnv::Prod) => anyhow::bail!("{} must be set when APP_ENV=prod", key),
+            }
+        };
+
+        // Checked first, so an empty prod environment names the database before anything else
+        let database_url = with_default(
+            "DATABASE_URL",
+            "postgres://localhost:5432/trading_saas",
+            "postgres://localhost:5432/trading_saas_test",
+        )?;
+        let encryption_key_id = var("ENCRYPTION_KEY_ID").unwrap_or_else(|| "k1".to_string());
+        let mut encryption_keys = parse_encryption_keys(&var("ENCRYPTION_PREVIOUS_KEYS").unwrap_or_default())?;
+        encryption_keys.insert(
+            encryption_key_id.clone(),
+            with_default("ENCRYPTION_KEY", DEV_ENCRYPTION_KEY, DEV_ENCRYPTION_KEY)?,
+        );
+        KeyRing::new(&encryption_key_id, &encryption_keys)?;
+
+        let config = Config {
+            app_env,
+            server_address: var("SERVER_ADDRESS")
+                .unwrap_or_else(|| "0.0.0.0:8000".to_string()),
+            database_url,
+            redis_url: var("REDIS_URL")
+                .unwrap_or_else(|| "redis://localhost:6379".to_string()),
+            jwt_secret: with_default("JWT_SECRET_KEY", DEV_JWT_SECRET, DEV_JWT_SECRET)?,
+            jwt_expiry_minutes: var("JWT_EXPIRY_MINUTES")
+                .and_then(|v| v.parse().ok())
+                .filter(|v| *v > 0)
+                .unwrap_or(DEFAULT_TOKEN_LIFETIME_MINUTES),
+            jwt_issuer: var("JWT_ISSUER").unwrap_or_else(|| DEFAULT_TOKEN_ISSUER.to_string()),
+            jwt_audience: var("JWT_AUDIENCE").unwrap_or_else(|| DEFAULT_TOKEN_AUDIENCE.to_string()),
+            jwt_require_claims: var("JWT_REQUIRE_CLAIMS")
+                .map(|v| matches!(v.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+                .unwrap_or(false),
+            encryption_key_id,
+            encryption_keys,
+            stripe_secret_key: with_default("STRIPE_SECRET_KEY", MOCK_STRIPE_SECRET_KEY, MOCK_STRIPE_SECRET_KEY)?,
+            stripe_publishable_key: with_default(
+                "STRIPE_PUBLISHABLE_KEY",
+                MOCK_STRIPE_PUBLISHABLE_KEY,
+                MOCK_STRIPE_PUBLISHABLE_KEY,
+            )?,
+            stripe_webhook_secret: with_default("STRIPE_WEBHOOK_SECRET", MOCK_STRIPE_WEBHOOK_SECRET, MOCK_STRIPE_WEBHOOK_SECRET)?,
+            stripe_price_ids: parse_stripe_price_ids(&with_default(
+                "STRIPE_PRICE_IDS",
+                MOCK_STRIPE_PRICE_IDS,
+                MOCK_STRIPE_PRICE_IDS,
+            )?)?,
+            checkout_success_url: with_default(
+                "CHECKOUT_SUCCESS_URL",
+                "http://localhost:3000/billing/success?session_id={CHECKOUT_SESSION_ID}",
+                "http://localhost:3000/billing/success?session_id={CHECKOUT_SESSION_ID}",
+            )?,
+            checkout_cancel_url: with_default(
+                "CHECKOUT_CANCEL_URL",
+                "http://localhost:3000/billing/cancel",
+                "http://localhost:3000/billing/cancel",
+            )?,
+            public_base_url: with_default("PUBLIC_BASE_URL", "http://localhost:8000", "http://localhost:8000")?,
+            mt5_login: var("MT5_LOGIN"),
+            mt5_password: var("MT5_PASSWORD"),
+            mt5_server: var("MT5_SERVER"),
+            smtp_host: var("SMTP_HOST"),
+            smtp_user: var("SMTP_USER"),
+            smtp_password: var("SMTP_PASSWORD"),
+            admin_alert_email: var("ADMIN_ALERT_EMAIL"),
+            quote_fallback_url: var("QUOTE_FALLBACK_URL"),
+            quote_fallback_api_key: var("QUOTE_FALLBACK_API_KEY"),
+            model_path: var("MODEL_PATH")
+                .unwrap_or_else(|| "../model/trading_model.onnx".to_string()),
+            broker_rate_limits: parse_broker_rate_limits(
+                &var("BROKER_RATE_LIMITS").unwrap_or_default(),
+            )?,
+            broker_max_queue_wait_ms: var("BROKER_MAX_QUEUE_WAIT_MS")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(5000),
+            broker_max_queue_depth: var("BROKER_MAX_QUEUE_DEPTH")
+                .and_then(|v| v.parse().ok())
+                .filter(|v| *v > 0)
+                .unwrap_or(DEFAULT_MAX_QUEUE_DEPTH),
+            ws_user_channel_capacity: var("WS_USER_CHANNEL_CAPACITY")
+                .and_then(|v| v.parse().ok())
+                .filter(|v| *v > 0)
+                .unwrap_or(DEFAULT_USER_CHANNEL_CAPACITY),
+            ws_global_channel_capacity: var("WS_GLOBAL_CHANNEL_CAPACITY")
+                .and_then(|v| v.parse().ok())
+                .filter(|v| *v > 0)
+                .unwrap_or(DEFAULT_GLOBAL_CHANNEL_CAPACITY),
+            ws_batch_window_ms: var("WS_BATCH_WINDOW_MS")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_BATCH_WINDOW.as_millis() as u64),
+            ws_shed_watermark_percent: var("WS_SHED_WATERMARK_PERCENT")
+                .and_then(|v| v.parse().ok())
+                .filter(|v| (1..=100).contains(v))
+                .unwrap_or(DEFAULT_SHED_WATERMARK_PERCENT),
+            idle_robot_days: var("IDLE_ROBOT_DAYS")
+                .and_then(|v| v.parse().ok())
+                .filter(|v| *v > 0)
+                .unwrap_or(DEFAULT_IDLE_ROBOT_DAYS),
+            nudge_monthly_cap: var("NUDGE_MONTHLY_CAP")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_NUDGE_MONTHLY_CAP),
+            leaderboard_min_trades: var("LEADERBOARD_MIN_TRADES")
+                .and_then(|v| v.parse().ok())
+                .filter(|v| *v > 0)
+                .unwrap_or(DEFAULT_LEADERBOARD_MIN_TRADES),
+            order_drain_seconds: var("ORDER_DRAIN_SECONDS")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_ORDER_DRAIN_SECONDS),
+            db_statement_timeout_ms: var("DB_STATEMENT_TIMEOUT_MS")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_STATEMENT_TIMEOUT.as_millis() as u64),
+            db_export_statement_timeout_ms: var("DB_EXPORT_STATEMENT_TIMEOUT_MS")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_EXPORT_STATEMENT_TIMEOUT.as_millis() as u64),
+            cors_allowed_origins: var("CORS_ALLOWED_ORIGINS")
+                .map(|v| {
+                    v.split(',')
+                        .map(str::trim)
+                        .filter(|o| !o.is_empty())
+                        .map(String::from)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            public_stats_round_to: var("PUBLIC_STATS_ROUND_TO")
+                .and_then(|v| v.parse().ok())
+                .filter(|v| *v > 0)
+                .unwrap_or(100),
+            skip_migrations: var("SKIP_MIGRATIONS")
+                .map(|v| matches!(v.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+                .unwrap_or(false),
+            migration_lock_timeout_secs: var("MIGRATION_LOCK_TIMEOUT_SECS")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(300),
+            strict_migrations: var("STRICT_MIGRATIONS")
+                .map(|v| matches!(v.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+                .unwrap_or(false),
+            password_policy: PasswordPolicy {
+                min_length: var("PASSWORD_MIN_LENGTH")
+                    .and_then(|v| v.parse().ok())
+                    .filter(|v| *v > 0)
+                    .unwrap_or(DEFAULT_MIN_PASSWORD_LENGTH),
+                max_length: var("PASSWORD_MAX_LENGTH")
+                    .and_then(|v| v.parse().ok())
+                    .filter(|v| *v > 0)
+                    .unwrap_or(DEFAULT_MAX_PASSWORD_LENGTH),
+                require_character_classes: var("PASSWORD_REQUIRE_CHARACTER_CLASSES")
+                    .map(|v| matches!(v.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+                    .unwrap_or(false),
+                reject_email_local_part: var("PASSWORD_REJECT_EMAIL_LOCAL_PART")
+                    .map(|v| matches!(v.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+                    .unwrap_or(true),
+                breach_check: var("PASSWORD_BREACH_CHECK")
+                    .map(|v| matches!(v.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+                    .unwrap_or(false),
+            },
+            password_breach_check_url: var("PASSWORD_BREACH_CHECK_URL").unwrap_or_else(|| HIBP_RANGE_URL.to_string()),
+            password_breach_check_timeout_ms: var("PASSWORD_BREACH_CHECK_TIMEOUT_MS")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_BREACH_CHECK_TIMEOUT_MS),
+            trade_origin_retention_days: var("TRADE_ORIGIN_RETENTION_DAYS")
+                .and_then(|v| v.parse().ok())
+                .filter(|v| *v > 0)
+                .unwrap_or(DEFAULT_ORIGIN_RETENTION_DAYS),
+            deleted_retention_days: var("DELETED_RETENTION_DAYS")
+                .and_then(|v| v.parse().ok())
+                .filter(|v| *v > 0)
+                .unwrap_or(DEFAULT_DELETED_RETENTION_DAYS),
+            telemetry: TelemetryConfig {
+                otlp_endpoint: var("OTEL_EXPORTER_OTLP_ENDPOINT"),
+                service_name: var("OTEL_SERVICE_NAME").unwrap_or_else(|| DEFAULT_SERVICE_NAME.to_string()),
+                sample_ratio: var("OTEL_TRACES_SAMPLER_ARG")
+                    .and_then(|v| v.parse().ok())
+                    .filter(|v: &f64| (0.0..=1.0).contains(v))
+                    .unwrap_or(1.0),
+            },
+            login_throttle: {
+                let defaults = LoginThrottlePolicy::default();
+                let positive = |key: &str, default: u64| var(key).and_then(|v| v.parse().ok()).filter(|v| *v > 0).unwrap_or(default);
+                LoginThrottlePolicy {
+                    window_seconds: positive("LOGIN_FAILURE_WINDOW_SECONDS", defaults.window_seconds),
+                    max_failures_per_email: positive("LOGIN_MAX_FAILURES_PER_EMAIL", defaults.max_failures_per_email),
+                    max_failures_per_ip: positive("LOGIN_MAX_FAILURES_PER_IP", defaults.max_failures_per_ip),
+                    lockout_threshold: positive("LOGIN_LOCKOUT_THRESHOLD", defaults.lockout_threshold),
+                    lockout_seconds: positive("LOGIN_LOCKOUT_SECONDS", defaults.lockout_seconds),
+                }
+            },
+            google_client_id: var("GOOGLE_CLIENT_ID"),
+            google_tokeninfo_url: var("GOOGLE_TOKENINFO_URL").unwrap_or_else(|| GOOGLE_TOKENINFO_URL.to_string()),
+            github_client_id: var("GITHUB_CLIENT_ID"),
+            github_client_secret: var("GITHUB_CLIENT_SECRET"),
+            github_api_url: var("GITHUB_API_URL").unwrap_or_else(|| GITHUB_API_URL.to_string()),
+            apple_client_id: var("APPLE_CLIENT_ID"),
+            apple_keys_url: var("APPLE_KEYS_URL").unwrap_or_else(|| APPLE_KEYS_URL.to_string()),
+        };
+
+        if config.password_policy.min_length > config.password_policy.max_length {
+            anyhow::bail!(
+                "PASSWORD_MIN_LENGTH ({}) is above PASSWORD_MAX_LENGTH ({})",
+                config.password_policy.min_length,
+                config.password_policy.max_length
+            );
+        }
+
+        if config.github_client_id.is_some() != config.github_client_secret.is_some() {
+            anyhow::bail!("GITHUB_CLIENT_ID and GITHUB_CLIENT_SECRET must be set together");
+        }
+
+        if app_env == AppEnv::Prod {
+            config.check_prod_safety()?;
+        }
+
+        Ok(config)
+    }
+
+    pub fn token_settings(&self) -> TokenSettings<'_> {
+        TokenSettings {
+            secret: &self.jwt_secret,
+            lifetime: chrono::Duration::minutes(self.jwt_expiry_minutes),
+            issuer: &self.jwt_issuer,
+            audience: &self.jwt_audience,
+            require_claims: self.jwt_require_claims,
+        }
+    }
+
+    // Subsystems that run against a stand-in instead of the real service
+    pub fn mock_subsystems(&self) -> Vec<&'static str> {
+        let mut mocks = Vec::new();
+        if self.stripe_secret_key.starts_with(MOCK_STRIPE_SECRET_KEY) {
+            mocks.push("stripe");
+        }
+        if self.smtp_host.is_none() {
+            mocks.push("smtp (log only)");
+        }
+        if self.cors_allowed_origins.is_empty() {
+            mocks.push("cors (permissive)");
+        }
+        mocks
+    }
+
+    fn check_prod_safety(&self) -> anyhow::Result<()> {
+        let mut problems = Vec::new();
+        if self.stripe_secret_key.starts_with(MOCK_STRIPE_SECRET_KEY) {
+            problems.push("STRIPE_SECRET_KEY is a mock key");
+        }
+        if self.stripe_publishable_key == MOCK_STRIPE_PUBLISHABLE_KEY {
+            problems.push("STRIPE_PUBLISHABLE_KEY is a mock key");
+        }
+        if self.stripe_webhook_secret == MOCK_STRIPE_WEBHOOK_SECRET {
+            problems.push("STRIPE_WEBHOOK_SECRET is a mock secret");
+        }
+        if self.jwt_secret == DEV_JWT_SECRET {
+            problems.push("JWT_SECRET_KEY is the development secret");
+        }
+        if self.encryption_keys.get(&self.encryption_key_id).map(String::as_str) == Some(DEV_ENCRYPTION_KEY) {
+            problems.push("ENCRYPTION_KEY is the development key");
+        }
+        if self.smtp_host.is_none() {
+            problems.push("SMTP_HOST is not set, emails would only be logged");
+        }
+        if self.cors_allowed_origins.is_empty() || self.cors_allowed_origins.iter().any(|o| o == "*") {
+            problems.push("CORS_ALLOWED_ORIGINS must list explicit origins");
+        }
+
+        if !problems.is_empty() {
+            anyhow::bail!("Refusing to start with APP_ENV=prod: {}", problems.join("; "));
+        }
+        Ok(())
+    }
+}
+
+// Format: "mt5=5:10,paper=50:100" (broker_type=requests_per_second:burst)
+fn parse_broker_rate_limits(raw: &str) -> anyhow::Result<HashMap<String, BrokerRateLimit>> {
+    let mut limits = HashMap::new();
+
+    for entry in raw.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let (broker_type, limit) = entry
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Invalid BROKER_RATE_LIMITS entry: {}", entry))?;
+        let (rate, burst) = limit
+            .split_once(':')
+            .ok_or_else(|| anyhow::anyhow!("Invalid BROKER_RATE_LIMITS entry: {}", entry))?;
+
+        let requests_per_second: f64 = rate.trim().parse()?;
+        let burst: u32 = burst.trim().parse()?;
+        if requests_per_second <= 0.0 || burst == 0 {
+            anyhow::bail!("BROKER_RATE_LIMITS values must be positive: {}", entry);
+        }
+
+        limits.insert(
+            broker_type.trim().to_lowercase(),
+            BrokerRateLimit { requests_per_second, burst },
+        );
+    }
+
+    Ok(limits)
+}
+
+// Format: "k1=<hex key>,k0=<hex key>" (key_id=key), keys being 32 bytes
+fn parse_encryption_keys(raw: &str) -> anyhow::Result<HashMap<String, String>> {
+    let mut keys = HashMap::new();
+
+    for entry in raw.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let (key_id, key) = entry
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Invalid ENCRYPTION_PREVIOUS_KEYS entry, expected key_id=key"))?;
+        let key_id = key_id.trim();
+        if key_id.is_empty() {
+            anyhow::bail!("ENCRYPTION_PREVIOUS_KEYS entries need a key id");
+        }
+        keys.insert(key_id.to_string(), key.trim().to_string());
+    }
+
+    Ok(keys)
+}
+
+// Format: "essential=price_123,pro=price_456" (plan_name=price_id)
+fn parse_stripe_price_ids(raw: &str) -> anyhow::Result<HashMap<String, String>> {
+    let mut prices = HashMap::new();
+
+    for entry in raw.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let (plan, price_id) = entry
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Invalid STRIPE_PRICE_IDS entry: {}", entry))?;
+        let (plan, price_id) = (plan.trim().to_lowercase(), price_id.trim());
+        if plan.is_empty() || price_id.is_empty() {
+            anyhow::bail!("Invalid STRIPE_PRICE_IDS entry: {}", entry);
+        }
+        prices.insert(plan, price_id.to_string());
+    }
+
+    Ok(prices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        move |key| vars.get(key).cloned()
+    }
+
+    fn prod_vars() -> Vec<(&'static str, &'static str)> {
+        vec![
+            ("DATABASE_URL", "postgres://db/trading"),
+            ("JWT_SECRET_KEY", "a-real-secret"),
+            ("STRIPE_SECRET_KEY", "sk_live_123"),
+            ("STRIPE_PUBLISHABLE_KEY", "pk_live_123"),
+            ("SMTP_HOST", "smtp.example.com"),
+            ("CORS_ALLOWED_ORIGINS", "https://app.example.com"),
+            ("PUBLIC_BASE_URL", "https://api.example.com"),
+            ("STRIPE_WEBHOOK_SECRET", "whsec_live_123"),
+            ("STRIPE_PRICE_IDS", "essential=price_e,pro=price_p,elite=price_x"),
+            ("CHECKOUT_SUCCESS_URL", "https://app.example.com/billing/success"),
+            ("CHECKOUT_CANCEL_URL", "https://app.example.com/billing/cancel"),
+            ("ENCRYPTION_KEY", "7f3c9a1e5b2d8046c1f7e9a3b5d2c8e0417a6f3b9c2e5d8a1b4f7c0e3a6d9b2c"),
+        ]
+    }
+
+    #[test]
+    fn test_app_env_parsing() {
+        assert_eq!(AppEnv::parse(None).unwrap(), AppEnv::Dev);
+        assert_eq!(AppEnv::parse(Some("PROD")).unwrap(), AppEnv::Prod);
+        assert_eq!(AppEnv::parse(Some("test")).unwrap(), AppEnv::Test);
+        assert!(AppEnv::parse(Some("staging")).is_err());
+    }
+
+    #[test]
+    fn test_dev_falls_back_to_mock_subsystems() {
+        let config = Config::from_lookup(AppEnv::Dev, lookup(&[])).unwrap();
+
+        assert!(config.stripe_secret_key.starts_with(MOCK_STRIPE_SECRET_KEY));
+        assert!(config.smtp_host.is_none());
+        assert_eq!(
+            config.mock_subsystems(),
+            vec!["stripe", "smtp (log only)", "cors (permissive)"]
+        );
+    }
+
+    #[test]
+    fn test_prod_refuses_mock_keys() {
+        let mut vars = prod_vars();
+        vars[2] = ("STRIPE_SECRET_KEY", "sk_test_mock_123");
+
+        let err = Config::from_lookup(AppEnv::Prod, lookup(&vars)).unwrap_err().to_string();
+        assert!(err.contains("STRIPE_SECRET_KEY is a mock key"), "{}", err);
+    }
+
+    #[test]
+    fn test_prod_refuses_permissive_cors_and_missing_values() {
+        let vars: Vec<_> = prod_vars().into_iter().filter(|(k, _)| *k != "CORS_ALLOWED_ORIGINS").collect();
+        let err = Config::from_lookup(AppEnv::Prod, lookup(&vars)).unwrap_err().to_string();
+        assert!(err.contains("CORS_ALLOWED_ORIGINS"), "{}", err);
+
+        let err = Config::from_lookup(AppEnv::Prod, lookup(&[])).unwrap_err().to_string();
+        assert!(err.contains("DATABASE_URL must be set"), "{}", err);
+    }
+
+    #[test]
+    fn test_prod_accepts_real_settings() {
+        let config = Config::from_lookup(AppEnv::Prod, lookup(&prod_vars())).unwrap();
+        assert!(config.mock_subsystems().is_empty());
+        assert_eq!(config.cors_allowed_origins, vec!["https://app.example.com"]);
+        assert_eq!(config.stripe_price_ids.get("pro").map(String::as_str), Some("price_p"));
+    }
+
+    #[test]
+    fn test_encryption_key_ring_includes_previous_keys() {
+        let previous_key = "ab".repeat(32);
+        let previous = format!("k1={}", previous_key);
+        let mut vars = prod_vars();
+        vars.push(("ENCRYPTION_KEY_ID", "k2"));
+        vars.push(("ENCRYPTION_PREVIOUS_KEYS", &previous));
+
+        let config = Config::from_lookup(AppEnv::Prod, lookup(&vars)).unwrap();
+        assert_eq!(config.encryption_key_id, "k2");
+        assert_eq!(config.encryption_keys.get("k1"), Some(&previous_key));
+        assert_eq!(config.encryption_keys.len(), 2);
+
+        // A missing key id, or a key that is not 32 bytes, fails at startup
+        vars.pop();
+        vars.push(("ENCRYPTION_PREVIOUS_KEYS", &previous_key));
+        let err = Config::from_lookup(AppEnv::Prod, lookup(&vars)).unwrap_err().to_string();
+        assert!(err.contains("ENCRYPTION_PREVIOUS_KEYS"), "{}", err);
+        vars.pop();
+        vars.push(("ENCRYPTION_PREVIOUS_KEYS", "k1=abcd"));
+        assert!(Config::from_lookup(AppEnv::Prod, lookup(&vars)).is_err());
+    }
+
+    #[test]
+    fn test_password_policy_settings() {
+        let config = Config::from_lookup(AppEnv::Dev, lookup(&[])).unwrap();
+        assert_eq!(config.password_policy, PasswordPolicy::default());
+
+        let config = Config::from_lookup(
+            AppEnv::Dev,
+            lookup(&[
+                ("PASSWORD_MIN_LENGTH", "14"),
+                ("PASSWORD_REQUIRE_CHARACTER_CLASSES", "true"),
+                ("PASSWORD_REJECT_EMAIL_LOCAL_PART", "no"),
+                ("PASSWORD_BREACH_CHECK", "1"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(
+            config.password_policy,
+            PasswordPolicy {
+                min_length: 14,
+                max_length: DEFAULT_MAX_PASSWORD_LENGTH,
+                require_character_classes: true,
+                reject_email_local_part: false,
+                breach_check: true,
+            }
+        );
+
+        let err = Config::from_lookup(AppEnv::Dev, lookup(&[("PASSWORD_MIN_LENGTH", "40"), ("PASSWORD_MAX_LENGTH", "32")]))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("PASSWORD_MIN_LENGTH"), "{}", err);
+    }
+
+    #[test]
+    fn test_sign_in_provider_settings() {
+        let config = Config::from_lookup(AppEnv::Dev, lookup(&[])).unwrap();
+        assert_eq!((config.github_client_id, config.apple_client_id), (None, None));
+        assert_eq!(config.github_api_url, GITHUB_API_URL);
+
+        let config = Config::from_lookup(
+            AppEnv::Dev,
+            lookup(&[("GITHUB_CLIENT_ID", "Iv1.abc"), ("GITHUB_CLIENT_SECRET", "s3cret"), ("APPLE_CLIENT_ID", "com.example.web")]),
+        )
+        .unwrap();
+        assert_eq!(config.github_client_id.as_deref(), Some("Iv1.abc"));
+        assert_eq!(config.apple_client_id.as_deref(), Some("com.example.web"));
+
+        let err = Config::from_lookup(AppEnv::Dev, lookup(&[("GITHUB_CLIENT_ID", "Iv1.abc")])).unwrap_err().to_string();
+        assert!(err.contains("GITHUB_CLIENT_SECRET"), "{}", err);
+    }
+
+    #[test]
+    fn test_telemetry_settings() {
+        let config = Config::from_lookup(AppEnv::Dev, lookup(&[])).unwrap();
+        assert_eq!(config.telemetry.otlp_endpoint, None);
+        assert_eq!(config.telemetry.service_name, DEFAULT_SERVICE_NAME);
+        assert_eq!(config.telemetry.sample_ratio, 1.0);
+
+        let config = Config::from_lookup(
+            AppEnv::Dev,
+            lookup(&[
+                ("OTEL_EXPORTER_OTLP_ENDPOINT", "http://otel-collector:4318"),
+                ("OTEL_SERVICE_NAME", "api-eu"),
+                ("OTEL_TRACES_SAMPLER_ARG", "0.1"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(config.telemetry.otlp_endpoint.as_deref(), Some("http://otel-collector:4318"));
+        assert_eq!(config.telemetry.service_name, "api-eu");
+        assert_eq!(config.telemetry.sample_ratio, 0.1);
+
+        // Out of range keeps every trace rather than guessing
+        let config = Config::from_lookup(AppEnv::Dev, lookup(&[("OTEL_TRACES_SAMPLER_ARG", "5")])).unwrap();
+        assert_eq!(config.telemetry.sample_ratio, 1.0);
+    }
+
+    #[test]
+    fn test_login_throttle_settings() {
+        let config = Config::from_lookup(AppEnv::Dev, lookup(&[])).unwrap();
+        assert_eq!(config.login_throttle, LoginThrottlePolicy::default());
+
+        let config = Config::from_lookup(
+            AppEnv::Dev,
+            lookup(&[("LOGIN_MAX_FAILURES_PER_EMAIL", "3"), ("LOGIN_LOCKOUT_SECONDS", "600"), ("LOGIN_LOCKOUT_THRESHOLD", "0")]),
+        )
+        .unwrap();
+        assert_eq!(config.login_throttle.max_failures_per_email, 3);
+        assert_eq!(config.login_throttle.lockout_seconds, 600);
+        // Zero would lock on the first failure, so it falls back to the default
+        assert_eq!(config.login_throttle.lockout_threshold, LoginThrottlePolicy::default().lockout_threshold);
+    }
+
+    #[test]
+    fn test_token_settings() {
+        let config = Config::from_lookup(AppEnv::Dev, lookup(&[])).unwrap();
+        let settings = config.token_settings();
+        assert_eq!(settings.lifetime, chrono::Duration::minutes(DEFAULT_TOKEN_LIFETIME_MINUTES));
+        assert_eq!((settings.issuer, settings.audience), (DEFAULT_TOKEN_ISSUER, DEFAULT_TOKEN_AUDIENCE));
+        assert!(!settings.require_claims);
+
+        let config = Config::from_lookup(
+            AppEnv::Dev,
+            lookup(&[("JWT_EXPIRY_MINUTES", "15"), ("JWT_ISSUER", "auth.example.com"), ("JWT_REQUIRE_CLAIMS", "true")]),
+        )
+        .unwrap();
+        let settings = config.token_settings();
+        assert_eq!(settings.lifetime, chrono::Duration::minutes(15));
+        assert_eq!(settings.issuer, "auth.example.com");
+        assert!(settings.require_claims);
+
+        // A token that expires as it is issued is no use to anyone
+        let config = Config::from_lookup(AppEnv::Dev, lookup(&[("JWT_EXPIRY_MINUTES", "0")])).unwrap();
+        assert_eq!(config.jwt_expiry_minutes, DEFAULT_TOKEN_LIFETIME_MINUTES);
+    }
+
+    #[test]
+    fn test_for_tests_needs_no_environment() {
+        let config = Config::for_tests();
+        assert_eq!(config.app_env, AppEnv::Test);
+        assert!(config.database_url.ends_with("trading_saas_test"));
     }
 }
@@ -1,33 +1,102 @@
-use sqlx::{PgPool, Row};
+use sqlx::{postgres::PgPoolOptions, Executor, PgPool, Row};
 use anyhow::Result;
+use std::time::Duration;
+
+pub const DEFAULT_STATEMENT_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_EXPORT_STATEMENT_TIMEOUT: Duration = Duration::from_secs(300);
+// Connections kept apart for exports and other long jobs, so they cannot starve requests
+const EXPORT_POOL_SIZE: u32 = 4;
 
 #[derive(Clone)]
 pub struct Database {
     pool: PgPool,
+    export_pool: PgPool,
 }
 
 impl Database {
-    pub async fn new(database_url: &str) -> Result<Self> {
-        let pool = PgPool::connect(database_url).await?;
-        Ok(Database { pool })
+    // Every connection gets a statement_timeout, so a runaway query or a stuck lock frees its
+    // connection instead of holding it after the client has given up
+    pub async fn new(database_url: &str, statement_timeout: Duration, export_statement_timeout: Duration) -> Result<Self> {
+        let pool = connect(PgPoolOptions::new(), database_url, statement_timeout).await?;
+        let export_pool = connect(PgPoolOptions::new().max_connections(EXPORT_POOL_SIZE), database_url, export_statement_timeout).await?;
+        Ok(Database { pool, export_pool })
     }
 
-    pub async fn migrate(&self) -> Result<()> {
-        sqlx::migrate!("./migrations").run(&self.pool).await?;
-        Ok(())
+    // One pool for both roles, as the integration tests get a single migrated database per test
+    pub fn from_pool(pool: PgPool) -> Self {
+        Database { export_pool: pool.clone(), pool }
     }
 
     pub fn pool(&self) -> &PgPool {
         &self.pool
     }
 
+    // For CSV exports and background jobs that may legitimately run for minutes
+    pub fn export_pool(&self) -> &PgPool {
+        &self.export_pool
+    }
+
     // Health check method
     pub async fn health_check(&self) -> Result<bool> {
         let row = sqlx::query("SELECT 1 as health")
             .fetch_one(&self.pool)
             .await?;
-        
+
         let health: i32 = row.get("health");
         Ok(health == 1)
     }
 }
+
+async fn connect(options: PgPoolOptions, database_url: &str, statement_timeout: Duration) -> Result<PgPool> {
+    let set_timeout = statement_timeout_sql(statement_timeout);
+    let pool = options
+        .after_connect(move |conn, _| {
+            let set_timeout = set_timeout.clone();
+            Box::pin(async move {
+                conn.execute(set_timeout.as_str()).await?;
+                Ok(())
+            })
+        })
+        .connect(database_url)
+        .await?;
+    Ok(pool)
+}
+
+// Zero disables the timeout, as in Postgres
+fn statement_timeout_sql(timeout: Duration) -> String {
+    format!("SET statement_timeout = {}", timeout.as_millis())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::{AppError, DbOp};
+    use axum::{http::StatusCode, response::IntoResponse};
+
+    #[test]
+    fn test_statement_timeout_is_set_in_milliseconds() {
+        assert_eq!(statement_timeout_sql(Duration::from_secs(10)), "SET statement_timeout = 10000");
+        assert_eq!(statement_timeout_sql(Duration::from_millis(250)), "SET statement_timeout = 250");
+    }
+
+    // Needs a Postgres server: TEST_DATABASE_URL=postgres://... cargo test -- --ignored
+    #[tokio::test]
+    #[ignore]
+    async fn test_query_past_the_statement_timeout_is_a_504() {
+        let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL");
+        let db = Database::new(&url, Duration::from_millis(200), Duration::from_secs(5)).await.unwrap();
+
+        let error = sqlx::query("SELECT pg_sleep(1)")
+            .execute(db.pool())
+            .await
+            .db_op("tests.pg_sleep")
+            .unwrap_err();
+        assert!(matches!(&error, AppError::Database { source, .. } if crate::errors::is_statement_timeout(source)));
+        let response = error.into_response();
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert!(response.headers().contains_key("retry-after"));
+
+        // The longer export timeout lets the same query finish
+        sqlx::query("SELECT pg_sleep(1)").execute(db.export_pool()).await.unwrap();
+    }
+}
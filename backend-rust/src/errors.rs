@@ -1,21 +1,46 @@
 use axum::{
-    http::StatusCode,
+    http::{header, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
+use serde::Serialize;
+use schemars::JsonSchema;
 use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::{Mutex, OnceLock};
 use thiserror::Error;
+use uuid::Uuid;
+
+// Operation name for queries that were not tagged with db_op
+pub const UNNAMED_DB_OP: &str = "unnamed";
+// Suggested wait before retrying a query that hit the statement timeout
+pub const STATEMENT_TIMEOUT_RETRY_SECONDS: u64 = 5;
+pub const PLAN_LIMIT_CODE: &str = "plan_limit";
 
 #[derive(Error, Debug)]
 pub enum AppError {
-    #[error("Database error: {0}")]
-    Database(#[from] sqlx::Error),
+    #[error("Database error in {op}: {source}")]
+    Database {
+        op: &'static str,
+        #[source]
+        source: sqlx::Error,
+    },
     
     #[error("Authentication error: {0}")]
     Auth(String),
     
     #[error("Validation error: {0}")]
     Validation(String),
+
+    // Rules broken by specific request fields, all of them rather than the first
+    #[error("Validation error: {message}")]
+    InvalidFields {
+        message: String,
+        fields: Vec<FieldError>,
+    },
+
+    #[error("Unprocessable: {0}")]
+    Unprocessable(String),
     
     #[error("Not found: {0}")]
     NotFound(String),
@@ -43,19 +68,64 @@ pub enum AppError {
     
     #[error("MT5 error: {0}")]
     Mt5(String),
+
+    #[error("Broker unavailable: {0}")]
+    BrokerUnavailable(String),
+
+    #[error("Service unavailable: {0}")]
+    Unavailable(String),
+
+    // The caller already runs as many jobs of this kind as their plan allows
+    #[error("Job limit reached: {message}")]
+    JobLimit {
+        message: String,
+        running_job_ids: Vec<Uuid>,
+    },
+
+    // Beyond what the caller's plan covers; the body's code lets the client offer an upgrade
+    #[error("Plan limit: {0}")]
+    PlanLimit(String),
+
+    // A setting the caller's plan does not allow; the request itself needs changing, so 400
+    #[error("Plan limit: {0}")]
+    PlanSetting(String),
+
+    // Refused after too many recent failures, e.g. logins; the client may retry after the given time
+    #[error("Too many attempts: {message}")]
+    TooManyAttempts {
+        message: String,
+        retry_after_seconds: u64,
+    },
+
+    // The user already has a connection to this broker account
+    #[error("Duplicate broker connection: {message}")]
+    DuplicateConnection {
+        message: String,
+        existing_connection_id: Uuid,
+    },
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
         let (status, error_message) = match self {
-            AppError::Database(ref e) => {
-                tracing::error!("Database error: {:?}", e);
+            AppError::Database { op, ref source } if is_statement_timeout(source) => {
+                record_database_error(op);
+                tracing::warn!(op, "Statement timeout in {}", op);
+                (StatusCode::GATEWAY_TIMEOUT, "The query took too long, try again or narrow it down")
+            }
+            AppError::Database { op, ref source } => {
+                record_database_error(op);
+                tracing::error!(op, "Database error in {}: {:?}", op, source);
                 (StatusCode::INTERNAL_SERVER_ERROR, "Database error")
             }
             AppError::Auth(ref message) => (StatusCode::UNAUTHORIZED, message.as_str()),
             AppError::Validation(ref message) => (StatusCode::BAD_REQUEST, message.as_str()),
+            AppError::InvalidFields { ref message, .. } => (StatusCode::BAD_REQUEST, message.as_str()),
+            AppError::Unprocessable(ref message) => (StatusCode::UNPROCESSABLE_ENTITY, message.as_str()),
             AppError::NotFound(ref message) => (StatusCode::NOT_FOUND, message.as_str()),
             AppError::Forbidden(ref message) => (StatusCode::FORBIDDEN, message.as_str()),
+            AppError::PlanLimit(ref message) => (StatusCode::FORBIDDEN, message.as_str()),
+            AppError::PlanSetting(ref message) => (StatusCode::BAD_REQUEST, message.as_str()),
             AppError::Internal(ref e) => {
                 tracing::error!("Internal error: {:?}", e);
                 (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
@@ -75,15 +145,266 @@ impl IntoResponse for AppError {
             AppError::Stripe(ref message) => (StatusCode::BAD_REQUEST, message.as_str()),
             AppError::AiModel(ref message) => (StatusCode::INTERNAL_SERVER_ERROR, message.as_str()),
             AppError::Mt5(ref message) => (StatusCode::BAD_REQUEST, message.as_str()),
+            AppError::BrokerUnavailable(ref message) => {
+                tracing::warn!("Broker unavailable: {}", message);
+                (StatusCode::SERVICE_UNAVAILABLE, message.as_str())
+            }
+            AppError::Unavailable(ref message) => (StatusCode::SERVICE_UNAVAILABLE, message.as_str()),
+            AppError::JobLimit { ref message, .. } => (StatusCode::TOO_MANY_REQUESTS, message.as_str()),
+            AppError::TooManyAttempts { ref message, .. } => (StatusCode::TOO_MANY_REQUESTS, message.as_str()),
+            AppError::DuplicateConnection { ref message, .. } => (StatusCode::CONFLICT, message.as_str()),
         };
 
-        let body = Json(json!({
+        let mut body = json!({
             "error": error_message,
             "status": status.as_u16()
-        }));
+        });
+        // Lets the client point the user at the jobs holding the slots
+        if let AppError::JobLimit { ref running_job_ids, .. } = self {
+            body["running_job_ids"] = json!(running_job_ids);
+        }
+        if let AppError::InvalidFields { ref fields, .. } = self {
+            body["fields"] = json!(fields);
+        }
+        if let AppError::PlanLimit(_) | AppError::PlanSetting(_) = self {
+            body["code"] = json!(PLAN_LIMIT_CODE);
+        }
+        if let AppError::DuplicateConnection { existing_connection_id, .. } = self {
+            body["existing_connection_id"] = json!(existing_connection_id);
+        }
+        let retry_after_seconds = match self {
+            AppError::TooManyAttempts { retry_after_seconds, .. } => Some(retry_after_seconds),
+            _ if status == StatusCode::GATEWAY_TIMEOUT => Some(STATEMENT_TIMEOUT_RETRY_SECONDS),
+            _ => None,
+        };
+        if let Some(seconds) = retry_after_seconds {
+            body["retry_after_seconds"] = json!(seconds);
+            let retry_after = [(header::RETRY_AFTER, seconds.to_string())];
+            return (status, retry_after, Json(body)).into_response();
+        }
+        let body = Json(body);
 
         (status, body).into_response()
     }
 }
 
-pub type Result<T> = std::result::Result<T, AppError>;
+// Postgres cancelled the statement because it ran past statement_timeout (SQLSTATE 57014 is
+// also used for cancels on request, which are not timeouts)
+pub fn is_statement_timeout(error: &sqlx::Error) -> bool {
+    match error {
+        sqlx::Error::Database(e) => e.code().as_deref() == Some("57014") && e.message().contains("statement timeout"),
+        _ => false,
+    }
+}
+
+impl From<sqlx::Error> for AppError {
+    fn from(source: sqlx::Error) -> Self {
+        AppError::Database { op: UNNAMED_DB_OP, source }
+    }
+}
+
+// Tags a query result with a stable operation name, e.g. "trades.find_page"
+pub trait DbOp<T> {
+    fn db_op(self, op: &'static str) -> Result<T>;
+}
+
+impl<T> DbOp<T> for std::result::Result<T, sqlx::Error> {
+    fn db_op(self, op: &'static str) -> Result<T> {
+        self.map_err(|source| AppError::Database { op, source })
+    }
+}
+
+// e.g. { "field": "password", "rule": "min_length", "message": "Must be at least 8 characters" }
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct FieldError {
+    pub field: String,
+    pub rule: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct DatabaseErrorCount {
+    pub op: &'static str,
+    pub count: u64,
+}
+
+fn database_error_counter() -> &'static Mutex<BTreeMap<&'static str, u64>> {
+    static COUNTER: OnceLock<Mutex<BTreeMap<&'static str, u64>>> = OnceLock::new();
+    COUNTER.get_or_init(|| Mutex::new(BTreeMap::new()))
+}
+
+fn record_database_error(op: &'static str) {
+    *database_error_counter().lock().unwrap().entry(op).or_insert(0) += 1;
+}
+
+// Database errors returned to clients, labelled by operation
+pub fn database_error_counts() -> Vec<DatabaseErrorCount> {
+    database_error_counter()
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(op, count)| DatabaseErrorCount { op, count: *count })
+        .collect()
+}
+
+pub type Result<T, E = AppError> = std::result::Result<T, E>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+    use std::error::Error as StdError;
+    use std::fmt;
+    use std::io;
+    use std::sync::Arc;
+
+    #[derive(Debug)]
+    struct UniqueViolation;
+
+    impl fmt::Display for UniqueViolation {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "duplicate key value violates unique constraint \"users_email_key\"")
+        }
+    }
+
+    impl StdError for UniqueViolation {}
+
+    impl sqlx::error::DatabaseError for UniqueViolation {
+        fn message(&self) -> &str {
+            "duplicate key value violates unique constraint \"users_email_key\""
+        }
+
+        fn code(&self) -> Option<Cow<'_, str>> {
+            Some(Cow::Borrowed("23505"))
+        }
+
+        fn as_error(&self) -> &(dyn StdError + Send + Sync + 'static) {
+            self
+        }
+
+        fn as_error_mut(&mut self) -> &mut (dyn StdError + Send + Sync + 'static) {
+            self
+        }
+
+        fn into_error(self: Box<Self>) -> Box<dyn StdError + Send + Sync + 'static> {
+            self
+        }
+
+        fn kind(&self) -> sqlx::error::ErrorKind {
+            sqlx::error::ErrorKind::UniqueViolation
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn count_for(op: &str) -> u64 {
+        database_error_counts()
+            .into_iter()
+            .find(|c| c.op == op)
+            .map(|c| c.count)
+            .unwrap_or(0)
+    }
+
+    #[tokio::test]
+    async fn test_constraint_violation_is_labelled_with_op_but_hidden_from_client() {
+        let op = "users.create_for_error_test";
+        let result: std::result::Result<(), sqlx::Error> = Err(sqlx::Error::Database(Box::new(UniqueViolation)));
+        let error = result.db_op(op).unwrap_err();
+        assert!(matches!(error, AppError::Database { op: "users.create_for_error_test", .. }));
+
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+        let response = tracing::subscriber::with_default(subscriber, || error.into_response());
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("Database error"));
+        assert!(!body.contains(op));
+        assert!(!body.contains("users_email_key"));
+
+        let logged = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(logged.contains(op));
+        assert_eq!(count_for(op), 1);
+    }
+
+    #[derive(Debug)]
+    struct QueryCanceled(&'static str);
+
+    impl fmt::Display for QueryCanceled {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{}", self.0)
+        }
+    }
+
+    impl StdError for QueryCanceled {}
+
+    impl sqlx::error::DatabaseError for QueryCanceled {
+        fn message(&self) -> &str {
+            self.0
+        }
+
+        fn code(&self) -> Option<Cow<'_, str>> {
+            Some(Cow::Borrowed("57014"))
+        }
+
+        fn as_error(&self) -> &(dyn StdError + Send + Sync + 'static) {
+            self
+        }
+
+        fn as_error_mut(&mut self) -> &mut (dyn StdError + Send + Sync + 'static) {
+            self
+        }
+
+        fn into_error(self: Box<Self>) -> Box<dyn StdError + Send + Sync + 'static> {
+            self
+        }
+
+        fn kind(&self) -> sqlx::error::ErrorKind {
+            sqlx::error::ErrorKind::Other
+        }
+    }
+
+    #[tokio::test]
+    async fn test_statement_timeout_is_a_504_with_a_retry_hint() {
+        let op = "trades.statistics_for_timeout_test";
+        let timeout = sqlx::Error::Database(Box::new(QueryCanceled("canceling statement due to statement timeout")));
+        let response = Err::<(), _>(timeout).db_op(op).unwrap_err().into_response();
+
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "5");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["status"], 504);
+        assert_eq!(body["retry_after_seconds"], 5);
+        assert_eq!(count_for(op), 1);
+
+        // An admin cancelling the backend is not a timeout
+        let cancelled = sqlx::Error::Database(Box::new(QueryCanceled("canceling statement due to user request")));
+        assert!(!is_statement_timeout(&cancelled));
+        let response = AppError::Database { op, source: cancelled }.into_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[test]
+    fn test_untagged_sqlx_errors_get_the_unnamed_op() {
+        let error: AppError = sqlx::Error::RowNotFound.into();
+        assert!(matches!(error, AppError::Database { op: UNNAMED_DB_OP, .. }));
+    }
+}
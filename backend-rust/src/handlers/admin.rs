@@ -1,60 +1,131 @@
 use axum::{
-    extract::{Query, State},
-    response::Json,
+    body::Body,
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Json, Response},
 };
 use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
 use uuid::Uuid;
-use chrono::Utc;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use validator::Validate;
 
 use crate::{
-    models::User,
-    errors::Result,
+    app_middleware::{request_counts_by_client, ClientRequestCount},
+    models::{
+        admin_user_columns, admin_user_csv_header, TradeOrigin, ActivateTemplateRequest, AdminUserResponse, BrokerMaintenance, BrokerMaintenanceRequest, ActivationNudge, ActivationRisk, AdminSetting, AdminUserFilter, AdminUserOrder, AdminUserRow, ClientCount, CreateIncidentRequest, FeatureFlag, Incident, IncidentResponse, IncidentUpdate, IncidentUpdateRequest,
+        IntegrityRun, MaintenanceNotice, MessageTemplate, OutboxEmail, OutboxHealth, PlatformStatsDay, PreviewTemplateRequest, RenderedTemplate, RuntimeSettings, RuntimeSettingsPatch, SaveTemplateRequest, StatsExportSettings, Trade, TradingRobot,
+        AuditActor, AuditEntry, AuthEvent, AuthEventFilter, Role, Subscription, SubscriptionResponse, UpdateFeatureFlagRequest, UpdateUserPlanRequest, UpdateUserRoleRequest, User, UserResponse, BROKER_MAINTENANCE_SETTING, DEFAULT_LOCALE, MAINTENANCE_SETTING, RUNTIME_SETTING, STATS_EXPORT_SETTING,
+    },
+    services::{
+        activation_nudges::PlannedNudge,
+        broker_throttle::ConnectionThrottleMetrics,
+        credential_vault::RotationStatus,
+        export_stream::{paged_export, ExportCancellation},
+        integrity_service::IntegrityJob,
+        job_service::JobClassUsage,
+        platform_stats::{PgPlatformStatsStore, PlatformStats, CSV_HEADER},
+        task_supervisor::{spawn_supervised, task_panic_counts, TaskClass, TaskPanicCount},
+        websocket_manager::WebSocketConnectionMetrics,
+        ws_shedding::{WebSocketChannelMetrics, WebSocketShedCount},
+        auth_service::AuthService,
+        event_bus::{DomainEvent, EventPublisher},
+        plan_downgrade::RUNNING_STATUSES,
+        ActivationNudges, IntegrityService, RuntimeConfig, TradeSearch,
+    },
+    errors::{database_error_counts, AppError, DatabaseErrorCount, DbOp, Result},
+    pagination::{Page, Paginated},
     AppState,
 };
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 pub struct AdminUsersQuery {
     pub limit: Option<i64>,
     pub offset: Option<i64>,
+    // created_at (default), email, plan, last_login or robot_count
+    pub sort: Option<String>,
+    // asc or desc; defaults to newest and busiest first, email and plan alphabetically
+    pub order: Option<String>,
+    #[serde(alias = "subscription_plan")]
+    pub plan: Option<String>,
+    #[serde(alias = "is_active")]
+    pub active: Option<bool>,
+    // Part of the address, case-insensitive
+    pub email: Option<String>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    // Deleted accounts are left out unless true
+    pub include_deleted: Option<bool>,
+    // csv streams every matching user instead of a page
+    pub export: Option<String>,
+    // true answers with a Paginated page instead of AdminUserList
+    pub envelope: Option<bool>,
+    // Comma-separated CSV columns; all of them by default
+    pub columns: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct UserResponse {
-    pub id: Uuid,
-    pub email: String,
-    pub is_active: bool,
-    pub is_superuser: bool,
-    pub subscription_plan: String,
-    pub created_at: String,
-    pub updated_at: String,
-}
-
-impl From<User> for UserResponse {
-    fn from(user: User) -> Self {
-        Self {
-            id: user.id,
-            email: user.email,
-            is_active: user.is_active,
-            is_superuser: user.is_superuser,
-            subscription_plan: user.subscription_plan,
-            created_at: user.created_at.format("%Y-%m-%d %H:%M:%S").to_string(),
-            updated_at: user.updated_at.format("%Y-%m-%d %H:%M:%S").to_string(),
-        }
-    }
+#[derive(Deserialize, JsonSchema)]
+pub struct AdminSecurityEventsQuery {
+    pub user_id: Option<Uuid>,
+    pub event_type: Option<String>,
+    // success or failure
+    pub outcome: Option<String>,
+    pub ip_address: Option<String>,
+    // Inclusive lower and exclusive upper bound on when the event happened
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+// A token to use the API as the user; it names the admin and cannot reach credentials or trading
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct ImpersonationResponse {
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+    pub user: UserResponse,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct AdminPlanChange {
+    pub user: UserResponse,
+    // Its plan_details are the limits now in effect
+    pub subscription: SubscriptionResponse,
+    // Robots stopped because the new plan allows fewer
+    pub robots_limited: Vec<Uuid>,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct AdminUserList {
+    pub users: Vec<AdminUserResponse>,
+    // Users matching the filters, across all pages
+    pub total: i64,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+// Omitting user_id covers every user
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+pub struct IntegrityScope {
+    pub user_id: Option<Uuid>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct SystemStats {
     pub total_users: i64,
     pub active_users: i64,
     pub total_robots: i64,
     pub active_robots: i64,
     pub total_trades: i64,
+    #[serde(serialize_with = "crate::money::serialize_aggregate")]
     pub total_profit: f64,
     pub subscription_breakdown: SubscriptionBreakdown,
+    pub robots_by_client: Vec<ClientCount>,
+    pub trades_by_client: Vec<ClientCount>,
+    pub activation_risk: ActivationRisk,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct SubscriptionBreakdown {
     pub free: i64,
     pub essential: i64,
@@ -62,20 +133,308 @@ pub struct SubscriptionBreakdown {
     pub elite: i64,
 }
 
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct AdminHealth {
+    pub database: bool,
+    pub broker_throttle: Vec<ConnectionThrottleMetrics>,
+    pub websocket_connections: Vec<WebSocketConnectionMetrics>,
+    pub websocket_global_channel: WebSocketChannelMetrics,
+    // Messages dropped at send time by type since startup
+    pub websocket_shed: Vec<WebSocketShedCount>,
+    pub database_errors: Vec<DatabaseErrorCount>,
+    pub requests_by_client: Vec<ClientRequestCount>,
+    pub job_slots: Vec<JobClassUsage>,
+    pub email_outbox: OutboxHealth,
+    // Panics caught in background tasks since startup
+    pub task_panics: Vec<TaskPanicCount>,
+    pub timestamp: String,
+}
+
+// Rows fetched per query while streaming an export
+const EXPORT_CHUNK: i64 = 500;
+// How long a request to demote an admin waits for a second admin to confirm it
+const DEMOTION_CONFIRM_HOURS: i64 = 24;
+
 pub async fn list_all_users(
     State(state): State<AppState>,
     Query(query): Query<AdminUsersQuery>,
     _current_user: User,
-) -> Result<Json<Vec<UserResponse>>> {
+) -> Result<Response> {
     // This endpoint should be protected by admin middleware
-    let limit = query.limit.unwrap_or(50).min(100);
-    let offset = query.offset.unwrap_or(0);
+    let order = AdminUserOrder::parse(query.sort.as_deref(), query.order.as_deref())?;
+    let filter = AdminUserFilter {
+        plan: query.plan.map(|p| p.trim().to_lowercase()).filter(|p| !p.is_empty()),
+        active: query.active,
+        email: query.email.as_deref().map(str::trim).filter(|e| !e.is_empty()).map(TradeSearch::like_pattern),
+        created_after: query.created_after,
+        created_before: query.created_before,
+        include_deleted: query.include_deleted.unwrap_or(false),
+    };
+    filter.validate()?;
+
+    match query.export.as_deref() {
+        None => {
+            let page = Page::new(query.limit, query.offset);
+            let users = AdminUserRow::page(state.db.pool(), &filter, order, page.limit, page.offset).await?;
+            let total = AdminUserRow::count(state.db.pool(), &filter).await?;
+            let users = users.into_iter().map(AdminUserResponse::from).collect();
+            if query.envelope.unwrap_or(false) {
+                return Ok(Json(Paginated::new(users, total, page)).into_response());
+            }
+            Ok(Json(AdminUserList { users, total, limit: page.limit, offset: page.offset }).into_response())
+        }
+        Some("csv") => {
+            let columns = admin_user_columns(query.columns.as_deref())?;
+            // Pages through the filtered set so the whole user table is never held in memory
+            let pool = state.db.export_pool().clone();
+            let rows = paged_export(admin_user_csv_header(&columns), EXPORT_CHUNK, ExportCancellation::default(), move |offset| {
+                let (pool, filter, columns) = (pool.clone(), filter.clone(), columns.clone());
+                async move {
+                    let users = AdminUserRow::page(&pool, &filter, order, EXPORT_CHUNK, offset).await?;
+                    Ok((users.iter().map(|u| u.csv_row(&columns)).collect(), users.len()))
+                }
+            });
+            let body = Body::from_stream(rows);
+            Ok((
+                [
+                    (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+                    (header::CONTENT_DISPOSITION, "attachment; filename=\"users.csv\"".to_string()),
+                ],
+                body,
+            )
+                .into_response())
+        }
+        Some(other) => Err(AppError::Validation(format!("Unknown export '{}', expected csv", other))),
+    }
+}
+
+// Auth events across all users, newest first, e.g. to follow failed sign-ins from one address
+pub async fn list_security_events(
+    State(state): State<AppState>,
+    Query(query): Query<AdminSecurityEventsQuery>,
+    _current_user: User,
+) -> Result<Json<Vec<AuthEvent>>> {
+    let filter = AuthEventFilter {
+        user_id: query.user_id,
+        event_type: query.event_type.map(|t| t.trim().to_lowercase()).filter(|t| !t.is_empty()),
+        outcome: query.outcome.map(|o| o.trim().to_lowercase()).filter(|o| !o.is_empty()),
+        ip_address: query.ip_address.map(|ip| ip.trim().to_string()).filter(|ip| !ip.is_empty()),
+        since: query.since,
+        until: query.until,
+    };
+    filter.validate()?;
+    let limit = query.limit.unwrap_or(50).clamp(1, 100);
+    let offset = query.offset.unwrap_or(0).max(0);
+    Ok(Json(AuthEvent::search(state.db.pool(), &filter, limit, offset).await?))
+}
+
+// Admins cannot change their own role, so the last admin cannot lock everyone out
+// Demoting an admin takes two admins: the first call records the request and answers 202 with
+// the user unchanged, and it applies once a different admin asks for the same role.
+pub async fn update_user_role(
+    State(state): State<AppState>,
+    Path(user_id): Path<Uuid>,
+    current_user: User,
+    Json(payload): Json<UpdateUserRoleRequest>,
+) -> Result<(StatusCode, Json<UserResponse>)> {
+    if user_id == current_user.id {
+        return Err(AppError::Forbidden("You cannot change your own role".to_string()));
+    }
+
+    let pool = state.db.pool();
+    let user = User::find_by_id(pool, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+    let actor = AuditActor::admin(current_user.id);
+    let details = serde_json::json!({ "from": user.role, "to": payload.role.as_str() });
+    if user.role == Role::Admin.as_str() && payload.role != Role::Admin {
+        let history = AuditEntry::find_by_target(pool, "user", user.id).await?;
+        if !demotion_confirmed(&history, &actor, payload.role, Utc::now()) {
+            AuditEntry::record(pool, &actor, "user.demote_request", "user", Some(user.id), details).await?;
+            return Ok((StatusCode::ACCEPTED, Json(user.into())));
+        }
+    }
+    User::set_role(pool, user.id, payload.role).await?;
+    AuditEntry::record(pool, &actor, "user.role", "user", Some(user.id), details).await?;
+
+    let updated = User { role: payload.role.as_str().to_string(), is_superuser: payload.role == Role::Admin, ..user };
+    Ok((StatusCode::OK, Json(updated.into())))
+}
+
+// Whether another admin asked for this demotion recently, since the user's role last changed
+fn demotion_confirmed(history: &[AuditEntry], actor: &AuditActor, role: Role, now: DateTime<Utc>) -> bool {
+    let since_last_change = history.iter().rposition(|e| e.action == "user.role").map_or(0, |i| i + 1);
+    history[since_last_change..].iter().any(|e| {
+        e.action == "user.demote_request"
+            && e.actor_type == actor.actor_type
+            && e.actor != actor.name
+            && e.details["to"] == role.as_str()
+            && e.created_at > now - Duration::hours(DEMOTION_CONFIRM_HOURS)
+    })
+}
+
+// Sets a user's plan by hand, e.g. to comp one or repair a failed Stripe sync. The subscription is
+// marked manual, and robots over the new plan's limit are parked as on any downgrade.
+pub async fn update_user_plan(
+    State(state): State<AppState>,
+    Path(user_id): Path<Uuid>,
+    current_user: User,
+    Json(payload): Json<UpdateUserPlanRequest>,
+) -> Result<Json<AdminPlanChange>> {
+    payload.validate().map_err(|e| AppError::Validation(e.to_string()))?;
+    let plan_name = Subscription::known_plan(&payload.plan_name)?;
+    let pool = state.db.pool();
+    let user = User::find_by_id_including_deleted(pool, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+    if user.is_deleted() {
+        return Err(AppError::Unprocessable("A deleted account has no plan to change".to_string()));
+    }
+
+    let subscription = Subscription::set_manual(pool, user.id, &plan_name, Utc::now()).await?;
+    User::update_subscription_plan(pool, user.id, &plan_name).await?;
+    let report = state.plan_downgrades.reconcile(user.id, &user.email, &plan_name, &state.runners).await?;
+    let robots_limited: Vec<Uuid> = report.limited.iter().map(|r| r.id).collect();
+
+    AuditEntry::record(
+        pool,
+        &AuditActor::admin(current_user.id),
+        "user.plan",
+        "user",
+        Some(user.id),
+        serde_json::json!({
+            "from": user.subscription_plan,
+            "to": plan_name,
+            "reason": payload.reason.as_deref().map(str::trim).filter(|r| !r.is_empty()),
+            "robots_limited": robots_limited,
+        }),
+    )
+    .await?;
+    state.events.publish(DomainEvent::SubscriptionChanged {
+        user_id: user.id,
+        email: user.email.clone(),
+        plan_name: plan_name.clone(),
+        action: "updated".to_string(),
+    });
+    Ok(Json(AdminPlanChange {
+        user: User { subscription_plan: plan_name, ..user }.into(),
+        subscription: subscription.into(),
+        robots_limited,
+    }))
+}
+
+// Refuses logins and revokes every token the user holds, and stops their running robots. Admins
+// have to be demoted first, which takes two admins, so no admin can lock the others out alone.
+pub async fn deactivate_user(
+    State(state): State<AppState>,
+    Path(user_id): Path<Uuid>,
+    current_user: User,
+) -> Result<Json<UserResponse>> {
+    if user_id == current_user.id {
+        return Err(AppError::Forbidden("You cannot deactivate your own account".to_string()));
+    }
+    let pool = state.db.pool();
+    let user = User::find_by_id(pool, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+    if user.is_superuser || user.role() == Role::Admin {
+        return Err(AppError::Forbidden("Admins cannot be deactivated; change their role first".to_string()));
+    }
+    if !user.is_active {
+        return Ok(Json(user.into()));
+    }
+
+    User::set_active(pool, user.id, false).await?;
+    state.token_revocations.bump_version(user.id).await?;
+    let mut stopped = Vec::new();
+    for robot in TradingRobot::find_by_user_id(pool, user.id).await? {
+        if RUNNING_STATUSES.contains(&robot.status.as_str()) {
+            TradingRobot::update_status(pool, robot.id, user.id, "stopped").await?;
+            state.runners.stop(robot.id);
+            state.events.publish(DomainEvent::RobotStatusChanged { robot_id: robot.id, user_id: user.id, status: "stopped".to_string() });
+            stopped.push(robot.id);
+        }
+    }
+
+    AuditEntry::record(
+        pool,
+        &AuditActor::admin(current_user.id),
+        "user.deactivate",
+        "user",
+        Some(user.id),
+        serde_json::json!({ "email": user.email, "robots_stopped": stopped }),
+    )
+    .await?;
+    state.events.publish(DomainEvent::AccountDeactivated { user_id: user.id, email: user.email.clone(), admin_id: current_user.id });
+    Ok(Json(User { is_active: false, ..user }.into()))
+}
+
+// Lets the user sign in again. Robots stopped on deactivation stay stopped for the user to restart.
+pub async fn activate_user(
+    State(state): State<AppState>,
+    Path(user_id): Path<Uuid>,
+    current_user: User,
+) -> Result<Json<UserResponse>> {
+    let pool = state.db.pool();
+    let user = User::find_by_id_including_deleted(pool, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+    if user.is_deleted() {
+        return Err(AppError::Unprocessable("A deleted account cannot be reactivated".to_string()));
+    }
+    if user.is_active {
+        return Ok(Json(user.into()));
+    }
+
+    User::set_active(pool, user.id, true).await?;
+    AuditEntry::record(
+        pool,
+        &AuditActor::admin(current_user.id),
+        "user.activate",
+        "user",
+        Some(user.id),
+        serde_json::json!({ "email": user.email }),
+    )
+    .await?;
+    state.events.publish(DomainEvent::AccountReactivated { user_id: user.id, email: user.email.clone(), admin_id: current_user.id });
+    Ok(Json(User { is_active: true, ..user }.into()))
+}
 
-    let users = User::list_all(state.db.pool(), limit, offset).await?;
+// Only regular users can be impersonated, so an impersonation never reaches the admin API. Every
+// start is written to audit_log before the token is handed out.
+pub async fn impersonate_user(
+    State(state): State<AppState>,
+    Path(user_id): Path<Uuid>,
+    current_user: User,
+) -> Result<(StatusCode, Json<ImpersonationResponse>)> {
+    if user_id == current_user.id {
+        return Err(AppError::Forbidden("You cannot impersonate yourself".to_string()));
+    }
+    let user = User::find_by_id(state.db.pool(), user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+    if user.role() != Role::User {
+        return Err(AppError::Forbidden("Only regular users can be impersonated".to_string()));
+    }
+    if !user.is_active {
+        return Err(AppError::Validation("A disabled account cannot be impersonated".to_string()));
+    }
 
-    let user_responses: Vec<UserResponse> = users.into_iter().map(|u| u.into()).collect();
+    let version = state.token_revocations.version(user.id).await?;
+    let (token, claims) =
+        AuthService::issue_impersonation_token(user.id, version, current_user.id, &state.config.token_settings())?;
+    let expires_at = DateTime::from_timestamp(claims.exp as i64, 0).unwrap_or_else(Utc::now);
+    AuditEntry::record(
+        state.db.pool(),
+        &AuditActor::admin(current_user.id),
+        "user.impersonate",
+        "user",
+        Some(user.id),
+        serde_json::json!({ "admin_id": current_user.id, "token_id": claims.jti, "expires_at": expires_at }),
+    )
+    .await?;
 
-    Ok(Json(user_responses))
+    Ok((StatusCode::CREATED, Json(ImpersonationResponse { token, expires_at, user: user.into() })))
 }
 
 pub async fn get_system_stats(
@@ -89,7 +448,8 @@ pub async fn get_system_stats(
         "SELECT COUNT(*) FROM users"
     )
     .fetch_one(state.db.pool())
-    .await?
+    .await
+    .db_op("admin.stats.total_users")?
     .map(|count| count as i64)
     .unwrap_or(0);
 
@@ -97,7 +457,8 @@ pub async fn get_system_stats(
         "SELECT COUNT(*) FROM users WHERE is_active = true"
     )
     .fetch_one(state.db.pool())
-    .await?
+    .await
+    .db_op("admin.stats.active_users")?
     .map(|count| count as i64)
     .unwrap_or(0);
 
@@ -106,7 +467,8 @@ pub async fn get_system_stats(
         "SELECT COUNT(*) FROM trading_robots"
     )
     .fetch_one(state.db.pool())
-    .await?
+    .await
+    .db_op("admin.stats.total_robots")?
     .map(|count| count as i64)
     .unwrap_or(0);
 
@@ -114,24 +476,27 @@ pub async fn get_system_stats(
         "SELECT COUNT(*) FROM trading_robots WHERE status = 'active'"
     )
     .fetch_one(state.db.pool())
-    .await?
+    .await
+    .db_op("admin.stats.active_robots")?
     .map(|count| count as i64)
     .unwrap_or(0);
 
     // Get trade statistics
     let total_trades = sqlx::query_scalar!(
-        "SELECT COUNT(*) FROM trades"
+        "SELECT COUNT(*) FROM trades WHERE is_demo = FALSE"
     )
     .fetch_one(state.db.pool())
-    .await?
+    .await
+    .db_op("admin.stats.total_trades")?
     .map(|count| count as i64)
     .unwrap_or(0);
 
     let total_profit: f64 = sqlx::query_scalar!(
-        "SELECT COALESCE(SUM(profit_loss), 0.0)::FLOAT FROM trades WHERE status = 'closed'"
+        "SELECT COALESCE(SUM(profit_loss), 0.0)::FLOAT FROM trades WHERE status = 'closed' AND is_demo = FALSE"
     )
     .fetch_one(state.db.pool())
-    .await?
+    .await
+    .db_op("admin.stats.total_profit")?
     .unwrap_or(0.0);
 
     // Get subscription breakdown
@@ -139,7 +504,8 @@ pub async fn get_system_stats(
         "SELECT COUNT(*) FROM users WHERE subscription_plan = 'free'"
     )
     .fetch_one(state.db.pool())
-    .await?
+    .await
+    .db_op("admin.stats.free_users")?
     .map(|count| count as i64)
     .unwrap_or(0);
 
@@ -147,7 +513,8 @@ pub async fn get_system_stats(
         "SELECT COUNT(*) FROM users WHERE subscription_plan = 'essential'"
     )
     .fetch_one(state.db.pool())
-    .await?
+    .await
+    .db_op("admin.stats.essential_users")?
     .map(|count| count as i64)
     .unwrap_or(0);
 
@@ -155,7 +522,8 @@ pub async fn get_system_stats(
         "SELECT COUNT(*) FROM users WHERE subscription_plan = 'pro'"
     )
     .fetch_one(state.db.pool())
-    .await?
+    .await
+    .db_op("admin.stats.pro_users")?
     .map(|count| count as i64)
     .unwrap_or(0);
 
@@ -163,10 +531,12 @@ pub async fn get_system_stats(
         "SELECT COUNT(*) FROM users WHERE subscription_plan = 'elite'"
     )
     .fetch_one(state.db.pool())
-    .await?
+    .await
+    .db_op("admin.stats.elite_users")?
     .map(|count| count as i64)
     .unwrap_or(0);
 
+    let now = Utc::now();
     let stats = SystemStats {
         total_users,
         active_users,
@@ -180,7 +550,415 @@ pub async fn get_system_stats(
             pro: pro_users,
             elite: elite_users,
         },
+        robots_by_client: TradingRobot::count_by_client(state.db.pool()).await?,
+        trades_by_client: Trade::count_by_client(state.db.pool()).await?,
+        activation_risk: ActivationNudge::risk(
+            state.db.pool(),
+            ActivationNudges::new(state.config.idle_robot_days, state.config.nudge_monthly_cap).robot_cutoff(now),
+            ActivationNudges::user_cutoff(now),
+        )
+        .await?,
     };
 
     Ok(Json(stats))
 }
+
+// Who the next activation nudge run would email, without sending
+pub async fn preview_nudges(
+    State(state): State<AppState>,
+    _current_user: User,
+) -> Result<Json<Vec<PlannedNudge>>> {
+    let nudges = ActivationNudges::new(state.config.idle_robot_days, state.config.nudge_monthly_cap);
+    Ok(Json(nudges.preview(state.nudges.as_ref(), Utc::now()).await?))
+}
+
+pub async fn get_admin_health(
+    State(state): State<AppState>,
+    _current_user: User,
+) -> Result<Json<AdminHealth>> {
+    let database = state.db.health_check().await.unwrap_or(false);
+
+    Ok(Json(AdminHealth {
+        database,
+        broker_throttle: state.broker_throttle.metrics(),
+        websocket_connections: state.websocket.connection_metrics().await,
+        websocket_global_channel: state.websocket.global_channel_metrics(),
+        websocket_shed: state.websocket.shed_counts(),
+        database_errors: database_error_counts(),
+        requests_by_client: request_counts_by_client(),
+        job_slots: state.jobs.usage().await.unwrap_or_default(),
+        email_outbox: OutboxEmail::health(state.db.pool()).await.unwrap_or_default(),
+        task_panics: task_panic_counts(),
+        timestamp: Utc::now().to_rfc3339(),
+    }))
+}
+
+pub async fn list_feature_flags(
+    State(state): State<AppState>,
+    _current_user: User,
+) -> Result<Json<Vec<FeatureFlag>>> {
+    let flags = FeatureFlag::find_all(state.db.pool()).await?;
+    Ok(Json(flags))
+}
+
+// Creates the flag if it does not exist yet
+pub async fn update_feature_flag(
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+    current_user: User,
+    Json(payload): Json<UpdateFeatureFlagRequest>,
+) -> Result<Json<FeatureFlag>> {
+    payload.validate().map_err(|e| AppError::Validation(e.to_string()))?;
+    if key.is_empty() || key.len() > 100 {
+        return Err(AppError::Validation("Flag key must be 1-100 characters".to_string()));
+    }
+
+    let previous = FeatureFlag::find_by_key(state.db.pool(), &key).await?;
+    let updated = previous
+        .clone()
+        .unwrap_or_else(|| FeatureFlag::new(&key))
+        .apply(payload, current_user.id);
+
+    let saved = FeatureFlag::save(state.db.pool(), &updated, previous.as_ref()).await?;
+    state.feature_flags.invalidate();
+
+    tracing::info!(
+        "Feature flag {} updated by {}: enabled={}, users={}, rollout={}%",
+        saved.key,
+        current_user.id,
+        saved.enabled,
+        saved.enabled_user_ids.len(),
+        saved.rollout_percentage
+    );
+
+    Ok(Json(saved))
+}
+
+async fn start_integrity_run(
+    state: &AppState,
+    job: IntegrityJob,
+    scope: IntegrityScope,
+    requested_by: &User,
+) -> Result<(StatusCode, Json<IntegrityRun>)> {
+    if let Some(user_id) = scope.user_id {
+        User::find_by_id_including_deleted(state.db.pool(), user_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+    }
+
+    let run = IntegrityRun::create(state.db.pool(), job.as_str(), scope.user_id, requested_by.id).await?;
+    tracing::info!(
+        "Integrity {} {} started by {} for {}",
+        job.as_str(),
+        run.id,
+        requested_by.id,
+        scope.user_id.map(|id| id.to_string()).unwrap_or_else(|| "all users".to_string())
+    );
+
+    spawn_supervised(
+        format!("integrity:{}", run.id),
+        TaskClass::Background,
+        IntegrityService::run_and_record(state.db.export_pool().clone(), run.id, job, scope.user_id),
+    );
+
+    Ok((StatusCode::ACCEPTED, Json(run)))
+}
+
+// Re-encrypts every broker connection with the current ENCRYPTION_KEY in the background.
+// While a rotation runs, calling this again just returns its progress.
+pub async fn rotate_encryption(
+    State(state): State<AppState>,
+    current_user: User,
+) -> Result<(StatusCode, Json<RotationStatus>)> {
+    let status = state.credentials.start_rotation();
+    tracing::info!("Encryption key rotation to {} requested by {}", status.key_id, current_user.id);
+    Ok((StatusCode::ACCEPTED, Json(status)))
+}
+
+pub async fn get_encryption_rotation(
+    State(state): State<AppState>,
+    _current_user: User,
+) -> Result<Json<RotationStatus>> {
+    Ok(Json(state.credentials.rotation_status()))
+}
+
+// Rebuilds robot and session totals from the trades table
+pub async fn recalculate_integrity(
+    State(state): State<AppState>,
+    current_user: User,
+    payload: Option<Json<IntegrityScope>>,
+) -> Result<(StatusCode, Json<IntegrityRun>)> {
+    let scope = payload.map(|Json(scope)| scope).unwrap_or_default();
+    start_integrity_run(&state, IntegrityJob::Recalculate, scope, &current_user).await
+}
+
+// Reports discrepancies without fixing them
+pub async fn check_integrity(
+    State(state): State<AppState>,
+    Query(scope): Query<IntegrityScope>,
+    current_user: User,
+) -> Result<(StatusCode, Json<IntegrityRun>)> {
+    start_integrity_run(&state, IntegrityJob::Check, scope, &current_user).await
+}
+
+pub async fn get_integrity_run(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    _current_user: User,
+) -> Result<Json<IntegrityRun>> {
+    let run = IntegrityRun::find_by_id(state.db.pool(), id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Integrity run not found".to_string()))?;
+    Ok(Json(run))
+}
+
+// Any user's trade, for support and disputes
+pub async fn get_trade_origin(
+    State(state): State<AppState>,
+    Path(trade_id): Path<Uuid>,
+    _current_user: User,
+) -> Result<Json<TradeOrigin>> {
+    let origin = TradeOrigin::find(state.db.pool(), trade_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("No origin recorded for this trade".to_string()))?;
+    Ok(Json(origin))
+}
+
+pub async fn list_templates(
+    State(state): State<AppState>,
+    _current_user: User,
+) -> Result<Json<Vec<MessageTemplate>>> {
+    Ok(Json(state.templates.list().await?))
+}
+
+// Saves a new inactive version; placeholders the key is not rendered with are refused
+pub async fn save_template(
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+    current_user: User,
+    Json(payload): Json<SaveTemplateRequest>,
+) -> Result<(StatusCode, Json<MessageTemplate>)> {
+    payload.validate().map_err(|e| AppError::Validation(e.to_string()))?;
+    let saved = state.templates.save(&key, &payload, current_user.id).await?;
+
+    tracing::info!(
+        target: "audit",
+        "Template {} ({}) version {} saved by {}",
+        saved.key, saved.locale, saved.version, current_user.id
+    );
+    Ok((StatusCode::CREATED, Json(saved)))
+}
+
+pub async fn activate_template(
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+    current_user: User,
+    Json(payload): Json<ActivateTemplateRequest>,
+) -> Result<Json<MessageTemplate>> {
+    let locale = payload.locale.as_deref().unwrap_or(DEFAULT_LOCALE);
+    let activated = state.templates.activate(&key, locale, payload.version).await?;
+
+    tracing::info!(
+        target: "audit",
+        "Template {} ({}) version {} activated by {}",
+        activated.key, activated.locale, activated.version, current_user.id
+    );
+    Ok(Json(activated))
+}
+
+// Renders with sample values and sends nothing
+pub async fn preview_template(
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+    _current_user: User,
+    Json(payload): Json<PreviewTemplateRequest>,
+) -> Result<Json<RenderedTemplate>> {
+    Ok(Json(state.templates.preview(&key, &payload).await?))
+}
+
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+pub struct StatsHistoryQuery {
+    // Inclusive dates; defaults to the last 30 days
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    // json (default) or csv
+    pub format: Option<String>,
+}
+
+// Daily platform KPIs for BI tools, as JSON or a streamed CSV file
+pub async fn get_stats_history(
+    State(state): State<AppState>,
+    Query(query): Query<StatsHistoryQuery>,
+    _current_user: User,
+) -> Result<Response> {
+    let (from, to) = PlatformStats::history_range(query.from, query.to, Utc::now().date_naive())?;
+    let days = PlatformStatsDay::range(state.db.export_pool(), from, to).await?;
+
+    match query.format.as_deref().unwrap_or("json") {
+        "json" => Ok(Json(days).into_response()),
+        "csv" => {
+            let rows = std::iter::once(CSV_HEADER.to_string()).chain(days.iter().map(PlatformStats::csv_row).collect::<Vec<_>>());
+            let body = Body::from_stream(futures_util::stream::iter(rows.map(Ok::<_, std::convert::Infallible>)));
+            let disposition = format!("attachment; filename=\"platform-stats-{}-{}.csv\"", from, to);
+            Ok((
+                [(header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()), (header::CONTENT_DISPOSITION, disposition)],
+                body,
+            )
+                .into_response())
+        }
+        other => Err(AppError::Validation(format!("Unknown format '{}', expected json or csv", other))),
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct StatsBackfillQuery {
+    pub from: NaiveDate,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct StatsBackfillResponse {
+    pub days: usize,
+}
+
+// Recomputes finished days from the raw tables; days snapshotted live keep their live-only columns
+pub async fn backfill_stats(
+    State(state): State<AppState>,
+    Query(query): Query<StatsBackfillQuery>,
+    current_user: User,
+) -> Result<Json<StatsBackfillResponse>> {
+    let store = PgPlatformStatsStore::new(state.db.pool().clone());
+    let days = PlatformStats::backfill(&store, query.from, Utc::now()).await?;
+    tracing::info!("Platform stats backfilled from {} by {} ({} days)", query.from, current_user.id, days);
+    Ok(Json(StatsBackfillResponse { days }))
+}
+
+pub async fn get_stats_export_settings(
+    State(state): State<AppState>,
+    _current_user: User,
+) -> Result<Json<StatsExportSettings>> {
+    Ok(Json(AdminSetting::get(state.db.pool(), STATS_EXPORT_SETTING).await?))
+}
+
+pub async fn update_stats_export_settings(
+    State(state): State<AppState>,
+    current_user: User,
+    Json(payload): Json<StatsExportSettings>,
+) -> Result<Json<StatsExportSettings>> {
+    payload.validate().map_err(|e| AppError::Validation(e.to_string()))?;
+    AdminSetting::put(state.db.pool(), STATS_EXPORT_SETTING, &payload, current_user.id).await?;
+    tracing::info!("Stats export settings updated by {}", current_user.id);
+    Ok(Json(payload))
+}
+
+pub async fn get_maintenance_settings(
+    State(state): State<AppState>,
+    _current_user: User,
+) -> Result<Json<MaintenanceNotice>> {
+    Ok(Json(AdminSetting::get(state.db.pool(), MAINTENANCE_SETTING).await?))
+}
+
+// An empty message takes the notice off the public status page
+pub async fn update_maintenance_settings(
+    State(state): State<AppState>,
+    current_user: User,
+    Json(payload): Json<MaintenanceNotice>,
+) -> Result<Json<MaintenanceNotice>> {
+    payload.validate().map_err(|e| AppError::Validation(e.to_string()))?;
+    AdminSetting::put(state.db.pool(), MAINTENANCE_SETTING, &payload, current_user.id).await?;
+    tracing::info!("Maintenance notice updated by {}: {:?}", current_user.id, payload.message);
+    Ok(Json(payload))
+}
+
+pub async fn get_broker_maintenance(
+    State(state): State<AppState>,
+    _current_user: User,
+) -> Result<Json<BrokerMaintenance>> {
+    Ok(Json(AdminSetting::get(state.db.pool(), BROKER_MAINTENANCE_SETTING).await?))
+}
+
+// Robots on connections of this broker type are paused now rather than on the next sync
+pub async fn set_broker_maintenance(
+    State(state): State<AppState>,
+    current_user: User,
+    Path(broker_type): Path<String>,
+    Json(payload): Json<BrokerMaintenanceRequest>,
+) -> Result<Json<BrokerMaintenance>> {
+    payload.validate().map_err(|e| AppError::Validation(e.to_string()))?;
+    let maintenance = state
+        .broker_maintenance
+        .set(&broker_type, payload, &state.runners, current_user.id, Utc::now())
+        .await?;
+    tracing::info!(target: "audit", "Broker maintenance for {} set by {}", broker_type, current_user.id);
+    Ok(Json(maintenance))
+}
+
+pub async fn lift_broker_maintenance(
+    State(state): State<AppState>,
+    current_user: User,
+    Path(broker_type): Path<String>,
+) -> Result<Json<BrokerMaintenance>> {
+    let maintenance = state
+        .broker_maintenance
+        .lift(&broker_type, &state.runners, current_user.id, Utc::now())
+        .await?;
+    tracing::info!(target: "audit", "Broker maintenance for {} lifted by {}", broker_type, current_user.id);
+    Ok(Json(maintenance))
+}
+
+pub async fn get_runtime_settings(
+    State(state): State<AppState>,
+    _current_user: User,
+) -> Result<Json<RuntimeSettings>> {
+    Ok(Json(AdminSetting::get(state.db.pool(), RUNTIME_SETTING).await?))
+}
+
+// Applied here at once and by the other instances on their next poll
+pub async fn update_runtime_settings(
+    State(state): State<AppState>,
+    current_user: User,
+    Json(payload): Json<RuntimeSettingsPatch>,
+) -> Result<Json<RuntimeSettings>> {
+    let before: RuntimeSettings = AdminSetting::get(state.db.pool(), RUNTIME_SETTING).await?;
+    let mut after = before.clone();
+    after.apply(payload);
+    RuntimeConfig::validate(&after)?;
+
+    AdminSetting::put(state.db.pool(), RUNTIME_SETTING, &after, current_user.id).await?;
+    tracing::info!(target: "audit", "Runtime settings changed by {}: {:?} -> {:?}", current_user.id, before, after);
+    state.runtime.reload().await?;
+    Ok(Json(after))
+}
+
+pub async fn create_incident(
+    State(state): State<AppState>,
+    current_user: User,
+    Json(payload): Json<CreateIncidentRequest>,
+) -> Result<(StatusCode, Json<IncidentResponse>)> {
+    payload.validate().map_err(|e| AppError::Validation(e.to_string()))?;
+    Incident::validate_status(&payload.status)?;
+
+    let incident = Incident::new(payload.title, payload.status, payload.message, current_user.id, Utc::now());
+    Incident::create(state.db.pool(), &incident).await?;
+    tracing::info!("Incident {} ({}) opened by {}", incident.id, incident.status, current_user.id);
+
+    Ok((StatusCode::CREATED, Json(incident.into())))
+}
+
+// Appends to the timeline; an update with status resolved closes the incident
+pub async fn add_incident_update(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    current_user: User,
+    Json(payload): Json<IncidentUpdateRequest>,
+) -> Result<Json<IncidentResponse>> {
+    payload.validate().map_err(|e| AppError::Validation(e.to_string()))?;
+    Incident::validate_status(&payload.status)?;
+
+    let update = IncidentUpdate { status: payload.status, message: payload.message, created_at: Utc::now() };
+    let incident = Incident::add_update(state.db.pool(), id, &update)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Incident not found".to_string()))?;
+    tracing::info!("Incident {} moved to {} by {}", incident.id, incident.status, current_user.id);
+
+    Ok(Json(incident.into()))
+}
@@ -0,0 +1,60 @@
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::Json,
+};
+use chrono::Utc;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{
+    app_middleware::NotImpersonated,
+    models::{
+        ApiKey, ApiKeyResponse, CreateApiKeyRequest, CreatedApiKeyResponse, User, AUTH_EVENT_API_KEY_CREATED,
+        AUTH_EVENT_API_KEY_REVOKED, AUTH_OUTCOME_SUCCESS,
+    },
+    services::{auth_events, ApiKeyService},
+    errors::{AppError, Result},
+    AppState,
+};
+
+// The key is in this response only; afterwards just its prefix is shown
+pub async fn create_api_key(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    current_user: User,
+    _not_impersonated: NotImpersonated,
+    Json(payload): Json<CreateApiKeyRequest>,
+) -> Result<(StatusCode, Json<CreatedApiKeyResponse>)> {
+    payload.validate().map_err(|e| AppError::Validation(e.to_string()))?;
+    let scopes = ApiKeyService::normalize_scopes(&payload.scopes)?;
+
+    let (key, api_key) = ApiKey::generate(current_user.id, payload.label.trim().to_string(), scopes, Utc::now());
+    ApiKey::create(state.db.pool(), &api_key).await?;
+    tracing::info!(target: "audit", "API key {} ({}) created by {}", api_key.id, api_key.scopes.join(", "), current_user.id);
+    auth_events::record(state.db.pool(), auth_events::event(&headers, Some(current_user.id), AUTH_EVENT_API_KEY_CREATED, AUTH_OUTCOME_SUCCESS));
+
+    Ok((StatusCode::CREATED, Json(CreatedApiKeyResponse { key, api_key: api_key.to_response() })))
+}
+
+pub async fn list_api_keys(
+    State(state): State<AppState>,
+    current_user: User,
+) -> Result<Json<Vec<ApiKeyResponse>>> {
+    let keys = ApiKey::find_by_user(state.db.pool(), current_user.id).await?;
+    Ok(Json(keys.iter().map(ApiKey::to_response).collect()))
+}
+
+pub async fn revoke_api_key(
+    State(state): State<AppState>,
+    Path(key_id): Path<Uuid>,
+    headers: HeaderMap,
+    current_user: User,
+) -> Result<StatusCode> {
+    if !ApiKey::revoke(state.db.pool(), current_user.id, key_id, Utc::now()).await? {
+        return Err(AppError::NotFound("API key not found".to_string()));
+    }
+    tracing::info!(target: "audit", "API key {} revoked by {}", key_id, current_user.id);
+    auth_events::record(state.db.pool(), auth_events::event(&headers, Some(current_user.id), AUTH_EVENT_API_KEY_REVOKED, AUTH_OUTCOME_SUCCESS));
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -1,69 +1,115 @@
 use axum::{
-    extract::State,
+    extract::{Path, State},
+    http::{header::USER_AGENT, HeaderMap, StatusCode},
     response::Json,
 };
 use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
 use uuid::Uuid;
 
 use crate::{
-    models::User,
-    services::auth_service::AuthService,
-    errors::Result,
+    app_middleware::NotImpersonated,
+    models::{
+        LinkedAccount, PasswordReset, User, UserResponse, UserSession, UserSessionResponse, AUTH_EVENT_LOGIN,
+        AUTH_EVENT_LOGOUT, AUTH_EVENT_LOGOUT_ALL, AUTH_EVENT_PASSWORD_CHANGED, AUTH_EVENT_PASSWORD_RESET,
+        AUTH_EVENT_SESSION_REVOKED, AUTH_OUTCOME_FAILURE, AUTH_OUTCOME_SUCCESS, AUTH_REASON_ACCOUNT_DISABLED,
+        AUTH_REASON_INVALID_PASSWORD, AUTH_REASON_UNKNOWN_EMAIL,
+    },
+    services::{
+        auth_events,
+        auth_service::{AuthService, Claims},
+        event_bus::{DomainEvent, EventPublisher},
+        oauth::PROVIDER_GOOGLE,
+        password_policy::PasswordPolicy,
+        trade_origins,
+    },
+    errors::{AppError, Result},
     AppState,
 };
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(rename = "RegisterRequest")]
 pub struct CreateUserRequest {
     pub email: String,
     pub password: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct LoginRequest {
     pub email: String,
     pub password: String,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct GoogleLoginRequest {
+// A Google or Apple ID token, or a GitHub access token
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct OAuthLoginRequest {
     pub token: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct ChangePasswordResponse {
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct PasswordResetRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct PasswordResetConfirmRequest {
+    // From the emailed link
+    pub token: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct LoginResponse {
     pub token: String,
     pub user: UserResponse,
 }
 
-#[derive(Debug, Serialize)]
-pub struct UserResponse {
-    pub id: Uuid,
-    pub email: String,
-    pub is_active: bool,
-    pub is_superuser: bool,
-    pub subscription_plan: String,
-    pub created_at: String,
-    pub updated_at: String,
-}
-
-impl From<User> for UserResponse {
-    fn from(user: User) -> Self {
-        Self {
-            id: user.id,
-            email: user.email,
-            is_active: user.is_active,
-            is_superuser: user.is_superuser,
-            subscription_plan: user.subscription_plan,
-            created_at: user.created_at.format("%Y-%m-%d %H:%M:%S").to_string(),
-            updated_at: user.updated_at.format("%Y-%m-%d %H:%M:%S").to_string(),
-        }
+impl LoginResponse {
+    fn new(token: String, user: User) -> Self {
+        LoginResponse { token, user: user.into() }
     }
 }
 
+// Issued at the user's current token version, so it survives until the next "logout all devices".
+// Each token is a session the user can see and sign out from the sessions list.
+async fn issue_token(state: &AppState, user_id: Uuid, headers: &HeaderMap) -> Result<String> {
+    let version = state.token_revocations.version(user_id).await?;
+    start_session(state, user_id, version, headers).await
+}
+
+async fn start_session(state: &AppState, user_id: Uuid, version: u32, headers: &HeaderMap) -> Result<String> {
+    let (token, claims) = AuthService::issue_token(user_id, version, &state.config.token_settings())?;
+    let session = UserSession::new(
+        AuthService::token_id(&claims)?,
+        user_id,
+        version,
+        headers.get(USER_AGENT).and_then(|v| v.to_str().ok()),
+        trade_origins::source_ip(headers),
+        chrono::DateTime::from_timestamp(claims.iat as i64, 0).unwrap_or_else(chrono::Utc::now),
+        chrono::DateTime::from_timestamp(claims.exp as i64, 0).unwrap_or_else(chrono::Utc::now),
+    );
+    UserSession::create(state.db.pool(), &session).await?;
+    Ok(token)
+}
+
 pub async fn register(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Json(payload): Json<CreateUserRequest>,
-) -> Result<Json<serde_json::Value>> {
+) -> Result<Json<LoginResponse>> {
+    state.passwords.check(&payload.password, &payload.email).await?;
+
     // Check if user already exists
     if let Some(_) = User::find_by_email(state.db.pool(), &payload.email).await? {
         return Err(crate::errors::AppError::Validation("Email already exists".to_string()));
@@ -75,83 +121,116 @@ pub async fn register(
         password: payload.password,
     };
     let user = User::create(state.db.pool(), create_request).await?;
+    state.events.publish(DomainEvent::UserRegistered { user_id: user.id, email: user.email.clone() });
 
     // Generate token
-    let token = AuthService::create_token(user.id, &state.config.jwt_secret)?;
+    let token = issue_token(&state, user.id, &headers).await?;
 
-    Ok(Json(serde_json::json!({
-        "token": token,
-        "user": UserResponse::from(user)
-    })))
+    Ok(Json(LoginResponse::new(token, user)))
 }
 
 pub async fn login(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Json(payload): Json<LoginRequest>,
-) -> Result<Json<serde_json::Value>> {
-    // Find user by email
-    let user = User::find_by_email(state.db.pool(), &payload.email)
-        .await?
-        .ok_or_else(|| crate::errors::AppError::Auth("Invalid credentials".to_string()))?;
+) -> Result<Json<LoginResponse>> {
+    let ip = trade_origins::source_ip(&headers);
+    state.login_throttle.check(&payload.email, ip.as_deref()).await?;
 
-    // Verify password
-    if !user.verify_password(&payload.password) {
-        return Err(crate::errors::AppError::Auth("Invalid credentials".to_string()));
-    }
+    // Find user by email; an unknown address counts as a failure like a wrong password
+    let user = match User::find_by_email(state.db.pool(), &payload.email).await? {
+        Some(user) if user.verify_password(&payload.password) => user,
+        user => {
+            let reason = if user.is_some() { AUTH_REASON_INVALID_PASSWORD } else { AUTH_REASON_UNKNOWN_EMAIL };
+            let event = auth_events::event(&headers, user.as_ref().map(|u| u.id), AUTH_EVENT_LOGIN, AUTH_OUTCOME_FAILURE);
+            auth_events::record(state.db.pool(), event.with_reason(reason));
+            state.login_throttle.record_failure(&payload.email, ip.as_deref(), user.as_ref()).await;
+            return Err(crate::errors::AppError::Auth("Invalid credentials".to_string()));
+        }
+    };
 
     // Check if user is active
     if !user.is_active {
+        let event = auth_events::event(&headers, Some(user.id), AUTH_EVENT_LOGIN, AUTH_OUTCOME_FAILURE);
+        auth_events::record(state.db.pool(), event.with_reason(AUTH_REASON_ACCOUNT_DISABLED));
         return Err(crate::errors::AppError::Auth("Account is disabled".to_string()));
     }
 
+    state.login_throttle.reset(&payload.email).await;
+
     // Update last login
     User::update_last_login(state.db.pool(), user.id).await?;
 
     // Generate token
-    let token = AuthService::create_token(user.id, &state.config.jwt_secret)?;
+    let token = issue_token(&state, user.id, &headers).await?;
+    auth_events::record(state.db.pool(), auth_events::event(&headers, Some(user.id), AUTH_EVENT_LOGIN, AUTH_OUTCOME_SUCCESS));
 
-    Ok(Json(serde_json::json!({
-        "token": token,
-        "user": UserResponse::from(user)
-    })))
+    Ok(Json(LoginResponse::new(token, user)))
 }
 
+// Kept for clients built before the other providers; same as /api/v1/auth/oauth/google
 pub async fn google_login(
+    state: State<AppState>,
+    headers: HeaderMap,
+    payload: Json<OAuthLoginRequest>,
+) -> Result<Json<LoginResponse>> {
+    oauth_login(state, Path(PROVIDER_GOOGLE.to_string()), headers, payload).await
+}
+
+// Signs in with a token from one of the configured providers. The provider's account is linked
+// to the user on first use: to the account registered with the same verified email if there is
+// one, so every provider with that address reaches the same user, or else to a new account.
+pub async fn oauth_login(
     State(state): State<AppState>,
-    Json(payload): Json<GoogleLoginRequest>,
-) -> Result<Json<serde_json::Value>> {
-    // Verify Google token and get user info
-    let google_user = AuthService::verify_google_token(&payload.token).await?;
-    
-    // Check if user exists
-    let user = match User::find_by_email(state.db.pool(), &google_user.email).await? {
-        Some(existing_user) => {
-            // Update last login
-            User::update_last_login(state.db.pool(), existing_user.id).await?;
-            existing_user
-        }
-        None => {
-            // Create new user from Google info
-            let create_request = crate::models::user::CreateUserRequest {
-                email: google_user.email,
-                password: uuid::Uuid::new_v4().to_string(), // Random password for OAuth users
-            };
-            User::create(state.db.pool(), create_request).await?
-        }
+    Path(provider): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<OAuthLoginRequest>,
+) -> Result<Json<LoginResponse>> {
+    let provider = state.oauth.get(&provider)?;
+    let external = provider.verify(&payload.token).await?;
+    let pool = state.db.pool();
+
+    let linked = LinkedAccount::find_user_id(pool, provider.name(), &external.external_id).await?;
+    let user = match linked {
+        Some(user_id) => User::find_by_id(pool, user_id)
+            .await?
+            .ok_or_else(|| AppError::Auth("User not found".to_string()))?,
+        None => match User::find_by_email(pool, &external.email).await? {
+            // The provider has verified the address, so the account registered with it is theirs
+            Some(user) => {
+                if !LinkedAccount::link(pool, user.id, provider.name(), &external.external_id, &external.email).await? {
+                    return Err(AppError::Auth(format!(
+                        "This account is linked to a different {} account",
+                        provider.name()
+                    )));
+                }
+                user
+            }
+            None => {
+                let create_request = crate::models::user::CreateUserRequest {
+                    email: external.email.clone(),
+                    password: Uuid::new_v4().to_string(), // Random password for OAuth users
+                };
+                let user = User::create(pool, create_request).await?;
+                LinkedAccount::link(pool, user.id, provider.name(), &external.external_id, &external.email).await?;
+                state.events.publish(DomainEvent::UserRegistered { user_id: user.id, email: user.email.clone() });
+                user
+            }
+        },
     };
+    let user = User::fill_profile(pool, user, external.name, external.avatar_url).await?;
 
-    // Check if user is active
     if !user.is_active {
-        return Err(crate::errors::AppError::Auth("Account is disabled".to_string()));
+        let event = auth_events::event(&headers, Some(user.id), provider.login_event(), AUTH_OUTCOME_FAILURE);
+        auth_events::record(pool, event.with_reason(AUTH_REASON_ACCOUNT_DISABLED));
+        return Err(AppError::Auth("Account is disabled".to_string()));
     }
+    User::update_last_login(pool, user.id).await?;
 
-    // Generate token
-    let token = AuthService::create_token(user.id, &state.config.jwt_secret)?;
+    let token = issue_token(&state, user.id, &headers).await?;
+    auth_events::record(pool, auth_events::event(&headers, Some(user.id), provider.login_event(), AUTH_OUTCOME_SUCCESS));
 
-    Ok(Json(serde_json::json!({
-        "token": token,
-        "user": UserResponse::from(user)
-    })))
+    Ok(Json(LoginResponse::new(token, user)))
 }
 
 pub async fn me(
@@ -160,3 +239,148 @@ pub async fn me(
 ) -> Result<Json<UserResponse>> {
     Ok(Json(UserResponse::from(current_user)))
 }
+
+// Lets the frontend check a new password before submitting it
+pub async fn get_password_policy(State(state): State<AppState>) -> Json<PasswordPolicy> {
+    Json(state.passwords.policy().clone())
+}
+
+// Sets the new password and signs out every token issued so far. Returns the new token version.
+async fn replace_password(state: &AppState, user: &User, headers: &HeaderMap, payload: &ChangePasswordRequest) -> Result<u32> {
+    if !user.verify_password(&payload.current_password) {
+        let event = auth_events::event(headers, Some(user.id), AUTH_EVENT_PASSWORD_CHANGED, AUTH_OUTCOME_FAILURE);
+        auth_events::record(state.db.pool(), event.with_reason(AUTH_REASON_INVALID_PASSWORD));
+        return Err(AppError::Validation("The current password is incorrect".to_string()));
+    }
+    state.passwords.check(&payload.new_password, &user.email).await?;
+
+    User::set_password(state.db.pool(), user.id, &payload.new_password).await?;
+    let version = state.token_revocations.bump_version(user.id).await?;
+    let event = auth_events::event(headers, Some(user.id), AUTH_EVENT_PASSWORD_CHANGED, AUTH_OUTCOME_SUCCESS);
+    auth_events::record(state.db.pool(), event);
+    Ok(version)
+}
+
+// Signs the caller out too; clients that want to stay signed in use `change_password_and_reissue`
+pub async fn change_password(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    current_user: User,
+    _not_impersonated: NotImpersonated,
+    Json(payload): Json<ChangePasswordRequest>,
+) -> Result<StatusCode> {
+    replace_password(&state, &current_user, &headers, &payload).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Other devices are signed out; the caller gets a token at the new version to carry on with
+pub async fn change_password_and_reissue(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    current_user: User,
+    _not_impersonated: NotImpersonated,
+    Json(payload): Json<ChangePasswordRequest>,
+) -> Result<Json<ChangePasswordResponse>> {
+    let version = replace_password(&state, &current_user, &headers, &payload).await?;
+    let token = start_session(&state, current_user.id, version, &headers).await?;
+    Ok(Json(ChangePasswordResponse { token }))
+}
+
+// Signs out the token the request was made with; the user's other sessions stay signed in
+pub async fn logout(State(state): State<AppState>, headers: HeaderMap, claims: Claims) -> Result<StatusCode> {
+    if claims.jti.is_empty() {
+        return Err(crate::errors::AppError::Validation(
+            "This token predates per-session logout; use logout-all to sign it out".to_string(),
+        ));
+    }
+    let now = chrono::Utc::now();
+    let user_id = AuthService::user_id(&claims)?;
+    state.token_revocations.revoke(&claims.jti, claims.remaining_seconds(now)).await?;
+    if let Ok(session_id) = AuthService::token_id(&claims) {
+        UserSession::revoke(state.db.pool(), user_id, session_id, now).await?;
+    }
+    auth_events::record(state.db.pool(), auth_events::event(&headers, Some(user_id), AUTH_EVENT_LOGOUT, AUTH_OUTCOME_SUCCESS));
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Signs out every token issued to the user so far, on all devices
+pub async fn logout_all(State(state): State<AppState>, headers: HeaderMap, claims: Claims) -> Result<StatusCode> {
+    let user_id = AuthService::user_id(&claims)?;
+    state.token_revocations.bump_version(user_id).await?;
+    auth_events::record(state.db.pool(), auth_events::event(&headers, Some(user_id), AUTH_EVENT_LOGOUT_ALL, AUTH_OUTCOME_SUCCESS));
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Where the token's user is signed in. Sessions are the signed-in user's own, even when acting for
+// a delegating account.
+pub async fn list_sessions(State(state): State<AppState>, claims: Claims) -> Result<Json<Vec<UserSessionResponse>>> {
+    let user_id = AuthService::user_id(&claims)?;
+    let version = state.token_revocations.version(user_id).await?;
+    let sessions = UserSession::find_active(state.db.pool(), user_id, version, chrono::Utc::now()).await?;
+    let current = AuthService::token_id(&claims).ok();
+    Ok(Json(sessions.iter().map(|session| session.to_response(current)).collect()))
+}
+
+// Signs a device out; its token is refused from the next request on
+pub async fn revoke_session(
+    State(state): State<AppState>,
+    Path(session_id): Path<Uuid>,
+    headers: HeaderMap,
+    claims: Claims,
+) -> Result<StatusCode> {
+    let now = chrono::Utc::now();
+    let user_id = AuthService::user_id(&claims)?;
+    let session = UserSession::find_open(state.db.pool(), user_id, session_id, now)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Session not found".to_string()))?;
+    // Denylisted first, so the session is never shown as signed out while its token still works
+    state
+        .token_revocations
+        .revoke(&session.id.to_string(), session.remaining_seconds(now))
+        .await?;
+    UserSession::revoke(state.db.pool(), user_id, session.id, now).await?;
+    auth_events::record(state.db.pool(), auth_events::event(&headers, Some(user_id), AUTH_EVENT_SESSION_REVOKED, AUTH_OUTCOME_SUCCESS));
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Answers the same whether or not the address has an account, so it can't be used to find one out
+pub async fn request_password_reset(
+    State(state): State<AppState>,
+    Json(payload): Json<PasswordResetRequest>,
+) -> Result<StatusCode> {
+    let user = User::find_by_email(state.db.pool(), payload.email.trim()).await?.filter(|u| u.is_active);
+    if let Some(user) = user {
+        let token = PasswordReset::create(state.db.pool(), user.id, chrono::Utc::now()).await?;
+        let reset_url = format!(
+            "{}/reset-password?token={}",
+            state.config.public_base_url.trim_end_matches('/'),
+            token
+        );
+        state.events.publish(DomainEvent::PasswordResetRequested { user_id: user.id, email: user.email, reset_url });
+    }
+    Ok(StatusCode::ACCEPTED)
+}
+
+// Sets the new password under the same policy as registration and signs out every existing session
+pub async fn confirm_password_reset(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<PasswordResetConfirmRequest>,
+) -> Result<StatusCode> {
+    let now = chrono::Utc::now();
+    let invalid = || AppError::Validation("This password reset link is not valid".to_string());
+    let reset = PasswordReset::find_by_token(state.db.pool(), payload.token.trim())
+        .await?
+        .ok_or_else(invalid)?;
+    reset.check_usable(now)?;
+    let user = User::find_by_id(state.db.pool(), reset.user_id).await?.ok_or_else(invalid)?;
+    state.passwords.check(&payload.new_password, &user.email).await?;
+
+    // Another request may have used the token since it was checked
+    if !PasswordReset::redeem(state.db.pool(), reset.id, &payload.new_password, now).await? {
+        return Err(AppError::Validation("This password reset link has already been used".to_string()));
+    }
+    state.token_revocations.bump_version(user.id).await?;
+    auth_events::record(state.db.pool(), auth_events::event(&headers, Some(user.id), AUTH_EVENT_PASSWORD_RESET, AUTH_OUTCOME_SUCCESS));
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,37 @@
+use axum::{
+    extract::State,
+    http::HeaderMap,
+    response::Json,
+};
+use chrono::Utc;
+
+use crate::{
+    errors::{AppError, Result},
+    models::BridgeToken,
+    services::{
+        bridge_events::{BridgeEvent, BridgeEventOutcome, PgBridgeStore, BRIDGE_TOKEN_HEADER},
+        BridgeEvents,
+    },
+    AppState,
+};
+
+// Called by a connection's MT5 bridge, so it authenticates with the bridge token instead of a
+// session. Errors make the bridge retry; duplicates are acknowledged.
+pub async fn receive_event(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(event): Json<BridgeEvent>,
+) -> Result<Json<BridgeEventOutcome>> {
+    let token = headers
+        .get(BRIDGE_TOKEN_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .ok_or_else(|| AppError::Auth("Missing X-Bridge-Token header".to_string()))?;
+    let connection = BridgeToken::find_connection(state.db.pool(), &BridgeEvents::hash_token(token))
+        .await?
+        .ok_or_else(|| AppError::Auth("Invalid bridge token".to_string()))?;
+
+    let store = PgBridgeStore::new(state.db.pool().clone());
+    // Websocket updates, cache invalidation and loss streaks follow from the published events
+    let outcome = BridgeEvents::apply(&store, state.events.as_ref(), &connection, &event, Utc::now()).await?;
+    Ok(Json(outcome))
+}
@@ -1,36 +1,140 @@
 use axum::{
-    extract::{Path, State},
-    response::Json,
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
 };
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+use schemars::JsonSchema;
 use uuid::Uuid;
 use validator::Validate;
 
 use crate::{
-    models::{User, BrokerConnection, CreateBrokerConnectionRequest, BrokerConnectionResponse, TestConnectionResponse},
+    app_middleware::NotImpersonated,
+    models::{
+        User, AccountSnapshot, TradingRobot, BridgeToken, Subscription, BridgeTokenResponse, BrokerConnection, CreateBrokerConnectionRequest,
+        BrokerConnectionResponse, SnapshotGranularity, TestConnectionResponse, UpdateBrokerCredentialsRequest,
+        AUTH_EVENT_BROKER_CREDENTIALS_CHANGED, AUTH_OUTCOME_SUCCESS,
+    },
+    services::{
+        auth_events,
+        broker_connection_service::{PgBrokerConnectionStore, CREATE_TEST_TIMEOUT},
+        event_bus::{DomainEvent, EventPublisher},
+        plan_downgrade::RUNNING_STATUSES,
+        BridgeEvents, BrokerConnectionService, PlanService,
+    },
     errors::{Result, AppError},
+    pagination::{PageQuery, Paginated},
     AppState,
 };
 
+// A bare array of every connection unless envelope=true asks for a page
 pub async fn list_brokers(
     State(state): State<AppState>,
+    Query(query): Query<PageQuery>,
     current_user: User,
-) -> Result<Json<Vec<BrokerConnectionResponse>>> {
-    let connections = BrokerConnection::find_by_user_id(state.db.pool(), current_user.id).await?;
+) -> Result<Response> {
+    let page = query.page();
+    let (limit, offset) = page.map_or((None, 0), |p| (Some(p.limit), p.offset));
+    let connections = BrokerConnection::find_page_by_user_id(state.db.pool(), current_user.id, limit, offset).await?;
     let responses: Vec<BrokerConnectionResponse> = connections.into_iter().map(|c| c.into()).collect();
-    Ok(Json(responses))
+    match page {
+        Some(page) => {
+            let total = BrokerConnection::count_by_user_id(state.db.pool(), current_user.id).await?;
+            Ok(Json(Paginated::new(responses, total, page)).into_response())
+        }
+        None => Ok(Json(responses).into_response()),
+    }
 }
 
 pub async fn create_broker(
     State(state): State<AppState>,
     current_user: User,
+    _not_impersonated: NotImpersonated,
     Json(payload): Json<CreateBrokerConnectionRequest>,
 ) -> Result<Json<BrokerConnectionResponse>> {
     payload.validate().map_err(|e| AppError::Validation(e.to_string()))?;
 
-    let connection = BrokerConnection::create(state.db.pool(), current_user.id, payload).await?;
+    let store = PgBrokerConnectionStore::new(state.db.pool().clone(), state.credentials.clone());
+    if payload.test_on_create {
+        let response = BrokerConnectionService::create_tested(
+            &store,
+            state.mt5.as_ref(),
+            current_user.id,
+            payload,
+            CREATE_TEST_TIMEOUT,
+        )
+        .await?;
+        return Ok(Json(response));
+    }
+
+    let connection = BrokerConnection::from_request(current_user.id, payload);
+    BrokerConnectionService::ensure_not_duplicate(&store, &connection).await?;
+    let connection = BrokerConnection::create(state.db.pool(), state.credentials.seal(connection)).await?;
     Ok(Json(connection.into()))
 }
 
+// Replaces the stored credentials, e.g. once they could no longer be decrypted
+pub async fn update_credentials(
+    State(state): State<AppState>,
+    Path(connection_id): Path<Uuid>,
+    headers: HeaderMap,
+    current_user: User,
+    _not_impersonated: NotImpersonated,
+    Json(payload): Json<UpdateBrokerCredentialsRequest>,
+) -> Result<Json<BrokerConnectionResponse>> {
+    payload.validate().map_err(|e| AppError::Validation(e.to_string()))?;
+    let connection = BrokerConnection::find_by_id(state.db.pool(), connection_id, current_user.id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Broker connection not found".to_string()))?;
+
+    let sealed = state.credentials.seal_credentials(&payload.api_key, &payload.api_secret);
+    let key_id = state.credentials.current_key_id();
+    BrokerConnection::update_credentials(state.db.pool(), connection_id, current_user.id, &sealed.api_key, &sealed.api_secret, key_id)
+        .await?;
+    let event = auth_events::event(&headers, Some(current_user.id), AUTH_EVENT_BROKER_CREDENTIALS_CHANGED, AUTH_OUTCOME_SUCCESS);
+    auth_events::record(state.db.pool(), event);
+
+    Ok(Json(
+        BrokerConnection {
+            api_key: sealed.api_key,
+            api_secret: sealed.api_secret,
+            credentials_key_id: Some(key_id.to_string()),
+            needs_credentials: false,
+            ..connection
+        }
+        .into(),
+    ))
+}
+
+// Deletes a connection no running robot trades through. Its credentials are erased at once;
+// stopped robots using it are left without a connection.
+pub async fn delete_broker(
+    State(state): State<AppState>,
+    Path(connection_id): Path<Uuid>,
+    current_user: User,
+    _not_impersonated: NotImpersonated,
+) -> Result<StatusCode> {
+    let pool = state.db.pool();
+    BrokerConnection::find_by_id(pool, connection_id, current_user.id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Broker connection not found".to_string()))?;
+    let running = TradingRobot::find_by_user_id(pool, current_user.id)
+        .await?
+        .into_iter()
+        .filter(|r| r.broker_connection_id == Some(connection_id) && RUNNING_STATUSES.contains(&r.status.as_str()))
+        .count();
+    if running > 0 {
+        return Err(AppError::Unprocessable(format!(
+            "Stop the robots using this connection before deleting it ({} running robot(s))",
+            running
+        )));
+    }
+
+    BrokerConnection::delete(pool, connection_id, current_user.id, chrono::Utc::now()).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
 pub async fn test_connection(
     State(state): State<AppState>,
     Path(connection_id): Path<Uuid>,
@@ -53,6 +157,65 @@ pub async fn test_connection(
         connection_id,
         if test_result.success { "success" } else { "failed" },
     ).await?;
+    if !test_result.success {
+        state.events.publish(DomainEvent::BrokerTestFailed {
+            connection_id,
+            user_id: current_user.id,
+            error: test_result.message.clone(),
+        });
+    }
 
     Ok(Json(test_result))
 }
+
+// Issues the token the connection's MT5 bridge pushes events with, replacing any earlier one
+pub async fn issue_bridge_token(
+    State(state): State<AppState>,
+    Path(connection_id): Path<Uuid>,
+    current_user: User,
+    _not_impersonated: NotImpersonated,
+) -> Result<Json<BridgeTokenResponse>> {
+    BrokerConnection::find_by_id(state.db.pool(), connection_id, current_user.id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Broker connection not found".to_string()))?;
+
+    let token = BridgeEvents::new_token();
+    BridgeToken::issue(state.db.pool(), connection_id, &BridgeEvents::hash_token(&token)).await?;
+    tracing::info!(target: "audit", "Bridge token issued by {} for connection {}", current_user.id, connection_id);
+    Ok(Json(BridgeTokenResponse { connection_id, token }))
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct SnapshotsQuery {
+    // hour (kept for 7 days) | day
+    #[serde(default)]
+    pub granularity: SnapshotGranularity,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+pub async fn list_snapshots(
+    State(state): State<AppState>,
+    Path(connection_id): Path<Uuid>,
+    Query(query): Query<SnapshotsQuery>,
+    current_user: User,
+) -> Result<(HeaderMap, Json<Vec<AccountSnapshot>>)> {
+    BrokerConnection::find_by_id(state.db.pool(), connection_id, current_user.id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Broker connection not found".to_string()))?;
+
+    let to = query.to.unwrap_or_else(Utc::now);
+    let from = query.from.unwrap_or_else(|| match query.granularity {
+        SnapshotGranularity::Hour => to - Duration::days(7),
+        SnapshotGranularity::Day => to - Duration::days(365),
+    });
+    if from > to {
+        return Err(AppError::Validation("from must not be after to".to_string()));
+    }
+    let plan = Subscription::plan_details(&current_user.subscription_plan);
+    let (from, truncated) = PlanService::clamp_range(&plan, from, Utc::now());
+
+    let snapshots =
+        AccountSnapshot::find_range(state.db.pool(), connection_id, current_user.id, query.granularity, from, to).await?;
+    Ok((PlanService::truncation_headers(truncated), Json(snapshots)))
+}
@@ -1,57 +1,97 @@
 use axum::{
-    extract::State,
+    extract::{Query, State},
     response::Json,
 };
 use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
 
 use crate::{
-    models::{User, Trade, TradingRobot, TradeStatistics},
-    errors::Result,
+    models::{User, BrokerConnection, DemoMode, Trade, TradeCorrection, TradeCursor, TradeFilter, TradeReview, TradingRobot, TradeStatistics},
+    services::{
+        account_snapshot_service::{AccountSnapshotService, PgSnapshotEnv},
+        dashboard_service::{DashboardService, Sparklines},
+        robot_journal::{PgRobotJournalStore, RobotJournal},
+        TradePages,
+    },
+    errors::{AppError, Result},
     AppState,
 };
 
-#[derive(Debug, Serialize, Deserialize)]
+const RECENT_TRADES: i64 = 10;
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct DashboardQuery {
+    // Comma-separated list of optional sections, e.g. `include=sparklines`
+    pub include: Option<String>,
+    // true | only; demo trades are excluded by default
+    pub include_demo: Option<String>,
+}
+
+impl DashboardQuery {
+    fn includes(&self, section: &str) -> bool {
+        self.include
+            .as_deref()
+            .map(|include| include.split(',').any(|s| s.trim() == section))
+            .unwrap_or(false)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct DashboardData {
     pub user_info: DashboardUserInfo,
     pub trading_stats: TradeStatistics,
     pub active_robots: Vec<DashboardRobot>,
     pub recent_trades: Vec<DashboardTrade>,
+    // `after` for GET /api/v1/trades to page on from the recent trades; null when there are no more
+    pub recent_trades_after: Option<String>,
     pub performance_summary: PerformanceSummary,
+    // Closed trades waiting for a journal entry, for the journal badge
+    pub pending_reviews: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sparklines: Option<Sparklines>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct DashboardUserInfo {
     pub email: String,
     pub subscription_plan: String,
+    #[serde(serialize_with = "crate::money::serialize_amount")]
     pub account_balance: f64,
     pub total_robots: i32,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct DashboardRobot {
     pub id: uuid::Uuid,
     pub name: String,
     pub symbol: String,
     pub status: String,
+    #[serde(serialize_with = "crate::money::serialize_aggregate")]
     pub total_profit: f64,
+    #[serde(serialize_with = "crate::money::serialize_percent")]
     pub win_rate: f64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct DashboardTrade {
     pub id: uuid::Uuid,
     pub symbol: String,
     pub trade_type: String,
+    #[serde(serialize_with = "crate::money::serialize_opt_amount")]
     pub profit_loss: Option<f64>,
     pub status: String,
     pub opened_at: chrono::DateTime<chrono::Utc>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct PerformanceSummary {
+    #[serde(serialize_with = "crate::money::serialize_aggregate")]
     pub today_profit: f64,
+    #[serde(serialize_with = "crate::money::serialize_aggregate")]
     pub week_profit: f64,
+    #[serde(serialize_with = "crate::money::serialize_aggregate")]
     pub month_profit: f64,
+    #[serde(serialize_with = "crate::money::serialize_aggregate")]
     pub total_profit: f64,
     pub best_performing_symbol: Option<String>,
     pub worst_performing_symbol: Option<String>,
@@ -59,13 +99,18 @@ pub struct PerformanceSummary {
 
 pub async fn get_dashboard(
     State(state): State<AppState>,
+    Query(query): Query<DashboardQuery>,
     current_user: User,
 ) -> Result<Json<DashboardData>> {
+    let demo = DemoMode::from_query(query.include_demo.as_deref()).map_err(AppError::Validation)?;
+
     // Get trading statistics
-    let trading_stats = Trade::get_statistics(state.db.pool(), current_user.id).await?;
+    let filter = TradeFilter { demo, ..Default::default() };
+    let trading_stats = Trade::get_filtered_statistics(state.db.pool(), current_user.id, &filter, None).await?;
 
     // Get active robots
-    let robots = TradingRobot::find_by_user_id(state.db.pool(), current_user.id).await?;
+    let mut robots = TradingRobot::find_by_user_id(state.db.pool(), current_user.id).await?;
+    TradingRobot::fill_missing_performance(state.db.pool(), &mut robots).await?;
     let active_robots: Vec<DashboardRobot> = robots
         .into_iter()
         .filter(|r| r.status == "active")
@@ -83,10 +128,13 @@ pub async fn get_dashboard(
         })
         .collect();
 
-    // Get recent trades
-    let trades = Trade::find_by_user_id(state.db.pool(), current_user.id).await?;
+    // The first page of the trades list, so the rest continues from recent_trades_after
+    let trades = Trade::find_page(state.db.pool(), current_user.id, &filter, None, RECENT_TRADES + 1, 0).await?;
+    let recent_trades_after = (trades.len() as i64 > RECENT_TRADES)
+        .then(|| TradePages::encode(&TradeCursor::of(&trades[RECENT_TRADES as usize - 1]), &state.config.jwt_secret));
     let recent_trades: Vec<DashboardTrade> = trades
         .into_iter()
+        .take(RECENT_TRADES as usize)
         .map(|t| DashboardTrade {
             id: t.id,
             symbol: t.symbol,
@@ -107,18 +155,80 @@ pub async fn get_dashboard(
         worst_performing_symbol: None, // TODO: Calculate from trades
     };
 
+    let pending_reviews = TradeReview::count_pending(state.db.pool(), current_user.id).await?;
+
+    let sparklines = if query.includes("sparklines") {
+        Some(DashboardService::get_sparklines(state.db.pool(), &state.cache, current_user.id, None).await?)
+    } else {
+        None
+    };
+
+    // Balance of the first active broker account; a broker outage must not break the dashboard
+    let account_balance = match BrokerConnection::find_by_user_id(state.db.pool(), current_user.id)
+        .await?
+        .into_iter()
+        .find(|c| c.is_active)
+    {
+        Some(connection) => {
+            let env = PgSnapshotEnv::new(state.db.pool().clone(), state.mt5.clone());
+            AccountSnapshotService::current_balance(&env, &connection, chrono::Utc::now())
+                .await
+                .unwrap_or_else(|e| {
+                    tracing::warn!("No balance for connection {}: {}", connection.id, e);
+                    0.0
+                })
+        }
+        None => 0.0,
+    };
+
     let dashboard_data = DashboardData {
         user_info: DashboardUserInfo {
             email: current_user.email,
             subscription_plan: current_user.subscription_plan,
-            account_balance: 10000.0, // TODO: Get from broker connection
+            account_balance,
             total_robots: active_robots.len() as i32,
         },
         trading_stats,
         active_robots,
         recent_trades,
+        recent_trades_after,
         performance_summary,
+        pending_reviews,
+        sparklines,
     };
 
     Ok(Json(dashboard_data))
 }
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct SparklinesQuery {
+    // `include=changes` overlays robot config changes on the series
+    pub include: Option<String>,
+    // The series as it stood at this instant, over the seven days up to it
+    pub as_of: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+pub async fn get_sparklines(
+    State(state): State<AppState>,
+    Query(query): Query<SparklinesQuery>,
+    current_user: User,
+) -> Result<Json<Sparklines>> {
+    TradeCorrection::validate_as_of(query.as_of, chrono::Utc::now()).map_err(AppError::Validation)?;
+    let mut sparklines = DashboardService::get_sparklines(state.db.pool(), &state.cache, current_user.id, query.as_of).await?;
+
+    // Markers are read fresh so an edit shows up without waiting for the cache to expire
+    let includes_changes = query
+        .include
+        .as_deref()
+        .is_some_and(|include| include.split(',').any(|s| s.trim() == "changes"));
+    if includes_changes {
+        let store = PgRobotJournalStore::new(state.db.pool().clone());
+        let mut markers = RobotJournal::markers(&store, current_user.id, sparklines.start).await?;
+        if let Some(as_of) = query.as_of {
+            markers.retain(|m| m.changed_at <= as_of);
+        }
+        sparklines.change_markers = Some(markers);
+    }
+
+    Ok(Json(sparklines))
+}
@@ -0,0 +1,85 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Json,
+};
+use chrono::Utc;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{
+    models::{AcceptDelegationRequest, CreateDelegationRequest, Delegation, DelegationResponse, User},
+    services::{
+        delegation_service::PgDelegationStore,
+        event_bus::{DomainEvent, EventPublisher},
+        DelegationService,
+    },
+    errors::{AppError, Result},
+    AppState,
+};
+
+pub async fn create_delegate(
+    State(state): State<AppState>,
+    current_user: User,
+    Json(payload): Json<CreateDelegationRequest>,
+) -> Result<Json<DelegationResponse>> {
+    payload.validate().map_err(|e| AppError::Validation(e.to_string()))?;
+    let email = DelegationService::normalize_invite(&current_user, &payload.email)?;
+
+    if Delegation::find_open_for_email(state.db.pool(), current_user.id, &email).await?.is_some() {
+        return Err(AppError::Validation(format!("{} has already been invited", email)));
+    }
+
+    let now = Utc::now();
+    let delegation = Delegation::new(current_user.id, email, now);
+    Delegation::create(state.db.pool(), &delegation).await?;
+
+    state.events.publish(DomainEvent::DelegateInvited {
+        delegation_id: delegation.id,
+        grantor_email: current_user.email.clone(),
+        email: delegation.delegate_email.clone(),
+        token: delegation.invite_token,
+    });
+
+    Ok(Json(delegation.to_response(None, now)))
+}
+
+pub async fn list_delegates(
+    State(state): State<AppState>,
+    current_user: User,
+) -> Result<Json<Vec<DelegationResponse>>> {
+    let now = Utc::now();
+    let delegations = Delegation::find_by_grantor(state.db.pool(), current_user.id).await?;
+    Ok(Json(delegations.iter().map(|(d, last_accessed_at)| d.to_response(*last_accessed_at, now)).collect()))
+}
+
+pub async fn revoke_delegate(
+    State(state): State<AppState>,
+    Path(delegation_id): Path<Uuid>,
+    current_user: User,
+) -> Result<StatusCode> {
+    let store = PgDelegationStore::new(state.db.pool().clone());
+    DelegationService::revoke(&store, current_user.id, delegation_id, Utc::now()).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn accept_delegation(
+    State(state): State<AppState>,
+    current_user: User,
+    Json(payload): Json<AcceptDelegationRequest>,
+) -> Result<Json<DelegationResponse>> {
+    let now = Utc::now();
+    let store = PgDelegationStore::new(state.db.pool().clone());
+    let delegation = DelegationService::accept(&store, &current_user, payload.token, now).await?;
+    Ok(Json(delegation.to_response(None, now)))
+}
+
+// Accounts the caller can read with X-On-Behalf-Of
+pub async fn list_delegations(
+    State(state): State<AppState>,
+    current_user: User,
+) -> Result<Json<Vec<DelegationResponse>>> {
+    let now = Utc::now();
+    let delegations = Delegation::find_by_delegate(state.db.pool(), current_user.id).await?;
+    Ok(Json(delegations.iter().map(|d| d.to_response(None, now)).collect()))
+}
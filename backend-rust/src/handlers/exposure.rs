@@ -0,0 +1,49 @@
+use axum::{
+    extract::{Query, State},
+    response::Json,
+};
+use chrono::Utc;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::{
+    models::User,
+    services::exposure::{ExposureByConnection, ExposureService, NetExposure},
+    errors::Result,
+    AppState,
+};
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct NetExposureQuery {
+    // Currency to combine P/L in; defaults to the accounts' own when they all share one, else USD
+    pub currency: Option<String>,
+}
+
+// Open trades per broker connection, valued by that connection's own prices
+pub async fn by_connection(
+    State(state): State<AppState>,
+    current_user: User,
+) -> Result<Json<ExposureByConnection>> {
+    Ok(Json(
+        ExposureService::by_connection(state.db.pool(), &state.mt5, &state.quotes, current_user.id, Utc::now()).await?,
+    ))
+}
+
+// Open trades netted per symbol across every connection
+pub async fn net(
+    State(state): State<AppState>,
+    Query(query): Query<NetExposureQuery>,
+    current_user: User,
+) -> Result<Json<NetExposure>> {
+    Ok(Json(
+        ExposureService::net(
+            state.db.pool(),
+            &state.mt5,
+            &state.quotes,
+            current_user.id,
+            query.currency.as_deref(),
+            Utc::now(),
+        )
+        .await?,
+    ))
+}
@@ -0,0 +1,40 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Json,
+};
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::{
+    errors::Result,
+    models::{Job, User},
+    AppState,
+};
+
+// The caller's jobs of every kind, newest first
+pub async fn list_jobs(
+    State(state): State<AppState>,
+    current_user: User,
+) -> Result<Json<Vec<Job>>> {
+    Ok(Json(state.jobs.list(current_user.id).await?))
+}
+
+pub async fn get_job(
+    State(state): State<AppState>,
+    Path(job_id): Path<Uuid>,
+    current_user: User,
+) -> Result<Json<Job>> {
+    Ok(Json(state.jobs.find(current_user.id, job_id).await?))
+}
+
+// 202: the worker is signalled, and the job turns cancelled once it acknowledges at its next
+// checkpoint. Poll the job to see how far it got.
+pub async fn cancel_job(
+    State(state): State<AppState>,
+    Path(job_id): Path<Uuid>,
+    current_user: User,
+) -> Result<(StatusCode, Json<Job>)> {
+    let job = state.jobs.cancel(current_user.id, job_id, Utc::now()).await?;
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
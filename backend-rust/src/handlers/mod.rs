@@ -6,3 +6,15 @@ pub mod robots;
 pub mod trades;
 pub mod dashboard;
 pub mod admin;
+pub mod presets;
+pub mod public;
+pub mod watchlist;
+pub mod delegations;
+pub mod webhooks;
+pub mod bridge;
+pub mod quotes;
+pub mod statements;
+pub mod jobs;
+pub mod tools;
+pub mod exposure;
+pub mod api_keys;
@@ -0,0 +1,50 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Json,
+};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{
+    models::{User, FilterPreset, CreateFilterPresetRequest, FilterPresetResponse},
+    services::PresetService,
+    errors::{Result, AppError},
+    AppState,
+};
+
+pub async fn list_presets(
+    State(state): State<AppState>,
+    current_user: User,
+) -> Result<Json<Vec<FilterPresetResponse>>> {
+    let presets = FilterPreset::find_by_user_id(state.db.pool(), current_user.id).await?;
+
+    let mut responses = Vec::with_capacity(presets.len());
+    for preset in presets {
+        responses.push(PresetService::to_response(state.db.pool(), preset).await?);
+    }
+
+    Ok(Json(responses))
+}
+
+pub async fn create_preset(
+    State(state): State<AppState>,
+    current_user: User,
+    Json(payload): Json<CreateFilterPresetRequest>,
+) -> Result<Json<FilterPresetResponse>> {
+    payload.validate().map_err(|e| AppError::Validation(e.to_string()))?;
+
+    let preset = PresetService::create(state.db.pool(), current_user.id, payload.name, payload.filter).await?;
+    Ok(Json(preset))
+}
+
+pub async fn delete_preset(
+    State(state): State<AppState>,
+    Path(preset_id): Path<Uuid>,
+    current_user: User,
+) -> Result<StatusCode> {
+    if !FilterPreset::delete(state.db.pool(), preset_id, current_user.id).await? {
+        return Err(AppError::NotFound("Filter preset not found".to_string()));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
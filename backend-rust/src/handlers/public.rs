@@ -0,0 +1,68 @@
+use axum::{
+    extract::{Query, State},
+    http::header,
+    response::{IntoResponse, Json},
+};
+use chrono::Utc;
+use serde::Deserialize;
+use schemars::JsonSchema;
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::{
+    errors::{AppError, Result},
+    models::{Leaderboard, PublicLeaderboard, User},
+    services::{
+        leaderboard::{LEADERBOARD_CACHE_CONTROL, LEADERBOARD_PUBLIC_SIZE},
+        public_stats::PUBLIC_STATS_CACHE_CONTROL,
+        system_status::{PgStatusStore, StatusPage, PUBLIC_STATUS_CACHE_CONTROL},
+    },
+    AppState,
+};
+
+// Unauthenticated landing page widget; only ever reads the precomputed snapshot
+pub async fn get_public_stats(State(state): State<AppState>) -> Result<impl IntoResponse> {
+    let stats = state.public_stats.current(Utc::now()).await?;
+    Ok(([(header::CACHE_CONTROL, PUBLIC_STATS_CACHE_CONTROL)], Json(stats)))
+}
+
+// "Is it us or them": coarse component states, the maintenance notice and recent incidents
+pub async fn get_public_status(State(state): State<AppState>) -> impl IntoResponse {
+    let store = PgStatusStore::new(state.db.pool().clone());
+    let status = StatusPage::public(&state.system_monitor, &store, Utc::now()).await;
+    ([(header::CACHE_CONTROL, PUBLIC_STATUS_CACHE_CONTROL)], Json(status))
+}
+
+// Anonymized: labels, strategies and percentages only, for users who opted in
+pub async fn get_public_leaderboard(State(state): State<AppState>) -> Result<impl IntoResponse> {
+    let pool = state.db.pool();
+    let week_start = Leaderboard::latest_week(pool).await?;
+    let entries = match week_start {
+        Some(week) => Leaderboard::top(pool, week, LEADERBOARD_PUBLIC_SIZE).await?,
+        None => Vec::new(),
+    };
+
+    Ok(([(header::CACHE_CONTROL, LEADERBOARD_CACHE_CONTROL)], Json(PublicLeaderboard { week_start, entries })))
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct UnsubscribeQuery {
+    pub token: Uuid,
+}
+
+// Linked from every onboarding email, so it works without logging in
+pub async fn unsubscribe(
+    State(state): State<AppState>,
+    Query(query): Query<UnsubscribeQuery>,
+) -> Result<Json<Value>> {
+    if !User::unsubscribe_onboarding(state.db.pool(), query.token).await? {
+        return Err(AppError::NotFound("Unknown unsubscribe link".to_string()));
+    }
+
+    Ok(Json(json!({ "unsubscribed": true })))
+}
+
+// Generated from the DTOs; the committed openapi.json is checked against it in tests
+pub async fn get_openapi() -> Json<Value> {
+    Json(crate::openapi::spec())
+}
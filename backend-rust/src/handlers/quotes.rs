@@ -0,0 +1,49 @@
+use axum::{
+    extract::{Query, State},
+    response::Json,
+};
+use chrono::Utc;
+use serde::Deserialize;
+use schemars::JsonSchema;
+
+use crate::{
+    models::{User, Watchlist},
+    services::{
+        quote_service::{SourcedQuote, MAX_QUOTE_SYMBOLS},
+        WatchlistService,
+    },
+    errors::{AppError, Result},
+    AppState,
+};
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct QuotesQuery {
+    // Comma-separated, e.g. `symbols=EURUSD,XAUUSD`; defaults to the watchlist
+    pub symbols: Option<String>,
+}
+
+// Latest price per symbol from the best source available, tagged with where it came from
+pub async fn get_quotes(
+    State(state): State<AppState>,
+    Query(query): Query<QuotesQuery>,
+    current_user: User,
+) -> Result<Json<Vec<SourcedQuote>>> {
+    let symbols = match query.symbols.as_deref() {
+        Some(raw) => {
+            let mut symbols = Vec::new();
+            for symbol in raw.split(',').filter(|s| !s.trim().is_empty()) {
+                let symbol = WatchlistService::normalize_symbol(symbol)?;
+                if !symbols.contains(&symbol) {
+                    symbols.push(symbol);
+                }
+            }
+            symbols
+        }
+        None => Watchlist::find_symbols(state.db.pool(), current_user.id).await?,
+    };
+    if symbols.len() > MAX_QUOTE_SYMBOLS {
+        return Err(AppError::Validation(format!("At most {} symbols can be quoted at once", MAX_QUOTE_SYMBOLS)));
+    }
+
+    Ok(Json(state.quotes.quotes(current_user.id, &symbols, Utc::now()).await))
+}
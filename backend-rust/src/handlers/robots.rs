@@ -1,54 +1,469 @@
 use axum::{
-    extract::{Path, State},
-    response::Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
 };
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use schemars::JsonSchema;
 use uuid::Uuid;
 use validator::Validate;
 
 use crate::{
-    models::{User, TradingRobot, CreateTradingRobotRequest, TradingRobotResponse},
+    app_middleware::{ClientInfo, NotImpersonated},
+    models::{User, BrokerConnection, CompositeStrategy, LossStreakCooldown, RobotChange, RobotLog, SignalStability, StopManagement, Subscription, Trade, TradingRobot, CreateTradingRobotRequest, RobotPreflight, TradingRobotResponse, UpdateAllocationRequest, UpdateTradingRobotRequest},
+    services::{
+        ai_quality::{AiQuality, AiQualityReport},
+        cooldown_service::COOLING_DOWN,
+        event_bus::{DomainEvent, EventPublisher},
+        robot_journal::PgRobotJournalStore,
+        job_service::JobClass,
+        plan_downgrade::{PLAN_LIMITED, RUNNING_STATUSES},
+        signal_stability::{ConfirmationState, RobotSignalHistory},
+        strategy_optimizer::{OptimizationJob, OptimizeRobotRequest, StrategyOptimizer},
+        AllocationService, PlanService, RiskTemplateService, RobotJournal,
+    },
     errors::{Result, AppError},
+    pagination::{PageQuery, Paginated},
     AppState,
 };
 
+// A bare array of every robot unless envelope=true asks for a page
 pub async fn list_robots(
     State(state): State<AppState>,
+    Query(query): Query<PageQuery>,
     current_user: User,
-) -> Result<Json<Vec<TradingRobotResponse>>> {
-    let robots = TradingRobot::find_by_user_id(state.db.pool(), current_user.id).await?;
-    let responses: Vec<TradingRobotResponse> = robots.into_iter().map(|r| r.into()).collect();
-    Ok(Json(responses))
+) -> Result<Response> {
+    let page = query.page();
+    let (limit, offset) = page.map_or((None, 0), |p| (Some(p.limit), p.offset));
+    let robots = TradingRobot::find_page_by_user_id(state.db.pool(), current_user.id, limit, offset).await?;
+    let connections = BrokerConnection::find_by_user_id(state.db.pool(), current_user.id).await?;
+    let responses = TradingRobotResponse::from_robots(state.db.pool(), robots, &connections).await?;
+    match page {
+        Some(page) => {
+            let total = TradingRobot::count_by_user_id(state.db.pool(), current_user.id).await?;
+            Ok(Json(Paginated::new(responses, total, page)).into_response())
+        }
+        None => Ok(Json(responses).into_response()),
+    }
 }
 
 pub async fn create_robot(
     State(state): State<AppState>,
     current_user: User,
-    Json(payload): Json<CreateTradingRobotRequest>,
+    client: ClientInfo,
+    Json(mut payload): Json<CreateTradingRobotRequest>,
 ) -> Result<Json<TradingRobotResponse>> {
     payload.validate().map_err(|e| AppError::Validation(e.to_string()))?;
+    let mut allocation_percent = None;
+    if let Some(risk_config) = &payload.risk_config {
+        StopManagement::from_risk_config(risk_config).map_err(AppError::Validation)?;
+        LossStreakCooldown::from_risk_config(risk_config).map_err(AppError::Validation)?;
+        SignalStability::from_risk_config(risk_config).map_err(AppError::Validation)?;
+        CompositeStrategy::from_risk_config(risk_config).map_err(AppError::Validation)?;
+        allocation_percent = TradingRobot::allocation_from_risk_config(risk_config).map_err(AppError::Validation)?;
+        PlanService::check_evaluation_interval(&Subscription::plan_details(&current_user.subscription_plan), risk_config)?;
+    }
+
+    // Settings left out of the request come from the user's risk template, if they saved one
+    let template = User::risk_template(state.db.pool(), current_user.id).await?;
+    payload.risk_config = Some(RiskTemplateService::resolve(template.as_ref(), payload.risk_config.as_ref()));
+
+    // Trialing users carry the trial plan in subscription_plan, so they get its limits
+    let plan = Subscription::plan_details(&current_user.subscription_plan);
+    let existing = TradingRobot::find_by_user_id(state.db.pool(), current_user.id).await?;
+    PlanService::check_robot_limit(&plan, existing.len())?;
+
+    let connections = BrokerConnection::find_by_user_id(state.db.pool(), current_user.id).await?;
+    let connection = match payload.broker_connection_id {
+        Some(connection_id) => Some(
+            connections
+                .iter()
+                .find(|c| c.id == connection_id)
+                .ok_or_else(|| AppError::NotFound("Broker connection not found".to_string()))?,
+        ),
+        None => None,
+    };
+
+    if allocation_percent.is_some() {
+        let connection = connection
+            .ok_or_else(|| AppError::Validation("An allocation needs a broker connection".to_string()))?;
+        AllocationService::check_connection_total(&existing, connection.id, Uuid::nil(), allocation_percent)?;
+    }
+
+    // The allocation is stored by AllocationService::apply along with its baseline
+    if let Some(serde_json::Value::Object(risk_config)) = payload.risk_config.as_mut() {
+        risk_config.remove("allocation_percent");
+    }
+
+    let robot = TradingRobot::create(state.db.pool(), current_user.id, payload, client.as_str()).await?;
+    let robot = match (allocation_percent, connection) {
+        (Some(percent), Some(connection)) => {
+            AllocationService::apply(state.db.pool(), &state.mt5, &robot, connection, Some(percent)).await?;
+            TradingRobot::find_by_id(state.db.pool(), robot.id, current_user.id)
+                .await?
+                .ok_or_else(|| AppError::NotFound("Trading robot not found".to_string()))?
+        }
+        _ => robot,
+    };
+    Ok(Json(TradingRobotResponse::from_robot(state.db.pool(), robot, &connections).await?))
+}
+
+// Changing the allocation re-baselines the robot's virtual equity on the current balance
+pub async fn update_allocation(
+    State(state): State<AppState>,
+    Path(robot_id): Path<Uuid>,
+    current_user: User,
+    Json(payload): Json<UpdateAllocationRequest>,
+) -> Result<Json<TradingRobotResponse>> {
+    let allocation_percent = TradingRobot::allocation_from_risk_config(&serde_json::json!({
+        "allocation_percent": payload.allocation_percent
+    }))
+    .map_err(AppError::Validation)?;
+
+    let robot = TradingRobot::find_by_id(state.db.pool(), robot_id, current_user.id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Trading robot not found".to_string()))?;
+    let connection_id = robot
+        .broker_connection_id
+        .ok_or_else(|| AppError::Validation("An allocation needs a broker connection".to_string()))?;
+    let connection = BrokerConnection::find_by_id(state.db.pool(), connection_id, current_user.id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Broker connection not found".to_string()))?;
+
+    let robots = TradingRobot::find_by_user_id(state.db.pool(), current_user.id).await?;
+    AllocationService::check_connection_total(&robots, connection_id, robot_id, allocation_percent)?;
+    AllocationService::apply(state.db.pool(), &state.mt5, &robot, &connection, allocation_percent).await?;
+
+    let updated_robot = TradingRobot::find_by_id(state.db.pool(), robot_id, current_user.id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Trading robot not found".to_string()))?;
+    let connections = BrokerConnection::find_by_user_id(state.db.pool(), current_user.id).await?;
+    Ok(Json(TradingRobotResponse::from_robot(state.db.pool(), updated_robot, &connections).await?))
+}
 
-    let robot = TradingRobot::create(state.db.pool(), current_user.id, payload).await?;
-    Ok(Json(robot.into()))
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+pub struct UpdateRobotQuery {
+    // Resets risk_config to the user's risk template (or the platform defaults) before applying the edit
+    pub reset_risk_config: Option<bool>,
+}
+
+// Edits to strategy and risk_config are recorded in the robot's change journal
+pub async fn update_robot(
+    State(state): State<AppState>,
+    Path(robot_id): Path<Uuid>,
+    Query(query): Query<UpdateRobotQuery>,
+    current_user: User,
+    Json(payload): Json<UpdateTradingRobotRequest>,
+) -> Result<Json<TradingRobotResponse>> {
+    let robot = TradingRobot::find_by_id(state.db.pool(), robot_id, current_user.id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Trading robot not found".to_string()))?;
+
+    // Only an interval asked for here is checked; one already stored is raised to the plan's minimum when run
+    if let Some(risk_config) = &payload.risk_config {
+        let plan = Subscription::plan_details(&current_user.subscription_plan);
+        PlanService::check_evaluation_interval(&plan, &serde_json::Value::Object(risk_config.clone()))?;
+    }
+
+    let reset_risk_config = if query.reset_risk_config.unwrap_or(false) {
+        let template = User::risk_template(state.db.pool(), current_user.id).await?;
+        Some(RiskTemplateService::resolve(template.as_ref(), None))
+    } else {
+        None
+    };
+
+    let store = PgRobotJournalStore::new(state.db.pool().clone());
+    let (updated_robot, _) =
+        RobotJournal::update(&store, &robot, current_user.id, payload, reset_risk_config, chrono::Utc::now()).await?;
+
+    let connections = BrokerConnection::find_by_user_id(state.db.pool(), current_user.id).await?;
+    Ok(Json(TradingRobotResponse::from_robot(state.db.pool(), updated_robot, &connections).await?))
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct RobotChangesQuery {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+pub async fn list_robot_changes(
+    State(state): State<AppState>,
+    Path(robot_id): Path<Uuid>,
+    Query(query): Query<RobotChangesQuery>,
+    current_user: User,
+) -> Result<Json<Vec<RobotChange>>> {
+    TradingRobot::find_by_id(state.db.pool(), robot_id, current_user.id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Trading robot not found".to_string()))?;
+
+    let limit = query.limit.unwrap_or(50).clamp(1, 100);
+    let offset = query.offset.unwrap_or(0).max(0);
+    let changes = RobotChange::find_by_robot_id(state.db.pool(), robot_id, current_user.id, limit, offset).await?;
+    Ok(Json(changes))
+}
+
+// Recent signal evaluations and the confirmation in progress; kept in memory by the robot's runner
+pub async fn robot_signals(
+    State(state): State<AppState>,
+    Path(robot_id): Path<Uuid>,
+    current_user: User,
+) -> Result<Json<RobotSignalHistory>> {
+    let robot = TradingRobot::find_by_id(state.db.pool(), robot_id, current_user.id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Trading robot not found".to_string()))?;
+
+    let (confirmation, history, running) = match state.runners.signal_history(robot_id) {
+        Some((confirmation, history)) => (confirmation, history, true),
+        None => {
+            let required = robot.signal_stability().confirmation_count;
+            (ConfirmationState { required, ..ConfirmationState::default() }, Vec::new(), false)
+        }
+    };
+    let plan = Subscription::plan_details(&current_user.subscription_plan);
+    let effective_interval = PlanService::effective_evaluation_interval(&plan, &robot.risk_config).as_secs();
+    let config_warnings = robot.config_warnings();
+    Ok(Json(RobotSignalHistory { robot_id, running, effective_interval, config_warnings, confirmation, history }))
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct AiQualityQuery {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    // Last N days when from is not given; 90 by default
+    pub days: Option<i64>,
+}
+
+// How well the AI model's decisions turned out, from the robot's closed AI trades
+pub async fn robot_ai_quality(
+    State(state): State<AppState>,
+    Path(robot_id): Path<Uuid>,
+    Query(query): Query<AiQualityQuery>,
+    current_user: User,
+) -> Result<Json<AiQualityReport>> {
+    TradingRobot::find_by_id(state.db.pool(), robot_id, current_user.id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Trading robot not found".to_string()))?;
+
+    let (from, to) = AiQuality::period(query.from, query.to, query.days, Utc::now())?;
+    Ok(Json(AiQuality::report(state.db.pool(), robot_id, from, to).await?))
+}
+
+// Starts a grid search over backtests; it counts as one of the user's backtest jobs
+pub async fn optimize_robot(
+    State(state): State<AppState>,
+    Path(robot_id): Path<Uuid>,
+    current_user: User,
+    Json(payload): Json<OptimizeRobotRequest>,
+) -> Result<Json<OptimizationJob>> {
+    let robot = TradingRobot::find_by_id(state.db.pool(), robot_id, current_user.id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Trading robot not found".to_string()))?;
+
+    let plan = Subscription::plan_details(&current_user.subscription_plan);
+    let combinations = StrategyOptimizer::validate(&robot, &plan, &payload, chrono::Utc::now())?;
+    let handle = state.jobs.register_for_plan(current_user.id, &current_user.subscription_plan, JobClass::Backtest).await?;
+    Ok(Json(state.optimizer.start(robot, &payload, combinations, handle)))
+}
+
+pub async fn get_optimization(
+    State(state): State<AppState>,
+    Path(job_id): Path<Uuid>,
+    current_user: User,
+) -> Result<Json<OptimizationJob>> {
+    state
+        .optimizer
+        .job(current_user.id, job_id)
+        .map(Json)
+        .ok_or_else(|| AppError::NotFound("Optimization not found".to_string()))
+}
+
+// Writes the best combination into the robot's risk_config, journaled like any other edit
+pub async fn apply_optimization(
+    State(state): State<AppState>,
+    Path(job_id): Path<Uuid>,
+    current_user: User,
+) -> Result<Json<TradingRobotResponse>> {
+    let job = state
+        .optimizer
+        .job(current_user.id, job_id)
+        .ok_or_else(|| AppError::NotFound("Optimization not found".to_string()))?;
+    let robot = TradingRobot::find_by_id(state.db.pool(), job.robot_id, current_user.id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Trading robot not found".to_string()))?;
+
+    let store = PgRobotJournalStore::new(state.db.pool().clone());
+    let updated_robot = state
+        .optimizer
+        .apply_best(&store, &robot, current_user.id, job_id, chrono::Utc::now())
+        .await?;
+
+    let connections = BrokerConnection::find_by_user_id(state.db.pool(), current_user.id).await?;
+    Ok(Json(TradingRobotResponse::from_robot(state.db.pool(), updated_robot, &connections).await?))
+}
+
+// What starting the robot would run into, checked without starting it
+pub async fn robot_preflight(
+    State(state): State<AppState>,
+    Path(robot_id): Path<Uuid>,
+    current_user: User,
+) -> Result<Json<RobotPreflight>> {
+    let robot = TradingRobot::find_by_id(state.db.pool(), robot_id, current_user.id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Trading robot not found".to_string()))?;
+
+    Ok(Json(RobotPreflight::for_robot(&robot)))
+}
+
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+pub struct StartRobotQuery {
+    // Restarts a robot that is cooling down after a losing streak
+    pub force: Option<bool>,
+    // Starts a robot whose risk_config has fields this version ignores
+    pub acknowledge_warnings: Option<bool>,
 }
 
 pub async fn start_robot(
     State(state): State<AppState>,
     Path(robot_id): Path<Uuid>,
+    Query(query): Query<StartRobotQuery>,
     current_user: User,
+    _not_impersonated: NotImpersonated,
 ) -> Result<Json<TradingRobotResponse>> {
     let robot = TradingRobot::find_by_id(state.db.pool(), robot_id, current_user.id)
         .await?
         .ok_or_else(|| AppError::NotFound("Trading robot not found".to_string()))?;
 
-    TradingRobot::update_status(state.db.pool(), robot_id, current_user.id, "active").await?;
+    // Parked by a downgrade; only POST /robots/reactivate brings it back, within the plan's limit
+    if robot.status == PLAN_LIMITED {
+        return Err(AppError::PlanLimit(format!(
+            "Robot was paused because the {} plan allows fewer robots; upgrade and reactivate it",
+            Subscription::plan_details(&current_user.subscription_plan).name
+        )));
+    }
+
+    // Settings the runner would ignore weaken the robot's protections without anyone noticing
+    let ignored = robot.config_warnings().ignored_fields;
+    if !ignored.is_empty() {
+        if !query.acknowledge_warnings.unwrap_or(false) {
+            return Err(AppError::Validation(format!(
+                "risk_config has fields this version ignores ({}); pass ?acknowledge_warnings=true to start it anyway",
+                ignored.join(", ")
+            )));
+        }
+        let message = format!("Started with ignored risk_config fields: {}", ignored.join(", "));
+        RobotLog::create(state.db.pool(), robot_id, current_user.id, "warn", &message).await?;
+    }
+
+    if robot.status == COOLING_DOWN {
+        if !query.force.unwrap_or(false) {
+            let until = robot
+                .resume_at()
+                .map(|at| format!(" until {}", at.format("%Y-%m-%d %H:%M UTC")))
+                .unwrap_or_default();
+            return Err(AppError::Validation(format!(
+                "Robot is cooling down after a losing streak{}; pass ?force=true to restart it now",
+                until
+            )));
+        }
+        TradingRobot::end_cooldown(state.db.pool(), robot_id).await?;
+        RobotLog::create(state.db.pool(), robot_id, current_user.id, "warn", "Cooldown ended early by a forced restart").await?;
+    }
+
+    let connections = BrokerConnection::find_by_user_id(state.db.pool(), current_user.id).await?;
+    activate_robot(&state, &robot, &connections).await?;
 
-    // TODO: Start the actual trading logic
-    
     let updated_robot = TradingRobot::find_by_id(state.db.pool(), robot_id, current_user.id)
         .await?
         .unwrap();
 
-    Ok(Json(updated_robot.into()))
+    Ok(Json(TradingRobotResponse::from_robot(state.db.pool(), updated_robot, &connections).await?))
+}
+
+async fn activate_robot(state: &AppState, robot: &TradingRobot, connections: &[BrokerConnection]) -> Result<()> {
+    TradingRobot::update_status(state.db.pool(), robot.id, robot.user_id, "active").await?;
+    state.events.publish(DomainEvent::RobotStatusChanged {
+        robot_id: robot.id,
+        user_id: robot.user_id,
+        status: "active".to_string(),
+    });
+
+    state.runners.start(robot.id, robot.user_id, false);
+    let open_trades = Trade::get_open_trades_for_robot(state.db.pool(), robot.id).await?;
+    state.runners.monitor_trades(robot.id, &open_trades);
+
+    // Starting during a maintenance window of the robot's broker type leaves it paused until the window ends
+    if let Some(connection) = connections.iter().find(|c| Some(c.id) == robot.broker_connection_id) {
+        state
+            .broker_maintenance
+            .pause_if_scoped(robot.id, &connection.broker_type, &state.runners, chrono::Utc::now())
+            .await?;
+    }
+    Ok(())
+}
+
+// Brings back robots a downgrade parked, oldest first, as many as the current plan has room
+// for. Those that were running start again; the rest come back stopped.
+pub async fn reactivate_robots(
+    State(state): State<AppState>,
+    current_user: User,
+    _not_impersonated: NotImpersonated,
+) -> Result<Json<Vec<TradingRobotResponse>>> {
+    let plan = Subscription::plan_details(&current_user.subscription_plan);
+    let robots = TradingRobot::find_by_user_id(state.db.pool(), current_user.id).await?;
+    let parked = robots.iter().filter(|r| r.status == PLAN_LIMITED).count();
+    if parked == 0 {
+        return Ok(Json(Vec::new()));
+    }
+    let room = (plan.max_robots >= 0).then(|| (plan.max_robots as i64 - (robots.len() - parked) as i64).max(0));
+    if room == Some(0) {
+        return Err(AppError::PlanLimit(format!(
+            "The {} plan allows at most {} trading robot(s); upgrade to reactivate paused robots",
+            plan.name, plan.max_robots
+        )));
+    }
+
+    let released = TradingRobot::release_plan_limited(state.db.pool(), current_user.id, room).await?;
+    let connections = BrokerConnection::find_by_user_id(state.db.pool(), current_user.id).await?;
+    let mut reactivated = Vec::with_capacity(released.len());
+    for entry in &released {
+        let Some(robot) = robots.iter().find(|r| r.id == entry.id) else { continue };
+        if entry.previous_status == "active" {
+            activate_robot(&state, robot, &connections).await?;
+        }
+        RobotLog::create(state.db.pool(), robot.id, current_user.id, "info", "Reactivated after a plan upgrade").await?;
+        if let Some(updated) = TradingRobot::find_by_id(state.db.pool(), robot.id, current_user.id).await? {
+            reactivated.push(updated);
+        }
+    }
+
+    Ok(Json(TradingRobotResponse::from_robots(state.db.pool(), reactivated, &connections).await?))
+}
+
+// Deletes a stopped robot without open trades. Its trades stay in statistics and statements until
+// the purge job removes the robot.
+pub async fn delete_robot(
+    State(state): State<AppState>,
+    Path(robot_id): Path<Uuid>,
+    current_user: User,
+) -> Result<StatusCode> {
+    let pool = state.db.pool();
+    let robot = TradingRobot::find_by_id(pool, robot_id, current_user.id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Trading robot not found".to_string()))?;
+    if RUNNING_STATUSES.contains(&robot.status.as_str()) {
+        return Err(AppError::Unprocessable("Stop the robot before deleting it".to_string()));
+    }
+    let open_trades = TradingRobot::open_trade_count(pool, robot_id).await?;
+    if open_trades > 0 {
+        return Err(AppError::Unprocessable(format!(
+            "Close the robot's trades before deleting it ({} open trade(s))",
+            open_trades
+        )));
+    }
+
+    TradingRobot::soft_delete(pool, robot_id, current_user.id, chrono::Utc::now()).await?;
+    Ok(StatusCode::NO_CONTENT)
 }
 
 pub async fn stop_robot(
@@ -61,12 +476,18 @@ pub async fn stop_robot(
         .ok_or_else(|| AppError::NotFound("Trading robot not found".to_string()))?;
 
     TradingRobot::update_status(state.db.pool(), robot_id, current_user.id, "stopped").await?;
+    state.events.publish(DomainEvent::RobotStatusChanged {
+        robot_id,
+        user_id: current_user.id,
+        status: "stopped".to_string(),
+    });
+
+    state.runners.stop(robot_id);
 
-    // TODO: Stop the actual trading logic
-    
     let updated_robot = TradingRobot::find_by_id(state.db.pool(), robot_id, current_user.id)
         .await?
         .unwrap();
 
-    Ok(Json(updated_robot.into()))
+    let connections = BrokerConnection::find_by_user_id(state.db.pool(), current_user.id).await?;
+    Ok(Json(TradingRobotResponse::from_robot(state.db.pool(), updated_robot, &connections).await?))
 }
@@ -0,0 +1,46 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::header,
+    response::{IntoResponse, Json, Response},
+};
+use chrono::Utc;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::{
+    errors::{AppError, Result},
+    models::User,
+    services::{statements::StatementPeriod, StatementService},
+    AppState,
+};
+
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+pub struct StatementQuery {
+    // json (default) or html, a printable document
+    pub format: Option<String>,
+}
+
+// A finished month is generated once and served from the stored copy afterwards, so later trade
+// corrections show up as adjustments on the next statement. The month in progress is provisional.
+pub async fn get_statement(
+    State(state): State<AppState>,
+    Path((year, month)): Path<(i32, u32)>,
+    Query(query): Query<StatementQuery>,
+    current_user: User,
+) -> Result<Response> {
+    let period = StatementPeriod::new(year, month)?;
+    let (statement, html) = StatementService::get(state.statements.as_ref(), current_user.id, period, Utc::now()).await?;
+
+    match query.format.as_deref().unwrap_or("json") {
+        "json" => Ok(Json(statement).into_response()),
+        "html" => {
+            let disposition = format!("inline; filename=\"statement-{}-{:02}.html\"", year, month);
+            Ok((
+                [(header::CONTENT_TYPE, "text/html; charset=utf-8".to_string()), (header::CONTENT_DISPOSITION, disposition)],
+                html,
+            )
+                .into_response())
+        }
+        other => Err(AppError::Validation(format!("Unknown format '{}', expected json or html", other))),
+    }
+}
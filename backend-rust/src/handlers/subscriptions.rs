@@ -1,11 +1,18 @@
 use axum::{
-    extract::State,
+    extract::{Path, State},
     response::Json,
 };
+use chrono::Utc;
+use serde_json::{json, Value};
 
 use crate::{
-    models::{User, Subscription, CreateSubscriptionRequest, SubscriptionResponse},
-    errors::Result,
+    models::{CheckoutActivation, CheckoutSession, User, Subscription, CreateSubscriptionRequest, SubscriptionResponse},
+    services::{
+        checkout_service::{CheckoutService, CheckoutSessionResponse, CreateCheckoutSessionRequest, PgCheckoutStore},
+        event_bus::{DomainEvent, EventPublisher},
+        feature_flags, TrialService,
+    },
+    errors::{AppError, Result},
     AppState,
 };
 
@@ -22,6 +29,17 @@ pub async fn create_subscription(
     current_user: User,
     Json(payload): Json<CreateSubscriptionRequest>,
 ) -> Result<Json<SubscriptionResponse>> {
+    // Checked before anything changes, so a missing or invalid choice refuses the whole change
+    let parked = state
+        .plan_downgrades
+        .plan_change(current_user.id, &payload.plan_name, payload.keep_robot_ids.as_deref())
+        .await?;
+
+    // Subscribing for real during a trial ends the trial immediately
+    if Subscription::convert_trial(state.db.pool(), current_user.id).await? {
+        tracing::info!("User {} converted their trial to {}", current_user.id, payload.plan_name);
+    }
+
     // TODO: Integrate with Stripe
     let subscription = Subscription::create(
         state.db.pool(),
@@ -31,5 +49,94 @@ pub async fn create_subscription(
         None,
     ).await?;
 
+    User::update_subscription_plan(state.db.pool(), current_user.id, &subscription.plan_name).await?;
+    state
+        .plan_downgrades
+        .apply(current_user.id, &current_user.email, &subscription.plan_name, &parked, &state.runners)
+        .await?;
+    state.events.publish(DomainEvent::SubscriptionChanged {
+        user_id: current_user.id,
+        email: current_user.email,
+        plan_name: subscription.plan_name.clone(),
+        action: "activated".to_string(),
+    });
+
     Ok(Json(subscription.into()))
 }
+
+pub async fn start_trial(
+    State(state): State<AppState>,
+    current_user: User,
+) -> Result<Json<SubscriptionResponse>> {
+    if !state.feature_flags.is_enabled(feature_flags::PRO_TRIAL, current_user.id).await {
+        return Err(AppError::Forbidden("Trials are not available right now".to_string()));
+    }
+
+    let subscription = TrialService::start_trial(
+        state.db.pool(),
+        current_user.id,
+        &current_user.subscription_plan,
+    ).await?;
+    state.events.publish(DomainEvent::SubscriptionChanged {
+        user_id: current_user.id,
+        email: current_user.email,
+        plan_name: subscription.plan_name.clone(),
+        action: "trial started".to_string(),
+    });
+
+    Ok(Json(subscription.into()))
+}
+
+// Stripe-hosted alternative to sending a payment_method id; the subscription is created by
+// the checkout.session.completed webhook once the customer has paid
+pub async fn create_checkout_session(
+    State(state): State<AppState>,
+    current_user: User,
+    Json(payload): Json<CreateCheckoutSessionRequest>,
+) -> Result<Json<CheckoutSessionResponse>> {
+    let params = CheckoutService::session_params(
+        &current_user,
+        &payload.plan_name,
+        &state.config.stripe_price_ids,
+        &state.config.checkout_success_url,
+        &state.config.checkout_cancel_url,
+    )?;
+    let store = PgCheckoutStore::new(state.db.pool().clone());
+    let session = CheckoutService::start(state.stripe.as_ref(), &store, &current_user, &params, Utc::now()).await?;
+    Ok(Json(session))
+}
+
+// Local development only: with a mock Stripe key nobody will ever pay the fake session,
+// so this delivers the completion event Stripe would have sent
+pub async fn complete_mock_checkout(
+    State(state): State<AppState>,
+    Path(session_id): Path<String>,
+    current_user: User,
+) -> Result<Json<Value>> {
+    if !state.stripe.is_mock() {
+        return Err(AppError::NotFound("Not found".to_string()));
+    }
+    let session = CheckoutSession::find_by_id(state.db.pool(), &session_id)
+        .await?
+        .filter(|s| s.user_id == current_user.id)
+        .ok_or_else(|| AppError::NotFound("Checkout session not found".to_string()))?;
+
+    let store = PgCheckoutStore::new(state.db.pool().clone());
+    let event = CheckoutService::simulated_completion(&session);
+    let activation = CheckoutService::handle_event(&store, state.events.as_ref(), &event, Utc::now()).await?;
+    if let Some(activation) = &activation {
+        reconcile_plan(&state, activation).await?;
+    }
+    Ok(Json(json!({ "activated": activation.is_some() })))
+}
+
+// A plan bought through checkout has nobody to ask which robots to keep, so a downgrade parks
+// the automatic pick
+pub(crate) async fn reconcile_plan(state: &AppState, activation: &CheckoutActivation) -> Result<()> {
+    let subscription = &activation.subscription;
+    state
+        .plan_downgrades
+        .reconcile(subscription.user_id, &activation.email, &subscription.plan_name, &state.runners)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,61 @@
+use axum::{extract::State, response::Json};
+use chrono::Utc;
+
+use crate::{
+    models::{BrokerConnection, User},
+    services::{
+        risk_calculator::{AccountTerms, RiskCalculation, RiskCalculatorRequest, DEFAULT_ACCOUNT_CURRENCY},
+        symbol_spec::SymbolSpec,
+        AllocationService, RiskCalculator, WatchlistService,
+    },
+    errors::{AppError, Result},
+    AppState,
+};
+
+// Position size, margin and worst-case losses for risk settings, before any robot is created
+pub async fn risk_calculator(
+    State(state): State<AppState>,
+    current_user: User,
+    Json(payload): Json<RiskCalculatorRequest>,
+) -> Result<Json<RiskCalculation>> {
+    let symbol = WatchlistService::normalize_symbol(&payload.symbol)?;
+    let spec = SymbolSpec::for_symbol(&symbol).ok_or_else(|| {
+        AppError::Validation(format!("No contract details for {}; currency pairs and spot metals are supported", symbol))
+    })?;
+
+    let account = match payload.account_balance {
+        Some(balance) => AccountTerms {
+            balance,
+            currency: payload
+                .account_currency
+                .clone()
+                .unwrap_or_else(|| DEFAULT_ACCOUNT_CURRENCY.to_string())
+                .to_uppercase(),
+            broker_connection_id: None,
+        },
+        None => {
+            // The first active broker account, the one the dashboard shows the balance of
+            let connection = BrokerConnection::find_by_user_id(state.db.pool(), current_user.id)
+                .await?
+                .into_iter()
+                .find(|connection| connection.is_active)
+                .ok_or_else(|| {
+                    AppError::Validation("Pass account_balance, or connect a broker account to use its balance".to_string())
+                })?;
+            let info = AllocationService::account_info(&state.mt5, &connection).await?;
+            AccountTerms { balance: info.balance, currency: info.currency, broker_connection_id: Some(connection.id) }
+        }
+    };
+
+    let price = match payload.price {
+        Some(price) => price,
+        None => state
+            .quotes
+            .quote(current_user.id, &symbol, Utc::now())
+            .await
+            .map(|quote| (quote.bid + quote.ask) / 2.0)
+            .ok_or_else(|| AppError::Unprocessable(format!("No price is available for {}; pass price", symbol)))?,
+    };
+
+    Ok(Json(RiskCalculator::calculate(&payload, &spec, &account, price)?))
+}
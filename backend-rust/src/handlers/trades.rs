@@ -1,39 +1,389 @@
 use axum::{
-    extract::{Query, State},
-    response::Json,
+    extract::{Path, Query, State},
+    http::{HeaderMap, HeaderValue},
+    response::{IntoResponse, Json, Response},
 };
+use chrono::{DateTime, Utc};
 use serde::Deserialize;
+use schemars::JsonSchema;
+use uuid::Uuid;
 
 use crate::{
-    models::{User, Trade, TradeResponse, TradeStatistics},
-    errors::Result,
+    app_middleware::{ClientInfo, NotImpersonated},
+    models::{
+        User, BrokerConnection, DemoMode, PendingReview, StopManagement, SubmitTradeReviewRequest, Subscription, Trade, TradeFilter,
+        TradeCorrection, TradeOrigin, TradeResponse, TradeReview, TradeStatistics, TradingRobot, ORIGIN_MANUAL,
+    },
+    services::{
+        execution_queue::{on_behalf_of, Requester},
+        trade_close_service::{CloseBatchRequest, CloseBatchResponse, Mt5PositionCloser, PgClosedTradeStore},
+        trade_reentry::Mt5ReentryBroker,
+        trade_journal::PgTradeJournalStore,
+        quote_service::FloatingTrade,
+        trade_search::TradeSearchResult,
+        trade_pages::{DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE},
+        trade_origins::{self, MAX_ORIGIN_PAYLOAD_BYTES},
+        feature_flags, PlanService, PresetService, TradeCloseService, TradeJournal, TradePositions, TradeReentry, TradeSearch, TradePages,
+    },
+    errors::{AppError, Result},
+    pagination::{Page, Paginated},
     AppState,
 };
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 pub struct ListTradesQuery {
     pub limit: Option<i64>,
+    // `after` of the previous page
+    pub after: Option<String>,
+    // Deprecated in favour of `after`; still honoured for one release
     pub offset: Option<i64>,
+    // `position` nests partial closes under the trade they were split from; pages by limit and
+    // offset over positions
+    pub group_by: Option<String>,
+    // Same filters as /api/v1/trades/statistics, except that demo trades are listed unless
+    // include_demo=false
+    pub preset_id: Option<Uuid>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub days: Option<i64>,
+    pub robot_ids: Option<String>,
+    pub symbols: Option<String>,
+    pub include_demo: Option<String>,
+    // true answers with a Paginated page by limit and offset, with the total, instead of `after`
+    pub envelope: Option<bool>,
 }
 
+// Sent while a client still pages with `offset`
+const DEPRECATION_HEADER: &str = "deprecation";
+
 pub async fn list_trades(
     State(state): State<AppState>,
     Query(query): Query<ListTradesQuery>,
     current_user: User,
-) -> Result<Json<Vec<TradeResponse>>> {
-    let limit = query.limit.unwrap_or(50).min(100);
-    let offset = query.offset.unwrap_or(0);
+) -> Result<Response> {
+    let by_position = match query.group_by.as_deref() {
+        None => false,
+        Some("position") => true,
+        Some(other) => return Err(AppError::Validation(format!("Unknown group_by '{}', expected position", other))),
+    };
+    if by_position && query.after.is_some() {
+        return Err(AppError::Validation("group_by=position pages by offset; drop after".to_string()));
+    }
+
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let offset = query.offset.unwrap_or(0).max(0);
+    if query.after.is_some() && query.offset.is_some() {
+        return Err(AppError::Validation("Use either after or offset, not both".to_string()));
+    }
+    let envelope = query.envelope.unwrap_or(false);
+    if envelope && query.after.is_some() {
+        return Err(AppError::Validation("envelope pages by offset; drop after".to_string()));
+    }
+    let after = query
+        .after
+        .as_deref()
+        .map(|cursor| TradePages::decode(cursor, &state.config.jwt_secret))
+        .transpose()?;
+    let filters = StatisticsQuery {
+        preset_id: query.preset_id,
+        from: query.from,
+        to: query.to,
+        days: query.days,
+        robot_ids: query.robot_ids,
+        symbols: query.symbols,
+        include_demo: query.include_demo,
+        breakdown: None,
+        as_of: None,
+    };
+    let mut filter = requested_filter(&state, &current_user, &filters).await?;
+    if filters.preset_id.is_none() && filters.include_demo.is_none() {
+        filter.demo = DemoMode::Include;
+    }
+
+    if by_position {
+        let page = Page::new(query.limit, query.offset);
+        let legs = Trade::find_position_legs(state.db.pool(), current_user.id, &filter, page.limit, page.offset).await?;
+        let positions = TradePositions::group(legs);
+        if envelope {
+            let total = Trade::count_positions(state.db.pool(), current_user.id, &filter).await?;
+            return Ok(Json(Paginated::new(positions, total, page)).into_response());
+        }
+        return Ok(Json(positions).into_response());
+    }
+
+    if envelope {
+        let page = Page::new(query.limit, query.offset);
+        let trades = Trade::find_page(state.db.pool(), current_user.id, &filter, None, page.limit, page.offset).await?;
+        let total = Trade::count_page(state.db.pool(), current_user.id, &filter).await?;
+        let trades = trades.into_iter().map(TradeResponse::from).collect();
+        return Ok(Json(Paginated::new(trades, total, page)).into_response());
+    }
+
+    // One extra row tells whether another page follows
+    let trades = Trade::find_page(state.db.pool(), current_user.id, &filter, after.as_ref(), limit + 1, offset).await?;
+    let page = TradePages::page(trades, limit, &state.config.jwt_secret);
+
+    let mut response = Json(page).into_response();
+    if query.offset.is_some() {
+        response.headers_mut().insert(DEPRECATION_HEADER, HeaderValue::from_static("true"));
+    }
+    Ok(response)
+}
+
+// Open trades valued at the latest quote. Prices from anywhere but the user's own broker are
+// flagged indicative.
+pub async fn get_floating_trades(
+    State(state): State<AppState>,
+    current_user: User,
+) -> Result<Json<Vec<FloatingTrade>>> {
+    let trades = Trade::get_open_trades(state.db.pool(), current_user.id).await?;
+    Ok(Json(state.quotes.floating(current_user.id, &trades, Utc::now()).await))
+}
 
-    let trades = Trade::find_by_user_id(state.db.pool(), current_user.id).await?;
-    let responses: Vec<TradeResponse> = trades.into_iter().map(|t| t.into()).collect();
-    
-    Ok(Json(responses))
+#[derive(Deserialize, JsonSchema)]
+pub struct StatisticsQuery {
+    pub preset_id: Option<Uuid>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub days: Option<i64>,
+    pub robot_ids: Option<String>,
+    pub symbols: Option<String>,
+    // true | only; demo trades are excluded by default
+    pub include_demo: Option<String>,
+    // `review` adds closed trades split by followed plan and by emotion tag
+    pub breakdown: Option<String>,
+    // The statistics as they stood at this instant: later trades and corrections are left out
+    pub as_of: Option<DateTime<Utc>>,
 }
 
 pub async fn get_statistics(
     State(state): State<AppState>,
+    Query(query): Query<StatisticsQuery>,
     current_user: User,
 ) -> Result<Json<TradeStatistics>> {
-    let stats = Trade::get_statistics(state.db.pool(), current_user.id).await?;
+    let by_review = match query.breakdown.as_deref() {
+        None => false,
+        Some("review") => true,
+        Some(other) => return Err(AppError::Validation(format!("Unknown breakdown '{}', expected review", other))),
+    };
+    TradeCorrection::validate_as_of(query.as_of, Utc::now()).map_err(AppError::Validation)?;
+    // Reviews are edited in place, so there is no telling what they said back then
+    if by_review && query.as_of.is_some() {
+        return Err(AppError::Validation("breakdown=review cannot be combined with as_of".to_string()));
+    }
+    let (filter, truncated) = resolve_filter(&state, &current_user, &query).await?;
+    let mut stats = Trade::get_filtered_statistics(state.db.pool(), current_user.id, &filter, query.as_of).await?;
+    stats.truncated = truncated;
+    if by_review {
+        stats.review_breakdown = Some(TradeReview::breakdown(state.db.pool(), current_user.id, &filter).await?);
+    }
     Ok(Json(stats))
 }
+
+#[derive(Deserialize, JsonSchema)]
+pub struct SearchTradesQuery {
+    pub q: String,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+    // Same filters as /api/v1/trades/statistics; not flattened because serde_urlencoded
+    // cannot parse numbers inside a flattened struct
+    pub preset_id: Option<Uuid>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub days: Option<i64>,
+    pub robot_ids: Option<String>,
+    pub symbols: Option<String>,
+    pub include_demo: Option<String>,
+}
+
+pub async fn search_trades(
+    State(state): State<AppState>,
+    Query(query): Query<SearchTradesQuery>,
+    current_user: User,
+) -> Result<(HeaderMap, Json<Vec<TradeSearchResult>>)> {
+    let q = TradeSearch::normalize_query(&query.q)?;
+    let limit = query.limit.unwrap_or(50).clamp(1, 100);
+    let offset = query.offset.unwrap_or(0).max(0);
+    let filters = StatisticsQuery {
+        preset_id: query.preset_id,
+        from: query.from,
+        to: query.to,
+        days: query.days,
+        robot_ids: query.robot_ids,
+        symbols: query.symbols,
+        include_demo: query.include_demo,
+        breakdown: None,
+        as_of: None,
+    };
+    let (filter, truncated) = resolve_filter(&state, &current_user, &filters).await?;
+
+    let trades = Trade::search(
+        state.db.pool(),
+        current_user.id,
+        &TradeSearch::like_pattern(&q),
+        &filter,
+        limit,
+        offset,
+    )
+    .await?;
+
+    Ok((
+        PlanService::truncation_headers(truncated),
+        Json(trades.into_iter().map(|t| TradeSearch::result(t, &q)).collect()),
+    ))
+}
+
+// Also returns whether the range was cut to the user's plan
+async fn resolve_filter(state: &AppState, user: &User, query: &StatisticsQuery) -> Result<(TradeFilter, bool)> {
+    let filter = requested_filter(state, user, query).await?;
+    let plan = Subscription::plan_details(&user.subscription_plan);
+    Ok(PlanService::clamp_filter(&plan, &filter, Utc::now()))
+}
+
+// The filter as asked for, before any plan limit
+async fn requested_filter(state: &AppState, user: &User, query: &StatisticsQuery) -> Result<TradeFilter> {
+    // A saved preset takes precedence over any explicit filter parameters
+    let mut filter = match query.preset_id {
+        Some(preset_id) => PresetService::resolve(state.db.pool(), user.id, preset_id).await?,
+        None => PresetService::explicit_filter(
+            query.from,
+            query.to,
+            query.days,
+            query.robot_ids.as_deref(),
+            query.symbols.as_deref(),
+        )?,
+    };
+
+    if query.include_demo.is_some() {
+        filter.demo = DemoMode::from_query(query.include_demo.as_deref()).map_err(AppError::Validation)?;
+    }
+    Ok(filter)
+}
+
+pub async fn close_batch(
+    State(state): State<AppState>,
+    current_user: User,
+    _not_impersonated: NotImpersonated,
+    Json(payload): Json<CloseBatchRequest>,
+) -> Result<Json<CloseBatchResponse>> {
+    if !state.feature_flags.is_enabled(feature_flags::BATCH_CLOSE, current_user.id).await {
+        return Err(AppError::Forbidden("Batch close is not available right now".to_string()));
+    }
+
+    let ids = TradeCloseService::normalize_ids(&payload.trade_ids)?;
+    let owned = Trade::find_by_ids(state.db.pool(), current_user.id, &ids).await?;
+
+    let connection = BrokerConnection::find_by_user_id(state.db.pool(), current_user.id)
+        .await?
+        .into_iter()
+        .find(|c| c.is_active)
+        .ok_or_else(|| AppError::Validation("No active broker connection".to_string()))?;
+
+    let connection_id = connection.id.to_string();
+    if !state.mt5.is_connected(&connection_id) {
+        state.mt5.connect(&connection).await?;
+    }
+
+    let closer = Mt5PositionCloser::new(state.mt5.clone(), connection_id);
+    let store = PgClosedTradeStore::new(state.db.pool().clone());
+    // Websocket updates, cache invalidation and loss streaks follow from the TradeClosed events
+    let requester = Requester::new(current_user.id, &current_user.subscription_plan);
+    let results =
+        on_behalf_of(requester, TradeCloseService::close_batch(&ids, owned, &closer, &store, state.events.as_ref())).await;
+
+    Ok(Json(TradeCloseService::summarize(results)))
+}
+
+// Opens a fresh market order mirroring one of the caller's trades, on the robot's broker connection
+pub async fn reenter_trade(
+    State(state): State<AppState>,
+    Path(trade_id): Path<Uuid>,
+    current_user: User,
+    _not_impersonated: NotImpersonated,
+    client: ClientInfo,
+    headers: HeaderMap,
+) -> Result<Json<TradeResponse>> {
+    let original = Trade::find_by_id(state.db.pool(), trade_id, current_user.id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Trade not found".to_string()))?;
+
+    let robot = TradingRobot::find_by_id(state.db.pool(), original.robot_id, current_user.id)
+        .await?
+        .ok_or_else(|| AppError::Unprocessable("The trade's robot no longer exists".to_string()))?;
+    let connection_id = robot
+        .broker_connection_id
+        .ok_or_else(|| AppError::Unprocessable("The trade's robot has no broker connection".to_string()))?;
+    let connection = BrokerConnection::find_by_id(state.db.pool(), connection_id, current_user.id)
+        .await?
+        .filter(|c| c.is_active)
+        .ok_or_else(|| AppError::Unprocessable("The robot's broker connection is not active".to_string()))?;
+    state.broker_maintenance.check_order(&connection.broker_type, Utc::now())?;
+
+    PlanService::ensure_can_open_trade(state.db.pool(), current_user.id, &current_user.subscription_plan, connection.is_demo)
+        .await?;
+    let stop_management = StopManagement::from_risk_config(&robot.risk_config).map_err(AppError::Validation)?;
+
+    let connection_id = connection.id.to_string();
+    if !state.mt5.is_connected(&connection_id) {
+        state.mt5.connect(&connection).await?;
+    }
+
+    let broker = Mt5ReentryBroker::new(state.mt5.clone(), connection_id, state.orders.clone(), client.as_str());
+    let requester = Requester::new(current_user.id, &current_user.subscription_plan).with_robot(robot.id);
+    let trade =
+        on_behalf_of(requester, TradeReentry::reenter(&original, &broker, connection.is_demo, stop_management, Utc::now()))
+            .await?;
+
+    // The order is placed by now, so a failure here only costs the audit trail
+    let request = serde_json::json!({ "action": "reenter", "trade_id": original.id });
+    let origin = TradeOrigin::new(trade.id, ORIGIN_MANUAL, Some(original.id.to_string()), trade.created_at).with_payload(
+        trade_origins::capture(&headers, &request, MAX_ORIGIN_PAYLOAD_BYTES),
+        trade_origins::source_ip(&headers),
+    );
+    if let Err(e) = TradeOrigin::record(state.db.pool(), &origin).await {
+        tracing::warn!("Could not record the origin of trade {}: {}", trade.id, e);
+    }
+
+    Ok(Json(trade.into()))
+}
+
+// What caused the trade, with the inbound payload while it is retained
+pub async fn get_trade_origin(
+    State(state): State<AppState>,
+    Path(trade_id): Path<Uuid>,
+    current_user: User,
+) -> Result<Json<TradeOrigin>> {
+    let origin = TradeOrigin::find_for_user(state.db.pool(), current_user.id, trade_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("No origin recorded for this trade".to_string()))?;
+    Ok(Json(origin))
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct PendingReviewsQuery {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+// Closed trades still waiting for a journal entry, oldest first
+pub async fn list_pending_reviews(
+    State(state): State<AppState>,
+    Query(query): Query<PendingReviewsQuery>,
+    current_user: User,
+) -> Result<Json<Vec<PendingReview>>> {
+    let limit = query.limit.unwrap_or(50).clamp(1, 100);
+    let offset = query.offset.unwrap_or(0).max(0);
+    Ok(Json(TradeReview::find_pending(state.db.pool(), current_user.id, limit, offset).await?))
+}
+
+// Creates or edits the trade's journal entry; entries lock 7 days after they are first submitted
+pub async fn submit_review(
+    State(state): State<AppState>,
+    Path(trade_id): Path<Uuid>,
+    current_user: User,
+    Json(payload): Json<SubmitTradeReviewRequest>,
+) -> Result<Json<TradeReview>> {
+    let store = PgTradeJournalStore::new(state.db.pool().clone());
+    let review = TradeJournal::submit(&store, current_user.id, trade_id, payload, Utc::now()).await?;
+    Ok(Json(review))
+}
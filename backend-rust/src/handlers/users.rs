@@ -1,29 +1,47 @@
 use axum::{
     extract::{Path, Query, State},
+    http::StatusCode,
     response::Json,
 };
 use serde::Deserialize;
+use schemars::JsonSchema;
 use uuid::Uuid;
 
 use crate::{
-    models::{User, UserResponse},
-    errors::Result,
+    app_middleware::NotImpersonated,
+    models::{
+        AuditActor, AuditEntry, AuthEvent, AuthEventFilter, DeleteAccountRequest, Leaderboard, LeaderboardSharing, RiskTemplate, Role, Subscription,
+        UpdateProfileRequest, User, UserLeaderboard, UserResponse,
+    },
+    services::{
+        auth_service::{AuthService, Claims},
+        event_bus::{DomainEvent, EventPublisher},
+        leaderboard::LEADERBOARD_PUBLIC_SIZE,
+        RiskTemplateService,
+    },
+    errors::{AppError, Result},
     AppState,
 };
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 pub struct ListUsersQuery {
     pub limit: Option<i64>,
     pub offset: Option<i64>,
 }
 
+#[derive(Deserialize, JsonSchema)]
+pub struct SecurityEventsQuery {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
 pub async fn list_users(
     State(state): State<AppState>,
     Query(query): Query<ListUsersQuery>,
     current_user: User,
 ) -> Result<Json<Vec<UserResponse>>> {
-    // Only allow admins to list all users
-    if !current_user.is_superuser {
+    // Only support and admins can list all users
+    if current_user.role() < Role::Support {
         return Ok(Json(vec![current_user.into()]));
     }
 
@@ -41,8 +59,8 @@ pub async fn get_user(
     Path(user_id): Path<Uuid>,
     current_user: User,
 ) -> Result<Json<UserResponse>> {
-    // Users can only access their own profile, unless they're admin
-    if current_user.id != user_id && !current_user.is_superuser {
+    // Users can only access their own profile, unless they're support or admin
+    if current_user.id != user_id && current_user.role() < Role::Support {
         return Err(crate::errors::AppError::Forbidden("Access denied".to_string()));
     }
 
@@ -52,3 +70,136 @@ pub async fn get_user(
 
     Ok(Json(user.into()))
 }
+
+// Name, avatar, timezone and locale; fields left out stay as they are
+pub async fn update_profile(
+    State(state): State<AppState>,
+    current_user: User,
+    Json(payload): Json<UpdateProfileRequest>,
+) -> Result<Json<UserResponse>> {
+    let mut user = current_user;
+    payload.apply(&mut user, chrono::Utc::now())?;
+    User::save_profile(state.db.pool(), &user).await?;
+    Ok(Json(user.into()))
+}
+
+pub async fn get_risk_template(
+    State(state): State<AppState>,
+    current_user: User,
+) -> Result<Json<RiskTemplate>> {
+    let template = User::risk_template(state.db.pool(), current_user.id).await?;
+    Ok(Json(RiskTemplate {
+        risk_config: template.and_then(|t| t.as_object().cloned()),
+    }))
+}
+
+// A null risk_config removes the template, so new robots start from the platform defaults again
+pub async fn update_risk_template(
+    State(state): State<AppState>,
+    current_user: User,
+    Json(payload): Json<RiskTemplate>,
+) -> Result<Json<RiskTemplate>> {
+    let template = payload.risk_config.map(RiskTemplateService::validate).transpose()?;
+    User::set_risk_template(state.db.pool(), current_user.id, template.as_ref()).await?;
+
+    Ok(Json(RiskTemplate {
+        risk_config: template.and_then(|t| t.as_object().cloned()),
+    }))
+}
+
+pub async fn get_leaderboard_sharing(
+    State(state): State<AppState>,
+    current_user: User,
+) -> Result<Json<LeaderboardSharing>> {
+    let share_performance_anonymously = User::shares_performance(state.db.pool(), current_user.id).await?;
+    Ok(Json(LeaderboardSharing { share_performance_anonymously }))
+}
+
+// Opting out hides the user's robots from the published weeks straight away
+pub async fn update_leaderboard_sharing(
+    State(state): State<AppState>,
+    current_user: User,
+    Json(payload): Json<LeaderboardSharing>,
+) -> Result<Json<LeaderboardSharing>> {
+    User::set_shares_performance(state.db.pool(), current_user.id, payload.share_performance_anonymously).await?;
+    Ok(Json(payload))
+}
+
+// Deletes the caller's account once robots are stopped and trades closed. The Stripe subscription
+// is cancelled first, so a failure there leaves the account as it was and can be retried.
+pub async fn delete_account(
+    State(state): State<AppState>,
+    current_user: User,
+    _not_impersonated: NotImpersonated,
+    Json(payload): Json<DeleteAccountRequest>,
+) -> Result<StatusCode> {
+    if !current_user.verify_password(&payload.password) {
+        return Err(AppError::Validation("The password is incorrect".to_string()));
+    }
+    let pool = state.db.pool();
+    let (running_robots, open_trades) = User::deletion_blockers(pool, current_user.id).await?;
+    if running_robots > 0 || open_trades > 0 {
+        return Err(AppError::Unprocessable(format!(
+            "Stop your robots and close your trades before deleting your account ({} running robot(s), {} open trade(s))",
+            running_robots, open_trades
+        )));
+    }
+
+    let subscription = Subscription::find_by_user_id(pool, current_user.id).await?;
+    if let Some(subscription) = &subscription {
+        if let Some(stripe_id) = &subscription.stripe_subscription_id {
+            state.stripe.cancel_subscription(stripe_id).await?;
+        }
+        Subscription::update_status(pool, subscription.id, "cancelled").await?;
+    }
+    let connections = User::anonymize(pool, current_user.id, chrono::Utc::now()).await?;
+
+    AuditEntry::record(
+        pool,
+        &AuditActor::user(current_user.id),
+        "user.delete",
+        "user",
+        Some(current_user.id),
+        serde_json::json!({
+            "subscription_cancelled": subscription.is_some(),
+            "broker_connections_deleted": connections,
+        }),
+    )
+    .await?;
+    state.events.publish(DomainEvent::AccountDeleted { user_id: current_user.id, email: current_user.email });
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Sign-ins, sign-outs and credential changes of the signed-in user, newest first. Like the sessions
+// list these are the user's own, even when acting for a delegating account.
+pub async fn list_security_events(
+    State(state): State<AppState>,
+    Query(query): Query<SecurityEventsQuery>,
+    claims: Claims,
+) -> Result<Json<Vec<AuthEvent>>> {
+    let filter = AuthEventFilter { user_id: Some(AuthService::user_id(&claims)?), ..Default::default() };
+    let limit = query.limit.unwrap_or(50).clamp(1, 100);
+    let offset = query.offset.unwrap_or(0).max(0);
+    Ok(Json(AuthEvent::search(state.db.pool(), &filter, limit, offset).await?))
+}
+
+// The public leaderboard plus where the caller's own robots ranked
+pub async fn get_leaderboard(
+    State(state): State<AppState>,
+    current_user: User,
+) -> Result<Json<UserLeaderboard>> {
+    let pool = state.db.pool();
+    let opted_in = User::shares_performance(pool, current_user.id).await?;
+    let week_start = Leaderboard::latest_week(pool).await?;
+
+    let (entries, own) = match week_start {
+        Some(week) => {
+            let entries = Leaderboard::top(pool, week, LEADERBOARD_PUBLIC_SIZE).await?;
+            let own = if opted_in { Leaderboard::own(pool, week, current_user.id).await? } else { Vec::new() };
+            (entries, own)
+        }
+        None => (Vec::new(), Vec::new()),
+    };
+
+    Ok(Json(UserLeaderboard { week_start, opted_in, entries, own }))
+}
@@ -0,0 +1,64 @@
+use axum::{
+    extract::{Path, State},
+    response::Json,
+};
+
+use crate::{
+    models::{AddWatchlistSymbolRequest, ReplaceWatchlistRequest, Subscription, User, Watchlist, WatchlistResponse},
+    services::WatchlistService,
+    errors::Result,
+    AppState,
+};
+
+async fn save(state: &AppState, user: &User, symbols: Vec<String>) -> Result<Json<WatchlistResponse>> {
+    Watchlist::replace(state.db.pool(), user.id, &symbols).await?;
+    state.market_data.watchlist_changed(user.id, symbols.clone());
+
+    Ok(Json(WatchlistResponse {
+        symbols,
+        max_symbols: Subscription::plan_details(&user.subscription_plan).max_watchlist_symbols,
+    }))
+}
+
+pub async fn get_watchlist(
+    State(state): State<AppState>,
+    current_user: User,
+) -> Result<Json<WatchlistResponse>> {
+    let symbols = Watchlist::find_symbols(state.db.pool(), current_user.id).await?;
+    Ok(Json(WatchlistResponse {
+        symbols,
+        max_symbols: Subscription::plan_details(&current_user.subscription_plan).max_watchlist_symbols,
+    }))
+}
+
+pub async fn add_symbol(
+    State(state): State<AppState>,
+    current_user: User,
+    Json(payload): Json<AddWatchlistSymbolRequest>,
+) -> Result<Json<WatchlistResponse>> {
+    let plan = Subscription::plan_details(&current_user.subscription_plan);
+    let symbols = Watchlist::find_symbols(state.db.pool(), current_user.id).await?;
+    let symbols = WatchlistService::add(&plan, symbols, &payload.symbol)?;
+    save(&state, &current_user, symbols).await
+}
+
+// Replaces the whole list; this is also how it is reordered
+pub async fn replace_watchlist(
+    State(state): State<AppState>,
+    current_user: User,
+    Json(payload): Json<ReplaceWatchlistRequest>,
+) -> Result<Json<WatchlistResponse>> {
+    let plan = Subscription::plan_details(&current_user.subscription_plan);
+    let symbols = WatchlistService::normalize(&plan, &payload.symbols)?;
+    save(&state, &current_user, symbols).await
+}
+
+pub async fn remove_symbol(
+    State(state): State<AppState>,
+    Path(symbol): Path<String>,
+    current_user: User,
+) -> Result<Json<WatchlistResponse>> {
+    let symbols = Watchlist::find_symbols(state.db.pool(), current_user.id).await?;
+    let symbols = WatchlistService::remove(symbols, &symbol)?;
+    save(&state, &current_user, symbols).await
+}
@@ -0,0 +1,37 @@
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::HeaderMap,
+    response::Json,
+};
+use chrono::Utc;
+use serde_json::{json, Value};
+
+use crate::{
+    errors::{AppError, Result},
+    services::checkout_service::{CheckoutService, PgCheckoutStore},
+    AppState,
+};
+
+// Called by Stripe, so it authenticates with the signature instead of a session. Errors make
+// Stripe retry; events we do not act on are acknowledged.
+pub async fn stripe_webhook(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<Value>> {
+    let signature = headers
+        .get("stripe-signature")
+        .and_then(|v| v.to_str().ok())
+        .ok_or_else(|| AppError::Stripe("Missing Stripe-Signature header".to_string()))?;
+    let now = Utc::now();
+    CheckoutService::verify_signature(&body, signature, &state.config.stripe_webhook_secret, now)?;
+
+    let event = CheckoutService::parse_event(&body)?;
+    let store = PgCheckoutStore::new(state.db.pool().clone());
+    if let Some(activation) = CheckoutService::handle_event(&store, state.events.as_ref(), &event, now).await? {
+        super::subscriptions::reconcile_plan(&state, &activation).await?;
+    }
+
+    Ok(Json(json!({ "received": true })))
+}
@@ -0,0 +1,289 @@
+// The server as a library: main.rs wires the services, and the integration tests in tests/
+// build the same router around a disposable database
+use axum::{
+    extract::State,
+    http::{HeaderValue, StatusCode},
+    middleware,
+    response::Json,
+    routing::{delete, get, patch, post, put},
+    Router,
+};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tower::ServiceBuilder;
+use tower_http::{
+    cors::{AllowOrigin, Any, CorsLayer},
+    trace::TraceLayer,
+};
+
+pub mod config;
+pub mod database;
+pub mod models;
+pub mod handlers;
+pub mod services;
+pub mod app_middleware;
+pub mod errors;
+pub mod money;
+pub mod pagination;
+pub mod openapi;
+pub mod admin_cli;
+pub mod telemetry;
+
+use config::Config;
+use database::Database;
+use models::Role;
+use services::{
+    broker_throttle::BrokerThrottle, migration_coordinator::{SchemaGate, SchemaStatus}, system_status::SystemMonitor, task_supervisor,
+    CacheService, CredentialVault, EventBus, FeatureFlags, JobService, MarketDataStreamer, MessageTemplates, Mt5Service, OrderDrain, PublicStatsService, QuoteService, RobotRunnerRegistry, RuntimeConfig, StrategyOptimizer, StripeService, WebSocketManager,
+    cooldown_service::PgCooldownEnv, activation_nudges::PgNudgeEnv, statements::PgStatementEnv, BrokerMaintenanceService, PlanDowngradeService,
+    password_policy::PasswordChecker, token_revocation::TokenRevocations, LoginThrottle, oauth::OAuthProviders,
+};
+
+#[derive(Clone)]
+pub struct AppState {
+    pub db: Database,
+    pub config: Arc<Config>,
+    pub cache: CacheService,
+    pub broker_throttle: Arc<BrokerThrottle>,
+    pub mt5: Arc<Mt5Service>,
+    pub credentials: Arc<CredentialVault>,
+    pub orders: Arc<OrderDrain>,
+    pub websocket: Arc<WebSocketManager>,
+    pub runners: Arc<RobotRunnerRegistry>,
+    pub cooldowns: Arc<PgCooldownEnv>,
+    pub nudges: Arc<PgNudgeEnv>,
+    pub jobs: Arc<JobService>,
+    pub events: Arc<EventBus>,
+    pub feature_flags: Arc<FeatureFlags>,
+    pub public_stats: Arc<PublicStatsService>,
+    pub schema: Arc<SchemaGate>,
+    pub market_data: Arc<MarketDataStreamer>,
+    pub optimizer: Arc<StrategyOptimizer>,
+    pub system_monitor: Arc<SystemMonitor>,
+    pub stripe: Arc<StripeService>,
+    pub quotes: Arc<QuoteService>,
+    pub runtime: Arc<RuntimeConfig>,
+    pub templates: Arc<MessageTemplates>,
+    pub statements: Arc<PgStatementEnv>,
+    pub broker_maintenance: Arc<BrokerMaintenanceService>,
+    pub plan_downgrades: Arc<PlanDowngradeService>,
+    pub passwords: Arc<PasswordChecker>,
+    pub token_revocations: Arc<dyn TokenRevocations>,
+    pub login_throttle: Arc<LoginThrottle>,
+    // Sign-in providers configured from the environment
+    pub oauth: Arc<OAuthProviders>,
+}
+
+pub fn create_app(state: AppState) -> anyhow::Result<Router> {
+    // Public routes (no authentication required)
+    let public_routes = Router::new()
+        .route("/health", get(health_check))
+        .route("/ready", get(readiness_check))
+        .route("/metrics", get(metrics))
+        .route("/api/v1/auth/register", post(handlers::auth::register))
+        .route("/api/v1/auth/login", post(handlers::auth::login))
+        .route("/api/v1/auth/google", post(handlers::auth::google_login))
+        .route("/api/v1/auth/oauth/:provider", post(handlers::auth::oauth_login))
+        .route("/api/v1/auth/password-policy", get(handlers::auth::get_password_policy))
+        .route("/api/v1/auth/password-reset/request", post(handlers::auth::request_password_reset))
+        .route("/api/v1/auth/password-reset/confirm", post(handlers::auth::confirm_password_reset))
+        .route("/api/v1/public/stats", get(handlers::public::get_public_stats))
+        .route("/api/v1/public/status", get(handlers::public::get_public_status))
+        .route("/api/v1/public/leaderboard", get(handlers::public::get_public_leaderboard))
+        .route("/api/v1/public/unsubscribe", get(handlers::public::unsubscribe))
+        .route("/api/v1/openapi.json", get(handlers::public::get_openapi))
+        .route("/api/v1/webhooks/stripe", post(handlers::webhooks::stripe_webhook))
+        .route("/api/v1/bridge/events", post(handlers::bridge::receive_event));
+
+    // Protected routes (authentication required)
+    let protected_routes = Router::new()
+        .route("/api/v1/auth/me", get(handlers::auth::me))
+        .route("/api/v1/auth/password", put(handlers::auth::change_password))
+        .route("/api/v1/auth/change-password", post(handlers::auth::change_password_and_reissue))
+        .route("/api/v1/auth/logout", post(handlers::auth::logout))
+        .route("/api/v1/auth/logout-all", post(handlers::auth::logout_all))
+        .route("/api/v1/auth/sessions", get(handlers::auth::list_sessions))
+        .route("/api/v1/auth/sessions/:id", delete(handlers::auth::revoke_session))
+        .route("/api/v1/users", get(handlers::users::list_users))
+        .route("/api/v1/users/:id", get(handlers::users::get_user))
+        .route("/api/v1/users/me", delete(handlers::users::delete_account))
+        .route("/api/v1/users/me", patch(handlers::users::update_profile))
+        .route("/api/v1/users/me/risk-template", get(handlers::users::get_risk_template))
+        .route("/api/v1/users/me/risk-template", put(handlers::users::update_risk_template))
+        .route("/api/v1/users/me/leaderboard-sharing", get(handlers::users::get_leaderboard_sharing))
+        .route("/api/v1/users/me/leaderboard-sharing", put(handlers::users::update_leaderboard_sharing))
+        .route("/api/v1/users/me/security-events", get(handlers::users::list_security_events))
+        .route("/api/v1/leaderboard", get(handlers::users::get_leaderboard))
+        .route("/api/v1/users/me/delegates", get(handlers::delegations::list_delegates))
+        .route("/api/v1/users/me/delegates", post(handlers::delegations::create_delegate))
+        .route("/api/v1/users/me/delegates/:id", delete(handlers::delegations::revoke_delegate))
+        .route("/api/v1/delegations", get(handlers::delegations::list_delegations))
+        .route("/api/v1/delegations/accept", post(handlers::delegations::accept_delegation))
+        .route("/api/v1/api-keys", get(handlers::api_keys::list_api_keys))
+        .route("/api/v1/api-keys", post(handlers::api_keys::create_api_key))
+        .route("/api/v1/api-keys/:id", delete(handlers::api_keys::revoke_api_key))
+        .route("/api/v1/watchlist", get(handlers::watchlist::get_watchlist))
+        .route("/api/v1/watchlist", post(handlers::watchlist::add_symbol))
+        .route("/api/v1/watchlist", put(handlers::watchlist::replace_watchlist))
+        .route("/api/v1/watchlist/:symbol", delete(handlers::watchlist::remove_symbol))
+        .route("/api/v1/quotes", get(handlers::quotes::get_quotes))
+        .route("/api/v1/tools/risk-calculator", post(handlers::tools::risk_calculator))
+        .route("/api/v1/subscriptions", get(handlers::subscriptions::list_subscriptions))
+        .route("/api/v1/subscriptions", post(handlers::subscriptions::create_subscription))
+        .route("/api/v1/subscriptions/trial", post(handlers::subscriptions::start_trial))
+        .route("/api/v1/subscriptions/checkout-session", post(handlers::subscriptions::create_checkout_session))
+        .route("/api/v1/subscriptions/checkout-session/:id/complete", post(handlers::subscriptions::complete_mock_checkout))
+        .route("/api/v1/brokers", get(handlers::brokers::list_brokers))
+        .route("/api/v1/brokers", post(handlers::brokers::create_broker))
+        .route("/api/v1/brokers/:id", delete(handlers::brokers::delete_broker))
+        .route("/api/v1/brokers/:id/test", post(handlers::brokers::test_connection))
+        .route("/api/v1/brokers/:id/credentials", put(handlers::brokers::update_credentials))
+        .route("/api/v1/brokers/:id/snapshots", get(handlers::brokers::list_snapshots))
+        .route("/api/v1/brokers/:id/bridge-token", post(handlers::brokers::issue_bridge_token))
+        .route("/api/v1/robots", get(handlers::robots::list_robots))
+        .route("/api/v1/robots", post(handlers::robots::create_robot))
+        .route("/api/v1/robots/reactivate", post(handlers::robots::reactivate_robots))
+        .route("/api/v1/robots/:id", patch(handlers::robots::update_robot))
+        .route("/api/v1/robots/:id", delete(handlers::robots::delete_robot))
+        .route("/api/v1/robots/:id/changes", get(handlers::robots::list_robot_changes))
+        .route("/api/v1/robots/:id/signals", get(handlers::robots::robot_signals))
+        .route("/api/v1/robots/:id/ai-quality", get(handlers::robots::robot_ai_quality))
+        .route("/api/v1/robots/:id/optimize", post(handlers::robots::optimize_robot))
+        .route("/api/v1/optimizations/:job_id", get(handlers::robots::get_optimization))
+        .route("/api/v1/optimizations/:job_id/apply", post(handlers::robots::apply_optimization))
+        .route("/api/v1/jobs", get(handlers::jobs::list_jobs))
+        .route("/api/v1/jobs/:id", get(handlers::jobs::get_job))
+        .route("/api/v1/jobs/:id/cancel", post(handlers::jobs::cancel_job))
+        .route("/api/v1/robots/:id/preflight", get(handlers::robots::robot_preflight))
+        .route("/api/v1/robots/:id/start", post(handlers::robots::start_robot))
+        .route("/api/v1/robots/:id/stop", post(handlers::robots::stop_robot))
+        .route("/api/v1/robots/:id/allocation", put(handlers::robots::update_allocation))
+        .route("/api/v1/trades", get(handlers::trades::list_trades))
+        .route("/api/v1/trades/statistics", get(handlers::trades::get_statistics))
+        .route("/api/v1/trades/search", get(handlers::trades::search_trades))
+        .route("/api/v1/trades/floating", get(handlers::trades::get_floating_trades))
+        .route("/api/v1/exposure/by-connection", get(handlers::exposure::by_connection))
+        .route("/api/v1/exposure/net", get(handlers::exposure::net))
+        .route("/api/v1/trades/close-batch", post(handlers::trades::close_batch))
+        .route("/api/v1/trades/:id/reenter", post(handlers::trades::reenter_trade))
+        .route("/api/v1/trades/:id/origin", get(handlers::trades::get_trade_origin))
+        .route("/api/v1/trades/reviews/pending", get(handlers::trades::list_pending_reviews))
+        .route("/api/v1/trades/:id/review", put(handlers::trades::submit_review))
+        .route("/api/v1/statements/:year/:month", get(handlers::statements::get_statement))
+        .route("/api/v1/presets", get(handlers::presets::list_presets))
+        .route("/api/v1/presets", post(handlers::presets::create_preset))
+        .route("/api/v1/presets/:id", delete(handlers::presets::delete_preset))
+        .route("/api/v1/dashboard", get(handlers::dashboard::get_dashboard))
+        .route("/api/v1/dashboard/sparklines", get(handlers::dashboard::get_sparklines))
+        .layer(middleware::from_fn_with_state(state.clone(), app_middleware::auth_middleware));
+
+    // Read-only admin routes (support role or above)
+    let support_routes = Router::new()
+        .route("/api/v1/admin/users", get(handlers::admin::list_all_users))
+        .route("/api/v1/admin/stats", get(handlers::admin::get_system_stats))
+        .route("/api/v1/admin/stats/history", get(handlers::admin::get_stats_history))
+        .route("/api/v1/admin/health", get(handlers::admin::get_admin_health))
+        .route("/api/v1/admin/security-events", get(handlers::admin::list_security_events))
+        .layer(middleware::from_fn_with_state(Role::Support, app_middleware::require_role))
+        .layer(middleware::from_fn_with_state(state.clone(), app_middleware::auth_middleware));
+
+    // Admin routes (admin role required)
+    let admin_routes = Router::new()
+        .route("/api/v1/admin/users/:id/role", put(handlers::admin::update_user_role))
+        .route("/api/v1/admin/users/:id/plan", put(handlers::admin::update_user_plan))
+        .route("/api/v1/admin/users/:id/impersonate", post(handlers::admin::impersonate_user))
+        .route("/api/v1/admin/users/:id/activate", post(handlers::admin::activate_user))
+        .route("/api/v1/admin/users/:id/deactivate", post(handlers::admin::deactivate_user))
+        .route("/api/v1/admin/stats/backfill", post(handlers::admin::backfill_stats))
+        .route("/api/v1/admin/nudges/preview", get(handlers::admin::preview_nudges))
+        .route("/api/v1/admin/rotate-encryption", post(handlers::admin::rotate_encryption))
+        .route("/api/v1/admin/rotate-encryption", get(handlers::admin::get_encryption_rotation))
+        .route("/api/v1/admin/settings/stats-export", get(handlers::admin::get_stats_export_settings))
+        .route("/api/v1/admin/settings/stats-export", put(handlers::admin::update_stats_export_settings))
+        .route("/api/v1/admin/settings/maintenance", get(handlers::admin::get_maintenance_settings))
+        .route("/api/v1/admin/settings/maintenance", put(handlers::admin::update_maintenance_settings))
+        .route("/api/v1/admin/settings/maintenance/brokers", get(handlers::admin::get_broker_maintenance))
+        .route("/api/v1/admin/settings/maintenance/brokers/:broker_type", put(handlers::admin::set_broker_maintenance))
+        .route("/api/v1/admin/settings/maintenance/brokers/:broker_type", delete(handlers::admin::lift_broker_maintenance))
+        .route("/api/v1/admin/settings/runtime", get(handlers::admin::get_runtime_settings))
+        .route("/api/v1/admin/settings/runtime", patch(handlers::admin::update_runtime_settings))
+        .route("/api/v1/admin/incidents", post(handlers::admin::create_incident))
+        .route("/api/v1/admin/incidents/:id/updates", post(handlers::admin::add_incident_update))
+        .route("/api/v1/admin/feature-flags", get(handlers::admin::list_feature_flags))
+        .route("/api/v1/admin/feature-flags/:key", put(handlers::admin::update_feature_flag))
+        .route("/api/v1/admin/integrity/recalculate", post(handlers::admin::recalculate_integrity))
+        .route("/api/v1/admin/integrity/check", get(handlers::admin::check_integrity))
+        .route("/api/v1/admin/integrity/runs/:id", get(handlers::admin::get_integrity_run))
+        .route("/api/v1/admin/trades/:id/origin", get(handlers::admin::get_trade_origin))
+        .route("/api/v1/admin/templates", get(handlers::admin::list_templates))
+        .route("/api/v1/admin/templates/:key", post(handlers::admin::save_template))
+        .route("/api/v1/admin/templates/:key/activate", post(handlers::admin::activate_template))
+        .route("/api/v1/admin/templates/:key/preview", post(handlers::admin::preview_template))
+        .layer(middleware::from_fn_with_state(Role::Admin, app_middleware::require_role))
+        .layer(middleware::from_fn_with_state(state.clone(), app_middleware::auth_middleware));
+
+    // An empty origin list only survives config loading outside prod
+    let cors = if state.config.cors_allowed_origins.is_empty() {
+        CorsLayer::permissive()
+    } else {
+        let origins = state
+            .config
+            .cors_allowed_origins
+            .iter()
+            .map(|origin| origin.parse::<HeaderValue>())
+            .collect::<Result<Vec<_>, _>>()?;
+        CorsLayer::new()
+            .allow_origin(AllowOrigin::list(origins))
+            .allow_methods(Any)
+            .allow_headers(Any)
+    };
+
+    // Combine all routes
+    Ok(Router::new()
+        .merge(public_routes)
+        .merge(protected_routes)
+        .merge(support_routes)
+        .merge(admin_routes)
+        .layer(
+            ServiceBuilder::new()
+                .layer(TraceLayer::new_for_http().make_span_with(telemetry::HttpSpan))
+                .layer(cors)
+                .layer(middleware::from_fn(app_middleware::client_middleware))
+                .layer(middleware::from_fn(telemetry::handler_span))
+        )
+        .with_state(state))
+}
+
+async fn health_check() -> Result<Json<Value>, StatusCode> {
+    Ok(Json(json!({
+        "status": "healthy",
+        "timestamp": chrono::Utc::now().to_rfc3339()
+    })))
+}
+
+// Prometheus scrape target
+async fn metrics() -> ([(axum::http::header::HeaderName, &'static str); 1], String) {
+    (
+        [(axum::http::header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        task_supervisor::render_prometheus(),
+    )
+}
+
+// Not ready while the database is behind the migrations this build expects
+async fn readiness_check(State(state): State<AppState>) -> (StatusCode, Json<Value>) {
+    match state.schema.status().await {
+        Ok(status) if status.is_ready() => (StatusCode::OK, Json(json!({ "status": "ready" }))),
+        Ok(SchemaStatus::Pending(versions)) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "status": "migrations pending", "pending": versions })),
+        ),
+        Ok(SchemaStatus::Modified(version)) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "status": "migration modified", "version": version })),
+        ),
+        _ => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "status": "database unavailable" })),
+        ),
+    }
+}
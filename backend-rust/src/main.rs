@@ -1,124 +1,638 @@
-use axum::{
-    http::StatusCode,
-    middleware,
-    response::Json,
-    routing::{get, post},
-    Router,
-};
-use serde_json::{json, Value};
 use std::sync::Arc;
-use tower::ServiceBuilder;
-use tower_http::{cors::CorsLayer, trace::TraceLayer};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-
-mod config;
-mod database;
-mod models;
-mod handlers;
-mod services;
-mod app_middleware;
-mod errors;
-
-use config::Config;
-use database::Database;
-
-#[derive(Clone)]
-pub struct AppState {
-    pub db: Database,
-    pub config: Arc<Config>,
-}
+use opentelemetry::trace::TracerProvider as _;
+use tracing_subscriber::{layer::SubscriberExt, reload, util::SubscriberInitExt, Layer};
+
+use trading_saas_backend::{
+    config::Config,
+    create_app,
+    database::Database,
+    telemetry,
+    models::{Job, TradeOrigin},
+    services::{
+        self,
+        account_snapshot_service::PgSnapshotEnv,
+        activation_nudges::PgNudgeEnv,
+        apple_identity::{AppleKeysEndpoint, AppleVerifier},
+        backtest_engine::{CandleBacktestEngine, Mt5CandleSource},
+        broker_maintenance::PgBrokerMaintenanceEnv,
+        broker_throttle::{BrokerThrottle, PgQueueOverflowLog},
+        cooldown_service::PgCooldownEnv,
+        credential_vault::{KeyRing, PgCredentialStore},
+        email_outbox::PgOutboxStore,
+        event_subscribers::{AuditSubscriber, CacheSubscriber, CooldownSubscriber, JournalSubscriber, NotificationSubscriber, WebSocketSubscriber},
+        feature_flags::PgFlagSource,
+        github_identity::{GitHubApi, GitHubVerifier},
+        google_identity::{GoogleVerifier, TokenInfoEndpoint},
+        job_service::PgJobStore,
+        leaderboard::PgLeaderboardStore,
+        login_throttle::RedisLoginAttempts,
+        market_data_streamer::{Mt5QuoteFeed, PgWatchlistStore},
+        message_templates::PgTemplateStore,
+        migration_coordinator::{embedded_scripts, embedded_versions, PgMigrationTarget, SchemaGate},
+        migration_lint::MigrationGuard,
+        oauth::OAuthProviders,
+        onboarding_service::PgOnboardingEnv,
+        order_drain::PgOrderStore,
+        password_policy::{HibpRange, PasswordChecker},
+        plan_downgrade::PgPlanDowngradeEnv,
+        plan_service::PgPlanLimiter,
+        platform_stats::{PgPlatformStatsStore, WebhookStatsPush},
+        quote_service::{BrokerQuotes, ExternalRates, PlatformQuoteCache, PlatformQuotes, QuoteLookup, QuoteSource},
+        robot_recovery::PgRecoveryEnv,
+        robot_runner::{Mt5StopExecutor, PgRunnerCrashHandler},
+        runtime_settings::{LogFilter, PgRuntimeSettingsSource, RUNTIME_SETTINGS_POLL_SECONDS},
+        statements::PgStatementEnv,
+        system_status::{LiveHealthSource, SystemMonitor},
+        task_supervisor::{self, AdminPanicAlerts, TaskClass},
+        token_revocation::RedisTokenRevocations,
+        trade_journal::PgTradeJournalStore,
+        user_events::RedisUserEventLog,
+        ws_shedding::{AdminSheddingAlerts, ShedPolicy},
+        AccountSnapshotService, ActivationNudges, BrokerMaintenanceService, CacheService, CooldownService,
+        CredentialVault, DeletionPurge, EmailOutbox, EventBus, FeatureFlags, JobService, LeaderboardService,
+        LoginThrottle, MarketDataStreamer, MessageTemplates, MigrationCoordinator, Mt5Service,
+        NotificationService, OnboardingService, OrderDrain, PlanDowngradeService, PlatformStats,
+        PublicStatsService, QuoteService, RobotRecovery, RobotRunnerRegistry, RuntimeConfig, Scheduler,
+        StatementService, StrategyOptimizer, StripeService, TaskSupervisor, TradeFactsBackfill, TrialService,
+        WebSocketManager,
+    },
+    AppState,
+};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     dotenvy::dotenv().ok(); // no main.rs
 
-    // Initialize tracing
+    // Load configuration
+    let config = Arc::new(Config::from_env()?);
+
+    // Initialize tracing; the filter stays reloadable for the runtime log_level setting. It only
+    // filters the log output, so trace export sees spans whatever the log level.
+    let log_directives = std::env::var(tracing_subscriber::EnvFilter::DEFAULT_ENV)
+        .ok()
+        .filter(|directives| tracing_subscriber::EnvFilter::try_new(directives).is_ok())
+        .unwrap_or_else(|| "trading_saas_backend=debug,tower_http=debug".to_string());
+    let (log_filter, log_handle) = reload::Layer::new(tracing_subscriber::EnvFilter::new(&log_directives));
+    let tracer_provider = telemetry::provider(&config.telemetry)?;
     tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "trading_saas_backend=debug,tower_http=debug".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
+        .with(tracing_subscriber::fmt::layer().with_filter(log_filter))
+        .with(telemetry::layer(tracer_provider.as_ref().map(|provider| provider.tracer(config.telemetry.service_name.clone()))))
         .init();
+    task_supervisor::install_panic_hook();
 
-    // Load configuration
-    let config = Arc::new(Config::from_env()?);
+    let mocks = config.mock_subsystems();
+    tracing::info!(
+        "Starting with APP_ENV={} (mock subsystems: {})",
+        config.app_env.as_str(),
+        if mocks.is_empty() { "none".to_string() } else { mocks.join(", ") }
+    );
     
     // Initialize database
-    let db = Database::new(&config.database_url).await?;
+    let db = Database::new(
+        &config.database_url,
+        std::time::Duration::from_millis(config.db_statement_timeout_ms),
+        std::time::Duration::from_millis(config.db_export_statement_timeout_ms),
+    )
+    .await?;
     
-    // Run migrations
-    db.migrate().await?;
+    // Migrations run under an advisory lock so concurrent replicas apply them once.
+    // `--migrate-only` runs them as a deploy step; SKIP_MIGRATIONS leaves them to that step.
+    // With STRICT_MIGRATIONS, destructive statements only run from `--migrate-only --allow-destructive`
+    // after the operator confirms them.
+    let migrate_only = std::env::args().any(|arg| arg == "--migrate-only");
+    let allow_destructive = std::env::args().any(|arg| arg == "--allow-destructive");
+    if allow_destructive && !migrate_only {
+        anyhow::bail!("--allow-destructive is only accepted together with --migrate-only");
+    }
+    let migrations = Arc::new(PgMigrationTarget::new(db.export_pool().clone()));
+    if migrate_only || !config.skip_migrations {
+        let mut guard = MigrationGuard::new(embedded_scripts()).with_strict(config.strict_migrations);
+        if allow_destructive {
+            let flagged = MigrationCoordinator::pending_destructive(migrations.as_ref(), &embedded_versions(), &guard).await?;
+            if !flagged.is_empty() {
+                eprintln!("The pending migrations contain destructive statements:");
+                for f in &flagged {
+                    eprintln!("  {} ({}): {}", f.version, f.kind.describe(), f.statement);
+                }
+                eprint!("Type \"apply\" to run them: ");
+                let mut answer = String::new();
+                std::io::stdin().read_line(&mut answer)?;
+                if answer.trim() != "apply" {
+                    anyhow::bail!("Destructive migrations were not confirmed");
+                }
+                guard = guard.with_allow_destructive(true);
+            }
+        }
+        let outcome = MigrationCoordinator::run(
+            migrations.as_ref(),
+            &embedded_versions(),
+            &guard,
+            std::time::Duration::from_secs(config.migration_lock_timeout_secs),
+            std::time::Duration::from_secs(2),
+        )
+        .await?;
+        tracing::info!("Migrations: {:?}", outcome);
+        if migrate_only {
+            return Ok(());
+        }
+    } else {
+        tracing::info!("SKIP_MIGRATIONS is set; not ready until the schema is migrated");
+    }
+    let schema = Arc::new(SchemaGate::new(migrations, embedded_versions()));
+
+    // Initialize Redis cache
+    let cache = CacheService::new(&config.redis_url)?;
+
+    // Per-connection rate limiting toward brokers, shared by every broker client; queued calls
+    // are served by plan class and turned away once a connection's queue is full
+    let broker_throttle = Arc::new(
+        BrokerThrottle::new(
+            config.broker_rate_limits.clone(),
+            std::time::Duration::from_millis(config.broker_max_queue_wait_ms),
+        )
+        .with_max_queue_depth(config.broker_max_queue_depth)
+        .with_overflow_sink(Arc::new(PgQueueOverflowLog::new(db.pool().clone()))),
+    );
+
+    // Broker credentials are sealed with the current key; older keys only decrypt
+    let credentials = Arc::new(CredentialVault::new(
+        KeyRing::new(&config.encryption_key_id, &config.encryption_keys)?,
+        Arc::new(PgCredentialStore::new(db.pool().clone())),
+    ));
+    credentials.warn_if_stale().await;
+
+    // Log level, broker rate limits and job intervals an admin can change without a restart
+    let runtime = Arc::new(
+        RuntimeConfig::new(Arc::new(PgRuntimeSettingsSource::new(db.pool().clone())))
+            .with_log_filter(LogFilter::new(log_handle, log_directives))
+            .with_throttle(broker_throttle.clone()),
+    );
+    if let Err(e) = runtime.reload().await {
+        tracing::warn!("Runtime settings not applied at startup: {}", e);
+    }
+
+    let mt5 = Arc::new(Mt5Service::with_throttle(broker_throttle.clone()).with_credentials(credentials.clone()));
+
+    // Streams quotes for robot symbols and for the watchlists of connected users
+    let quote_cache = Arc::new(PlatformQuoteCache::new());
+    let market_data = Arc::new(
+        MarketDataStreamer::new(Arc::new(PgWatchlistStore::new(db.pool().clone()))).with_quote_cache(quote_cache.clone()),
+    );
+
+    // Indicative prices for display: the user's broker, then any session, then the rates API
+    let mut quote_sources: Vec<(QuoteSource, Arc<dyn QuoteLookup>)> = vec![
+        (QuoteSource::Broker, Arc::new(BrokerQuotes::new(db.pool().clone(), mt5.clone()))),
+        (QuoteSource::Platform, Arc::new(PlatformQuotes::new(quote_cache, mt5.clone()))),
+    ];
+    if let Some(url) = &config.quote_fallback_url {
+        quote_sources.push((QuoteSource::External, Arc::new(ExternalRates::new(url.clone(), config.quote_fallback_api_key.clone()))));
+    }
+    let quotes = Arc::new(QuoteService::new(quote_sources));
+
+    let templates = Arc::new(MessageTemplates::new(Arc::new(PgTemplateStore::new(db.pool().clone()))));
+    let notifications = Arc::new(
+        NotificationService::new(
+            config.smtp_host.clone(),
+            config.smtp_user.clone(),
+            config.smtp_password.clone(),
+        )
+        .with_outbox(Arc::new(PgOutboxStore::new(db.pool().clone())))
+        .with_templates(templates.clone()),
+    );
+
+    // The admin hears when a channel starts shedding market data
+    let shedding = match &config.admin_alert_email {
+        Some(email) => ShedPolicy::new(config.ws_shed_watermark_percent)
+            .with_alerts(Arc::new(AdminSheddingAlerts::new(notifications.clone(), email.clone()))),
+        None => ShedPolicy::new(config.ws_shed_watermark_percent),
+    };
+    let websocket = Arc::new(
+        WebSocketManager::with_capacities(config.ws_user_channel_capacity, config.ws_global_channel_capacity)
+            .with_presence(market_data.clone())
+            .with_batch_window(std::time::Duration::from_millis(config.ws_batch_window_ms))
+            .with_shedding(shedding)
+            // Shared across instances so a client can resume on whichever one it reconnects to
+            .with_event_log(Arc::new(RedisUserEventLog::new(&config.redis_url)?)),
+    );
+
+    // Panics in critical background tasks are emailed to the admin when an address is set
+    let supervisor = match &config.admin_alert_email {
+        Some(email) => TaskSupervisor::new().with_alerts(Arc::new(AdminPanicAlerts::new(notifications.clone(), email.clone()))),
+        None => TaskSupervisor::new(),
+    };
+
+    // Platform-managed SL/TP is enforced by the robot runners
+    let stop_executor = Arc::new(Mt5StopExecutor::new(db.pool().clone(), mt5.clone()));
+    let cooldowns = Arc::new(PgCooldownEnv::new(db.pool().clone(), notifications.clone()));
+    let runners = Arc::new(
+        RobotRunnerRegistry::new()
+            .with_stop_executor(stop_executor)
+            .with_cooldowns(cooldowns.clone())
+            .with_supervisor(supervisor.clone())
+            .with_crash_handler(Arc::new(PgRunnerCrashHandler::new(db.pool().clone(), notifications.clone())))
+            .with_plan_limiter(Arc::new(PgPlanLimiter::new(db.pool().clone()))),
+    );
+
+    // Side effects of domain events, each subscriber on its own task
+    let events = Arc::new(EventBus::new());
+    events.subscribe(Arc::new(WebSocketSubscriber::new(websocket.clone())));
+    events.subscribe(Arc::new(CacheSubscriber::new(Arc::new(cache.clone()))));
+    events.subscribe(Arc::new(CooldownSubscriber::new(cooldowns.clone(), runners.clone())));
+    events.subscribe(Arc::new(NotificationSubscriber::new(notifications.clone())));
+    events.subscribe(Arc::new(JournalSubscriber::new(Arc::new(PgTradeJournalStore::new(db.pool().clone())), websocket.clone())));
+    events.subscribe(Arc::new(AuditSubscriber));
+
+    let feature_flags = Arc::new(FeatureFlags::new(Arc::new(PgFlagSource::new(db.pool().clone()))));
+
+    let public_stats = Arc::new(PublicStatsService::new(Arc::new(cache.clone()), config.public_stats_round_to));
+
+    // Parameter sweeps replay MT5 history; their progress goes out as backtest events
+    let optimizer = Arc::new(StrategyOptimizer::new(
+        Arc::new(CandleBacktestEngine::new(Arc::new(Mt5CandleSource::new(mt5.clone())))),
+        websocket.backtests(),
+        websocket.clone(),
+    ));
+
+    let nudges = Arc::new(PgNudgeEnv::new(db.pool().clone(), notifications.clone(), &config.public_base_url));
+    let statements = Arc::new(PgStatementEnv::new(db.pool().clone(), notifications.clone()));
+    let broker_maintenance = Arc::new(BrokerMaintenanceService::new(Arc::new(PgBrokerMaintenanceEnv::new(
+        db.pool().clone(),
+        notifications.clone(),
+    ))));
+    let plan_downgrades = Arc::new(PlanDowngradeService::new(Arc::new(PgPlanDowngradeEnv::new(
+        db.pool().clone(),
+        notifications.clone(),
+    ))));
+    let login_throttle = Arc::new(LoginThrottle::new(
+        Arc::new(RedisLoginAttempts::new(&config.redis_url)?),
+        config.login_throttle.clone(),
+        notifications.clone(),
+    ));
+    let orders = Arc::new(OrderDrain::new(Arc::new(PgOrderStore::new(db.pool().clone()))));
+    let jobs = Arc::new(JobService::new(Arc::new(PgJobStore::new(db.pool().clone()))));
+    let mut passwords = PasswordChecker::new(config.password_policy.clone());
+    if config.password_policy.breach_check {
+        passwords = passwords.with_breach_lookup(
+            Arc::new(HibpRange::new(config.password_breach_check_url.clone())),
+            std::time::Duration::from_millis(config.password_breach_check_timeout_ms),
+        );
+    }
+
+    // Each sign-in provider is offered once its client is configured
+    let mut oauth = OAuthProviders::new();
+    if let Some(client_id) = &config.google_client_id {
+        oauth = oauth.with_provider(Arc::new(GoogleVerifier::new(
+            Arc::new(TokenInfoEndpoint::new(config.google_tokeninfo_url.clone())),
+            Some(client_id.clone()),
+        )));
+    }
+    if let (Some(client_id), Some(secret)) = (&config.github_client_id, &config.github_client_secret) {
+        oauth = oauth.with_provider(Arc::new(GitHubVerifier::new(Arc::new(GitHubApi::new(
+            config.github_api_url.clone(),
+            client_id.clone(),
+            secret.clone(),
+        )))));
+    }
+    if let Some(client_id) = &config.apple_client_id {
+        oauth = oauth.with_provider(Arc::new(AppleVerifier::new(
+            Arc::new(AppleKeysEndpoint::new(config.apple_keys_url.clone())),
+            client_id.clone(),
+        )));
+    }
+    tracing::info!("Sign-in providers: {:?}", oauth.names());
 
     // Create application state
     let state = AppState {
         db,
         config: config.clone(),
+        cache,
+        broker_throttle,
+        mt5,
+        credentials,
+        orders: orders.clone(),
+        websocket,
+        runners,
+        cooldowns,
+        nudges,
+        jobs,
+        events,
+        feature_flags,
+        public_stats,
+        schema,
+        market_data,
+        optimizer,
+        system_monitor: Arc::new(SystemMonitor::new(chrono::Utc::now())),
+        stripe: Arc::new(StripeService::new(config.stripe_secret_key.clone())),
+        quotes,
+        runtime: runtime.clone(),
+        templates,
+        statements,
+        broker_maintenance,
+        plan_downgrades,
+        passwords: Arc::new(passwords),
+        token_revocations: Arc::new(RedisTokenRevocations::new(&config.redis_url, config.token_settings().lifetime)?),
+        login_throttle,
+        oauth: Arc::new(oauth),
     };
 
+    // Bring back the runners of robots that were running before the restart
+    {
+        let env = PgRecoveryEnv::new(state.db.pool().clone(), state.mt5.clone(), notifications.clone());
+        let runners = state.runners.clone();
+        supervisor.spawn_supervised("robot_recovery", TaskClass::Background, async move {
+            if let Err(e) = RobotRecovery::recover(&env, &runners).await {
+                tracing::error!("Robot recovery failed: {}", e);
+            }
+        });
+    }
+
+    // Background jobs
+    let mut scheduler = Scheduler::new().with_supervisor(supervisor.clone()).with_intervals(runtime.clone());
+    {
+        let runtime = runtime.clone();
+        scheduler.every("runtime_settings", std::time::Duration::from_secs(RUNTIME_SETTINGS_POLL_SECONDS), move || {
+            let runtime = runtime.clone();
+            async move { runtime.reload().await }
+        });
+    }
+    {
+        let pool = state.db.pool().clone();
+        let notifications = notifications.clone();
+        scheduler.every("trial_expiry", std::time::Duration::from_secs(15 * 60), move || {
+            let pool = pool.clone();
+            let notifications = notifications.clone();
+            async move { TrialService::process_trials(&pool, &notifications).await }
+        });
+    }
+    {
+        let env = Arc::new(PgOnboardingEnv::new(
+            state.db.pool().clone(),
+            notifications.clone(),
+            &config.public_base_url,
+        ));
+        scheduler.every("onboarding_emails", std::time::Duration::from_secs(15 * 60), move || {
+            let env = env.clone();
+            async move { OnboardingService::process(env.as_ref(), chrono::Utc::now()).await.map(|_| ()) }
+        });
+    }
+    {
+        let env = state.nudges.clone();
+        let nudges = Arc::new(ActivationNudges::new(config.idle_robot_days, config.nudge_monthly_cap));
+        scheduler.every("activation_nudges", std::time::Duration::from_secs(7 * 24 * 60 * 60), move || {
+            let env = env.clone();
+            let nudges = nudges.clone();
+            async move { nudges.process(env.as_ref(), chrono::Utc::now()).await.map(|_| ()) }
+        });
+    }
+    {
+        let store = Arc::new(PgLeaderboardStore::new(state.db.pool().clone()));
+        let min_trades = config.leaderboard_min_trades;
+        scheduler.every(
+            "leaderboard",
+            std::time::Duration::from_secs(services::leaderboard::LEADERBOARD_CHECK_SECONDS),
+            move || {
+                let store = store.clone();
+                async move { LeaderboardService::publish(store.as_ref(), chrono::Utc::now(), min_trades).await.map(|_| ()) }
+            },
+        );
+    }
+    {
+        let cooldowns = state.cooldowns.clone();
+        let runners = state.runners.clone();
+        scheduler.every("loss_streak_cooldowns", std::time::Duration::from_secs(60), move || {
+            let cooldowns = cooldowns.clone();
+            let runners = runners.clone();
+            async move { CooldownService::resume_due(cooldowns.as_ref(), &runners, chrono::Utc::now()).await.map(|_| ()) }
+        });
+    }
+    {
+        let pool = state.db.pool().clone();
+        let public_stats = state.public_stats.clone();
+        scheduler.every(
+            "public_stats",
+            std::time::Duration::from_secs(services::public_stats::PUBLIC_STATS_REFRESH_SECONDS),
+            move || {
+                let pool = pool.clone();
+                let public_stats = public_stats.clone();
+                async move { public_stats.run(&pool).await }
+            },
+        );
+    }
+
+    {
+        let store = Arc::new(PgOutboxStore::new(state.db.pool().clone()));
+        let notifications = notifications.clone();
+        scheduler.every(
+            "email_outbox",
+            std::time::Duration::from_secs(services::email_outbox::OUTBOX_POLL_SECONDS),
+            move || {
+                let store = store.clone();
+                let notifications = notifications.clone();
+                async move {
+                    EmailOutbox::process(store.as_ref(), notifications.as_ref(), chrono::Utc::now()).await.map(|_| ())
+                }
+            },
+        );
+    }
+    {
+        let feed = Arc::new(Mt5QuoteFeed::new(state.db.pool().clone(), state.mt5.clone()));
+        let market_data = state.market_data.clone();
+        let websocket = state.websocket.clone();
+        scheduler.every(
+            "market_data",
+            std::time::Duration::from_secs(services::market_data_streamer::MARKET_DATA_TICK_SECONDS),
+            move || {
+                let feed = feed.clone();
+                let market_data = market_data.clone();
+                let websocket = websocket.clone();
+                async move { market_data.tick(feed.as_ref(), &websocket).await }
+            },
+        );
+    }
+    {
+        // One-time fill of trade_daily_facts, retried until it completes and a no-op afterwards;
+        // statistics read the trades table until then
+        let pool = state.db.pool().clone();
+        scheduler.every(
+            "trade_facts_backfill",
+            std::time::Duration::from_secs(services::trade_facts::BACKFILL_RETRY_SECONDS),
+            move || {
+                let pool = pool.clone();
+                async move { TradeFactsBackfill::run(&pool).await.map(|_| ()) }
+            },
+        );
+    }
+    {
+        // Jobs whose worker died with its process stop counting against their owner's limit
+        let pool = state.db.pool().clone();
+        scheduler.every(
+            "stale_jobs",
+            std::time::Duration::from_secs(services::job_service::STALE_SWEEP_SECONDS),
+            move || {
+                let pool = pool.clone();
+                async move {
+                    let now = chrono::Utc::now();
+                    let before = now - chrono::Duration::minutes(services::job_service::STALE_JOB_MINUTES);
+                    let failed = Job::fail_stale(&pool, before, now).await?;
+                    if failed > 0 {
+                        tracing::warn!("Failed {} job(s) that stopped reporting progress", failed);
+                    }
+                    Ok(())
+                }
+            },
+        );
+    }
+    {
+        // Old inbound payloads go; the origin type and reference stay with the trade
+        let pool = state.db.pool().clone();
+        let retention_days = config.trade_origin_retention_days;
+        scheduler.every("trade_origin_retention", std::time::Duration::from_secs(24 * 60 * 60), move || {
+            let pool = pool.clone();
+            async move {
+                let now = chrono::Utc::now();
+                let pruned = TradeOrigin::prune_payloads(&pool, now - chrono::Duration::days(retention_days), now).await?;
+                if pruned > 0 {
+                    tracing::info!("Pruned {} trade origin payload(s)", pruned);
+                }
+                Ok(())
+            }
+        });
+    }
+    {
+        let pool = state.db.pool().clone();
+        let retention_days = config.deleted_retention_days;
+        scheduler.every("deletion_purge", std::time::Duration::from_secs(24 * 60 * 60), move || {
+            let pool = pool.clone();
+            async move {
+                let purged = DeletionPurge::purge(&pool, DeletionPurge::cutoff(chrono::Utc::now(), retention_days)).await?;
+                if purged.total() > 0 {
+                    tracing::info!(
+                        "Purged {} deleted user(s), {} robot(s) and {} broker connection(s)",
+                        purged.users,
+                        purged.robots,
+                        purged.broker_connections
+                    );
+                }
+                Ok(())
+            }
+        });
+    }
+    {
+        let env = state.statements.clone();
+        scheduler.every(
+            "monthly_statements",
+            std::time::Duration::from_secs(services::statements::STATEMENT_CHECK_SECONDS),
+            move || {
+                let env = env.clone();
+                async move { StatementService::generate_due(env.as_ref(), chrono::Utc::now()).await.map(|_| ()) }
+            },
+        );
+    }
+    {
+        let maintenance = state.broker_maintenance.clone();
+        let runners = state.runners.clone();
+        scheduler.every(
+            "broker_maintenance",
+            std::time::Duration::from_secs(services::broker_maintenance::BROKER_MAINTENANCE_SYNC_SECONDS),
+            move || {
+                let maintenance = maintenance.clone();
+                let runners = runners.clone();
+                async move { maintenance.sync(&runners, chrono::Utc::now()).await.map(|_| ()) }
+            },
+        );
+    }
+    {
+        let env = Arc::new(PgSnapshotEnv::new(state.db.pool().clone(), state.mt5.clone()));
+        scheduler.every(
+            "account_snapshots",
+            std::time::Duration::from_secs(services::account_snapshot_service::SNAPSHOT_INTERVAL_SECONDS),
+            move || {
+                let env = env.clone();
+                async move { AccountSnapshotService::run(env.as_ref(), chrono::Utc::now()).await }
+            },
+        );
+    }
+    {
+        let store = Arc::new(PgPlatformStatsStore::new(state.db.pool().clone()));
+        let target = Arc::new(WebhookStatsPush::new());
+        scheduler.every(
+            "platform_stats",
+            std::time::Duration::from_secs(services::platform_stats::SNAPSHOT_INTERVAL_SECONDS),
+            move || {
+                let store = store.clone();
+                let target = target.clone();
+                async move { PlatformStats::run(store.as_ref(), target.as_ref(), chrono::Utc::now()).await.map(|_| ()) }
+            },
+        );
+    }
+
+    {
+        let source = Arc::new(LiveHealthSource::new(state.db.pool().clone(), state.mt5.clone(), state.websocket.clone()));
+        let monitor = state.system_monitor.clone();
+        scheduler.every(
+            "system_status",
+            std::time::Duration::from_secs(services::system_status::STATUS_CHECK_SECONDS),
+            move || {
+                let source = source.clone();
+                let monitor = monitor.clone();
+                async move { monitor.check(source.as_ref(), chrono::Utc::now()).await }
+            },
+        );
+    }
+
     // Build our application with routes
-    let app = create_app(state);
+    let app = create_app(state)?;
 
     // Run the server
     let addr = config.server_address.parse::<std::net::SocketAddr>()?;
     tracing::info!("Server running on {}", config.server_address);
     
     let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    let drain_timeout = std::time::Duration::from_secs(config.order_drain_seconds);
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async move {
+            shutdown_signal().await;
+            orders.drain(drain_timeout).await;
+        })
+        .await?;
+
+    // Sends the spans still batched
+    if let Some(provider) = tracer_provider {
+        if let Err(e) = provider.shutdown() {
+            eprintln!("Could not flush traces on shutdown: {}", e);
+        }
+    }
 
     Ok(())
 }
 
-fn create_app(state: AppState) -> Router {
-    // Public routes (no authentication required)
-    let public_routes = Router::new()
-        .route("/health", get(health_check))
-        .route("/api/v1/auth/register", post(handlers::auth::register))
-        .route("/api/v1/auth/login", post(handlers::auth::login))
-        .route("/api/v1/auth/google", post(handlers::auth::google_login));
-
-    // Protected routes (authentication required)
-    let protected_routes = Router::new()
-        .route("/api/v1/auth/me", get(handlers::auth::me))
-        .route("/api/v1/users", get(handlers::users::list_users))
-        .route("/api/v1/users/:id", get(handlers::users::get_user))
-        .route("/api/v1/subscriptions", get(handlers::subscriptions::list_subscriptions))
-        .route("/api/v1/subscriptions", post(handlers::subscriptions::create_subscription))
-        .route("/api/v1/brokers", get(handlers::brokers::list_brokers))
-        .route("/api/v1/brokers", post(handlers::brokers::create_broker))
-        .route("/api/v1/brokers/:id/test", post(handlers::brokers::test_connection))
-        .route("/api/v1/robots", get(handlers::robots::list_robots))
-        .route("/api/v1/robots", post(handlers::robots::create_robot))
-        .route("/api/v1/robots/:id/start", post(handlers::robots::start_robot))
-        .route("/api/v1/robots/:id/stop", post(handlers::robots::stop_robot))
-        .route("/api/v1/trades", get(handlers::trades::list_trades))
-        .route("/api/v1/trades/statistics", get(handlers::trades::get_statistics))
-        .route("/api/v1/dashboard", get(handlers::dashboard::get_dashboard))
-        .layer(middleware::from_fn_with_state(state.clone(), app_middleware::auth_middleware));
-
-    // Admin routes (admin authentication required)
-    let admin_routes = Router::new()
-        .route("/api/v1/admin/users", get(handlers::admin::list_all_users))
-        .route("/api/v1/admin/stats", get(handlers::admin::get_system_stats))
-        .layer(middleware::from_fn(app_middleware::admin_middleware))
-        .layer(middleware::from_fn_with_state(state.clone(), app_middleware::auth_middleware));
-
-    // Combine all routes
-    Router::new()
-        .merge(public_routes)
-        .merge(protected_routes)
-        .merge(admin_routes)
-        .layer(
-            ServiceBuilder::new()
-                .layer(TraceLayer::new_for_http())
-                .layer(CorsLayer::permissive())
-        )
-        .with_state(state)
-}
+// Ctrl-C, or the SIGTERM a deploy sends
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("Could not listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("Could not listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
 
-async fn health_check() -> Result<Json<Value>, StatusCode> {
-    Ok(Json(json!({
-        "status": "healthy",
-        "timestamp": chrono::Utc::now().to_rfc3339()
-    })))
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+    tracing::info!("Shutdown signal received");
 }
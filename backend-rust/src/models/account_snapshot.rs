@@ -0,0 +1,180 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+use crate::errors::{DbOp, Result};
+use super::{AccountInfo, BrokerConnection};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SnapshotGranularity {
+    #[default]
+    Hour,
+    Day,
+}
+
+impl SnapshotGranularity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SnapshotGranularity::Hour => "hour",
+            SnapshotGranularity::Day => "day",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema, FromRow)]
+pub struct AccountSnapshot {
+    pub id: Uuid,
+    pub connection_id: Uuid,
+    pub user_id: Uuid,
+    pub granularity: String,
+    #[serde(serialize_with = "crate::money::serialize_amount")]
+    pub balance: f64,
+    #[serde(serialize_with = "crate::money::serialize_amount")]
+    pub equity: f64,
+    #[serde(serialize_with = "crate::money::serialize_amount")]
+    pub margin: f64,
+    #[serde(serialize_with = "crate::money::serialize_amount")]
+    pub free_margin: f64,
+    pub currency: String,
+    pub captured_at: DateTime<Utc>,
+}
+
+const SNAPSHOT_COLUMNS: &str =
+    "id, connection_id, user_id, granularity, balance, equity, margin, free_margin, currency, captured_at";
+
+impl AccountSnapshot {
+    pub fn new(
+        connection: &BrokerConnection,
+        info: &AccountInfo,
+        granularity: SnapshotGranularity,
+        captured_at: DateTime<Utc>,
+    ) -> Self {
+        AccountSnapshot {
+            id: Uuid::new_v4(),
+            connection_id: connection.id,
+            user_id: connection.user_id,
+            granularity: granularity.as_str().to_string(),
+            balance: info.balance,
+            equity: info.equity,
+            margin: info.margin,
+            free_margin: info.free_margin,
+            currency: info.currency.clone(),
+            captured_at,
+        }
+    }
+
+    // A second capture in the same period replaces the first
+    pub async fn upsert(pool: &PgPool, snapshot: &AccountSnapshot) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO account_snapshots (id, connection_id, user_id, granularity, balance, equity, margin, free_margin, currency, captured_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            ON CONFLICT (connection_id, granularity, captured_at) DO UPDATE SET
+                balance = EXCLUDED.balance,
+                equity = EXCLUDED.equity,
+                margin = EXCLUDED.margin,
+                free_margin = EXCLUDED.free_margin,
+                currency = EXCLUDED.currency
+            "#,
+        )
+        .bind(snapshot.id)
+        .bind(snapshot.connection_id)
+        .bind(snapshot.user_id)
+        .bind(&snapshot.granularity)
+        .bind(snapshot.balance)
+        .bind(snapshot.equity)
+        .bind(snapshot.margin)
+        .bind(snapshot.free_margin)
+        .bind(&snapshot.currency)
+        .bind(snapshot.captured_at)
+        .execute(pool)
+        .await
+        .db_op("account_snapshots.upsert")?;
+
+        Ok(())
+    }
+
+    pub async fn find_range(
+        pool: &PgPool,
+        connection_id: Uuid,
+        user_id: Uuid,
+        granularity: SnapshotGranularity,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<AccountSnapshot>> {
+        sqlx::query_as::<_, AccountSnapshot>(&format!(
+            "SELECT {} FROM account_snapshots WHERE connection_id = $1 AND user_id = $2 AND granularity = $3 AND captured_at >= $4 AND captured_at <= $5 ORDER BY captured_at",
+            SNAPSHOT_COLUMNS
+        ))
+        .bind(connection_id)
+        .bind(user_id)
+        .bind(granularity.as_str())
+        .bind(from)
+        .bind(to)
+        .fetch_all(pool)
+        .await
+        .db_op("account_snapshots.find_range")
+    }
+
+    pub async fn find_latest(pool: &PgPool, connection_id: Uuid) -> Result<Option<AccountSnapshot>> {
+        sqlx::query_as::<_, AccountSnapshot>(&format!(
+            "SELECT {} FROM account_snapshots WHERE connection_id = $1 ORDER BY captured_at DESC LIMIT 1",
+            SNAPSHOT_COLUMNS
+        ))
+        .bind(connection_id)
+        .fetch_optional(pool)
+        .await
+        .db_op("account_snapshots.find_latest")
+    }
+
+    pub async fn find_hourly_before(pool: &PgPool, before: DateTime<Utc>) -> Result<Vec<AccountSnapshot>> {
+        sqlx::query_as::<_, AccountSnapshot>(&format!(
+            "SELECT {} FROM account_snapshots WHERE granularity = 'hour' AND captured_at < $1 ORDER BY connection_id, captured_at",
+            SNAPSHOT_COLUMNS
+        ))
+        .bind(before)
+        .fetch_all(pool)
+        .await
+        .db_op("account_snapshots.find_hourly_before")
+    }
+
+    // Writes the daily rows and drops the hourly rows they summarize in one transaction
+    pub async fn replace_with_daily(pool: &PgPool, daily: &[AccountSnapshot], hourly_ids: &[Uuid]) -> Result<()> {
+        let mut tx = pool.begin().await.db_op("account_snapshots.replace_with_daily")?;
+
+        for snapshot in daily {
+            sqlx::query(
+                r#"
+                INSERT INTO account_snapshots (id, connection_id, user_id, granularity, balance, equity, margin, free_margin, currency, captured_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+                ON CONFLICT (connection_id, granularity, captured_at) DO NOTHING
+                "#,
+            )
+            .bind(snapshot.id)
+            .bind(snapshot.connection_id)
+            .bind(snapshot.user_id)
+            .bind(&snapshot.granularity)
+            .bind(snapshot.balance)
+            .bind(snapshot.equity)
+            .bind(snapshot.margin)
+            .bind(snapshot.free_margin)
+            .bind(&snapshot.currency)
+            .bind(snapshot.captured_at)
+            .execute(&mut *tx)
+            .await
+            .db_op("account_snapshots.replace_with_daily")?;
+        }
+
+        sqlx::query("DELETE FROM account_snapshots WHERE id = ANY($1)")
+            .bind(hourly_ids)
+            .execute(&mut *tx)
+            .await
+            .db_op("account_snapshots.replace_with_daily")?;
+
+        tx.commit().await.db_op("account_snapshots.replace_with_daily")?;
+        Ok(())
+    }
+}
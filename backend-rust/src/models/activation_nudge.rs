@@ -0,0 +1,144 @@
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+use crate::errors::{DbOp, Result};
+
+// Idle situations, as SQL over trading_robots r / users u. $1 is the robot idle cutoff and $2
+// the cutoff for users without an active robot.
+const NEVER_STARTED: &str = "COALESCE(r.status, 'inactive') <> 'active' AND r.last_signal_at IS NULL \
+     AND r.created_at < $1 AND NOT EXISTS (SELECT 1 FROM trades t WHERE t.robot_id = r.id)";
+const SILENT: &str = "r.status = 'active' AND r.updated_at < $1 AND (r.last_signal_at IS NULL OR r.last_signal_at < $1) \
+     AND NOT EXISTS (SELECT 1 FROM trades t WHERE t.robot_id = r.id AND t.opened_at >= $1)";
+// A robot stopped or edited since the cutoff counts as recent activity
+const WITHOUT_ACTIVE_ROBOTS: &str = "u.created_at < $2 AND COALESCE(u.is_active, TRUE) = TRUE \
+     AND NOT EXISTS (SELECT 1 FROM trading_robots r WHERE r.user_id = u.id AND (r.status = 'active' OR r.updated_at >= $2))";
+
+// One idle situation of a user who can still be nudged
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct IdleCandidate {
+    pub user_id: Uuid,
+    pub email: String,
+    pub unsubscribe_token: Uuid,
+    pub kind: String,
+    pub robot_id: Option<Uuid>,
+    pub robot_name: Option<String>,
+    pub idle_since: DateTime<Utc>,
+    pub nudges_this_month: i64,
+    pub last_nudged_at: Option<DateTime<Utc>>,
+}
+
+// Counts behind the admin "activation risk" metric, nudged or not
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema, FromRow)]
+pub struct ActivationRisk {
+    pub never_started_robots: i64,
+    pub silent_robots: i64,
+    pub users_without_active_robots: i64,
+}
+
+pub struct ActivationNudge;
+
+impl ActivationNudge {
+    // Situations not nudged yet, for users who still accept onboarding emails, oldest first
+    pub async fn find_candidates(
+        pool: &PgPool,
+        robot_cutoff: DateTime<Utc>,
+        user_cutoff: DateTime<Utc>,
+        month_start: DateTime<Utc>,
+    ) -> Result<Vec<IdleCandidate>> {
+        let sql = format!(
+            r#"
+            WITH idle AS (
+                SELECT r.user_id, 'robot_never_started' AS kind, r.id AS robot_id, r.name AS robot_name, r.created_at AS idle_since
+                FROM trading_robots r WHERE {never_started}
+                UNION ALL
+                SELECT r.user_id, 'robot_silent', r.id, r.name, COALESCE(r.last_signal_at, r.updated_at)
+                FROM trading_robots r WHERE {silent}
+                UNION ALL
+                SELECT u.id, 'no_active_robots', NULL, NULL, u.created_at
+                FROM users u WHERE {without_active}
+            )
+            SELECT
+                i.user_id,
+                u.email,
+                u.unsubscribe_token,
+                i.kind,
+                i.robot_id,
+                i.robot_name,
+                i.idle_since,
+                (SELECT COUNT(*) FROM activation_nudges n WHERE n.user_id = i.user_id AND n.sent_at >= $3) AS nudges_this_month,
+                (SELECT MAX(n.sent_at) FROM activation_nudges n WHERE n.user_id = i.user_id) AS last_nudged_at
+            FROM idle i
+            JOIN users u ON u.id = i.user_id
+            WHERE u.onboarding_emails = TRUE
+              AND COALESCE(u.is_active, TRUE) = TRUE
+              AND NOT EXISTS (
+                  SELECT 1 FROM activation_nudges n
+                  WHERE n.user_id = i.user_id AND n.kind = i.kind AND n.robot_id IS NOT DISTINCT FROM i.robot_id
+              )
+            ORDER BY i.user_id, i.idle_since
+            "#,
+            never_started = NEVER_STARTED,
+            silent = SILENT,
+            without_active = WITHOUT_ACTIVE_ROBOTS,
+        );
+        sqlx::query_as::<_, IdleCandidate>(&sql)
+            .bind(robot_cutoff)
+            .bind(user_cutoff)
+            .bind(month_start)
+            .fetch_all(pool)
+            .await
+            .db_op("activation_nudges.find_candidates")
+    }
+
+    pub async fn risk(pool: &PgPool, robot_cutoff: DateTime<Utc>, user_cutoff: DateTime<Utc>) -> Result<ActivationRisk> {
+        let sql = format!(
+            r#"
+            SELECT
+                (SELECT COUNT(*) FROM trading_robots r WHERE {never_started}) AS never_started_robots,
+                (SELECT COUNT(*) FROM trading_robots r WHERE {silent}) AS silent_robots,
+                (SELECT COUNT(*) FROM users u WHERE {without_active}) AS users_without_active_robots
+            "#,
+            never_started = NEVER_STARTED,
+            silent = SILENT,
+            without_active = WITHOUT_ACTIVE_ROBOTS,
+        );
+        sqlx::query_as::<_, ActivationRisk>(&sql)
+            .bind(robot_cutoff)
+            .bind(user_cutoff)
+            .fetch_one(pool)
+            .await
+            .db_op("activation_nudges.risk")
+    }
+
+    // Returns false when the situation was already nudged, e.g. by another instance
+    pub async fn claim(pool: &PgPool, candidate: &IdleCandidate, now: DateTime<Utc>) -> Result<bool> {
+        let result = sqlx::query(
+            "INSERT INTO activation_nudges (user_id, kind, robot_id, sent_at) VALUES ($1, $2, $3, $4) ON CONFLICT DO NOTHING",
+        )
+        .bind(candidate.user_id)
+        .bind(&candidate.kind)
+        .bind(candidate.robot_id)
+        .bind(now)
+        .execute(pool)
+        .await
+        .db_op("activation_nudges.claim")?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    // Undoes a claim whose email could not be sent so the next run retries it
+    pub async fn release(pool: &PgPool, candidate: &IdleCandidate) -> Result<()> {
+        sqlx::query("DELETE FROM activation_nudges WHERE user_id = $1 AND kind = $2 AND robot_id IS NOT DISTINCT FROM $3")
+            .bind(candidate.user_id)
+            .bind(&candidate.kind)
+            .bind(candidate.robot_id)
+            .execute(pool)
+            .await
+            .db_op("activation_nudges.release")?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,185 @@
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use schemars::JsonSchema;
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::errors::{AppError, DbOp, Result};
+use crate::services::broker_throttle::BrokerRateLimit;
+
+pub const STATS_EXPORT_SETTING: &str = "stats_export";
+pub const MAINTENANCE_SETTING: &str = "maintenance";
+pub const RUNTIME_SETTING: &str = "runtime";
+pub const BROKER_MAINTENANCE_SETTING: &str = "broker_maintenance";
+
+#[derive(Debug, Clone, FromRow)]
+pub struct AdminSetting {
+    pub key: String,
+    pub value: serde_json::Value,
+    pub updated_by: Option<Uuid>,
+    pub updated_at: DateTime<Utc>,
+}
+
+// Where finished days of platform stats are delivered each night; no URL turns delivery off
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema, Validate)]
+pub struct StatsExportSettings {
+    #[validate(url)]
+    pub webhook_url: Option<String>,
+}
+
+// Shown on the public status page while set; an `until` in the past hides it again
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema, Validate)]
+pub struct MaintenanceNotice {
+    #[validate(length(min = 1, max = 500))]
+    pub message: Option<String>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl MaintenanceNotice {
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.message.is_some() && self.until.is_none_or(|until| until > now)
+    }
+}
+
+// Order placement through one broker integration is suspended while a scope is active; robots
+// trading through it are paused and resume once it is lifted or its `until` has passed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct BrokerMaintenanceScope {
+    // Upper-case, e.g. "MT5"
+    pub broker_type: String,
+    pub message: String,
+    pub until: Option<DateTime<Utc>>,
+    pub since: DateTime<Utc>,
+}
+
+impl BrokerMaintenanceScope {
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.until.is_none_or(|until| until > now)
+    }
+}
+
+// Every broker integration under maintenance, one scope per broker_type
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct BrokerMaintenance {
+    pub scopes: Vec<BrokerMaintenanceScope>,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema, Validate)]
+pub struct BrokerMaintenanceRequest {
+    #[validate(length(min = 1, max = 500))]
+    pub message: String,
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl BrokerMaintenance {
+    pub fn normalize(broker_type: &str) -> String {
+        broker_type.trim().to_uppercase()
+    }
+
+    // Replaces the scope for the broker type; `since` is kept when an active scope is only edited
+    pub fn set(&mut self, broker_type: &str, request: BrokerMaintenanceRequest, now: DateTime<Utc>) {
+        let broker_type = Self::normalize(broker_type);
+        let since = self
+            .scopes
+            .iter()
+            .find(|s| s.broker_type == broker_type && s.is_active(now))
+            .map(|s| s.since)
+            .unwrap_or(now);
+        self.scopes.retain(|s| s.broker_type != broker_type);
+        self.scopes.push(BrokerMaintenanceScope { broker_type, message: request.message, until: request.until, since });
+        self.scopes.sort_by(|a, b| a.broker_type.cmp(&b.broker_type));
+    }
+
+    // False if the broker type had no scope
+    pub fn lift(&mut self, broker_type: &str) -> bool {
+        let broker_type = Self::normalize(broker_type);
+        let before = self.scopes.len();
+        self.scopes.retain(|s| s.broker_type != broker_type);
+        self.scopes.len() < before
+    }
+
+    pub fn active(&self, now: DateTime<Utc>) -> Vec<BrokerMaintenanceScope> {
+        self.scopes.iter().filter(|s| s.is_active(now)).cloned().collect()
+    }
+}
+
+// Operational toggles every instance picks up without a restart; anything unset keeps the env value
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RuntimeSettings {
+    // An EnvFilter directive such as "trading_saas_backend=info"
+    pub log_level: Option<String>,
+    #[serde(default)]
+    pub broker_rate_limits: HashMap<String, BrokerRateLimit>,
+    // Seconds between runs, by scheduler job name
+    #[serde(default)]
+    pub job_intervals: HashMap<String, u64>,
+}
+
+// Omitted fields are left alone, a null entry drops its override and an empty log level restores the
+// default. Secrets such as the JWT secret or database URL are not runtime settings and are rejected.
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RuntimeSettingsPatch {
+    pub log_level: Option<String>,
+    pub broker_rate_limits: Option<HashMap<String, Option<BrokerRateLimit>>>,
+    pub job_intervals: Option<HashMap<String, Option<u64>>>,
+}
+
+impl RuntimeSettings {
+    pub fn apply(&mut self, patch: RuntimeSettingsPatch) {
+        if let Some(log_level) = patch.log_level {
+            self.log_level = Some(log_level).filter(|level| !level.trim().is_empty());
+        }
+        for (broker_type, limit) in patch.broker_rate_limits.unwrap_or_default() {
+            let broker_type = broker_type.to_lowercase();
+            match limit {
+                Some(limit) => self.broker_rate_limits.insert(broker_type, limit),
+                None => self.broker_rate_limits.remove(&broker_type),
+            };
+        }
+        for (job, seconds) in patch.job_intervals.unwrap_or_default() {
+            match seconds {
+                Some(seconds) => self.job_intervals.insert(job, seconds),
+                None => self.job_intervals.remove(&job),
+            };
+        }
+    }
+}
+
+impl AdminSetting {
+    pub async fn find(pool: &PgPool, key: &str) -> Result<Option<AdminSetting>> {
+        sqlx::query_as::<_, AdminSetting>("SELECT key, value, updated_by, updated_at FROM admin_settings WHERE key = $1")
+            .bind(key)
+            .fetch_optional(pool)
+            .await
+            .db_op("admin_settings.find")
+    }
+
+    // The stored document, or the type's default when the key was never set
+    pub async fn get<T: DeserializeOwned + Default>(pool: &PgPool, key: &str) -> Result<T> {
+        match Self::find(pool, key).await? {
+            Some(setting) => serde_json::from_value(setting.value)
+                .map_err(|e| AppError::Internal(anyhow::anyhow!("Unreadable admin setting {}: {}", key, e))),
+            None => Ok(T::default()),
+        }
+    }
+
+    pub async fn put<T: Serialize>(pool: &PgPool, key: &str, value: &T, updated_by: Uuid) -> Result<()> {
+        let value = serde_json::to_value(value).map_err(|e| AppError::Internal(e.into()))?;
+        sqlx::query(
+            r#"
+            INSERT INTO admin_settings (key, value, updated_by, updated_at) VALUES ($1, $2, $3, NOW())
+            ON CONFLICT (key) DO UPDATE SET value = EXCLUDED.value, updated_by = EXCLUDED.updated_by, updated_at = NOW()
+            "#,
+        )
+        .bind(key)
+        .bind(value)
+        .bind(updated_by)
+        .execute(pool)
+        .await
+        .db_op("admin_settings.put")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,420 @@
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use sqlx::{FromRow, PgPool, Postgres, QueryBuilder};
+use uuid::Uuid;
+
+use crate::errors::{AppError, DbOp, Result};
+use super::{Role, UserResponse};
+
+// Every column of the admin user list, in CSV order
+pub const ADMIN_USER_COLUMNS: [&str; 15] = [
+    "id",
+    "email",
+    "is_active",
+    "is_superuser",
+    "role",
+    "subscription_plan",
+    "robot_count",
+    "last_login_at",
+    "created_at",
+    "updated_at",
+    "full_name",
+    "avatar_url",
+    "timezone",
+    "locale",
+    "deleted_at",
+];
+
+const SELECT: &str = r#"
+    SELECT u.id, u.email, u.is_active, u.is_superuser, u.role, u.subscription_plan, u.full_name, u.avatar_url, u.timezone, u.locale,
+           r.robot_count, u.last_login_at, u.created_at, u.updated_at, u.deleted_at
+    FROM users u
+    CROSS JOIN LATERAL (SELECT COUNT(*) AS robot_count FROM trading_robots tr WHERE tr.user_id = u.id AND tr.deleted_at IS NULL) r
+    WHERE TRUE"#;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AdminUserSort {
+    CreatedAt,
+    Email,
+    Plan,
+    LastLogin,
+    RobotCount,
+}
+
+impl AdminUserSort {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "created_at" => Ok(AdminUserSort::CreatedAt),
+            "email" => Ok(AdminUserSort::Email),
+            "plan" => Ok(AdminUserSort::Plan),
+            "last_login" => Ok(AdminUserSort::LastLogin),
+            "robot_count" => Ok(AdminUserSort::RobotCount),
+            other => Err(AppError::Validation(format!(
+                "Unknown sort '{}', expected created_at, email, plan, last_login or robot_count",
+                other
+            ))),
+        }
+    }
+
+    fn column(self) -> &'static str {
+        match self {
+            AdminUserSort::CreatedAt => "u.created_at",
+            AdminUserSort::Email => "u.email",
+            AdminUserSort::Plan => "u.subscription_plan",
+            AdminUserSort::LastLogin => "u.last_login_at",
+            AdminUserSort::RobotCount => "r.robot_count",
+        }
+    }
+
+    // Newest and busiest first, names alphabetically
+    pub fn default_descending(self) -> bool {
+        !matches!(self, AdminUserSort::Email | AdminUserSort::Plan)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AdminUserFilter {
+    pub plan: Option<String>,
+    pub active: Option<bool>,
+    // An ILIKE pattern with its wildcards already escaped
+    pub email: Option<String>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    // Deleted accounts are left out unless asked for
+    pub include_deleted: bool,
+}
+
+impl AdminUserFilter {
+    pub fn validate(&self) -> Result<()> {
+        if let (Some(after), Some(before)) = (self.created_after, self.created_before) {
+            if after >= before {
+                return Err(AppError::Validation("created_after must be before created_before".to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn push_conditions<'a>(&'a self, builder: &mut QueryBuilder<'a, Postgres>) {
+        if !self.include_deleted {
+            builder.push(" AND u.deleted_at IS NULL");
+        }
+        if let Some(plan) = &self.plan {
+            builder.push(" AND u.subscription_plan = ").push_bind(plan);
+        }
+        if let Some(active) = self.active {
+            builder.push(" AND u.is_active = ").push_bind(active);
+        }
+        if let Some(email) = &self.email {
+            builder.push(" AND u.email ILIKE ").push_bind(email);
+        }
+        if let Some(after) = self.created_after {
+            builder.push(" AND u.created_at >= ").push_bind(after);
+        }
+        if let Some(before) = self.created_before {
+            builder.push(" AND u.created_at < ").push_bind(before);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdminUserOrder {
+    pub sort: AdminUserSort,
+    pub descending: bool,
+}
+
+impl Default for AdminUserOrder {
+    fn default() -> Self {
+        AdminUserOrder { sort: AdminUserSort::CreatedAt, descending: true }
+    }
+}
+
+impl AdminUserOrder {
+    // `order` is asc or desc and defaults to the sort key's natural direction
+    pub fn parse(sort: Option<&str>, order: Option<&str>) -> Result<Self> {
+        let sort = sort.map(AdminUserSort::parse).transpose()?.unwrap_or(AdminUserSort::CreatedAt);
+        let descending = match order {
+            None => sort.default_descending(),
+            Some("asc") => false,
+            Some("desc") => true,
+            Some(other) => return Err(AppError::Validation(format!("Unknown order '{}', expected asc or desc", other))),
+        };
+        Ok(AdminUserOrder { sort, descending })
+    }
+
+    // Users who never logged in go last either way; the id keeps pages stable across ties
+    pub fn push(&self, builder: &mut QueryBuilder<'_, Postgres>) {
+        let direction = if self.descending { "DESC" } else { "ASC" };
+        builder.push(format!(" ORDER BY {} {} NULLS LAST, u.id {}", self.sort.column(), direction, direction));
+    }
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema, FromRow)]
+pub struct AdminUserRow {
+    pub id: Uuid,
+    pub email: String,
+    pub is_active: bool,
+    pub is_superuser: bool,
+    pub role: String,
+    pub subscription_plan: String,
+    pub full_name: Option<String>,
+    pub avatar_url: Option<String>,
+    pub timezone: Option<String>,
+    pub locale: Option<String>,
+    pub robot_count: i64,
+    pub last_login_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+// The canonical user plus what only admins see
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct AdminUserResponse {
+    #[serde(flatten)]
+    pub user: UserResponse,
+    pub robot_count: i64,
+    pub last_login_at: Option<DateTime<Utc>>,
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+impl From<AdminUserRow> for AdminUserResponse {
+    fn from(row: AdminUserRow) -> Self {
+        AdminUserResponse {
+            user: UserResponse {
+                role: Role::parse(&row.role).unwrap_or(Role::User),
+                id: row.id,
+                email: row.email,
+                is_active: row.is_active,
+                is_superuser: row.is_superuser,
+                subscription_plan: row.subscription_plan,
+                full_name: row.full_name,
+                avatar_url: row.avatar_url,
+                timezone: row.timezone,
+                locale: row.locale,
+                created_at: row.created_at,
+                updated_at: row.updated_at,
+            },
+            robot_count: row.robot_count,
+            last_login_at: row.last_login_at,
+            deleted_at: row.deleted_at,
+        }
+    }
+}
+
+impl AdminUserRow {
+    pub async fn page(
+        pool: &PgPool,
+        filter: &AdminUserFilter,
+        order: AdminUserOrder,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<AdminUserRow>> {
+        let mut builder = Self::page_query(filter, order);
+        builder.push(" LIMIT ").push_bind(limit).push(" OFFSET ").push_bind(offset);
+        builder.build_query_as::<AdminUserRow>().fetch_all(pool).await.db_op("users.admin_page")
+    }
+
+    pub async fn count(pool: &PgPool, filter: &AdminUserFilter) -> Result<i64> {
+        let mut builder = QueryBuilder::<Postgres>::new("SELECT COUNT(*) FROM users u WHERE TRUE");
+        filter.push_conditions(&mut builder);
+        builder.build_query_scalar().fetch_one(pool).await.db_op("users.admin_count")
+    }
+
+    fn page_query(filter: &AdminUserFilter, order: AdminUserOrder) -> QueryBuilder<'_, Postgres> {
+        let mut builder = QueryBuilder::<Postgres>::new(SELECT);
+        filter.push_conditions(&mut builder);
+        order.push(&mut builder);
+        builder
+    }
+
+    fn field(&self, column: &str) -> String {
+        let time = |t: &DateTime<Utc>| t.to_rfc3339();
+        match column {
+            "id" => self.id.to_string(),
+            "email" => self.email.clone(),
+            "is_active" => self.is_active.to_string(),
+            "is_superuser" => self.is_superuser.to_string(),
+            "role" => self.role.clone(),
+            "subscription_plan" => self.subscription_plan.clone(),
+            "robot_count" => self.robot_count.to_string(),
+            "last_login_at" => self.last_login_at.as_ref().map(time).unwrap_or_default(),
+            "created_at" => time(&self.created_at),
+            "updated_at" => time(&self.updated_at),
+            "full_name" => self.full_name.clone().unwrap_or_default(),
+            "avatar_url" => self.avatar_url.clone().unwrap_or_default(),
+            "timezone" => self.timezone.clone().unwrap_or_default(),
+            "locale" => self.locale.clone().unwrap_or_default(),
+            "deleted_at" => self.deleted_at.as_ref().map(time).unwrap_or_default(),
+            _ => String::new(),
+        }
+    }
+
+    pub fn csv_row(&self, columns: &[&str]) -> String {
+        let fields: Vec<String> = columns.iter().map(|c| csv_field(&self.field(c))).collect();
+        format!("{}\n", fields.join(","))
+    }
+}
+
+// Keeps the requested columns in the list's own order; none means all of them
+pub fn admin_user_columns(requested: Option<&str>) -> Result<Vec<&'static str>> {
+    let Some(requested) = requested else {
+        return Ok(ADMIN_USER_COLUMNS.to_vec());
+    };
+    let requested: Vec<&str> = requested.split(',').map(str::trim).filter(|c| !c.is_empty()).collect();
+    if let Some(unknown) = requested.iter().find(|c| !ADMIN_USER_COLUMNS.contains(c)) {
+        return Err(AppError::Validation(format!("Unknown column '{}'", unknown)));
+    }
+    if requested.is_empty() {
+        return Err(AppError::Validation("Select at least one column".to_string()));
+    }
+    Ok(ADMIN_USER_COLUMNS.iter().copied().filter(|c| requested.contains(c)).collect())
+}
+
+pub fn admin_user_csv_header(columns: &[&str]) -> String {
+    format!("{}\n", columns.join(","))
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn sql_for(filter: &AdminUserFilter, order: AdminUserOrder) -> String {
+        AdminUserRow::page_query(filter, order).sql().to_string()
+    }
+
+    fn order_clause(sort: &str, order: Option<&str>) -> String {
+        let sql = sql_for(&AdminUserFilter::default(), AdminUserOrder::parse(Some(sort), order).unwrap());
+        sql[sql.find(" ORDER BY").unwrap()..].to_string()
+    }
+
+    #[test]
+    fn test_each_sort_key_orders_in_sql() {
+        assert_eq!(order_clause("created_at", None), " ORDER BY u.created_at DESC NULLS LAST, u.id DESC");
+        assert_eq!(order_clause("email", None), " ORDER BY u.email ASC NULLS LAST, u.id ASC");
+        assert_eq!(order_clause("plan", None), " ORDER BY u.subscription_plan ASC NULLS LAST, u.id ASC");
+        assert_eq!(order_clause("last_login", None), " ORDER BY u.last_login_at DESC NULLS LAST, u.id DESC");
+        assert_eq!(order_clause("robot_count", None), " ORDER BY r.robot_count DESC NULLS LAST, u.id DESC");
+        assert_eq!(order_clause("robot_count", Some("asc")), " ORDER BY r.robot_count ASC NULLS LAST, u.id ASC");
+        assert_eq!(order_clause("email", Some("desc")), " ORDER BY u.email DESC NULLS LAST, u.id DESC");
+
+        // robot_count comes from the lateral join, not from loading robots per user
+        let sql = sql_for(&AdminUserFilter::default(), AdminUserOrder::default());
+        assert!(sql.contains("CROSS JOIN LATERAL (SELECT COUNT(*) AS robot_count FROM trading_robots"));
+        assert!(sql.ends_with(" ORDER BY u.created_at DESC NULLS LAST, u.id DESC"));
+    }
+
+    #[test]
+    fn test_unknown_sort_or_order_is_rejected() {
+        assert!(AdminUserOrder::parse(Some("password_hash"), None).is_err());
+        assert!(AdminUserOrder::parse(Some("email"), Some("sideways")).is_err());
+        assert_eq!(AdminUserOrder::parse(None, None).unwrap(), AdminUserOrder::default());
+    }
+
+    #[test]
+    fn test_filters_combine() {
+        let filter = AdminUserFilter { plan: Some("pro".to_string()), active: Some(false), ..Default::default() };
+        let sql = sql_for(&filter, AdminUserOrder::parse(Some("robot_count"), None).unwrap());
+        assert!(sql.contains("WHERE TRUE AND u.deleted_at IS NULL AND u.subscription_plan = $1 AND u.is_active = $2 ORDER BY r.robot_count DESC"));
+
+        let day = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let signups = AdminUserFilter {
+            email: Some("%ana%".to_string()),
+            created_after: Some(day),
+            created_before: Some(day + chrono::Duration::days(7)),
+            ..Default::default()
+        };
+        let sql = sql_for(&signups, AdminUserOrder::default());
+        assert!(sql.contains("WHERE TRUE AND u.deleted_at IS NULL AND u.email ILIKE $1 AND u.created_at >= $2 AND u.created_at < $3 ORDER BY"));
+        assert!(signups.validate().is_ok());
+        let backwards = AdminUserFilter { created_before: Some(day), created_after: Some(day), ..Default::default() };
+        assert!(matches!(backwards.validate(), Err(AppError::Validation(_))));
+
+        let only_active = AdminUserFilter { active: Some(true), include_deleted: true, ..Default::default() };
+        let sql = sql_for(&only_active, AdminUserOrder::default());
+        assert!(sql.contains("WHERE TRUE AND u.is_active = $1 ORDER BY"));
+        assert!(!sql.contains("subscription_plan ="));
+    }
+
+    fn row() -> AdminUserRow {
+        AdminUserRow {
+            id: Uuid::nil(),
+            email: "a,b@example.com".to_string(),
+            is_active: true,
+            is_superuser: false,
+            role: "user".to_string(),
+            subscription_plan: "pro".to_string(),
+            full_name: Some("Ana Souza".to_string()),
+            avatar_url: None,
+            timezone: Some("Europe/Lisbon".to_string()),
+            locale: None,
+            robot_count: 3,
+            last_login_at: None,
+            created_at: Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap(),
+            updated_at: Utc.with_ymd_and_hms(2024, 2, 3, 4, 5, 6).unwrap(),
+            deleted_at: None,
+        }
+    }
+
+    // Freezes the admin user JSON: the canonical user's fields plus the admin-only ones
+    #[test]
+    fn test_admin_user_response_json() {
+        let response = AdminUserResponse::from(AdminUserRow {
+            last_login_at: Some(Utc.with_ymd_and_hms(2024, 3, 4, 5, 6, 7).unwrap()),
+            ..row()
+        });
+        assert_eq!(
+            serde_json::to_value(&response).unwrap(),
+            serde_json::json!({
+                "id": "00000000-0000-0000-0000-000000000000",
+                "email": "a,b@example.com",
+                "is_active": true,
+                "is_superuser": false,
+                "role": "user",
+                "subscription_plan": "pro",
+                "full_name": "Ana Souza",
+                "avatar_url": null,
+                "timezone": "Europe/Lisbon",
+                "locale": null,
+                "created_at": "2024-01-02T03:04:05Z",
+                "updated_at": "2024-02-03T04:05:06Z",
+                "robot_count": 3,
+                "last_login_at": "2024-03-04T05:06:07Z",
+                "deleted_at": null
+            })
+        );
+    }
+
+    #[test]
+    fn test_csv_header_and_row_match() {
+        let columns = admin_user_columns(None).unwrap();
+        assert_eq!(
+            admin_user_csv_header(&columns),
+            "id,email,is_active,is_superuser,role,subscription_plan,robot_count,last_login_at,created_at,updated_at,full_name,avatar_url,timezone,locale,deleted_at\n"
+        );
+        assert_eq!(
+            row().csv_row(&columns),
+            "00000000-0000-0000-0000-000000000000,\"a,b@example.com\",true,false,user,pro,3,,2024-01-02T03:04:05+00:00,2024-02-03T04:05:06+00:00,Ana Souza,,Europe/Lisbon,,\n"
+        );
+    }
+
+    #[test]
+    fn test_selected_columns_keep_list_order() {
+        let columns = admin_user_columns(Some("robot_count, email")).unwrap();
+        assert_eq!(columns, vec!["email", "robot_count"]);
+        assert_eq!(admin_user_csv_header(&columns), "email,robot_count\n");
+        assert_eq!(row().csv_row(&columns), "\"a,b@example.com\",3\n");
+
+        assert!(admin_user_columns(Some("email,password_hash")).is_err());
+        assert!(admin_user_columns(Some(" , ")).is_err());
+    }
+}
@@ -0,0 +1,172 @@
+use chrono::{DateTime, Duration, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::errors::{DbOp, Result};
+
+pub const API_KEY_SCOPE_READ: &str = "read";
+pub const API_KEY_SCOPE_TRADE: &str = "trade";
+pub const API_KEY_SCOPES: [&str; 2] = [API_KEY_SCOPE_READ, API_KEY_SCOPE_TRADE];
+// Keys start with this, so they are recognisable in logs and secret scanners
+pub const API_KEY_PREFIX: &str = "tsk_";
+const DISPLAYED_PREFIX_LENGTH: usize = 12;
+// last_used_at is only written again once it is this old, not on every request
+const LAST_USED_RESOLUTION_SECONDS: i64 = 60;
+
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub key_hash: String,
+    pub prefix: String,
+    pub label: String,
+    pub scopes: Vec<String>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Validate)]
+pub struct CreateApiKeyRequest {
+    #[validate(length(min = 1, max = 100))]
+    pub label: String,
+    // read | trade; a trade key can read too. Defaults to read.
+    #[serde(default)]
+    pub scopes: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ApiKeyResponse {
+    pub id: Uuid,
+    pub prefix: String,
+    pub label: String,
+    pub scopes: Vec<String>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+// The only time the key itself is returned
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct CreatedApiKeyResponse {
+    pub key: String,
+    #[serde(flatten)]
+    pub api_key: ApiKeyResponse,
+}
+
+const COLUMNS: &str = "id, user_id, key_hash, prefix, label, scopes, last_used_at, revoked_at, created_at";
+
+impl ApiKey {
+    // The key to hand out, and the row that stores only its hash
+    pub fn generate(user_id: Uuid, label: String, scopes: Vec<String>, now: DateTime<Utc>) -> (String, ApiKey) {
+        let key = format!("{}{}{}", API_KEY_PREFIX, Uuid::new_v4().simple(), Uuid::new_v4().simple());
+        let api_key = ApiKey {
+            id: Uuid::new_v4(),
+            user_id,
+            key_hash: Self::hash_key(&key),
+            prefix: key[..DISPLAYED_PREFIX_LENGTH].to_string(),
+            label,
+            scopes,
+            last_used_at: None,
+            revoked_at: None,
+            created_at: now,
+        };
+        (key, api_key)
+    }
+
+    pub fn hash_key(key: &str) -> String {
+        hex::encode(Sha256::digest(key.as_bytes()))
+    }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope)
+    }
+
+    pub fn to_response(&self) -> ApiKeyResponse {
+        ApiKeyResponse {
+            id: self.id,
+            prefix: self.prefix.clone(),
+            label: self.label.clone(),
+            scopes: self.scopes.clone(),
+            last_used_at: self.last_used_at,
+            created_at: self.created_at,
+        }
+    }
+
+    pub async fn create(pool: &PgPool, api_key: &ApiKey) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO api_keys (id, user_id, key_hash, prefix, label, scopes, created_at) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        )
+        .bind(api_key.id)
+        .bind(api_key.user_id)
+        .bind(&api_key.key_hash)
+        .bind(&api_key.prefix)
+        .bind(&api_key.label)
+        .bind(&api_key.scopes)
+        .bind(api_key.created_at)
+        .execute(pool)
+        .await
+        .db_op("api_keys.create")?;
+        Ok(())
+    }
+
+    // Revoked keys are not found
+    pub async fn find_by_key(pool: &PgPool, key: &str) -> Result<Option<ApiKey>> {
+        sqlx::query_as::<_, ApiKey>(&format!("SELECT {} FROM api_keys WHERE key_hash = $1 AND revoked_at IS NULL", COLUMNS))
+            .bind(Self::hash_key(key))
+            .fetch_optional(pool)
+            .await
+            .db_op("api_keys.find_by_key")
+    }
+
+    // The user's keys that still work, newest first
+    pub async fn find_by_user(pool: &PgPool, user_id: Uuid) -> Result<Vec<ApiKey>> {
+        sqlx::query_as::<_, ApiKey>(&format!(
+            "SELECT {} FROM api_keys WHERE user_id = $1 AND revoked_at IS NULL ORDER BY created_at DESC",
+            COLUMNS
+        ))
+        .bind(user_id)
+        .fetch_all(pool)
+        .await
+        .db_op("api_keys.find_by_user")
+    }
+
+    pub async fn revoke(pool: &PgPool, user_id: Uuid, id: Uuid, now: DateTime<Utc>) -> Result<bool> {
+        let result = sqlx::query("UPDATE api_keys SET revoked_at = $3 WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL")
+            .bind(id)
+            .bind(user_id)
+            .bind(now)
+            .execute(pool)
+            .await
+            .db_op("api_keys.revoke")?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn touch(pool: &PgPool, id: Uuid, now: DateTime<Utc>) -> Result<()> {
+        sqlx::query("UPDATE api_keys SET last_used_at = $2 WHERE id = $1 AND (last_used_at IS NULL OR last_used_at < $3)")
+            .bind(id)
+            .bind(now)
+            .bind(now - Duration::seconds(LAST_USED_RESOLUTION_SECONDS))
+            .execute(pool)
+            .await
+            .db_op("api_keys.touch")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_the_hash_and_a_short_prefix_are_kept() {
+        let (key, api_key) = ApiKey::generate(Uuid::new_v4(), "bot".to_string(), vec![API_KEY_SCOPE_READ.to_string()], Utc::now());
+        assert!(key.starts_with(API_KEY_PREFIX));
+        assert_eq!(api_key.key_hash, ApiKey::hash_key(&key));
+        assert!(key.starts_with(&api_key.prefix) && api_key.prefix.len() < key.len() / 4);
+        assert!(!api_key.key_hash.contains(&key[API_KEY_PREFIX.len()..]));
+    }
+}
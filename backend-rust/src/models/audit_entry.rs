@@ -0,0 +1,112 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+use crate::errors::{DbOp, Result};
+
+// Actions taken from the admin-cli binary
+pub const ACTOR_CLI: &str = "cli";
+// Actions users take on their own account through the API
+pub const ACTOR_USER: &str = "user";
+// Admin API actions that need a durable record
+pub const ACTOR_ADMIN: &str = "admin";
+
+#[derive(Debug, Clone, PartialEq, Serialize, FromRow)]
+pub struct AuditEntry {
+    pub id: Uuid,
+    pub actor_type: String,
+    pub actor: String,
+    pub action: String,
+    pub target_type: String,
+    pub target_id: Option<Uuid>,
+    pub details: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+}
+
+// Who an entry is attributed to
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditActor {
+    pub actor_type: &'static str,
+    pub name: String,
+}
+
+impl AuditActor {
+    pub fn cli(operator: &str) -> Self {
+        AuditActor { actor_type: ACTOR_CLI, name: operator.to_string() }
+    }
+
+    // By id, since the email may not outlive the action
+    pub fn user(user_id: Uuid) -> Self {
+        AuditActor { actor_type: ACTOR_USER, name: user_id.to_string() }
+    }
+
+    pub fn admin(admin_id: Uuid) -> Self {
+        AuditActor { actor_type: ACTOR_ADMIN, name: admin_id.to_string() }
+    }
+}
+
+impl AuditEntry {
+    // Also written to the `audit` log target, like the API's admin actions
+    pub async fn record(
+        pool: &PgPool,
+        actor: &AuditActor,
+        action: &str,
+        target_type: &str,
+        target_id: Option<Uuid>,
+        details: serde_json::Value,
+    ) -> Result<AuditEntry> {
+        let entry = sqlx::query_as::<_, AuditEntry>(
+            r#"
+            INSERT INTO audit_log (actor_type, actor, action, target_type, target_id, details)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id, actor_type, actor, action, target_type, target_id, details, created_at
+            "#,
+        )
+        .bind(actor.actor_type)
+        .bind(&actor.name)
+        .bind(action)
+        .bind(target_type)
+        .bind(target_id)
+        .bind(&details)
+        .fetch_one(pool)
+        .await
+        .db_op("audit_log.record")?;
+
+        tracing::info!(
+            target: "audit",
+            "{} {} by {} {} on {} {}: {}",
+            entry.action,
+            entry.id,
+            entry.actor_type,
+            entry.actor,
+            entry.target_type,
+            entry.target_id.map(|id| id.to_string()).unwrap_or_default(),
+            entry.details
+        );
+        Ok(entry)
+    }
+
+    // Oldest first
+    pub async fn find_by_target(pool: &PgPool, target_type: &str, target_id: Uuid) -> Result<Vec<AuditEntry>> {
+        sqlx::query_as::<_, AuditEntry>(
+            "SELECT id, actor_type, actor, action, target_type, target_id, details, created_at FROM audit_log WHERE target_type = $1 AND target_id = $2 ORDER BY created_at, id",
+        )
+        .bind(target_type)
+        .bind(target_id)
+        .fetch_all(pool)
+        .await
+        .db_op("audit_log.find_by_target")
+    }
+
+    // Newest first
+    pub async fn recent(pool: &PgPool, limit: i64) -> Result<Vec<AuditEntry>> {
+        sqlx::query_as::<_, AuditEntry>(
+            "SELECT id, actor_type, actor, action, target_type, target_id, details, created_at FROM audit_log ORDER BY created_at DESC, id DESC LIMIT $1",
+        )
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+        .db_op("audit_log.recent")
+    }
+}
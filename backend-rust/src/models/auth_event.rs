@@ -0,0 +1,193 @@
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool, Postgres, QueryBuilder};
+use uuid::Uuid;
+
+use crate::errors::{AppError, DbOp, Result};
+use super::clean_user_agent;
+
+pub const AUTH_EVENT_LOGIN: &str = "login";
+pub const AUTH_EVENT_GOOGLE_LOGIN: &str = "google_login";
+pub const AUTH_EVENT_GITHUB_LOGIN: &str = "github_login";
+pub const AUTH_EVENT_APPLE_LOGIN: &str = "apple_login";
+pub const AUTH_EVENT_LOGOUT: &str = "logout";
+pub const AUTH_EVENT_LOGOUT_ALL: &str = "logout_all";
+pub const AUTH_EVENT_SESSION_REVOKED: &str = "session_revoked";
+pub const AUTH_EVENT_PASSWORD_CHANGED: &str = "password_changed";
+pub const AUTH_EVENT_PASSWORD_RESET: &str = "password_reset";
+pub const AUTH_EVENT_API_KEY_CREATED: &str = "api_key_created";
+pub const AUTH_EVENT_API_KEY_REVOKED: &str = "api_key_revoked";
+pub const AUTH_EVENT_BROKER_CREDENTIALS_CHANGED: &str = "broker_credentials_changed";
+// A signed-out or outdated token was presented
+pub const AUTH_EVENT_TOKEN_REJECTED: &str = "token_rejected";
+
+pub const AUTH_EVENT_TYPES: [&str; 13] = [
+    AUTH_EVENT_LOGIN,
+    AUTH_EVENT_GOOGLE_LOGIN,
+    AUTH_EVENT_GITHUB_LOGIN,
+    AUTH_EVENT_APPLE_LOGIN,
+    AUTH_EVENT_LOGOUT,
+    AUTH_EVENT_LOGOUT_ALL,
+    AUTH_EVENT_SESSION_REVOKED,
+    AUTH_EVENT_PASSWORD_CHANGED,
+    AUTH_EVENT_PASSWORD_RESET,
+    AUTH_EVENT_API_KEY_CREATED,
+    AUTH_EVENT_API_KEY_REVOKED,
+    AUTH_EVENT_BROKER_CREDENTIALS_CHANGED,
+    AUTH_EVENT_TOKEN_REJECTED,
+];
+
+pub const AUTH_OUTCOME_SUCCESS: &str = "success";
+pub const AUTH_OUTCOME_FAILURE: &str = "failure";
+
+// Why a failed event failed
+pub const AUTH_REASON_UNKNOWN_EMAIL: &str = "unknown_email";
+pub const AUTH_REASON_INVALID_PASSWORD: &str = "invalid_password";
+pub const AUTH_REASON_ACCOUNT_DISABLED: &str = "account_disabled";
+pub const AUTH_REASON_TOKEN_REVOKED: &str = "token_revoked";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema, FromRow)]
+pub struct AuthEvent {
+    pub id: Uuid,
+    // None for a failed sign-in with an address that has no account
+    pub user_id: Option<Uuid>,
+    pub event_type: String,
+    // success or failure
+    pub outcome: String,
+    pub reason: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+const COLUMNS: &str = "id, user_id, event_type, outcome, reason, ip_address, user_agent, created_at";
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AuthEventFilter {
+    pub user_id: Option<Uuid>,
+    pub event_type: Option<String>,
+    pub outcome: Option<String>,
+    pub ip_address: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl AuthEventFilter {
+    // Unknown event types and outcomes are refused rather than matching nothing
+    pub fn validate(&self) -> Result<()> {
+        if let Some(event_type) = &self.event_type {
+            if !AUTH_EVENT_TYPES.contains(&event_type.as_str()) {
+                return Err(AppError::Validation(format!(
+                    "Unknown event_type '{}', expected one of {}",
+                    event_type,
+                    AUTH_EVENT_TYPES.join(", ")
+                )));
+            }
+        }
+        if let Some(outcome) = &self.outcome {
+            if outcome != AUTH_OUTCOME_SUCCESS && outcome != AUTH_OUTCOME_FAILURE {
+                return Err(AppError::Validation(format!("Unknown outcome '{}', expected success or failure", outcome)));
+            }
+        }
+        Ok(())
+    }
+
+    fn push_conditions<'a>(&'a self, builder: &mut QueryBuilder<'a, Postgres>) {
+        if let Some(user_id) = self.user_id {
+            builder.push(" AND user_id = ").push_bind(user_id);
+        }
+        if let Some(event_type) = &self.event_type {
+            builder.push(" AND event_type = ").push_bind(event_type);
+        }
+        if let Some(outcome) = &self.outcome {
+            builder.push(" AND outcome = ").push_bind(outcome);
+        }
+        if let Some(ip_address) = &self.ip_address {
+            builder.push(" AND ip_address = ").push_bind(ip_address);
+        }
+        if let Some(since) = self.since {
+            builder.push(" AND created_at >= ").push_bind(since);
+        }
+        if let Some(until) = self.until {
+            builder.push(" AND created_at < ").push_bind(until);
+        }
+    }
+}
+
+impl AuthEvent {
+    pub fn new(
+        user_id: Option<Uuid>,
+        event_type: &str,
+        outcome: &str,
+        user_agent: Option<&str>,
+        ip_address: Option<String>,
+        now: DateTime<Utc>,
+    ) -> Self {
+        AuthEvent {
+            id: Uuid::new_v4(),
+            user_id,
+            event_type: event_type.to_string(),
+            outcome: outcome.to_string(),
+            reason: None,
+            ip_address,
+            user_agent: clean_user_agent(user_agent),
+            created_at: now,
+        }
+    }
+
+    pub fn with_reason(mut self, reason: &str) -> Self {
+        self.reason = Some(reason.to_string());
+        self
+    }
+
+    pub async fn create(pool: &PgPool, event: &AuthEvent) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO auth_events (id, user_id, event_type, outcome, reason, ip_address, user_agent, created_at) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+        )
+        .bind(event.id)
+        .bind(event.user_id)
+        .bind(&event.event_type)
+        .bind(&event.outcome)
+        .bind(&event.reason)
+        .bind(&event.ip_address)
+        .bind(&event.user_agent)
+        .bind(event.created_at)
+        .execute(pool)
+        .await
+        .db_op("auth_events.create")?;
+        Ok(())
+    }
+
+    // Newest first
+    pub async fn search(pool: &PgPool, filter: &AuthEventFilter, limit: i64, offset: i64) -> Result<Vec<AuthEvent>> {
+        let mut builder = QueryBuilder::<Postgres>::new(format!("SELECT {} FROM auth_events WHERE TRUE", COLUMNS));
+        filter.push_conditions(&mut builder);
+        builder
+            .push(" ORDER BY created_at DESC, id DESC LIMIT ")
+            .push_bind(limit)
+            .push(" OFFSET ")
+            .push_bind(offset);
+        builder
+            .build_query_as::<AuthEvent>()
+            .fetch_all(pool)
+            .await
+            .db_op("auth_events.search")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_refuses_unknown_types_and_outcomes() {
+        let filter = AuthEventFilter { event_type: Some("login".to_string()), outcome: Some("failure".to_string()), ..Default::default() };
+        assert!(filter.validate().is_ok());
+        let filter = AuthEventFilter { event_type: Some("signin".to_string()), ..Default::default() };
+        assert!(matches!(filter.validate(), Err(AppError::Validation(m)) if m.contains("signin")));
+        let filter = AuthEventFilter { outcome: Some("ok".to_string()), ..Default::default() };
+        assert!(filter.validate().is_err());
+    }
+}
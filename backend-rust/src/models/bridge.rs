@@ -0,0 +1,100 @@
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::errors::{DbOp, Result};
+use super::BrokerConnection;
+
+// Shown once; only its hash is kept
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct BridgeTokenResponse {
+    pub connection_id: Uuid,
+    pub token: String,
+}
+
+pub struct BridgeToken;
+
+impl BridgeToken {
+    // Replaces the connection's previous token, if any
+    pub async fn issue(pool: &PgPool, connection_id: Uuid, token_hash: &str) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO broker_bridge_tokens (connection_id, token_hash, created_at) VALUES ($1, $2, NOW())
+            ON CONFLICT (connection_id) DO UPDATE SET token_hash = EXCLUDED.token_hash, created_at = EXCLUDED.created_at
+            "#,
+        )
+        .bind(connection_id)
+        .bind(token_hash)
+        .execute(pool)
+        .await
+        .db_op("broker_bridge_tokens.issue")?;
+
+        Ok(())
+    }
+
+    // The active connection the token was issued for
+    pub async fn find_connection(pool: &PgPool, token_hash: &str) -> Result<Option<BrokerConnection>> {
+        sqlx::query_as::<_, BrokerConnection>(
+            r#"
+            SELECT c.id, c.user_id, c.name, c.broker_type, c.api_key, c.api_secret, c.credentials_key_id, c.needs_credentials,
+                   c.server, c.login, c.is_active, c.is_demo, c.last_test_at, c.last_test_status, c.allow_duplicate, c.created_at, c.updated_at
+            FROM broker_bridge_tokens t
+            JOIN broker_connections c ON c.id = t.connection_id
+            WHERE t.token_hash = $1 AND c.is_active = TRUE
+            "#,
+        )
+        .bind(token_hash)
+        .fetch_optional(pool)
+        .await
+        .db_op("broker_bridge_tokens.find_connection")
+    }
+
+    pub async fn touch(pool: &PgPool, connection_id: Uuid, at: DateTime<Utc>) -> Result<()> {
+        sqlx::query("UPDATE broker_bridge_tokens SET last_event_at = $2 WHERE connection_id = $1")
+            .bind(connection_id)
+            .bind(at)
+            .execute(pool)
+            .await
+            .db_op("broker_bridge_tokens.touch")?;
+
+        Ok(())
+    }
+
+    // Connections whose bridge pushed something since `since`
+    pub async fn pushed_since(pool: &PgPool, since: DateTime<Utc>) -> Result<Vec<Uuid>> {
+        sqlx::query_scalar("SELECT connection_id FROM broker_bridge_tokens WHERE last_event_at >= $1")
+            .bind(since)
+            .fetch_all(pool)
+            .await
+            .db_op("broker_bridge_tokens.pushed_since")
+    }
+
+    // False when the deal was already recorded
+    pub async fn claim_deal(pool: &PgPool, connection_id: Uuid, deal_id: i64, kind: &str) -> Result<bool> {
+        let result = sqlx::query(
+            "INSERT INTO broker_bridge_deals (connection_id, deal_id, kind) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+        )
+        .bind(connection_id)
+        .bind(deal_id)
+        .bind(kind)
+        .execute(pool)
+        .await
+        .db_op("broker_bridge_deals.claim")?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    // Forgets a deal that could not be applied, so a redelivery gets another try
+    pub async fn release_deal(pool: &PgPool, connection_id: Uuid, deal_id: i64) -> Result<()> {
+        sqlx::query("DELETE FROM broker_bridge_deals WHERE connection_id = $1 AND deal_id = $2")
+            .bind(connection_id)
+            .bind(deal_id)
+            .execute(pool)
+            .await
+            .db_op("broker_bridge_deals.release")?;
+
+        Ok(())
+    }
+}
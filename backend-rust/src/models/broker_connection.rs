@@ -1,28 +1,37 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, PgPool};
+use schemars::JsonSchema;
+use sqlx::{FromRow, PgConnection, PgPool, Postgres, Transaction};
 use uuid::Uuid;
 use validator::Validate;
 
+use crate::errors::{DbOp, Result};
+
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct BrokerConnection {
     pub id: Uuid,
     pub user_id: Uuid,
     pub name: String,
     pub broker_type: String,
-    pub api_key: String, // Encrypted
-    pub api_secret: String, // Encrypted
+    // Encrypted with the key named by credentials_key_id; plaintext when that is None
+    pub api_key: String,
+    pub api_secret: String,
+    pub credentials_key_id: Option<String>,
+    // The stored credentials could not be decrypted and have to be entered again
+    pub needs_credentials: bool,
     pub server: Option<String>,
     pub login: Option<String>,
     pub is_active: bool,
     pub is_demo: bool,
     pub last_test_at: Option<DateTime<Utc>>,
     pub last_test_status: Option<String>,
+    // Saved on purpose next to another connection to the same broker account
+    pub allow_duplicate: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Validate)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Validate)]
 pub struct CreateBrokerConnectionRequest {
     #[validate(length(min = 1))]
     pub name: String,
@@ -32,9 +41,25 @@ pub struct CreateBrokerConnectionRequest {
     pub server: Option<String>,
     pub login: Option<String>,
     pub is_demo: bool,
+    // Tests the credentials first and only saves the connection if the broker accepts them
+    #[serde(default)]
+    pub test_on_create: bool,
+    // Saves the connection even when the user already has one for the same account,
+    // e.g. sub-accounts the broker tells apart by comment
+    #[serde(default)]
+    pub allow_duplicate: bool,
+}
+
+// The broker account behind a connection. Broker types and servers are compared trimmed and
+// case-folded, logins trimmed.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BrokerAccountKey {
+    pub broker_type: String,
+    pub server: String,
+    pub login: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct BrokerConnectionResponse {
     pub id: Uuid,
     pub name: String,
@@ -45,17 +70,29 @@ pub struct BrokerConnectionResponse {
     pub is_demo: bool,
     pub last_test_at: Option<DateTime<Utc>>,
     pub last_test_status: Option<String>,
+    pub needs_credentials: bool,
     pub created_at: DateTime<Utc>,
+    // Only set when the connection was tested on creation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub account_info: Option<AccountInfo>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Validate)]
+pub struct UpdateBrokerCredentialsRequest {
+    #[validate(length(min = 1))]
+    pub api_key: String,
+    #[validate(length(min = 1))]
+    pub api_secret: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct TestConnectionResponse {
     pub success: bool,
     pub message: String,
     pub account_info: Option<AccountInfo>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct AccountInfo {
     pub account_number: String,
     pub balance: f64,
@@ -65,6 +102,12 @@ pub struct AccountInfo {
     pub currency: String,
 }
 
+// A connection inserted in a transaction that has not been committed yet
+pub struct PendingBrokerConnection {
+    tx: Transaction<'static, Postgres>,
+    pub connection: BrokerConnection,
+}
+
 impl BrokerConnection {
     pub fn new(
         user_id: Uuid,
@@ -82,39 +125,56 @@ impl BrokerConnection {
             user_id,
             name,
             broker_type,
-            api_key, // Should be encrypted before storing
-            api_secret, // Should be encrypted before storing
+            api_key,
+            api_secret,
+            credentials_key_id: None,
+            needs_credentials: false,
             server,
             login,
             is_active: true,
             is_demo,
             last_test_at: None,
             last_test_status: None,
+            allow_duplicate: false,
             created_at: now,
             updated_at: now,
         }
     }
 
-    pub async fn create(
-        pool: &PgPool,
-        user_id: Uuid,
-        request: CreateBrokerConnectionRequest,
-    ) -> Result<BrokerConnection, sqlx::Error> {
-        let broker_connection = BrokerConnection::new(
-            user_id,
-            request.name,
-            request.broker_type,
-            request.api_key, // TODO: Encrypt before storing
-            request.api_secret, // TODO: Encrypt before storing
-            request.server,
-            request.login,
-            request.is_demo,
-        );
+    pub fn from_request(user_id: Uuid, request: CreateBrokerConnectionRequest) -> Self {
+        let trimmed = |value: Option<String>| value.map(|v| v.trim().to_string());
+        BrokerConnection {
+            allow_duplicate: request.allow_duplicate,
+            ..BrokerConnection::new(
+                user_id,
+                request.name,
+                request.broker_type,
+                request.api_key,
+                request.api_secret,
+                trimmed(request.server),
+                trimmed(request.login),
+                request.is_demo,
+            )
+        }
+    }
 
+    // None when the server or login is missing, as nothing identifies the account then
+    pub fn account_key(&self) -> Option<BrokerAccountKey> {
+        let server = self.server.as_deref().map(str::trim).filter(|s| !s.is_empty())?;
+        let login = self.login.as_deref().map(str::trim).filter(|l| !l.is_empty())?;
+        Some(BrokerAccountKey {
+            broker_type: self.broker_type.trim().to_uppercase(),
+            server: server.to_lowercase(),
+            login: login.to_string(),
+        })
+    }
+
+    // Credentials must already be sealed by the CredentialVault
+    pub async fn create(pool: &PgPool, broker_connection: BrokerConnection) -> Result<BrokerConnection> {
         sqlx::query!(
             r#"
-            INSERT INTO broker_connections (id, user_id, name, broker_type, api_key, api_secret, server, login, is_active, is_demo, created_at, updated_at)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+            INSERT INTO broker_connections (id, user_id, name, broker_type, api_key, api_secret, credentials_key_id, server, login, is_active, is_demo, allow_duplicate, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)
             "#,
             broker_connection.id,
             broker_connection.user_id,
@@ -122,26 +182,68 @@ impl BrokerConnection {
             broker_connection.broker_type,
             broker_connection.api_key,
             broker_connection.api_secret,
+            broker_connection.credentials_key_id,
             broker_connection.server,
             broker_connection.login,
             broker_connection.is_active,
             broker_connection.is_demo,
+            broker_connection.allow_duplicate,
             broker_connection.created_at,
             broker_connection.updated_at
         )
         .execute(pool)
-        .await?;
+        .await
+        .db_op("broker_connections.create")?;
 
         Ok(broker_connection)
     }
 
-    pub async fn find_by_user_id(pool: &PgPool, user_id: Uuid) -> Result<Vec<BrokerConnection>, sqlx::Error> {
+    // Nothing is visible to other queries until the pending connection is committed
+    pub async fn begin_create(pool: &PgPool, connection: BrokerConnection) -> Result<PendingBrokerConnection> {
+        let mut tx = pool.begin().await.db_op("broker_connections.begin_create")?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO broker_connections (id, user_id, name, broker_type, api_key, api_secret, credentials_key_id, server, login, is_active, is_demo, allow_duplicate, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)
+            "#,
+        )
+        .bind(connection.id)
+        .bind(connection.user_id)
+        .bind(&connection.name)
+        .bind(&connection.broker_type)
+        .bind(&connection.api_key)
+        .bind(&connection.api_secret)
+        .bind(&connection.credentials_key_id)
+        .bind(&connection.server)
+        .bind(&connection.login)
+        .bind(connection.is_active)
+        .bind(connection.is_demo)
+        .bind(connection.allow_duplicate)
+        .bind(connection.created_at)
+        .bind(connection.updated_at)
+        .execute(&mut *tx)
+        .await
+        .db_op("broker_connections.begin_create")?;
+
+        Ok(PendingBrokerConnection { tx, connection })
+    }
+
+    pub async fn find_by_user_id(pool: &PgPool, user_id: Uuid) -> Result<Vec<BrokerConnection>> {
+        Self::find_page_by_user_id(pool, user_id, None, 0).await
+    }
+
+    // Newest first; no limit returns them all
+    pub async fn find_page_by_user_id(pool: &PgPool, user_id: Uuid, limit: Option<i64>, offset: i64) -> Result<Vec<BrokerConnection>> {
         let rows = sqlx::query!(
-            r#"SELECT id, user_id, name, broker_type, api_key, api_secret, server, login, is_active, is_demo, last_test_at, last_test_status, created_at, updated_at FROM broker_connections WHERE user_id = $1 ORDER BY created_at DESC"#,
-            user_id
+            r#"SELECT id, user_id, name, broker_type, api_key, api_secret, credentials_key_id, needs_credentials, server, login, is_active, is_demo, last_test_at, last_test_status, allow_duplicate, created_at, updated_at FROM broker_connections WHERE user_id = $1 AND deleted_at IS NULL ORDER BY created_at DESC, id DESC LIMIT $2 OFFSET $3"#,
+            user_id,
+            limit,
+            offset
         )
         .fetch_all(pool)
-        .await?;
+        .await
+        .db_op("broker_connections.find_by_user_id")?;
 
         let connections = rows.into_iter().map(|row| BrokerConnection {
             id: row.id,
@@ -150,12 +252,15 @@ impl BrokerConnection {
             broker_type: row.broker_type,
             api_key: row.api_key,
             api_secret: row.api_secret,
+            credentials_key_id: row.credentials_key_id,
+            needs_credentials: row.needs_credentials,
             server: row.server,
             login: row.login,
             is_active: row.is_active,
             is_demo: row.is_demo,
             last_test_at: row.last_test_at,
             last_test_status: row.last_test_status,
+            allow_duplicate: row.allow_duplicate,
             created_at: row.created_at,
             updated_at: row.updated_at,
         }).collect();
@@ -163,14 +268,23 @@ impl BrokerConnection {
         Ok(connections)
     }
 
-    pub async fn find_by_id(pool: &PgPool, id: Uuid, user_id: Uuid) -> Result<Option<BrokerConnection>, sqlx::Error> {
+    pub async fn count_by_user_id(pool: &PgPool, user_id: Uuid) -> Result<i64> {
+        sqlx::query_scalar("SELECT COUNT(*) FROM broker_connections WHERE user_id = $1 AND deleted_at IS NULL")
+            .bind(user_id)
+            .fetch_one(pool)
+            .await
+            .db_op("broker_connections.count_by_user_id")
+    }
+
+    pub async fn find_by_id(pool: &PgPool, id: Uuid, user_id: Uuid) -> Result<Option<BrokerConnection>> {
         let row = sqlx::query!(
-            r#"SELECT id, user_id, name, broker_type, api_key, api_secret, server, login, is_active, is_demo, last_test_at, last_test_status, created_at, updated_at FROM broker_connections WHERE id = $1 AND user_id = $2"#,
+            r#"SELECT id, user_id, name, broker_type, api_key, api_secret, credentials_key_id, needs_credentials, server, login, is_active, is_demo, last_test_at, last_test_status, allow_duplicate, created_at, updated_at FROM broker_connections WHERE id = $1 AND user_id = $2 AND deleted_at IS NULL"#,
             id,
             user_id
         )
         .fetch_optional(pool)
-        .await?;
+        .await
+        .db_op("broker_connections.find_by_id")?;
 
         if let Some(row) = row {
             Ok(Some(BrokerConnection {
@@ -180,12 +294,15 @@ impl BrokerConnection {
                 broker_type: row.broker_type,
                 api_key: row.api_key,
                 api_secret: row.api_secret,
+                credentials_key_id: row.credentials_key_id,
+                needs_credentials: row.needs_credentials,
                 server: row.server,
                 login: row.login,
                 is_active: row.is_active,
                 is_demo: row.is_demo,
                 last_test_at: row.last_test_at,
                 last_test_status: row.last_test_status,
+                allow_duplicate: row.allow_duplicate,
                 created_at: row.created_at,
                 updated_at: row.updated_at,
             }))
@@ -194,11 +311,76 @@ impl BrokerConnection {
         }
     }
 
+    // Every user's enabled connections, for background jobs
+    pub async fn find_active(pool: &PgPool) -> Result<Vec<BrokerConnection>> {
+        sqlx::query_as::<_, BrokerConnection>(
+            "SELECT id, user_id, name, broker_type, api_key, api_secret, credentials_key_id, needs_credentials, server, login, is_active, is_demo, last_test_at, last_test_status, allow_duplicate, created_at, updated_at FROM broker_connections WHERE is_active = TRUE AND deleted_at IS NULL ORDER BY created_at",
+        )
+        .fetch_all(pool)
+        .await
+        .db_op("broker_connections.find_active")
+    }
+
+    // The oldest other connection of this user to the same broker account
+    pub async fn find_duplicate(pool: &PgPool, user_id: Uuid, account: &BrokerAccountKey) -> Result<Option<Uuid>> {
+        sqlx::query_scalar(
+            "SELECT id FROM broker_connections WHERE user_id = $1 AND deleted_at IS NULL AND upper(btrim(broker_type)) = $2 AND lower(btrim(server)) = $3 AND btrim(login) = $4 ORDER BY created_at LIMIT 1",
+        )
+        .bind(user_id)
+        .bind(&account.broker_type)
+        .bind(&account.server)
+        .bind(&account.login)
+        .fetch_optional(pool)
+        .await
+        .db_op("broker_connections.find_duplicate")
+    }
+
+    // Deletes the user's connection `id`, or all of them when None, keeping the rows until the
+    // purge job: credentials are erased and robots using them are left without a connection, as
+    // a hard delete would. Returns the number deleted.
+    pub async fn soft_delete(conn: &mut PgConnection, user_id: Uuid, id: Option<Uuid>, now: DateTime<Utc>) -> Result<u64> {
+        let deleted: Vec<Uuid> = sqlx::query_scalar(
+            "UPDATE broker_connections SET api_key = '', api_secret = '', credentials_key_id = NULL, needs_credentials = TRUE, \
+             is_active = FALSE, deleted_at = $3, updated_at = $3 \
+             WHERE user_id = $1 AND ($2::uuid IS NULL OR id = $2) AND deleted_at IS NULL RETURNING id",
+        )
+        .bind(user_id)
+        .bind(id)
+        .bind(now)
+        .fetch_all(&mut *conn)
+        .await
+        .db_op("broker_connections.soft_delete")?;
+        sqlx::query("UPDATE trading_robots SET broker_connection_id = NULL, updated_at = $2 WHERE broker_connection_id = ANY($1)")
+            .bind(&deleted)
+            .bind(now)
+            .execute(&mut *conn)
+            .await
+            .db_op("broker_connections.soft_delete")?;
+        Ok(deleted.len() as u64)
+    }
+
+    pub async fn delete(pool: &PgPool, id: Uuid, user_id: Uuid, now: DateTime<Utc>) -> Result<bool> {
+        let mut tx = pool.begin().await.db_op("broker_connections.delete")?;
+        let deleted = Self::soft_delete(&mut tx, user_id, Some(id), now).await?;
+        tx.commit().await.db_op("broker_connections.delete")?;
+        Ok(deleted > 0)
+    }
+
+    // Removes connections deleted before `before`, with their snapshots
+    pub async fn purge_deleted(pool: &PgPool, before: DateTime<Utc>) -> Result<u64> {
+        let result = sqlx::query("DELETE FROM broker_connections WHERE deleted_at < $1")
+            .bind(before)
+            .execute(pool)
+            .await
+            .db_op("broker_connections.purge_deleted")?;
+        Ok(result.rows_affected())
+    }
+
     pub async fn update_test_result(
         pool: &PgPool,
         id: Uuid,
         status: &str,
-    ) -> Result<(), sqlx::Error> {
+    ) -> Result<()> {
         sqlx::query!(
             "UPDATE broker_connections SET last_test_at = $1, last_test_status = $2, updated_at = $3 WHERE id = $4",
             Utc::now(),
@@ -207,7 +389,8 @@ impl BrokerConnection {
             id
         )
         .execute(pool)
-        .await?;
+        .await
+        .db_op("broker_connections.update_test_result")?;
 
         Ok(())
     }
@@ -216,7 +399,7 @@ impl BrokerConnection {
         pool: &PgPool,
         id: Uuid,
         is_active: bool,
-    ) -> Result<(), sqlx::Error> {
+    ) -> Result<()> {
         sqlx::query!(
             "UPDATE broker_connections SET is_active = $1, updated_at = $2 WHERE id = $3",
             is_active,
@@ -224,7 +407,89 @@ impl BrokerConnection {
             id
         )
         .execute(pool)
-        .await?;
+        .await
+        .db_op("broker_connections.set_active")?;
+
+        Ok(())
+    }
+
+    // Connections whose credentials are not under `key_id`, by id after `after`. Quarantined
+    // connections are left out as there is nothing left to re-encrypt.
+    pub async fn find_stale_credentials(pool: &PgPool, key_id: &str, after: Option<Uuid>, limit: i64) -> Result<Vec<BrokerConnection>> {
+        sqlx::query_as::<_, BrokerConnection>(
+            "SELECT id, user_id, name, broker_type, api_key, api_secret, credentials_key_id, needs_credentials, server, login, is_active, is_demo, last_test_at, last_test_status, allow_duplicate, created_at, updated_at FROM broker_connections WHERE credentials_key_id IS DISTINCT FROM $1 AND needs_credentials = FALSE AND ($2::uuid IS NULL OR id > $2) ORDER BY id LIMIT $3",
+        )
+        .bind(key_id)
+        .bind(after)
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+        .db_op("broker_connections.find_stale_credentials")
+    }
+
+    pub async fn count_stale_credentials(pool: &PgPool, key_id: &str) -> Result<i64> {
+        sqlx::query_scalar("SELECT COUNT(*) FROM broker_connections WHERE credentials_key_id IS DISTINCT FROM $1 AND needs_credentials = FALSE")
+            .bind(key_id)
+            .fetch_one(pool)
+            .await
+            .db_op("broker_connections.count_stale_credentials")
+    }
+
+    // Swaps in re-encrypted credentials unless the row changed key since it was read, e.g.
+    // because the user entered new credentials meanwhile. Returns whether it was updated.
+    pub async fn replace_credentials(
+        pool: &PgPool,
+        id: Uuid,
+        previous_key_id: Option<&str>,
+        api_key: &str,
+        api_secret: &str,
+        key_id: &str,
+    ) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE broker_connections SET api_key = $1, api_secret = $2, credentials_key_id = $3 WHERE id = $4 AND credentials_key_id IS NOT DISTINCT FROM $5 AND needs_credentials = FALSE",
+        )
+        .bind(api_key)
+        .bind(api_secret)
+        .bind(key_id)
+        .bind(id)
+        .bind(previous_key_id)
+        .execute(pool)
+        .await
+        .db_op("broker_connections.replace_credentials")?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    // New credentials entered by the user, already sealed
+    pub async fn update_credentials(
+        pool: &PgPool,
+        id: Uuid,
+        user_id: Uuid,
+        api_key: &str,
+        api_secret: &str,
+        key_id: &str,
+    ) -> Result<()> {
+        sqlx::query(
+            "UPDATE broker_connections SET api_key = $1, api_secret = $2, credentials_key_id = $3, needs_credentials = FALSE, updated_at = NOW() WHERE id = $4 AND user_id = $5",
+        )
+        .bind(api_key)
+        .bind(api_secret)
+        .bind(key_id)
+        .bind(id)
+        .bind(user_id)
+        .execute(pool)
+        .await
+        .db_op("broker_connections.update_credentials")?;
+
+        Ok(())
+    }
+
+    pub async fn mark_needs_credentials(pool: &PgPool, id: Uuid) -> Result<()> {
+        sqlx::query("UPDATE broker_connections SET needs_credentials = TRUE, updated_at = NOW() WHERE id = $1")
+            .bind(id)
+            .execute(pool)
+            .await
+            .db_op("broker_connections.mark_needs_credentials")?;
 
         Ok(())
     }
@@ -242,7 +507,35 @@ impl From<BrokerConnection> for BrokerConnectionResponse {
             is_demo: connection.is_demo,
             last_test_at: connection.last_test_at,
             last_test_status: connection.last_test_status,
+            needs_credentials: connection.needs_credentials,
             created_at: connection.created_at,
+            account_info: None,
         }
     }
 }
+
+impl PendingBrokerConnection {
+    pub async fn commit(mut self, test_status: &str) -> Result<BrokerConnection> {
+        let now = Utc::now();
+        sqlx::query("UPDATE broker_connections SET last_test_at = $1, last_test_status = $2, updated_at = $1 WHERE id = $3")
+            .bind(now)
+            .bind(test_status)
+            .bind(self.connection.id)
+            .execute(&mut *self.tx)
+            .await
+            .db_op("broker_connections.commit")?;
+
+        self.tx.commit().await.db_op("broker_connections.commit")?;
+
+        Ok(BrokerConnection {
+            last_test_at: Some(now),
+            last_test_status: Some(test_status.to_string()),
+            updated_at: now,
+            ..self.connection
+        })
+    }
+
+    pub async fn rollback(self) -> Result<()> {
+        self.tx.rollback().await.db_op("broker_connections.rollback")
+    }
+}
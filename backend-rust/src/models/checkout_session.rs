@@ -0,0 +1,167 @@
+use chrono::{DateTime, Utc};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    errors::{DbOp, Result},
+    models::Subscription,
+};
+
+pub const CHECKOUT_OPEN: &str = "open";
+pub const CHECKOUT_COMPLETED: &str = "completed";
+
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct CheckoutSession {
+    // Stripe's session id
+    pub id: String,
+    pub user_id: Uuid,
+    pub plan_name: String,
+    pub status: String,
+    pub subscription_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+// The parts of a paid checkout that end up on the subscription
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckoutPayment {
+    pub session_id: String,
+    pub stripe_customer_id: Option<String>,
+    pub stripe_subscription_id: Option<String>,
+}
+
+// What completing a checkout changed, for the confirmation email
+#[derive(Debug, Clone)]
+pub struct CheckoutActivation {
+    pub subscription: Subscription,
+    pub email: String,
+    pub converted_trial: bool,
+}
+
+const COLUMNS: &str = "id, user_id, plan_name, status, subscription_id, created_at, completed_at";
+
+impl CheckoutSession {
+    pub fn new(id: String, user_id: Uuid, plan_name: String, now: DateTime<Utc>) -> Self {
+        CheckoutSession {
+            id,
+            user_id,
+            plan_name,
+            status: CHECKOUT_OPEN.to_string(),
+            subscription_id: None,
+            created_at: now,
+            completed_at: None,
+        }
+    }
+
+    pub async fn create(pool: &PgPool, session: &CheckoutSession) -> Result<()> {
+        sqlx::query("INSERT INTO checkout_sessions (id, user_id, plan_name, status, created_at) VALUES ($1, $2, $3, $4, $5)")
+            .bind(&session.id)
+            .bind(session.user_id)
+            .bind(&session.plan_name)
+            .bind(&session.status)
+            .bind(session.created_at)
+            .execute(pool)
+            .await
+            .db_op("checkout_sessions.create")?;
+        Ok(())
+    }
+
+    pub async fn find_by_id(pool: &PgPool, id: &str) -> Result<Option<CheckoutSession>> {
+        sqlx::query_as::<_, CheckoutSession>(&format!("SELECT {} FROM checkout_sessions WHERE id = $1", COLUMNS))
+            .bind(id)
+            .fetch_optional(pool)
+            .await
+            .db_op("checkout_sessions.find_by_id")
+    }
+
+    // One transaction per webhook event: records the event id, completes the session, ends a
+    // running trial, creates the subscription and moves the user to the plan. Returns None when
+    // the event was handled before or the session is unknown or already completed.
+    pub async fn complete(
+        pool: &PgPool,
+        event_id: &str,
+        event_type: &str,
+        payment: &CheckoutPayment,
+        now: DateTime<Utc>,
+    ) -> Result<Option<CheckoutActivation>> {
+        let mut tx = pool.begin().await.db_op("checkout_sessions.complete")?;
+
+        let first_delivery = sqlx::query("INSERT INTO stripe_events (id, event_type, processed_at) VALUES ($1, $2, $3) ON CONFLICT (id) DO NOTHING")
+            .bind(event_id)
+            .bind(event_type)
+            .bind(now)
+            .execute(&mut *tx)
+            .await
+            .db_op("checkout_sessions.complete")?;
+        if first_delivery.rows_affected() == 0 {
+            return Ok(None);
+        }
+
+        let session: Option<(Uuid, String)> = sqlx::query_as(
+            "UPDATE checkout_sessions SET status = $1, completed_at = $2 WHERE id = $3 AND status = $4 RETURNING user_id, plan_name",
+        )
+        .bind(CHECKOUT_COMPLETED)
+        .bind(now)
+        .bind(&payment.session_id)
+        .bind(CHECKOUT_OPEN)
+        .fetch_optional(&mut *tx)
+        .await
+        .db_op("checkout_sessions.complete")?;
+        let Some((user_id, plan_name)) = session else {
+            tx.commit().await.db_op("checkout_sessions.complete")?;
+            return Ok(None);
+        };
+
+        let converted = sqlx::query(
+            "UPDATE subscriptions SET status = 'converted', current_period_end = $1, updated_at = $1 WHERE user_id = $2 AND status = 'trialing'",
+        )
+        .bind(now)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await
+        .db_op("checkout_sessions.complete")?;
+
+        let subscription = Subscription {
+            stripe_subscription_id: payment.stripe_subscription_id.clone(),
+            stripe_customer_id: payment.stripe_customer_id.clone(),
+            ..Subscription::new(user_id, plan_name)
+        };
+        sqlx::query(
+            r#"
+            INSERT INTO subscriptions (id, user_id, plan_name, stripe_subscription_id, stripe_customer_id, status, current_period_start, current_period_end, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            "#,
+        )
+        .bind(subscription.id)
+        .bind(subscription.user_id)
+        .bind(&subscription.plan_name)
+        .bind(&subscription.stripe_subscription_id)
+        .bind(&subscription.stripe_customer_id)
+        .bind(&subscription.status)
+        .bind(subscription.current_period_start)
+        .bind(subscription.current_period_end)
+        .bind(subscription.created_at)
+        .bind(subscription.updated_at)
+        .execute(&mut *tx)
+        .await
+        .db_op("checkout_sessions.complete")?;
+
+        sqlx::query("UPDATE checkout_sessions SET subscription_id = $1 WHERE id = $2")
+            .bind(subscription.id)
+            .bind(&payment.session_id)
+            .execute(&mut *tx)
+            .await
+            .db_op("checkout_sessions.complete")?;
+
+        let email: String = sqlx::query_scalar("UPDATE users SET subscription_plan = $1, updated_at = $2 WHERE id = $3 RETURNING email")
+            .bind(&subscription.plan_name)
+            .bind(now)
+            .bind(user_id)
+            .fetch_one(&mut *tx)
+            .await
+            .db_op("checkout_sessions.complete")?;
+
+        tx.commit().await.db_op("checkout_sessions.complete")?;
+        Ok(Some(CheckoutActivation { subscription, email, converted_trial: converted.rows_affected() > 0 }))
+    }
+}
@@ -0,0 +1,227 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::errors::{DbOp, Result};
+
+pub const DELEGATION_SCOPE_READ_ONLY: &str = "read_only";
+pub const INVITATION_VALID_DAYS: i64 = 7;
+
+#[derive(Debug, Clone, FromRow)]
+pub struct Delegation {
+    pub id: Uuid,
+    pub grantor_id: Uuid,
+    pub delegate_email: String,
+    pub delegate_id: Option<Uuid>,
+    pub scope: String,
+    pub invite_token: Uuid,
+    pub invite_expires_at: DateTime<Utc>,
+    pub accepted_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Validate)]
+pub struct CreateDelegationRequest {
+    #[validate(email)]
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct AcceptDelegationRequest {
+    pub token: Uuid,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct DelegationResponse {
+    pub id: Uuid,
+    pub grantor_id: Uuid,
+    pub delegate_email: String,
+    pub delegate_id: Option<Uuid>,
+    pub scope: String,
+    // pending | active | expired | revoked
+    pub status: String,
+    pub invite_expires_at: DateTime<Utc>,
+    pub accepted_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub last_accessed_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, FromRow)]
+struct DelegationWithAccess {
+    #[sqlx(flatten)]
+    delegation: Delegation,
+    last_accessed_at: Option<DateTime<Utc>>,
+}
+
+const COLUMNS: &str = "id, grantor_id, delegate_email, delegate_id, scope, invite_token, invite_expires_at, accepted_at, revoked_at, created_at";
+
+impl Delegation {
+    pub fn new(grantor_id: Uuid, delegate_email: String, now: DateTime<Utc>) -> Self {
+        Delegation {
+            id: Uuid::new_v4(),
+            grantor_id,
+            delegate_email,
+            delegate_id: None,
+            scope: DELEGATION_SCOPE_READ_ONLY.to_string(),
+            invite_token: Uuid::new_v4(),
+            invite_expires_at: now + Duration::days(INVITATION_VALID_DAYS),
+            accepted_at: None,
+            revoked_at: None,
+            created_at: now,
+        }
+    }
+
+    pub fn status(&self, now: DateTime<Utc>) -> &'static str {
+        if self.revoked_at.is_some() {
+            "revoked"
+        } else if self.accepted_at.is_some() {
+            "active"
+        } else if self.invite_expires_at <= now {
+            "expired"
+        } else {
+            "pending"
+        }
+    }
+
+    pub fn to_response(&self, last_accessed_at: Option<DateTime<Utc>>, now: DateTime<Utc>) -> DelegationResponse {
+        DelegationResponse {
+            id: self.id,
+            grantor_id: self.grantor_id,
+            delegate_email: self.delegate_email.clone(),
+            delegate_id: self.delegate_id,
+            scope: self.scope.clone(),
+            status: self.status(now).to_string(),
+            invite_expires_at: self.invite_expires_at,
+            accepted_at: self.accepted_at,
+            revoked_at: self.revoked_at,
+            last_accessed_at,
+            created_at: self.created_at,
+        }
+    }
+
+    pub async fn create(pool: &PgPool, delegation: &Delegation) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO delegations (id, grantor_id, delegate_email, delegate_id, scope, invite_token, invite_expires_at, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            "#,
+        )
+        .bind(delegation.id)
+        .bind(delegation.grantor_id)
+        .bind(&delegation.delegate_email)
+        .bind(delegation.delegate_id)
+        .bind(&delegation.scope)
+        .bind(delegation.invite_token)
+        .bind(delegation.invite_expires_at)
+        .bind(delegation.created_at)
+        .execute(pool)
+        .await
+        .db_op("delegations.create")?;
+        Ok(())
+    }
+
+    // Not revoked, whether or not the invitation has been accepted or has expired
+    pub async fn find_open_for_email(pool: &PgPool, grantor_id: Uuid, email: &str) -> Result<Option<Delegation>> {
+        sqlx::query_as::<_, Delegation>(&format!(
+            "SELECT {} FROM delegations WHERE grantor_id = $1 AND LOWER(delegate_email) = LOWER($2) AND revoked_at IS NULL",
+            COLUMNS
+        ))
+        .bind(grantor_id)
+        .bind(email)
+        .fetch_optional(pool)
+        .await
+        .db_op("delegations.find_open_for_email")
+    }
+
+    pub async fn find_by_token(pool: &PgPool, token: Uuid) -> Result<Option<Delegation>> {
+        sqlx::query_as::<_, Delegation>(&format!("SELECT {} FROM delegations WHERE invite_token = $1", COLUMNS))
+            .bind(token)
+            .fetch_optional(pool)
+            .await
+            .db_op("delegations.find_by_token")
+    }
+
+    pub async fn find_active(pool: &PgPool, grantor_id: Uuid, delegate_id: Uuid) -> Result<Option<Delegation>> {
+        sqlx::query_as::<_, Delegation>(&format!(
+            "SELECT {} FROM delegations WHERE grantor_id = $1 AND delegate_id = $2 AND accepted_at IS NOT NULL AND revoked_at IS NULL",
+            COLUMNS
+        ))
+        .bind(grantor_id)
+        .bind(delegate_id)
+        .fetch_optional(pool)
+        .await
+        .db_op("delegations.find_active")
+    }
+
+    // Newest first, with when each delegate last used it
+    pub async fn find_by_grantor(pool: &PgPool, grantor_id: Uuid) -> Result<Vec<(Delegation, Option<DateTime<Utc>>)>> {
+        let rows = sqlx::query_as::<_, DelegationWithAccess>(
+            r#"
+            SELECT d.id, d.grantor_id, d.delegate_email, d.delegate_id, d.scope, d.invite_token, d.invite_expires_at,
+                   d.accepted_at, d.revoked_at, d.created_at,
+                   (SELECT MAX(l.accessed_at) FROM delegation_access_log l WHERE l.delegation_id = d.id) AS last_accessed_at
+            FROM delegations d
+            WHERE d.grantor_id = $1
+            ORDER BY d.created_at DESC
+            "#,
+        )
+        .bind(grantor_id)
+        .fetch_all(pool)
+        .await
+        .db_op("delegations.find_by_grantor")?;
+
+        Ok(rows.into_iter().map(|row| (row.delegation, row.last_accessed_at)).collect())
+    }
+
+    // Accounts the user may act on behalf of
+    pub async fn find_by_delegate(pool: &PgPool, delegate_id: Uuid) -> Result<Vec<Delegation>> {
+        sqlx::query_as::<_, Delegation>(&format!(
+            "SELECT {} FROM delegations WHERE delegate_id = $1 AND accepted_at IS NOT NULL AND revoked_at IS NULL ORDER BY accepted_at",
+            COLUMNS
+        ))
+        .bind(delegate_id)
+        .fetch_all(pool)
+        .await
+        .db_op("delegations.find_by_delegate")
+    }
+
+    pub async fn mark_accepted(pool: &PgPool, id: Uuid, delegate_id: Uuid, now: DateTime<Utc>) -> Result<()> {
+        sqlx::query("UPDATE delegations SET delegate_id = $1, accepted_at = $2 WHERE id = $3 AND accepted_at IS NULL AND revoked_at IS NULL")
+            .bind(delegate_id)
+            .bind(now)
+            .bind(id)
+            .execute(pool)
+            .await
+            .db_op("delegations.mark_accepted")?;
+        Ok(())
+    }
+
+    pub async fn revoke(pool: &PgPool, grantor_id: Uuid, id: Uuid, now: DateTime<Utc>) -> Result<bool> {
+        let result = sqlx::query("UPDATE delegations SET revoked_at = $1 WHERE id = $2 AND grantor_id = $3 AND revoked_at IS NULL")
+            .bind(now)
+            .bind(id)
+            .bind(grantor_id)
+            .execute(pool)
+            .await
+            .db_op("delegations.revoke")?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn record_access(pool: &PgPool, id: Uuid, method: &str, path: &str, now: DateTime<Utc>) -> Result<()> {
+        sqlx::query("INSERT INTO delegation_access_log (id, delegation_id, method, path, accessed_at) VALUES ($1, $2, $3, $4, $5)")
+            .bind(Uuid::new_v4())
+            .bind(id)
+            .bind(method)
+            .bind(path)
+            .bind(now)
+            .execute(pool)
+            .await
+            .db_op("delegation_access_log.record_access")?;
+        Ok(())
+    }
+}
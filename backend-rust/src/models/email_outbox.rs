@@ -0,0 +1,157 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+use crate::errors::{DbOp, Result};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct OutboxEmail {
+    pub id: Uuid,
+    pub recipient: String,
+    pub template_key: String,
+    pub payload: serde_json::Value,
+    // pending | sent | dead
+    pub status: String,
+    pub attempts: i32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub sent_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, JsonSchema)]
+pub struct OutboxHealth {
+    pub pending: i64,
+    // Gave up after the maximum number of attempts
+    pub dead: i64,
+    pub oldest_pending_at: Option<DateTime<Utc>>,
+}
+
+const OUTBOX_COLUMNS: &str =
+    "id, recipient, template_key, payload, status, attempts, next_attempt_at, last_error, created_at, sent_at";
+
+impl OutboxEmail {
+    pub fn new(recipient: &str, template_key: &str, payload: serde_json::Value, now: DateTime<Utc>) -> Self {
+        OutboxEmail {
+            id: Uuid::new_v4(),
+            recipient: recipient.to_string(),
+            template_key: template_key.to_string(),
+            payload,
+            status: "pending".to_string(),
+            attempts: 0,
+            next_attempt_at: now,
+            last_error: None,
+            created_at: now,
+            sent_at: None,
+        }
+    }
+
+    pub async fn enqueue(pool: &PgPool, email: &OutboxEmail) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO email_outbox (id, recipient, template_key, payload, status, attempts, next_attempt_at, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            "#,
+        )
+        .bind(email.id)
+        .bind(&email.recipient)
+        .bind(&email.template_key)
+        .bind(&email.payload)
+        .bind(&email.status)
+        .bind(email.attempts)
+        .bind(email.next_attempt_at)
+        .bind(email.created_at)
+        .execute(pool)
+        .await
+        .db_op("email_outbox.enqueue")?;
+
+        Ok(())
+    }
+
+    // Due rows are leased until `lease_until` so another replica's worker skips them meanwhile
+    pub async fn claim_due(
+        pool: &PgPool,
+        now: DateTime<Utc>,
+        lease_until: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<OutboxEmail>> {
+        sqlx::query_as::<_, OutboxEmail>(&format!(
+            r#"
+            UPDATE email_outbox SET next_attempt_at = $2
+            WHERE id IN (
+                SELECT id FROM email_outbox
+                WHERE status = 'pending' AND next_attempt_at <= $1
+                ORDER BY next_attempt_at
+                LIMIT $3
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING {}
+            "#,
+            OUTBOX_COLUMNS
+        ))
+        .bind(now)
+        .bind(lease_until)
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+        .db_op("email_outbox.claim_due")
+    }
+
+    pub async fn mark_sent(pool: &PgPool, id: Uuid, attempts: i32, now: DateTime<Utc>) -> Result<()> {
+        sqlx::query("UPDATE email_outbox SET status = 'sent', attempts = $2, sent_at = $3, last_error = NULL WHERE id = $1")
+            .bind(id)
+            .bind(attempts)
+            .bind(now)
+            .execute(pool)
+            .await
+            .db_op("email_outbox.mark_sent")?;
+        Ok(())
+    }
+
+    // `next_attempt_at` None dead-letters the row
+    pub async fn mark_failed(
+        pool: &PgPool,
+        id: Uuid,
+        attempts: i32,
+        next_attempt_at: Option<DateTime<Utc>>,
+        error: &str,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE email_outbox
+            SET attempts = $2,
+                last_error = $3,
+                status = CASE WHEN $4::timestamptz IS NULL THEN 'dead' ELSE 'pending' END,
+                next_attempt_at = COALESCE($4, next_attempt_at)
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .bind(attempts)
+        .bind(error)
+        .bind(next_attempt_at)
+        .execute(pool)
+        .await
+        .db_op("email_outbox.mark_failed")?;
+        Ok(())
+    }
+
+    pub async fn health(pool: &PgPool) -> Result<OutboxHealth> {
+        let (pending, dead, oldest_pending_at): (i64, i64, Option<DateTime<Utc>>) = sqlx::query_as(
+            r#"
+            SELECT
+                COUNT(*) FILTER (WHERE status = 'pending'),
+                COUNT(*) FILTER (WHERE status = 'dead'),
+                MIN(created_at) FILTER (WHERE status = 'pending')
+            FROM email_outbox
+            "#,
+        )
+        .fetch_one(pool)
+        .await
+        .db_op("email_outbox.health")?;
+
+        Ok(OutboxHealth { pending, dead, oldest_pending_at })
+    }
+}
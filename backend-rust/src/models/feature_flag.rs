@@ -0,0 +1,133 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use sqlx::{types::Json, FromRow, PgPool};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::errors::{DbOp, Result};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema, FromRow)]
+pub struct FeatureFlag {
+    pub key: String,
+    pub description: String,
+    pub enabled: bool,
+    pub enabled_user_ids: Vec<Uuid>,
+    pub rollout_percentage: i16,
+    pub updated_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+// Fields left out keep their current value
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Validate)]
+pub struct UpdateFeatureFlagRequest {
+    #[validate(length(max = 500))]
+    pub description: Option<String>,
+    pub enabled: Option<bool>,
+    pub enabled_user_ids: Option<Vec<Uuid>>,
+    #[validate(range(min = 0, max = 100))]
+    pub rollout_percentage: Option<i16>,
+}
+
+impl FeatureFlag {
+    pub fn new(key: &str) -> Self {
+        let now = Utc::now();
+        FeatureFlag {
+            key: key.to_string(),
+            description: String::new(),
+            enabled: false,
+            enabled_user_ids: Vec::new(),
+            rollout_percentage: 0,
+            updated_by: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    pub fn apply(mut self, request: UpdateFeatureFlagRequest, changed_by: Uuid) -> Self {
+        if let Some(description) = request.description {
+            self.description = description;
+        }
+        if let Some(enabled) = request.enabled {
+            self.enabled = enabled;
+        }
+        if let Some(mut user_ids) = request.enabled_user_ids {
+            user_ids.sort();
+            user_ids.dedup();
+            self.enabled_user_ids = user_ids;
+        }
+        if let Some(percentage) = request.rollout_percentage {
+            self.rollout_percentage = percentage;
+        }
+        self.updated_by = Some(changed_by);
+        self
+    }
+
+    pub async fn find_all(pool: &PgPool) -> Result<Vec<FeatureFlag>> {
+        sqlx::query_as::<_, FeatureFlag>(
+            "SELECT key, description, enabled, enabled_user_ids, rollout_percentage, updated_by, created_at, updated_at FROM feature_flags ORDER BY key",
+        )
+        .fetch_all(pool)
+        .await
+        .db_op("feature_flags.find_all")
+    }
+
+    pub async fn find_by_key(pool: &PgPool, key: &str) -> Result<Option<FeatureFlag>> {
+        sqlx::query_as::<_, FeatureFlag>(
+            "SELECT key, description, enabled, enabled_user_ids, rollout_percentage, updated_by, created_at, updated_at FROM feature_flags WHERE key = $1",
+        )
+        .bind(key)
+        .fetch_optional(pool)
+        .await
+        .db_op("feature_flags.find_by_key")
+    }
+
+    // Writes the flag and its audit entry in one transaction
+    pub async fn save(
+        pool: &PgPool,
+        flag: &FeatureFlag,
+        previous: Option<&FeatureFlag>,
+    ) -> Result<FeatureFlag> {
+        let mut tx = pool.begin().await.db_op("feature_flags.save")?;
+
+        let saved = sqlx::query_as::<_, FeatureFlag>(
+            r#"
+            INSERT INTO feature_flags (key, description, enabled, enabled_user_ids, rollout_percentage, updated_by)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (key) DO UPDATE SET
+                description = EXCLUDED.description,
+                enabled = EXCLUDED.enabled,
+                enabled_user_ids = EXCLUDED.enabled_user_ids,
+                rollout_percentage = EXCLUDED.rollout_percentage,
+                updated_by = EXCLUDED.updated_by
+            RETURNING key, description, enabled, enabled_user_ids, rollout_percentage, updated_by, created_at, updated_at
+            "#,
+        )
+        .bind(&flag.key)
+        .bind(&flag.description)
+        .bind(flag.enabled)
+        .bind(&flag.enabled_user_ids)
+        .bind(flag.rollout_percentage)
+        .bind(flag.updated_by)
+        .fetch_one(&mut *tx)
+        .await
+        .db_op("feature_flags.save")?;
+
+        sqlx::query(
+            "INSERT INTO feature_flag_audit (id, flag_key, changed_by, previous, current, created_at) VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(Uuid::new_v4())
+        .bind(&saved.key)
+        .bind(saved.updated_by)
+        .bind(previous.map(Json))
+        .bind(Json(&saved))
+        .bind(Utc::now())
+        .execute(&mut *tx)
+        .await
+        .db_op("feature_flags.save")?;
+
+        tx.commit().await.db_op("feature_flags.save")?;
+        Ok(saved)
+    }
+}
@@ -0,0 +1,106 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use sqlx::{types::Json, FromRow, PgPool};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::errors::{DbOp, Result};
+use super::TradeFilter;
+
+pub const MAX_PRESETS_PER_USER: i64 = 20;
+
+#[derive(Debug, Clone, FromRow)]
+pub struct FilterPreset {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    pub filter: Json<TradeFilter>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Validate)]
+pub struct CreateFilterPresetRequest {
+    #[validate(length(min = 1, max = 100))]
+    pub name: String,
+    pub filter: TradeFilter,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct FilterPresetResponse {
+    pub id: Uuid,
+    pub name: String,
+    pub filter: TradeFilter,
+    // Robots referenced by the filter that have since been deleted; they are ignored when applied
+    pub missing_robot_ids: Vec<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl FilterPreset {
+    pub async fn count_by_user_id(pool: &PgPool, user_id: Uuid) -> Result<i64> {
+        sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM filter_presets WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(pool)
+            .await
+            .db_op("filter_presets.count_by_user_id")
+    }
+
+    pub async fn create(
+        pool: &PgPool,
+        user_id: Uuid,
+        name: String,
+        filter: TradeFilter,
+    ) -> Result<FilterPreset> {
+        let now = Utc::now();
+        sqlx::query_as::<_, FilterPreset>(
+            r#"
+            INSERT INTO filter_presets (id, user_id, name, filter, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id, user_id, name, filter, created_at, updated_at
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(user_id)
+        .bind(name)
+        .bind(Json(filter))
+        .bind(now)
+        .bind(now)
+        .fetch_one(pool)
+        .await
+        .db_op("filter_presets.create")
+    }
+
+    pub async fn find_by_user_id(pool: &PgPool, user_id: Uuid) -> Result<Vec<FilterPreset>> {
+        sqlx::query_as::<_, FilterPreset>(
+            "SELECT id, user_id, name, filter, created_at, updated_at FROM filter_presets WHERE user_id = $1 ORDER BY name",
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await
+        .db_op("filter_presets.find_by_user_id")
+    }
+
+    pub async fn find_by_id(pool: &PgPool, id: Uuid, user_id: Uuid) -> Result<Option<FilterPreset>> {
+        sqlx::query_as::<_, FilterPreset>(
+            "SELECT id, user_id, name, filter, created_at, updated_at FROM filter_presets WHERE id = $1 AND user_id = $2",
+        )
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await
+        .db_op("filter_presets.find_by_id")
+    }
+
+    pub async fn delete(pool: &PgPool, id: Uuid, user_id: Uuid) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM filter_presets WHERE id = $1 AND user_id = $2")
+            .bind(id)
+            .bind(user_id)
+            .execute(pool)
+            .await
+            .db_op("filter_presets.delete")?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
@@ -0,0 +1,157 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use sqlx::{types::Json, FromRow, PgPool};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::errors::{AppError, DbOp, Result};
+
+pub const INCIDENT_STATUSES: [&str; 4] = ["investigating", "identified", "monitoring", "resolved"];
+pub const INCIDENT_RESOLVED: &str = "resolved";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct IncidentUpdate {
+    pub status: String,
+    pub message: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct Incident {
+    pub id: Uuid,
+    pub title: String,
+    pub status: String,
+    pub updates: Json<Vec<IncidentUpdate>>,
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub resolved_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Validate)]
+pub struct CreateIncidentRequest {
+    #[validate(length(min = 1, max = 200))]
+    pub title: String,
+    // investigating | identified | monitoring | resolved
+    pub status: String,
+    #[validate(length(min = 1, max = 2000))]
+    pub message: String,
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Validate)]
+pub struct IncidentUpdateRequest {
+    pub status: String,
+    #[validate(length(min = 1, max = 2000))]
+    pub message: String,
+}
+
+// Also what the public status page shows, so it carries nothing about who posted it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct IncidentResponse {
+    pub id: Uuid,
+    pub title: String,
+    pub status: String,
+    // Oldest first
+    pub updates: Vec<IncidentUpdate>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub resolved_at: Option<DateTime<Utc>>,
+}
+
+const COLUMNS: &str = "id, title, status, updates, created_by, created_at, updated_at, resolved_at";
+
+impl Incident {
+    pub fn validate_status(status: &str) -> Result<()> {
+        if INCIDENT_STATUSES.contains(&status) {
+            return Ok(());
+        }
+        Err(AppError::Validation(format!(
+            "Invalid incident status '{}', expected one of {}",
+            status,
+            INCIDENT_STATUSES.join(", ")
+        )))
+    }
+
+    pub fn new(title: String, status: String, message: String, created_by: Uuid, now: DateTime<Utc>) -> Self {
+        Incident {
+            id: Uuid::new_v4(),
+            title,
+            resolved_at: (status == INCIDENT_RESOLVED).then_some(now),
+            updates: Json(vec![IncidentUpdate { status: status.clone(), message, created_at: now }]),
+            status,
+            created_by: Some(created_by),
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    pub async fn create(pool: &PgPool, incident: &Incident) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO incidents (id, title, status, updates, created_by, created_at, updated_at, resolved_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+        )
+        .bind(incident.id)
+        .bind(&incident.title)
+        .bind(&incident.status)
+        .bind(&incident.updates)
+        .bind(incident.created_by)
+        .bind(incident.created_at)
+        .bind(incident.updated_at)
+        .bind(incident.resolved_at)
+        .execute(pool)
+        .await
+        .db_op("incidents.create")?;
+        Ok(())
+    }
+
+    // Appends to the timeline and moves the incident to the update's status. Resolving sets
+    // resolved_at; a later update that reopens the incident clears it.
+    pub async fn add_update(pool: &PgPool, id: Uuid, update: &IncidentUpdate) -> Result<Option<Incident>> {
+        sqlx::query_as::<_, Incident>(&format!(
+            r#"
+            UPDATE incidents SET
+                status = $2,
+                updates = updates || $3,
+                updated_at = $4,
+                resolved_at = CASE WHEN $2 = '{}' THEN COALESCE(resolved_at, $4) END
+            WHERE id = $1
+            RETURNING {}
+            "#,
+            INCIDENT_RESOLVED, COLUMNS
+        ))
+        .bind(id)
+        .bind(&update.status)
+        .bind(Json(vec![update]))
+        .bind(update.created_at)
+        .fetch_optional(pool)
+        .await
+        .db_op("incidents.add_update")
+    }
+
+    // Open incidents and those resolved after `resolved_since`, newest first
+    pub async fn find_recent(pool: &PgPool, resolved_since: DateTime<Utc>, limit: i64) -> Result<Vec<Incident>> {
+        sqlx::query_as::<_, Incident>(&format!(
+            "SELECT {} FROM incidents WHERE resolved_at IS NULL OR resolved_at >= $1 ORDER BY created_at DESC LIMIT $2",
+            COLUMNS
+        ))
+        .bind(resolved_since)
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+        .db_op("incidents.find_recent")
+    }
+}
+
+impl From<Incident> for IncidentResponse {
+    fn from(incident: Incident) -> Self {
+        IncidentResponse {
+            id: incident.id,
+            title: incident.title,
+            status: incident.status,
+            updates: incident.updates.0,
+            created_at: incident.created_at,
+            updated_at: incident.updated_at,
+            resolved_at: incident.resolved_at,
+        }
+    }
+}
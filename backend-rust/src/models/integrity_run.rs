@@ -0,0 +1,288 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+use crate::errors::{DbOp, Result};
+use crate::models::TradeDailyFact;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, FromRow)]
+pub struct IntegrityRun {
+    pub id: Uuid,
+    pub kind: String,
+    pub user_id: Option<Uuid>,
+    pub requested_by: Option<Uuid>,
+    pub status: String,
+    pub robots_total: i32,
+    pub robots_processed: i32,
+    pub report: Option<serde_json::Value>,
+    pub error: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+// Denormalized totals as stored on a robot or session
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Default)]
+pub struct TradeTotals {
+    pub total_trades: i32,
+    pub winning_trades: i32,
+    pub total_profit: f64,
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct StoredRobotTotals {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub total_trades: i32,
+    pub winning_trades: i32,
+    pub total_profit: f64,
+    // Active or paused, i.e. its runner comes back on its own
+    pub running: bool,
+    pub risk_config: serde_json::Value,
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct StoredSessionTotals {
+    pub id: Uuid,
+    pub robot_id: Uuid,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: Option<DateTime<Utc>>,
+    pub total_trades: i32,
+    pub winning_trades: i32,
+    pub total_profit: f64,
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct ClosedTradeRow {
+    pub id: Uuid,
+    pub robot_id: Uuid,
+    pub symbol: String,
+    pub is_demo: bool,
+    pub volume: f64,
+    pub profit_loss: Option<f64>,
+    pub opened_at: DateTime<Utc>,
+    pub closed_at: Option<DateTime<Utc>>,
+}
+
+// Everything stored for one batch of robots
+#[derive(Debug, Clone, Default)]
+pub struct IntegrityBatch {
+    pub robots: Vec<StoredRobotTotals>,
+    pub sessions: Vec<StoredSessionTotals>,
+    pub trades: Vec<ClosedTradeRow>,
+    pub facts: Vec<TradeDailyFact>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TotalsCorrection {
+    Robot { robot_id: Uuid, totals: TradeTotals },
+    Session { session_id: Uuid, totals: TradeTotals },
+    // The robot's trade_daily_facts rows are rebuilt from its trades
+    Facts { robot_id: Uuid },
+}
+
+impl StoredRobotTotals {
+    pub fn totals(&self) -> TradeTotals {
+        TradeTotals {
+            total_trades: self.total_trades,
+            winning_trades: self.winning_trades,
+            total_profit: self.total_profit,
+        }
+    }
+}
+
+impl StoredSessionTotals {
+    pub fn totals(&self) -> TradeTotals {
+        TradeTotals {
+            total_trades: self.total_trades,
+            winning_trades: self.winning_trades,
+            total_profit: self.total_profit,
+        }
+    }
+}
+
+impl IntegrityRun {
+    pub async fn create(pool: &PgPool, kind: &str, user_id: Option<Uuid>, requested_by: Uuid) -> Result<IntegrityRun> {
+        sqlx::query_as::<_, IntegrityRun>(
+            r#"
+            INSERT INTO integrity_runs (id, kind, user_id, requested_by, status, started_at)
+            VALUES ($1, $2, $3, $4, 'running', $5)
+            RETURNING id, kind, user_id, requested_by, status, robots_total, robots_processed, report, error, started_at, finished_at
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(kind)
+        .bind(user_id)
+        .bind(requested_by)
+        .bind(Utc::now())
+        .fetch_one(pool)
+        .await
+        .db_op("integrity_runs.create")
+    }
+
+    pub async fn find_by_id(pool: &PgPool, id: Uuid) -> Result<Option<IntegrityRun>> {
+        sqlx::query_as::<_, IntegrityRun>(
+            "SELECT id, kind, user_id, requested_by, status, robots_total, robots_processed, report, error, started_at, finished_at FROM integrity_runs WHERE id = $1",
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+        .db_op("integrity_runs.find_by_id")
+    }
+
+    pub async fn update_progress(pool: &PgPool, id: Uuid, processed: i32, total: i32) -> Result<()> {
+        sqlx::query("UPDATE integrity_runs SET robots_processed = $1, robots_total = $2 WHERE id = $3")
+            .bind(processed)
+            .bind(total)
+            .bind(id)
+            .execute(pool)
+            .await
+            .db_op("integrity_runs.update_progress")?;
+
+        Ok(())
+    }
+
+    pub async fn finish(pool: &PgPool, id: Uuid, report: &serde_json::Value) -> Result<()> {
+        sqlx::query("UPDATE integrity_runs SET status = 'completed', report = $1, finished_at = $2 WHERE id = $3")
+            .bind(report)
+            .bind(Utc::now())
+            .bind(id)
+            .execute(pool)
+            .await
+            .db_op("integrity_runs.finish")?;
+
+        Ok(())
+    }
+
+    pub async fn fail(pool: &PgPool, id: Uuid, error: &str) -> Result<()> {
+        sqlx::query("UPDATE integrity_runs SET status = 'failed', error = $1, finished_at = $2 WHERE id = $3")
+            .bind(error)
+            .bind(Utc::now())
+            .bind(id)
+            .execute(pool)
+            .await
+            .db_op("integrity_runs.fail")?;
+
+        Ok(())
+    }
+
+    // Robots in scope, in a stable order so progress is meaningful
+    pub async fn robot_ids(pool: &PgPool, user_id: Option<Uuid>) -> Result<Vec<Uuid>> {
+        sqlx::query_scalar("SELECT id FROM trading_robots WHERE $1::UUID IS NULL OR user_id = $1 ORDER BY id")
+            .bind(user_id)
+            .fetch_all(pool)
+            .await
+            .db_op("integrity_runs.robot_ids")
+    }
+
+    pub async fn load_batch(pool: &PgPool, robot_ids: &[Uuid]) -> Result<IntegrityBatch> {
+        let robots = sqlx::query_as::<_, StoredRobotTotals>(
+            r#"
+            SELECT
+                id,
+                user_id,
+                COALESCE(total_trades, 0) AS total_trades,
+                COALESCE((performance_metrics->>'winning_trades')::INT, 0) AS winning_trades,
+                COALESCE((performance_metrics->>'total_profit')::FLOAT8, 0) AS total_profit,
+                status IN ('active', 'paused_risk', 'paused_broker', 'cooling_down') AS running,
+                risk_config
+            FROM trading_robots
+            WHERE id = ANY($1)
+            "#,
+        )
+        .bind(robot_ids)
+        .fetch_all(pool)
+        .await
+        .db_op("integrity_runs.load_robots")?;
+
+        let sessions = sqlx::query_as::<_, StoredSessionTotals>(
+            r#"
+            SELECT
+                id,
+                robot_id,
+                started_at,
+                ended_at,
+                COALESCE(total_trades, 0) AS total_trades,
+                COALESCE(winning_trades, 0) AS winning_trades,
+                COALESCE(total_profit, 0)::FLOAT8 AS total_profit
+            FROM trading_sessions
+            WHERE robot_id = ANY($1)
+            "#,
+        )
+        .bind(robot_ids)
+        .fetch_all(pool)
+        .await
+        .db_op("integrity_runs.load_sessions")?;
+
+        let trades = sqlx::query_as::<_, ClosedTradeRow>(
+            "SELECT id, robot_id, symbol, is_demo, volume::FLOAT8 AS volume, profit_loss::FLOAT8 AS profit_loss, opened_at, closed_at FROM trades WHERE robot_id = ANY($1) AND status = 'closed'",
+        )
+        .bind(robot_ids)
+        .fetch_all(pool)
+        .await
+        .db_op("integrity_runs.load_trades")?;
+
+        let facts = TradeDailyFact::find_by_robot_ids(pool, robot_ids).await?;
+
+        Ok(IntegrityBatch { robots, sessions, trades, facts })
+    }
+
+    // Writes one batch of corrections atomically
+    pub async fn apply_corrections(pool: &PgPool, corrections: &[TotalsCorrection]) -> Result<()> {
+        let mut tx = pool.begin().await.db_op("integrity_runs.apply_corrections")?;
+        let mut fact_robots = Vec::new();
+
+        for correction in corrections {
+            match correction {
+                TotalsCorrection::Robot { robot_id, totals } => {
+                    sqlx::query(
+                        r#"
+                        UPDATE trading_robots SET
+                            total_trades = $1,
+                            performance_metrics = COALESCE(performance_metrics, '{}'::jsonb) || jsonb_build_object(
+                                'total_profit', $2::FLOAT8,
+                                'winning_trades', $3::INT
+                            ) || CASE
+                                WHEN performance_metrics ? 'allocation_baseline' THEN jsonb_build_object(
+                                    'virtual_equity',
+                                    (performance_metrics->>'allocation_baseline')::FLOAT8 + $2::FLOAT8
+                                        - COALESCE((performance_metrics->>'allocation_profit_offset')::FLOAT8, 0)
+                                )
+                                ELSE '{}'::jsonb
+                            END,
+                            updated_at = NOW()
+                        WHERE id = $4
+                        "#,
+                    )
+                    .bind(totals.total_trades)
+                    .bind(totals.total_profit)
+                    .bind(totals.winning_trades)
+                    .bind(robot_id)
+                    .execute(&mut *tx)
+                    .await
+                    .db_op("integrity_runs.correct_robot")?;
+                }
+                TotalsCorrection::Session { session_id, totals } => {
+                    sqlx::query(
+                        "UPDATE trading_sessions SET total_trades = $1, winning_trades = $2, total_profit = $3, updated_at = NOW() WHERE id = $4",
+                    )
+                    .bind(totals.total_trades)
+                    .bind(totals.winning_trades)
+                    .bind(totals.total_profit)
+                    .bind(session_id)
+                    .execute(&mut *tx)
+                    .await
+                    .db_op("integrity_runs.correct_session")?;
+                }
+                TotalsCorrection::Facts { robot_id } => fact_robots.push(*robot_id),
+            }
+        }
+        if !fact_robots.is_empty() {
+            TradeDailyFact::rebuild_robots(&mut tx, &fact_robots).await?;
+        }
+
+        tx.commit().await.db_op("integrity_runs.apply_corrections")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,248 @@
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+use crate::errors::{DbOp, Result};
+
+pub const JOB_QUEUED: &str = "queued";
+pub const JOB_RUNNING: &str = "running";
+pub const JOB_COMPLETED: &str = "completed";
+pub const JOB_FAILED: &str = "failed";
+pub const JOB_CANCELLED: &str = "cancelled";
+
+#[derive(Debug, Clone, PartialEq, Serialize, FromRow, JsonSchema)]
+pub struct Job {
+    pub id: Uuid,
+    #[serde(skip)]
+    pub user_id: Uuid,
+    // export | backtest | import
+    pub kind: String,
+    // queued | running | completed | failed | cancelled
+    pub status: String,
+    // Percent complete
+    pub progress: f64,
+    // What the worker last saved about how far it got; kept when the job is cancelled
+    pub checkpoint: serde_json::Value,
+    // Where the output of a completed job can be read
+    pub result_ref: Option<String>,
+    pub error: Option<String>,
+    // Set once a cancel was requested; the status changes when the worker acknowledges it
+    pub cancel_requested_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    #[serde(skip)]
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct JobKindUsage {
+    pub kind: String,
+    pub running_jobs: i64,
+    pub users: i64,
+}
+
+const COLUMNS: &str =
+    "id, user_id, kind, status, progress, checkpoint, result_ref, error, cancel_requested_at, created_at, started_at, finished_at, updated_at";
+
+impl Job {
+    pub fn new(user_id: Uuid, kind: &str, now: DateTime<Utc>) -> Self {
+        Job {
+            id: Uuid::new_v4(),
+            user_id,
+            kind: kind.to_string(),
+            status: JOB_QUEUED.to_string(),
+            progress: 0.0,
+            checkpoint: serde_json::json!({}),
+            result_ref: None,
+            error: None,
+            cancel_requested_at: None,
+            created_at: now,
+            started_at: None,
+            finished_at: None,
+            updated_at: now,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.status == JOB_QUEUED || self.status == JOB_RUNNING
+    }
+
+    // Inserts the job unless its owner already has `limit` active jobs of its kind, whose ids are
+    // returned instead. The advisory lock serializes registrations per user and kind.
+    pub async fn register(pool: &PgPool, job: &Job, limit: i64) -> Result<std::result::Result<(), Vec<Uuid>>> {
+        let mut tx = pool.begin().await.db_op("jobs.register")?;
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtextextended($1, 0))")
+            .bind(format!("jobs:{}:{}", job.user_id, job.kind))
+            .execute(&mut *tx)
+            .await
+            .db_op("jobs.register_lock")?;
+
+        let running: Vec<Uuid> = sqlx::query_scalar(
+            "SELECT id FROM jobs WHERE user_id = $1 AND kind = $2 AND status IN ('queued', 'running') ORDER BY created_at",
+        )
+        .bind(job.user_id)
+        .bind(&job.kind)
+        .fetch_all(&mut *tx)
+        .await
+        .db_op("jobs.register_running")?;
+        if running.len() as i64 >= limit {
+            return Ok(Err(running));
+        }
+
+        sqlx::query(
+            "INSERT INTO jobs (id, user_id, kind, status, progress, checkpoint, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $7)",
+        )
+        .bind(job.id)
+        .bind(job.user_id)
+        .bind(&job.kind)
+        .bind(&job.status)
+        .bind(job.progress)
+        .bind(&job.checkpoint)
+        .bind(job.created_at)
+        .execute(&mut *tx)
+        .await
+        .db_op("jobs.register")?;
+        tx.commit().await.db_op("jobs.register")?;
+        Ok(Ok(()))
+    }
+
+    pub async fn start(pool: &PgPool, id: Uuid, now: DateTime<Utc>) -> Result<()> {
+        sqlx::query("UPDATE jobs SET status = 'running', started_at = $2, updated_at = $2 WHERE id = $1 AND status = 'queued'")
+            .bind(id)
+            .bind(now)
+            .execute(pool)
+            .await
+            .db_op("jobs.start")?;
+        Ok(())
+    }
+
+    // Returns whether a cancel has been requested for the job
+    pub async fn checkpoint(pool: &PgPool, id: Uuid, progress: f64, checkpoint: &serde_json::Value, now: DateTime<Utc>) -> Result<bool> {
+        let cancel_requested: Option<bool> = sqlx::query_scalar(
+            r#"
+            UPDATE jobs SET progress = $2, checkpoint = $3, updated_at = $4
+            WHERE id = $1 AND status IN ('queued', 'running')
+            RETURNING cancel_requested_at IS NOT NULL
+            "#,
+        )
+        .bind(id)
+        .bind(progress)
+        .bind(checkpoint)
+        .bind(now)
+        .fetch_optional(pool)
+        .await
+        .db_op("jobs.checkpoint")?;
+        Ok(cancel_requested.unwrap_or(false))
+    }
+
+    // Only active jobs finish; a completed job reports 100%, the others keep their last progress
+    pub async fn finish(
+        pool: &PgPool,
+        id: Uuid,
+        status: &str,
+        result_ref: Option<&str>,
+        error: Option<&str>,
+        now: DateTime<Utc>,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE jobs SET
+                status = $2,
+                progress = CASE WHEN $2 = 'completed' THEN 100 ELSE progress END,
+                result_ref = $3,
+                error = $4,
+                finished_at = $5,
+                updated_at = $5
+            WHERE id = $1 AND status IN ('queued', 'running')
+            "#,
+        )
+        .bind(id)
+        .bind(status)
+        .bind(result_ref)
+        .bind(error)
+        .bind(now)
+        .execute(pool)
+        .await
+        .db_op("jobs.finish")?;
+        Ok(())
+    }
+
+    // None when the job is not the user's or has already finished
+    pub async fn request_cancel(pool: &PgPool, user_id: Uuid, id: Uuid, now: DateTime<Utc>) -> Result<Option<Job>> {
+        sqlx::query_as::<_, Job>(&format!(
+            r#"
+            UPDATE jobs SET cancel_requested_at = COALESCE(cancel_requested_at, $3)
+            WHERE id = $1 AND user_id = $2 AND status IN ('queued', 'running')
+            RETURNING {}
+            "#,
+            COLUMNS
+        ))
+        .bind(id)
+        .bind(user_id)
+        .bind(now)
+        .fetch_optional(pool)
+        .await
+        .db_op("jobs.request_cancel")
+    }
+
+    pub async fn find(pool: &PgPool, user_id: Uuid, id: Uuid) -> Result<Option<Job>> {
+        sqlx::query_as::<_, Job>(&format!("SELECT {} FROM jobs WHERE id = $1 AND user_id = $2", COLUMNS))
+            .bind(id)
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await
+            .db_op("jobs.find")
+    }
+
+    // Newest first
+    pub async fn find_by_user(pool: &PgPool, user_id: Uuid, limit: i64) -> Result<Vec<Job>> {
+        sqlx::query_as::<_, Job>(&format!(
+            "SELECT {} FROM jobs WHERE user_id = $1 ORDER BY created_at DESC LIMIT $2",
+            COLUMNS
+        ))
+        .bind(user_id)
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+        .db_op("jobs.find_by_user")
+    }
+
+    pub async fn usage(pool: &PgPool) -> Result<Vec<JobKindUsage>> {
+        sqlx::query_as::<_, JobKindUsage>(
+            r#"
+            SELECT kind, COUNT(*) AS running_jobs, COUNT(DISTINCT user_id) AS users
+            FROM jobs
+            WHERE status IN ('queued', 'running')
+            GROUP BY kind
+            ORDER BY kind
+            "#,
+        )
+        .fetch_all(pool)
+        .await
+        .db_op("jobs.usage")
+    }
+
+    // Active jobs that have not checkpointed since `before`, e.g. because their process restarted.
+    // A job with a pending cancel counts as cancelled.
+    pub async fn fail_stale(pool: &PgPool, before: DateTime<Utc>, now: DateTime<Utc>) -> Result<u64> {
+        let result = sqlx::query(
+            r#"
+            UPDATE jobs SET
+                status = CASE WHEN cancel_requested_at IS NULL THEN 'failed' ELSE 'cancelled' END,
+                error = CASE WHEN cancel_requested_at IS NULL THEN 'The job stopped reporting progress' END,
+                finished_at = $2,
+                updated_at = $2
+            WHERE status IN ('queued', 'running') AND updated_at < $1
+            "#,
+        )
+        .bind(before)
+        .bind(now)
+        .execute(pool)
+        .await
+        .db_op("jobs.fail_stale")?;
+        Ok(result.rows_affected())
+    }
+}
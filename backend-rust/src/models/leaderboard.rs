@@ -0,0 +1,204 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool, QueryBuilder};
+use uuid::Uuid;
+
+use crate::errors::{DbOp, Result};
+
+// A closed live trade of the week; `shared` is whether its owner opted in
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct LeaderboardTrade {
+    pub robot_id: Uuid,
+    pub user_id: Uuid,
+    pub shared: bool,
+    pub strategy: String,
+    pub trade_type: String,
+    pub entry_price: f64,
+    pub exit_price: f64,
+}
+
+// A robot's place in a week, as stored
+#[derive(Debug, Clone, PartialEq)]
+pub struct RankedRobot {
+    pub robot_id: Uuid,
+    pub user_id: Uuid,
+    pub rank: i32,
+    pub label: String,
+    pub strategy: String,
+    pub return_pct: f64,
+    pub win_rate: f64,
+    pub trade_count: i32,
+    pub score: f64,
+}
+
+// What anyone may see: no ids, symbols, sizes or balances
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema, FromRow)]
+pub struct LeaderboardEntry {
+    pub rank: i32,
+    pub label: String,
+    pub strategy: String,
+    pub return_pct: f64,
+    pub win_rate: f64,
+    pub trade_count: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema, FromRow)]
+pub struct OwnLeaderboardEntry {
+    pub robot_id: Uuid,
+    pub robot_name: String,
+    pub rank: i32,
+    pub label: String,
+    pub strategy: String,
+    pub return_pct: f64,
+    pub win_rate: f64,
+    pub trade_count: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct LeaderboardSharing {
+    pub share_performance_anonymously: bool,
+}
+
+// The latest published week; empty until the first one is computed
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct PublicLeaderboard {
+    pub week_start: Option<NaiveDate>,
+    pub entries: Vec<LeaderboardEntry>,
+}
+
+// `own` lists the caller's ranked robots, and stays empty unless they opted in
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct UserLeaderboard {
+    pub week_start: Option<NaiveDate>,
+    pub opted_in: bool,
+    pub entries: Vec<LeaderboardEntry>,
+    pub own: Vec<OwnLeaderboardEntry>,
+}
+
+pub struct Leaderboard;
+
+impl Leaderboard {
+    pub async fn closed_trades(pool: &PgPool, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<LeaderboardTrade>> {
+        sqlx::query_as::<_, LeaderboardTrade>(
+            r#"
+            SELECT t.robot_id, t.user_id, u.share_performance_anonymously AS shared, r.strategy, t.trade_type,
+                   t.entry_price::FLOAT8 AS entry_price, t.exit_price::FLOAT8 AS exit_price
+            FROM trades t
+            JOIN trading_robots r ON r.id = t.robot_id
+            JOIN users u ON u.id = t.user_id
+            WHERE t.status = 'closed' AND t.is_demo = FALSE AND t.exit_price IS NOT NULL AND t.entry_price > 0
+              AND t.closed_at >= $1 AND t.closed_at < $2
+            "#,
+        )
+        .bind(from)
+        .bind(to)
+        .fetch_all(pool)
+        .await
+        .db_op("leaderboard.closed_trades")
+    }
+
+    pub async fn has_week(pool: &PgPool, week_start: NaiveDate) -> Result<bool> {
+        sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM leaderboard_entries WHERE week_start = $1)")
+            .bind(week_start)
+            .fetch_one(pool)
+            .await
+            .db_op("leaderboard.has_week")
+    }
+
+    pub async fn labels(pool: &PgPool, robot_ids: &[Uuid]) -> Result<Vec<(Uuid, i32)>> {
+        sqlx::query_as("SELECT robot_id, label_number FROM leaderboard_labels WHERE robot_id = ANY($1)")
+            .bind(robot_ids)
+            .fetch_all(pool)
+            .await
+            .db_op("leaderboard.labels")
+    }
+
+    // False when the robot already has a label or the number is taken
+    pub async fn assign_label(pool: &PgPool, robot_id: Uuid, label_number: i32) -> Result<bool> {
+        let result = sqlx::query("INSERT INTO leaderboard_labels (robot_id, label_number) VALUES ($1, $2) ON CONFLICT DO NOTHING")
+            .bind(robot_id)
+            .bind(label_number)
+            .execute(pool)
+            .await
+            .db_op("leaderboard.assign_label")?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    // Replaces the week's ranking
+    pub async fn save_week(pool: &PgPool, week_start: NaiveDate, robots: &[RankedRobot]) -> Result<()> {
+        let mut tx = pool.begin().await.db_op("leaderboard.save_week")?;
+
+        sqlx::query("DELETE FROM leaderboard_entries WHERE week_start = $1")
+            .bind(week_start)
+            .execute(&mut *tx)
+            .await
+            .db_op("leaderboard.save_week")?;
+
+        if !robots.is_empty() {
+            let mut builder = QueryBuilder::new(
+                "INSERT INTO leaderboard_entries (week_start, robot_id, user_id, rank, label, strategy, return_pct, win_rate, trade_count, score) ",
+            );
+            builder.push_values(robots, |mut row, robot| {
+                row.push_bind(week_start)
+                    .push_bind(robot.robot_id)
+                    .push_bind(robot.user_id)
+                    .push_bind(robot.rank)
+                    .push_bind(&robot.label)
+                    .push_bind(&robot.strategy)
+                    .push_bind(robot.return_pct)
+                    .push_bind(robot.win_rate)
+                    .push_bind(robot.trade_count)
+                    .push_bind(robot.score);
+            });
+            builder.build().execute(&mut *tx).await.db_op("leaderboard.save_week")?;
+        }
+
+        tx.commit().await.db_op("leaderboard.save_week")?;
+        Ok(())
+    }
+
+    pub async fn latest_week(pool: &PgPool) -> Result<Option<NaiveDate>> {
+        sqlx::query_scalar("SELECT MAX(week_start) FROM leaderboard_entries")
+            .fetch_one(pool)
+            .await
+            .db_op("leaderboard.latest_week")
+    }
+
+    // Users who opted out since the week was computed are left out
+    pub async fn top(pool: &PgPool, week_start: NaiveDate, limit: i64) -> Result<Vec<LeaderboardEntry>> {
+        sqlx::query_as::<_, LeaderboardEntry>(
+            r#"
+            SELECT e.rank, e.label, e.strategy, e.return_pct, e.win_rate, e.trade_count
+            FROM leaderboard_entries e
+            JOIN users u ON u.id = e.user_id
+            WHERE e.week_start = $1 AND u.share_performance_anonymously = TRUE
+            ORDER BY e.rank
+            LIMIT $2
+            "#,
+        )
+        .bind(week_start)
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+        .db_op("leaderboard.top")
+    }
+
+    pub async fn own(pool: &PgPool, week_start: NaiveDate, user_id: Uuid) -> Result<Vec<OwnLeaderboardEntry>> {
+        sqlx::query_as::<_, OwnLeaderboardEntry>(
+            r#"
+            SELECT e.robot_id, r.name AS robot_name, e.rank, e.label, e.strategy, e.return_pct, e.win_rate, e.trade_count
+            FROM leaderboard_entries e
+            JOIN trading_robots r ON r.id = e.robot_id
+            WHERE e.week_start = $1 AND e.user_id = $2
+            ORDER BY e.rank
+            "#,
+        )
+        .bind(week_start)
+        .bind(user_id)
+        .fetch_all(pool)
+        .await
+        .db_op("leaderboard.own")
+    }
+}
@@ -0,0 +1,54 @@
+use chrono::{DateTime, Utc};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+use crate::errors::{DbOp, Result};
+
+// An account at a sign-in provider, linked to the user it signs in as
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct LinkedAccount {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub provider: String,
+    pub external_id: String,
+    pub email: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl LinkedAccount {
+    // The user the provider's account is linked to
+    pub async fn find_user_id(pool: &PgPool, provider: &str, external_id: &str) -> Result<Option<Uuid>> {
+        sqlx::query_scalar::<_, Uuid>("SELECT user_id FROM linked_accounts WHERE provider = $1 AND external_id = $2")
+            .bind(provider)
+            .bind(external_id)
+            .fetch_optional(pool)
+            .await
+            .db_op("linked_accounts.find_user_id")
+    }
+
+    pub async fn find_by_user(pool: &PgPool, user_id: Uuid) -> Result<Vec<LinkedAccount>> {
+        sqlx::query_as::<_, LinkedAccount>(
+            "SELECT id, user_id, provider, external_id, email, created_at FROM linked_accounts WHERE user_id = $1 ORDER BY created_at",
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await
+        .db_op("linked_accounts.find_by_user")
+    }
+
+    // Returns false when the user is already linked to another account of the provider, or the
+    // account to another user
+    pub async fn link(pool: &PgPool, user_id: Uuid, provider: &str, external_id: &str, email: &str) -> Result<bool> {
+        let result = sqlx::query(
+            "INSERT INTO linked_accounts (user_id, provider, external_id, email) VALUES ($1, $2, $3, $4) ON CONFLICT DO NOTHING",
+        )
+        .bind(user_id)
+        .bind(provider)
+        .bind(external_id)
+        .bind(email)
+        .execute(pool)
+        .await
+        .db_op("linked_accounts.link")?;
+        Ok(result.rows_affected() > 0)
+    }
+}
@@ -0,0 +1,166 @@
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::errors::{DbOp, Result};
+
+pub const DEFAULT_LOCALE: &str = "en";
+
+// One saved version of an email's copy; at most one version per key and locale is active
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema, FromRow)]
+pub struct MessageTemplate {
+    pub id: Uuid,
+    pub key: String,
+    pub locale: String,
+    pub subject: String,
+    pub body: String,
+    pub version: i32,
+    pub is_active: bool,
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+// Saved as the next version, inactive until activated
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Validate)]
+pub struct SaveTemplateRequest {
+    // en by default
+    #[validate(length(min = 2, max = 10))]
+    pub locale: Option<String>,
+    #[validate(length(min = 1, max = 500))]
+    pub subject: String,
+    #[validate(length(min = 1, max = 100000))]
+    pub body: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ActivateTemplateRequest {
+    pub locale: Option<String>,
+    pub version: i32,
+}
+
+// With subject and body, renders that draft; otherwise the given version, or what would be sent now
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct PreviewTemplateRequest {
+    pub locale: Option<String>,
+    pub version: Option<i32>,
+    pub subject: Option<String>,
+    pub body: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct RenderedTemplate {
+    pub key: String,
+    pub locale: String,
+    // None when the compiled default was used
+    pub version: Option<i32>,
+    pub subject: String,
+    pub body: String,
+}
+
+const TEMPLATE_COLUMNS: &str = "id, key, locale, subject, body, version, is_active, created_by, created_at";
+
+impl MessageTemplate {
+    pub async fn find_all(pool: &PgPool) -> Result<Vec<MessageTemplate>> {
+        sqlx::query_as::<_, MessageTemplate>(&format!(
+            "SELECT {} FROM message_templates ORDER BY key, locale, version DESC",
+            TEMPLATE_COLUMNS
+        ))
+        .fetch_all(pool)
+        .await
+        .db_op("message_templates.find_all")
+    }
+
+    pub async fn find_active(pool: &PgPool, key: &str, locale: &str) -> Result<Option<MessageTemplate>> {
+        sqlx::query_as::<_, MessageTemplate>(&format!(
+            "SELECT {} FROM message_templates WHERE key = $1 AND locale = $2 AND is_active = TRUE",
+            TEMPLATE_COLUMNS
+        ))
+        .bind(key)
+        .bind(locale)
+        .fetch_optional(pool)
+        .await
+        .db_op("message_templates.find_active")
+    }
+
+    pub async fn find_version(pool: &PgPool, key: &str, locale: &str, version: i32) -> Result<Option<MessageTemplate>> {
+        sqlx::query_as::<_, MessageTemplate>(&format!(
+            "SELECT {} FROM message_templates WHERE key = $1 AND locale = $2 AND version = $3",
+            TEMPLATE_COLUMNS
+        ))
+        .bind(key)
+        .bind(locale)
+        .bind(version)
+        .fetch_optional(pool)
+        .await
+        .db_op("message_templates.find_version")
+    }
+
+    // Numbered after the latest version of the key and locale; the unique key catches a concurrent save
+    pub async fn create_version(
+        pool: &PgPool,
+        key: &str,
+        locale: &str,
+        subject: &str,
+        body: &str,
+        created_by: Uuid,
+    ) -> Result<MessageTemplate> {
+        sqlx::query_as::<_, MessageTemplate>(&format!(
+            r#"
+            INSERT INTO message_templates (id, key, locale, subject, body, version, is_active, created_by, created_at)
+            SELECT $1, $2, $3, $4, $5, COALESCE(MAX(version), 0) + 1, FALSE, $6, NOW()
+            FROM message_templates WHERE key = $2 AND locale = $3
+            RETURNING {}
+            "#,
+            TEMPLATE_COLUMNS
+        ))
+        .bind(Uuid::new_v4())
+        .bind(key)
+        .bind(locale)
+        .bind(subject)
+        .bind(body)
+        .bind(created_by)
+        .fetch_one(pool)
+        .await
+        .db_op("message_templates.create_version")
+    }
+
+    // Makes `version` the only active one; None when it does not exist
+    pub async fn activate(pool: &PgPool, key: &str, locale: &str, version: i32) -> Result<Option<MessageTemplate>> {
+        let mut tx = pool.begin().await.db_op("message_templates.activate")?;
+
+        let exists: Option<i32> =
+            sqlx::query_scalar("SELECT version FROM message_templates WHERE key = $1 AND locale = $2 AND version = $3 FOR UPDATE")
+                .bind(key)
+                .bind(locale)
+                .bind(version)
+                .fetch_optional(&mut *tx)
+                .await
+                .db_op("message_templates.activate")?;
+        if exists.is_none() {
+            return Ok(None);
+        }
+
+        sqlx::query("UPDATE message_templates SET is_active = FALSE WHERE key = $1 AND locale = $2 AND is_active = TRUE")
+            .bind(key)
+            .bind(locale)
+            .execute(&mut *tx)
+            .await
+            .db_op("message_templates.activate")?;
+        let activated = sqlx::query_as::<_, MessageTemplate>(&format!(
+            "UPDATE message_templates SET is_active = TRUE WHERE key = $1 AND locale = $2 AND version = $3 RETURNING {}",
+            TEMPLATE_COLUMNS
+        ))
+        .bind(key)
+        .bind(locale)
+        .bind(version)
+        .fetch_one(&mut *tx)
+        .await
+        .db_op("message_templates.activate")?;
+
+        tx.commit().await.db_op("message_templates.activate")?;
+        Ok(Some(activated))
+    }
+}
@@ -4,6 +4,38 @@ pub mod broker_connection;
 pub mod trading_robot;
 pub mod trade;
 pub mod trading_session;
+pub mod filter_preset;
+pub mod robot_log;
+pub mod feature_flag;
+pub mod onboarding_email;
+pub mod integrity_run;
+pub mod robot_change;
+pub mod account_snapshot;
+pub mod watchlist;
+pub mod email_outbox;
+pub mod delegation;
+pub mod platform_stats;
+pub mod admin_setting;
+pub mod incident;
+pub mod checkout_session;
+pub mod admin_user;
+pub mod trade_review;
+pub mod activation_nudge;
+pub mod leaderboard;
+pub mod bridge;
+pub mod message_template;
+pub mod statement;
+pub mod trade_daily_fact;
+pub mod job;
+pub mod trade_origin;
+pub mod trade_ai_decision;
+pub mod trade_correction;
+pub mod audit_entry;
+pub mod password_reset;
+pub mod api_key;
+pub mod user_session;
+pub mod auth_event;
+pub mod linked_account;
 
 pub use user::*;
 pub use subscription::*;
@@ -12,3 +44,35 @@ pub use trading_robot::*;
 pub use trade::*;
 pub use trade::TradeStatistics;
 pub use trading_session::*;
+pub use filter_preset::*;
+pub use robot_log::*;
+pub use feature_flag::*;
+pub use onboarding_email::*;
+pub use integrity_run::*;
+pub use robot_change::*;
+pub use account_snapshot::*;
+pub use watchlist::*;
+pub use email_outbox::*;
+pub use delegation::*;
+pub use platform_stats::*;
+pub use admin_setting::*;
+pub use incident::*;
+pub use checkout_session::*;
+pub use admin_user::*;
+pub use trade_review::*;
+pub use activation_nudge::*;
+pub use leaderboard::*;
+pub use bridge::*;
+pub use message_template::*;
+pub use statement::*;
+pub use trade_daily_fact::*;
+pub use job::*;
+pub use trade_origin::*;
+pub use trade_ai_decision::*;
+pub use trade_correction::*;
+pub use audit_entry::*;
+pub use password_reset::*;
+pub use api_key::*;
+pub use user_session::*;
+pub use auth_event::*;
+pub use linked_account::*;
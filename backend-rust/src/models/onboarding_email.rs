@@ -0,0 +1,75 @@
+use chrono::{DateTime, Utc};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+use crate::errors::{DbOp, Result};
+
+// A user still inside the onboarding window, with what they have done so far
+#[derive(Debug, Clone, FromRow)]
+pub struct OnboardingCandidate {
+    pub user_id: Uuid,
+    pub email: String,
+    pub created_at: DateTime<Utc>,
+    pub unsubscribe_token: Uuid,
+    pub has_broker: bool,
+    pub has_robot: bool,
+    pub has_trade: bool,
+    pub sent_steps: Vec<String>,
+}
+
+pub struct OnboardingEmail;
+
+impl OnboardingEmail {
+    // Users that registered after `since`, have not opted out and have not traded yet
+    pub async fn find_candidates(pool: &PgPool, since: DateTime<Utc>) -> Result<Vec<OnboardingCandidate>> {
+        sqlx::query_as::<_, OnboardingCandidate>(
+            r#"
+            SELECT
+                u.id AS user_id,
+                u.email,
+                u.created_at,
+                u.unsubscribe_token,
+                EXISTS (SELECT 1 FROM broker_connections b WHERE b.user_id = u.id) AS has_broker,
+                EXISTS (SELECT 1 FROM trading_robots r WHERE r.user_id = u.id) AS has_robot,
+                EXISTS (SELECT 1 FROM trades t WHERE t.user_id = u.id) AS has_trade,
+                ARRAY(SELECT o.step::TEXT FROM onboarding_emails o WHERE o.user_id = u.id) AS sent_steps
+            FROM users u
+            WHERE u.created_at >= $1
+              AND u.onboarding_emails = TRUE
+              AND COALESCE(u.is_active, TRUE) = TRUE
+              AND NOT EXISTS (SELECT 1 FROM trades t WHERE t.user_id = u.id)
+            "#,
+        )
+        .bind(since)
+        .fetch_all(pool)
+        .await
+        .db_op("onboarding_emails.find_candidates")
+    }
+
+    // Returns false when the step was already recorded, e.g. by another instance
+    pub async fn claim(pool: &PgPool, user_id: Uuid, step: &str) -> Result<bool> {
+        let result = sqlx::query(
+            "INSERT INTO onboarding_emails (user_id, step, sent_at) VALUES ($1, $2, $3) ON CONFLICT (user_id, step) DO NOTHING",
+        )
+        .bind(user_id)
+        .bind(step)
+        .bind(Utc::now())
+        .execute(pool)
+        .await
+        .db_op("onboarding_emails.claim")?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    // Undoes a claim whose email could not be sent so the next run retries it
+    pub async fn release(pool: &PgPool, user_id: Uuid, step: &str) -> Result<()> {
+        sqlx::query("DELETE FROM onboarding_emails WHERE user_id = $1 AND step = $2")
+            .bind(user_id)
+            .bind(step)
+            .execute(pool)
+            .await
+            .db_op("onboarding_emails.release")?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+use chrono::{DateTime, Duration, Utc};
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+use crate::errors::{AppError, DbOp, Result};
+
+pub const PASSWORD_RESET_VALID_MINUTES: i64 = 60;
+
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct PasswordReset {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub token_hash: String,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+const COLUMNS: &str = "id, user_id, token_hash, expires_at, used_at, created_at";
+
+impl PasswordReset {
+    pub fn hash_token(token: &str) -> String {
+        hex::encode(Sha256::digest(token.as_bytes()))
+    }
+
+    // Why the token can no longer be used, if it can't
+    pub fn check_usable(&self, now: DateTime<Utc>) -> Result<()> {
+        if self.used_at.is_some() {
+            return Err(AppError::Validation("This password reset link has already been used".to_string()));
+        }
+        if self.expires_at <= now {
+            return Err(AppError::Validation("This password reset link has expired; request a new one".to_string()));
+        }
+        Ok(())
+    }
+
+    // Returns the token to send; only its hash is stored
+    pub async fn create(pool: &PgPool, user_id: Uuid, now: DateTime<Utc>) -> Result<String> {
+        let token = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+        sqlx::query("INSERT INTO password_resets (user_id, token_hash, expires_at, created_at) VALUES ($1, $2, $3, $4)")
+            .bind(user_id)
+            .bind(Self::hash_token(&token))
+            .bind(now + Duration::minutes(PASSWORD_RESET_VALID_MINUTES))
+            .bind(now)
+            .execute(pool)
+            .await
+            .db_op("password_resets.create")?;
+        Ok(token)
+    }
+
+    pub async fn find_by_token(pool: &PgPool, token: &str) -> Result<Option<PasswordReset>> {
+        sqlx::query_as::<_, PasswordReset>(&format!("SELECT {} FROM password_resets WHERE token_hash = $1", COLUMNS))
+            .bind(Self::hash_token(token))
+            .fetch_optional(pool)
+            .await
+            .db_op("password_resets.find_by_token")
+    }
+
+    // Uses the token up and sets the password in one transaction. False when the token was used
+    // or expired in the meantime, in which case the password is left alone.
+    pub async fn redeem(pool: &PgPool, id: Uuid, password: &str, now: DateTime<Utc>) -> Result<bool> {
+        let mut tx = pool.begin().await.db_op("password_resets.redeem")?;
+        let user_id: Option<Uuid> = sqlx::query_scalar(
+            "UPDATE password_resets SET used_at = $2 WHERE id = $1 AND used_at IS NULL AND expires_at > $2 RETURNING user_id",
+        )
+        .bind(id)
+        .bind(now)
+        .fetch_optional(&mut *tx)
+        .await
+        .db_op("password_resets.redeem")?;
+        let Some(user_id) = user_id else {
+            return Ok(false);
+        };
+
+        // Stored the way User::set_password stores it
+        sqlx::query("UPDATE users SET password_hash = $2, updated_at = $3 WHERE id = $1")
+            .bind(user_id)
+            .bind(password)
+            .bind(now)
+            .execute(&mut *tx)
+            .await
+            .db_op("password_resets.redeem")?;
+        tx.commit().await.db_op("password_resets.redeem")?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reset(expires_in_minutes: i64, used: bool) -> PasswordReset {
+        let now = Utc::now();
+        PasswordReset {
+            id: Uuid::new_v4(),
+            user_id: Uuid::new_v4(),
+            token_hash: PasswordReset::hash_token("token"),
+            expires_at: now + Duration::minutes(expires_in_minutes),
+            used_at: used.then_some(now),
+            created_at: now,
+        }
+    }
+
+    #[test]
+    fn test_used_and_expired_tokens_are_refused() {
+        let now = Utc::now();
+        assert!(reset(30, false).check_usable(now).is_ok());
+
+        let used = reset(30, true).check_usable(now).unwrap_err();
+        assert!(matches!(&used, AppError::Validation(m) if m.contains("already been used")));
+        let expired = reset(-1, false).check_usable(now).unwrap_err();
+        assert!(matches!(&expired, AppError::Validation(m) if m.contains("expired")));
+    }
+
+    #[test]
+    fn test_only_the_hash_is_kept() {
+        let hash = PasswordReset::hash_token("a-token");
+        assert_eq!(hash.len(), 64);
+        assert_ne!(hash, PasswordReset::hash_token("another-token"));
+        assert_eq!(hash, PasswordReset::hash_token("a-token"));
+    }
+}
@@ -0,0 +1,184 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+use schemars::JsonSchema;
+use sqlx::{FromRow, PgPool};
+
+use crate::errors::{DbOp, Result};
+
+pub const STATS_SOURCE_LIVE: &str = "live";
+pub const STATS_SOURCE_BACKFILL: &str = "backfill";
+
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema, FromRow)]
+pub struct PlatformStatsDay {
+    pub stat_date: NaiveDate,
+    pub total_users: i64,
+    pub active_users: Option<i64>,
+    pub total_robots: i64,
+    pub active_robots: Option<i64>,
+    pub total_trades: i64,
+    #[serde(serialize_with = "crate::money::serialize_aggregate")]
+    pub total_profit: f64,
+    pub free_users: Option<i64>,
+    pub essential_users: Option<i64>,
+    pub pro_users: Option<i64>,
+    pub elite_users: Option<i64>,
+    pub source: String,
+    pub computed_at: DateTime<Utc>,
+    pub pushed_at: Option<DateTime<Utc>>,
+}
+
+// Counts that can be recomputed for any past moment from the raw tables
+#[derive(Debug, Clone, Copy, PartialEq, FromRow)]
+pub struct StatsTotals {
+    pub total_users: i64,
+    pub total_robots: i64,
+    pub total_trades: i64,
+    pub total_profit: f64,
+}
+
+// Counts that only exist as current state
+#[derive(Debug, Clone, Copy, PartialEq, FromRow)]
+pub struct LiveStats {
+    pub active_users: i64,
+    pub active_robots: i64,
+    pub free_users: i64,
+    pub essential_users: i64,
+    pub pro_users: i64,
+    pub elite_users: i64,
+}
+
+const COLUMNS: &str = "stat_date, total_users, active_users, total_robots, active_robots, total_trades, total_profit::FLOAT8 AS total_profit, free_users, essential_users, pro_users, elite_users, source, computed_at, pushed_at";
+
+impl PlatformStatsDay {
+    pub fn new(stat_date: NaiveDate, totals: StatsTotals, live: Option<LiveStats>, computed_at: DateTime<Utc>) -> Self {
+        PlatformStatsDay {
+            stat_date,
+            total_users: totals.total_users,
+            active_users: live.map(|l| l.active_users),
+            total_robots: totals.total_robots,
+            active_robots: live.map(|l| l.active_robots),
+            total_trades: totals.total_trades,
+            total_profit: totals.total_profit,
+            free_users: live.map(|l| l.free_users),
+            essential_users: live.map(|l| l.essential_users),
+            pro_users: live.map(|l| l.pro_users),
+            elite_users: live.map(|l| l.elite_users),
+            source: if live.is_some() { STATS_SOURCE_LIVE } else { STATS_SOURCE_BACKFILL }.to_string(),
+            computed_at,
+            pushed_at: None,
+        }
+    }
+
+    // Everything created, opened or closed before `as_of`
+    pub async fn totals_as_of(pool: &PgPool, as_of: DateTime<Utc>) -> Result<StatsTotals> {
+        sqlx::query_as::<_, StatsTotals>(
+            r#"
+            SELECT
+                (SELECT COUNT(*) FROM users WHERE created_at < $1) AS total_users,
+                (SELECT COUNT(*) FROM trading_robots WHERE created_at < $1) AS total_robots,
+                (SELECT COUNT(*) FROM trades WHERE is_demo = FALSE AND opened_at < $1) AS total_trades,
+                (SELECT COALESCE(SUM(profit_loss), 0)::FLOAT8 FROM trades
+                  WHERE status = 'closed' AND is_demo = FALSE AND closed_at < $1) AS total_profit
+            "#,
+        )
+        .bind(as_of)
+        .fetch_one(pool)
+        .await
+        .db_op("platform_stats_daily.totals_as_of")
+    }
+
+    pub async fn live(pool: &PgPool) -> Result<LiveStats> {
+        sqlx::query_as::<_, LiveStats>(
+            r#"
+            SELECT
+                (SELECT COUNT(*) FROM users WHERE is_active = TRUE) AS active_users,
+                (SELECT COUNT(*) FROM trading_robots WHERE status = 'active') AS active_robots,
+                (SELECT COUNT(*) FROM users WHERE subscription_plan = 'free') AS free_users,
+                (SELECT COUNT(*) FROM users WHERE subscription_plan = 'essential') AS essential_users,
+                (SELECT COUNT(*) FROM users WHERE subscription_plan = 'pro') AS pro_users,
+                (SELECT COUNT(*) FROM users WHERE subscription_plan = 'elite') AS elite_users
+            "#,
+        )
+        .fetch_one(pool)
+        .await
+        .db_op("platform_stats_daily.live")
+    }
+
+    // One row per date: retaking a snapshot replaces it. A backfill never erases the live-only
+    // columns of a day that was snapshotted live, and a recomputed day is pushed again.
+    pub async fn upsert(pool: &PgPool, day: &PlatformStatsDay) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO platform_stats_daily (stat_date, total_users, active_users, total_robots, active_robots, total_trades, total_profit,
+                free_users, essential_users, pro_users, elite_users, source, computed_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+            ON CONFLICT (stat_date) DO UPDATE SET
+                total_users = EXCLUDED.total_users,
+                active_users = COALESCE(EXCLUDED.active_users, platform_stats_daily.active_users),
+                total_robots = EXCLUDED.total_robots,
+                active_robots = COALESCE(EXCLUDED.active_robots, platform_stats_daily.active_robots),
+                total_trades = EXCLUDED.total_trades,
+                total_profit = EXCLUDED.total_profit,
+                free_users = COALESCE(EXCLUDED.free_users, platform_stats_daily.free_users),
+                essential_users = COALESCE(EXCLUDED.essential_users, platform_stats_daily.essential_users),
+                pro_users = COALESCE(EXCLUDED.pro_users, platform_stats_daily.pro_users),
+                elite_users = COALESCE(EXCLUDED.elite_users, platform_stats_daily.elite_users),
+                source = CASE WHEN EXCLUDED.active_users IS NULL THEN platform_stats_daily.source ELSE EXCLUDED.source END,
+                computed_at = EXCLUDED.computed_at,
+                pushed_at = NULL
+            "#,
+        )
+        .bind(day.stat_date)
+        .bind(day.total_users)
+        .bind(day.active_users)
+        .bind(day.total_robots)
+        .bind(day.active_robots)
+        .bind(day.total_trades)
+        .bind(day.total_profit)
+        .bind(day.free_users)
+        .bind(day.essential_users)
+        .bind(day.pro_users)
+        .bind(day.elite_users)
+        .bind(&day.source)
+        .bind(day.computed_at)
+        .execute(pool)
+        .await
+        .db_op("platform_stats_daily.upsert")?;
+        Ok(())
+    }
+
+    // Inclusive on both ends, oldest first
+    pub async fn range(pool: &PgPool, from: NaiveDate, to: NaiveDate) -> Result<Vec<PlatformStatsDay>> {
+        sqlx::query_as::<_, PlatformStatsDay>(&format!(
+            "SELECT {} FROM platform_stats_daily WHERE stat_date BETWEEN $1 AND $2 ORDER BY stat_date",
+            COLUMNS
+        ))
+        .bind(from)
+        .bind(to)
+        .fetch_all(pool)
+        .await
+        .db_op("platform_stats_daily.range")
+    }
+
+    // Finished days that have not been delivered since they were last computed
+    pub async fn unpushed_before(pool: &PgPool, before: NaiveDate) -> Result<Vec<PlatformStatsDay>> {
+        sqlx::query_as::<_, PlatformStatsDay>(&format!(
+            "SELECT {} FROM platform_stats_daily WHERE stat_date < $1 AND pushed_at IS NULL ORDER BY stat_date",
+            COLUMNS
+        ))
+        .bind(before)
+        .fetch_all(pool)
+        .await
+        .db_op("platform_stats_daily.unpushed_before")
+    }
+
+    pub async fn mark_pushed(pool: &PgPool, dates: &[NaiveDate], at: DateTime<Utc>) -> Result<()> {
+        sqlx::query("UPDATE platform_stats_daily SET pushed_at = $1 WHERE stat_date = ANY($2)")
+            .bind(at)
+            .bind(dates)
+            .execute(pool)
+            .await
+            .db_op("platform_stats_daily.mark_pushed")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,86 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+use crate::errors::{DbOp, Result};
+
+// One configuration edit of a robot. `changes` maps each changed field to {"old": .., "new": ..};
+// risk_config keys are journaled individually as "risk_config.<key>".
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema, FromRow)]
+pub struct RobotChange {
+    pub id: Uuid,
+    pub robot_id: Uuid,
+    pub user_id: Uuid,
+    pub actor_id: Option<Uuid>,
+    pub changes: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+}
+
+// A config change as drawn on a performance chart
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ChangeMarker {
+    pub robot_id: Uuid,
+    pub change_id: Uuid,
+    pub changed_at: DateTime<Utc>,
+    pub fields: Vec<String>,
+}
+
+impl RobotChange {
+    pub fn marker(&self) -> ChangeMarker {
+        ChangeMarker {
+            robot_id: self.robot_id,
+            change_id: self.id,
+            changed_at: self.created_at,
+            fields: self
+                .changes
+                .as_object()
+                .map(|changes| changes.keys().cloned().collect())
+                .unwrap_or_default(),
+        }
+    }
+
+    // Newest first
+    pub async fn find_by_robot_id(
+        pool: &PgPool,
+        robot_id: Uuid,
+        user_id: Uuid,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<RobotChange>> {
+        sqlx::query_as::<_, RobotChange>(
+            r#"
+            SELECT id, robot_id, user_id, actor_id, changes, created_at
+            FROM robot_changes
+            WHERE robot_id = $1 AND user_id = $2
+            ORDER BY created_at DESC, id
+            LIMIT $3 OFFSET $4
+            "#,
+        )
+        .bind(robot_id)
+        .bind(user_id)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(pool)
+        .await
+        .db_op("robot_changes.find_by_robot_id")
+    }
+
+    // Oldest first, for overlaying on a chart that starts at `start`
+    pub async fn find_since(pool: &PgPool, user_id: Uuid, start: DateTime<Utc>) -> Result<Vec<RobotChange>> {
+        sqlx::query_as::<_, RobotChange>(
+            r#"
+            SELECT id, robot_id, user_id, actor_id, changes, created_at
+            FROM robot_changes
+            WHERE user_id = $1 AND created_at >= $2
+            ORDER BY created_at, id
+            "#,
+        )
+        .bind(user_id)
+        .bind(start)
+        .fetch_all(pool)
+        .await
+        .db_op("robot_changes.find_since")
+    }
+}
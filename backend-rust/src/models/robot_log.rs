@@ -0,0 +1,60 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+use crate::errors::{DbOp, Result};
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct RobotLog {
+    pub id: Uuid,
+    pub robot_id: Uuid,
+    pub user_id: Uuid,
+    pub level: String,
+    pub message: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl RobotLog {
+    pub async fn create(
+        pool: &PgPool,
+        robot_id: Uuid,
+        user_id: Uuid,
+        level: &str,
+        message: &str,
+    ) -> Result<RobotLog> {
+        sqlx::query_as::<_, RobotLog>(
+            r#"
+            INSERT INTO robot_logs (id, robot_id, user_id, level, message, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id, robot_id, user_id, level, message, created_at
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(robot_id)
+        .bind(user_id)
+        .bind(level)
+        .bind(message)
+        .bind(Utc::now())
+        .fetch_one(pool)
+        .await
+        .db_op("robot_logs.create")
+    }
+
+    pub async fn find_by_robot_id(
+        pool: &PgPool,
+        robot_id: Uuid,
+        user_id: Uuid,
+        limit: i64,
+    ) -> Result<Vec<RobotLog>> {
+        sqlx::query_as::<_, RobotLog>(
+            "SELECT id, robot_id, user_id, level, message, created_at FROM robot_logs WHERE robot_id = $1 AND user_id = $2 ORDER BY created_at DESC LIMIT $3",
+        )
+        .bind(robot_id)
+        .bind(user_id)
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+        .db_op("robot_logs.find_by_robot_id")
+    }
+}
@@ -0,0 +1,387 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+use crate::errors::{AppError, DbOp, Result};
+
+// A live trade as a statement sees it; status, is_demo and closed_at decide whether it still counts
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct StatementTrade {
+    pub id: Uuid,
+    // The connection of the robot that placed it
+    pub connection_id: Option<Uuid>,
+    pub symbol: String,
+    pub trade_type: String,
+    pub volume: f64,
+    pub entry_price: f64,
+    pub exit_price: Option<f64>,
+    pub profit_loss: Option<f64>,
+    pub commission: Option<f64>,
+    pub swap: Option<f64>,
+    pub status: String,
+    pub is_demo: bool,
+    pub opened_at: DateTime<Utc>,
+    pub closed_at: Option<DateTime<Utc>>,
+}
+
+impl StatementTrade {
+    pub fn net(&self) -> f64 {
+        self.profit_loss.unwrap_or(0.0) + self.commission.unwrap_or(0.0) + self.swap.unwrap_or(0.0)
+    }
+}
+
+// A trade an earlier statement already covered, with the amount booked for it so far
+#[derive(Debug, Clone, FromRow)]
+pub struct BookedTrade {
+    #[sqlx(flatten)]
+    pub trade: StatementTrade,
+    pub booked: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct StatementBalance {
+    pub connection_id: Uuid,
+    pub balance: f64,
+    pub currency: String,
+}
+
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct StatementConnection {
+    pub id: Uuid,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct StatementTradeLine {
+    pub trade_id: Uuid,
+    pub symbol: String,
+    pub trade_type: String,
+    pub volume: f64,
+    #[serde(serialize_with = "crate::money::serialize_price")]
+    pub entry_price: f64,
+    #[serde(serialize_with = "crate::money::serialize_opt_price")]
+    pub exit_price: Option<f64>,
+    pub opened_at: DateTime<Utc>,
+    pub closed_at: DateTime<Utc>,
+    #[serde(serialize_with = "crate::money::serialize_amount")]
+    pub profit_loss: f64,
+    #[serde(serialize_with = "crate::money::serialize_amount")]
+    pub commission: f64,
+    #[serde(serialize_with = "crate::money::serialize_amount")]
+    pub swap: f64,
+    #[serde(serialize_with = "crate::money::serialize_amount")]
+    pub net: f64,
+}
+
+// A change to a trade an earlier statement covered, or a trade closed in an earlier month that
+// arrived after that month's statement was issued
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct StatementAdjustment {
+    pub trade_id: Uuid,
+    pub symbol: String,
+    pub closed_at: Option<DateTime<Utc>>,
+    #[serde(serialize_with = "crate::money::serialize_amount")]
+    pub previously_booked: f64,
+    #[serde(serialize_with = "crate::money::serialize_amount")]
+    pub amount: f64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct SymbolTotals {
+    pub symbol: String,
+    pub trades: i64,
+    #[serde(serialize_with = "crate::money::serialize_amount")]
+    pub profit_loss: f64,
+    #[serde(serialize_with = "crate::money::serialize_amount")]
+    pub commission: f64,
+    #[serde(serialize_with = "crate::money::serialize_amount")]
+    pub swap: f64,
+    #[serde(serialize_with = "crate::money::serialize_amount")]
+    pub net: f64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct StatementTotals {
+    pub trades: i64,
+    #[serde(serialize_with = "crate::money::serialize_amount")]
+    pub profit_loss: f64,
+    #[serde(serialize_with = "crate::money::serialize_amount")]
+    pub commission: f64,
+    #[serde(serialize_with = "crate::money::serialize_amount")]
+    pub swap: f64,
+    // Profit/loss plus commission and swap, which the broker reports as signed amounts
+    #[serde(serialize_with = "crate::money::serialize_amount")]
+    pub net: f64,
+    #[serde(serialize_with = "crate::money::serialize_amount")]
+    pub adjustments: f64,
+}
+
+// One broker connection's month; trades whose robot has no connection are grouped with no id
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct StatementAccount {
+    pub connection_id: Option<Uuid>,
+    pub connection_name: Option<String>,
+    pub currency: Option<String>,
+    // From the account snapshots; None when there is none for the period
+    #[serde(serialize_with = "crate::money::serialize_opt_amount")]
+    pub opening_balance: Option<f64>,
+    #[serde(serialize_with = "crate::money::serialize_opt_amount")]
+    pub ending_balance: Option<f64>,
+    pub trades: Vec<StatementTradeLine>,
+    pub symbols: Vec<SymbolTotals>,
+    pub adjustments: Vec<StatementAdjustment>,
+    pub totals: StatementTotals,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Statement {
+    pub year: i32,
+    pub month: u32,
+    pub period_start: DateTime<Utc>,
+    pub period_end: DateTime<Utc>,
+    // False for the month in progress, which is assembled on every request and never stored
+    pub is_final: bool,
+    pub generated_at: DateTime<Utc>,
+    pub accounts: Vec<StatementAccount>,
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct StoredStatement {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub period_start: NaiveDate,
+    pub content: serde_json::Value,
+    pub html: String,
+    pub generated_at: DateTime<Utc>,
+    pub notified_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct StatementRecipient {
+    pub user_id: Uuid,
+    pub email: String,
+}
+
+const STATEMENT_TRADE_COLUMNS: &str = "t.id, r.broker_connection_id AS connection_id, t.symbol, t.trade_type, t.volume, t.entry_price, t.exit_price, \
+     t.profit_loss, t.commission, t.swap, t.status, t.is_demo, t.opened_at, t.closed_at";
+
+const STORED_STATEMENT_COLUMNS: &str = "id, user_id, period_start, content, html, generated_at, notified_at";
+
+pub struct MonthlyStatement;
+
+impl MonthlyStatement {
+    pub async fn find(pool: &PgPool, user_id: Uuid, period_start: NaiveDate) -> Result<Option<StoredStatement>> {
+        sqlx::query_as::<_, StoredStatement>(&format!(
+            "SELECT {} FROM monthly_statements WHERE user_id = $1 AND period_start = $2",
+            STORED_STATEMENT_COLUMNS
+        ))
+        .bind(user_id)
+        .bind(period_start)
+        .fetch_optional(pool)
+        .await
+        .db_op("monthly_statements.find")
+    }
+
+    // The month of the user's first stored statement
+    pub async fn first_period(pool: &PgPool, user_id: Uuid) -> Result<Option<NaiveDate>> {
+        sqlx::query_scalar("SELECT MIN(period_start) FROM monthly_statements WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(pool)
+            .await
+            .db_op("monthly_statements.first_period")
+    }
+
+    // Live closed trades with closed_at in [from, to)
+    pub async fn closed_trades(pool: &PgPool, user_id: Uuid, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<StatementTrade>> {
+        sqlx::query_as::<_, StatementTrade>(&format!(
+            r#"
+            SELECT {}
+            FROM trades t
+            JOIN trading_robots r ON r.id = t.robot_id
+            WHERE t.user_id = $1 AND t.status = 'closed' AND t.is_demo = FALSE AND t.closed_at >= $2 AND t.closed_at < $3
+            ORDER BY t.closed_at, t.id
+            "#,
+            STATEMENT_TRADE_COLUMNS
+        ))
+        .bind(user_id)
+        .bind(from)
+        .bind(to)
+        .fetch_all(pool)
+        .await
+        .db_op("monthly_statements.closed_trades")
+    }
+
+    // Every trade the user's stored statements booked, as it is now
+    pub async fn booked_trades(pool: &PgPool, user_id: Uuid) -> Result<Vec<BookedTrade>> {
+        sqlx::query_as::<_, BookedTrade>(&format!(
+            r#"
+            SELECT {}, b.booked
+            FROM (
+                SELECT e.trade_id, SUM(e.amount) AS booked
+                FROM statement_entries e
+                JOIN monthly_statements s ON s.id = e.statement_id
+                WHERE s.user_id = $1
+                GROUP BY e.trade_id
+            ) b
+            JOIN trades t ON t.id = b.trade_id
+            JOIN trading_robots r ON r.id = t.robot_id
+            ORDER BY t.closed_at, t.id
+            "#,
+            STATEMENT_TRADE_COLUMNS
+        ))
+        .bind(user_id)
+        .fetch_all(pool)
+        .await
+        .db_op("monthly_statements.booked_trades")
+    }
+
+    pub async fn connections(pool: &PgPool, user_id: Uuid) -> Result<Vec<StatementConnection>> {
+        sqlx::query_as::<_, StatementConnection>(
+            "SELECT id, name, created_at FROM broker_connections WHERE user_id = $1 AND is_demo = FALSE ORDER BY created_at, id",
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await
+        .db_op("monthly_statements.connections")
+    }
+
+    // Each connection's last snapshot before `before`
+    pub async fn balances_before(pool: &PgPool, user_id: Uuid, before: DateTime<Utc>) -> Result<Vec<StatementBalance>> {
+        sqlx::query_as::<_, StatementBalance>(
+            r#"
+            SELECT DISTINCT ON (connection_id) connection_id, balance, currency
+            FROM account_snapshots
+            WHERE user_id = $1 AND captured_at < $2
+            ORDER BY connection_id, captured_at DESC
+            "#,
+        )
+        .bind(user_id)
+        .bind(before)
+        .fetch_all(pool)
+        .await
+        .db_op("monthly_statements.balances_before")
+    }
+
+    // Each connection's first snapshot in [from, to), for accounts that had none before the period
+    pub async fn first_balances(pool: &PgPool, user_id: Uuid, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<StatementBalance>> {
+        sqlx::query_as::<_, StatementBalance>(
+            r#"
+            SELECT DISTINCT ON (connection_id) connection_id, balance, currency
+            FROM account_snapshots
+            WHERE user_id = $1 AND captured_at >= $2 AND captured_at < $3
+            ORDER BY connection_id, captured_at
+            "#,
+        )
+        .bind(user_id)
+        .bind(from)
+        .bind(to)
+        .fetch_all(pool)
+        .await
+        .db_op("monthly_statements.first_balances")
+    }
+
+    // Users with live trading or account activity in the period whose statement is missing or was never sent
+    pub async fn due_recipients(pool: &PgPool, period_start: NaiveDate, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<StatementRecipient>> {
+        sqlx::query_as::<_, StatementRecipient>(
+            r#"
+            SELECT u.id AS user_id, u.email
+            FROM users u
+            WHERE u.is_active = TRUE
+              AND (
+                  EXISTS (SELECT 1 FROM trades t WHERE t.user_id = u.id AND t.status = 'closed' AND t.is_demo = FALSE AND t.closed_at >= $2 AND t.closed_at < $3)
+                  OR EXISTS (SELECT 1 FROM account_snapshots a WHERE a.user_id = u.id AND a.captured_at >= $2 AND a.captured_at < $3)
+              )
+              AND NOT EXISTS (
+                  SELECT 1 FROM monthly_statements s WHERE s.user_id = u.id AND s.period_start = $1 AND s.notified_at IS NOT NULL
+              )
+            ORDER BY u.created_at, u.id
+            "#,
+        )
+        .bind(period_start)
+        .bind(from)
+        .bind(to)
+        .fetch_all(pool)
+        .await
+        .db_op("monthly_statements.due_recipients")
+    }
+
+    // Stores the statement and what it booked per trade. A statement already stored for the
+    // period wins, so concurrent generation still leaves exactly one.
+    pub async fn insert(
+        pool: &PgPool,
+        user_id: Uuid,
+        period_start: NaiveDate,
+        content: &serde_json::Value,
+        html: &str,
+        generated_at: DateTime<Utc>,
+        entries: &[(Uuid, f64)],
+    ) -> Result<StoredStatement> {
+        let mut tx = pool.begin().await.db_op("monthly_statements.insert")?;
+
+        let inserted = sqlx::query_as::<_, StoredStatement>(&format!(
+            r#"
+            INSERT INTO monthly_statements (id, user_id, period_start, content, html, generated_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (user_id, period_start) DO NOTHING
+            RETURNING {}
+            "#,
+            STORED_STATEMENT_COLUMNS
+        ))
+        .bind(Uuid::new_v4())
+        .bind(user_id)
+        .bind(period_start)
+        .bind(content)
+        .bind(html)
+        .bind(generated_at)
+        .fetch_optional(&mut *tx)
+        .await
+        .db_op("monthly_statements.insert")?;
+
+        let Some(statement) = inserted else {
+            tx.rollback().await.db_op("monthly_statements.insert")?;
+            return Self::find(pool, user_id, period_start)
+                .await?
+                .ok_or_else(|| AppError::Internal(anyhow::anyhow!("Statement vanished after a conflicting insert")));
+        };
+
+        if !entries.is_empty() {
+            let (trade_ids, amounts): (Vec<Uuid>, Vec<f64>) = entries.iter().copied().unzip();
+            sqlx::query(
+                "INSERT INTO statement_entries (statement_id, trade_id, amount) SELECT $1, * FROM UNNEST($2::uuid[], $3::float8[])",
+            )
+            .bind(statement.id)
+            .bind(&trade_ids)
+            .bind(&amounts)
+            .execute(&mut *tx)
+            .await
+            .db_op("monthly_statements.insert")?;
+        }
+
+        tx.commit().await.db_op("monthly_statements.insert")?;
+        Ok(statement)
+    }
+
+    // False when another run already claimed the notification
+    pub async fn claim_notification(pool: &PgPool, id: Uuid) -> Result<bool> {
+        let result = sqlx::query("UPDATE monthly_statements SET notified_at = NOW() WHERE id = $1 AND notified_at IS NULL")
+            .bind(id)
+            .execute(pool)
+            .await
+            .db_op("monthly_statements.claim_notification")?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn release_notification(pool: &PgPool, id: Uuid) -> Result<()> {
+        sqlx::query("UPDATE monthly_statements SET notified_at = NULL WHERE id = $1")
+            .bind(id)
+            .execute(pool)
+            .await
+            .db_op("monthly_statements.release_notification")?;
+
+        Ok(())
+    }
+}
@@ -1,7 +1,13 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
 use sqlx::{FromRow, PgPool};
 use uuid::Uuid;
+use validator::Validate;
+
+use crate::errors::{AppError, DbOp, Result};
+
+pub const PLAN_NAMES: [&str; 4] = ["free", "essential", "pro", "elite"];
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct Subscription {
@@ -13,11 +19,25 @@ pub struct Subscription {
     pub status: String,
     pub current_period_start: DateTime<Utc>,
     pub current_period_end: DateTime<Utc>,
+    pub trial_end: Option<DateTime<Utc>>,
+    // Set by an admin rather than bought
+    pub manual: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+// A trialing subscription as seen by the trial scheduler job
+#[derive(Debug, Clone, FromRow)]
+pub struct TrialSubscription {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub email: String,
+    pub status: String,
+    pub trial_end: DateTime<Utc>,
+    pub trial_reminder_days: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct SubscriptionPlan {
     pub name: String,
     pub price: f64,
@@ -26,22 +46,45 @@ pub struct SubscriptionPlan {
     pub max_robots: i32,
     pub max_assets: i32,
     pub max_operations_per_day: i32,
+    // Exports, backtests and imports a user can have running at the same time
+    pub max_concurrent_jobs: i32,
+    // Symbols on the dashboard watchlist
+    pub max_watchlist_symbols: i32,
+    // Parameter combinations one optimization job may backtest
+    pub max_optimization_combinations: i32,
+    // How far back statistics, account history and backtests may reach
+    pub max_history_days: i32,
+    // Shortest time between two signal evaluations of a robot, whatever the robot asks for
+    pub min_evaluation_interval_seconds: i32,
     pub features: Vec<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct CreateSubscriptionRequest {
     pub plan_name: String,
     pub payment_method_id: String,
+    // On a downgrade that leaves more robots than the plan allows, the ones to keep running; the
+    // others are stopped and marked plan_limited
+    pub keep_robot_ids: Option<Vec<Uuid>>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema, Validate)]
+pub struct UpdateUserPlanRequest {
+    pub plan_name: String,
+    // Why, for the audit log
+    #[validate(length(max = 500))]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct SubscriptionResponse {
     pub id: Uuid,
     pub plan_name: String,
     pub status: String,
     pub current_period_start: DateTime<Utc>,
     pub current_period_end: DateTime<Utc>,
+    pub trial_end: Option<DateTime<Utc>>,
+    pub manual: bool,
     pub plan_details: SubscriptionPlan,
 }
 
@@ -57,18 +100,33 @@ impl Subscription {
             status: "active".to_string(),
             current_period_start: now,
             current_period_end: now + chrono::Duration::days(30),
+            trial_end: None,
+            manual: false,
             created_at: now,
             updated_at: now,
         }
     }
 
+    // The plan name as stored, refusing anything but a known plan
+    pub fn known_plan(plan_name: &str) -> Result<String> {
+        let plan_name = plan_name.trim().to_lowercase();
+        if !PLAN_NAMES.contains(&plan_name.as_str()) {
+            return Err(AppError::Validation(format!(
+                "Unknown plan '{}', expected one of {}",
+                plan_name,
+                PLAN_NAMES.join(", ")
+            )));
+        }
+        Ok(plan_name)
+    }
+
     pub async fn create(
         pool: &PgPool,
         user_id: Uuid,
         plan_name: String,
         stripe_subscription_id: Option<String>,
         stripe_customer_id: Option<String>,
-    ) -> Result<Subscription, sqlx::Error> {
+    ) -> Result<Subscription> {
         let subscription = Subscription {
             stripe_subscription_id,
             stripe_customer_id,
@@ -92,28 +150,73 @@ impl Subscription {
             subscription.updated_at
         )
         .execute(pool)
-        .await?;
+        .await
+        .db_op("subscriptions.create")?;
 
         Ok(subscription)
     }
 
-    pub async fn find_by_user_id(pool: &PgPool, user_id: Uuid) -> Result<Option<Subscription>, sqlx::Error> {
+    pub async fn find_by_user_id(pool: &PgPool, user_id: Uuid) -> Result<Option<Subscription>> {
         let subscription = sqlx::query_as!(
             Subscription,
-            r#"SELECT id as "id: Uuid", user_id as "user_id: Uuid", plan_name, stripe_subscription_id, stripe_customer_id, status, current_period_start as "current_period_start: DateTime<Utc>", current_period_end as "current_period_end: DateTime<Utc>", created_at as "created_at: DateTime<Utc>", updated_at as "updated_at: DateTime<Utc>" FROM subscriptions WHERE user_id = $1 AND status = 'active' ORDER BY created_at DESC LIMIT 1"#,
+            r#"SELECT id as "id: Uuid", user_id as "user_id: Uuid", plan_name, stripe_subscription_id, stripe_customer_id, status, current_period_start as "current_period_start: DateTime<Utc>", current_period_end as "current_period_end: DateTime<Utc>", trial_end as "trial_end: DateTime<Utc>", manual, created_at as "created_at: DateTime<Utc>", updated_at as "updated_at: DateTime<Utc>" FROM subscriptions WHERE user_id = $1 AND status IN ('active', 'trialing') ORDER BY created_at DESC LIMIT 1"#,
             user_id
         )
         .fetch_optional(pool)
-        .await?;
+        .await
+        .db_op("subscriptions.find_by_user_id")?;
 
         Ok(subscription)
     }
 
+    // Puts the user on `plan_name` by hand: their current subscription is switched over and
+    // marked manual, or a manual one is created when they have none. A running trial ends.
+    pub async fn set_manual(pool: &PgPool, user_id: Uuid, plan_name: &str, now: DateTime<Utc>) -> Result<Subscription> {
+        let mut tx = pool.begin().await.db_op("subscriptions.set_manual")?;
+        let updated = sqlx::query_as::<_, Subscription>(
+            "UPDATE subscriptions SET plan_name = $2, status = 'active', trial_end = NULL, manual = TRUE, updated_at = $3 \
+             WHERE id = (SELECT id FROM subscriptions WHERE user_id = $1 AND status IN ('active', 'trialing') ORDER BY created_at DESC LIMIT 1) \
+             RETURNING id, user_id, plan_name, stripe_subscription_id, stripe_customer_id, status, current_period_start, current_period_end, trial_end, manual, created_at, updated_at",
+        )
+        .bind(user_id)
+        .bind(plan_name)
+        .bind(now)
+        .fetch_optional(&mut *tx)
+        .await
+        .db_op("subscriptions.set_manual")?;
+
+        let subscription = match updated {
+            Some(subscription) => subscription,
+            None => {
+                let subscription = Subscription { manual: true, ..Subscription::new(user_id, plan_name.to_string()) };
+                sqlx::query(
+                    "INSERT INTO subscriptions (id, user_id, plan_name, status, current_period_start, current_period_end, manual, created_at, updated_at) \
+                     VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+                )
+                .bind(subscription.id)
+                .bind(subscription.user_id)
+                .bind(&subscription.plan_name)
+                .bind(&subscription.status)
+                .bind(subscription.current_period_start)
+                .bind(subscription.current_period_end)
+                .bind(subscription.manual)
+                .bind(subscription.created_at)
+                .bind(subscription.updated_at)
+                .execute(&mut *tx)
+                .await
+                .db_op("subscriptions.set_manual")?;
+                subscription
+            }
+        };
+        tx.commit().await.db_op("subscriptions.set_manual")?;
+        Ok(subscription)
+    }
+
     pub async fn update_status(
         pool: &PgPool,
         subscription_id: Uuid,
         status: &str,
-    ) -> Result<(), sqlx::Error> {
+    ) -> Result<()> {
         sqlx::query!(
             "UPDATE subscriptions SET status = $1, updated_at = $2 WHERE id = $3",
             status,
@@ -121,13 +224,142 @@ impl Subscription {
             subscription_id
         )
         .execute(pool)
-        .await?;
+        .await
+        .db_op("subscriptions.update_status")?;
+
+        Ok(())
+    }
+
+    // Creates the one trial a user ever gets; returns None if the trial was already used
+    pub async fn start_trial(
+        pool: &PgPool,
+        user_id: Uuid,
+        plan_name: &str,
+        trial_end: DateTime<Utc>,
+    ) -> Result<Option<Subscription>> {
+        let now = Utc::now();
+        let mut tx = pool.begin().await.db_op("subscriptions.start_trial")?;
+
+        let claimed = sqlx::query("UPDATE users SET trial_used = TRUE, subscription_plan = $1, updated_at = $2 WHERE id = $3 AND trial_used = FALSE")
+            .bind(plan_name)
+            .bind(now)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await
+            .db_op("subscriptions.start_trial")?;
+
+        if claimed.rows_affected() == 0 {
+            return Ok(None);
+        }
+
+        let subscription = Subscription {
+            status: "trialing".to_string(),
+            current_period_end: trial_end,
+            trial_end: Some(trial_end),
+            ..Subscription::new(user_id, plan_name.to_string())
+        };
+
+        sqlx::query(
+            r#"
+            INSERT INTO subscriptions (id, user_id, plan_name, status, current_period_start, current_period_end, trial_end, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            "#,
+        )
+        .bind(subscription.id)
+        .bind(subscription.user_id)
+        .bind(&subscription.plan_name)
+        .bind(&subscription.status)
+        .bind(subscription.current_period_start)
+        .bind(subscription.current_period_end)
+        .bind(subscription.trial_end)
+        .bind(subscription.created_at)
+        .bind(subscription.updated_at)
+        .execute(&mut *tx)
+        .await
+        .db_op("subscriptions.start_trial")?;
+
+        tx.commit().await.db_op("subscriptions.start_trial")?;
+        Ok(Some(subscription))
+    }
+
+    // Ends a running trial because the user subscribed for real; returns whether one was running
+    pub async fn convert_trial(pool: &PgPool, user_id: Uuid) -> Result<bool> {
+        let now = Utc::now();
+        let result = sqlx::query(
+            "UPDATE subscriptions SET status = 'converted', current_period_end = $1, updated_at = $1 WHERE user_id = $2 AND status = 'trialing'",
+        )
+        .bind(now)
+        .bind(user_id)
+        .execute(pool)
+        .await
+        .db_op("subscriptions.convert_trial")?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn find_trials_ending_before(
+        pool: &PgPool,
+        before: DateTime<Utc>,
+    ) -> Result<Vec<TrialSubscription>> {
+        sqlx::query_as::<_, TrialSubscription>(
+            r#"
+            SELECT s.id, s.user_id, u.email, s.status, s.trial_end, s.trial_reminder_days
+            FROM subscriptions s
+            JOIN users u ON u.id = s.user_id
+            WHERE s.status = 'trialing' AND s.trial_end IS NOT NULL AND s.trial_end <= $1
+            ORDER BY s.trial_end
+            "#,
+        )
+        .bind(before)
+        .fetch_all(pool)
+        .await
+        .db_op("subscriptions.find_trials_ending_before")
+    }
+
+    // Downgrades the user to free. Guarded on status so a conversion that raced ahead wins.
+    pub async fn expire_trial(pool: &PgPool, id: Uuid, user_id: Uuid) -> Result<bool> {
+        let now = Utc::now();
+        let mut tx = pool.begin().await.db_op("subscriptions.expire_trial")?;
+
+        let expired = sqlx::query("UPDATE subscriptions SET status = 'expired', updated_at = $1 WHERE id = $2 AND status = 'trialing'")
+            .bind(now)
+            .bind(id)
+            .execute(&mut *tx)
+            .await
+            .db_op("subscriptions.expire_trial")?;
+
+        if expired.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        sqlx::query("UPDATE users SET subscription_plan = 'free', updated_at = $1 WHERE id = $2")
+            .bind(now)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await
+            .db_op("subscriptions.expire_trial")?;
+
+        tx.commit().await.db_op("subscriptions.expire_trial")?;
+        Ok(true)
+    }
+
+    pub async fn mark_trial_reminder(pool: &PgPool, id: Uuid, days_left: i32) -> Result<()> {
+        sqlx::query("UPDATE subscriptions SET trial_reminder_days = $1 WHERE id = $2")
+            .bind(days_left)
+            .bind(id)
+            .execute(pool)
+            .await
+            .db_op("subscriptions.mark_trial_reminder")?;
 
         Ok(())
     }
 
     pub fn get_plan_details(&self) -> SubscriptionPlan {
-        match self.plan_name.as_str() {
+        Self::plan_details(&self.plan_name)
+    }
+
+    pub fn plan_details(plan_name: &str) -> SubscriptionPlan {
+        match plan_name {
             "free" => SubscriptionPlan {
                 name: "Free".to_string(),
                 price: 0.0,
@@ -136,6 +368,11 @@ impl Subscription {
                 max_robots: 0,
                 max_assets: 0,
                 max_operations_per_day: 0,
+                max_concurrent_jobs: 1,
+                max_watchlist_symbols: 5,
+                max_optimization_combinations: 0,
+                max_history_days: 30,
+                min_evaluation_interval_seconds: 60,
                 features: vec!["Demo trading".to_string(), "Community support".to_string()],
             },
             "essential" => SubscriptionPlan {
@@ -146,6 +383,11 @@ impl Subscription {
                 max_robots: 1,
                 max_assets: 1,
                 max_operations_per_day: 50,
+                max_concurrent_jobs: 2,
+                max_watchlist_symbols: 10,
+                max_optimization_combinations: 9,
+                max_history_days: 90,
+                min_evaluation_interval_seconds: 30,
                 features: vec![
                     "1 trading robot".to_string(),
                     "1 asset".to_string(),
@@ -161,6 +403,11 @@ impl Subscription {
                 max_robots: 5,
                 max_assets: 10,
                 max_operations_per_day: 200,
+                max_concurrent_jobs: 3,
+                max_watchlist_symbols: 25,
+                max_optimization_combinations: 50,
+                max_history_days: 365,
+                min_evaluation_interval_seconds: 10,
                 features: vec![
                     "5 trading robots".to_string(),
                     "10 assets".to_string(),
@@ -177,6 +424,11 @@ impl Subscription {
                 max_robots: -1, // Unlimited
                 max_assets: -1, // Unlimited
                 max_operations_per_day: -1, // Unlimited
+                max_concurrent_jobs: 5,
+                max_watchlist_symbols: -1, // Unlimited
+                max_optimization_combinations: 200,
+                max_history_days: -1, // Unlimited
+                min_evaluation_interval_seconds: 5,
                 features: vec![
                     "Unlimited robots".to_string(),
                     "Unlimited assets".to_string(),
@@ -194,6 +446,11 @@ impl Subscription {
                 max_robots: 0,
                 max_assets: 0,
                 max_operations_per_day: 0,
+                max_concurrent_jobs: 1,
+                max_watchlist_symbols: 5,
+                max_optimization_combinations: 0,
+                max_history_days: 30,
+                min_evaluation_interval_seconds: 60,
                 features: vec![],
             },
         }
@@ -209,7 +466,23 @@ impl From<Subscription> for SubscriptionResponse {
             status: subscription.status,
             current_period_start: subscription.current_period_start,
             current_period_end: subscription.current_period_end,
+            trial_end: subscription.trial_end,
+            manual: subscription.manual,
             plan_details,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_known_plans_are_accepted() {
+        assert_eq!(Subscription::known_plan(" Pro ").unwrap(), "pro");
+        for plan in PLAN_NAMES {
+            assert_ne!(Subscription::plan_details(plan).name, "Unknown");
+        }
+        assert!(matches!(Subscription::known_plan("platinum"), Err(AppError::Validation(m)) if m.contains("free, essential, pro, elite")));
+    }
+}
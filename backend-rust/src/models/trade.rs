@@ -1,12 +1,16 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::PgPool;
+use schemars::JsonSchema;
+use sqlx::{FromRow, PgPool, Postgres, QueryBuilder};
 use uuid::Uuid;
 use validator::Validate;
 use bigdecimal::BigDecimal;
 use num_traits::FromPrimitive;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use crate::errors::{DbOp, Result};
+use super::{ReviewBreakdown, StopManagement, TradeAmendment, TradeCorrection, TradeDailyFact, CORRECTION_LATE_FILL};
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct Trade {
     pub id: Uuid,
     pub user_id: Uuid,
@@ -25,12 +29,25 @@ pub struct Trade {
     pub ai_confidence: Option<f64>,
     pub ai_reasoning: Option<String>,
     pub broker_trade_id: Option<String>,
+    pub is_demo: bool,
+    // Who enforces SL/TP for this trade, fixed when it is opened
+    pub stop_management: String,
+    // The trade this one re-entered, for manual re-entries
+    pub reentered_from: Option<Uuid>,
     pub opened_at: DateTime<Utc>,
     pub closed_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+// A trade with the position it belongs to; partial closes are children of the original trade
+#[derive(Debug, Clone, FromRow)]
+pub struct PositionLeg {
+    #[sqlx(flatten)]
+    pub trade: Trade,
+    pub parent_trade_id: Option<Uuid>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Validate)]
 pub struct CreateTradeRequest {
     pub robot_id: Uuid,
@@ -44,24 +61,34 @@ pub struct CreateTradeRequest {
     pub ai_reasoning: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct TradeResponse {
     pub id: Uuid,
     pub robot_id: Uuid,
     pub symbol: String,
     pub trade_type: String,
     pub volume: f64,
+    #[serde(serialize_with = "crate::money::serialize_price")]
     pub entry_price: f64,
+    #[serde(serialize_with = "crate::money::serialize_opt_price")]
     pub exit_price: Option<f64>,
+    #[serde(serialize_with = "crate::money::serialize_opt_price")]
     pub stop_loss: Option<f64>,
+    #[serde(serialize_with = "crate::money::serialize_opt_price")]
     pub take_profit: Option<f64>,
     pub status: String,
+    #[serde(serialize_with = "crate::money::serialize_opt_amount")]
     pub profit_loss: Option<f64>,
+    #[serde(serialize_with = "crate::money::serialize_opt_amount")]
     pub commission: Option<f64>,
+    #[serde(serialize_with = "crate::money::serialize_opt_amount")]
     pub swap: Option<f64>,
     pub ai_confidence: Option<f64>,
     pub ai_reasoning: Option<String>,
     pub broker_trade_id: Option<String>,
+    pub is_demo: bool,
+    pub stop_management: String,
+    pub reentered_from: Option<Uuid>,
     pub opened_at: DateTime<Utc>,
     pub closed_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
@@ -99,6 +126,9 @@ impl Trade {
             ai_confidence,
             ai_reasoning,
             broker_trade_id: None,
+            is_demo: false,
+            stop_management: StopManagement::default().as_str().to_string(),
+            reentered_from: None,
             opened_at: now,
             closed_at: None,
             created_at: now,
@@ -106,28 +136,45 @@ impl Trade {
         }
     }
 
+    // is_demo comes from the broker connection that executes the trade,
+    // stop_management from the robot's risk config at the time
     pub async fn create(
         pool: &PgPool,
         user_id: Uuid,
         request: CreateTradeRequest,
-    ) -> Result<Trade, sqlx::Error> {
-        let trade = Trade::new(
-            user_id,
-            request.robot_id,
-            request.symbol,
-            request.trade_type,
-            request.volume,
-            request.entry_price,
-            request.stop_loss,
-            request.take_profit,
-            request.ai_confidence,
-            request.ai_reasoning,
-        );
+        is_demo: bool,
+        stop_management: StopManagement,
+        created_via: &str,
+    ) -> Result<Trade> {
+        let trade = Trade {
+            is_demo,
+            stop_management: stop_management.as_str().to_string(),
+            ..Trade::new(
+                user_id,
+                request.robot_id,
+                request.symbol,
+                request.trade_type,
+                request.volume,
+                request.entry_price,
+                request.stop_loss,
+                request.take_profit,
+                request.ai_confidence,
+                request.ai_reasoning,
+            )
+        };
+
+        Self::insert(pool, &trade, created_via).await?;
+        Ok(trade)
+    }
 
+    // A trade inserted already closed goes into trade_daily_facts with it. Costs and confidence that
+    // are not known yet are stored as 0, which reads back as None.
+    pub async fn insert(pool: &PgPool, trade: &Trade, created_via: &str) -> Result<()> {
+        let mut tx = pool.begin().await.db_op("trades.insert")?;
         sqlx::query!(
             r#"
-            INSERT INTO trades (id, user_id, robot_id, symbol, trade_type, volume, entry_price, exit_price, stop_loss, take_profit, status, profit_loss, commission, swap, ai_confidence, ai_reasoning, broker_trade_id, opened_at, closed_at, created_at, updated_at)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21)
+            INSERT INTO trades (id, user_id, robot_id, symbol, trade_type, volume, entry_price, exit_price, stop_loss, take_profit, status, profit_loss, commission, swap, ai_confidence, ai_reasoning, broker_trade_id, is_demo, stop_management, reentered_from, opened_at, closed_at, created_at, updated_at, created_via)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, COALESCE($13::FLOAT8, 0), COALESCE($14::FLOAT8, 0), COALESCE($15::FLOAT8, 0), $16, $17, $18, $19, $20, $21, $22, $23, $24, $25)
             "#,
             trade.id,
             trade.user_id,
@@ -146,60 +193,153 @@ impl Trade {
             trade.ai_confidence,
             trade.ai_reasoning,
             trade.broker_trade_id,
+            trade.is_demo,
+            trade.stop_management,
+            trade.reentered_from,
             trade.opened_at,
             trade.closed_at,
             trade.created_at,
-            trade.updated_at
+            trade.updated_at,
+            created_via
+        )
+        .execute(&mut *tx)
+        .await
+        .db_op("trades.insert")?;
+        if trade.status == "closed" {
+            TradeDailyFact::record_close(&mut tx, trade.id).await?;
+        }
+        tx.commit().await.db_op("trades.insert")?;
+
+        Ok(())
+    }
+
+    // Like insert, but settles an execution_pending row already written for the same trade by
+    // a shutdown drain; any other existing row is left alone
+    pub async fn record_order(pool: &PgPool, trade: &Trade, created_via: &str) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO trades (id, user_id, robot_id, symbol, trade_type, volume, entry_price, exit_price, stop_loss, take_profit, status, profit_loss, commission, swap, ai_confidence, ai_reasoning, broker_trade_id, is_demo, stop_management, reentered_from, opened_at, closed_at, created_at, updated_at, created_via)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, COALESCE($13::FLOAT8, 0), COALESCE($14::FLOAT8, 0), COALESCE($15::FLOAT8, 0), $16, $17, $18, $19, $20, $21, $22, $23, $24, $25)
+            ON CONFLICT (id) DO UPDATE SET status = EXCLUDED.status, broker_trade_id = EXCLUDED.broker_trade_id, updated_at = EXCLUDED.updated_at
+            WHERE trades.status = 'execution_pending'
+            "#,
         )
+        .bind(trade.id)
+        .bind(trade.user_id)
+        .bind(trade.robot_id)
+        .bind(&trade.symbol)
+        .bind(&trade.trade_type)
+        .bind(trade.volume)
+        .bind(trade.entry_price)
+        .bind(trade.exit_price)
+        .bind(trade.stop_loss)
+        .bind(trade.take_profit)
+        .bind(&trade.status)
+        .bind(trade.profit_loss)
+        .bind(trade.commission)
+        .bind(trade.swap)
+        .bind(trade.ai_confidence)
+        .bind(&trade.ai_reasoning)
+        .bind(&trade.broker_trade_id)
+        .bind(trade.is_demo)
+        .bind(&trade.stop_management)
+        .bind(trade.reentered_from)
+        .bind(trade.opened_at)
+        .bind(trade.closed_at)
+        .bind(trade.created_at)
+        .bind(trade.updated_at)
+        .bind(created_via)
         .execute(pool)
-        .await?;
+        .await
+        .db_op("trades.record_order")?;
 
-        Ok(trade)
+        Ok(())
     }
 
-    pub async fn find_by_user_id(pool: &PgPool, user_id: Uuid) -> Result<Vec<Trade>, sqlx::Error> {
-        let rows = sqlx::query!(
-            r#"SELECT id, user_id, robot_id, symbol, trade_type, volume::FLOAT8 as volume, entry_price::FLOAT8 as entry_price, exit_price::FLOAT8 as exit_price, stop_loss::FLOAT8 as stop_loss, take_profit::FLOAT8 as take_profit, status, profit_loss::FLOAT8 as profit_loss, commission::FLOAT8 as commission, swap::FLOAT8 as swap, ai_confidence::FLOAT8 as ai_confidence, ai_reasoning, broker_trade_id, opened_at, closed_at, created_at, updated_at FROM trades WHERE user_id = $1 ORDER BY created_at DESC"#,
-            user_id
+    pub async fn find_execution_pending(pool: &PgPool, robot_id: Uuid) -> Result<Vec<Trade>> {
+        sqlx::query_as::<_, Trade>(
+            r#"SELECT id, user_id, robot_id, symbol, trade_type, volume::FLOAT8 as volume, entry_price::FLOAT8 as entry_price, exit_price::FLOAT8 as exit_price, stop_loss::FLOAT8 as stop_loss, take_profit::FLOAT8 as take_profit, status, profit_loss::FLOAT8 as profit_loss, commission::FLOAT8 as commission, swap::FLOAT8 as swap, ai_confidence::FLOAT8 as ai_confidence, ai_reasoning, broker_trade_id, is_demo, stop_management, reentered_from, opened_at, closed_at, created_at, updated_at FROM trades WHERE robot_id = $1 AND status = 'execution_pending' ORDER BY opened_at"#,
         )
+        .bind(robot_id)
         .fetch_all(pool)
-        .await?;
+        .await
+        .db_op("trades.find_execution_pending")
+    }
 
-        let trades = rows.into_iter().map(|row| Trade {
-            id: row.id,
-            user_id: row.user_id,
-            robot_id: row.robot_id,
-            symbol: row.symbol,
-            trade_type: row.trade_type,
-            volume: row.volume,
-            entry_price: row.entry_price,
-            exit_price: row.exit_price,
-            stop_loss: row.stop_loss,
-            take_profit: row.take_profit,
-            status: row.status,
-            profit_loss: row.profit_loss,
-            commission: if row.commission == 0.0 { None } else { Some(row.commission) },
-            swap: if row.swap == 0.0 { None } else { Some(row.swap) },
-            ai_confidence: if row.ai_confidence == 0.0 { None } else { Some(row.ai_confidence) },
-            ai_reasoning: row.ai_reasoning.filter(|s| !s.is_empty()),
-            broker_trade_id: row.broker_trade_id,
-            opened_at: row.opened_at,
-            closed_at: row.closed_at,
-            created_at: row.created_at,
-            updated_at: row.updated_at,
-        }).collect();
+    // Broker tickets of the user's open trades, which pending orders must not be matched to
+    pub async fn open_broker_trade_ids(pool: &PgPool, user_id: Uuid) -> Result<Vec<String>> {
+        sqlx::query_scalar("SELECT broker_trade_id FROM trades WHERE user_id = $1 AND status = 'open' AND broker_trade_id IS NOT NULL")
+            .bind(user_id)
+            .fetch_all(pool)
+            .await
+            .db_op("trades.open_broker_trade_ids")
+    }
 
-        Ok(trades)
+    // Opens a pending trade on the broker position found for it, or cancels it when there is none
+    pub async fn resolve_execution_pending(pool: &PgPool, id: Uuid, ticket: Option<i64>) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE trades
+            SET status = CASE WHEN $2::TEXT IS NULL THEN 'cancelled' ELSE 'open' END, broker_trade_id = $2, updated_at = NOW()
+            WHERE id = $1 AND status = 'execution_pending'
+            "#,
+        )
+        .bind(id)
+        .bind(ticket.map(|t| t.to_string()))
+        .execute(pool)
+        .await
+        .db_op("trades.resolve_execution_pending")?;
+
+        Ok(())
+    }
+
+    // A page of the user's positions whose original trade matches `filter`, newest first, each
+    // parent directly followed by its children. `limit` and `offset` count positions, not legs.
+    pub async fn find_position_legs(
+        pool: &PgPool,
+        user_id: Uuid,
+        filter: &TradeFilter,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<PositionLeg>> {
+        let mut builder = QueryBuilder::<Postgres>::new(
+            "WITH positions AS (SELECT id, created_at FROM trades WHERE parent_trade_id IS NULL AND user_id = ",
+        );
+        builder.push_bind(user_id);
+        filter.push_conditions(&mut builder, Utc::now());
+        builder.push(" ORDER BY created_at DESC, id LIMIT ");
+        builder.push_bind(limit);
+        builder.push(" OFFSET ");
+        builder.push_bind(offset);
+        builder.push(
+            r#")
+            SELECT t.id, t.user_id, t.robot_id, t.symbol, t.trade_type, t.volume::FLOAT8 as volume, t.entry_price::FLOAT8 as entry_price, t.exit_price::FLOAT8 as exit_price, t.stop_loss::FLOAT8 as stop_loss, t.take_profit::FLOAT8 as take_profit, t.status, t.profit_loss::FLOAT8 as profit_loss, t.commission::FLOAT8 as commission, t.swap::FLOAT8 as swap, t.ai_confidence::FLOAT8 as ai_confidence, t.ai_reasoning, t.broker_trade_id, t.is_demo, t.stop_management, t.reentered_from, t.opened_at, t.closed_at, t.created_at, t.updated_at, t.parent_trade_id
+            FROM positions pos
+            JOIN trades t ON COALESCE(t.parent_trade_id, t.id) = pos.id
+            ORDER BY pos.created_at DESC, pos.id, t.parent_trade_id NULLS FIRST, t.opened_at
+            "#,
+        );
+        builder.build_query_as::<PositionLeg>().fetch_all(pool).await.db_op("trades.find_position_legs")
+    }
+
+    // Positions `find_position_legs` pages through
+    pub async fn count_positions(pool: &PgPool, user_id: Uuid, filter: &TradeFilter) -> Result<i64> {
+        let mut builder =
+            QueryBuilder::<Postgres>::new("SELECT COUNT(*) FROM trades WHERE parent_trade_id IS NULL AND user_id = ");
+        builder.push_bind(user_id);
+        filter.push_conditions(&mut builder, Utc::now());
+        builder.build_query_scalar::<i64>().fetch_one(pool).await.db_op("trades.count_positions")
     }
 
-    pub async fn find_by_robot_id(pool: &PgPool, robot_id: Uuid, user_id: Uuid) -> Result<Vec<Trade>, sqlx::Error> {
+    pub async fn find_by_robot_id(pool: &PgPool, robot_id: Uuid, user_id: Uuid) -> Result<Vec<Trade>> {
         let rows = sqlx::query!(
-            r#"SELECT id, user_id, robot_id, symbol, trade_type, volume::FLOAT8 as volume, entry_price::FLOAT8 as entry_price, exit_price::FLOAT8 as exit_price, stop_loss::FLOAT8 as stop_loss, take_profit::FLOAT8 as take_profit, status, profit_loss::FLOAT8 as profit_loss, commission::FLOAT8 as commission, swap::FLOAT8 as swap, ai_confidence::FLOAT8 as ai_confidence, ai_reasoning, broker_trade_id, opened_at, closed_at, created_at, updated_at FROM trades WHERE robot_id = $1 AND user_id = $2 ORDER BY created_at DESC"#,
+            r#"SELECT id, user_id, robot_id, symbol, trade_type, volume::FLOAT8 as volume, entry_price::FLOAT8 as entry_price, exit_price::FLOAT8 as exit_price, stop_loss::FLOAT8 as stop_loss, take_profit::FLOAT8 as take_profit, status, profit_loss::FLOAT8 as profit_loss, commission::FLOAT8 as commission, swap::FLOAT8 as swap, ai_confidence::FLOAT8 as ai_confidence, ai_reasoning, broker_trade_id, is_demo, stop_management, reentered_from, opened_at, closed_at, created_at, updated_at FROM trades WHERE robot_id = $1 AND user_id = $2 ORDER BY created_at DESC"#,
             robot_id,
             user_id
         )
         .fetch_all(pool)
-        .await?;
+        .await
+        .db_op("trades.find_by_robot_id")?;
 
         let trades = rows.into_iter().map(|row| Trade {
             id: row.id,
@@ -219,6 +359,9 @@ impl Trade {
             ai_confidence: if row.ai_confidence == 0.0 { None } else { Some(row.ai_confidence) },
             ai_reasoning: row.ai_reasoning.filter(|s| !s.is_empty()),
             broker_trade_id: row.broker_trade_id,
+            is_demo: row.is_demo,
+            stop_management: row.stop_management,
+            reentered_from: row.reentered_from,
             opened_at: row.opened_at,
             closed_at: row.closed_at,
             created_at: row.created_at,
@@ -228,14 +371,15 @@ impl Trade {
         Ok(trades)
     }
 
-    pub async fn find_by_id(pool: &PgPool, id: Uuid, user_id: Uuid) -> Result<Option<Trade>, sqlx::Error> {
+    pub async fn find_by_id(pool: &PgPool, id: Uuid, user_id: Uuid) -> Result<Option<Trade>> {
         let row = sqlx::query!(
-            r#"SELECT id, user_id, robot_id, symbol, trade_type, volume::FLOAT8 as volume, entry_price::FLOAT8 as entry_price, exit_price::FLOAT8 as exit_price, stop_loss::FLOAT8 as stop_loss, take_profit::FLOAT8 as take_profit, status, profit_loss::FLOAT8 as profit_loss, commission::FLOAT8 as commission, swap::FLOAT8 as swap, ai_confidence::FLOAT8 as ai_confidence, ai_reasoning, broker_trade_id, opened_at, closed_at, created_at, updated_at FROM trades WHERE id = $1 AND user_id = $2"#,
+            r#"SELECT id, user_id, robot_id, symbol, trade_type, volume::FLOAT8 as volume, entry_price::FLOAT8 as entry_price, exit_price::FLOAT8 as exit_price, stop_loss::FLOAT8 as stop_loss, take_profit::FLOAT8 as take_profit, status, profit_loss::FLOAT8 as profit_loss, commission::FLOAT8 as commission, swap::FLOAT8 as swap, ai_confidence::FLOAT8 as ai_confidence, ai_reasoning, broker_trade_id, is_demo, stop_management, reentered_from, opened_at, closed_at, created_at, updated_at FROM trades WHERE id = $1 AND user_id = $2"#,
             id,
             user_id
         )
         .fetch_optional(pool)
-        .await?;
+        .await
+        .db_op("trades.find_by_id")?;
 
         if let Some(row) = row {
             Ok(Some(Trade {
@@ -256,6 +400,9 @@ impl Trade {
                 ai_confidence: if row.ai_confidence == 0.0 { None } else { Some(row.ai_confidence) },
                 ai_reasoning: row.ai_reasoning.filter(|s| !s.is_empty()),
                 broker_trade_id: row.broker_trade_id,
+                is_demo: row.is_demo,
+                stop_management: row.stop_management,
+                reentered_from: row.reentered_from,
                 opened_at: row.opened_at,
                 closed_at: row.closed_at,
                 created_at: row.created_at,
@@ -274,8 +421,9 @@ impl Trade {
         commission: Option<f64>,
         swap: Option<f64>,
         broker_trade_id: Option<String>,
-    ) -> Result<(), sqlx::Error> {
-        sqlx::query!(
+    ) -> Result<()> {
+        let mut tx = pool.begin().await.db_op("trades.close_trade")?;
+        let result = sqlx::query!(
             "UPDATE trades SET exit_price = $1, status = 'closed', commission = $2, swap = $3, broker_trade_id = $4, closed_at = $5, updated_at = $6 WHERE id = $7 AND user_id = $8",
             exit_price,
             commission,
@@ -286,19 +434,146 @@ impl Trade {
             id,
             user_id
         )
-        .execute(pool)
-        .await?;
+        .execute(&mut *tx)
+        .await
+        .db_op("trades.close_trade")?;
+        if result.rows_affected() > 0 {
+            TradeDailyFact::record_close(&mut tx, id).await?;
+        }
+        tx.commit().await.db_op("trades.close_trade")?;
 
         Ok(())
     }
 
-    pub async fn get_open_trades(pool: &PgPool, user_id: Uuid) -> Result<Vec<Trade>, sqlx::Error> {
+    // For operator tools, which address trades by id alone
+    pub async fn owner_id(pool: &PgPool, id: Uuid) -> Result<Option<Uuid>> {
+        sqlx::query_scalar("SELECT user_id FROM trades WHERE id = $1")
+            .bind(id)
+            .fetch_optional(pool)
+            .await
+            .db_op("trades.owner_id")
+    }
+
+    pub async fn find_by_ids(pool: &PgPool, user_id: Uuid, ids: &[Uuid]) -> Result<Vec<Trade>> {
+        sqlx::query_as::<_, Trade>(
+            r#"SELECT id, user_id, robot_id, symbol, trade_type, volume::FLOAT8 as volume, entry_price::FLOAT8 as entry_price, exit_price::FLOAT8 as exit_price, stop_loss::FLOAT8 as stop_loss, take_profit::FLOAT8 as take_profit, status, profit_loss::FLOAT8 as profit_loss, commission::FLOAT8 as commission, swap::FLOAT8 as swap, ai_confidence::FLOAT8 as ai_confidence, ai_reasoning, broker_trade_id, is_demo, stop_management, reentered_from, opened_at, closed_at, created_at, updated_at FROM trades WHERE user_id = $1 AND id = ANY($2)"#,
+        )
+        .bind(user_id)
+        .bind(ids)
+        .fetch_all(pool)
+        .await
+        .db_op("trades.find_by_ids")
+    }
+
+    // Only transitions trades that are still open, so concurrent closes can't double-close
+    pub async fn close_open_trade(
+        pool: &PgPool,
+        id: Uuid,
+        user_id: Uuid,
+        exit_price: f64,
+        profit_loss: f64,
+    ) -> Result<bool> {
+        let now = Utc::now();
+        let mut tx = pool.begin().await.db_op("trades.close_open_trade")?;
+        let result = sqlx::query(
+            "UPDATE trades SET exit_price = $1, profit_loss = $2, status = 'closed', closed_at = $3, updated_at = $3 WHERE id = $4 AND user_id = $5 AND status = 'open'",
+        )
+        .bind(exit_price)
+        .bind(profit_loss)
+        .bind(now)
+        .bind(id)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await
+        .db_op("trades.close_open_trade")?;
+
+        let closed = result.rows_affected() > 0;
+        if closed {
+            TradeDailyFact::record_close(&mut tx, id).await?;
+        }
+        tx.commit().await.db_op("trades.close_open_trade")?;
+        Ok(closed)
+    }
+
+    // The trade a broker ticket belongs to, through the robot trading on the connection
+    pub async fn find_by_broker_ticket(pool: &PgPool, connection_id: Uuid, ticket: &str) -> Result<Option<Trade>> {
+        sqlx::query_as::<_, Trade>(
+            r#"SELECT t.id, t.user_id, t.robot_id, t.symbol, t.trade_type, t.volume::FLOAT8 as volume, t.entry_price::FLOAT8 as entry_price, t.exit_price::FLOAT8 as exit_price, t.stop_loss::FLOAT8 as stop_loss, t.take_profit::FLOAT8 as take_profit, t.status, t.profit_loss::FLOAT8 as profit_loss, t.commission::FLOAT8 as commission, t.swap::FLOAT8 as swap, t.ai_confidence::FLOAT8 as ai_confidence, t.ai_reasoning, t.broker_trade_id, t.is_demo, t.stop_management, t.reentered_from, t.opened_at, t.closed_at, t.created_at, t.updated_at FROM trades t JOIN trading_robots r ON r.id = t.robot_id WHERE r.broker_connection_id = $1 AND t.broker_trade_id = $2"#,
+        )
+        .bind(connection_id)
+        .bind(ticket)
+        .fetch_optional(pool)
+        .await
+        .db_op("trades.find_by_broker_ticket")
+    }
+
+    // Takes the broker's fill price; an execution_pending trade is open from here on. For a trade
+    // the broker already reported closed, the price is recorded as a correction.
+    pub async fn record_fill(pool: &PgPool, id: Uuid, entry_price: f64) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE trades SET entry_price = $1, status = CASE WHEN status = 'execution_pending' THEN 'open' ELSE status END, updated_at = NOW() WHERE id = $2 AND status IN ('open', 'execution_pending')",
+        )
+        .bind(entry_price)
+        .bind(id)
+        .execute(pool)
+        .await
+        .db_op("trades.record_fill")?;
+        if result.rows_affected() > 0 {
+            return Ok(true);
+        }
+
+        let amendment = TradeAmendment { entry_price: Some(entry_price), ..Default::default() };
+        Ok(TradeCorrection::record(pool, id, &amendment, CORRECTION_LATE_FILL).await?.is_some())
+    }
+
+    // A close reported by the broker, which may arrive before the fill did
+    pub async fn close_from_broker(
+        pool: &PgPool,
+        id: Uuid,
+        exit_price: f64,
+        profit_loss: f64,
+        commission: f64,
+        swap: f64,
+    ) -> Result<bool> {
+        let mut tx = pool.begin().await.db_op("trades.close_from_broker")?;
+        let result = sqlx::query(
+            "UPDATE trades SET exit_price = $1, profit_loss = $2, commission = $3, swap = $4, status = 'closed', closed_at = NOW(), updated_at = NOW() WHERE id = $5 AND status IN ('open', 'execution_pending')",
+        )
+        .bind(exit_price)
+        .bind(profit_loss)
+        .bind(commission)
+        .bind(swap)
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        .db_op("trades.close_from_broker")?;
+
+        let closed = result.rows_affected() > 0;
+        if closed {
+            TradeDailyFact::record_close(&mut tx, id).await?;
+        }
+        tx.commit().await.db_op("trades.close_from_broker")?;
+        Ok(closed)
+    }
+
+    pub async fn get_open_trades_for_robot(pool: &PgPool, robot_id: Uuid) -> Result<Vec<Trade>> {
+        sqlx::query_as::<_, Trade>(
+            r#"SELECT id, user_id, robot_id, symbol, trade_type, volume::FLOAT8 as volume, entry_price::FLOAT8 as entry_price, exit_price::FLOAT8 as exit_price, stop_loss::FLOAT8 as stop_loss, take_profit::FLOAT8 as take_profit, status, profit_loss::FLOAT8 as profit_loss, commission::FLOAT8 as commission, swap::FLOAT8 as swap, ai_confidence::FLOAT8 as ai_confidence, ai_reasoning, broker_trade_id, is_demo, stop_management, reentered_from, opened_at, closed_at, created_at, updated_at FROM trades WHERE robot_id = $1 AND status = 'open' ORDER BY opened_at"#,
+        )
+        .bind(robot_id)
+        .fetch_all(pool)
+        .await
+        .db_op("trades.get_open_trades_for_robot")
+    }
+
+    pub async fn get_open_trades(pool: &PgPool, user_id: Uuid) -> Result<Vec<Trade>> {
         let rows = sqlx::query!(
-            r#"SELECT id, user_id, robot_id, symbol, trade_type, volume::FLOAT8 as volume, entry_price::FLOAT8 as entry_price, exit_price::FLOAT8 as exit_price, stop_loss::FLOAT8 as stop_loss, take_profit::FLOAT8 as take_profit, status, profit_loss::FLOAT8 as profit_loss, commission::FLOAT8 as commission, swap::FLOAT8 as swap, ai_confidence::FLOAT8 as ai_confidence, ai_reasoning, broker_trade_id, opened_at, closed_at, created_at, updated_at FROM trades WHERE user_id = $1 AND status = 'open' ORDER BY created_at DESC"#,
+            r#"SELECT id, user_id, robot_id, symbol, trade_type, volume::FLOAT8 as volume, entry_price::FLOAT8 as entry_price, exit_price::FLOAT8 as exit_price, stop_loss::FLOAT8 as stop_loss, take_profit::FLOAT8 as take_profit, status, profit_loss::FLOAT8 as profit_loss, commission::FLOAT8 as commission, swap::FLOAT8 as swap, ai_confidence::FLOAT8 as ai_confidence, ai_reasoning, broker_trade_id, is_demo, stop_management, reentered_from, opened_at, closed_at, created_at, updated_at FROM trades WHERE user_id = $1 AND status = 'open' ORDER BY created_at DESC"#,
             user_id
         )
         .fetch_all(pool)
-        .await?;
+        .await
+        .db_op("trades.get_open_trades")?;
 
         let trades = rows.into_iter().map(|row| Trade {
             id: row.id,
@@ -318,6 +593,9 @@ impl Trade {
             ai_confidence: if row.ai_confidence == 0.0 { None } else { Some(row.ai_confidence) },
             ai_reasoning: row.ai_reasoning.filter(|s| !s.is_empty()),
             broker_trade_id: row.broker_trade_id,
+            is_demo: row.is_demo,
+            stop_management: row.stop_management,
+            reentered_from: row.reentered_from,
             opened_at: row.opened_at,
             closed_at: row.closed_at,
             created_at: row.created_at,
@@ -327,6 +605,65 @@ impl Trade {
         Ok(trades)
     }
 
+    // `pattern` is an ILIKE pattern with its wildcards already escaped
+    pub async fn search(
+        pool: &PgPool,
+        user_id: Uuid,
+        pattern: &str,
+        filter: &TradeFilter,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Trade>> {
+        let mut builder = QueryBuilder::<Postgres>::new(
+            r#"SELECT id, user_id, robot_id, symbol, trade_type, volume::FLOAT8 as volume, entry_price::FLOAT8 as entry_price, exit_price::FLOAT8 as exit_price, stop_loss::FLOAT8 as stop_loss, take_profit::FLOAT8 as take_profit, status, profit_loss::FLOAT8 as profit_loss, commission::FLOAT8 as commission, swap::FLOAT8 as swap, ai_confidence::FLOAT8 as ai_confidence, ai_reasoning, broker_trade_id, is_demo, stop_management, reentered_from, opened_at, closed_at, created_at, updated_at FROM trades WHERE user_id = "#,
+        );
+        builder.push_bind(user_id);
+        builder.push(" AND ").push(TRADE_SEARCH_DOCUMENT).push(" ILIKE ").push_bind(pattern);
+        filter.push_conditions(&mut builder, Utc::now());
+        builder.push(" ORDER BY opened_at DESC LIMIT ").push_bind(limit).push(" OFFSET ").push_bind(offset);
+
+        builder.build_query_as::<Trade>().fetch_all(pool).await.db_op("trades.search")
+    }
+
+    // Newest first by (created_at, id), which stays put while trades are inserted. `after` is
+    // the last trade of the previous page; `offset` is only for clients still paging by it.
+    pub async fn find_page(
+        pool: &PgPool,
+        user_id: Uuid,
+        filter: &TradeFilter,
+        after: Option<&TradeCursor>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Trade>> {
+        let mut builder = QueryBuilder::<Postgres>::new(
+            r#"SELECT id, user_id, robot_id, symbol, trade_type, volume::FLOAT8 as volume, entry_price::FLOAT8 as entry_price, exit_price::FLOAT8 as exit_price, stop_loss::FLOAT8 as stop_loss, take_profit::FLOAT8 as take_profit, status, profit_loss::FLOAT8 as profit_loss, commission::FLOAT8 as commission, swap::FLOAT8 as swap, ai_confidence::FLOAT8 as ai_confidence, ai_reasoning, broker_trade_id, is_demo, stop_management, reentered_from, opened_at, closed_at, created_at, updated_at FROM trades WHERE user_id = "#,
+        );
+        builder.push_bind(user_id);
+        filter.push_conditions(&mut builder, Utc::now());
+        if let Some(after) = after {
+            builder
+                .push(" AND (created_at, id) < (")
+                .push_bind(after.created_at)
+                .push(", ")
+                .push_bind(after.id)
+                .push(")");
+        }
+        builder.push(" ORDER BY created_at DESC, id DESC LIMIT ").push_bind(limit);
+        if offset > 0 {
+            builder.push(" OFFSET ").push_bind(offset);
+        }
+
+        builder.build_query_as::<Trade>().fetch_all(pool).await.db_op("trades.find_page")
+    }
+
+    // Every trade find_page would page through
+    pub async fn count_page(pool: &PgPool, user_id: Uuid, filter: &TradeFilter) -> Result<i64> {
+        let mut builder = QueryBuilder::<Postgres>::new("SELECT COUNT(*) FROM trades WHERE user_id = ");
+        builder.push_bind(user_id);
+        filter.push_conditions(&mut builder, Utc::now());
+        builder.build_query_scalar::<i64>().fetch_one(pool).await.db_op("trades.count_page")
+    }
+
     pub fn calculate_profit_loss(&self, current_price: f64) -> f64 {
         match self.trade_type.as_str() {
             "buy" => current_price - self.entry_price,
@@ -339,43 +676,272 @@ impl Trade {
         self.calculate_profit_loss(current_price) > 0.0
     }
 
-    pub async fn get_statistics(pool: &PgPool, user_id: Uuid) -> Result<TradeStatistics, sqlx::Error> {
-        let stats = sqlx::query!(
+    // Closed trades only; read from trade_daily_facts once its backfill has completed. With
+    // `as_of`, the statistics as they stood at that instant, which only the trades table can show.
+    pub async fn get_filtered_statistics(
+        pool: &PgPool,
+        user_id: Uuid,
+        filter: &TradeFilter,
+        as_of: Option<DateTime<Utc>>,
+    ) -> Result<TradeStatistics> {
+        let now = as_of.unwrap_or_else(Utc::now);
+        let totals = if as_of.is_none() && TradeDailyFact::is_ready(pool).await? {
+            TradeDailyFact::closed_totals(pool, user_id, filter, now).await?
+        } else {
+            TradeDailyFact::closed_totals_from_trades(pool, user_id, filter, now, as_of).await?
+        };
+
+        let (avg_profit, win_rate) = if totals.trades > 0 {
+            (totals.net_profit / totals.trades as f64, (totals.wins as f64 / totals.trades as f64) * 100.0)
+        } else {
+            (0.0, 0.0)
+        };
+        Ok(TradeStatistics {
+            total_trades: totals.trades as i32,
+            winning_trades: totals.wins as i32,
+            total_profit: totals.net_profit,
+            avg_profit,
+            win_rate,
+            review_breakdown: None,
+            truncated: false,
+        })
+    }
+
+    // Live trades per UTC day of closing; `since` is a UTC midnight. With `as_of`, the days as
+    // they stood at that instant.
+    pub async fn get_daily_summaries(
+        pool: &PgPool,
+        user_id: Uuid,
+        since: DateTime<Utc>,
+        as_of: Option<DateTime<Utc>>,
+    ) -> Result<Vec<DailyTradeSummary>> {
+        if as_of.is_none() && TradeDailyFact::is_ready(pool).await? {
+            return TradeDailyFact::daily_summaries(pool, user_id, since).await;
+        }
+        let mut builder = QueryBuilder::<Postgres>::new(
             r#"
-            SELECT 
-                COUNT(*) as total_trades,
+            SELECT
+                date_trunc('day', closed_at AT TIME ZONE 'UTC') AT TIME ZONE 'UTC' as day,
+                COUNT(*) as trades,
                 COUNT(CASE WHEN profit_loss::FLOAT8 > 0 THEN 1 END) as winning_trades,
-                COALESCE(SUM(profit_loss::FLOAT8), 0) as total_profit,
-                COALESCE(AVG(profit_loss::FLOAT8), 0) as avg_profit
-            FROM trades 
-            WHERE user_id = $1
-            "#,
-            user_id
+                COALESCE(SUM(profit_loss::FLOAT8), 0) as profit
+            FROM "#,
+        );
+        TradeCorrection::push_corrected_trades(&mut builder, as_of);
+        builder.push(" WHERE user_id = ").push_bind(user_id);
+        builder.push(" AND status = 'closed' AND closed_at >= ").push_bind(since);
+        builder.push(" AND is_demo = FALSE");
+        TradeCorrection::push_as_of(&mut builder, as_of);
+        builder.push(" GROUP BY 1 ORDER BY 1");
+
+        builder
+            .build_query_as::<DailyTradeSummary>()
+            .fetch_all(pool)
+            .await
+            .db_op("trades.get_daily_summaries")
+    }
+
+    // Trades that count against the plan's daily operation limit; demo trades are exempt
+    pub async fn count_live_operations_since(
+        pool: &PgPool,
+        user_id: Uuid,
+        since: DateTime<Utc>,
+    ) -> Result<i64> {
+        sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM trades WHERE user_id = $1 AND opened_at >= $2 AND is_demo = FALSE",
         )
+        .bind(user_id)
+        .bind(since)
         .fetch_one(pool)
-        .await?;
+        .await
+        .db_op("trades.count_live_operations_since")
+    }
 
-        Ok(TradeStatistics {
-            total_trades: stats.total_trades.unwrap_or(0) as i32,
-            winning_trades: stats.winning_trades.unwrap_or(0) as i32,
-            total_profit: stats.total_profit.unwrap_or(0.0),
-            avg_profit: stats.avg_profit.unwrap_or(0.0),
-            win_rate: if stats.total_trades.unwrap_or(0) > 0 {
-                (stats.winning_trades.unwrap_or(0) as f64 / stats.total_trades.unwrap_or(1) as f64) * 100.0
-            } else {
-                0.0
-            },
-        })
+    pub async fn count_by_client(pool: &PgPool) -> Result<Vec<ClientCount>> {
+        sqlx::query_as::<_, ClientCount>(
+            "SELECT created_via, COUNT(*) AS count FROM trades GROUP BY created_via ORDER BY count DESC",
+        )
+        .fetch_all(pool)
+        .await
+        .db_op("trades.count_by_client")
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+// Live-only unless the caller opts in to demo trades
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DemoMode {
+    #[default]
+    Exclude,
+    Include,
+    Only,
+}
+
+impl DemoMode {
+    // Parses the `include_demo=true|only` query parameter
+    pub fn from_query(value: Option<&str>) -> Result<DemoMode, String> {
+        match value.map(|v| v.trim().to_lowercase()).as_deref() {
+            None | Some("") | Some("false") => Ok(DemoMode::Exclude),
+            Some("true") => Ok(DemoMode::Include),
+            Some("only") => Ok(DemoMode::Only),
+            Some(other) => Err(format!("Invalid include_demo value: {}", other)),
+        }
+    }
+
+    pub fn matches(&self, is_demo: bool) -> bool {
+        match self {
+            DemoMode::Exclude => !is_demo,
+            DemoMode::Include => true,
+            DemoMode::Only => is_demo,
+        }
+    }
+}
+
+// Shared trade selection used by statistics and saved presets
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct TradeFilter {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    // Rolling window in days ending now; mutually exclusive with from/to
+    pub rolling_days: Option<i64>,
+    #[serde(default)]
+    pub robot_ids: Vec<Uuid>,
+    #[serde(default)]
+    pub symbols: Vec<String>,
+    #[serde(default)]
+    pub demo: DemoMode,
+}
+
+impl TradeFilter {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(days) = self.rolling_days {
+            if self.from.is_some() || self.to.is_some() {
+                return Err("Use either a date range or a rolling window, not both".to_string());
+            }
+            if !(1..=3650).contains(&days) {
+                return Err("Rolling window must be between 1 and 3650 days".to_string());
+            }
+        }
+        if let (Some(from), Some(to)) = (self.from, self.to) {
+            if from >= to {
+                return Err("Filter start must be before its end".to_string());
+            }
+        }
+        if self.symbols.iter().any(|s| s.trim().is_empty() || s.len() > 20) {
+            return Err("Invalid symbol in filter".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn window(&self, now: DateTime<Utc>) -> (Option<DateTime<Utc>>, Option<DateTime<Utc>>) {
+        match self.rolling_days {
+            Some(days) => (Some(now - chrono::Duration::days(days)), None),
+            None => (self.from, self.to),
+        }
+    }
+
+    // Drops robot references that no longer resolve; if none remain the robot criterion is ignored
+    pub fn without_robots(&self, missing: &[Uuid]) -> TradeFilter {
+        TradeFilter {
+            robot_ids: self
+                .robot_ids
+                .iter()
+                .filter(|id| !missing.contains(id))
+                .copied()
+                .collect(),
+            ..self.clone()
+        }
+    }
+
+    // The UTC days the window covers completely, as [first, end); None where it is open-ended
+    pub fn full_days(&self, now: DateTime<Utc>) -> (Option<NaiveDate>, Option<NaiveDate>) {
+        let (from, to) = self.window(now);
+        let first = from.map(|from| {
+            let day = from.date_naive();
+            if from.time() == NaiveTime::MIN { day } else { day.succ_opt().unwrap_or(day) }
+        });
+        (first, to.map(|to| to.date_naive()))
+    }
+
+    pub fn push_conditions<'a>(&'a self, builder: &mut QueryBuilder<'a, Postgres>, now: DateTime<Utc>) {
+        let (from, to) = self.window(now);
+        if let Some(from) = from {
+            builder.push(" AND opened_at >= ").push_bind(from);
+        }
+        if let Some(to) = to {
+            builder.push(" AND opened_at < ").push_bind(to);
+        }
+        self.push_scope(builder);
+    }
+
+    // Robot, symbol and demo conditions only; trade_daily_facts has the same columns
+    pub fn push_scope<'a>(&'a self, builder: &mut QueryBuilder<'a, Postgres>) {
+        if !self.robot_ids.is_empty() {
+            builder.push(" AND robot_id = ANY(").push_bind(&self.robot_ids).push(")");
+        }
+        if !self.symbols.is_empty() {
+            builder.push(" AND symbol = ANY(").push_bind(&self.symbols).push(")");
+        }
+        match self.demo {
+            DemoMode::Exclude => {
+                builder.push(" AND is_demo = FALSE");
+            }
+            DemoMode::Only => {
+                builder.push(" AND is_demo = TRUE");
+            }
+            DemoMode::Include => {}
+        }
+    }
+}
+
+// Where a page of trades ended, in the list's (created_at, id) order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TradeCursor {
+    pub created_at: DateTime<Utc>,
+    pub id: Uuid,
+}
+
+impl TradeCursor {
+    pub fn of(trade: &Trade) -> TradeCursor {
+        TradeCursor { created_at: trade.created_at, id: trade.id }
+    }
+}
+
+// Text matched by trade search; must stay identical to the expression indexed by idx_trades_search
+pub const TRADE_SEARCH_DOCUMENT: &str =
+    "(COALESCE(ai_reasoning, '') || ' ' || symbol || ' ' || COALESCE(broker_trade_id, ''))";
+
+// Rows per X-Client value, for the admin overview
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, FromRow)]
+pub struct ClientCount {
+    pub created_via: String,
+    pub count: i64,
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct DailyTradeSummary {
+    pub day: DateTime<Utc>,
+    pub trades: i64,
+    pub winning_trades: i64,
+    pub profit: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct TradeStatistics {
     pub total_trades: i32,
     pub winning_trades: i32,
+    #[serde(serialize_with = "crate::money::serialize_aggregate")]
     pub total_profit: f64,
+    #[serde(serialize_with = "crate::money::serialize_aggregate")]
     pub avg_profit: f64,
+    #[serde(serialize_with = "crate::money::serialize_percent")]
     pub win_rate: f64,
+    // Only with `breakdown=review`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub review_breakdown: Option<ReviewBreakdown>,
+    // The range was cut to the history the plan covers
+    #[serde(default)]
+    pub truncated: bool,
 }
 
 impl From<Trade> for TradeResponse {
@@ -397,6 +963,9 @@ impl From<Trade> for TradeResponse {
             ai_confidence: trade.ai_confidence,
             ai_reasoning: trade.ai_reasoning,
             broker_trade_id: trade.broker_trade_id,
+            is_demo: trade.is_demo,
+            stop_management: trade.stop_management,
+            reentered_from: trade.reentered_from,
             opened_at: trade.opened_at,
             closed_at: trade.closed_at,
             created_at: trade.created_at,
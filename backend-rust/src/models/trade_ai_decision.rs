@@ -0,0 +1,196 @@
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+use crate::errors::{DbOp, Result};
+
+pub const DECISION_MODEL: &str = "model";
+pub const DECISION_FALLBACK: &str = "fallback";
+
+#[derive(Debug, Clone, PartialEq, Serialize, FromRow, JsonSchema)]
+pub struct TradeAiDecision {
+    pub trade_id: Uuid,
+    // model | fallback
+    pub source: String,
+    // What the rule-based fallback said for the same market data: buy | sell | hold
+    pub fallback_signal: Option<String>,
+    pub decided_at: DateTime<Utc>,
+}
+
+// Closed AI decisions of one robot by source
+#[derive(Debug, Clone, Copy, Default, PartialEq, FromRow)]
+pub struct DecisionCounts {
+    pub decisions: i64,
+    pub model_decisions: i64,
+}
+
+// A closed model decision: the confidence it was made with and whether it made money
+#[derive(Debug, Clone, Copy, PartialEq, FromRow)]
+pub struct DecisionOutcome {
+    pub confidence: f64,
+    pub won: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, FromRow, JsonSchema)]
+pub struct SignalHitRate {
+    // buy | sell
+    pub signal: String,
+    pub trades: i64,
+    pub wins: i64,
+    pub hit_rate: f64,
+}
+
+// One tenth of the model decisions, ordered by confidence
+#[derive(Debug, Clone, PartialEq, Serialize, FromRow, JsonSchema)]
+pub struct ConfidenceDecile {
+    // 1 is the least confident
+    pub decile: i32,
+    pub min_confidence: f64,
+    pub max_confidence: f64,
+    pub trades: i64,
+    // Trades with a stop loss to measure R against
+    pub trades_with_r: i64,
+    pub average_r: Option<f64>,
+}
+
+// Model decisions whose fallback signal is known, split by whether the model went another way
+#[derive(Debug, Clone, Copy, Default, PartialEq, FromRow)]
+pub struct OverrideTotals {
+    pub compared: i64,
+    pub overridden: i64,
+    pub overridden_avg_profit: Option<f64>,
+    pub agreed_avg_profit: Option<f64>,
+}
+
+// Closed AI trades of robot $1 closed in [$2, $3)
+const CLOSED_DECISIONS: &str = r#"
+    FROM trade_ai_decisions d
+    JOIN trades t ON t.id = d.trade_id
+    WHERE t.robot_id = $1 AND t.status = 'closed' AND t.closed_at >= $2 AND t.closed_at < $3"#;
+
+// Realized move over the initial risk to the stop loss, in the trade's direction
+const R_MULTIPLE: &str = r#"
+    CASE
+        WHEN t.stop_loss IS NULL OR t.exit_price IS NULL OR t.stop_loss = t.entry_price THEN NULL
+        WHEN t.trade_type = 'buy' THEN (t.exit_price - t.entry_price)::FLOAT8 / (t.entry_price - t.stop_loss)::FLOAT8
+        ELSE (t.entry_price - t.exit_price)::FLOAT8 / (t.stop_loss - t.entry_price)::FLOAT8
+    END"#;
+
+impl TradeAiDecision {
+    pub fn new(trade_id: Uuid, source: &str, decided_at: DateTime<Utc>) -> Self {
+        TradeAiDecision { trade_id, source: source.to_string(), fallback_signal: None, decided_at }
+    }
+
+    pub fn with_fallback_signal(mut self, signal: &str) -> Self {
+        self.fallback_signal = Some(signal.to_ascii_lowercase());
+        self
+    }
+
+    pub async fn record(pool: &PgPool, decision: &TradeAiDecision) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO trade_ai_decisions (trade_id, source, fallback_signal, decided_at)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (trade_id) DO NOTHING
+            "#,
+        )
+        .bind(decision.trade_id)
+        .bind(&decision.source)
+        .bind(&decision.fallback_signal)
+        .bind(decision.decided_at)
+        .execute(pool)
+        .await
+        .db_op("trade_ai_decisions.record")?;
+        Ok(())
+    }
+
+    pub async fn counts(pool: &PgPool, robot_id: Uuid, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<DecisionCounts> {
+        sqlx::query_as::<_, DecisionCounts>(&format!(
+            "SELECT COUNT(*) AS decisions, COUNT(*) FILTER (WHERE d.source = 'model') AS model_decisions {}",
+            CLOSED_DECISIONS
+        ))
+        .bind(robot_id)
+        .bind(from)
+        .bind(to)
+        .fetch_one(pool)
+        .await
+        .db_op("trade_ai_decisions.counts")
+    }
+
+    pub async fn model_outcomes(pool: &PgPool, robot_id: Uuid, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<DecisionOutcome>> {
+        sqlx::query_as::<_, DecisionOutcome>(&format!(
+            "SELECT t.ai_confidence::FLOAT8 AS confidence, COALESCE(t.profit_loss > 0, FALSE) AS won {} AND d.source = 'model' AND t.ai_confidence IS NOT NULL",
+            CLOSED_DECISIONS
+        ))
+        .bind(robot_id)
+        .bind(from)
+        .bind(to)
+        .fetch_all(pool)
+        .await
+        .db_op("trade_ai_decisions.model_outcomes")
+    }
+
+    pub async fn hit_rate_by_signal(pool: &PgPool, robot_id: Uuid, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<SignalHitRate>> {
+        sqlx::query_as::<_, SignalHitRate>(&format!(
+            r#"
+            SELECT t.trade_type AS signal, COUNT(*) AS trades, COUNT(*) FILTER (WHERE t.profit_loss > 0) AS wins,
+                (COUNT(*) FILTER (WHERE t.profit_loss > 0))::FLOAT8 / COUNT(*) AS hit_rate
+            {} AND d.source = 'model'
+            GROUP BY t.trade_type
+            ORDER BY t.trade_type
+            "#,
+            CLOSED_DECISIONS
+        ))
+        .bind(robot_id)
+        .bind(from)
+        .bind(to)
+        .fetch_all(pool)
+        .await
+        .db_op("trade_ai_decisions.hit_rate_by_signal")
+    }
+
+    pub async fn r_by_confidence_decile(pool: &PgPool, robot_id: Uuid, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<ConfidenceDecile>> {
+        sqlx::query_as::<_, ConfidenceDecile>(&format!(
+            r#"
+            SELECT decile, MIN(confidence) AS min_confidence, MAX(confidence) AS max_confidence, COUNT(*) AS trades,
+                COUNT(r) AS trades_with_r, AVG(r) AS average_r
+            FROM (
+                SELECT t.ai_confidence::FLOAT8 AS confidence, NTILE(10) OVER (ORDER BY t.ai_confidence, t.id) AS decile, {} AS r
+                {} AND d.source = 'model' AND t.ai_confidence IS NOT NULL
+            ) ranked
+            GROUP BY decile
+            ORDER BY decile
+            "#,
+            R_MULTIPLE, CLOSED_DECISIONS
+        ))
+        .bind(robot_id)
+        .bind(from)
+        .bind(to)
+        .fetch_all(pool)
+        .await
+        .db_op("trade_ai_decisions.r_by_confidence_decile")
+    }
+
+    pub async fn override_totals(pool: &PgPool, robot_id: Uuid, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<OverrideTotals> {
+        sqlx::query_as::<_, OverrideTotals>(&format!(
+            r#"
+            SELECT COUNT(*) AS compared, COUNT(*) FILTER (WHERE overridden) AS overridden,
+                AVG(profit_loss) FILTER (WHERE overridden) AS overridden_avg_profit,
+                AVG(profit_loss) FILTER (WHERE NOT overridden) AS agreed_avg_profit
+            FROM (
+                SELECT d.fallback_signal <> t.trade_type AS overridden, t.profit_loss::FLOAT8 AS profit_loss
+                {} AND d.source = 'model' AND d.fallback_signal IS NOT NULL
+            ) compared
+            "#,
+            CLOSED_DECISIONS
+        ))
+        .bind(robot_id)
+        .bind(from)
+        .bind(to)
+        .fetch_one(pool)
+        .await
+        .db_op("trade_ai_decisions.override_totals")
+    }
+}
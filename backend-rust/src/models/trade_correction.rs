@@ -0,0 +1,178 @@
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool, Postgres, QueryBuilder};
+use uuid::Uuid;
+
+use crate::errors::{DbOp, Result};
+use super::{TradeDailyFact, TradingRobot};
+
+// A broker fill that arrived after the trade was already closed
+pub const CORRECTION_LATE_FILL: &str = "late_fill";
+
+// The P/L fields of a closed trade from `recorded_at` on, until a later correction
+#[derive(Debug, Clone, PartialEq, Serialize, FromRow, JsonSchema)]
+pub struct TradeCorrection {
+    pub id: Uuid,
+    pub trade_id: Uuid,
+    pub entry_price: f64,
+    pub exit_price: Option<f64>,
+    pub profit_loss: Option<f64>,
+    pub commission: Option<f64>,
+    pub swap: Option<f64>,
+    pub reason: String,
+    pub recorded_at: DateTime<Utc>,
+}
+
+// New values for some of a closed trade's P/L fields; the others carry over
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, JsonSchema)]
+pub struct TradeAmendment {
+    pub entry_price: Option<f64>,
+    pub exit_price: Option<f64>,
+    pub profit_loss: Option<f64>,
+    pub commission: Option<f64>,
+    pub swap: Option<f64>,
+}
+
+// Trades with each one's P/L fields taken from its latest correction, under the trades table's
+// own name and columns so filters written against it apply unchanged
+const CORRECTED_TRADES_START: &str = r#"(
+    SELECT t.id, t.user_id, t.robot_id, t.symbol, t.is_demo, t.status, t.volume, t.opened_at, t.closed_at, t.recorded_at,
+        CASE WHEN c.id IS NULL THEN t.entry_price ELSE c.entry_price END AS entry_price,
+        CASE WHEN c.id IS NULL THEN t.exit_price ELSE c.exit_price END AS exit_price,
+        CASE WHEN c.id IS NULL THEN t.profit_loss ELSE c.profit_loss END AS profit_loss,
+        CASE WHEN c.id IS NULL THEN t.commission ELSE c.commission END AS commission,
+        CASE WHEN c.id IS NULL THEN t.swap ELSE c.swap END AS swap
+    FROM trades t
+    LEFT JOIN LATERAL (
+        SELECT id, entry_price, exit_price, profit_loss, commission, swap
+        FROM trade_corrections
+        WHERE trade_id = t.id"#;
+
+const CORRECTED_TRADES_END: &str = r#"
+        ORDER BY recorded_at DESC, id DESC
+        LIMIT 1
+    ) c ON TRUE
+) trades"#;
+
+impl TradeCorrection {
+    // An `as_of` must be in the past; leaving it out asks for the current view
+    pub fn validate_as_of(as_of: Option<DateTime<Utc>>, now: DateTime<Utc>) -> Result<(), String> {
+        match as_of {
+            Some(as_of) if as_of > now => Err("as_of must not be in the future".to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    // The current view: every correction applies
+    pub fn corrected_trades() -> String {
+        format!("{}{}", CORRECTED_TRADES_START, CORRECTED_TRADES_END)
+    }
+
+    // The view as it stood at `as_of`, when given: later corrections are left out. Trades
+    // recorded or closed later are left out by `push_as_of`.
+    pub fn push_corrected_trades(builder: &mut QueryBuilder<'_, Postgres>, as_of: Option<DateTime<Utc>>) {
+        builder.push(CORRECTED_TRADES_START);
+        if let Some(as_of) = as_of {
+            builder.push(" AND recorded_at <= ").push_bind(as_of);
+        }
+        builder.push(CORRECTED_TRADES_END);
+    }
+
+    // Leaves out trades recorded, or closed, after `as_of`, for queries on the corrected trades
+    pub fn push_as_of(builder: &mut QueryBuilder<'_, Postgres>, as_of: Option<DateTime<Utc>>) {
+        if let Some(as_of) = as_of {
+            builder.push(" AND recorded_at <= ").push_bind(as_of);
+            builder.push(" AND closed_at <= ").push_bind(as_of);
+        }
+    }
+
+    // Appends a correction to a closed trade, starting from its current values, and brings its
+    // robot's facts and rollups up to date. None when the trade is not closed.
+    pub async fn record(pool: &PgPool, trade_id: Uuid, amendment: &TradeAmendment, reason: &str) -> Result<Option<TradeCorrection>> {
+        let mut tx = pool.begin().await.db_op("trade_corrections.record")?;
+        let correction = sqlx::query_as::<_, TradeCorrection>(&format!(
+            r#"
+            INSERT INTO trade_corrections (trade_id, entry_price, exit_price, profit_loss, commission, swap, reason)
+            SELECT id, COALESCE($2, entry_price), COALESCE($3, exit_price), COALESCE($4, profit_loss),
+                COALESCE($5, commission), COALESCE($6, swap), $7
+            FROM {}
+            WHERE id = $1 AND status = 'closed'
+            RETURNING id, trade_id, entry_price, exit_price, profit_loss, commission, swap, reason, recorded_at
+            "#,
+            Self::corrected_trades()
+        ))
+        .bind(trade_id)
+        .bind(amendment.entry_price)
+        .bind(amendment.exit_price)
+        .bind(amendment.profit_loss)
+        .bind(amendment.commission)
+        .bind(amendment.swap)
+        .bind(reason)
+        .fetch_optional(&mut *tx)
+        .await
+        .db_op("trade_corrections.record")?;
+
+        let Some(correction) = correction else {
+            return Ok(None);
+        };
+        // Only the profit feeds the facts and rollups
+        let robot_id = if amendment.profit_loss.is_some() {
+            let robot_id: Uuid = sqlx::query_scalar("SELECT robot_id FROM trades WHERE id = $1")
+                .bind(trade_id)
+                .fetch_one(&mut *tx)
+                .await
+                .db_op("trade_corrections.robot")?;
+            TradeDailyFact::rebuild_robots(&mut tx, &[robot_id]).await?;
+            Some(robot_id)
+        } else {
+            None
+        };
+        tx.commit().await.db_op("trade_corrections.record")?;
+
+        if let Some(robot_id) = robot_id {
+            TradingRobot::refresh_performance(pool, robot_id).await?;
+        }
+        Ok(Some(correction))
+    }
+
+    // Oldest first
+    pub async fn find_by_trade_id(pool: &PgPool, trade_id: Uuid) -> Result<Vec<TradeCorrection>> {
+        sqlx::query_as::<_, TradeCorrection>(
+            "SELECT id, trade_id, entry_price, exit_price, profit_loss, commission, swap, reason, recorded_at FROM trade_corrections WHERE trade_id = $1 ORDER BY recorded_at, id",
+        )
+        .bind(trade_id)
+        .fetch_all(pool)
+        .await
+        .db_op("trade_corrections.find_by_trade_id")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_as_of_must_not_be_in_the_future() {
+        let now = Utc::now();
+        assert!(TradeCorrection::validate_as_of(None, now).is_ok());
+        assert!(TradeCorrection::validate_as_of(Some(now - Duration::days(7)), now).is_ok());
+        assert!(TradeCorrection::validate_as_of(Some(now + Duration::seconds(1)), now).is_err());
+    }
+
+    #[test]
+    fn test_as_of_leaves_out_later_corrections_and_trades() {
+        let as_of = Utc::now();
+        let mut current = QueryBuilder::<Postgres>::new("SELECT profit_loss FROM ");
+        TradeCorrection::push_corrected_trades(&mut current, None);
+        let mut past = QueryBuilder::<Postgres>::new("SELECT profit_loss FROM ");
+        TradeCorrection::push_corrected_trades(&mut past, Some(as_of));
+        past.push(" WHERE status = 'closed'");
+        TradeCorrection::push_as_of(&mut past, Some(as_of));
+
+        assert_eq!(current.sql(), format!("SELECT profit_loss FROM {}", TradeCorrection::corrected_trades()));
+        assert!(past.sql().contains("WHERE trade_id = t.id AND recorded_at <= $1"));
+        assert!(past.sql().ends_with(") trades WHERE status = 'closed' AND recorded_at <= $2 AND closed_at <= $3"));
+    }
+}
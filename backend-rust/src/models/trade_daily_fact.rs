@@ -0,0 +1,308 @@
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgConnection, PgPool, Postgres, QueryBuilder};
+use uuid::Uuid;
+
+use crate::errors::{DbOp, Result};
+use crate::models::{DailyTradeSummary, TradeCorrection, TradeFilter};
+
+// Closed trades of one robot and symbol, opened on `opened_on` and closed on `date` (UTC)
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct TradeDailyFact {
+    pub user_id: Uuid,
+    pub robot_id: Uuid,
+    pub symbol: String,
+    pub is_demo: bool,
+    pub opened_on: NaiveDate,
+    pub date: NaiveDate,
+    pub trades: i32,
+    pub wins: i32,
+    pub gross_profit: f64,
+    pub gross_loss: f64,
+    pub net_profit: f64,
+    pub volume: f64,
+}
+
+// The measures of one fact row
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct FactTotals {
+    pub trades: i32,
+    pub wins: i32,
+    pub gross_profit: f64,
+    pub gross_loss: f64,
+    pub net_profit: f64,
+    pub volume: f64,
+}
+
+impl FactTotals {
+    // Same rules as the SQL that maintains the facts
+    pub fn add_trade(&mut self, profit_loss: Option<f64>, volume: f64) {
+        self.trades += 1;
+        if let Some(profit_loss) = profit_loss {
+            if profit_loss > 0.0 {
+                self.wins += 1;
+            }
+            self.gross_profit += profit_loss.max(0.0);
+            self.gross_loss += (-profit_loss).max(0.0);
+            self.net_profit += profit_loss;
+        }
+        self.volume += volume;
+    }
+}
+
+// Closed trades summed up, from the facts or from the trades table
+#[derive(Debug, Clone, Copy, Default, PartialEq, FromRow)]
+pub struct ClosedTotals {
+    pub trades: i64,
+    pub wins: i64,
+    pub net_profit: f64,
+}
+
+impl ClosedTotals {
+    fn add(self, other: ClosedTotals) -> ClosedTotals {
+        ClosedTotals {
+            trades: self.trades + other.trades,
+            wins: self.wins + other.wins,
+            net_profit: self.net_profit + other.net_profit,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, FromRow)]
+pub struct BackfillProgress {
+    pub last_user_id: Option<Uuid>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+// The facts of the closed trades matching the appended condition, with their corrections
+// applied. A trade counts once it is closed and has its closed_at; a missing profit_loss adds
+// nothing and is not a win.
+const FACTS_FROM_TRADES: &str = r#"
+    INSERT INTO trade_daily_facts (user_id, robot_id, symbol, is_demo, opened_on, date, trades, wins, gross_profit, gross_loss, net_profit, volume)
+    SELECT
+        user_id, robot_id, symbol, is_demo,
+        (opened_at AT TIME ZONE 'UTC')::DATE,
+        (closed_at AT TIME ZONE 'UTC')::DATE,
+        COUNT(*)::INT,
+        COUNT(*) FILTER (WHERE profit_loss > 0)::INT,
+        COALESCE(SUM(GREATEST(profit_loss, 0)), 0),
+        COALESCE(SUM(GREATEST(-profit_loss, 0)), 0),
+        COALESCE(SUM(profit_loss), 0),
+        COALESCE(SUM(volume), 0)
+    FROM "#;
+
+const FACTS_WHERE: &str = " WHERE status = 'closed' AND closed_at IS NOT NULL AND ";
+
+fn facts_from_trades() -> String {
+    format!("{}{}{}", FACTS_FROM_TRADES, TradeCorrection::corrected_trades(), FACTS_WHERE)
+}
+
+const FACTS_GROUP_BY: &str = " GROUP BY 1, 2, 3, 4, 5, 6";
+
+fn midnight(day: NaiveDate) -> DateTime<Utc> {
+    day.and_time(NaiveTime::MIN).and_utc()
+}
+
+impl TradeDailyFact {
+    pub fn totals(&self) -> FactTotals {
+        FactTotals {
+            trades: self.trades,
+            wins: self.wins,
+            gross_profit: self.gross_profit,
+            gross_loss: self.gross_loss,
+            net_profit: self.net_profit,
+            volume: self.volume,
+        }
+    }
+
+    // Adds a trade that was just closed; call it in the transaction that closed it
+    pub async fn record_close(conn: &mut PgConnection, trade_id: Uuid) -> Result<()> {
+        sqlx::query(&format!(
+            r#"{}id = $1{}
+            ON CONFLICT (user_id, robot_id, symbol, is_demo, opened_on, date) DO UPDATE SET
+                trades = trade_daily_facts.trades + EXCLUDED.trades,
+                wins = trade_daily_facts.wins + EXCLUDED.wins,
+                gross_profit = trade_daily_facts.gross_profit + EXCLUDED.gross_profit,
+                gross_loss = trade_daily_facts.gross_loss + EXCLUDED.gross_loss,
+                net_profit = trade_daily_facts.net_profit + EXCLUDED.net_profit,
+                volume = trade_daily_facts.volume + EXCLUDED.volume"#,
+            facts_from_trades(), FACTS_GROUP_BY
+        ))
+        .bind(trade_id)
+        .execute(conn)
+        .await
+        .db_op("trade_daily_facts.record_close")?;
+
+        Ok(())
+    }
+
+    // Recomputes the facts of these users from their trades. Closing a trade waits for the table
+    // lock, so a close can neither be missed nor counted twice.
+    pub async fn rebuild_users(pool: &PgPool, user_ids: &[Uuid]) -> Result<()> {
+        let mut tx = pool.begin().await.db_op("trade_daily_facts.rebuild_users")?;
+        Self::rebuild(&mut tx, "user_id", user_ids).await?;
+        tx.commit().await.db_op("trade_daily_facts.rebuild_users")?;
+        Ok(())
+    }
+
+    // Within the caller's transaction, e.g. with the other integrity corrections
+    pub async fn rebuild_robots(conn: &mut PgConnection, robot_ids: &[Uuid]) -> Result<()> {
+        Self::rebuild(conn, "robot_id", robot_ids).await
+    }
+
+    async fn rebuild(conn: &mut PgConnection, column: &'static str, ids: &[Uuid]) -> Result<()> {
+        sqlx::query("LOCK TABLE trade_daily_facts IN SHARE ROW EXCLUSIVE MODE")
+            .execute(&mut *conn)
+            .await
+            .db_op("trade_daily_facts.lock")?;
+        sqlx::query(&format!("DELETE FROM trade_daily_facts WHERE {} = ANY($1)", column))
+            .bind(ids)
+            .execute(&mut *conn)
+            .await
+            .db_op("trade_daily_facts.clear")?;
+        sqlx::query(&format!("{}{} = ANY($1){}", facts_from_trades(), column, FACTS_GROUP_BY))
+            .bind(ids)
+            .execute(&mut *conn)
+            .await
+            .db_op("trade_daily_facts.rebuild")?;
+        Ok(())
+    }
+
+    pub async fn find_by_robot_ids(pool: &PgPool, robot_ids: &[Uuid]) -> Result<Vec<TradeDailyFact>> {
+        sqlx::query_as::<_, TradeDailyFact>(
+            "SELECT user_id, robot_id, symbol, is_demo, opened_on, date, trades, wins, gross_profit, gross_loss, net_profit, volume FROM trade_daily_facts WHERE robot_id = ANY($1)",
+        )
+        .bind(robot_ids)
+        .fetch_all(pool)
+        .await
+        .db_op("trade_daily_facts.find_by_robot_ids")
+    }
+
+    pub async fn backfill_progress(pool: &PgPool) -> Result<BackfillProgress> {
+        Ok(sqlx::query_as::<_, BackfillProgress>("SELECT last_user_id, completed_at FROM trade_facts_backfill")
+            .fetch_optional(pool)
+            .await
+            .db_op("trade_facts_backfill.find")?
+            .unwrap_or_default())
+    }
+
+    pub async fn save_backfill_progress(pool: &PgPool, last_user_id: Option<Uuid>, completed: bool) -> Result<()> {
+        sqlx::query(
+            "UPDATE trade_facts_backfill SET last_user_id = $1, completed_at = CASE WHEN $2 THEN NOW() ELSE completed_at END",
+        )
+        .bind(last_user_id)
+        .bind(completed)
+        .execute(pool)
+        .await
+        .db_op("trade_facts_backfill.save")?;
+
+        Ok(())
+    }
+
+    // Until the backfill has completed the facts miss older trades and must not be read
+    pub async fn is_ready(pool: &PgPool) -> Result<bool> {
+        Ok(Self::backfill_progress(pool).await?.completed_at.is_some())
+    }
+
+    pub async fn users_after(pool: &PgPool, after: Option<Uuid>, limit: i64) -> Result<Vec<Uuid>> {
+        sqlx::query_scalar("SELECT id FROM users WHERE $1::UUID IS NULL OR id > $1 ORDER BY id LIMIT $2")
+            .bind(after)
+            .bind(limit)
+            .fetch_all(pool)
+            .await
+            .db_op("trade_facts_backfill.users_after")
+    }
+
+    // Closed trades matching the filter. Whole days of the window come from the facts; trades
+    // opened on a partially covered first or last day are read from the trades table.
+    pub async fn closed_totals(pool: &PgPool, user_id: Uuid, filter: &TradeFilter, now: DateTime<Utc>) -> Result<ClosedTotals> {
+        let (first_day, end_day) = filter.full_days(now);
+
+        let mut facts = QueryBuilder::<Postgres>::new(
+            "SELECT COALESCE(SUM(trades), 0)::INT8 AS trades, COALESCE(SUM(wins), 0)::INT8 AS wins, COALESCE(SUM(net_profit), 0)::FLOAT8 AS net_profit FROM trade_daily_facts WHERE user_id = ",
+        );
+        facts.push_bind(user_id);
+        if let Some(first_day) = first_day {
+            facts.push(" AND opened_on >= ").push_bind(first_day);
+        }
+        if let Some(end_day) = end_day {
+            facts.push(" AND opened_on < ").push_bind(end_day);
+        }
+        filter.push_scope(&mut facts);
+        let totals: ClosedTotals = facts.build_query_as().fetch_one(pool).await.db_op("trade_daily_facts.closed_totals")?;
+
+        if first_day.is_none() && end_day.is_none() {
+            return Ok(totals);
+        }
+        let mut edges = closed_trades_query(user_id, filter, now, None);
+        edges.push(" AND (");
+        if let Some(first_day) = first_day {
+            edges.push("opened_at < ").push_bind(midnight(first_day));
+        }
+        if let Some(end_day) = end_day {
+            if first_day.is_some() {
+                edges.push(" OR ");
+            }
+            edges.push("opened_at >= ").push_bind(midnight(end_day));
+        }
+        edges.push(")");
+        let partial: ClosedTotals = edges.build_query_as().fetch_one(pool).await.db_op("trades.closed_totals_edges")?;
+
+        Ok(totals.add(partial))
+    }
+
+    // The same totals straight from the trades table, while the facts are not ready yet or for a
+    // past instant the facts cannot show
+    pub async fn closed_totals_from_trades(
+        pool: &PgPool,
+        user_id: Uuid,
+        filter: &TradeFilter,
+        now: DateTime<Utc>,
+        as_of: Option<DateTime<Utc>>,
+    ) -> Result<ClosedTotals> {
+        closed_trades_query(user_id, filter, now, as_of)
+            .build_query_as()
+            .fetch_one(pool)
+            .await
+            .db_op("trades.closed_totals")
+    }
+
+    // Live trades per UTC day of closing, from `since` (a UTC midnight) on
+    pub async fn daily_summaries(pool: &PgPool, user_id: Uuid, since: DateTime<Utc>) -> Result<Vec<DailyTradeSummary>> {
+        sqlx::query_as::<_, DailyTradeSummary>(
+            r#"
+            SELECT
+                date::TIMESTAMP AT TIME ZONE 'UTC' AS day,
+                SUM(trades)::INT8 AS trades,
+                SUM(wins)::INT8 AS winning_trades,
+                SUM(net_profit)::FLOAT8 AS profit
+            FROM trade_daily_facts
+            WHERE user_id = $1 AND date >= $2 AND is_demo = FALSE
+            GROUP BY date
+            ORDER BY date
+            "#,
+        )
+        .bind(user_id)
+        .bind(since.date_naive())
+        .fetch_all(pool)
+        .await
+        .db_op("trade_daily_facts.daily_summaries")
+    }
+}
+
+fn closed_trades_query<'a>(
+    user_id: Uuid,
+    filter: &'a TradeFilter,
+    now: DateTime<Utc>,
+    as_of: Option<DateTime<Utc>>,
+) -> QueryBuilder<'a, Postgres> {
+    let mut builder = QueryBuilder::<Postgres>::new(
+        "SELECT COUNT(*) AS trades, COUNT(*) FILTER (WHERE profit_loss > 0) AS wins, COALESCE(SUM(profit_loss), 0)::FLOAT8 AS net_profit FROM ",
+    );
+    TradeCorrection::push_corrected_trades(&mut builder, as_of);
+    builder.push(" WHERE status = 'closed' AND closed_at IS NOT NULL AND user_id = ");
+    builder.push_bind(user_id);
+    TradeCorrection::push_as_of(&mut builder, as_of);
+    filter.push_conditions(&mut builder, now);
+    builder
+}
@@ -0,0 +1,103 @@
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+use crate::errors::{DbOp, Result};
+
+pub const ORIGIN_WEBHOOK: &str = "webhook";
+pub const ORIGIN_SIGNAL: &str = "signal";
+pub const ORIGIN_MANUAL: &str = "manual";
+pub const ORIGIN_IMPORT: &str = "import";
+
+#[derive(Debug, Clone, PartialEq, Serialize, FromRow, JsonSchema)]
+pub struct TradeOrigin {
+    pub trade_id: Uuid,
+    // webhook | signal | manual | import
+    pub origin_type: String,
+    // Alert id, signal id, the trade a manual re-entry came from, or the import job
+    pub reference: Option<String>,
+    // The inbound request as received, minus sensitive headers; null once pruned
+    pub payload: Option<serde_json::Value>,
+    pub received_at: DateTime<Utc>,
+    pub source_ip: Option<String>,
+    pub payload_pruned_at: Option<DateTime<Utc>>,
+}
+
+const COLUMNS: &str = "o.trade_id, o.origin_type, o.reference, o.payload, o.received_at, o.source_ip, o.payload_pruned_at";
+
+impl TradeOrigin {
+    pub fn new(trade_id: Uuid, origin_type: &str, reference: Option<String>, received_at: DateTime<Utc>) -> Self {
+        TradeOrigin {
+            trade_id,
+            origin_type: origin_type.to_string(),
+            reference,
+            payload: None,
+            received_at,
+            source_ip: None,
+            payload_pruned_at: None,
+        }
+    }
+
+    pub fn with_payload(mut self, payload: serde_json::Value, source_ip: Option<String>) -> Self {
+        self.payload = Some(payload);
+        self.source_ip = source_ip;
+        self
+    }
+
+    // A trade has one origin; the first one recorded wins
+    pub async fn record(pool: &PgPool, origin: &TradeOrigin) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO trade_origins (trade_id, origin_type, reference, payload, received_at, source_ip)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (trade_id) DO NOTHING
+            "#,
+        )
+        .bind(origin.trade_id)
+        .bind(&origin.origin_type)
+        .bind(&origin.reference)
+        .bind(&origin.payload)
+        .bind(origin.received_at)
+        .bind(&origin.source_ip)
+        .execute(pool)
+        .await
+        .db_op("trade_origins.record")?;
+        Ok(())
+    }
+
+    // Only the trade's owner sees it
+    pub async fn find_for_user(pool: &PgPool, user_id: Uuid, trade_id: Uuid) -> Result<Option<TradeOrigin>> {
+        sqlx::query_as::<_, TradeOrigin>(&format!(
+            "SELECT {} FROM trade_origins o JOIN trades t ON t.id = o.trade_id WHERE o.trade_id = $1 AND t.user_id = $2",
+            COLUMNS
+        ))
+        .bind(trade_id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await
+        .db_op("trade_origins.find_for_user")
+    }
+
+    pub async fn find(pool: &PgPool, trade_id: Uuid) -> Result<Option<TradeOrigin>> {
+        sqlx::query_as::<_, TradeOrigin>(&format!("SELECT {} FROM trade_origins o WHERE o.trade_id = $1", COLUMNS))
+            .bind(trade_id)
+            .fetch_optional(pool)
+            .await
+            .db_op("trade_origins.find")
+    }
+
+    // Clears payloads received before `before`; the rows stay as stubs
+    pub async fn prune_payloads(pool: &PgPool, before: DateTime<Utc>, now: DateTime<Utc>) -> Result<u64> {
+        let result = sqlx::query(
+            "UPDATE trade_origins SET payload = NULL, source_ip = NULL, payload_pruned_at = $2 WHERE payload IS NOT NULL AND received_at < $1",
+        )
+        .bind(before)
+        .bind(now)
+        .execute(pool)
+        .await
+        .db_op("trade_origins.prune_payloads")?;
+        Ok(result.rows_affected())
+    }
+}
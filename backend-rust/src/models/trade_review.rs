@@ -0,0 +1,337 @@
+use chrono::{DateTime, Duration, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool, Postgres, QueryBuilder};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::errors::{DbOp, Result};
+use crate::models::{TradeCorrection, TradeFilter};
+
+// Emotions a trader can tag a review with, in the order they are reported
+pub const EMOTION_TAGS: [&str; 10] = [
+    "calm",
+    "confident",
+    "overconfident",
+    "fearful",
+    "greedy",
+    "fomo",
+    "revenge",
+    "impatient",
+    "hesitant",
+    "bored",
+];
+
+// A review can be edited for this long after it is first submitted
+pub const REVIEW_EDIT_DAYS: i64 = 7;
+
+// Journal entry for a closed trade. Created as a pending prompt when the trade closes;
+// `reviewed_at` is set by the first submission.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema, FromRow)]
+pub struct TradeReview {
+    pub id: Uuid,
+    pub trade_id: Uuid,
+    pub user_id: Uuid,
+    pub followed_plan: Option<bool>,
+    pub execution_rating: Option<i16>,
+    pub emotion_tags: Vec<String>,
+    pub notes: Option<String>,
+    pub reviewed_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl TradeReview {
+    pub fn pending(trade_id: Uuid, user_id: Uuid, now: DateTime<Utc>) -> Self {
+        TradeReview {
+            id: Uuid::new_v4(),
+            trade_id,
+            user_id,
+            followed_plan: None,
+            execution_rating: None,
+            emotion_tags: Vec::new(),
+            notes: None,
+            reviewed_at: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    pub fn locks_at(&self) -> Option<DateTime<Utc>> {
+        self.reviewed_at.map(|at| at + Duration::days(REVIEW_EDIT_DAYS))
+    }
+
+    pub fn is_locked(&self, now: DateTime<Utc>) -> bool {
+        self.locks_at().is_some_and(|at| now >= at)
+    }
+
+    // Does nothing when the trade already has an entry, so a redelivered close is harmless.
+    // Returns whether a prompt was created.
+    pub async fn create_prompt(pool: &PgPool, trade_id: Uuid, user_id: Uuid) -> Result<bool> {
+        let result = sqlx::query(
+            "INSERT INTO trade_reviews (trade_id, user_id) VALUES ($1, $2) ON CONFLICT (trade_id) DO NOTHING",
+        )
+        .bind(trade_id)
+        .bind(user_id)
+        .execute(pool)
+        .await
+        .db_op("trade_reviews.create_prompt")?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn find_by_trade_id(pool: &PgPool, user_id: Uuid, trade_id: Uuid) -> Result<Option<TradeReview>> {
+        sqlx::query_as::<_, TradeReview>(
+            r#"
+            SELECT id, trade_id, user_id, followed_plan, execution_rating, emotion_tags, notes, reviewed_at, created_at, updated_at
+            FROM trade_reviews
+            WHERE trade_id = $1 AND user_id = $2
+            "#,
+        )
+        .bind(trade_id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await
+        .db_op("trade_reviews.find_by_trade_id")
+    }
+
+    pub async fn save(pool: &PgPool, review: &TradeReview) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO trade_reviews (id, trade_id, user_id, followed_plan, execution_rating, emotion_tags, notes, reviewed_at, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            ON CONFLICT (trade_id) DO UPDATE SET
+                followed_plan = EXCLUDED.followed_plan,
+                execution_rating = EXCLUDED.execution_rating,
+                emotion_tags = EXCLUDED.emotion_tags,
+                notes = EXCLUDED.notes,
+                reviewed_at = EXCLUDED.reviewed_at,
+                updated_at = EXCLUDED.updated_at
+            "#,
+        )
+        .bind(review.id)
+        .bind(review.trade_id)
+        .bind(review.user_id)
+        .bind(review.followed_plan)
+        .bind(review.execution_rating)
+        .bind(&review.emotion_tags)
+        .bind(&review.notes)
+        .bind(review.reviewed_at)
+        .bind(review.created_at)
+        .bind(review.updated_at)
+        .execute(pool)
+        .await
+        .db_op("trade_reviews.save")?;
+        Ok(())
+    }
+
+    pub async fn count_pending(pool: &PgPool, user_id: Uuid) -> Result<i64> {
+        sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM trade_reviews WHERE user_id = $1 AND reviewed_at IS NULL")
+            .bind(user_id)
+            .fetch_one(pool)
+            .await
+            .db_op("trade_reviews.count_pending")
+    }
+
+    // Oldest prompt first
+    pub async fn find_pending(pool: &PgPool, user_id: Uuid, limit: i64, offset: i64) -> Result<Vec<PendingReview>> {
+        sqlx::query_as::<_, PendingReview>(
+            r#"
+            SELECT t.id AS trade_id, t.robot_id, t.symbol, t.trade_type, t.profit_loss::FLOAT8 AS profit_loss, t.closed_at, r.created_at AS prompted_at
+            FROM trade_reviews r
+            JOIN trades t ON t.id = r.trade_id
+            WHERE r.user_id = $1 AND r.reviewed_at IS NULL
+            ORDER BY r.created_at, t.id
+            LIMIT $2 OFFSET $3
+            "#,
+        )
+        .bind(user_id)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(pool)
+        .await
+        .db_op("trade_reviews.find_pending")
+    }
+
+    // Closed trades matching the filter, by whether the plan was followed and by emotion tag
+    pub async fn breakdown(pool: &PgPool, user_id: Uuid, filter: &TradeFilter) -> Result<ReviewBreakdown> {
+        let now = Utc::now();
+        let by_plan = plan_query(user_id, filter, now)
+            .build_query_as::<ReviewBucketRow>()
+            .fetch_all(pool)
+            .await
+            .db_op("trade_reviews.breakdown_by_plan")?;
+        let by_tag = tag_query(user_id, filter, now)
+            .build_query_as::<ReviewBucketRow>()
+            .fetch_all(pool)
+            .await
+            .db_op("trade_reviews.breakdown_by_tag")?;
+        Ok(ReviewBreakdown::from_rows(by_plan, by_tag))
+    }
+}
+
+// Filtered trades are selected in a subquery so the filter's bare column names stay unambiguous
+fn closed_trades<'a>(select: &str, user_id: Uuid, filter: &'a TradeFilter, now: DateTime<Utc>) -> QueryBuilder<'a, Postgres> {
+    let mut builder = QueryBuilder::<Postgres>::new(select);
+    builder.push(" FROM (SELECT id, profit_loss::FLOAT8 AS profit_loss FROM ");
+    builder.push(TradeCorrection::corrected_trades());
+    builder.push(" WHERE user_id = ");
+    builder.push_bind(user_id);
+    builder.push(" AND status = 'closed'");
+    filter.push_conditions(&mut builder, now);
+    builder.push(") t");
+    builder
+}
+
+const BUCKET_COLUMNS: &str = "COUNT(*) AS trades, COUNT(CASE WHEN t.profit_loss > 0 THEN 1 END) AS winning_trades, COALESCE(SUM(t.profit_loss), 0) AS total_profit";
+
+fn plan_query(user_id: Uuid, filter: &TradeFilter, now: DateTime<Utc>) -> QueryBuilder<'_, Postgres> {
+    let select = format!(
+        "SELECT CASE WHEN r.reviewed_at IS NULL THEN 'unreviewed' WHEN r.followed_plan THEN 'followed_plan' ELSE 'broke_plan' END AS key, {}",
+        BUCKET_COLUMNS
+    );
+    let mut builder = closed_trades(&select, user_id, filter, now);
+    builder.push(" LEFT JOIN trade_reviews r ON r.trade_id = t.id GROUP BY 1");
+    builder
+}
+
+fn tag_query(user_id: Uuid, filter: &TradeFilter, now: DateTime<Utc>) -> QueryBuilder<'_, Postgres> {
+    let select = format!("SELECT tag AS key, {}", BUCKET_COLUMNS);
+    let mut builder = closed_trades(&select, user_id, filter, now);
+    builder.push(
+        " JOIN trade_reviews r ON r.trade_id = t.id AND r.reviewed_at IS NOT NULL CROSS JOIN LATERAL unnest(r.emotion_tags) AS tag GROUP BY 1",
+    );
+    builder
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Validate)]
+pub struct SubmitTradeReviewRequest {
+    pub followed_plan: bool,
+    #[validate(range(min = 1, max = 5))]
+    pub execution_rating: Option<i16>,
+    // From EMOTION_TAGS
+    #[serde(default)]
+    pub emotion_tags: Vec<String>,
+    #[validate(length(max = 2000))]
+    pub notes: Option<String>,
+}
+
+// A closed trade still waiting for its journal entry
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, FromRow)]
+pub struct PendingReview {
+    pub trade_id: Uuid,
+    pub robot_id: Uuid,
+    pub symbol: String,
+    pub trade_type: String,
+    #[serde(serialize_with = "crate::money::serialize_opt_amount")]
+    pub profit_loss: Option<f64>,
+    pub closed_at: Option<DateTime<Utc>>,
+    pub prompted_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct ReviewBucketRow {
+    pub key: String,
+    pub trades: i64,
+    pub winning_trades: i64,
+    pub total_profit: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ReviewBucket {
+    pub key: String,
+    pub trades: i64,
+    pub winning_trades: i64,
+    #[serde(serialize_with = "crate::money::serialize_aggregate")]
+    pub total_profit: f64,
+    #[serde(serialize_with = "crate::money::serialize_percent")]
+    pub win_rate: f64,
+}
+
+impl From<ReviewBucketRow> for ReviewBucket {
+    fn from(row: ReviewBucketRow) -> Self {
+        let win_rate = if row.trades > 0 { row.winning_trades as f64 / row.trades as f64 * 100.0 } else { 0.0 };
+        ReviewBucket {
+            key: row.key,
+            trades: row.trades,
+            winning_trades: row.winning_trades,
+            total_profit: row.total_profit,
+            win_rate,
+        }
+    }
+}
+
+// `by_plan` always lists followed_plan, broke_plan and unreviewed; `by_tag` lists the tags
+// used, in EMOTION_TAGS order. A trade counts once under each of its tags.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ReviewBreakdown {
+    pub by_plan: Vec<ReviewBucket>,
+    pub by_tag: Vec<ReviewBucket>,
+}
+
+impl ReviewBreakdown {
+    pub fn from_rows(by_plan: Vec<ReviewBucketRow>, by_tag: Vec<ReviewBucketRow>) -> Self {
+        let by_plan = ["followed_plan", "broke_plan", "unreviewed"]
+            .into_iter()
+            .map(|key| {
+                let row = by_plan.iter().find(|r| r.key == key).cloned().unwrap_or(ReviewBucketRow {
+                    key: key.to_string(),
+                    trades: 0,
+                    winning_trades: 0,
+                    total_profit: 0.0,
+                });
+                ReviewBucket::from(row)
+            })
+            .collect();
+        let by_tag = EMOTION_TAGS
+            .iter()
+            .filter_map(|tag| by_tag.iter().find(|r| r.key == *tag).cloned())
+            .map(ReviewBucket::from)
+            .collect();
+        ReviewBreakdown { by_plan, by_tag }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(key: &str, trades: i64, winning_trades: i64, total_profit: f64) -> ReviewBucketRow {
+        ReviewBucketRow { key: key.to_string(), trades, winning_trades, total_profit }
+    }
+
+    #[test]
+    fn test_breakdown_by_plan_and_tag() {
+        let breakdown = ReviewBreakdown::from_rows(
+            vec![row("unreviewed", 3, 1, -20.0), row("followed_plan", 4, 3, 150.0)],
+            vec![row("fomo", 2, 0, -80.0), row("calm", 4, 3, 150.0), row("retired_tag", 1, 1, 5.0)],
+        );
+
+        let plan: Vec<(&str, i64)> = breakdown.by_plan.iter().map(|b| (b.key.as_str(), b.trades)).collect();
+        assert_eq!(plan, vec![("followed_plan", 4), ("broke_plan", 0), ("unreviewed", 3)]);
+        let win_rates: Vec<f64> = breakdown.by_plan.iter().map(|b| b.win_rate).collect();
+        for (win_rate, expected) in win_rates.iter().zip([75.0, 0.0, 100.0 / 3.0]) {
+            assert!((win_rate - expected).abs() < 1e-9, "{} != {}", win_rate, expected);
+        }
+
+        // Tags follow the fixed set's order and unknown ones are dropped
+        let tags: Vec<(&str, f64)> = breakdown.by_tag.iter().map(|b| (b.key.as_str(), b.total_profit)).collect();
+        assert_eq!(tags, vec![("calm", 150.0), ("fomo", -80.0)]);
+    }
+
+    #[test]
+    fn test_breakdown_queries_only_closed_filtered_trades() {
+        let filter = TradeFilter { symbols: vec!["EURUSD".to_string()], ..Default::default() };
+        let plan = plan_query(Uuid::nil(), &filter, Utc::now()).sql().to_string();
+        // Read through the corrections, like every other report
+        let closed = format!(
+            "FROM (SELECT id, profit_loss::FLOAT8 AS profit_loss FROM {} WHERE user_id = $1 AND status = 'closed' AND symbol = ANY($2) AND is_demo = FALSE) t",
+            TradeCorrection::corrected_trades()
+        );
+        assert!(plan.contains(&closed), "{}", plan);
+        assert!(plan.ends_with("LEFT JOIN trade_reviews r ON r.trade_id = t.id GROUP BY 1"));
+
+        let tags = tag_query(Uuid::nil(), &filter, Utc::now()).sql().to_string();
+        assert!(tags.starts_with("SELECT tag AS key, COUNT(*) AS trades"));
+        assert!(tags.contains("r.reviewed_at IS NOT NULL CROSS JOIN LATERAL unnest(r.emotion_tags) AS tag"));
+    }
+}
@@ -1,9 +1,13 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::PgPool;
+use schemars::JsonSchema;
+use sqlx::{FromRow, PgPool};
 use uuid::Uuid;
 use validator::Validate;
 
+use crate::errors::{DbOp, Result};
+use super::{BrokerConnection, ClientCount, RobotChange, TradeCorrection};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TradingRobot {
     pub id: Uuid,
@@ -15,19 +19,40 @@ pub struct TradingRobot {
     pub performance_metrics: serde_json::Value,
     pub last_signal_at: Option<DateTime<Utc>>,
     pub total_trades: i32,
+    // Connection the robot trades through; decides whether its trades are demo
+    pub broker_connection_id: Option<Uuid>,
+    pub notes: Option<String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Validate)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Validate)]
 pub struct CreateTradingRobotRequest {
     #[validate(length(min = 1))]
     pub name: String,
     pub strategy: String,
     pub risk_config: Option<serde_json::Value>,
+    pub broker_connection_id: Option<Uuid>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct UpdateAllocationRequest {
+    // None removes the allocation
+    pub allocation_percent: Option<f64>,
+}
+
+// Fields left out are unchanged. risk_config is merged key by key and a null value removes the key.
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema, Validate)]
+pub struct UpdateTradingRobotRequest {
+    #[validate(length(min = 1))]
+    pub strategy: Option<String>,
+    pub risk_config: Option<serde_json::Map<String, serde_json::Value>>,
+    // An empty string clears the notes
+    #[validate(length(max = 2000))]
+    pub notes: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct TradingRobotResponse {
     pub id: Uuid,
     pub name: String,
@@ -37,12 +62,368 @@ pub struct TradingRobotResponse {
     pub performance_metrics: serde_json::Value,
     pub last_signal_at: Option<DateTime<Utc>>,
     pub total_trades: i32,
+    #[serde(serialize_with = "crate::money::serialize_aggregate")]
     pub total_profit: f64,
     pub winning_trades: i32,
+    #[serde(serialize_with = "crate::money::serialize_percent")]
     pub win_rate: f64,
+    pub broker_connection_id: Option<Uuid>,
+    pub is_demo: bool,
+    pub allocation_percent: Option<f64>,
+    #[serde(serialize_with = "crate::money::serialize_opt_amount")]
+    pub virtual_equity: Option<f64>,
+    // When a robot cooling down after a losing streak starts trading again
+    pub resume_at: Option<DateTime<Utc>>,
+    pub notes: Option<String>,
     pub created_at: DateTime<Utc>,
 }
 
+// Who enforces a trade's SL/TP: the broker server, our monitoring loop, or both
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StopManagement {
+    #[default]
+    Broker,
+    Platform,
+    Both,
+}
+
+impl StopManagement {
+    pub fn parse(raw: &str) -> Option<StopManagement> {
+        match raw {
+            "broker" => Some(StopManagement::Broker),
+            "platform" => Some(StopManagement::Platform),
+            "both" => Some(StopManagement::Both),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StopManagement::Broker => "broker",
+            StopManagement::Platform => "platform",
+            StopManagement::Both => "both",
+        }
+    }
+
+    // Reads risk_config.stop_management; absent means the broker default
+    pub fn from_risk_config(risk_config: &serde_json::Value) -> Result<StopManagement, String> {
+        match risk_config.get("stop_management") {
+            None | Some(serde_json::Value::Null) => Ok(StopManagement::default()),
+            Some(serde_json::Value::String(raw)) => StopManagement::parse(raw)
+                .ok_or_else(|| format!("Invalid stop_management '{}', expected broker, platform or both", raw)),
+            Some(_) => Err("stop_management must be a string".to_string()),
+        }
+    }
+
+    // SL/TP are attached to the broker order
+    pub fn attaches_to_order(&self) -> bool {
+        matches!(self, StopManagement::Broker | StopManagement::Both)
+    }
+
+    // Our runner watches prices and closes the position itself
+    pub fn platform_monitors(&self) -> bool {
+        matches!(self, StopManagement::Platform | StopManagement::Both)
+    }
+}
+
+// Pause after `streak` consecutive losing trades, for `hours`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LossStreakCooldown {
+    pub streak: i32,
+    pub hours: f64,
+}
+
+impl LossStreakCooldown {
+    // Reads risk_config.loss_streak_cooldown; absent means the robot never cools down
+    pub fn from_risk_config(risk_config: &serde_json::Value) -> Result<Option<LossStreakCooldown>, String> {
+        let raw = match risk_config.get("loss_streak_cooldown") {
+            None | Some(serde_json::Value::Null) => return Ok(None),
+            Some(raw) => raw,
+        };
+
+        let cooldown: LossStreakCooldown = serde_json::from_value(raw.clone())
+            .map_err(|_| "loss_streak_cooldown must be {\"streak\": <trades>, \"hours\": <hours>}".to_string())?;
+        if !(1..=50).contains(&cooldown.streak) {
+            return Err("loss_streak_cooldown.streak must be between 1 and 50".to_string());
+        }
+        if !(cooldown.hours > 0.0 && cooldown.hours <= 168.0) {
+            return Err("loss_streak_cooldown.hours must be above 0 and at most 168".to_string());
+        }
+        Ok(Some(cooldown))
+    }
+
+    pub fn duration(&self) -> chrono::Duration {
+        chrono::Duration::seconds((self.hours * 3600.0).round() as i64)
+    }
+}
+
+// Keeps a robot from flip-flopping between buy and sell
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct SignalStability {
+    // The same direction this many evaluations in a row before acting; 1 acts on the first
+    pub confirmation_count: u32,
+    // A fresh position is not reversed within this many minutes...
+    pub min_holding_minutes: i64,
+    // ...unless the opposite signal is at least this confident
+    pub reversal_override_confidence: f64,
+}
+
+impl Default for SignalStability {
+    fn default() -> Self {
+        SignalStability {
+            confirmation_count: 1,
+            min_holding_minutes: 0,
+            reversal_override_confidence: 0.9,
+        }
+    }
+}
+
+impl SignalStability {
+    // Reads risk_config.signal_confirmation_count, min_holding_minutes and reversal_override_confidence
+    pub fn from_risk_config(risk_config: &serde_json::Value) -> Result<SignalStability, String> {
+        let field = |key: &str| risk_config.get(key).filter(|v| !v.is_null());
+        let mut stability = SignalStability::default();
+
+        if let Some(value) = field("signal_confirmation_count") {
+            stability.confirmation_count = value
+                .as_u64()
+                .filter(|count| (1..=20).contains(count))
+                .ok_or("signal_confirmation_count must be a whole number between 1 and 20")? as u32;
+        }
+        if let Some(value) = field("min_holding_minutes") {
+            stability.min_holding_minutes = value
+                .as_i64()
+                .filter(|minutes| (0..=1440).contains(minutes))
+                .ok_or("min_holding_minutes must be a whole number between 0 and 1440")?;
+        }
+        if let Some(value) = field("reversal_override_confidence") {
+            stability.reversal_override_confidence = value
+                .as_f64()
+                .filter(|confidence| (0.0..=1.0).contains(confidence))
+                .ok_or("reversal_override_confidence must be a number between 0 and 1")?;
+        }
+        Ok(stability)
+    }
+
+    pub fn min_holding(&self) -> chrono::Duration {
+        chrono::Duration::minutes(self.min_holding_minutes)
+    }
+}
+
+// How a composite robot turns its strategies' signals into one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompositeMode {
+    // Every strategy calls the same direction
+    Unanimous,
+    // More than half of the strategies call the same direction
+    Majority,
+    // The weighted confidence behind a direction reaches `threshold`
+    WeightedThreshold,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StrategyWeight {
+    pub strategy: String,
+    pub weight: f64,
+}
+
+// risk_config.composite_strategy: a robot trading on several strategies at once
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CompositeStrategy {
+    pub mode: CompositeMode,
+    pub strategies: Vec<StrategyWeight>,
+    // Only used by weighted_threshold
+    #[serde(default)]
+    pub threshold: Option<f64>,
+}
+
+impl CompositeStrategy {
+    // Absent means the robot trades on its own strategy alone
+    pub fn from_risk_config(risk_config: &serde_json::Value) -> Result<Option<CompositeStrategy>, String> {
+        let raw = match risk_config.get("composite_strategy") {
+            None | Some(serde_json::Value::Null) => return Ok(None),
+            Some(raw) => raw,
+        };
+
+        let composite: CompositeStrategy = serde_json::from_value(raw.clone()).map_err(|_| {
+            "composite_strategy must be {\"mode\": \"unanimous\" | \"majority\" | \"weighted_threshold\", \"strategies\": [{\"strategy\": <name>, \"weight\": <weight>}], \"threshold\": <confidence>}".to_string()
+        })?;
+        if composite.strategies.len() < 2 {
+            return Err("composite_strategy needs at least two strategies".to_string());
+        }
+        let mut names = std::collections::HashSet::new();
+        for entry in &composite.strategies {
+            if entry.strategy.trim().is_empty() || !names.insert(entry.strategy.as_str()) {
+                return Err("composite_strategy strategies must be named and listed once each".to_string());
+            }
+            if !(entry.weight > 0.0 && entry.weight <= 1.0) {
+                return Err(format!("composite_strategy weight for {} must be above 0 and at most 1", entry.strategy));
+            }
+        }
+        let total: f64 = composite.strategies.iter().map(|s| s.weight).sum();
+        if (total - 1.0).abs() > 1e-6 {
+            return Err(format!("composite_strategy weights must sum to 1, not {}", total));
+        }
+        match (composite.mode, composite.threshold) {
+            (CompositeMode::WeightedThreshold, None) => {
+                return Err("composite_strategy.threshold is required for weighted_threshold".to_string())
+            }
+            (_, Some(threshold)) if !(threshold > 0.0 && threshold <= 1.0) => {
+                return Err("composite_strategy.threshold must be above 0 and at most 1".to_string())
+            }
+            _ => {}
+        }
+        Ok(Some(composite))
+    }
+}
+
+// A robot paused or resumed with broker maintenance, and the broker type it trades through
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct MaintenanceRobot {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    pub broker_type: String,
+}
+
+// A robot parked by a plan downgrade, with the status it had before
+#[derive(Debug, Clone, PartialEq, Serialize, FromRow, JsonSchema)]
+pub struct PlanLimitedRobot {
+    pub id: Uuid,
+    pub name: String,
+    pub previous_status: String,
+}
+
+// Every risk_config key this version reads. Any other key is stored but not enforced, e.g. a
+// setting written for a newer runner or one that has since been removed.
+pub const KNOWN_RISK_FIELDS: [&str; 14] = [
+    "max_risk_per_trade",
+    "stop_loss_pips",
+    "take_profit_pips",
+    "max_daily_loss",
+    "min_confidence",
+    "symbols",
+    "stop_management",
+    "loss_streak_cooldown",
+    "signal_confirmation_count",
+    "min_holding_minutes",
+    "reversal_override_confidence",
+    "composite_strategy",
+    "allocation_percent",
+    "evaluation_interval_seconds",
+];
+
+// What the runner will make of a stored risk_config
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RiskConfigWarnings {
+    // Keys the runner does not understand; whatever they ask for is not enforced
+    pub ignored_fields: Vec<String>,
+    // Platform defaults the config lacks; the runner falls back to the default value
+    pub defaulted_fields: Vec<String>,
+}
+
+impl RiskConfigWarnings {
+    pub fn check(risk_config: &serde_json::Value) -> RiskConfigWarnings {
+        let present = |key: &str| risk_config.get(key).is_some_and(|value| !value.is_null());
+        let mut ignored_fields: Vec<String> = risk_config
+            .as_object()
+            .map(|config| {
+                config
+                    .keys()
+                    .filter(|key| present(key) && !KNOWN_RISK_FIELDS.contains(&key.as_str()))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+        ignored_fields.sort();
+
+        let mut defaulted_fields: Vec<String> = TradingRobot::default_risk_config()
+            .as_object()
+            .map(|defaults| defaults.keys().filter(|key| !present(key)).cloned().collect())
+            .unwrap_or_default();
+        defaulted_fields.sort();
+
+        RiskConfigWarnings { ignored_fields, defaulted_fields }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ignored_fields.is_empty() && self.defaulted_fields.is_empty()
+    }
+}
+
+// What starting the robot would run into
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct RobotPreflight {
+    pub robot_id: Uuid,
+    pub config_warnings: RiskConfigWarnings,
+    // Ignored risk fields must be acknowledged with ?acknowledge_warnings=true
+    pub requires_acknowledgement: bool,
+    // Cooling down after a losing streak; starting now needs ?force=true
+    pub resume_at: Option<DateTime<Utc>>,
+}
+
+impl RobotPreflight {
+    pub fn for_robot(robot: &TradingRobot) -> RobotPreflight {
+        let config_warnings = robot.config_warnings();
+        RobotPreflight {
+            robot_id: robot.id,
+            requires_acknowledgement: !config_warnings.ignored_fields.is_empty(),
+            config_warnings,
+            resume_at: robot.resume_at(),
+        }
+    }
+}
+
+// The trade counters kept in performance_metrics. A missing key reads as zero and is listed in
+// `missing`, since a zero there can't be told apart from a counter that was never written.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PerformanceMetrics {
+    pub total_profit: f64,
+    pub winning_trades: i32,
+    pub missing: Vec<&'static str>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct StoredCounters {
+    #[serde(default)]
+    total_profit: Option<f64>,
+    #[serde(default)]
+    winning_trades: Option<i32>,
+}
+
+impl PerformanceMetrics {
+    // NULL, a non-object or a counter of the wrong type counts as missing too
+    pub fn parse(performance_metrics: &serde_json::Value) -> PerformanceMetrics {
+        let stored: StoredCounters = serde_json::from_value(performance_metrics.clone()).unwrap_or_default();
+        let mut missing = Vec::new();
+        if stored.total_profit.is_none() {
+            missing.push("total_profit");
+        }
+        if stored.winning_trades.is_none() {
+            missing.push("winning_trades");
+        }
+        PerformanceMetrics {
+            total_profit: stored.total_profit.unwrap_or(0.0),
+            winning_trades: stored.winning_trades.unwrap_or(0),
+            missing,
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.missing.is_empty()
+    }
+
+    pub fn win_rate(&self, total_trades: i32) -> f64 {
+        if total_trades == 0 {
+            0.0
+        } else {
+            (self.winning_trades as f64 / total_trades as f64) * 100.0
+        }
+    }
+}
+
 impl TradingRobot {
     pub fn new(
         user_id: Uuid,
@@ -56,38 +437,56 @@ impl TradingRobot {
             name,
             strategy,
             status: "inactive".to_string(),
-            risk_config: serde_json::json!({
-                "max_risk_per_trade": 0.02,
-                "stop_loss_pips": 20,
-                "take_profit_pips": 40,
-                "max_daily_loss": 0.05
-            }),
+            risk_config: Self::default_risk_config(),
             performance_metrics: serde_json::json!({
                 "total_profit": 0.0,
                 "winning_trades": 0
             }),
             last_signal_at: None,
             total_trades: 0,
+            broker_connection_id: None,
+            notes: None,
             created_at: now,
             updated_at: now,
         }
     }
 
+    // Platform defaults, the base that user templates and explicit settings are merged over
+    pub fn default_risk_config() -> serde_json::Value {
+        serde_json::json!({
+            "max_risk_per_trade": 0.02,
+            "stop_loss_pips": 20,
+            "take_profit_pips": 40,
+            "max_daily_loss": 0.05
+        })
+    }
+
     pub async fn create(
         pool: &PgPool,
         user_id: Uuid,
         request: CreateTradingRobotRequest,
-    ) -> Result<TradingRobot, sqlx::Error> {
-        let robot = TradingRobot::new(
-            user_id,
-            request.name,
-            request.strategy,
-        );
+        created_via: &str,
+    ) -> Result<TradingRobot> {
+        let mut robot = TradingRobot {
+            broker_connection_id: request.broker_connection_id,
+            ..TradingRobot::new(
+                user_id,
+                request.name,
+                request.strategy,
+            )
+        };
+
+        // Settings from the request override the defaults key by key
+        if let (Some(serde_json::Value::Object(overrides)), serde_json::Value::Object(defaults)) =
+            (request.risk_config, &mut robot.risk_config)
+        {
+            defaults.extend(overrides);
+        }
 
         sqlx::query!(
             r#"
-            INSERT INTO trading_robots (id, user_id, name, strategy, status, risk_config, performance_metrics, last_signal_at, total_trades, created_at, updated_at)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            INSERT INTO trading_robots (id, user_id, name, strategy, status, risk_config, performance_metrics, last_signal_at, total_trades, broker_connection_id, created_at, updated_at, created_via)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
             "#,
             robot.id,
             robot.user_id,
@@ -98,22 +497,33 @@ impl TradingRobot {
             robot.performance_metrics,
             robot.last_signal_at,
             robot.total_trades,
+            robot.broker_connection_id,
             robot.created_at,
-            robot.updated_at
+            robot.updated_at,
+            created_via
         )
         .execute(pool)
-        .await?;
+        .await
+        .db_op("trading_robots.create")?;
 
         Ok(robot)
     }
 
-    pub async fn find_by_user_id(pool: &PgPool, user_id: Uuid) -> Result<Vec<TradingRobot>, sqlx::Error> {
+    pub async fn find_by_user_id(pool: &PgPool, user_id: Uuid) -> Result<Vec<TradingRobot>> {
+        Self::find_page_by_user_id(pool, user_id, None, 0).await
+    }
+
+    // Newest first; no limit returns them all
+    pub async fn find_page_by_user_id(pool: &PgPool, user_id: Uuid, limit: Option<i64>, offset: i64) -> Result<Vec<TradingRobot>> {
         let rows = sqlx::query!(
-            r#"SELECT id, user_id, name, strategy, status, risk_config, performance_metrics, last_signal_at, total_trades, created_at, updated_at FROM trading_robots WHERE user_id = $1 ORDER BY created_at DESC"#,
-            user_id
+            r#"SELECT id, user_id, name, strategy, status, risk_config, performance_metrics, last_signal_at, total_trades, broker_connection_id, notes, created_at, updated_at FROM trading_robots WHERE user_id = $1 AND deleted_at IS NULL ORDER BY created_at DESC, id DESC LIMIT $2 OFFSET $3"#,
+            user_id,
+            limit,
+            offset
         )
         .fetch_all(pool)
-        .await?;
+        .await
+        .db_op("trading_robots.find_by_user_id")?;
 
         let robots = rows.into_iter().map(|row| TradingRobot {
             id: row.id,
@@ -122,9 +532,11 @@ impl TradingRobot {
             strategy: row.strategy.unwrap_or_default(),
             status: row.status,
             risk_config: row.risk_config,
-            performance_metrics: row.performance_metrics.unwrap_or_default(),
+            performance_metrics: row.performance_metrics,
             last_signal_at: row.last_signal_at,
             total_trades: row.total_trades,
+            broker_connection_id: row.broker_connection_id,
+            notes: row.notes,
             created_at: row.created_at,
             updated_at: row.updated_at,
         }).collect();
@@ -132,14 +544,23 @@ impl TradingRobot {
         Ok(robots)
     }
 
-    pub async fn find_by_id(pool: &PgPool, id: Uuid, user_id: Uuid) -> Result<Option<TradingRobot>, sqlx::Error> {
+    pub async fn count_by_user_id(pool: &PgPool, user_id: Uuid) -> Result<i64> {
+        sqlx::query_scalar("SELECT COUNT(*) FROM trading_robots WHERE user_id = $1 AND deleted_at IS NULL")
+            .bind(user_id)
+            .fetch_one(pool)
+            .await
+            .db_op("trading_robots.count_by_user_id")
+    }
+
+    pub async fn find_by_id(pool: &PgPool, id: Uuid, user_id: Uuid) -> Result<Option<TradingRobot>> {
         let row = sqlx::query!(
-            r#"SELECT id, user_id, name, strategy, status, risk_config, performance_metrics, last_signal_at, total_trades, created_at, updated_at FROM trading_robots WHERE id = $1 AND user_id = $2"#,
+            r#"SELECT id, user_id, name, strategy, status, risk_config, performance_metrics, last_signal_at, total_trades, broker_connection_id, notes, created_at, updated_at FROM trading_robots WHERE id = $1 AND user_id = $2 AND deleted_at IS NULL"#,
             id,
             user_id
         )
         .fetch_optional(pool)
-        .await?;
+        .await
+        .db_op("trading_robots.find_by_id")?;
 
         if let Some(row) = row {
             Ok(Some(TradingRobot {
@@ -149,9 +570,11 @@ impl TradingRobot {
                 strategy: row.strategy.unwrap_or_default(),
                 status: row.status,
                 risk_config: row.risk_config,
-                performance_metrics: row.performance_metrics.unwrap_or_default(),
+                performance_metrics: row.performance_metrics,
                 last_signal_at: row.last_signal_at,
                 total_trades: row.total_trades,
+                broker_connection_id: row.broker_connection_id,
+                notes: row.notes,
                 created_at: row.created_at,
                 updated_at: row.updated_at,
             }))
@@ -160,12 +583,106 @@ impl TradingRobot {
         }
     }
 
+    // Robots that should have a live runner: active, or paused but still holding positions, plus
+    // any with an order a shutdown left unresolved
+    pub async fn find_recoverable(pool: &PgPool) -> Result<Vec<TradingRobot>> {
+        let rows = sqlx::query!(
+            r#"SELECT id, user_id, name, strategy, status, risk_config, performance_metrics, last_signal_at, total_trades, broker_connection_id, notes, created_at, updated_at FROM trading_robots WHERE deleted_at IS NULL AND (status IN ('active', 'paused_risk', 'paused_broker', 'cooling_down') OR id IN (SELECT robot_id FROM trades WHERE status = 'execution_pending')) ORDER BY created_at"#
+        )
+        .fetch_all(pool)
+        .await
+        .db_op("trading_robots.find_recoverable")?;
+
+        let robots = rows.into_iter().map(|row| TradingRobot {
+            id: row.id,
+            user_id: row.user_id,
+            name: row.name,
+            strategy: row.strategy.unwrap_or_default(),
+            status: row.status,
+            risk_config: row.risk_config,
+            performance_metrics: row.performance_metrics,
+            last_signal_at: row.last_signal_at,
+            total_trades: row.total_trades,
+            broker_connection_id: row.broker_connection_id,
+            notes: row.notes,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        }).collect();
+
+        Ok(robots)
+    }
+
+    // Cooling-down robots whose resume time has come
+    pub async fn find_due_cooldowns(pool: &PgPool, now: DateTime<Utc>) -> Result<Vec<TradingRobot>> {
+        let rows = sqlx::query!(
+            r#"SELECT id, user_id, name, strategy, status, risk_config, performance_metrics, last_signal_at, total_trades, broker_connection_id, notes, created_at, updated_at FROM trading_robots WHERE status = 'cooling_down' AND deleted_at IS NULL AND (performance_metrics->>'cooldown_until')::TIMESTAMPTZ <= $1 ORDER BY created_at"#,
+            now
+        )
+        .fetch_all(pool)
+        .await
+        .db_op("trading_robots.find_due_cooldowns")?;
+
+        let robots = rows.into_iter().map(|row| TradingRobot {
+            id: row.id,
+            user_id: row.user_id,
+            name: row.name,
+            strategy: row.strategy.unwrap_or_default(),
+            status: row.status,
+            risk_config: row.risk_config,
+            performance_metrics: row.performance_metrics,
+            last_signal_at: row.last_signal_at,
+            total_trades: row.total_trades,
+            broker_connection_id: row.broker_connection_id,
+            notes: row.notes,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        }).collect();
+
+        Ok(robots)
+    }
+
+    pub async fn existing_ids(pool: &PgPool, user_id: Uuid, ids: &[Uuid]) -> Result<Vec<Uuid>> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        sqlx::query_scalar::<_, Uuid>("SELECT id FROM trading_robots WHERE user_id = $1 AND id = ANY($2) AND deleted_at IS NULL")
+            .bind(user_id)
+            .bind(ids)
+            .fetch_all(pool)
+            .await
+            .db_op("trading_robots.existing_ids")
+    }
+
+    // Symbols listed under risk_config.symbols by active robots
+    pub async fn active_symbols(pool: &PgPool) -> Result<Vec<String>> {
+        sqlx::query_scalar::<_, String>(
+            r#"
+            SELECT DISTINCT jsonb_array_elements_text(risk_config->'symbols')
+            FROM trading_robots
+            WHERE status = 'active' AND jsonb_typeof(risk_config->'symbols') = 'array'
+            "#,
+        )
+        .fetch_all(pool)
+        .await
+        .db_op("trading_robots.active_symbols")
+    }
+
+    pub async fn count_by_client(pool: &PgPool) -> Result<Vec<ClientCount>> {
+        sqlx::query_as::<_, ClientCount>(
+            "SELECT created_via, COUNT(*) AS count FROM trading_robots GROUP BY created_via ORDER BY count DESC",
+        )
+        .fetch_all(pool)
+        .await
+        .db_op("trading_robots.count_by_client")
+    }
+
     pub async fn update_status(
         pool: &PgPool,
         id: Uuid,
         user_id: Uuid,
         status: &str,
-    ) -> Result<(), sqlx::Error> {
+    ) -> Result<()> {
         sqlx::query!(
             "UPDATE trading_robots SET status = $1, updated_at = $2 WHERE id = $3 AND user_id = $4",
             status,
@@ -174,40 +691,517 @@ impl TradingRobot {
             user_id
         )
         .execute(pool)
-        .await?;
+        .await
+        .db_op("trading_robots.update_status")?;
 
         Ok(())
     }
 
-    pub fn get_total_profit(&self) -> f64 {
-        self.performance_metrics
-            .get("total_profit")
-            .and_then(|v| v.as_f64())
-            .unwrap_or(0.0)
+    // Moves the robot to `to` only while it is still `from`; false otherwise
+    pub async fn transition_status(pool: &PgPool, id: Uuid, from: &str, to: &str) -> Result<bool> {
+        let result = sqlx::query("UPDATE trading_robots SET status = $3, updated_at = $4 WHERE id = $1 AND status = $2")
+            .bind(id)
+            .bind(from)
+            .bind(to)
+            .bind(Utc::now())
+            .execute(pool)
+            .await
+            .db_op("trading_robots.transition_status")?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    // For operator tools, which address robots by id alone
+    pub async fn owner_id(pool: &PgPool, id: Uuid) -> Result<Option<Uuid>> {
+        sqlx::query_scalar("SELECT user_id FROM trading_robots WHERE id = $1 AND deleted_at IS NULL")
+            .bind(id)
+            .fetch_optional(pool)
+            .await
+            .db_op("trading_robots.owner_id")
+    }
+
+    // Trades of the robot still open or waiting on the broker
+    pub async fn open_trade_count(pool: &PgPool, id: Uuid) -> Result<i64> {
+        sqlx::query_scalar("SELECT COUNT(*) FROM trades WHERE robot_id = $1 AND status IN ('open', 'execution_pending')")
+            .bind(id)
+            .fetch_one(pool)
+            .await
+            .db_op("trading_robots.open_trade_count")
+    }
+
+    // Hides the robot from its owner and every find_*; its trades stay until the purge job
+    pub async fn soft_delete(pool: &PgPool, id: Uuid, user_id: Uuid, now: DateTime<Utc>) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE trading_robots SET deleted_at = $3, updated_at = $3 WHERE id = $1 AND user_id = $2 AND deleted_at IS NULL",
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(now)
+        .execute(pool)
+        .await
+        .db_op("trading_robots.soft_delete")?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    // Removes robots deleted before `before` that have no trades; the others are kept with them
+    pub async fn purge_deleted(pool: &PgPool, before: DateTime<Utc>) -> Result<u64> {
+        let result = sqlx::query(
+            "DELETE FROM trading_robots r WHERE r.deleted_at < $1 AND NOT EXISTS (SELECT 1 FROM trades t WHERE t.robot_id = r.id)",
+        )
+            .bind(before)
+            .execute(pool)
+            .await
+            .db_op("trading_robots.purge_deleted")?;
+        Ok(result.rows_affected())
+    }
+
+    // Saves the edited strategy, risk_config and notes together with the journal entry describing them
+    pub async fn update_config(pool: &PgPool, robot: &TradingRobot, change: Option<&RobotChange>) -> Result<()> {
+        let mut tx = pool.begin().await.db_op("trading_robots.update_config")?;
+
+        sqlx::query(
+            "UPDATE trading_robots SET strategy = $1, risk_config = $2, notes = $3, updated_at = $4 WHERE id = $5 AND user_id = $6",
+        )
+        .bind(&robot.strategy)
+        .bind(&robot.risk_config)
+        .bind(&robot.notes)
+        .bind(robot.updated_at)
+        .bind(robot.id)
+        .bind(robot.user_id)
+        .execute(&mut *tx)
+        .await
+        .db_op("trading_robots.update_config")?;
+
+        if let Some(change) = change {
+            sqlx::query(
+                r#"
+                INSERT INTO robot_changes (id, robot_id, user_id, actor_id, changes, created_at)
+                VALUES ($1, $2, $3, $4, $5, $6)
+                "#,
+            )
+            .bind(change.id)
+            .bind(change.robot_id)
+            .bind(change.user_id)
+            .bind(change.actor_id)
+            .bind(&change.changes)
+            .bind(change.created_at)
+            .execute(&mut *tx)
+            .await
+            .db_op("trading_robots.update_config")?;
+        }
+
+        tx.commit().await.db_op("trading_robots.update_config")?;
+        Ok(())
+    }
+
+    // Stores the new allocation together with its baseline (the allocated slice of the balance)
+    // and the profit realized so far, so the virtual equity restarts from the baseline
+    pub async fn set_allocation(
+        pool: &PgPool,
+        id: Uuid,
+        user_id: Uuid,
+        allocation_percent: Option<f64>,
+        baseline: Option<f64>,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE trading_robots SET
+                risk_config = CASE
+                    WHEN $3::FLOAT8 IS NULL THEN risk_config - 'allocation_percent'
+                    ELSE risk_config || jsonb_build_object('allocation_percent', $3::FLOAT8)
+                END,
+                performance_metrics = CASE
+                    WHEN $4::FLOAT8 IS NULL THEN
+                        COALESCE(performance_metrics, '{}'::jsonb) - 'allocation_baseline' - 'allocation_profit_offset' - 'virtual_equity'
+                    ELSE COALESCE(performance_metrics, '{}'::jsonb) || jsonb_build_object(
+                        'allocation_baseline', $4::FLOAT8,
+                        'allocation_profit_offset', COALESCE((performance_metrics->>'total_profit')::FLOAT8, 0),
+                        'virtual_equity', $4::FLOAT8
+                    )
+                END,
+                updated_at = NOW()
+            WHERE id = $1 AND user_id = $2
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(allocation_percent)
+        .bind(baseline)
+        .execute(pool)
+        .await
+        .db_op("trading_robots.set_allocation")?;
+
+        Ok(())
+    }
+
+    // A win resets the consecutive loss count, a loss extends it. Returns the new count.
+    pub async fn record_trade_result(pool: &PgPool, id: Uuid, won: bool) -> Result<i32> {
+        sqlx::query_scalar::<_, i32>(
+            r#"
+            UPDATE trading_robots SET
+                performance_metrics = COALESCE(performance_metrics, '{}'::jsonb) || jsonb_build_object(
+                    'loss_streak',
+                    CASE WHEN $2 THEN 0 ELSE COALESCE((performance_metrics->>'loss_streak')::INT, 0) + 1 END
+                ),
+                updated_at = NOW()
+            WHERE id = $1
+            RETURNING (performance_metrics->>'loss_streak')::INT
+            "#,
+        )
+        .bind(id)
+        .bind(won)
+        .fetch_one(pool)
+        .await
+        .db_op("trading_robots.record_trade_result")
+    }
+
+    // Moves an active robot to cooling_down; false if it was not active anymore
+    pub async fn start_cooldown(pool: &PgPool, id: Uuid, resume_at: DateTime<Utc>) -> Result<bool> {
+        let result = sqlx::query(
+            r#"
+            UPDATE trading_robots SET
+                status = 'cooling_down',
+                performance_metrics = COALESCE(performance_metrics, '{}'::jsonb) || jsonb_build_object(
+                    'loss_streak', 0,
+                    'cooldown_until', $2::TIMESTAMPTZ
+                ),
+                updated_at = NOW()
+            WHERE id = $1 AND status = 'active'
+            "#,
+        )
+        .bind(id)
+        .bind(resume_at)
+        .execute(pool)
+        .await
+        .db_op("trading_robots.start_cooldown")?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    // Pauses active robots whose connection is of one of `broker_types` (upper-case), or just
+    // `robot_id` when given. The broker type is recorded so only these robots resume afterwards.
+    pub async fn pause_for_maintenance(
+        pool: &PgPool,
+        broker_types: &[String],
+        robot_id: Option<Uuid>,
+    ) -> Result<Vec<MaintenanceRobot>> {
+        sqlx::query_as::<_, MaintenanceRobot>(
+            r#"
+            UPDATE trading_robots r SET
+                status = 'paused_broker',
+                performance_metrics = COALESCE(r.performance_metrics, '{}'::jsonb) || jsonb_build_object('maintenance_broker_type', UPPER(c.broker_type)),
+                updated_at = NOW()
+            FROM broker_connections c
+            WHERE c.id = r.broker_connection_id
+                AND r.status = 'active'
+                AND UPPER(c.broker_type) = ANY($1)
+                AND ($2::UUID IS NULL OR r.id = $2)
+            RETURNING r.id, r.user_id, r.name, UPPER(c.broker_type) AS broker_type
+            "#,
+        )
+        .bind(broker_types)
+        .bind(robot_id)
+        .fetch_all(pool)
+        .await
+        .db_op("trading_robots.pause_for_maintenance")
+    }
+
+    // Puts robots paused for maintenance back to active unless their broker type is in
+    // `still_scoped`. A robot stopped or restarted meanwhile is left alone and loses the mark.
+    pub async fn resume_after_maintenance(pool: &PgPool, still_scoped: &[String]) -> Result<Vec<MaintenanceRobot>> {
+        sqlx::query(
+            r#"
+            UPDATE trading_robots SET performance_metrics = performance_metrics - 'maintenance_broker_type'
+            WHERE status <> 'paused_broker' AND performance_metrics->>'maintenance_broker_type' IS NOT NULL
+            "#,
+        )
+        .execute(pool)
+        .await
+        .db_op("trading_robots.clear_maintenance")?;
+
+        sqlx::query_as::<_, MaintenanceRobot>(
+            r#"
+            UPDATE trading_robots r SET
+                status = 'active',
+                performance_metrics = r.performance_metrics - 'maintenance_broker_type',
+                updated_at = NOW()
+            FROM (
+                SELECT id, performance_metrics->>'maintenance_broker_type' AS broker_type
+                FROM trading_robots
+                WHERE status = 'paused_broker'
+                    AND performance_metrics->>'maintenance_broker_type' IS NOT NULL
+                    AND NOT (performance_metrics->>'maintenance_broker_type' = ANY($1))
+                FOR UPDATE
+            ) paused
+            WHERE r.id = paused.id
+            RETURNING r.id, r.user_id, r.name, paused.broker_type
+            "#,
+        )
+        .bind(still_scoped)
+        .fetch_all(pool)
+        .await
+        .db_op("trading_robots.resume_after_maintenance")
+    }
+
+    // Parks the user's robots among `ids` in plan_limited, recording the status each had so a
+    // later reactivation can restore it. Robots already parked are left as they are.
+    pub async fn limit_by_plan(pool: &PgPool, user_id: Uuid, ids: &[Uuid]) -> Result<Vec<PlanLimitedRobot>> {
+        sqlx::query_as::<_, PlanLimitedRobot>(
+            r#"
+            UPDATE trading_robots r SET
+                status = 'plan_limited',
+                performance_metrics = r.performance_metrics || jsonb_build_object('plan_limited_from', parked.status),
+                updated_at = NOW()
+            FROM (
+                SELECT id, status
+                FROM trading_robots
+                WHERE user_id = $1 AND id = ANY($2) AND status <> 'plan_limited'
+                FOR UPDATE
+            ) parked
+            WHERE r.id = parked.id
+            RETURNING r.id, r.name, parked.status AS previous_status
+            "#,
+        )
+        .bind(user_id)
+        .bind(ids)
+        .fetch_all(pool)
+        .await
+        .db_op("trading_robots.limit_by_plan")
     }
 
-    pub fn get_winning_trades(&self) -> i32 {
+    // Moves up to `max` of the user's plan-limited robots, oldest first, or all of them when
+    // None, back to stopped; returns them with the status they had before they were parked
+    pub async fn release_plan_limited(pool: &PgPool, user_id: Uuid, max: Option<i64>) -> Result<Vec<PlanLimitedRobot>> {
+        sqlx::query_as::<_, PlanLimitedRobot>(
+            r#"
+            UPDATE trading_robots r SET
+                status = 'stopped',
+                performance_metrics = r.performance_metrics - 'plan_limited_from',
+                updated_at = NOW()
+            FROM (
+                SELECT id, COALESCE(performance_metrics->>'plan_limited_from', 'stopped') AS previous_status
+                FROM trading_robots
+                WHERE user_id = $1 AND status = 'plan_limited'
+                ORDER BY created_at, id
+                LIMIT $2
+                FOR UPDATE
+            ) parked
+            WHERE r.id = parked.id
+            RETURNING r.id, r.name, parked.previous_status
+            "#,
+        )
+        .bind(user_id)
+        .bind(max)
+        .fetch_all(pool)
+        .await
+        .db_op("trading_robots.release_plan_limited")
+    }
+
+    // Puts a cooling-down robot back to active; false if it was not cooling down
+    pub async fn end_cooldown(pool: &PgPool, id: Uuid) -> Result<bool> {
+        let result = sqlx::query(
+            r#"
+            UPDATE trading_robots SET
+                status = 'active',
+                performance_metrics = COALESCE(performance_metrics, '{}'::jsonb) - 'cooldown_until',
+                updated_at = NOW()
+            WHERE id = $1 AND status = 'cooling_down'
+            "#,
+        )
+        .bind(id)
+        .execute(pool)
+        .await
+        .db_op("trading_robots.end_cooldown")?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    // Recomputes the robot's trade counters from its corrected closed trades in a single statement
+    pub async fn refresh_performance(pool: &PgPool, id: Uuid) -> Result<()> {
+        sqlx::query(&format!(
+            r#"
+            UPDATE trading_robots r SET
+                total_trades = s.total_trades,
+                performance_metrics = COALESCE(r.performance_metrics, '{{}}'::jsonb) || jsonb_build_object(
+                    'total_profit', s.total_profit,
+                    'winning_trades', s.winning_trades
+                ) || CASE
+                    WHEN r.performance_metrics ? 'allocation_baseline' THEN jsonb_build_object(
+                        'virtual_equity',
+                        (r.performance_metrics->>'allocation_baseline')::FLOAT8 + s.total_profit
+                            - COALESCE((r.performance_metrics->>'allocation_profit_offset')::FLOAT8, 0)
+                    )
+                    ELSE '{{}}'::jsonb
+                END,
+                updated_at = NOW()
+            FROM (
+                SELECT
+                    COUNT(*)::INT as total_trades,
+                    COUNT(CASE WHEN profit_loss::FLOAT8 > 0 THEN 1 END)::INT as winning_trades,
+                    COALESCE(SUM(profit_loss::FLOAT8), 0) as total_profit
+                FROM {}
+                WHERE robot_id = $1 AND status = 'closed'
+            ) s
+            WHERE r.id = $1
+            "#,
+            TradeCorrection::corrected_trades()
+        ))
+        .bind(id)
+        .execute(pool)
+        .await
+        .db_op("trading_robots.refresh_performance")?;
+
+        Ok(())
+    }
+
+    pub fn stop_management(&self) -> StopManagement {
+        StopManagement::from_risk_config(&self.risk_config).unwrap_or_default()
+    }
+
+    // Reads risk_config.allocation_percent; absent means the robot sizes against the whole account
+    pub fn allocation_from_risk_config(risk_config: &serde_json::Value) -> Result<Option<f64>, String> {
+        match risk_config.get("allocation_percent") {
+            None | Some(serde_json::Value::Null) => Ok(None),
+            Some(value) => match value.as_f64() {
+                Some(percent) if percent > 0.0 && percent <= 100.0 => Ok(Some(percent)),
+                _ => Err("allocation_percent must be a number above 0 and at most 100".to_string()),
+            },
+        }
+    }
+
+    pub fn allocation_percent(&self) -> Option<f64> {
+        Self::allocation_from_risk_config(&self.risk_config).unwrap_or(None)
+    }
+
+    // Reads risk_config.evaluation_interval_seconds; absent means as often as the plan allows
+    pub fn evaluation_interval_from_risk_config(risk_config: &serde_json::Value) -> Result<Option<u64>, String> {
+        match risk_config.get("evaluation_interval_seconds") {
+            None | Some(serde_json::Value::Null) => Ok(None),
+            Some(value) => match value.as_u64() {
+                Some(seconds) if (1..=86400).contains(&seconds) => Ok(Some(seconds)),
+                _ => Err("evaluation_interval_seconds must be a whole number between 1 and 86400".to_string()),
+            },
+        }
+    }
+
+    // The owner's plan and the robot's risk_config, read by the runner every cycle
+    pub async fn find_plan_and_risk_config(pool: &PgPool, robot_id: Uuid) -> Result<Option<(String, serde_json::Value)>> {
+        sqlx::query_as::<_, (String, serde_json::Value)>(
+            "SELECT COALESCE(u.subscription_plan, 'free'), r.risk_config FROM trading_robots r JOIN users u ON u.id = r.user_id WHERE r.id = $1",
+        )
+        .bind(robot_id)
+        .fetch_optional(pool)
+        .await
+        .db_op("trading_robots.find_plan_and_risk_config")
+    }
+
+    // Allocated baseline plus the profit realized since the allocation was last set
+    pub fn virtual_equity(&self) -> Option<f64> {
+        let metric = |key: &str| self.performance_metrics.get(key).and_then(|v| v.as_f64());
+        let baseline = metric("allocation_baseline")?;
+        let total_profit = PerformanceMetrics::parse(&self.performance_metrics).total_profit;
+        Some(baseline + total_profit - metric("allocation_profit_offset").unwrap_or(0.0))
+    }
+
+    pub fn loss_streak_cooldown(&self) -> Option<LossStreakCooldown> {
+        LossStreakCooldown::from_risk_config(&self.risk_config).unwrap_or(None)
+    }
+
+    pub fn signal_stability(&self) -> SignalStability {
+        SignalStability::from_risk_config(&self.risk_config).unwrap_or_default()
+    }
+
+    pub fn composite_strategy(&self) -> Option<CompositeStrategy> {
+        CompositeStrategy::from_risk_config(&self.risk_config).unwrap_or(None)
+    }
+
+    pub fn config_warnings(&self) -> RiskConfigWarnings {
+        RiskConfigWarnings::check(&self.risk_config)
+    }
+
+    pub fn resume_at(&self) -> Option<DateTime<Utc>> {
+        if self.status != "cooling_down" {
+            return None;
+        }
         self.performance_metrics
-            .get("winning_trades")
-            .and_then(|v| v.as_i64())
-            .unwrap_or(0) as i32
+            .get("cooldown_until")
+            .and_then(|v| v.as_str())
+            .and_then(|raw| DateTime::parse_from_rfc3339(raw).ok())
+            .map(|at| at.with_timezone(&Utc))
+    }
+
+    // Warns when counters are missing; run the robots through `fill_missing_performance` first
+    // so the response doesn't report them as zero
+    pub fn performance(&self) -> PerformanceMetrics {
+        let metrics = PerformanceMetrics::parse(&self.performance_metrics);
+        if !metrics.is_complete() {
+            tracing::warn!(
+                robot_id = %self.id,
+                "Robot {} performance_metrics is missing {}",
+                self.id,
+                metrics.missing.join(", ")
+            );
+        }
+        metrics
     }
 
     pub fn calculate_win_rate(&self) -> f64 {
-        if self.total_trades == 0 {
-            0.0
-        } else {
-            (self.get_winning_trades() as f64 / self.total_trades as f64) * 100.0
+        self.performance().win_rate(self.total_trades)
+    }
+
+    // Robots whose counters are incomplete get them from their corrected closed trades, in
+    // memory only, so a legacy row reports its real win rate instead of 0%
+    pub async fn fill_missing_performance(pool: &PgPool, robots: &mut [TradingRobot]) -> Result<()> {
+        let ids: Vec<Uuid> = robots
+            .iter()
+            .filter(|r| !r.performance().is_complete())
+            .map(|r| r.id)
+            .collect();
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        let counts = sqlx::query_as::<_, (Uuid, i32, i32, f64)>(&format!(
+            r#"
+            SELECT robot_id,
+                COUNT(*)::INT,
+                COUNT(CASE WHEN profit_loss::FLOAT8 > 0 THEN 1 END)::INT,
+                COALESCE(SUM(profit_loss::FLOAT8), 0)
+            FROM {}
+            WHERE robot_id = ANY($1) AND status = 'closed'
+            GROUP BY robot_id
+            "#,
+            TradeCorrection::corrected_trades()
+        ))
+        .bind(&ids)
+        .fetch_all(pool)
+        .await
+        .db_op("trading_robots.fill_missing_performance")?;
+
+        for robot in robots.iter_mut().filter(|r| ids.contains(&r.id)) {
+            let (total_trades, winning_trades, total_profit) = counts
+                .iter()
+                .find(|(id, ..)| *id == robot.id)
+                .map(|&(_, total, winning, profit)| (total, winning, profit))
+                .unwrap_or((0, 0, 0.0));
+            robot.total_trades = total_trades;
+            if !robot.performance_metrics.is_object() {
+                robot.performance_metrics = serde_json::json!({});
+            }
+            robot.performance_metrics["total_profit"] = serde_json::json!(total_profit);
+            robot.performance_metrics["winning_trades"] = serde_json::json!(winning_trades);
         }
+        Ok(())
     }
 }
 
 impl From<TradingRobot> for TradingRobotResponse {
     fn from(robot: TradingRobot) -> Self {
-        let total_profit = robot.get_total_profit();
-        let winning_trades = robot.get_winning_trades();
-        let win_rate = robot.calculate_win_rate();
-        
+        let performance = robot.performance();
+        let win_rate = performance.win_rate(robot.total_trades);
+        let allocation_percent = robot.allocation_percent();
+        let virtual_equity = robot.virtual_equity();
+        let resume_at = robot.resume_at();
+
         TradingRobotResponse {
             id: robot.id,
             name: robot.name,
@@ -217,10 +1211,81 @@ impl From<TradingRobot> for TradingRobotResponse {
             performance_metrics: robot.performance_metrics,
             last_signal_at: robot.last_signal_at,
             total_trades: robot.total_trades,
-            total_profit,
-            winning_trades,
+            total_profit: performance.total_profit,
+            winning_trades: performance.winning_trades,
             win_rate,
+            broker_connection_id: robot.broker_connection_id,
+            is_demo: false,
+            allocation_percent,
+            virtual_equity,
+            resume_at,
+            notes: robot.notes,
             created_at: robot.created_at,
         }
     }
 }
+
+impl TradingRobotResponse {
+    // Badges the robot as demo when its bound connection is a demo account
+    pub fn with_connections(robot: TradingRobot, connections: &[BrokerConnection]) -> Self {
+        let is_demo = robot
+            .broker_connection_id
+            .and_then(|id| connections.iter().find(|c| c.id == id))
+            .map(|c| c.is_demo)
+            .unwrap_or(false);
+
+        TradingRobotResponse {
+            is_demo,
+            ..robot.into()
+        }
+    }
+
+    // Like `with_connections`, once counters missing from legacy rows are filled in from the trades
+    pub async fn from_robots(
+        pool: &PgPool,
+        mut robots: Vec<TradingRobot>,
+        connections: &[BrokerConnection],
+    ) -> Result<Vec<Self>> {
+        TradingRobot::fill_missing_performance(pool, &mut robots).await?;
+        Ok(robots.into_iter().map(|r| Self::with_connections(r, connections)).collect())
+    }
+
+    pub async fn from_robot(pool: &PgPool, robot: TradingRobot, connections: &[BrokerConnection]) -> Result<Self> {
+        let mut robots = [robot];
+        TradingRobot::fill_missing_performance(pool, &mut robots).await?;
+        let [robot] = robots;
+        Ok(Self::with_connections(robot, connections))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_a_null_legacy_row_is_incomplete() {
+        let metrics = PerformanceMetrics::parse(&serde_json::Value::Null);
+        assert_eq!(metrics.missing, ["total_profit", "winning_trades"]);
+        assert_eq!((metrics.total_profit, metrics.winning_trades), (0.0, 0));
+        assert!(!PerformanceMetrics::parse(&json!({})).is_complete());
+    }
+
+    #[test]
+    fn test_a_partial_row_lists_what_it_lacks() {
+        let metrics = PerformanceMetrics::parse(&json!({ "total_profit": 12.5, "loss_streak": 2 }));
+        assert_eq!((metrics.total_profit, metrics.missing.as_slice()), (12.5, ["winning_trades"].as_slice()));
+        // A counter of the wrong type can't be trusted either
+        assert!(!PerformanceMetrics::parse(&json!({ "total_profit": 1.0, "winning_trades": "3" })).is_complete());
+    }
+
+    #[test]
+    fn test_a_complete_row_is_read_as_stored() {
+        let metrics = PerformanceMetrics::parse(&json!({ "total_profit": 0, "winning_trades": 3, "cooldown_until": null }));
+        assert!(metrics.is_complete());
+        assert_eq!((metrics.total_profit, metrics.winning_trades), (0.0, 3));
+        assert_eq!(metrics.win_rate(4), 75.0);
+        assert_eq!(metrics.win_rate(0), 0.0);
+        assert!(PerformanceMetrics::parse(&TradingRobot::new(Uuid::new_v4(), "r".into(), "s".into()).performance_metrics).is_complete());
+    }
+}
@@ -5,6 +5,8 @@ use uuid::Uuid;
 use validator::Validate;
 use bigdecimal::{BigDecimal, FromPrimitive};
 
+use crate::errors::{DbOp, Result};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TradingSession {
     pub id: Uuid,
@@ -62,7 +64,7 @@ impl TradingSession {
         pool: &PgPool,
         user_id: Uuid,
         request: CreateTradingSessionRequest,
-    ) -> Result<TradingSession, sqlx::Error> {
+    ) -> Result<TradingSession> {
         let session = TradingSession::new(user_id, request.robot_id);
 
         sqlx::query!(
@@ -82,18 +84,20 @@ impl TradingSession {
             session.updated_at
         )
         .execute(pool)
-        .await?;
+        .await
+        .db_op("trading_sessions.create")?;
 
         Ok(session)
     }
 
-    pub async fn find_by_user_id(pool: &PgPool, user_id: Uuid) -> Result<Vec<TradingSession>, sqlx::Error> {
+    pub async fn find_by_user_id(pool: &PgPool, user_id: Uuid) -> Result<Vec<TradingSession>> {
         let rows = sqlx::query!(
             r#"SELECT id, user_id, robot_id, status, total_trades, winning_trades, total_profit::FLOAT8 as total_profit, started_at, ended_at, created_at, updated_at FROM trading_sessions WHERE user_id = $1 ORDER BY created_at DESC"#,
             user_id
         )
         .fetch_all(pool)
-        .await?;
+        .await
+        .db_op("trading_sessions.find_by_user_id")?;
 
         let sessions = rows.into_iter().map(|row| TradingSession {
             id: row.id,
@@ -112,14 +116,15 @@ impl TradingSession {
         Ok(sessions)
     }
 
-    pub async fn find_by_id(pool: &PgPool, id: Uuid, user_id: Uuid) -> Result<Option<TradingSession>, sqlx::Error> {
+    pub async fn find_by_id(pool: &PgPool, id: Uuid, user_id: Uuid) -> Result<Option<TradingSession>> {
         let row = sqlx::query!(
             r#"SELECT id, user_id, robot_id, status, total_trades, winning_trades, total_profit::FLOAT8 as total_profit, started_at, ended_at, created_at, updated_at FROM trading_sessions WHERE id = $1 AND user_id = $2"#,
             id,
             user_id
         )
         .fetch_optional(pool)
-        .await?;
+        .await
+        .db_op("trading_sessions.find_by_id")?;
 
         if let Some(row) = row {
             Ok(Some(TradingSession {
@@ -140,6 +145,35 @@ impl TradingSession {
         }
     }
 
+    // Recomputes the robot's active session totals from trades closed since it started
+    pub async fn refresh_active_for_robot(pool: &PgPool, robot_id: Uuid) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE trading_sessions ts SET
+                total_trades = s.total_trades,
+                winning_trades = s.winning_trades,
+                total_profit = s.total_profit,
+                updated_at = NOW()
+            FROM trading_sessions cur
+            CROSS JOIN LATERAL (
+                SELECT
+                    COUNT(*)::INT as total_trades,
+                    COUNT(CASE WHEN profit_loss::FLOAT8 > 0 THEN 1 END)::INT as winning_trades,
+                    COALESCE(SUM(profit_loss::FLOAT8), 0) as total_profit
+                FROM trades
+                WHERE robot_id = cur.robot_id AND status = 'closed' AND closed_at >= cur.started_at
+            ) s
+            WHERE ts.id = cur.id AND cur.robot_id = $1 AND cur.status = 'active'
+            "#,
+        )
+        .bind(robot_id)
+        .execute(pool)
+        .await
+        .db_op("trading_sessions.refresh_active_for_robot")?;
+
+        Ok(())
+    }
+
     pub fn calculate_win_rate(&self) -> f64 {
         if self.total_trades == 0 {
             0.0
@@ -1,17 +1,63 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
 use sqlx::PgPool;
 use uuid::Uuid;
 use validator::Validate;
 
+use crate::errors::{AppError, DbOp, Result};
+use crate::services::plan_downgrade::RUNNING_STATUSES;
+use super::BrokerConnection;
+
+// Deleted accounts get an address under this reserved domain in place of theirs
+pub const DELETED_EMAIL_DOMAIN: &str = "deleted.invalid";
+
+// What a user may do beyond their own account. Support staff can read the admin API, admins can
+// also change things. Ordered, so requiring support lets admins through too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Support,
+    Admin,
+}
+
+impl Role {
+    pub fn parse(value: &str) -> Result<Role> {
+        match value.trim().to_lowercase().as_str() {
+            "user" => Ok(Role::User),
+            "support" => Ok(Role::Support),
+            "admin" => Ok(Role::Admin),
+            other => Err(AppError::Validation(format!("Unknown role '{}', expected user, support or admin", other))),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Support => "support",
+            Role::Admin => "admin",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
     pub id: Uuid,
     pub email: String,
     pub password_hash: String,
     pub is_active: bool,
+    // Set together with role; true exactly for admins
     pub is_superuser: bool,
+    // user | support | admin, see Role
+    pub role: String,
     pub subscription_plan: String,
+    pub full_name: Option<String>,
+    pub avatar_url: Option<String>,
+    // An IANA name such as Europe/Lisbon
+    pub timezone: Option<String>,
+    // A language tag such as pt-BR
+    pub locale: Option<String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -24,14 +70,129 @@ pub struct CreateUserRequest {
     pub password: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+// The one public shape of a user; endpoints that show more wrap it, like AdminUserResponse
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct UserResponse {
     pub id: Uuid,
     pub email: String,
     pub is_active: bool,
     pub is_superuser: bool,
+    pub role: Role,
     pub subscription_plan: String,
+    pub full_name: Option<String>,
+    pub avatar_url: Option<String>,
+    pub timezone: Option<String>,
+    pub locale: Option<String>,
     pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+// Fields left out stay as they are and an empty string clears one
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+pub struct UpdateProfileRequest {
+    pub full_name: Option<String>,
+    // https only
+    pub avatar_url: Option<String>,
+    // An IANA name such as America/Sao_Paulo
+    pub timezone: Option<String>,
+    // A language tag such as pt-BR
+    pub locale: Option<String>,
+}
+
+impl UpdateProfileRequest {
+    // Checks every field sent before changing any of them
+    pub fn apply(self, user: &mut User, now: DateTime<Utc>) -> Result<()> {
+        let full_name = self.full_name.map(profile_name).transpose()?;
+        let avatar_url = self.avatar_url.map(profile_avatar_url).transpose()?;
+        let timezone = self.timezone.map(profile_timezone).transpose()?;
+        let locale = self.locale.map(profile_locale).transpose()?;
+        if let Some(full_name) = full_name {
+            user.full_name = full_name;
+        }
+        if let Some(avatar_url) = avatar_url {
+            user.avatar_url = avatar_url;
+        }
+        if let Some(timezone) = timezone {
+            user.timezone = timezone;
+        }
+        if let Some(locale) = locale {
+            user.locale = locale;
+        }
+        user.updated_at = now;
+        Ok(())
+    }
+}
+
+const MAX_FULL_NAME: usize = 100;
+const MAX_AVATAR_URL: usize = 2048;
+
+// Trimmed; None for an empty value
+fn profile_value(value: String) -> Option<String> {
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+fn profile_name(value: String) -> Result<Option<String>> {
+    match profile_value(value) {
+        Some(name) if name.chars().count() > MAX_FULL_NAME => {
+            Err(AppError::Validation(format!("full_name must be at most {} characters", MAX_FULL_NAME)))
+        }
+        name => Ok(name),
+    }
+}
+
+fn profile_avatar_url(value: String) -> Result<Option<String>> {
+    match profile_value(value) {
+        Some(url) if !url.starts_with("https://") || url.len() > MAX_AVATAR_URL || url.contains(char::is_whitespace) => {
+            Err(AppError::Validation("avatar_url must be an https URL".to_string()))
+        }
+        url => Ok(url),
+    }
+}
+
+// Stored under its canonical IANA name
+fn profile_timezone(value: String) -> Result<Option<String>> {
+    profile_value(value)
+        .map(|name| {
+            name.parse::<chrono_tz::Tz>().map(|tz| tz.name().to_string()).map_err(|_| {
+                AppError::Validation(format!("Unknown timezone '{}', expected an IANA name such as Europe/Lisbon", name))
+            })
+        })
+        .transpose()
+}
+
+// A BCP 47 tag in its usual shape: a 2 or 3 letter language, then subtags such as BR or Hant
+fn profile_locale(value: String) -> Result<Option<String>> {
+    let Some(locale) = profile_value(value) else {
+        return Ok(None);
+    };
+    let mut parts = locale.split('-');
+    let language = parts.next().unwrap_or_default();
+    let valid = locale.len() <= 35
+        && (2..=3).contains(&language.len())
+        && language.chars().all(|c| c.is_ascii_alphabetic())
+        && parts.all(|part| (2..=8).contains(&part.len()) && part.chars().all(|c| c.is_ascii_alphanumeric()));
+    if !valid {
+        return Err(AppError::Validation(format!("Unknown locale '{}', expected a language tag such as pt-BR", locale)));
+    }
+    Ok(Some(locale))
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct UpdateUserRoleRequest {
+    pub role: Role,
+}
+
+// The account password, asked again before the account is deleted
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct DeleteAccountRequest {
+    pub password: String,
+}
+
+// Default risk_config for the user's new robots; null when none is saved
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct RiskTemplate {
+    pub risk_config: Option<serde_json::Map<String, serde_json::Value>>,
 }
 
 impl User {
@@ -43,7 +204,12 @@ impl User {
             password_hash,
             is_active: true,
             is_superuser: false,
+            role: Role::User.as_str().to_string(),
             subscription_plan: "free".to_string(),
+            full_name: None,
+            avatar_url: None,
+            timezone: None,
+            locale: None,
             created_at: now,
             updated_at: now,
         }
@@ -52,51 +218,69 @@ impl User {
     pub async fn create(
         pool: &PgPool,
         request: CreateUserRequest,
-    ) -> Result<User, sqlx::Error> {
+    ) -> Result<User> {
         // Simple password hashing - in production use bcrypt
         let password_hash = request.password; // This should be hashed
         let user = User::new(request.email, password_hash);
 
         sqlx::query!(
             r#"
-            INSERT INTO users (id, email, password_hash, is_active, is_superuser, subscription_plan, created_at, updated_at)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            INSERT INTO users (id, email, password_hash, is_active, is_superuser, role, subscription_plan, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
             "#,
             user.id,
             user.email,
             user.password_hash,
             user.is_active,
             user.is_superuser,
+            user.role,
             user.subscription_plan,
             user.created_at,
             user.updated_at
         )
         .execute(pool)
-        .await?;
+        .await
+        .db_op("users.create")?;
 
         Ok(user)
     }
 
-    pub async fn find_by_email(pool: &PgPool, email: &str) -> Result<Option<User>, sqlx::Error> {
+    pub async fn find_by_email(pool: &PgPool, email: &str) -> Result<Option<User>> {
         let user = sqlx::query_as!(
             User,
-            r#"SELECT id, email, password_hash, is_active, is_superuser, subscription_plan, created_at, updated_at FROM users WHERE email = $1"#,
+            r#"SELECT id, email, password_hash, is_active, is_superuser, role, subscription_plan, full_name, avatar_url, timezone, locale, created_at, updated_at FROM users WHERE email = $1 AND deleted_at IS NULL"#,
             email
         )
         .fetch_optional(pool)
-        .await?;
+        .await
+        .db_op("users.find_by_email")?;
+
+        Ok(user)
+    }
+
+    pub async fn find_by_id(pool: &PgPool, id: Uuid) -> Result<Option<User>> {
+        let user = sqlx::query_as!(
+            User,
+            r#"SELECT id, email, password_hash, is_active, is_superuser, role, subscription_plan, full_name, avatar_url, timezone, locale, created_at, updated_at FROM users WHERE id = $1 AND deleted_at IS NULL"#,
+            id
+        )
+        .fetch_optional(pool)
+        .await
+        .db_op("users.find_by_id")?;
 
         Ok(user)
     }
 
-    pub async fn find_by_id(pool: &PgPool, id: Uuid) -> Result<Option<User>, sqlx::Error> {
+    // Deleted accounts too, anonymized and inactive, for admin and audit views that refer to them by id
+    pub async fn find_by_id_including_deleted(pool: &PgPool, id: Uuid) -> Result<Option<User>> {
         let user = sqlx::query_as!(
             User,
-            r#"SELECT id, email, password_hash, is_active, is_superuser, subscription_plan, created_at, updated_at FROM users WHERE id = $1"#,
+            r#"SELECT id, email, password_hash, is_active, is_superuser, role, subscription_plan, full_name, avatar_url, timezone, locale, created_at, updated_at FROM users WHERE id = $1"#,
             id
         )
         .fetch_optional(pool)
-        .await?;
+        .await
+        .db_op("users.find_by_id_including_deleted")?;
 
         Ok(user)
     }
@@ -104,29 +288,173 @@ impl User {
     pub fn verify_password(&self, password: &str) -> bool {
         // Simple password verification - in production use bcrypt
         // For now, just compare directly (this should be hashed comparison)
-        self.password_hash == password
+        // A deleted account has no password, and nothing matches it
+        !self.password_hash.is_empty() && self.password_hash == password
+    }
+
+    // Stored the way create stores it
+    pub async fn set_password(pool: &PgPool, id: Uuid, password: &str) -> Result<()> {
+        sqlx::query("UPDATE users SET password_hash = $2, updated_at = $3 WHERE id = $1")
+            .bind(id)
+            .bind(password)
+            .bind(Utc::now())
+            .execute(pool)
+            .await
+            .db_op("users.set_password")?;
+        Ok(())
+    }
+
+    // An unknown or corrupt stored role counts as a plain user
+    pub fn role(&self) -> Role {
+        Role::parse(&self.role).unwrap_or(Role::User)
+    }
+
+    // Returns false for an unknown user
+    pub async fn set_role(pool: &PgPool, id: Uuid, role: Role) -> Result<bool> {
+        let result = sqlx::query("UPDATE users SET role = $2, is_superuser = $3, updated_at = $4 WHERE id = $1")
+            .bind(id)
+            .bind(role.as_str())
+            .bind(role == Role::Admin)
+            .bind(Utc::now())
+            .execute(pool)
+            .await
+            .db_op("users.set_role")?;
+        Ok(result.rows_affected() > 0)
     }
 
-    pub async fn update_last_login(pool: &PgPool, user_id: Uuid) -> Result<(), sqlx::Error> {
+    pub async fn save_profile(pool: &PgPool, user: &User) -> Result<()> {
+        sqlx::query("UPDATE users SET full_name = $2, avatar_url = $3, timezone = $4, locale = $5, updated_at = $6 WHERE id = $1")
+            .bind(user.id)
+            .bind(&user.full_name)
+            .bind(&user.avatar_url)
+            .bind(&user.timezone)
+            .bind(&user.locale)
+            .bind(user.updated_at)
+            .execute(pool)
+            .await
+            .db_op("users.save_profile")?;
+        Ok(())
+    }
+
+    // The name and picture a sign-in provider knows stand in for the ones the user has not set.
+    // Values the profile would refuse are skipped rather than failing the sign-in.
+    pub async fn fill_profile(pool: &PgPool, mut user: User, full_name: Option<String>, avatar_url: Option<String>) -> Result<User> {
+        let full_name = full_name.and_then(|name| profile_name(name).ok().flatten());
+        let avatar_url = avatar_url.and_then(|url| profile_avatar_url(url).ok().flatten());
+        let fills_name = user.full_name.is_none() && full_name.is_some();
+        let fills_avatar = user.avatar_url.is_none() && avatar_url.is_some();
+        if !fills_name && !fills_avatar {
+            return Ok(user);
+        }
+        if fills_name {
+            user.full_name = full_name;
+        }
+        if fills_avatar {
+            user.avatar_url = avatar_url;
+        }
+        user.updated_at = Utc::now();
+        Self::save_profile(pool, &user).await?;
+        Ok(user)
+    }
+
+    // Robots with a live runner and trades still open; an account is only deleted without either
+    pub async fn deletion_blockers(pool: &PgPool, id: Uuid) -> Result<(i64, i64)> {
+        sqlx::query_as::<_, (i64, i64)>(
+            "SELECT (SELECT COUNT(*) FROM trading_robots WHERE user_id = $1 AND status = ANY($2)), \
+                    (SELECT COUNT(*) FROM trades WHERE user_id = $1 AND status IN ('open', 'execution_pending'))",
+        )
+        .bind(id)
+        .bind(&RUNNING_STATUSES[..])
+        .fetch_one(pool)
+        .await
+        .db_op("users.deletion_blockers")
+    }
+
+    // Erases the account but keeps the row, so trades, robots and statements stay for accounting
+    // under a user nobody can identify or sign in as, until the purge job removes it. Broker
+    // connections are deleted, their credentials erased, and API keys, sessions and linked sign-in
+    // accounts go with them. Returns the number of broker connections deleted.
+    pub async fn anonymize(pool: &PgPool, id: Uuid, now: DateTime<Utc>) -> Result<u64> {
+        let mut tx = pool.begin().await.db_op("users.anonymize")?;
+        let connections = BrokerConnection::soft_delete(&mut tx, id, None, now).await?;
+        for table in ["api_keys", "user_sessions", "linked_accounts"] {
+            sqlx::query(&format!("DELETE FROM {} WHERE user_id = $1", table))
+                .bind(id)
+                .execute(&mut *tx)
+                .await
+                .db_op("users.anonymize")?;
+        }
+        sqlx::query(
+            "UPDATE users SET email = $2, password_hash = '', google_id = NULL, is_active = FALSE, is_superuser = FALSE, \
+             role = 'user', subscription_plan = 'free', full_name = NULL, avatar_url = NULL, timezone = NULL, locale = NULL, onboarding_emails = FALSE, share_performance_anonymously = FALSE, \
+             risk_template = NULL, last_login_at = NULL, deleted_at = $3, updated_at = $3 WHERE id = $1",
+        )
+        .bind(id)
+        .bind(Self::deleted_email(id))
+        .bind(now)
+        .execute(&mut *tx)
+        .await
+        .db_op("users.anonymize")?;
+        tx.commit().await.db_op("users.anonymize")?;
+        Ok(connections)
+    }
+
+    // Removes accounts deleted before `before` that have no trades; the others stay anonymized
+    // so their trades are kept for accounting
+    pub async fn purge_deleted(pool: &PgPool, before: DateTime<Utc>) -> Result<u64> {
+        let result = sqlx::query(
+            "DELETE FROM users u WHERE u.deleted_at < $1 AND NOT EXISTS (SELECT 1 FROM trades t WHERE t.user_id = u.id)",
+        )
+            .bind(before)
+            .execute(pool)
+            .await
+            .db_op("users.purge_deleted")?;
+        Ok(result.rows_affected())
+    }
+
+    // Unique like the address it replaces, and never deliverable
+    pub fn deleted_email(id: Uuid) -> String {
+        format!("deleted-{}@{}", id.simple(), DELETED_EMAIL_DOMAIN)
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.email == Self::deleted_email(self.id)
+    }
+
+    // Inactive users are refused at login and on every authenticated request
+    pub async fn set_active(pool: &PgPool, id: Uuid, active: bool) -> Result<bool> {
+        let result = sqlx::query("UPDATE users SET is_active = $2, updated_at = $3 WHERE id = $1")
+            .bind(id)
+            .bind(active)
+            .bind(Utc::now())
+            .execute(pool)
+            .await
+            .db_op("users.set_active")?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn update_last_login(pool: &PgPool, user_id: Uuid) -> Result<()> {
         sqlx::query!(
-            "UPDATE users SET updated_at = NOW() WHERE id = $1",
+            "UPDATE users SET last_login_at = NOW() WHERE id = $1",
             user_id
         )
         .execute(pool)
-        .await?;
+        .await
+        .db_op("users.update_last_login")?;
 
         Ok(())
     }
 
-    pub async fn list_all(pool: &PgPool, limit: i64, offset: i64) -> Result<Vec<User>, sqlx::Error> {
+    pub async fn list_all(pool: &PgPool, limit: i64, offset: i64) -> Result<Vec<User>> {
         let users = sqlx::query_as!(
             User,
-            r#"SELECT id, email, password_hash, is_active, is_superuser, subscription_plan, created_at, updated_at FROM users ORDER BY created_at DESC LIMIT $1 OFFSET $2"#,
+            r#"SELECT id, email, password_hash, is_active, is_superuser, role, subscription_plan, full_name, avatar_url, timezone, locale, created_at, updated_at FROM users WHERE deleted_at IS NULL ORDER BY created_at DESC LIMIT $1 OFFSET $2"#,
             limit,
             offset
         )
         .fetch_all(pool)
-        .await?;
+        .await
+        .db_op("users.list_all")?;
 
         Ok(users)
     }
@@ -135,7 +463,7 @@ impl User {
         pool: &PgPool,
         id: Uuid,
         plan: &str,
-    ) -> Result<(), sqlx::Error> {
+    ) -> Result<()> {
         sqlx::query!(
             "UPDATE users SET subscription_plan = $1, updated_at = $2 WHERE id = $3",
             plan,
@@ -143,21 +471,186 @@ impl User {
             id
         )
         .execute(pool)
-        .await?;
+        .await
+        .db_op("users.update_subscription_plan")?;
 
         Ok(())
     }
+
+    pub async fn risk_template(pool: &PgPool, id: Uuid) -> Result<Option<serde_json::Value>> {
+        let template = sqlx::query_scalar::<_, Option<serde_json::Value>>("SELECT risk_template FROM users WHERE id = $1")
+            .bind(id)
+            .fetch_optional(pool)
+            .await
+            .db_op("users.risk_template")?;
+
+        Ok(template.flatten())
+    }
+
+    pub async fn set_risk_template(pool: &PgPool, id: Uuid, template: Option<&serde_json::Value>) -> Result<()> {
+        sqlx::query("UPDATE users SET risk_template = $1, updated_at = $2 WHERE id = $3")
+            .bind(template)
+            .bind(Utc::now())
+            .bind(id)
+            .execute(pool)
+            .await
+            .db_op("users.set_risk_template")?;
+
+        Ok(())
+    }
+
+    pub async fn shares_performance(pool: &PgPool, id: Uuid) -> Result<bool> {
+        let shares = sqlx::query_scalar("SELECT share_performance_anonymously FROM users WHERE id = $1")
+            .bind(id)
+            .fetch_optional(pool)
+            .await
+            .db_op("users.shares_performance")?;
+
+        Ok(shares.unwrap_or(false))
+    }
+
+    pub async fn set_shares_performance(pool: &PgPool, id: Uuid, share: bool) -> Result<()> {
+        sqlx::query("UPDATE users SET share_performance_anonymously = $1, updated_at = $2 WHERE id = $3")
+            .bind(share)
+            .bind(Utc::now())
+            .bind(id)
+            .execute(pool)
+            .await
+            .db_op("users.set_shares_performance")?;
+
+        Ok(())
+    }
+
+    // Returns false for an unknown token
+    pub async fn unsubscribe_onboarding(pool: &PgPool, token: Uuid) -> Result<bool> {
+        let result = sqlx::query("UPDATE users SET onboarding_emails = FALSE, updated_at = $1 WHERE unsubscribe_token = $2")
+            .bind(Utc::now())
+            .bind(token)
+            .execute(pool)
+            .await
+            .db_op("users.unsubscribe_onboarding")?;
+
+        Ok(result.rows_affected() > 0)
+    }
 }
 
 impl From<User> for UserResponse {
     fn from(user: User) -> Self {
+        let role = user.role();
         UserResponse {
             id: user.id,
             email: user.email,
             is_active: user.is_active,
             is_superuser: user.is_superuser,
+            role,
             subscription_plan: user.subscription_plan,
+            full_name: user.full_name,
+            avatar_url: user.avatar_url,
+            timezone: user.timezone,
+            locale: user.locale,
             created_at: user.created_at,
+            updated_at: user.updated_at,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    // Freezes the user JSON every endpoint returns; a field change here is an API change
+    #[test]
+    fn test_user_response_json() {
+        let user = User {
+            id: Uuid::nil(),
+            email: "ana@example.com".to_string(),
+            password_hash: "secret".to_string(),
+            is_active: true,
+            is_superuser: false,
+            role: "support".to_string(),
+            subscription_plan: "pro".to_string(),
+            full_name: Some("Ana Souza".to_string()),
+            avatar_url: None,
+            timezone: Some("America/Sao_Paulo".to_string()),
+            locale: Some("pt-BR".to_string()),
+            created_at: Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap(),
+            updated_at: Utc.with_ymd_and_hms(2024, 2, 3, 4, 5, 6).unwrap(),
+        };
+        assert_eq!(
+            serde_json::to_value(UserResponse::from(user)).unwrap(),
+            serde_json::json!({
+                "id": "00000000-0000-0000-0000-000000000000",
+                "email": "ana@example.com",
+                "is_active": true,
+                "is_superuser": false,
+                "role": "support",
+                "subscription_plan": "pro",
+                "full_name": "Ana Souza",
+                "avatar_url": null,
+                "timezone": "America/Sao_Paulo",
+                "locale": "pt-BR",
+                "created_at": "2024-01-02T03:04:05Z",
+                "updated_at": "2024-02-03T04:05:06Z"
+            })
+        );
+    }
+
+    #[test]
+    fn test_roles_are_ordered_by_what_they_allow() {
+        assert!(Role::User < Role::Support && Role::Support < Role::Admin);
+        assert_eq!(Role::parse(" Support ").unwrap(), Role::Support);
+        assert!(Role::parse("superuser").is_err());
+
+        let user = User { role: "owner".to_string(), ..User::new("a@example.com".to_string(), String::new()) };
+        assert_eq!(user.role(), Role::User);
+    }
+
+    fn profile(full_name: Option<&str>, avatar_url: Option<&str>, timezone: Option<&str>, locale: Option<&str>) -> UpdateProfileRequest {
+        let owned = |value: Option<&str>| value.map(str::to_string);
+        UpdateProfileRequest {
+            full_name: owned(full_name),
+            avatar_url: owned(avatar_url),
+            timezone: owned(timezone),
+            locale: owned(locale),
         }
     }
+
+    #[test]
+    fn test_profile_updates_only_what_is_sent() {
+        let mut user = User::new("a@example.com".to_string(), String::new());
+        let now = Utc.with_ymd_and_hms(2024, 5, 6, 7, 8, 9).unwrap();
+        profile(Some(" Ana Souza "), Some("https://example.com/ana.png"), Some("America/Sao_Paulo"), Some("pt-BR"))
+            .apply(&mut user, now)
+            .unwrap();
+        assert_eq!(user.full_name.as_deref(), Some("Ana Souza"));
+        assert_eq!(user.updated_at, now);
+
+        // Left out keeps, empty clears
+        profile(None, Some(""), None, Some("en")).apply(&mut user, now).unwrap();
+        assert_eq!(
+            (user.full_name.as_deref(), user.avatar_url.as_deref(), user.timezone.as_deref(), user.locale.as_deref()),
+            (Some("Ana Souza"), None, Some("America/Sao_Paulo"), Some("en"))
+        );
+    }
+
+    #[test]
+    fn test_profile_refuses_invalid_values_and_changes_nothing() {
+        let mut user = User::new("a@example.com".to_string(), String::new());
+        for refused in [
+            profile(Some("Ana"), None, Some("Mars/Olympus_Mons"), None),
+            profile(None, None, Some("UTC+3"), None),
+            profile(None, Some("http://example.com/ana.png"), None, None),
+            profile(None, Some("javascript:alert(1)"), None, None),
+            profile(None, None, None, Some("portuguese")),
+            profile(None, None, None, Some("pt_BR")),
+            profile(Some(&"a".repeat(MAX_FULL_NAME + 1)), None, None, None),
+        ] {
+            assert!(matches!(refused.apply(&mut user, Utc::now()), Err(AppError::Validation(_))));
+        }
+        assert_eq!(user.full_name, None);
+
+        profile(None, None, Some("Asia/Kolkata"), Some("zh-Hant-TW")).apply(&mut user, Utc::now()).unwrap();
+        assert_eq!((user.timezone.as_deref(), user.locale.as_deref()), (Some("Asia/Kolkata"), Some("zh-Hant-TW")));
+    }
 }
@@ -0,0 +1,178 @@
+use chrono::{DateTime, Duration, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+use crate::errors::{DbOp, Result};
+
+// last_seen_at is only written again once it is this old, not on every request
+const LAST_SEEN_RESOLUTION_SECONDS: i64 = 60;
+// Longer user agents are cut, they are only shown to help recognise the device
+const MAX_USER_AGENT_LENGTH: usize = 512;
+
+// A signed-in device: one token issued at sign-in, keyed by its jti
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct UserSession {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub token_version: i64,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct UserSessionResponse {
+    pub id: Uuid,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    // The session the request was made with
+    pub current: bool,
+}
+
+// Trimmed and cut to the column's length; blank agents are dropped
+pub fn clean_user_agent(user_agent: Option<&str>) -> Option<String> {
+    user_agent
+        .map(str::trim)
+        .filter(|agent| !agent.is_empty())
+        .map(|agent| agent.chars().take(MAX_USER_AGENT_LENGTH).collect())
+}
+
+const COLUMNS: &str = "id, user_id, token_version, user_agent, ip_address, created_at, last_seen_at, expires_at, revoked_at";
+
+impl UserSession {
+    pub fn new(
+        id: Uuid,
+        user_id: Uuid,
+        token_version: u32,
+        user_agent: Option<&str>,
+        ip_address: Option<String>,
+        now: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> Self {
+        UserSession {
+            id,
+            user_id,
+            token_version: i64::from(token_version),
+            user_agent: clean_user_agent(user_agent),
+            ip_address,
+            created_at: now,
+            last_seen_at: now,
+            expires_at,
+            revoked_at: None,
+        }
+    }
+
+    // Seconds until the session's token expires by itself, how long a revocation has to be kept
+    pub fn remaining_seconds(&self, now: DateTime<Utc>) -> u64 {
+        (self.expires_at - now).num_seconds().max(0) as u64
+    }
+
+    pub fn to_response(&self, current_id: Option<Uuid>) -> UserSessionResponse {
+        UserSessionResponse {
+            id: self.id,
+            user_agent: self.user_agent.clone(),
+            ip_address: self.ip_address.clone(),
+            created_at: self.created_at,
+            last_seen_at: self.last_seen_at,
+            expires_at: self.expires_at,
+            current: current_id == Some(self.id),
+        }
+    }
+
+    pub async fn create(pool: &PgPool, session: &UserSession) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO user_sessions (id, user_id, token_version, user_agent, ip_address, created_at, last_seen_at, expires_at) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+        )
+        .bind(session.id)
+        .bind(session.user_id)
+        .bind(session.token_version)
+        .bind(&session.user_agent)
+        .bind(&session.ip_address)
+        .bind(session.created_at)
+        .bind(session.last_seen_at)
+        .bind(session.expires_at)
+        .execute(pool)
+        .await
+        .db_op("user_sessions.create")?;
+        Ok(())
+    }
+
+    // Sessions whose token still works: not revoked, not expired and issued at the user's current
+    // token version or later. Most recently used first.
+    pub async fn find_active(pool: &PgPool, user_id: Uuid, token_version: u32, now: DateTime<Utc>) -> Result<Vec<UserSession>> {
+        sqlx::query_as::<_, UserSession>(&format!(
+            "SELECT {} FROM user_sessions \
+             WHERE user_id = $1 AND revoked_at IS NULL AND expires_at > $2 AND token_version >= $3 \
+             ORDER BY last_seen_at DESC, created_at DESC",
+            COLUMNS
+        ))
+        .bind(user_id)
+        .bind(now)
+        .bind(i64::from(token_version))
+        .fetch_all(pool)
+        .await
+        .db_op("user_sessions.find_active")
+    }
+
+    // None when the user has no such session that is neither revoked nor expired
+    pub async fn find_open(pool: &PgPool, user_id: Uuid, id: Uuid, now: DateTime<Utc>) -> Result<Option<UserSession>> {
+        sqlx::query_as::<_, UserSession>(&format!(
+            "SELECT {} FROM user_sessions WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL AND expires_at > $3",
+            COLUMNS
+        ))
+        .bind(id)
+        .bind(user_id)
+        .bind(now)
+        .fetch_optional(pool)
+        .await
+        .db_op("user_sessions.find_open")
+    }
+
+    pub async fn revoke(pool: &PgPool, user_id: Uuid, id: Uuid, now: DateTime<Utc>) -> Result<bool> {
+        let result = sqlx::query("UPDATE user_sessions SET revoked_at = $3 WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL")
+            .bind(id)
+            .bind(user_id)
+            .bind(now)
+            .execute(pool)
+            .await
+            .db_op("user_sessions.revoke")?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn touch(pool: &PgPool, id: Uuid, now: DateTime<Utc>) -> Result<()> {
+        sqlx::query("UPDATE user_sessions SET last_seen_at = $2 WHERE id = $1 AND last_seen_at < $3")
+            .bind(id)
+            .bind(now)
+            .bind(now - Duration::seconds(LAST_SEEN_RESOLUTION_SECONDS))
+            .execute(pool)
+            .await
+            .db_op("user_sessions.touch")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_user_agent_is_trimmed_and_capped() {
+        let now = Utc::now();
+        let long = "a".repeat(MAX_USER_AGENT_LENGTH + 10);
+        let session = UserSession::new(Uuid::new_v4(), Uuid::new_v4(), 0, Some(&long), None, now, now + Duration::hours(1));
+        assert_eq!(session.user_agent.map(|agent| agent.len()), Some(MAX_USER_AGENT_LENGTH));
+        let session = UserSession::new(Uuid::new_v4(), Uuid::new_v4(), 0, Some("  "), None, now, now + Duration::hours(1));
+        assert_eq!(session.user_agent, None);
+        assert_eq!(session.remaining_seconds(now), 3600);
+        assert_eq!(session.remaining_seconds(now + Duration::hours(2)), 0);
+    }
+}
@@ -0,0 +1,60 @@
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::errors::{DbOp, Result};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct WatchlistResponse {
+    // In display order
+    pub symbols: Vec<String>,
+    // -1 means unlimited
+    pub max_symbols: i32,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct AddWatchlistSymbolRequest {
+    pub symbol: String,
+}
+
+// The full list in its new order; symbols left out are removed
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct ReplaceWatchlistRequest {
+    pub symbols: Vec<String>,
+}
+
+pub struct Watchlist;
+
+impl Watchlist {
+    pub async fn find_symbols(pool: &PgPool, user_id: Uuid) -> Result<Vec<String>> {
+        sqlx::query_scalar("SELECT symbol FROM watchlist_symbols WHERE user_id = $1 ORDER BY position, symbol")
+            .bind(user_id)
+            .fetch_all(pool)
+            .await
+            .db_op("watchlist_symbols.find_symbols")
+    }
+
+    pub async fn replace(pool: &PgPool, user_id: Uuid, symbols: &[String]) -> Result<()> {
+        let mut tx = pool.begin().await.db_op("watchlist_symbols.replace")?;
+
+        sqlx::query("DELETE FROM watchlist_symbols WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await
+            .db_op("watchlist_symbols.replace")?;
+
+        for (position, symbol) in symbols.iter().enumerate() {
+            sqlx::query("INSERT INTO watchlist_symbols (user_id, symbol, position) VALUES ($1, $2, $3)")
+                .bind(user_id)
+                .bind(symbol)
+                .bind(position as i32)
+                .execute(&mut *tx)
+                .await
+                .db_op("watchlist_symbols.replace")?;
+        }
+
+        tx.commit().await.db_op("watchlist_symbols.replace")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,224 @@
+use serde::Serializer;
+
+// Rounding happens only when values leave the system (JSON, emails, exports);
+// everything upstream keeps full f64 precision.
+pub const AMOUNT_DECIMALS: i32 = 2;
+pub const PRICE_DECIMALS: i32 = 5;
+pub const PERCENT_DECIMALS: i32 = 2;
+
+// Scaled values are snapped to this many extra digits first, so 1.005 (stored as
+// 1.00499999...) is treated as the half it was meant to be
+const REPRESENTATION_DIGITS: i32 = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rounding {
+    // Half away from zero, for single amounts and prices
+    HalfUp,
+    // Half to even, for sums and averages so repeated halves do not drift one way
+    HalfEven,
+}
+
+pub fn round(value: f64, decimals: i32, rounding: Rounding) -> f64 {
+    if !value.is_finite() {
+        return value;
+    }
+
+    let factor = 10f64.powi(decimals);
+    let snap = 10f64.powi(REPRESENTATION_DIGITS);
+    let scaled = (value * factor * snap).round() / snap;
+
+    let rounded = match rounding {
+        Rounding::HalfUp => scaled.round(),
+        Rounding::HalfEven => {
+            let floor = scaled.floor();
+            let diff = scaled - floor;
+            if (diff - 0.5).abs() < 1.0 / snap {
+                if floor % 2.0 == 0.0 {
+                    floor
+                } else {
+                    floor + 1.0
+                }
+            } else {
+                scaled.round()
+            }
+        }
+    };
+
+    // Avoid serializing -0.0
+    (rounded / factor) + 0.0
+}
+
+// Account currency amounts: profit of one trade, commission, balance
+pub fn round_amount(value: f64) -> f64 {
+    round(value, AMOUNT_DECIMALS, Rounding::HalfUp)
+}
+
+pub fn round_price(value: f64) -> f64 {
+    round(value, PRICE_DECIMALS, Rounding::HalfUp)
+}
+
+// Totals and averages over many trades
+pub fn round_aggregate(value: f64) -> f64 {
+    round(value, AMOUNT_DECIMALS, Rounding::HalfEven)
+}
+
+pub fn round_percent(value: f64) -> f64 {
+    round(value, PERCENT_DECIMALS, Rounding::HalfUp)
+}
+
+pub fn format_amount(value: f64, currency: &str) -> String {
+    format!("{:.2} {}", round_amount(value), currency)
+}
+
+pub fn format_price(value: f64) -> String {
+    format!("{:.5}", round_price(value))
+}
+
+pub fn serialize_amount<S: Serializer>(value: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(round_amount(*value))
+}
+
+pub fn serialize_opt_amount<S: Serializer>(value: &Option<f64>, serializer: S) -> Result<S::Ok, S::Error> {
+    match value {
+        Some(v) => serializer.serialize_some(&round_amount(*v)),
+        None => serializer.serialize_none(),
+    }
+}
+
+pub fn serialize_price<S: Serializer>(value: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(round_price(*value))
+}
+
+pub fn serialize_opt_price<S: Serializer>(value: &Option<f64>, serializer: S) -> Result<S::Ok, S::Error> {
+    match value {
+        Some(v) => serializer.serialize_some(&round_price(*v)),
+        None => serializer.serialize_none(),
+    }
+}
+
+pub fn serialize_aggregate<S: Serializer>(value: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(round_aggregate(*value))
+}
+
+pub fn serialize_aggregate_series<S: Serializer>(values: &[f64], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(values.iter().map(|v| round_aggregate(*v)))
+}
+
+pub fn serialize_percent_series<S: Serializer>(values: &[f64], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(values.iter().map(|v| round_percent(*v)))
+}
+
+pub fn serialize_percent<S: Serializer>(value: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(round_percent(*value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Serialize;
+
+    // Small deterministic generator so the property checks are reproducible without extra crates
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next_f64(&mut self) -> f64 {
+            self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (self.0 >> 11) as f64 / (1u64 << 53) as f64
+        }
+    }
+
+    #[test]
+    fn test_float_artifacts_are_removed() {
+        assert_eq!(round_amount(10.200000000000001), 10.2);
+        assert_eq!(round_amount(0.1 + 0.2), 0.3);
+        assert_eq!(round_amount(1.005), 1.01);
+        assert_eq!(round_amount(-1.005), -1.01);
+        assert_eq!(round_price(1.100015), 1.10002);
+        assert_eq!(round_amount(-0.001).to_string(), "0");
+    }
+
+    #[test]
+    fn test_aggregates_use_bankers_rounding() {
+        assert_eq!(round_aggregate(0.125), 0.12);
+        assert_eq!(round_aggregate(0.135), 0.14);
+        assert_eq!(round_aggregate(-0.125), -0.12);
+        assert_eq!(round_aggregate(2.5551), 2.56);
+    }
+
+    #[test]
+    fn test_sum_of_rounded_profits_tracks_rounded_total() {
+        let mut rng = Lcg(42);
+
+        for _ in 0..500 {
+            let count = 1 + (rng.next_f64() * 40.0) as usize;
+            let profits: Vec<f64> = (0..count).map(|_| (rng.next_f64() - 0.5) * 2_000.0).collect();
+
+            let total = round_aggregate(profits.iter().sum());
+            let sum_of_rounded: f64 = profits.iter().map(|p| round_amount(*p)).sum();
+
+            // Each per-trade rounding moves at most half a cent
+            let tolerance = 0.005 * count as f64 + 0.01;
+            assert!(
+                (round_aggregate(sum_of_rounded) - total).abs() <= tolerance,
+                "{} trades: {} vs {}",
+                count,
+                sum_of_rounded,
+                total
+            );
+        }
+    }
+
+    #[test]
+    fn test_rounding_is_idempotent() {
+        let mut rng = Lcg(7);
+        for _ in 0..1_000 {
+            let value = (rng.next_f64() - 0.5) * 100_000.0;
+            assert_eq!(round_amount(round_amount(value)), round_amount(value));
+            assert_eq!(round_price(round_price(value)), round_price(value));
+            assert_eq!(round_aggregate(round_aggregate(value)), round_aggregate(value));
+        }
+    }
+
+    #[derive(Serialize)]
+    struct Sample {
+        #[serde(serialize_with = "serialize_amount")]
+        profit: f64,
+        #[serde(serialize_with = "serialize_opt_amount")]
+        commission: Option<f64>,
+        #[serde(serialize_with = "serialize_opt_price")]
+        exit_price: Option<f64>,
+        #[serde(serialize_with = "serialize_price")]
+        entry_price: f64,
+        #[serde(serialize_with = "serialize_aggregate")]
+        total_profit: f64,
+        #[serde(serialize_with = "serialize_percent")]
+        win_rate: f64,
+        #[serde(serialize_with = "serialize_aggregate_series")]
+        series: Vec<f64>,
+    }
+
+    #[test]
+    fn test_json_snapshot() {
+        let sample = Sample {
+            profit: 10.200000000000001,
+            commission: None,
+            exit_price: Some(1.0845299999999),
+            entry_price: 1.08,
+            total_profit: -1234.565,
+            win_rate: 66.66666666666667,
+            series: vec![0.1 + 0.2, 0.0, -7.41567],
+        };
+
+        assert_eq!(
+            serde_json::to_string(&sample).unwrap(),
+            r#"{"profit":10.2,"commission":null,"exit_price":1.08453,"entry_price":1.08,"total_profit":-1234.56,"win_rate":66.67,"series":[0.3,0.0,-7.42]}"#
+        );
+    }
+
+    #[test]
+    fn test_formatting_for_emails() {
+        assert_eq!(format_amount(10.200000000000001, "USD"), "10.20 USD");
+        assert_eq!(format_amount(-3.5, "EUR"), "-3.50 EUR");
+        assert_eq!(format_price(1.1), "1.10000");
+    }
+}
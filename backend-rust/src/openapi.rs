@@ -0,0 +1,621 @@
+use schemars::{
+    gen::{SchemaGenerator, SchemaSettings},
+    schema::Schema,
+    JsonSchema,
+};
+use serde_json::{json, Map, Value};
+use uuid::Uuid;
+
+use crate::{
+    errors::FieldError,
+    handlers::{admin, auth, brokers::SnapshotsQuery, dashboard, exposure, public, quotes, robots, statements, trades, users},
+    models::{
+        AcceptDelegationRequest, AccountSnapshot, ApiKeyResponse, AuthEvent, CreateApiKeyRequest, CreatedApiKeyResponse, AddWatchlistSymbolRequest, BridgeTokenResponse, BrokerConnectionResponse, CreateBrokerConnectionRequest, CreateFilterPresetRequest,
+        CreateDelegationRequest, CreateIncidentRequest, DeleteAccountRequest, IncidentResponse, IncidentUpdateRequest, MaintenanceNotice, BrokerMaintenance, BrokerMaintenanceRequest, RuntimeSettings, RuntimeSettingsPatch, CreateSubscriptionRequest, CreateTradingRobotRequest, DelegationResponse, FeatureFlag, FilterPresetResponse, IntegrityRun, Job, PlatformStatsDay,
+        LeaderboardSharing, PublicLeaderboard, RiskTemplate, RobotChange, RobotPreflight, UserLeaderboard, SubscriptionResponse, TestConnectionResponse, TradeOrigin, TradeResponse, TradeStatistics, TradingRobotResponse,
+        PendingReview, ReplaceWatchlistRequest, Statement, StatsExportSettings, SubmitTradeReviewRequest, TradeReview, UpdateAllocationRequest, UpdateBrokerCredentialsRequest, UpdateFeatureFlagRequest, UpdateTradingRobotRequest, UpdateUserPlanRequest, UpdateUserRoleRequest,
+        UpdateProfileRequest, UserResponse, UserSessionResponse, WatchlistResponse, ActivateTemplateRequest, MessageTemplate, PreviewTemplateRequest, RenderedTemplate, SaveTemplateRequest,
+    },
+    pagination::PageQuery,
+    services::{
+        activation_nudges::PlannedNudge,
+        ai_quality::AiQualityReport,
+        bridge_events::{BridgeEvent, BridgeEventOutcome},
+        checkout_service::{CheckoutSessionResponse, CreateCheckoutSessionRequest},
+        credential_vault::RotationStatus,
+        dashboard_service::Sparklines,
+        exposure::{ExposureByConnection, NetExposure},
+        password_policy::PasswordPolicy,
+        public_stats::PublicStatsResponse,
+        quote_service::{FloatingTrade, SourcedQuote},
+        risk_calculator::{RiskCalculation, RiskCalculatorRequest},
+        signal_stability::RobotSignalHistory,
+        strategy_optimizer::{OptimizationJob, OptimizeRobotRequest},
+        system_status::PublicStatus,
+        trade_close_service::{CloseBatchRequest, CloseBatchResponse},
+        trade_pages::TradePage,
+        trade_search::TradeSearchResult,
+        websocket_manager::WebSocketMessage,
+        ws_protocol::BatchEnvelope,
+    },
+};
+
+type SchemaFn = fn(&mut SchemaGenerator) -> Schema;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Access {
+    Public,
+    // A token or an API key
+    User,
+    // A token only; API keys are refused
+    Session,
+    Admin,
+}
+
+struct Operation {
+    method: &'static str,
+    path: &'static str,
+    access: Access,
+    path_params: Vec<(&'static str, SchemaFn)>,
+    query: Option<SchemaFn>,
+    body: Option<SchemaFn>,
+    body_required: bool,
+    status: u16,
+    response: Option<SchemaFn>,
+}
+
+impl Operation {
+    fn new(method: &'static str, path: &'static str, access: Access) -> Self {
+        Operation {
+            method,
+            path,
+            access,
+            path_params: Vec::new(),
+            query: None,
+            body: None,
+            body_required: true,
+            status: 200,
+            response: None,
+        }
+    }
+
+    fn get(path: &'static str, access: Access) -> Self {
+        Self::new("get", path, access)
+    }
+
+    fn post(path: &'static str, access: Access) -> Self {
+        Self::new("post", path, access)
+    }
+
+    fn put(path: &'static str, access: Access) -> Self {
+        Self::new("put", path, access)
+    }
+
+    fn patch(path: &'static str, access: Access) -> Self {
+        Self::new("patch", path, access)
+    }
+
+    fn delete(path: &'static str, access: Access) -> Self {
+        Self::new("delete", path, access)
+    }
+
+    fn path_param<T: JsonSchema>(mut self, name: &'static str) -> Self {
+        self.path_params.push((name, |gen| gen.subschema_for::<T>()));
+        self
+    }
+
+    // Every field of T becomes a query parameter, so T must be a plain struct
+    fn query<T: JsonSchema>(mut self) -> Self {
+        self.query = Some(T::json_schema);
+        self
+    }
+
+    fn body<T: JsonSchema>(mut self) -> Self {
+        self.body = Some(|gen| gen.subschema_for::<T>());
+        self
+    }
+
+    fn optional_body<T: JsonSchema>(mut self) -> Self {
+        self.body_required = false;
+        self.body::<T>()
+    }
+
+    fn returns<T: JsonSchema>(mut self) -> Self {
+        self.response = Some(|gen| gen.subschema_for::<T>());
+        self
+    }
+
+    fn status(mut self, status: u16) -> Self {
+        self.status = status;
+        self
+    }
+}
+
+// Mirrors the routes registered in create_app; add new endpoints here as well
+fn operations() -> Vec<Operation> {
+    use Access::*;
+
+    vec![
+        Operation::get("/health", Public).returns::<Value>(),
+        Operation::get("/ready", Public).returns::<Value>(),
+        Operation::get("/metrics", Public).returns::<String>(),
+        Operation::get("/api/v1/openapi.json", Public).returns::<Value>(),
+        Operation::post("/api/v1/auth/register", Public).body::<auth::CreateUserRequest>().returns::<auth::LoginResponse>(),
+        Operation::post("/api/v1/auth/login", Public).body::<auth::LoginRequest>().returns::<auth::LoginResponse>(),
+        Operation::post("/api/v1/auth/google", Public).body::<auth::OAuthLoginRequest>().returns::<auth::LoginResponse>(),
+        Operation::post("/api/v1/auth/oauth/:provider", Public)
+            .path_param::<String>("provider")
+            .body::<auth::OAuthLoginRequest>()
+            .returns::<auth::LoginResponse>(),
+        Operation::get("/api/v1/auth/password-policy", Public).returns::<PasswordPolicy>(),
+        Operation::post("/api/v1/auth/password-reset/request", Public).body::<auth::PasswordResetRequest>().status(202),
+        Operation::post("/api/v1/auth/password-reset/confirm", Public).body::<auth::PasswordResetConfirmRequest>().status(204),
+        Operation::get("/api/v1/public/stats", Public).returns::<PublicStatsResponse>(),
+        Operation::get("/api/v1/public/status", Public).returns::<PublicStatus>(),
+        Operation::get("/api/v1/public/leaderboard", Public).returns::<PublicLeaderboard>(),
+        Operation::get("/api/v1/public/unsubscribe", Public).query::<public::UnsubscribeQuery>().returns::<Value>(),
+        Operation::post("/api/v1/webhooks/stripe", Public).returns::<Value>(),
+        Operation::post("/api/v1/bridge/events", Public).body::<BridgeEvent>().returns::<BridgeEventOutcome>(),
+        Operation::get("/api/v1/auth/me", User).returns::<UserResponse>(),
+        Operation::put("/api/v1/auth/password", User).body::<auth::ChangePasswordRequest>().status(204),
+        Operation::post("/api/v1/auth/change-password", User)
+            .body::<auth::ChangePasswordRequest>()
+            .returns::<auth::ChangePasswordResponse>(),
+        Operation::post("/api/v1/auth/logout", User).status(204),
+        Operation::post("/api/v1/auth/logout-all", User).status(204),
+        Operation::get("/api/v1/auth/sessions", Session).returns::<Vec<UserSessionResponse>>(),
+        Operation::delete("/api/v1/auth/sessions/:id", Session).path_param::<Uuid>("id").status(204),
+        Operation::get("/api/v1/users", User).query::<users::ListUsersQuery>().returns::<Vec<UserResponse>>(),
+        Operation::get("/api/v1/users/:id", User).path_param::<Uuid>("id").returns::<UserResponse>(),
+        Operation::delete("/api/v1/users/me", Session).body::<DeleteAccountRequest>().status(204),
+        Operation::patch("/api/v1/users/me", User).body::<UpdateProfileRequest>().returns::<UserResponse>(),
+        Operation::get("/api/v1/users/me/risk-template", User).returns::<RiskTemplate>(),
+        Operation::put("/api/v1/users/me/risk-template", User).body::<RiskTemplate>().returns::<RiskTemplate>(),
+        Operation::get("/api/v1/users/me/leaderboard-sharing", User).returns::<LeaderboardSharing>(),
+        Operation::put("/api/v1/users/me/leaderboard-sharing", User).body::<LeaderboardSharing>().returns::<LeaderboardSharing>(),
+        Operation::get("/api/v1/users/me/security-events", Session).query::<users::SecurityEventsQuery>().returns::<Vec<AuthEvent>>(),
+        Operation::get("/api/v1/leaderboard", User).returns::<UserLeaderboard>(),
+        Operation::get("/api/v1/users/me/delegates", User).returns::<Vec<DelegationResponse>>(),
+        Operation::post("/api/v1/users/me/delegates", User).body::<CreateDelegationRequest>().returns::<DelegationResponse>(),
+        Operation::delete("/api/v1/users/me/delegates/:id", User).path_param::<Uuid>("id").status(204),
+        Operation::get("/api/v1/delegations", User).returns::<Vec<DelegationResponse>>(),
+        Operation::post("/api/v1/delegations/accept", User).body::<AcceptDelegationRequest>().returns::<DelegationResponse>(),
+        Operation::get("/api/v1/api-keys", Session).returns::<Vec<ApiKeyResponse>>(),
+        Operation::post("/api/v1/api-keys", Session).body::<CreateApiKeyRequest>().returns::<CreatedApiKeyResponse>().status(201),
+        Operation::delete("/api/v1/api-keys/:id", Session).path_param::<Uuid>("id").status(204),
+        Operation::get("/api/v1/watchlist", User).returns::<WatchlistResponse>(),
+        Operation::post("/api/v1/watchlist", User).body::<AddWatchlistSymbolRequest>().returns::<WatchlistResponse>(),
+        Operation::put("/api/v1/watchlist", User).body::<ReplaceWatchlistRequest>().returns::<WatchlistResponse>(),
+        Operation::delete("/api/v1/watchlist/:symbol", User).path_param::<String>("symbol").returns::<WatchlistResponse>(),
+        Operation::get("/api/v1/quotes", User).query::<quotes::QuotesQuery>().returns::<Vec<SourcedQuote>>(),
+        Operation::post("/api/v1/tools/risk-calculator", User).body::<RiskCalculatorRequest>().returns::<RiskCalculation>(),
+        Operation::get("/api/v1/subscriptions", User).returns::<Option<SubscriptionResponse>>(),
+        Operation::post("/api/v1/subscriptions", User).body::<CreateSubscriptionRequest>().returns::<SubscriptionResponse>(),
+        Operation::post("/api/v1/subscriptions/trial", User).returns::<SubscriptionResponse>(),
+        Operation::post("/api/v1/subscriptions/checkout-session", User)
+            .body::<CreateCheckoutSessionRequest>()
+            .returns::<CheckoutSessionResponse>(),
+        Operation::post("/api/v1/subscriptions/checkout-session/:id/complete", User)
+            .path_param::<String>("id")
+            .returns::<Value>(),
+        Operation::get("/api/v1/brokers", User).query::<PageQuery>().returns::<Vec<BrokerConnectionResponse>>(),
+        Operation::post("/api/v1/brokers", User).body::<CreateBrokerConnectionRequest>().returns::<BrokerConnectionResponse>(),
+        Operation::delete("/api/v1/brokers/:id", User).path_param::<Uuid>("id").status(204),
+        Operation::post("/api/v1/brokers/:id/test", User).path_param::<Uuid>("id").returns::<TestConnectionResponse>(),
+        Operation::put("/api/v1/brokers/:id/credentials", User)
+            .path_param::<Uuid>("id")
+            .body::<UpdateBrokerCredentialsRequest>()
+            .returns::<BrokerConnectionResponse>(),
+        Operation::get("/api/v1/brokers/:id/snapshots", User)
+            .path_param::<Uuid>("id")
+            .query::<SnapshotsQuery>()
+            .returns::<Vec<AccountSnapshot>>(),
+        Operation::post("/api/v1/brokers/:id/bridge-token", User).path_param::<Uuid>("id").returns::<BridgeTokenResponse>(),
+        Operation::get("/api/v1/robots", User).query::<PageQuery>().returns::<Vec<TradingRobotResponse>>(),
+        Operation::post("/api/v1/robots", User).body::<CreateTradingRobotRequest>().returns::<TradingRobotResponse>(),
+        Operation::post("/api/v1/robots/reactivate", User).returns::<Vec<TradingRobotResponse>>(),
+        Operation::patch("/api/v1/robots/:id", User)
+            .path_param::<Uuid>("id")
+            .query::<robots::UpdateRobotQuery>()
+            .body::<UpdateTradingRobotRequest>()
+            .returns::<TradingRobotResponse>(),
+        Operation::delete("/api/v1/robots/:id", User).path_param::<Uuid>("id").status(204),
+        Operation::get("/api/v1/robots/:id/changes", User)
+            .path_param::<Uuid>("id")
+            .query::<robots::RobotChangesQuery>()
+            .returns::<Vec<RobotChange>>(),
+        Operation::get("/api/v1/robots/:id/signals", User)
+            .path_param::<Uuid>("id")
+            .returns::<RobotSignalHistory>(),
+        Operation::get("/api/v1/robots/:id/ai-quality", User)
+            .path_param::<Uuid>("id")
+            .query::<robots::AiQualityQuery>()
+            .returns::<AiQualityReport>(),
+        Operation::post("/api/v1/robots/:id/optimize", User)
+            .path_param::<Uuid>("id")
+            .body::<OptimizeRobotRequest>()
+            .returns::<OptimizationJob>(),
+        Operation::get("/api/v1/optimizations/:job_id", User)
+            .path_param::<Uuid>("job_id")
+            .returns::<OptimizationJob>(),
+        Operation::post("/api/v1/optimizations/:job_id/apply", User)
+            .path_param::<Uuid>("job_id")
+            .returns::<TradingRobotResponse>(),
+        Operation::get("/api/v1/jobs", User).returns::<Vec<Job>>(),
+        Operation::get("/api/v1/jobs/:id", User).path_param::<Uuid>("id").returns::<Job>(),
+        Operation::post("/api/v1/jobs/:id/cancel", User)
+            .path_param::<Uuid>("id")
+            .status(202)
+            .returns::<Job>(),
+        Operation::get("/api/v1/robots/:id/preflight", User)
+            .path_param::<Uuid>("id")
+            .returns::<RobotPreflight>(),
+        Operation::post("/api/v1/robots/:id/start", User)
+            .path_param::<Uuid>("id")
+            .query::<robots::StartRobotQuery>()
+            .returns::<TradingRobotResponse>(),
+        Operation::post("/api/v1/robots/:id/stop", User).path_param::<Uuid>("id").returns::<TradingRobotResponse>(),
+        Operation::put("/api/v1/robots/:id/allocation", User)
+            .path_param::<Uuid>("id")
+            .body::<UpdateAllocationRequest>()
+            .returns::<TradingRobotResponse>(),
+        Operation::get("/api/v1/trades", User).query::<trades::ListTradesQuery>().returns::<TradePage>(),
+        Operation::get("/api/v1/trades/statistics", User).query::<trades::StatisticsQuery>().returns::<TradeStatistics>(),
+        Operation::get("/api/v1/trades/search", User)
+            .query::<trades::SearchTradesQuery>()
+            .returns::<Vec<TradeSearchResult>>(),
+        Operation::get("/api/v1/trades/floating", User).returns::<Vec<FloatingTrade>>(),
+        Operation::get("/api/v1/exposure/by-connection", User).returns::<ExposureByConnection>(),
+        Operation::get("/api/v1/exposure/net", User).query::<exposure::NetExposureQuery>().returns::<NetExposure>(),
+        Operation::post("/api/v1/trades/close-batch", User).body::<CloseBatchRequest>().returns::<CloseBatchResponse>(),
+        Operation::post("/api/v1/trades/:id/reenter", User).path_param::<Uuid>("id").returns::<TradeResponse>(),
+        Operation::get("/api/v1/trades/:id/origin", User).path_param::<Uuid>("id").returns::<TradeOrigin>(),
+        Operation::get("/api/v1/trades/reviews/pending", User)
+            .query::<trades::PendingReviewsQuery>()
+            .returns::<Vec<PendingReview>>(),
+        Operation::put("/api/v1/trades/:id/review", User)
+            .path_param::<Uuid>("id")
+            .body::<SubmitTradeReviewRequest>()
+            .returns::<TradeReview>(),
+        Operation::get("/api/v1/statements/:year/:month", User)
+            .path_param::<i32>("year")
+            .path_param::<u32>("month")
+            .query::<statements::StatementQuery>()
+            .returns::<Statement>(),
+        Operation::get("/api/v1/presets", User).returns::<Vec<FilterPresetResponse>>(),
+        Operation::post("/api/v1/presets", User).body::<CreateFilterPresetRequest>().returns::<FilterPresetResponse>(),
+        Operation::delete("/api/v1/presets/:id", User).path_param::<Uuid>("id").status(204),
+        Operation::get("/api/v1/dashboard", User).query::<dashboard::DashboardQuery>().returns::<dashboard::DashboardData>(),
+        Operation::get("/api/v1/dashboard/sparklines", User)
+            .query::<dashboard::SparklinesQuery>()
+            .returns::<Sparklines>(),
+        Operation::get("/api/v1/admin/users", Admin).query::<admin::AdminUsersQuery>().returns::<admin::AdminUserList>(),
+        Operation::put("/api/v1/admin/users/:id/role", Admin)
+            .path_param::<Uuid>("id")
+            .body::<UpdateUserRoleRequest>()
+            .returns::<UserResponse>(),
+        Operation::put("/api/v1/admin/users/:id/plan", Admin)
+            .path_param::<Uuid>("id")
+            .body::<UpdateUserPlanRequest>()
+            .returns::<admin::AdminPlanChange>(),
+        Operation::post("/api/v1/admin/users/:id/impersonate", Admin)
+            .path_param::<Uuid>("id")
+            .returns::<admin::ImpersonationResponse>()
+            .status(201),
+        Operation::post("/api/v1/admin/users/:id/activate", Admin).path_param::<Uuid>("id").returns::<UserResponse>(),
+        Operation::post("/api/v1/admin/users/:id/deactivate", Admin).path_param::<Uuid>("id").returns::<UserResponse>(),
+        Operation::get("/api/v1/admin/stats", Admin).returns::<admin::SystemStats>(),
+        Operation::get("/api/v1/admin/stats/history", Admin)
+            .query::<admin::StatsHistoryQuery>()
+            .returns::<Vec<PlatformStatsDay>>(),
+        Operation::post("/api/v1/admin/stats/backfill", Admin)
+            .query::<admin::StatsBackfillQuery>()
+            .returns::<admin::StatsBackfillResponse>(),
+        Operation::get("/api/v1/admin/nudges/preview", Admin).returns::<Vec<PlannedNudge>>(),
+        Operation::get("/api/v1/admin/settings/stats-export", Admin).returns::<StatsExportSettings>(),
+        Operation::put("/api/v1/admin/settings/stats-export", Admin)
+            .body::<StatsExportSettings>()
+            .returns::<StatsExportSettings>(),
+        Operation::get("/api/v1/admin/settings/maintenance", Admin).returns::<MaintenanceNotice>(),
+        Operation::put("/api/v1/admin/settings/maintenance", Admin)
+            .body::<MaintenanceNotice>()
+            .returns::<MaintenanceNotice>(),
+        Operation::get("/api/v1/admin/settings/maintenance/brokers", Admin).returns::<BrokerMaintenance>(),
+        Operation::put("/api/v1/admin/settings/maintenance/brokers/:broker_type", Admin)
+            .path_param::<String>("broker_type")
+            .body::<BrokerMaintenanceRequest>()
+            .returns::<BrokerMaintenance>(),
+        Operation::delete("/api/v1/admin/settings/maintenance/brokers/:broker_type", Admin)
+            .path_param::<String>("broker_type")
+            .returns::<BrokerMaintenance>(),
+        Operation::get("/api/v1/admin/settings/runtime", Admin).returns::<RuntimeSettings>(),
+        Operation::patch("/api/v1/admin/settings/runtime", Admin)
+            .body::<RuntimeSettingsPatch>()
+            .returns::<RuntimeSettings>(),
+        Operation::post("/api/v1/admin/incidents", Admin)
+            .body::<CreateIncidentRequest>()
+            .status(201)
+            .returns::<IncidentResponse>(),
+        Operation::post("/api/v1/admin/incidents/:id/updates", Admin)
+            .path_param::<Uuid>("id")
+            .body::<IncidentUpdateRequest>()
+            .returns::<IncidentResponse>(),
+        Operation::get("/api/v1/admin/health", Admin).returns::<admin::AdminHealth>(),
+        Operation::get("/api/v1/admin/security-events", Admin)
+            .query::<admin::AdminSecurityEventsQuery>()
+            .returns::<Vec<AuthEvent>>(),
+        Operation::get("/api/v1/admin/feature-flags", Admin).returns::<Vec<FeatureFlag>>(),
+        Operation::put("/api/v1/admin/feature-flags/:key", Admin)
+            .path_param::<String>("key")
+            .body::<UpdateFeatureFlagRequest>()
+            .returns::<FeatureFlag>(),
+        Operation::post("/api/v1/admin/integrity/recalculate", Admin)
+            .optional_body::<admin::IntegrityScope>()
+            .status(202)
+            .returns::<IntegrityRun>(),
+        Operation::get("/api/v1/admin/integrity/check", Admin)
+            .query::<admin::IntegrityScope>()
+            .status(202)
+            .returns::<IntegrityRun>(),
+        Operation::get("/api/v1/admin/integrity/runs/:id", Admin).path_param::<Uuid>("id").returns::<IntegrityRun>(),
+        Operation::get("/api/v1/admin/trades/:id/origin", Admin).path_param::<Uuid>("id").returns::<TradeOrigin>(),
+        Operation::get("/api/v1/admin/templates", Admin).returns::<Vec<MessageTemplate>>(),
+        Operation::post("/api/v1/admin/templates/:key", Admin)
+            .path_param::<String>("key")
+            .body::<SaveTemplateRequest>()
+            .status(201)
+            .returns::<MessageTemplate>(),
+        Operation::post("/api/v1/admin/templates/:key/activate", Admin)
+            .path_param::<String>("key")
+            .body::<ActivateTemplateRequest>()
+            .returns::<MessageTemplate>(),
+        Operation::post("/api/v1/admin/templates/:key/preview", Admin)
+            .path_param::<String>("key")
+            .body::<PreviewTemplateRequest>()
+            .returns::<RenderedTemplate>(),
+        Operation::post("/api/v1/admin/rotate-encryption", Admin).status(202).returns::<RotationStatus>(),
+        Operation::get("/api/v1/admin/rotate-encryption", Admin).returns::<RotationStatus>(),
+    ]
+}
+
+// `/robots/:id/start` -> `/robots/{id}/start`
+fn openapi_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| match segment.strip_prefix(':') {
+            Some(name) => format!("{{{}}}", name),
+            None => segment.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn to_value(schema: Schema) -> Value {
+    serde_json::to_value(schema).expect("schemas serialize to JSON")
+}
+
+fn query_parameters(gen: &mut SchemaGenerator, schema_fn: SchemaFn) -> Vec<Value> {
+    let Schema::Object(schema) = schema_fn(gen) else {
+        return Vec::new();
+    };
+    let Some(object) = schema.object else {
+        return Vec::new();
+    };
+
+    object
+        .properties
+        .into_iter()
+        .map(|(name, property)| {
+            json!({
+                "name": name,
+                "in": "query",
+                "required": object.required.contains(&name),
+                "schema": to_value(property),
+            })
+        })
+        .collect()
+}
+
+fn operation_value(gen: &mut SchemaGenerator, op: &Operation) -> Value {
+    let mut value = Map::new();
+
+    let mut parameters: Vec<Value> = op
+        .path_params
+        .iter()
+        .map(|(name, schema_fn)| {
+            json!({ "name": name, "in": "path", "required": true, "schema": to_value(schema_fn(gen)) })
+        })
+        .collect();
+    if let Some(query) = op.query {
+        parameters.extend(query_parameters(gen, query));
+    }
+    if !parameters.is_empty() {
+        value.insert("parameters".to_string(), Value::Array(parameters));
+    }
+
+    if let Some(body) = op.body {
+        value.insert(
+            "requestBody".to_string(),
+            json!({
+                "required": op.body_required,
+                "content": { "application/json": { "schema": to_value(body(gen)) } },
+            }),
+        );
+    }
+
+    let success = match op.response {
+        Some(response) => json!({
+            "description": "Success",
+            "content": { "application/json": { "schema": to_value(response(gen)) } },
+        }),
+        None => json!({ "description": "Success" }),
+    };
+    value.insert(
+        "responses".to_string(),
+        json!({
+            (op.status.to_string()): success,
+            "default": { "$ref": "#/components/responses/Error" },
+        }),
+    );
+
+    match op.access {
+        Access::Public => {}
+        Access::User => {
+            value.insert("security".to_string(), json!([{ "bearerAuth": [] }, { "apiKeyAuth": [] }]));
+        }
+        Access::Session | Access::Admin => {
+            value.insert("security".to_string(), json!([{ "bearerAuth": [] }]));
+        }
+    }
+    if op.access == Access::Admin {
+        value.insert("tags".to_string(), json!(["admin"]));
+    }
+
+    Value::Object(value)
+}
+
+pub fn spec() -> Value {
+    let mut gen = SchemaSettings::openapi3().into_generator();
+
+    let mut paths = Map::new();
+    for op in operations() {
+        let entry = paths.entry(openapi_path(op.path)).or_insert_with(|| json!({}));
+        entry[op.method] = operation_value(&mut gen, &op);
+    }
+
+    // Pushed over the WebSocket rather than returned by a route
+    gen.subschema_for::<WebSocketMessage>();
+    gen.subschema_for::<BatchEnvelope>();
+    // Referenced by the shared error response
+    gen.subschema_for::<FieldError>();
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Trading SaaS API",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": {
+            "schemas": gen.take_definitions(),
+            "responses": {
+                "Error": {
+                    "description": "Error",
+                    "content": {
+                        "application/json": {
+                            "schema": {
+                                "type": "object",
+                                "required": ["error", "status"],
+                                "properties": {
+                                    "error": { "type": "string" },
+                                    "status": { "type": "integer", "format": "uint16" },
+                                    // Only on 429s from the job limiter
+                                    "running_job_ids": { "type": "array", "items": { "type": "string", "format": "uuid" } },
+                                    // Only on 400s naming the rules each field broke
+                                    "fields": { "type": "array", "items": { "$ref": "#/components/schemas/FieldError" } },
+                                },
+                            },
+                        },
+                    },
+                },
+            },
+            "securitySchemes": {
+                "bearerAuth": { "type": "http", "scheme": "bearer", "bearerFormat": "JWT" },
+                // Read keys may only GET; trade keys may also write trades and robots
+                "apiKeyAuth": { "type": "apiKey", "in": "header", "name": "X-API-Key" },
+            },
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GOLDEN_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/openapi.json");
+
+    // Fields that change without the API changing
+    fn normalized(mut spec: Value) -> Value {
+        spec["info"]["version"] = json!("0.0.0");
+        spec
+    }
+
+    fn diff(path: &str, expected: &Value, actual: &Value, out: &mut Vec<String>) {
+        match (expected, actual) {
+            (Value::Object(expected), Value::Object(actual)) => {
+                for (key, value) in expected {
+                    let child = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                    match actual.get(key) {
+                        Some(other) => diff(&child, value, other, out),
+                        None => out.push(format!("{}: removed", child)),
+                    }
+                }
+                for key in actual.keys().filter(|key| !expected.contains_key(*key)) {
+                    let child = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                    out.push(format!("{}: added", child));
+                }
+            }
+            (Value::Array(e), Value::Array(a)) if e.len() == a.len() => {
+                for (i, (e, a)) in e.iter().zip(a).enumerate() {
+                    diff(&format!("{}[{}]", path, i), e, a, out);
+                }
+            }
+            _ if expected != actual => out.push(format!("{}: {} -> {}", path, expected, actual)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn test_spec_matches_golden_file() {
+        let golden = std::fs::read_to_string(GOLDEN_PATH).expect("openapi.json is missing, run `cargo openapi`");
+        let expected: Value = serde_json::from_str(&golden).expect("openapi.json is not valid JSON");
+
+        let mut changes = Vec::new();
+        diff("", &normalized(expected), &normalized(spec()), &mut changes);
+
+        assert!(
+            changes.is_empty(),
+            "API shape changed; if intended, run `cargo openapi` and commit openapi.json:\n  {}",
+            changes.join("\n  ")
+        );
+    }
+
+    #[test]
+    fn test_renamed_field_is_reported_by_path() {
+        let expected = spec();
+        let mut actual = expected.clone();
+        let properties = actual["components"]["schemas"]["TradeResponse"]["properties"].as_object_mut().unwrap();
+        let profit_loss = properties.remove("profit_loss").unwrap();
+        properties.insert("pnl".to_string(), profit_loss);
+
+        let mut changes = Vec::new();
+        diff("", &expected, &actual, &mut changes);
+
+        assert_eq!(
+            changes,
+            vec![
+                "components.schemas.TradeResponse.properties.profit_loss: removed",
+                "components.schemas.TradeResponse.properties.pnl: added",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_spec_covers_query_parameters_and_dtos() {
+        let spec = spec();
+
+        assert_eq!(spec["paths"]["/api/v1/robots/{id}/start"]["post"]["parameters"][2]["name"], "force");
+        assert!(spec["paths"]["/api/v1/presets/{id}"]["delete"]["responses"]["204"].is_object());
+        for name in ["DashboardData", "SystemStats", "TestConnectionResponse", "WebSocketMessage"] {
+            assert!(spec["components"]["schemas"][name].is_object(), "{} has no schema", name);
+        }
+    }
+
+    // `cargo openapi` runs this to accept the current shape
+    #[test]
+    #[ignore]
+    fn write_golden() {
+        let mut json = serde_json::to_string_pretty(&normalized(spec())).unwrap();
+        json.push('\n');
+        std::fs::write(GOLDEN_PATH, json).unwrap();
+    }
+}
@@ -0,0 +1,73 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_PAGE_LIMIT: i64 = 50;
+pub const MAX_PAGE_LIMIT: i64 = 100;
+
+// The standard shape of a page of a list. `total` counts everything matching the request's
+// filters, so another page follows while offset + items.len() < total.
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct Paginated<T> {
+    pub items: Vec<T>,
+    pub total: i64,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+impl<T> Paginated<T> {
+    pub fn new(items: Vec<T>, total: i64, page: Page) -> Self {
+        Paginated { items, total, limit: page.limit, offset: page.offset }
+    }
+}
+
+// A request's limit and offset, bounded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Page {
+    pub limit: i64,
+    pub offset: i64,
+}
+
+impl Page {
+    pub fn new(limit: Option<i64>, offset: Option<i64>) -> Self {
+        Page {
+            limit: limit.unwrap_or(DEFAULT_PAGE_LIMIT).clamp(1, MAX_PAGE_LIMIT),
+            offset: offset.unwrap_or(0).max(0),
+        }
+    }
+}
+
+// For lists that answered v1 clients with a bare array of everything and still do unless
+// `envelope=true` asks for a Paginated page
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+pub struct PageQuery {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+    pub envelope: Option<bool>,
+}
+
+impl PageQuery {
+    // None for the v1 bare array
+    pub fn page(&self) -> Option<Page> {
+        self.envelope.unwrap_or(false).then(|| Page::new(self.limit, self.offset))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pages_are_bounded_and_v1_lists_stay_bare() {
+        assert_eq!(Page::new(None, None), Page { limit: DEFAULT_PAGE_LIMIT, offset: 0 });
+        assert_eq!(Page::new(Some(1000), Some(-5)), Page { limit: MAX_PAGE_LIMIT, offset: 0 });
+        assert_eq!(Page::new(Some(0), Some(20)), Page { limit: 1, offset: 20 });
+
+        let page = Paginated::new(vec!["a", "b"], 5, Page::new(Some(2), Some(2)));
+        assert_eq!((page.total, page.limit, page.offset), (5, 2, 2));
+
+        let v1 = PageQuery { limit: Some(10), ..Default::default() };
+        assert_eq!(v1.page(), None);
+        let enveloped = PageQuery { envelope: Some(true), ..v1 };
+        assert_eq!(enveloped.page(), Some(Page { limit: 10, offset: 0 }));
+    }
+}
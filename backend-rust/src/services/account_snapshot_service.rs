@@ -0,0 +1,433 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use sqlx::PgPool;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{
+    errors::Result,
+    models::{AccountInfo, AccountSnapshot, BridgeToken, BrokerConnection, SnapshotGranularity},
+    services::{bridge_events::BRIDGE_ACTIVE_MINUTES, Mt5Service},
+};
+
+pub const SNAPSHOT_INTERVAL_SECONDS: u64 = 3600;
+// Hourly rows are kept this long, then folded into one row per day
+const HOURLY_RETENTION_DAYS: i64 = 7;
+// An older snapshot no longer passes for the current balance
+const SNAPSHOT_STALE_AFTER_MINUTES: i64 = 90;
+
+#[async_trait]
+pub trait SnapshotEnv: Send + Sync {
+    async fn active_connections(&self) -> Result<Vec<BrokerConnection>>;
+    // Connections whose bridge pushed an event since `since`
+    async fn bridge_active_since(&self, since: DateTime<Utc>) -> Result<Vec<Uuid>>;
+    async fn account_info(&self, connection: &BrokerConnection) -> Result<AccountInfo>;
+    async fn save(&self, snapshot: &AccountSnapshot) -> Result<()>;
+    async fn latest(&self, connection_id: Uuid) -> Result<Option<AccountSnapshot>>;
+    async fn hourly_before(&self, before: DateTime<Utc>) -> Result<Vec<AccountSnapshot>>;
+    async fn replace_with_daily(&self, daily: &[AccountSnapshot], hourly_ids: &[Uuid]) -> Result<()>;
+}
+
+pub struct PgSnapshotEnv {
+    pool: PgPool,
+    mt5: Arc<Mt5Service>,
+}
+
+impl PgSnapshotEnv {
+    pub fn new(pool: PgPool, mt5: Arc<Mt5Service>) -> Self {
+        PgSnapshotEnv { pool, mt5 }
+    }
+}
+
+#[async_trait]
+impl SnapshotEnv for PgSnapshotEnv {
+    async fn active_connections(&self) -> Result<Vec<BrokerConnection>> {
+        BrokerConnection::find_active(&self.pool).await
+    }
+
+    async fn bridge_active_since(&self, since: DateTime<Utc>) -> Result<Vec<Uuid>> {
+        BridgeToken::pushed_since(&self.pool, since).await
+    }
+
+    async fn account_info(&self, connection: &BrokerConnection) -> Result<AccountInfo> {
+        let connection_id = connection.id.to_string();
+        if !self.mt5.is_connected(&connection_id) {
+            self.mt5.connect(connection).await?;
+        }
+        self.mt5.get_account_info(&connection_id).await
+    }
+
+    async fn save(&self, snapshot: &AccountSnapshot) -> Result<()> {
+        AccountSnapshot::upsert(&self.pool, snapshot).await
+    }
+
+    async fn latest(&self, connection_id: Uuid) -> Result<Option<AccountSnapshot>> {
+        AccountSnapshot::find_latest(&self.pool, connection_id).await
+    }
+
+    async fn hourly_before(&self, before: DateTime<Utc>) -> Result<Vec<AccountSnapshot>> {
+        AccountSnapshot::find_hourly_before(&self.pool, before).await
+    }
+
+    async fn replace_with_daily(&self, daily: &[AccountSnapshot], hourly_ids: &[Uuid]) -> Result<()> {
+        AccountSnapshot::replace_with_daily(&self.pool, daily, hourly_ids).await
+    }
+}
+
+pub struct AccountSnapshotService;
+
+impl AccountSnapshotService {
+    pub fn hour_start(at: DateTime<Utc>) -> DateTime<Utc> {
+        at.duration_trunc(Duration::hours(1)).expect("an hour divides a day")
+    }
+
+    fn day_start(at: DateTime<Utc>) -> DateTime<Utc> {
+        at.duration_trunc(Duration::days(1)).expect("a day divides a day")
+    }
+
+    // Whole days only, so a day is never compacted in two halves
+    pub fn compaction_cutoff(now: DateTime<Utc>) -> DateTime<Utc> {
+        Self::day_start(now - Duration::days(HOURLY_RETENTION_DAYS))
+    }
+
+    // One daily row per connection and day, holding that day's last hourly values
+    pub fn plan_compaction(hourly: &[AccountSnapshot]) -> (Vec<AccountSnapshot>, Vec<Uuid>) {
+        let mut closing: BTreeMap<(Uuid, DateTime<Utc>), &AccountSnapshot> = BTreeMap::new();
+        for snapshot in hourly {
+            let key = (snapshot.connection_id, Self::day_start(snapshot.captured_at));
+            if closing.get(&key).is_none_or(|last| last.captured_at < snapshot.captured_at) {
+                closing.insert(key, snapshot);
+            }
+        }
+
+        let daily = closing
+            .into_iter()
+            .map(|((_, day), last)| AccountSnapshot {
+                id: Uuid::new_v4(),
+                granularity: SnapshotGranularity::Day.as_str().to_string(),
+                captured_at: day,
+                ..last.clone()
+            })
+            .collect();
+        (daily, hourly.iter().map(|s| s.id).collect())
+    }
+
+    // Scheduler job: snapshot every active connection, then compact old hourly rows.
+    // A connection the broker does not answer for is skipped until the next run.
+    pub async fn run(env: &dyn SnapshotEnv, now: DateTime<Utc>) -> Result<()> {
+        let captured = Self::capture(env, now).await?;
+        let compacted = Self::compact(env, now).await?;
+        tracing::info!("Captured {} account snapshot(s), compacted {} day(s)", captured, compacted);
+        Ok(())
+    }
+
+    // Duplicate connections to one broker account are read once; the oldest reachable one
+    // holds the account's snapshots. Connections whose bridge is pushing account updates are
+    // left to it, so this only backs up a bridge that went quiet.
+    pub async fn capture(env: &dyn SnapshotEnv, now: DateTime<Utc>) -> Result<usize> {
+        let pushing: HashSet<Uuid> =
+            env.bridge_active_since(now - Duration::minutes(BRIDGE_ACTIVE_MINUTES)).await?.into_iter().collect();
+        let mut captured = 0;
+        let mut accounts = HashSet::new();
+        for connection in env.active_connections().await? {
+            let account = connection.account_key().map(|key| (connection.user_id, key));
+            if account.as_ref().is_some_and(|a| accounts.contains(a)) {
+                tracing::debug!("Skipping connection {}: its broker account was already snapshotted", connection.id);
+                continue;
+            }
+            if pushing.contains(&connection.id) {
+                tracing::debug!("Skipping connection {}: its bridge is pushing updates", connection.id);
+                accounts.extend(account);
+                continue;
+            }
+            match env.account_info(&connection).await {
+                Ok(info) => {
+                    let snapshot =
+                        AccountSnapshot::new(&connection, &info, SnapshotGranularity::Hour, Self::hour_start(now));
+                    env.save(&snapshot).await?;
+                    captured += 1;
+                    accounts.extend(account);
+                }
+                Err(e) => tracing::warn!("No account snapshot for connection {}: {}", connection.id, e),
+            }
+        }
+        Ok(captured)
+    }
+
+    // Returns how many daily rows were written
+    pub async fn compact(env: &dyn SnapshotEnv, now: DateTime<Utc>) -> Result<usize> {
+        let hourly = env.hourly_before(Self::compaction_cutoff(now)).await?;
+        if hourly.is_empty() {
+            return Ok(0);
+        }
+
+        let (daily, hourly_ids) = Self::plan_compaction(&hourly);
+        env.replace_with_daily(&daily, &hourly_ids).await?;
+        Ok(daily.len())
+    }
+
+    // The latest snapshot while it is fresh, otherwise a live read (recorded as this hour's
+    // snapshot). If the broker is unreachable a stale snapshot beats nothing.
+    pub async fn current_balance(env: &dyn SnapshotEnv, connection: &BrokerConnection, now: DateTime<Utc>) -> Result<f64> {
+        let latest = env.latest(connection.id).await?;
+        if let Some(snapshot) = &latest {
+            if now - snapshot.captured_at <= Duration::minutes(SNAPSHOT_STALE_AFTER_MINUTES) {
+                return Ok(snapshot.balance);
+            }
+        }
+
+        match env.account_info(connection).await {
+            Ok(info) => {
+                let snapshot = AccountSnapshot::new(connection, &info, SnapshotGranularity::Hour, Self::hour_start(now));
+                env.save(&snapshot).await?;
+                Ok(info.balance)
+            }
+            Err(e) => match latest {
+                Some(snapshot) => {
+                    tracing::warn!("Using a stale balance for connection {}: {}", connection.id, e);
+                    Ok(snapshot.balance)
+                }
+                None => Err(e),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::AppError;
+    use chrono::TimeZone;
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct FakeEnv {
+        connections: Vec<BrokerConnection>,
+        // Connections the broker does not answer for
+        unreachable: Vec<Uuid>,
+        // Connections whose bridge pushed recently
+        pushing: Vec<Uuid>,
+        balance: f64,
+        live_reads: AtomicUsize,
+        snapshots: Mutex<Vec<AccountSnapshot>>,
+    }
+
+    impl FakeEnv {
+        fn new(connections: Vec<BrokerConnection>) -> Self {
+            FakeEnv {
+                connections,
+                unreachable: Vec::new(),
+                pushing: Vec::new(),
+                balance: 5000.0,
+                live_reads: AtomicUsize::new(0),
+                snapshots: Mutex::new(Vec::new()),
+            }
+        }
+
+        fn count(&self, granularity: SnapshotGranularity) -> usize {
+            self.snapshots.lock().unwrap().iter().filter(|s| s.granularity == granularity.as_str()).count()
+        }
+    }
+
+    #[async_trait]
+    impl SnapshotEnv for FakeEnv {
+        async fn active_connections(&self) -> Result<Vec<BrokerConnection>> {
+            Ok(self.connections.clone())
+        }
+
+        async fn bridge_active_since(&self, _since: DateTime<Utc>) -> Result<Vec<Uuid>> {
+            Ok(self.pushing.clone())
+        }
+
+        async fn account_info(&self, connection: &BrokerConnection) -> Result<AccountInfo> {
+            self.live_reads.fetch_add(1, Ordering::SeqCst);
+            if self.unreachable.contains(&connection.id) {
+                return Err(AppError::BrokerUnavailable("timeout".to_string()));
+            }
+            Ok(info(self.balance))
+        }
+
+        async fn save(&self, snapshot: &AccountSnapshot) -> Result<()> {
+            let mut snapshots = self.snapshots.lock().unwrap();
+            snapshots.retain(|s| {
+                (s.connection_id, &s.granularity, s.captured_at) != (snapshot.connection_id, &snapshot.granularity, snapshot.captured_at)
+            });
+            snapshots.push(snapshot.clone());
+            Ok(())
+        }
+
+        async fn latest(&self, connection_id: Uuid) -> Result<Option<AccountSnapshot>> {
+            Ok(self
+                .snapshots
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|s| s.connection_id == connection_id)
+                .max_by_key(|s| s.captured_at)
+                .cloned())
+        }
+
+        async fn hourly_before(&self, before: DateTime<Utc>) -> Result<Vec<AccountSnapshot>> {
+            Ok(self
+                .snapshots
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|s| s.granularity == "hour" && s.captured_at < before)
+                .cloned()
+                .collect())
+        }
+
+        async fn replace_with_daily(&self, daily: &[AccountSnapshot], hourly_ids: &[Uuid]) -> Result<()> {
+            let mut snapshots = self.snapshots.lock().unwrap();
+            snapshots.retain(|s| !hourly_ids.contains(&s.id));
+            snapshots.extend(daily.iter().cloned());
+            Ok(())
+        }
+    }
+
+    fn info(balance: f64) -> AccountInfo {
+        AccountInfo {
+            account_number: "5012345".to_string(),
+            balance,
+            equity: balance,
+            margin: 0.0,
+            free_margin: balance,
+            currency: "USD".to_string(),
+        }
+    }
+
+    fn connection() -> BrokerConnection {
+        BrokerConnection::new(Uuid::new_v4(), "Main".to_string(), "MT5".to_string(), "key".to_string(), "secret".to_string(), None, None, true)
+    }
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 12, 14, 10, 20, 0).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_compaction_keeps_a_week_hourly_and_one_row_per_day_before_that() {
+        let connection = connection();
+        let env = FakeEnv::new(vec![connection.clone()]);
+        // Ten days of hourly snapshots whose balance is the hour index
+        let start = Utc.with_ymd_and_hms(2023, 12, 4, 0, 0, 0).unwrap();
+        let hours = (now() - start).num_hours();
+        for hour in 0..=hours {
+            let snapshot = AccountSnapshot::new(&connection, &info(hour as f64), SnapshotGranularity::Hour, start + Duration::hours(hour));
+            env.save(&snapshot).await.unwrap();
+        }
+
+        let compacted = AccountSnapshotService::compact(&env, now()).await.unwrap();
+
+        // Cutoff is midnight seven days back: Dec 7, so Dec 4-6 become daily rows
+        let cutoff = AccountSnapshotService::compaction_cutoff(now());
+        assert_eq!(cutoff, Utc.with_ymd_and_hms(2023, 12, 7, 0, 0, 0).unwrap());
+        assert_eq!(compacted, 3);
+        assert_eq!(env.count(SnapshotGranularity::Day), 3);
+        assert_eq!(env.count(SnapshotGranularity::Hour), (now() - cutoff).num_hours() as usize + 1);
+
+        let snapshots = env.snapshots.lock().unwrap().clone();
+        assert!(snapshots.iter().all(|s| s.granularity == "day" || s.captured_at >= cutoff));
+        let mut daily: Vec<(DateTime<Utc>, f64)> = snapshots
+            .iter()
+            .filter(|s| s.granularity == "day")
+            .map(|s| (s.captured_at, s.balance))
+            .collect();
+        daily.sort_by_key(|(at, _)| *at);
+        // Each day keeps its closing (23:00) values
+        assert_eq!(
+            daily,
+            vec![(start, 23.0), (start + Duration::days(1), 47.0), (start + Duration::days(2), 71.0)]
+        );
+
+        // Nothing left to compact on the next run
+        assert_eq!(AccountSnapshotService::compact(&env, now()).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_capture_snapshots_each_reachable_connection_for_the_hour() {
+        let reachable = connection();
+        let down = connection();
+        let mut env = FakeEnv::new(vec![reachable.clone(), down.clone()]);
+        env.unreachable = vec![down.id];
+
+        assert_eq!(AccountSnapshotService::capture(&env, now()).await.unwrap(), 1);
+        // A second run in the same hour replaces the snapshot instead of adding one
+        assert_eq!(AccountSnapshotService::capture(&env, now() + Duration::minutes(30)).await.unwrap(), 1);
+
+        let snapshots = env.snapshots.lock().unwrap().clone();
+        assert_eq!(snapshots.len(), 1);
+        assert_eq!(snapshots[0].connection_id, reachable.id);
+        assert_eq!(snapshots[0].captured_at, Utc.with_ymd_and_hms(2023, 12, 14, 10, 0, 0).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_capture_reads_each_broker_account_once_per_sweep() {
+        let user_id = Uuid::new_v4();
+        let account = |server: &str, login: &str| BrokerConnection {
+            user_id,
+            server: Some(server.to_string()),
+            login: Some(login.to_string()),
+            ..connection()
+        };
+        let down = account("Broker-Live", "7001");
+        let original = account("Broker-Live", "7001");
+        let duplicate = account(" broker-live", "7001 ");
+        let other = account("Broker-Live", "7002");
+        // Another user's connection to the same account is theirs to snapshot
+        let someone_else = BrokerConnection { user_id: Uuid::new_v4(), ..account("Broker-Live", "7001") };
+        let mut env = FakeEnv::new(vec![down.clone(), original.clone(), duplicate, other.clone(), someone_else.clone()]);
+        env.unreachable = vec![down.id];
+
+        assert_eq!(AccountSnapshotService::capture(&env, now()).await.unwrap(), 3);
+
+        // The unreachable connection did not use up its account's turn
+        assert_eq!(env.live_reads.load(Ordering::SeqCst), 4);
+        let mut snapshotted: Vec<Uuid> = env.snapshots.lock().unwrap().iter().map(|s| s.connection_id).collect();
+        snapshotted.sort();
+        let mut expected = vec![original.id, other.id, someone_else.id];
+        expected.sort();
+        assert_eq!(snapshotted, expected);
+    }
+
+    #[tokio::test]
+    async fn test_capture_leaves_connections_with_a_pushing_bridge_alone() {
+        let pushed = connection();
+        let polled = connection();
+        let mut env = FakeEnv::new(vec![pushed.clone(), polled.clone()]);
+        env.pushing = vec![pushed.id];
+
+        assert_eq!(AccountSnapshotService::capture(&env, now()).await.unwrap(), 1);
+        assert_eq!(env.live_reads.load(Ordering::SeqCst), 1);
+        assert_eq!(env.snapshots.lock().unwrap()[0].connection_id, polled.id);
+
+        // Once the bridge goes quiet the poll picks the connection up again
+        env.pushing.clear();
+        assert_eq!(AccountSnapshotService::capture(&env, now()).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_current_balance_reads_live_once_the_snapshot_is_stale() {
+        let connection = connection();
+        let mut env = FakeEnv::new(vec![connection.clone()]);
+        env.save(&AccountSnapshot::new(&connection, &info(4200.0), SnapshotGranularity::Hour, now() - Duration::minutes(80)))
+            .await
+            .unwrap();
+
+        // Fresh enough: served from the snapshot
+        assert_eq!(AccountSnapshotService::current_balance(&env, &connection, now()).await.unwrap(), 4200.0);
+        assert_eq!(env.live_reads.load(Ordering::SeqCst), 0);
+
+        // Stale: read live and recorded
+        let later = now() + Duration::minutes(30);
+        assert_eq!(AccountSnapshotService::current_balance(&env, &connection, later).await.unwrap(), 5000.0);
+        assert_eq!(env.live_reads.load(Ordering::SeqCst), 1);
+        assert_eq!(env.latest(connection.id).await.unwrap().unwrap().balance, 5000.0);
+
+        // Stale and the broker is down: the stale snapshot is still better than nothing
+        env.unreachable = vec![connection.id];
+        let much_later = later + Duration::hours(5);
+        assert_eq!(AccountSnapshotService::current_balance(&env, &connection, much_later).await.unwrap(), 5000.0);
+    }
+}
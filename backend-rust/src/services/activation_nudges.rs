@@ -0,0 +1,406 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Datelike, Duration, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use sqlx::PgPool;
+use std::collections::HashSet;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{
+    errors::Result,
+    models::{ActivationNudge, IdleCandidate},
+    services::NotificationService,
+};
+
+pub const DEFAULT_IDLE_ROBOT_DAYS: i64 = 7;
+pub const DEFAULT_NUDGE_MONTHLY_CAP: i64 = 2;
+// Users are nudged about having no active robot once it has lasted this long
+pub const NO_ACTIVE_ROBOT_DAYS: i64 = 14;
+// Shorter than the weekly period so a run that starts late still nudges, but a restart
+// (the scheduler runs jobs at startup) cannot nudge the same user twice in one week
+const MIN_DAYS_BETWEEN_NUDGES: i64 = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum NudgeKind {
+    RobotSilent,
+    RobotNeverStarted,
+    NoActiveRobots,
+}
+
+impl NudgeKind {
+    // Most specific first: when a user is idle in several ways, one run nudges the first
+    pub const PRIORITY: [NudgeKind; 3] = [NudgeKind::RobotSilent, NudgeKind::RobotNeverStarted, NudgeKind::NoActiveRobots];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NudgeKind::RobotSilent => "robot_silent",
+            NudgeKind::RobotNeverStarted => "robot_never_started",
+            NudgeKind::NoActiveRobots => "no_active_robots",
+        }
+    }
+
+    pub fn parse(kind: &str) -> Option<Self> {
+        Self::PRIORITY.into_iter().find(|k| k.as_str() == kind)
+    }
+
+    pub fn subject(&self) -> &'static str {
+        match self {
+            NudgeKind::RobotSilent => "Your robot hasn't traded in a while",
+            NudgeKind::RobotNeverStarted => "Your robot is ready to start",
+            NudgeKind::NoActiveRobots => "None of your robots are running",
+        }
+    }
+
+    pub fn message(&self, robot_name: Option<&str>) -> String {
+        let robot = robot_name.unwrap_or("your robot");
+        match self {
+            NudgeKind::RobotSilent => format!(
+                "{} is running but hasn't sent a signal or opened a trade recently. Check that its broker connection is still active and that its symbols are trading.",
+                robot
+            ),
+            NudgeKind::RobotNeverStarted => format!(
+                "{} was set up but never started. Start it on a demo account to see how it trades before any real money is involved.",
+                robot
+            ),
+            NudgeKind::NoActiveRobots => {
+                "You haven't had a robot running for a couple of weeks. Pick up where you left off, or try one of the strategy presets.".to_string()
+            }
+        }
+    }
+}
+
+// Someone the next run would nudge, and why
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct PlannedNudge {
+    pub user_id: Uuid,
+    pub email: String,
+    pub kind: NudgeKind,
+    pub robot_id: Option<Uuid>,
+    pub robot_name: Option<String>,
+    pub idle_since: DateTime<Utc>,
+    pub nudges_this_month: i64,
+}
+
+#[async_trait]
+pub trait NudgeEnv: Send + Sync {
+    async fn candidates(
+        &self,
+        robot_cutoff: DateTime<Utc>,
+        user_cutoff: DateTime<Utc>,
+        month_start: DateTime<Utc>,
+    ) -> Result<Vec<IdleCandidate>>;
+    // Records the nudge as sent; false if it already was
+    async fn claim(&self, candidate: &IdleCandidate, now: DateTime<Utc>) -> Result<bool>;
+    async fn release(&self, candidate: &IdleCandidate) -> Result<()>;
+    async fn send(&self, candidate: &IdleCandidate, kind: NudgeKind) -> Result<()>;
+}
+
+pub struct PgNudgeEnv {
+    pool: PgPool,
+    notifications: Arc<NotificationService>,
+    public_base_url: String,
+}
+
+impl PgNudgeEnv {
+    pub fn new(pool: PgPool, notifications: Arc<NotificationService>, public_base_url: &str) -> Self {
+        PgNudgeEnv {
+            pool,
+            notifications,
+            public_base_url: public_base_url.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl NudgeEnv for PgNudgeEnv {
+    async fn candidates(
+        &self,
+        robot_cutoff: DateTime<Utc>,
+        user_cutoff: DateTime<Utc>,
+        month_start: DateTime<Utc>,
+    ) -> Result<Vec<IdleCandidate>> {
+        ActivationNudge::find_candidates(&self.pool, robot_cutoff, user_cutoff, month_start).await
+    }
+
+    async fn claim(&self, candidate: &IdleCandidate, now: DateTime<Utc>) -> Result<bool> {
+        ActivationNudge::claim(&self.pool, candidate, now).await
+    }
+
+    async fn release(&self, candidate: &IdleCandidate) -> Result<()> {
+        ActivationNudge::release(&self.pool, candidate).await
+    }
+
+    async fn send(&self, candidate: &IdleCandidate, kind: NudgeKind) -> Result<()> {
+        let unsubscribe_url = format!(
+            "{}/api/v1/public/unsubscribe?token={}",
+            self.public_base_url, candidate.unsubscribe_token
+        );
+        self.notifications
+            .send_onboarding_email(
+                &candidate.email,
+                kind.subject(),
+                &kind.message(candidate.robot_name.as_deref()),
+                &unsubscribe_url,
+            )
+            .await
+    }
+}
+
+// Weekly job nudging users whose robots sit idle. Each idle situation is nudged once, a user
+// gets at most one nudge per run and `monthly_cap` per calendar month.
+pub struct ActivationNudges {
+    idle_robot_days: i64,
+    monthly_cap: i64,
+}
+
+impl ActivationNudges {
+    pub fn new(idle_robot_days: i64, monthly_cap: i64) -> Self {
+        ActivationNudges { idle_robot_days: idle_robot_days.max(1), monthly_cap: monthly_cap.max(0) }
+    }
+
+    pub fn robot_cutoff(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        now - Duration::days(self.idle_robot_days)
+    }
+
+    pub fn user_cutoff(now: DateTime<Utc>) -> DateTime<Utc> {
+        now - Duration::days(NO_ACTIVE_ROBOT_DAYS)
+    }
+
+    fn month_start(now: DateTime<Utc>) -> DateTime<Utc> {
+        now.date_naive()
+            .with_day(1)
+            .expect("the first is a valid day")
+            .and_hms_opt(0, 0, 0)
+            .expect("midnight is a valid time")
+            .and_utc()
+    }
+
+    // Picks at most one nudge per user from the candidates, in NudgeKind::PRIORITY order
+    pub fn plan(&self, candidates: Vec<IdleCandidate>, now: DateTime<Utc>) -> Vec<(IdleCandidate, NudgeKind)> {
+        let mut candidates: Vec<(IdleCandidate, NudgeKind)> = candidates
+            .into_iter()
+            .filter_map(|c| NudgeKind::parse(&c.kind).map(|kind| (c, kind)))
+            .filter(|(c, _)| c.nudges_this_month < self.monthly_cap)
+            .filter(|(c, _)| c.last_nudged_at.is_none_or(|at| now - at >= Duration::days(MIN_DAYS_BETWEEN_NUDGES)))
+            .collect();
+        candidates.sort_by_key(|(c, kind)| {
+            let priority = NudgeKind::PRIORITY.iter().position(|k| k == kind);
+            (c.user_id, priority, c.idle_since)
+        });
+
+        let mut users = HashSet::new();
+        candidates.retain(|(c, _)| users.insert(c.user_id));
+        candidates
+    }
+
+    async fn planned(&self, env: &dyn NudgeEnv, now: DateTime<Utc>) -> Result<Vec<(IdleCandidate, NudgeKind)>> {
+        let candidates = env
+            .candidates(self.robot_cutoff(now), Self::user_cutoff(now), Self::month_start(now))
+            .await?;
+        Ok(self.plan(candidates, now))
+    }
+
+    // What a run at `now` would send, without sending it
+    pub async fn preview(&self, env: &dyn NudgeEnv, now: DateTime<Utc>) -> Result<Vec<PlannedNudge>> {
+        Ok(self
+            .planned(env, now)
+            .await?
+            .into_iter()
+            .map(|(c, kind)| PlannedNudge {
+                user_id: c.user_id,
+                email: c.email,
+                kind,
+                robot_id: c.robot_id,
+                robot_name: c.robot_name,
+                idle_since: c.idle_since,
+                nudges_this_month: c.nudges_this_month,
+            })
+            .collect())
+    }
+
+    // Scheduler job; returns how many nudges went out
+    pub async fn process(&self, env: &dyn NudgeEnv, now: DateTime<Utc>) -> Result<usize> {
+        let mut sent = 0;
+
+        for (candidate, kind) in self.planned(env, now).await? {
+            if !env.claim(&candidate, now).await? {
+                continue;
+            }
+
+            match env.send(&candidate, kind).await {
+                Ok(()) => sent += 1,
+                Err(e) => {
+                    tracing::warn!("Activation nudge {} to user {} failed: {}", kind.as_str(), candidate.user_id, e);
+                    env.release(&candidate).await?;
+                }
+            }
+        }
+
+        Ok(sent)
+    }
+}
+
+impl Default for ActivationNudges {
+    fn default() -> Self {
+        Self::new(DEFAULT_IDLE_ROBOT_DAYS, DEFAULT_NUDGE_MONTHLY_CAP)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::sync::Mutex;
+
+    // An idle situation seeded into the fake, as the candidates query would find it
+    struct Situation {
+        user: &'static str,
+        kind: NudgeKind,
+        robot: Option<Uuid>,
+    }
+
+    // (user, kind, robot, sent_at), like a row of the activation_nudges table
+    type SentNudge = (Uuid, NudgeKind, Option<Uuid>, DateTime<Utc>);
+
+    struct FakeEnv {
+        users: Vec<(&'static str, Uuid)>,
+        situations: Vec<Situation>,
+        nudges: Mutex<Vec<SentNudge>>,
+        outbox: Mutex<Vec<(&'static str, NudgeKind)>>,
+    }
+
+    impl FakeEnv {
+        fn new(situations: Vec<(&'static str, NudgeKind, bool)>) -> Self {
+            let mut users: Vec<(&'static str, Uuid)> = Vec::new();
+            for (user, _, _) in &situations {
+                if !users.iter().any(|(name, _)| name == user) {
+                    users.push((user, Uuid::new_v4()));
+                }
+            }
+            FakeEnv {
+                users,
+                situations: situations
+                    .into_iter()
+                    .map(|(user, kind, has_robot)| Situation { user, kind, robot: has_robot.then(Uuid::new_v4) })
+                    .collect(),
+                nudges: Mutex::new(Vec::new()),
+                outbox: Mutex::new(Vec::new()),
+            }
+        }
+
+        fn user_id(&self, name: &str) -> Uuid {
+            self.users.iter().find(|(n, _)| *n == name).unwrap().1
+        }
+
+        fn sent_to(&self, name: &str) -> Vec<NudgeKind> {
+            self.outbox.lock().unwrap().iter().filter(|(n, _)| *n == name).map(|(_, kind)| *kind).collect()
+        }
+    }
+
+    #[async_trait]
+    impl NudgeEnv for FakeEnv {
+        async fn candidates(&self, _: DateTime<Utc>, _: DateTime<Utc>, month_start: DateTime<Utc>) -> Result<Vec<IdleCandidate>> {
+            let nudges = self.nudges.lock().unwrap();
+            Ok(self
+                .situations
+                .iter()
+                .map(|s| (self.user_id(s.user), s))
+                .filter(|(user_id, s)| !nudges.iter().any(|n| (n.0, n.1, n.2) == (*user_id, s.kind, s.robot)))
+                .map(|(user_id, s)| {
+                    let sent: Vec<DateTime<Utc>> = nudges.iter().filter(|n| n.0 == user_id).map(|n| n.3).collect();
+                    IdleCandidate {
+                        user_id,
+                        email: format!("{}@example.com", s.user),
+                        unsubscribe_token: Uuid::new_v4(),
+                        kind: s.kind.as_str().to_string(),
+                        robot_id: s.robot,
+                        robot_name: s.robot.map(|_| "Scalper".to_string()),
+                        idle_since: Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap(),
+                        nudges_this_month: sent.iter().filter(|at| **at >= month_start).count() as i64,
+                        last_nudged_at: sent.into_iter().max(),
+                    }
+                })
+                .collect())
+        }
+
+        async fn claim(&self, candidate: &IdleCandidate, now: DateTime<Utc>) -> Result<bool> {
+            let kind = NudgeKind::parse(&candidate.kind).unwrap();
+            let mut nudges = self.nudges.lock().unwrap();
+            if nudges.iter().any(|n| (n.0, n.1, n.2) == (candidate.user_id, kind, candidate.robot_id)) {
+                return Ok(false);
+            }
+            nudges.push((candidate.user_id, kind, candidate.robot_id, now));
+            Ok(true)
+        }
+
+        async fn release(&self, candidate: &IdleCandidate) -> Result<()> {
+            let kind = NudgeKind::parse(&candidate.kind).unwrap();
+            self.nudges.lock().unwrap().retain(|n| (n.0, n.1, n.2) != (candidate.user_id, kind, candidate.robot_id));
+            Ok(())
+        }
+
+        async fn send(&self, candidate: &IdleCandidate, kind: NudgeKind) -> Result<()> {
+            let name = self.users.iter().find(|(_, id)| *id == candidate.user_id).unwrap().0;
+            self.outbox.lock().unwrap().push((name, kind));
+            Ok(())
+        }
+    }
+
+    // Weekly runs, plus a restart right after each one
+    async fn run_weeks(env: &FakeEnv, nudges: &ActivationNudges, start: DateTime<Utc>, weeks: i64) -> usize {
+        let mut sent = 0;
+        for week in 0..weeks {
+            let now = start + Duration::weeks(week);
+            sent += nudges.process(env, now).await.unwrap();
+            sent += nudges.process(env, now + Duration::hours(1)).await.unwrap();
+        }
+        sent
+    }
+
+    #[tokio::test]
+    async fn test_each_idle_scenario_is_nudged_exactly_once() {
+        use NudgeKind::*;
+
+        let env = FakeEnv::new(vec![
+            ("never_started", RobotNeverStarted, true),
+            ("silent", RobotSilent, true),
+            ("no_active_robots", NoActiveRobots, false),
+        ]);
+        let start = Utc.with_ymd_and_hms(2024, 3, 4, 9, 0, 0).unwrap();
+
+        let sent = run_weeks(&env, &ActivationNudges::default(), start, 4).await;
+
+        assert_eq!(sent, 3);
+        assert_eq!(env.sent_to("never_started"), vec![RobotNeverStarted]);
+        assert_eq!(env.sent_to("silent"), vec![RobotSilent]);
+        assert_eq!(env.sent_to("no_active_robots"), vec![NoActiveRobots]);
+    }
+
+    #[tokio::test]
+    async fn test_monthly_cap_holds_remaining_nudges_until_next_month() {
+        use NudgeKind::*;
+
+        let env = FakeEnv::new(vec![
+            ("busy", NoActiveRobots, false),
+            ("busy", RobotNeverStarted, true),
+            ("busy", RobotNeverStarted, true),
+            ("busy", RobotSilent, true),
+        ]);
+        let nudges = ActivationNudges::new(DEFAULT_IDLE_ROBOT_DAYS, 2);
+
+        // Four weekly runs in March: one nudge per run, most specific first, then the cap
+        run_weeks(&env, &nudges, Utc.with_ymd_and_hms(2024, 3, 4, 9, 0, 0).unwrap(), 4).await;
+        assert_eq!(env.sent_to("busy"), vec![RobotSilent, RobotNeverStarted]);
+
+        run_weeks(&env, &nudges, Utc.with_ymd_and_hms(2024, 4, 1, 9, 0, 0).unwrap(), 4).await;
+        assert_eq!(env.sent_to("busy"), vec![RobotSilent, RobotNeverStarted, RobotNeverStarted, NoActiveRobots]);
+
+        // The preview reflects the same rules without sending anything
+        let quiet = FakeEnv::new(vec![("quiet", RobotSilent, true), ("quiet", NoActiveRobots, false)]);
+        let preview = nudges.preview(&quiet, Utc::now()).await.unwrap();
+        assert_eq!(preview.iter().map(|p| p.kind).collect::<Vec<_>>(), vec![RobotSilent]);
+        assert!(quiet.outbox.lock().unwrap().is_empty() && quiet.nudges.lock().unwrap().is_empty());
+    }
+}
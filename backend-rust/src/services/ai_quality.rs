@@ -0,0 +1,193 @@
+use chrono::{DateTime, Duration, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    errors::{AppError, Result},
+    models::{ConfidenceDecile, DecisionOutcome, SignalHitRate, TradeAiDecision},
+};
+
+pub const CALIBRATION_BUCKETS: usize = 10;
+pub const DEFAULT_QUALITY_DAYS: i64 = 90;
+const MAX_QUALITY_DAYS: i64 = 730;
+
+pub const QUALITY_MODEL: &str = "model";
+pub const QUALITY_FALLBACK_ONLY: &str = "fallback_only";
+pub const QUALITY_NO_DECISIONS: &str = "no_decisions";
+
+// Model decisions whose confidence fell in [lower, upper); the last bucket includes 1.0
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct CalibrationBucket {
+    pub lower: f64,
+    pub upper: f64,
+    pub trades: i64,
+    // Mean confidence of the bucket's decisions, against the share that won
+    pub predicted: Option<f64>,
+    pub win_rate: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct ModelOverrides {
+    // Model decisions whose fallback signal is known
+    pub compared: i64,
+    // Of those, the ones where the model went another way than the fallback
+    pub overridden: i64,
+    pub share: f64,
+    pub overridden_avg_profit: Option<f64>,
+    pub agreed_avg_profit: Option<f64>,
+    // Average profit when overriding minus when agreeing
+    pub outcome_delta: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct AiQualityReport {
+    pub robot_id: Uuid,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    // model | fallback_only | no_decisions; the metrics below are only present for `model`
+    pub status: String,
+    pub message: Option<String>,
+    // Closed AI trades in the period, and how many of them the model decided
+    pub decisions: i64,
+    pub model_decisions: i64,
+    pub calibration: Option<Vec<CalibrationBucket>>,
+    pub hit_rate_by_signal: Option<Vec<SignalHitRate>>,
+    pub r_by_confidence_decile: Option<Vec<ConfidenceDecile>>,
+    pub overrides: Option<ModelOverrides>,
+}
+
+pub struct AiQuality;
+
+impl AiQuality {
+    // `from`/`to` win over `days`; the default is the last 90 days
+    pub fn period(
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        days: Option<i64>,
+        now: DateTime<Utc>,
+    ) -> Result<(DateTime<Utc>, DateTime<Utc>)> {
+        let days = days.unwrap_or(DEFAULT_QUALITY_DAYS);
+        if !(1..=MAX_QUALITY_DAYS).contains(&days) {
+            return Err(AppError::Validation(format!("days must be between 1 and {}", MAX_QUALITY_DAYS)));
+        }
+        let to = to.unwrap_or(now);
+        let from = from.unwrap_or(to - Duration::days(days));
+        if from >= to {
+            return Err(AppError::Validation("from must be before to".to_string()));
+        }
+        Ok((from, to))
+    }
+
+    pub fn calibrate(outcomes: &[DecisionOutcome], buckets: usize) -> Vec<CalibrationBucket> {
+        let mut totals = vec![(0i64, 0i64, 0.0f64); buckets];
+        for outcome in outcomes {
+            let confidence = outcome.confidence.clamp(0.0, 1.0);
+            let index = ((confidence * buckets as f64) as usize).min(buckets - 1);
+            let (trades, wins, sum) = &mut totals[index];
+            *trades += 1;
+            *wins += outcome.won as i64;
+            *sum += confidence;
+        }
+        totals
+            .into_iter()
+            .enumerate()
+            .map(|(index, (trades, wins, sum))| CalibrationBucket {
+                lower: index as f64 / buckets as f64,
+                upper: (index + 1) as f64 / buckets as f64,
+                trades,
+                predicted: (trades > 0).then(|| sum / trades as f64),
+                win_rate: (trades > 0).then(|| wins as f64 / trades as f64),
+            })
+            .collect()
+    }
+
+    pub async fn report(pool: &PgPool, robot_id: Uuid, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<AiQualityReport> {
+        let counts = TradeAiDecision::counts(pool, robot_id, from, to).await?;
+        let mut report = AiQualityReport {
+            robot_id,
+            from,
+            to,
+            status: QUALITY_MODEL.to_string(),
+            message: None,
+            decisions: counts.decisions,
+            model_decisions: counts.model_decisions,
+            calibration: None,
+            hit_rate_by_signal: None,
+            r_by_confidence_decile: None,
+            overrides: None,
+        };
+        if counts.decisions == 0 {
+            report.status = QUALITY_NO_DECISIONS.to_string();
+            report.message = Some("No AI decisions were closed in this period".to_string());
+            return Ok(report);
+        }
+        if counts.model_decisions == 0 {
+            report.status = QUALITY_FALLBACK_ONLY.to_string();
+            report.message = Some(
+                "Every decision in this period came from the rule-based fallback; the AI model made none".to_string(),
+            );
+            return Ok(report);
+        }
+
+        let outcomes = TradeAiDecision::model_outcomes(pool, robot_id, from, to).await?;
+        report.calibration = Some(Self::calibrate(&outcomes, CALIBRATION_BUCKETS));
+        report.hit_rate_by_signal = Some(TradeAiDecision::hit_rate_by_signal(pool, robot_id, from, to).await?);
+        report.r_by_confidence_decile = Some(TradeAiDecision::r_by_confidence_decile(pool, robot_id, from, to).await?);
+
+        let totals = TradeAiDecision::override_totals(pool, robot_id, from, to).await?;
+        report.overrides = Some(ModelOverrides {
+            compared: totals.compared,
+            overridden: totals.overridden,
+            share: if totals.compared > 0 { totals.overridden as f64 / totals.compared as f64 } else { 0.0 },
+            overridden_avg_profit: totals.overridden_avg_profit,
+            agreed_avg_profit: totals.agreed_avg_profit,
+            outcome_delta: totals.overridden_avg_profit.zip(totals.agreed_avg_profit).map(|(o, a)| o - a),
+        });
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outcome(confidence: f64, won: bool) -> DecisionOutcome {
+        DecisionOutcome { confidence, won }
+    }
+
+    #[test]
+    fn test_calibration_buckets_by_confidence() {
+        let outcomes = [
+            outcome(0.55, true),
+            outcome(0.58, false),
+            outcome(0.72, true),
+            outcome(0.74, true),
+            outcome(0.76, false),
+            outcome(1.0, true),
+        ];
+
+        let buckets = AiQuality::calibrate(&outcomes, 10);
+
+        assert_eq!(buckets.len(), 10);
+        assert_eq!(buckets[5].trades, 2);
+        assert!((buckets[5].predicted.unwrap() - 0.565).abs() < 1e-9);
+        assert_eq!(buckets[5].win_rate, Some(0.5));
+        assert_eq!(buckets[7].trades, 3);
+        assert!((buckets[7].win_rate.unwrap() - 2.0 / 3.0).abs() < 1e-9);
+        // 1.0 lands in the top bucket rather than an eleventh one
+        assert_eq!(buckets[9].trades, 1);
+        assert_eq!(buckets[9].win_rate, Some(1.0));
+        assert_eq!(buckets[0], CalibrationBucket { lower: 0.0, upper: 0.1, trades: 0, predicted: None, win_rate: None });
+    }
+
+    #[test]
+    fn test_period_defaults_and_validation() {
+        let now = Utc::now();
+        assert_eq!(AiQuality::period(None, None, None, now).unwrap(), (now - Duration::days(90), now));
+        assert_eq!(AiQuality::period(None, None, Some(7), now).unwrap(), (now - Duration::days(7), now));
+        assert!(AiQuality::period(None, None, Some(0), now).is_err());
+        assert!(AiQuality::period(Some(now), Some(now - Duration::days(1)), None, now).is_err());
+    }
+}
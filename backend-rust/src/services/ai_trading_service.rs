@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
 
 use crate::errors::{AppError, Result};
+use crate::models::DECISION_FALLBACK;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TradingSignal {
@@ -10,6 +11,8 @@ pub struct TradingSignal {
     pub entry_price: Option<f64>,
     pub stop_loss: Option<f64>,
     pub take_profit: Option<f64>,
+    // model | fallback; recorded with the trade in trade_ai_decisions
+    pub source: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -93,6 +96,7 @@ impl AiTradingService {
             entry_price: None, // TODO: Calculate based on current market price
             stop_loss: None,   // TODO: Calculate based on risk management rules
             take_profit: None, // TODO: Calculate based on risk/reward ratio
+            source: DECISION_FALLBACK.to_string(),
         })
     }
 
@@ -103,7 +107,11 @@ impl AiTradingService {
         stop_loss_pips: f64,
         pip_value: f64,
     ) -> f64 {
-        let risk_amount = account_balance * risk_per_trade;
+        Self::position_size(account_balance, risk_per_trade, stop_loss_pips, pip_value)
+    }
+
+    pub fn position_size(equity: f64, risk_per_trade: f64, stop_loss_pips: f64, pip_value: f64) -> f64 {
+        let risk_amount = equity * risk_per_trade;
         let position_size = risk_amount / (stop_loss_pips * pip_value);
         position_size.max(0.01) // Minimum position size
     }
@@ -181,6 +189,7 @@ mod tests {
             entry_price: None,
             stop_loss: None,
             take_profit: None,
+            source: DECISION_FALLBACK.to_string(),
         };
 
         assert!(service.should_trade(&signal, 0.7));
@@ -0,0 +1,194 @@
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    errors::{AppError, Result},
+    models::{AccountInfo, BrokerConnection, RobotLog, TradingRobot},
+    services::{symbol_spec::SymbolSpec, AiTradingService, Mt5Service},
+};
+
+const DEFAULT_RISK_PER_TRADE: f64 = 0.02;
+const DEFAULT_STOP_LOSS_PIPS: f64 = 20.0;
+const DEFAULT_MAX_DAILY_LOSS: f64 = 0.05;
+
+// Splits one broker account between the robots trading on it. A robot with an allocation
+// sizes positions and applies its loss limits to its own slice instead of the whole balance.
+pub struct AllocationService;
+
+impl AllocationService {
+    // The user's robots on one connection may not be allocated more than 100% between them
+    pub fn check_connection_total(
+        robots: &[TradingRobot],
+        connection_id: Uuid,
+        robot_id: Uuid,
+        allocation_percent: Option<f64>,
+    ) -> Result<()> {
+        let Some(requested) = allocation_percent else {
+            return Ok(());
+        };
+
+        let others: f64 = robots
+            .iter()
+            .filter(|r| r.id != robot_id && r.broker_connection_id == Some(connection_id))
+            .filter_map(|r| r.allocation_percent())
+            .sum();
+
+        if others + requested > 100.0 + 1e-9 {
+            return Err(AppError::Validation(format!(
+                "Robots on this broker connection already have {}% allocated, only {}% is left",
+                others,
+                (100.0 - others).max(0.0)
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn baseline(account_balance: f64, allocation_percent: f64) -> f64 {
+        crate::money::round_amount(account_balance * allocation_percent / 100.0)
+    }
+
+    // Equity the robot's risk rules apply to
+    pub fn sizing_equity(robot: &TradingRobot, account_balance: f64) -> f64 {
+        robot.virtual_equity().unwrap_or(account_balance)
+    }
+
+    pub fn position_size(robot: &TradingRobot, account_balance: f64, pip_value: f64) -> f64 {
+        let risk = |key: &str, default: f64| robot.risk_config.get(key).and_then(|v| v.as_f64()).unwrap_or(default);
+        AiTradingService::position_size(
+            Self::sizing_equity(robot, account_balance),
+            risk("max_risk_per_trade", DEFAULT_RISK_PER_TRADE),
+            risk("stop_loss_pips", DEFAULT_STOP_LOSS_PIPS),
+            pip_value,
+        )
+    }
+
+    // The lots the robot's order goes out with, within the broker's lot rules for the symbol
+    pub fn volume(robot: &TradingRobot, account_balance: f64, spec: &SymbolSpec, pip_value: f64) -> f64 {
+        spec.round_lots(Self::position_size(robot, account_balance, pip_value))
+    }
+
+    pub fn daily_loss_limit(robot: &TradingRobot, account_balance: f64) -> f64 {
+        let max_daily_loss = robot
+            .risk_config
+            .get("max_daily_loss")
+            .and_then(|v| v.as_f64())
+            .unwrap_or(DEFAULT_MAX_DAILY_LOSS);
+        Self::sizing_equity(robot, account_balance) * max_daily_loss
+    }
+
+    pub async fn account_info(mt5: &Mt5Service, connection: &BrokerConnection) -> Result<AccountInfo> {
+        let connection_id = connection.id.to_string();
+        if !mt5.is_connected(&connection_id) {
+            mt5.connect(connection).await?;
+        }
+        mt5.get_account_info(&connection_id).await
+    }
+
+    // Re-baselines the robot on the current balance and records the change in its log
+    pub async fn apply(
+        pool: &PgPool,
+        mt5: &Mt5Service,
+        robot: &TradingRobot,
+        connection: &BrokerConnection,
+        allocation_percent: Option<f64>,
+    ) -> Result<()> {
+        let previous = robot.allocation_percent();
+        let (baseline, currency) = match allocation_percent {
+            Some(percent) => {
+                let account = Self::account_info(mt5, connection).await?;
+                (Some(Self::baseline(account.balance, percent)), account.currency)
+            }
+            None => (None, String::new()),
+        };
+
+        TradingRobot::set_allocation(pool, robot.id, robot.user_id, allocation_percent, baseline).await?;
+
+        let describe = |percent: Option<f64>| percent.map(|p| format!("{}%", p)).unwrap_or_else(|| "none".to_string());
+        let message = match baseline {
+            Some(baseline) => format!(
+                "Allocation changed from {} to {}, baseline {}",
+                describe(previous),
+                describe(allocation_percent),
+                crate::money::format_amount(baseline, &currency)
+            ),
+            None => format!("Allocation changed from {} to none, sizing against the whole account", describe(previous)),
+        };
+        RobotLog::create(pool, robot.id, robot.user_id, "info", &message).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn robot(connection_id: Uuid, allocation_percent: Option<f64>) -> TradingRobot {
+        let mut robot = TradingRobot::new(Uuid::new_v4(), "robot".to_string(), "trend".to_string());
+        robot.broker_connection_id = Some(connection_id);
+        if let Some(percent) = allocation_percent {
+            robot.risk_config["allocation_percent"] = json!(percent);
+        }
+        robot
+    }
+
+    #[test]
+    fn test_allocations_on_a_connection_cannot_exceed_100_percent() {
+        let connection = Uuid::new_v4();
+        let other_connection = Uuid::new_v4();
+        let robots = vec![
+            robot(connection, Some(60.0)),
+            robot(connection, Some(30.0)),
+            robot(connection, None),
+            robot(other_connection, Some(90.0)),
+        ];
+        let new_robot = Uuid::new_v4();
+
+        assert!(AllocationService::check_connection_total(&robots, connection, new_robot, Some(10.0)).is_ok());
+        assert!(matches!(
+            AllocationService::check_connection_total(&robots, connection, new_robot, Some(10.5)),
+            Err(AppError::Validation(_))
+        ));
+        assert!(AllocationService::check_connection_total(&robots, connection, new_robot, None).is_ok());
+
+        // Changing an existing robot's allocation does not count its old share
+        assert!(AllocationService::check_connection_total(&robots, connection, robots[0].id, Some(70.0)).is_ok());
+        assert!(AllocationService::check_connection_total(&robots, connection, robots[0].id, Some(71.0)).is_err());
+    }
+
+    #[test]
+    fn test_allocation_percent_is_validated() {
+        assert_eq!(TradingRobot::allocation_from_risk_config(&json!({})), Ok(None));
+        assert_eq!(TradingRobot::allocation_from_risk_config(&json!({"allocation_percent": 30})), Ok(Some(30.0)));
+        assert!(TradingRobot::allocation_from_risk_config(&json!({"allocation_percent": 0})).is_err());
+        assert!(TradingRobot::allocation_from_risk_config(&json!({"allocation_percent": 120})).is_err());
+        assert!(TradingRobot::allocation_from_risk_config(&json!({"allocation_percent": "30"})).is_err());
+    }
+
+    #[test]
+    fn test_sizing_uses_the_allocated_slice_plus_realized_profit() {
+        let connection = Uuid::new_v4();
+        let mut allocated = robot(connection, Some(30.0));
+        // Allocated 30% of 10,000 after 200 had already been made, then made 300 more
+        allocated.performance_metrics = json!({
+            "total_profit": 500.0,
+            "winning_trades": 3,
+            "allocation_baseline": AllocationService::baseline(10_000.0, 30.0),
+            "allocation_profit_offset": 200.0,
+        });
+
+        assert_eq!(allocated.virtual_equity(), Some(3_300.0));
+        assert_eq!(AllocationService::sizing_equity(&allocated, 10_000.0), 3_300.0);
+        assert!((AllocationService::daily_loss_limit(&allocated, 10_000.0) - 165.0).abs() < 1e-9);
+
+        // 2% of 3,300 risked over a 20 pip stop at 1.0 per pip
+        let size = AllocationService::position_size(&allocated, 10_000.0, 1.0);
+        assert!((size - 3.3).abs() < 1e-9);
+
+        let unallocated = robot(connection, None);
+        assert_eq!(unallocated.virtual_equity(), None);
+        let size = AllocationService::position_size(&unallocated, 10_000.0, 1.0);
+        assert!((size - 10.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,196 @@
+use async_trait::async_trait;
+use axum::http::Method;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    errors::{AppError, Result},
+    models::{ApiKey, API_KEY_SCOPES, API_KEY_SCOPE_READ, API_KEY_SCOPE_TRADE},
+};
+
+// What a trade key may change on top of reading
+const TRADE_WRITE_PATHS: &[&str] = &["/api/v1/trades", "/api/v1/robots"];
+// Never reachable with a key, whatever its scopes: a leaked key must not mint more keys, see or
+// sign out the user's devices, or reach the admin API
+const KEYLESS_PATHS: &[&str] =
+    &["/api/v1/api-keys", "/api/v1/auth/sessions", "/api/v1/users/me/security-events", "/api/v1/admin"];
+
+#[async_trait]
+pub trait ApiKeyStore: Send + Sync {
+    async fn find_by_key(&self, key: &str) -> Result<Option<ApiKey>>;
+    async fn touch(&self, id: Uuid, now: DateTime<Utc>) -> Result<()>;
+}
+
+pub struct PgApiKeyStore {
+    pool: PgPool,
+}
+
+impl PgApiKeyStore {
+    pub fn new(pool: PgPool) -> Self {
+        PgApiKeyStore { pool }
+    }
+}
+
+#[async_trait]
+impl ApiKeyStore for PgApiKeyStore {
+    async fn find_by_key(&self, key: &str) -> Result<Option<ApiKey>> {
+        ApiKey::find_by_key(&self.pool, key).await
+    }
+
+    async fn touch(&self, id: Uuid, now: DateTime<Utc>) -> Result<()> {
+        ApiKey::touch(&self.pool, id, now).await
+    }
+}
+
+// Put in the request extensions when the request was authenticated by an API key rather than a token
+#[derive(Debug, Clone)]
+pub struct ApiKeyAccess {
+    pub key_id: Uuid,
+    pub user_id: Uuid,
+    pub scopes: Vec<String>,
+}
+
+pub struct ApiKeyService;
+
+impl ApiKeyService {
+    // Defaults to read; trade implies read. Listed in API_KEY_SCOPES order.
+    pub fn normalize_scopes(requested: &[String]) -> Result<Vec<String>> {
+        let requested: Vec<String> = requested.iter().map(|s| s.trim().to_lowercase()).collect();
+        if let Some(unknown) = requested.iter().find(|s| !API_KEY_SCOPES.contains(&s.as_str())) {
+            return Err(AppError::Validation(format!(
+                "Unknown scope '{}', expected one of {}",
+                unknown,
+                API_KEY_SCOPES.join(", ")
+            )));
+        }
+        let trade = requested.iter().any(|s| s == API_KEY_SCOPE_TRADE);
+        Ok(API_KEY_SCOPES
+            .iter()
+            .filter(|scope| **scope == API_KEY_SCOPE_READ || trade)
+            .map(|scope| scope.to_string())
+            .collect())
+    }
+
+    pub fn permits(api_key: &ApiKey, method: &Method, path: &str) -> Result<()> {
+        let path = path.trim_end_matches('/');
+        if KEYLESS_PATHS.iter().any(|prefix| Self::under(path, prefix)) {
+            return Err(AppError::Forbidden("API keys cannot be used here; sign in instead".to_string()));
+        }
+        let allowed = if method.is_safe() {
+            api_key.has_scope(API_KEY_SCOPE_READ)
+        } else {
+            api_key.has_scope(API_KEY_SCOPE_TRADE) && TRADE_WRITE_PATHS.iter().any(|prefix| Self::under(path, prefix))
+        };
+        if !allowed {
+            return Err(AppError::Forbidden(format!(
+                "This API key's scopes ({}) do not allow {} {}",
+                api_key.scopes.join(", "),
+                method,
+                path
+            )));
+        }
+        Ok(())
+    }
+
+    // Checks an X-API-Key request and notes that the key was used
+    pub async fn authenticate(
+        store: &dyn ApiKeyStore,
+        key: &str,
+        method: &Method,
+        path: &str,
+        now: DateTime<Utc>,
+    ) -> Result<ApiKeyAccess> {
+        let api_key = store
+            .find_by_key(key.trim())
+            .await?
+            .ok_or_else(|| AppError::Auth("Invalid API key".to_string()))?;
+        if let Err(e) = Self::permits(&api_key, method, path) {
+            tracing::warn!(target: "audit", "API key {} of {} denied {} {}", api_key.id, api_key.user_id, method, path);
+            return Err(e);
+        }
+
+        store.touch(api_key.id, now).await?;
+        Ok(ApiKeyAccess { key_id: api_key.id, user_id: api_key.user_id, scopes: api_key.scopes })
+    }
+
+    fn under(path: &str, prefix: &str) -> bool {
+        path.strip_prefix(prefix).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct FakeStore {
+        keys: Mutex<Vec<ApiKey>>,
+        touched: Mutex<Vec<Uuid>>,
+    }
+
+    #[async_trait]
+    impl ApiKeyStore for FakeStore {
+        async fn find_by_key(&self, key: &str) -> Result<Option<ApiKey>> {
+            let hash = ApiKey::hash_key(key);
+            Ok(self.keys.lock().unwrap().iter().find(|k| k.key_hash == hash && k.revoked_at.is_none()).cloned())
+        }
+
+        async fn touch(&self, id: Uuid, _now: DateTime<Utc>) -> Result<()> {
+            self.touched.lock().unwrap().push(id);
+            Ok(())
+        }
+    }
+
+    fn key(scopes: &[&str]) -> ApiKey {
+        let scopes = ApiKeyService::normalize_scopes(&scopes.iter().map(|s| s.to_string()).collect::<Vec<_>>()).unwrap();
+        ApiKey::generate(Uuid::new_v4(), "bot".to_string(), scopes, Utc::now()).1
+    }
+
+    #[test]
+    fn test_scopes_default_to_read_and_trade_implies_read() {
+        let normalize = |scopes: &[&str]| ApiKeyService::normalize_scopes(&scopes.iter().map(|s| s.to_string()).collect::<Vec<_>>());
+        assert_eq!(normalize(&[]).unwrap(), vec!["read"]);
+        assert_eq!(normalize(&[" Trade "]).unwrap(), vec!["read", "trade"]);
+        assert_eq!(normalize(&["trade", "read", "trade"]).unwrap(), vec!["read", "trade"]);
+        assert!(matches!(normalize(&["admin"]), Err(AppError::Validation(_))));
+    }
+
+    #[test]
+    fn test_read_keys_only_read_and_trade_keys_only_write_trading_routes() {
+        let (read, trade) = (key(&["read"]), key(&["trade"]));
+
+        assert!(ApiKeyService::permits(&read, &Method::GET, "/api/v1/trades").is_ok());
+        assert!(ApiKeyService::permits(&read, &Method::POST, "/api/v1/trades/close-batch").is_err());
+        assert!(ApiKeyService::permits(&read, &Method::POST, "/api/v1/robots").is_err());
+
+        assert!(ApiKeyService::permits(&trade, &Method::GET, "/api/v1/dashboard").is_ok());
+        assert!(ApiKeyService::permits(&trade, &Method::POST, "/api/v1/robots/").is_ok());
+        assert!(ApiKeyService::permits(&trade, &Method::POST, "/api/v1/trades/close-batch").is_ok());
+        assert!(ApiKeyService::permits(&trade, &Method::PUT, "/api/v1/auth/password").is_err());
+        assert!(ApiKeyService::permits(&trade, &Method::POST, "/api/v1/tradesman").is_err());
+
+        for path in ["/api/v1/api-keys", "/api/v1/auth/sessions", "/api/v1/admin/users"] {
+            assert!(matches!(ApiKeyService::permits(&trade, &Method::GET, path), Err(AppError::Forbidden(_))), "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unknown_and_revoked_keys_are_refused() {
+        let store = FakeStore::default();
+        let (plain, api_key) = ApiKey::generate(Uuid::new_v4(), "bot".to_string(), vec!["read".to_string()], Utc::now());
+        store.keys.lock().unwrap().push(api_key.clone());
+
+        let access = ApiKeyService::authenticate(&store, &plain, &Method::GET, "/api/v1/trades", Utc::now()).await.unwrap();
+        assert_eq!((access.key_id, access.user_id), (api_key.id, api_key.user_id));
+        assert_eq!(*store.touched.lock().unwrap(), vec![api_key.id]);
+
+        let unknown = ApiKeyService::authenticate(&store, "tsk_unknown", &Method::GET, "/api/v1/trades", Utc::now()).await;
+        assert!(matches!(unknown, Err(AppError::Auth(_))));
+
+        store.keys.lock().unwrap()[0].revoked_at = Some(Utc::now());
+        let revoked = ApiKeyService::authenticate(&store, &plain, &Method::GET, "/api/v1/trades", Utc::now()).await;
+        assert!(matches!(revoked, Err(AppError::Auth(_))));
+    }
+}
@@ -0,0 +1,33 @@
+use axum::http::{header::USER_AGENT, HeaderMap};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    models::AuthEvent,
+    services::{
+        task_supervisor::{spawn_supervised, TaskClass},
+        trade_origins,
+    },
+};
+
+// An event for the request the headers came with, stamped with its client address and agent
+pub fn event(headers: &HeaderMap, user_id: Option<Uuid>, event_type: &str, outcome: &str) -> AuthEvent {
+    AuthEvent::new(
+        user_id,
+        event_type,
+        outcome,
+        headers.get(USER_AGENT).and_then(|v| v.to_str().ok()),
+        trade_origins::source_ip(headers),
+        chrono::Utc::now(),
+    )
+}
+
+// Written in the background so a slow insert never holds up a sign-in; a failed write is only logged
+pub fn record(pool: &PgPool, event: AuthEvent) {
+    let pool = pool.clone();
+    spawn_supervised("auth_events:record", TaskClass::Background, async move {
+        if let Err(e) = AuthEvent::create(&pool, &event).await {
+            tracing::warn!("Could not record {} auth event for {:?}: {}", event.event_type, event.user_id, e);
+        }
+    });
+}
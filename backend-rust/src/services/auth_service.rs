@@ -1,111 +1,155 @@
-use chrono::{Duration, Utc};
+use chrono::{DateTime, Duration, Utc};
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::errors::AppError;
 
-#[derive(Debug, Deserialize)]
-pub struct GoogleUser {
-    pub email: String,
-    pub name: String,
-    pub picture: String,
-}
+pub const DEFAULT_TOKEN_LIFETIME_MINUTES: i64 = 24 * 60;
+pub const DEFAULT_TOKEN_ISSUER: &str = "trading-saas";
+pub const DEFAULT_TOKEN_AUDIENCE: &str = "trading-saas-api";
+// Impersonation tokens last at most this long, however long ordinary tokens do
+pub const IMPERSONATION_LIFETIME_MINUTES: i64 = 15;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String, // Subject (user ID)
     pub exp: usize,  // Expiration time
     pub iat: usize,  // Issued at
+    // Who signed the token and who it is for; tokens issued before these existed have neither
+    #[serde(default)]
+    pub iss: String,
+    #[serde(default)]
+    pub aud: String,
+    // Token ID, denylisted on logout; tokens issued before logout existed have none
+    #[serde(default)]
+    pub jti: String,
+    // The user's token version at issue time; "logout all devices" bumps it past every earlier token
+    #[serde(default)]
+    pub ver: u32,
+    // The admin acting as the user, on tokens from the admin impersonation endpoint
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub impersonator: Option<String>,
+}
+
+impl Claims {
+    // Seconds until the token expires on its own, which is how long a revocation must last
+    pub fn remaining_seconds(&self, now: DateTime<Utc>) -> u64 {
+        (self.exp as i64 - now.timestamp()).max(0) as u64
+    }
+}
+
+// How tokens are signed and which ones are accepted
+#[derive(Debug, Clone)]
+pub struct TokenSettings<'a> {
+    pub secret: &'a str,
+    pub lifetime: Duration,
+    pub issuer: &'a str,
+    pub audience: &'a str,
+    // Also refuse tokens with no iss, aud or jti, i.e. those issued before they were added.
+    // Off until every such token has expired, so a deploy does not sign everyone out.
+    pub require_claims: bool,
 }
 
 pub struct AuthService;
 
 impl AuthService {
-    pub fn create_token(user_id: Uuid, secret: &str) -> Result<String, AppError> {
+    pub fn create_token(user_id: Uuid, token_version: u32, settings: &TokenSettings) -> Result<String, AppError> {
+        Self::issue_token(user_id, token_version, settings).map(|(token, _)| token)
+    }
+
+    // The token and the claims it carries, for callers that record the session it starts
+    pub fn issue_token(user_id: Uuid, token_version: u32, settings: &TokenSettings) -> Result<(String, Claims), AppError> {
+        Self::sign(user_id, token_version, None, settings.lifetime, settings)
+    }
+
+    // A short-lived token for `user_id` that carries who is impersonating them
+    pub fn issue_impersonation_token(
+        user_id: Uuid,
+        token_version: u32,
+        admin_id: Uuid,
+        settings: &TokenSettings,
+    ) -> Result<(String, Claims), AppError> {
+        let lifetime = settings.lifetime.min(Duration::minutes(IMPERSONATION_LIFETIME_MINUTES));
+        Self::sign(user_id, token_version, Some(admin_id), lifetime, settings)
+    }
+
+    fn sign(
+        user_id: Uuid,
+        token_version: u32,
+        impersonator: Option<Uuid>,
+        lifetime: Duration,
+        settings: &TokenSettings,
+    ) -> Result<(String, Claims), AppError> {
         let now = Utc::now();
-        let exp = now + Duration::hours(24); // Token expires in 24 hours
+        let exp = now + lifetime;
 
         let claims = Claims {
             sub: user_id.to_string(),
             exp: exp.timestamp() as usize,
             iat: now.timestamp() as usize,
+            iss: settings.issuer.to_string(),
+            aud: settings.audience.to_string(),
+            jti: Uuid::new_v4().to_string(),
+            ver: token_version,
+            impersonator: impersonator.map(|id| id.to_string()),
         };
 
-        encode(
+        let token = encode(
             &Header::default(),
             &claims,
-            &EncodingKey::from_secret(secret.as_ref()),
+            &EncodingKey::from_secret(settings.secret.as_ref()),
         )
-        .map_err(|e| AppError::Jwt(e))
+        .map_err(AppError::Jwt)?;
+        Ok((token, claims))
     }
 
-    pub fn verify_token(token: &str, secret: &str) -> Result<Claims, AppError> {
-        decode::<Claims>(
+    // iss and aud are checked whenever the token has them, and required once `require_claims` is on
+    pub fn verify_token(token: &str, settings: &TokenSettings) -> Result<Claims, AppError> {
+        let mut validation = Validation::default();
+        validation.set_issuer(&[settings.issuer]);
+        validation.set_audience(&[settings.audience]);
+        if settings.require_claims {
+            validation.set_required_spec_claims(&["exp", "sub", "iss", "aud"]);
+        }
+
+        let claims = decode::<Claims>(
             token,
-            &DecodingKey::from_secret(secret.as_ref()),
-            &Validation::default(),
+            &DecodingKey::from_secret(settings.secret.as_ref()),
+            &validation,
         )
         .map(|data| data.claims)
-        .map_err(|e| AppError::Jwt(e))
+        .map_err(AppError::Jwt)?;
+
+        if settings.require_claims && claims.jti.is_empty() {
+            return Err(AppError::Auth("Token has no id".to_string()));
+        }
+        Ok(claims)
+    }
+
+    pub fn extract_user_id_from_token(token: &str, settings: &TokenSettings) -> Result<Uuid, AppError> {
+        let claims = Self::verify_token(token, settings)?;
+        Self::user_id(&claims)
     }
 
-    pub fn extract_user_id_from_token(token: &str, secret: &str) -> Result<Uuid, AppError> {
-        let claims = Self::verify_token(token, secret)?;
+    pub fn user_id(claims: &Claims) -> Result<Uuid, AppError> {
         claims.sub.parse::<Uuid>()
             .map_err(|e| AppError::Auth(format!("Invalid user ID in token: {}", e)))
     }
 
-    pub async fn verify_google_token(token: &str) -> Result<GoogleUser, AppError> {
-        // For now, we'll create a mock implementation
-        // In production, you would verify the token with Google's API
-        // https://www.googleapis.com/oauth2/v3/tokeninfo?id_token={token}
-        
-        // Mock implementation - replace with actual Google token verification
-        if token.starts_with("mock_google_token_") {
-            let email = token.replace("mock_google_token_", "");
-            return Ok(GoogleUser {
-                email: format!("{}@gmail.com", email),
-                name: format!("User {}", email),
-                picture: "https://via.placeholder.com/150".to_string(),
-            });
-        }
-
-        // For production, implement actual Google token verification
-        // This is a simplified version - you should use reqwest to call Google's API
-        let client = reqwest::Client::new();
-        let response = client
-            .get(&format!("https://www.googleapis.com/oauth2/v3/tokeninfo?id_token={}", token))
-            .send()
-            .await
-            .map_err(|e| AppError::Auth(format!("Failed to verify Google token: {}", e)))?;
-
-        if !response.status().is_success() {
-            return Err(AppError::Auth("Invalid Google token".to_string()));
-        }
-
-        let google_response: serde_json::Value = response
-            .json()
-            .await
-            .map_err(|e| AppError::Auth(format!("Failed to parse Google response: {}", e)))?;
-
-        let email = google_response["email"]
-            .as_str()
-            .ok_or_else(|| AppError::Auth("No email in Google token".to_string()))?;
-
-        let name = google_response["name"]
-            .as_str()
-            .unwrap_or("Unknown User");
-
-        let picture = google_response["picture"]
-            .as_str()
-            .unwrap_or("");
+    // The admin a token was minted for by impersonation, None for the user's own tokens
+    pub fn impersonator(claims: &Claims) -> Result<Option<Uuid>, AppError> {
+        claims
+            .impersonator
+            .as_deref()
+            .map(|id| id.parse::<Uuid>().map_err(|e| AppError::Auth(format!("Invalid impersonator in token: {}", e))))
+            .transpose()
+    }
 
-        Ok(GoogleUser {
-            email: email.to_string(),
-            name: name.to_string(),
-            picture: picture.to_string(),
-        })
+    // The jti as the id of the session the token belongs to; tokens issued before jti existed have none
+    pub fn token_id(claims: &Claims) -> Result<Uuid, AppError> {
+        claims.jti.parse::<Uuid>()
+            .map_err(|_| AppError::Auth("Token has no session id".to_string()))
     }
 }
 
@@ -113,25 +157,86 @@ impl AuthService {
 mod tests {
     use super::*;
 
+    fn settings() -> TokenSettings<'static> {
+        TokenSettings {
+            secret: "test_secret",
+            lifetime: Duration::minutes(DEFAULT_TOKEN_LIFETIME_MINUTES),
+            issuer: DEFAULT_TOKEN_ISSUER,
+            audience: DEFAULT_TOKEN_AUDIENCE,
+            require_claims: true,
+        }
+    }
+
+    // A token as issued before iss, aud and jti were added
+    fn legacy_token(user_id: Uuid, secret: &str) -> String {
+        let claims = serde_json::json!({
+            "sub": user_id.to_string(),
+            "exp": (Utc::now() + Duration::hours(1)).timestamp(),
+            "iat": Utc::now().timestamp(),
+        });
+        encode(&Header::default(), &claims, &EncodingKey::from_secret(secret.as_ref())).unwrap()
+    }
+
     #[test]
     fn test_jwt_token() {
         let user_id = Uuid::new_v4();
-        let secret = "test_secret";
-        
-        let token = AuthService::create_token(user_id, secret).unwrap();
-        let claims = AuthService::verify_token(&token, secret).unwrap();
-        let extracted_id = AuthService::extract_user_id_from_token(&token, secret).unwrap();
+        let settings = settings();
+
+        let token = AuthService::create_token(user_id, 3, &settings).unwrap();
+        let claims = AuthService::verify_token(&token, &settings).unwrap();
+        let extracted_id = AuthService::extract_user_id_from_token(&token, &settings).unwrap();
 
         assert_eq!(claims.sub, user_id.to_string());
+        assert_eq!(claims.ver, 3);
+        assert_eq!((claims.iss.as_str(), claims.aud.as_str()), (DEFAULT_TOKEN_ISSUER, DEFAULT_TOKEN_AUDIENCE));
+        assert_eq!(claims.exp - claims.iat, DEFAULT_TOKEN_LIFETIME_MINUTES as usize * 60);
+        assert!(claims.jti.parse::<Uuid>().is_ok());
         assert_eq!(extracted_id, user_id);
+
+        // Every token gets its own ID, so logging one out leaves the others alone
+        let other = AuthService::verify_token(&AuthService::create_token(user_id, 3, &settings).unwrap(), &settings).unwrap();
+        assert_ne!(other.jti, claims.jti);
+    }
+
+    #[test]
+    fn test_impersonation_tokens_name_the_admin_and_are_short_lived() {
+        let (user_id, admin_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let settings = settings();
+
+        let (token, _) = AuthService::issue_impersonation_token(user_id, 2, admin_id, &settings).unwrap();
+        let claims = AuthService::verify_token(&token, &settings).unwrap();
+        assert_eq!((AuthService::user_id(&claims).unwrap(), claims.ver), (user_id, 2));
+        assert_eq!(AuthService::impersonator(&claims).unwrap(), Some(admin_id));
+        assert_eq!(claims.exp - claims.iat, IMPERSONATION_LIFETIME_MINUTES as usize * 60);
+
+        let own = AuthService::verify_token(&AuthService::create_token(user_id, 2, &settings).unwrap(), &settings).unwrap();
+        assert_eq!(AuthService::impersonator(&own).unwrap(), None);
     }
 
-    #[tokio::test]
-    async fn test_mock_google_token() {
-        let token = "mock_google_token_testuser";
-        let result = AuthService::verify_google_token(token).await.unwrap();
-        
-        assert_eq!(result.email, "testuser@gmail.com");
-        assert_eq!(result.name, "User testuser");
+    #[test]
+    fn test_expired_and_foreign_tokens_are_refused() {
+        let user_id = Uuid::new_v4();
+        let settings = settings();
+        // Past the minute of leeway the validation allows
+        let expired = TokenSettings { lifetime: Duration::minutes(-5), ..settings.clone() };
+        let other_issuer = TokenSettings { issuer: "someone-else", ..settings.clone() };
+        let other_audience = TokenSettings { audience: "another-api", ..settings.clone() };
+
+        for issuing in [expired, other_issuer, other_audience] {
+            let token = AuthService::create_token(user_id, 0, &issuing).unwrap();
+            assert!(matches!(AuthService::verify_token(&token, &settings), Err(AppError::Jwt(_))), "{:?}", issuing);
+        }
+    }
+
+    #[test]
+    fn test_tokens_without_the_new_claims_only_pass_until_they_are_required() {
+        let user_id = Uuid::new_v4();
+        let token = legacy_token(user_id, "test_secret");
+
+        let lenient = TokenSettings { require_claims: false, ..settings() };
+        let claims = AuthService::verify_token(&token, &lenient).unwrap();
+        assert_eq!((claims.sub, claims.jti, claims.iss), (user_id.to_string(), String::new(), String::new()));
+
+        assert!(AuthService::verify_token(&token, &settings()).is_err());
     }
 }
@@ -0,0 +1,235 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::sync::Arc;
+
+use crate::{
+    errors::{AppError, Result},
+    models::TradingRobot,
+    services::Mt5Service,
+};
+
+const DEFAULT_STOP_LOSS_PIPS: f64 = 20.0;
+const DEFAULT_MIN_CONFIDENCE: f64 = 0.6;
+// Hourly candles; a year of them at most
+const HISTORY_TIMEFRAME: &str = "H1";
+const MAX_HISTORY_CANDLES: i64 = 24 * 366;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Candle {
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+}
+
+impl Candle {
+    // A candle's body gives the direction; its share of the range the confidence
+    fn signal(&self) -> (Option<bool>, f64) {
+        let range = self.high - self.low;
+        if range <= 0.0 || self.close == self.open {
+            return (None, 0.0);
+        }
+        (Some(self.close > self.open), ((self.close - self.open).abs() / range).min(1.0))
+    }
+}
+
+#[async_trait]
+pub trait CandleSource: Send + Sync {
+    // Oldest first
+    async fn candles(&self, symbol: &str, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<Vec<Candle>>;
+}
+
+pub struct Mt5CandleSource {
+    mt5: Arc<Mt5Service>,
+}
+
+impl Mt5CandleSource {
+    pub fn new(mt5: Arc<Mt5Service>) -> Self {
+        Mt5CandleSource { mt5 }
+    }
+}
+
+#[async_trait]
+impl CandleSource for Mt5CandleSource {
+    async fn candles(&self, symbol: &str, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<Vec<Candle>> {
+        let connection_id = self.mt5.any_connected().ok_or_else(|| {
+            AppError::Unprocessable("No broker connection is available to load market history".to_string())
+        })?;
+        let count = (end - start).num_hours().clamp(1, MAX_HISTORY_CANDLES) as i32;
+        let history = self.mt5.get_historical_data(&connection_id, symbol, HISTORY_TIMEFRAME, count).await?;
+        Ok(history
+            .into_iter()
+            .map(|[open, high, low, close, _volume]| Candle { open, high, low, close })
+            .collect())
+    }
+}
+
+// The risk_config settings a backtest replays
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BacktestRules {
+    pub stop_loss_pips: f64,
+    pub take_profit_pips: f64,
+    pub min_confidence: f64,
+}
+
+impl BacktestRules {
+    // Take profit defaults to twice the stop
+    pub fn from_risk_config(risk_config: &serde_json::Value) -> std::result::Result<BacktestRules, String> {
+        let number = |key: &str| -> std::result::Result<Option<f64>, String> {
+            match risk_config.get(key) {
+                None | Some(serde_json::Value::Null) => Ok(None),
+                Some(value) => value.as_f64().map(Some).ok_or_else(|| format!("{} must be a number", key)),
+            }
+        };
+
+        let stop_loss_pips = number("stop_loss_pips")?.unwrap_or(DEFAULT_STOP_LOSS_PIPS);
+        let take_profit_pips = number("take_profit_pips")?.unwrap_or(stop_loss_pips * 2.0);
+        let min_confidence = number("min_confidence")?.unwrap_or(DEFAULT_MIN_CONFIDENCE);
+        if !(stop_loss_pips > 0.0 && stop_loss_pips <= 10_000.0) {
+            return Err("stop_loss_pips must be above 0 and at most 10000".to_string());
+        }
+        if !(take_profit_pips > 0.0 && take_profit_pips <= 10_000.0) {
+            return Err("take_profit_pips must be above 0 and at most 10000".to_string());
+        }
+        if !(0.0..=1.0).contains(&min_confidence) {
+            return Err("min_confidence must be between 0 and 1".to_string());
+        }
+        Ok(BacktestRules { stop_loss_pips, take_profit_pips, min_confidence })
+    }
+}
+
+// Profit figures are in pips
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct BacktestMetrics {
+    pub net_profit: f64,
+    pub max_drawdown: f64,
+    // None when no trade lost
+    pub profit_factor: Option<f64>,
+    pub total_trades: u32,
+}
+
+impl BacktestMetrics {
+    pub fn from_trades(pips: &[f64]) -> Self {
+        let (mut equity, mut peak, mut max_drawdown) = (0.0_f64, 0.0_f64, 0.0_f64);
+        let (mut gross_profit, mut gross_loss) = (0.0, 0.0);
+        for &result in pips {
+            equity += result;
+            peak = peak.max(equity);
+            max_drawdown = max_drawdown.max(peak - equity);
+            if result >= 0.0 {
+                gross_profit += result;
+            } else {
+                gross_loss -= result;
+            }
+        }
+        BacktestMetrics {
+            net_profit: equity,
+            max_drawdown,
+            profit_factor: (gross_loss > 0.0).then(|| gross_profit / gross_loss),
+            total_trades: pips.len() as u32,
+        }
+    }
+}
+
+#[async_trait]
+pub trait BacktestEngine: Send + Sync {
+    // Simulates `robot` trading with `risk_config` in place of its own
+    async fn run(
+        &self,
+        robot: &TradingRobot,
+        risk_config: &serde_json::Value,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<BacktestMetrics>;
+}
+
+pub fn pip_size(symbol: &str) -> f64 {
+    if symbol.to_ascii_uppercase().contains("JPY") {
+        0.01
+    } else {
+        0.0001
+    }
+}
+
+// Replays candles one position at a time: a signal candle opens a trade at the next open,
+// which runs until its stop or target is touched or the series ends. Returns each trade's pips.
+pub fn simulate(candles: &[Candle], rules: &BacktestRules, pip: f64) -> Vec<f64> {
+    let mut trades = Vec::new();
+    // (is_buy, entry price)
+    let mut position: Option<(bool, f64)> = None;
+
+    for window in candles.windows(2) {
+        let (previous, candle) = (window[0], window[1]);
+        if position.is_none() {
+            let (direction, confidence) = previous.signal();
+            if let Some(is_buy) = direction.filter(|_| confidence >= rules.min_confidence) {
+                position = Some((is_buy, candle.open));
+            }
+        }
+
+        let Some((is_buy, entry)) = position else { continue };
+        let side = if is_buy { 1.0 } else { -1.0 };
+        let stop = entry - side * rules.stop_loss_pips * pip;
+        let target = entry + side * rules.take_profit_pips * pip;
+        let (stopped, reached) = if is_buy {
+            (candle.low <= stop, candle.high >= target)
+        } else {
+            (candle.high >= stop, candle.low <= target)
+        };
+        // Which level a candle touched first is unknown, so the stop is assumed
+        if stopped {
+            trades.push(-rules.stop_loss_pips);
+            position = None;
+        } else if reached {
+            trades.push(rules.take_profit_pips);
+            position = None;
+        }
+    }
+
+    if let (Some((is_buy, entry)), Some(last)) = (position, candles.last()) {
+        let side = if is_buy { 1.0 } else { -1.0 };
+        trades.push((last.close - entry) * side / pip);
+    }
+    trades
+}
+
+// Backtests every symbol in the robot's risk_config.symbols on historical candles
+pub struct CandleBacktestEngine {
+    candles: Arc<dyn CandleSource>,
+}
+
+impl CandleBacktestEngine {
+    pub fn new(candles: Arc<dyn CandleSource>) -> Self {
+        CandleBacktestEngine { candles }
+    }
+}
+
+#[async_trait]
+impl BacktestEngine for CandleBacktestEngine {
+    async fn run(
+        &self,
+        robot: &TradingRobot,
+        risk_config: &serde_json::Value,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<BacktestMetrics> {
+        let rules = BacktestRules::from_risk_config(risk_config).map_err(AppError::Validation)?;
+        let symbols: Vec<&str> = risk_config
+            .get("symbols")
+            .and_then(|s| s.as_array())
+            .map(|symbols| symbols.iter().filter_map(|s| s.as_str()).collect())
+            .unwrap_or_default();
+        if symbols.is_empty() {
+            return Err(AppError::Unprocessable(format!("Robot {} has no symbols to backtest", robot.name)));
+        }
+
+        let mut trades = Vec::new();
+        for symbol in symbols {
+            let candles = self.candles.candles(symbol, start, end).await?;
+            trades.extend(simulate(&candles, &rules, pip_size(symbol)));
+        }
+        Ok(BacktestMetrics::from_trades(&trades))
+    }
+}
@@ -0,0 +1,282 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+use crate::services::websocket_manager::{WebSocketManager, WebSocketMessage};
+
+pub const PROGRESS_EVERY_CANDLES: u64 = 250;
+const FINISHED_JOB_RETENTION_MINUTES: i64 = 60;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BacktestProgress {
+    pub job_id: Uuid,
+    pub percent_complete: f64,
+    pub candles_processed: u64,
+    pub total_candles: u64,
+    pub trades_simulated: u64,
+    pub current_equity: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BacktestEvent {
+    Progress(BacktestProgress),
+    Complete { job_id: Uuid, report_id: Uuid },
+    Failed { job_id: Uuid, error: String },
+}
+
+impl BacktestEvent {
+    pub fn to_message(&self) -> WebSocketMessage {
+        let (message_type, data) = match self {
+            BacktestEvent::Progress(progress) => ("backtest_progress", serde_json::json!(progress)),
+            BacktestEvent::Complete { job_id, report_id } => (
+                "backtest_complete",
+                serde_json::json!({ "job_id": job_id, "report_id": report_id }),
+            ),
+            BacktestEvent::Failed { job_id, error } => (
+                "backtest_failed",
+                serde_json::json!({ "job_id": job_id, "error": error }),
+            ),
+        };
+
+        WebSocketMessage {
+            message_type: message_type.to_string(),
+            data,
+            timestamp: Utc::now(),
+            seq: None,
+        }
+    }
+
+    fn is_final(&self) -> bool {
+        !matches!(self, BacktestEvent::Progress(_))
+    }
+}
+
+// Where backtest events are delivered; the WebSocket manager in production
+#[async_trait]
+pub trait BacktestEventSink: Send + Sync {
+    async fn publish(&self, user_id: Uuid, message: WebSocketMessage);
+}
+
+#[async_trait]
+impl BacktestEventSink for WebSocketManager {
+    async fn publish(&self, user_id: Uuid, message: WebSocketMessage) {
+        if let Err(e) = self.send_to_user(user_id, message).await {
+            tracing::warn!("Failed to publish backtest event to user {}: {}", user_id, e);
+        }
+    }
+}
+
+struct BacktestJob {
+    user_id: Uuid,
+    last_event: BacktestEvent,
+    finished_at: Option<DateTime<Utc>>,
+}
+
+// Last known state of every backtest job, so clients that subscribe late can catch up
+#[derive(Default)]
+pub struct BacktestJobRegistry {
+    jobs: Mutex<HashMap<Uuid, BacktestJob>>,
+}
+
+impl BacktestJobRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn record(&self, user_id: Uuid, event: BacktestEvent) {
+        let now = Utc::now();
+        let mut jobs = self.jobs.lock().unwrap();
+
+        // Finished jobs only need to linger long enough for a reconnecting client
+        let cutoff = now - Duration::minutes(FINISHED_JOB_RETENTION_MINUTES);
+        jobs.retain(|_, job| !matches!(job.finished_at, Some(at) if at <= cutoff));
+
+        let job_id = match &event {
+            BacktestEvent::Progress(progress) => progress.job_id,
+            BacktestEvent::Complete { job_id, .. } | BacktestEvent::Failed { job_id, .. } => *job_id,
+        };
+        let finished_at = event.is_final().then_some(now);
+        jobs.insert(job_id, BacktestJob { user_id, last_event: event, finished_at });
+    }
+
+    // Catch-up message for a job, only for its owner
+    pub fn snapshot(&self, user_id: Uuid, job_id: Uuid) -> Option<WebSocketMessage> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .get(&job_id)
+            .filter(|job| job.user_id == user_id)
+            .map(|job| job.last_event.to_message())
+    }
+}
+
+// Handed to the backtest worker; throttles progress to one event every few hundred candles
+pub struct BacktestProgressReporter {
+    job_id: Uuid,
+    user_id: Uuid,
+    total_candles: u64,
+    every_candles: u64,
+    last_reported: Option<u64>,
+    registry: Arc<BacktestJobRegistry>,
+    sink: Arc<dyn BacktestEventSink>,
+}
+
+impl BacktestProgressReporter {
+    pub fn start(
+        job_id: Uuid,
+        user_id: Uuid,
+        total_candles: u64,
+        registry: Arc<BacktestJobRegistry>,
+        sink: Arc<dyn BacktestEventSink>,
+    ) -> Self {
+        BacktestProgressReporter {
+            job_id,
+            user_id,
+            total_candles,
+            every_candles: PROGRESS_EVERY_CANDLES,
+            last_reported: None,
+            registry,
+            sink,
+        }
+    }
+
+    pub fn with_interval(mut self, every_candles: u64) -> Self {
+        self.every_candles = every_candles.max(1);
+        self
+    }
+
+    pub async fn candle(&mut self, candles_processed: u64, trades_simulated: u64, current_equity: f64) {
+        let due = match self.last_reported {
+            None => true,
+            Some(last) => candles_processed >= last + self.every_candles || candles_processed >= self.total_candles,
+        };
+        if !due || self.last_reported == Some(candles_processed) {
+            return;
+        }
+        self.last_reported = Some(candles_processed);
+
+        let percent_complete = if self.total_candles > 0 {
+            ((candles_processed as f64 / self.total_candles as f64) * 100.0).min(100.0)
+        } else {
+            100.0
+        };
+
+        self.emit(BacktestEvent::Progress(BacktestProgress {
+            job_id: self.job_id,
+            percent_complete,
+            candles_processed,
+            total_candles: self.total_candles,
+            trades_simulated,
+            current_equity,
+        }))
+        .await;
+    }
+
+    pub async fn complete(self, report_id: Uuid) {
+        let event = BacktestEvent::Complete { job_id: self.job_id, report_id };
+        self.emit(event).await;
+    }
+
+    pub async fn fail(self, error: &str) {
+        let event = BacktestEvent::Failed {
+            job_id: self.job_id,
+            error: error.to_string(),
+        };
+        self.emit(event).await;
+    }
+
+    async fn emit(&self, event: BacktestEvent) {
+        let message = event.to_message();
+        self.registry.record(self.user_id, event);
+        self.sink.publish(self.user_id, message).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct RecordingSink {
+        sent: Mutex<Vec<(Uuid, WebSocketMessage)>>,
+    }
+
+    impl RecordingSink {
+        fn types(&self) -> Vec<String> {
+            self.sent.lock().unwrap().iter().map(|(_, m)| m.message_type.clone()).collect()
+        }
+    }
+
+    #[async_trait]
+    impl BacktestEventSink for RecordingSink {
+        async fn publish(&self, user_id: Uuid, message: WebSocketMessage) {
+            self.sent.lock().unwrap().push((user_id, message));
+        }
+    }
+
+    // Stands in for the backtest engine: one trade every 100 candles, equity drifting up
+    async fn run_mock_backtest(reporter: &mut BacktestProgressReporter, candles: u64) {
+        for candle in 1..=candles {
+            reporter.candle(candle, candle / 100, 10_000.0 + candle as f64).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_progress_events_are_ordered_and_throttled() {
+        let registry = Arc::new(BacktestJobRegistry::new());
+        let sink = Arc::new(RecordingSink::default());
+        let user_id = Uuid::new_v4();
+        let job_id = Uuid::new_v4();
+        let report_id = Uuid::new_v4();
+
+        let mut reporter = BacktestProgressReporter::start(job_id, user_id, 1000, registry.clone(), sink.clone());
+        run_mock_backtest(&mut reporter, 1000).await;
+        reporter.complete(report_id).await;
+
+        let sent = sink.sent.lock().unwrap();
+        let progress: Vec<_> = sent
+            .iter()
+            .filter(|(_, m)| m.message_type == "backtest_progress")
+            .map(|(_, m)| m.data["candles_processed"].as_u64().unwrap())
+            .collect();
+        assert_eq!(progress, vec![1, 251, 501, 751, 1000]);
+
+        let last_progress = &sent[sent.len() - 2].1;
+        assert_eq!(last_progress.data["percent_complete"], 100.0);
+        assert_eq!(last_progress.data["trades_simulated"], 10);
+        assert_eq!(last_progress.data["current_equity"], 11_000.0);
+
+        let (owner, complete) = sent.last().unwrap();
+        assert_eq!(*owner, user_id);
+        assert_eq!(complete.message_type, "backtest_complete");
+        assert_eq!(complete.data["report_id"], serde_json::json!(report_id));
+    }
+
+    #[tokio::test]
+    async fn test_late_subscriber_gets_last_snapshot() {
+        let registry = Arc::new(BacktestJobRegistry::new());
+        let sink = Arc::new(RecordingSink::default());
+        let user_id = Uuid::new_v4();
+        let job_id = Uuid::new_v4();
+
+        let mut reporter = BacktestProgressReporter::start(job_id, user_id, 1000, registry.clone(), sink.clone())
+            .with_interval(100);
+        run_mock_backtest(&mut reporter, 450).await;
+
+        let catch_up = registry.snapshot(user_id, job_id).unwrap();
+        assert_eq!(catch_up.message_type, "backtest_progress");
+        assert_eq!(catch_up.data["candles_processed"], 401);
+
+        // Other users cannot peek at the job
+        assert!(registry.snapshot(Uuid::new_v4(), job_id).is_none());
+
+        reporter.fail("market data gap").await;
+        let catch_up = registry.snapshot(user_id, job_id).unwrap();
+        assert_eq!(catch_up.message_type, "backtest_failed");
+        assert_eq!(catch_up.data["error"], "market data gap");
+        assert_eq!(sink.types().last().unwrap(), "backtest_failed");
+    }
+}
@@ -0,0 +1,448 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    errors::Result,
+    models::{AccountInfo, AccountSnapshot, BridgeToken, BrokerConnection, SnapshotGranularity, Trade, TradingRobot, TradingSession},
+    services::{
+        event_bus::{DomainEvent, EventPublisher},
+        AccountSnapshotService,
+    },
+};
+
+pub const BRIDGE_TOKEN_HEADER: &str = "x-bridge-token";
+// While a connection's bridge pushed within this window the snapshot poll leaves it alone
+pub const BRIDGE_ACTIVE_MINUTES: i64 = 15;
+
+// What the MT5 bridge pushes. Tickets and deal ids are the broker's; fills and closes are
+// applied once per deal id, whatever order they arrive in.
+#[derive(Debug, Clone, PartialEq, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BridgeEvent {
+    OrderFilled {
+        deal_id: i64,
+        ticket: i64,
+        price: f64,
+    },
+    PositionClosed {
+        deal_id: i64,
+        ticket: i64,
+        price: f64,
+        // The broker's result, before commission and swap
+        profit: f64,
+        #[serde(default)]
+        commission: f64,
+        #[serde(default)]
+        swap: f64,
+    },
+    AccountUpdate {
+        balance: f64,
+        equity: f64,
+        margin: f64,
+        free_margin: f64,
+        currency: String,
+    },
+}
+
+impl BridgeEvent {
+    fn kind(&self) -> &'static str {
+        match self {
+            BridgeEvent::OrderFilled { .. } => "order_filled",
+            BridgeEvent::PositionClosed { .. } => "position_closed",
+            BridgeEvent::AccountUpdate { .. } => "account_update",
+        }
+    }
+}
+
+// `unmatched` means no trade carries the ticket yet; the bridge should deliver the event again
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, JsonSchema)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum BridgeEventOutcome {
+    Applied,
+    Duplicate,
+    Unmatched,
+}
+
+#[async_trait]
+pub trait BridgeStore: Send + Sync {
+    // False when the deal was already applied
+    async fn claim_deal(&self, connection_id: Uuid, deal_id: i64, kind: &str) -> Result<bool>;
+    async fn release_deal(&self, connection_id: Uuid, deal_id: i64) -> Result<()>;
+    async fn find_trade(&self, connection_id: Uuid, ticket: &str) -> Result<Option<Trade>>;
+    async fn record_fill(&self, trade_id: Uuid, entry_price: f64) -> Result<bool>;
+    async fn close(&self, trade_id: Uuid, exit_price: f64, profit_loss: f64, commission: f64, swap: f64) -> Result<bool>;
+    async fn refresh_robot(&self, robot_id: Uuid) -> Result<()>;
+    async fn save_snapshot(&self, snapshot: &AccountSnapshot) -> Result<()>;
+    async fn touch(&self, connection_id: Uuid, at: DateTime<Utc>) -> Result<()>;
+}
+
+pub struct PgBridgeStore {
+    pool: PgPool,
+}
+
+impl PgBridgeStore {
+    pub fn new(pool: PgPool) -> Self {
+        PgBridgeStore { pool }
+    }
+}
+
+#[async_trait]
+impl BridgeStore for PgBridgeStore {
+    async fn claim_deal(&self, connection_id: Uuid, deal_id: i64, kind: &str) -> Result<bool> {
+        BridgeToken::claim_deal(&self.pool, connection_id, deal_id, kind).await
+    }
+
+    async fn release_deal(&self, connection_id: Uuid, deal_id: i64) -> Result<()> {
+        BridgeToken::release_deal(&self.pool, connection_id, deal_id).await
+    }
+
+    async fn find_trade(&self, connection_id: Uuid, ticket: &str) -> Result<Option<Trade>> {
+        Trade::find_by_broker_ticket(&self.pool, connection_id, ticket).await
+    }
+
+    async fn record_fill(&self, trade_id: Uuid, entry_price: f64) -> Result<bool> {
+        Trade::record_fill(&self.pool, trade_id, entry_price).await
+    }
+
+    async fn close(&self, trade_id: Uuid, exit_price: f64, profit_loss: f64, commission: f64, swap: f64) -> Result<bool> {
+        Trade::close_from_broker(&self.pool, trade_id, exit_price, profit_loss, commission, swap).await
+    }
+
+    async fn refresh_robot(&self, robot_id: Uuid) -> Result<()> {
+        TradingRobot::refresh_performance(&self.pool, robot_id).await?;
+        TradingSession::refresh_active_for_robot(&self.pool, robot_id).await?;
+        Ok(())
+    }
+
+    async fn save_snapshot(&self, snapshot: &AccountSnapshot) -> Result<()> {
+        AccountSnapshot::upsert(&self.pool, snapshot).await
+    }
+
+    async fn touch(&self, connection_id: Uuid, at: DateTime<Utc>) -> Result<()> {
+        BridgeToken::touch(&self.pool, connection_id, at).await
+    }
+}
+
+pub struct BridgeEvents;
+
+impl BridgeEvents {
+    pub fn new_token() -> String {
+        format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+    }
+
+    pub fn hash_token(token: &str) -> String {
+        hex::encode(Sha256::digest(token.as_bytes()))
+    }
+
+    // Applies one pushed event to the connection it was authenticated for. A fill or close
+    // that fails is forgotten again so the bridge's retry is not taken for a duplicate.
+    pub async fn apply(
+        store: &dyn BridgeStore,
+        events: &dyn EventPublisher,
+        connection: &BrokerConnection,
+        event: &BridgeEvent,
+        now: DateTime<Utc>,
+    ) -> Result<BridgeEventOutcome> {
+        store.touch(connection.id, now).await?;
+
+        let deal_id = match event {
+            BridgeEvent::AccountUpdate { balance, equity, margin, free_margin, currency } => {
+                let info = AccountInfo {
+                    account_number: connection.login.clone().unwrap_or_default(),
+                    balance: *balance,
+                    equity: *equity,
+                    margin: *margin,
+                    free_margin: *free_margin,
+                    currency: currency.clone(),
+                };
+                let hour = AccountSnapshotService::hour_start(now);
+                store.save_snapshot(&AccountSnapshot::new(connection, &info, SnapshotGranularity::Hour, hour)).await?;
+                return Ok(BridgeEventOutcome::Applied);
+            }
+            BridgeEvent::OrderFilled { deal_id, .. } | BridgeEvent::PositionClosed { deal_id, .. } => *deal_id,
+        };
+
+        if !store.claim_deal(connection.id, deal_id, event.kind()).await? {
+            return Ok(BridgeEventOutcome::Duplicate);
+        }
+        let outcome = Self::apply_deal(store, events, connection.id, event).await;
+        if !matches!(outcome, Ok(BridgeEventOutcome::Applied)) {
+            store.release_deal(connection.id, deal_id).await?;
+        }
+        outcome
+    }
+
+    async fn apply_deal(
+        store: &dyn BridgeStore,
+        events: &dyn EventPublisher,
+        connection_id: Uuid,
+        event: &BridgeEvent,
+    ) -> Result<BridgeEventOutcome> {
+        let ticket = match event {
+            BridgeEvent::OrderFilled { ticket, .. } | BridgeEvent::PositionClosed { ticket, .. } => ticket.to_string(),
+            BridgeEvent::AccountUpdate { .. } => return Ok(BridgeEventOutcome::Applied),
+        };
+        let Some(trade) = store.find_trade(connection_id, &ticket).await? else {
+            tracing::debug!("No trade for ticket {} on connection {} yet", ticket, connection_id);
+            return Ok(BridgeEventOutcome::Unmatched);
+        };
+
+        match event {
+            BridgeEvent::OrderFilled { price, .. } => {
+                if !store.record_fill(trade.id, *price).await? {
+                    tracing::warn!("Broker reported a fill for trade {}, which is {}", trade.id, trade.status);
+                } else if trade.status != "closed" {
+                    events.publish(DomainEvent::OrderFilled {
+                        trade: Box::new(Trade { entry_price: *price, status: "open".to_string(), ..trade }),
+                    });
+                }
+            }
+            BridgeEvent::PositionClosed { price, profit, commission, swap, .. } => {
+                // Closed here first, e.g. by a platform stop; that close stands
+                if store.close(trade.id, *price, *profit, *commission, *swap).await? {
+                    if let Err(e) = store.refresh_robot(trade.robot_id).await {
+                        tracing::warn!("Failed to refresh rollups for robot {}: {}", trade.robot_id, e);
+                    }
+                    events.publish(DomainEvent::TradeClosed {
+                        trade: Box::new(Trade {
+                            exit_price: Some(*price),
+                            profit_loss: Some(*profit),
+                            commission: Some(*commission),
+                            swap: Some(*swap),
+                            status: "closed".to_string(),
+                            closed_at: Some(Utc::now()),
+                            ..trade
+                        }),
+                    });
+                }
+            }
+            BridgeEvent::AccountUpdate { .. } => {}
+        }
+        Ok(BridgeEventOutcome::Applied)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::order_drain::EXECUTION_PENDING;
+    use std::collections::{HashMap, HashSet};
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct FakeStore {
+        deals: Mutex<HashSet<(Uuid, i64)>>,
+        trades: Mutex<HashMap<String, Trade>>,
+        refreshed: Mutex<Vec<Uuid>>,
+        snapshots: Mutex<Vec<AccountSnapshot>>,
+        touched: Mutex<Vec<DateTime<Utc>>>,
+    }
+
+    impl FakeStore {
+        fn trade(&self, ticket: &str) -> Trade {
+            self.trades.lock().unwrap()[ticket].clone()
+        }
+
+        fn update(&self, trade_id: Uuid, apply: impl FnOnce(&mut Trade) -> bool) -> bool {
+            let mut trades = self.trades.lock().unwrap();
+            trades.values_mut().find(|t| t.id == trade_id).is_some_and(apply)
+        }
+    }
+
+    #[async_trait]
+    impl BridgeStore for FakeStore {
+        async fn claim_deal(&self, connection_id: Uuid, deal_id: i64, _kind: &str) -> Result<bool> {
+            Ok(self.deals.lock().unwrap().insert((connection_id, deal_id)))
+        }
+
+        async fn release_deal(&self, connection_id: Uuid, deal_id: i64) -> Result<()> {
+            self.deals.lock().unwrap().remove(&(connection_id, deal_id));
+            Ok(())
+        }
+
+        async fn find_trade(&self, _connection_id: Uuid, ticket: &str) -> Result<Option<Trade>> {
+            Ok(self.trades.lock().unwrap().get(ticket).cloned())
+        }
+
+        async fn record_fill(&self, trade_id: Uuid, entry_price: f64) -> Result<bool> {
+            Ok(self.update(trade_id, |trade| {
+                if trade.status == "cancelled" {
+                    return false;
+                }
+                trade.entry_price = entry_price;
+                if trade.status == EXECUTION_PENDING {
+                    trade.status = "open".to_string();
+                }
+                true
+            }))
+        }
+
+        async fn close(&self, trade_id: Uuid, exit_price: f64, profit_loss: f64, commission: f64, swap: f64) -> Result<bool> {
+            Ok(self.update(trade_id, |trade| {
+                if trade.status != "open" && trade.status != EXECUTION_PENDING {
+                    return false;
+                }
+                trade.exit_price = Some(exit_price);
+                trade.profit_loss = Some(profit_loss);
+                trade.commission = Some(commission);
+                trade.swap = Some(swap);
+                trade.status = "closed".to_string();
+                true
+            }))
+        }
+
+        async fn refresh_robot(&self, robot_id: Uuid) -> Result<()> {
+            self.refreshed.lock().unwrap().push(robot_id);
+            Ok(())
+        }
+
+        async fn save_snapshot(&self, snapshot: &AccountSnapshot) -> Result<()> {
+            self.snapshots.lock().unwrap().push(snapshot.clone());
+            Ok(())
+        }
+
+        async fn touch(&self, _connection_id: Uuid, at: DateTime<Utc>) -> Result<()> {
+            self.touched.lock().unwrap().push(at);
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct RecordingPublisher {
+        events: Mutex<Vec<DomainEvent>>,
+    }
+
+    impl EventPublisher for RecordingPublisher {
+        fn publish(&self, event: DomainEvent) {
+            self.events.lock().unwrap().push(event);
+        }
+    }
+
+    impl RecordingPublisher {
+        fn names(&self) -> Vec<&'static str> {
+            self.events.lock().unwrap().iter().map(|e| e.name()).collect()
+        }
+    }
+
+    fn connection() -> BrokerConnection {
+        BrokerConnection::new(Uuid::new_v4(), "Main".to_string(), "MT5".to_string(), "key".to_string(), "secret".to_string(), None, None, true)
+    }
+
+    fn store_with_trade(ticket: &str, status: &str) -> FakeStore {
+        let mut trade = Trade::new(Uuid::new_v4(), Uuid::new_v4(), "EURUSD".to_string(), "buy".to_string(), 0.1, 1.1000, None, None, None, None);
+        trade.status = status.to_string();
+        trade.broker_trade_id = Some(ticket.to_string());
+        let store = FakeStore::default();
+        store.trades.lock().unwrap().insert(ticket.to_string(), trade);
+        store
+    }
+
+    fn filled(deal_id: i64) -> BridgeEvent {
+        BridgeEvent::OrderFilled { deal_id, ticket: 501, price: 1.1004 }
+    }
+
+    fn closed(deal_id: i64) -> BridgeEvent {
+        BridgeEvent::PositionClosed { deal_id, ticket: 501, price: 1.1050, profit: 46.0, commission: -0.7, swap: -0.12 }
+    }
+
+    fn assert_settled(trade: &Trade) {
+        assert_eq!(trade.status, "closed");
+        assert_eq!(trade.entry_price, 1.1004);
+        assert_eq!(trade.exit_price, Some(1.1050));
+        assert_eq!((trade.profit_loss, trade.commission, trade.swap), (Some(46.0), Some(-0.7), Some(-0.12)));
+    }
+
+    #[tokio::test]
+    async fn test_redelivered_deals_are_applied_once() {
+        let store = store_with_trade("501", EXECUTION_PENDING);
+        let events = RecordingPublisher::default();
+        let connection = connection();
+        let apply = |event: BridgeEvent| {
+            let (store, events, connection) = (&store, &events, &connection);
+            async move { BridgeEvents::apply(store, events, connection, &event, Utc::now()).await.unwrap() }
+        };
+
+        assert_eq!(apply(filled(9001)).await, BridgeEventOutcome::Applied);
+        assert_eq!(store.trade("501").status, "open");
+        assert_eq!(apply(filled(9001)).await, BridgeEventOutcome::Duplicate);
+        assert_eq!(apply(closed(9002)).await, BridgeEventOutcome::Applied);
+        assert_eq!(apply(closed(9002)).await, BridgeEventOutcome::Duplicate);
+        assert_eq!(apply(filled(9001)).await, BridgeEventOutcome::Duplicate);
+
+        assert_settled(&store.trade("501"));
+        assert_eq!(events.names(), vec!["order_filled", "trade_closed"]);
+        assert_eq!(store.refreshed.lock().unwrap().len(), 1);
+        // Every delivery counts as the bridge being alive, duplicates included
+        assert_eq!(store.touched.lock().unwrap().len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_close_before_fill_settles_the_same_way() {
+        let store = store_with_trade("501", EXECUTION_PENDING);
+        let events = RecordingPublisher::default();
+        let connection = connection();
+
+        let close = BridgeEvents::apply(&store, &events, &connection, &closed(9002), Utc::now()).await.unwrap();
+        let fill = BridgeEvents::apply(&store, &events, &connection, &filled(9001), Utc::now()).await.unwrap();
+
+        assert_eq!((close, fill), (BridgeEventOutcome::Applied, BridgeEventOutcome::Applied));
+        // The late fill corrects the entry price without reopening the trade
+        assert_settled(&store.trade("501"));
+        assert_eq!(events.names(), vec!["trade_closed"]);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_ticket_is_retried_once_the_trade_is_stored() {
+        let store = FakeStore::default();
+        let events = RecordingPublisher::default();
+        let connection = connection();
+
+        let early = BridgeEvents::apply(&store, &events, &connection, &filled(9001), Utc::now()).await.unwrap();
+        assert_eq!(early, BridgeEventOutcome::Unmatched);
+        assert!(store.deals.lock().unwrap().is_empty());
+
+        // The placement stored its ticket in the meantime, so the redelivery lands
+        let trade = store_with_trade("501", "open").trade("501");
+        store.trades.lock().unwrap().insert("501".to_string(), trade);
+        let retried = BridgeEvents::apply(&store, &events, &connection, &filled(9001), Utc::now()).await.unwrap();
+        assert_eq!(retried, BridgeEventOutcome::Applied);
+        assert_eq!(store.trade("501").entry_price, 1.1004);
+    }
+
+    #[tokio::test]
+    async fn test_account_update_is_this_hours_snapshot() {
+        let store = FakeStore::default();
+        let connection = connection();
+        let update = serde_json::from_value::<BridgeEvent>(serde_json::json!({
+            "type": "account_update",
+            "balance": 5100.0,
+            "equity": 5146.0,
+            "margin": 110.0,
+            "free_margin": 5036.0,
+            "currency": "USD",
+        }))
+        .unwrap();
+        let now = Utc::now();
+
+        let outcome = BridgeEvents::apply(&store, &RecordingPublisher::default(), &connection, &update, now).await.unwrap();
+
+        assert_eq!(outcome, BridgeEventOutcome::Applied);
+        let snapshots = store.snapshots.lock().unwrap();
+        assert_eq!((snapshots[0].connection_id, snapshots[0].equity), (connection.id, 5146.0));
+        assert_eq!(snapshots[0].captured_at, AccountSnapshotService::hour_start(now));
+    }
+
+    #[test]
+    fn test_tokens_are_stored_hashed() {
+        let token = BridgeEvents::new_token();
+        assert_eq!(token.len(), 64);
+        assert_ne!(token, BridgeEvents::new_token());
+        assert_eq!(BridgeEvents::hash_token(&token), BridgeEvents::hash_token(&token));
+        assert_ne!(BridgeEvents::hash_token(&token), token);
+    }
+}
@@ -0,0 +1,313 @@
+use async_trait::async_trait;
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::{
+    errors::{AppError, Result},
+    models::{AccountInfo, BrokerAccountKey, BrokerConnection, BrokerConnectionResponse, CreateBrokerConnectionRequest, PendingBrokerConnection},
+    services::{CredentialVault, Mt5Service},
+};
+
+// Keeps a hanging broker from holding the create request open
+pub const CREATE_TEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[async_trait]
+pub trait ConnectionTester: Send + Sync {
+    async fn test_connection(&self, connection: &BrokerConnection) -> Result<AccountInfo>;
+}
+
+#[async_trait]
+impl ConnectionTester for Mt5Service {
+    async fn test_connection(&self, connection: &BrokerConnection) -> Result<AccountInfo> {
+        Mt5Service::test_connection(self, connection).await
+    }
+}
+
+// An inserted connection that is rolled back unless committed
+#[async_trait]
+pub trait PendingConnection: Send {
+    fn connection(&self) -> &BrokerConnection;
+    async fn commit(self: Box<Self>, test_status: &str) -> Result<BrokerConnection>;
+    async fn rollback(self: Box<Self>) -> Result<()>;
+}
+
+#[async_trait]
+impl PendingConnection for PendingBrokerConnection {
+    fn connection(&self) -> &BrokerConnection {
+        &self.connection
+    }
+
+    async fn commit(self: Box<Self>, test_status: &str) -> Result<BrokerConnection> {
+        PendingBrokerConnection::commit(*self, test_status).await
+    }
+
+    async fn rollback(self: Box<Self>) -> Result<()> {
+        PendingBrokerConnection::rollback(*self).await
+    }
+}
+
+#[async_trait]
+pub trait BrokerConnectionStore: Send + Sync {
+    async fn find_duplicate(&self, user_id: Uuid, account: &BrokerAccountKey) -> Result<Option<Uuid>>;
+    async fn begin_create(&self, connection: BrokerConnection) -> Result<Box<dyn PendingConnection>>;
+}
+
+pub struct PgBrokerConnectionStore {
+    pool: PgPool,
+    credentials: Arc<CredentialVault>,
+}
+
+impl PgBrokerConnectionStore {
+    pub fn new(pool: PgPool, credentials: Arc<CredentialVault>) -> Self {
+        PgBrokerConnectionStore { pool, credentials }
+    }
+}
+
+#[async_trait]
+impl BrokerConnectionStore for PgBrokerConnectionStore {
+    async fn find_duplicate(&self, user_id: Uuid, account: &BrokerAccountKey) -> Result<Option<Uuid>> {
+        BrokerConnection::find_duplicate(&self.pool, user_id, account).await
+    }
+
+    async fn begin_create(&self, connection: BrokerConnection) -> Result<Box<dyn PendingConnection>> {
+        let connection = self.credentials.seal(connection);
+        Ok(Box::new(BrokerConnection::begin_create(&self.pool, connection).await?))
+    }
+}
+
+pub struct BrokerConnectionService;
+
+impl BrokerConnectionService {
+    // A 409 naming the existing connection when the user already has one to the same account,
+    // unless this one is saved with allow_duplicate
+    pub async fn ensure_not_duplicate(store: &dyn BrokerConnectionStore, connection: &BrokerConnection) -> Result<()> {
+        if connection.allow_duplicate {
+            return Ok(());
+        }
+        let Some(account) = connection.account_key() else {
+            return Ok(());
+        };
+        match store.find_duplicate(connection.user_id, &account).await? {
+            Some(existing_connection_id) => Err(AppError::DuplicateConnection {
+                message: format!(
+                    "Login {} on {} is already connected; pass allow_duplicate to add it again",
+                    account.login,
+                    connection.server.as_deref().unwrap_or_default()
+                ),
+                existing_connection_id,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    // The connection is only committed once the broker accepts its credentials. A rejection or
+    // a broker that does not answer within `timeout` rolls the insert back and becomes a 422.
+    pub async fn create_tested(
+        store: &dyn BrokerConnectionStore,
+        tester: &dyn ConnectionTester,
+        user_id: Uuid,
+        request: CreateBrokerConnectionRequest,
+        timeout: Duration,
+    ) -> Result<BrokerConnectionResponse> {
+        let connection = BrokerConnection::from_request(user_id, request);
+        Self::ensure_not_duplicate(store, &connection).await?;
+        let pending = store.begin_create(connection).await?;
+        let account_info = match tokio::time::timeout(timeout, tester.test_connection(pending.connection())).await {
+            Ok(Ok(account_info)) => account_info,
+            Ok(Err(e)) => {
+                pending.rollback().await?;
+                return Err(match e {
+                    AppError::Mt5(message) | AppError::External(message) => AppError::Unprocessable(message),
+                    other => other,
+                });
+            }
+            Err(_) => {
+                pending.rollback().await?;
+                return Err(AppError::Unprocessable(format!(
+                    "Broker did not respond within {} seconds",
+                    timeout.as_secs()
+                )));
+            }
+        };
+
+        let connection = pending.commit("success").await?;
+        Ok(BrokerConnectionResponse {
+            account_info: Some(account_info),
+            ..connection.into()
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct FakeStore {
+        events: Arc<Mutex<Vec<String>>>,
+        // Connections already saved, with the user they belong to
+        existing: Vec<BrokerConnection>,
+    }
+
+    struct FakePending {
+        connection: BrokerConnection,
+        events: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl PendingConnection for FakePending {
+        fn connection(&self) -> &BrokerConnection {
+            &self.connection
+        }
+
+        async fn commit(self: Box<Self>, test_status: &str) -> Result<BrokerConnection> {
+            self.events.lock().unwrap().push(format!("commit {}", test_status));
+            Ok(BrokerConnection { last_test_status: Some(test_status.to_string()), ..self.connection })
+        }
+
+        async fn rollback(self: Box<Self>) -> Result<()> {
+            self.events.lock().unwrap().push("rollback".to_string());
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl BrokerConnectionStore for FakeStore {
+        async fn find_duplicate(&self, user_id: Uuid, account: &BrokerAccountKey) -> Result<Option<Uuid>> {
+            Ok(self
+                .existing
+                .iter()
+                .find(|c| c.user_id == user_id && c.account_key().as_ref() == Some(account))
+                .map(|c| c.id))
+        }
+
+        async fn begin_create(&self, connection: BrokerConnection) -> Result<Box<dyn PendingConnection>> {
+            self.events.lock().unwrap().push("insert".to_string());
+            Ok(Box::new(FakePending { connection, events: self.events.clone() }))
+        }
+    }
+
+    enum FakeBroker {
+        Accepts,
+        Rejects,
+        Hangs,
+    }
+
+    #[async_trait]
+    impl ConnectionTester for FakeBroker {
+        async fn test_connection(&self, _connection: &BrokerConnection) -> Result<AccountInfo> {
+            match self {
+                FakeBroker::Accepts => Ok(AccountInfo {
+                    account_number: "5012345".to_string(),
+                    balance: 2500.0,
+                    equity: 2500.0,
+                    margin: 0.0,
+                    free_margin: 2500.0,
+                    currency: "EUR".to_string(),
+                }),
+                FakeBroker::Rejects => Err(AppError::Mt5("Invalid account".to_string())),
+                FakeBroker::Hangs => std::future::pending().await,
+            }
+        }
+    }
+
+    fn request() -> CreateBrokerConnectionRequest {
+        CreateBrokerConnectionRequest {
+            name: "Main account".to_string(),
+            broker_type: "MT5".to_string(),
+            api_key: "key".to_string(),
+            api_secret: "secret".to_string(),
+            server: Some("Broker-Demo".to_string()),
+            login: Some("5012345".to_string()),
+            is_demo: true,
+            test_on_create: true,
+            allow_duplicate: false,
+        }
+    }
+
+    async fn create(store: &FakeStore, broker: FakeBroker) -> Result<BrokerConnectionResponse> {
+        BrokerConnectionService::create_tested(store, &broker, Uuid::new_v4(), request(), CREATE_TEST_TIMEOUT).await
+    }
+
+    // The same account as request(), spelled differently
+    fn saved_account(user_id: Uuid) -> BrokerConnection {
+        BrokerConnection::new(
+            user_id,
+            "Old name".to_string(),
+            "mt5".to_string(),
+            "key".to_string(),
+            "secret".to_string(),
+            Some("  broker-demo ".to_string()),
+            Some("5012345 ".to_string()),
+            true,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_accepted_credentials_are_saved_with_account_info() {
+        let store = FakeStore::default();
+
+        let response = create(&store, FakeBroker::Accepts).await.unwrap();
+
+        assert_eq!(response.last_test_status.as_deref(), Some("success"));
+        assert_eq!(response.account_info.unwrap().account_number, "5012345");
+        assert_eq!(*store.events.lock().unwrap(), vec!["insert", "commit success"]);
+    }
+
+    #[tokio::test]
+    async fn test_rejected_credentials_roll_back_with_the_broker_message() {
+        let store = FakeStore::default();
+
+        let err = create(&store, FakeBroker::Rejects).await.unwrap_err();
+
+        assert!(matches!(err, AppError::Unprocessable(ref message) if message == "Invalid account"), "{}", err);
+        assert_eq!(*store.events.lock().unwrap(), vec!["insert", "rollback"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_unresponsive_broker_times_out_and_rolls_back() {
+        let store = FakeStore::default();
+
+        let err = create(&store, FakeBroker::Hangs).await.unwrap_err();
+
+        assert!(matches!(err, AppError::Unprocessable(ref message) if message.contains("10 seconds")), "{}", err);
+        assert_eq!(*store.events.lock().unwrap(), vec!["insert", "rollback"]);
+    }
+
+    #[tokio::test]
+    async fn test_same_account_is_a_conflict_naming_the_existing_connection() {
+        let user_id = Uuid::new_v4();
+        let existing = saved_account(user_id);
+        let store = FakeStore { existing: vec![existing.clone()], ..FakeStore::default() };
+
+        let err = BrokerConnectionService::create_tested(&store, &FakeBroker::Accepts, user_id, request(), CREATE_TEST_TIMEOUT)
+            .await
+            .unwrap_err();
+
+        assert!(
+            matches!(err, AppError::DuplicateConnection { existing_connection_id, .. } if existing_connection_id == existing.id),
+            "{}",
+            err
+        );
+        assert!(store.events.lock().unwrap().is_empty());
+        // Another user's connection to the same account is not a duplicate
+        assert!(create(&store, FakeBroker::Accepts).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_allow_duplicate_saves_a_second_connection_to_the_account() {
+        let user_id = Uuid::new_v4();
+        let store = FakeStore { existing: vec![saved_account(user_id)], ..FakeStore::default() };
+        let request = CreateBrokerConnectionRequest { allow_duplicate: true, ..request() };
+
+        let response =
+            BrokerConnectionService::create_tested(&store, &FakeBroker::Accepts, user_id, request, CREATE_TEST_TIMEOUT).await.unwrap();
+
+        assert_eq!(response.last_test_status.as_deref(), Some("success"));
+        assert_eq!(*store.events.lock().unwrap(), vec!["insert", "commit success"]);
+    }
+}
@@ -0,0 +1,395 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::sync::{Arc, RwLock};
+use uuid::Uuid;
+
+use crate::{
+    errors::{AppError, Result},
+    models::{AdminSetting, BrokerMaintenance, BrokerMaintenanceRequest, BrokerMaintenanceScope, MaintenanceRobot, RobotLog, TradingRobot, User, BROKER_MAINTENANCE_SETTING},
+    services::{robot_runner::RobotRunnerRegistry, NotificationService},
+};
+
+// How often every instance picks up scopes set elsewhere and resumes robots after an expired `until`
+pub const BROKER_MAINTENANCE_SYNC_SECONDS: u64 = 15;
+
+// Stored scopes and the robots they pause
+#[async_trait]
+pub trait BrokerMaintenanceEnv: Send + Sync {
+    async fn load(&self) -> Result<BrokerMaintenance>;
+    async fn save(&self, maintenance: &BrokerMaintenance, updated_by: Uuid) -> Result<()>;
+    // Active robots on those broker types, or only `robot_id`; returns those paused
+    async fn pause(&self, broker_types: &[String], robot_id: Option<Uuid>) -> Result<Vec<MaintenanceRobot>>;
+    // Robots paused for a broker type no longer in `still_scoped`; returns those resumed
+    async fn resume(&self, still_scoped: &[String]) -> Result<Vec<MaintenanceRobot>>;
+    async fn log(&self, robot: &MaintenanceRobot, level: &str, message: &str) -> Result<()>;
+    async fn notify(&self, robot: &MaintenanceRobot, status: &str) -> Result<()>;
+}
+
+pub struct PgBrokerMaintenanceEnv {
+    pool: PgPool,
+    notifications: Arc<NotificationService>,
+}
+
+impl PgBrokerMaintenanceEnv {
+    pub fn new(pool: PgPool, notifications: Arc<NotificationService>) -> Self {
+        PgBrokerMaintenanceEnv { pool, notifications }
+    }
+}
+
+#[async_trait]
+impl BrokerMaintenanceEnv for PgBrokerMaintenanceEnv {
+    async fn load(&self) -> Result<BrokerMaintenance> {
+        AdminSetting::get(&self.pool, BROKER_MAINTENANCE_SETTING).await
+    }
+
+    async fn save(&self, maintenance: &BrokerMaintenance, updated_by: Uuid) -> Result<()> {
+        AdminSetting::put(&self.pool, BROKER_MAINTENANCE_SETTING, maintenance, updated_by).await
+    }
+
+    async fn pause(&self, broker_types: &[String], robot_id: Option<Uuid>) -> Result<Vec<MaintenanceRobot>> {
+        TradingRobot::pause_for_maintenance(&self.pool, broker_types, robot_id).await
+    }
+
+    async fn resume(&self, still_scoped: &[String]) -> Result<Vec<MaintenanceRobot>> {
+        TradingRobot::resume_after_maintenance(&self.pool, still_scoped).await
+    }
+
+    async fn log(&self, robot: &MaintenanceRobot, level: &str, message: &str) -> Result<()> {
+        RobotLog::create(&self.pool, robot.id, robot.user_id, level, message).await?;
+        Ok(())
+    }
+
+    async fn notify(&self, robot: &MaintenanceRobot, status: &str) -> Result<()> {
+        let user = User::find_by_id(&self.pool, robot.user_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+        self.notifications
+            .send_robot_status_notification(&user.email, &robot.name, status)
+            .await
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MaintenanceSync {
+    pub paused: usize,
+    pub resumed: usize,
+}
+
+// Maintenance scoped to one broker integration. Orders through connections of a scoped type are
+// refused and their robots paused; reads and other broker types carry on as normal.
+pub struct BrokerMaintenanceService {
+    env: Arc<dyn BrokerMaintenanceEnv>,
+    // Last loaded scopes, so order placement does not read the database
+    scopes: RwLock<Vec<BrokerMaintenanceScope>>,
+}
+
+impl BrokerMaintenanceService {
+    pub fn new(env: Arc<dyn BrokerMaintenanceEnv>) -> Self {
+        BrokerMaintenanceService { env, scopes: RwLock::new(Vec::new()) }
+    }
+
+    pub fn scope_for(&self, broker_type: &str, now: DateTime<Utc>) -> Option<BrokerMaintenanceScope> {
+        let broker_type = BrokerMaintenance::normalize(broker_type);
+        self.scopes
+            .read()
+            .unwrap()
+            .iter()
+            .find(|s| s.broker_type == broker_type && s.is_active(now))
+            .cloned()
+    }
+
+    // Called before an order is sent through a connection of `broker_type`
+    pub fn check_order(&self, broker_type: &str, now: DateTime<Utc>) -> Result<()> {
+        match self.scope_for(broker_type, now) {
+            Some(scope) => Err(AppError::BrokerUnavailable(format!(
+                "{} order placement is paused for maintenance: {}",
+                scope.broker_type, scope.message
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    pub async fn set(
+        &self,
+        broker_type: &str,
+        request: BrokerMaintenanceRequest,
+        runners: &RobotRunnerRegistry,
+        updated_by: Uuid,
+        now: DateTime<Utc>,
+    ) -> Result<BrokerMaintenance> {
+        if BrokerMaintenance::normalize(broker_type).is_empty() {
+            return Err(AppError::Validation("broker_type is required".to_string()));
+        }
+        if request.until.is_some_and(|until| until <= now) {
+            return Err(AppError::Validation("until must be in the future".to_string()));
+        }
+
+        let mut maintenance = self.env.load().await?;
+        maintenance.set(broker_type, request, now);
+        self.env.save(&maintenance, updated_by).await?;
+        self.sync(runners, now).await?;
+        Ok(maintenance)
+    }
+
+    pub async fn lift(
+        &self,
+        broker_type: &str,
+        runners: &RobotRunnerRegistry,
+        updated_by: Uuid,
+        now: DateTime<Utc>,
+    ) -> Result<BrokerMaintenance> {
+        let mut maintenance = self.env.load().await?;
+        if !maintenance.lift(broker_type) {
+            return Err(AppError::NotFound(format!("No maintenance scope for {}", BrokerMaintenance::normalize(broker_type))));
+        }
+        self.env.save(&maintenance, updated_by).await?;
+        self.sync(runners, now).await?;
+        Ok(maintenance)
+    }
+
+    // Reloads the scopes, pauses robots on newly scoped broker types and resumes those whose
+    // scope was lifted or expired
+    pub async fn sync(&self, runners: &RobotRunnerRegistry, now: DateTime<Utc>) -> Result<MaintenanceSync> {
+        let maintenance = self.env.load().await?;
+        let active = maintenance.active(now);
+        *self.scopes.write().unwrap() = maintenance.scopes;
+
+        let scoped: Vec<String> = active.iter().map(|s| s.broker_type.clone()).collect();
+        let mut report = MaintenanceSync::default();
+
+        for robot in self.env.resume(&scoped).await? {
+            runners.set_paused(robot.id, false);
+            report.resumed += 1;
+            let message = format!("{} maintenance is over, trading resumed", robot.broker_type);
+            self.record(&robot, "info", &message, "active (broker maintenance over)").await;
+        }
+
+        if !scoped.is_empty() {
+            for robot in self.env.pause(&scoped, None).await? {
+                self.paused(&robot, &active, runners).await;
+                report.paused += 1;
+            }
+        }
+
+        if report != MaintenanceSync::default() {
+            tracing::info!("Broker maintenance: {} robot(s) paused, {} resumed", report.paused, report.resumed);
+        }
+        Ok(report)
+    }
+
+    // A robot started while its broker type is scoped goes straight to paused_broker.
+    // Returns true if it was paused.
+    pub async fn pause_if_scoped(
+        &self,
+        robot_id: Uuid,
+        broker_type: &str,
+        runners: &RobotRunnerRegistry,
+        now: DateTime<Utc>,
+    ) -> Result<bool> {
+        let Some(scope) = self.scope_for(broker_type, now) else {
+            return Ok(false);
+        };
+        let paused = self.env.pause(std::slice::from_ref(&scope.broker_type), Some(robot_id)).await?;
+        for robot in &paused {
+            self.paused(robot, std::slice::from_ref(&scope), runners).await;
+        }
+        Ok(!paused.is_empty())
+    }
+
+    async fn paused(&self, robot: &MaintenanceRobot, scopes: &[BrokerMaintenanceScope], runners: &RobotRunnerRegistry) {
+        runners.set_paused(robot.id, true);
+        let reason = scopes
+            .iter()
+            .find(|s| s.broker_type == robot.broker_type)
+            .map(|s| s.message.as_str())
+            .unwrap_or("maintenance");
+        let message = format!("Paused: {} maintenance ({})", robot.broker_type, reason);
+        self.record(robot, "warn", &message, &format!("paused ({} maintenance)", robot.broker_type)).await;
+    }
+
+    async fn record(&self, robot: &MaintenanceRobot, level: &str, message: &str, status: &str) {
+        if let Err(e) = self.env.log(robot, level, message).await {
+            tracing::warn!("Could not write maintenance log for robot {}: {}", robot.id, e);
+        }
+        if let Err(e) = self.env.notify(robot, status).await {
+            tracing::warn!("Could not notify owner of robot {}: {}", robot.id, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+    use std::sync::Mutex;
+
+    struct FakeRobot {
+        robot: MaintenanceRobot,
+        status: String,
+        paused_for: Option<String>,
+    }
+
+    #[derive(Default)]
+    struct FakeEnv {
+        maintenance: Mutex<BrokerMaintenance>,
+        robots: Mutex<Vec<FakeRobot>>,
+        notifications: Mutex<Vec<(Uuid, String)>>,
+    }
+
+    impl FakeEnv {
+        fn with_robots(robots: &[(&str, &str)]) -> Self {
+            let env = FakeEnv::default();
+            for (name, broker_type) in robots {
+                env.robots.lock().unwrap().push(FakeRobot {
+                    robot: MaintenanceRobot {
+                        id: Uuid::new_v4(),
+                        user_id: Uuid::new_v4(),
+                        name: name.to_string(),
+                        broker_type: broker_type.to_string(),
+                    },
+                    status: "active".to_string(),
+                    paused_for: None,
+                });
+            }
+            env
+        }
+
+        fn status(&self, name: &str) -> String {
+            self.robots.lock().unwrap().iter().find(|r| r.robot.name == name).unwrap().status.clone()
+        }
+    }
+
+    #[async_trait]
+    impl BrokerMaintenanceEnv for FakeEnv {
+        async fn load(&self) -> Result<BrokerMaintenance> {
+            Ok(self.maintenance.lock().unwrap().clone())
+        }
+
+        async fn save(&self, maintenance: &BrokerMaintenance, _updated_by: Uuid) -> Result<()> {
+            *self.maintenance.lock().unwrap() = maintenance.clone();
+            Ok(())
+        }
+
+        async fn pause(&self, broker_types: &[String], robot_id: Option<Uuid>) -> Result<Vec<MaintenanceRobot>> {
+            let mut paused = Vec::new();
+            for robot in self.robots.lock().unwrap().iter_mut() {
+                if robot.status == "active"
+                    && broker_types.contains(&robot.robot.broker_type)
+                    && robot_id.is_none_or(|id| id == robot.robot.id)
+                {
+                    robot.status = "paused_broker".to_string();
+                    robot.paused_for = Some(robot.robot.broker_type.clone());
+                    paused.push(robot.robot.clone());
+                }
+            }
+            Ok(paused)
+        }
+
+        async fn resume(&self, still_scoped: &[String]) -> Result<Vec<MaintenanceRobot>> {
+            let mut resumed = Vec::new();
+            for robot in self.robots.lock().unwrap().iter_mut() {
+                match &robot.paused_for {
+                    Some(broker_type) if robot.status == "paused_broker" && !still_scoped.contains(broker_type) => {
+                        robot.status = "active".to_string();
+                        robot.paused_for = None;
+                        resumed.push(robot.robot.clone());
+                    }
+                    _ => {}
+                }
+            }
+            Ok(resumed)
+        }
+
+        async fn log(&self, _robot: &MaintenanceRobot, _level: &str, _message: &str) -> Result<()> {
+            Ok(())
+        }
+
+        async fn notify(&self, robot: &MaintenanceRobot, status: &str) -> Result<()> {
+            self.notifications.lock().unwrap().push((robot.id, status.to_string()));
+            Ok(())
+        }
+    }
+
+    fn at(minute: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 6, 3, 12, 0, 0).unwrap() + Duration::minutes(minute)
+    }
+
+    fn request(message: &str, until: Option<DateTime<Utc>>) -> BrokerMaintenanceRequest {
+        BrokerMaintenanceRequest { message: message.to_string(), until }
+    }
+
+    #[tokio::test]
+    async fn test_scope_pauses_only_its_broker_type_and_resumes_when_lifted() {
+        let env = Arc::new(FakeEnv::with_robots(&[("mt5 robot", "MT5"), ("paper robot", "PAPER")]));
+        let service = BrokerMaintenanceService::new(env.clone());
+        let runners = RobotRunnerRegistry::new();
+        let admin = Uuid::new_v4();
+
+        service.set("mt5", request("MT5 bridge degraded", None), &runners, admin, at(0)).await.unwrap();
+        assert_eq!((env.status("mt5 robot"), env.status("paper robot")), ("paused_broker".to_string(), "active".to_string()));
+        assert!(matches!(service.check_order("Mt5", at(1)), Err(AppError::BrokerUnavailable(message)) if message.contains("MT5 bridge degraded")));
+        assert!(service.check_order("PAPER", at(1)).is_ok());
+
+        // A second sync finds nothing new
+        assert_eq!(service.sync(&runners, at(2)).await.unwrap(), MaintenanceSync::default());
+
+        service.lift("MT5", &runners, admin, at(3)).await.unwrap();
+        assert_eq!(env.status("mt5 robot"), "active");
+        assert!(service.check_order("MT5", at(4)).is_ok());
+        let notified: Vec<String> = env.notifications.lock().unwrap().iter().map(|(_, status)| status.clone()).collect();
+        assert_eq!(notified, vec!["paused (MT5 maintenance)", "active (broker maintenance over)"]);
+        assert!(matches!(service.lift("MT5", &runners, admin, at(5)).await, Err(AppError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_scopes_and_expiry() {
+        let env = Arc::new(FakeEnv::with_robots(&[("mt5 robot", "MT5"), ("ctrader robot", "CTRADER"), ("paper robot", "PAPER")]));
+        let service = BrokerMaintenanceService::new(env.clone());
+        let runners = RobotRunnerRegistry::new();
+        let admin = Uuid::new_v4();
+
+        service.set("MT5", request("Bridge upgrade", Some(at(30))), &runners, admin, at(0)).await.unwrap();
+        let maintenance = service.set("ctrader", request("Provider outage", None), &runners, admin, at(5)).await.unwrap();
+        assert_eq!(maintenance.scopes.iter().map(|s| s.broker_type.as_str()).collect::<Vec<_>>(), vec!["CTRADER", "MT5"]);
+        assert_eq!(env.status("mt5 robot"), "paused_broker");
+        assert_eq!(env.status("ctrader robot"), "paused_broker");
+        assert_eq!(env.status("paper robot"), "active");
+
+        // Editing the message keeps the scope's start
+        let maintenance = service.set("MT5", request("Bridge upgrade, nearly done", Some(at(30))), &runners, admin, at(10)).await.unwrap();
+        assert_eq!(maintenance.scopes[1].since, at(0));
+
+        // The MT5 window runs out; the cTrader outage carries on
+        let report = service.sync(&runners, at(31)).await.unwrap();
+        assert_eq!(report, MaintenanceSync { paused: 0, resumed: 1 });
+        assert_eq!(env.status("mt5 robot"), "active");
+        assert_eq!(env.status("ctrader robot"), "paused_broker");
+        assert!(service.check_order("MT5", at(31)).is_ok());
+        assert!(service.check_order("CTRADER", at(31)).is_err());
+
+        assert!(matches!(
+            service.set("MT5", request("Late", Some(at(20))), &runners, admin, at(31)).await,
+            Err(AppError::Validation(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_robot_started_during_maintenance_is_paused() {
+        let env = Arc::new(FakeEnv::with_robots(&[("mt5 robot", "MT5"), ("other mt5 robot", "MT5")]));
+        let service = BrokerMaintenanceService::new(env.clone());
+        let runners = RobotRunnerRegistry::new();
+        service.set("MT5", request("Bridge upgrade", None), &runners, Uuid::new_v4(), at(0)).await.unwrap();
+
+        // Restarted by its owner while the scope is on
+        let robot_id = {
+            let mut robots = env.robots.lock().unwrap();
+            robots[0].status = "active".to_string();
+            robots[0].robot.id
+        };
+        assert!(service.pause_if_scoped(robot_id, "mt5", &runners, at(1)).await.unwrap());
+        assert_eq!(env.status("mt5 robot"), "paused_broker");
+        assert!(!service.pause_if_scoped(robot_id, "PAPER", &runners, at(1)).await.unwrap());
+    }
+}
@@ -0,0 +1,567 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use sqlx::PgPool;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::sync::oneshot;
+use tokio::time::{Duration, Instant};
+
+use crate::errors::{AppError, Result};
+use crate::models::RobotLog;
+use crate::services::execution_queue::{current_requester, ExecutionClass, ExecutionQueue, Requester};
+use crate::services::task_supervisor::{spawn_supervised, TaskClass};
+
+pub const DEFAULT_MAX_QUEUE_DEPTH: usize = 100;
+
+// Lower value = served first when several calls are waiting on the same connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BrokerCallPriority {
+    // Market orders and closes
+    Order = 0,
+    // Modifying or cancelling pending orders and stops
+    OrderMaintenance = 1,
+    Account = 2,
+    MarketData = 3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct BrokerRateLimit {
+    pub requests_per_second: f64,
+    pub burst: u32,
+}
+
+impl Default for BrokerRateLimit {
+    fn default() -> Self {
+        BrokerRateLimit {
+            requests_per_second: 5.0,
+            burst: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct ConnectionThrottleMetrics {
+    pub connection_id: String,
+    pub broker_type: String,
+    pub queue_depth: usize,
+    pub total_calls: u64,
+    pub queued_calls: u64,
+    pub timed_out_calls: u64,
+    pub avg_wait_ms: f64,
+    pub max_wait_ms: u64,
+    // Turned away because the queue was full
+    pub rejected_calls: u64,
+    pub wait_by_class: Vec<ClassWaitMetrics>,
+}
+
+// Queue wait of the calls that were queued and then served, per plan class
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct ClassWaitMetrics {
+    pub class: ExecutionClass,
+    pub served_calls: u64,
+    pub avg_wait_ms: f64,
+    pub max_wait_ms: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct WaitTotals {
+    served: u64,
+    total_wait_ms: u64,
+    max_wait_ms: u64,
+}
+
+struct Waiter {
+    notify: oneshot::Sender<()>,
+}
+
+// Told when a call is turned away because its connection's queue is full
+#[async_trait]
+pub trait QueueOverflowSink: Send + Sync {
+    async fn overflowed(&self, connection_id: &str, requester: &Requester, depth: usize);
+}
+
+// Leaves the note on the robot that made the call, so its owner sees why it failed
+pub struct PgQueueOverflowLog {
+    pool: PgPool,
+}
+
+impl PgQueueOverflowLog {
+    pub fn new(pool: PgPool) -> Self {
+        PgQueueOverflowLog { pool }
+    }
+}
+
+#[async_trait]
+impl QueueOverflowSink for PgQueueOverflowLog {
+    async fn overflowed(&self, connection_id: &str, requester: &Requester, depth: usize) {
+        let Some(robot_id) = requester.robot_id else {
+            return;
+        };
+        let message = format!(
+            "Broker call not sent: connection {} already has {} calls queued; it will be retried",
+            connection_id, depth
+        );
+        if let Err(e) = RobotLog::create(&self.pool, robot_id, requester.user_id, "warn", &message).await {
+            tracing::warn!("Could not log queue overflow for robot {}: {}", robot_id, e);
+        }
+    }
+}
+
+struct BucketState {
+    // Held with the tokens so a runtime limit change applies to the next refill
+    limit: BrokerRateLimit,
+    tokens: f64,
+    last_refill: Instant,
+    queue: ExecutionQueue<Waiter>,
+    dispatcher_running: bool,
+    total_calls: u64,
+    queued_calls: u64,
+    timed_out_calls: u64,
+    rejected_calls: u64,
+    total_wait_ms: u64,
+    max_wait_ms: u64,
+    class_waits: BTreeMap<ExecutionClass, WaitTotals>,
+}
+
+struct ConnectionBucket {
+    broker_type: String,
+    state: Mutex<BucketState>,
+}
+
+impl ConnectionBucket {
+    fn new(broker_type: &str, limit: BrokerRateLimit) -> Self {
+        ConnectionBucket {
+            broker_type: broker_type.to_string(),
+            state: Mutex::new(BucketState {
+                limit,
+                tokens: limit.burst as f64,
+                last_refill: Instant::now(),
+                queue: ExecutionQueue::new(),
+                dispatcher_running: false,
+                total_calls: 0,
+                queued_calls: 0,
+                timed_out_calls: 0,
+                rejected_calls: 0,
+                total_wait_ms: 0,
+                max_wait_ms: 0,
+                class_waits: BTreeMap::new(),
+            }),
+        }
+    }
+
+    fn refill(&self, state: &mut BucketState) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(state.last_refill).as_secs_f64();
+        state.tokens = (state.tokens + elapsed * state.limit.requests_per_second).min(state.limit.burst as f64);
+        state.last_refill = now;
+    }
+
+    fn time_until_next_token(&self, state: &BucketState) -> Duration {
+        let missing = (1.0 - state.tokens).max(0.0);
+        Duration::from_secs_f64(missing / state.limit.requests_per_second)
+    }
+
+    // Hands out tokens to queued callers in queue order until the queue drains
+    async fn dispatch(self: Arc<Self>) {
+        loop {
+            let sleep_for = {
+                let mut state = self.state.lock().unwrap();
+                self.refill(&mut state);
+
+                while state.tokens >= 1.0 {
+                    match state.queue.pop() {
+                        // A failed send means the caller already gave up; keep the token
+                        Some(waiter) => {
+                            if waiter.notify.send(()).is_ok() {
+                                state.tokens -= 1.0;
+                            }
+                        }
+                        None => break,
+                    }
+                }
+
+                if state.queue.is_empty() {
+                    state.dispatcher_running = false;
+                    return;
+                }
+
+                self.time_until_next_token(&state)
+            };
+
+            tokio::time::sleep(sleep_for).await;
+        }
+    }
+}
+
+pub struct BrokerThrottle {
+    limits: HashMap<String, BrokerRateLimit>,
+    // Runtime settings, taking precedence over `limits`
+    overrides: RwLock<HashMap<String, BrokerRateLimit>>,
+    default_limit: BrokerRateLimit,
+    max_queue_wait: Duration,
+    max_queue_depth: usize,
+    overflow: Option<Arc<dyn QueueOverflowSink>>,
+    buckets: Mutex<HashMap<String, Arc<ConnectionBucket>>>,
+}
+
+impl BrokerThrottle {
+    pub fn new(limits: HashMap<String, BrokerRateLimit>, max_queue_wait: Duration) -> Self {
+        // broker_type lookups are case-insensitive ("MT5" and "mt5" share a limit)
+        let limits = limits
+            .into_iter()
+            .map(|(broker_type, limit)| (broker_type.to_lowercase(), limit))
+            .collect();
+
+        BrokerThrottle {
+            limits,
+            overrides: RwLock::new(HashMap::new()),
+            default_limit: BrokerRateLimit::default(),
+            max_queue_wait,
+            max_queue_depth: DEFAULT_MAX_QUEUE_DEPTH,
+            overflow: None,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Calls queued per connection beyond which new ones are turned away as transient errors
+    pub fn with_max_queue_depth(mut self, depth: usize) -> Self {
+        self.max_queue_depth = depth.max(1);
+        self
+    }
+
+    pub fn with_overflow_sink(mut self, sink: Arc<dyn QueueOverflowSink>) -> Self {
+        self.overflow = Some(sink);
+        self
+    }
+
+    fn limit_for(&self, broker_type: &str) -> BrokerRateLimit {
+        let broker_type = broker_type.to_lowercase();
+        self.overrides
+            .read()
+            .unwrap()
+            .get(&broker_type)
+            .or_else(|| self.limits.get(&broker_type))
+            .copied()
+            .unwrap_or(self.default_limit)
+    }
+
+    // Replaces the runtime overrides; connections already throttled switch on their next refill
+    pub fn set_overrides(&self, overrides: &HashMap<String, BrokerRateLimit>) {
+        *self.overrides.write().unwrap() = overrides
+            .iter()
+            .map(|(broker_type, limit)| (broker_type.to_lowercase(), *limit))
+            .collect();
+
+        for bucket in self.buckets.lock().unwrap().values() {
+            let limit = self.limit_for(&bucket.broker_type);
+            let mut state = bucket.state.lock().unwrap();
+            bucket.refill(&mut state);
+            state.limit = limit;
+            state.tokens = state.tokens.min(limit.burst as f64);
+        }
+    }
+
+    fn bucket(&self, connection_id: &str, broker_type: &str) -> Arc<ConnectionBucket> {
+        let mut buckets = self.buckets.lock().unwrap();
+        buckets
+            .entry(connection_id.to_string())
+            .or_insert_with(|| Arc::new(ConnectionBucket::new(broker_type, self.limit_for(broker_type))))
+            .clone()
+    }
+
+    // Waits for a request slot on the connection, queued as the current requester (see
+    // execution_queue::on_behalf_of); errors as transient once max_queue_wait elapses or when the
+    // connection's queue is full
+    pub async fn acquire(
+        &self,
+        connection_id: &str,
+        broker_type: &str,
+        priority: BrokerCallPriority,
+    ) -> Result<()> {
+        let bucket = self.bucket(connection_id, broker_type);
+        let requester = current_requester();
+        let started = Instant::now();
+
+        let queued = {
+            let mut state = bucket.state.lock().unwrap();
+            state.total_calls += 1;
+            bucket.refill(&mut state);
+
+            if state.queue.is_empty() && state.tokens >= 1.0 {
+                state.tokens -= 1.0;
+                return Ok(());
+            }
+
+            if state.queue.len() >= self.max_queue_depth {
+                state.rejected_calls += 1;
+                Err(state.queue.len())
+            } else {
+                let (notify, receiver) = oneshot::channel();
+                state.queued_calls += 1;
+                state.queue.push(priority, &requester, Waiter { notify });
+
+                if !state.dispatcher_running {
+                    state.dispatcher_running = true;
+                    spawn_supervised("broker_throttle:dispatch", TaskClass::Background, bucket.clone().dispatch());
+                }
+                Ok(receiver)
+            }
+        };
+
+        let receiver = match queued {
+            Ok(receiver) => receiver,
+            Err(depth) => {
+                if let Some(sink) = &self.overflow {
+                    sink.overflowed(connection_id, &requester, depth).await;
+                }
+                return Err(AppError::BrokerUnavailable(format!(
+                    "Broker connection {} has {} calls queued, retry shortly",
+                    connection_id, depth
+                )));
+            }
+        };
+
+        let outcome = tokio::time::timeout(self.max_queue_wait, receiver).await;
+        let waited_ms = started.elapsed().as_millis() as u64;
+
+        let mut state = bucket.state.lock().unwrap();
+        match outcome {
+            Ok(Ok(())) => {
+                state.total_wait_ms += waited_ms;
+                state.max_wait_ms = state.max_wait_ms.max(waited_ms);
+                let class = state.class_waits.entry(requester.class).or_default();
+                class.served += 1;
+                class.total_wait_ms += waited_ms;
+                class.max_wait_ms = class.max_wait_ms.max(waited_ms);
+                Ok(())
+            }
+            _ => {
+                state.timed_out_calls += 1;
+                Err(AppError::BrokerUnavailable(format!(
+                    "Broker connection {} is busy, request waited {}ms",
+                    connection_id, waited_ms
+                )))
+            }
+        }
+    }
+
+    pub fn metrics(&self) -> Vec<ConnectionThrottleMetrics> {
+        let buckets = self.buckets.lock().unwrap();
+        let mut metrics: Vec<ConnectionThrottleMetrics> = buckets
+            .iter()
+            .map(|(connection_id, bucket)| {
+                let state = bucket.state.lock().unwrap();
+                let served = state.queued_calls.saturating_sub(state.timed_out_calls);
+                ConnectionThrottleMetrics {
+                    connection_id: connection_id.clone(),
+                    broker_type: bucket.broker_type.clone(),
+                    queue_depth: state.queue.len(),
+                    total_calls: state.total_calls,
+                    queued_calls: state.queued_calls,
+                    timed_out_calls: state.timed_out_calls,
+                    avg_wait_ms: if served > 0 {
+                        state.total_wait_ms as f64 / served as f64
+                    } else {
+                        0.0
+                    },
+                    max_wait_ms: state.max_wait_ms,
+                    rejected_calls: state.rejected_calls,
+                    wait_by_class: state
+                        .class_waits
+                        .iter()
+                        .map(|(class, totals)| ClassWaitMetrics {
+                            class: *class,
+                            served_calls: totals.served,
+                            avg_wait_ms: totals.total_wait_ms as f64 / totals.served as f64,
+                            max_wait_ms: totals.max_wait_ms,
+                        })
+                        .collect(),
+                }
+            })
+            .collect();
+        metrics.sort_by(|a, b| a.connection_id.cmp(&b.connection_id));
+        metrics
+    }
+}
+
+impl Default for BrokerThrottle {
+    fn default() -> Self {
+        Self::new(HashMap::new(), Duration::from_secs(5))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::execution_queue::on_behalf_of;
+    use std::sync::Arc;
+    use uuid::Uuid;
+
+    fn limited(requests_per_second: f64, burst: u32, max_wait_ms: u64) -> BrokerThrottle {
+        let mut limits = HashMap::new();
+        limits.insert("MT5".to_string(), BrokerRateLimit { requests_per_second, burst });
+        BrokerThrottle::new(limits, Duration::from_millis(max_wait_ms))
+    }
+
+    fn throttle(requests_per_second: f64, burst: u32, max_wait_ms: u64) -> Arc<BrokerThrottle> {
+        Arc::new(limited(requests_per_second, burst, max_wait_ms))
+    }
+
+    #[derive(Default)]
+    struct RecordingSink {
+        overflows: Mutex<Vec<(String, Option<Uuid>, usize)>>,
+    }
+
+    #[async_trait]
+    impl QueueOverflowSink for RecordingSink {
+        async fn overflowed(&self, connection_id: &str, requester: &Requester, depth: usize) {
+            self.overflows.lock().unwrap().push((connection_id.to_string(), requester.robot_id, depth));
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_burst_is_paced_to_the_configured_rate() {
+        let throttle = throttle(10.0, 2, 5_000);
+        let start = Instant::now();
+
+        let mut handles = Vec::new();
+        for _ in 0..6 {
+            let throttle = throttle.clone();
+            handles.push(tokio::spawn(async move {
+                throttle.acquire("conn-1", "mt5", BrokerCallPriority::MarketData).await.unwrap();
+                start.elapsed()
+            }));
+        }
+
+        let mut elapsed = Vec::new();
+        for handle in handles {
+            elapsed.push(handle.await.unwrap());
+        }
+        elapsed.sort();
+
+        // Two calls ride the burst, the remaining four are spaced at 100ms
+        assert!(elapsed[1] < Duration::from_millis(10));
+        assert!(elapsed[5] >= Duration::from_millis(390));
+        assert!(elapsed[5] < Duration::from_millis(500));
+        for pair in elapsed[2..].windows(2) {
+            assert!(pair[1] - pair[0] >= Duration::from_millis(90));
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_orders_jump_ahead_of_market_data() {
+        let throttle = throttle(1.0, 1, 10_000);
+        throttle.acquire("conn-1", "mt5", BrokerCallPriority::Order).await.unwrap();
+
+        let order_log = Arc::new(Mutex::new(Vec::new()));
+        let mut handles = Vec::new();
+        for (label, priority) in [
+            ("market_data_1", BrokerCallPriority::MarketData),
+            ("market_data_2", BrokerCallPriority::MarketData),
+            ("order", BrokerCallPriority::Order),
+        ] {
+            let throttle = throttle.clone();
+            let order_log = order_log.clone();
+            handles.push(tokio::spawn(async move {
+                throttle.acquire("conn-1", "mt5", priority).await.unwrap();
+                order_log.lock().unwrap().push(label);
+            }));
+            // Make sure the market data calls are queued first
+            tokio::task::yield_now().await;
+        }
+
+        for handle in handles {
+            handle.await.unwrap();
+        }
+
+        let order_log = order_log.lock().unwrap();
+        assert_eq!(*order_log, vec!["order", "market_data_1", "market_data_2"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_call_errors_as_transient_after_max_queue_wait() {
+        let throttle = throttle(0.5, 1, 200);
+        throttle.acquire("conn-1", "mt5", BrokerCallPriority::Order).await.unwrap();
+
+        let result = throttle.acquire("conn-1", "mt5", BrokerCallPriority::MarketData).await;
+        assert!(matches!(result, Err(AppError::BrokerUnavailable(_))));
+
+        let metrics = throttle.metrics();
+        assert_eq!(metrics.len(), 1);
+        assert_eq!(metrics[0].timed_out_calls, 1);
+        assert_eq!(metrics[0].total_calls, 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_connections_are_limited_independently() {
+        let throttle = throttle(1.0, 1, 100);
+        throttle.acquire("conn-1", "mt5", BrokerCallPriority::Order).await.unwrap();
+        throttle.acquire("conn-2", "mt5", BrokerCallPriority::Order).await.unwrap();
+
+        let metrics = throttle.metrics();
+        assert_eq!(metrics.len(), 2);
+        assert!(metrics.iter().all(|m| m.queued_calls == 0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_paying_class_waits_less_and_is_measured() {
+        let throttle = throttle(1.0, 1, 10_000);
+        throttle.acquire("conn-1", "mt5", BrokerCallPriority::Order).await.unwrap();
+
+        let mut handles = Vec::new();
+        for plan in ["free", "elite"] {
+            let throttle = throttle.clone();
+            let requester = Requester::new(Uuid::new_v4(), plan);
+            handles.push(tokio::spawn(on_behalf_of(requester, async move {
+                throttle.acquire("conn-1", "mt5", BrokerCallPriority::Order).await
+            })));
+            tokio::task::yield_now().await;
+        }
+        for handle in handles {
+            handle.await.unwrap().unwrap();
+        }
+
+        let waits = &throttle.metrics()[0].wait_by_class;
+        assert_eq!(waits.iter().map(|w| (w.class, w.served_calls)).collect::<Vec<_>>(), vec![
+            (ExecutionClass::Elite, 1),
+            (ExecutionClass::Free, 1),
+        ]);
+        // The elite order queued second but got the first token
+        assert!(waits[0].max_wait_ms < waits[1].max_wait_ms, "{:?}", waits);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_full_queue_turns_calls_away_as_transient() {
+        let sink = Arc::new(RecordingSink::default());
+        let throttle = Arc::new(limited(1.0, 1, 10_000).with_max_queue_depth(2).with_overflow_sink(sink.clone()));
+        throttle.acquire("conn-1", "mt5", BrokerCallPriority::Order).await.unwrap();
+
+        let mut queued = Vec::new();
+        for _ in 0..2 {
+            let throttle = throttle.clone();
+            queued.push(tokio::spawn(async move {
+                throttle.acquire("conn-1", "mt5", BrokerCallPriority::Order).await
+            }));
+            tokio::task::yield_now().await;
+        }
+
+        let robot_id = Uuid::new_v4();
+        let started = Instant::now();
+        let requester = Requester::new(Uuid::new_v4(), "elite").with_robot(robot_id);
+        let result = on_behalf_of(requester, throttle.acquire("conn-1", "mt5", BrokerCallPriority::Order)).await;
+
+        // Refused at once rather than after max_queue_wait, whatever the plan
+        assert!(matches!(result, Err(AppError::BrokerUnavailable(_))), "{:?}", result);
+        assert_eq!(started.elapsed(), Duration::ZERO);
+        assert_eq!(*sink.overflows.lock().unwrap(), vec![("conn-1".to_string(), Some(robot_id), 2)]);
+
+        // The calls already queued are still served
+        for handle in queued {
+            handle.await.unwrap().unwrap();
+        }
+        let metrics = throttle.metrics();
+        assert_eq!((metrics[0].queued_calls, metrics[0].rejected_calls), (2, 1));
+    }
+}
@@ -0,0 +1,37 @@
+use redis::AsyncCommands;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::errors::{AppError, Result};
+
+#[derive(Clone)]
+pub struct CacheService {
+    client: redis::Client,
+}
+
+impl CacheService {
+    pub fn new(redis_url: &str) -> Result<Self> {
+        let client = redis::Client::open(redis_url)?;
+        Ok(CacheService { client })
+    }
+
+    pub async fn get_json<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        let mut conn = self.client.get_multiplexed_tokio_connection().await?;
+        let raw: Option<String> = conn.get(key).await?;
+
+        // A payload that no longer matches the type is treated as a miss
+        Ok(raw.and_then(|value| serde_json::from_str(&value).ok()))
+    }
+
+    pub async fn set_json<T: Serialize>(&self, key: &str, value: &T, ttl_seconds: u64) -> Result<()> {
+        let raw = serde_json::to_string(value).map_err(|e| AppError::Internal(e.into()))?;
+        let mut conn = self.client.get_multiplexed_tokio_connection().await?;
+        conn.set_ex::<_, _, ()>(key, raw, ttl_seconds).await?;
+        Ok(())
+    }
+
+    pub async fn delete(&self, key: &str) -> Result<()> {
+        let mut conn = self.client.get_multiplexed_tokio_connection().await?;
+        conn.del::<_, ()>(key).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,436 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sqlx::PgPool;
+use std::collections::HashMap;
+
+use crate::{
+    errors::{AppError, Result},
+    models::{CheckoutActivation, CheckoutPayment, CheckoutSession, User},
+    services::{
+        event_bus::{DomainEvent, EventPublisher},
+        stripe_service::{CheckoutSessionParams, StripeCheckoutSession},
+        StripeService,
+    },
+};
+
+pub const CHECKOUT_COMPLETED_EVENT: &str = "checkout.session.completed";
+// Deliveries signed longer ago than this are refused as possible replays
+const SIGNATURE_TOLERANCE_SECONDS: i64 = 300;
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct CreateCheckoutSessionRequest {
+    pub plan_name: String,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct CheckoutSessionResponse {
+    pub session_id: String,
+    // Stripe's hosted payment page
+    pub url: String,
+}
+
+// The parts of a webhook delivery we read
+#[derive(Debug, Clone, Deserialize)]
+pub struct StripeEvent {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub event_type: String,
+    pub data: StripeEventData,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct StripeEventData {
+    pub object: serde_json::Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct CompletedCheckoutObject {
+    id: String,
+    customer: Option<String>,
+    subscription: Option<String>,
+}
+
+#[async_trait]
+pub trait CheckoutGateway: Send + Sync {
+    async fn create_checkout_session(&self, params: &CheckoutSessionParams) -> Result<StripeCheckoutSession>;
+}
+
+#[async_trait]
+impl CheckoutGateway for StripeService {
+    async fn create_checkout_session(&self, params: &CheckoutSessionParams) -> Result<StripeCheckoutSession> {
+        StripeService::create_checkout_session(self, params).await
+    }
+}
+
+#[async_trait]
+pub trait CheckoutStore: Send + Sync {
+    async fn record_session(&self, session: &CheckoutSession) -> Result<()>;
+    // Idempotent per event id; None when there was nothing (left) to complete
+    async fn complete(&self, event_id: &str, event_type: &str, payment: &CheckoutPayment, now: DateTime<Utc>) -> Result<Option<CheckoutActivation>>;
+}
+
+pub struct PgCheckoutStore {
+    pool: PgPool,
+}
+
+impl PgCheckoutStore {
+    pub fn new(pool: PgPool) -> Self {
+        PgCheckoutStore { pool }
+    }
+}
+
+#[async_trait]
+impl CheckoutStore for PgCheckoutStore {
+    async fn record_session(&self, session: &CheckoutSession) -> Result<()> {
+        CheckoutSession::create(&self.pool, session).await
+    }
+
+    async fn complete(&self, event_id: &str, event_type: &str, payment: &CheckoutPayment, now: DateTime<Utc>) -> Result<Option<CheckoutActivation>> {
+        CheckoutSession::complete(&self.pool, event_id, event_type, payment, now).await
+    }
+}
+
+pub struct CheckoutService;
+
+impl CheckoutService {
+    // Only plans with a configured Stripe price can be bought through checkout
+    pub fn session_params(
+        user: &User,
+        plan_name: &str,
+        price_ids: &HashMap<String, String>,
+        success_url: &str,
+        cancel_url: &str,
+    ) -> Result<CheckoutSessionParams> {
+        let plan_name = plan_name.trim().to_lowercase();
+        let price_id = price_ids
+            .get(&plan_name)
+            .ok_or_else(|| AppError::Validation(format!("Plan '{}' cannot be bought through checkout", plan_name)))?;
+
+        Ok(CheckoutSessionParams {
+            price_id: price_id.clone(),
+            customer_email: user.email.clone(),
+            client_reference_id: user.id.to_string(),
+            plan_name,
+            success_url: success_url.to_string(),
+            cancel_url: cancel_url.to_string(),
+        })
+    }
+
+    // The session is remembered so the webhook knows whose plan to change
+    pub async fn start(
+        gateway: &dyn CheckoutGateway,
+        store: &dyn CheckoutStore,
+        user: &User,
+        params: &CheckoutSessionParams,
+        now: DateTime<Utc>,
+    ) -> Result<CheckoutSessionResponse> {
+        let session = gateway.create_checkout_session(params).await?;
+        store.record_session(&CheckoutSession::new(session.id.clone(), user.id, params.plan_name.clone(), now)).await?;
+        tracing::info!("Checkout session {} started by {} for {}", session.id, user.id, params.plan_name);
+        Ok(CheckoutSessionResponse { session_id: session.id, url: session.url })
+    }
+
+    // Stripe-Signature: t=<unix time>,v1=<hex HMAC-SHA256 of "<t>.<payload>">[,v1=...]
+    pub fn verify_signature(payload: &[u8], header: &str, secret: &str, now: DateTime<Utc>) -> Result<()> {
+        let invalid = || AppError::Stripe("Invalid Stripe signature".to_string());
+
+        let mut timestamp = None;
+        let mut signatures = Vec::new();
+        for part in header.split(',') {
+            match part.trim().split_once('=') {
+                Some(("t", value)) => timestamp = value.parse::<i64>().ok(),
+                Some(("v1", value)) => signatures.extend(hex::decode(value).ok()),
+                _ => {}
+            }
+        }
+        let timestamp = timestamp.ok_or_else(invalid)?;
+        if (now.timestamp() - timestamp).abs() > SIGNATURE_TOLERANCE_SECONDS {
+            return Err(AppError::Stripe("Stripe signature is too old".to_string()));
+        }
+
+        let signed = |signature: &[u8]| {
+            let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+            mac.update(timestamp.to_string().as_bytes());
+            mac.update(b".");
+            mac.update(payload);
+            mac.verify_slice(signature).is_ok()
+        };
+        if signatures.iter().any(|s| signed(s)) {
+            Ok(())
+        } else {
+            Err(invalid())
+        }
+    }
+
+    pub fn parse_event(payload: &[u8]) -> Result<StripeEvent> {
+        serde_json::from_slice(payload).map_err(|e| AppError::Stripe(format!("Unreadable Stripe event: {}", e)))
+    }
+
+    // What Stripe would send once a mock session is paid, for local development
+    pub fn simulated_completion(session: &CheckoutSession) -> StripeEvent {
+        let suffix = session.id.trim_start_matches("cs_test_");
+        StripeEvent {
+            id: format!("evt_mock_{}", suffix),
+            event_type: CHECKOUT_COMPLETED_EVENT.to_string(),
+            data: StripeEventData {
+                object: serde_json::json!({
+                    "id": session.id,
+                    "customer": format!("cus_mock_{}", suffix),
+                    "subscription": format!("sub_mock_{}", suffix),
+                }),
+            },
+        }
+    }
+
+    // Stripe redelivers until it gets a 2xx, so everything not acted on is still acknowledged.
+    // Returns the activation when a subscription was created.
+    pub async fn handle_event(
+        store: &dyn CheckoutStore,
+        events: &dyn EventPublisher,
+        event: &StripeEvent,
+        now: DateTime<Utc>,
+    ) -> Result<Option<CheckoutActivation>> {
+        if event.event_type != CHECKOUT_COMPLETED_EVENT {
+            tracing::debug!("Ignoring Stripe event {} ({})", event.id, event.event_type);
+            return Ok(None);
+        }
+
+        let object: CompletedCheckoutObject = serde_json::from_value(event.data.object.clone())
+            .map_err(|e| AppError::Stripe(format!("Unreadable checkout session in {}: {}", event.id, e)))?;
+        let payment = CheckoutPayment {
+            session_id: object.id,
+            stripe_customer_id: object.customer,
+            stripe_subscription_id: object.subscription,
+        };
+
+        let Some(activation) = store.complete(&event.id, &event.event_type, &payment, now).await? else {
+            tracing::info!("Stripe event {} for {} needs no action (redelivered or unknown session)", event.id, payment.session_id);
+            return Ok(None);
+        };
+
+        let subscription = &activation.subscription;
+        if activation.converted_trial {
+            tracing::info!("User {} converted their trial to {}", subscription.user_id, subscription.plan_name);
+        }
+        tracing::info!("Checkout {} activated {} for {}", payment.session_id, subscription.plan_name, subscription.user_id);
+        events.publish(DomainEvent::SubscriptionChanged {
+            user_id: subscription.user_id,
+            email: activation.email.clone(),
+            plan_name: subscription.plan_name.clone(),
+            action: "activated".to_string(),
+        });
+        Ok(Some(activation))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Subscription;
+    use chrono::TimeZone;
+    use std::collections::HashSet;
+    use std::sync::Mutex;
+    use uuid::Uuid;
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 12, 23, 12, 0, 0).unwrap()
+    }
+
+    fn user() -> User {
+        User {
+            id: Uuid::new_v4(),
+            email: "trader@example.com".to_string(),
+            password_hash: String::new(),
+            is_active: true,
+            is_superuser: false,
+            role: "user".to_string(),
+            subscription_plan: "free".to_string(),
+            full_name: None,
+            avatar_url: None,
+            timezone: None,
+            locale: None,
+            created_at: now(),
+            updated_at: now(),
+        }
+    }
+
+    fn prices() -> HashMap<String, String> {
+        HashMap::from([("pro".to_string(), "price_pro".to_string()), ("elite".to_string(), "price_elite".to_string())])
+    }
+
+    struct FakeGateway;
+
+    #[async_trait]
+    impl CheckoutGateway for FakeGateway {
+        async fn create_checkout_session(&self, _params: &CheckoutSessionParams) -> Result<StripeCheckoutSession> {
+            Ok(StripeCheckoutSession { id: "cs_test_1".to_string(), url: "https://checkout.stripe.test/pay/cs_test_1".to_string() })
+        }
+    }
+
+    // Mirrors the transaction in CheckoutSession::complete
+    #[derive(Default)]
+    struct FakeStore {
+        sessions: Mutex<HashMap<String, CheckoutSession>>,
+        seen_events: Mutex<HashSet<String>>,
+        subscriptions: Mutex<Vec<Subscription>>,
+    }
+
+    #[async_trait]
+    impl CheckoutStore for FakeStore {
+        async fn record_session(&self, session: &CheckoutSession) -> Result<()> {
+            self.sessions.lock().unwrap().insert(session.id.clone(), session.clone());
+            Ok(())
+        }
+
+        async fn complete(&self, event_id: &str, _event_type: &str, payment: &CheckoutPayment, now: DateTime<Utc>) -> Result<Option<CheckoutActivation>> {
+            if !self.seen_events.lock().unwrap().insert(event_id.to_string()) {
+                return Ok(None);
+            }
+            let mut sessions = self.sessions.lock().unwrap();
+            let Some(session) = sessions.get_mut(&payment.session_id).filter(|s| s.status == "open") else {
+                return Ok(None);
+            };
+            session.status = "completed".to_string();
+            session.completed_at = Some(now);
+
+            let subscription = Subscription {
+                stripe_customer_id: payment.stripe_customer_id.clone(),
+                stripe_subscription_id: payment.stripe_subscription_id.clone(),
+                ..Subscription::new(session.user_id, session.plan_name.clone())
+            };
+            self.subscriptions.lock().unwrap().push(subscription.clone());
+            Ok(Some(CheckoutActivation { subscription, email: "trader@example.com".to_string(), converted_trial: false }))
+        }
+    }
+
+    #[derive(Default)]
+    struct RecordingPublisher {
+        events: Mutex<Vec<DomainEvent>>,
+    }
+
+    impl EventPublisher for RecordingPublisher {
+        fn publish(&self, event: DomainEvent) {
+            self.events.lock().unwrap().push(event);
+        }
+    }
+
+    fn event(json: &str) -> StripeEvent {
+        CheckoutService::parse_event(json.as_bytes()).unwrap()
+    }
+
+    fn completed_event(event_id: &str, session_id: &str) -> StripeEvent {
+        event(&format!(
+            r#"{{"id": "{}", "type": "checkout.session.completed", "data": {{"object": {{"id": "{}", "object": "checkout.session", "customer": "cus_1", "subscription": "sub_1", "client_reference_id": "ignored"}}}}}}"#,
+            event_id, session_id
+        ))
+    }
+
+    #[test]
+    fn test_session_params_use_the_plan_price_and_configured_urls() {
+        let user = user();
+        let params = CheckoutService::session_params(&user, " Pro ", &prices(), "https://app/ok?session_id={CHECKOUT_SESSION_ID}", "https://app/cancel").unwrap();
+
+        assert_eq!(params.price_id, "price_pro");
+        assert_eq!(params.plan_name, "pro");
+        let form: HashMap<_, _> = params.form().into_iter().collect();
+        assert_eq!(form["mode"], "subscription");
+        assert_eq!(form["line_items[0][price]"], "price_pro");
+        assert_eq!(form["line_items[0][quantity]"], "1");
+        assert_eq!(form["customer_email"], "trader@example.com");
+        assert_eq!(form["client_reference_id"], user.id.to_string());
+        assert_eq!(form["metadata[plan_name]"], "pro");
+        assert_eq!(form["success_url"], "https://app/ok?session_id={CHECKOUT_SESSION_ID}");
+        assert_eq!(form["cancel_url"], "https://app/cancel");
+
+        // Free has no price, and unknown plans are not guessed at
+        assert!(matches!(CheckoutService::session_params(&user, "free", &prices(), "", ""), Err(AppError::Validation(_))));
+        assert!(matches!(CheckoutService::session_params(&user, "platinum", &prices(), "", ""), Err(AppError::Validation(_))));
+    }
+
+    #[tokio::test]
+    async fn test_completed_checkout_creates_the_subscription_once() {
+        let user = user();
+        let store = FakeStore::default();
+        let events = RecordingPublisher::default();
+        let params = CheckoutService::session_params(&user, "pro", &prices(), "https://app/ok", "https://app/cancel").unwrap();
+
+        let started = CheckoutService::start(&FakeGateway, &store, &user, &params, now()).await.unwrap();
+        assert_eq!(started.url, "https://checkout.stripe.test/pay/cs_test_1");
+        assert_eq!(store.sessions.lock().unwrap()["cs_test_1"].user_id, user.id);
+
+        let event = completed_event("evt_1", "cs_test_1");
+        assert!(CheckoutService::handle_event(&store, &events, &event, now()).await.unwrap().is_some());
+        // Redelivery of the same event changes nothing and sends no second email
+        assert!(CheckoutService::handle_event(&store, &events, &event, now()).await.unwrap().is_none());
+        // Nor does a different event for the session that was already completed
+        assert!(CheckoutService::handle_event(&store, &events, &completed_event("evt_2", "cs_test_1"), now()).await.unwrap().is_none());
+
+        let subscriptions = store.subscriptions.lock().unwrap();
+        assert_eq!(subscriptions.len(), 1);
+        assert_eq!(subscriptions[0].user_id, user.id);
+        assert_eq!(subscriptions[0].plan_name, "pro");
+        assert_eq!(subscriptions[0].stripe_customer_id.as_deref(), Some("cus_1"));
+        assert_eq!(subscriptions[0].stripe_subscription_id.as_deref(), Some("sub_1"));
+        assert_eq!(store.sessions.lock().unwrap()["cs_test_1"].status, "completed");
+
+        let published = events.events.lock().unwrap();
+        assert_eq!(published.len(), 1);
+        assert!(matches!(&published[0], DomainEvent::SubscriptionChanged { plan_name, action, .. } if plan_name == "pro" && action == "activated"));
+    }
+
+    #[tokio::test]
+    async fn test_other_events_and_unknown_sessions_are_acknowledged_without_effect() {
+        let store = FakeStore::default();
+        let events = RecordingPublisher::default();
+
+        let other = event(r#"{"id": "evt_9", "type": "invoice.paid", "data": {"object": {"id": "in_1"}}}"#);
+        assert!(CheckoutService::handle_event(&store, &events, &other, now()).await.unwrap().is_none());
+        assert!(CheckoutService::handle_event(&store, &events, &completed_event("evt_3", "cs_unknown"), now()).await.unwrap().is_none());
+        assert!(events.events.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_simulated_completion_goes_through_the_webhook_path() {
+        let user = user();
+        let store = FakeStore::default();
+        let events = RecordingPublisher::default();
+        let session = CheckoutSession::new("cs_test_abc".to_string(), user.id, "elite".to_string(), now());
+        store.record_session(&session).await.unwrap();
+
+        let event = CheckoutService::simulated_completion(&session);
+        assert_eq!(event.id, "evt_mock_abc");
+        assert!(CheckoutService::handle_event(&store, &events, &event, now()).await.unwrap().is_some());
+        // Simulating twice is as harmless as a redelivery
+        assert!(CheckoutService::handle_event(&store, &events, &CheckoutService::simulated_completion(&session), now()).await.unwrap().is_none());
+        assert_eq!(store.subscriptions.lock().unwrap()[0].plan_name, "elite");
+    }
+
+    #[test]
+    fn test_signature_verification() {
+        let payload = br#"{"id": "evt_1"}"#;
+        let secret = "whsec_test";
+        let sign = |timestamp: i64, body: &[u8]| {
+            let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+            mac.update(format!("{}.", timestamp).as_bytes());
+            mac.update(body);
+            hex::encode(mac.finalize().into_bytes())
+        };
+        let t = now().timestamp();
+
+        let header = format!("t={},v1={},v0=ignored", t, sign(t, payload));
+        assert!(CheckoutService::verify_signature(payload, &header, secret, now()).is_ok());
+        // Rotated secrets send one v1 per secret; any match is enough
+        let header = format!("t={},v1={},v1={}", t, "00".repeat(32), sign(t, payload));
+        assert!(CheckoutService::verify_signature(payload, &header, secret, now()).is_ok());
+
+        let tampered = format!("t={},v1={}", t, sign(t, br#"{"id": "evt_2"}"#));
+        assert!(CheckoutService::verify_signature(payload, &tampered, secret, now()).is_err());
+        let stale = format!("t={},v1={}", t - 600, sign(t - 600, payload));
+        assert!(CheckoutService::verify_signature(payload, &stale, secret, now()).is_err());
+        assert!(CheckoutService::verify_signature(payload, "garbage", secret, now()).is_err());
+    }
+}
@@ -0,0 +1,243 @@
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{
+    errors::{AppError, Result},
+    models::{CompositeMode, CompositeStrategy},
+    services::signal_stability::RobotSignal,
+};
+
+// One strategy's part in a composite signal
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct SignalComponent {
+    pub strategy: String,
+    pub weight: f64,
+    // buy, sell or hold
+    pub direction: String,
+    pub confidence: f64,
+}
+
+// A single strategy's view of the robot's market
+#[async_trait]
+pub trait Strategy: Send + Sync {
+    async fn signal(&self, robot_id: Uuid) -> Result<RobotSignal>;
+}
+
+// Strategies composite robots can combine, by the name used in risk_config.composite_strategy
+#[derive(Default)]
+pub struct StrategyRegistry {
+    strategies: HashMap<String, Arc<dyn Strategy>>,
+}
+
+impl StrategyRegistry {
+    pub fn register(mut self, name: &str, strategy: Arc<dyn Strategy>) -> Self {
+        self.strategies.insert(name.to_string(), strategy);
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn Strategy>> {
+        self.strategies.get(name).cloned()
+    }
+}
+
+pub struct CompositeSignal;
+
+impl CompositeSignal {
+    // Asks every strategy of the composite for its signal. One that is not registered or fails
+    // counts as a hold, so it can only make the robot more cautious.
+    pub async fn evaluate(registry: &StrategyRegistry, robot_id: Uuid, composite: &CompositeStrategy) -> RobotSignal {
+        let mut components = Vec::with_capacity(composite.strategies.len());
+        for entry in &composite.strategies {
+            let signal = match registry.get(&entry.strategy) {
+                Some(strategy) => strategy.signal(robot_id).await,
+                None => Err(AppError::Unprocessable(format!("Strategy {} is not available", entry.strategy))),
+            };
+            let (direction, confidence) = match signal {
+                Ok(signal) => (signal.direction.to_ascii_lowercase(), signal.confidence),
+                Err(e) => {
+                    tracing::warn!("Robot {} got no {} signal: {}", robot_id, entry.strategy, e);
+                    ("hold".to_string(), 0.0)
+                }
+            };
+            components.push(SignalComponent {
+                strategy: entry.strategy.clone(),
+                weight: entry.weight,
+                direction,
+                confidence,
+            });
+        }
+        Self::combine(composite, components)
+    }
+
+    // The weighted confidence behind the winning direction becomes the signal's confidence;
+    // anything short of the mode's agreement is a hold
+    pub fn combine(composite: &CompositeStrategy, components: Vec<SignalComponent>) -> RobotSignal {
+        let votes = |direction: &str| components.iter().filter(|c| c.direction == direction).count();
+        let support = |direction: &str| -> f64 {
+            components
+                .iter()
+                .filter(|c| c.direction == direction)
+                .map(|c| c.weight * c.confidence)
+                .sum()
+        };
+        let (buy, sell) = (support("buy"), support("sell"));
+
+        let direction = match composite.mode {
+            CompositeMode::Unanimous => ["buy", "sell"].into_iter().find(|d| votes(d) == components.len()),
+            CompositeMode::Majority => ["buy", "sell"].into_iter().find(|d| votes(d) * 2 > components.len()),
+            CompositeMode::WeightedThreshold => {
+                let threshold = composite.threshold.unwrap_or(1.0);
+                if buy > sell && buy >= threshold {
+                    Some("buy")
+                } else if sell > buy && sell >= threshold {
+                    Some("sell")
+                } else {
+                    None
+                }
+            }
+        };
+
+        let (direction, confidence) = match direction {
+            Some(direction) => (direction, support(direction)),
+            None => ("hold", 0.0),
+        };
+        RobotSignal { direction: direction.to_string(), confidence, components }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::StrategyWeight;
+
+    fn composite(mode: CompositeMode, weights: &[(&str, f64)], threshold: Option<f64>) -> CompositeStrategy {
+        CompositeStrategy {
+            mode,
+            strategies: weights
+                .iter()
+                .map(|(strategy, weight)| StrategyWeight { strategy: strategy.to_string(), weight: *weight })
+                .collect(),
+            threshold,
+        }
+    }
+
+    fn component(strategy: &str, weight: f64, direction: &str, confidence: f64) -> SignalComponent {
+        SignalComponent { strategy: strategy.to_string(), weight, direction: direction.to_string(), confidence }
+    }
+
+    struct Fixed(&'static str, f64);
+
+    #[async_trait]
+    impl Strategy for Fixed {
+        async fn signal(&self, _robot_id: Uuid) -> Result<RobotSignal> {
+            Ok(RobotSignal { direction: self.0.to_string(), confidence: self.1, components: Vec::new() })
+        }
+    }
+
+    struct Failing;
+
+    #[async_trait]
+    impl Strategy for Failing {
+        async fn signal(&self, _robot_id: Uuid) -> Result<RobotSignal> {
+            Err(AppError::AiModel("model not loaded".to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_agreeing_strategies_trade_with_their_weighted_confidence() {
+        let registry = StrategyRegistry::default()
+            .register("ai_model", Arc::new(Fixed("BUY", 0.8)))
+            .register("mean_reversion", Arc::new(Fixed("buy", 0.6)));
+        let unanimous = composite(CompositeMode::Unanimous, &[("ai_model", 0.75), ("mean_reversion", 0.25)], None);
+
+        let signal = CompositeSignal::evaluate(&registry, Uuid::new_v4(), &unanimous).await;
+
+        assert_eq!(signal.direction, "buy");
+        assert!((signal.confidence - 0.75).abs() < 1e-9);
+        assert_eq!(
+            signal.components,
+            vec![component("ai_model", 0.75, "buy", 0.8), component("mean_reversion", 0.25, "buy", 0.6)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_conflicting_or_missing_strategies_hold() {
+        let registry = StrategyRegistry::default()
+            .register("ai_model", Arc::new(Fixed("buy", 0.9)))
+            .register("mean_reversion", Arc::new(Fixed("sell", 0.7)))
+            .register("broken", Arc::new(Failing));
+
+        let conflict = composite(CompositeMode::Unanimous, &[("ai_model", 0.5), ("mean_reversion", 0.5)], None);
+        let signal = CompositeSignal::evaluate(&registry, Uuid::new_v4(), &conflict).await;
+        assert_eq!((signal.direction.as_str(), signal.confidence), ("hold", 0.0));
+        assert_eq!(signal.components.len(), 2);
+
+        // A one-all split has no majority either
+        let majority = composite(CompositeMode::Majority, &[("ai_model", 0.5), ("mean_reversion", 0.5)], None);
+        assert_eq!(CompositeSignal::evaluate(&registry, Uuid::new_v4(), &majority).await.direction, "hold");
+
+        // Unavailable strategies count as holds
+        let degraded = composite(CompositeMode::Unanimous, &[("ai_model", 0.5), ("broken", 0.25), ("unknown", 0.25)], None);
+        let signal = CompositeSignal::evaluate(&registry, Uuid::new_v4(), &degraded).await;
+        assert_eq!(signal.direction, "hold");
+        assert_eq!(signal.components[1], component("broken", 0.25, "hold", 0.0));
+        assert_eq!(signal.components[2], component("unknown", 0.25, "hold", 0.0));
+    }
+
+    #[test]
+    fn test_majority_lets_dissent_lower_the_confidence() {
+        let majority = composite(CompositeMode::Majority, &[("a", 0.4), ("b", 0.3), ("c", 0.3)], None);
+        let signal = CompositeSignal::combine(
+            &majority,
+            vec![component("a", 0.4, "sell", 0.8), component("b", 0.3, "sell", 0.6), component("c", 0.3, "buy", 0.9)],
+        );
+        assert_eq!(signal.direction, "sell");
+        assert!((signal.confidence - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_weighted_threshold_edges() {
+        let weighted = composite(CompositeMode::WeightedThreshold, &[("a", 0.6), ("b", 0.4)], Some(0.48));
+        let combine = |a: (&str, f64), b: (&str, f64)| {
+            CompositeSignal::combine(&weighted, vec![component("a", 0.6, a.0, a.1), component("b", 0.4, b.0, b.1)])
+        };
+
+        // Exactly at the threshold trades, even with the other strategy holding
+        let at = combine(("buy", 0.8), ("hold", 0.5));
+        assert_eq!(at.direction, "buy");
+        assert!((at.confidence - 0.48).abs() < 1e-9);
+        // Just below it does not
+        assert_eq!(combine(("buy", 0.79), ("hold", 0.5)).direction, "hold");
+        // The heavier side wins a disagreement once it clears the threshold
+        assert_eq!(combine(("sell", 0.9), ("buy", 0.9)).direction, "sell");
+        // Equal support either way is no decision
+        let tied = CompositeSignal::combine(
+            &composite(CompositeMode::WeightedThreshold, &[("a", 0.5), ("b", 0.5)], Some(0.1)),
+            vec![component("a", 0.5, "buy", 0.8), component("b", 0.5, "sell", 0.8)],
+        );
+        assert_eq!(tied.direction, "hold");
+    }
+
+    #[test]
+    fn test_composite_config_is_validated() {
+        let parse = |value: serde_json::Value| CompositeStrategy::from_risk_config(&serde_json::json!({ "composite_strategy": value }));
+        let two = |a: f64, b: f64| serde_json::json!([{"strategy": "ai_model", "weight": a}, {"strategy": "mean_reversion", "weight": b}]);
+
+        assert_eq!(CompositeStrategy::from_risk_config(&serde_json::json!({})), Ok(None));
+        let parsed = parse(serde_json::json!({"mode": "majority", "strategies": two(0.7, 0.3)})).unwrap().unwrap();
+        assert_eq!(parsed.mode, CompositeMode::Majority);
+
+        let one = serde_json::json!([{"strategy": "ai_model", "weight": 1.0}]);
+        assert!(parse(serde_json::json!({"mode": "unanimous", "strategies": one})).unwrap_err().contains("at least two"));
+        assert!(parse(serde_json::json!({"mode": "unanimous", "strategies": two(0.5, 0.4)})).unwrap_err().contains("sum to 1"));
+        assert!(parse(serde_json::json!({"mode": "weighted_threshold", "strategies": two(0.5, 0.5)})).is_err());
+        assert!(parse(serde_json::json!({"mode": "weighted_threshold", "strategies": two(0.5, 0.5), "threshold": 0.6})).is_ok());
+        assert!(parse(serde_json::json!({"mode": "sometimes", "strategies": two(0.5, 0.5)})).is_err());
+        let repeated = serde_json::json!([{"strategy": "ai_model", "weight": 0.5}, {"strategy": "ai_model", "weight": 0.5}]);
+        assert!(parse(serde_json::json!({"mode": "majority", "strategies": repeated})).is_err());
+    }
+}
@@ -0,0 +1,311 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{
+    errors::{AppError, Result},
+    models::{RobotLog, TradingRobot, User},
+    services::{robot_runner::RobotRunnerRegistry, NotificationService},
+};
+
+pub const COOLING_DOWN: &str = "cooling_down";
+
+// Robot state and side effects for loss streak cooldowns
+#[async_trait]
+pub trait CooldownEnv: Send + Sync {
+    async fn robot(&self, robot_id: Uuid, user_id: Uuid) -> Result<Option<TradingRobot>>;
+    // Returns the robot's consecutive losses after this result
+    async fn record_result(&self, robot_id: Uuid, won: bool) -> Result<i32>;
+    // False if the robot was no longer active
+    async fn start_cooldown(&self, robot: &TradingRobot, resume_at: DateTime<Utc>) -> Result<bool>;
+    async fn due_cooldowns(&self, now: DateTime<Utc>) -> Result<Vec<TradingRobot>>;
+    // False if the robot was no longer cooling down
+    async fn end_cooldown(&self, robot: &TradingRobot) -> Result<bool>;
+    async fn log(&self, robot: &TradingRobot, level: &str, message: &str) -> Result<()>;
+    async fn notify(&self, robot: &TradingRobot, status: &str) -> Result<()>;
+}
+
+pub struct PgCooldownEnv {
+    pool: PgPool,
+    notifications: Arc<NotificationService>,
+}
+
+impl PgCooldownEnv {
+    pub fn new(pool: PgPool, notifications: Arc<NotificationService>) -> Self {
+        PgCooldownEnv { pool, notifications }
+    }
+}
+
+#[async_trait]
+impl CooldownEnv for PgCooldownEnv {
+    async fn robot(&self, robot_id: Uuid, user_id: Uuid) -> Result<Option<TradingRobot>> {
+        TradingRobot::find_by_id(&self.pool, robot_id, user_id).await
+    }
+
+    async fn record_result(&self, robot_id: Uuid, won: bool) -> Result<i32> {
+        TradingRobot::record_trade_result(&self.pool, robot_id, won).await
+    }
+
+    async fn start_cooldown(&self, robot: &TradingRobot, resume_at: DateTime<Utc>) -> Result<bool> {
+        TradingRobot::start_cooldown(&self.pool, robot.id, resume_at).await
+    }
+
+    async fn due_cooldowns(&self, now: DateTime<Utc>) -> Result<Vec<TradingRobot>> {
+        TradingRobot::find_due_cooldowns(&self.pool, now).await
+    }
+
+    async fn end_cooldown(&self, robot: &TradingRobot) -> Result<bool> {
+        TradingRobot::end_cooldown(&self.pool, robot.id).await
+    }
+
+    async fn log(&self, robot: &TradingRobot, level: &str, message: &str) -> Result<()> {
+        RobotLog::create(&self.pool, robot.id, robot.user_id, level, message).await?;
+        Ok(())
+    }
+
+    async fn notify(&self, robot: &TradingRobot, status: &str) -> Result<()> {
+        let user = User::find_by_id(&self.pool, robot.user_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+        self.notifications
+            .send_robot_status_notification(&user.email, &robot.name, status)
+            .await
+    }
+}
+
+pub struct CooldownService;
+
+impl CooldownService {
+    // Called once per closed trade. A winner resets the streak and a breakeven trade leaves it
+    // alone. Returns when the robot resumes if this loss started a cooldown.
+    pub async fn record_close(
+        env: &dyn CooldownEnv,
+        robot_id: Uuid,
+        user_id: Uuid,
+        profit_loss: f64,
+        now: DateTime<Utc>,
+    ) -> Result<Option<DateTime<Utc>>> {
+        if profit_loss == 0.0 {
+            return Ok(None);
+        }
+
+        let streak = env.record_result(robot_id, profit_loss > 0.0).await?;
+        if profit_loss > 0.0 {
+            return Ok(None);
+        }
+
+        let Some(robot) = env.robot(robot_id, user_id).await? else {
+            return Ok(None);
+        };
+        let Some(cooldown) = robot.loss_streak_cooldown() else {
+            return Ok(None);
+        };
+        if streak < cooldown.streak {
+            return Ok(None);
+        }
+
+        let resume_at = now + cooldown.duration();
+        if !env.start_cooldown(&robot, resume_at).await? {
+            return Ok(None);
+        }
+
+        let message = format!(
+            "Paused after {} consecutive losing trades, resuming at {}",
+            streak,
+            resume_at.format("%Y-%m-%d %H:%M UTC")
+        );
+        tracing::info!("Robot {}: {}", robot.id, message);
+        env.log(&robot, "warn", &message).await?;
+        if let Err(e) = env
+            .notify(&robot, &format!("cooling down until {}", resume_at.format("%Y-%m-%d %H:%M UTC")))
+            .await
+        {
+            tracing::warn!("Could not notify owner of robot {}: {}", robot.id, e);
+        }
+
+        Ok(Some(resume_at))
+    }
+
+    // Scheduler job: puts robots whose cooldown is over back to trading. Returns how many resumed.
+    pub async fn resume_due(env: &dyn CooldownEnv, runners: &RobotRunnerRegistry, now: DateTime<Utc>) -> Result<usize> {
+        let mut resumed = 0;
+
+        for robot in env.due_cooldowns(now).await? {
+            // Stopped or force-restarted in the meantime
+            if !env.end_cooldown(&robot).await? {
+                continue;
+            }
+            runners.set_paused(robot.id, false);
+            resumed += 1;
+
+            env.log(&robot, "info", "Cooldown over, trading resumed").await?;
+            if let Err(e) = env.notify(&robot, "active (cooldown over)").await {
+                tracing::warn!("Could not notify owner of robot {}: {}", robot.id, e);
+            }
+        }
+
+        Ok(resumed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+    use std::sync::Mutex;
+
+    struct FakeEnv {
+        robot: Mutex<TradingRobot>,
+        logs: Mutex<Vec<String>>,
+        notifications: Mutex<Vec<String>>,
+    }
+
+    impl FakeEnv {
+        fn new(cooldown: serde_json::Value) -> Self {
+            let mut robot = TradingRobot::new(Uuid::new_v4(), "robot".to_string(), "trend".to_string());
+            robot.status = "active".to_string();
+            robot.risk_config["loss_streak_cooldown"] = cooldown;
+            FakeEnv { robot: Mutex::new(robot), logs: Mutex::new(Vec::new()), notifications: Mutex::new(Vec::new()) }
+        }
+
+        fn robot(&self) -> TradingRobot {
+            self.robot.lock().unwrap().clone()
+        }
+
+        fn streak(&self) -> i64 {
+            self.robot().performance_metrics["loss_streak"].as_i64().unwrap_or(0)
+        }
+    }
+
+    #[async_trait]
+    impl CooldownEnv for FakeEnv {
+        async fn robot(&self, _robot_id: Uuid, _user_id: Uuid) -> Result<Option<TradingRobot>> {
+            Ok(Some(self.robot()))
+        }
+
+        async fn record_result(&self, _robot_id: Uuid, won: bool) -> Result<i32> {
+            let mut robot = self.robot.lock().unwrap();
+            let streak = if won { 0 } else { robot.performance_metrics["loss_streak"].as_i64().unwrap_or(0) + 1 };
+            robot.performance_metrics["loss_streak"] = serde_json::json!(streak);
+            Ok(streak as i32)
+        }
+
+        async fn start_cooldown(&self, _robot: &TradingRobot, resume_at: DateTime<Utc>) -> Result<bool> {
+            let mut robot = self.robot.lock().unwrap();
+            if robot.status != "active" {
+                return Ok(false);
+            }
+            robot.status = COOLING_DOWN.to_string();
+            robot.performance_metrics["loss_streak"] = serde_json::json!(0);
+            robot.performance_metrics["cooldown_until"] = serde_json::json!(resume_at.to_rfc3339());
+            Ok(true)
+        }
+
+        async fn due_cooldowns(&self, now: DateTime<Utc>) -> Result<Vec<TradingRobot>> {
+            let robot = self.robot();
+            Ok(robot.resume_at().filter(|at| *at <= now).map(|_| robot).into_iter().collect())
+        }
+
+        async fn end_cooldown(&self, _robot: &TradingRobot) -> Result<bool> {
+            let mut robot = self.robot.lock().unwrap();
+            if robot.status != COOLING_DOWN {
+                return Ok(false);
+            }
+            robot.status = "active".to_string();
+            robot.performance_metrics.as_object_mut().unwrap().remove("cooldown_until");
+            Ok(true)
+        }
+
+        async fn log(&self, _robot: &TradingRobot, _level: &str, message: &str) -> Result<()> {
+            self.logs.lock().unwrap().push(message.to_string());
+            Ok(())
+        }
+
+        async fn notify(&self, _robot: &TradingRobot, status: &str) -> Result<()> {
+            self.notifications.lock().unwrap().push(status.to_string());
+            Ok(())
+        }
+    }
+
+    fn at(minutes: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 5, 6, 9, 0, 0).unwrap() + Duration::minutes(minutes)
+    }
+
+    #[tokio::test]
+    async fn test_losing_streak_pauses_then_resumes_on_time() {
+        let env = FakeEnv::new(serde_json::json!({ "streak": 3, "hours": 2 }));
+        let robot = env.robot();
+        let runners = RobotRunnerRegistry::new();
+        runners.start(robot.id, robot.user_id, false);
+
+        for (minute, profit_loss) in [(0, -10.0), (5, -4.5), (10, 0.0)] {
+            assert_eq!(CooldownService::record_close(&env, robot.id, robot.user_id, profit_loss, at(minute)).await.unwrap(), None);
+        }
+        assert_eq!(env.streak(), 2);
+
+        // The third loss starts the cooldown, timed from that close
+        let resume_at = CooldownService::record_close(&env, robot.id, robot.user_id, -1.0, at(15)).await.unwrap();
+        assert_eq!(resume_at, Some(at(135)));
+        // As the close handlers do
+        runners.set_paused(robot.id, true);
+        assert_eq!(env.robot().status, COOLING_DOWN);
+        assert_eq!(env.robot().resume_at(), Some(at(135)));
+        assert_eq!(env.streak(), 0);
+        assert_eq!(env.logs.lock().unwrap().len(), 1);
+        assert!(env.notifications.lock().unwrap()[0].starts_with("cooling down until"));
+
+        // Nothing resumes early
+        assert_eq!(CooldownService::resume_due(&env, &runners, at(134)).await.unwrap(), 0);
+        assert_eq!(env.robot().status, COOLING_DOWN);
+        assert!(runners.statuses()[0].paused);
+
+        assert_eq!(CooldownService::resume_due(&env, &runners, at(135)).await.unwrap(), 1);
+        assert_eq!(env.robot().status, "active");
+        assert_eq!(env.robot().resume_at(), None);
+        assert!(!runners.statuses()[0].paused);
+        assert_eq!(CooldownService::resume_due(&env, &runners, at(200)).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_a_winner_resets_the_streak() {
+        let env = FakeEnv::new(serde_json::json!({ "streak": 3, "hours": 2 }));
+        let robot = env.robot();
+
+        for (minute, profit_loss) in [(0, -10.0), (1, -10.0), (2, 25.0), (3, -10.0), (4, -10.0)] {
+            assert_eq!(CooldownService::record_close(&env, robot.id, robot.user_id, profit_loss, at(minute)).await.unwrap(), None);
+        }
+        assert_eq!(env.streak(), 2);
+        assert_eq!(env.robot().status, "active");
+
+        let resume_at = CooldownService::record_close(&env, robot.id, robot.user_id, -10.0, at(5)).await.unwrap();
+        assert_eq!(resume_at, Some(at(125)));
+    }
+
+    #[tokio::test]
+    async fn test_robots_without_the_rule_only_track_the_streak() {
+        let env = FakeEnv::new(serde_json::Value::Null);
+        let robot = env.robot();
+
+        for minute in 0..5 {
+            assert_eq!(CooldownService::record_close(&env, robot.id, robot.user_id, -1.0, at(minute)).await.unwrap(), None);
+        }
+        assert_eq!(env.streak(), 5);
+        assert_eq!(env.robot().status, "active");
+    }
+
+    #[test]
+    fn test_cooldown_settings_are_validated() {
+        use crate::models::LossStreakCooldown;
+
+        let parse = |value: serde_json::Value| LossStreakCooldown::from_risk_config(&serde_json::json!({ "loss_streak_cooldown": value }));
+        assert_eq!(parse(serde_json::json!({ "streak": 3, "hours": 4 })), Ok(Some(LossStreakCooldown { streak: 3, hours: 4.0 })));
+        assert_eq!(LossStreakCooldown::from_risk_config(&serde_json::json!({})), Ok(None));
+        assert!(parse(serde_json::json!({ "streak": 0, "hours": 4 })).is_err());
+        assert!(parse(serde_json::json!({ "streak": 3, "hours": 0 })).is_err());
+        assert!(parse(serde_json::json!({ "streak": 3 })).is_err());
+        assert!(parse(serde_json::json!("3 losses")).is_err());
+    }
+}
@@ -0,0 +1,466 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+use crate::{
+    errors::{AppError, Result},
+    models::BrokerConnection,
+    services::task_supervisor::{spawn_supervised, TaskClass},
+};
+
+// Connections re-encrypted per query during a rotation
+pub const ROTATION_BATCH: i64 = 100;
+const NONCE_BYTES: usize = 12;
+
+// The current encryption key and the previous ones still needed to read older rows
+pub struct KeyRing {
+    current_id: String,
+    keys: HashMap<String, Aes256Gcm>,
+}
+
+impl KeyRing {
+    // `keys` maps key ids to hex-encoded 32-byte keys and must contain `current_id`
+    pub fn new(current_id: &str, keys: &HashMap<String, String>) -> anyhow::Result<Self> {
+        let mut ciphers = HashMap::new();
+        for (id, hex_key) in keys {
+            let bytes = hex::decode(hex_key.trim()).map_err(|_| anyhow::anyhow!("Encryption key {} is not hex", id))?;
+            if bytes.len() != 32 {
+                anyhow::bail!("Encryption key {} must be 32 bytes, got {}", id, bytes.len());
+            }
+            ciphers.insert(id.clone(), Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&bytes)));
+        }
+        if !ciphers.contains_key(current_id) {
+            anyhow::bail!("The current encryption key {} is not in the key ring", current_id);
+        }
+        Ok(KeyRing { current_id: current_id.to_string(), keys: ciphers })
+    }
+
+    pub fn current_id(&self) -> &str {
+        &self.current_id
+    }
+
+    // Hex of a random nonce followed by the ciphertext, always under the current key
+    pub fn encrypt(&self, plaintext: &str) -> String {
+        let cipher = &self.keys[&self.current_id];
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = cipher.encrypt(&nonce, plaintext.as_bytes()).expect("AES-GCM encryption does not fail");
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+        hex::encode(sealed)
+    }
+
+    // A missing key id means the value predates encryption and is stored as is
+    pub fn decrypt(&self, key_id: Option<&str>, stored: &str) -> std::result::Result<String, String> {
+        let Some(key_id) = key_id else {
+            return Ok(stored.to_string());
+        };
+        let cipher = self.keys.get(key_id).ok_or_else(|| format!("unknown key {}", key_id))?;
+        let sealed = hex::decode(stored).map_err(|_| "not hex".to_string())?;
+        if sealed.len() < NONCE_BYTES {
+            return Err("too short".to_string());
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_BYTES);
+        let plaintext = cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| format!("does not decrypt with key {}", key_id))?;
+        String::from_utf8(plaintext).map_err(|_| "not UTF-8".to_string())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BrokerCredentials {
+    pub api_key: String,
+    pub api_secret: String,
+}
+
+#[async_trait]
+pub trait CredentialStore: Send + Sync {
+    async fn stale(&self, key_id: &str, after: Option<Uuid>, limit: i64) -> Result<Vec<BrokerConnection>>;
+    async fn count_stale(&self, key_id: &str) -> Result<i64>;
+    // False when the row changed key since it was read
+    async fn replace(&self, connection: &BrokerConnection, sealed: &BrokerCredentials, key_id: &str) -> Result<bool>;
+    async fn mark_needs_credentials(&self, connection_id: Uuid) -> Result<()>;
+}
+
+pub struct PgCredentialStore {
+    pool: PgPool,
+}
+
+impl PgCredentialStore {
+    pub fn new(pool: PgPool) -> Self {
+        PgCredentialStore { pool }
+    }
+}
+
+#[async_trait]
+impl CredentialStore for PgCredentialStore {
+    async fn stale(&self, key_id: &str, after: Option<Uuid>, limit: i64) -> Result<Vec<BrokerConnection>> {
+        BrokerConnection::find_stale_credentials(&self.pool, key_id, after, limit).await
+    }
+
+    async fn count_stale(&self, key_id: &str) -> Result<i64> {
+        BrokerConnection::count_stale_credentials(&self.pool, key_id).await
+    }
+
+    async fn replace(&self, connection: &BrokerConnection, sealed: &BrokerCredentials, key_id: &str) -> Result<bool> {
+        BrokerConnection::replace_credentials(
+            &self.pool,
+            connection.id,
+            connection.credentials_key_id.as_deref(),
+            &sealed.api_key,
+            &sealed.api_secret,
+            key_id,
+        )
+        .await
+    }
+
+    async fn mark_needs_credentials(&self, connection_id: Uuid) -> Result<()> {
+        BrokerConnection::mark_needs_credentials(&self.pool, connection_id).await
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RotationState {
+    Idle,
+    Running,
+    Finished,
+    Failed,
+}
+
+// Progress of the re-encryption job on this instance
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct RotationStatus {
+    pub state: RotationState,
+    pub key_id: String,
+    // Connections not under the current key when the job started
+    pub total: i64,
+    pub processed: i64,
+    pub rotated: i64,
+    // Connections whose credentials could not be decrypted and now need re-entry
+    pub quarantined: i64,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub error: Option<String>,
+}
+
+// Seals broker credentials with the key ring on the way into the database and opens them on
+// the way out to the broker, and runs the job that moves every row to the current key.
+pub struct CredentialVault {
+    keys: KeyRing,
+    store: Arc<dyn CredentialStore>,
+    rotation: Mutex<RotationStatus>,
+}
+
+impl CredentialVault {
+    pub fn new(keys: KeyRing, store: Arc<dyn CredentialStore>) -> Self {
+        let rotation = RotationStatus {
+            state: RotationState::Idle,
+            key_id: keys.current_id().to_string(),
+            total: 0,
+            processed: 0,
+            rotated: 0,
+            quarantined: 0,
+            started_at: None,
+            finished_at: None,
+            error: None,
+        };
+        CredentialVault { keys, store, rotation: Mutex::new(rotation) }
+    }
+
+    pub fn current_key_id(&self) -> &str {
+        self.keys.current_id()
+    }
+
+    pub fn seal_credentials(&self, api_key: &str, api_secret: &str) -> BrokerCredentials {
+        BrokerCredentials { api_key: self.keys.encrypt(api_key), api_secret: self.keys.encrypt(api_secret) }
+    }
+
+    // The connection with its plaintext credentials encrypted under the current key
+    pub fn seal(&self, connection: BrokerConnection) -> BrokerConnection {
+        let sealed = self.seal_credentials(&connection.api_key, &connection.api_secret);
+        BrokerConnection {
+            api_key: sealed.api_key,
+            api_secret: sealed.api_secret,
+            credentials_key_id: Some(self.current_key_id().to_string()),
+            ..connection
+        }
+    }
+
+    fn decrypt(&self, connection: &BrokerConnection) -> std::result::Result<BrokerCredentials, String> {
+        let key_id = connection.credentials_key_id.as_deref();
+        Ok(BrokerCredentials {
+            api_key: self.keys.decrypt(key_id, &connection.api_key)?,
+            api_secret: self.keys.decrypt(key_id, &connection.api_secret)?,
+        })
+    }
+
+    async fn quarantine(&self, connection: &BrokerConnection, reason: &str) {
+        tracing::warn!(
+            "Credentials of broker connection {} cannot be read ({}); asking the user to enter them again",
+            connection.id,
+            reason
+        );
+        if let Err(e) = self.store.mark_needs_credentials(connection.id).await {
+            tracing::error!("Could not flag broker connection {} for new credentials: {}", connection.id, e);
+        }
+    }
+
+    // Credentials that cannot be decrypted flag the connection for re-entry instead of failing
+    // every later read the same way
+    pub async fn open(&self, connection: &BrokerConnection) -> Result<BrokerCredentials> {
+        let unreadable = || {
+            AppError::Unprocessable(format!(
+                "The credentials of broker connection {} can no longer be read; enter them again",
+                connection.name
+            ))
+        };
+        if connection.needs_credentials {
+            return Err(unreadable());
+        }
+        match self.decrypt(connection) {
+            Ok(credentials) => Ok(credentials),
+            Err(reason) => {
+                self.quarantine(connection, &reason).await;
+                Err(unreadable())
+            }
+        }
+    }
+
+    pub async fn warn_if_stale(&self) {
+        match self.store.count_stale(self.current_key_id()).await {
+            Ok(0) => {}
+            Ok(stale) => tracing::warn!(
+                "{} broker connections are not encrypted with the current key {}; run POST /api/v1/admin/rotate-encryption",
+                stale,
+                self.current_key_id()
+            ),
+            Err(e) => tracing::error!("Could not count broker connections under old encryption keys: {}", e),
+        }
+    }
+
+    pub fn rotation_status(&self) -> RotationStatus {
+        self.rotation.lock().unwrap().clone()
+    }
+
+    // Starts the re-encryption job unless one is already running; either way returns its status
+    pub fn start_rotation(self: &Arc<Self>) -> RotationStatus {
+        {
+            let mut rotation = self.rotation.lock().unwrap();
+            if rotation.state == RotationState::Running {
+                return rotation.clone();
+            }
+            *rotation = RotationStatus {
+                state: RotationState::Running,
+                key_id: self.current_key_id().to_string(),
+                total: 0,
+                processed: 0,
+                rotated: 0,
+                quarantined: 0,
+                started_at: Some(Utc::now()),
+                finished_at: None,
+                error: None,
+            };
+        }
+        let vault = self.clone();
+        spawn_supervised("credentials:rotation", TaskClass::Background, async move {
+            vault.rotate(ROTATION_BATCH).await;
+        });
+        self.rotation_status()
+    }
+
+    // Re-encrypts every connection not under the current key, `batch_size` at a time. Rows are
+    // only swapped if their key did not change meanwhile, so reads never see a half-rotated row.
+    pub async fn rotate(&self, batch_size: i64) -> RotationStatus {
+        let result = self.rotate_batches(batch_size).await;
+        let mut rotation = self.rotation.lock().unwrap();
+        rotation.state = match result {
+            Ok(()) => RotationState::Finished,
+            Err(e) => {
+                tracing::error!("Encryption key rotation failed after {} connections: {}", rotation.processed, e);
+                rotation.error = Some(e.to_string());
+                RotationState::Failed
+            }
+        };
+        rotation.finished_at = Some(Utc::now());
+        tracing::info!(
+            "Encryption key rotation to {} {:?}: {} rotated, {} quarantined",
+            rotation.key_id,
+            rotation.state,
+            rotation.rotated,
+            rotation.quarantined
+        );
+        rotation.clone()
+    }
+
+    async fn rotate_batches(&self, batch_size: i64) -> Result<()> {
+        let key_id = self.current_key_id();
+        let total = self.store.count_stale(key_id).await?;
+        self.rotation.lock().unwrap().total = total;
+
+        let mut after = None;
+        loop {
+            let batch = self.store.stale(key_id, after, batch_size).await?;
+            let Some(last) = batch.last() else {
+                return Ok(());
+            };
+            after = Some(last.id);
+
+            for connection in &batch {
+                let (rotated, quarantined) = match self.decrypt(connection) {
+                    Ok(credentials) => {
+                        let sealed = self.seal_credentials(&credentials.api_key, &credentials.api_secret);
+                        (self.store.replace(connection, &sealed, key_id).await?, false)
+                    }
+                    Err(reason) => {
+                        self.quarantine(connection, &reason).await;
+                        (false, true)
+                    }
+                };
+                let mut rotation = self.rotation.lock().unwrap();
+                rotation.processed += 1;
+                rotation.rotated += rotated as i64;
+                rotation.quarantined += quarantined as i64;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD_KEY: &str = "1111111111111111111111111111111111111111111111111111111111111111";
+    const NEW_KEY: &str = "2222222222222222222222222222222222222222222222222222222222222222";
+
+    #[derive(Default)]
+    struct MemoryStore {
+        connections: Mutex<Vec<BrokerConnection>>,
+    }
+
+    #[async_trait]
+    impl CredentialStore for MemoryStore {
+        async fn stale(&self, key_id: &str, after: Option<Uuid>, limit: i64) -> Result<Vec<BrokerConnection>> {
+            let mut stale: Vec<BrokerConnection> = self
+                .connections
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|c| c.credentials_key_id.as_deref() != Some(key_id) && !c.needs_credentials)
+                .filter(|c| after.is_none_or(|after| c.id > after))
+                .cloned()
+                .collect();
+            stale.sort_by_key(|c| c.id);
+            stale.truncate(limit as usize);
+            Ok(stale)
+        }
+
+        async fn count_stale(&self, key_id: &str) -> Result<i64> {
+            Ok(self.stale(key_id, None, i64::MAX).await?.len() as i64)
+        }
+
+        async fn replace(&self, connection: &BrokerConnection, sealed: &BrokerCredentials, key_id: &str) -> Result<bool> {
+            let mut connections = self.connections.lock().unwrap();
+            let Some(row) = connections
+                .iter_mut()
+                .find(|c| c.id == connection.id && c.credentials_key_id == connection.credentials_key_id)
+            else {
+                return Ok(false);
+            };
+            row.api_key = sealed.api_key.clone();
+            row.api_secret = sealed.api_secret.clone();
+            row.credentials_key_id = Some(key_id.to_string());
+            Ok(true)
+        }
+
+        async fn mark_needs_credentials(&self, connection_id: Uuid) -> Result<()> {
+            for row in self.connections.lock().unwrap().iter_mut().filter(|c| c.id == connection_id) {
+                row.needs_credentials = true;
+            }
+            Ok(())
+        }
+    }
+
+    fn ring(current: &str, keys: &[(&str, &str)]) -> KeyRing {
+        let keys = keys.iter().map(|(id, key)| (id.to_string(), key.to_string())).collect();
+        KeyRing::new(current, &keys).unwrap()
+    }
+
+    fn connection(secret: &str) -> BrokerConnection {
+        BrokerConnection::new(
+            Uuid::new_v4(),
+            "Main".to_string(),
+            "MT5".to_string(),
+            format!("{}-key", secret),
+            secret.to_string(),
+            Some("Broker-Live".to_string()),
+            Some("7001".to_string()),
+            false,
+        )
+    }
+
+    fn row(store: &MemoryStore, id: Uuid) -> BrokerConnection {
+        store.connections.lock().unwrap().iter().find(|c| c.id == id).unwrap().clone()
+    }
+
+    #[tokio::test]
+    async fn test_rotation_moves_every_row_to_the_new_key() {
+        let store = Arc::new(MemoryStore::default());
+        let before = CredentialVault::new(ring("k1", &[("k1", OLD_KEY)]), store.clone());
+        let legacy = connection("plain");
+        let mut seeded = vec![legacy.clone()];
+        seeded.extend(["alpha", "beta", "gamma"].map(|secret| before.seal(connection(secret))));
+        *store.connections.lock().unwrap() = seeded.clone();
+        assert_ne!(seeded[1].api_secret, "alpha");
+
+        let vault = CredentialVault::new(ring("k2", &[("k1", OLD_KEY), ("k2", NEW_KEY)]), store.clone());
+        // Rows under the previous key still open, new ones are sealed with the current key
+        assert_eq!(vault.open(&seeded[1]).await.unwrap().api_secret, "alpha");
+        assert_eq!(vault.open(&legacy).await.unwrap().api_key, "plain-key");
+        let added = vault.seal(connection("delta"));
+        assert_eq!(added.credentials_key_id.as_deref(), Some("k2"));
+        assert!(before.open(&added).await.is_err());
+
+        let status = vault.rotate(2).await;
+        assert_eq!((status.state, status.total, status.processed, status.rotated, status.quarantined), (RotationState::Finished, 4, 4, 4, 0));
+        assert_eq!(store.count_stale("k2").await.unwrap(), 0);
+
+        let only_new_key = CredentialVault::new(ring("k2", &[("k2", NEW_KEY)]), store.clone());
+        for (original, secret) in seeded.iter().zip(["plain", "alpha", "beta", "gamma"]) {
+            let rotated = row(&store, original.id);
+            assert_eq!(rotated.credentials_key_id.as_deref(), Some("k2"));
+            let credentials = only_new_key.open(&rotated).await.unwrap();
+            assert_eq!((credentials.api_key, credentials.api_secret), (format!("{}-key", secret), secret.to_string()));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_credentials_are_quarantined() {
+        let store = Arc::new(MemoryStore::default());
+        let vault = CredentialVault::new(ring("k2", &[("k1", OLD_KEY), ("k2", NEW_KEY)]), store.clone());
+        let old = CredentialVault::new(ring("k1", &[("k1", OLD_KEY)]), store.clone());
+        let healthy = old.seal(connection("alpha"));
+        let corrupted = BrokerConnection { api_secret: "00ff".repeat(10), ..old.seal(connection("beta")) };
+        let retired_key = BrokerConnection { credentials_key_id: Some("k0".to_string()), ..old.seal(connection("gamma")) };
+        *store.connections.lock().unwrap() = vec![healthy.clone(), corrupted.clone(), retired_key.clone()];
+
+        let error = vault.open(&corrupted).await.unwrap_err();
+        assert!(matches!(error, AppError::Unprocessable(_)));
+        assert!(row(&store, corrupted.id).needs_credentials);
+
+        let status = vault.rotate(ROTATION_BATCH).await;
+        assert_eq!((status.state, status.processed, status.rotated, status.quarantined), (RotationState::Finished, 2, 1, 1));
+        assert!(row(&store, retired_key.id).needs_credentials);
+        assert_eq!(row(&store, healthy.id).credentials_key_id.as_deref(), Some("k2"));
+        // Quarantined rows are not retried, and reads fail without touching the ciphertext
+        assert_eq!(store.count_stale("k2").await.unwrap(), 0);
+        assert!(vault.open(&row(&store, retired_key.id)).await.is_err());
+    }
+}
@@ -0,0 +1,114 @@
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::services::quote_service::{QuoteLookup, QuoteService, QuoteSource};
+
+// Market convention for which currency of a pair is the base: EURUSD, GBPJPY, USDCHF
+const BASE_PRIORITY: [&str; 8] = ["EUR", "GBP", "AUD", "NZD", "USD", "CAD", "CHF", "JPY"];
+
+// The rate an amount was converted at, shown next to converted amounts
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct Conversion {
+    pub from: String,
+    pub to: String,
+    // Units of `to` per unit of `from`
+    pub rate: f64,
+    // The pair quoted for it; null when no conversion was needed
+    pub symbol: Option<String>,
+    pub source: Option<QuoteSource>,
+    pub indicative: bool,
+}
+
+impl Conversion {
+    pub fn convert(&self, amount: f64) -> f64 {
+        amount * self.rate
+    }
+}
+
+// Rates from the mid of the pair's quote, through the same chain that prices trades
+pub struct CurrencyConverter;
+
+impl CurrencyConverter {
+    // None when no source quotes the pair either way round
+    pub async fn rate(
+        quotes: &QuoteService,
+        connection: Option<&dyn QuoteLookup>,
+        user_id: Uuid,
+        from: &str,
+        to: &str,
+        now: DateTime<Utc>,
+    ) -> Option<Conversion> {
+        let (from, to) = (from.trim().to_ascii_uppercase(), to.trim().to_ascii_uppercase());
+        if from == to {
+            return Some(Conversion { from, to, rate: 1.0, symbol: None, source: None, indicative: false });
+        }
+        for (base, quote) in Self::pairs(&from, &to) {
+            let symbol = format!("{}{}", base, quote);
+            let Some(sourced) = quotes.quote_on(connection, user_id, &symbol, now).await else {
+                continue;
+            };
+            let mid = (sourced.bid + sourced.ask) / 2.0;
+            if mid <= 0.0 {
+                continue;
+            }
+            return Some(Conversion {
+                rate: if base == from { mid } else { 1.0 / mid },
+                from,
+                to,
+                symbol: Some(symbol),
+                source: Some(sourced.source),
+                indicative: sourced.indicative,
+            });
+        }
+        None
+    }
+
+    // The conventionally quoted pair first; currencies outside the list are tried both ways round
+    fn pairs<'a>(from: &'a str, to: &'a str) -> Vec<(&'a str, &'a str)> {
+        let priority = |currency: &str| BASE_PRIORITY.iter().position(|c| *c == currency);
+        match (priority(from), priority(to)) {
+            (Some(f), Some(t)) if f < t => vec![(from, to)],
+            (Some(_), Some(_)) => vec![(to, from)],
+            _ => vec![(from, to), (to, from)],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{errors::Result, services::market_data_streamer::Quote};
+    use async_trait::async_trait;
+    use std::sync::Arc;
+
+    struct Eurusd;
+
+    #[async_trait]
+    impl QuoteLookup for Eurusd {
+        async fn quote(&self, _user_id: Uuid, symbol: &str) -> Result<Option<Quote>> {
+            // Brokers have no USDEUR; anything other than EURUSD is unquoted
+            Ok((symbol == "EURUSD").then(|| Quote { symbol: symbol.to_string(), bid: 1.0999, ask: 1.1001, time: Utc::now() }))
+        }
+    }
+
+    fn quotes() -> QuoteService {
+        QuoteService::new(vec![(QuoteSource::Broker, Arc::new(Eurusd) as Arc<dyn QuoteLookup>)])
+    }
+
+    #[tokio::test]
+    async fn test_rates_come_from_the_conventional_pair_either_way() {
+        let quotes = quotes();
+        let user_id = Uuid::new_v4();
+
+        let to_usd = CurrencyConverter::rate(&quotes, None, user_id, "eur", "USD", Utc::now()).await.unwrap();
+        assert_eq!((to_usd.rate, to_usd.symbol.as_deref(), to_usd.source), (1.1, Some("EURUSD"), Some(QuoteSource::Broker)));
+        let to_eur = CurrencyConverter::rate(&quotes, None, user_id, "USD", "EUR", Utc::now()).await.unwrap();
+        assert!((to_eur.convert(110.0) - 100.0).abs() < 1e-9);
+
+        let same = CurrencyConverter::rate(&quotes, None, user_id, "USD", "usd", Utc::now()).await.unwrap();
+        assert_eq!((same.rate, same.symbol), (1.0, None));
+        assert!(CurrencyConverter::rate(&quotes, None, user_id, "GBP", "JPY", Utc::now()).await.is_none());
+    }
+}
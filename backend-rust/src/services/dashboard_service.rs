@@ -0,0 +1,195 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    errors::Result,
+    models::{ChangeMarker, DailyTradeSummary, Trade},
+    services::cache_service::CacheService,
+};
+
+pub const SPARKLINE_DAYS: usize = 7;
+const SPARKLINE_STEP_SECONDS: i64 = 86_400;
+const SPARKLINE_CACHE_TTL_SECONDS: u64 = 60;
+
+// Where cached sparklines are dropped from when a trade closes
+#[async_trait]
+pub trait SparklineCache: Send + Sync {
+    async fn remove(&self, key: &str) -> Result<()>;
+}
+
+#[async_trait]
+impl SparklineCache for CacheService {
+    async fn remove(&self, key: &str) -> Result<()> {
+        self.delete(key).await
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Sparklines {
+    pub start: DateTime<Utc>,
+    pub step_seconds: i64,
+    #[serde(serialize_with = "crate::money::serialize_aggregate_series")]
+    pub profit: Vec<f64>,
+    pub trade_count: Vec<i64>,
+    #[serde(serialize_with = "crate::money::serialize_percent_series")]
+    pub win_rate: Vec<f64>,
+    // Robot config changes inside the window, only with `include=changes`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub change_markers: Option<Vec<ChangeMarker>>,
+}
+
+pub struct DashboardService;
+
+impl DashboardService {
+    pub fn sparklines_cache_key(user_id: Uuid) -> String {
+        format!("dashboard:sparklines:{}", user_id)
+    }
+
+    // Midnight UTC six days ago, so the window is the last seven calendar days including today
+    pub fn sparkline_window_start(now: DateTime<Utc>) -> DateTime<Utc> {
+        let today = now
+            .date_naive()
+            .and_hms_opt(0, 0, 0)
+            .expect("midnight is a valid time")
+            .and_utc();
+        today - Duration::days(SPARKLINE_DAYS as i64 - 1)
+    }
+
+    pub fn build_sparklines(start: DateTime<Utc>, rows: &[DailyTradeSummary]) -> Sparklines {
+        let mut profit = vec![0.0; SPARKLINE_DAYS];
+        let mut trade_count = vec![0; SPARKLINE_DAYS];
+        let mut win_rate = vec![0.0; SPARKLINE_DAYS];
+
+        for row in rows {
+            let offset = (row.day - start).num_days();
+            if offset < 0 || offset >= SPARKLINE_DAYS as i64 {
+                continue;
+            }
+            let index = offset as usize;
+            profit[index] = row.profit;
+            trade_count[index] = row.trades;
+            win_rate[index] = if row.trades > 0 {
+                (row.winning_trades as f64 / row.trades as f64) * 100.0
+            } else {
+                0.0
+            };
+        }
+
+        Sparklines {
+            start,
+            step_seconds: SPARKLINE_STEP_SECONDS,
+            profit,
+            trade_count,
+            win_rate,
+            change_markers: None,
+        }
+    }
+
+    // The cache only holds the current series; the series as of a past instant is always computed
+    pub async fn get_sparklines(pool: &PgPool, cache: &CacheService, user_id: Uuid, as_of: Option<DateTime<Utc>>) -> Result<Sparklines> {
+        if let Some(as_of) = as_of {
+            let start = Self::sparkline_window_start(as_of);
+            let rows = Trade::get_daily_summaries(pool, user_id, start, Some(as_of)).await?;
+            return Ok(Self::build_sparklines(start, &rows));
+        }
+
+        let key = Self::sparklines_cache_key(user_id);
+
+        // The cache is an optimization only; Redis trouble falls through to the database
+        match cache.get_json::<Sparklines>(&key).await {
+            Ok(Some(cached)) => return Ok(cached),
+            Ok(None) => {}
+            Err(e) => tracing::warn!("Sparkline cache read failed for user {}: {}", user_id, e),
+        }
+
+        let start = Self::sparkline_window_start(Utc::now());
+        let rows = Trade::get_daily_summaries(pool, user_id, start, None).await?;
+        let sparklines = Self::build_sparklines(start, &rows);
+
+        if let Err(e) = cache.set_json(&key, &sparklines, SPARKLINE_CACHE_TTL_SECONDS).await {
+            tracing::warn!("Sparkline cache write failed for user {}: {}", user_id, e);
+        }
+
+        Ok(sparklines)
+    }
+
+    // Called whenever a trade closes so the next dashboard load reflects it immediately
+    pub async fn invalidate_sparklines(cache: &dyn SparklineCache, user_id: Uuid) {
+        if let Err(e) = cache.remove(&Self::sparklines_cache_key(user_id)).await {
+            tracing::warn!("Sparkline cache invalidation failed for user {}: {}", user_id, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn summary(start: DateTime<Utc>, day: i64, trades: i64, winning_trades: i64, profit: f64) -> DailyTradeSummary {
+        DailyTradeSummary {
+            day: start + Duration::days(day),
+            trades,
+            winning_trades,
+            profit,
+        }
+    }
+
+    #[test]
+    fn test_window_start_is_midnight_six_days_back() {
+        let now = Utc.with_ymd_and_hms(2024, 3, 10, 15, 42, 7).unwrap();
+        let start = DashboardService::sparkline_window_start(now);
+        assert_eq!(start, Utc.with_ymd_and_hms(2024, 3, 4, 0, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn test_sparklines_are_dense_with_zero_days() {
+        let start = Utc.with_ymd_and_hms(2024, 3, 4, 0, 0, 0).unwrap();
+        let rows = vec![
+            summary(start, 1, 4, 3, 120.5),
+            summary(start, 4, 2, 0, -30.0),
+        ];
+
+        let sparklines = DashboardService::build_sparklines(start, &rows);
+
+        assert_eq!(sparklines.start, start);
+        assert_eq!(sparklines.step_seconds, 86_400);
+        assert_eq!(sparklines.profit, vec![0.0, 120.5, 0.0, 0.0, -30.0, 0.0, 0.0]);
+        assert_eq!(sparklines.trade_count, vec![0, 4, 0, 0, 2, 0, 0]);
+        assert_eq!(sparklines.win_rate, vec![0.0, 75.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_sparklines_without_trades_still_have_seven_points() {
+        let start = Utc.with_ymd_and_hms(2024, 3, 4, 0, 0, 0).unwrap();
+        let sparklines = DashboardService::build_sparklines(start, &[]);
+
+        assert_eq!(sparklines.profit.len(), SPARKLINE_DAYS);
+        assert_eq!(sparklines.trade_count.len(), SPARKLINE_DAYS);
+        assert_eq!(sparklines.win_rate.len(), SPARKLINE_DAYS);
+        assert!(sparklines.trade_count.iter().all(|count| *count == 0));
+    }
+
+    #[test]
+    fn test_sparklines_ignore_rows_outside_window() {
+        let start = Utc.with_ymd_and_hms(2024, 3, 4, 0, 0, 0).unwrap();
+        let rows = vec![summary(start, -1, 1, 1, 10.0), summary(start, 7, 1, 1, 10.0)];
+
+        let sparklines = DashboardService::build_sparklines(start, &rows);
+        assert!(sparklines.profit.iter().all(|p| *p == 0.0));
+    }
+
+    #[test]
+    fn test_sparklines_cache_key_is_per_user() {
+        let a = Uuid::new_v4();
+        let b = Uuid::new_v4();
+        assert_ne!(
+            DashboardService::sparklines_cache_key(a),
+            DashboardService::sparklines_cache_key(b)
+        );
+    }
+}
@@ -0,0 +1,341 @@
+use async_trait::async_trait;
+use axum::http::Method;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    errors::{AppError, Result},
+    models::{Delegation, User},
+};
+
+// Everything a delegate can reach: the grantor's trades, statistics and exports, read only
+const DELEGATED_READ_PATHS: &[&str] = &["/api/v1/trades", "/api/v1/trades/statistics", "/api/v1/trades/search"];
+const DELEGATED_READ_PREFIXES: &[&str] = &["/api/v1/exports/", "/api/v1/statements/"];
+
+#[async_trait]
+pub trait DelegationStore: Send + Sync {
+    async fn find_by_token(&self, token: Uuid) -> Result<Option<Delegation>>;
+    async fn find_active(&self, grantor_id: Uuid, delegate_id: Uuid) -> Result<Option<Delegation>>;
+    async fn mark_accepted(&self, id: Uuid, delegate_id: Uuid, now: DateTime<Utc>) -> Result<()>;
+    async fn revoke(&self, grantor_id: Uuid, id: Uuid, now: DateTime<Utc>) -> Result<bool>;
+    async fn record_access(&self, id: Uuid, method: &str, path: &str, now: DateTime<Utc>) -> Result<()>;
+}
+
+pub struct PgDelegationStore {
+    pool: PgPool,
+}
+
+impl PgDelegationStore {
+    pub fn new(pool: PgPool) -> Self {
+        PgDelegationStore { pool }
+    }
+}
+
+#[async_trait]
+impl DelegationStore for PgDelegationStore {
+    async fn find_by_token(&self, token: Uuid) -> Result<Option<Delegation>> {
+        Delegation::find_by_token(&self.pool, token).await
+    }
+
+    async fn find_active(&self, grantor_id: Uuid, delegate_id: Uuid) -> Result<Option<Delegation>> {
+        Delegation::find_active(&self.pool, grantor_id, delegate_id).await
+    }
+
+    async fn mark_accepted(&self, id: Uuid, delegate_id: Uuid, now: DateTime<Utc>) -> Result<()> {
+        Delegation::mark_accepted(&self.pool, id, delegate_id, now).await
+    }
+
+    async fn revoke(&self, grantor_id: Uuid, id: Uuid, now: DateTime<Utc>) -> Result<bool> {
+        Delegation::revoke(&self.pool, grantor_id, id, now).await
+    }
+
+    async fn record_access(&self, id: Uuid, method: &str, path: &str, now: DateTime<Utc>) -> Result<()> {
+        Delegation::record_access(&self.pool, id, method, path, now).await
+    }
+}
+
+// Put in the request extensions when a delegate acts on behalf of a grantor; the `User`
+// extension is then the grantor
+#[derive(Debug, Clone)]
+pub struct DelegatedAccess {
+    pub delegation_id: Uuid,
+    pub grantor_id: Uuid,
+    pub delegate_id: Uuid,
+}
+
+pub struct DelegationService;
+
+impl DelegationService {
+    pub fn normalize_invite(grantor: &User, email: &str) -> Result<String> {
+        let email = email.trim().to_lowercase();
+        if email == grantor.email.to_lowercase() {
+            return Err(AppError::Validation("You cannot delegate access to yourself".to_string()));
+        }
+        Ok(email)
+    }
+
+    pub fn is_delegated_read(method: &Method, path: &str) -> bool {
+        let path = path.trim_end_matches('/');
+        *method == Method::GET
+            && (DELEGATED_READ_PATHS.contains(&path)
+                || DELEGATED_READ_PREFIXES.iter().any(|prefix| path.starts_with(prefix)))
+    }
+
+    pub async fn accept(store: &dyn DelegationStore, user: &User, token: Uuid, now: DateTime<Utc>) -> Result<Delegation> {
+        let not_found = || AppError::NotFound("Invitation not found".to_string());
+        let mut delegation = store.find_by_token(token).await?.ok_or_else(not_found)?;
+
+        if delegation.revoked_at.is_some() {
+            return Err(not_found());
+        }
+        if delegation.grantor_id == user.id {
+            return Err(AppError::Validation("You cannot accept your own invitation".to_string()));
+        }
+        // The invitation is tied to the address it was sent to, so a forwarded link is useless
+        if !delegation.delegate_email.eq_ignore_ascii_case(&user.email) {
+            return Err(AppError::Forbidden("This invitation was sent to another address".to_string()));
+        }
+        match delegation.delegate_id {
+            Some(delegate_id) if delegate_id == user.id => return Ok(delegation),
+            Some(_) => return Err(not_found()),
+            None => {}
+        }
+        if delegation.invite_expires_at <= now {
+            return Err(AppError::Validation("This invitation has expired".to_string()));
+        }
+
+        store.mark_accepted(delegation.id, user.id, now).await?;
+        delegation.delegate_id = Some(user.id);
+        delegation.accepted_at = Some(now);
+        Ok(delegation)
+    }
+
+    pub async fn revoke(store: &dyn DelegationStore, grantor_id: Uuid, id: Uuid, now: DateTime<Utc>) -> Result<()> {
+        if !store.revoke(grantor_id, id, now).await? {
+            return Err(AppError::NotFound("Delegation not found".to_string()));
+        }
+        Ok(())
+    }
+
+    // Checks an X-On-Behalf-Of request from `delegate_id` and records it in the access log
+    pub async fn authorize(
+        store: &dyn DelegationStore,
+        delegate_id: Uuid,
+        on_behalf_of: &str,
+        method: &Method,
+        path: &str,
+        now: DateTime<Utc>,
+    ) -> Result<DelegatedAccess> {
+        let grantor_id = Uuid::parse_str(on_behalf_of.trim())
+            .map_err(|_| AppError::Validation("X-On-Behalf-Of must be a user id".to_string()))?;
+
+        if !Self::is_delegated_read(method, path) {
+            tracing::warn!(target: "audit", "Delegate {} denied {} {} on behalf of {}", delegate_id, method, path, grantor_id);
+            return Err(AppError::Forbidden("Delegated access is read-only and limited to trades, statistics and statements".to_string()));
+        }
+
+        let Some(delegation) = store.find_active(grantor_id, delegate_id).await? else {
+            tracing::warn!(target: "audit", "User {} has no delegation from {} for {} {}", delegate_id, grantor_id, method, path);
+            return Err(AppError::Forbidden("You have no delegated access to this account".to_string()));
+        };
+
+        store.record_access(delegation.id, method.as_str(), path, now).await?;
+        tracing::info!(target: "audit", "Delegate {} read {} {} on behalf of {}", delegate_id, method, path, grantor_id);
+        Ok(DelegatedAccess { delegation_id: delegation.id, grantor_id, delegate_id })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct FakeStore {
+        delegations: Mutex<Vec<Delegation>>,
+        accesses: Mutex<Vec<(Uuid, String, String)>>,
+    }
+
+    impl FakeStore {
+        fn update(&self, id: Uuid, change: impl FnOnce(&mut Delegation)) {
+            let mut delegations = self.delegations.lock().unwrap();
+            change(delegations.iter_mut().find(|d| d.id == id).unwrap());
+        }
+    }
+
+    #[async_trait]
+    impl DelegationStore for FakeStore {
+        async fn find_by_token(&self, token: Uuid) -> Result<Option<Delegation>> {
+            Ok(self.delegations.lock().unwrap().iter().find(|d| d.invite_token == token).cloned())
+        }
+
+        async fn find_active(&self, grantor_id: Uuid, delegate_id: Uuid) -> Result<Option<Delegation>> {
+            Ok(self
+                .delegations
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|d| {
+                    d.grantor_id == grantor_id
+                        && d.delegate_id == Some(delegate_id)
+                        && d.accepted_at.is_some()
+                        && d.revoked_at.is_none()
+                })
+                .cloned())
+        }
+
+        async fn mark_accepted(&self, id: Uuid, delegate_id: Uuid, now: DateTime<Utc>) -> Result<()> {
+            self.update(id, |d| {
+                d.delegate_id = Some(delegate_id);
+                d.accepted_at = Some(now);
+            });
+            Ok(())
+        }
+
+        async fn revoke(&self, grantor_id: Uuid, id: Uuid, now: DateTime<Utc>) -> Result<bool> {
+            let mut delegations = self.delegations.lock().unwrap();
+            match delegations.iter_mut().find(|d| d.id == id && d.grantor_id == grantor_id && d.revoked_at.is_none()) {
+                Some(d) => {
+                    d.revoked_at = Some(now);
+                    Ok(true)
+                }
+                None => Ok(false),
+            }
+        }
+
+        async fn record_access(&self, id: Uuid, method: &str, path: &str, _now: DateTime<Utc>) -> Result<()> {
+            self.accesses.lock().unwrap().push((id, method.to_string(), path.to_string()));
+            Ok(())
+        }
+    }
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 12, 18, 10, 0, 0).unwrap()
+    }
+
+    fn user(email: &str) -> User {
+        User::new(email.to_string(), "hash".to_string())
+    }
+
+    // Grantor invites the accountant, who accepts
+    async fn accepted(store: &FakeStore) -> (User, User, Delegation) {
+        let grantor = user("trader@example.com");
+        let accountant = user("books@example.com");
+        let email = DelegationService::normalize_invite(&grantor, " Books@Example.com ").unwrap();
+        let invitation = Delegation::new(grantor.id, email, now());
+        store.delegations.lock().unwrap().push(invitation.clone());
+
+        let delegation = DelegationService::accept(store, &accountant, invitation.invite_token, now()).await.unwrap();
+        (grantor, accountant, delegation)
+    }
+
+    async fn read(store: &FakeStore, delegate: &User, on_behalf_of: Uuid, method: Method, path: &str) -> Result<DelegatedAccess> {
+        DelegationService::authorize(store, delegate.id, &on_behalf_of.to_string(), &method, path, now()).await
+    }
+
+    #[tokio::test]
+    async fn test_accepted_delegate_reads_trades_and_statistics_and_is_logged() {
+        let store = FakeStore::default();
+        let (grantor, accountant, delegation) = accepted(&store).await;
+        assert_eq!(delegation.status(now()), "active");
+
+        for path in ["/api/v1/trades", "/api/v1/trades/statistics"] {
+            let access = read(&store, &accountant, grantor.id, Method::GET, path).await.unwrap();
+            assert_eq!(
+                (access.delegation_id, access.grantor_id, access.delegate_id),
+                (delegation.id, grantor.id, accountant.id)
+            );
+        }
+
+        assert_eq!(
+            *store.accesses.lock().unwrap(),
+            vec![
+                (delegation.id, "GET".to_string(), "/api/v1/trades".to_string()),
+                (delegation.id, "GET".to_string(), "/api/v1/trades/statistics".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_writes_and_other_endpoints_are_rejected_for_delegates() {
+        let store = FakeStore::default();
+        let (grantor, accountant, _) = accepted(&store).await;
+
+        for (method, path) in [
+            (Method::POST, "/api/v1/trades/close-batch"),
+            (Method::POST, "/api/v1/robots"),
+            (Method::PATCH, "/api/v1/robots/1"),
+            (Method::DELETE, "/api/v1/presets/1"),
+            (Method::GET, "/api/v1/brokers"),
+            (Method::GET, "/api/v1/admin/users"),
+        ] {
+            let err = read(&store, &accountant, grantor.id, method.clone(), path).await.unwrap_err();
+            assert!(matches!(err, AppError::Forbidden(_)), "{} {}: {}", method, path, err);
+        }
+        assert!(store.accesses.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_revoked_delegation_stops_working_immediately() {
+        let store = FakeStore::default();
+        let (grantor, accountant, delegation) = accepted(&store).await;
+        assert!(read(&store, &accountant, grantor.id, Method::GET, "/api/v1/trades").await.is_ok());
+
+        // Only the grantor can revoke
+        let err = DelegationService::revoke(&store, accountant.id, delegation.id, now()).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        DelegationService::revoke(&store, grantor.id, delegation.id, now()).await.unwrap();
+
+        let err = read(&store, &accountant, grantor.id, Method::GET, "/api/v1/trades").await.unwrap_err();
+        assert!(matches!(err, AppError::Forbidden(_)), "{}", err);
+        // A revoked invitation cannot be accepted again either
+        let err = DelegationService::accept(&store, &accountant, delegation.invite_token, now()).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+    }
+
+    #[tokio::test]
+    async fn test_header_without_a_delegation_is_denied() {
+        let store = FakeStore::default();
+        let (grantor, accountant, _) = accepted(&store).await;
+        let stranger = user("stranger@example.com");
+
+        // Someone else naming the grantor, or the accountant naming another account
+        let err = read(&store, &stranger, grantor.id, Method::GET, "/api/v1/trades").await.unwrap_err();
+        assert!(matches!(err, AppError::Forbidden(_)), "{}", err);
+        let err = read(&store, &accountant, stranger.id, Method::GET, "/api/v1/trades").await.unwrap_err();
+        assert!(matches!(err, AppError::Forbidden(_)), "{}", err);
+
+        let err = DelegationService::authorize(&store, accountant.id, "admin", &Method::GET, "/api/v1/trades", now())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::Validation(_)), "{}", err);
+        assert!(store.accesses.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_invitation_is_bound_to_its_address_and_expires() {
+        let store = FakeStore::default();
+        let grantor = user("trader@example.com");
+        let invitation = Delegation::new(grantor.id, "books@example.com".to_string(), now());
+        store.delegations.lock().unwrap().push(invitation.clone());
+
+        let err = DelegationService::accept(&store, &user("other@example.com"), invitation.invite_token, now())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::Forbidden(_)), "{}", err);
+
+        let late = now() + Duration::days(8);
+        assert_eq!(invitation.status(late), "expired");
+        let err = DelegationService::accept(&store, &user("books@example.com"), invitation.invite_token, late)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::Validation(_)), "{}", err);
+
+        assert!(matches!(
+            DelegationService::normalize_invite(&grantor, "TRADER@example.com"),
+            Err(AppError::Validation(_))
+        ));
+    }
+}
@@ -0,0 +1,53 @@
+use chrono::{DateTime, Duration, Utc};
+use sqlx::PgPool;
+
+use crate::{
+    errors::Result,
+    models::{BrokerConnection, TradingRobot, User},
+};
+
+pub const DEFAULT_DELETED_RETENTION_DAYS: i64 = 365;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PurgedRows {
+    pub robots: u64,
+    pub broker_connections: u64,
+    pub users: u64,
+}
+
+impl PurgedRows {
+    pub fn total(&self) -> u64 {
+        self.robots + self.broker_connections + self.users
+    }
+}
+
+// Hard-deletes users, robots and broker connections soft-deleted longer ago than the retention
+// window. Users and robots that still have trades are left in place, so trades are never purged.
+pub struct DeletionPurge;
+
+impl DeletionPurge {
+    pub fn cutoff(now: DateTime<Utc>, retention_days: i64) -> DateTime<Utc> {
+        now - Duration::days(retention_days.max(1))
+    }
+
+    pub async fn purge(pool: &PgPool, before: DateTime<Utc>) -> Result<PurgedRows> {
+        Ok(PurgedRows {
+            robots: TradingRobot::purge_deleted(pool, before).await?,
+            broker_connections: BrokerConnection::purge_deleted(pool, before).await?,
+            users: User::purge_deleted(pool, before).await?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_cutoff_keeps_at_least_a_day() {
+        let now = Utc.with_ymd_and_hms(2024, 6, 1, 12, 0, 0).unwrap();
+        assert_eq!(DeletionPurge::cutoff(now, 30), Utc.with_ymd_and_hms(2024, 5, 2, 12, 0, 0).unwrap());
+        assert_eq!(DeletionPurge::cutoff(now, 0), now - Duration::days(1));
+    }
+}
@@ -0,0 +1,277 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    errors::{AppError, Result},
+    models::OutboxEmail,
+    services::notification_service::EmailNotification,
+};
+
+pub const OUTBOX_POLL_SECONDS: u64 = 30;
+pub const MAX_ATTEMPTS: i32 = 6;
+const BATCH_SIZE: i64 = 50;
+const BASE_BACKOFF_SECONDS: i64 = 60;
+const MAX_BACKOFF_SECONDS: i64 = 3600;
+// Long enough for a batch to be sent before another worker may pick the rows up again
+const CLAIM_LEASE_SECONDS: i64 = 300;
+
+#[async_trait]
+pub trait OutboxStore: Send + Sync {
+    async fn enqueue(&self, email: &OutboxEmail) -> Result<()>;
+    async fn claim_due(&self, now: DateTime<Utc>, lease_until: DateTime<Utc>, limit: i64) -> Result<Vec<OutboxEmail>>;
+    async fn mark_sent(&self, id: Uuid, attempts: i32, now: DateTime<Utc>) -> Result<()>;
+    // `next_attempt_at` None dead-letters the email
+    async fn mark_failed(&self, id: Uuid, attempts: i32, next_attempt_at: Option<DateTime<Utc>>, error: &str) -> Result<()>;
+}
+
+// What actually talks to the mail server
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn deliver(&self, email: &EmailNotification) -> Result<()>;
+}
+
+pub struct PgOutboxStore {
+    pool: PgPool,
+}
+
+impl PgOutboxStore {
+    pub fn new(pool: PgPool) -> Self {
+        PgOutboxStore { pool }
+    }
+}
+
+#[async_trait]
+impl OutboxStore for PgOutboxStore {
+    async fn enqueue(&self, email: &OutboxEmail) -> Result<()> {
+        OutboxEmail::enqueue(&self.pool, email).await
+    }
+
+    async fn claim_due(&self, now: DateTime<Utc>, lease_until: DateTime<Utc>, limit: i64) -> Result<Vec<OutboxEmail>> {
+        OutboxEmail::claim_due(&self.pool, now, lease_until, limit).await
+    }
+
+    async fn mark_sent(&self, id: Uuid, attempts: i32, now: DateTime<Utc>) -> Result<()> {
+        OutboxEmail::mark_sent(&self.pool, id, attempts, now).await
+    }
+
+    async fn mark_failed(&self, id: Uuid, attempts: i32, next_attempt_at: Option<DateTime<Utc>>, error: &str) -> Result<()> {
+        OutboxEmail::mark_failed(&self.pool, id, attempts, next_attempt_at, error).await
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct OutboxRun {
+    pub sent: usize,
+    pub retried: usize,
+    pub dead: usize,
+}
+
+pub struct EmailOutbox;
+
+impl EmailOutbox {
+    // Wait before the next attempt after `attempts` failures: 1m, 2m, 4m, ... capped at an hour
+    pub fn backoff(attempts: i32) -> Duration {
+        let exponent = (attempts - 1).clamp(0, 20) as u32;
+        Duration::seconds((BASE_BACKOFF_SECONDS << exponent).min(MAX_BACKOFF_SECONDS))
+    }
+
+    // Scheduler job: sends every due email once
+    pub async fn process(store: &dyn OutboxStore, mailer: &dyn Mailer, now: DateTime<Utc>) -> Result<OutboxRun> {
+        let mut run = OutboxRun::default();
+        let lease_until = now + Duration::seconds(CLAIM_LEASE_SECONDS);
+
+        for email in store.claim_due(now, lease_until, BATCH_SIZE).await? {
+            let attempts = email.attempts + 1;
+            let delivered = match serde_json::from_value::<EmailNotification>(email.payload.clone()) {
+                Ok(notification) => mailer.deliver(&notification).await,
+                Err(e) => Err(AppError::Validation(format!("Unreadable outbox payload: {}", e))),
+            };
+
+            match delivered {
+                Ok(()) => {
+                    store.mark_sent(email.id, attempts, now).await?;
+                    run.sent += 1;
+                }
+                Err(e) if attempts >= MAX_ATTEMPTS => {
+                    tracing::error!(
+                        "Giving up on {} email {} to {} after {} attempts: {}",
+                        email.template_key, email.id, email.recipient, attempts, e
+                    );
+                    store.mark_failed(email.id, attempts, None, &e.to_string()).await?;
+                    run.dead += 1;
+                }
+                Err(e) => {
+                    tracing::warn!("Email {} failed (attempt {}), retrying: {}", email.id, attempts, e);
+                    store
+                        .mark_failed(email.id, attempts, Some(now + Self::backoff(attempts)), &e.to_string())
+                        .await?;
+                    run.retried += 1;
+                }
+            }
+        }
+        Ok(run)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::NotificationService;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct FakeStore {
+        emails: Mutex<Vec<OutboxEmail>>,
+    }
+
+    impl FakeStore {
+        fn only(&self) -> OutboxEmail {
+            let emails = self.emails.lock().unwrap();
+            assert_eq!(emails.len(), 1);
+            emails[0].clone()
+        }
+
+        fn update(&self, id: Uuid, change: impl FnOnce(&mut OutboxEmail)) {
+            let mut emails = self.emails.lock().unwrap();
+            change(emails.iter_mut().find(|e| e.id == id).unwrap());
+        }
+    }
+
+    #[async_trait]
+    impl OutboxStore for FakeStore {
+        async fn enqueue(&self, email: &OutboxEmail) -> Result<()> {
+            self.emails.lock().unwrap().push(email.clone());
+            Ok(())
+        }
+
+        async fn claim_due(&self, now: DateTime<Utc>, lease_until: DateTime<Utc>, limit: i64) -> Result<Vec<OutboxEmail>> {
+            let mut emails = self.emails.lock().unwrap();
+            Ok(emails
+                .iter_mut()
+                .filter(|e| e.status == "pending" && e.next_attempt_at <= now)
+                .take(limit as usize)
+                .map(|e| {
+                    e.next_attempt_at = lease_until;
+                    e.clone()
+                })
+                .collect())
+        }
+
+        async fn mark_sent(&self, id: Uuid, attempts: i32, now: DateTime<Utc>) -> Result<()> {
+            self.update(id, |e| {
+                e.status = "sent".to_string();
+                e.attempts = attempts;
+                e.sent_at = Some(now);
+            });
+            Ok(())
+        }
+
+        async fn mark_failed(&self, id: Uuid, attempts: i32, next_attempt_at: Option<DateTime<Utc>>, error: &str) -> Result<()> {
+            self.update(id, |e| {
+                e.attempts = attempts;
+                e.last_error = Some(error.to_string());
+                match next_attempt_at {
+                    Some(at) => e.next_attempt_at = at,
+                    None => e.status = "dead".to_string(),
+                }
+            });
+            Ok(())
+        }
+    }
+
+    // Fails the first `failures` deliveries to each recipient
+    struct FlakyMailer {
+        failures: usize,
+        attempts: Mutex<HashMap<String, usize>>,
+        delivered: Mutex<Vec<String>>,
+    }
+
+    impl FlakyMailer {
+        fn new(failures: usize) -> Self {
+            FlakyMailer { failures, attempts: Mutex::new(HashMap::new()), delivered: Mutex::new(Vec::new()) }
+        }
+    }
+
+    #[async_trait]
+    impl Mailer for FlakyMailer {
+        async fn deliver(&self, email: &EmailNotification) -> Result<()> {
+            let mut attempts = self.attempts.lock().unwrap();
+            let count = attempts.entry(email.to.clone()).or_insert(0);
+            *count += 1;
+            if *count <= self.failures {
+                return Err(AppError::External("SMTP 421 service not available".to_string()));
+            }
+            self.delivered.lock().unwrap().push(email.subject.clone());
+            Ok(())
+        }
+    }
+
+    // Queues a welcome email and returns when it became due
+    async fn queue_welcome(store: Arc<FakeStore>) -> DateTime<Utc> {
+        let notifications = NotificationService::new(None, None, None).with_outbox(store.clone());
+        notifications.send_welcome_email("ana@example.com", "ana").await.unwrap();
+        store.only().next_attempt_at
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_an_hour() {
+        let waits: Vec<i64> = (1..=8).map(|attempts| EmailOutbox::backoff(attempts).num_seconds()).collect();
+        assert_eq!(waits, vec![60, 120, 240, 480, 960, 1920, 3600, 3600]);
+    }
+
+    #[tokio::test]
+    async fn test_handler_only_enqueues_and_the_worker_delivers_after_transient_failures() {
+        let store = Arc::new(FakeStore::default());
+        let mailer = FlakyMailer::new(2);
+
+        let now = queue_welcome(store.clone()).await;
+        let queued = store.only();
+        assert_eq!((queued.template_key.as_str(), queued.status.as_str()), ("welcome", "pending"));
+        assert!(mailer.attempts.lock().unwrap().is_empty());
+
+        // First attempt fails and is pushed back by a minute
+        let run = EmailOutbox::process(store.as_ref(), &mailer, now).await.unwrap();
+        assert_eq!(run, OutboxRun { sent: 0, retried: 1, dead: 0 });
+        assert_eq!(store.only().next_attempt_at, now + Duration::minutes(1));
+
+        // Not due yet: left alone
+        let run = EmailOutbox::process(store.as_ref(), &mailer, now + Duration::seconds(30)).await.unwrap();
+        assert_eq!(run, OutboxRun::default());
+
+        // Second failure waits two minutes, the third attempt gets through
+        let second = now + Duration::minutes(1);
+        EmailOutbox::process(store.as_ref(), &mailer, second).await.unwrap();
+        assert_eq!(store.only().next_attempt_at, second + Duration::minutes(2));
+        let third = second + Duration::minutes(2);
+        let run = EmailOutbox::process(store.as_ref(), &mailer, third).await.unwrap();
+        assert_eq!(run, OutboxRun { sent: 1, retried: 0, dead: 0 });
+
+        let email = store.only();
+        assert_eq!((email.status.as_str(), email.attempts, email.sent_at), ("sent", 3, Some(third)));
+        assert_eq!(*mailer.delivered.lock().unwrap(), vec!["Welcome to Trading SaaS Platform!".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_email_is_dead_lettered_after_the_attempt_cap() {
+        let store = Arc::new(FakeStore::default());
+        let mailer = FlakyMailer::new(usize::MAX);
+        let mut at = queue_welcome(store.clone()).await;
+
+        for _ in 0..MAX_ATTEMPTS {
+            EmailOutbox::process(store.as_ref(), &mailer, at).await.unwrap();
+            at = store.only().next_attempt_at;
+        }
+
+        let email = store.only();
+        assert_eq!((email.status.as_str(), email.attempts), ("dead", MAX_ATTEMPTS));
+        assert!(email.last_error.unwrap().contains("SMTP 421"));
+
+        // Dead emails are never picked up again
+        let run = EmailOutbox::process(store.as_ref(), &mailer, at + Duration::days(1)).await.unwrap();
+        assert_eq!(run, OutboxRun::default());
+        assert_eq!(mailer.attempts.lock().unwrap()["ana@example.com"], MAX_ATTEMPTS as usize);
+    }
+}
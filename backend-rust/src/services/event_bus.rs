@@ -0,0 +1,262 @@
+use async_trait::async_trait;
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::{
+    errors::Result,
+    models::Trade,
+    services::task_supervisor::{spawn_supervised, TaskClass},
+};
+
+const MAX_DELIVERY_ATTEMPTS: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_millis(200);
+
+// Something that happened, published once the database change behind it is committed
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DomainEvent {
+    UserRegistered { user_id: Uuid, email: String },
+    TradeClosed { trade: Box<Trade> },
+    // The broker confirmed the entry of a trade
+    OrderFilled { trade: Box<Trade> },
+    RobotStatusChanged { robot_id: Uuid, user_id: Uuid, status: String },
+    SubscriptionChanged { user_id: Uuid, email: String, plan_name: String, action: String },
+    BrokerTestFailed { connection_id: Uuid, user_id: Uuid, error: String },
+    // The token is the invitation's only secret, so it stays out of the audit log
+    DelegateInvited {
+        delegation_id: Uuid,
+        grantor_email: String,
+        email: String,
+        #[serde(skip_serializing)]
+        token: Uuid,
+    },
+    // The link carries the reset token, so it stays out of the audit log too
+    PasswordResetRequested {
+        user_id: Uuid,
+        email: String,
+        #[serde(skip_serializing)]
+        reset_url: String,
+    },
+    // The address is gone from the account, so it stays out of the audit log as well
+    AccountDeleted {
+        user_id: Uuid,
+        #[serde(skip_serializing)]
+        email: String,
+    },
+    // By an admin; the user's robots were stopped and tokens revoked
+    AccountDeactivated { user_id: Uuid, email: String, admin_id: Uuid },
+    AccountReactivated { user_id: Uuid, email: String, admin_id: Uuid },
+}
+
+impl DomainEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DomainEvent::UserRegistered { .. } => "user_registered",
+            DomainEvent::TradeClosed { .. } => "trade_closed",
+            DomainEvent::OrderFilled { .. } => "order_filled",
+            DomainEvent::RobotStatusChanged { .. } => "robot_status_changed",
+            DomainEvent::SubscriptionChanged { .. } => "subscription_changed",
+            DomainEvent::BrokerTestFailed { .. } => "broker_test_failed",
+            DomainEvent::DelegateInvited { .. } => "delegate_invited",
+            DomainEvent::PasswordResetRequested { .. } => "password_reset_requested",
+            DomainEvent::AccountDeleted { .. } => "account_deleted",
+            DomainEvent::AccountDeactivated { .. } => "account_deactivated",
+            DomainEvent::AccountReactivated { .. } => "account_reactivated",
+        }
+    }
+}
+
+pub trait EventPublisher: Send + Sync {
+    fn publish(&self, event: DomainEvent);
+}
+
+// A side effect of domain events. Delivery is at least once, so handling the same event
+// twice must be harmless; events a subscriber does not care about are ignored with Ok(()).
+#[async_trait]
+pub trait EventSubscriber: Send + Sync + 'static {
+    fn name(&self) -> &'static str;
+    async fn handle(&self, event: &DomainEvent) -> Result<()>;
+}
+
+// In-process fan-out. Every subscriber has its own queue and task, so a slow or failing
+// subscriber never holds up the others or the publisher.
+#[derive(Default)]
+pub struct EventBus {
+    subscribers: Mutex<Vec<(&'static str, mpsc::UnboundedSender<DomainEvent>)>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn subscribe(&self, subscriber: Arc<dyn EventSubscriber>) -> JoinHandle<()> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.subscribers.lock().unwrap().push((subscriber.name(), sender));
+        spawn_supervised(format!("events:{}", subscriber.name()), TaskClass::Background, deliver(subscriber, receiver))
+    }
+}
+
+impl EventPublisher for EventBus {
+    fn publish(&self, event: DomainEvent) {
+        tracing::debug!("Publishing {}", event.name());
+        for (name, sender) in self.subscribers.lock().unwrap().iter() {
+            if sender.send(event.clone()).is_err() {
+                tracing::error!("Event subscriber {} is gone, dropped {}", name, event.name());
+            }
+        }
+    }
+}
+
+// Runs each delivery on its own task so a panic only costs that attempt. Failed and panicked
+// deliveries are retried before the event is given up on.
+async fn deliver(subscriber: Arc<dyn EventSubscriber>, mut events: mpsc::UnboundedReceiver<DomainEvent>) {
+    while let Some(event) = events.recv().await {
+        for attempt in 1..=MAX_DELIVERY_ATTEMPTS {
+            let handler = subscriber.clone();
+            let delivered = event.clone();
+            match tokio::spawn(async move { handler.handle(&delivered).await }).await {
+                Ok(Ok(())) => break,
+                Ok(Err(e)) => tracing::warn!(
+                    "Subscriber {} failed on {} (attempt {}): {}",
+                    subscriber.name(),
+                    event.name(),
+                    attempt,
+                    e
+                ),
+                Err(e) => tracing::error!(
+                    "Subscriber {} panicked on {} (attempt {}): {}",
+                    subscriber.name(),
+                    event.name(),
+                    attempt,
+                    e
+                ),
+            }
+
+            if attempt == MAX_DELIVERY_ATTEMPTS {
+                tracing::error!("Subscriber {} gave up on {}", subscriber.name(), event.name());
+            } else {
+                tokio::time::sleep(RETRY_DELAY * attempt).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::AppError;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    struct Recorder {
+        name: &'static str,
+        seen: mpsc::UnboundedSender<(&'static str, serde_json::Value)>,
+        // Fails (or panics) on this many deliveries before succeeding
+        failures: AtomicU32,
+        panics: bool,
+    }
+
+    #[async_trait]
+    impl EventSubscriber for Recorder {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        async fn handle(&self, event: &DomainEvent) -> Result<()> {
+            if self.failures.load(Ordering::SeqCst) > 0 {
+                self.failures.fetch_sub(1, Ordering::SeqCst);
+                if self.panics {
+                    panic!("{} crashed", self.name);
+                }
+                return Err(AppError::External(format!("{} is down", self.name)));
+            }
+            self.seen.send((self.name, serde_json::to_value(event).unwrap())).unwrap();
+            Ok(())
+        }
+    }
+
+    fn recorder(
+        name: &'static str,
+        seen: &mpsc::UnboundedSender<(&'static str, serde_json::Value)>,
+        failures: u32,
+        panics: bool,
+    ) -> Arc<dyn EventSubscriber> {
+        Arc::new(Recorder { name, seen: seen.clone(), failures: AtomicU32::new(failures), panics })
+    }
+
+    fn status_changed(status: &str) -> DomainEvent {
+        DomainEvent::RobotStatusChanged {
+            robot_id: Uuid::nil(),
+            user_id: Uuid::nil(),
+            status: status.to_string(),
+        }
+    }
+
+    fn observed(status: &str) -> serde_json::Value {
+        serde_json::to_value(status_changed(status)).unwrap()
+    }
+
+    async fn received(seen: &mut mpsc::UnboundedReceiver<(&'static str, serde_json::Value)>, count: usize) -> Vec<(&'static str, serde_json::Value)> {
+        let mut events = Vec::new();
+        for _ in 0..count {
+            events.push(tokio::time::timeout(Duration::from_secs(5), seen.recv()).await.unwrap().unwrap());
+        }
+        events.sort_by_key(|(name, _)| *name);
+        events
+    }
+
+    #[tokio::test]
+    async fn test_every_subscriber_observes_a_published_event() {
+        let (tx, mut seen) = mpsc::unbounded_channel();
+        let bus = EventBus::new();
+        for name in ["audit", "cache", "notifications", "websocket"] {
+            bus.subscribe(recorder(name, &tx, 0, false));
+        }
+
+        bus.publish(status_changed("active"));
+
+        let events = received(&mut seen, 4).await;
+        assert_eq!(
+            events,
+            ["audit", "cache", "notifications", "websocket"].map(|name| (name, observed("active"))).to_vec()
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_panicking_subscriber_does_not_stop_the_others_and_is_retried() {
+        let (tx, mut seen) = mpsc::unbounded_channel();
+        let bus = EventBus::new();
+        bus.subscribe(recorder("crashes_once", &tx, 1, true));
+        bus.subscribe(recorder("fails_twice", &tx, 2, false));
+        bus.subscribe(recorder("healthy", &tx, 0, false));
+
+        bus.publish(status_changed("active"));
+        bus.publish(status_changed("stopped"));
+
+        // Each subscriber still gets both events, in order, despite its earlier failures
+        let mut events = received(&mut seen, 6).await;
+        events.sort_by_key(|(name, event)| (*name, event["status"] == "stopped"));
+        let expected: Vec<_> = ["crashes_once", "fails_twice", "healthy"]
+            .into_iter()
+            .flat_map(|name| [(name, observed("active")), (name, observed("stopped"))])
+            .collect();
+        assert_eq!(events, expected);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_event_is_given_up_after_the_last_attempt() {
+        let (tx, mut seen) = mpsc::unbounded_channel();
+        let bus = EventBus::new();
+        bus.subscribe(recorder("broken", &tx, MAX_DELIVERY_ATTEMPTS, false));
+
+        bus.publish(status_changed("active"));
+        bus.publish(status_changed("stopped"));
+
+        // The first event used up every attempt; the next one goes through
+        assert_eq!(received(&mut seen, 1).await, vec![("broken", observed("stopped"))]);
+    }
+}
@@ -0,0 +1,261 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use std::sync::Arc;
+
+use crate::{
+    errors::Result,
+    models::TradeResponse,
+    services::{
+        cooldown_service::CooldownEnv,
+        dashboard_service::SparklineCache,
+        event_bus::{DomainEvent, EventSubscriber},
+        trade_journal::TradeJournalStore,
+        CooldownService, DashboardService, NotificationService, RobotRunnerRegistry, TradeJournal, WebSocketManager,
+    },
+};
+
+// Pushes trade and robot updates to the owner's open websocket sessions
+pub struct WebSocketSubscriber {
+    websocket: Arc<WebSocketManager>,
+}
+
+impl WebSocketSubscriber {
+    pub fn new(websocket: Arc<WebSocketManager>) -> Self {
+        WebSocketSubscriber { websocket }
+    }
+}
+
+#[async_trait]
+impl EventSubscriber for WebSocketSubscriber {
+    fn name(&self) -> &'static str {
+        "websocket"
+    }
+
+    async fn handle(&self, event: &DomainEvent) -> Result<()> {
+        match event {
+            DomainEvent::TradeClosed { trade } => {
+                let fill = serde_json::json!({
+                    "trade_id": trade.id,
+                    "ticket": trade.broker_trade_id,
+                    "symbol": trade.symbol,
+                    "price": trade.exit_price,
+                    "volume": trade.volume,
+                    "closes_position": true,
+                });
+                self.websocket.broadcast_order_filled(trade.user_id, fill).await?;
+                let data = serde_json::to_value(TradeResponse::from(trade.as_ref().clone())).unwrap_or_default();
+                self.websocket.broadcast_trade_closed(trade.user_id, data).await
+            }
+            DomainEvent::OrderFilled { trade } => {
+                let fill = serde_json::json!({
+                    "trade_id": trade.id,
+                    "ticket": trade.broker_trade_id,
+                    "symbol": trade.symbol,
+                    "price": trade.entry_price,
+                    "volume": trade.volume,
+                    "closes_position": false,
+                });
+                self.websocket.broadcast_order_filled(trade.user_id, fill).await
+            }
+            DomainEvent::RobotStatusChanged { robot_id, user_id, status } => {
+                let data = serde_json::json!({ "robot_id": robot_id, "status": status });
+                self.websocket.broadcast_robot_status(*user_id, data).await
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// Drops cached aggregates that a closed trade makes stale
+pub struct CacheSubscriber {
+    cache: Arc<dyn SparklineCache>,
+}
+
+impl CacheSubscriber {
+    pub fn new(cache: Arc<dyn SparklineCache>) -> Self {
+        CacheSubscriber { cache }
+    }
+}
+
+#[async_trait]
+impl EventSubscriber for CacheSubscriber {
+    fn name(&self) -> &'static str {
+        "cache"
+    }
+
+    async fn handle(&self, event: &DomainEvent) -> Result<()> {
+        if let DomainEvent::TradeClosed { trade } = event {
+            DashboardService::invalidate_sparklines(self.cache.as_ref(), trade.user_id).await;
+        }
+        Ok(())
+    }
+}
+
+// Counts closed trades toward the robot's losing streak and pauses its runner when a
+// cooldown starts. Trades closed by the runner itself are counted there.
+pub struct CooldownSubscriber {
+    cooldowns: Arc<dyn CooldownEnv>,
+    runners: Arc<RobotRunnerRegistry>,
+}
+
+impl CooldownSubscriber {
+    pub fn new(cooldowns: Arc<dyn CooldownEnv>, runners: Arc<RobotRunnerRegistry>) -> Self {
+        CooldownSubscriber { cooldowns, runners }
+    }
+}
+
+#[async_trait]
+impl EventSubscriber for CooldownSubscriber {
+    fn name(&self) -> &'static str {
+        "cooldown"
+    }
+
+    async fn handle(&self, event: &DomainEvent) -> Result<()> {
+        if let DomainEvent::TradeClosed { trade } = event {
+            let profit_loss = trade.profit_loss.unwrap_or(0.0);
+            let cooldown =
+                CooldownService::record_close(self.cooldowns.as_ref(), trade.robot_id, trade.user_id, profit_loss, Utc::now())
+                    .await?;
+            if cooldown.is_some() {
+                self.runners.set_paused(trade.robot_id, true);
+            }
+        }
+        Ok(())
+    }
+}
+
+// Asks for a journal entry on every closed trade and tells the owner's open sessions
+pub struct JournalSubscriber {
+    store: Arc<dyn TradeJournalStore>,
+    websocket: Arc<WebSocketManager>,
+}
+
+impl JournalSubscriber {
+    pub fn new(store: Arc<dyn TradeJournalStore>, websocket: Arc<WebSocketManager>) -> Self {
+        JournalSubscriber { store, websocket }
+    }
+}
+
+#[async_trait]
+impl EventSubscriber for JournalSubscriber {
+    fn name(&self) -> &'static str {
+        "journal"
+    }
+
+    async fn handle(&self, event: &DomainEvent) -> Result<()> {
+        if let DomainEvent::TradeClosed { trade } = event {
+            if TradeJournal::prompt(self.store.as_ref(), trade).await? {
+                let data = serde_json::json!({
+                    "trade_id": trade.id,
+                    "symbol": trade.symbol,
+                    "profit_loss": trade.profit_loss,
+                });
+                self.websocket.broadcast_journal_prompt(trade.user_id, data).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Account emails that follow from a domain event
+pub struct NotificationSubscriber {
+    notifications: Arc<NotificationService>,
+}
+
+impl NotificationSubscriber {
+    pub fn new(notifications: Arc<NotificationService>) -> Self {
+        NotificationSubscriber { notifications }
+    }
+}
+
+#[async_trait]
+impl EventSubscriber for NotificationSubscriber {
+    fn name(&self) -> &'static str {
+        "notifications"
+    }
+
+    async fn handle(&self, event: &DomainEvent) -> Result<()> {
+        match event {
+            DomainEvent::UserRegistered { email, .. } => {
+                let name = email.split('@').next().unwrap_or(email);
+                self.notifications.send_welcome_email(email, name).await
+            }
+            DomainEvent::SubscriptionChanged { email, plan_name, action, .. } => {
+                self.notifications.send_subscription_notification(email, plan_name, action).await
+            }
+            DomainEvent::DelegateInvited { grantor_email, email, token, .. } => {
+                self.notifications.send_delegate_invitation(email, grantor_email, *token).await
+            }
+            DomainEvent::PasswordResetRequested { email, reset_url, .. } => {
+                self.notifications.send_password_reset(email, reset_url).await
+            }
+            DomainEvent::AccountDeleted { email, .. } => self.notifications.send_account_deleted(email).await,
+            DomainEvent::AccountDeactivated { email, .. } => self.notifications.send_account_deactivated(email).await,
+            DomainEvent::AccountReactivated { email, .. } => self.notifications.send_account_reactivated(email).await,
+            _ => Ok(()),
+        }
+    }
+}
+
+// Structured record of every event under the `audit` tracing target
+pub struct AuditSubscriber;
+
+#[async_trait]
+impl EventSubscriber for AuditSubscriber {
+    fn name(&self) -> &'static str {
+        "audit"
+    }
+
+    async fn handle(&self, event: &DomainEvent) -> Result<()> {
+        let payload = serde_json::to_string(event).unwrap_or_default();
+        tracing::info!(target: "audit", event = event.name(), "{}", payload);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::Trade,
+        services::event_bus::{EventBus, EventPublisher},
+    };
+    use std::collections::HashSet;
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+    use uuid::Uuid;
+
+    struct MemoryCache {
+        keys: Mutex<HashSet<String>>,
+        removed: mpsc::UnboundedSender<String>,
+    }
+
+    #[async_trait]
+    impl SparklineCache for MemoryCache {
+        async fn remove(&self, key: &str) -> Result<()> {
+            self.keys.lock().unwrap().remove(key);
+            self.removed.send(key.to_string()).unwrap();
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_closed_trade_drops_the_owners_cached_sparklines() {
+        let trade = Trade::new(Uuid::new_v4(), Uuid::new_v4(), "EURUSD".to_string(), "buy".to_string(), 1.0, 1.1000, None, None, None, None);
+        let other = DashboardService::sparklines_cache_key(Uuid::new_v4());
+        let (tx, mut removed) = mpsc::unbounded_channel();
+        let cache = Arc::new(MemoryCache {
+            keys: Mutex::new(HashSet::from([DashboardService::sparklines_cache_key(trade.user_id), other.clone()])),
+            removed: tx,
+        });
+        let bus = EventBus::new();
+        bus.subscribe(Arc::new(CacheSubscriber::new(cache.clone())));
+
+        bus.publish(DomainEvent::TradeClosed { trade: Box::new(trade.clone()) });
+
+        let key = tokio::time::timeout(Duration::from_secs(5), removed.recv()).await.unwrap().unwrap();
+        assert_eq!(key, DashboardService::sparklines_cache_key(trade.user_id));
+        assert_eq!(*cache.keys.lock().unwrap(), HashSet::from([other]));
+    }
+}
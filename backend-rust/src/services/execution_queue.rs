@@ -0,0 +1,278 @@
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::future::Future;
+use std::ops::Bound;
+use uuid::Uuid;
+
+use crate::services::broker_throttle::BrokerCallPriority;
+
+// Broker call classes by plan, best first. Each class is served `weight` calls in a row before
+// the next one gets its turn, so paying tiers go first without starving the free tier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecutionClass {
+    Elite,
+    Pro,
+    Essential,
+    Free,
+}
+
+impl ExecutionClass {
+    pub fn for_plan(plan: &str) -> Self {
+        match plan {
+            "elite" => ExecutionClass::Elite,
+            "pro" => ExecutionClass::Pro,
+            "essential" => ExecutionClass::Essential,
+            _ => ExecutionClass::Free,
+        }
+    }
+
+    pub fn weight(&self) -> u32 {
+        match self {
+            ExecutionClass::Elite => 8,
+            ExecutionClass::Pro => 4,
+            ExecutionClass::Essential => 2,
+            ExecutionClass::Free => 1,
+        }
+    }
+}
+
+// Who a broker call is made for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Requester {
+    pub user_id: Uuid,
+    pub class: ExecutionClass,
+    // The robot acting, which gets a robot_log entry when its call is turned away
+    pub robot_id: Option<Uuid>,
+}
+
+impl Requester {
+    pub fn new(user_id: Uuid, plan: &str) -> Self {
+        Requester { user_id, class: ExecutionClass::for_plan(plan), robot_id: None }
+    }
+
+    pub fn with_robot(mut self, robot_id: Uuid) -> Self {
+        self.robot_id = Some(robot_id);
+        self
+    }
+
+    // Calls made outside any user's request, e.g. the quote streamer; they share one free slot
+    pub fn platform() -> Self {
+        Requester { user_id: Uuid::nil(), class: ExecutionClass::Free, robot_id: None }
+    }
+}
+
+tokio::task_local! {
+    static REQUESTER: Requester;
+}
+
+// Broker calls made while `fut` runs are queued as `requester`'s
+pub async fn on_behalf_of<F: Future>(requester: Requester, fut: F) -> F::Output {
+    REQUESTER.scope(requester, fut).await
+}
+
+pub fn current_requester() -> Requester {
+    REQUESTER.try_with(|requester| *requester).unwrap_or_else(|_| Requester::platform())
+}
+
+// One class's calls; users take turns, one call each
+struct ClassQueue<T> {
+    turns: VecDeque<Uuid>,
+    calls: HashMap<Uuid, VecDeque<T>>,
+}
+
+impl<T> ClassQueue<T> {
+    fn new() -> Self {
+        ClassQueue { turns: VecDeque::new(), calls: HashMap::new() }
+    }
+
+    fn push(&mut self, user_id: Uuid, item: T) {
+        let calls = self.calls.entry(user_id).or_default();
+        if calls.is_empty() {
+            self.turns.push_back(user_id);
+        }
+        calls.push_back(item);
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        let user_id = self.turns.pop_front()?;
+        let calls = self.calls.get_mut(&user_id)?;
+        let item = calls.pop_front();
+        if calls.is_empty() {
+            self.calls.remove(&user_id);
+        } else {
+            self.turns.push_back(user_id);
+        }
+        item
+    }
+
+    fn is_empty(&self) -> bool {
+        self.turns.is_empty()
+    }
+}
+
+// Calls of one priority; only non-empty classes are kept
+struct Lane<T> {
+    classes: BTreeMap<ExecutionClass, ClassQueue<T>>,
+    // The class being served and the turns it has left in this round
+    serving: Option<(ExecutionClass, u32)>,
+}
+
+impl<T> Lane<T> {
+    fn new() -> Self {
+        Lane { classes: BTreeMap::new(), serving: None }
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        let class = match self.serving {
+            Some((class, left)) if left > 0 && self.classes.contains_key(&class) => {
+                self.serving = Some((class, left - 1));
+                class
+            }
+            serving => {
+                let after = serving.map_or(Bound::Unbounded, |(class, _)| Bound::Excluded(class));
+                let next = *self
+                    .classes
+                    .range((after, Bound::Unbounded))
+                    .next()
+                    .or_else(|| self.classes.iter().next())?
+                    .0;
+                self.serving = Some((next, next.weight() - 1));
+                next
+            }
+        };
+
+        let queue = self.classes.get_mut(&class)?;
+        let item = queue.pop();
+        if queue.is_empty() {
+            self.classes.remove(&class);
+        }
+        item
+    }
+}
+
+// Broker calls waiting on one connection. Higher call priorities are always served first
+// (orders before maintenance before account and market data); within a priority plan classes
+// take weighted turns and users within a class take turns.
+pub struct ExecutionQueue<T> {
+    lanes: BTreeMap<BrokerCallPriority, Lane<T>>,
+    len: usize,
+}
+
+impl<T> ExecutionQueue<T> {
+    pub fn new() -> Self {
+        ExecutionQueue { lanes: BTreeMap::new(), len: 0 }
+    }
+
+    pub fn push(&mut self, priority: BrokerCallPriority, requester: &Requester, item: T) {
+        self.lanes
+            .entry(priority)
+            .or_insert_with(Lane::new)
+            .classes
+            .entry(requester.class)
+            .or_insert_with(ClassQueue::new)
+            .push(requester.user_id, item);
+        self.len += 1;
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        let (&priority, lane) = self.lanes.iter_mut().next()?;
+        let item = lane.pop();
+        // A lane that empties forgets its round, so the next burst starts with the best class
+        if lane.classes.is_empty() {
+            self.lanes.remove(&priority);
+        }
+        if item.is_some() {
+            self.len -= 1;
+        }
+        item
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<T> Default for ExecutionQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain(queue: &mut ExecutionQueue<String>) -> Vec<String> {
+        std::iter::from_fn(|| queue.pop()).collect()
+    }
+
+    #[test]
+    fn test_elite_burst_goes_first_while_free_users_still_progress() {
+        let elite = [Requester::new(Uuid::new_v4(), "elite"), Requester::new(Uuid::new_v4(), "elite")];
+        let free = [Requester::new(Uuid::new_v4(), "free"), Requester::new(Uuid::new_v4(), "free")];
+        let mut queue = ExecutionQueue::new();
+        // Free orders arrive first; the elite burst lands behind them
+        for (user, requester) in free.iter().enumerate() {
+            for n in 0..3 {
+                queue.push(BrokerCallPriority::Order, requester, format!("free{}-{}", user, n));
+            }
+        }
+        for n in 0..10 {
+            for (user, requester) in elite.iter().enumerate() {
+                queue.push(BrokerCallPriority::Order, requester, format!("elite{}-{}", user, n));
+            }
+        }
+        assert_eq!(queue.len(), 26);
+
+        let order = drain(&mut queue);
+
+        // Eight elite orders, alternating between the two elite users, then one free order
+        assert_eq!(&order[..4], ["elite0-0", "elite1-0", "elite0-1", "elite1-1"]);
+        assert!(order[..8].iter().all(|o| o.starts_with("elite")));
+        assert_eq!(order[8], "free0-0");
+        assert!(order[9..17].iter().all(|o| o.starts_with("elite")));
+        assert_eq!(order[17], "free1-0");
+        // Once the elite burst is served the free users finish, still taking turns
+        assert_eq!(&order[22..], ["free0-1", "free1-1", "free0-2", "free1-2"]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_market_orders_go_before_pending_order_maintenance() {
+        let free = Requester::new(Uuid::new_v4(), "free");
+        let elite = Requester::new(Uuid::new_v4(), "elite");
+        let mut queue = ExecutionQueue::new();
+        queue.push(BrokerCallPriority::MarketData, &elite, "quote".to_string());
+        queue.push(BrokerCallPriority::OrderMaintenance, &elite, "modify".to_string());
+        queue.push(BrokerCallPriority::Order, &free, "order".to_string());
+
+        assert_eq!(drain(&mut queue), ["order", "modify", "quote"]);
+    }
+
+    #[test]
+    fn test_a_new_burst_starts_with_the_best_class() {
+        let pro = Requester::new(Uuid::new_v4(), "pro");
+        let elite = Requester::new(Uuid::new_v4(), "elite");
+        let mut queue = ExecutionQueue::new();
+        queue.push(BrokerCallPriority::Order, &pro, "pro-0".to_string());
+        assert_eq!(queue.pop().as_deref(), Some("pro-0"));
+
+        queue.push(BrokerCallPriority::Order, &pro, "pro-1".to_string());
+        queue.push(BrokerCallPriority::Order, &elite, "elite-0".to_string());
+        assert_eq!(drain(&mut queue), ["elite-0", "pro-1"]);
+    }
+
+    #[tokio::test]
+    async fn test_requester_is_scoped_to_the_call() {
+        let user_id = Uuid::new_v4();
+        assert_eq!(current_requester(), Requester::platform());
+        let inside = on_behalf_of(Requester::new(user_id, "pro"), async { current_requester() }).await;
+        assert_eq!((inside.user_id, inside.class), (user_id, ExecutionClass::Pro));
+    }
+}
@@ -0,0 +1,133 @@
+use futures_util::Stream;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::errors::Result;
+
+// Set when an export's body is dropped before it finished, i.e. the client went away
+#[derive(Debug, Clone, Default)]
+pub struct ExportCancellation(Arc<AtomicBool>);
+
+impl ExportCancellation {
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// Flags the cancellation unless the export ran to its end
+struct DropGuard {
+    cancellation: ExportCancellation,
+    finished: bool,
+}
+
+impl DropGuard {
+    fn finish(&mut self) {
+        self.finished = true;
+    }
+}
+
+impl Drop for DropGuard {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.cancellation.0.store(true, Ordering::Relaxed);
+            tracing::info!("Export abandoned by the client");
+        }
+    }
+}
+
+struct Paging<F> {
+    fetch: F,
+    offset: i64,
+    header: Option<String>,
+    done: bool,
+    guard: DropGuard,
+}
+
+// Streams `header`, then the chunks `fetch(offset)` returns, `page_size` rows at a time, until
+// a page comes back short. A page is only fetched once the client has taken the previous one,
+// so an abandoned download drops the query in flight and fetches nothing more.
+pub fn paged_export<F, Fut>(
+    header: String,
+    page_size: i64,
+    cancellation: ExportCancellation,
+    fetch: F,
+) -> impl Stream<Item = std::io::Result<String>>
+where
+    F: FnMut(i64) -> Fut,
+    Fut: Future<Output = Result<(String, usize)>>,
+{
+    let paging = Paging {
+        fetch,
+        offset: 0,
+        header: Some(header),
+        done: false,
+        guard: DropGuard { cancellation, finished: false },
+    };
+    futures_util::stream::unfold(paging, move |mut paging| async move {
+        if let Some(header) = paging.header.take() {
+            return Some((Ok(header), paging));
+        }
+        if paging.done {
+            paging.guard.finish();
+            return None;
+        }
+        match (paging.fetch)(paging.offset).await {
+            Ok((chunk, rows)) => {
+                paging.offset += rows as i64;
+                paging.done = (rows as i64) < page_size;
+                Some((Ok(chunk), paging))
+            }
+            Err(e) => {
+                tracing::error!("Export failed at row {}: {}", paging.offset, e);
+                paging.done = true;
+                paging.guard.finish();
+                Some((Err(std::io::Error::other(e.to_string())), paging))
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+    use std::sync::Mutex;
+
+    fn pages(total: i64, fetched: Arc<Mutex<Vec<i64>>>) -> impl FnMut(i64) -> std::future::Ready<Result<(String, usize)>> {
+        move |offset| {
+            fetched.lock().unwrap().push(offset);
+            let rows = (total - offset).clamp(0, 2);
+            let chunk: String = (offset..offset + rows).map(|n| format!("{}\n", n)).collect();
+            std::future::ready(Ok((chunk, rows as usize)))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_export_pages_until_a_short_page() {
+        let fetched = Arc::new(Mutex::new(Vec::new()));
+        let cancellation = ExportCancellation::default();
+        let stream = paged_export("n\n".to_string(), 2, cancellation.clone(), pages(5, fetched.clone()));
+
+        let chunks: Vec<String> = stream.map(|c| c.unwrap()).collect().await;
+        assert_eq!(chunks.concat(), "n\n0\n1\n2\n3\n4\n");
+        assert_eq!(*fetched.lock().unwrap(), vec![0, 2, 4]);
+        assert!(!cancellation.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn test_export_stops_when_the_client_disconnects() {
+        let fetched = Arc::new(Mutex::new(Vec::new()));
+        let cancellation = ExportCancellation::default();
+        let stream = paged_export("n\n".to_string(), 2, cancellation.clone(), pages(1_000, fetched.clone()));
+        let mut stream = Box::pin(stream);
+
+        assert_eq!(stream.next().await.unwrap().unwrap(), "n\n");
+        assert_eq!(stream.next().await.unwrap().unwrap(), "0\n1\n");
+        // The response body is dropped when the connection goes away
+        drop(stream);
+
+        assert!(cancellation.is_cancelled());
+        assert_eq!(*fetched.lock().unwrap(), vec![0]);
+    }
+}
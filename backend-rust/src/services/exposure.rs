@@ -0,0 +1,397 @@
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use sqlx::PgPool;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{
+    errors::{AppError, Result},
+    models::{AccountSnapshot, BrokerConnection, Trade, TradingRobot},
+    money::{round, Rounding},
+    services::{
+        currency_converter::{Conversion, CurrencyConverter},
+        quote_service::{ConnectionQuotes, FloatingTrade, QuoteLookup, QuoteService},
+        risk_calculator::DEFAULT_ACCOUNT_CURRENCY,
+        symbol_spec::SymbolSpec,
+        Mt5Service,
+    },
+};
+
+// Lots are summed to this many decimals, so 2.0 long against 0.5 short is 1.5 and not 1.4999...
+const VOLUME_DECIMALS: i32 = 8;
+
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct SymbolExposure {
+    pub symbol: String,
+    pub long_volume: f64,
+    pub short_volume: f64,
+    // Long minus short, in lots; negative when net short
+    pub net_volume: f64,
+    // long, short or flat
+    pub direction: String,
+    // Volume-weighted entry of the trades on the net side; null when flat
+    #[serde(serialize_with = "crate::money::serialize_opt_price")]
+    pub average_entry_price: Option<f64>,
+    pub open_trades: usize,
+    // In the currency of the enclosing view; null when a trade could not be priced or converted
+    #[serde(serialize_with = "crate::money::serialize_opt_amount")]
+    pub floating_profit_loss: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct ConnectionExposure {
+    pub connection_id: Uuid,
+    pub connection_name: String,
+    pub is_demo: bool,
+    // From the latest account snapshot, or the open session; null when neither is known
+    pub account_currency: Option<String>,
+    // Some trade was priced by a source other than this connection's own session
+    pub indicative: bool,
+    pub symbols: Vec<SymbolExposure>,
+    // In account_currency
+    #[serde(serialize_with = "crate::money::serialize_opt_amount")]
+    pub floating_profit_loss: Option<f64>,
+    // Quote currencies converted into account_currency, at this connection's own prices
+    pub conversions: Vec<Conversion>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct ExposureByConnection {
+    pub connections: Vec<ConnectionExposure>,
+}
+
+// One connection's part of a netted symbol, in its own account currency
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct ConnectionShare {
+    pub connection_id: Uuid,
+    pub connection_name: String,
+    pub account_currency: Option<String>,
+    pub net_volume: f64,
+    #[serde(serialize_with = "crate::money::serialize_opt_amount")]
+    pub floating_profit_loss: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct NetSymbolExposure {
+    #[serde(flatten)]
+    pub totals: SymbolExposure,
+    pub connections: Vec<ConnectionShare>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct NetExposure {
+    // Every combined amount is in this currency
+    pub currency: String,
+    // The accounts are held in more than one currency; see conversions for the rates applied
+    pub mixed_currencies: bool,
+    pub symbols: Vec<NetSymbolExposure>,
+    #[serde(serialize_with = "crate::money::serialize_opt_amount")]
+    pub floating_profit_loss: Option<f64>,
+    // Account currencies converted into `currency`
+    pub conversions: Vec<Conversion>,
+}
+
+// An open trade with its floating P/L in its account's currency
+struct Leg {
+    trade: Trade,
+    profit_loss: Option<f64>,
+}
+
+struct ConnectionLegs {
+    connection: BrokerConnection,
+    currency: Option<String>,
+    indicative: bool,
+    legs: Vec<Leg>,
+    conversions: Vec<Conversion>,
+}
+
+// Open trades per broker connection, through the robots they were placed by. Trades of robots
+// without a connection have no account to count against and are left out.
+pub struct ExposureService;
+
+impl ExposureService {
+    pub async fn by_connection(
+        pool: &PgPool,
+        mt5: &Arc<Mt5Service>,
+        quotes: &QuoteService,
+        user_id: Uuid,
+        now: DateTime<Utc>,
+    ) -> Result<ExposureByConnection> {
+        let connections = Self::connection_legs(pool, mt5, quotes, user_id, now).await?;
+        Ok(ExposureByConnection {
+            connections: connections
+                .into_iter()
+                .map(|c| {
+                    let legs: Vec<(&Trade, Option<f64>)> = c.legs.iter().map(|leg| (&leg.trade, leg.profit_loss)).collect();
+                    ConnectionExposure {
+                        connection_id: c.connection.id,
+                        connection_name: c.connection.name.clone(),
+                        is_demo: c.connection.is_demo,
+                        account_currency: c.currency.clone(),
+                        indicative: c.indicative,
+                        floating_profit_loss: total(legs.iter().map(|(_, pnl)| *pnl)),
+                        symbols: by_symbol(&legs),
+                        conversions: c.conversions,
+                    }
+                })
+                .collect(),
+        })
+    }
+
+    // Every connection's trades netted per symbol in `currency`; by default the accounts' own
+    // currency when they share one
+    pub async fn net(
+        pool: &PgPool,
+        mt5: &Arc<Mt5Service>,
+        quotes: &QuoteService,
+        user_id: Uuid,
+        currency: Option<&str>,
+        now: DateTime<Utc>,
+    ) -> Result<NetExposure> {
+        let connections = Self::connection_legs(pool, mt5, quotes, user_id, now).await?;
+        let mut account_currencies: Vec<&str> = connections.iter().filter_map(|c| c.currency.as_deref()).collect();
+        account_currencies.sort_unstable();
+        account_currencies.dedup();
+        let currency = match currency {
+            Some(currency) => parse_currency(currency)?,
+            None if account_currencies.len() == 1 => account_currencies[0].to_string(),
+            None => DEFAULT_ACCOUNT_CURRENCY.to_string(),
+        };
+
+        let mut conversions: Vec<Conversion> = Vec::new();
+        let mut legs: Vec<(&Trade, Option<f64>)> = Vec::new();
+        for connection in &connections {
+            let conversion = match &connection.currency {
+                Some(from) => Self::conversion(quotes, None, user_id, from, &currency, now, &mut conversions).await,
+                None => None,
+            };
+            for leg in &connection.legs {
+                let profit_loss = leg.profit_loss.zip(conversion.as_ref()).map(|(pnl, c)| c.convert(pnl));
+                legs.push((&leg.trade, profit_loss));
+            }
+        }
+
+        let symbols: Vec<NetSymbolExposure> = by_symbol(&legs)
+            .into_iter()
+            .map(|totals| {
+                let symbol = totals.symbol.as_str();
+                let shares = connections
+                    .iter()
+                    .filter_map(|connection| {
+                        let own: Vec<(&Trade, Option<f64>)> = connection
+                            .legs
+                            .iter()
+                            .filter(|leg| leg.trade.symbol == symbol)
+                            .map(|leg| (&leg.trade, leg.profit_loss))
+                            .collect();
+                        (!own.is_empty()).then(|| {
+                            let exposure = summarize(symbol, &own);
+                            ConnectionShare {
+                                connection_id: connection.connection.id,
+                                connection_name: connection.connection.name.clone(),
+                                account_currency: connection.currency.clone(),
+                                net_volume: exposure.net_volume,
+                                floating_profit_loss: exposure.floating_profit_loss,
+                            }
+                        })
+                    })
+                    .collect();
+                NetSymbolExposure { totals, connections: shares }
+            })
+            .collect();
+
+        Ok(NetExposure {
+            floating_profit_loss: total(symbols.iter().map(|s| s.totals.floating_profit_loss)),
+            mixed_currencies: account_currencies.len() > 1,
+            currency,
+            symbols,
+            conversions,
+        })
+    }
+
+    // Open trades valued by each connection's own session, the way /trades/floating values them,
+    // with P/L turned into money in the account's currency
+    async fn connection_legs(
+        pool: &PgPool,
+        mt5: &Arc<Mt5Service>,
+        quotes: &QuoteService,
+        user_id: Uuid,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<ConnectionLegs>> {
+        let trades = Trade::get_open_trades(pool, user_id).await?;
+        let robots: HashMap<Uuid, Uuid> = TradingRobot::find_by_user_id(pool, user_id)
+            .await?
+            .into_iter()
+            .filter_map(|robot| robot.broker_connection_id.map(|connection_id| (robot.id, connection_id)))
+            .collect();
+        let mut trades_by_connection: HashMap<Uuid, Vec<Trade>> = HashMap::new();
+        for trade in trades {
+            if let Some(connection_id) = robots.get(&trade.robot_id) {
+                trades_by_connection.entry(*connection_id).or_default().push(trade);
+            }
+        }
+
+        let mut connections = Vec::new();
+        for connection in BrokerConnection::find_by_user_id(pool, user_id).await? {
+            let Some(trades) = trades_by_connection.remove(&connection.id) else {
+                continue;
+            };
+            let own_quotes = ConnectionQuotes::new(mt5.clone(), connection.id);
+            let floating = quotes.floating_on(Some(&own_quotes), user_id, &trades, now).await;
+            let currency = Self::account_currency(pool, mt5, &connection).await?;
+
+            let mut conversions = Vec::new();
+            let mut legs = Vec::with_capacity(trades.len());
+            for (trade, value) in trades.into_iter().zip(&floating) {
+                let spec = SymbolSpec::for_symbol(&trade.symbol);
+                let conversion = match (&spec, &currency) {
+                    (Some(spec), Some(currency)) => {
+                        Self::conversion(quotes, Some(&own_quotes), user_id, &spec.quote_currency, currency, now, &mut conversions).await
+                    }
+                    _ => None,
+                };
+                let profit_loss = in_quote_currency(value, spec.as_ref()).zip(conversion).map(|(pnl, c)| c.convert(pnl));
+                legs.push(Leg { trade, profit_loss });
+            }
+            connections.push(ConnectionLegs {
+                indicative: floating.iter().any(|value| value.indicative),
+                connection,
+                currency,
+                legs,
+                conversions,
+            });
+        }
+        Ok(connections)
+    }
+
+    async fn account_currency(pool: &PgPool, mt5: &Mt5Service, connection: &BrokerConnection) -> Result<Option<String>> {
+        if let Some(snapshot) = AccountSnapshot::find_latest(pool, connection.id).await? {
+            return Ok(Some(snapshot.currency));
+        }
+        let connection_id = connection.id.to_string();
+        if !mt5.is_connected(&connection_id) {
+            return Ok(None);
+        }
+        Ok(mt5.get_account_info(&connection_id).await.ok().map(|info| info.currency))
+    }
+
+    // Looked up once per currency pair and listed in `seen` unless it is the identity
+    async fn conversion(
+        quotes: &QuoteService,
+        connection: Option<&dyn QuoteLookup>,
+        user_id: Uuid,
+        from: &str,
+        to: &str,
+        now: DateTime<Utc>,
+        seen: &mut Vec<Conversion>,
+    ) -> Option<Conversion> {
+        if let Some(known) = seen.iter().find(|c| c.from == from && c.to == to) {
+            return Some(known.clone());
+        }
+        let conversion = CurrencyConverter::rate(quotes, connection, user_id, from, to, now).await?;
+        if conversion.symbol.is_some() {
+            seen.push(conversion.clone());
+        }
+        Some(conversion)
+    }
+}
+
+// Floating P/L in the symbol's quote currency: the price move on each unit of the contract
+fn in_quote_currency(value: &FloatingTrade, spec: Option<&SymbolSpec>) -> Option<f64> {
+    Some(value.floating_profit_loss? * value.volume * spec?.contract_size)
+}
+
+fn by_symbol(legs: &[(&Trade, Option<f64>)]) -> Vec<SymbolExposure> {
+    let mut symbols: BTreeMap<&str, Vec<(&Trade, Option<f64>)>> = BTreeMap::new();
+    for (trade, pnl) in legs {
+        symbols.entry(trade.symbol.as_str()).or_default().push((*trade, *pnl));
+    }
+    symbols.into_iter().map(|(symbol, legs)| summarize(symbol, &legs)).collect()
+}
+
+fn summarize(symbol: &str, legs: &[(&Trade, Option<f64>)]) -> SymbolExposure {
+    let is_buy = |trade: &Trade| trade.trade_type.eq_ignore_ascii_case("buy");
+    let lots = |value: f64| round(value, VOLUME_DECIMALS, Rounding::HalfUp);
+    let long_volume = lots(legs.iter().filter(|(t, _)| is_buy(t)).map(|(t, _)| t.volume).sum());
+    let short_volume = lots(legs.iter().filter(|(t, _)| !is_buy(t)).map(|(t, _)| t.volume).sum());
+    let net_volume = lots(long_volume - short_volume);
+    let direction = if net_volume > 0.0 {
+        "long"
+    } else if net_volume < 0.0 {
+        "short"
+    } else {
+        "flat"
+    };
+
+    let net_side: Vec<&Trade> = legs.iter().map(|(t, _)| *t).filter(|t| (net_volume > 0.0) == is_buy(t)).collect();
+    let side_volume: f64 = net_side.iter().map(|t| t.volume).sum();
+    let average_entry_price = (net_volume != 0.0 && side_volume > 0.0)
+        .then(|| net_side.iter().map(|t| t.entry_price * t.volume).sum::<f64>() / side_volume);
+
+    SymbolExposure {
+        symbol: symbol.to_string(),
+        long_volume,
+        short_volume,
+        net_volume,
+        direction: direction.to_string(),
+        average_entry_price,
+        open_trades: legs.len(),
+        floating_profit_loss: total(legs.iter().map(|(_, pnl)| *pnl)),
+    }
+}
+
+// None as soon as one part is unknown: a partial sum would read as the whole
+fn total(parts: impl Iterator<Item = Option<f64>>) -> Option<f64> {
+    parts.sum()
+}
+
+fn parse_currency(raw: &str) -> Result<String> {
+    let currency = raw.trim().to_ascii_uppercase();
+    if currency.len() != 3 || !currency.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err(AppError::Validation(format!("Invalid currency '{}', expected a code like USD", raw.trim())));
+    }
+    Ok(currency)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trade(symbol: &str, trade_type: &str, volume: f64, entry_price: f64) -> Trade {
+        Trade::new(Uuid::new_v4(), Uuid::new_v4(), symbol.to_string(), trade_type.to_string(), volume, entry_price, None, None, None, None)
+    }
+
+    #[test]
+    fn test_offsetting_trades_net_to_the_larger_side() {
+        let trades = [
+            trade("EURUSD", "buy", 2.0, 1.0950),
+            trade("EURUSD", "buy", 1.0, 1.0980),
+            trade("EURUSD", "sell", 0.5, 1.1050),
+        ];
+        let legs: Vec<(&Trade, Option<f64>)> = vec![(&trades[0], Some(1000.0)), (&trades[1], Some(200.0)), (&trades[2], Some(-24.0))];
+
+        let exposure = summarize("EURUSD", &legs);
+        assert_eq!((exposure.long_volume, exposure.short_volume, exposure.net_volume), (3.0, 0.5, 2.5));
+        assert_eq!(exposure.direction, "long");
+        // Only the longs make up the net position's entry
+        assert!((exposure.average_entry_price.unwrap() - 1.0960).abs() < 1e-9);
+        assert_eq!(exposure.floating_profit_loss, Some(1176.0));
+
+        let hedged = summarize("EURUSD", &legs[1..]);
+        assert_eq!((hedged.net_volume, hedged.direction.as_str()), (0.5, "long"));
+        let flat = summarize("EURUSD", &[(&trades[2], None), (&trade("EURUSD", "buy", 0.5, 1.1), None)]);
+        assert_eq!((flat.direction.as_str(), flat.average_entry_price, flat.floating_profit_loss), ("flat", None, None));
+    }
+
+    #[test]
+    fn test_net_short_and_unpriced_legs() {
+        let trades = [trade("GBPUSD", "sell", 0.3, 1.2700), trade("GBPUSD", "buy", 0.1, 1.2600)];
+        let exposure = summarize("GBPUSD", &[(&trades[0], Some(30.0)), (&trades[1], None)]);
+        assert_eq!((exposure.net_volume, exposure.direction.as_str()), (-0.2, "short"));
+        assert!((exposure.average_entry_price.unwrap() - 1.2700).abs() < 1e-9);
+        assert_eq!(exposure.floating_profit_loss, None);
+        assert!(parse_currency("eur").is_ok() && parse_currency("EURO").is_err());
+    }
+}
@@ -0,0 +1,236 @@
+use async_trait::async_trait;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::time::Instant;
+use uuid::Uuid;
+
+use crate::{errors::Result, models::FeatureFlag};
+
+pub const PRO_TRIAL: &str = "pro_trial";
+pub const BATCH_CLOSE: &str = "batch_close";
+
+const FLAG_CACHE_TTL: Duration = Duration::from_secs(30);
+
+#[async_trait]
+pub trait FlagSource: Send + Sync {
+    async fn load_all(&self) -> Result<Vec<FeatureFlag>>;
+}
+
+pub struct PgFlagSource {
+    pool: PgPool,
+}
+
+impl PgFlagSource {
+    pub fn new(pool: PgPool) -> Self {
+        PgFlagSource { pool }
+    }
+}
+
+#[async_trait]
+impl FlagSource for PgFlagSource {
+    async fn load_all(&self) -> Result<Vec<FeatureFlag>> {
+        Ok(FeatureFlag::find_all(&self.pool).await?)
+    }
+}
+
+struct FlagSnapshot {
+    loaded_at: Instant,
+    flags: Arc<HashMap<String, FeatureFlag>>,
+}
+
+// Flags are read from the database at most once per TTL, so admin changes
+// reach every instance within that window without a restart
+pub struct FeatureFlags {
+    source: Arc<dyn FlagSource>,
+    ttl: Duration,
+    snapshot: RwLock<Option<FlagSnapshot>>,
+}
+
+impl FeatureFlags {
+    pub fn new(source: Arc<dyn FlagSource>) -> Self {
+        Self::with_ttl(source, FLAG_CACHE_TTL)
+    }
+
+    pub fn with_ttl(source: Arc<dyn FlagSource>, ttl: Duration) -> Self {
+        FeatureFlags {
+            source,
+            ttl,
+            snapshot: RwLock::new(None),
+        }
+    }
+
+    // Unknown flags are off
+    pub async fn is_enabled(&self, key: &str, user_id: Uuid) -> bool {
+        let flags = self.flags().await;
+        flags.get(key).is_some_and(|flag| Self::evaluate(flag, user_id))
+    }
+
+    // Drops the cached flags so this instance sees an admin change immediately
+    pub fn invalidate(&self) {
+        *self.snapshot.write().unwrap() = None;
+    }
+
+    pub fn evaluate(flag: &FeatureFlag, user_id: Uuid) -> bool {
+        flag.enabled
+            || flag.enabled_user_ids.contains(&user_id)
+            || Self::bucket(&flag.key, user_id) < flag.rollout_percentage.clamp(0, 100) as u8
+    }
+
+    // Stable 0..100 bucket per (flag, user): raising the percentage only ever adds users,
+    // and each flag splits users independently
+    pub fn bucket(key: &str, user_id: Uuid) -> u8 {
+        // FNV-1a, fixed so buckets survive restarts and compiler upgrades
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for byte in key.as_bytes().iter().chain(b":").chain(user_id.as_bytes()) {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        (hash % 100) as u8
+    }
+
+    async fn flags(&self) -> Arc<HashMap<String, FeatureFlag>> {
+        let stale = {
+            let snapshot = self.snapshot.read().unwrap();
+            match snapshot.as_ref() {
+                Some(s) if s.loaded_at.elapsed() < self.ttl => return s.flags.clone(),
+                Some(s) => Some(s.flags.clone()),
+                None => None,
+            }
+        };
+
+        match self.source.load_all().await {
+            Ok(flags) => {
+                let flags = Arc::new(flags.into_iter().map(|f| (f.key.clone(), f)).collect::<HashMap<_, _>>());
+                *self.snapshot.write().unwrap() = Some(FlagSnapshot {
+                    loaded_at: Instant::now(),
+                    flags: flags.clone(),
+                });
+                flags
+            }
+            Err(e) => {
+                // Keep serving the last known flags rather than switching everything off
+                tracing::warn!("Failed to reload feature flags: {}", e);
+                stale.unwrap_or_default()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::AppError;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct MockSource {
+        flags: Mutex<Vec<FeatureFlag>>,
+        loads: AtomicUsize,
+        failing: AtomicBool,
+    }
+
+    impl MockSource {
+        fn set(&self, flag: FeatureFlag) {
+            let mut flags = self.flags.lock().unwrap();
+            flags.retain(|f| f.key != flag.key);
+            flags.push(flag);
+        }
+    }
+
+    #[async_trait]
+    impl FlagSource for MockSource {
+        async fn load_all(&self) -> Result<Vec<FeatureFlag>> {
+            self.loads.fetch_add(1, Ordering::SeqCst);
+            if self.failing.load(Ordering::SeqCst) {
+                return Err(AppError::Internal(anyhow::anyhow!("database down")));
+            }
+            Ok(self.flags.lock().unwrap().clone())
+        }
+    }
+
+    fn flag(key: &str, enabled: bool) -> FeatureFlag {
+        FeatureFlag {
+            enabled,
+            ..FeatureFlag::new(key)
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_global_on_off_takes_effect_after_ttl() {
+        let source = Arc::new(MockSource::default());
+        source.set(flag("shadow_model", true));
+        let flags = FeatureFlags::with_ttl(source.clone(), Duration::from_secs(30));
+        let user = Uuid::new_v4();
+
+        assert!(flags.is_enabled("shadow_model", user).await);
+        assert!(!flags.is_enabled("unknown", user).await);
+
+        source.set(flag("shadow_model", false));
+        // Still cached
+        assert!(flags.is_enabled("shadow_model", user).await);
+        assert_eq!(source.loads.load(Ordering::SeqCst), 1);
+
+        tokio::time::advance(Duration::from_secs(31)).await;
+        assert!(!flags.is_enabled("shadow_model", user).await);
+
+        source.set(flag("shadow_model", true));
+        flags.invalidate();
+        assert!(flags.is_enabled("shadow_model", user).await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reload_failure_keeps_last_known_flags() {
+        let source = Arc::new(MockSource::default());
+        source.set(flag("webhooks", true));
+        let flags = FeatureFlags::with_ttl(source.clone(), Duration::from_secs(30));
+        let user = Uuid::new_v4();
+
+        assert!(flags.is_enabled("webhooks", user).await);
+        source.failing.store(true, Ordering::SeqCst);
+        tokio::time::advance(Duration::from_secs(31)).await;
+        assert!(flags.is_enabled("webhooks", user).await);
+    }
+
+    #[test]
+    fn test_per_user_override() {
+        let insider = Uuid::new_v4();
+        let flag = FeatureFlag {
+            enabled_user_ids: vec![insider],
+            ..flag("paper_trading", false)
+        };
+
+        assert!(FeatureFlags::evaluate(&flag, insider));
+        assert!(!FeatureFlags::evaluate(&flag, Uuid::new_v4()));
+    }
+
+    #[test]
+    fn test_percentage_bucketing_is_stable_and_monotonic() {
+        let user = Uuid::parse_str("6f1c2b9e-3d4a-4f5b-8c7d-1e2f3a4b5c6d").unwrap();
+        let bucket = FeatureFlags::bucket("shadow_model", user);
+        // Same answer every time, on every instance
+        assert_eq!(bucket, FeatureFlags::bucket("shadow_model", user));
+        assert_eq!(bucket, 84);
+
+        let mut rollout = flag("shadow_model", false);
+        rollout.rollout_percentage = bucket as i16;
+        assert!(!FeatureFlags::evaluate(&rollout, user));
+        rollout.rollout_percentage = bucket as i16 + 1;
+        assert!(FeatureFlags::evaluate(&rollout, user));
+        rollout.rollout_percentage = 100;
+        assert!(FeatureFlags::evaluate(&rollout, user));
+    }
+
+    #[test]
+    fn test_percentage_rollout_is_roughly_proportional() {
+        let mut rollout = flag("webhooks", false);
+        rollout.rollout_percentage = 25;
+
+        let enabled = (0..10_000)
+            .filter(|i| FeatureFlags::evaluate(&rollout, Uuid::from_u128(*i as u128 * 7919)))
+            .count();
+        assert!((2_200..2_800).contains(&enabled), "{} of 10000 enabled", enabled);
+    }
+}
@@ -0,0 +1,210 @@
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::errors::{AppError, Result};
+use crate::models::AUTH_EVENT_GITHUB_LOGIN;
+use super::oauth::{ExternalUser, OAuthProvider, PROVIDER_GITHUB};
+
+pub const GITHUB_API_URL: &str = "https://api.github.com";
+// GitHub refuses API requests without a User-Agent
+const USER_AGENT: &str = "trading-saas-backend";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GitHubAccount {
+    // Numeric and permanent, unlike the login, which can be renamed
+    pub id: i64,
+    pub login: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub avatar_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GitHubEmail {
+    pub email: String,
+    pub primary: bool,
+    pub verified: bool,
+}
+
+#[derive(Deserialize)]
+struct TokenCheck {
+    user: GitHubAccount,
+}
+
+#[async_trait]
+pub trait GitHubSource: Send + Sync {
+    // The account an access token belongs to; Err unless this app's OAuth client issued the token
+    async fn account(&self, access_token: &str) -> Result<GitHubAccount>;
+    // Needs the user:email scope
+    async fn emails(&self, access_token: &str) -> Result<Vec<GitHubEmail>>;
+}
+
+// The REST API. The token is checked against this app's client first, so a token some other app
+// obtained for its users cannot sign them in here.
+pub struct GitHubApi {
+    client: reqwest::Client,
+    url: String,
+    client_id: String,
+    client_secret: String,
+}
+
+impl GitHubApi {
+    pub fn new(url: String, client_id: String, client_secret: String) -> Self {
+        GitHubApi { client: reqwest::Client::new(), url, client_id, client_secret }
+    }
+
+    async fn read<T: for<'de> Deserialize<'de>>(response: reqwest::Result<reqwest::Response>) -> Result<T> {
+        let response = response.map_err(|e| AppError::External(format!("GitHub token check failed: {}", e)))?;
+        if response.status().is_client_error() {
+            return Err(AppError::Auth("Invalid GitHub token".to_string()));
+        }
+        if !response.status().is_success() {
+            return Err(AppError::External(format!("GitHub answered {}", response.status())));
+        }
+        response
+            .json()
+            .await
+            .map_err(|e| AppError::External(format!("Unreadable GitHub answer: {}", e)))
+    }
+}
+
+#[async_trait]
+impl GitHubSource for GitHubApi {
+    async fn account(&self, access_token: &str) -> Result<GitHubAccount> {
+        let response = self
+            .client
+            .post(format!("{}/applications/{}/token", self.url.trim_end_matches('/'), self.client_id))
+            .basic_auth(&self.client_id, Some(&self.client_secret))
+            .header("Accept", "application/vnd.github+json")
+            .header("User-Agent", USER_AGENT)
+            .json(&serde_json::json!({ "access_token": access_token }))
+            .send()
+            .await;
+        Ok(Self::read::<TokenCheck>(response).await?.user)
+    }
+
+    async fn emails(&self, access_token: &str) -> Result<Vec<GitHubEmail>> {
+        let response = self
+            .client
+            .get(format!("{}/user/emails", self.url.trim_end_matches('/')))
+            .bearer_auth(access_token)
+            .header("Accept", "application/vnd.github+json")
+            .header("User-Agent", USER_AGENT)
+            .send()
+            .await;
+        Self::read(response).await
+    }
+}
+
+// Tokens handed out by the test itself; anything else is refused like a foreign one
+#[derive(Default)]
+pub struct MemoryGitHubTokens {
+    tokens: Mutex<HashMap<String, (GitHubAccount, Vec<GitHubEmail>)>>,
+}
+
+impl MemoryGitHubTokens {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn issue(&self, access_token: &str, account: GitHubAccount, emails: Vec<GitHubEmail>) {
+        self.tokens.lock().unwrap().insert(access_token.to_string(), (account, emails));
+    }
+
+    fn lookup(&self, access_token: &str) -> Result<(GitHubAccount, Vec<GitHubEmail>)> {
+        self.tokens
+            .lock()
+            .unwrap()
+            .get(access_token)
+            .cloned()
+            .ok_or_else(|| AppError::Auth("Invalid GitHub token".to_string()))
+    }
+}
+
+#[async_trait]
+impl GitHubSource for MemoryGitHubTokens {
+    async fn account(&self, access_token: &str) -> Result<GitHubAccount> {
+        Ok(self.lookup(access_token)?.0)
+    }
+
+    async fn emails(&self, access_token: &str) -> Result<Vec<GitHubEmail>> {
+        Ok(self.lookup(access_token)?.1)
+    }
+}
+
+// GitHub sign-in with an OAuth access token obtained by the frontend
+pub struct GitHubVerifier {
+    source: Arc<dyn GitHubSource>,
+}
+
+impl GitHubVerifier {
+    pub fn new(source: Arc<dyn GitHubSource>) -> Self {
+        GitHubVerifier { source }
+    }
+}
+
+#[async_trait]
+impl OAuthProvider for GitHubVerifier {
+    fn name(&self) -> &'static str {
+        PROVIDER_GITHUB
+    }
+
+    fn login_event(&self) -> &'static str {
+        AUTH_EVENT_GITHUB_LOGIN
+    }
+
+    async fn verify(&self, access_token: &str) -> Result<ExternalUser> {
+        let access_token = access_token.trim();
+        let account = self.source.account(access_token).await?;
+        let email = primary_email(self.source.emails(access_token).await?)?;
+        Ok(ExternalUser {
+            external_id: account.id.to_string(),
+            email,
+            name: account.name.or(Some(account.login)),
+            avatar_url: account.avatar_url,
+        })
+    }
+}
+
+// Only the primary address, and only once GitHub has verified it; any other address on the
+// account could belong to someone else's account here
+fn primary_email(emails: Vec<GitHubEmail>) -> Result<String> {
+    emails
+        .into_iter()
+        .find(|e| e.primary && e.verified && !e.email.trim().is_empty())
+        .map(|e| e.email.trim().to_string())
+        .ok_or_else(|| AppError::Auth("GitHub has no verified primary email for this account".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn email(address: &str, primary: bool, verified: bool) -> GitHubEmail {
+        GitHubEmail { email: address.to_string(), primary, verified }
+    }
+
+    #[test]
+    fn test_only_a_verified_primary_email_is_used() {
+        let emails = vec![email("old@example.com", false, true), email(" trader@example.com", true, true)];
+        assert_eq!(primary_email(emails).unwrap(), "trader@example.com");
+        let unverified = vec![email("old@example.com", false, true), email("trader@example.com", true, false)];
+        assert!(matches!(primary_email(unverified), Err(AppError::Auth(_))));
+        assert!(primary_email(Vec::new()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tokens_map_to_the_numeric_account_id() {
+        let tokens = Arc::new(MemoryGitHubTokens::new());
+        let account = GitHubAccount { id: 583231, login: "octocat".to_string(), name: None, avatar_url: None };
+        tokens.issue("gho_issued", account, vec![email("octocat@example.com", true, true)]);
+        let verifier = GitHubVerifier::new(tokens);
+
+        let user = verifier.verify(" gho_issued ").await.unwrap();
+        assert_eq!((user.external_id.as_str(), user.name.as_deref()), ("583231", Some("octocat")));
+        assert!(matches!(verifier.verify("gho_forged").await, Err(AppError::Auth(_))));
+    }
+}
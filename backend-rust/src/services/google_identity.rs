@@ -0,0 +1,236 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::errors::{AppError, Result};
+use crate::models::AUTH_EVENT_GOOGLE_LOGIN;
+use super::oauth::{ExternalUser, OAuthProvider, PROVIDER_GOOGLE};
+
+pub const GOOGLE_TOKENINFO_URL: &str = "https://oauth2.googleapis.com/tokeninfo";
+// The two spellings Google puts in `iss`
+const GOOGLE_ISSUERS: [&str; 2] = ["accounts.google.com", "https://accounts.google.com"];
+
+// Claims of a Google ID token. tokeninfo sends every value as a string, a decoded JWT does not.
+#[derive(Debug, Clone, Deserialize)]
+pub struct GoogleTokenInfo {
+    pub iss: String,
+    pub aud: String,
+    // The Google account id; stays the same when the account's email changes
+    pub sub: String,
+    #[serde(deserialize_with = "seconds")]
+    pub exp: i64,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default, deserialize_with = "flag")]
+    pub email_verified: bool,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub picture: Option<String>,
+}
+
+#[async_trait]
+pub trait GoogleTokenSource: Send + Sync {
+    // Claims of a token whose signature checks out; Err for one Google does not recognise
+    async fn token_info(&self, id_token: &str) -> Result<GoogleTokenInfo>;
+}
+
+// Google's tokeninfo endpoint, which checks the signature against its current keys
+pub struct TokenInfoEndpoint {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl TokenInfoEndpoint {
+    pub fn new(url: String) -> Self {
+        TokenInfoEndpoint { client: reqwest::Client::new(), url }
+    }
+}
+
+#[async_trait]
+impl GoogleTokenSource for TokenInfoEndpoint {
+    async fn token_info(&self, id_token: &str) -> Result<GoogleTokenInfo> {
+        let response = self
+            .client
+            .get(&self.url)
+            .query(&[("id_token", id_token)])
+            .send()
+            .await
+            .map_err(|e| AppError::External(format!("Google token check failed: {}", e)))?;
+        if response.status().is_client_error() {
+            return Err(AppError::Auth("Invalid Google token".to_string()));
+        }
+        if !response.status().is_success() {
+            return Err(AppError::External(format!("Google token check answered {}", response.status())));
+        }
+        response
+            .json()
+            .await
+            .map_err(|e| AppError::External(format!("Unreadable Google token check answer: {}", e)))
+    }
+}
+
+// Tokens handed out by the test itself; anything else is refused like a forged one
+#[derive(Default)]
+pub struct MemoryGoogleTokens {
+    tokens: Mutex<HashMap<String, GoogleTokenInfo>>,
+}
+
+impl MemoryGoogleTokens {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn issue(&self, id_token: &str, info: GoogleTokenInfo) {
+        self.tokens.lock().unwrap().insert(id_token.to_string(), info);
+    }
+}
+
+#[async_trait]
+impl GoogleTokenSource for MemoryGoogleTokens {
+    async fn token_info(&self, id_token: &str) -> Result<GoogleTokenInfo> {
+        self.tokens
+            .lock()
+            .unwrap()
+            .get(id_token)
+            .cloned()
+            .ok_or_else(|| AppError::Auth("Invalid Google token".to_string()))
+    }
+}
+
+// Google sign-in for this app's OAuth client. Without a client id there is nothing a token could
+// be checked against, so every token is refused.
+pub struct GoogleVerifier {
+    source: Arc<dyn GoogleTokenSource>,
+    client_id: Option<String>,
+}
+
+impl GoogleVerifier {
+    pub fn new(source: Arc<dyn GoogleTokenSource>, client_id: Option<String>) -> Self {
+        GoogleVerifier { source, client_id }
+    }
+}
+
+#[async_trait]
+impl OAuthProvider for GoogleVerifier {
+    fn name(&self) -> &'static str {
+        PROVIDER_GOOGLE
+    }
+
+    fn login_event(&self) -> &'static str {
+        AUTH_EVENT_GOOGLE_LOGIN
+    }
+
+    async fn verify(&self, id_token: &str) -> Result<ExternalUser> {
+        let client_id = self
+            .client_id
+            .as_deref()
+            .ok_or_else(|| AppError::Auth("Google sign-in is not configured".to_string()))?;
+        let info = self.source.token_info(id_token.trim()).await?;
+        check_claims(info, client_id, Utc::now())
+    }
+}
+
+// A token signed by Google but issued to another app, expired, or for an email Google has not
+// confirmed would let someone sign in as an address they do not own
+fn check_claims(info: GoogleTokenInfo, client_id: &str, now: DateTime<Utc>) -> Result<ExternalUser> {
+    if info.aud != client_id {
+        return Err(AppError::Auth("Google token was issued to another application".to_string()));
+    }
+    if !GOOGLE_ISSUERS.contains(&info.iss.as_str()) {
+        return Err(AppError::Auth("Google token has an unknown issuer".to_string()));
+    }
+    if info.exp <= now.timestamp() {
+        return Err(AppError::Auth("Google token has expired".to_string()));
+    }
+    if info.sub.trim().is_empty() {
+        return Err(AppError::Auth("Google token has no account id".to_string()));
+    }
+    let email = info
+        .email
+        .filter(|email| !email.trim().is_empty())
+        .ok_or_else(|| AppError::Auth("No email in Google token".to_string()))?;
+    if !info.email_verified {
+        return Err(AppError::Auth("Google has not verified this email address".to_string()));
+    }
+
+    Ok(ExternalUser { external_id: info.sub, email: email.trim().to_string(), name: info.name, avatar_url: info.picture })
+}
+
+fn seconds<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<i64, D::Error> {
+    match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::Number(n) => n.as_i64(),
+        serde_json::Value::String(s) => s.parse().ok(),
+        _ => None,
+    }
+    .ok_or_else(|| serde::de::Error::custom("exp is not a number of seconds"))
+}
+
+// Providers send email_verified as a boolean or as "true"/"false"
+pub(crate) fn flag<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<bool, D::Error> {
+    Ok(match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::Bool(b) => b,
+        serde_json::Value::String(s) => s.eq_ignore_ascii_case("true"),
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLIENT_ID: &str = "123.apps.googleusercontent.com";
+
+    fn info(now: DateTime<Utc>) -> GoogleTokenInfo {
+        serde_json::from_value(serde_json::json!({
+            "iss": "https://accounts.google.com",
+            "aud": CLIENT_ID,
+            "sub": "110169484474386276334",
+            "exp": (now.timestamp() + 600).to_string(),
+            "email": " trader@example.com",
+            "email_verified": "true",
+            "name": "Trader",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_claims_of_a_token_for_this_app_are_accepted() {
+        let now = Utc::now();
+        let user = check_claims(info(now), CLIENT_ID, now).unwrap();
+        assert_eq!(user.external_id, "110169484474386276334");
+        assert_eq!(user.email, "trader@example.com");
+        assert_eq!(user.name.as_deref(), Some("Trader"));
+    }
+
+    #[test]
+    fn test_foreign_expired_and_unverified_tokens_are_refused() {
+        let now = Utc::now();
+        let refused: Vec<GoogleTokenInfo> = vec![
+            GoogleTokenInfo { aud: "another-app.apps.googleusercontent.com".to_string(), ..info(now) },
+            GoogleTokenInfo { iss: "evil.example.com".to_string(), ..info(now) },
+            GoogleTokenInfo { exp: now.timestamp(), ..info(now) },
+            GoogleTokenInfo { email_verified: false, ..info(now) },
+            GoogleTokenInfo { email: None, ..info(now) },
+        ];
+        for token in refused {
+            assert!(matches!(check_claims(token.clone(), CLIENT_ID, now), Err(AppError::Auth(_))), "{:?}", token);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_nothing_is_accepted_without_a_client_id_or_from_unknown_tokens() {
+        let tokens = Arc::new(MemoryGoogleTokens::new());
+        tokens.issue("issued", info(Utc::now()));
+
+        let unconfigured = GoogleVerifier::new(tokens.clone(), None);
+        assert!(unconfigured.verify("issued").await.is_err());
+
+        let verifier = GoogleVerifier::new(tokens, Some(CLIENT_ID.to_string()));
+        assert!(verifier.verify("issued").await.is_ok());
+        // The prefix the old mock mode let through
+        assert!(verifier.verify("mock_google_token_admin").await.is_err());
+    }
+}
@@ -0,0 +1,611 @@
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use serde::Serialize;
+use sqlx::PgPool;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use uuid::Uuid;
+
+use crate::{
+    errors::Result,
+    models::{ClosedTradeRow, FactTotals, IntegrityBatch, IntegrityRun, RiskConfigWarnings, TotalsCorrection, TradeTotals},
+};
+
+// Robots loaded, compared and corrected per transaction
+pub const BATCH_SIZE: usize = 50;
+// Discrepancies listed in a report; the counts always cover all of them
+pub const MAX_LISTED_DISCREPANCIES: usize = 500;
+// Money is compared at cent precision
+const PROFIT_TOLERANCE: f64 = 0.005;
+const VOLUME_TOLERANCE: f64 = 1e-6;
+
+// A trade_daily_facts row: robot, symbol, is_demo, opened_on and date
+pub type FactKey = (Uuid, String, bool, NaiveDate, NaiveDate);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntegrityJob {
+    Check,
+    Recalculate,
+}
+
+impl IntegrityJob {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IntegrityJob::Check => "check",
+            IntegrityJob::Recalculate => "recalculate",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Discrepancy {
+    RobotTotals { robot_id: Uuid, user_id: Uuid, stored: TradeTotals, expected: TradeTotals },
+    SessionTotals { session_id: Uuid, robot_id: Uuid, stored: TradeTotals, expected: TradeTotals },
+    // Cannot be repaired from the trades table; the broker history is needed
+    ClosedWithoutProfitLoss { trade_id: Uuid, robot_id: Uuid },
+    // A running robot whose risk_config has settings this version does not enforce
+    IgnoredRiskFields { robot_id: Uuid, user_id: Uuid, fields: Vec<String> },
+    // A trade_daily_facts row that does not add up to the robot's closed trades; a missing row
+    // is all zeros
+    DailyFacts {
+        robot_id: Uuid,
+        symbol: String,
+        is_demo: bool,
+        opened_on: NaiveDate,
+        date: NaiveDate,
+        stored: FactTotals,
+        expected: FactTotals,
+    },
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct IntegrityReport {
+    pub robots_checked: usize,
+    pub sessions_checked: usize,
+    pub trades_checked: usize,
+    pub robot_discrepancies: usize,
+    pub session_discrepancies: usize,
+    pub fact_discrepancies: usize,
+    pub trades_without_profit_loss: usize,
+    pub robots_with_ignored_risk_fields: usize,
+    // Running robots per ignored risk_config field
+    pub ignored_risk_fields: BTreeMap<String, usize>,
+    // Robots, sessions and robots' facts rewritten; always 0 for a check
+    pub repaired: usize,
+    pub discrepancies: Vec<Discrepancy>,
+}
+
+#[async_trait]
+pub trait IntegrityStore: Send + Sync {
+    async fn robot_ids(&self, user_id: Option<Uuid>) -> Result<Vec<Uuid>>;
+    async fn load_batch(&self, robot_ids: &[Uuid]) -> Result<IntegrityBatch>;
+    // All corrections for one batch, in one transaction
+    async fn apply(&self, corrections: &[TotalsCorrection]) -> Result<()>;
+    async fn progress(&self, run_id: Uuid, processed: usize, total: usize) -> Result<()>;
+}
+
+pub struct PgIntegrityStore {
+    pool: PgPool,
+}
+
+impl PgIntegrityStore {
+    pub fn new(pool: PgPool) -> Self {
+        PgIntegrityStore { pool }
+    }
+}
+
+#[async_trait]
+impl IntegrityStore for PgIntegrityStore {
+    async fn robot_ids(&self, user_id: Option<Uuid>) -> Result<Vec<Uuid>> {
+        IntegrityRun::robot_ids(&self.pool, user_id).await
+    }
+
+    async fn load_batch(&self, robot_ids: &[Uuid]) -> Result<IntegrityBatch> {
+        IntegrityRun::load_batch(&self.pool, robot_ids).await
+    }
+
+    async fn apply(&self, corrections: &[TotalsCorrection]) -> Result<()> {
+        IntegrityRun::apply_corrections(&self.pool, corrections).await
+    }
+
+    async fn progress(&self, run_id: Uuid, processed: usize, total: usize) -> Result<()> {
+        IntegrityRun::update_progress(&self.pool, run_id, processed as i32, total as i32).await
+    }
+}
+
+pub struct IntegrityService;
+
+impl IntegrityService {
+    // Same rules as TradingRobot::refresh_performance: every closed trade counts, a missing
+    // profit_loss adds nothing to the profit and is not a win
+    pub fn totals<'a>(trades: impl Iterator<Item = &'a ClosedTradeRow>) -> TradeTotals {
+        trades.fold(TradeTotals::default(), |mut totals, trade| {
+            totals.total_trades += 1;
+            if let Some(profit_loss) = trade.profit_loss {
+                if profit_loss > 0.0 {
+                    totals.winning_trades += 1;
+                }
+                totals.total_profit += profit_loss;
+            }
+            totals
+        })
+    }
+
+    // What trade_daily_facts should hold for these trades; those without a closed_at are left out
+    pub fn expected_facts<'a>(trades: impl Iterator<Item = &'a ClosedTradeRow>) -> BTreeMap<FactKey, FactTotals> {
+        let mut facts: BTreeMap<FactKey, FactTotals> = BTreeMap::new();
+        for trade in trades {
+            let Some(closed_at) = trade.closed_at else { continue };
+            let key = (trade.robot_id, trade.symbol.clone(), trade.is_demo, trade.opened_at.date_naive(), closed_at.date_naive());
+            facts.entry(key).or_default().add_trade(trade.profit_loss, trade.volume);
+        }
+        facts
+    }
+
+    fn facts_match(stored: &FactTotals, expected: &FactTotals) -> bool {
+        stored.trades == expected.trades
+            && stored.wins == expected.wins
+            && (stored.gross_profit - expected.gross_profit).abs() < PROFIT_TOLERANCE
+            && (stored.gross_loss - expected.gross_loss).abs() < PROFIT_TOLERANCE
+            && (stored.net_profit - expected.net_profit).abs() < PROFIT_TOLERANCE
+            && (stored.volume - expected.volume).abs() < VOLUME_TOLERANCE
+    }
+
+    fn matches(stored: &TradeTotals, expected: &TradeTotals) -> bool {
+        stored.total_trades == expected.total_trades
+            && stored.winning_trades == expected.winning_trades
+            && (stored.total_profit - expected.total_profit).abs() < PROFIT_TOLERANCE
+    }
+
+    pub fn find_discrepancies(batch: &IntegrityBatch) -> Vec<Discrepancy> {
+        let mut by_robot: HashMap<Uuid, Vec<&ClosedTradeRow>> = HashMap::new();
+        for trade in &batch.trades {
+            by_robot.entry(trade.robot_id).or_default().push(trade);
+        }
+        let trades_of = |robot_id: Uuid| by_robot.get(&robot_id).map(|t| t.as_slice()).unwrap_or_default();
+
+        let mut discrepancies = Vec::new();
+
+        for robot in &batch.robots {
+            let expected = Self::totals(trades_of(robot.id).iter().copied());
+            let stored = robot.totals();
+            if !Self::matches(&stored, &expected) {
+                discrepancies.push(Discrepancy::RobotTotals { robot_id: robot.id, user_id: robot.user_id, stored, expected });
+            }
+        }
+
+        for session in &batch.sessions {
+            let in_window = trades_of(session.robot_id).iter().copied().filter(|trade| {
+                trade.closed_at.is_some_and(|closed_at| {
+                    closed_at >= session.started_at && session.ended_at.is_none_or(|ended_at| closed_at <= ended_at)
+                })
+            });
+            let expected = Self::totals(in_window);
+            let stored = session.totals();
+            if !Self::matches(&stored, &expected) {
+                discrepancies.push(Discrepancy::SessionTotals {
+                    session_id: session.id,
+                    robot_id: session.robot_id,
+                    stored,
+                    expected,
+                });
+            }
+        }
+
+        let expected_facts = Self::expected_facts(batch.trades.iter());
+        let stored_facts: BTreeMap<FactKey, FactTotals> = batch
+            .facts
+            .iter()
+            .map(|f| ((f.robot_id, f.symbol.clone(), f.is_demo, f.opened_on, f.date), f.totals()))
+            .collect();
+        let fact_keys: BTreeSet<&FactKey> = expected_facts.keys().chain(stored_facts.keys()).collect();
+        for key in fact_keys {
+            let stored = stored_facts.get(key).copied().unwrap_or_default();
+            let expected = expected_facts.get(key).copied().unwrap_or_default();
+            if !Self::facts_match(&stored, &expected) {
+                let (robot_id, symbol, is_demo, opened_on, date) = key.clone();
+                discrepancies.push(Discrepancy::DailyFacts { robot_id, symbol, is_demo, opened_on, date, stored, expected });
+            }
+        }
+
+        for trade in batch.trades.iter().filter(|trade| trade.profit_loss.is_none()) {
+            discrepancies.push(Discrepancy::ClosedWithoutProfitLoss { trade_id: trade.id, robot_id: trade.robot_id });
+        }
+
+        for robot in batch.robots.iter().filter(|robot| robot.running) {
+            let fields = RiskConfigWarnings::check(&robot.risk_config).ignored_fields;
+            if !fields.is_empty() {
+                discrepancies.push(Discrepancy::IgnoredRiskFields { robot_id: robot.id, user_id: robot.user_id, fields });
+            }
+        }
+
+        discrepancies
+    }
+
+    fn correction(discrepancy: &Discrepancy) -> Option<TotalsCorrection> {
+        match discrepancy {
+            Discrepancy::RobotTotals { robot_id, expected, .. } => {
+                Some(TotalsCorrection::Robot { robot_id: *robot_id, totals: *expected })
+            }
+            Discrepancy::SessionTotals { session_id, expected, .. } => {
+                Some(TotalsCorrection::Session { session_id: *session_id, totals: *expected })
+            }
+            Discrepancy::DailyFacts { robot_id, .. } => Some(TotalsCorrection::Facts { robot_id: *robot_id }),
+            Discrepancy::ClosedWithoutProfitLoss { .. } | Discrepancy::IgnoredRiskFields { .. } => None,
+        }
+    }
+
+    // Walks the robots in scope batch by batch, recording progress on the run after each one
+    pub async fn run(
+        store: &dyn IntegrityStore,
+        run_id: Uuid,
+        job: IntegrityJob,
+        user_id: Option<Uuid>,
+    ) -> Result<IntegrityReport> {
+        let robot_ids = store.robot_ids(user_id).await?;
+        let mut report = IntegrityReport::default();
+        store.progress(run_id, 0, robot_ids.len()).await?;
+
+        for (index, chunk) in robot_ids.chunks(BATCH_SIZE).enumerate() {
+            let batch = store.load_batch(chunk).await?;
+            report.robots_checked += batch.robots.len();
+            report.sessions_checked += batch.sessions.len();
+            report.trades_checked += batch.trades.len();
+
+            let discrepancies = Self::find_discrepancies(&batch);
+            if job == IntegrityJob::Recalculate {
+                let mut corrections: Vec<_> = discrepancies.iter().filter_map(Self::correction).collect();
+                // A robot with several wrong fact rows is rebuilt once
+                corrections.dedup();
+                if !corrections.is_empty() {
+                    store.apply(&corrections).await?;
+                    report.repaired += corrections.len();
+                }
+            }
+
+            for discrepancy in discrepancies {
+                match discrepancy {
+                    Discrepancy::RobotTotals { .. } => report.robot_discrepancies += 1,
+                    Discrepancy::SessionTotals { .. } => report.session_discrepancies += 1,
+                    Discrepancy::DailyFacts { .. } => report.fact_discrepancies += 1,
+                    Discrepancy::ClosedWithoutProfitLoss { .. } => report.trades_without_profit_loss += 1,
+                    Discrepancy::IgnoredRiskFields { ref fields, .. } => {
+                        report.robots_with_ignored_risk_fields += 1;
+                        for field in fields {
+                            *report.ignored_risk_fields.entry(field.clone()).or_default() += 1;
+                        }
+                    }
+                }
+                if report.discrepancies.len() < MAX_LISTED_DISCREPANCIES {
+                    report.discrepancies.push(discrepancy);
+                }
+            }
+
+            let processed = (index * BATCH_SIZE + chunk.len()).min(robot_ids.len());
+            store.progress(run_id, processed, robot_ids.len()).await?;
+        }
+
+        Ok(report)
+    }
+
+    // Background job entry point; the outcome is persisted on the run either way
+    pub async fn run_and_record(pool: PgPool, run_id: Uuid, job: IntegrityJob, user_id: Option<Uuid>) {
+        let store = PgIntegrityStore::new(pool.clone());
+        let outcome = match Self::run(&store, run_id, job, user_id).await {
+            Ok(report) => {
+                tracing::info!(
+                    "Integrity {} {} finished: {} robot, {} session, {} daily fact and {} trade discrepancies, {} repaired, {} running robot(s) with ignored risk fields",
+                    job.as_str(),
+                    run_id,
+                    report.robot_discrepancies,
+                    report.session_discrepancies,
+                    report.fact_discrepancies,
+                    report.trades_without_profit_loss,
+                    report.repaired,
+                    report.robots_with_ignored_risk_fields
+                );
+                IntegrityRun::finish(&pool, run_id, &serde_json::to_value(&report).unwrap_or_default()).await
+            }
+            Err(e) => {
+                tracing::error!("Integrity {} {} failed: {}", job.as_str(), run_id, e);
+                IntegrityRun::fail(&pool, run_id, &e.to_string()).await
+            }
+        };
+
+        if let Err(e) = outcome {
+            tracing::error!("Could not record the outcome of integrity run {}: {}", run_id, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{StoredRobotTotals, StoredSessionTotals, TradeDailyFact};
+    use chrono::{DateTime, Duration, TimeZone, Utc};
+    use std::sync::Mutex;
+
+    // An in-memory copy of the tables involved
+    struct FakeStore {
+        batch: Mutex<IntegrityBatch>,
+        progress: Mutex<Vec<(usize, usize)>>,
+        transactions: Mutex<usize>,
+    }
+
+    impl FakeStore {
+        fn new(batch: IntegrityBatch) -> Self {
+            FakeStore { batch: Mutex::new(batch), progress: Mutex::new(Vec::new()), transactions: Mutex::new(0) }
+        }
+    }
+
+    #[async_trait]
+    impl IntegrityStore for FakeStore {
+        async fn robot_ids(&self, user_id: Option<Uuid>) -> Result<Vec<Uuid>> {
+            let batch = self.batch.lock().unwrap();
+            Ok(batch.robots.iter().filter(|r| user_id.is_none_or(|u| r.user_id == u)).map(|r| r.id).collect())
+        }
+
+        async fn load_batch(&self, robot_ids: &[Uuid]) -> Result<IntegrityBatch> {
+            let batch = self.batch.lock().unwrap();
+            Ok(IntegrityBatch {
+                robots: batch.robots.iter().filter(|r| robot_ids.contains(&r.id)).cloned().collect(),
+                sessions: batch.sessions.iter().filter(|s| robot_ids.contains(&s.robot_id)).cloned().collect(),
+                trades: batch.trades.iter().filter(|t| robot_ids.contains(&t.robot_id)).cloned().collect(),
+                facts: batch.facts.iter().filter(|f| robot_ids.contains(&f.robot_id)).cloned().collect(),
+            })
+        }
+
+        async fn apply(&self, corrections: &[TotalsCorrection]) -> Result<()> {
+            *self.transactions.lock().unwrap() += 1;
+            let mut batch = self.batch.lock().unwrap();
+            for correction in corrections {
+                match correction {
+                    TotalsCorrection::Robot { robot_id, totals } => {
+                        let robot = batch.robots.iter_mut().find(|r| r.id == *robot_id).unwrap();
+                        robot.total_trades = totals.total_trades;
+                        robot.winning_trades = totals.winning_trades;
+                        robot.total_profit = totals.total_profit;
+                    }
+                    TotalsCorrection::Session { session_id, totals } => {
+                        let session = batch.sessions.iter_mut().find(|s| s.id == *session_id).unwrap();
+                        session.total_trades = totals.total_trades;
+                        session.winning_trades = totals.winning_trades;
+                        session.total_profit = totals.total_profit;
+                    }
+                    TotalsCorrection::Facts { robot_id } => {
+                        let user_id = batch.robots.iter().find(|r| r.id == *robot_id).unwrap().user_id;
+                        let rebuilt = facts_of(user_id, batch.trades.iter().filter(|t| t.robot_id == *robot_id));
+                        batch.facts.retain(|f| f.robot_id != *robot_id);
+                        batch.facts.extend(rebuilt);
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        async fn progress(&self, _run_id: Uuid, processed: usize, total: usize) -> Result<()> {
+            self.progress.lock().unwrap().push((processed, total));
+            Ok(())
+        }
+    }
+
+    fn at(hour: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 5, 6, 0, 0, 0).unwrap() + Duration::hours(hour)
+    }
+
+    fn trade(robot_id: Uuid, hour: i64, profit_loss: Option<f64>) -> ClosedTradeRow {
+        ClosedTradeRow {
+            id: Uuid::new_v4(),
+            robot_id,
+            symbol: "EURUSD".to_string(),
+            is_demo: false,
+            volume: 0.1,
+            profit_loss,
+            opened_at: at(hour) - Duration::minutes(30),
+            closed_at: Some(at(hour)),
+        }
+    }
+
+    fn facts_of<'a>(user_id: Uuid, trades: impl Iterator<Item = &'a ClosedTradeRow>) -> Vec<TradeDailyFact> {
+        IntegrityService::expected_facts(trades)
+            .into_iter()
+            .map(|((robot_id, symbol, is_demo, opened_on, date), totals)| TradeDailyFact {
+                user_id,
+                robot_id,
+                symbol,
+                is_demo,
+                opened_on,
+                date,
+                trades: totals.trades,
+                wins: totals.wins,
+                gross_profit: totals.gross_profit,
+                gross_loss: totals.gross_loss,
+                net_profit: totals.net_profit,
+                volume: totals.volume,
+            })
+            .collect()
+    }
+
+    // Seeds robots whose stored totals and sessions agree with their trades
+    fn seed(user_id: Uuid, robots: usize) -> IntegrityBatch {
+        let mut batch = IntegrityBatch::default();
+        for _ in 0..robots {
+            let robot_id = Uuid::new_v4();
+            let trades = vec![
+                trade(robot_id, 1, Some(12.5)),
+                trade(robot_id, 2, Some(-4.25)),
+                trade(robot_id, 10, Some(3.0)),
+            ];
+            let all = IntegrityService::totals(trades.iter());
+            let first_session = IntegrityService::totals(trades[..2].iter());
+            let second_session = IntegrityService::totals(trades[2..].iter());
+
+            batch.robots.push(StoredRobotTotals {
+                id: robot_id,
+                user_id,
+                total_trades: all.total_trades,
+                winning_trades: all.winning_trades,
+                total_profit: all.total_profit,
+                running: true,
+                risk_config: crate::models::TradingRobot::default_risk_config(),
+            });
+            for (started, ended, totals) in [(0, Some(5), first_session), (8, None, second_session)] {
+                batch.sessions.push(StoredSessionTotals {
+                    id: Uuid::new_v4(),
+                    robot_id,
+                    started_at: at(started),
+                    ended_at: ended.map(at),
+                    total_trades: totals.total_trades,
+                    winning_trades: totals.winning_trades,
+                    total_profit: totals.total_profit,
+                });
+            }
+            batch.facts.extend(facts_of(user_id, trades.iter()));
+            batch.trades.extend(trades);
+        }
+        batch
+    }
+
+    #[test]
+    fn test_consistent_data_has_no_discrepancies() {
+        let batch = seed(Uuid::new_v4(), 3);
+        assert!(IntegrityService::find_discrepancies(&batch).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_check_finds_corruption_and_recalculate_repairs_it() {
+        let user_id = Uuid::new_v4();
+        let mut batch = seed(user_id, BATCH_SIZE + 5);
+
+        // The 0.0 sentinel bug: a robot whose profit was zeroed
+        let zeroed = batch.robots[0].id;
+        batch.robots[0].total_profit = 0.0;
+        // A session counting a trade from outside its window
+        let session = batch.sessions[2].id;
+        batch.sessions[2].total_trades += 1;
+        // A trade closed without a profit_loss
+        let missing = batch.trades[BATCH_SIZE * 3].id;
+        batch.trades[BATCH_SIZE * 3].profit_loss = None;
+        let missing_robot = batch.trades[BATCH_SIZE * 3].robot_id;
+
+        // Another user's data is out of scope
+        let mut other = seed(Uuid::new_v4(), 1);
+        other.robots[0].total_trades = 99;
+        batch.robots.extend(other.robots);
+        batch.sessions.extend(other.sessions);
+        batch.trades.extend(other.trades);
+        batch.facts.extend(other.facts);
+
+        let store = FakeStore::new(batch);
+        let run_id = Uuid::new_v4();
+
+        let report = IntegrityService::run(&store, run_id, IntegrityJob::Check, Some(user_id)).await.unwrap();
+        assert_eq!(report.robots_checked, BATCH_SIZE + 5);
+        assert_eq!(report.repaired, 0);
+        assert_eq!(*store.transactions.lock().unwrap(), 0);
+        assert!(report.discrepancies.iter().any(|d| matches!(d,
+            Discrepancy::RobotTotals { robot_id, stored, expected, .. }
+                if *robot_id == zeroed && stored.total_profit == 0.0 && (expected.total_profit - 11.25).abs() < 1e-9)));
+        assert!(report.discrepancies.iter().any(|d| matches!(d,
+            Discrepancy::SessionTotals { session_id, .. } if *session_id == session)));
+        assert!(report.discrepancies.contains(&Discrepancy::ClosedWithoutProfitLoss { trade_id: missing, robot_id: missing_robot }));
+        // The missing profit also leaves that robot's stored totals and daily facts out of line with its trades
+        assert_eq!((report.robot_discrepancies, report.session_discrepancies, report.trades_without_profit_loss), (2, 2, 1));
+        assert_eq!(report.fact_discrepancies, 1);
+        assert_eq!(*store.progress.lock().unwrap(), vec![(0, BATCH_SIZE + 5), (BATCH_SIZE, BATCH_SIZE + 5), (BATCH_SIZE + 5, BATCH_SIZE + 5)]);
+
+        let report = IntegrityService::run(&store, run_id, IntegrityJob::Recalculate, Some(user_id)).await.unwrap();
+        assert_eq!(report.repaired, 5);
+        // One transaction per batch that had something to fix
+        assert_eq!(*store.transactions.lock().unwrap(), 2);
+
+        let report = IntegrityService::run(&store, run_id, IntegrityJob::Check, Some(user_id)).await.unwrap();
+        assert_eq!(report.discrepancies, vec![Discrepancy::ClosedWithoutProfitLoss { trade_id: missing, robot_id: missing_robot }]);
+
+        // A global check still sees the other user's corrupted robot
+        let report = IntegrityService::run(&store, run_id, IntegrityJob::Check, None).await.unwrap();
+        assert_eq!(report.robot_discrepancies, 1);
+    }
+
+    #[test]
+    fn test_running_robots_with_ignored_risk_fields_are_aggregated() {
+        let mut batch = seed(Uuid::new_v4(), 4);
+        // Written for a newer runner
+        batch.robots[0].risk_config["trailing_stop"] = serde_json::json!({ "pips": 15 });
+        batch.robots[1].risk_config["trailing_stop"] = serde_json::json!({ "pips": 10 });
+        batch.robots[1].risk_config["news_blackout_minutes"] = serde_json::json!(30);
+        // Stopped, so nothing is weakened until it is started, which asks for an acknowledgement
+        batch.robots[2].risk_config["trailing_stop"] = serde_json::json!({ "pips": 5 });
+        batch.robots[2].running = false;
+        // A legacy config missing a default is filled in at runtime, not a discrepancy
+        batch.robots[3].risk_config = serde_json::json!({ "max_risk_per_trade": 0.01 });
+
+        let discrepancies = IntegrityService::find_discrepancies(&batch);
+        assert_eq!(
+            discrepancies,
+            vec![
+                Discrepancy::IgnoredRiskFields {
+                    robot_id: batch.robots[0].id,
+                    user_id: batch.robots[0].user_id,
+                    fields: vec!["trailing_stop".to_string()],
+                },
+                Discrepancy::IgnoredRiskFields {
+                    robot_id: batch.robots[1].id,
+                    user_id: batch.robots[1].user_id,
+                    fields: vec!["news_blackout_minutes".to_string(), "trailing_stop".to_string()],
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_report_counts_robots_per_ignored_field() {
+        let mut batch = seed(Uuid::new_v4(), 3);
+        for robot in &mut batch.robots {
+            robot.risk_config["trailing_stop"] = serde_json::json!({ "pips": 15 });
+        }
+        batch.robots[0].risk_config["news_blackout_minutes"] = serde_json::json!(30);
+        let store = FakeStore::new(batch);
+
+        let report = IntegrityService::run(&store, Uuid::new_v4(), IntegrityJob::Recalculate, None).await.unwrap();
+        assert_eq!(report.robots_with_ignored_risk_fields, 3);
+        assert_eq!(
+            report.ignored_risk_fields,
+            BTreeMap::from([("news_blackout_minutes".to_string(), 1), ("trailing_stop".to_string(), 3)])
+        );
+        // Nothing to repair from the database
+        assert_eq!(report.repaired, 0);
+    }
+
+    #[tokio::test]
+    async fn test_daily_facts_are_compared_with_the_trades_and_rebuilt() {
+        let mut batch = seed(Uuid::new_v4(), 2);
+        let robot_id = batch.robots[0].id;
+        // A close whose upsert was lost, a stale row for a day without trades, and a trade
+        // without closed_at, which the facts leave out
+        let lost = batch.facts.iter().position(|f| f.robot_id == robot_id).unwrap();
+        batch.facts[lost].trades -= 1;
+        let mut stale = batch.facts[lost].clone();
+        stale.date = stale.date.succ_opt().unwrap();
+        batch.facts.push(stale);
+        let mut unfinished = trade(batch.robots[1].id, 3, Some(1.0));
+        unfinished.closed_at = None;
+        batch.trades.push(unfinished);
+        batch.robots[1].total_trades += 1;
+        batch.robots[1].winning_trades += 1;
+        batch.robots[1].total_profit += 1.0;
+
+        let store = FakeStore::new(batch);
+        let report = IntegrityService::run(&store, Uuid::new_v4(), IntegrityJob::Check, None).await.unwrap();
+        assert_eq!(report.fact_discrepancies, 2);
+        assert!(report.discrepancies.iter().all(|d| matches!(d, Discrepancy::DailyFacts { robot_id: r, .. } if *r == robot_id)));
+        assert!(report.discrepancies.iter().any(|d| matches!(d,
+            Discrepancy::DailyFacts { stored, expected, .. } if stored.trades == 2 && expected.trades == 0)));
+
+        let report = IntegrityService::run(&store, Uuid::new_v4(), IntegrityJob::Recalculate, None).await.unwrap();
+        // Both rows belong to one robot, rebuilt once
+        assert_eq!(report.repaired, 1);
+        let report = IntegrityService::run(&store, Uuid::new_v4(), IntegrityJob::Check, None).await.unwrap();
+        assert!(report.discrepancies.is_empty(), "{:?}", report.discrepancies);
+    }
+}
@@ -0,0 +1,509 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::{
+    errors::{AppError, Result},
+    models::{Job, Subscription, JOB_CANCELLED, JOB_COMPLETED, JOB_FAILED},
+};
+
+// Active jobs that have not checkpointed for this long are failed by the stale sweep
+pub const STALE_JOB_MINUTES: i64 = 30;
+pub const STALE_SWEEP_SECONDS: u64 = 300;
+const JOB_LIST_LIMIT: i64 = 100;
+
+// Endpoints expensive enough that one user running many of them at once slows down everyone
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum JobClass {
+    Export,
+    Backtest,
+    Import,
+}
+
+impl JobClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobClass::Export => "export",
+            JobClass::Backtest => "backtest",
+            JobClass::Import => "import",
+        }
+    }
+
+    pub fn parse(kind: &str) -> Option<JobClass> {
+        [JobClass::Export, JobClass::Backtest, JobClass::Import].into_iter().find(|class| class.as_str() == kind)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct JobClassUsage {
+    pub job_class: JobClass,
+    pub running_jobs: usize,
+    pub users: usize,
+}
+
+#[async_trait]
+pub trait JobStore: Send + Sync {
+    // Inserts the job unless its owner already has `limit` active jobs of its kind; the error
+    // carries their ids
+    async fn register(&self, job: &Job, limit: usize) -> Result<std::result::Result<(), Vec<Uuid>>>;
+    async fn start(&self, job_id: Uuid, now: DateTime<Utc>) -> Result<()>;
+    // Returns whether a cancel has been requested
+    async fn checkpoint(&self, job_id: Uuid, progress: f64, checkpoint: &serde_json::Value, now: DateTime<Utc>) -> Result<bool>;
+    async fn finish(&self, job_id: Uuid, status: &str, result_ref: Option<&str>, error: Option<&str>, now: DateTime<Utc>) -> Result<()>;
+    async fn request_cancel(&self, user_id: Uuid, job_id: Uuid, now: DateTime<Utc>) -> Result<Option<Job>>;
+    async fn find(&self, user_id: Uuid, job_id: Uuid) -> Result<Option<Job>>;
+    async fn list(&self, user_id: Uuid) -> Result<Vec<Job>>;
+    async fn usage(&self) -> Result<Vec<JobClassUsage>>;
+}
+
+pub struct PgJobStore {
+    pool: PgPool,
+}
+
+impl PgJobStore {
+    pub fn new(pool: PgPool) -> Self {
+        PgJobStore { pool }
+    }
+}
+
+#[async_trait]
+impl JobStore for PgJobStore {
+    async fn register(&self, job: &Job, limit: usize) -> Result<std::result::Result<(), Vec<Uuid>>> {
+        Job::register(&self.pool, job, limit as i64).await
+    }
+
+    async fn start(&self, job_id: Uuid, now: DateTime<Utc>) -> Result<()> {
+        Job::start(&self.pool, job_id, now).await
+    }
+
+    async fn checkpoint(&self, job_id: Uuid, progress: f64, checkpoint: &serde_json::Value, now: DateTime<Utc>) -> Result<bool> {
+        Job::checkpoint(&self.pool, job_id, progress, checkpoint, now).await
+    }
+
+    async fn finish(&self, job_id: Uuid, status: &str, result_ref: Option<&str>, error: Option<&str>, now: DateTime<Utc>) -> Result<()> {
+        Job::finish(&self.pool, job_id, status, result_ref, error, now).await
+    }
+
+    async fn request_cancel(&self, user_id: Uuid, job_id: Uuid, now: DateTime<Utc>) -> Result<Option<Job>> {
+        Job::request_cancel(&self.pool, user_id, job_id, now).await
+    }
+
+    async fn find(&self, user_id: Uuid, job_id: Uuid) -> Result<Option<Job>> {
+        Job::find(&self.pool, user_id, job_id).await
+    }
+
+    async fn list(&self, user_id: Uuid) -> Result<Vec<Job>> {
+        Job::find_by_user(&self.pool, user_id, JOB_LIST_LIMIT).await
+    }
+
+    async fn usage(&self) -> Result<Vec<JobClassUsage>> {
+        Ok(Job::usage(&self.pool)
+            .await?
+            .into_iter()
+            .filter_map(|usage| {
+                Some(JobClassUsage {
+                    job_class: JobClass::parse(&usage.kind)?,
+                    running_jobs: usage.running_jobs as usize,
+                    users: usage.users as usize,
+                })
+            })
+            .collect())
+    }
+}
+
+// Registers long-running jobs in the jobs table, which also enforces the per-user limits, and
+// relays cancel requests to the workers running in this process
+pub struct JobService {
+    store: Arc<dyn JobStore>,
+    tokens: Mutex<HashMap<Uuid, CancellationToken>>,
+}
+
+impl JobService {
+    pub fn new(store: Arc<dyn JobStore>) -> Self {
+        JobService { store, tokens: Mutex::new(HashMap::new()) }
+    }
+
+    pub async fn register(self: &Arc<Self>, user_id: Uuid, job_class: JobClass, limit: usize) -> Result<JobHandle> {
+        let job = Job::new(user_id, job_class.as_str(), Utc::now());
+        if let Err(running_job_ids) = self.store.register(&job, limit).await? {
+            return Err(AppError::JobLimit {
+                message: format!(
+                    "At most {} {} job(s) can run at the same time; wait for one to finish",
+                    limit,
+                    job_class.as_str()
+                ),
+                running_job_ids,
+            });
+        }
+
+        let token = CancellationToken::new();
+        self.tokens.lock().unwrap().insert(job.id, token.clone());
+        Ok(JobHandle { jobs: self.clone(), job_id: job.id, token, finished: false })
+    }
+
+    // Limit taken from the user's plan
+    pub async fn register_for_plan(self: &Arc<Self>, user_id: Uuid, plan_name: &str, job_class: JobClass) -> Result<JobHandle> {
+        let limit = Subscription::plan_details(plan_name).max_concurrent_jobs.max(0) as usize;
+        self.register(user_id, job_class, limit).await
+    }
+
+    // Newest first; only the owner's
+    pub async fn list(&self, user_id: Uuid) -> Result<Vec<Job>> {
+        self.store.list(user_id).await
+    }
+
+    pub async fn find(&self, user_id: Uuid, job_id: Uuid) -> Result<Job> {
+        self.store.find(user_id, job_id).await?.ok_or_else(|| AppError::NotFound("Job not found".to_string()))
+    }
+
+    // Asks the job's worker to stop. The job stays active until the worker acknowledges, which
+    // keeps its last checkpoint as how far it got.
+    pub async fn cancel(&self, user_id: Uuid, job_id: Uuid, now: DateTime<Utc>) -> Result<Job> {
+        let Some(job) = self.store.request_cancel(user_id, job_id, now).await? else {
+            let job = self.find(user_id, job_id).await?;
+            return Err(AppError::Unprocessable(format!("The job has already {}", job.status)));
+        };
+        // Workers in other processes hear about it at their next checkpoint
+        if let Some(token) = self.tokens.lock().unwrap().get(&job_id) {
+            token.cancel();
+        }
+        Ok(job)
+    }
+
+    pub async fn usage(&self) -> Result<Vec<JobClassUsage>> {
+        self.store.usage().await
+    }
+
+    fn forget(&self, job_id: Uuid) {
+        self.tokens.lock().unwrap().remove(&job_id);
+    }
+}
+
+// Move this into the task doing the work and finish it through one of complete, fail or
+// acknowledge_cancel. Dropped unfinished, on an error path or a panic, it fails the job so it
+// stops counting against its owner's limit.
+pub struct JobHandle {
+    jobs: Arc<JobService>,
+    job_id: Uuid,
+    token: CancellationToken,
+    finished: bool,
+}
+
+impl JobHandle {
+    pub fn job_id(&self) -> Uuid {
+        self.job_id
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    // Resolves once a cancel has been requested
+    pub async fn cancelled(&self) {
+        self.token.cancelled().await
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        self.jobs.store.start(self.job_id, Utc::now()).await
+    }
+
+    // Saves how far the job got, as a percentage and whatever the worker needs to describe it
+    pub async fn checkpoint(&self, progress: f64, checkpoint: serde_json::Value) -> Result<()> {
+        let cancel_requested = self.jobs.store.checkpoint(self.job_id, progress.clamp(0.0, 100.0), &checkpoint, Utc::now()).await?;
+        if cancel_requested {
+            self.token.cancel();
+        }
+        Ok(())
+    }
+
+    pub async fn complete(mut self, result_ref: Option<String>) -> Result<()> {
+        self.finish(JOB_COMPLETED, result_ref.as_deref(), None).await
+    }
+
+    pub async fn fail(mut self, error: &str) -> Result<()> {
+        self.finish(JOB_FAILED, None, Some(error)).await
+    }
+
+    // The worker stopped because of a cancel; the last checkpoint stays as how far it got
+    pub async fn acknowledge_cancel(mut self) -> Result<()> {
+        self.finish(JOB_CANCELLED, None, None).await
+    }
+
+    async fn finish(&mut self, status: &str, result_ref: Option<&str>, error: Option<&str>) -> Result<()> {
+        self.finished = true;
+        self.jobs.forget(self.job_id);
+        self.jobs.store.finish(self.job_id, status, result_ref, error, Utc::now()).await
+    }
+}
+
+impl Drop for JobHandle {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        self.jobs.forget(self.job_id);
+        let (store, job_id) = (self.jobs.store.clone(), self.job_id);
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(async move {
+                if let Err(e) = store.finish(job_id, JOB_FAILED, None, Some("The job stopped unexpectedly"), Utc::now()).await {
+                    tracing::warn!("Failed to mark job {} as failed: {}", job_id, e);
+                }
+            });
+        }
+    }
+}
+
+// In-memory jobs table, also used by the tests of the services that run jobs
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct MemoryJobStore {
+    pub jobs: Mutex<Vec<Job>>,
+}
+
+#[cfg(test)]
+impl MemoryJobStore {
+    pub fn job(&self, job_id: Uuid) -> Job {
+        self.jobs.lock().unwrap().iter().find(|job| job.id == job_id).cloned().expect("job")
+    }
+
+    fn update<T>(&self, job_id: Uuid, apply: impl FnOnce(&mut Job) -> T) -> Option<T> {
+        self.jobs.lock().unwrap().iter_mut().find(|job| job.id == job_id && job.is_active()).map(apply)
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl JobStore for MemoryJobStore {
+    async fn register(&self, job: &Job, limit: usize) -> Result<std::result::Result<(), Vec<Uuid>>> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let running: Vec<Uuid> = jobs
+            .iter()
+            .filter(|other| other.user_id == job.user_id && other.kind == job.kind && other.is_active())
+            .map(|other| other.id)
+            .collect();
+        if running.len() >= limit {
+            return Ok(Err(running));
+        }
+        jobs.push(job.clone());
+        Ok(Ok(()))
+    }
+
+    async fn start(&self, job_id: Uuid, now: DateTime<Utc>) -> Result<()> {
+        self.update(job_id, |job| {
+            job.status = crate::models::JOB_RUNNING.to_string();
+            job.started_at = Some(now);
+        });
+        Ok(())
+    }
+
+    async fn checkpoint(&self, job_id: Uuid, progress: f64, checkpoint: &serde_json::Value, now: DateTime<Utc>) -> Result<bool> {
+        Ok(self
+            .update(job_id, |job| {
+                job.progress = progress;
+                job.checkpoint = checkpoint.clone();
+                job.updated_at = now;
+                job.cancel_requested_at.is_some()
+            })
+            .unwrap_or(false))
+    }
+
+    async fn finish(&self, job_id: Uuid, status: &str, result_ref: Option<&str>, error: Option<&str>, now: DateTime<Utc>) -> Result<()> {
+        self.update(job_id, |job| {
+            if status == JOB_COMPLETED {
+                job.progress = 100.0;
+            }
+            job.status = status.to_string();
+            job.result_ref = result_ref.map(str::to_string);
+            job.error = error.map(str::to_string);
+            job.finished_at = Some(now);
+        });
+        Ok(())
+    }
+
+    async fn request_cancel(&self, user_id: Uuid, job_id: Uuid, now: DateTime<Utc>) -> Result<Option<Job>> {
+        Ok(self
+            .update(job_id, |job| {
+                (job.user_id == user_id).then(|| {
+                    job.cancel_requested_at.get_or_insert(now);
+                    job.clone()
+                })
+            })
+            .flatten())
+    }
+
+    async fn find(&self, user_id: Uuid, job_id: Uuid) -> Result<Option<Job>> {
+        Ok(self.jobs.lock().unwrap().iter().find(|job| job.id == job_id && job.user_id == user_id).cloned())
+    }
+
+    async fn list(&self, user_id: Uuid) -> Result<Vec<Job>> {
+        Ok(self.jobs.lock().unwrap().iter().rev().filter(|job| job.user_id == user_id).cloned().collect())
+    }
+
+    async fn usage(&self) -> Result<Vec<JobClassUsage>> {
+        let mut usage: std::collections::BTreeMap<JobClass, (usize, std::collections::HashSet<Uuid>)> = Default::default();
+        for job in self.jobs.lock().unwrap().iter().filter(|job| job.is_active()) {
+            let entry = usage.entry(JobClass::parse(&job.kind).unwrap()).or_default();
+            entry.0 += 1;
+            entry.1.insert(job.user_id);
+        }
+        Ok(usage
+            .into_iter()
+            .map(|(job_class, (running_jobs, users))| JobClassUsage { job_class, running_jobs, users: users.len() })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{http::StatusCode, response::IntoResponse};
+
+    fn service() -> (Arc<MemoryJobStore>, Arc<JobService>) {
+        let store = Arc::new(MemoryJobStore::default());
+        (store.clone(), Arc::new(JobService::new(store)))
+    }
+
+    // Lets the tasks spawned by dropped handles run
+    async fn settle() {
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_saturated_cap_returns_429_with_running_jobs() {
+        let (_, jobs) = service();
+        let user_id = Uuid::new_v4();
+
+        let first = jobs.register_for_plan(user_id, "free", JobClass::Backtest).await.unwrap();
+        // Other classes and other users have their own slots
+        let _export = jobs.register_for_plan(user_id, "free", JobClass::Export).await.unwrap();
+        let _other_user = jobs.register_for_plan(Uuid::new_v4(), "free", JobClass::Backtest).await.unwrap();
+
+        let err = jobs.register_for_plan(user_id, "free", JobClass::Backtest).await.err().expect("over the cap");
+        let response = err.into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["status"], 429);
+        assert_eq!(body["running_job_ids"], serde_json::json!([first.job_id()]));
+        assert!(body["error"].as_str().unwrap().contains("backtest"));
+    }
+
+    #[tokio::test]
+    async fn test_plan_sets_the_cap() {
+        let (_, jobs) = service();
+        let user_id = Uuid::new_v4();
+
+        let mut handles = Vec::new();
+        for _ in 0..3 {
+            handles.push(jobs.register_for_plan(user_id, "pro", JobClass::Export).await.unwrap());
+        }
+        assert!(jobs.register_for_plan(user_id, "pro", JobClass::Export).await.is_err());
+
+        assert_eq!(
+            jobs.usage().await.unwrap(),
+            vec![JobClassUsage { job_class: JobClass::Export, running_jobs: 3, users: 1 }]
+        );
+        for handle in handles {
+            handle.complete(None).await.unwrap();
+        }
+        assert!(jobs.usage().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_failed_or_panicking_job_releases_its_slot() {
+        let (store, jobs) = service();
+        let user_id = Uuid::new_v4();
+
+        let handle = jobs.register_for_plan(user_id, "free", JobClass::Import).await.unwrap();
+        let failed_id = handle.job_id();
+        let failed = tokio::spawn(async move {
+            let _handle = handle;
+            Err::<(), _>(AppError::Validation("bad file".to_string()))
+        });
+        assert!(failed.await.unwrap().is_err());
+        settle().await;
+        assert_eq!(store.job(failed_id).status, JOB_FAILED);
+
+        let handle = jobs.register_for_plan(user_id, "free", JobClass::Import).await.unwrap();
+        let panicked = tokio::spawn(async move {
+            let _handle = handle;
+            panic!("worker crashed");
+        });
+        assert!(panicked.await.is_err());
+        settle().await;
+
+        assert!(jobs.register_for_plan(user_id, "free", JobClass::Import).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_mid_flight_job_keeps_its_checkpoint() {
+        let (store, jobs) = service();
+        let user_id = Uuid::new_v4();
+        let handle = jobs.register_for_plan(user_id, "pro", JobClass::Import).await.unwrap();
+        let job_id = handle.job_id();
+
+        // Stands in for an import: a row per tick, checkpointed, until cancelled
+        let (tick, mut ticks) = tokio::sync::mpsc::channel::<()>(1);
+        let (imported, mut checkpoints) = tokio::sync::mpsc::channel::<u64>(1);
+        let worker = tokio::spawn(async move {
+            handle.start().await.unwrap();
+            let mut rows = 0;
+            loop {
+                tokio::select! {
+                    _ = handle.cancelled() => break,
+                    Some(()) = ticks.recv() => {
+                        rows += 1;
+                        handle.checkpoint(rows as f64, serde_json::json!({ "rows_imported": rows })).await.unwrap();
+                        imported.send(rows).await.unwrap();
+                    }
+                }
+            }
+            handle.acknowledge_cancel().await.unwrap();
+            rows
+        });
+
+        for _ in 0..3 {
+            tick.send(()).await.unwrap();
+            checkpoints.recv().await.unwrap();
+        }
+        // Requested but not yet acknowledged: still running
+        let requested = jobs.cancel(user_id, job_id, Utc::now()).await.unwrap();
+        assert!(requested.cancel_requested_at.is_some());
+        assert_eq!(requested.status, crate::models::JOB_RUNNING);
+
+        assert_eq!(worker.await.unwrap(), 3);
+        let job = jobs.find(user_id, job_id).await.unwrap();
+        assert_eq!(job.status, JOB_CANCELLED);
+        assert_eq!(job.progress, 3.0);
+        assert_eq!(job.checkpoint, serde_json::json!({ "rows_imported": 3 }));
+        assert!(job.finished_at.is_some());
+        assert_eq!(store.job(job_id).error, None);
+
+        // Finished jobs cannot be cancelled, and other users cannot see the job at all
+        assert!(matches!(jobs.cancel(user_id, job_id, Utc::now()).await, Err(AppError::Unprocessable(_))));
+        assert!(matches!(jobs.cancel(Uuid::new_v4(), job_id, Utc::now()).await, Err(AppError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_cancel_requested_elsewhere_reaches_the_worker_at_its_checkpoint() {
+        let (store, jobs) = service();
+        let user_id = Uuid::new_v4();
+        let handle = jobs.register_for_plan(user_id, "pro", JobClass::Backtest).await.unwrap();
+
+        // Another instance flagged the job; this process holds no token for the request
+        store.request_cancel(user_id, handle.job_id(), Utc::now()).await.unwrap();
+        assert!(!handle.is_cancelled());
+        handle.checkpoint(50.0, serde_json::json!({})).await.unwrap();
+        assert!(handle.is_cancelled());
+    }
+}
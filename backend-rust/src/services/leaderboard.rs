@@ -0,0 +1,335 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::{
+    errors::{AppError, Result},
+    models::{Leaderboard, LeaderboardTrade, RankedRobot},
+};
+
+pub const DEFAULT_LEADERBOARD_MIN_TRADES: i64 = 10;
+pub const LEADERBOARD_PUBLIC_SIZE: i64 = 20;
+pub const LEADERBOARD_CACHE_CONTROL: &str = "public, max-age=300, stale-while-revalidate=3600";
+// Hourly, so a week is published soon after it ends even if an instance was down on Monday
+pub const LEADERBOARD_CHECK_SECONDS: u64 = 60 * 60;
+
+// Keeps a robot with a handful of identical winners from ranking on a near-zero deviation
+const MIN_DEVIATION_PCT: f64 = 0.1;
+const LABEL_ATTEMPTS: usize = 20;
+
+#[async_trait]
+pub trait LeaderboardStore: Send + Sync {
+    async fn has_week(&self, week_start: NaiveDate) -> Result<bool>;
+    async fn closed_trades(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<LeaderboardTrade>>;
+    async fn labels(&self, robot_ids: &[Uuid]) -> Result<HashMap<Uuid, i32>>;
+    // False when the number is already taken
+    async fn assign_label(&self, robot_id: Uuid, label_number: i32) -> Result<bool>;
+    async fn save_week(&self, week_start: NaiveDate, robots: &[RankedRobot]) -> Result<()>;
+}
+
+pub struct PgLeaderboardStore {
+    pool: PgPool,
+}
+
+impl PgLeaderboardStore {
+    pub fn new(pool: PgPool) -> Self {
+        PgLeaderboardStore { pool }
+    }
+}
+
+#[async_trait]
+impl LeaderboardStore for PgLeaderboardStore {
+    async fn has_week(&self, week_start: NaiveDate) -> Result<bool> {
+        Leaderboard::has_week(&self.pool, week_start).await
+    }
+
+    async fn closed_trades(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<LeaderboardTrade>> {
+        Leaderboard::closed_trades(&self.pool, from, to).await
+    }
+
+    async fn labels(&self, robot_ids: &[Uuid]) -> Result<HashMap<Uuid, i32>> {
+        Ok(Leaderboard::labels(&self.pool, robot_ids).await?.into_iter().collect())
+    }
+
+    async fn assign_label(&self, robot_id: Uuid, label_number: i32) -> Result<bool> {
+        Leaderboard::assign_label(&self.pool, robot_id, label_number).await
+    }
+
+    async fn save_week(&self, week_start: NaiveDate, robots: &[RankedRobot]) -> Result<()> {
+        Leaderboard::save_week(&self.pool, week_start, robots).await
+    }
+}
+
+// A robot's week before it is ranked and labelled
+#[derive(Debug, Clone, PartialEq)]
+pub struct RobotWeek {
+    pub robot_id: Uuid,
+    pub user_id: Uuid,
+    pub strategy: String,
+    pub return_pct: f64,
+    pub win_rate: f64,
+    pub trade_count: i32,
+    pub score: f64,
+}
+
+// Weekly ranking of opted-in users' robots by risk-adjusted return: the mean percentage
+// return per trade over its standard deviation. Only live trades count.
+pub struct LeaderboardService;
+
+impl LeaderboardService {
+    // The Monday starting the last full week before `now`
+    pub fn last_complete_week(now: DateTime<Utc>) -> NaiveDate {
+        let today = now.date_naive();
+        today - Duration::days(today.weekday().num_days_from_monday() as i64 + 7)
+    }
+
+    fn week_bounds(week_start: NaiveDate) -> (DateTime<Utc>, DateTime<Utc>) {
+        let from = week_start.and_hms_opt(0, 0, 0).expect("midnight is a valid time").and_utc();
+        (from, from + Duration::weeks(1))
+    }
+
+    pub fn label(label_number: i32) -> String {
+        format!("Trader #{}", label_number)
+    }
+
+    fn trade_return_pct(trade: &LeaderboardTrade) -> f64 {
+        let pct = (trade.exit_price - trade.entry_price) / trade.entry_price * 100.0;
+        if trade.trade_type.eq_ignore_ascii_case("sell") {
+            -pct
+        } else {
+            pct
+        }
+    }
+
+    // Best first; robots of users who have not opted in or with fewer than `min_trades` are left out
+    pub fn rank(trades: &[LeaderboardTrade], min_trades: i64) -> Vec<RobotWeek> {
+        let mut by_robot: HashMap<Uuid, (&LeaderboardTrade, Vec<f64>)> = HashMap::new();
+        for trade in trades.iter().filter(|t| t.shared) {
+            by_robot
+                .entry(trade.robot_id)
+                .or_insert_with(|| (trade, Vec::new()))
+                .1
+                .push(Self::trade_return_pct(trade));
+        }
+
+        let mut robots: Vec<RobotWeek> = by_robot
+            .into_values()
+            .filter(|(_, returns)| returns.len() as i64 >= min_trades.max(1))
+            .map(|(first, returns)| {
+                let n = returns.len() as f64;
+                let mean = returns.iter().sum::<f64>() / n;
+                let variance = if returns.len() > 1 {
+                    returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (n - 1.0)
+                } else {
+                    0.0
+                };
+                let wins = returns.iter().filter(|r| **r > 0.0).count() as f64;
+                RobotWeek {
+                    robot_id: first.robot_id,
+                    user_id: first.user_id,
+                    strategy: first.strategy.clone(),
+                    return_pct: round2(returns.iter().sum()),
+                    win_rate: round2(wins / n * 100.0),
+                    trade_count: returns.len() as i32,
+                    score: mean / variance.sqrt().max(MIN_DEVIATION_PCT),
+                }
+            })
+            .collect();
+
+        robots.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then(b.return_pct.total_cmp(&a.return_pct))
+                .then(a.robot_id.cmp(&b.robot_id))
+        });
+        robots
+    }
+
+    // A robot keeps the label it was first given, so it is recognisable week to week
+    async fn label_numbers(store: &dyn LeaderboardStore, robot_ids: &[Uuid]) -> Result<HashMap<Uuid, i32>> {
+        let mut labels = store.labels(robot_ids).await?;
+
+        for robot_id in robot_ids.iter().filter(|id| !labels.contains_key(id)) {
+            let mut assigned = false;
+            for _ in 0..LABEL_ATTEMPTS {
+                let number = (Uuid::new_v4().as_u128() % 90_000) as i32 + 10_000;
+                if store.assign_label(*robot_id, number).await? {
+                    assigned = true;
+                    break;
+                }
+            }
+            if !assigned {
+                return Err(AppError::Internal(anyhow::anyhow!("Could not find a free leaderboard label")));
+            }
+        }
+
+        if labels.len() < robot_ids.len() {
+            labels = store.labels(robot_ids).await?;
+        }
+        Ok(labels)
+    }
+
+    // Scheduler job; computes the last full week once and returns how many robots were ranked
+    pub async fn publish(store: &dyn LeaderboardStore, now: DateTime<Utc>, min_trades: i64) -> Result<Option<usize>> {
+        let week_start = Self::last_complete_week(now);
+        if store.has_week(week_start).await? {
+            return Ok(None);
+        }
+
+        let (from, to) = Self::week_bounds(week_start);
+        let robots = Self::rank(&store.closed_trades(from, to).await?, min_trades);
+        let robot_ids: Vec<Uuid> = robots.iter().map(|r| r.robot_id).collect();
+        let labels = Self::label_numbers(store, &robot_ids).await?;
+
+        let ranked: Vec<RankedRobot> = robots
+            .into_iter()
+            .enumerate()
+            .map(|(i, robot)| RankedRobot {
+                label: Self::label(labels[&robot.robot_id]),
+                robot_id: robot.robot_id,
+                user_id: robot.user_id,
+                rank: i as i32 + 1,
+                strategy: robot.strategy,
+                return_pct: robot.return_pct,
+                win_rate: robot.win_rate,
+                trade_count: robot.trade_count,
+                score: robot.score,
+            })
+            .collect();
+
+        // An empty week is still saved, so it is not recomputed every hour
+        store.save_week(week_start, &ranked).await?;
+        tracing::info!("Leaderboard for the week of {} ranks {} robots", week_start, ranked.len());
+        Ok(Some(ranked.len()))
+    }
+}
+
+fn round2(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct FakeStore {
+        trades: Mutex<Vec<(DateTime<Utc>, LeaderboardTrade)>>,
+        labels: Mutex<HashMap<Uuid, i32>>,
+        weeks: Mutex<HashMap<NaiveDate, Vec<RankedRobot>>>,
+    }
+
+    impl FakeStore {
+        // `count` trades of the robot closing at `at`, each returning `pct` percent on a buy
+        fn trade(&self, at: DateTime<Utc>, robot_id: Uuid, shared: bool, count: usize, pcts: &[f64]) {
+            let mut trades = self.trades.lock().unwrap();
+            for i in 0..count {
+                trades.push((
+                    at,
+                    LeaderboardTrade {
+                        robot_id,
+                        user_id: robot_id,
+                        shared,
+                        strategy: "trend_following".to_string(),
+                        trade_type: "buy".to_string(),
+                        entry_price: 100.0,
+                        exit_price: 100.0 + pcts[i % pcts.len()],
+                    },
+                ));
+            }
+        }
+
+        fn week(&self, week_start: NaiveDate) -> Vec<RankedRobot> {
+            self.weeks.lock().unwrap()[&week_start].clone()
+        }
+    }
+
+    #[async_trait]
+    impl LeaderboardStore for FakeStore {
+        async fn has_week(&self, week_start: NaiveDate) -> Result<bool> {
+            Ok(self.weeks.lock().unwrap().contains_key(&week_start))
+        }
+
+        async fn closed_trades(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<LeaderboardTrade>> {
+            Ok(self
+                .trades
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(at, _)| *at >= from && *at < to)
+                .map(|(_, t)| t.clone())
+                .collect())
+        }
+
+        async fn labels(&self, robot_ids: &[Uuid]) -> Result<HashMap<Uuid, i32>> {
+            let labels = self.labels.lock().unwrap();
+            Ok(robot_ids.iter().filter_map(|id| labels.get(id).map(|n| (*id, *n))).collect())
+        }
+
+        async fn assign_label(&self, robot_id: Uuid, label_number: i32) -> Result<bool> {
+            let mut labels = self.labels.lock().unwrap();
+            if labels.contains_key(&robot_id) || labels.values().any(|n| *n == label_number) {
+                return Ok(false);
+            }
+            labels.insert(robot_id, label_number);
+            Ok(true)
+        }
+
+        async fn save_week(&self, week_start: NaiveDate, robots: &[RankedRobot]) -> Result<()> {
+            self.weeks.lock().unwrap().insert(week_start, robots.to_vec());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_opted_out_users_and_thin_robots_are_left_out() {
+        let store = FakeStore::default();
+        let tuesday = Utc.with_ymd_and_hms(2024, 3, 5, 12, 0, 0).unwrap();
+        let (steady, volatile, opted_out, thin) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        store.trade(tuesday, steady, true, 10, &[0.5, 0.6]);
+        store.trade(tuesday, volatile, true, 10, &[4.0, -2.0]);
+        store.trade(tuesday, opted_out, false, 20, &[1.0]);
+        store.trade(tuesday, thin, true, 9, &[1.0]);
+
+        let now = Utc.with_ymd_and_hms(2024, 3, 11, 1, 0, 0).unwrap();
+        let ranked = LeaderboardService::publish(&store, now, 10).await.unwrap();
+        assert_eq!(ranked, Some(2));
+
+        // The steady robot returns less in total but ranks first on a risk-adjusted basis
+        let week = store.week(NaiveDate::from_ymd_opt(2024, 3, 4).unwrap());
+        let order: Vec<(Uuid, i32)> = week.iter().map(|r| (r.robot_id, r.rank)).collect();
+        assert_eq!(order, vec![(steady, 1), (volatile, 2)]);
+        assert_eq!((week[1].return_pct, week[1].win_rate, week[1].trade_count), (10.0, 50.0, 10));
+
+        // A later run in the same week does not recompute it
+        assert_eq!(LeaderboardService::publish(&store, now + Duration::hours(1), 10).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_labels_are_stable_across_weeks() {
+        let store = FakeStore::default();
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        let week1 = Utc.with_ymd_and_hms(2024, 3, 5, 12, 0, 0).unwrap();
+        let week2 = week1 + Duration::weeks(1);
+        store.trade(week1, a, true, 3, &[1.0]);
+        store.trade(week1, b, true, 3, &[2.0, 1.0]);
+        store.trade(week2, a, true, 3, &[2.0, 1.0]);
+        store.trade(week2, b, true, 3, &[0.5]);
+
+        LeaderboardService::publish(&store, week1 + Duration::weeks(1), 3).await.unwrap();
+        LeaderboardService::publish(&store, week2 + Duration::weeks(1), 3).await.unwrap();
+
+        let label_of = |week: NaiveDate, robot: Uuid| {
+            store.week(week).into_iter().find(|r| r.robot_id == robot).unwrap().label
+        };
+        let (first, second) = (NaiveDate::from_ymd_opt(2024, 3, 4).unwrap(), NaiveDate::from_ymd_opt(2024, 3, 11).unwrap());
+        assert_eq!(label_of(first, a), label_of(second, a));
+        assert_eq!(label_of(first, b), label_of(second, b));
+        assert_ne!(label_of(first, a), label_of(first, b));
+        assert!(label_of(first, a).starts_with("Trader #"));
+    }
+}
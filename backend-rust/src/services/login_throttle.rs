@@ -0,0 +1,344 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::{
+    errors::{AppError, Result},
+    models::User,
+    services::NotificationService,
+};
+
+pub const DEFAULT_LOGIN_WINDOW_SECONDS: u64 = 900;
+pub const DEFAULT_MAX_FAILURES_PER_EMAIL: u64 = 5;
+pub const DEFAULT_MAX_FAILURES_PER_IP: u64 = 20;
+pub const DEFAULT_LOCKOUT_THRESHOLD: u64 = 10;
+pub const DEFAULT_LOCKOUT_SECONDS: u64 = 1800;
+// Failures towards a lockout are forgotten a day after the first one, or on a successful login
+const LOCKOUT_COUNT_SECONDS: u64 = 86_400;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct LoginThrottlePolicy {
+    // Failed logins per email, and per source IP, within the window before further attempts get a 429
+    pub window_seconds: u64,
+    pub max_failures_per_email: u64,
+    pub max_failures_per_ip: u64,
+    // Failed logins for one email that lock it, whatever the password, for lockout_seconds
+    pub lockout_threshold: u64,
+    pub lockout_seconds: u64,
+}
+
+impl Default for LoginThrottlePolicy {
+    fn default() -> Self {
+        LoginThrottlePolicy {
+            window_seconds: DEFAULT_LOGIN_WINDOW_SECONDS,
+            max_failures_per_email: DEFAULT_MAX_FAILURES_PER_EMAIL,
+            max_failures_per_ip: DEFAULT_MAX_FAILURES_PER_IP,
+            lockout_threshold: DEFAULT_LOCKOUT_THRESHOLD,
+            lockout_seconds: DEFAULT_LOCKOUT_SECONDS,
+        }
+    }
+}
+
+// Expiring counters shared by every instance
+#[async_trait]
+pub trait LoginAttempts: Send + Sync {
+    // Adds one to `key`, starting a count that expires in `ttl_seconds` when there is none; returns the count
+    async fn increment(&self, key: &str, ttl_seconds: u64) -> Result<u64>;
+    // The count under `key` and the seconds until it expires, while it is set
+    async fn get(&self, key: &str) -> Result<Option<(u64, u64)>>;
+    async fn set(&self, key: &str, ttl_seconds: u64) -> Result<()>;
+    async fn clear(&self, keys: &[String]) -> Result<()>;
+}
+
+// Single-instance counters; they are lost when the process restarts
+#[derive(Default)]
+pub struct MemoryLoginAttempts {
+    counts: Mutex<HashMap<String, (u64, DateTime<Utc>)>>,
+}
+
+impl MemoryLoginAttempts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl LoginAttempts for MemoryLoginAttempts {
+    async fn increment(&self, key: &str, ttl_seconds: u64) -> Result<u64> {
+        let now = Utc::now();
+        let mut counts = self.counts.lock().unwrap();
+        counts.retain(|_, (_, until)| *until > now);
+        let entry = counts.entry(key.to_string()).or_insert((0, now + Duration::seconds(ttl_seconds as i64)));
+        entry.0 += 1;
+        Ok(entry.0)
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<(u64, u64)>> {
+        let now = Utc::now();
+        Ok(self
+            .counts
+            .lock()
+            .unwrap()
+            .get(key)
+            .filter(|(_, until)| *until > now)
+            .map(|(count, until)| (*count, (*until - now).num_seconds().max(1) as u64)))
+    }
+
+    async fn set(&self, key: &str, ttl_seconds: u64) -> Result<()> {
+        let until = Utc::now() + Duration::seconds(ttl_seconds as i64);
+        self.counts.lock().unwrap().insert(key.to_string(), (1, until));
+        Ok(())
+    }
+
+    async fn clear(&self, keys: &[String]) -> Result<()> {
+        let mut counts = self.counts.lock().unwrap();
+        for key in keys {
+            counts.remove(key);
+        }
+        Ok(())
+    }
+}
+
+pub struct RedisLoginAttempts {
+    client: redis::Client,
+}
+
+impl RedisLoginAttempts {
+    pub fn new(redis_url: &str) -> Result<Self> {
+        let client = redis::Client::open(redis_url)?;
+        Ok(RedisLoginAttempts { client })
+    }
+}
+
+#[async_trait]
+impl LoginAttempts for RedisLoginAttempts {
+    async fn increment(&self, key: &str, ttl_seconds: u64) -> Result<u64> {
+        let mut conn = self.client.get_multiplexed_tokio_connection().await?;
+        let count: u64 = redis::cmd("INCR").arg(key).query_async(&mut conn).await?;
+        if count == 1 {
+            redis::cmd("EXPIRE").arg(key).arg(ttl_seconds).query_async::<_, ()>(&mut conn).await?;
+        }
+        Ok(count)
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<(u64, u64)>> {
+        let mut conn = self.client.get_multiplexed_tokio_connection().await?;
+        let (count, ttl): (Option<u64>, i64) = redis::pipe().get(key).ttl(key).query_async(&mut conn).await?;
+        Ok(count.map(|count| (count, ttl.max(1) as u64)))
+    }
+
+    async fn set(&self, key: &str, ttl_seconds: u64) -> Result<()> {
+        let mut conn = self.client.get_multiplexed_tokio_connection().await?;
+        redis::cmd("SET").arg(key).arg(1).arg("EX").arg(ttl_seconds).query_async::<_, ()>(&mut conn).await?;
+        Ok(())
+    }
+
+    async fn clear(&self, keys: &[String]) -> Result<()> {
+        if keys.is_empty() {
+            return Ok(());
+        }
+        let mut conn = self.client.get_multiplexed_tokio_connection().await?;
+        redis::cmd("DEL").arg(keys).query_async::<_, ()>(&mut conn).await?;
+        Ok(())
+    }
+}
+
+// Failed logins per email and per source IP. Unknown emails are counted like real ones, so the
+// answers do not tell which accounts exist. When the counters cannot be reached logins go
+// through unthrottled rather than locking everyone out.
+pub struct LoginThrottle {
+    attempts: Arc<dyn LoginAttempts>,
+    policy: LoginThrottlePolicy,
+    notifications: Arc<NotificationService>,
+}
+
+impl LoginThrottle {
+    pub fn new(attempts: Arc<dyn LoginAttempts>, policy: LoginThrottlePolicy, notifications: Arc<NotificationService>) -> Self {
+        LoginThrottle { attempts, policy, notifications }
+    }
+
+    fn email_key(email: &str) -> String {
+        format!("login_failures:email:{}", email.trim().to_lowercase())
+    }
+
+    fn ip_key(ip: &str) -> String {
+        format!("login_failures:ip:{}", ip)
+    }
+
+    fn lockout_count_key(email: &str) -> String {
+        format!("login_lockout_failures:{}", email.trim().to_lowercase())
+    }
+
+    fn lock_key(email: &str) -> String {
+        format!("login_lock:{}", email.trim().to_lowercase())
+    }
+
+    // Refuses the attempt before the password is looked at
+    pub async fn check(&self, email: &str, ip: Option<&str>) -> Result<()> {
+        match self.refusal(email, ip).await {
+            Ok(None) => Ok(()),
+            Ok(Some(refusal)) => Err(refusal),
+            Err(e) => {
+                tracing::warn!("Login throttle unavailable, not checking attempts: {}", e);
+                Ok(())
+            }
+        }
+    }
+
+    async fn refusal(&self, email: &str, ip: Option<&str>) -> Result<Option<AppError>> {
+        if let Some((_, seconds)) = self.attempts.get(&Self::lock_key(email)).await? {
+            return Ok(Some(AppError::TooManyAttempts {
+                message: format!(
+                    "This account is locked after repeated failed logins; try again in {} minute(s)",
+                    seconds.div_ceil(60)
+                ),
+                retry_after_seconds: seconds,
+            }));
+        }
+        let mut over = vec![(Self::email_key(email), self.policy.max_failures_per_email)];
+        if let Some(ip) = ip {
+            over.push((Self::ip_key(ip), self.policy.max_failures_per_ip));
+        }
+        for (key, limit) in over {
+            if let Some((count, seconds)) = self.attempts.get(&key).await? {
+                if count >= limit {
+                    return Ok(Some(AppError::TooManyAttempts {
+                        message: format!("Too many failed logins; try again in {} minute(s)", seconds.div_ceil(60)),
+                        retry_after_seconds: seconds,
+                    }));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    // Counts a wrong email or password. Reaching the lockout threshold locks the email and, when
+    // it belongs to an account, tells its owner.
+    pub async fn record_failure(&self, email: &str, ip: Option<&str>, user: Option<&User>) {
+        if let Err(e) = self.count_failure(email, ip, user).await {
+            tracing::warn!("Could not record a failed login: {}", e);
+        }
+    }
+
+    async fn count_failure(&self, email: &str, ip: Option<&str>, user: Option<&User>) -> Result<()> {
+        self.attempts.increment(&Self::email_key(email), self.policy.window_seconds).await?;
+        if let Some(ip) = ip {
+            self.attempts.increment(&Self::ip_key(ip), self.policy.window_seconds).await?;
+        }
+        let failures = self.attempts.increment(&Self::lockout_count_key(email), LOCKOUT_COUNT_SECONDS).await?;
+        if failures < self.policy.lockout_threshold {
+            return Ok(());
+        }
+
+        self.attempts.set(&Self::lock_key(email), self.policy.lockout_seconds).await?;
+        self.attempts.clear(&[Self::lockout_count_key(email)]).await?;
+        tracing::warn!("Login locked for {} after {} failed attempts", email, failures);
+        if let Some(user) = user {
+            let minutes = self.policy.lockout_seconds.div_ceil(60);
+            if let Err(e) = self.notifications.send_account_locked(&user.email, failures, minutes).await {
+                tracing::warn!("Could not email user {} about their locked account: {}", user.id, e);
+            }
+        }
+        Ok(())
+    }
+
+    // A successful login starts the email's counts over. The IP's count is left to run out, or one
+    // could sign in to their own account now and then to keep guessing others from one address.
+    pub async fn reset(&self, email: &str) {
+        let keys = [Self::email_key(email), Self::lockout_count_key(email)];
+        if let Err(e) = self.attempts.clear(&keys).await {
+            tracing::warn!("Could not reset failed login counts: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn throttle(policy: LoginThrottlePolicy) -> LoginThrottle {
+        let notifications = Arc::new(NotificationService::new(None, None, None));
+        LoginThrottle::new(Arc::new(MemoryLoginAttempts::new()), policy, notifications)
+    }
+
+    fn policy() -> LoginThrottlePolicy {
+        LoginThrottlePolicy { max_failures_per_email: 3, max_failures_per_ip: 5, lockout_threshold: 4, ..Default::default() }
+    }
+
+    fn retry_after(result: Result<()>) -> u64 {
+        match result {
+            Err(AppError::TooManyAttempts { retry_after_seconds, .. }) => retry_after_seconds,
+            other => panic!("expected a 429, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failures_per_email_are_throttled_within_the_window() {
+        let throttle = throttle(policy());
+        for _ in 0..3 {
+            throttle.check("Trader@Example.com", Some("203.0.113.7")).await.unwrap();
+            throttle.record_failure("trader@example.com", Some("203.0.113.7"), None).await;
+        }
+
+        let seconds = retry_after(throttle.check(" TRADER@example.com", Some("198.51.100.2")).await);
+        assert!(seconds > 0 && seconds <= DEFAULT_LOGIN_WINDOW_SECONDS);
+        // Another account from the same address is still allowed
+        throttle.check("other@example.com", Some("203.0.113.7")).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_failures_per_ip_are_throttled_across_emails() {
+        let throttle = throttle(policy());
+        for n in 0..5 {
+            throttle.record_failure(&format!("user{}@example.com", n), Some("203.0.113.7"), None).await;
+        }
+
+        retry_after(throttle.check("fresh@example.com", Some("203.0.113.7")).await);
+        throttle.check("fresh@example.com", Some("198.51.100.2")).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_threshold_locks_the_account() {
+        let throttle = throttle(LoginThrottlePolicy { max_failures_per_email: 100, ..policy() });
+        for n in 0..4 {
+            throttle.record_failure("trader@example.com", Some(&format!("10.0.0.{}", n)), None).await;
+        }
+
+        let seconds = retry_after(throttle.check("trader@example.com", None).await);
+        assert!(seconds > DEFAULT_LOGIN_WINDOW_SECONDS && seconds <= DEFAULT_LOCKOUT_SECONDS);
+        // A successful login elsewhere does not lift the lock
+        throttle.reset("trader@example.com").await;
+        retry_after(throttle.check("trader@example.com", None).await);
+    }
+
+    #[tokio::test]
+    async fn test_success_resets_the_counts() {
+        let throttle = throttle(policy());
+        for _ in 0..3 {
+            throttle.record_failure("trader@example.com", Some("203.0.113.7"), None).await;
+        }
+        throttle.reset("trader@example.com").await;
+
+        throttle.check("trader@example.com", Some("203.0.113.7")).await.unwrap();
+        // Two more failures stay under the lockout threshold: the success started that count over too
+        for _ in 0..2 {
+            throttle.record_failure("trader@example.com", Some("198.51.100.2"), None).await;
+        }
+        throttle.check("trader@example.com", Some("198.51.100.2")).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_success_leaves_the_ip_count_running() {
+        let throttle = throttle(policy());
+        for n in 0..4 {
+            throttle.record_failure(&format!("victim{}@example.com", n), Some("203.0.113.7"), None).await;
+        }
+        // Signing in to one's own account does not buy more guesses from that address
+        throttle.reset("attacker@example.com").await;
+        throttle.record_failure("victim9@example.com", Some("203.0.113.7"), None).await;
+
+        retry_after(throttle.check("attacker@example.com", Some("203.0.113.7")).await);
+    }
+}
@@ -0,0 +1,317 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, RwLock};
+use uuid::Uuid;
+
+use crate::{
+    errors::Result,
+    models::{TradingRobot, Watchlist},
+    services::{quote_service::PlatformQuoteCache, websocket_manager::WebSocketMessage, Mt5Service, WebSocketManager},
+};
+
+pub const MARKET_DATA_TICK_SECONDS: u64 = 5;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Quote {
+    pub symbol: String,
+    pub bid: f64,
+    pub ask: f64,
+    pub time: DateTime<Utc>,
+}
+
+#[async_trait]
+pub trait QuoteFeed: Send + Sync {
+    // Symbols the active robots trade; their quotes are broadcast to everyone
+    async fn robot_symbols(&self) -> Result<Vec<String>>;
+    async fn quotes(&self, symbols: &[String]) -> Result<Vec<Quote>>;
+}
+
+#[async_trait]
+pub trait WatchlistStore: Send + Sync {
+    async fn symbols(&self, user_id: Uuid) -> Result<Vec<String>>;
+}
+
+// Told when a user's first WebSocket connection opens and their last one closes
+#[async_trait]
+pub trait PresenceListener: Send + Sync {
+    async fn user_connected(&self, user_id: Uuid);
+    async fn user_disconnected(&self, user_id: Uuid);
+}
+
+pub struct PgWatchlistStore {
+    pool: PgPool,
+}
+
+impl PgWatchlistStore {
+    pub fn new(pool: PgPool) -> Self {
+        PgWatchlistStore { pool }
+    }
+}
+
+#[async_trait]
+impl WatchlistStore for PgWatchlistStore {
+    async fn symbols(&self, user_id: Uuid) -> Result<Vec<String>> {
+        Watchlist::find_symbols(&self.pool, user_id).await
+    }
+}
+
+// Quotes ride on whichever broker session is open; with none there is nothing to stream
+pub struct Mt5QuoteFeed {
+    pool: PgPool,
+    mt5: Arc<Mt5Service>,
+}
+
+impl Mt5QuoteFeed {
+    pub fn new(pool: PgPool, mt5: Arc<Mt5Service>) -> Self {
+        Mt5QuoteFeed { pool, mt5 }
+    }
+}
+
+#[async_trait]
+impl QuoteFeed for Mt5QuoteFeed {
+    async fn robot_symbols(&self) -> Result<Vec<String>> {
+        TradingRobot::active_symbols(&self.pool).await
+    }
+
+    async fn quotes(&self, symbols: &[String]) -> Result<Vec<Quote>> {
+        let Some(connection_id) = self.mt5.any_connected() else {
+            return Ok(Vec::new());
+        };
+
+        let mut quotes = Vec::with_capacity(symbols.len());
+        for symbol in symbols {
+            let data = self.mt5.get_market_data(&connection_id, symbol).await?;
+            quotes.push(Quote { symbol: data.symbol, bid: data.bid, ask: data.ask, time: data.time });
+        }
+        Ok(quotes)
+    }
+}
+
+struct Watcher {
+    connections: usize,
+    symbols: Vec<String>,
+}
+
+#[derive(Default)]
+struct StreamerState {
+    robot_symbols: BTreeSet<String>,
+    watchers: HashMap<Uuid, Watcher>,
+}
+
+// Decides which symbols are polled: robot symbols always, watchlist symbols only while
+// their owner has a WebSocket open.
+pub struct MarketDataStreamer {
+    watchlists: Arc<dyn WatchlistStore>,
+    state: RwLock<StreamerState>,
+    quote_cache: Option<Arc<PlatformQuoteCache>>,
+}
+
+impl MarketDataStreamer {
+    pub fn new(watchlists: Arc<dyn WatchlistStore>) -> Self {
+        MarketDataStreamer { watchlists, state: RwLock::new(StreamerState::default()), quote_cache: None }
+    }
+
+    // Every polled quote is kept there as a fallback for users whose broker is offline
+    pub fn with_quote_cache(mut self, quote_cache: Arc<PlatformQuoteCache>) -> Self {
+        self.quote_cache = Some(quote_cache);
+        self
+    }
+
+    pub fn needed_symbols(&self) -> Vec<String> {
+        let state = self.state.read().unwrap();
+        let mut needed = state.robot_symbols.clone();
+        for watcher in state.watchers.values() {
+            needed.extend(watcher.symbols.iter().cloned());
+        }
+        needed.into_iter().collect()
+    }
+
+    pub fn set_robot_symbols(&self, symbols: Vec<String>) {
+        self.state.write().unwrap().robot_symbols = symbols.into_iter().collect();
+    }
+
+    // Called after a watchlist edit; only matters while the user is connected
+    pub fn watchlist_changed(&self, user_id: Uuid, symbols: Vec<String>) {
+        if let Some(watcher) = self.state.write().unwrap().watchers.get_mut(&user_id) {
+            watcher.symbols = symbols;
+        }
+    }
+
+    // One watchlist_quotes message per connected user with the quotes on their list, in list order
+    pub fn watchlist_messages(&self, quotes: &[Quote], now: DateTime<Utc>) -> Vec<(Uuid, WebSocketMessage)> {
+        let by_symbol: HashMap<&str, &Quote> = quotes.iter().map(|q| (q.symbol.as_str(), q)).collect();
+        let state = self.state.read().unwrap();
+        let mut messages: Vec<(Uuid, WebSocketMessage)> = state
+            .watchers
+            .iter()
+            .filter_map(|(user_id, watcher)| {
+                let relevant: Vec<&Quote> =
+                    watcher.symbols.iter().filter_map(|s| by_symbol.get(s.as_str()).copied()).collect();
+                (!relevant.is_empty()).then(|| {
+                    let message = WebSocketMessage {
+                        message_type: "watchlist_quotes".to_string(),
+                        data: serde_json::json!({ "quotes": relevant }),
+                        timestamp: now,
+                        seq: None,
+                    };
+                    (*user_id, message)
+                })
+            })
+            .collect();
+        messages.sort_by_key(|(user_id, _)| *user_id);
+        messages
+    }
+
+    // Scheduler job: poll every needed symbol once, broadcast robot symbols and push
+    // each connected user the quotes on their watchlist
+    pub async fn tick(&self, feed: &dyn QuoteFeed, websocket: &WebSocketManager) -> Result<()> {
+        self.set_robot_symbols(feed.robot_symbols().await?);
+        let needed = self.needed_symbols();
+        if needed.is_empty() {
+            return Ok(());
+        }
+
+        let quotes = feed.quotes(&needed).await?;
+        if let Some(cache) = &self.quote_cache {
+            cache.record(&quotes);
+        }
+        let robot_symbols = self.state.read().unwrap().robot_symbols.clone();
+        for quote in quotes.iter().filter(|q| robot_symbols.contains(&q.symbol)) {
+            websocket.broadcast_market_data(serde_json::to_value(quote).unwrap_or_default()).await?;
+        }
+        for (user_id, message) in self.watchlist_messages(&quotes, Utc::now()) {
+            websocket.send_to_user(user_id, message).await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl PresenceListener for MarketDataStreamer {
+    async fn user_connected(&self, user_id: Uuid) {
+        {
+            let mut state = self.state.write().unwrap();
+            if let Some(watcher) = state.watchers.get_mut(&user_id) {
+                watcher.connections += 1;
+                return;
+            }
+            state.watchers.insert(user_id, Watcher { connections: 1, symbols: Vec::new() });
+        }
+
+        let symbols = self.watchlists.symbols(user_id).await.unwrap_or_else(|e| {
+            tracing::warn!("Could not load the watchlist of user {}: {}", user_id, e);
+            Vec::new()
+        });
+        // The user may already have disconnected while the list was loading
+        self.watchlist_changed(user_id, symbols);
+    }
+
+    async fn user_disconnected(&self, user_id: Uuid) {
+        let mut state = self.state.write().unwrap();
+        if let Some(watcher) = state.watchers.get_mut(&user_id) {
+            watcher.connections -= 1;
+            if watcher.connections == 0 {
+                state.watchers.remove(&user_id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[derive(Default)]
+    struct FakeWatchlists {
+        lists: HashMap<Uuid, Vec<String>>,
+    }
+
+    #[async_trait]
+    impl WatchlistStore for FakeWatchlists {
+        async fn symbols(&self, user_id: Uuid) -> Result<Vec<String>> {
+            Ok(self.lists.get(&user_id).cloned().unwrap_or_default())
+        }
+    }
+
+    fn symbols(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn quote(symbol: &str, bid: f64) -> Quote {
+        Quote {
+            symbol: symbol.to_string(),
+            bid,
+            ask: bid + 0.0002,
+            time: Utc.with_ymd_and_hms(2023, 12, 15, 9, 0, 0).unwrap(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_watchlist_symbols_are_needed_only_while_the_user_is_connected() {
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut watchlists = FakeWatchlists::default();
+        watchlists.lists.insert(alice, symbols(&["XAUUSD", "EURUSD"]));
+        watchlists.lists.insert(bob, symbols(&["USDJPY"]));
+        let streamer = MarketDataStreamer::new(Arc::new(watchlists));
+        streamer.set_robot_symbols(symbols(&["EURUSD"]));
+
+        assert_eq!(streamer.needed_symbols(), symbols(&["EURUSD"]));
+
+        // Two tabs open: the symbols stay needed until both close
+        streamer.user_connected(alice).await;
+        streamer.user_connected(alice).await;
+        streamer.user_connected(bob).await;
+        assert_eq!(streamer.needed_symbols(), symbols(&["EURUSD", "USDJPY", "XAUUSD"]));
+
+        streamer.user_disconnected(alice).await;
+        assert_eq!(streamer.needed_symbols(), symbols(&["EURUSD", "USDJPY", "XAUUSD"]));
+        streamer.user_disconnected(alice).await;
+        assert_eq!(streamer.needed_symbols(), symbols(&["EURUSD", "USDJPY"]));
+
+        // Edits apply to connected users and are ignored for everyone else
+        streamer.watchlist_changed(bob, symbols(&["GBPUSD"]));
+        streamer.watchlist_changed(alice, symbols(&["BTCUSD"]));
+        assert_eq!(streamer.needed_symbols(), symbols(&["EURUSD", "GBPUSD"]));
+
+        streamer.user_disconnected(bob).await;
+        assert_eq!(streamer.needed_symbols(), symbols(&["EURUSD"]));
+    }
+
+    #[tokio::test]
+    async fn test_each_user_gets_only_the_quotes_on_their_watchlist() {
+        let (alice, bob, carol) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let mut watchlists = FakeWatchlists::default();
+        watchlists.lists.insert(alice, symbols(&["XAUUSD", "EURUSD"]));
+        watchlists.lists.insert(bob, symbols(&["USDJPY"]));
+        watchlists.lists.insert(carol, symbols(&["BTCUSD"]));
+        let streamer = MarketDataStreamer::new(Arc::new(watchlists));
+        for user_id in [alice, bob, carol] {
+            streamer.user_connected(user_id).await;
+        }
+        let now = Utc::now();
+
+        let messages = streamer.watchlist_messages(
+            &[quote("EURUSD", 1.1), quote("USDJPY", 142.3), quote("XAUUSD", 2031.5)],
+            now,
+        );
+
+        let mut expected = vec![
+            (
+                alice,
+                serde_json::json!({ "quotes": [quote("XAUUSD", 2031.5), quote("EURUSD", 1.1)] }),
+            ),
+            (bob, serde_json::json!({ "quotes": [quote("USDJPY", 142.3)] })),
+        ];
+        expected.sort_by_key(|(user_id, _)| *user_id);
+        // Carol has no quote in this batch, so she gets no message at all
+        assert_eq!(
+            messages.iter().map(|(user_id, m)| (*user_id, m.data.clone())).collect::<Vec<_>>(),
+            expected
+        );
+        assert!(messages.iter().all(|(_, m)| m.message_type == "watchlist_quotes" && m.timestamp == now));
+    }
+}
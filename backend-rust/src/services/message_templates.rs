@@ -0,0 +1,624 @@
+use async_trait::async_trait;
+use sqlx::PgPool;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{
+    errors::{AppError, Result},
+    models::{MessageTemplate, PreviewTemplateRequest, RenderedTemplate, SaveTemplateRequest, DEFAULT_LOCALE},
+};
+
+// The copy each email is sent with until an admin activates a stored version. Migration
+// 20231231000001, or the one that added a later key, seeds version 1 of every key from these.
+pub struct BuiltinTemplate {
+    pub key: &'static str,
+    pub subject: &'static str,
+    pub body: &'static str,
+    // The placeholders the key is rendered with, and what a preview fills them with
+    pub variables: &'static [(&'static str, &'static str)],
+}
+
+pub const BUILTIN_TEMPLATES: &[BuiltinTemplate] = &[
+    BuiltinTemplate {
+        key: "welcome",
+        subject: "Welcome to Trading SaaS Platform!",
+        body: r#"<html>
+<body>
+    <h1>Welcome to Trading SaaS Platform, {{name}}!</h1>
+    <p>Thank you for joining our AI-powered trading platform.</p>
+    <p>You can now:</p>
+    <ul>
+        <li>Connect your MT5 broker account</li>
+        <li>Create and configure trading robots</li>
+        <li>Monitor your trading performance in real-time</li>
+        <li>Access advanced AI trading strategies</li>
+    </ul>
+    <p>Get started by logging into your dashboard and setting up your first trading robot.</p>
+    <p>Happy trading!</p>
+    <p>The Trading SaaS Team</p>
+</body>
+</html>"#,
+        variables: &[("name", "Ana")],
+    },
+    BuiltinTemplate {
+        key: "trade_alert",
+        subject: "Trading Alert - New Trade Executed",
+        body: r#"<html>
+<body>
+    <h2>Trading Alert</h2>
+    <p>A new trade has been executed on your account:</p>
+    <div style="background-color: #f5f5f5; padding: 10px; border-radius: 5px;">
+        <pre>{{trade_info}}</pre>
+    </div>
+    <p>You can view more details in your trading dashboard.</p>
+    <p>Best regards,<br>Trading SaaS Platform</p>
+</body>
+</html>"#,
+        variables: &[("trade_info", "BUY 0.1 EURUSD\nEntry: 1.08450")],
+    },
+    BuiltinTemplate {
+        key: "robot_status",
+        subject: "Robot Status Update - {{robot_name}}",
+        body: r#"<html>
+<body>
+    <h2>Robot Status Update</h2>
+    <p>Your trading robot <strong>{{robot_name}}</strong> status has changed to: <strong>{{status}}</strong></p>
+    <p>Please check your dashboard for more details.</p>
+    <p>Best regards,<br>Trading SaaS Platform</p>
+</body>
+</html>"#,
+        variables: &[("robot_name", "EURUSD Trend"), ("status", "stopped")],
+    },
+    BuiltinTemplate {
+        key: "subscription",
+        subject: "Subscription {{action}} - {{plan}}",
+        body: r#"<html>
+<body>
+    <h2>Subscription Update</h2>
+    <p>Your subscription to the <strong>{{plan}}</strong> plan has been <strong>{{action}}</strong>.</p>
+    <p>You can manage your subscription in your account settings.</p>
+    <p>Thank you for using Trading SaaS Platform!</p>
+    <p>Best regards,<br>Trading SaaS Team</p>
+</body>
+</html>"#,
+        variables: &[("plan", "pro"), ("action", "activated")],
+    },
+    BuiltinTemplate {
+        key: "trial_reminder",
+        subject: "Your Pro trial ends in {{days_left}}",
+        body: r#"<html>
+<body>
+    <h2>Your Pro trial is almost over</h2>
+    <p>Your free Pro trial ends in <strong>{{days_left}}</strong>.</p>
+    <p>Subscribe before it ends to keep your robots running without interruption.</p>
+    <p>Best regards,<br>Trading SaaS Team</p>
+</body>
+</html>"#,
+        variables: &[("days_left", "3 days")],
+    },
+    BuiltinTemplate {
+        key: "trial_expired",
+        subject: "Your Pro trial has ended",
+        body: r#"<html>
+<body>
+    <h2>Your Pro trial has ended</h2>
+    <p>Your account is now on the <strong>Free</strong> plan.</p>
+    <p>Upgrade to Pro at any time from your account settings to pick up where you left off.</p>
+    <p>Best regards,<br>Trading SaaS Team</p>
+</body>
+</html>"#,
+        variables: &[],
+    },
+    BuiltinTemplate {
+        key: "delegate_invitation",
+        subject: "{{grantor_email}} shared their trading records with you",
+        body: r#"<html>
+<body>
+    <h2>Read-only access to a trading account</h2>
+    <p>{{grantor_email}} invited you to view their trades and statistics on Trading SaaS Platform.</p>
+    <p>Sign in or create an account with this email address and accept the invitation with this code:</p>
+    <p><strong>{{token}}</strong></p>
+    <p>The invitation expires in {{valid_days}} days. You will not be able to change anything on their account.</p>
+    <p>Best regards,<br>Trading SaaS Team</p>
+</body>
+</html>"#,
+        variables: &[
+            ("grantor_email", "ana@example.com"),
+            ("token", "6f1c9e52-3b0a-4c7e-9d43-2a8f5b7e1c04"),
+            ("valid_days", "7"),
+        ],
+    },
+    BuiltinTemplate {
+        key: "onboarding",
+        subject: "{{subject}}",
+        body: r#"<html>
+<body>
+    <h2>{{subject}}</h2>
+    <p>{{message}}</p>
+    <p>Best regards,<br>Trading SaaS Team</p>
+    <p style="font-size: 12px"><a href="{{unsubscribe_url}}">Stop sending me getting-started emails</a></p>
+</body>
+</html>"#,
+        variables: &[
+            ("subject", "Connect your broker"),
+            ("message", "Link your MT5 account to start trading."),
+            ("unsubscribe_url", "https://example.com/api/v1/public/unsubscribe?token=sample"),
+        ],
+    },
+    BuiltinTemplate {
+        key: "statement_ready",
+        subject: "Your {{period}} statement is ready",
+        body: r#"<html>
+<body>
+    <h2>Your monthly statement for {{period}}</h2>
+    <p>Your statement with the month's closed trades, fees and balances per broker account is ready.</p>
+    <p>Download it from your account, or from <code>{{statement_path}}</code> in the API.</p>
+    <p>Issued statements do not change. Corrections to their trades appear on the next statement as adjustments.</p>
+    <p>Best regards,<br>Trading SaaS Team</p>
+</body>
+</html>"#,
+        variables: &[("period", "March 2024"), ("statement_path", "/api/v1/statements/2024/3")],
+    },
+    BuiltinTemplate {
+        key: "password_reset",
+        subject: "Reset your Trading SaaS password",
+        body: r#"<html>
+<body>
+    <h2>Reset your password</h2>
+    <p>Someone asked to reset the password of your Trading SaaS Platform account.</p>
+    <p><a href="{{reset_url}}">Choose a new password</a></p>
+    <p>The link works once and expires in {{valid_minutes}} minutes. Setting a new password signs you out on every device.</p>
+    <p>If this was not you, ignore this email; your password stays as it is.</p>
+    <p>Best regards,<br>Trading SaaS Team</p>
+</body>
+</html>"#,
+        variables: &[("reset_url", "https://example.com/reset-password?token=sample"), ("valid_minutes", "60")],
+    },
+    BuiltinTemplate {
+        key: "plan_limited",
+        subject: "Robots paused by your {{plan}} plan",
+        body: r#"<html>
+<body>
+    <h2>Some of your robots were paused</h2>
+    <p>Your account is now on the <strong>{{plan}}</strong> plan, which allows {{max_robots}} trading robot(s). These robots were stopped and marked as limited by your plan:</p>
+    <p>{{robots}}</p>
+    <p>They keep their settings, trades and history. Upgrade again and reactivate them from your dashboard in one click.</p>
+    <p>Best regards,<br>Trading SaaS Team</p>
+</body>
+</html>"#,
+        variables: &[("plan", "Essential"), ("max_robots", "1"), ("robots", "EURUSD Trend, GBPUSD Scalper")],
+    },
+    BuiltinTemplate {
+        key: "account_locked",
+        subject: "Your account was locked after failed logins",
+        body: r#"<html>
+<body>
+    <h2>Your account was locked</h2>
+    <p>There were {{failures}} failed attempts to log in to your account, so logins are blocked for the next {{minutes}} minutes.</p>
+    <p>If this was you, wait and try again. If it was not, someone may be guessing your password: once the lock ends, reset your password from the login page.</p>
+    <p>Best regards,<br>Trading SaaS Team</p>
+</body>
+</html>"#,
+        variables: &[("failures", "10"), ("minutes", "30")],
+    },
+    BuiltinTemplate {
+        key: "account_deleted",
+        subject: "Your Trading SaaS account was deleted",
+        body: r#"<html>
+<body>
+    <h2>Your account was deleted</h2>
+    <p>As you asked, your Trading SaaS Platform account was deleted. Your email address, password and broker connections were removed and any subscription was cancelled.</p>
+    <p>Records of past trades are kept for accounting, no longer linked to you.</p>
+    <p>If you did not ask for this, reply to this email.</p>
+    <p>Best regards,<br>Trading SaaS Team</p>
+</body>
+</html>"#,
+        variables: &[],
+    },
+    BuiltinTemplate {
+        key: "account_deactivated",
+        subject: "Your Trading SaaS account was deactivated",
+        body: r#"<html>
+<body>
+    <h2>Your account was deactivated</h2>
+    <p>Your Trading SaaS Platform account was deactivated by our team. You are signed out on every device, logins are refused and your running robots were stopped.</p>
+    <p>Your trades, robots and statements are kept. If you think this is a mistake, reply to this email.</p>
+    <p>Best regards,<br>Trading SaaS Team</p>
+</body>
+</html>"#,
+        variables: &[],
+    },
+    BuiltinTemplate {
+        key: "account_reactivated",
+        subject: "Your Trading SaaS account is active again",
+        body: r#"<html>
+<body>
+    <h2>Your account is active again</h2>
+    <p>Your Trading SaaS Platform account was reactivated and you can log in again.</p>
+    <p>Robots stopped when the account was deactivated stay stopped until you start them from your dashboard.</p>
+    <p>Best regards,<br>Trading SaaS Team</p>
+</body>
+</html>"#,
+        variables: &[],
+    },
+    BuiltinTemplate {
+        key: "system_alert",
+        subject: "System Alert - Trading SaaS Platform",
+        body: r#"<html>
+<body>
+    <h2 style="color: red;">System Alert</h2>
+    <p><strong>Alert Message:</strong></p>
+    <div style="background-color: #ffe6e6; padding: 10px; border-left: 4px solid #ff0000;">
+        {{alert_message}}
+    </div>
+    <p><strong>Timestamp:</strong> {{timestamp}}</p>
+    <p>Please investigate this issue immediately.</p>
+</body>
+</html>"#,
+        variables: &[("alert_message", "Task market_data panicked 3 times"), ("timestamp", "2024-01-01 12:00:00 UTC")],
+    },
+];
+
+pub fn builtin(key: &str) -> Option<&'static BuiltinTemplate> {
+    BUILTIN_TEMPLATES.iter().find(|t| t.key == key)
+}
+
+enum Segment<'a> {
+    Text(&'a str),
+    Variable(&'a str),
+}
+
+// `{{name}}` placeholders; anything else is literal text
+fn parse(source: &str) -> Result<Vec<Segment<'_>>> {
+    let mut segments = Vec::new();
+    let mut rest = source;
+
+    while let Some(start) = rest.find("{{") {
+        segments.push(Segment::Text(&rest[..start]));
+        let after = &rest[start + 2..];
+        let end = after
+            .find("}}")
+            .ok_or_else(|| AppError::Validation("Unclosed {{ in template".to_string()))?;
+        let name = after[..end].trim();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_lowercase() || c == '_') {
+            return Err(AppError::Validation(format!("Invalid placeholder {{{{{}}}}}", &after[..end])));
+        }
+        segments.push(Segment::Variable(name));
+        rest = &after[end + 2..];
+    }
+    segments.push(Segment::Text(rest));
+    Ok(segments)
+}
+
+// Fails on a placeholder `variables` has no value for
+pub fn render(source: &str, variables: &[(&str, &str)]) -> Result<String> {
+    let mut rendered = String::with_capacity(source.len());
+    for segment in parse(source)? {
+        match segment {
+            Segment::Text(text) => rendered.push_str(text),
+            Segment::Variable(name) => {
+                let (_, value) = variables
+                    .iter()
+                    .find(|(n, _)| *n == name)
+                    .ok_or_else(|| AppError::Validation(format!("Unknown placeholder {{{{{}}}}}", name)))?;
+                rendered.push_str(value);
+            }
+        }
+    }
+    Ok(rendered)
+}
+
+#[async_trait]
+pub trait TemplateStore: Send + Sync {
+    async fn list(&self) -> Result<Vec<MessageTemplate>>;
+    async fn active(&self, key: &str, locale: &str) -> Result<Option<MessageTemplate>>;
+    async fn version(&self, key: &str, locale: &str, version: i32) -> Result<Option<MessageTemplate>>;
+    async fn create_version(&self, key: &str, locale: &str, subject: &str, body: &str, created_by: Uuid) -> Result<MessageTemplate>;
+    async fn activate(&self, key: &str, locale: &str, version: i32) -> Result<Option<MessageTemplate>>;
+}
+
+pub struct PgTemplateStore {
+    pool: PgPool,
+}
+
+impl PgTemplateStore {
+    pub fn new(pool: PgPool) -> Self {
+        PgTemplateStore { pool }
+    }
+}
+
+#[async_trait]
+impl TemplateStore for PgTemplateStore {
+    async fn list(&self) -> Result<Vec<MessageTemplate>> {
+        MessageTemplate::find_all(&self.pool).await
+    }
+
+    async fn active(&self, key: &str, locale: &str) -> Result<Option<MessageTemplate>> {
+        MessageTemplate::find_active(&self.pool, key, locale).await
+    }
+
+    async fn version(&self, key: &str, locale: &str, version: i32) -> Result<Option<MessageTemplate>> {
+        MessageTemplate::find_version(&self.pool, key, locale, version).await
+    }
+
+    async fn create_version(&self, key: &str, locale: &str, subject: &str, body: &str, created_by: Uuid) -> Result<MessageTemplate> {
+        MessageTemplate::create_version(&self.pool, key, locale, subject, body, created_by).await
+    }
+
+    async fn activate(&self, key: &str, locale: &str, version: i32) -> Result<Option<MessageTemplate>> {
+        MessageTemplate::activate(&self.pool, key, locale, version).await
+    }
+}
+
+// Renders notification emails from the active stored version of each template, falling back to
+// the compiled default when there is none or it cannot be rendered, so a bad edit never stops mail
+pub struct MessageTemplates {
+    store: Arc<dyn TemplateStore>,
+}
+
+impl MessageTemplates {
+    pub fn new(store: Arc<dyn TemplateStore>) -> Self {
+        MessageTemplates { store }
+    }
+
+    fn known(key: &str) -> Result<&'static BuiltinTemplate> {
+        builtin(key).ok_or_else(|| AppError::NotFound(format!("Unknown template {}", key)))
+    }
+
+    // Every placeholder must be one the key is rendered with
+    pub fn validate(key: &str, subject: &str, body: &str) -> Result<()> {
+        let template = Self::known(key)?;
+        for source in [subject, body] {
+            for segment in parse(source)? {
+                if let Segment::Variable(name) = segment {
+                    if !template.variables.iter().any(|(allowed, _)| *allowed == name) {
+                        let allowed: Vec<&str> = template.variables.iter().map(|(n, _)| *n).collect();
+                        return Err(AppError::Validation(format!(
+                            "Unknown placeholder {{{{{}}}}} for {}; allowed: {}",
+                            name,
+                            key,
+                            allowed.join(", ")
+                        )));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn render_builtin(key: &str, locale: &str, variables: &[(&str, &str)]) -> Result<RenderedTemplate> {
+        let template = Self::known(key)?;
+        Ok(RenderedTemplate {
+            key: key.to_string(),
+            locale: locale.to_string(),
+            version: None,
+            subject: render(template.subject, variables)?,
+            body: render(template.body, variables)?,
+        })
+    }
+
+    fn render_stored(template: &MessageTemplate, variables: &[(&str, &str)]) -> Result<RenderedTemplate> {
+        Ok(RenderedTemplate {
+            key: template.key.clone(),
+            locale: template.locale.clone(),
+            version: Some(template.version),
+            subject: render(&template.subject, variables)?,
+            body: render(&template.body, variables)?,
+        })
+    }
+
+    pub async fn render(&self, key: &str, locale: &str, variables: &[(&str, &str)]) -> Result<RenderedTemplate> {
+        match self.store.active(key, locale).await {
+            Ok(Some(template)) => match Self::render_stored(&template, variables) {
+                Ok(rendered) => return Ok(rendered),
+                Err(e) => tracing::warn!(
+                    "Template {} ({}) version {} does not render, using the default: {}",
+                    key, locale, template.version, e
+                ),
+            },
+            Ok(None) => {}
+            Err(e) => tracing::warn!("Could not load template {} ({}), using the default: {}", key, locale, e),
+        }
+        Self::render_builtin(key, locale, variables)
+    }
+
+    pub async fn list(&self) -> Result<Vec<MessageTemplate>> {
+        self.store.list().await
+    }
+
+    pub async fn save(&self, key: &str, request: &SaveTemplateRequest, created_by: Uuid) -> Result<MessageTemplate> {
+        Self::validate(key, &request.subject, &request.body)?;
+        let locale = request.locale.as_deref().unwrap_or(DEFAULT_LOCALE);
+        self.store.create_version(key, locale, &request.subject, &request.body, created_by).await
+    }
+
+    pub async fn activate(&self, key: &str, locale: &str, version: i32) -> Result<MessageTemplate> {
+        Self::known(key)?;
+        self.store
+            .activate(key, locale, version)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("Template {} ({}) has no version {}", key, locale, version)))
+    }
+
+    // Renders with the key's sample values; nothing is sent
+    pub async fn preview(&self, key: &str, request: &PreviewTemplateRequest) -> Result<RenderedTemplate> {
+        let sample = Self::known(key)?.variables;
+        let locale = request.locale.as_deref().unwrap_or(DEFAULT_LOCALE);
+
+        match (&request.subject, &request.body, request.version) {
+            (Some(subject), Some(body), _) => {
+                Self::validate(key, subject, body)?;
+                Ok(RenderedTemplate {
+                    key: key.to_string(),
+                    locale: locale.to_string(),
+                    version: None,
+                    subject: render(subject, sample)?,
+                    body: render(body, sample)?,
+                })
+            }
+            (None, None, Some(version)) => {
+                let template = self
+                    .store
+                    .version(key, locale, version)
+                    .await?
+                    .ok_or_else(|| AppError::NotFound(format!("Template {} ({}) has no version {}", key, locale, version)))?;
+                Self::render_stored(&template, sample)
+            }
+            (None, None, None) => self.render(key, locale, sample).await,
+            _ => Err(AppError::Validation("A draft preview needs both subject and body".to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct FakeStore {
+        templates: Mutex<Vec<MessageTemplate>>,
+    }
+
+    #[async_trait]
+    impl TemplateStore for FakeStore {
+        async fn list(&self) -> Result<Vec<MessageTemplate>> {
+            Ok(self.templates.lock().unwrap().clone())
+        }
+
+        async fn active(&self, key: &str, locale: &str) -> Result<Option<MessageTemplate>> {
+            Ok(self.templates.lock().unwrap().iter().find(|t| t.key == key && t.locale == locale && t.is_active).cloned())
+        }
+
+        async fn version(&self, key: &str, locale: &str, version: i32) -> Result<Option<MessageTemplate>> {
+            Ok(self
+                .templates
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|t| t.key == key && t.locale == locale && t.version == version)
+                .cloned())
+        }
+
+        async fn create_version(&self, key: &str, locale: &str, subject: &str, body: &str, created_by: Uuid) -> Result<MessageTemplate> {
+            let mut templates = self.templates.lock().unwrap();
+            let version = templates.iter().filter(|t| t.key == key && t.locale == locale).map(|t| t.version).max().unwrap_or(0) + 1;
+            let template = MessageTemplate {
+                id: Uuid::new_v4(),
+                key: key.to_string(),
+                locale: locale.to_string(),
+                subject: subject.to_string(),
+                body: body.to_string(),
+                version,
+                is_active: false,
+                created_by: Some(created_by),
+                created_at: chrono::Utc::now(),
+            };
+            templates.push(template.clone());
+            Ok(template)
+        }
+
+        async fn activate(&self, key: &str, locale: &str, version: i32) -> Result<Option<MessageTemplate>> {
+            let mut templates = self.templates.lock().unwrap();
+            if !templates.iter().any(|t| t.key == key && t.locale == locale && t.version == version) {
+                return Ok(None);
+            }
+            for template in templates.iter_mut().filter(|t| t.key == key && t.locale == locale) {
+                template.is_active = template.version == version;
+            }
+            Ok(templates.iter().find(|t| t.key == key && t.locale == locale && t.version == version).cloned())
+        }
+    }
+
+    fn save_request(subject: &str, body: &str) -> SaveTemplateRequest {
+        SaveTemplateRequest { locale: None, subject: subject.to_string(), body: body.to_string() }
+    }
+
+    #[tokio::test]
+    async fn test_activated_version_is_the_one_sent() {
+        let templates = MessageTemplates::new(Arc::new(FakeStore::default()));
+        let admin = Uuid::new_v4();
+
+        // Nothing stored yet: the compiled default
+        let rendered = templates.render("welcome", "en", &[("name", "Ana")]).await.unwrap();
+        assert_eq!((rendered.version, rendered.subject.as_str()), (None, "Welcome to Trading SaaS Platform!"));
+        assert!(rendered.body.contains("Welcome to Trading SaaS Platform, Ana!"));
+
+        let first = templates.save("welcome", &save_request("Hi {{name}}", "<p>First</p>"), admin).await.unwrap();
+        let second = templates.save("welcome", &save_request("Hello {{ name }}", "<p>Second</p>"), admin).await.unwrap();
+        assert_eq!((first.version, second.version), (1, 2));
+        assert!(!second.is_active);
+
+        // Saving alone changes nothing that is sent
+        let rendered = templates.render("welcome", "en", &[("name", "Ana")]).await.unwrap();
+        assert_eq!(rendered.version, None);
+
+        templates.activate("welcome", "en", 2).await.unwrap();
+        let rendered = templates.render("welcome", "en", &[("name", "Ana")]).await.unwrap();
+        assert_eq!((rendered.version, rendered.subject.as_str(), rendered.body.as_str()), (Some(2), "Hello Ana", "<p>Second</p>"));
+
+        // Rolling back is activating the older version
+        templates.activate("welcome", "en", 1).await.unwrap();
+        let rendered = templates.render("welcome", "en", &[("name", "Ana")]).await.unwrap();
+        assert_eq!((rendered.version, rendered.subject.as_str()), (Some(1), "Hi Ana"));
+
+        assert!(matches!(templates.activate("welcome", "en", 9).await, Err(AppError::NotFound(_))));
+        assert!(matches!(templates.activate("welcome", "pt", 1).await, Err(AppError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_broken_active_template_falls_back_to_the_default() {
+        let store = Arc::new(FakeStore::default());
+        let templates = MessageTemplates::new(store.clone());
+        // Written around the save-time checks, as a hand edit of the table would be
+        let broken = store.create_version("robot_status", "en", "Robot {{robot_name}}", "<p>{{status</p>", Uuid::new_v4()).await.unwrap();
+        store.activate("robot_status", "en", broken.version).await.unwrap();
+
+        let rendered = templates
+            .render("robot_status", "en", &[("robot_name", "EURUSD Trend"), ("status", "stopped")])
+            .await
+            .unwrap();
+        assert_eq!(rendered.version, None);
+        assert_eq!(rendered.subject, "Robot Status Update - EURUSD Trend");
+        assert!(rendered.body.contains("<strong>stopped</strong>"));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_placeholder_is_refused_at_save_time() {
+        let templates = MessageTemplates::new(Arc::new(FakeStore::default()));
+        let admin = Uuid::new_v4();
+
+        let typo = templates.save("welcome", &save_request("Hi {{nmae}}", "<p>Hi</p>"), admin).await;
+        assert!(matches!(typo, Err(AppError::Validation(message)) if message.contains("{{nmae}}") && message.contains("name")));
+        let unclosed = templates.save("welcome", &save_request("Hi", "<p>{{name</p>"), admin).await;
+        assert!(matches!(unclosed, Err(AppError::Validation(_))));
+        let unknown_key = templates.save("newsletter", &save_request("Hi", "<p>Hi</p>"), admin).await;
+        assert!(matches!(unknown_key, Err(AppError::NotFound(_))));
+
+        assert!(templates.list().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_preview_renders_sample_data() {
+        let templates = MessageTemplates::new(Arc::new(FakeStore::default()));
+
+        let current = templates.preview("subscription", &PreviewTemplateRequest::default()).await.unwrap();
+        assert_eq!(current.subject, "Subscription activated - pro");
+        assert!(!current.body.contains("{{"));
+
+        let draft = PreviewTemplateRequest {
+            subject: Some("Now on {{plan}}".to_string()),
+            body: Some("<p>{{action}}</p>".to_string()),
+            ..Default::default()
+        };
+        let rendered = templates.preview("subscription", &draft).await.unwrap();
+        assert_eq!((rendered.subject.as_str(), rendered.body.as_str()), ("Now on pro", "<p>activated</p>"));
+
+        // Every built-in renders with its own sample values
+        for template in BUILTIN_TEMPLATES {
+            let rendered = templates.preview(template.key, &PreviewTemplateRequest::default()).await.unwrap();
+            assert!(!rendered.body.contains("{{"), "{}", template.key);
+            MessageTemplates::validate(template.key, template.subject, template.body).unwrap();
+        }
+    }
+}
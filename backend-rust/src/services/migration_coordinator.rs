@@ -0,0 +1,421 @@
+use async_trait::async_trait;
+use sqlx::{migrate::Migrator, pool::PoolConnection, PgPool, Postgres, Row};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+use crate::errors::{AppError, DbOp, Result};
+use crate::services::migration_lint::{FlaggedStatement, MigrationGuard, MigrationScript};
+
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+// Arbitrary key for pg_advisory_lock, shared by every replica of this service
+const MIGRATION_LOCK_KEY: i64 = 4_207_316_859;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaVersion {
+    pub version: i64,
+    pub checksum: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SchemaStatus {
+    Current,
+    Pending(Vec<i64>),
+    // Applied, but the migration file has changed since
+    Modified(i64),
+    // A newer deploy already migrated further than this binary knows about
+    Ahead(Vec<i64>),
+}
+
+impl SchemaStatus {
+    // A replica of the previous release keeps serving while the next one rolls out
+    pub fn is_ready(&self) -> bool {
+        matches!(self, SchemaStatus::Current | SchemaStatus::Ahead(_))
+    }
+
+    pub fn compare(expected: &[SchemaVersion], applied: &[SchemaVersion]) -> SchemaStatus {
+        for migration in expected {
+            if let Some(done) = applied.iter().find(|a| a.version == migration.version) {
+                if done.checksum != migration.checksum {
+                    return SchemaStatus::Modified(migration.version);
+                }
+            }
+        }
+
+        let pending: Vec<i64> = expected
+            .iter()
+            .filter(|m| !applied.iter().any(|a| a.version == m.version))
+            .map(|m| m.version)
+            .collect();
+        if !pending.is_empty() {
+            return SchemaStatus::Pending(pending);
+        }
+
+        let ahead: Vec<i64> = applied
+            .iter()
+            .filter(|a| !expected.iter().any(|m| m.version == a.version))
+            .map(|a| a.version)
+            .collect();
+        if !ahead.is_empty() {
+            return SchemaStatus::Ahead(ahead);
+        }
+        SchemaStatus::Current
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrationOutcome {
+    Applied(usize),
+    UpToDate,
+}
+
+#[async_trait]
+pub trait MigrationTarget: Send + Sync {
+    // Non-blocking; true when this process now holds the cluster-wide migration lock
+    async fn try_lock(&self) -> Result<bool>;
+    async fn unlock(&self) -> Result<()>;
+    async fn applied(&self) -> Result<Vec<SchemaVersion>>;
+    async fn apply(&self) -> Result<()>;
+}
+
+// The migrations embedded in this binary
+pub fn embedded_versions() -> Vec<SchemaVersion> {
+    MIGRATOR
+        .iter()
+        .filter(|m| m.migration_type.is_up_migration())
+        .map(|m| SchemaVersion { version: m.version, checksum: m.checksum.to_vec() })
+        .collect()
+}
+
+pub fn embedded_scripts() -> Vec<MigrationScript> {
+    MIGRATOR
+        .iter()
+        .filter(|m| m.migration_type.is_up_migration())
+        .map(|m| MigrationScript { version: m.version, sql: m.sql.to_string() })
+        .collect()
+}
+
+pub struct PgMigrationTarget {
+    pool: PgPool,
+    // Advisory locks belong to a session, so the connection that took it is kept until unlock
+    lock_conn: Mutex<Option<PoolConnection<Postgres>>>,
+}
+
+impl PgMigrationTarget {
+    pub fn new(pool: PgPool) -> Self {
+        PgMigrationTarget { pool, lock_conn: Mutex::new(None) }
+    }
+}
+
+#[async_trait]
+impl MigrationTarget for PgMigrationTarget {
+    async fn try_lock(&self) -> Result<bool> {
+        let mut conn = self.pool.acquire().await.db_op("migrations.try_lock")?;
+        let locked: bool = sqlx::query_scalar("SELECT pg_try_advisory_lock($1)")
+            .bind(MIGRATION_LOCK_KEY)
+            .fetch_one(&mut *conn)
+            .await
+            .db_op("migrations.try_lock")?;
+        if locked {
+            *self.lock_conn.lock().await = Some(conn);
+        }
+        Ok(locked)
+    }
+
+    async fn unlock(&self) -> Result<()> {
+        if let Some(mut conn) = self.lock_conn.lock().await.take() {
+            sqlx::query("SELECT pg_advisory_unlock($1)")
+                .bind(MIGRATION_LOCK_KEY)
+                .execute(&mut *conn)
+                .await
+                .db_op("migrations.unlock")?;
+        }
+        Ok(())
+    }
+
+    async fn applied(&self) -> Result<Vec<SchemaVersion>> {
+        let exists: bool = sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+            .fetch_one(&self.pool)
+            .await
+            .db_op("migrations.applied")?;
+        if !exists {
+            return Ok(Vec::new());
+        }
+
+        let rows = sqlx::query("SELECT version, checksum FROM _sqlx_migrations WHERE success ORDER BY version")
+            .fetch_all(&self.pool)
+            .await
+            .db_op("migrations.applied")?;
+        Ok(rows
+            .iter()
+            .map(|row| SchemaVersion { version: row.get("version"), checksum: row.get("checksum") })
+            .collect())
+    }
+
+    async fn apply(&self) -> Result<()> {
+        MIGRATOR
+            .run(&self.pool)
+            .await
+            .map_err(|e| AppError::Internal(anyhow::anyhow!("Migration failed: {}", e)))
+    }
+}
+
+pub struct MigrationCoordinator;
+
+impl MigrationCoordinator {
+    // Applies pending migrations under the advisory lock. Replicas that find the lock taken
+    // poll until it is released (and then usually find nothing left to do) or `wait` runs out.
+    pub async fn run(
+        target: &dyn MigrationTarget,
+        expected: &[SchemaVersion],
+        guard: &MigrationGuard,
+        wait: Duration,
+        poll: Duration,
+    ) -> Result<MigrationOutcome> {
+        let deadline = tokio::time::Instant::now() + wait;
+        while !target.try_lock().await? {
+            if tokio::time::Instant::now() >= deadline {
+                return Err(AppError::Unavailable(format!(
+                    "Gave up after {}s waiting for another replica to finish migrations",
+                    wait.as_secs()
+                )));
+            }
+            tracing::info!("Migration lock is held by another replica, waiting");
+            tokio::time::sleep(poll).await;
+        }
+
+        let outcome = Self::migrate_locked(target, expected, guard).await;
+        target.unlock().await?;
+        outcome
+    }
+
+    // Destructive statements among the pending migrations, for the operator to confirm
+    pub async fn pending_destructive(
+        target: &dyn MigrationTarget,
+        expected: &[SchemaVersion],
+        guard: &MigrationGuard,
+    ) -> Result<Vec<FlaggedStatement>> {
+        let applied = target.applied().await?;
+        match SchemaStatus::compare(expected, &applied) {
+            SchemaStatus::Pending(versions) if !applied.is_empty() => Ok(guard.flagged(&versions)),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    async fn migrate_locked(
+        target: &dyn MigrationTarget,
+        expected: &[SchemaVersion],
+        guard: &MigrationGuard,
+    ) -> Result<MigrationOutcome> {
+        let applied = target.applied().await?;
+        match SchemaStatus::compare(expected, &applied) {
+            SchemaStatus::Pending(versions) => {
+                // A fresh database has no data to lose
+                if !applied.is_empty() {
+                    guard.check(&versions)?;
+                }
+                tracing::info!("Applying {} migration(s): {:?}", versions.len(), versions);
+                target.apply().await?;
+                Ok(MigrationOutcome::Applied(versions.len()))
+            }
+            SchemaStatus::Modified(version) => Err(AppError::Internal(anyhow::anyhow!(
+                "Migration {} was changed after it was applied",
+                version
+            ))),
+            SchemaStatus::Ahead(versions) => {
+                tracing::warn!("Database has migrations this build does not know: {:?}", versions);
+                Ok(MigrationOutcome::UpToDate)
+            }
+            SchemaStatus::Current => Ok(MigrationOutcome::UpToDate),
+        }
+    }
+}
+
+// Readiness stays false until the schema matches the migrations this binary was built with.
+// Once it does it stays ready, so probes stop querying the database.
+pub struct SchemaGate {
+    target: Arc<dyn MigrationTarget>,
+    expected: Vec<SchemaVersion>,
+    ready: AtomicBool,
+}
+
+impl SchemaGate {
+    pub fn new(target: Arc<dyn MigrationTarget>, expected: Vec<SchemaVersion>) -> Self {
+        SchemaGate { target, expected, ready: AtomicBool::new(false) }
+    }
+
+    pub async fn status(&self) -> Result<SchemaStatus> {
+        if self.ready.load(Ordering::Relaxed) {
+            return Ok(SchemaStatus::Current);
+        }
+
+        let status = SchemaStatus::compare(&self.expected, &self.target.applied().await?);
+        if status.is_ready() {
+            self.ready.store(true, Ordering::Relaxed);
+        }
+        Ok(status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    // One database shared by every migrator in a test
+    #[derive(Default)]
+    struct FakeDatabase {
+        lock_holder: std::sync::Mutex<Option<usize>>,
+        applied: std::sync::Mutex<Vec<SchemaVersion>>,
+        applies: AtomicUsize,
+    }
+
+    struct FakeReplica {
+        id: usize,
+        db: Arc<FakeDatabase>,
+        migrations: Vec<SchemaVersion>,
+    }
+
+    #[async_trait]
+    impl MigrationTarget for FakeReplica {
+        async fn try_lock(&self) -> Result<bool> {
+            let mut holder = self.db.lock_holder.lock().unwrap();
+            if holder.is_some() {
+                return Ok(false);
+            }
+            *holder = Some(self.id);
+            Ok(true)
+        }
+
+        async fn unlock(&self) -> Result<()> {
+            let mut holder = self.db.lock_holder.lock().unwrap();
+            if *holder == Some(self.id) {
+                *holder = None;
+            }
+            Ok(())
+        }
+
+        async fn applied(&self) -> Result<Vec<SchemaVersion>> {
+            Ok(self.db.applied.lock().unwrap().clone())
+        }
+
+        async fn apply(&self) -> Result<()> {
+            self.db.applies.fetch_add(1, Ordering::SeqCst);
+            // Long enough for the other replica to find the lock taken
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            self.db.applied.lock().unwrap().clone_from(&self.migrations);
+            Ok(())
+        }
+    }
+
+    fn migrations() -> Vec<SchemaVersion> {
+        vec![
+            SchemaVersion { version: 20231213000001, checksum: vec![1, 2] },
+            SchemaVersion { version: 20231214000001, checksum: vec![3, 4] },
+        ]
+    }
+
+    fn replica(id: usize, db: &Arc<FakeDatabase>) -> FakeReplica {
+        FakeReplica { id, db: db.clone(), migrations: migrations() }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_replicas_apply_migrations_once() {
+        let db = Arc::new(FakeDatabase::default());
+        let (first, second) = (replica(1, &db), replica(2, &db));
+        let gate = SchemaGate::new(Arc::new(replica(3, &db)), migrations());
+
+        assert_eq!(
+            gate.status().await.unwrap(),
+            SchemaStatus::Pending(vec![20231213000001, 20231214000001])
+        );
+
+        let expected = migrations();
+        let guard = MigrationGuard::default();
+        let wait = Duration::from_secs(5);
+        let poll = Duration::from_millis(10);
+        let (a, b) = tokio::join!(
+            MigrationCoordinator::run(&first, &expected, &guard, wait, poll),
+            MigrationCoordinator::run(&second, &expected, &guard, wait, poll),
+        );
+
+        let mut outcomes = vec![a.unwrap(), b.unwrap()];
+        outcomes.sort_by_key(|o| *o == MigrationOutcome::UpToDate);
+        assert_eq!(outcomes, vec![MigrationOutcome::Applied(2), MigrationOutcome::UpToDate]);
+        assert_eq!(db.applies.load(Ordering::SeqCst), 1);
+        assert!(db.lock_holder.lock().unwrap().is_none());
+
+        assert_eq!(gate.status().await.unwrap(), SchemaStatus::Current);
+    }
+
+    #[tokio::test]
+    async fn test_waiting_replica_times_out_while_the_lock_is_held() {
+        let db = Arc::new(FakeDatabase::default());
+        *db.lock_holder.lock().unwrap() = Some(99);
+
+        let err = MigrationCoordinator::run(&replica(1, &db), &migrations(), &MigrationGuard::default(), Duration::from_millis(50), Duration::from_millis(10))
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, AppError::Unavailable(_)), "{}", err);
+        assert_eq!(db.applies.load(Ordering::SeqCst), 0);
+        assert_eq!(*db.lock_holder.lock().unwrap(), Some(99));
+    }
+
+    #[tokio::test]
+    async fn test_edited_migration_is_refused_and_never_ready() {
+        let db = Arc::new(FakeDatabase::default());
+        let mut applied = migrations();
+        applied[0].checksum = vec![9, 9];
+        *db.applied.lock().unwrap() = applied;
+
+        let err = MigrationCoordinator::run(&replica(1, &db), &migrations(), &MigrationGuard::default(), Duration::from_secs(1), Duration::from_millis(10))
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, AppError::Internal(_)), "{}", err);
+        assert_eq!(db.applies.load(Ordering::SeqCst), 0);
+        // The lock is released even though the run failed
+        assert!(db.lock_holder.lock().unwrap().is_none());
+
+        let gate = SchemaGate::new(Arc::new(replica(2, &db)), migrations());
+        assert_eq!(gate.status().await.unwrap(), SchemaStatus::Modified(20231213000001));
+    }
+
+    #[tokio::test]
+    async fn test_strict_mode_refuses_destructive_pending_migrations() {
+        let db = Arc::new(FakeDatabase::default());
+        *db.applied.lock().unwrap() = migrations()[..1].to_vec();
+        let guard = MigrationGuard::new(vec![MigrationScript {
+            version: 20231214000001,
+            sql: "ALTER TABLE trades DROP COLUMN swap;".to_string(),
+        }])
+        .with_strict(true);
+        let (wait, poll) = (Duration::from_secs(1), Duration::from_millis(10));
+
+        let flagged = MigrationCoordinator::pending_destructive(&replica(1, &db), &migrations(), &guard).await.unwrap();
+        assert_eq!(flagged.len(), 1);
+        let err = MigrationCoordinator::run(&replica(1, &db), &migrations(), &guard, wait, poll).await.unwrap_err();
+        assert!(err.to_string().contains("destructive"), "{}", err);
+        assert_eq!(db.applies.load(Ordering::SeqCst), 0);
+        assert!(db.lock_holder.lock().unwrap().is_none());
+
+        let allowed = guard.with_allow_destructive(true);
+        let outcome = MigrationCoordinator::run(&replica(1, &db), &migrations(), &allowed, wait, poll).await.unwrap();
+        assert_eq!(outcome, MigrationOutcome::Applied(1));
+    }
+
+    #[test]
+    fn test_schema_from_a_newer_release_still_counts_as_ready() {
+        let mut applied = migrations();
+        applied.push(SchemaVersion { version: 20231215000001, checksum: vec![5] });
+
+        let status = SchemaStatus::compare(&migrations(), &applied);
+
+        assert_eq!(status, SchemaStatus::Ahead(vec![20231215000001]));
+        assert!(status.is_ready());
+        assert!(!SchemaStatus::compare(&migrations(), &migrations()[..1]).is_ready());
+    }
+}
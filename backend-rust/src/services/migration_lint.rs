@@ -0,0 +1,348 @@
+use serde::Serialize;
+
+use crate::errors::{AppError, Result};
+
+// Statements that break the previous release while a rolling deploy still runs it, or that
+// cannot be undone
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DestructiveKind {
+    DropTable,
+    DropColumn,
+    // Except to TEXT; the old type's length, precision or decoding may no longer hold
+    ColumnTypeChange,
+    // A NOT NULL column added without a default, or SET NOT NULL on an existing one that the same
+    // statement does not give a default
+    NotNullWithoutDefault,
+}
+
+impl DestructiveKind {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            DestructiveKind::DropTable => "drops a table",
+            DestructiveKind::DropColumn => "drops a column",
+            DestructiveKind::ColumnTypeChange => "changes a column type",
+            DestructiveKind::NotNullWithoutDefault => "requires NOT NULL without a default",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FlaggedStatement {
+    pub version: i64,
+    pub kind: DestructiveKind,
+    pub statement: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationScript {
+    pub version: i64,
+    pub sql: String,
+}
+
+// Decides whether pending migrations may run on their own. Flagged statements are always logged;
+// with strict mode on they are refused unless an operator allowed them from the CLI.
+#[derive(Debug, Clone, Default)]
+pub struct MigrationGuard {
+    scripts: Vec<MigrationScript>,
+    strict: bool,
+    allow_destructive: bool,
+}
+
+impl MigrationGuard {
+    pub fn new(scripts: Vec<MigrationScript>) -> Self {
+        MigrationGuard { scripts, strict: false, allow_destructive: false }
+    }
+
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn with_allow_destructive(mut self, allow: bool) -> Self {
+        self.allow_destructive = allow;
+        self
+    }
+
+    pub fn flagged(&self, pending: &[i64]) -> Vec<FlaggedStatement> {
+        self.scripts
+            .iter()
+            .filter(|script| pending.contains(&script.version))
+            .flat_map(|script| {
+                classify(&script.sql).into_iter().map(|(kind, statement)| FlaggedStatement {
+                    version: script.version,
+                    kind,
+                    statement,
+                })
+            })
+            .collect()
+    }
+
+    pub fn check(&self, pending: &[i64]) -> Result<()> {
+        let flagged = self.flagged(pending);
+        for f in &flagged {
+            tracing::warn!(version = f.version, "Migration {} {}: {}", f.version, f.kind.describe(), f.statement);
+        }
+        if flagged.is_empty() || !self.strict || self.allow_destructive {
+            return Ok(());
+        }
+        let versions: Vec<String> = flagged.iter().map(|f| f.version.to_string()).collect();
+        Err(AppError::Internal(anyhow::anyhow!(
+            "Refusing {} destructive statement(s) in migration(s) {} with STRICT_MIGRATIONS on; \
+             apply them with --migrate-only --allow-destructive",
+            flagged.len(),
+            versions.join(", ")
+        )))
+    }
+}
+
+// Every destructive statement in the script, with comments stripped and whitespace collapsed
+pub fn classify(sql: &str) -> Vec<(DestructiveKind, String)> {
+    let mut flagged = Vec::new();
+    for statement in split_statements(sql) {
+        let upper = statement.to_uppercase();
+        let words: Vec<&str> = upper.split_whitespace().collect();
+        let mut kinds: Vec<DestructiveKind> = match words.as_slice() {
+            ["DROP", "TABLE", ..] => vec![DestructiveKind::DropTable],
+            ["ALTER", "TABLE", rest @ ..] => {
+                let actions = split_top_level(&rest.join(" "), ',');
+                // The previous release leaves these columns out of its inserts and gets the default
+                let defaulted: Vec<String> = actions.iter().filter_map(|action| defaulted_column(action)).collect();
+                actions.iter().filter_map(|action| classify_alter_action(action, &defaulted)).collect()
+            }
+            _ => Vec::new(),
+        };
+        kinds.dedup();
+        flagged.extend(kinds.into_iter().map(|kind| (kind, statement.clone())));
+    }
+    flagged
+}
+
+// The words of one ALTER TABLE action, from its keyword on. The table name (and ONLY / IF EXISTS)
+// precede the first action.
+fn action_words(action: &str) -> Option<Vec<&str>> {
+    let words: Vec<&str> = action.split_whitespace().collect();
+    let start = words.iter().position(|w| matches!(*w, "DROP" | "ALTER" | "ADD"))?;
+    Some(words[start..].to_vec())
+}
+
+// The column of an "ALTER [COLUMN] x SET DEFAULT ..." action
+fn defaulted_column(action: &str) -> Option<String> {
+    let words = action_words(action)?;
+    let rest = match words.as_slice() {
+        ["ALTER", "COLUMN", rest @ ..] | ["ALTER", rest @ ..] => rest,
+        _ => return None,
+    };
+    matches!(rest, [_, "SET", "DEFAULT", ..]).then(|| rest[0].to_string())
+}
+
+// One action of ALTER TABLE, uppercased, e.g. "DROP COLUMN notes" or "ADD COLUMN x INT NOT NULL"
+fn classify_alter_action(action: &str, defaulted: &[String]) -> Option<DestructiveKind> {
+    let words = action_words(action)?;
+    match words.as_slice() {
+        ["DROP", "CONSTRAINT", ..] => None,
+        ["DROP", ..] => Some(DestructiveKind::DropColumn),
+        ["ADD", "CONSTRAINT" | "PRIMARY" | "UNIQUE" | "FOREIGN" | "CHECK" | "EXCLUDE", ..] => None,
+        ["ADD", ..] => {
+            let not_null = words.windows(2).any(|pair| pair == ["NOT", "NULL"]);
+            (not_null && !words.contains(&"DEFAULT")).then_some(DestructiveKind::NotNullWithoutDefault)
+        }
+        ["ALTER", rest @ ..] => {
+            let rest = if rest.first() == Some(&"COLUMN") { &rest[1..] } else { rest };
+            match rest.get(1..).unwrap_or_default() {
+                ["TYPE", target @ ..] | ["SET", "DATA", "TYPE", target @ ..] => {
+                    (target.first() != Some(&"TEXT")).then_some(DestructiveKind::ColumnTypeChange)
+                }
+                ["SET", "NOT", "NULL", ..] => {
+                    (!defaulted.iter().any(|c| Some(&c.as_str()) == rest.first())).then_some(DestructiveKind::NotNullWithoutDefault)
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+// Splits on `separator` outside parentheses and quotes
+fn split_top_level(text: &str, separator: char) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut depth = 0usize;
+    let mut quoted = false;
+    let mut current = String::new();
+    for c in text.chars() {
+        match c {
+            '\'' => quoted = !quoted,
+            _ if quoted => {}
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            _ if c == separator && depth == 0 => {
+                parts.push(std::mem::take(&mut current));
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    parts.push(current);
+    parts
+}
+
+// Statements end at semicolons outside quotes, comments and dollar-quoted bodies
+fn split_statements(sql: &str) -> Vec<String> {
+    let chars: Vec<char> = sql.chars().collect();
+    let mut statements = Vec::new();
+    let mut current = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        if c == '-' && next == Some('-') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+            current.push(' ');
+            continue;
+        }
+        if c == '/' && next == Some('*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                i += 1;
+            }
+            i += 2;
+            current.push(' ');
+            continue;
+        }
+        if c == '\'' || c == '"' {
+            let end = (i + 1..chars.len()).find(|&j| chars[j] == c).unwrap_or(chars.len() - 1);
+            current.extend(&chars[i..=end]);
+            i = end + 1;
+            continue;
+        }
+        if c == '$' {
+            if let Some(tag_end) = (i + 1..chars.len()).find(|&j| !(chars[j].is_alphanumeric() || chars[j] == '_')) {
+                if chars[tag_end] == '$' {
+                    let tag: String = chars[i..=tag_end].iter().collect();
+                    let body: String = chars[tag_end + 1..].iter().collect();
+                    let close = body.find(&tag).map(|at| tag_end + 1 + body[..at].chars().count() + tag.chars().count());
+                    let end = close.unwrap_or(chars.len());
+                    current.extend(&chars[i..end]);
+                    i = end;
+                    continue;
+                }
+            }
+        }
+        if c == ';' {
+            statements.push(std::mem::take(&mut current));
+        } else {
+            current.push(c);
+        }
+        i += 1;
+    }
+    statements.push(current);
+    statements
+        .into_iter()
+        .map(|s| s.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(sql: &str) -> Vec<DestructiveKind> {
+        classify(sql).into_iter().map(|(kind, _)| kind).collect()
+    }
+
+    const SAFE: &[&str] = &[
+        "CREATE TABLE notes (id UUID PRIMARY KEY, body TEXT NOT NULL, created_at TIMESTAMPTZ NOT NULL)",
+        "ALTER TABLE users ADD COLUMN trial_used BOOLEAN NOT NULL DEFAULT FALSE",
+        "ALTER TABLE users ADD COLUMN nickname VARCHAR(50)",
+        "ALTER TABLE trades ALTER COLUMN ai_reasoning DROP NOT NULL",
+        "ALTER TABLE trades ALTER COLUMN status SET DEFAULT 'open'",
+        // Rows the previous release inserts without the column get the default
+        "ALTER TABLE robots ALTER COLUMN metrics SET DEFAULT '{\"a\": 0, \"b\": 0}'::jsonb, ALTER COLUMN metrics SET NOT NULL",
+        "ALTER TABLE trades ALTER COLUMN status DROP DEFAULT",
+        "ALTER TABLE trades DROP CONSTRAINT trades_status_check, ADD CONSTRAINT trades_status_check CHECK (status IN ('open', 'closed'))",
+        "ALTER TABLE robots ALTER COLUMN name TYPE TEXT",
+        "DROP INDEX idx_trades_user",
+        "DROP TRIGGER IF EXISTS update_users_updated_at ON users",
+        "CREATE INDEX idx_trades_open ON trades(user_id) WHERE status = 'open'",
+        "UPDATE users SET plan = 'free' WHERE plan IS NULL",
+        // Keywords inside strings, comments and function bodies are not statements
+        "COMMENT ON TABLE trades IS 'never DROP TABLE this; really'",
+        "-- DROP TABLE trades;\nSELECT 1",
+        "/* ALTER TABLE trades DROP COLUMN swap; */ SELECT 1",
+        "CREATE FUNCTION cleanup() RETURNS void AS $$ BEGIN DROP TABLE scratch; END; $$ LANGUAGE plpgsql",
+        "CREATE FUNCTION cleanup() RETURNS void AS $body$ BEGIN ALTER TABLE t DROP COLUMN c; END; $body$ LANGUAGE plpgsql",
+    ];
+
+    const DESTRUCTIVE: &[(&str, DestructiveKind)] = &[
+        ("DROP TABLE trading_sessions", DestructiveKind::DropTable),
+        ("drop table if exists legacy_reports cascade", DestructiveKind::DropTable),
+        ("ALTER TABLE trades DROP COLUMN swap", DestructiveKind::DropColumn),
+        ("ALTER TABLE trades DROP IF EXISTS swap", DestructiveKind::DropColumn),
+        ("ALTER TABLE ONLY trades DROP COLUMN IF EXISTS commission CASCADE", DestructiveKind::DropColumn),
+        ("ALTER TABLE trades ALTER COLUMN symbol TYPE VARCHAR(10)", DestructiveKind::ColumnTypeChange),
+        ("ALTER TABLE trades ALTER volume SET DATA TYPE REAL", DestructiveKind::ColumnTypeChange),
+        ("ALTER TABLE trades ALTER COLUMN volume TYPE DOUBLE PRECISION USING volume::FLOAT8", DestructiveKind::ColumnTypeChange),
+        ("ALTER TABLE users ADD COLUMN region VARCHAR(10) NOT NULL", DestructiveKind::NotNullWithoutDefault),
+        ("ALTER TABLE users ADD region VARCHAR(10) NOT NULL REFERENCES regions(code)", DestructiveKind::NotNullWithoutDefault),
+        ("ALTER TABLE users ALTER COLUMN email SET NOT NULL", DestructiveKind::NotNullWithoutDefault),
+        ("ALTER TABLE users ALTER COLUMN plan SET DEFAULT 'free', ALTER COLUMN email SET NOT NULL", DestructiveKind::NotNullWithoutDefault),
+    ];
+
+    #[test]
+    fn test_safe_statements_are_not_flagged() {
+        for sql in SAFE {
+            assert_eq!(kinds(sql), vec![], "{}", sql);
+        }
+    }
+
+    #[test]
+    fn test_destructive_statements_are_flagged() {
+        for (sql, kind) in DESTRUCTIVE {
+            assert_eq!(kinds(sql), vec![*kind], "{}", sql);
+        }
+    }
+
+    #[test]
+    fn test_every_action_of_a_multi_action_alter_counts() {
+        let sql = "-- reshape\nALTER TABLE trades\n    ADD COLUMN origin TEXT,\n    DROP COLUMN swap,\n    ALTER COLUMN volume TYPE NUMERIC(10, 2);\nCREATE INDEX idx ON trades(origin);";
+        let flagged = classify(sql);
+        assert_eq!(
+            flagged.iter().map(|(kind, _)| *kind).collect::<Vec<_>>(),
+            vec![DestructiveKind::DropColumn, DestructiveKind::ColumnTypeChange]
+        );
+        assert_eq!(
+            flagged[0].1,
+            "ALTER TABLE trades ADD COLUMN origin TEXT, DROP COLUMN swap, ALTER COLUMN volume TYPE NUMERIC(10, 2)"
+        );
+    }
+
+    #[test]
+    fn test_shipped_migrations_only_flag_the_column_type_changes() {
+        let scripts: Vec<MigrationScript> = crate::services::migration_coordinator::embedded_scripts();
+        let guard = MigrationGuard::new(scripts.clone());
+        let all: Vec<i64> = scripts.iter().map(|s| s.version).collect();
+        let versions: Vec<i64> = guard.flagged(&all).into_iter().map(|f| f.version).collect();
+        assert_eq!(versions, vec![20231202000001, 20231202000001, 20240125000001]);
+    }
+
+    #[test]
+    fn test_strict_guard_refuses_unless_allowed() {
+        let scripts = vec![
+            MigrationScript { version: 1, sql: "ALTER TABLE trades DROP COLUMN swap;".to_string() },
+            MigrationScript { version: 2, sql: "ALTER TABLE trades ADD COLUMN note TEXT;".to_string() },
+        ];
+
+        // Only pending migrations are looked at
+        let strict = MigrationGuard::new(scripts.clone()).with_strict(true);
+        assert!(strict.check(&[2]).is_ok());
+        let err = strict.check(&[1, 2]).unwrap_err().to_string();
+        assert!(err.contains("--allow-destructive") && err.contains(" 1 "), "{}", err);
+
+        assert!(MigrationGuard::new(scripts.clone()).check(&[1, 2]).is_ok());
+        assert!(strict.with_allow_destructive(true).check(&[1, 2]).is_ok());
+    }
+}
@@ -4,6 +4,82 @@ pub mod mt5_service;
 pub mod stripe_service;
 pub mod websocket_manager;
 pub mod notification_service;
+pub mod cache_service;
+pub mod dashboard_service;
+pub mod broker_throttle;
+pub mod execution_queue;
+pub mod preset_service;
+pub mod scheduler;
+pub mod trial_service;
+pub mod trade_close_service;
+pub mod plan_service;
+pub mod robot_runner;
+pub mod robot_recovery;
+pub mod backtest_progress;
+pub mod feature_flags;
+pub mod public_stats;
+pub mod onboarding_service;
+pub mod allocation_service;
+pub mod ws_protocol;
+pub mod integrity_service;
+pub mod composite_signal;
+pub mod cooldown_service;
+pub mod broker_connection_service;
+pub mod job_service;
+pub mod robot_journal;
+pub mod event_bus;
+pub mod event_subscribers;
+pub mod risk_template_service;
+pub mod account_snapshot_service;
+pub mod migration_coordinator;
+pub mod migration_lint;
+pub mod watchlist_service;
+pub mod market_data_streamer;
+pub mod email_outbox;
+pub mod trade_search;
+pub mod trade_reentry;
+pub mod signal_stability;
+pub mod platform_stats;
+pub mod delegation_service;
+pub mod task_supervisor;
+pub mod user_events;
+pub mod backtest_engine;
+pub mod strategy_optimizer;
+pub mod system_status;
+pub mod checkout_service;
+pub mod quote_service;
+pub mod export_stream;
+pub mod trade_journal;
+pub mod ws_shedding;
+pub mod activation_nudges;
+pub mod credential_vault;
+pub mod leaderboard;
+pub mod order_drain;
+pub mod trade_positions;
+pub mod bridge_events;
+pub mod message_templates;
+pub mod runtime_settings;
+pub mod statements;
+pub mod broker_maintenance;
+pub mod trade_facts;
+pub mod password_policy;
+pub mod trade_origins;
+pub mod ai_quality;
+pub mod token_revocation;
+pub mod login_throttle;
+pub mod plan_downgrade;
+pub mod symbol_spec;
+pub mod risk_calculator;
+pub mod trade_pages;
+pub mod google_identity;
+pub mod github_identity;
+pub mod apple_identity;
+pub mod oauth;
+pub mod currency_converter;
+pub mod exposure;
+pub mod api_keys;
+pub mod auth_events;
+pub mod deletion_purge;
 
 pub use auth_service::AuthService;
 pub use ai_trading_service::AiTradingService;
@@ -11,3 +87,54 @@ pub use mt5_service::Mt5Service;
 pub use stripe_service::StripeService;
 pub use websocket_manager::WebSocketManager;
 pub use notification_service::NotificationService;
+pub use cache_service::CacheService;
+pub use dashboard_service::DashboardService;
+pub use preset_service::PresetService;
+pub use scheduler::Scheduler;
+pub use trial_service::TrialService;
+pub use trade_close_service::TradeCloseService;
+pub use plan_service::PlanService;
+pub use robot_runner::RobotRunnerRegistry;
+pub use robot_recovery::RobotRecovery;
+pub use feature_flags::FeatureFlags;
+pub use public_stats::PublicStatsService;
+pub use onboarding_service::OnboardingService;
+pub use allocation_service::AllocationService;
+pub use integrity_service::IntegrityService;
+pub use cooldown_service::CooldownService;
+pub use broker_connection_service::BrokerConnectionService;
+pub use job_service::JobService;
+pub use robot_journal::RobotJournal;
+pub use event_bus::EventBus;
+pub use risk_template_service::RiskTemplateService;
+pub use account_snapshot_service::AccountSnapshotService;
+pub use migration_coordinator::MigrationCoordinator;
+pub use watchlist_service::WatchlistService;
+pub use market_data_streamer::MarketDataStreamer;
+pub use email_outbox::EmailOutbox;
+pub use trade_search::TradeSearch;
+pub use trade_reentry::TradeReentry;
+pub use platform_stats::PlatformStats;
+pub use delegation_service::DelegationService;
+pub use api_keys::ApiKeyService;
+pub use task_supervisor::TaskSupervisor;
+pub use strategy_optimizer::StrategyOptimizer;
+pub use quote_service::QuoteService;
+pub use trade_journal::TradeJournal;
+pub use activation_nudges::ActivationNudges;
+pub use credential_vault::CredentialVault;
+pub use leaderboard::LeaderboardService;
+pub use order_drain::OrderDrain;
+pub use trade_positions::TradePositions;
+pub use ai_quality::AiQuality;
+pub use runtime_settings::RuntimeConfig;
+pub use bridge_events::BridgeEvents;
+pub use message_templates::MessageTemplates;
+pub use statements::StatementService;
+pub use broker_maintenance::BrokerMaintenanceService;
+pub use plan_downgrade::PlanDowngradeService;
+pub use login_throttle::LoginThrottle;
+pub use trade_facts::TradeFactsBackfill;
+pub use risk_calculator::RiskCalculator;
+pub use trade_pages::TradePages;
+pub use deletion_purge::DeletionPurge;
@@ -1,12 +1,20 @@
+use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
 
 use crate::{
     errors::{AppError, Result},
-    models::{BrokerConnection, AccountInfo},
+    models::{BrokerConnection, AccountInfo, StopManagement, Trade},
+    services::{
+        broker_throttle::{BrokerCallPriority, BrokerThrottle},
+        CredentialVault,
+    },
 };
 
-#[derive(Debug, Serialize, Deserialize)]
+const BROKER_TYPE: &str = "MT5";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Mt5Order {
     pub symbol: String,
     pub order_type: String, // BUY, SELL
@@ -17,7 +25,23 @@ pub struct Mt5Order {
     pub comment: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl Mt5Order {
+    // Platform-managed trades go out without SL/TP so the broker never sees the levels
+    pub fn for_trade(trade: &Trade, stop_management: StopManagement) -> Self {
+        let attach = stop_management.attaches_to_order();
+        Mt5Order {
+            symbol: trade.symbol.clone(),
+            order_type: trade.trade_type.to_uppercase(),
+            volume: trade.volume,
+            price: Some(trade.entry_price),
+            stop_loss: trade.stop_loss.filter(|_| attach),
+            take_profit: trade.take_profit.filter(|_| attach),
+            comment: format!("trade {}", trade.id),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Mt5Position {
     pub ticket: i64,
     pub symbol: String,
@@ -40,8 +64,30 @@ pub struct Mt5MarketData {
     pub time: chrono::DateTime<chrono::Utc>,
 }
 
+// The calls that reach the broker itself. Mt5Service keeps the sessions and throttles calls, and
+// hands each call to the client, keyed by the connection it was opened for.
+#[async_trait]
+pub trait BrokerClient: Send + Sync {
+    async fn login(&self, connection_id: &str, login: &str, password: &str, server: &str) -> Result<()>;
+    // Checks an account without opening a session
+    async fn test_login(&self, connection: &BrokerConnection) -> Result<AccountInfo>;
+    async fn account_info(&self, connection_id: &str) -> Result<AccountInfo>;
+    // Returns the broker's ticket
+    async fn place_order(&self, connection_id: &str, order: &Mt5Order) -> Result<i64>;
+    async fn close_position(&self, connection_id: &str, ticket: i64) -> Result<()>;
+    async fn modify_order(&self, connection_id: &str, ticket: i64, stop_loss: Option<f64>, take_profit: Option<f64>) -> Result<()>;
+    async fn positions(&self, connection_id: &str) -> Result<Vec<Mt5Position>>;
+    async fn market_data(&self, connection_id: &str, symbol: &str) -> Result<Mt5MarketData>;
+    // OHLCV bars, oldest first
+    async fn history(&self, connection_id: &str, symbol: &str, timeframe: &str, count: i32) -> Result<Vec<[f64; 5]>>;
+}
+
 pub struct Mt5Service {
-    connections: HashMap<String, Mt5Connection>,
+    connections: RwLock<HashMap<String, Mt5Connection>>,
+    throttle: Arc<BrokerThrottle>,
+    // Without a vault stored credentials are used as they are
+    credentials: Option<Arc<CredentialVault>>,
+    client: Arc<dyn BrokerClient>,
 }
 
 struct Mt5Connection {
@@ -53,36 +99,69 @@ struct Mt5Connection {
 
 impl Mt5Service {
     pub fn new() -> Self {
+        Self::with_throttle(Arc::new(BrokerThrottle::default()))
+    }
+
+    pub fn with_throttle(throttle: Arc<BrokerThrottle>) -> Self {
         Mt5Service {
-            connections: HashMap::new(),
+            connections: RwLock::new(HashMap::new()),
+            throttle,
+            credentials: None,
+            client: Arc::new(SimulatedBroker),
         }
     }
 
-    pub async fn connect(&mut self, connection: &BrokerConnection) -> Result<()> {
-        // TODO: Implement actual MT5 connection
-        // This is a placeholder implementation
-        
+    // Replaces the simulated broker, e.g. with a test double
+    pub fn with_client(mut self, client: Arc<dyn BrokerClient>) -> Self {
+        self.client = client;
+        self
+    }
+
+    pub fn with_credentials(mut self, credentials: Arc<CredentialVault>) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    // Checked before every broker call; the lock is never held across an await
+    fn ensure_connected(&self, connection_id: &str) -> Result<()> {
+        let connections = self.connections.read().unwrap();
+        let connection = connections.get(connection_id)
+            .ok_or_else(|| AppError::Mt5("Connection not found".to_string()))?;
+
+        if !connection.is_connected {
+            return Err(AppError::Mt5("Not connected to MT5".to_string()));
+        }
+        Ok(())
+    }
+
+    pub async fn connect(&self, connection: &BrokerConnection) -> Result<()> {
         let login = connection.login.as_ref()
             .ok_or_else(|| AppError::Mt5("Login required for MT5 connection".to_string()))?;
         
         let server = connection.server.as_ref()
             .ok_or_else(|| AppError::Mt5("Server required for MT5 connection".to_string()))?;
 
+        let password = match &self.credentials {
+            Some(credentials) => credentials.open(connection).await?.api_secret,
+            None => connection.api_secret.clone(),
+        };
+        self.client.login(&connection.id.to_string(), login, &password, server).await?;
+
         let mt5_connection = Mt5Connection {
             login: login.clone(),
-            password: connection.api_secret.clone(), // In real implementation, this should be decrypted
+            password,
             server: server.clone(),
-            is_connected: true, // Simulate successful connection
+            is_connected: true,
         };
 
-        self.connections.insert(connection.id.to_string(), mt5_connection);
+        self.connections.write().unwrap().insert(connection.id.to_string(), mt5_connection);
         
         tracing::info!("Connected to MT5 for connection {}", connection.id);
         Ok(())
     }
 
-    pub async fn disconnect(&mut self, connection_id: &str) -> Result<()> {
-        if let Some(connection) = self.connections.get_mut(connection_id) {
+    pub async fn disconnect(&self, connection_id: &str) -> Result<()> {
+        if let Some(connection) = self.connections.write().unwrap().get_mut(connection_id) {
             connection.is_connected = false;
             tracing::info!("Disconnected from MT5 for connection {}", connection_id);
         }
@@ -90,102 +169,68 @@ impl Mt5Service {
     }
 
     pub async fn test_connection(&self, connection: &BrokerConnection) -> Result<AccountInfo> {
-        // TODO: Implement actual MT5 connection test
-        // This is a placeholder implementation
-        
-        // Simulate connection test
-        tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
-        
-        // Return mock account info
-        Ok(AccountInfo {
-            account_number: "12345678".to_string(),
-            balance: 10000.0,
-            equity: 10000.0,
-            margin: 0.0,
-            free_margin: 10000.0,
-            currency: "USD".to_string(),
-        })
+        if connection.login.as_deref().is_none_or(|login| login.trim().is_empty()) {
+            return Err(AppError::Mt5("Login required for MT5 connection".to_string()));
+        }
+        if connection.server.as_deref().is_none_or(|server| server.trim().is_empty()) {
+            return Err(AppError::Mt5("Server required for MT5 connection".to_string()));
+        }
+
+        self.client.test_login(connection).await
     }
 
     pub async fn get_account_info(&self, connection_id: &str) -> Result<AccountInfo> {
-        let _connection = self.connections.get(connection_id)
-            .ok_or_else(|| AppError::Mt5("Connection not found".to_string()))?;
+        if !self.connections.read().unwrap().contains_key(connection_id) {
+            return Err(AppError::Mt5("Connection not found".to_string()));
+        }
 
-        // TODO: Implement actual MT5 account info retrieval
-        Ok(AccountInfo {
-            account_number: "12345678".to_string(),
-            balance: 10000.0,
-            equity: 10000.0,
-            margin: 0.0,
-            free_margin: 10000.0,
-            currency: "USD".to_string(),
-        })
+        self.throttle.acquire(connection_id, BROKER_TYPE, BrokerCallPriority::Account).await?;
+
+        self.client.account_info(connection_id).await
     }
 
     pub async fn place_order(&self, connection_id: &str, order: &Mt5Order) -> Result<i64> {
-        let connection = self.connections.get(connection_id)
-            .ok_or_else(|| AppError::Mt5("Connection not found".to_string()))?;
+        self.ensure_connected(connection_id)?;
 
-        if !connection.is_connected {
-            return Err(AppError::Mt5("Not connected to MT5".to_string()));
-        }
+        self.throttle.acquire(connection_id, BROKER_TYPE, BrokerCallPriority::Order).await?;
 
-        // TODO: Implement actual MT5 order placement
-        // This is a placeholder implementation
-        
         tracing::info!("Placing MT5 order: {:?}", order);
-        
-        // Simulate order placement
-        let ticket = chrono::Utc::now().timestamp() as i64; // Mock ticket number
-        
-        Ok(ticket)
+        self.client.place_order(connection_id, order).await
     }
 
     pub async fn close_position(&self, connection_id: &str, ticket: i64) -> Result<()> {
-        let connection = self.connections.get(connection_id)
-            .ok_or_else(|| AppError::Mt5("Connection not found".to_string()))?;
+        self.ensure_connected(connection_id)?;
 
-        if !connection.is_connected {
-            return Err(AppError::Mt5("Not connected to MT5".to_string()));
-        }
+        self.throttle.acquire(connection_id, BROKER_TYPE, BrokerCallPriority::Order).await?;
 
-        // TODO: Implement actual MT5 position closing
         tracing::info!("Closing MT5 position: {}", ticket);
-        
-        Ok(())
+        self.client.close_position(connection_id, ticket).await
+    }
+
+    // Moves the stops of an open position or the price of a pending order
+    pub async fn modify_order(&self, connection_id: &str, ticket: i64, stop_loss: Option<f64>, take_profit: Option<f64>) -> Result<()> {
+        self.ensure_connected(connection_id)?;
+
+        self.throttle.acquire(connection_id, BROKER_TYPE, BrokerCallPriority::OrderMaintenance).await?;
+
+        tracing::info!("Modifying MT5 order {}: sl={:?} tp={:?}", ticket, stop_loss, take_profit);
+        self.client.modify_order(connection_id, ticket, stop_loss, take_profit).await
     }
 
     pub async fn get_positions(&self, connection_id: &str) -> Result<Vec<Mt5Position>> {
-        let connection = self.connections.get(connection_id)
-            .ok_or_else(|| AppError::Mt5("Connection not found".to_string()))?;
+        self.ensure_connected(connection_id)?;
 
-        if !connection.is_connected {
-            return Err(AppError::Mt5("Not connected to MT5".to_string()));
-        }
+        self.throttle.acquire(connection_id, BROKER_TYPE, BrokerCallPriority::Account).await?;
 
-        // TODO: Implement actual MT5 positions retrieval
-        // Return empty positions for now
-        Ok(vec![])
+        self.client.positions(connection_id).await
     }
 
     pub async fn get_market_data(&self, connection_id: &str, symbol: &str) -> Result<Mt5MarketData> {
-        let connection = self.connections.get(connection_id)
-            .ok_or_else(|| AppError::Mt5("Connection not found".to_string()))?;
+        self.ensure_connected(connection_id)?;
 
-        if !connection.is_connected {
-            return Err(AppError::Mt5("Not connected to MT5".to_string()));
-        }
+        self.throttle.acquire(connection_id, BROKER_TYPE, BrokerCallPriority::MarketData).await?;
 
-        // TODO: Implement actual MT5 market data retrieval
-        // Return mock data for now
-        Ok(Mt5MarketData {
-            symbol: symbol.to_string(),
-            bid: 1.1000,
-            ask: 1.1002,
-            last: 1.1001,
-            volume: 1000.0,
-            time: chrono::Utc::now(),
-        })
+        self.client.market_data(connection_id, symbol).await
     }
 
     pub async fn get_historical_data(
@@ -195,32 +240,32 @@ impl Mt5Service {
         timeframe: &str,
         count: i32,
     ) -> Result<Vec<[f64; 5]>> {
-        let connection = self.connections.get(connection_id)
-            .ok_or_else(|| AppError::Mt5("Connection not found".to_string()))?;
+        self.ensure_connected(connection_id)?;
 
-        if !connection.is_connected {
-            return Err(AppError::Mt5("Not connected to MT5".to_string()));
-        }
+        self.throttle.acquire(connection_id, BROKER_TYPE, BrokerCallPriority::MarketData).await?;
 
-        // TODO: Implement actual MT5 historical data retrieval
-        // Return mock OHLCV data for now
-        let mut data = Vec::new();
-        for i in 0..count {
-            let base_price = 1.1000 + (i as f64 * 0.0001);
-            data.push([
-                base_price,           // Open
-                base_price + 0.0005,  // High
-                base_price - 0.0005,  // Low
-                base_price + 0.0002,  // Close
-                1000.0,               // Volume
-            ]);
-        }
-        
-        Ok(data)
+        self.client.history(connection_id, symbol, timeframe, count).await
+    }
+
+    // Some open session, for calls that do not depend on the account (quotes)
+    pub fn any_connected(&self) -> Option<String> {
+        self.connections
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, c)| c.is_connected)
+            .map(|(id, _)| id.clone())
+            .min()
+    }
+
+    // (open sessions, sessions still connected)
+    pub fn session_counts(&self) -> (usize, usize) {
+        let connections = self.connections.read().unwrap();
+        (connections.len(), connections.values().filter(|c| c.is_connected).count())
     }
 
     pub fn is_connected(&self, connection_id: &str) -> bool {
-        self.connections.get(connection_id)
+        self.connections.read().unwrap().get(connection_id)
             .map(|c| c.is_connected)
             .unwrap_or(false)
     }
@@ -232,6 +277,76 @@ impl Default for Mt5Service {
     }
 }
 
+// Stands in for the MT5 terminal until there is a real one to talk to: every login succeeds,
+// orders fill, nothing is ever open and every symbol is quoted at 1.1000 / 1.1002
+pub struct SimulatedBroker;
+
+impl SimulatedBroker {
+    fn account() -> AccountInfo {
+        AccountInfo {
+            account_number: "12345678".to_string(),
+            balance: 10000.0,
+            equity: 10000.0,
+            margin: 0.0,
+            free_margin: 10000.0,
+            currency: "USD".to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl BrokerClient for SimulatedBroker {
+    async fn login(&self, _connection_id: &str, _login: &str, _password: &str, _server: &str) -> Result<()> {
+        Ok(())
+    }
+
+    async fn test_login(&self, _connection: &BrokerConnection) -> Result<AccountInfo> {
+        tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+        Ok(Self::account())
+    }
+
+    async fn account_info(&self, _connection_id: &str) -> Result<AccountInfo> {
+        Ok(Self::account())
+    }
+
+    async fn place_order(&self, _connection_id: &str, _order: &Mt5Order) -> Result<i64> {
+        Ok(chrono::Utc::now().timestamp())
+    }
+
+    async fn close_position(&self, _connection_id: &str, _ticket: i64) -> Result<()> {
+        Ok(())
+    }
+
+    async fn modify_order(&self, _connection_id: &str, _ticket: i64, _stop_loss: Option<f64>, _take_profit: Option<f64>) -> Result<()> {
+        Ok(())
+    }
+
+    async fn positions(&self, _connection_id: &str) -> Result<Vec<Mt5Position>> {
+        Ok(vec![])
+    }
+
+    async fn market_data(&self, _connection_id: &str, symbol: &str) -> Result<Mt5MarketData> {
+        Ok(Mt5MarketData {
+            symbol: symbol.to_string(),
+            bid: 1.1000,
+            ask: 1.1002,
+            last: 1.1001,
+            volume: 1000.0,
+            time: chrono::Utc::now(),
+        })
+    }
+
+    async fn history(&self, _connection_id: &str, _symbol: &str, _timeframe: &str, count: i32) -> Result<Vec<[f64; 5]>> {
+        // A gentle uptrend: open, high, low, close, volume
+        Ok((0..count)
+            .map(|i| {
+                let base_price = 1.1000 + (i as f64 * 0.0001);
+                [base_price, base_price + 0.0005, base_price - 0.0005, base_price + 0.0002, 1000.0]
+            })
+            .collect())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -245,12 +360,15 @@ mod tests {
             broker_type: "MT5".to_string(),
             api_key: "test_key".to_string(),
             api_secret: "test_secret".to_string(),
+            credentials_key_id: None,
+            needs_credentials: false,
             server: Some("MetaQuotes-Demo".to_string()),
             login: Some("12345678".to_string()),
             is_active: true,
             is_demo: true,
             last_test_at: None,
             last_test_status: None,
+            allow_duplicate: false,
             created_at: chrono::Utc::now(),
             updated_at: chrono::Utc::now(),
         }
@@ -258,7 +376,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_mt5_connection() {
-        let mut service = Mt5Service::new();
+        let service = Mt5Service::new();
         let connection = create_test_connection();
         
         let result = service.connect(&connection).await;
@@ -266,9 +384,35 @@ mod tests {
         assert!(service.is_connected(&connection.id.to_string()));
     }
 
+    #[test]
+    fn test_order_attaches_stops_only_when_broker_manages_them() {
+        let trade = Trade::new(
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            "EURUSD".to_string(),
+            "buy".to_string(),
+            0.1,
+            1.1,
+            Some(1.09),
+            Some(1.12),
+            None,
+            None,
+        );
+
+        let broker = Mt5Order::for_trade(&trade, StopManagement::Broker);
+        assert_eq!((broker.stop_loss, broker.take_profit), (Some(1.09), Some(1.12)));
+        assert_eq!(broker.order_type, "BUY");
+
+        let both = Mt5Order::for_trade(&trade, StopManagement::Both);
+        assert_eq!((both.stop_loss, both.take_profit), (Some(1.09), Some(1.12)));
+
+        let platform = Mt5Order::for_trade(&trade, StopManagement::Platform);
+        assert_eq!((platform.stop_loss, platform.take_profit), (None, None));
+    }
+
     #[tokio::test]
     async fn test_account_info() {
-        let mut service = Mt5Service::new();
+        let service = Mt5Service::new();
         let connection = create_test_connection();
         
         service.connect(&connection).await.unwrap();
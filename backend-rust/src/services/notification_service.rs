@@ -1,6 +1,12 @@
+use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 
 use crate::errors::{AppError, Result};
+use crate::models::{OutboxEmail, Trade, DEFAULT_LOCALE};
+use crate::money;
+use crate::services::email_outbox::{Mailer, OutboxStore};
+use crate::services::message_templates::MessageTemplates;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct EmailNotification {
@@ -23,6 +29,8 @@ pub struct NotificationService {
     smtp_host: Option<String>,
     smtp_user: Option<String>,
     smtp_password: Option<String>,
+    outbox: Option<Arc<dyn OutboxStore>>,
+    templates: Option<Arc<MessageTemplates>>,
 }
 
 impl NotificationService {
@@ -35,10 +43,53 @@ impl NotificationService {
             smtp_host,
             smtp_user,
             smtp_password,
+            outbox: None,
+            templates: None,
         }
     }
 
+    // Emails are queued and sent by the outbox worker, so callers never wait on SMTP
+    pub fn with_outbox(mut self, outbox: Arc<dyn OutboxStore>) -> Self {
+        self.outbox = Some(outbox);
+        self
+    }
+
+    // Without it, emails use the compiled default copy
+    pub fn with_templates(mut self, templates: Arc<MessageTemplates>) -> Self {
+        self.templates = Some(templates);
+        self
+    }
+
     pub async fn send_email(&self, notification: EmailNotification) -> Result<()> {
+        self.queue("email", notification).await
+    }
+
+    async fn queue(&self, template_key: &str, notification: EmailNotification) -> Result<()> {
+        let Some(outbox) = &self.outbox else {
+            return self.deliver(&notification).await;
+        };
+
+        let payload = serde_json::to_value(&notification).map_err(|e| AppError::Internal(e.into()))?;
+        let email = OutboxEmail::new(&notification.to, template_key, payload, chrono::Utc::now());
+        outbox.enqueue(&email).await
+    }
+
+    async fn send_template(&self, to: &str, template_key: &str, variables: &[(&str, &str)]) -> Result<()> {
+        let rendered = match &self.templates {
+            Some(templates) => templates.render(template_key, DEFAULT_LOCALE, variables).await?,
+            None => MessageTemplates::render_builtin(template_key, DEFAULT_LOCALE, variables)?,
+        };
+        let notification = EmailNotification {
+            to: to.to_string(),
+            subject: rendered.subject,
+            body: rendered.body,
+            is_html: true,
+        };
+
+        self.queue(template_key, notification).await
+    }
+
+    pub async fn deliver(&self, notification: &EmailNotification) -> Result<()> {
         // For now, we'll just log the email instead of actually sending it
         // This avoids the lettre dependency issues
         tracing::info!(
@@ -51,107 +102,99 @@ impl NotificationService {
     }
 
     pub async fn send_welcome_email(&self, email: &str, name: &str) -> Result<()> {
-        let notification = EmailNotification {
-            to: email.to_string(),
-            subject: "Welcome to Trading SaaS Platform!".to_string(),
-            body: format!(
-                r#"
-                <html>
-                <body>
-                    <h1>Welcome to Trading SaaS Platform, {}!</h1>
-                    <p>Thank you for joining our AI-powered trading platform.</p>
-                    <p>You can now:</p>
-                    <ul>
-                        <li>Connect your MT5 broker account</li>
-                        <li>Create and configure trading robots</li>
-                        <li>Monitor your trading performance in real-time</li>
-                        <li>Access advanced AI trading strategies</li>
-                    </ul>
-                    <p>Get started by logging into your dashboard and setting up your first trading robot.</p>
-                    <p>Happy trading!</p>
-                    <p>The Trading SaaS Team</p>
-                </body>
-                </html>
-                "#,
-                name
-            ),
-            is_html: true,
-        };
+        self.send_template(email, "welcome", &[("name", name)]).await
+    }
 
-        self.send_email(notification).await
+    // Plain-text block for send_trade_notification, with prices and amounts rounded for display
+    pub fn trade_info(trade: &Trade, currency: &str) -> String {
+        let mut lines = vec![
+            format!("{} {} {}", trade.trade_type, trade.volume, trade.symbol),
+            format!("Entry: {}", money::format_price(trade.entry_price)),
+        ];
+        if let Some(exit_price) = trade.exit_price {
+            lines.push(format!("Exit: {}", money::format_price(exit_price)));
+        }
+        if let Some(profit_loss) = trade.profit_loss {
+            lines.push(format!("Profit/Loss: {}", money::format_amount(profit_loss, currency)));
+        }
+        lines.join("\n")
     }
 
     pub async fn send_trade_notification(&self, email: &str, trade_info: &str) -> Result<()> {
-        let notification = EmailNotification {
-            to: email.to_string(),
-            subject: "Trading Alert - New Trade Executed".to_string(),
-            body: format!(
-                r#"
-                <html>
-                <body>
-                    <h2>Trading Alert</h2>
-                    <p>A new trade has been executed on your account:</p>
-                    <div style="background-color: #f5f5f5; padding: 10px; border-radius: 5px;">
-                        <pre>{}</pre>
-                    </div>
-                    <p>You can view more details in your trading dashboard.</p>
-                    <p>Best regards,<br>Trading SaaS Platform</p>
-                </body>
-                </html>
-                "#,
-                trade_info
-            ),
-            is_html: true,
-        };
-
-        self.send_email(notification).await
+        self.send_template(email, "trade_alert", &[("trade_info", trade_info)]).await
     }
 
     pub async fn send_robot_status_notification(&self, email: &str, robot_name: &str, status: &str) -> Result<()> {
-        let notification = EmailNotification {
-            to: email.to_string(),
-            subject: format!("Robot Status Update - {}", robot_name),
-            body: format!(
-                r#"
-                <html>
-                <body>
-                    <h2>Robot Status Update</h2>
-                    <p>Your trading robot <strong>{}</strong> status has changed to: <strong>{}</strong></p>
-                    <p>Please check your dashboard for more details.</p>
-                    <p>Best regards,<br>Trading SaaS Platform</p>
-                </body>
-                </html>
-                "#,
-                robot_name, status
-            ),
-            is_html: true,
-        };
-
-        self.send_email(notification).await
+        self.send_template(email, "robot_status", &[("robot_name", robot_name), ("status", status)]).await
     }
 
     pub async fn send_subscription_notification(&self, email: &str, plan: &str, action: &str) -> Result<()> {
-        let notification = EmailNotification {
-            to: email.to_string(),
-            subject: format!("Subscription {} - {}", action, plan),
-            body: format!(
-                r#"
-                <html>
-                <body>
-                    <h2>Subscription Update</h2>
-                    <p>Your subscription to the <strong>{}</strong> plan has been <strong>{}</strong>.</p>
-                    <p>You can manage your subscription in your account settings.</p>
-                    <p>Thank you for using Trading SaaS Platform!</p>
-                    <p>Best regards,<br>Trading SaaS Team</p>
-                </body>
-                </html>
-                "#,
-                plan, action
-            ),
-            is_html: true,
-        };
+        self.send_template(email, "subscription", &[("plan", plan), ("action", action)]).await
+    }
 
-        self.send_email(notification).await
+    pub async fn send_trial_reminder_email(&self, email: &str, days_left: i32) -> Result<()> {
+        let days_left = format!("{} day{}", days_left, if days_left == 1 { "" } else { "s" });
+        self.send_template(email, "trial_reminder", &[("days_left", &days_left)]).await
+    }
+
+    pub async fn send_trial_expired_email(&self, email: &str) -> Result<()> {
+        self.send_template(email, "trial_expired", &[]).await
+    }
+
+    pub async fn send_delegate_invitation(&self, email: &str, grantor_email: &str, token: uuid::Uuid) -> Result<()> {
+        let token = token.to_string();
+        let valid_days = crate::models::INVITATION_VALID_DAYS.to_string();
+        self.send_template(
+            email,
+            "delegate_invitation",
+            &[("grantor_email", grantor_email), ("token", &token), ("valid_days", &valid_days)],
+        )
+        .await
+    }
+
+    pub async fn send_onboarding_email(&self, email: &str, subject: &str, message: &str, unsubscribe_url: &str) -> Result<()> {
+        self.send_template(
+            email,
+            "onboarding",
+            &[("subject", subject), ("message", message), ("unsubscribe_url", unsubscribe_url)],
+        )
+        .await
+    }
+
+    pub async fn send_password_reset(&self, email: &str, reset_url: &str) -> Result<()> {
+        let valid_minutes = crate::models::PASSWORD_RESET_VALID_MINUTES.to_string();
+        self.send_template(email, "password_reset", &[("reset_url", reset_url), ("valid_minutes", &valid_minutes)])
+            .await
+    }
+
+    pub async fn send_plan_limited(&self, email: &str, plan: &str, max_robots: i32, robot_names: &[String]) -> Result<()> {
+        let max_robots = max_robots.to_string();
+        let robots = robot_names.join(", ");
+        self.send_template(email, "plan_limited", &[("plan", plan), ("max_robots", &max_robots), ("robots", &robots)])
+            .await
+    }
+
+    pub async fn send_account_locked(&self, email: &str, failures: u64, minutes: u64) -> Result<()> {
+        let failures = failures.to_string();
+        let minutes = minutes.to_string();
+        self.send_template(email, "account_locked", &[("failures", &failures), ("minutes", &minutes)]).await
+    }
+
+    // Sent to the address the account had before it was anonymized
+    pub async fn send_account_deleted(&self, email: &str) -> Result<()> {
+        self.send_template(email, "account_deleted", &[]).await
+    }
+
+    pub async fn send_account_deactivated(&self, email: &str) -> Result<()> {
+        self.send_template(email, "account_deactivated", &[]).await
+    }
+
+    pub async fn send_account_reactivated(&self, email: &str) -> Result<()> {
+        self.send_template(email, "account_reactivated", &[]).await
+    }
+
+    pub async fn send_statement_ready(&self, email: &str, period: &str, statement_path: &str) -> Result<()> {
+        self.send_template(email, "statement_ready", &[("period", period), ("statement_path", statement_path)]).await
     }
 
     pub fn create_trading_notification(
@@ -172,30 +215,16 @@ impl NotificationService {
     }
 
     pub async fn send_system_alert(&self, admin_email: &str, alert_message: &str) -> Result<()> {
-        let notification = EmailNotification {
-            to: admin_email.to_string(),
-            subject: "System Alert - Trading SaaS Platform".to_string(),
-            body: format!(
-                r#"
-                <html>
-                <body>
-                    <h2 style="color: red;">System Alert</h2>
-                    <p><strong>Alert Message:</strong></p>
-                    <div style="background-color: #ffe6e6; padding: 10px; border-left: 4px solid #ff0000;">
-                        {}
-                    </div>
-                    <p><strong>Timestamp:</strong> {}</p>
-                    <p>Please investigate this issue immediately.</p>
-                </body>
-                </html>
-                "#,
-                alert_message,
-                chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC")
-            ),
-            is_html: true,
-        };
+        let timestamp = chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC").to_string();
+        self.send_template(admin_email, "system_alert", &[("alert_message", alert_message), ("timestamp", &timestamp)])
+            .await
+    }
+}
 
-        self.send_email(notification).await
+#[async_trait]
+impl Mailer for NotificationService {
+    async fn deliver(&self, email: &EmailNotification) -> Result<()> {
+        NotificationService::deliver(self, email).await
     }
 }
 
@@ -203,6 +232,29 @@ impl NotificationService {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_trade_info_rounds_money() {
+        let mut trade = Trade::new(
+            uuid::Uuid::new_v4(),
+            uuid::Uuid::new_v4(),
+            "EURUSD".to_string(),
+            "BUY".to_string(),
+            0.1,
+            1.0845,
+            None,
+            None,
+            None,
+            None,
+        );
+        trade.exit_price = Some(1.0947000000000002);
+        trade.profit_loss = Some(10.200000000000001);
+
+        assert_eq!(
+            NotificationService::trade_info(&trade, "USD"),
+            "BUY 0.1 EURUSD\nEntry: 1.08450\nExit: 1.09470\nProfit/Loss: 10.20 USD"
+        );
+    }
+
     #[test]
     fn test_notification_creation() {
         let service = NotificationService::new(None, None, None);
@@ -0,0 +1,91 @@
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::errors::{AppError, Result};
+
+pub const PROVIDER_GOOGLE: &str = "google";
+pub const PROVIDER_GITHUB: &str = "github";
+pub const PROVIDER_APPLE: &str = "apple";
+
+// Who a provider says signed in. `external_id` is the provider's own account id, which stays the
+// same when the account's email changes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExternalUser {
+    pub external_id: String,
+    pub email: String,
+    pub name: Option<String>,
+    pub avatar_url: Option<String>,
+}
+
+#[async_trait]
+pub trait OAuthProvider: Send + Sync {
+    // As it appears in /api/v1/auth/oauth/:provider and linked_accounts.provider
+    fn name(&self) -> &'static str;
+    // The auth event type sign-ins through this provider are recorded under
+    fn login_event(&self) -> &'static str;
+    // The account behind a token the provider issued to this app, with an email it has verified.
+    // Auth errors for anything else.
+    async fn verify(&self, token: &str) -> Result<ExternalUser>;
+}
+
+// The sign-in providers configured for this deployment, by name
+#[derive(Default, Clone)]
+pub struct OAuthProviders {
+    providers: BTreeMap<&'static str, Arc<dyn OAuthProvider>>,
+}
+
+impl OAuthProviders {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_provider(mut self, provider: Arc<dyn OAuthProvider>) -> Self {
+        self.providers.insert(provider.name(), provider);
+        self
+    }
+
+    // A provider that is unknown or not configured here is a 404, like a missing route
+    pub fn get(&self, name: &str) -> Result<Arc<dyn OAuthProvider>> {
+        self.providers
+            .get(name.trim().to_lowercase().as_str())
+            .cloned()
+            .ok_or_else(|| AppError::NotFound(format!("Sign-in with '{}' is not available", name)))
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.providers.keys().copied().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fixed(&'static str);
+
+    #[async_trait]
+    impl OAuthProvider for Fixed {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+
+        fn login_event(&self) -> &'static str {
+            "login"
+        }
+
+        async fn verify(&self, token: &str) -> Result<ExternalUser> {
+            Ok(ExternalUser { external_id: token.to_string(), email: format!("{}@example.com", token), name: None, avatar_url: None })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_providers_are_found_by_name_and_others_are_not_found() {
+        let providers = OAuthProviders::new().with_provider(Arc::new(Fixed(PROVIDER_GITHUB))).with_provider(Arc::new(Fixed(PROVIDER_APPLE)));
+        assert_eq!(providers.names(), [PROVIDER_APPLE, PROVIDER_GITHUB]);
+
+        let github = providers.get("GitHub").unwrap();
+        assert_eq!(github.verify("42").await.unwrap().external_id, "42");
+        assert!(matches!(providers.get(PROVIDER_GOOGLE), Err(AppError::NotFound(_))));
+    }
+}
@@ -0,0 +1,337 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use sqlx::PgPool;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{
+    errors::Result,
+    models::{OnboardingCandidate, OnboardingEmail},
+    services::NotificationService,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnboardingStep {
+    ConnectBroker,
+    CreateRobot,
+    StartPaperTrading,
+}
+
+impl OnboardingStep {
+    pub const ALL: [OnboardingStep; 3] = [
+        OnboardingStep::ConnectBroker,
+        OnboardingStep::CreateRobot,
+        OnboardingStep::StartPaperTrading,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OnboardingStep::ConnectBroker => "connect_broker",
+            OnboardingStep::CreateRobot => "create_robot",
+            OnboardingStep::StartPaperTrading => "start_paper_trading",
+        }
+    }
+
+    // Days after registration the email goes out
+    pub fn day(&self) -> i64 {
+        match self {
+            OnboardingStep::ConnectBroker => 1,
+            OnboardingStep::CreateRobot => 3,
+            OnboardingStep::StartPaperTrading => 7,
+        }
+    }
+
+    fn is_done(&self, candidate: &OnboardingCandidate) -> bool {
+        match self {
+            OnboardingStep::ConnectBroker => candidate.has_broker,
+            OnboardingStep::CreateRobot => candidate.has_robot,
+            OnboardingStep::StartPaperTrading => candidate.has_trade,
+        }
+    }
+
+    pub fn subject(&self) -> &'static str {
+        match self {
+            OnboardingStep::ConnectBroker => "Connect your broker to get started",
+            OnboardingStep::CreateRobot => "Create your first trading robot",
+            OnboardingStep::StartPaperTrading => "Try your robot with paper trading",
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            OnboardingStep::ConnectBroker => {
+                "Link your MT5 account from the Brokers page. It takes a couple of minutes and your credentials are stored encrypted."
+            }
+            OnboardingStep::CreateRobot => {
+                "Pick a strategy, set your risk limits and your robot is ready to trade on your connected account."
+            }
+            OnboardingStep::StartPaperTrading => {
+                "Start your robot on a demo account to see how it trades before any real money is involved."
+            }
+        }
+    }
+}
+
+#[async_trait]
+pub trait OnboardingEnv: Send + Sync {
+    async fn candidates(&self, since: DateTime<Utc>) -> Result<Vec<OnboardingCandidate>>;
+    // Records the step as sent; false if it already was
+    async fn claim(&self, user_id: Uuid, step: OnboardingStep) -> Result<bool>;
+    async fn release(&self, user_id: Uuid, step: OnboardingStep) -> Result<()>;
+    async fn send(&self, candidate: &OnboardingCandidate, step: OnboardingStep) -> Result<()>;
+}
+
+pub struct PgOnboardingEnv {
+    pool: PgPool,
+    notifications: Arc<NotificationService>,
+    public_base_url: String,
+}
+
+impl PgOnboardingEnv {
+    pub fn new(pool: PgPool, notifications: Arc<NotificationService>, public_base_url: &str) -> Self {
+        PgOnboardingEnv {
+            pool,
+            notifications,
+            public_base_url: public_base_url.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl OnboardingEnv for PgOnboardingEnv {
+    async fn candidates(&self, since: DateTime<Utc>) -> Result<Vec<OnboardingCandidate>> {
+        OnboardingEmail::find_candidates(&self.pool, since).await
+    }
+
+    async fn claim(&self, user_id: Uuid, step: OnboardingStep) -> Result<bool> {
+        OnboardingEmail::claim(&self.pool, user_id, step.as_str()).await
+    }
+
+    async fn release(&self, user_id: Uuid, step: OnboardingStep) -> Result<()> {
+        OnboardingEmail::release(&self.pool, user_id, step.as_str()).await
+    }
+
+    async fn send(&self, candidate: &OnboardingCandidate, step: OnboardingStep) -> Result<()> {
+        let unsubscribe_url = format!(
+            "{}/api/v1/public/unsubscribe?token={}",
+            self.public_base_url, candidate.unsubscribe_token
+        );
+        self.notifications
+            .send_onboarding_email(&candidate.email, step.subject(), step.message(), &unsubscribe_url)
+            .await
+    }
+}
+
+pub struct OnboardingService;
+
+impl OnboardingService {
+    // The step whose day has most recently come, unless it was already sent or the user
+    // has done it. Earlier steps are never sent late, so a missed run cannot send a burst.
+    pub fn next_step(candidate: &OnboardingCandidate, now: DateTime<Utc>) -> Option<OnboardingStep> {
+        if candidate.has_trade {
+            return None;
+        }
+
+        let age = now - candidate.created_at;
+        let step = OnboardingStep::ALL
+            .iter()
+            .rev()
+            .find(|step| age >= Duration::days(step.day()))?;
+
+        let sent = candidate.sent_steps.iter().any(|s| s == step.as_str());
+        if sent || step.is_done(candidate) {
+            return None;
+        }
+        Some(*step)
+    }
+
+    // Scheduler job; returns how many emails went out
+    pub async fn process(env: &dyn OnboardingEnv, now: DateTime<Utc>) -> Result<usize> {
+        let last_day = OnboardingStep::ALL.iter().map(|step| step.day()).max().unwrap_or(0);
+        let since = now - Duration::days(last_day + 1);
+        let mut sent = 0;
+
+        for candidate in env.candidates(since).await? {
+            let Some(step) = Self::next_step(&candidate, now) else {
+                continue;
+            };
+            if !env.claim(candidate.user_id, step).await? {
+                continue;
+            }
+
+            match env.send(&candidate, step).await {
+                Ok(()) => sent += 1,
+                Err(e) => {
+                    tracing::warn!("Onboarding email {} to user {} failed: {}", step.as_str(), candidate.user_id, e);
+                    env.release(candidate.user_id, step).await?;
+                }
+            }
+        }
+
+        Ok(sent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    // A user's progress over time: each field is when they did it, if ever
+    #[derive(Clone, Default)]
+    struct Timeline {
+        broker_at: Option<i64>,
+        robot_at: Option<i64>,
+        trade_at: Option<i64>,
+        unsubscribed_at: Option<i64>,
+    }
+
+    struct FakeEnv {
+        start: DateTime<Utc>,
+        clock: Mutex<DateTime<Utc>>,
+        users: Vec<(Uuid, &'static str, Timeline)>,
+        sent_steps: Mutex<HashMap<Uuid, Vec<OnboardingStep>>>,
+        outbox: Mutex<Vec<(&'static str, OnboardingStep, i64)>>,
+    }
+
+    impl FakeEnv {
+        fn new(users: Vec<(&'static str, Timeline)>) -> Self {
+            FakeEnv {
+                start: Utc.with_ymd_and_hms(2024, 3, 1, 9, 30, 0).unwrap(),
+                clock: Mutex::new(Utc.with_ymd_and_hms(2024, 3, 1, 9, 30, 0).unwrap()),
+                users: users.into_iter().map(|(name, t)| (Uuid::new_v4(), name, t)).collect(),
+                sent_steps: Mutex::new(HashMap::new()),
+                outbox: Mutex::new(Vec::new()),
+            }
+        }
+
+        fn day(&self) -> i64 {
+            (*self.clock.lock().unwrap() - self.start).num_days()
+        }
+
+        fn emails_for(&self, name: &str) -> Vec<(OnboardingStep, i64)> {
+            self.outbox
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(n, _, _)| *n == name)
+                .map(|(_, step, day)| (*step, *day))
+                .collect()
+        }
+    }
+
+    #[async_trait]
+    impl OnboardingEnv for FakeEnv {
+        async fn candidates(&self, since: DateTime<Utc>) -> Result<Vec<OnboardingCandidate>> {
+            let day = self.day();
+            let done = |at: Option<i64>| at.is_some_and(|at| at <= day);
+            let sent_steps = self.sent_steps.lock().unwrap();
+
+            Ok(self
+                .users
+                .iter()
+                .filter(|(_, _, t)| self.start >= since && !done(t.unsubscribed_at) && !done(t.trade_at))
+                .map(|(id, name, t)| OnboardingCandidate {
+                    user_id: *id,
+                    email: format!("{}@example.com", name),
+                    created_at: self.start,
+                    unsubscribe_token: Uuid::new_v4(),
+                    has_broker: done(t.broker_at),
+                    has_robot: done(t.robot_at),
+                    has_trade: done(t.trade_at),
+                    sent_steps: sent_steps
+                        .get(id)
+                        .map(|steps| steps.iter().map(|s| s.as_str().to_string()).collect())
+                        .unwrap_or_default(),
+                })
+                .collect())
+        }
+
+        async fn claim(&self, user_id: Uuid, step: OnboardingStep) -> Result<bool> {
+            let mut sent_steps = self.sent_steps.lock().unwrap();
+            let steps = sent_steps.entry(user_id).or_default();
+            if steps.contains(&step) {
+                return Ok(false);
+            }
+            steps.push(step);
+            Ok(true)
+        }
+
+        async fn release(&self, user_id: Uuid, step: OnboardingStep) -> Result<()> {
+            if let Some(steps) = self.sent_steps.lock().unwrap().get_mut(&user_id) {
+                steps.retain(|s| *s != step);
+            }
+            Ok(())
+        }
+
+        async fn send(&self, candidate: &OnboardingCandidate, step: OnboardingStep) -> Result<()> {
+            let name = self.users.iter().find(|(id, _, _)| *id == candidate.user_id).unwrap().1;
+            let day = self.day();
+            self.outbox.lock().unwrap().push((name, step, day));
+            Ok(())
+        }
+    }
+
+    async fn run_for_days(env: &FakeEnv, days: i64) {
+        // The scheduler runs every 15 minutes
+        let ticks = days * 24 * 4;
+        for _ in 0..ticks {
+            let now = *env.clock.lock().unwrap();
+            OnboardingService::process(env, now).await.unwrap();
+            *env.clock.lock().unwrap() = now + Duration::minutes(15);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sequence_follows_each_users_progress() {
+        use OnboardingStep::*;
+
+        let env = FakeEnv::new(vec![
+            ("idle", Timeline::default()),
+            ("connected_day_2", Timeline { broker_at: Some(2), ..Default::default() }),
+            ("set_up_on_day_0", Timeline { broker_at: Some(0), robot_at: Some(0), ..Default::default() }),
+            ("traded_day_5", Timeline { broker_at: Some(0), trade_at: Some(5), ..Default::default() }),
+            ("unsubscribed_day_2", Timeline { unsubscribed_at: Some(2), ..Default::default() }),
+        ]);
+
+        run_for_days(&env, 12).await;
+
+        assert_eq!(
+            env.emails_for("idle"),
+            vec![(ConnectBroker, 1), (CreateRobot, 3), (StartPaperTrading, 7)]
+        );
+        assert_eq!(
+            env.emails_for("connected_day_2"),
+            vec![(ConnectBroker, 1), (CreateRobot, 3), (StartPaperTrading, 7)]
+        );
+        assert_eq!(env.emails_for("set_up_on_day_0"), vec![(StartPaperTrading, 7)]);
+        assert_eq!(env.emails_for("traded_day_5"), vec![(CreateRobot, 3)]);
+        assert_eq!(env.emails_for("unsubscribed_day_2"), vec![(ConnectBroker, 1)]);
+    }
+
+    #[test]
+    fn test_missed_runs_only_send_the_latest_step() {
+        let candidate = OnboardingCandidate {
+            user_id: Uuid::new_v4(),
+            email: "late@example.com".to_string(),
+            created_at: Utc.with_ymd_and_hms(2024, 3, 1, 9, 30, 0).unwrap(),
+            unsubscribe_token: Uuid::new_v4(),
+            has_broker: false,
+            has_robot: false,
+            has_trade: false,
+            sent_steps: vec![],
+        };
+        let day = |d: i64| candidate.created_at + Duration::days(d);
+
+        assert_eq!(OnboardingService::next_step(&candidate, day(0)), None);
+        assert_eq!(OnboardingService::next_step(&candidate, day(4)), Some(OnboardingStep::CreateRobot));
+
+        let done = OnboardingCandidate { has_robot: true, ..candidate.clone() };
+        assert_eq!(OnboardingService::next_step(&done, day(4)), None);
+
+        let traded = OnboardingCandidate { has_trade: true, ..candidate.clone() };
+        assert_eq!(OnboardingService::next_step(&traded, day(7)), None);
+    }
+}
@@ -0,0 +1,258 @@
+use async_trait::async_trait;
+use serde::Serialize;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+use uuid::Uuid;
+
+use crate::{
+    errors::{AppError, Result},
+    models::Trade,
+};
+
+pub const DEFAULT_ORDER_DRAIN_SECONDS: u64 = 20;
+// Sent to the broker, outcome unknown; restart recovery opens or cancels it
+pub const EXECUTION_PENDING: &str = "execution_pending";
+
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+#[async_trait]
+pub trait OrderStore: Send + Sync {
+    // Inserts the trade, or settles the execution_pending row written for it at the deadline
+    async fn record(&self, trade: &Trade, created_via: &str) -> Result<()>;
+}
+
+pub struct PgOrderStore {
+    pool: PgPool,
+}
+
+impl PgOrderStore {
+    pub fn new(pool: PgPool) -> Self {
+        PgOrderStore { pool }
+    }
+}
+
+#[async_trait]
+impl OrderStore for PgOrderStore {
+    async fn record(&self, trade: &Trade, created_via: &str) -> Result<()> {
+        Trade::record_order(&self.pool, trade, created_via).await
+    }
+}
+
+struct InFlightOrder {
+    trade: Trade,
+    created_via: String,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct DrainReport {
+    pub completed: usize,
+    pub execution_pending: usize,
+}
+
+// Shutdown barrier for broker orders. Every placement goes through `place`, which tracks it until
+// the trade is stored; once a shutdown starts new placements are refused and `drain` waits for
+// the tracked ones, writing any still unresolved at the deadline as execution_pending.
+pub struct OrderDrain {
+    store: Arc<dyn OrderStore>,
+    draining: AtomicBool,
+    in_flight: Mutex<HashMap<Uuid, InFlightOrder>>,
+    settled: Notify,
+}
+
+// Untracks the order however `place` ends, including when the request is dropped
+struct Settle<'a> {
+    drain: &'a OrderDrain,
+    trade_id: Uuid,
+}
+
+impl Drop for Settle<'_> {
+    fn drop(&mut self) {
+        self.drain.in_flight.lock().unwrap().remove(&self.trade_id);
+        self.drain.settled.notify_waiters();
+    }
+}
+
+impl OrderDrain {
+    pub fn new(store: Arc<dyn OrderStore>) -> Self {
+        OrderDrain {
+            store,
+            draining: AtomicBool::new(false),
+            in_flight: Mutex::new(HashMap::new()),
+            settled: Notify::new(),
+        }
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.lock().unwrap().len()
+    }
+
+    // Sends the order with `place`, which returns the broker ticket, and stores the trade with it
+    pub async fn place<F>(&self, mut trade: Trade, created_via: &str, place: F) -> Result<Trade>
+    where
+        F: Future<Output = Result<i64>>,
+    {
+        {
+            // Checked under the lock `drain` flips the flag under, so no order slips past it
+            let mut in_flight = self.in_flight.lock().unwrap();
+            if self.draining.load(Ordering::SeqCst) {
+                return Err(AppError::BrokerUnavailable(
+                    "The server is restarting; retry the order in a moment".to_string(),
+                ));
+            }
+            in_flight.insert(trade.id, InFlightOrder { trade: trade.clone(), created_via: created_via.to_string() });
+        }
+        let _settle = Settle { drain: self, trade_id: trade.id };
+
+        let ticket = place.await?;
+        trade.broker_trade_id = Some(ticket.to_string());
+        self.store.record(&trade, created_via).await?;
+        Ok(trade)
+    }
+
+    // Refuses new orders and waits up to `timeout` for the ones in flight
+    pub async fn drain(&self, timeout: Duration) -> DrainReport {
+        let started = {
+            let in_flight = self.in_flight.lock().unwrap();
+            self.draining.store(true, Ordering::SeqCst);
+            in_flight.len()
+        };
+        tracing::info!("Draining {} in-flight broker order(s)", started);
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            // Registered before the count is read so a settle in between is not missed
+            let settled = self.settled.notified();
+            let remaining = self.in_flight();
+            let now = Instant::now();
+            if remaining == 0 || now >= deadline {
+                break;
+            }
+            tracing::info!(
+                "Waiting for {} broker order(s), {}s until they are left for recovery",
+                remaining,
+                (deadline - now).as_secs()
+            );
+            let _ = tokio::time::timeout(PROGRESS_INTERVAL.min(deadline - now), settled).await;
+        }
+
+        let unresolved: Vec<(Trade, String)> = self
+            .in_flight
+            .lock()
+            .unwrap()
+            .values()
+            .map(|order| (order.trade.clone(), order.created_via.clone()))
+            .collect();
+        for (mut trade, created_via) in unresolved.iter().cloned() {
+            trade.status = EXECUTION_PENDING.to_string();
+            if let Err(e) = self.store.record(&trade, &created_via).await {
+                tracing::error!("Could not record unresolved order for trade {}: {}", trade.id, e);
+            }
+        }
+
+        let report = DrainReport {
+            completed: started.saturating_sub(unresolved.len()),
+            execution_pending: unresolved.len(),
+        };
+        tracing::info!(
+            "Broker orders drained: {} completed, {} left as execution_pending",
+            report.completed,
+            report.execution_pending
+        );
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct FakeStore {
+        trades: Mutex<HashMap<Uuid, Trade>>,
+    }
+
+    impl FakeStore {
+        fn status(&self, id: Uuid) -> Option<(String, Option<String>)> {
+            self.trades.lock().unwrap().get(&id).map(|t| (t.status.clone(), t.broker_trade_id.clone()))
+        }
+    }
+
+    #[async_trait]
+    impl OrderStore for FakeStore {
+        async fn record(&self, trade: &Trade, _created_via: &str) -> Result<()> {
+            let mut trades = self.trades.lock().unwrap();
+            match trades.get(&trade.id) {
+                Some(existing) if existing.status != EXECUTION_PENDING => {}
+                _ => {
+                    trades.insert(trade.id, trade.clone());
+                }
+            }
+            Ok(())
+        }
+    }
+
+    fn trade() -> Trade {
+        Trade::new(Uuid::new_v4(), Uuid::new_v4(), "EURUSD".to_string(), "buy".to_string(), 0.1, 1.1, None, None, None, None)
+    }
+
+    async fn slow_order(ticket: i64, delay: Duration) -> Result<i64> {
+        tokio::time::sleep(delay).await;
+        Ok(ticket)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_shutdown_waits_for_an_order_in_flight() {
+        let store = Arc::new(FakeStore::default());
+        let drain = Arc::new(OrderDrain::new(store.clone()));
+        let slow = trade();
+        let slow_id = slow.id;
+
+        let order = {
+            let drain = drain.clone();
+            tokio::spawn(async move { drain.place(slow, "web/1.0", slow_order(42, Duration::from_secs(3))).await })
+        };
+        tokio::task::yield_now().await;
+        assert_eq!(drain.in_flight(), 1);
+
+        let started = Instant::now();
+        let report = drain.drain(Duration::from_secs(10)).await;
+
+        // The drain returned once the late response was stored, well before its deadline
+        assert_eq!(report, DrainReport { completed: 1, execution_pending: 0 });
+        assert_eq!(started.elapsed(), Duration::from_secs(3));
+        assert_eq!(store.status(slow_id), Some(("open".to_string(), Some("42".to_string()))));
+        assert!(order.await.unwrap().is_ok());
+
+        // New orders are refused with a retryable error
+        let refused = drain.place(trade(), "web/1.0", slow_order(43, Duration::ZERO)).await;
+        assert!(matches!(refused, Err(AppError::BrokerUnavailable(_))));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_order_unresolved_at_the_deadline_is_left_execution_pending() {
+        let store = Arc::new(FakeStore::default());
+        let drain = Arc::new(OrderDrain::new(store.clone()));
+        let stuck = trade();
+        let stuck_id = stuck.id;
+
+        let order = {
+            let drain = drain.clone();
+            tokio::spawn(async move { drain.place(stuck, "web/1.0", slow_order(77, Duration::from_secs(60))).await })
+        };
+        tokio::task::yield_now().await;
+
+        let report = drain.drain(Duration::from_secs(5)).await;
+        assert_eq!(report, DrainReport { completed: 0, execution_pending: 1 });
+        assert_eq!(store.status(stuck_id), Some((EXECUTION_PENDING.to_string(), None)));
+
+        // Should the process live long enough, the broker's answer still settles the row
+        order.await.unwrap().unwrap();
+        assert_eq!(store.status(stuck_id), Some(("open".to_string(), Some("77".to_string()))));
+    }
+}
@@ -0,0 +1,354 @@
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::errors::{AppError, FieldError, Result};
+
+pub const DEFAULT_MIN_PASSWORD_LENGTH: usize = 8;
+pub const DEFAULT_MAX_PASSWORD_LENGTH: usize = 128;
+pub const DEFAULT_BREACH_CHECK_TIMEOUT_MS: u64 = 1500;
+pub const HIBP_RANGE_URL: &str = "https://api.pwnedpasswords.com/range";
+
+pub const RULE_MIN_LENGTH: &str = "min_length";
+pub const RULE_MAX_LENGTH: &str = "max_length";
+pub const RULE_CHARACTER_CLASSES: &str = "character_classes";
+pub const RULE_EMAIL_LOCAL_PART: &str = "email_local_part";
+pub const RULE_COMMON_PASSWORD: &str = "common_password";
+pub const RULE_BREACHED: &str = "breached";
+
+// One password per line, lowercased; lines starting with # are comments
+const COMMON_PASSWORDS: &str = include_str!("../../data/common_passwords.txt");
+
+// What a new password has to meet. Served as-is so the frontend can check before submitting;
+// the common-password and breach checks only run on the server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+    // At least one lowercase letter, uppercase letter, digit and symbol
+    pub require_character_classes: bool,
+    // Refuses the part of the email before the @, ignoring case
+    pub reject_email_local_part: bool,
+    // Whether passwords are also looked up in the Have I Been Pwned breach corpus
+    pub breach_check: bool,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        PasswordPolicy {
+            min_length: DEFAULT_MIN_PASSWORD_LENGTH,
+            max_length: DEFAULT_MAX_PASSWORD_LENGTH,
+            require_character_classes: false,
+            reject_email_local_part: true,
+            breach_check: false,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    // Every local rule the password breaks, so the form can list them together
+    pub fn violations(&self, password: &str, email: &str) -> Vec<FieldError> {
+        let mut failures = Vec::new();
+        let length = password.chars().count();
+        if length < self.min_length {
+            failures.push(password_error(
+                RULE_MIN_LENGTH,
+                format!("Must be at least {} characters", self.min_length),
+            ));
+        }
+        if length > self.max_length {
+            failures.push(password_error(
+                RULE_MAX_LENGTH,
+                format!("Must be at most {} characters", self.max_length),
+            ));
+        }
+
+        if self.require_character_classes {
+            let missing: Vec<&str> = [
+                ("a lowercase letter", password.chars().any(char::is_lowercase)),
+                ("an uppercase letter", password.chars().any(char::is_uppercase)),
+                ("a digit", password.chars().any(|c| c.is_ascii_digit())),
+                ("a symbol", password.chars().any(|c| !c.is_alphanumeric())),
+            ]
+            .into_iter()
+            .filter(|(_, present)| !present)
+            .map(|(class, _)| class)
+            .collect();
+            if !missing.is_empty() {
+                failures.push(password_error(
+                    RULE_CHARACTER_CLASSES,
+                    format!("Must contain {}", missing.join(", ")),
+                ));
+            }
+        }
+
+        if self.reject_email_local_part {
+            let local_part = email.split('@').next().unwrap_or_default().trim();
+            if !local_part.is_empty() && password.eq_ignore_ascii_case(local_part) {
+                failures.push(password_error(RULE_EMAIL_LOCAL_PART, "Must not be the name in your email address".to_string()));
+            }
+        }
+        failures
+    }
+}
+
+fn password_error(rule: &str, message: String) -> FieldError {
+    FieldError { field: "password".to_string(), rule: rule.to_string(), message }
+}
+
+#[async_trait]
+pub trait BreachLookup: Send + Sync {
+    // How many times the password appears in known breaches
+    async fn times_seen(&self, password: &str) -> Result<u64>;
+}
+
+// The k-anonymity range API: only the first five hex digits of the SHA-1 leave the server, and
+// the suffix is matched locally against the answer. Padding hides how many suffixes matched.
+pub struct HibpRange {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl HibpRange {
+    pub fn new(url: String) -> Self {
+        HibpRange { client: reqwest::Client::new(), url }
+    }
+}
+
+#[async_trait]
+impl BreachLookup for HibpRange {
+    async fn times_seen(&self, password: &str) -> Result<u64> {
+        let hash = hex::encode_upper(Sha1::digest(password.as_bytes()));
+        let (prefix, suffix) = hash.split_at(5);
+        let response = self
+            .client
+            .get(format!("{}/{}", self.url.trim_end_matches('/'), prefix))
+            .header("Add-Padding", "true")
+            .send()
+            .await
+            .map_err(|e| AppError::External(format!("Breach check failed: {}", e)))?;
+        if !response.status().is_success() {
+            return Err(AppError::External(format!("Breach check answered {}", response.status())));
+        }
+        let body = response
+            .text()
+            .await
+            .map_err(|e| AppError::External(format!("Unreadable breach check answer: {}", e)))?;
+        Ok(count_in_range(&body, suffix))
+    }
+}
+
+// Lines are SUFFIX:COUNT; padding entries have a count of 0
+fn count_in_range(body: &str, suffix: &str) -> u64 {
+    body.lines()
+        .filter_map(|line| line.trim().split_once(':'))
+        .find(|(candidate, _)| candidate.eq_ignore_ascii_case(suffix))
+        .and_then(|(_, count)| count.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+pub struct PasswordChecker {
+    policy: PasswordPolicy,
+    common: HashSet<&'static str>,
+    breaches: Option<Arc<dyn BreachLookup>>,
+    breach_timeout: Duration,
+}
+
+impl PasswordChecker {
+    pub fn new(policy: PasswordPolicy) -> Self {
+        let common = COMMON_PASSWORDS
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .collect();
+        PasswordChecker {
+            policy,
+            common,
+            breaches: None,
+            breach_timeout: Duration::from_millis(DEFAULT_BREACH_CHECK_TIMEOUT_MS),
+        }
+    }
+
+    pub fn with_breach_lookup(mut self, lookup: Arc<dyn BreachLookup>, timeout: Duration) -> Self {
+        self.breaches = Some(lookup);
+        self.breach_timeout = timeout;
+        self
+    }
+
+    pub fn policy(&self) -> &PasswordPolicy {
+        &self.policy
+    }
+
+    pub fn is_common(&self, password: &str) -> bool {
+        self.common.contains(password.to_lowercase().as_str())
+    }
+
+    // The breach lookup only runs once the local rules pass, and fails open: a slow or broken
+    // range API must not keep people from signing up
+    pub async fn check(&self, password: &str, email: &str) -> Result<()> {
+        let mut failures = self.policy.violations(password, email);
+        if self.is_common(password) {
+            failures.push(password_error(RULE_COMMON_PASSWORD, "Is too common, choose a less predictable one".to_string()));
+        }
+
+        if failures.is_empty() && self.policy.breach_check {
+            if let Some(breaches) = &self.breaches {
+                match tokio::time::timeout(self.breach_timeout, breaches.times_seen(password)).await {
+                    Ok(Ok(0)) => {}
+                    Ok(Ok(times)) => failures.push(password_error(
+                        RULE_BREACHED,
+                        format!("Has appeared in {} known data breaches, choose another one", times),
+                    )),
+                    Ok(Err(e)) => tracing::warn!("Password breach check skipped: {}", e),
+                    Err(_) => tracing::warn!("Password breach check skipped: no answer within {:?}", self.breach_timeout),
+                }
+            }
+        }
+
+        if failures.is_empty() {
+            return Ok(());
+        }
+        Err(AppError::InvalidFields {
+            message: "The password does not meet the password policy".to_string(),
+            fields: failures,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const EMAIL: &str = "jane.doe@example.com";
+
+    fn rules(result: Result<()>) -> Vec<String> {
+        match result {
+            Ok(()) => Vec::new(),
+            Err(AppError::InvalidFields { fields, .. }) => fields.into_iter().map(|f| f.rule).collect(),
+            Err(e) => panic!("unexpected error: {}", e),
+        }
+    }
+
+    fn strict() -> PasswordPolicy {
+        PasswordPolicy { min_length: 12, max_length: 20, require_character_classes: true, ..PasswordPolicy::default() }
+    }
+
+    // Answers a fixed count after an optional delay, or fails
+    struct FakeBreaches {
+        times: Option<u64>,
+        delay: Duration,
+        calls: AtomicUsize,
+    }
+
+    impl FakeBreaches {
+        fn new(times: Option<u64>, delay: Duration) -> Arc<Self> {
+            Arc::new(FakeBreaches { times, delay, calls: AtomicUsize::new(0) })
+        }
+    }
+
+    #[async_trait]
+    impl BreachLookup for FakeBreaches {
+        async fn times_seen(&self, _password: &str) -> Result<u64> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(self.delay).await;
+            self.times.ok_or_else(|| AppError::External("range API down".to_string()))
+        }
+    }
+
+    fn with_breaches(breaches: Arc<FakeBreaches>) -> PasswordChecker {
+        PasswordChecker::new(PasswordPolicy { breach_check: true, ..PasswordPolicy::default() })
+            .with_breach_lookup(breaches, Duration::from_millis(DEFAULT_BREACH_CHECK_TIMEOUT_MS))
+    }
+
+    #[tokio::test]
+    async fn test_length_limits() {
+        let checker = PasswordChecker::new(strict());
+        assert_eq!(rules(checker.check("Sh0rt!pass", EMAIL).await), vec![RULE_MIN_LENGTH]);
+        assert_eq!(rules(checker.check("Way-t00-long-for-the-policy", EMAIL).await), vec![RULE_MAX_LENGTH]);
+        assert!(checker.check("Just-l0ng-enough", EMAIL).await.is_ok());
+
+        // Counted in characters, not bytes
+        let checker = PasswordChecker::new(PasswordPolicy { max_length: 10, ..PasswordPolicy::default() });
+        assert!(checker.check("ééééééééé", EMAIL).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_character_classes_name_what_is_missing() {
+        let checker = PasswordChecker::new(strict());
+        let err = checker.check("only-lowercase-here", EMAIL).await.unwrap_err();
+        let AppError::InvalidFields { fields, .. } = err else { panic!("expected field errors") };
+        assert_eq!(fields.len(), 1);
+        assert_eq!((fields[0].field.as_str(), fields[0].rule.as_str()), ("password", RULE_CHARACTER_CLASSES));
+        assert_eq!(fields[0].message, "Must contain an uppercase letter, a digit");
+
+        assert!(PasswordChecker::new(PasswordPolicy::default()).check("only-lowercase-here", EMAIL).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_email_local_part_is_refused_unless_disabled() {
+        let checker = PasswordChecker::new(PasswordPolicy::default());
+        assert_eq!(rules(checker.check("Jane.Doe", EMAIL).await), vec![RULE_EMAIL_LOCAL_PART]);
+        assert!(checker.check("jane.doe-2024", EMAIL).await.is_ok());
+
+        let checker = PasswordChecker::new(PasswordPolicy { reject_email_local_part: false, ..PasswordPolicy::default() });
+        assert!(checker.check("jane.doe", EMAIL).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_common_passwords_are_refused_in_any_case() {
+        let checker = PasswordChecker::new(PasswordPolicy::default());
+        assert_eq!(rules(checker.check("password", EMAIL).await), vec![RULE_COMMON_PASSWORD]);
+        assert_eq!(rules(checker.check("QWERTYUIOP", EMAIL).await), vec![RULE_COMMON_PASSWORD]);
+        assert!(checker.common.iter().all(|p| !p.starts_with('#')));
+    }
+
+    #[tokio::test]
+    async fn test_every_failed_rule_is_reported() {
+        let checker = PasswordChecker::new(strict());
+        assert_eq!(
+            rules(checker.check("monkey", "monkey@example.com").await),
+            vec![RULE_MIN_LENGTH, RULE_CHARACTER_CLASSES, RULE_EMAIL_LOCAL_PART, RULE_COMMON_PASSWORD],
+        );
+    }
+
+    #[tokio::test]
+    async fn test_breached_passwords_are_refused() {
+        let breaches = FakeBreaches::new(Some(42), Duration::ZERO);
+        let checker = with_breaches(breaches.clone());
+        assert_eq!(rules(checker.check("correct horse battery", EMAIL).await), vec![RULE_BREACHED]);
+
+        // Not looked up when a local rule already failed
+        assert_eq!(rules(checker.check("short", EMAIL).await), vec![RULE_MIN_LENGTH]);
+        assert_eq!(breaches.calls.load(Ordering::SeqCst), 1);
+
+        // Nor when the policy leaves it off
+        let off = PasswordChecker::new(PasswordPolicy::default()).with_breach_lookup(breaches.clone(), Duration::from_secs(1));
+        assert!(off.check("correct horse battery", EMAIL).await.is_ok());
+        assert_eq!(breaches.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_breach_check_fails_open_on_timeout_and_errors() {
+        let slow = FakeBreaches::new(Some(42), Duration::from_secs(30));
+        assert!(with_breaches(slow.clone()).check("correct horse battery", EMAIL).await.is_ok());
+        assert_eq!(slow.calls.load(Ordering::SeqCst), 1);
+
+        let down = FakeBreaches::new(None, Duration::ZERO);
+        assert!(with_breaches(down).check("correct horse battery", EMAIL).await.is_ok());
+    }
+
+    #[test]
+    fn test_range_answer_is_matched_on_the_suffix() {
+        let hash = hex::encode_upper(Sha1::digest(b"password"));
+        assert_eq!(&hash[..5], "5BAA6");
+        let body = "003D68EB55068C33ACE09247EE4C639306B:3\r\n1E4C9B93F3F0682250B6CF8331B7EE68FD8:9545824\r\n\
+                    011053FD0102E94D6AE2F8B83D76FAF94F6:0\r\n";
+        assert_eq!(count_in_range(body, &hash[5..]), 9545824);
+        assert_eq!(count_in_range(body, "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF"), 0);
+    }
+}
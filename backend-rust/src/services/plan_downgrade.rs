@@ -0,0 +1,335 @@
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::Serialize;
+use sqlx::PgPool;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{
+    errors::{AppError, Result},
+    models::{PlanLimitedRobot, RobotLog, Subscription, SubscriptionPlan, TradingRobot, Watchlist},
+    services::{robot_runner::RobotRunnerRegistry, NotificationService},
+};
+
+// A robot the account's plan no longer covers: stopped and kept, until an upgrade reactivates it
+pub const PLAN_LIMITED: &str = "plan_limited";
+
+// Statuses with a live runner; the automatic pick parks robots outside these first
+pub const RUNNING_STATUSES: [&str; 4] = ["active", "paused_risk", "paused_broker", "cooling_down"];
+
+// More of something than the plan allows. Only robots are brought back within the limit; the
+// watchlist limit already applies on its next edit.
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct PlanOverage {
+    pub resource: String,
+    pub limit: i32,
+    pub used: i64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, JsonSchema)]
+pub struct DowngradeReport {
+    pub overages: Vec<PlanOverage>,
+    pub limited: Vec<PlanLimitedRobot>,
+}
+
+// The account's robots and watchlist, and where parked robots are logged and reported
+#[async_trait]
+pub trait PlanDowngradeEnv: Send + Sync {
+    async fn robots(&self, user_id: Uuid) -> Result<Vec<TradingRobot>>;
+    async fn watchlist_size(&self, user_id: Uuid) -> Result<usize>;
+    async fn limit(&self, user_id: Uuid, robot_ids: &[Uuid]) -> Result<Vec<PlanLimitedRobot>>;
+    async fn log(&self, user_id: Uuid, robot: &PlanLimitedRobot, message: &str) -> Result<()>;
+    async fn notify(&self, email: &str, plan: &SubscriptionPlan, robots: &[PlanLimitedRobot]) -> Result<()>;
+}
+
+pub struct PgPlanDowngradeEnv {
+    pool: PgPool,
+    notifications: Arc<NotificationService>,
+}
+
+impl PgPlanDowngradeEnv {
+    pub fn new(pool: PgPool, notifications: Arc<NotificationService>) -> Self {
+        PgPlanDowngradeEnv { pool, notifications }
+    }
+}
+
+#[async_trait]
+impl PlanDowngradeEnv for PgPlanDowngradeEnv {
+    async fn robots(&self, user_id: Uuid) -> Result<Vec<TradingRobot>> {
+        TradingRobot::find_by_user_id(&self.pool, user_id).await
+    }
+
+    async fn watchlist_size(&self, user_id: Uuid) -> Result<usize> {
+        Ok(Watchlist::find_symbols(&self.pool, user_id).await?.len())
+    }
+
+    async fn limit(&self, user_id: Uuid, robot_ids: &[Uuid]) -> Result<Vec<PlanLimitedRobot>> {
+        TradingRobot::limit_by_plan(&self.pool, user_id, robot_ids).await
+    }
+
+    async fn log(&self, user_id: Uuid, robot: &PlanLimitedRobot, message: &str) -> Result<()> {
+        RobotLog::create(&self.pool, robot.id, user_id, "warn", message).await?;
+        Ok(())
+    }
+
+    async fn notify(&self, email: &str, plan: &SubscriptionPlan, robots: &[PlanLimitedRobot]) -> Result<()> {
+        let names: Vec<String> = robots.iter().map(|r| r.name.clone()).collect();
+        self.notifications.send_plan_limited(email, &plan.name, plan.max_robots, &names).await
+    }
+}
+
+// Brings an account back within a lower plan's robot limit once the plan has changed. Limits
+// otherwise only gate creating things, so without this a downgraded account keeps running
+// every robot it had.
+pub struct PlanDowngradeService {
+    env: Arc<dyn PlanDowngradeEnv>,
+}
+
+impl PlanDowngradeService {
+    pub fn new(env: Arc<dyn PlanDowngradeEnv>) -> Self {
+        PlanDowngradeService { env }
+    }
+
+    pub fn overages(plan: &SubscriptionPlan, robots: usize, watchlist_symbols: usize) -> Vec<PlanOverage> {
+        [("robots", plan.max_robots, robots), ("watchlist_symbols", plan.max_watchlist_symbols, watchlist_symbols)]
+            .into_iter()
+            .filter(|&(_, limit, used)| limit >= 0 && used as i64 > limit as i64)
+            .map(|(resource, limit, used)| PlanOverage { resource: resource.to_string(), limit, used: used as i64 })
+            .collect()
+    }
+
+    // The robots to park so the rest fit `plan`. With `keep`, the user's choice: required when
+    // there is one to make, and the kept robots must be theirs and fit the plan. Without it the
+    // pick is automatic: robots not running go first, oldest first.
+    pub fn select(plan: &SubscriptionPlan, robots: &[TradingRobot], keep: Option<&[Uuid]>) -> Result<Vec<Uuid>> {
+        let mut candidates: Vec<&TradingRobot> = robots.iter().filter(|r| r.status != PLAN_LIMITED).collect();
+        if plan.max_robots < 0 || candidates.len() <= plan.max_robots as usize {
+            return Ok(Vec::new());
+        }
+        let allowed = plan.max_robots as usize;
+
+        match keep {
+            Some(keep) => {
+                if let Some(unknown) = keep.iter().find(|id| !candidates.iter().any(|r| r.id == **id)) {
+                    return Err(AppError::Validation(format!("keep_robot_ids: {} is not one of your robots", unknown)));
+                }
+                let mut unique = keep.to_vec();
+                unique.sort();
+                unique.dedup();
+                if unique.len() > allowed {
+                    return Err(AppError::PlanSetting(format!(
+                        "The {} plan allows at most {} trading robot(s), so keep_robot_ids can list {} at most",
+                        plan.name, allowed, allowed
+                    )));
+                }
+                Ok(candidates.iter().filter(|r| !unique.contains(&r.id)).map(|r| r.id).collect())
+            }
+            None => {
+                candidates.sort_by_key(|r| (RUNNING_STATUSES.contains(&r.status.as_str()), r.created_at, r.id));
+                Ok(candidates[..candidates.len() - allowed].iter().map(|r| r.id).collect())
+            }
+        }
+    }
+
+    // What moving to `plan_name` would park, refused before the plan changes when the choice is
+    // the user's to make and they have not made it
+    pub async fn plan_change(&self, user_id: Uuid, plan_name: &str, keep: Option<&[Uuid]>) -> Result<Vec<Uuid>> {
+        let plan = Subscription::plan_details(plan_name);
+        let robots = self.env.robots(user_id).await?;
+        let over = robots.iter().filter(|r| r.status != PLAN_LIMITED).count();
+        if keep.is_none() && plan.max_robots > 0 && over > plan.max_robots as usize {
+            return Err(AppError::PlanSetting(format!(
+                "The {} plan allows at most {} trading robot(s) and you have {}; pass keep_robot_ids with the ones to keep running",
+                plan.name, plan.max_robots, over
+            )));
+        }
+        Self::select(&plan, &robots, keep)
+    }
+
+    // Parks `robot_ids`, stops their runners and tells the user what was paused and why
+    pub async fn apply(
+        &self,
+        user_id: Uuid,
+        email: &str,
+        plan_name: &str,
+        robot_ids: &[Uuid],
+        runners: &RobotRunnerRegistry,
+    ) -> Result<DowngradeReport> {
+        let plan = Subscription::plan_details(plan_name);
+        let limited = if robot_ids.is_empty() { Vec::new() } else { self.env.limit(user_id, robot_ids).await? };
+
+        let message = format!("Stopped: the {} plan allows {} trading robot(s)", plan.name, plan.max_robots);
+        for robot in &limited {
+            runners.stop(robot.id);
+            if let Err(e) = self.env.log(user_id, robot, &message).await {
+                tracing::warn!("Could not write plan limit log for robot {}: {}", robot.id, e);
+            }
+        }
+        if !limited.is_empty() {
+            tracing::info!("Plan change to {} parked {} robot(s) of user {}", plan.name, limited.len(), user_id);
+            if let Err(e) = self.env.notify(email, &plan, &limited).await {
+                tracing::warn!("Could not notify user {} of robots parked by their plan: {}", user_id, e);
+            }
+        }
+
+        let robots = self.env.robots(user_id).await?;
+        let watchlist = self.env.watchlist_size(user_id).await?;
+        let counted = robots.iter().filter(|r| r.status != PLAN_LIMITED).count();
+        Ok(DowngradeReport { overages: Self::overages(&plan, counted, watchlist), limited })
+    }
+
+    // For plan changes with nobody to ask, e.g. a Stripe webhook: the automatic pick
+    pub async fn reconcile(&self, user_id: Uuid, email: &str, plan_name: &str, runners: &RobotRunnerRegistry) -> Result<DowngradeReport> {
+        let robots = self.env.robots(user_id).await?;
+        let robot_ids = Self::select(&Subscription::plan_details(plan_name), &robots, None)?;
+        self.apply(user_id, email, plan_name, &robot_ids, runners).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone, Utc};
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct FakeEnv {
+        robots: Mutex<Vec<TradingRobot>>,
+        watchlist: usize,
+        logs: Mutex<Vec<(Uuid, String)>>,
+        notifications: Mutex<Vec<(String, String, Vec<String>)>>,
+    }
+
+    impl FakeEnv {
+        fn with_robots(user_id: Uuid, robots: &[(&str, &str)]) -> Self {
+            let env = FakeEnv::default();
+            let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+            for (i, (name, status)) in robots.iter().enumerate() {
+                let mut robot = TradingRobot::new(user_id, name.to_string(), "trend_following".to_string());
+                robot.status = status.to_string();
+                robot.created_at = start + Duration::days(i as i64);
+                env.robots.lock().unwrap().push(robot);
+            }
+            env
+        }
+
+        fn id(&self, name: &str) -> Uuid {
+            self.robots.lock().unwrap().iter().find(|r| r.name == name).unwrap().id
+        }
+
+        fn status(&self, name: &str) -> String {
+            self.robots.lock().unwrap().iter().find(|r| r.name == name).unwrap().status.clone()
+        }
+    }
+
+    #[async_trait]
+    impl PlanDowngradeEnv for FakeEnv {
+        async fn robots(&self, _user_id: Uuid) -> Result<Vec<TradingRobot>> {
+            Ok(self.robots.lock().unwrap().clone())
+        }
+
+        async fn watchlist_size(&self, _user_id: Uuid) -> Result<usize> {
+            Ok(self.watchlist)
+        }
+
+        async fn limit(&self, _user_id: Uuid, robot_ids: &[Uuid]) -> Result<Vec<PlanLimitedRobot>> {
+            let mut limited = Vec::new();
+            for robot in self.robots.lock().unwrap().iter_mut() {
+                if robot_ids.contains(&robot.id) && robot.status != PLAN_LIMITED {
+                    limited.push(PlanLimitedRobot { id: robot.id, name: robot.name.clone(), previous_status: robot.status.clone() });
+                    robot.status = PLAN_LIMITED.to_string();
+                }
+            }
+            Ok(limited)
+        }
+
+        async fn log(&self, _user_id: Uuid, robot: &PlanLimitedRobot, message: &str) -> Result<()> {
+            self.logs.lock().unwrap().push((robot.id, message.to_string()));
+            Ok(())
+        }
+
+        async fn notify(&self, email: &str, plan: &SubscriptionPlan, robots: &[PlanLimitedRobot]) -> Result<()> {
+            let names = robots.iter().map(|r| r.name.clone()).collect();
+            self.notifications.lock().unwrap().push((email.to_string(), plan.name.clone(), names));
+            Ok(())
+        }
+    }
+
+    const PRO_ACCOUNT: [(&str, &str); 5] =
+        [("Oldest", "active"), ("Stopped", "stopped"), ("Middle", "active"), ("Never started", "inactive"), ("Newest", "active")];
+
+    #[tokio::test]
+    async fn test_the_user_chooses_what_keeps_running() {
+        let user_id = Uuid::new_v4();
+        let env = Arc::new(FakeEnv::with_robots(user_id, &PRO_ACCOUNT));
+        let service = PlanDowngradeService::new(env.clone());
+        let runners = RobotRunnerRegistry::new();
+
+        // Essential keeps one robot, so which one is the user's call
+        let missing = service.plan_change(user_id, "essential", None).await;
+        assert!(matches!(missing, Err(AppError::PlanSetting(m)) if m.contains("keep_robot_ids")));
+        let too_many = [env.id("Middle"), env.id("Newest")];
+        assert!(matches!(service.plan_change(user_id, "essential", Some(&too_many)).await, Err(AppError::PlanSetting(_))));
+        let unknown = [Uuid::new_v4()];
+        assert!(matches!(service.plan_change(user_id, "essential", Some(&unknown)).await, Err(AppError::Validation(_))));
+
+        let keep = [env.id("Middle")];
+        let parked = service.plan_change(user_id, "essential", Some(&keep)).await.unwrap();
+        assert_eq!(parked.len(), 4);
+        let report = service.apply(user_id, "trader@example.com", "essential", &parked, &runners).await.unwrap();
+        assert_eq!(env.status("Middle"), "active");
+        for name in ["Oldest", "Stopped", "Never started", "Newest"] {
+            assert_eq!(env.status(name), PLAN_LIMITED);
+        }
+        assert!(report.overages.is_empty());
+        let previous: Vec<&str> = report.limited.iter().map(|r| r.previous_status.as_str()).collect();
+        assert_eq!(previous, ["active", "stopped", "inactive", "active"]);
+
+        // One email listing every parked robot; each robot's log says why
+        let notifications = env.notifications.lock().unwrap().clone();
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(notifications[0].1, "Essential");
+        assert_eq!(notifications[0].2, ["Oldest", "Stopped", "Never started", "Newest"]);
+        assert!(env.logs.lock().unwrap().iter().all(|(_, m)| m == "Stopped: the Essential plan allows 1 trading robot(s)"));
+    }
+
+    #[tokio::test]
+    async fn test_without_a_choice_idle_robots_go_first_then_the_oldest() {
+        let user_id = Uuid::new_v4();
+        let env = Arc::new(FakeEnv { watchlist: 12, ..FakeEnv::with_robots(user_id, &PRO_ACCOUNT) });
+        let service = PlanDowngradeService::new(env.clone());
+        let runners = RobotRunnerRegistry::new();
+
+        let report = service.reconcile(user_id, "trader@example.com", "essential", &runners).await.unwrap();
+        assert_eq!(env.status("Newest"), "active");
+        let parked: Vec<&str> = report.limited.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(parked, ["Oldest", "Stopped", "Middle", "Never started"]);
+        // The watchlist is reported, not trimmed
+        assert_eq!(report.overages, [PlanOverage { resource: "watchlist_symbols".to_string(), limit: 10, used: 12 }]);
+
+        // Nothing more to do the second time, and nobody is emailed again
+        let again = service.reconcile(user_id, "trader@example.com", "essential", &runners).await.unwrap();
+        assert!(again.limited.is_empty());
+        assert_eq!(env.notifications.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_the_free_plan_leaves_no_choice_and_upgrades_park_nothing() {
+        let user_id = Uuid::new_v4();
+        let env = Arc::new(FakeEnv::with_robots(user_id, &PRO_ACCOUNT[..2]));
+        let service = PlanDowngradeService::new(env.clone());
+
+        assert!(service.plan_change(user_id, "elite", None).await.unwrap().is_empty());
+        assert!(service.plan_change(user_id, "pro", None).await.unwrap().is_empty());
+        assert_eq!(service.plan_change(user_id, "free", None).await.unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_overages() {
+        let essential = Subscription::plan_details("essential");
+        assert!(PlanDowngradeService::overages(&essential, 1, 10).is_empty());
+        let over = PlanDowngradeService::overages(&essential, 3, 11);
+        assert_eq!(over.iter().map(|o| (o.resource.as_str(), o.used)).collect::<Vec<_>>(), [("robots", 3), ("watchlist_symbols", 11)]);
+        assert!(PlanDowngradeService::overages(&Subscription::plan_details("elite"), 500, 500).is_empty());
+    }
+}
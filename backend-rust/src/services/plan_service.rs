@@ -0,0 +1,284 @@
+use async_trait::async_trait;
+use axum::http::{HeaderMap, HeaderValue};
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    errors::{AppError, Result},
+    models::{Subscription, SubscriptionPlan, Trade, TradeFilter, TradingRobot},
+};
+
+// Set on list responses whose range was cut to the plan's history
+pub const HISTORY_TRUNCATED_HEADER: &str = "x-history-truncated";
+
+// Consulted by robot runners every cycle, so a plan change applies without a restart
+#[async_trait]
+pub trait PlanLimiter: Send + Sync {
+    async fn evaluation_interval(&self, robot_id: Uuid) -> Result<std::time::Duration>;
+}
+
+pub struct PgPlanLimiter {
+    pool: PgPool,
+}
+
+impl PgPlanLimiter {
+    pub fn new(pool: PgPool) -> Self {
+        PgPlanLimiter { pool }
+    }
+}
+
+#[async_trait]
+impl PlanLimiter for PgPlanLimiter {
+    async fn evaluation_interval(&self, robot_id: Uuid) -> Result<std::time::Duration> {
+        let (plan_name, risk_config) = TradingRobot::find_plan_and_risk_config(&self.pool, robot_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound("Trading robot not found".to_string()))?;
+        Ok(PlanService::effective_evaluation_interval(&Subscription::plan_details(&plan_name), &risk_config))
+    }
+}
+
+pub struct PlanService;
+
+impl PlanService {
+    // -1 means unlimited in the plan table
+    fn within_limit(limit: i32, used: i64) -> bool {
+        limit < 0 || used < limit as i64
+    }
+
+    pub fn check_robot_limit(plan: &SubscriptionPlan, existing_robots: usize) -> Result<()> {
+        if Self::within_limit(plan.max_robots, existing_robots as i64) {
+            return Ok(());
+        }
+        Err(AppError::Forbidden(format!(
+            "The {} plan allows at most {} trading robot(s)",
+            plan.name, plan.max_robots
+        )))
+    }
+
+    pub fn check_watchlist_limit(plan: &SubscriptionPlan, symbols: usize) -> Result<()> {
+        if plan.max_watchlist_symbols < 0 || symbols as i64 <= plan.max_watchlist_symbols as i64 {
+            return Ok(());
+        }
+        Err(AppError::Forbidden(format!(
+            "The {} plan allows at most {} watchlist symbol(s)",
+            plan.name, plan.max_watchlist_symbols
+        )))
+    }
+
+    pub fn check_optimization_limit(plan: &SubscriptionPlan, combinations: usize) -> Result<()> {
+        if plan.max_optimization_combinations < 0 || combinations as i64 <= plan.max_optimization_combinations as i64 {
+            return Ok(());
+        }
+        Err(AppError::Forbidden(format!(
+            "The {} plan allows at most {} parameter combination(s) per optimization, this grid has {}",
+            plan.name, plan.max_optimization_combinations, combinations
+        )))
+    }
+
+    // Demo trades never count against, or get blocked by, the daily operation limit
+    pub fn check_operation_limit(plan: &SubscriptionPlan, live_operations_today: i64, is_demo: bool) -> Result<()> {
+        if is_demo || Self::within_limit(plan.max_operations_per_day, live_operations_today) {
+            return Ok(());
+        }
+        Err(AppError::Forbidden(format!(
+            "The {} plan allows at most {} operations per day",
+            plan.name, plan.max_operations_per_day
+        )))
+    }
+
+    // The oldest moment the plan lets a user look at, from the start of that day; None when unlimited
+    pub fn history_start(plan: &SubscriptionPlan, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        if plan.max_history_days < 0 {
+            return None;
+        }
+        let start = now - Duration::days(plan.max_history_days as i64);
+        Some(start.duration_trunc(Duration::days(1)).expect("a day divides a day"))
+    }
+
+    // Moves a range's start up to the plan's history; true when that cut part of it off
+    pub fn clamp_range(plan: &SubscriptionPlan, from: DateTime<Utc>, now: DateTime<Utc>) -> (DateTime<Utc>, bool) {
+        match Self::history_start(plan, now) {
+            Some(earliest) if from < earliest => (earliest, true),
+            _ => (from, false),
+        }
+    }
+
+    // Same for a trade filter, where no start means all of it
+    pub fn clamp_filter(plan: &SubscriptionPlan, filter: &TradeFilter, now: DateTime<Utc>) -> (TradeFilter, bool) {
+        let Some(earliest) = Self::history_start(plan, now) else {
+            return (filter.clone(), false);
+        };
+        let (from, to) = filter.window(now);
+        if from.is_some_and(|from| from >= earliest) {
+            return (filter.clone(), false);
+        }
+        let clamped = TradeFilter { from: Some(earliest), to, rolling_days: None, ..filter.clone() };
+        (clamped, true)
+    }
+
+    // A backtest over a cut range would report results for a period it did not test, so it is refused
+    pub fn check_backtest_range(plan: &SubscriptionPlan, start: DateTime<Utc>, now: DateTime<Utc>) -> Result<()> {
+        match Self::history_start(plan, now) {
+            Some(earliest) if start < earliest => Err(AppError::PlanLimit(format!(
+                "The {} plan covers the last {} days of history, so a backtest can start on {} at the earliest",
+                plan.name,
+                plan.max_history_days,
+                earliest.date_naive()
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    // The robot's own interval, raised to the plan's minimum. A value stored before a downgrade is
+    // raised here rather than refused.
+    pub fn effective_evaluation_interval(plan: &SubscriptionPlan, risk_config: &serde_json::Value) -> std::time::Duration {
+        let configured = TradingRobot::evaluation_interval_from_risk_config(risk_config).unwrap_or(None).unwrap_or(0);
+        std::time::Duration::from_secs(configured.max(plan.min_evaluation_interval_seconds.max(0) as u64))
+    }
+
+    // Refuses a requested evaluation_interval_seconds below the plan's minimum
+    pub fn check_evaluation_interval(plan: &SubscriptionPlan, risk_config: &serde_json::Value) -> Result<()> {
+        let Some(seconds) = TradingRobot::evaluation_interval_from_risk_config(risk_config).map_err(AppError::Validation)? else {
+            return Ok(());
+        };
+        if seconds >= plan.min_evaluation_interval_seconds.max(0) as u64 {
+            return Ok(());
+        }
+        Err(AppError::PlanSetting(format!(
+            "The {} plan evaluates signals at most every {} seconds, so evaluation_interval_seconds cannot be {}",
+            plan.name, plan.min_evaluation_interval_seconds, seconds
+        )))
+    }
+
+    pub fn truncation_headers(truncated: bool) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if truncated {
+            headers.insert(HISTORY_TRUNCATED_HEADER, HeaderValue::from_static("true"));
+        }
+        headers
+    }
+
+    pub fn live_operation_count(trades: &[Trade], since: DateTime<Utc>) -> i64 {
+        trades
+            .iter()
+            .filter(|t| !t.is_demo && t.opened_at >= since)
+            .count() as i64
+    }
+
+    fn start_of_day(now: DateTime<Utc>) -> DateTime<Utc> {
+        now.date_naive()
+            .and_hms_opt(0, 0, 0)
+            .expect("midnight is a valid time")
+            .and_utc()
+    }
+
+    pub async fn ensure_can_open_trade(pool: &PgPool, user_id: Uuid, plan_name: &str, is_demo: bool) -> Result<()> {
+        if is_demo {
+            return Ok(());
+        }
+
+        let plan = Subscription::plan_details(plan_name);
+        let used = Trade::count_live_operations_since(pool, user_id, Self::start_of_day(Utc::now())).await?;
+        Self::check_operation_limit(&plan, used, is_demo)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trade(is_demo: bool) -> Trade {
+        Trade {
+            is_demo,
+            ..Trade::new(Uuid::new_v4(), Uuid::new_v4(), "EURUSD".to_string(), "buy".to_string(), 1.0, 1.1, None, None, None, None)
+        }
+    }
+
+    #[test]
+    fn test_robot_limit_follows_plan() {
+        let essential = Subscription::plan_details("essential");
+        assert!(PlanService::check_robot_limit(&essential, 0).is_ok());
+        assert!(matches!(PlanService::check_robot_limit(&essential, 1), Err(AppError::Forbidden(_))));
+        assert!(PlanService::check_robot_limit(&Subscription::plan_details("elite"), 500).is_ok());
+    }
+
+    #[test]
+    fn test_history_is_clamped_for_free_but_not_elite() {
+        let now = Utc::now();
+        let free = Subscription::plan_details("free");
+        let elite = Subscription::plan_details("elite");
+        let two_years_ago = now - Duration::days(730);
+        let earliest = PlanService::history_start(&free, now).unwrap();
+        assert!(now - earliest >= Duration::days(30) && now - earliest < Duration::days(31));
+
+        assert_eq!(PlanService::clamp_range(&free, two_years_ago, now), (earliest, true));
+        assert_eq!(PlanService::clamp_range(&free, now - Duration::days(7), now), (now - Duration::days(7), false));
+        assert_eq!(PlanService::clamp_range(&elite, two_years_ago, now), (two_years_ago, false));
+
+        // No start and a rolling window past the limit are both cut; the end is kept
+        let to = now - Duration::days(1);
+        let (clamped, truncated) = PlanService::clamp_filter(&free, &TradeFilter { to: Some(to), ..Default::default() }, now);
+        assert!(truncated);
+        assert_eq!((clamped.from, clamped.to), (Some(earliest), Some(to)));
+        let rolling = TradeFilter { rolling_days: Some(90), ..Default::default() };
+        let (clamped, truncated) = PlanService::clamp_filter(&free, &rolling, now);
+        assert!(truncated);
+        assert_eq!((clamped.from, clamped.rolling_days), (Some(earliest), None));
+        assert!(!PlanService::clamp_filter(&free, &TradeFilter { rolling_days: Some(7), ..Default::default() }, now).1);
+        assert_eq!(PlanService::clamp_filter(&elite, &rolling, now), (rolling, false));
+
+        assert!(PlanService::truncation_headers(true).contains_key(HISTORY_TRUNCATED_HEADER));
+        assert!(PlanService::truncation_headers(false).is_empty());
+    }
+
+    #[test]
+    fn test_backtest_beyond_the_history_is_refused() {
+        let now = Utc::now();
+        let free = Subscription::plan_details("free");
+        assert!(matches!(
+            PlanService::check_backtest_range(&free, now - Duration::days(60), now),
+            Err(AppError::PlanLimit(_))
+        ));
+        assert!(PlanService::check_backtest_range(&free, now - Duration::days(20), now).is_ok());
+        assert!(PlanService::check_backtest_range(&Subscription::plan_details("elite"), now - Duration::days(1000), now).is_ok());
+    }
+
+    #[test]
+    fn test_evaluation_interval_per_plan() {
+        let unset = serde_json::json!({});
+        let seconds = |plan: &str, risk_config: &serde_json::Value| {
+            PlanService::effective_evaluation_interval(&Subscription::plan_details(plan), risk_config).as_secs()
+        };
+        assert_eq!(
+            ["free", "essential", "pro", "elite"].map(|plan| seconds(plan, &unset)),
+            [60, 30, 10, 5]
+        );
+
+        // A slower robot keeps its own pace; a faster one is held to the plan
+        let every_20s = serde_json::json!({ "evaluation_interval_seconds": 20 });
+        assert_eq!((seconds("free", &every_20s), seconds("pro", &every_20s)), (60, 20));
+
+        let free = Subscription::plan_details("free");
+        assert!(matches!(PlanService::check_evaluation_interval(&free, &every_20s), Err(AppError::PlanSetting(_))));
+        assert!(PlanService::check_evaluation_interval(&Subscription::plan_details("pro"), &every_20s).is_ok());
+        assert!(PlanService::check_evaluation_interval(&free, &unset).is_ok());
+        let invalid = serde_json::json!({ "evaluation_interval_seconds": "fast" });
+        assert!(matches!(PlanService::check_evaluation_interval(&free, &invalid), Err(AppError::Validation(_))));
+    }
+
+    #[test]
+    fn test_demo_trades_are_exempt_from_operation_counter() {
+        let since = Utc::now() - chrono::Duration::hours(1);
+        let trades = vec![trade(false), trade(true), trade(true), trade(false)];
+        assert_eq!(PlanService::live_operation_count(&trades, since), 2);
+    }
+
+    #[test]
+    fn test_operation_limit_blocks_live_but_not_demo() {
+        let essential = Subscription::plan_details("essential");
+        assert!(PlanService::check_operation_limit(&essential, 49, false).is_ok());
+        assert!(PlanService::check_operation_limit(&essential, 50, false).is_err());
+        assert!(PlanService::check_operation_limit(&essential, 50, true).is_ok());
+    }
+}
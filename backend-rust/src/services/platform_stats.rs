@@ -0,0 +1,381 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+
+use crate::{
+    errors::{AppError, Result},
+    models::{AdminSetting, PlatformStatsDay, StatsExportSettings, StatsTotals, LiveStats, STATS_EXPORT_SETTING},
+};
+
+pub const SNAPSHOT_INTERVAL_SECONDS: u64 = 60 * 60;
+pub const MAX_BACKFILL_DAYS: i64 = 366;
+pub const DEFAULT_HISTORY_DAYS: i64 = 30;
+
+pub const CSV_HEADER: &str = "stat_date,total_users,active_users,total_robots,active_robots,total_trades,total_profit,free_users,essential_users,pro_users,elite_users,source,computed_at\n";
+
+#[async_trait]
+pub trait PlatformStatsStore: Send + Sync {
+    async fn totals_as_of(&self, as_of: DateTime<Utc>) -> Result<StatsTotals>;
+    async fn live(&self) -> Result<LiveStats>;
+    async fn upsert(&self, day: &PlatformStatsDay) -> Result<()>;
+    async fn unpushed_before(&self, before: NaiveDate) -> Result<Vec<PlatformStatsDay>>;
+    async fn mark_pushed(&self, dates: &[NaiveDate], at: DateTime<Utc>) -> Result<()>;
+    async fn export_settings(&self) -> Result<StatsExportSettings>;
+}
+
+pub struct PgPlatformStatsStore {
+    pool: PgPool,
+}
+
+impl PgPlatformStatsStore {
+    pub fn new(pool: PgPool) -> Self {
+        PgPlatformStatsStore { pool }
+    }
+}
+
+#[async_trait]
+impl PlatformStatsStore for PgPlatformStatsStore {
+    async fn totals_as_of(&self, as_of: DateTime<Utc>) -> Result<StatsTotals> {
+        PlatformStatsDay::totals_as_of(&self.pool, as_of).await
+    }
+
+    async fn live(&self) -> Result<LiveStats> {
+        PlatformStatsDay::live(&self.pool).await
+    }
+
+    async fn upsert(&self, day: &PlatformStatsDay) -> Result<()> {
+        PlatformStatsDay::upsert(&self.pool, day).await
+    }
+
+    async fn unpushed_before(&self, before: NaiveDate) -> Result<Vec<PlatformStatsDay>> {
+        PlatformStatsDay::unpushed_before(&self.pool, before).await
+    }
+
+    async fn mark_pushed(&self, dates: &[NaiveDate], at: DateTime<Utc>) -> Result<()> {
+        PlatformStatsDay::mark_pushed(&self.pool, dates, at).await
+    }
+
+    async fn export_settings(&self) -> Result<StatsExportSettings> {
+        AdminSetting::get(&self.pool, STATS_EXPORT_SETTING).await
+    }
+}
+
+// Delivery of finished days to the BI side
+#[async_trait]
+pub trait StatsPushTarget: Send + Sync {
+    async fn push(&self, settings: &StatsExportSettings, days: &[PlatformStatsDay]) -> Result<()>;
+}
+
+#[derive(Serialize)]
+struct StatsPushPayload<'a> {
+    days: &'a [PlatformStatsDay],
+}
+
+pub struct WebhookStatsPush {
+    client: reqwest::Client,
+}
+
+impl WebhookStatsPush {
+    pub fn new() -> Self {
+        WebhookStatsPush { client: reqwest::Client::new() }
+    }
+}
+
+impl Default for WebhookStatsPush {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl StatsPushTarget for WebhookStatsPush {
+    async fn push(&self, settings: &StatsExportSettings, days: &[PlatformStatsDay]) -> Result<()> {
+        let Some(url) = settings.webhook_url.as_deref() else {
+            return Ok(());
+        };
+        let response = self
+            .client
+            .post(url)
+            .timeout(std::time::Duration::from_secs(30))
+            .json(&StatsPushPayload { days })
+            .send()
+            .await
+            .map_err(|e| AppError::External(format!("Stats webhook failed: {}", e)))?;
+        if !response.status().is_success() {
+            return Err(AppError::External(format!("Stats webhook answered {}", response.status())));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StatsRun {
+    pub snapshotted: bool,
+    pub pushed: usize,
+}
+
+pub struct PlatformStats;
+
+impl PlatformStats {
+    fn end_of_day(date: NaiveDate) -> DateTime<Utc> {
+        (date + Duration::days(1))
+            .and_hms_opt(0, 0, 0)
+            .expect("midnight is a valid time")
+            .and_utc()
+    }
+
+    // Today's row, measured now; retaking it just replaces the row
+    pub async fn snapshot(store: &dyn PlatformStatsStore, now: DateTime<Utc>) -> Result<PlatformStatsDay> {
+        let totals = store.totals_as_of(now).await?;
+        let live = store.live().await?;
+        let day = PlatformStatsDay::new(now.date_naive(), totals, Some(live), now);
+        store.upsert(&day).await?;
+        Ok(day)
+    }
+
+    // Recomputes every finished day from `from` through yesterday from the raw tables
+    pub async fn backfill(store: &dyn PlatformStatsStore, from: NaiveDate, now: DateTime<Utc>) -> Result<usize> {
+        let today = now.date_naive();
+        if from >= today {
+            return Err(AppError::Validation("from must be before today".to_string()));
+        }
+        if (today - from).num_days() > MAX_BACKFILL_DAYS {
+            return Err(AppError::Validation(format!("Backfill covers at most {} days", MAX_BACKFILL_DAYS)));
+        }
+
+        let mut date = from;
+        let mut days = 0;
+        while date < today {
+            let totals = store.totals_as_of(Self::end_of_day(date)).await?;
+            store.upsert(&PlatformStatsDay::new(date, totals, None, now)).await?;
+            date += Duration::days(1);
+            days += 1;
+        }
+        Ok(days)
+    }
+
+    // Sends finished days that were not delivered yet, when a target is configured
+    pub async fn push_pending(store: &dyn PlatformStatsStore, target: &dyn StatsPushTarget, now: DateTime<Utc>) -> Result<usize> {
+        let settings = store.export_settings().await?;
+        if settings.webhook_url.is_none() {
+            return Ok(0);
+        }
+
+        let days = store.unpushed_before(now.date_naive()).await?;
+        if days.is_empty() {
+            return Ok(0);
+        }
+        target.push(&settings, &days).await?;
+        let dates: Vec<NaiveDate> = days.iter().map(|d| d.stat_date).collect();
+        store.mark_pushed(&dates, now).await?;
+        Ok(dates.len())
+    }
+
+    // Scheduler job
+    pub async fn run(store: &dyn PlatformStatsStore, target: &dyn StatsPushTarget, now: DateTime<Utc>) -> Result<StatsRun> {
+        Self::snapshot(store, now).await?;
+        let pushed = Self::push_pending(store, target, now).await?;
+        Ok(StatsRun { snapshotted: true, pushed })
+    }
+
+    // Defaults to the last 30 days through today
+    pub fn history_range(from: Option<NaiveDate>, to: Option<NaiveDate>, today: NaiveDate) -> Result<(NaiveDate, NaiveDate)> {
+        let to = to.unwrap_or(today);
+        let from = from.unwrap_or(to - Duration::days(DEFAULT_HISTORY_DAYS - 1));
+        if from > to {
+            return Err(AppError::Validation("from must not be after to".to_string()));
+        }
+        Ok((from, to))
+    }
+
+    pub fn csv_row(day: &PlatformStatsDay) -> String {
+        let opt = |value: Option<i64>| value.map(|v| v.to_string()).unwrap_or_default();
+        format!(
+            "{},{},{},{},{},{},{:.2},{},{},{},{},{},{}\n",
+            day.stat_date,
+            day.total_users,
+            opt(day.active_users),
+            day.total_robots,
+            opt(day.active_robots),
+            day.total_trades,
+            day.total_profit,
+            opt(day.free_users),
+            opt(day.essential_users),
+            opt(day.pro_users),
+            opt(day.elite_users),
+            day.source,
+            day.computed_at.to_rfc3339(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::sync::Mutex;
+
+    struct FakeStore {
+        days: Mutex<BTreeMap<NaiveDate, PlatformStatsDay>>,
+        users: Mutex<i64>,
+        settings: StatsExportSettings,
+    }
+
+    impl FakeStore {
+        fn new(webhook_url: Option<&str>) -> Self {
+            FakeStore {
+                days: Mutex::new(BTreeMap::new()),
+                users: Mutex::new(10),
+                settings: StatsExportSettings { webhook_url: webhook_url.map(str::to_string) },
+            }
+        }
+    }
+
+    #[async_trait]
+    impl PlatformStatsStore for FakeStore {
+        async fn totals_as_of(&self, _as_of: DateTime<Utc>) -> Result<StatsTotals> {
+            Ok(StatsTotals { total_users: *self.users.lock().unwrap(), total_robots: 4, total_trades: 20, total_profit: 125.5 })
+        }
+
+        async fn live(&self) -> Result<LiveStats> {
+            Ok(LiveStats { active_users: 8, active_robots: 2, free_users: 6, essential_users: 2, pro_users: 1, elite_users: 1 })
+        }
+
+        // Same merge as the SQL upsert
+        async fn upsert(&self, day: &PlatformStatsDay) -> Result<()> {
+            let mut days = self.days.lock().unwrap();
+            let merged = match days.get(&day.stat_date) {
+                Some(existing) if day.active_users.is_none() => PlatformStatsDay {
+                    active_users: existing.active_users,
+                    active_robots: existing.active_robots,
+                    free_users: existing.free_users,
+                    essential_users: existing.essential_users,
+                    pro_users: existing.pro_users,
+                    elite_users: existing.elite_users,
+                    source: existing.source.clone(),
+                    ..day.clone()
+                },
+                _ => day.clone(),
+            };
+            days.insert(day.stat_date, merged);
+            Ok(())
+        }
+
+        async fn unpushed_before(&self, before: NaiveDate) -> Result<Vec<PlatformStatsDay>> {
+            let days = self.days.lock().unwrap();
+            Ok(days.values().filter(|d| d.stat_date < before && d.pushed_at.is_none()).cloned().collect())
+        }
+
+        async fn mark_pushed(&self, dates: &[NaiveDate], at: DateTime<Utc>) -> Result<()> {
+            let mut days = self.days.lock().unwrap();
+            for date in dates {
+                days.get_mut(date).unwrap().pushed_at = Some(at);
+            }
+            Ok(())
+        }
+
+        async fn export_settings(&self) -> Result<StatsExportSettings> {
+            Ok(self.settings.clone())
+        }
+    }
+
+    #[derive(Default)]
+    struct RecordedPushes {
+        batches: Mutex<Vec<Vec<NaiveDate>>>,
+    }
+
+    #[async_trait]
+    impl StatsPushTarget for RecordedPushes {
+        async fn push(&self, _settings: &StatsExportSettings, days: &[PlatformStatsDay]) -> Result<()> {
+            self.batches.lock().unwrap().push(days.iter().map(|d| d.stat_date).collect());
+            Ok(())
+        }
+    }
+
+    fn at(date: &str, hour: u32) -> DateTime<Utc> {
+        NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap().and_hms_opt(hour, 0, 0).unwrap().and_utc()
+    }
+
+    fn date(raw: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(raw, "%Y-%m-%d").unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_snapshots_are_idempotent_per_date() {
+        let store = FakeStore::new(None);
+
+        PlatformStats::snapshot(&store, at("2024-03-01", 9)).await.unwrap();
+        *store.users.lock().unwrap() = 12;
+        PlatformStats::snapshot(&store, at("2024-03-01", 23)).await.unwrap();
+
+        let days = store.days.lock().unwrap();
+        assert_eq!(days.len(), 1);
+        let day = &days[&date("2024-03-01")];
+        assert_eq!((day.total_users, day.active_users, day.source.as_str()), (12, Some(8), "live"));
+        assert_eq!(day.computed_at, at("2024-03-01", 23));
+    }
+
+    #[tokio::test]
+    async fn test_backfill_recomputes_past_days_and_keeps_live_columns() {
+        let store = FakeStore::new(None);
+        PlatformStats::snapshot(&store, at("2024-03-02", 12)).await.unwrap();
+
+        let days = PlatformStats::backfill(&store, date("2024-02-28"), at("2024-03-04", 1)).await.unwrap();
+        assert_eq!(days, 5);
+        // Running it again changes nothing
+        assert_eq!(PlatformStats::backfill(&store, date("2024-02-28"), at("2024-03-04", 1)).await.unwrap(), 5);
+
+        assert!(PlatformStats::backfill(&store, date("2024-03-04"), at("2024-03-04", 1)).await.is_err());
+        assert!(PlatformStats::backfill(&store, date("2022-01-01"), at("2024-03-04", 1)).await.is_err());
+
+        let stored = store.days.lock().unwrap();
+        assert_eq!(stored.len(), 5);
+        assert_eq!(stored[&date("2024-02-28")].active_users, None);
+        assert_eq!(stored[&date("2024-02-28")].source, "backfill");
+        assert_eq!(stored[&date("2024-03-02")].active_users, Some(8));
+        assert_eq!(stored[&date("2024-03-02")].source, "live");
+        assert!(!stored.contains_key(&date("2024-03-04")));
+    }
+
+    #[tokio::test]
+    async fn test_finished_days_are_pushed_once() {
+        let store = FakeStore::new(Some("https://bi.example.com/hook"));
+        let pushes = RecordedPushes::default();
+
+        PlatformStats::run(&store, &pushes, at("2024-03-01", 22)).await.unwrap();
+        let run = PlatformStats::run(&store, &pushes, at("2024-03-02", 0)).await.unwrap();
+        assert_eq!(run, StatsRun { snapshotted: true, pushed: 1 });
+        PlatformStats::run(&store, &pushes, at("2024-03-02", 1)).await.unwrap();
+
+        assert_eq!(*pushes.batches.lock().unwrap(), vec![vec![date("2024-03-01")]]);
+
+        // Nothing is pushed without a target
+        let unconfigured = FakeStore::new(None);
+        PlatformStats::snapshot(&unconfigured, at("2024-03-01", 22)).await.unwrap();
+        assert_eq!(PlatformStats::push_pending(&unconfigured, &pushes, at("2024-03-02", 0)).await.unwrap(), 0);
+    }
+
+    #[test]
+    fn test_csv_rows_match_the_header() {
+        let live = LiveStats { active_users: 8, active_robots: 2, free_users: 6, essential_users: 2, pro_users: 1, elite_users: 1 };
+        let totals = StatsTotals { total_users: 10, total_robots: 4, total_trades: 20, total_profit: 125.5 };
+
+        let live_row = PlatformStats::csv_row(&PlatformStatsDay::new(date("2024-03-01"), totals, Some(live), at("2024-03-01", 23)));
+        assert_eq!(live_row, "2024-03-01,10,8,4,2,20,125.50,6,2,1,1,live,2024-03-01T23:00:00+00:00\n");
+
+        let backfilled = PlatformStats::csv_row(&PlatformStatsDay::new(date("2024-02-01"), totals, None, at("2024-03-01", 23)));
+        assert_eq!(backfilled, "2024-02-01,10,,4,,20,125.50,,,,,backfill,2024-03-01T23:00:00+00:00\n");
+
+        let columns = CSV_HEADER.trim_end().split(',').count();
+        assert_eq!(live_row.trim_end().split(',').count(), columns);
+        assert_eq!(backfilled.trim_end().split(',').count(), columns);
+    }
+
+    #[test]
+    fn test_history_defaults_to_the_last_30_days() {
+        let today = date("2024-03-31");
+        assert_eq!(PlatformStats::history_range(None, None, today).unwrap(), (date("2024-03-02"), today));
+        assert!(PlatformStats::history_range(Some(today), Some(date("2024-03-01")), today).is_err());
+    }
+}
@@ -0,0 +1,220 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    errors::{AppError, Result},
+    models::{FilterPreset, FilterPresetResponse, TradeFilter, TradingRobot, MAX_PRESETS_PER_USER},
+};
+
+pub struct PresetService;
+
+impl PresetService {
+    // Builds a filter from query-string style parameters; lists are comma-separated
+    pub fn explicit_filter(
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        rolling_days: Option<i64>,
+        robot_ids: Option<&str>,
+        symbols: Option<&str>,
+    ) -> Result<TradeFilter> {
+        let robot_ids = split_list(robot_ids)
+            .into_iter()
+            .map(|id| {
+                Uuid::parse_str(&id).map_err(|_| AppError::Validation(format!("Invalid robot id: {}", id)))
+            })
+            .collect::<Result<Vec<Uuid>>>()?;
+
+        let filter = TradeFilter {
+            from,
+            to,
+            rolling_days,
+            robot_ids,
+            symbols: split_list(symbols).into_iter().map(|s| s.to_uppercase()).collect(),
+            ..Default::default()
+        };
+        filter.validate().map_err(AppError::Validation)?;
+        Ok(filter)
+    }
+
+    pub fn missing_robot_ids(filter: &TradeFilter, existing: &[Uuid]) -> Vec<Uuid> {
+        filter
+            .robot_ids
+            .iter()
+            .filter(|id| !existing.contains(id))
+            .copied()
+            .collect()
+    }
+
+    pub async fn create(pool: &PgPool, user_id: Uuid, name: String, filter: TradeFilter) -> Result<FilterPresetResponse> {
+        filter.validate().map_err(AppError::Validation)?;
+
+        if FilterPreset::count_by_user_id(pool, user_id).await? >= MAX_PRESETS_PER_USER {
+            return Err(AppError::Validation(format!(
+                "A user can save at most {} presets",
+                MAX_PRESETS_PER_USER
+            )));
+        }
+
+        let preset = FilterPreset::create(pool, user_id, name, filter).await?;
+        Self::to_response(pool, preset).await
+    }
+
+    pub async fn to_response(pool: &PgPool, preset: FilterPreset) -> Result<FilterPresetResponse> {
+        let filter = preset.filter.0;
+        let existing = TradingRobot::existing_ids(pool, preset.user_id, &filter.robot_ids).await?;
+        let missing_robot_ids = Self::missing_robot_ids(&filter, &existing);
+
+        Ok(FilterPresetResponse {
+            id: preset.id,
+            name: preset.name,
+            filter,
+            missing_robot_ids,
+            created_at: preset.created_at,
+            updated_at: preset.updated_at,
+        })
+    }
+
+    pub async fn resolve(pool: &PgPool, user_id: Uuid, preset_id: Uuid) -> Result<TradeFilter> {
+        let preset = FilterPreset::find_by_id(pool, preset_id, user_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound("Filter preset not found".to_string()))?;
+
+        let filter = preset.filter.0;
+        let existing = TradingRobot::existing_ids(pool, user_id, &filter.robot_ids).await?;
+        let missing = Self::missing_robot_ids(&filter, &existing);
+        Ok(filter.without_robots(&missing))
+    }
+}
+
+fn split_list(raw: Option<&str>) -> Vec<String> {
+    raw.map(|value| {
+        value
+            .split(',')
+            .map(|item| item.trim().to_string())
+            .filter(|item| !item.is_empty())
+            .collect()
+    })
+    .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::DemoMode;
+    use chrono::TimeZone;
+    use sqlx::{Postgres, QueryBuilder};
+
+    fn sql_for(filter: &TradeFilter, now: DateTime<Utc>) -> String {
+        let mut builder = QueryBuilder::<Postgres>::new("SELECT 1 FROM trades WHERE user_id = $1");
+        filter.push_conditions(&mut builder, now);
+        builder.sql().to_string()
+    }
+
+    #[test]
+    fn test_stored_preset_applies_like_explicit_filter() {
+        let robot_id = Uuid::new_v4();
+        let from = Utc.with_ymd_and_hms(2024, 3, 3, 0, 0, 0).unwrap();
+        let now = Utc.with_ymd_and_hms(2024, 4, 1, 12, 0, 0).unwrap();
+
+        let stored = TradeFilter {
+            from: Some(from),
+            robot_ids: vec![robot_id],
+            symbols: vec!["EURUSD".to_string()],
+            ..Default::default()
+        };
+        // Round-trip through the JSONB representation the preset is saved as
+        let preset: TradeFilter = serde_json::from_value(serde_json::to_value(&stored).unwrap()).unwrap();
+
+        let explicit = PresetService::explicit_filter(
+            Some(from),
+            None,
+            None,
+            Some(&robot_id.to_string()),
+            Some("eurusd"),
+        )
+        .unwrap();
+
+        assert_eq!(preset, explicit);
+        assert_eq!(sql_for(&preset, now), sql_for(&explicit, now));
+    }
+
+    #[test]
+    fn test_deleted_robots_are_flagged_and_ignored() {
+        let kept = Uuid::new_v4();
+        let deleted = Uuid::new_v4();
+        let filter = TradeFilter {
+            robot_ids: vec![kept, deleted],
+            ..Default::default()
+        };
+
+        let missing = PresetService::missing_robot_ids(&filter, &[kept]);
+        assert_eq!(missing, vec![deleted]);
+        assert_eq!(filter.without_robots(&missing).robot_ids, vec![kept]);
+
+        // With every robot gone the robot condition disappears entirely
+        let effective = filter.without_robots(&[kept, deleted]);
+        let now = Utc::now();
+        assert!(!sql_for(&effective, now).contains("robot_id"));
+    }
+
+    #[test]
+    fn test_rolling_window_resolves_against_now() {
+        let filter = TradeFilter {
+            rolling_days: Some(30),
+            ..Default::default()
+        };
+        let now = Utc.with_ymd_and_hms(2024, 4, 1, 0, 0, 0).unwrap();
+
+        let (from, to) = filter.window(now);
+        assert_eq!(from, Some(Utc.with_ymd_and_hms(2024, 3, 2, 0, 0, 0).unwrap()));
+        assert_eq!(to, None);
+    }
+
+    #[test]
+    fn test_full_days_skip_partially_covered_edges() {
+        let day = |d| chrono::NaiveDate::from_ymd_opt(2024, 3, d).unwrap();
+        let now = Utc.with_ymd_and_hms(2024, 4, 1, 12, 0, 0).unwrap();
+
+        let partial = TradeFilter {
+            from: Some(Utc.with_ymd_and_hms(2024, 3, 3, 10, 0, 0).unwrap()),
+            to: Some(Utc.with_ymd_and_hms(2024, 3, 10, 15, 0, 0).unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(partial.full_days(now), (Some(day(4)), Some(day(10))));
+
+        let midnight = TradeFilter { from: Some(Utc.with_ymd_and_hms(2024, 3, 3, 0, 0, 0).unwrap()), ..Default::default() };
+        assert_eq!(midnight.full_days(now), (Some(day(3)), None));
+
+        let rolling = TradeFilter { rolling_days: Some(30), ..Default::default() };
+        assert_eq!(rolling.full_days(now), (Some(day(3)), None));
+    }
+
+    #[test]
+    fn test_demo_trades_are_excluded_by_default() {
+        let now = Utc::now();
+        let filter = PresetService::explicit_filter(None, None, None, None, None).unwrap();
+        assert_eq!(filter.demo, DemoMode::Exclude);
+        assert!(sql_for(&filter, now).contains("is_demo = FALSE"));
+
+        let include = TradeFilter { demo: DemoMode::from_query(Some("true")).unwrap(), ..filter.clone() };
+        assert!(!sql_for(&include, now).contains("is_demo"));
+
+        let only = TradeFilter { demo: DemoMode::from_query(Some("only")).unwrap(), ..filter };
+        assert!(sql_for(&only, now).contains("is_demo = TRUE"));
+
+        assert!(DemoMode::from_query(Some("sometimes")).is_err());
+        assert!(DemoMode::Exclude.matches(false) && !DemoMode::Exclude.matches(true));
+        assert!(DemoMode::Only.matches(true) && !DemoMode::Only.matches(false));
+        assert!(DemoMode::Include.matches(true) && DemoMode::Include.matches(false));
+    }
+
+    #[test]
+    fn test_invalid_filters_are_rejected() {
+        let now = Utc::now();
+        assert!(PresetService::explicit_filter(Some(now), None, Some(7), None, None).is_err());
+        assert!(PresetService::explicit_filter(Some(now), Some(now), None, None, None).is_err());
+        assert!(PresetService::explicit_filter(None, None, Some(0), None, None).is_err());
+        assert!(PresetService::explicit_filter(None, None, None, Some("not-a-uuid"), None).is_err());
+    }
+}
@@ -0,0 +1,249 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use sqlx::{FromRow, PgPool};
+use std::sync::{Arc, RwLock};
+
+use crate::{
+    errors::{AppError, DbOp, Result},
+    services::cache_service::CacheService,
+};
+
+pub const PUBLIC_STATS_REFRESH_SECONDS: u64 = 10 * 60;
+pub const PUBLIC_STATS_CACHE_CONTROL: &str = "public, max-age=60, stale-while-revalidate=600";
+const PUBLIC_STATS_CACHE_KEY: &str = "public:stats";
+// Kept well past the refresh period so an outage of the job still leaves something to serve
+const PUBLIC_STATS_CACHE_TTL_SECONDS: u64 = 7 * 24 * 3600;
+
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct PlatformTotals {
+    pub total_users: i64,
+    pub total_trades: i64,
+    pub total_robots: i64,
+    pub average_win_rate: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct PublicStats {
+    pub total_users: i64,
+    pub total_trades: i64,
+    pub total_robots: i64,
+    pub average_win_rate: f64,
+    pub computed_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct PublicStatsResponse {
+    #[serde(flatten)]
+    pub stats: PublicStats,
+    // The refresh job has missed at least two runs
+    pub stale: bool,
+}
+
+#[async_trait]
+pub trait PublicStatsStore: Send + Sync {
+    async fn load(&self) -> Result<Option<PublicStats>>;
+    async fn save(&self, stats: &PublicStats) -> Result<()>;
+}
+
+#[async_trait]
+impl PublicStatsStore for CacheService {
+    async fn load(&self) -> Result<Option<PublicStats>> {
+        self.get_json(PUBLIC_STATS_CACHE_KEY).await
+    }
+
+    async fn save(&self, stats: &PublicStats) -> Result<()> {
+        self.set_json(PUBLIC_STATS_CACHE_KEY, stats, PUBLIC_STATS_CACHE_TTL_SECONDS).await
+    }
+}
+
+pub struct PublicStatsService {
+    store: Arc<dyn PublicStatsStore>,
+    round_to: i64,
+    // Last snapshot this instance computed, used when the store cannot be read
+    last: RwLock<Option<PublicStats>>,
+}
+
+impl PublicStatsService {
+    pub fn new(store: Arc<dyn PublicStatsStore>, round_to: i64) -> Self {
+        PublicStatsService {
+            store,
+            round_to: round_to.max(1),
+            last: RwLock::new(None),
+        }
+    }
+
+    // Counts are floored to the configured step and the win rate to a whole percent,
+    // so the public numbers cannot be used to track individual signups or trades
+    pub fn fuzz(totals: &PlatformTotals, round_to: i64, now: DateTime<Utc>) -> PublicStats {
+        let step = round_to.max(1);
+        let floor = |value: i64| value.max(0) / step * step;
+
+        PublicStats {
+            total_users: floor(totals.total_users),
+            total_trades: floor(totals.total_trades),
+            total_robots: floor(totals.total_robots),
+            average_win_rate: totals.average_win_rate.clamp(0.0, 100.0).round(),
+            computed_at: now,
+        }
+    }
+
+    pub fn is_stale(stats: &PublicStats, now: DateTime<Utc>) -> bool {
+        now - stats.computed_at > Duration::seconds(2 * PUBLIC_STATS_REFRESH_SECONDS as i64)
+    }
+
+    // The only query that touches the large tables; run by the scheduler, never per request
+    pub async fn compute_totals(pool: &PgPool) -> Result<PlatformTotals> {
+        let totals = sqlx::query_as::<_, PlatformTotals>(
+            r#"
+            SELECT
+                (SELECT COUNT(*) FROM users WHERE is_active = TRUE) AS total_users,
+                (SELECT COUNT(*) FROM trades WHERE is_demo = FALSE) AS total_trades,
+                (SELECT COUNT(*) FROM trading_robots) AS total_robots,
+                (SELECT COALESCE(AVG(CASE WHEN profit_loss > 0 THEN 100.0 ELSE 0.0 END), 0)::FLOAT8
+                   FROM trades WHERE status = 'closed' AND is_demo = FALSE) AS average_win_rate
+            "#,
+        )
+        .fetch_one(pool)
+        .await
+        .db_op("public_stats.compute_totals")?;
+
+        Ok(totals)
+    }
+
+    pub async fn refresh(&self, totals: &PlatformTotals, now: DateTime<Utc>) -> Result<()> {
+        let stats = Self::fuzz(totals, self.round_to, now);
+        *self.last.write().unwrap() = Some(stats.clone());
+        self.store.save(&stats).await
+    }
+
+    // Scheduler job
+    pub async fn run(&self, pool: &PgPool) -> Result<()> {
+        let totals = Self::compute_totals(pool).await?;
+        self.refresh(&totals, Utc::now()).await
+    }
+
+    pub async fn current(&self, now: DateTime<Utc>) -> Result<PublicStatsResponse> {
+        let stored = match self.store.load().await {
+            Ok(stats) => stats,
+            Err(e) => {
+                tracing::warn!("Public stats read failed: {}", e);
+                None
+            }
+        };
+
+        let stats = stored
+            .or_else(|| self.last.read().unwrap().clone())
+            .ok_or_else(|| AppError::Unavailable("Public stats are not available yet".to_string()))?;
+
+        Ok(PublicStatsResponse {
+            stale: Self::is_stale(&stats, now),
+            stats,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct MemoryStore {
+        stats: Mutex<Option<PublicStats>>,
+        failing: AtomicBool,
+    }
+
+    #[async_trait]
+    impl PublicStatsStore for MemoryStore {
+        async fn load(&self) -> Result<Option<PublicStats>> {
+            if self.failing.load(Ordering::SeqCst) {
+                return Err(AppError::Internal(anyhow::anyhow!("redis down")));
+            }
+            Ok(self.stats.lock().unwrap().clone())
+        }
+
+        async fn save(&self, stats: &PublicStats) -> Result<()> {
+            if self.failing.load(Ordering::SeqCst) {
+                return Err(AppError::Internal(anyhow::anyhow!("redis down")));
+            }
+            *self.stats.lock().unwrap() = Some(stats.clone());
+            Ok(())
+        }
+    }
+
+    fn totals() -> PlatformTotals {
+        PlatformTotals {
+            total_users: 12_345,
+            total_trades: 987_654,
+            total_robots: 4_321,
+            average_win_rate: 57.64,
+        }
+    }
+
+    fn at(minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, 10, 12, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn test_fuzz_floors_counts_to_the_configured_step() {
+        let stats = PublicStatsService::fuzz(&totals(), 100, at(0));
+        assert_eq!(stats.total_users, 12_300);
+        assert_eq!(stats.total_trades, 987_600);
+        assert_eq!(stats.total_robots, 4_300);
+        assert_eq!(stats.average_win_rate, 58.0);
+
+        let exact = PublicStatsService::fuzz(&totals(), 1, at(0));
+        assert_eq!(exact.total_users, 12_345);
+
+        let small = PublicStatsService::fuzz(&PlatformTotals { total_users: 42, ..totals() }, 100, at(0));
+        assert_eq!(small.total_users, 0);
+    }
+
+    #[tokio::test]
+    async fn test_serves_the_stored_snapshot_and_flags_it_stale_when_the_job_stops() {
+        let store = Arc::new(MemoryStore::default());
+        let service = PublicStatsService::new(store.clone(), 100);
+        service.refresh(&totals(), at(0)).await.unwrap();
+
+        let fresh = service.current(at(15)).await.unwrap();
+        assert!(!fresh.stale);
+        assert_eq!(fresh.stats.total_users, 12_300);
+
+        // No refresh for 25 minutes: still served, but flagged
+        let stale = service.current(at(25)).await.unwrap();
+        assert!(stale.stale);
+        assert_eq!(stale.stats.computed_at, at(0));
+
+        let body = serde_json::to_value(&stale).unwrap();
+        assert_eq!(body["stale"], true);
+        assert_eq!(body["total_users"], 12_300);
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_from_another_instance_is_served() {
+        let store = Arc::new(MemoryStore::default());
+        PublicStatsService::new(store.clone(), 10).refresh(&totals(), at(0)).await.unwrap();
+
+        let reader = PublicStatsService::new(store, 10);
+        let response = reader.current(at(5)).await.unwrap();
+        assert_eq!(response.stats.total_users, 12_340);
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_to_last_local_snapshot_when_store_fails() {
+        let store = Arc::new(MemoryStore::default());
+        let service = PublicStatsService::new(store.clone(), 100);
+
+        assert!(matches!(service.current(at(0)).await, Err(AppError::Unavailable(_))));
+
+        store.failing.store(true, Ordering::SeqCst);
+        assert!(service.refresh(&totals(), at(0)).await.is_err());
+
+        let response = service.current(at(1)).await.unwrap();
+        assert_eq!(response.stats.total_trades, 987_600);
+    }
+}
@@ -0,0 +1,521 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use uuid::Uuid;
+
+use crate::{
+    errors::{AppError, Result},
+    models::{BrokerConnection, Trade},
+    services::{market_data_streamer::Quote, Mt5Service},
+};
+
+// Platform quotes older than this are not shown, not even as indicative prices
+pub const MAX_PLATFORM_QUOTE_AGE_SECONDS: i64 = 300;
+pub const MAX_QUOTE_SYMBOLS: usize = 25;
+
+// Where a quote came from, best first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QuoteSource {
+    // The user's own broker session
+    Broker,
+    // Another user's session quoting the same symbol; never says whose
+    Platform,
+    // The rates API configured as QUOTE_FALLBACK_URL
+    External,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct SourcedQuote {
+    pub symbol: String,
+    pub bid: f64,
+    pub ask: f64,
+    pub time: DateTime<Utc>,
+    pub source: QuoteSource,
+    pub staleness_seconds: i64,
+    // Not from the user's own broker: fine to display, never to trade or manage risk on
+    pub indicative: bool,
+}
+
+impl SourcedQuote {
+    pub fn new(quote: Quote, source: QuoteSource, now: DateTime<Utc>) -> Self {
+        SourcedQuote {
+            staleness_seconds: (now - quote.time).num_seconds().max(0),
+            symbol: quote.symbol,
+            bid: quote.bid,
+            ask: quote.ask,
+            time: quote.time,
+            source,
+            indicative: source != QuoteSource::Broker,
+        }
+    }
+
+    // (bid, ask) a stop, position size or order may act on; None for indicative quotes
+    pub fn executable(&self) -> Option<(f64, f64)> {
+        (!self.indicative).then_some((self.bid, self.ask))
+    }
+}
+
+// An open trade valued at the best quote available
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct FloatingTrade {
+    pub trade_id: Uuid,
+    pub symbol: String,
+    pub trade_type: String,
+    pub volume: f64,
+    #[serde(serialize_with = "crate::money::serialize_price")]
+    pub entry_price: f64,
+    // Bid for longs, ask for shorts; null when no source had a quote
+    #[serde(serialize_with = "crate::money::serialize_opt_price")]
+    pub current_price: Option<f64>,
+    #[serde(serialize_with = "crate::money::serialize_opt_amount")]
+    pub floating_profit_loss: Option<f64>,
+    pub source: Option<QuoteSource>,
+    pub staleness_seconds: Option<i64>,
+    pub indicative: bool,
+}
+
+impl FloatingTrade {
+    pub fn value(trade: &Trade, quote: Option<&SourcedQuote>) -> Self {
+        let is_buy = trade.trade_type.eq_ignore_ascii_case("buy");
+        let current_price = quote.map(|q| if is_buy { q.bid } else { q.ask });
+        FloatingTrade {
+            trade_id: trade.id,
+            symbol: trade.symbol.clone(),
+            trade_type: trade.trade_type.clone(),
+            volume: trade.volume,
+            entry_price: trade.entry_price,
+            current_price,
+            floating_profit_loss: current_price.map(|price| trade.calculate_profit_loss(price)),
+            source: quote.map(|q| q.source),
+            staleness_seconds: quote.map(|q| q.staleness_seconds),
+            indicative: quote.is_some_and(|q| q.indicative),
+        }
+    }
+}
+
+// One link of the chain; Ok(None) means the source has nothing for the symbol right now
+#[async_trait]
+pub trait QuoteLookup: Send + Sync {
+    async fn quote(&self, user_id: Uuid, symbol: &str) -> Result<Option<Quote>>;
+}
+
+// Latest quote per symbol from any session, filled by the market data job and by lookups
+#[derive(Default)]
+pub struct PlatformQuoteCache {
+    quotes: RwLock<HashMap<String, Quote>>,
+}
+
+impl PlatformQuoteCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, quotes: &[Quote]) {
+        let mut cached = self.quotes.write().unwrap();
+        for quote in quotes {
+            let newer = cached.get(&quote.symbol).is_none_or(|c| c.time <= quote.time);
+            if newer {
+                cached.insert(quote.symbol.clone(), quote.clone());
+            }
+        }
+    }
+
+    pub fn latest(&self, symbol: &str, now: DateTime<Utc>) -> Option<Quote> {
+        self.quotes
+            .read()
+            .unwrap()
+            .get(symbol)
+            .filter(|q| (now - q.time).num_seconds() <= MAX_PLATFORM_QUOTE_AGE_SECONDS)
+            .cloned()
+    }
+}
+
+// Tries the chain in order and tags the first quote found with its source. A failing source
+// is skipped like an empty one.
+pub struct QuoteService {
+    sources: Vec<(QuoteSource, Arc<dyn QuoteLookup>)>,
+}
+
+impl QuoteService {
+    pub fn new(sources: Vec<(QuoteSource, Arc<dyn QuoteLookup>)>) -> Self {
+        QuoteService { sources }
+    }
+
+    pub async fn quote(&self, user_id: Uuid, symbol: &str, now: DateTime<Utc>) -> Option<SourcedQuote> {
+        self.quote_on(None, user_id, symbol, now).await
+    }
+
+    // With `connection` standing in for the user's own broker session, so one account is priced
+    // by its own broker; the rest of the chain still fills in, as indicative prices
+    pub async fn quote_on(
+        &self,
+        connection: Option<&dyn QuoteLookup>,
+        user_id: Uuid,
+        symbol: &str,
+        now: DateTime<Utc>,
+    ) -> Option<SourcedQuote> {
+        let chain = self
+            .sources
+            .iter()
+            .filter(|(source, _)| connection.is_none() || *source != QuoteSource::Broker)
+            .map(|(source, lookup)| (*source, lookup.as_ref()));
+        for (source, lookup) in connection.map(|lookup| (QuoteSource::Broker, lookup)).into_iter().chain(chain) {
+            match lookup.quote(user_id, symbol).await {
+                Ok(Some(quote)) => return Some(SourcedQuote::new(quote, source, now)),
+                Ok(None) => {}
+                Err(e) => tracing::debug!("No {:?} quote for {}: {}", source, symbol, e),
+            }
+        }
+        None
+    }
+
+    // Symbols no source could quote are left out
+    pub async fn quotes(&self, user_id: Uuid, symbols: &[String], now: DateTime<Utc>) -> Vec<SourcedQuote> {
+        let mut quotes = Vec::with_capacity(symbols.len());
+        for symbol in symbols {
+            quotes.extend(self.quote(user_id, symbol, now).await);
+        }
+        quotes
+    }
+
+    pub async fn floating(&self, user_id: Uuid, trades: &[Trade], now: DateTime<Utc>) -> Vec<FloatingTrade> {
+        self.floating_on(None, user_id, trades, now).await
+    }
+
+    pub async fn floating_on(
+        &self,
+        connection: Option<&dyn QuoteLookup>,
+        user_id: Uuid,
+        trades: &[Trade],
+        now: DateTime<Utc>,
+    ) -> Vec<FloatingTrade> {
+        let mut by_symbol: HashMap<&str, Option<SourcedQuote>> = HashMap::new();
+        let mut floating = Vec::with_capacity(trades.len());
+        for trade in trades {
+            if !by_symbol.contains_key(trade.symbol.as_str()) {
+                let quote = self.quote_on(connection, user_id, &trade.symbol, now).await;
+                by_symbol.insert(&trade.symbol, quote);
+            }
+            floating.push(FloatingTrade::value(trade, by_symbol[trade.symbol.as_str()].as_ref()));
+        }
+        floating
+    }
+}
+
+// The user's own connected broker session
+pub struct BrokerQuotes {
+    pool: PgPool,
+    mt5: Arc<Mt5Service>,
+}
+
+impl BrokerQuotes {
+    pub fn new(pool: PgPool, mt5: Arc<Mt5Service>) -> Self {
+        BrokerQuotes { pool, mt5 }
+    }
+}
+
+#[async_trait]
+impl QuoteLookup for BrokerQuotes {
+    async fn quote(&self, user_id: Uuid, symbol: &str) -> Result<Option<Quote>> {
+        let connections = BrokerConnection::find_by_user_id(&self.pool, user_id).await?;
+        let Some(connection_id) = connections
+            .iter()
+            .map(|c| c.id.to_string())
+            .find(|id| self.mt5.is_connected(id))
+        else {
+            return Ok(None);
+        };
+        let data = self.mt5.get_market_data(&connection_id, symbol).await?;
+        Ok(Some(Quote { symbol: data.symbol, bid: data.bid, ask: data.ask, time: data.time }))
+    }
+}
+
+// One particular broker connection's session; nothing while it is not connected
+pub struct ConnectionQuotes {
+    mt5: Arc<Mt5Service>,
+    connection_id: String,
+}
+
+impl ConnectionQuotes {
+    pub fn new(mt5: Arc<Mt5Service>, connection_id: Uuid) -> Self {
+        ConnectionQuotes { mt5, connection_id: connection_id.to_string() }
+    }
+}
+
+#[async_trait]
+impl QuoteLookup for ConnectionQuotes {
+    async fn quote(&self, _user_id: Uuid, symbol: &str) -> Result<Option<Quote>> {
+        if !self.mt5.is_connected(&self.connection_id) {
+            return Ok(None);
+        }
+        let data = self.mt5.get_market_data(&self.connection_id, symbol).await?;
+        Ok(Some(Quote { symbol: data.symbol, bid: data.bid, ask: data.ask, time: data.time }))
+    }
+}
+
+// Whatever session is open on the platform, through the shared cache so one session's quotes
+// serve every user without revealing whose they are
+pub struct PlatformQuotes {
+    cache: Arc<PlatformQuoteCache>,
+    mt5: Arc<Mt5Service>,
+}
+
+impl PlatformQuotes {
+    pub fn new(cache: Arc<PlatformQuoteCache>, mt5: Arc<Mt5Service>) -> Self {
+        PlatformQuotes { cache, mt5 }
+    }
+}
+
+#[async_trait]
+impl QuoteLookup for PlatformQuotes {
+    async fn quote(&self, _user_id: Uuid, symbol: &str) -> Result<Option<Quote>> {
+        if let Some(quote) = self.cache.latest(symbol, Utc::now()) {
+            return Ok(Some(quote));
+        }
+        let Some(connection_id) = self.mt5.any_connected() else {
+            return Ok(None);
+        };
+        let data = self.mt5.get_market_data(&connection_id, symbol).await?;
+        let quote = Quote { symbol: data.symbol, bid: data.bid, ask: data.ask, time: data.time };
+        self.cache.record(std::slice::from_ref(&quote));
+        Ok(Some(quote))
+    }
+}
+
+// GET {url}?symbol=EURUSD answering {"bid": ..., "ask": ..., "time": ...}; time is optional
+#[derive(Debug, Deserialize)]
+struct ExternalRate {
+    bid: f64,
+    ask: f64,
+    time: Option<DateTime<Utc>>,
+}
+
+pub struct ExternalRates {
+    client: reqwest::Client,
+    url: String,
+    api_key: Option<String>,
+}
+
+impl ExternalRates {
+    pub fn new(url: String, api_key: Option<String>) -> Self {
+        ExternalRates { client: reqwest::Client::new(), url, api_key }
+    }
+}
+
+#[async_trait]
+impl QuoteLookup for ExternalRates {
+    async fn quote(&self, _user_id: Uuid, symbol: &str) -> Result<Option<Quote>> {
+        let mut request = self
+            .client
+            .get(&self.url)
+            .query(&[("symbol", symbol)])
+            .timeout(std::time::Duration::from_secs(5));
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| AppError::External(format!("Rates API failed: {}", e)))?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            return Err(AppError::External(format!("Rates API answered {}", response.status())));
+        }
+        let rate: ExternalRate = response
+            .json()
+            .await
+            .map_err(|e| AppError::External(format!("Unreadable rates API answer: {}", e)))?;
+        Ok(Some(Quote {
+            symbol: symbol.to_string(),
+            bid: rate.bid,
+            ask: rate.ask,
+            time: rate.time.unwrap_or_else(Utc::now),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::sync::Mutex;
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, 4, 12, 0, 0).unwrap()
+    }
+
+    fn quote(symbol: &str, bid: f64, age_seconds: i64) -> Quote {
+        Quote {
+            symbol: symbol.to_string(),
+            bid,
+            ask: bid + 0.0002,
+            time: now() - chrono::Duration::seconds(age_seconds),
+        }
+    }
+
+    // A source that is either down or quotes a fixed price, and counts the lookups it got
+    struct FakeSource {
+        quote: Mutex<Option<Quote>>,
+        down: Mutex<bool>,
+        calls: Mutex<usize>,
+    }
+
+    impl FakeSource {
+        fn quoting(quote: Quote) -> Arc<Self> {
+            Arc::new(FakeSource { quote: Mutex::new(Some(quote)), down: Mutex::new(false), calls: Mutex::new(0) })
+        }
+
+        fn empty() -> Arc<Self> {
+            Arc::new(FakeSource { quote: Mutex::new(None), down: Mutex::new(false), calls: Mutex::new(0) })
+        }
+
+        fn take_down(&self) {
+            *self.down.lock().unwrap() = true;
+        }
+
+        fn calls(&self) -> usize {
+            *self.calls.lock().unwrap()
+        }
+    }
+
+    #[async_trait]
+    impl QuoteLookup for FakeSource {
+        async fn quote(&self, _user_id: Uuid, symbol: &str) -> Result<Option<Quote>> {
+            *self.calls.lock().unwrap() += 1;
+            if *self.down.lock().unwrap() {
+                return Err(AppError::BrokerUnavailable("No MT5 session".to_string()));
+            }
+            Ok(self.quote.lock().unwrap().clone().filter(|q| q.symbol == symbol))
+        }
+    }
+
+    fn chain(broker: &Arc<FakeSource>, platform: &Arc<FakeSource>, external: &Arc<FakeSource>) -> QuoteService {
+        QuoteService::new(vec![
+            (QuoteSource::Broker, broker.clone() as Arc<dyn QuoteLookup>),
+            (QuoteSource::Platform, platform.clone() as Arc<dyn QuoteLookup>),
+            (QuoteSource::External, external.clone() as Arc<dyn QuoteLookup>),
+        ])
+    }
+
+    fn trade(trade_type: &str) -> Trade {
+        Trade::new(Uuid::new_v4(), Uuid::new_v4(), "EURUSD".to_string(), trade_type.to_string(), 0.1, 1.1000, None, None, None, None)
+    }
+
+    #[tokio::test]
+    async fn test_chain_falls_back_in_order_when_the_broker_is_down() {
+        let broker = FakeSource::quoting(quote("EURUSD", 1.1010, 0));
+        let platform = FakeSource::quoting(quote("EURUSD", 1.1020, 12));
+        let external = FakeSource::quoting(quote("EURUSD", 1.1030, 40));
+        let service = chain(&broker, &platform, &external);
+        let user_id = Uuid::new_v4();
+
+        let live = service.quote(user_id, "EURUSD", now()).await.unwrap();
+        assert_eq!((live.source, live.bid, live.indicative), (QuoteSource::Broker, 1.1010, false));
+        assert_eq!(live.executable(), Some((1.1010, 1.1012)));
+        assert_eq!(platform.calls(), 0);
+
+        broker.take_down();
+        let fallback = service.quote(user_id, "EURUSD", now()).await.unwrap();
+        assert_eq!((fallback.source, fallback.bid, fallback.indicative), (QuoteSource::Platform, 1.1020, true));
+        assert_eq!(fallback.staleness_seconds, 12);
+        assert_eq!(external.calls(), 0);
+
+        platform.take_down();
+        let external_quote = service.quote(user_id, "EURUSD", now()).await.unwrap();
+        assert_eq!((external_quote.source, external_quote.indicative), (QuoteSource::External, true));
+        assert_eq!(external_quote.staleness_seconds, 40);
+
+        external.take_down();
+        assert!(service.quote(user_id, "EURUSD", now()).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_a_source_without_the_symbol_passes_to_the_next() {
+        let broker = FakeSource::empty();
+        let platform = FakeSource::quoting(quote("GBPUSD", 1.2700, 0));
+        let external = FakeSource::quoting(quote("EURUSD", 1.1030, 0));
+        let service = chain(&broker, &platform, &external);
+
+        let quotes = service
+            .quotes(Uuid::new_v4(), &["EURUSD".to_string(), "GBPUSD".to_string(), "USDJPY".to_string()], now())
+            .await;
+        let sources: Vec<_> = quotes.iter().map(|q| (q.symbol.as_str(), q.source)).collect();
+        assert_eq!(sources, vec![("EURUSD", QuoteSource::External), ("GBPUSD", QuoteSource::Platform)]);
+    }
+
+    #[tokio::test]
+    async fn test_a_connection_prices_its_own_trades_before_the_platform() {
+        let broker = FakeSource::quoting(quote("EURUSD", 1.1050, 0));
+        let platform = FakeSource::quoting(quote("EURUSD", 1.1040, 3));
+        let connection = FakeSource::quoting(quote("EURUSD", 1.1010, 0));
+        let service = chain(&broker, &platform, &FakeSource::empty());
+        let trades = vec![trade("buy")];
+
+        let own = service.floating_on(Some(connection.as_ref() as &dyn QuoteLookup), Uuid::new_v4(), &trades, now()).await;
+        assert_eq!((own[0].current_price, own[0].indicative), (Some(1.1010), false));
+
+        // Another of the user's sessions is not this account's broker
+        connection.take_down();
+        let fallback = service.floating_on(Some(connection.as_ref() as &dyn QuoteLookup), Uuid::new_v4(), &trades, now()).await;
+        assert_eq!((fallback[0].current_price, fallback[0].source), (Some(1.1040), Some(QuoteSource::Platform)));
+        assert_eq!(broker.calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_floating_pnl_from_a_fallback_is_indicative() {
+        let broker = FakeSource::quoting(quote("EURUSD", 1.1050, 0));
+        let platform = FakeSource::quoting(quote("EURUSD", 1.1040, 3));
+        let service = chain(&broker, &platform, &FakeSource::empty());
+        let trades = vec![trade("buy"), trade("sell")];
+
+        let live = service.floating(Uuid::new_v4(), &trades, now()).await;
+        assert!(live.iter().all(|t| !t.indicative && t.source == Some(QuoteSource::Broker)));
+        // Both trades share one lookup
+        assert_eq!(broker.calls(), 1);
+
+        broker.take_down();
+        let floating = service.floating(Uuid::new_v4(), &trades, now()).await;
+        assert!(floating.iter().all(|t| t.indicative && t.source == Some(QuoteSource::Platform)));
+        assert_eq!(floating[0].current_price, Some(1.1040));
+        assert!((floating[0].floating_profit_loss.unwrap() - 0.0040).abs() < 1e-9);
+        // Shorts are valued at the ask
+        assert_eq!(floating[1].current_price, Some(1.1042));
+        assert!((floating[1].floating_profit_loss.unwrap() + 0.0042).abs() < 1e-9);
+
+        platform.take_down();
+        let blank = service.floating(Uuid::new_v4(), &trades, now()).await;
+        assert_eq!((blank[0].current_price, blank[0].floating_profit_loss, blank[0].indicative), (None, None, false));
+    }
+
+    #[tokio::test]
+    async fn test_risk_decisions_ignore_indicative_quotes() {
+        let broker = FakeSource::quoting(quote("EURUSD", 1.1000, 0));
+        let platform = FakeSource::quoting(quote("EURUSD", 1.0900, 0));
+        let service = chain(&broker, &platform, &FakeSource::empty());
+        broker.take_down();
+
+        // A stop at 1.0950 would fire on the indicative bid; there is no executable price to fire on
+        let quote = service.quote(Uuid::new_v4(), "EURUSD", now()).await.unwrap();
+        assert!(quote.indicative);
+        assert_eq!(quote.executable(), None);
+    }
+
+    #[test]
+    fn test_platform_cache_keeps_the_newest_quote_and_drops_stale_ones() {
+        let cache = PlatformQuoteCache::new();
+        cache.record(&[quote("EURUSD", 1.1010, 10)]);
+        cache.record(&[quote("EURUSD", 1.1000, 30)]);
+        assert_eq!(cache.latest("EURUSD", now()).unwrap().bid, 1.1010);
+
+        cache.record(&[quote("GBPUSD", 1.2700, MAX_PLATFORM_QUOTE_AGE_SECONDS + 1)]);
+        assert!(cache.latest("GBPUSD", now()).is_none());
+        assert!(cache.latest("USDJPY", now()).is_none());
+    }
+}
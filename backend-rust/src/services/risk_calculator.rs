@@ -0,0 +1,297 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    errors::{AppError, Result},
+    models::TradingRobot,
+    services::{symbol_spec::SymbolSpec, AllocationService},
+};
+
+pub const DEFAULT_LEVERAGE: f64 = 100.0;
+pub const DEFAULT_ACCOUNT_CURRENCY: &str = "USD";
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct RiskCalculatorRequest {
+    pub symbol: String,
+    // Share of the balance risked on one trade, in percent: 1 for 1%
+    pub risk_percent: f64,
+    pub stop_loss_pips: f64,
+    // Defaults to the balance of the user's default broker connection
+    pub account_balance: Option<f64>,
+    // Currency of account_balance; USD when omitted
+    pub account_currency: Option<String>,
+    // Defaults to the latest quote for the symbol
+    pub price: Option<f64>,
+    pub leverage: Option<f64>,
+    // Share of the balance a robot may lose in a day, in percent; the runner's default when omitted
+    pub max_daily_loss_percent: Option<f64>,
+    // Positions open at the same time; 1 when omitted
+    pub max_open_positions: Option<u32>,
+}
+
+// The balance a calculation is made against and where it came from
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountTerms {
+    pub balance: f64,
+    pub currency: String,
+    // None when the balance was given in the request
+    pub broker_connection_id: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct RiskCalculation {
+    pub symbol: String,
+    #[serde(serialize_with = "crate::money::serialize_amount")]
+    pub account_balance: f64,
+    pub account_currency: String,
+    pub broker_connection_id: Option<Uuid>,
+    #[serde(serialize_with = "crate::money::serialize_price")]
+    pub price: f64,
+    pub leverage: f64,
+    // One pip on one lot, in the account currency
+    #[serde(serialize_with = "crate::money::serialize_amount")]
+    pub pip_value: f64,
+    // Lots, as the runner would send the order
+    pub position_size: f64,
+    #[serde(serialize_with = "crate::money::serialize_amount")]
+    pub requested_risk_amount: f64,
+    // What the position actually risks after the broker's lot rules, as an amount and in percent
+    #[serde(serialize_with = "crate::money::serialize_amount")]
+    pub risk_amount: f64,
+    #[serde(serialize_with = "crate::money::serialize_percent")]
+    pub risk_percent: f64,
+    // For one position
+    #[serde(serialize_with = "crate::money::serialize_amount")]
+    pub margin_required: f64,
+    #[serde(serialize_with = "crate::money::serialize_amount")]
+    pub worst_case_loss_per_trade: f64,
+    #[serde(serialize_with = "crate::money::serialize_amount")]
+    pub daily_loss_limit: f64,
+    #[serde(serialize_with = "crate::money::serialize_amount")]
+    pub worst_case_daily_loss: f64,
+    pub warnings: Vec<String>,
+}
+
+// Answers "what would a robot with these settings trade" without creating one. Sizing goes
+// through the same AllocationService calls the runner makes, on a robot that is never stored.
+pub struct RiskCalculator;
+
+impl RiskCalculator {
+    pub fn calculate(
+        request: &RiskCalculatorRequest,
+        spec: &SymbolSpec,
+        account: &AccountTerms,
+        price: f64,
+    ) -> Result<RiskCalculation> {
+        let leverage = request.leverage.unwrap_or(DEFAULT_LEVERAGE);
+        let max_open_positions = request.max_open_positions.unwrap_or(1);
+        Self::validate(request, account, price, leverage, max_open_positions)?;
+
+        let pip_value = spec.pip_value(price, &account.currency).ok_or_else(|| {
+            AppError::Validation(format!(
+                "{} cannot be valued in {}; the account currency has to be one side of the pair",
+                spec.symbol, account.currency
+            ))
+        })?;
+
+        let robot = Self::what_if_robot(request);
+        let unrounded = AllocationService::position_size(&robot, account.balance, pip_value);
+        let position_size = AllocationService::volume(&robot, account.balance, spec, pip_value);
+        let daily_loss_limit = AllocationService::daily_loss_limit(&robot, account.balance);
+        let margin_required = spec
+            .margin(position_size, price, leverage, &account.currency)
+            .unwrap_or_default();
+
+        let requested_risk_amount = account.balance * request.risk_percent / 100.0;
+        let risk_amount = position_size * request.stop_loss_pips * pip_value;
+        let risk_percent = risk_amount / account.balance * 100.0;
+        // The runner stops opening trades once the day's losses reach the limit, but every
+        // position still open then can run to its stop
+        let worst_case_daily_loss = daily_loss_limit + risk_amount * max_open_positions as f64;
+
+        let mut warnings = Vec::new();
+        // Rounding only ever goes down, so more risk than asked for means the minimum lot
+        if risk_amount > requested_risk_amount + 1e-9 {
+            warnings.push(format!(
+                "The broker's minimum of {} lots risks {:.2}% instead of the requested {}%",
+                spec.min_lot, risk_percent, request.risk_percent
+            ));
+        }
+        if unrounded > spec.max_lot {
+            warnings.push(format!("The position is capped at the broker's maximum of {} lots", spec.max_lot));
+        }
+        if risk_amount > daily_loss_limit {
+            warnings.push("A single stopped-out trade loses more than the daily loss limit".to_string());
+        }
+        if margin_required * max_open_positions as f64 > account.balance {
+            warnings.push(format!(
+                "{} open position(s) need more margin than the account balance at 1:{} leverage",
+                max_open_positions, leverage
+            ));
+        }
+
+        Ok(RiskCalculation {
+            symbol: spec.symbol.clone(),
+            account_balance: account.balance,
+            account_currency: account.currency.clone(),
+            broker_connection_id: account.broker_connection_id,
+            price,
+            leverage,
+            pip_value,
+            position_size,
+            requested_risk_amount,
+            risk_amount,
+            risk_percent,
+            margin_required,
+            worst_case_loss_per_trade: risk_amount,
+            daily_loss_limit,
+            worst_case_daily_loss,
+            warnings,
+        })
+    }
+
+    fn validate(
+        request: &RiskCalculatorRequest,
+        account: &AccountTerms,
+        price: f64,
+        leverage: f64,
+        max_open_positions: u32,
+    ) -> Result<()> {
+        let positive = |value: f64| value.is_finite() && value > 0.0;
+        let percent = |value: f64| positive(value) && value <= 100.0;
+        if !percent(request.risk_percent) {
+            return Err(AppError::Validation("risk_percent must be above 0 and at most 100".to_string()));
+        }
+        if request.max_daily_loss_percent.is_some_and(|value| !percent(value)) {
+            return Err(AppError::Validation("max_daily_loss_percent must be above 0 and at most 100".to_string()));
+        }
+        if !positive(request.stop_loss_pips) {
+            return Err(AppError::Validation("stop_loss_pips must be above 0".to_string()));
+        }
+        if !positive(account.balance) {
+            return Err(AppError::Validation("The account balance must be above 0".to_string()));
+        }
+        if !positive(price) {
+            return Err(AppError::Validation("price must be above 0".to_string()));
+        }
+        if !(leverage.is_finite() && leverage >= 1.0) {
+            return Err(AppError::Validation("leverage must be at least 1".to_string()));
+        }
+        if max_open_positions == 0 {
+            return Err(AppError::Validation("max_open_positions must be at least 1".to_string()));
+        }
+        Ok(())
+    }
+
+    // A robot carrying the request's risk settings over the platform defaults, as one created
+    // with them would
+    fn what_if_robot(request: &RiskCalculatorRequest) -> TradingRobot {
+        let mut robot = TradingRobot::new(Uuid::nil(), "what-if".to_string(), "what-if".to_string());
+        robot.risk_config["max_risk_per_trade"] = serde_json::json!(request.risk_percent / 100.0);
+        robot.risk_config["stop_loss_pips"] = serde_json::json!(request.stop_loss_pips);
+        if let Some(percent) = request.max_daily_loss_percent {
+            robot.risk_config["max_daily_loss"] = serde_json::json!(percent / 100.0);
+        }
+        robot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn request(risk_percent: f64, stop_loss_pips: f64) -> RiskCalculatorRequest {
+        RiskCalculatorRequest {
+            symbol: "EURUSD".to_string(),
+            risk_percent,
+            stop_loss_pips,
+            account_balance: None,
+            account_currency: None,
+            price: None,
+            leverage: None,
+            max_daily_loss_percent: None,
+            max_open_positions: None,
+        }
+    }
+
+    fn account(balance: f64) -> AccountTerms {
+        AccountTerms { balance, currency: "USD".to_string(), broker_connection_id: None }
+    }
+
+    fn eurusd() -> SymbolSpec {
+        SymbolSpec::for_symbol("EURUSD").unwrap()
+    }
+
+    // A stored robot with the same settings, sized the way the runner sizes it
+    fn runner_volume(risk_config: serde_json::Value, balance: f64, spec: &SymbolSpec, pip_value: f64) -> f64 {
+        let mut robot = TradingRobot::new(Uuid::new_v4(), "robot".to_string(), "trend".to_string());
+        robot.risk_config = risk_config;
+        AllocationService::volume(&robot, balance, spec, pip_value)
+    }
+
+    #[test]
+    fn test_sizing_matches_the_runner() {
+        let spec = eurusd();
+        let cases = [(5_000.0, 1.0, 20.0), (10_000.0, 2.0, 35.0), (25_000.0, 0.5, 12.5), (3_300.0, 1.5, 40.0)];
+        for (balance, risk_percent, stop) in cases {
+            let result = RiskCalculator::calculate(&request(risk_percent, stop), &spec, &account(balance), 1.1).unwrap();
+            let runner = runner_volume(
+                json!({ "max_risk_per_trade": risk_percent / 100.0, "stop_loss_pips": stop }),
+                balance,
+                &spec,
+                10.0,
+            );
+            assert_eq!(result.position_size, runner, "{} at {}% over {} pips", balance, risk_percent, stop);
+        }
+
+        // $5,000, 1% and a 20 pip stop on EURUSD: $50 over $200 a lot
+        let result = RiskCalculator::calculate(&request(1.0, 20.0), &spec, &account(5_000.0), 1.1).unwrap();
+        assert_eq!(result.position_size, 0.25);
+        assert!((result.worst_case_loss_per_trade - 50.0).abs() < 1e-9);
+        assert!((result.margin_required - 275.0).abs() < 1e-9);
+        // The runner's default daily limit is 5%
+        assert!((result.daily_loss_limit - 250.0).abs() < 1e-9);
+        assert!((result.worst_case_daily_loss - 300.0).abs() < 1e-9);
+        assert!(result.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_daily_settings_match_the_runner() {
+        let spec = eurusd();
+        let mut what_if = request(2.0, 20.0);
+        what_if.max_daily_loss_percent = Some(3.0);
+        what_if.max_open_positions = Some(3);
+        let result = RiskCalculator::calculate(&what_if, &spec, &account(10_000.0), 1.1).unwrap();
+
+        let mut robot = TradingRobot::new(Uuid::new_v4(), "robot".to_string(), "trend".to_string());
+        robot.risk_config = json!({ "max_risk_per_trade": 0.02, "stop_loss_pips": 20.0, "max_daily_loss": 0.03 });
+        assert_eq!(result.daily_loss_limit, AllocationService::daily_loss_limit(&robot, 10_000.0));
+        assert!((result.worst_case_daily_loss - (300.0 + 3.0 * 200.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_min_lot_above_the_requested_risk_is_warned_about() {
+        // $500 at 0.5% over 50 pips would be 0.005 lots; the broker's minimum is 0.01
+        let result = RiskCalculator::calculate(&request(0.5, 50.0), &eurusd(), &account(500.0), 1.1).unwrap();
+        assert_eq!(result.position_size, 0.01);
+        assert!((result.risk_percent - 1.0).abs() < 1e-9);
+        assert_eq!(result.warnings.len(), 1);
+        assert!(result.warnings[0].contains("minimum of 0.01 lots risks 1.00%"));
+    }
+
+    #[test]
+    fn test_unusable_inputs_are_refused() {
+        let spec = eurusd();
+        assert!(RiskCalculator::calculate(&request(0.0, 20.0), &spec, &account(5_000.0), 1.1).is_err());
+        assert!(RiskCalculator::calculate(&request(1.0, -5.0), &spec, &account(5_000.0), 1.1).is_err());
+        assert!(RiskCalculator::calculate(&request(1.0, 20.0), &spec, &account(0.0), 1.1).is_err());
+        // Neither side of EURUSD is JPY
+        let yen = AccountTerms { currency: "JPY".to_string(), ..account(500_000.0) };
+        assert!(matches!(
+            RiskCalculator::calculate(&request(1.0, 20.0), &spec, &yen, 1.1),
+            Err(AppError::Validation(_))
+        ));
+    }
+}
@@ -0,0 +1,103 @@
+use serde_json::{Map, Value};
+
+use crate::{
+    errors::{AppError, Result},
+    models::{CompositeStrategy, LossStreakCooldown, SignalStability, StopManagement, TradingRobot},
+};
+
+pub struct RiskTemplateService;
+
+impl RiskTemplateService {
+    // Checked with the same rules as a robot's risk_config. Allocation is per robot and
+    // per broker account, so it cannot be part of a template.
+    pub fn validate(template: Map<String, Value>) -> Result<Value> {
+        if template.contains_key("allocation_percent") {
+            return Err(AppError::Validation(
+                "allocation_percent cannot be part of a risk template; set it per robot".to_string(),
+            ));
+        }
+
+        let template = Value::Object(template);
+        StopManagement::from_risk_config(&template).map_err(AppError::Validation)?;
+        LossStreakCooldown::from_risk_config(&template).map_err(AppError::Validation)?;
+        SignalStability::from_risk_config(&template).map_err(AppError::Validation)?;
+        CompositeStrategy::from_risk_config(&template).map_err(AppError::Validation)?;
+        TradingRobot::evaluation_interval_from_risk_config(&template).map_err(AppError::Validation)?;
+        Ok(template)
+    }
+
+    // Platform defaults, then the user's template, then explicit settings, key by key
+    pub fn resolve(template: Option<&Value>, explicit: Option<&Value>) -> Value {
+        let mut risk_config = TradingRobot::default_risk_config();
+        for layer in [template, explicit].into_iter().flatten() {
+            if let (Value::Object(base), Value::Object(overrides)) = (&mut risk_config, layer) {
+                base.extend(overrides.clone());
+            }
+        }
+        risk_config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn template() -> Value {
+        RiskTemplateService::validate(
+            json!({ "max_risk_per_trade": 0.01, "stop_loss_pips": 15, "stop_management": "both" })
+                .as_object()
+                .cloned()
+                .unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_robot_without_config_starts_from_the_template() {
+        let risk_config = RiskTemplateService::resolve(Some(&template()), None);
+
+        assert_eq!(
+            risk_config,
+            json!({
+                "max_risk_per_trade": 0.01,
+                "stop_loss_pips": 15,
+                "stop_management": "both",
+                // Keys the template leaves out keep the platform defaults
+                "take_profit_pips": 40,
+                "max_daily_loss": 0.05,
+            })
+        );
+        assert_eq!(RiskTemplateService::resolve(None, None), TradingRobot::default_risk_config());
+    }
+
+    #[test]
+    fn test_explicit_config_wins_over_the_template_field_by_field() {
+        let explicit = json!({ "stop_loss_pips": 30, "take_profit_pips": 90 });
+
+        let risk_config = RiskTemplateService::resolve(Some(&template()), Some(&explicit));
+
+        assert_eq!(
+            risk_config,
+            json!({
+                "max_risk_per_trade": 0.01,
+                "stop_loss_pips": 30,
+                "stop_management": "both",
+                "take_profit_pips": 90,
+                "max_daily_loss": 0.05,
+            })
+        );
+    }
+
+    #[test]
+    fn test_template_is_validated_like_a_robot_config() {
+        for invalid in [
+            json!({ "stop_management": "manual" }),
+            json!({ "loss_streak_cooldown": { "streak": 0, "hours": 2 } }),
+            json!({ "allocation_percent": 50 }),
+        ] {
+            let err = RiskTemplateService::validate(invalid.as_object().cloned().unwrap()).unwrap_err();
+            assert!(matches!(err, AppError::Validation(_)), "{}", err);
+        }
+    }
+}
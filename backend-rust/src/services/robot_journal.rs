@@ -0,0 +1,350 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde_json::{json, Map, Value};
+use sqlx::PgPool;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{
+    errors::{AppError, Result},
+    models::{ChangeMarker, CompositeStrategy, LossStreakCooldown, RobotChange, SignalStability, StopManagement, TradingRobot, UpdateTradingRobotRequest},
+};
+
+#[async_trait]
+pub trait RobotJournalStore: Send + Sync {
+    // Saves the robot and its journal entry atomically
+    async fn save(&self, robot: &TradingRobot, change: Option<&RobotChange>) -> Result<()>;
+    async fn changes_since(&self, user_id: Uuid, start: DateTime<Utc>) -> Result<Vec<RobotChange>>;
+}
+
+pub struct PgRobotJournalStore {
+    pool: PgPool,
+}
+
+impl PgRobotJournalStore {
+    pub fn new(pool: PgPool) -> Self {
+        PgRobotJournalStore { pool }
+    }
+}
+
+#[async_trait]
+impl RobotJournalStore for PgRobotJournalStore {
+    async fn save(&self, robot: &TradingRobot, change: Option<&RobotChange>) -> Result<()> {
+        TradingRobot::update_config(&self.pool, robot, change).await
+    }
+
+    async fn changes_since(&self, user_id: Uuid, start: DateTime<Utc>) -> Result<Vec<RobotChange>> {
+        RobotChange::find_since(&self.pool, user_id, start).await
+    }
+}
+
+pub struct RobotJournal;
+
+impl RobotJournal {
+    // Field -> {"old", "new"} for every journaled field that differs. Notes are not journaled.
+    pub fn diff(old: &TradingRobot, new: &TradingRobot) -> Map<String, Value> {
+        let mut changes = Map::new();
+        if old.strategy != new.strategy {
+            changes.insert("strategy".to_string(), json!({ "old": old.strategy, "new": new.strategy }));
+        }
+
+        let empty = Map::new();
+        let old_config = old.risk_config.as_object().unwrap_or(&empty);
+        let new_config = new.risk_config.as_object().unwrap_or(&empty);
+        let mut keys: Vec<&String> = old_config.keys().chain(new_config.keys()).collect();
+        keys.sort();
+        keys.dedup();
+        for key in keys {
+            let (old_value, new_value) = (old_config.get(key), new_config.get(key));
+            if old_value != new_value {
+                changes.insert(
+                    format!("risk_config.{}", key),
+                    json!({ "old": old_value, "new": new_value }),
+                );
+            }
+        }
+        changes
+    }
+
+    // Applies the edit, validates the resulting risk_config and saves it along with a journal
+    // entry when a journaled field changed. `reset_risk_config` replaces the robot's settings
+    // (apart from its allocation) before the edit is applied. Returns the updated robot and the entry.
+    pub async fn update(
+        store: &dyn RobotJournalStore,
+        robot: &TradingRobot,
+        actor_id: Uuid,
+        request: UpdateTradingRobotRequest,
+        reset_risk_config: Option<Value>,
+        now: DateTime<Utc>,
+    ) -> Result<(TradingRobot, Option<RobotChange>)> {
+        request.validate().map_err(|e| AppError::Validation(e.to_string()))?;
+
+        let mut updated = robot.clone();
+        if let Some(strategy) = request.strategy {
+            updated.strategy = strategy;
+        }
+        if request.risk_config.is_some() || reset_risk_config.is_some() {
+            let mut risk_config = match reset_risk_config {
+                Some(Value::Object(mut base)) => {
+                    base.remove("allocation_percent");
+                    if let Some(allocation) = robot.risk_config.get("allocation_percent") {
+                        base.insert("allocation_percent".to_string(), allocation.clone());
+                    }
+                    base
+                }
+                _ => updated.risk_config.as_object().cloned().unwrap_or_default(),
+            };
+            for (key, value) in request.risk_config.unwrap_or_default() {
+                if key == "allocation_percent" {
+                    return Err(AppError::Validation(
+                        "allocation_percent is changed through PUT /api/v1/robots/:id/allocation".to_string(),
+                    ));
+                }
+                if value.is_null() {
+                    risk_config.remove(&key);
+                } else {
+                    risk_config.insert(key, value);
+                }
+            }
+            updated.risk_config = Value::Object(risk_config);
+            StopManagement::from_risk_config(&updated.risk_config).map_err(AppError::Validation)?;
+            LossStreakCooldown::from_risk_config(&updated.risk_config).map_err(AppError::Validation)?;
+            SignalStability::from_risk_config(&updated.risk_config).map_err(AppError::Validation)?;
+            CompositeStrategy::from_risk_config(&updated.risk_config).map_err(AppError::Validation)?;
+            TradingRobot::evaluation_interval_from_risk_config(&updated.risk_config).map_err(AppError::Validation)?;
+        }
+        if let Some(notes) = request.notes {
+            updated.notes = Some(notes).filter(|notes| !notes.trim().is_empty());
+        }
+        updated.updated_at = now;
+
+        let changes = Self::diff(robot, &updated);
+        let change = (!changes.is_empty()).then(|| RobotChange {
+            id: Uuid::new_v4(),
+            robot_id: robot.id,
+            user_id: robot.user_id,
+            actor_id: Some(actor_id),
+            changes: Value::Object(changes),
+            created_at: now,
+        });
+
+        store.save(&updated, change.as_ref()).await?;
+        Ok((updated, change))
+    }
+
+    pub async fn markers(store: &dyn RobotJournalStore, user_id: Uuid, start: DateTime<Utc>) -> Result<Vec<ChangeMarker>> {
+        Ok(store
+            .changes_since(user_id, start)
+            .await?
+            .iter()
+            .map(RobotChange::marker)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct FakeStore {
+        robots: Mutex<Vec<TradingRobot>>,
+        changes: Mutex<Vec<RobotChange>>,
+    }
+
+    #[async_trait]
+    impl RobotJournalStore for FakeStore {
+        async fn save(&self, robot: &TradingRobot, change: Option<&RobotChange>) -> Result<()> {
+            self.robots.lock().unwrap().push(robot.clone());
+            self.changes.lock().unwrap().extend(change.cloned());
+            Ok(())
+        }
+
+        async fn changes_since(&self, user_id: Uuid, start: DateTime<Utc>) -> Result<Vec<RobotChange>> {
+            Ok(self
+                .changes
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|c| c.user_id == user_id && c.created_at >= start)
+                .cloned()
+                .collect())
+        }
+    }
+
+    fn robot() -> TradingRobot {
+        let mut robot = TradingRobot::new(Uuid::new_v4(), "Trend".to_string(), "trend_following".to_string());
+        robot.risk_config = json!({ "max_risk_per_trade": 0.02, "symbols": ["EURUSD"] });
+        robot
+    }
+
+    fn risk_config(value: Value) -> Option<Map<String, Value>> {
+        value.as_object().cloned()
+    }
+
+    #[tokio::test]
+    async fn test_two_edits_are_journaled_and_overlaid_as_markers() {
+        let store = FakeStore::default();
+        let robot = robot();
+        let actor_id = robot.user_id;
+        let first_at = Utc.with_ymd_and_hms(2023, 12, 4, 9, 30, 0).unwrap();
+        let second_at = first_at + Duration::days(2);
+
+        let (robot, first) = RobotJournal::update(
+            &store,
+            &robot,
+            actor_id,
+            UpdateTradingRobotRequest {
+                risk_config: risk_config(json!({ "max_risk_per_trade": 0.01, "schedule": "london" })),
+                notes: Some("Halved risk after the NFP drawdown".to_string()),
+                ..Default::default()
+            },
+            None,
+            first_at,
+        )
+        .await
+        .unwrap();
+
+        let first = first.unwrap();
+        assert_eq!(
+            first.changes,
+            json!({
+                "risk_config.max_risk_per_trade": { "old": 0.02, "new": 0.01 },
+                "risk_config.schedule": { "old": null, "new": "london" },
+            })
+        );
+        assert_eq!(first.actor_id, Some(actor_id));
+        assert_eq!(robot.notes.as_deref(), Some("Halved risk after the NFP drawdown"));
+
+        let (robot, second) = RobotJournal::update(
+            &store,
+            &robot,
+            actor_id,
+            UpdateTradingRobotRequest {
+                strategy: Some("mean_reversion".to_string()),
+                risk_config: risk_config(json!({ "symbols": ["EURUSD", "GBPUSD"], "schedule": null })),
+                ..Default::default()
+            },
+            None,
+            second_at,
+        )
+        .await
+        .unwrap();
+
+        let second = second.unwrap();
+        assert_eq!(
+            second.changes,
+            json!({
+                "strategy": { "old": "trend_following", "new": "mean_reversion" },
+                "risk_config.schedule": { "old": "london", "new": null },
+                "risk_config.symbols": { "old": ["EURUSD"], "new": ["EURUSD", "GBPUSD"] },
+            })
+        );
+        assert_eq!(robot.risk_config, json!({ "max_risk_per_trade": 0.01, "symbols": ["EURUSD", "GBPUSD"] }));
+        // Notes survive an edit that leaves them out
+        assert_eq!(robot.notes.as_deref(), Some("Halved risk after the NFP drawdown"));
+
+        let markers = RobotJournal::markers(&store, robot.user_id, first_at - Duration::days(1)).await.unwrap();
+        assert_eq!(
+            markers,
+            vec![
+                ChangeMarker {
+                    robot_id: robot.id,
+                    change_id: first.id,
+                    changed_at: first_at,
+                    fields: vec!["risk_config.max_risk_per_trade".to_string(), "risk_config.schedule".to_string()],
+                },
+                ChangeMarker {
+                    robot_id: robot.id,
+                    change_id: second.id,
+                    changed_at: second_at,
+                    fields: vec![
+                        "risk_config.schedule".to_string(),
+                        "risk_config.symbols".to_string(),
+                        "strategy".to_string(),
+                    ],
+                },
+            ]
+        );
+
+        // A marker window starting after the first edit only shows the second
+        let markers = RobotJournal::markers(&store, robot.user_id, first_at + Duration::hours(1)).await.unwrap();
+        assert_eq!(markers.len(), 1);
+        assert_eq!(markers[0].change_id, second.id);
+    }
+
+    #[tokio::test]
+    async fn test_notes_only_edit_writes_no_journal_entry() {
+        let store = FakeStore::default();
+        let robot = robot();
+
+        let (updated, change) = RobotJournal::update(
+            &store,
+            &robot,
+            robot.user_id,
+            UpdateTradingRobotRequest { notes: Some("Watching spreads".to_string()), ..Default::default() },
+            None,
+            Utc::now(),
+        )
+        .await
+        .unwrap();
+
+        assert!(change.is_none());
+        assert_eq!(updated.notes.as_deref(), Some("Watching spreads"));
+        assert_eq!(store.robots.lock().unwrap().len(), 1);
+        assert!(store.changes.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_risk_config_is_rejected_before_saving() {
+        let store = FakeStore::default();
+        let robot = robot();
+
+        for overrides in [json!({ "stop_management": "sometimes" }), json!({ "allocation_percent": 25.0 })] {
+            let err = RobotJournal::update(
+                &store,
+                &robot,
+                robot.user_id,
+                UpdateTradingRobotRequest { risk_config: risk_config(overrides), ..Default::default() },
+                None,
+                Utc::now(),
+            )
+            .await
+            .unwrap_err();
+            assert!(matches!(err, AppError::Validation(_)), "{}", err);
+        }
+        assert!(store.robots.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_reset_to_template_keeps_the_allocation_and_is_journaled() {
+        let store = FakeStore::default();
+        let mut robot = robot();
+        robot.risk_config = json!({ "max_risk_per_trade": 0.05, "allocation_percent": 40.0, "symbols": ["XAUUSD"] });
+        let defaults = json!({ "max_risk_per_trade": 0.01, "stop_loss_pips": 15 });
+
+        let (updated, change) = RobotJournal::update(
+            &store,
+            &robot,
+            robot.user_id,
+            UpdateTradingRobotRequest { risk_config: risk_config(json!({ "stop_loss_pips": 25 })), ..Default::default() },
+            Some(defaults),
+            Utc::now(),
+        )
+        .await
+        .unwrap();
+
+        // Edits sent along with the reset still apply on top of it
+        assert_eq!(updated.risk_config, json!({ "max_risk_per_trade": 0.01, "stop_loss_pips": 25, "allocation_percent": 40.0 }));
+        assert_eq!(
+            change.unwrap().changes,
+            json!({
+                "risk_config.max_risk_per_trade": { "old": 0.05, "new": 0.01 },
+                "risk_config.stop_loss_pips": { "old": null, "new": 25 },
+                "risk_config.symbols": { "old": ["XAUUSD"], "new": null },
+            })
+        );
+    }
+}
@@ -0,0 +1,510 @@
+use async_trait::async_trait;
+use futures_util::{stream, StreamExt};
+use serde::Serialize;
+use sqlx::PgPool;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+use crate::{
+    errors::{AppError, Result},
+    models::{BrokerAccountKey, BrokerConnection, RobotLog, Trade, TradingRobot, User},
+    services::{mt5_service::Mt5Position, robot_runner::RobotRunnerRegistry, Mt5Service, NotificationService},
+};
+
+const RECOVERY_CONCURRENCY: usize = 8;
+pub const PAUSED_BROKER: &str = "paused_broker";
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct RecoveryReport {
+    pub recovered: usize,
+    pub paused_broker: usize,
+    pub failed: usize,
+    pub reconciliation_issues: usize,
+}
+
+enum RobotRecoveryOutcome {
+    Recovered { issues: usize },
+    PausedBroker,
+    Failed,
+}
+
+// Everything recovery needs from the database, the broker and the mailer
+#[async_trait]
+pub trait RecoveryEnv: Send + Sync {
+    async fn recoverable_robots(&self) -> Result<Vec<TradingRobot>>;
+    async fn open_trades(&self, robot: &TradingRobot) -> Result<Vec<Trade>>;
+    // Returns the broker connection id the robot trades through once it is reachable
+    async fn preflight(&self, robot: &TradingRobot) -> Result<String>;
+    // Opens or cancels the robot's orders a shutdown left execution_pending; returns those opened
+    async fn resolve_pending_orders(&self, robot: &TradingRobot, connection_id: &str) -> Result<Vec<Trade>>;
+    // Returns how many open trades disagree with the broker's positions
+    async fn reconcile(&self, robot: &TradingRobot, connection_id: &str, trades: &[Trade]) -> Result<usize>;
+    async fn set_status(&self, robot: &TradingRobot, status: &str) -> Result<()>;
+    async fn log(&self, robot: &TradingRobot, level: &str, message: &str) -> Result<()>;
+    async fn notify_paused(&self, robot: &TradingRobot, reason: &str) -> Result<()>;
+}
+
+pub struct PgRecoveryEnv {
+    pool: PgPool,
+    mt5: Arc<Mt5Service>,
+    notifications: Arc<NotificationService>,
+    // Connection already reached for each broker account this sweep. Robots on duplicate
+    // connections share it, so the account is connected to and reconciled through once.
+    reached: Mutex<HashMap<(Uuid, BrokerAccountKey), String>>,
+}
+
+impl PgRecoveryEnv {
+    pub fn new(pool: PgPool, mt5: Arc<Mt5Service>, notifications: Arc<NotificationService>) -> Self {
+        PgRecoveryEnv { pool, mt5, notifications, reached: Mutex::new(HashMap::new()) }
+    }
+}
+
+#[async_trait]
+impl RecoveryEnv for PgRecoveryEnv {
+    async fn recoverable_robots(&self) -> Result<Vec<TradingRobot>> {
+        Ok(TradingRobot::find_recoverable(&self.pool).await?)
+    }
+
+    async fn open_trades(&self, robot: &TradingRobot) -> Result<Vec<Trade>> {
+        Ok(Trade::get_open_trades_for_robot(&self.pool, robot.id).await?)
+    }
+
+    async fn preflight(&self, robot: &TradingRobot) -> Result<String> {
+        let connection_id = robot
+            .broker_connection_id
+            .ok_or_else(|| AppError::BrokerUnavailable("Robot has no broker connection".to_string()))?;
+
+        let connection = BrokerConnection::find_by_id(&self.pool, connection_id, robot.user_id)
+            .await?
+            .ok_or_else(|| AppError::BrokerUnavailable("Broker connection no longer exists".to_string()))?;
+
+        if !connection.is_active {
+            return Err(AppError::BrokerUnavailable("Broker connection is disabled".to_string()));
+        }
+
+        let account = connection.account_key().map(|key| (connection.user_id, key));
+        if let Some(reached) = account.as_ref().and_then(|a| self.reached.lock().unwrap().get(a).cloned()) {
+            return Ok(reached);
+        }
+
+        self.mt5.connect(&connection).await?;
+        self.mt5.get_account_info(&connection.id.to_string()).await?;
+
+        if let Some(account) = account {
+            self.reached.lock().unwrap().insert(account, connection.id.to_string());
+        }
+        Ok(connection.id.to_string())
+    }
+
+    async fn resolve_pending_orders(&self, robot: &TradingRobot, connection_id: &str) -> Result<Vec<Trade>> {
+        let pending = Trade::find_execution_pending(&self.pool, robot.id).await?;
+        if pending.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let positions = self.mt5.get_positions(connection_id).await?;
+        let claimed: HashSet<String> = Trade::open_broker_trade_ids(&self.pool, robot.user_id).await?.into_iter().collect();
+
+        let mut opened = Vec::new();
+        for (mut trade, ticket) in RobotRecovery::match_pending_orders(pending, &positions, &claimed) {
+            Trade::resolve_execution_pending(&self.pool, trade.id, ticket).await?;
+            match ticket {
+                Some(ticket) => {
+                    trade.status = "open".to_string();
+                    trade.broker_trade_id = Some(ticket.to_string());
+                    opened.push(trade);
+                }
+                None => tracing::warn!("Order for trade {} never reached a broker position; cancelled", trade.id),
+            }
+        }
+        Ok(opened)
+    }
+
+    async fn reconcile(&self, robot: &TradingRobot, connection_id: &str, trades: &[Trade]) -> Result<usize> {
+        let tickets: HashSet<String> = self
+            .mt5
+            .get_positions(connection_id)
+            .await?
+            .into_iter()
+            .map(|p| p.ticket.to_string())
+            .collect();
+
+        let missing = trades
+            .iter()
+            .filter(|t| t.broker_trade_id.as_ref().is_some_and(|id| !tickets.contains(id)))
+            .count();
+
+        if missing > 0 {
+            tracing::warn!(
+                "Robot {} has {} open trade(s) with no matching broker position",
+                robot.id,
+                missing
+            );
+        }
+
+        Ok(missing)
+    }
+
+    async fn set_status(&self, robot: &TradingRobot, status: &str) -> Result<()> {
+        Ok(TradingRobot::update_status(&self.pool, robot.id, robot.user_id, status).await?)
+    }
+
+    async fn log(&self, robot: &TradingRobot, level: &str, message: &str) -> Result<()> {
+        RobotLog::create(&self.pool, robot.id, robot.user_id, level, message).await?;
+        Ok(())
+    }
+
+    async fn notify_paused(&self, robot: &TradingRobot, reason: &str) -> Result<()> {
+        let user = User::find_by_id(&self.pool, robot.user_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+        self.notifications
+            .send_robot_status_notification(&user.email, &robot.name, &format!("paused ({})", reason))
+            .await
+    }
+}
+
+pub struct RobotRecovery;
+
+impl RobotRecovery {
+    // Pairs each pending order with an unclaimed position of the same symbol, side and volume
+    pub fn match_pending_orders(
+        pending: Vec<Trade>,
+        positions: &[Mt5Position],
+        claimed: &HashSet<String>,
+    ) -> Vec<(Trade, Option<i64>)> {
+        let mut claimed = claimed.clone();
+        pending
+            .into_iter()
+            .map(|trade| {
+                let ticket = positions
+                    .iter()
+                    .find(|p| {
+                        !claimed.contains(&p.ticket.to_string())
+                            && p.symbol == trade.symbol
+                            && p.position_type.eq_ignore_ascii_case(&trade.trade_type)
+                            && (p.volume - trade.volume).abs() < 1e-9
+                    })
+                    .map(|p| p.ticket);
+                if let Some(ticket) = ticket {
+                    claimed.insert(ticket.to_string());
+                }
+                (trade, ticket)
+            })
+            .collect()
+    }
+
+    // Restarts runners for every robot that was running when the server went down
+    pub async fn recover(env: &dyn RecoveryEnv, registry: &RobotRunnerRegistry) -> Result<RecoveryReport> {
+        let robots = env.recoverable_robots().await?;
+        tracing::info!("Recovering {} robot(s) after restart", robots.len());
+
+        let outcomes: Vec<RobotRecoveryOutcome> = stream::iter(robots)
+            .map(|robot| async move { Self::recover_robot(env, registry, robot).await })
+            .buffer_unordered(RECOVERY_CONCURRENCY)
+            .collect()
+            .await;
+
+        let mut report = RecoveryReport::default();
+        for outcome in outcomes {
+            match outcome {
+                RobotRecoveryOutcome::Recovered { issues } => {
+                    report.recovered += 1;
+                    report.reconciliation_issues += issues;
+                }
+                RobotRecoveryOutcome::PausedBroker => report.paused_broker += 1,
+                RobotRecoveryOutcome::Failed => report.failed += 1,
+            }
+        }
+
+        tracing::info!(
+            "Robot recovery finished: {} recovered, {} paused for broker, {} failed",
+            report.recovered,
+            report.paused_broker,
+            report.failed
+        );
+        Ok(report)
+    }
+
+    async fn recover_robot(
+        env: &dyn RecoveryEnv,
+        registry: &RobotRunnerRegistry,
+        robot: TradingRobot,
+    ) -> RobotRecoveryOutcome {
+        let mut trades = match env.open_trades(&robot).await {
+            Ok(trades) => trades,
+            Err(e) => {
+                tracing::error!("Could not load open trades for robot {}: {}", robot.id, e);
+                return RobotRecoveryOutcome::Failed;
+            }
+        };
+
+        let connection_id = match env.preflight(&robot).await {
+            Ok(connection_id) => connection_id,
+            Err(e) => return Self::pause_for_broker(env, registry, &robot, &trades, &e.to_string()).await,
+        };
+
+        match env.resolve_pending_orders(&robot, &connection_id).await {
+            Ok(opened) => trades.extend(opened),
+            Err(e) => tracing::warn!("Could not resolve pending orders for robot {}: {}", robot.id, e),
+        }
+
+        let issues = match env.reconcile(&robot, &connection_id, &trades).await {
+            Ok(issues) => issues,
+            Err(e) => {
+                tracing::warn!("Reconciliation failed for robot {}: {}", robot.id, e);
+                0
+            }
+        };
+
+        // A robot paused for risk stays paused; it only gets its trades watched again
+        registry.start(robot.id, robot.user_id, robot.status != "active");
+        let monitored = registry.monitor_trades(robot.id, &trades);
+
+        let message = format!(
+            "Recovered after restart ({} open trade(s) monitored, {} reconciliation issue(s))",
+            monitored, issues
+        );
+        if let Err(e) = env.log(&robot, "info", &message).await {
+            tracing::warn!("Could not write recovery log for robot {}: {}", robot.id, e);
+        }
+
+        RobotRecoveryOutcome::Recovered { issues }
+    }
+
+    async fn pause_for_broker(
+        env: &dyn RecoveryEnv,
+        registry: &RobotRunnerRegistry,
+        robot: &TradingRobot,
+        trades: &[Trade],
+        reason: &str,
+    ) -> RobotRecoveryOutcome {
+        tracing::warn!("Broker preflight failed for robot {}: {}", robot.id, reason);
+
+        if robot.status != PAUSED_BROKER {
+            if let Err(e) = env.set_status(robot, PAUSED_BROKER).await {
+                tracing::error!("Could not pause robot {}: {}", robot.id, e);
+                return RobotRecoveryOutcome::Failed;
+            }
+            if let Err(e) = env.notify_paused(robot, reason).await {
+                tracing::warn!("Could not notify owner of robot {}: {}", robot.id, e);
+            }
+        }
+
+        // Open trades still need SL/TP monitoring while the broker is unreachable
+        registry.start(robot.id, robot.user_id, true);
+        registry.monitor_trades(robot.id, trades);
+
+        let message = format!("Paused after restart: broker preflight failed ({})", reason);
+        if let Err(e) = env.log(robot, "warn", &message).await {
+            tracing::warn!("Could not write recovery log for robot {}: {}", robot.id, e);
+        }
+
+        RobotRecoveryOutcome::PausedBroker
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    fn robot(status: &str) -> TradingRobot {
+        TradingRobot {
+            id: Uuid::new_v4(),
+            user_id: Uuid::new_v4(),
+            name: "Robot".to_string(),
+            strategy: "scalping".to_string(),
+            status: status.to_string(),
+            risk_config: serde_json::json!({}),
+            performance_metrics: serde_json::json!({}),
+            last_signal_at: None,
+            total_trades: 0,
+            broker_connection_id: Some(Uuid::new_v4()),
+            notes: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    fn open_trade(robot: &TradingRobot, broker_trade_id: &str) -> Trade {
+        Trade {
+            id: Uuid::new_v4(),
+            user_id: robot.user_id,
+            robot_id: robot.id,
+            symbol: "EURUSD".to_string(),
+            trade_type: "BUY".to_string(),
+            volume: 0.1,
+            entry_price: 1.1,
+            exit_price: None,
+            stop_loss: Some(1.09),
+            take_profit: Some(1.12),
+            status: "open".to_string(),
+            profit_loss: None,
+            commission: None,
+            swap: None,
+            ai_confidence: None,
+            ai_reasoning: None,
+            broker_trade_id: Some(broker_trade_id.to_string()),
+            is_demo: false,
+            stop_management: "platform".to_string(),
+            reentered_from: None,
+            opened_at: Utc::now(),
+            closed_at: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[derive(Default)]
+    struct MockEnv {
+        robots: Vec<TradingRobot>,
+        trades: HashMap<Uuid, Vec<Trade>>,
+        unreachable: HashSet<Uuid>,
+        broker_tickets: HashSet<String>,
+        statuses: Mutex<HashMap<Uuid, String>>,
+        logs: Mutex<Vec<(Uuid, String, String)>>,
+        notified: Mutex<Vec<Uuid>>,
+    }
+
+    #[async_trait]
+    impl RecoveryEnv for MockEnv {
+        async fn recoverable_robots(&self) -> Result<Vec<TradingRobot>> {
+            Ok(self.robots.clone())
+        }
+
+        async fn open_trades(&self, robot: &TradingRobot) -> Result<Vec<Trade>> {
+            Ok(self.trades.get(&robot.id).cloned().unwrap_or_default())
+        }
+
+        async fn preflight(&self, robot: &TradingRobot) -> Result<String> {
+            if self.unreachable.contains(&robot.id) {
+                return Err(AppError::BrokerUnavailable("connection refused".to_string()));
+            }
+            Ok(robot.broker_connection_id.unwrap().to_string())
+        }
+
+        async fn resolve_pending_orders(&self, _robot: &TradingRobot, _connection_id: &str) -> Result<Vec<Trade>> {
+            Ok(Vec::new())
+        }
+
+        async fn reconcile(&self, _robot: &TradingRobot, _connection_id: &str, trades: &[Trade]) -> Result<usize> {
+            Ok(trades
+                .iter()
+                .filter(|t| !self.broker_tickets.contains(t.broker_trade_id.as_ref().unwrap()))
+                .count())
+        }
+
+        async fn set_status(&self, robot: &TradingRobot, status: &str) -> Result<()> {
+            self.statuses.lock().unwrap().insert(robot.id, status.to_string());
+            Ok(())
+        }
+
+        async fn log(&self, robot: &TradingRobot, level: &str, message: &str) -> Result<()> {
+            self.logs
+                .lock()
+                .unwrap()
+                .push((robot.id, level.to_string(), message.to_string()));
+            Ok(())
+        }
+
+        async fn notify_paused(&self, robot: &TradingRobot, _reason: &str) -> Result<()> {
+            self.notified.lock().unwrap().push(robot.id);
+            Ok(())
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_restart_rehydrates_runners_and_monitors_open_trades() {
+        let active = robot("active");
+        let paused_risk = robot("paused_risk");
+
+        let mut env = MockEnv::default();
+        env.trades.insert(
+            active.id,
+            vec![open_trade(&active, "1001"), open_trade(&active, "1002")],
+        );
+        env.trades.insert(paused_risk.id, vec![open_trade(&paused_risk, "2001")]);
+        env.broker_tickets = ["1001", "2001"].iter().map(|t| t.to_string()).collect();
+        env.robots = vec![active.clone(), paused_risk.clone()];
+
+        let registry = RobotRunnerRegistry::new();
+        let report = RobotRecovery::recover(&env, &registry).await.unwrap();
+
+        assert_eq!(report.recovered, 2);
+        assert_eq!(report.paused_broker, 0);
+        assert_eq!(report.reconciliation_issues, 1);
+
+        assert!(registry.is_running(active.id));
+        assert!(registry.is_running(paused_risk.id));
+
+        let statuses = registry.statuses();
+        let active_status = statuses.iter().find(|s| s.robot_id == active.id).unwrap();
+        assert!(!active_status.paused);
+        assert_eq!(active_status.monitored_trades, 2);
+        let risk_status = statuses.iter().find(|s| s.robot_id == paused_risk.id).unwrap();
+        assert!(risk_status.paused);
+        assert_eq!(risk_status.monitored_trades, 1);
+
+        let logs = env.logs.lock().unwrap();
+        assert_eq!(logs.len(), 2);
+        assert!(logs.iter().all(|(_, level, message)| level == "info" && message.starts_with("Recovered after restart")));
+        assert!(env.statuses.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failed_preflight_pauses_robot_and_notifies_owner() {
+        let reachable = robot("active");
+        let unreachable = robot("active");
+
+        let mut env = MockEnv::default();
+        env.trades.insert(unreachable.id, vec![open_trade(&unreachable, "3001")]);
+        env.unreachable.insert(unreachable.id);
+        env.robots = vec![reachable.clone(), unreachable.clone()];
+
+        let registry = RobotRunnerRegistry::new();
+        let report = RobotRecovery::recover(&env, &registry).await.unwrap();
+
+        assert_eq!(report.recovered, 1);
+        assert_eq!(report.paused_broker, 1);
+        assert_eq!(report.failed, 0);
+
+        assert_eq!(
+            env.statuses.lock().unwrap().get(&unreachable.id).map(String::as_str),
+            Some(PAUSED_BROKER)
+        );
+        assert_eq!(*env.notified.lock().unwrap(), vec![unreachable.id]);
+
+        // The runner exists but stays paused so open trades keep their SL/TP watch
+        let statuses = registry.statuses();
+        let status = statuses.iter().find(|s| s.robot_id == unreachable.id).unwrap();
+        assert!(status.paused);
+        assert_eq!(status.monitored_trades, 1);
+
+        let logs = env.logs.lock().unwrap();
+        assert!(logs
+            .iter()
+            .any(|(id, level, _)| *id == unreachable.id && level == "warn"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_robot_already_paused_for_broker_is_not_notified_again() {
+        let paused = robot(PAUSED_BROKER);
+
+        let mut env = MockEnv::default();
+        env.unreachable.insert(paused.id);
+        env.robots = vec![paused.clone()];
+
+        let registry = RobotRunnerRegistry::new();
+        let report = RobotRecovery::recover(&env, &registry).await.unwrap();
+
+        assert_eq!(report.paused_broker, 1);
+        assert!(env.statuses.lock().unwrap().is_empty());
+        assert!(env.notified.lock().unwrap().is_empty());
+        assert!(registry.is_running(paused.id));
+    }
+}
@@ -0,0 +1,859 @@
+use async_trait::async_trait;
+use serde::Serialize;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::{
+    errors::{AppError, Result},
+    models::{CompositeStrategy, SignalStability, StopManagement, Trade, TradingRobot, User},
+    services::{
+        composite_signal::{CompositeSignal, StrategyRegistry},
+        cooldown_service::{CooldownEnv, CooldownService},
+        execution_queue::{on_behalf_of, Requester},
+        plan_service::PlanLimiter,
+        signal_stability::{ConfirmationState, OpenPosition, RobotSignal, SignalDecision, SignalFilter, SignalHistoryEntry},
+        task_supervisor::{TaskClass, TaskSupervisor},
+        Mt5Service, NotificationService,
+    },
+};
+
+const RUNNER_TICK: Duration = Duration::from_secs(5);
+// Set on a robot whose runner panicked; recovery leaves it alone until the owner restarts it
+pub const RUNNER_ERROR: &str = "error";
+
+// Protective levels watched for an open trade
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MonitoredTrade {
+    pub trade_id: Uuid,
+    pub robot_id: Uuid,
+    pub user_id: Uuid,
+    pub symbol: String,
+    pub trade_type: String,
+    pub stop_loss: Option<f64>,
+    pub take_profit: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StopReason {
+    StopLoss,
+    TakeProfit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct StopTrigger {
+    pub reason: StopReason,
+    pub price: f64,
+}
+
+impl MonitoredTrade {
+    // Longs exit on the bid, shorts on the ask
+    pub fn trigger(&self, bid: f64, ask: f64) -> Option<StopTrigger> {
+        let is_buy = self.trade_type.eq_ignore_ascii_case("buy");
+        let price = if is_buy { bid } else { ask };
+
+        let stop_hit = self
+            .stop_loss
+            .is_some_and(|sl| if is_buy { price <= sl } else { price >= sl });
+        if stop_hit {
+            return Some(StopTrigger { reason: StopReason::StopLoss, price });
+        }
+
+        let target_hit = self
+            .take_profit
+            .is_some_and(|tp| if is_buy { price >= tp } else { price <= tp });
+        if target_hit {
+            return Some(StopTrigger { reason: StopReason::TakeProfit, price });
+        }
+
+        None
+    }
+}
+
+impl From<&Trade> for MonitoredTrade {
+    fn from(trade: &Trade) -> Self {
+        MonitoredTrade {
+            trade_id: trade.id,
+            robot_id: trade.robot_id,
+            user_id: trade.user_id,
+            symbol: trade.symbol.clone(),
+            trade_type: trade.trade_type.clone(),
+            stop_loss: trade.stop_loss,
+            take_profit: trade.take_profit,
+        }
+    }
+}
+
+// Price source and closer used by platform-managed stops
+#[async_trait]
+pub trait StopExecutor: Send + Sync {
+    // Returns (bid, ask)
+    async fn quote(&self, trade: &MonitoredTrade) -> Result<(f64, f64)>;
+    // Returns the realized profit/loss
+    async fn close(&self, trade: &MonitoredTrade, trigger: StopTrigger) -> Result<f64>;
+}
+
+pub struct Mt5StopExecutor {
+    pool: PgPool,
+    mt5: Arc<Mt5Service>,
+}
+
+impl Mt5StopExecutor {
+    pub fn new(pool: PgPool, mt5: Arc<Mt5Service>) -> Self {
+        Mt5StopExecutor { pool, mt5 }
+    }
+
+    async fn connection_id(&self, trade: &MonitoredTrade) -> Result<String> {
+        TradingRobot::find_by_id(&self.pool, trade.robot_id, trade.user_id)
+            .await?
+            .and_then(|robot| robot.broker_connection_id)
+            .map(|id| id.to_string())
+            .ok_or_else(|| AppError::BrokerUnavailable("Robot has no broker connection".to_string()))
+    }
+}
+
+#[async_trait]
+impl StopExecutor for Mt5StopExecutor {
+    async fn quote(&self, trade: &MonitoredTrade) -> Result<(f64, f64)> {
+        let connection_id = self.connection_id(trade).await?;
+        let market = self.mt5.get_market_data(&connection_id, &trade.symbol).await?;
+        Ok((market.bid, market.ask))
+    }
+
+    async fn close(&self, monitored: &MonitoredTrade, trigger: StopTrigger) -> Result<f64> {
+        let trade = Trade::find_by_id(&self.pool, monitored.trade_id, monitored.user_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound("Trade not found".to_string()))?;
+
+        if let Some(ticket) = trade.broker_trade_id.as_deref().and_then(|t| t.parse().ok()) {
+            let connection_id = self.connection_id(monitored).await?;
+            let plan = User::find_by_id(&self.pool, monitored.user_id)
+                .await?
+                .map(|user| user.subscription_plan)
+                .unwrap_or_default();
+            let requester = Requester::new(monitored.user_id, &plan).with_robot(monitored.robot_id);
+            on_behalf_of(requester, self.mt5.close_position(&connection_id, ticket)).await?;
+        }
+
+        let profit_loss = trade.calculate_profit_loss(trigger.price);
+        Trade::close_open_trade(&self.pool, trade.id, trade.user_id, trigger.price, profit_loss).await?;
+        TradingRobot::refresh_performance(&self.pool, trade.robot_id).await?;
+        Ok(profit_loss)
+    }
+}
+
+// A robot's latest evaluation, with the settings and open position it applies to
+#[derive(Debug, Clone)]
+pub struct SignalContext {
+    pub signal: RobotSignal,
+    pub stability: SignalStability,
+    pub position: Option<OpenPosition>,
+    // Set for robots combining several strategies; their signals then replace `signal`
+    pub composite: Option<CompositeStrategy>,
+}
+
+// Produces strategy signals and opens the orders the runner decides to act on
+#[async_trait]
+pub trait SignalEngine: Send + Sync {
+    async fn evaluate(&self, robot_id: Uuid) -> Result<SignalContext>;
+    async fn execute(&self, robot_id: Uuid, signal: &RobotSignal) -> Result<()>;
+}
+
+// The engine and the confirmation state its signals go through
+#[derive(Clone)]
+struct SignalPipeline {
+    engine: Arc<dyn SignalEngine>,
+    strategies: Arc<StrategyRegistry>,
+    filter: Arc<Mutex<SignalFilter>>,
+}
+
+// Everything one runner task works with
+struct RunnerContext {
+    robot_id: Uuid,
+    tick: Duration,
+    paused: Arc<AtomicBool>,
+    monitored: Arc<Mutex<HashMap<Uuid, MonitoredTrade>>>,
+    stops: Option<Arc<dyn StopExecutor>>,
+    cooldowns: Option<Arc<dyn CooldownEnv>>,
+    signals: Option<SignalPipeline>,
+    limiter: Option<Arc<dyn PlanLimiter>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RunnerStatus {
+    pub robot_id: Uuid,
+    pub user_id: Uuid,
+    pub paused: bool,
+    pub monitored_trades: usize,
+}
+
+struct RobotRunner {
+    user_id: Uuid,
+    paused: Arc<AtomicBool>,
+    monitored: Arc<Mutex<HashMap<Uuid, MonitoredTrade>>>,
+    signals: Arc<Mutex<SignalFilter>>,
+    task: JoinHandle<()>,
+}
+
+// Told when a runner panics, so the robot does not stay active without one
+#[async_trait]
+pub trait RunnerCrashHandler: Send + Sync {
+    async fn runner_crashed(&self, robot_id: Uuid, user_id: Uuid) -> Result<()>;
+}
+
+pub struct PgRunnerCrashHandler {
+    pool: PgPool,
+    notifications: Arc<NotificationService>,
+}
+
+impl PgRunnerCrashHandler {
+    pub fn new(pool: PgPool, notifications: Arc<NotificationService>) -> Self {
+        PgRunnerCrashHandler { pool, notifications }
+    }
+}
+
+#[async_trait]
+impl RunnerCrashHandler for PgRunnerCrashHandler {
+    async fn runner_crashed(&self, robot_id: Uuid, user_id: Uuid) -> Result<()> {
+        TradingRobot::update_status(&self.pool, robot_id, user_id, RUNNER_ERROR).await?;
+
+        let robot = TradingRobot::find_by_id(&self.pool, robot_id, user_id).await?;
+        let owner = User::find_by_id(&self.pool, user_id).await?;
+        if let (Some(robot), Some(owner)) = (robot, owner) {
+            self.notifications
+                .send_robot_status_notification(&owner.email, &robot.name, RUNNER_ERROR)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+// Owns one background task per running robot
+pub struct RobotRunnerRegistry {
+    runners: Mutex<HashMap<Uuid, RobotRunner>>,
+    tick: Duration,
+    stops: Option<Arc<dyn StopExecutor>>,
+    cooldowns: Option<Arc<dyn CooldownEnv>>,
+    signals: Option<Arc<dyn SignalEngine>>,
+    strategies: Arc<StrategyRegistry>,
+    supervisor: TaskSupervisor,
+    crashes: Option<Arc<dyn RunnerCrashHandler>>,
+    limiter: Option<Arc<dyn PlanLimiter>>,
+}
+
+impl RobotRunnerRegistry {
+    pub fn new() -> Self {
+        Self::with_tick(RUNNER_TICK)
+    }
+
+    pub fn with_tick(tick: Duration) -> Self {
+        RobotRunnerRegistry {
+            runners: Mutex::new(HashMap::new()),
+            tick,
+            stops: None,
+            cooldowns: None,
+            signals: None,
+            strategies: Arc::new(StrategyRegistry::default()),
+            supervisor: TaskSupervisor::new(),
+            crashes: None,
+            limiter: None,
+        }
+    }
+
+    // Runner panics are reported through the supervisor's alerts
+    pub fn with_supervisor(mut self, supervisor: TaskSupervisor) -> Self {
+        self.supervisor = supervisor;
+        self
+    }
+
+    pub fn with_crash_handler(mut self, crashes: Arc<dyn RunnerCrashHandler>) -> Self {
+        self.crashes = Some(crashes);
+        self
+    }
+
+    // Without an executor, platform-managed stops are tracked but never enforced
+    pub fn with_stop_executor(mut self, stops: Arc<dyn StopExecutor>) -> Self {
+        self.stops = Some(stops);
+        self
+    }
+
+    // Stop closes count toward the robot's loss streak cooldown
+    pub fn with_cooldowns(mut self, cooldowns: Arc<dyn CooldownEnv>) -> Self {
+        self.cooldowns = Some(cooldowns);
+        self
+    }
+
+    // Without an engine, runners only enforce stops
+    pub fn with_signal_engine(mut self, signals: Arc<dyn SignalEngine>) -> Self {
+        self.signals = Some(signals);
+        self
+    }
+
+    // Without a limiter, signals are evaluated every tick
+    pub fn with_plan_limiter(mut self, limiter: Arc<dyn PlanLimiter>) -> Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    // Strategies composite robots are evaluated with
+    pub fn with_strategies(mut self, strategies: StrategyRegistry) -> Self {
+        self.strategies = Arc::new(strategies);
+        self
+    }
+
+    // Starts a runner, or updates the pause state of an existing one. Returns true if a task was spawned.
+    pub fn start(&self, robot_id: Uuid, user_id: Uuid, paused: bool) -> bool {
+        let mut runners = self.runners.lock().unwrap();
+        if let Some(runner) = runners.get(&robot_id) {
+            if !runner.task.is_finished() {
+                runner.paused.store(paused, Ordering::Relaxed);
+                return false;
+            }
+        }
+
+        let paused_flag = Arc::new(AtomicBool::new(paused));
+        let monitored = Arc::new(Mutex::new(HashMap::new()));
+        let filter = Arc::new(Mutex::new(SignalFilter::default()));
+        let pipeline = self.signals.clone().map(|engine| SignalPipeline {
+            engine,
+            strategies: self.strategies.clone(),
+            filter: filter.clone(),
+        });
+        let run = run_robot(RunnerContext {
+            robot_id,
+            tick: self.tick,
+            paused: paused_flag.clone(),
+            monitored: monitored.clone(),
+            stops: self.stops.clone(),
+            cooldowns: self.cooldowns.clone(),
+            signals: pipeline,
+            limiter: self.limiter.clone(),
+        });
+        let crashes = self.crashes.clone();
+        let on_panic = move || async move {
+            if let Some(crashes) = crashes {
+                if let Err(e) = crashes.runner_crashed(robot_id, user_id).await {
+                    tracing::error!("Could not mark robot {} as errored: {}", robot_id, e);
+                }
+            }
+        };
+        let task = self
+            .supervisor
+            .spawn_owned(format!("robot:{}", robot_id), TaskClass::RobotRunner, run, on_panic);
+
+        runners.insert(
+            robot_id,
+            RobotRunner {
+                user_id,
+                paused: paused_flag,
+                monitored,
+                signals: filter,
+                task,
+            },
+        );
+        true
+    }
+
+    pub fn stop(&self, robot_id: Uuid) -> bool {
+        match self.runners.lock().unwrap().remove(&robot_id) {
+            Some(runner) => {
+                runner.task.abort();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn set_paused(&self, robot_id: Uuid, paused: bool) {
+        if let Some(runner) = self.runners.lock().unwrap().get(&robot_id) {
+            runner.paused.store(paused, Ordering::Relaxed);
+        }
+    }
+
+    // Registers platform-managed trades for SL/TP monitoring; returns how many the runner now watches.
+    // Trades whose stops live only on the broker are left to the broker.
+    pub fn monitor_trades(&self, robot_id: Uuid, trades: &[Trade]) -> usize {
+        let runners = self.runners.lock().unwrap();
+        let Some(runner) = runners.get(&robot_id) else {
+            return 0;
+        };
+
+        let mut monitored = runner.monitored.lock().unwrap();
+        for trade in trades.iter().filter(|t| t.status == "open") {
+            let mode = StopManagement::parse(&trade.stop_management).unwrap_or_default();
+            if mode.platform_monitors() {
+                monitored.insert(trade.id, MonitoredTrade::from(trade));
+            }
+        }
+        monitored.len()
+    }
+
+    pub fn unmonitor_trade(&self, robot_id: Uuid, trade_id: Uuid) {
+        if let Some(runner) = self.runners.lock().unwrap().get(&robot_id) {
+            runner.monitored.lock().unwrap().remove(&trade_id);
+        }
+    }
+
+    pub fn is_running(&self, robot_id: Uuid) -> bool {
+        self.runners
+            .lock()
+            .unwrap()
+            .get(&robot_id)
+            .map(|r| !r.task.is_finished())
+            .unwrap_or(false)
+    }
+
+    // Confirmation state and recent decisions, while the robot has a runner
+    pub fn signal_history(&self, robot_id: Uuid) -> Option<(ConfirmationState, Vec<SignalHistoryEntry>)> {
+        let runners = self.runners.lock().unwrap();
+        let filter = runners.get(&robot_id)?.signals.lock().unwrap();
+        Some((filter.state(), filter.history()))
+    }
+
+    pub fn statuses(&self) -> Vec<RunnerStatus> {
+        let runners = self.runners.lock().unwrap();
+        let mut statuses: Vec<RunnerStatus> = runners
+            .iter()
+            .map(|(robot_id, runner)| RunnerStatus {
+                robot_id: *robot_id,
+                user_id: runner.user_id,
+                paused: runner.paused.load(Ordering::Relaxed),
+                monitored_trades: runner.monitored.lock().unwrap().len(),
+            })
+            .collect();
+        statuses.sort_by_key(|s| s.robot_id);
+        statuses
+    }
+}
+
+impl Default for RobotRunnerRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for RobotRunnerRegistry {
+    fn drop(&mut self) {
+        for (_, runner) in self.runners.lock().unwrap().drain() {
+            runner.task.abort();
+        }
+    }
+}
+
+// Closes every monitored trade whose SL or TP has been crossed. A close that completes a
+// losing streak pauses the runner until the cooldown is over.
+async fn enforce_stops(
+    monitored: &Mutex<HashMap<Uuid, MonitoredTrade>>,
+    stops: &dyn StopExecutor,
+    cooldowns: Option<&dyn CooldownEnv>,
+    paused: &AtomicBool,
+) {
+    let trades: Vec<MonitoredTrade> = monitored.lock().unwrap().values().cloned().collect();
+
+    for trade in trades {
+        let (bid, ask) = match stops.quote(&trade).await {
+            Ok(quote) => quote,
+            Err(e) => {
+                tracing::warn!("No quote for {} on trade {}: {}", trade.symbol, trade.trade_id, e);
+                continue;
+            }
+        };
+
+        let Some(trigger) = trade.trigger(bid, ask) else {
+            continue;
+        };
+
+        match stops.close(&trade, trigger).await {
+            Ok(profit_loss) => {
+                tracing::info!("Closed trade {} on {:?} at {}", trade.trade_id, trigger.reason, trigger.price);
+                monitored.lock().unwrap().remove(&trade.trade_id);
+
+                let Some(cooldowns) = cooldowns else {
+                    continue;
+                };
+                match CooldownService::record_close(cooldowns, trade.robot_id, trade.user_id, profit_loss, chrono::Utc::now()).await {
+                    Ok(Some(_)) => paused.store(true, Ordering::Relaxed),
+                    Ok(None) => {}
+                    Err(e) => tracing::error!("Could not record loss streak for robot {}: {}", trade.robot_id, e),
+                }
+            }
+            Err(e) => tracing::error!("Failed to close trade {} on {:?}: {}", trade.trade_id, trigger.reason, e),
+        }
+    }
+}
+
+// Feeds the latest signal through the confirmation filter and executes it once it is stable
+async fn evaluate_signals(robot_id: Uuid, signals: &SignalPipeline) {
+    let mut context = match signals.engine.evaluate(robot_id).await {
+        Ok(context) => context,
+        Err(e) => {
+            tracing::warn!("Could not evaluate signals for robot {}: {}", robot_id, e);
+            return;
+        }
+    };
+    if let Some(composite) = &context.composite {
+        context.signal = CompositeSignal::evaluate(&signals.strategies, robot_id, composite).await;
+    }
+
+    let decision = signals.filter.lock().unwrap().observe(
+        robot_id,
+        &context.signal,
+        &context.stability,
+        context.position.as_ref(),
+        chrono::Utc::now(),
+    );
+    if decision == SignalDecision::Execute {
+        if let Err(e) = signals.engine.execute(robot_id, &context.signal).await {
+            tracing::error!("Robot {} failed to act on its {} signal: {}", robot_id, context.signal.direction, e);
+        }
+    }
+}
+
+async fn run_robot(context: RunnerContext) {
+    let RunnerContext { robot_id, tick, paused, monitored, stops, cooldowns, signals, limiter } = context;
+    let mut interval = tokio::time::interval(tick);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut evaluation_interval = tick;
+    let mut last_evaluation: Option<tokio::time::Instant> = None;
+
+    loop {
+        let now = interval.tick().await;
+
+        // Paused robots keep enforcing stops on their open trades but open nothing new
+        if let Some(stops) = &stops {
+            enforce_stops(&monitored, stops.as_ref(), cooldowns.as_deref(), &paused).await;
+        }
+
+        let watched = monitored.lock().unwrap().len();
+        if paused.load(Ordering::Relaxed) {
+            tracing::trace!("Robot {} paused, watching {} trade(s)", robot_id, watched);
+            continue;
+        }
+
+        if let Some(signals) = &signals {
+            // Read every cycle, so a downgrade slows the robot down from its next evaluation
+            if let Some(limiter) = &limiter {
+                match limiter.evaluation_interval(robot_id).await {
+                    Ok(plan_interval) => evaluation_interval = plan_interval,
+                    Err(e) => tracing::warn!(
+                        "Could not read the evaluation interval of robot {}, keeping {:?}: {}",
+                        robot_id, evaluation_interval, e
+                    ),
+                }
+            }
+            if !matches!(last_evaluation, Some(at) if now - at < evaluation_interval) {
+                last_evaluation = Some(now);
+                evaluate_signals(robot_id, signals).await;
+            }
+        }
+        tracing::trace!("Robot {} tick, watching {} trade(s)", robot_id, watched);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trade(trade_type: &str, mode: StopManagement) -> Trade {
+        Trade {
+            stop_management: mode.as_str().to_string(),
+            ..Trade::new(
+                Uuid::new_v4(),
+                Uuid::new_v4(),
+                "EURUSD".to_string(),
+                trade_type.to_string(),
+                1.0,
+                1.1000,
+                Some(1.0950),
+                Some(1.1100),
+                None,
+                None,
+            )
+        }
+    }
+
+    // Replays a fixed list of mid prices and records what it was asked to close
+    struct MockStops {
+        prices: Mutex<Vec<f64>>,
+        closed: Mutex<Vec<(Uuid, StopTrigger)>>,
+    }
+
+    impl MockStops {
+        fn new(prices: &[f64]) -> Arc<Self> {
+            Arc::new(MockStops {
+                prices: Mutex::new(prices.iter().rev().copied().collect()),
+                closed: Mutex::new(Vec::new()),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl StopExecutor for MockStops {
+        async fn quote(&self, _trade: &MonitoredTrade) -> Result<(f64, f64)> {
+            let mut prices = self.prices.lock().unwrap();
+            let mid = if prices.len() > 1 { prices.pop().unwrap() } else { prices[0] };
+            Ok((mid - 0.0001, mid + 0.0001))
+        }
+
+        async fn close(&self, trade: &MonitoredTrade, trigger: StopTrigger) -> Result<f64> {
+            self.closed.lock().unwrap().push((trade.trade_id, trigger));
+            Ok(0.0)
+        }
+    }
+
+    #[test]
+    fn test_triggers_use_the_exit_side_of_the_quote() {
+        let long = MonitoredTrade::from(&trade("buy", StopManagement::Platform));
+        assert_eq!(long.trigger(1.1050, 1.1052), None);
+        assert_eq!(
+            long.trigger(1.0949, 1.0951),
+            Some(StopTrigger { reason: StopReason::StopLoss, price: 1.0949 })
+        );
+        assert_eq!(long.trigger(1.1100, 1.1102).unwrap().reason, StopReason::TakeProfit);
+
+        let short = MonitoredTrade {
+            stop_loss: Some(1.1050),
+            take_profit: Some(1.0900),
+            ..MonitoredTrade::from(&trade("sell", StopManagement::Platform))
+        };
+        assert_eq!(short.trigger(1.1049, 1.1051).unwrap().reason, StopReason::StopLoss);
+        assert_eq!(
+            short.trigger(1.0898, 1.0900),
+            Some(StopTrigger { reason: StopReason::TakeProfit, price: 1.0900 })
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_platform_mode_closes_at_the_level() {
+        let stops = MockStops::new(&[1.1000, 1.0980, 1.0960, 1.0940]);
+        let registry = RobotRunnerRegistry::with_tick(Duration::from_secs(1)).with_stop_executor(stops.clone());
+
+        let platform = trade("buy", StopManagement::Platform);
+        let robot_id = platform.robot_id;
+        registry.start(robot_id, platform.user_id, false);
+        assert_eq!(registry.monitor_trades(robot_id, std::slice::from_ref(&platform)), 1);
+
+        tokio::time::sleep(Duration::from_secs(10)).await;
+
+        let closed = stops.closed.lock().unwrap();
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].0, platform.id);
+        assert_eq!(closed[0].1.reason, StopReason::StopLoss);
+        assert!((closed[0].1.price - 1.0939).abs() < 1e-9);
+        assert_eq!(registry.statuses()[0].monitored_trades, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_broker_mode_is_left_to_the_broker() {
+        let stops = MockStops::new(&[1.0900]);
+        let registry = RobotRunnerRegistry::with_tick(Duration::from_secs(1)).with_stop_executor(stops.clone());
+
+        let broker = trade("buy", StopManagement::Broker);
+        let both = Trade {
+            robot_id: broker.robot_id,
+            ..trade("buy", StopManagement::Both)
+        };
+        registry.start(broker.robot_id, broker.user_id, false);
+        assert_eq!(registry.monitor_trades(broker.robot_id, &[broker.clone(), both.clone()]), 1);
+
+        tokio::time::sleep(Duration::from_secs(5)).await;
+
+        // Only the "both" trade gets a platform close; the broker-only trade is never touched
+        let closed = stops.closed.lock().unwrap();
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].0, both.id);
+    }
+
+    struct PanickingStops;
+
+    #[async_trait]
+    impl StopExecutor for PanickingStops {
+        async fn quote(&self, _trade: &MonitoredTrade) -> Result<(f64, f64)> {
+            panic!("deliberate");
+        }
+
+        async fn close(&self, _trade: &MonitoredTrade, _trigger: StopTrigger) -> Result<f64> {
+            unreachable!()
+        }
+    }
+
+    #[derive(Default)]
+    struct RecordedCrashes {
+        crashed: Mutex<Vec<(Uuid, Uuid)>>,
+    }
+
+    #[async_trait]
+    impl RunnerCrashHandler for RecordedCrashes {
+        async fn runner_crashed(&self, robot_id: Uuid, user_id: Uuid) -> Result<()> {
+            self.crashed.lock().unwrap().push((robot_id, user_id));
+            Ok(())
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_panicking_runner_marks_its_robot_and_is_not_restarted() {
+        let crashes = Arc::new(RecordedCrashes::default());
+        let registry = RobotRunnerRegistry::with_tick(Duration::from_secs(1))
+            .with_stop_executor(Arc::new(PanickingStops))
+            .with_crash_handler(crashes.clone());
+
+        let platform = trade("buy", StopManagement::Platform);
+        registry.start(platform.robot_id, platform.user_id, false);
+        registry.monitor_trades(platform.robot_id, std::slice::from_ref(&platform));
+
+        tokio::time::sleep(Duration::from_secs(5)).await;
+
+        assert_eq!(*crashes.crashed.lock().unwrap(), vec![(platform.robot_id, platform.user_id)]);
+        assert!(!registry.is_running(platform.robot_id));
+    }
+
+    // Replays a fixed list of signal directions, then holds
+    struct ScriptedSignals {
+        directions: Mutex<Vec<&'static str>>,
+        stability: SignalStability,
+        composite: Option<CompositeStrategy>,
+        executed: Mutex<Vec<String>>,
+    }
+
+    impl ScriptedSignals {
+        fn new(directions: &[&'static str], confirmation_count: u32) -> Arc<Self> {
+            Arc::new(ScriptedSignals {
+                directions: Mutex::new(directions.iter().rev().copied().collect()),
+                stability: SignalStability { confirmation_count, ..SignalStability::default() },
+                composite: None,
+                executed: Mutex::new(Vec::new()),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl SignalEngine for ScriptedSignals {
+        async fn evaluate(&self, _robot_id: Uuid) -> Result<SignalContext> {
+            let direction = self.directions.lock().unwrap().pop().unwrap_or("hold");
+            Ok(SignalContext {
+                signal: RobotSignal { direction: direction.to_string(), confidence: 0.7, components: Vec::new() },
+                stability: self.stability,
+                position: None,
+                composite: self.composite.clone(),
+            })
+        }
+
+        async fn execute(&self, _robot_id: Uuid, signal: &RobotSignal) -> Result<()> {
+            self.executed.lock().unwrap().push(signal.direction.clone());
+            Ok(())
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_alternating_signals_never_trade() {
+        let signals = ScriptedSignals::new(&["buy", "sell", "buy", "sell", "buy", "sell", "buy", "sell"], 2);
+        let registry = RobotRunnerRegistry::with_tick(Duration::from_secs(1)).with_signal_engine(signals.clone());
+        let robot_id = Uuid::new_v4();
+        registry.start(robot_id, Uuid::new_v4(), false);
+
+        tokio::time::sleep(Duration::from_secs(10)).await;
+
+        assert!(signals.executed.lock().unwrap().is_empty());
+        let (confirmation, history) = registry.signal_history(robot_id).unwrap();
+        assert_eq!(confirmation.suppressed_flips, 7);
+        assert!(history
+            .iter()
+            .filter(|e| e.direction != "hold")
+            .all(|e| e.decision == SignalDecision::Pending { seen: 1, required: 2 }));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stable_signal_trades_once_confirmed() {
+        let signals = ScriptedSignals::new(&["buy", "buy", "buy", "buy"], 3);
+        let registry = RobotRunnerRegistry::with_tick(Duration::from_secs(1)).with_signal_engine(signals.clone());
+        let robot_id = Uuid::new_v4();
+        registry.start(robot_id, Uuid::new_v4(), false);
+
+        tokio::time::sleep(Duration::from_secs(10)).await;
+
+        assert_eq!(*signals.executed.lock().unwrap(), vec!["buy".to_string()]);
+        let (confirmation, _) = registry.signal_history(robot_id).unwrap();
+        assert_eq!((confirmation.suppressed_flips, confirmation.required), (0, 3));
+    }
+
+    // The plan's interval for every robot, changed by the test as a plan change would
+    struct SwitchablePlan {
+        interval: Mutex<Duration>,
+    }
+
+    #[async_trait]
+    impl PlanLimiter for SwitchablePlan {
+        async fn evaluation_interval(&self, _robot_id: Uuid) -> Result<Duration> {
+            Ok(*self.interval.lock().unwrap())
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_downgrade_slows_evaluation_from_the_next_cycle() {
+        let signals = ScriptedSignals::new(&[], 1);
+        let plan = Arc::new(SwitchablePlan { interval: Mutex::new(Duration::from_secs(5)) });
+        let registry = RobotRunnerRegistry::with_tick(Duration::from_secs(1))
+            .with_signal_engine(signals.clone())
+            .with_plan_limiter(plan.clone());
+        let robot_id = Uuid::new_v4();
+        registry.start(robot_id, Uuid::new_v4(), false);
+        let evaluations = |registry: &RobotRunnerRegistry| registry.signal_history(robot_id).unwrap().1.len();
+
+        // Elite: every 5s on a 1s tick, at 0s, 5s and 10s
+        tokio::time::sleep(Duration::from_secs(12)).await;
+        assert_eq!(evaluations(&registry), 3);
+
+        // Down to free without restarting the runner: the next evaluation waits a full minute
+        *plan.interval.lock().unwrap() = Duration::from_secs(60);
+        tokio::time::sleep(Duration::from_secs(57)).await;
+        assert_eq!(evaluations(&registry), 3);
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert_eq!(evaluations(&registry), 4);
+        assert!(registry.is_running(robot_id));
+    }
+
+    struct Fixed(&'static str);
+
+    #[async_trait]
+    impl crate::services::composite_signal::Strategy for Fixed {
+        async fn signal(&self, _robot_id: Uuid) -> Result<RobotSignal> {
+            Ok(RobotSignal { direction: self.0.to_string(), confidence: 0.8, components: Vec::new() })
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_composite_robot_trades_on_its_strategies_and_records_them() {
+        let composite: CompositeStrategy = serde_json::from_value(serde_json::json!({
+            "mode": "unanimous",
+            "strategies": [{"strategy": "ai_model", "weight": 0.5}, {"strategy": "mean_reversion", "weight": 0.5}]
+        }))
+        .unwrap();
+        // The engine's own signal is ignored for a composite robot
+        let signals = Arc::new(ScriptedSignals {
+            directions: Mutex::new(vec!["sell"]),
+            stability: SignalStability::default(),
+            composite: Some(composite),
+            executed: Mutex::new(Vec::new()),
+        });
+        let strategies = StrategyRegistry::default()
+            .register("ai_model", Arc::new(Fixed("buy")))
+            .register("mean_reversion", Arc::new(Fixed("buy")));
+        let registry = RobotRunnerRegistry::with_tick(Duration::from_secs(1))
+            .with_signal_engine(signals.clone())
+            .with_strategies(strategies);
+        let robot_id = Uuid::new_v4();
+        registry.start(robot_id, Uuid::new_v4(), false);
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        assert_eq!(*signals.executed.lock().unwrap(), vec!["buy".to_string()]);
+        let (_, history) = registry.signal_history(robot_id).unwrap();
+        let strategies: Vec<&str> = history[0].components.iter().map(|c| c.strategy.as_str()).collect();
+        assert_eq!(strategies, vec!["ai_model", "mean_reversion"]);
+    }
+}
@@ -0,0 +1,234 @@
+use async_trait::async_trait;
+use sqlx::PgPool;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+use crate::{
+    errors::{AppError, Result},
+    models::{AdminSetting, RuntimeSettings, RUNTIME_SETTING},
+    services::{broker_throttle::BrokerThrottle, scheduler::JobIntervals},
+};
+
+pub const RUNTIME_SETTINGS_POLL_SECONDS: u64 = 10;
+
+const MAX_JOB_INTERVAL_SECONDS: u64 = 7 * 24 * 60 * 60;
+
+#[async_trait]
+pub trait RuntimeSettingsSource: Send + Sync {
+    async fn load(&self) -> Result<RuntimeSettings>;
+}
+
+pub struct PgRuntimeSettingsSource {
+    pool: PgPool,
+}
+
+impl PgRuntimeSettingsSource {
+    pub fn new(pool: PgPool) -> Self {
+        PgRuntimeSettingsSource { pool }
+    }
+}
+
+#[async_trait]
+impl RuntimeSettingsSource for PgRuntimeSettingsSource {
+    async fn load(&self) -> Result<RuntimeSettings> {
+        AdminSetting::get(&self.pool, RUNTIME_SETTING).await
+    }
+}
+
+// The installed tracing filter, and the directives it falls back to when no level is set
+pub struct LogFilter {
+    handle: reload::Handle<EnvFilter, Registry>,
+    default_directives: String,
+}
+
+impl LogFilter {
+    pub fn new(handle: reload::Handle<EnvFilter, Registry>, default_directives: String) -> Self {
+        LogFilter { handle, default_directives }
+    }
+
+    fn set(&self, directives: Option<&str>) -> Result<()> {
+        let filter = EnvFilter::try_new(directives.unwrap_or(&self.default_directives))
+            .map_err(|e| AppError::Internal(anyhow::anyhow!("Invalid log level: {}", e)))?;
+        self.handle
+            .reload(filter)
+            .map_err(|e| AppError::Internal(anyhow::anyhow!("Could not reload the log filter: {}", e)))
+    }
+}
+
+// Each instance polls the stored settings and applies what changed, so an admin edit reaches all of
+// them within one poll. The log filter, broker throttle and scheduler read from here.
+pub struct RuntimeConfig {
+    source: Arc<dyn RuntimeSettingsSource>,
+    log_filter: Option<LogFilter>,
+    throttle: Option<Arc<BrokerThrottle>>,
+    current: RwLock<Arc<RuntimeSettings>>,
+}
+
+impl RuntimeConfig {
+    pub fn new(source: Arc<dyn RuntimeSettingsSource>) -> Self {
+        RuntimeConfig {
+            source,
+            log_filter: None,
+            throttle: None,
+            current: RwLock::new(Arc::new(RuntimeSettings::default())),
+        }
+    }
+
+    pub fn with_log_filter(mut self, log_filter: LogFilter) -> Self {
+        self.log_filter = Some(log_filter);
+        self
+    }
+
+    pub fn with_throttle(mut self, throttle: Arc<BrokerThrottle>) -> Self {
+        self.throttle = Some(throttle);
+        self
+    }
+
+    pub fn current(&self) -> Arc<RuntimeSettings> {
+        self.current.read().unwrap().clone()
+    }
+
+    pub fn validate(settings: &RuntimeSettings) -> Result<()> {
+        if let Some(level) = &settings.log_level {
+            EnvFilter::try_new(level).map_err(|e| AppError::Validation(format!("Invalid log_level: {}", e)))?;
+        }
+        for (broker_type, limit) in &settings.broker_rate_limits {
+            if limit.requests_per_second <= 0.0 || limit.burst == 0 {
+                return Err(AppError::Validation(format!(
+                    "Rate limit for {} needs a positive requests_per_second and burst",
+                    broker_type
+                )));
+            }
+        }
+        for (job, seconds) in &settings.job_intervals {
+            if !(1..=MAX_JOB_INTERVAL_SECONDS).contains(seconds) {
+                return Err(AppError::Validation(format!(
+                    "Interval for {} must be between 1 and {} seconds",
+                    job, MAX_JOB_INTERVAL_SECONDS
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    // Loads the stored settings and applies them if they changed since the last poll
+    pub async fn reload(&self) -> Result<()> {
+        let settings = self.source.load().await?;
+        if Self::validate(&settings).is_err() {
+            // Only reachable by editing the table by hand; keep what is running
+            return Err(AppError::Internal(anyhow::anyhow!("Stored runtime settings are invalid")));
+        }
+
+        let previous = self.current();
+        if *previous == settings {
+            return Ok(());
+        }
+
+        if previous.log_level != settings.log_level {
+            if let Some(log_filter) = &self.log_filter {
+                log_filter.set(settings.log_level.as_deref())?;
+            }
+        }
+        if previous.broker_rate_limits != settings.broker_rate_limits {
+            if let Some(throttle) = &self.throttle {
+                throttle.set_overrides(&settings.broker_rate_limits);
+            }
+        }
+        tracing::info!("Runtime settings applied: {:?}", settings);
+        *self.current.write().unwrap() = Arc::new(settings);
+        Ok(())
+    }
+}
+
+impl JobIntervals for RuntimeConfig {
+    fn interval(&self, job: &str) -> Option<Duration> {
+        self.current.read().unwrap().job_intervals.get(job).map(|seconds| Duration::from_secs(*seconds))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::RuntimeSettingsPatch;
+    use crate::services::scheduler::Scheduler;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct FakeSource {
+        settings: Mutex<RuntimeSettings>,
+    }
+
+    #[async_trait]
+    impl RuntimeSettingsSource for FakeSource {
+        async fn load(&self) -> Result<RuntimeSettings> {
+            Ok(self.settings.lock().unwrap().clone())
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_patched_job_interval_applies_within_the_poll_window() {
+        let source = Arc::new(FakeSource::default());
+        let runtime = Arc::new(RuntimeConfig::new(source.clone()));
+        let runs = Arc::new(AtomicUsize::new(0));
+        let mut scheduler = Scheduler::new().with_intervals(runtime.clone());
+
+        {
+            let runtime = runtime.clone();
+            scheduler.every("runtime_settings", Duration::from_secs(RUNTIME_SETTINGS_POLL_SECONDS), move || {
+                let runtime = runtime.clone();
+                async move { runtime.reload().await }
+            });
+        }
+        let counter = runs.clone();
+        scheduler.every("sweep", Duration::from_secs(3600), move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            async { Ok(()) }
+        });
+
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        let mut settings = RuntimeSettings::default();
+        settings.apply(RuntimeSettingsPatch {
+            job_intervals: Some([("sweep".to_string(), Some(20))].into()),
+            ..Default::default()
+        });
+        *source.settings.lock().unwrap() = settings;
+
+        // Picked up by the poll at 10s, so the hourly job runs again at 20s and every 20s after
+        tokio::time::sleep(Duration::from_secs(16)).await;
+        assert_eq!(runtime.interval("sweep"), Some(Duration::from_secs(20)));
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        tokio::time::sleep(Duration::from_secs(20)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+
+        // Dropping the override restores the hourly period
+        source.settings.lock().unwrap().apply(RuntimeSettingsPatch {
+            job_intervals: Some([("sweep".to_string(), None)].into()),
+            ..Default::default()
+        });
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+        scheduler.shutdown();
+    }
+
+    #[test]
+    fn test_validation_rejects_out_of_range_values() {
+        let mut settings = RuntimeSettings::default();
+        settings.job_intervals.insert("sweep".to_string(), 0);
+        assert!(matches!(RuntimeConfig::validate(&settings), Err(AppError::Validation(_))));
+
+        settings.job_intervals.insert("sweep".to_string(), 30);
+        settings.log_level = Some("trading_saas_backend=loud".to_string());
+        assert!(matches!(RuntimeConfig::validate(&settings), Err(AppError::Validation(_))));
+
+        settings.log_level = Some("trading_saas_backend=info".to_string());
+        assert!(RuntimeConfig::validate(&settings).is_ok());
+
+        // Unknown keys, including secrets, are not accepted in a patch
+        let patch = serde_json::from_value::<RuntimeSettingsPatch>(serde_json::json!({ "jwt_secret": "x" }));
+        assert!(patch.is_err());
+    }
+}
@@ -0,0 +1,151 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use crate::errors::Result;
+use crate::services::task_supervisor::{TaskClass, TaskSupervisor};
+
+const INTERVAL_RECHECK: Duration = Duration::from_secs(1);
+
+// Per-job periods that can change while the jobs run
+pub trait JobIntervals: Send + Sync {
+    fn interval(&self, job: &str) -> Option<Duration>;
+}
+
+// Runs background jobs on a fixed period. A failing run is logged and retried on the next tick;
+// a panicking one is restarted by the supervisor after a backoff.
+#[derive(Default)]
+pub struct Scheduler {
+    jobs: Vec<(&'static str, JoinHandle<()>)>,
+    supervisor: TaskSupervisor,
+    intervals: Option<Arc<dyn JobIntervals>>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_supervisor(mut self, supervisor: TaskSupervisor) -> Self {
+        self.supervisor = supervisor;
+        self
+    }
+
+    // Overrides are read after every run, so a changed period applies from the next one
+    pub fn with_intervals(mut self, intervals: Arc<dyn JobIntervals>) -> Self {
+        self.intervals = Some(intervals);
+        self
+    }
+
+    pub fn every<F, Fut>(&mut self, name: &'static str, period: Duration, job: F)
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let job = Arc::new(job);
+        let intervals = self.intervals.clone();
+        let handle = self.supervisor.spawn_restartable(format!("job:{}", name), TaskClass::SchedulerJob, move || {
+            let job = job.clone();
+            let intervals = intervals.clone();
+            async move {
+                // The first run is immediate; a run longer than the period delays the next one
+                loop {
+                    let started = Instant::now();
+                    if let Err(e) = job().await {
+                        tracing::error!("Scheduled job {} failed: {}", name, e);
+                    }
+                    match &intervals {
+                        Some(intervals) => loop {
+                            // Rechecked while waiting so shortening a long period does not wait it out
+                            let due = started + intervals.interval(name).unwrap_or(period);
+                            let now = Instant::now();
+                            if now >= due {
+                                break;
+                            }
+                            tokio::time::sleep_until(due.min(now + INTERVAL_RECHECK)).await;
+                        },
+                        None => tokio::time::sleep_until(started + period).await,
+                    }
+                }
+            }
+        });
+
+        tracing::info!("Scheduled job {} every {:?}", name, period);
+        self.jobs.push((name, handle));
+    }
+
+    pub fn job_names(&self) -> Vec<&'static str> {
+        self.jobs.iter().map(|(name, _)| *name).collect()
+    }
+
+    pub fn shutdown(&mut self) {
+        for (_, handle) in self.jobs.drain(..) {
+            handle.abort();
+        }
+    }
+}
+
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_job_runs_every_period_and_survives_errors() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let mut scheduler = Scheduler::new();
+
+        let counter = runs.clone();
+        scheduler.every("flaky", Duration::from_secs(60), move || {
+            let counter = counter.clone();
+            async move {
+                let run = counter.fetch_add(1, Ordering::SeqCst);
+                if run.is_multiple_of(2) {
+                    return Err(crate::errors::AppError::Internal(anyhow::anyhow!("boom")));
+                }
+                Ok(())
+            }
+        });
+
+        // First tick fires immediately, then once per period
+        tokio::time::sleep(Duration::from_secs(150)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+        assert_eq!(scheduler.job_names(), vec!["flaky"]);
+
+        scheduler.shutdown();
+        tokio::time::sleep(Duration::from_secs(600)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_panicking_job_is_restarted() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let mut scheduler = Scheduler::new();
+
+        let counter = runs.clone();
+        scheduler.every("panicky", Duration::from_secs(60), move || {
+            let counter = counter.clone();
+            async move {
+                if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                    panic!("deliberate");
+                }
+                Ok(())
+            }
+        });
+
+        // Restarted a second after the panic, running immediately and then every period again
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+    }
+}
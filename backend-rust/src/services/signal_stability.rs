@@ -0,0 +1,242 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use schemars::JsonSchema;
+use std::collections::VecDeque;
+use uuid::Uuid;
+
+use crate::{models::{RiskConfigWarnings, SignalStability}, services::composite_signal::SignalComponent};
+
+// Evaluations kept per robot for the signal history endpoint
+const HISTORY_LIMIT: usize = 50;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RobotSignal {
+    // buy, sell or hold
+    pub direction: String,
+    pub confidence: f64,
+    // What each strategy of a composite robot contributed; empty otherwise
+    pub components: Vec<SignalComponent>,
+}
+
+// The robot's open position the signal would reverse
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpenPosition {
+    pub trade_type: String,
+    pub opened_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+#[serde(tag = "decision", rename_all = "snake_case")]
+pub enum SignalDecision {
+    Hold,
+    // Seen `seen` of the `required` evaluations in a row
+    Pending { seen: u32, required: u32 },
+    Suppressed { reason: String },
+    Execute,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, JsonSchema)]
+pub struct ConfirmationState {
+    // Direction being confirmed, if any
+    pub direction: Option<String>,
+    pub seen: u32,
+    pub required: u32,
+    pub suppressed_flips: u64,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct SignalHistoryEntry {
+    pub evaluated_at: DateTime<Utc>,
+    pub direction: String,
+    pub confidence: f64,
+    #[serde(flatten)]
+    pub decision: SignalDecision,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub components: Vec<SignalComponent>,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct RobotSignalHistory {
+    pub robot_id: Uuid,
+    pub running: bool,
+    // Seconds between evaluations: the robot's evaluation_interval_seconds raised to its plan's minimum
+    pub effective_interval: u64,
+    // Stored risk settings the runner ignores or fills with defaults
+    pub config_warnings: RiskConfigWarnings,
+    pub confirmation: ConfirmationState,
+    // Newest first
+    pub history: Vec<SignalHistoryEntry>,
+}
+
+// Per-robot confirmation counter and recent decisions
+#[derive(Debug, Default)]
+pub struct SignalFilter {
+    state: ConfirmationState,
+    history: VecDeque<SignalHistoryEntry>,
+}
+
+impl SignalFilter {
+    pub fn state(&self) -> ConfirmationState {
+        self.state.clone()
+    }
+
+    pub fn history(&self) -> Vec<SignalHistoryEntry> {
+        self.history.iter().rev().cloned().collect()
+    }
+
+    // Decides what to do with one evaluation. A direction only executes once it has been seen
+    // `confirmation_count` times in a row, and never reverses a position younger than the
+    // minimum holding period unless its confidence reaches the override threshold.
+    pub fn observe(
+        &mut self,
+        robot_id: Uuid,
+        signal: &RobotSignal,
+        stability: &SignalStability,
+        position: Option<&OpenPosition>,
+        now: DateTime<Utc>,
+    ) -> SignalDecision {
+        let decision = self.decide(robot_id, signal, stability, position, now);
+
+        self.history.push_back(SignalHistoryEntry {
+            evaluated_at: now,
+            direction: signal.direction.clone(),
+            confidence: signal.confidence,
+            decision: decision.clone(),
+            components: signal.components.clone(),
+        });
+        if self.history.len() > HISTORY_LIMIT {
+            self.history.pop_front();
+        }
+        decision
+    }
+
+    fn decide(
+        &mut self,
+        robot_id: Uuid,
+        signal: &RobotSignal,
+        stability: &SignalStability,
+        position: Option<&OpenPosition>,
+        now: DateTime<Utc>,
+    ) -> SignalDecision {
+        let direction = signal.direction.to_ascii_lowercase();
+        self.state.required = stability.confirmation_count;
+
+        if direction != "buy" && direction != "sell" {
+            self.state.direction = None;
+            self.state.seen = 0;
+            return SignalDecision::Hold;
+        }
+
+        if self.state.direction.as_deref() == Some(direction.as_str()) {
+            self.state.seen += 1;
+        } else {
+            if let Some(previous) = self.state.direction.as_deref().filter(|_| self.state.seen > 0) {
+                self.state.suppressed_flips += 1;
+                tracing::info!(
+                    "Robot {} dropped unconfirmed {} after {}/{} evaluation(s) on a {} signal ({} flip(s) suppressed)",
+                    robot_id, previous, self.state.seen, self.state.required, direction, self.state.suppressed_flips
+                );
+            }
+            self.state.direction = Some(direction.clone());
+            self.state.seen = 1;
+        }
+
+        if self.state.seen < self.state.required {
+            return SignalDecision::Pending { seen: self.state.seen, required: self.state.required };
+        }
+
+        let reverses = position.filter(|p| !p.trade_type.eq_ignore_ascii_case(&direction));
+        if let Some(position) = reverses {
+            let held = now - position.opened_at;
+            if held < stability.min_holding() && signal.confidence < stability.reversal_override_confidence {
+                self.state.suppressed_flips += 1;
+                tracing::info!(
+                    "Robot {} suppressed {} reversal of a {} held {}s (confidence {:.2} < {:.2}, {} flip(s) suppressed)",
+                    robot_id, direction, position.trade_type, held.num_seconds(), signal.confidence,
+                    stability.reversal_override_confidence, self.state.suppressed_flips
+                );
+                return SignalDecision::Suppressed {
+                    reason: format!(
+                        "Position opened {}s ago is inside the {} minute holding period",
+                        held.num_seconds(),
+                        stability.min_holding_minutes
+                    ),
+                };
+            }
+        }
+
+        // The next order needs a fresh run of confirmations
+        self.state.seen = 0;
+        SignalDecision::Execute
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn signal(direction: &str, confidence: f64) -> RobotSignal {
+        RobotSignal { direction: direction.to_string(), confidence, components: Vec::new() }
+    }
+
+    fn confirming(count: u32) -> SignalStability {
+        SignalStability { confirmation_count: count, ..SignalStability::default() }
+    }
+
+    #[test]
+    fn test_pending_confirmation_is_exposed() {
+        let mut filter = SignalFilter::default();
+        let now = Utc::now();
+
+        let decision = filter.observe(Uuid::new_v4(), &signal("BUY", 0.7), &confirming(3), None, now);
+        assert_eq!(decision, SignalDecision::Pending { seen: 1, required: 3 });
+        assert_eq!(filter.state().direction.as_deref(), Some("buy"));
+
+        // A hold breaks the run without counting as a flip
+        assert_eq!(filter.observe(Uuid::new_v4(), &signal("HOLD", 0.5), &confirming(3), None, now), SignalDecision::Hold);
+        assert_eq!((filter.state().seen, filter.state().suppressed_flips), (0, 0));
+
+        let history = filter.history();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].decision, SignalDecision::Hold);
+    }
+
+    #[test]
+    fn test_reversal_inside_the_holding_period_needs_the_override_confidence() {
+        let stability = SignalStability { min_holding_minutes: 30, reversal_override_confidence: 0.9, ..confirming(1) };
+        let now = Utc::now();
+        let position = OpenPosition { trade_type: "buy".to_string(), opened_at: now - Duration::minutes(10) };
+        let mut filter = SignalFilter::default();
+
+        let decision = filter.observe(Uuid::new_v4(), &signal("SELL", 0.8), &stability, Some(&position), now);
+        assert!(matches!(decision, SignalDecision::Suppressed { reason } if reason.contains("30 minute")));
+        assert_eq!(filter.state().suppressed_flips, 1);
+
+        let decision = filter.observe(Uuid::new_v4(), &signal("SELL", 0.95), &stability, Some(&position), now);
+        assert_eq!(decision, SignalDecision::Execute);
+
+        let later = now + Duration::minutes(25);
+        assert_eq!(filter.observe(Uuid::new_v4(), &signal("SELL", 0.6), &stability, Some(&position), later), SignalDecision::Execute);
+    }
+
+    #[test]
+    fn test_stability_is_read_from_the_risk_config() {
+        let parse = |value: serde_json::Value| SignalStability::from_risk_config(&value);
+        assert_eq!(parse(serde_json::json!({})), Ok(SignalStability::default()));
+        assert_eq!(parse(serde_json::json!({"signal_confirmation_count": 3})).unwrap().confirmation_count, 3);
+        assert_eq!(parse(serde_json::json!({"min_holding_minutes": 15})).unwrap().min_holding(), Duration::minutes(15));
+        assert!(parse(serde_json::json!({"signal_confirmation_count": 0})).is_err());
+        assert!(parse(serde_json::json!({"min_holding_minutes": -5})).is_err());
+        assert!(parse(serde_json::json!({"reversal_override_confidence": 1.5})).is_err());
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let mut filter = SignalFilter::default();
+        for _ in 0..(HISTORY_LIMIT + 10) {
+            filter.observe(Uuid::new_v4(), &signal("HOLD", 0.5), &confirming(1), None, Utc::now());
+        }
+        assert_eq!(filter.history().len(), HISTORY_LIMIT);
+    }
+}
@@ -0,0 +1,833 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use sqlx::PgPool;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{
+    errors::{AppError, Result},
+    models::{
+        BookedTrade, MonthlyStatement, Statement, StatementAccount, StatementAdjustment, StatementBalance, StatementConnection,
+        StatementRecipient, StatementTotals, StatementTrade, StatementTradeLine, StoredStatement, SymbolTotals,
+    },
+    money,
+    services::{message_templates::render, NotificationService},
+};
+
+pub const STATEMENT_CHECK_SECONDS: u64 = 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StatementPeriod {
+    pub year: i32,
+    pub month: u32,
+}
+
+impl StatementPeriod {
+    pub fn new(year: i32, month: u32) -> Result<Self> {
+        if !(2000..=9999).contains(&year) || !(1..=12).contains(&month) {
+            return Err(AppError::Validation(format!("No statement period {}-{:02}", year, month)));
+        }
+        Ok(StatementPeriod { year, month })
+    }
+
+    pub fn containing(at: DateTime<Utc>) -> Self {
+        StatementPeriod { year: at.year(), month: at.month() }
+    }
+
+    pub fn from_first_day(day: NaiveDate) -> Self {
+        StatementPeriod { year: day.year(), month: day.month() }
+    }
+
+    pub fn previous(&self) -> Self {
+        match self.month {
+            1 => StatementPeriod { year: self.year - 1, month: 12 },
+            month => StatementPeriod { year: self.year, month: month - 1 },
+        }
+    }
+
+    pub fn next(&self) -> Self {
+        match self.month {
+            12 => StatementPeriod { year: self.year + 1, month: 1 },
+            month => StatementPeriod { year: self.year, month: month + 1 },
+        }
+    }
+
+    pub fn first_day(&self) -> NaiveDate {
+        NaiveDate::from_ymd_opt(self.year, self.month, 1).expect("a validated month has a first day")
+    }
+
+    pub fn start(&self) -> DateTime<Utc> {
+        self.first_day().and_hms_opt(0, 0, 0).expect("midnight is a valid time").and_utc()
+    }
+
+    // Exclusive
+    pub fn end(&self) -> DateTime<Utc> {
+        self.next().start()
+    }
+
+    pub fn label(&self) -> String {
+        self.first_day().format("%B %Y").to_string()
+    }
+}
+
+// Everything a statement is assembled from, as it is at generation time
+#[derive(Debug, Clone, Default)]
+pub struct StatementInputs {
+    // Live closed trades from the earlier of the period and the first stored statement, up to the period's end
+    pub trades: Vec<StatementTrade>,
+    pub booked: Vec<BookedTrade>,
+    pub connections: Vec<StatementConnection>,
+    // Last snapshot before the period, first one inside it, and last one before its end
+    pub opening: Vec<StatementBalance>,
+    pub first_in_period: Vec<StatementBalance>,
+    pub ending: Vec<StatementBalance>,
+}
+
+#[async_trait]
+pub trait StatementEnv: Send + Sync {
+    async fn find(&self, user_id: Uuid, period: StatementPeriod) -> Result<Option<StoredStatement>>;
+    async fn inputs(&self, user_id: Uuid, period: StatementPeriod) -> Result<StatementInputs>;
+    // Returns the statement already stored for the period when there is one
+    async fn insert(&self, user_id: Uuid, statement: &Statement, html: &str, entries: &[(Uuid, f64)]) -> Result<StoredStatement>;
+    async fn due_recipients(&self, period: StatementPeriod) -> Result<Vec<StatementRecipient>>;
+    // Records the statement as sent; false if it already was
+    async fn claim_notification(&self, statement_id: Uuid) -> Result<bool>;
+    async fn release_notification(&self, statement_id: Uuid) -> Result<()>;
+    async fn send(&self, recipient: &StatementRecipient, period: StatementPeriod) -> Result<()>;
+}
+
+pub struct PgStatementEnv {
+    pool: PgPool,
+    notifications: Arc<NotificationService>,
+}
+
+impl PgStatementEnv {
+    pub fn new(pool: PgPool, notifications: Arc<NotificationService>) -> Self {
+        PgStatementEnv { pool, notifications }
+    }
+}
+
+#[async_trait]
+impl StatementEnv for PgStatementEnv {
+    async fn find(&self, user_id: Uuid, period: StatementPeriod) -> Result<Option<StoredStatement>> {
+        MonthlyStatement::find(&self.pool, user_id, period.first_day()).await
+    }
+
+    async fn inputs(&self, user_id: Uuid, period: StatementPeriod) -> Result<StatementInputs> {
+        let first = MonthlyStatement::first_period(&self.pool, user_id).await?.map(StatementPeriod::from_first_day);
+        let since = first.map_or(period, |first| first.min(period)).start();
+        let (start, end) = (period.start(), period.end());
+
+        Ok(StatementInputs {
+            trades: MonthlyStatement::closed_trades(&self.pool, user_id, since, end).await?,
+            booked: MonthlyStatement::booked_trades(&self.pool, user_id).await?,
+            connections: MonthlyStatement::connections(&self.pool, user_id).await?,
+            opening: MonthlyStatement::balances_before(&self.pool, user_id, start).await?,
+            first_in_period: MonthlyStatement::first_balances(&self.pool, user_id, start, end).await?,
+            ending: MonthlyStatement::balances_before(&self.pool, user_id, end).await?,
+        })
+    }
+
+    async fn insert(&self, user_id: Uuid, statement: &Statement, html: &str, entries: &[(Uuid, f64)]) -> Result<StoredStatement> {
+        let content = serde_json::to_value(statement).map_err(|e| AppError::Internal(e.into()))?;
+        let period = StatementPeriod { year: statement.year, month: statement.month };
+        MonthlyStatement::insert(&self.pool, user_id, period.first_day(), &content, html, statement.generated_at, entries).await
+    }
+
+    async fn due_recipients(&self, period: StatementPeriod) -> Result<Vec<StatementRecipient>> {
+        MonthlyStatement::due_recipients(&self.pool, period.first_day(), period.start(), period.end()).await
+    }
+
+    async fn claim_notification(&self, statement_id: Uuid) -> Result<bool> {
+        MonthlyStatement::claim_notification(&self.pool, statement_id).await
+    }
+
+    async fn release_notification(&self, statement_id: Uuid) -> Result<()> {
+        MonthlyStatement::release_notification(&self.pool, statement_id).await
+    }
+
+    async fn send(&self, recipient: &StatementRecipient, period: StatementPeriod) -> Result<()> {
+        let path = format!("/api/v1/statements/{}/{}", period.year, period.month);
+        self.notifications.send_statement_ready(&recipient.email, &period.label(), &path).await
+    }
+}
+
+const STATEMENT_DOCUMENT: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="utf-8">
+    <title>Statement {{period}}</title>
+    <style>
+        body { font-family: Helvetica, Arial, sans-serif; font-size: 12px; color: #222; margin: 24px; }
+        h1 { font-size: 20px; margin-bottom: 4px; }
+        h2 { font-size: 15px; margin-top: 28px; border-bottom: 1px solid #999; }
+        h3 { font-size: 13px; margin-top: 16px; }
+        table { border-collapse: collapse; width: 100%; margin-top: 6px; }
+        th, td { padding: 3px 6px; border-bottom: 1px solid #ddd; text-align: left; }
+        td.num, th.num { text-align: right; font-variant-numeric: tabular-nums; }
+        tr.total td { font-weight: bold; border-top: 1px solid #444; }
+        .meta { color: #666; }
+        .provisional { color: #a60; font-weight: bold; }
+        @media print {
+            body { margin: 0; }
+            section { page-break-inside: avoid; }
+        }
+    </style>
+</head>
+<body>
+    <h1>Monthly statement &mdash; {{period}}</h1>
+    <p class="meta">{{period_start}} to {{period_end}} (UTC). Generated {{generated_at}}.</p>
+    {{status}}
+    {{accounts}}
+</body>
+</html>"#;
+
+pub struct StatementService;
+
+impl StatementService {
+    // Whether a trade still counts toward the period ending at `end`
+    fn counts(trade: &StatementTrade, end: DateTime<Utc>) -> bool {
+        trade.status == "closed" && !trade.is_demo && trade.closed_at.is_some_and(|closed_at| closed_at < end)
+    }
+
+    // Builds the statement and what it books per trade. Trades closed in the period that no
+    // statement covered yet are listed; a covered trade whose result changed since, or one closed in
+    // an earlier covered month that was not on its statement, is booked as an adjustment.
+    pub fn assemble(
+        period: StatementPeriod,
+        inputs: &StatementInputs,
+        generated_at: DateTime<Utc>,
+        is_final: bool,
+    ) -> (Statement, Vec<(Uuid, f64)>) {
+        let (start, end) = (period.start(), period.end());
+        let booked: HashMap<Uuid, &BookedTrade> = inputs.booked.iter().map(|b| (b.trade.id, b)).collect();
+        let mut lines: HashMap<Option<Uuid>, Vec<StatementTradeLine>> = HashMap::new();
+        let mut adjustments: HashMap<Option<Uuid>, Vec<StatementAdjustment>> = HashMap::new();
+        let mut entries = Vec::new();
+
+        for trade in &inputs.trades {
+            if booked.contains_key(&trade.id) || !Self::counts(trade, end) {
+                continue;
+            }
+            let closed_at = trade.closed_at.expect("counted trades are closed");
+            if closed_at >= start {
+                lines.entry(trade.connection_id).or_default().push(Self::line(trade, closed_at));
+            } else {
+                adjustments.entry(trade.connection_id).or_default().push(StatementAdjustment {
+                    trade_id: trade.id,
+                    symbol: trade.symbol.clone(),
+                    closed_at: trade.closed_at,
+                    previously_booked: 0.0,
+                    amount: trade.net(),
+                });
+            }
+            entries.push((trade.id, trade.net()));
+        }
+
+        for booked in &inputs.booked {
+            let trade = &booked.trade;
+            let current = if Self::counts(trade, end) { trade.net() } else { 0.0 };
+            let amount = current - booked.booked;
+            if money::round_amount(amount) == 0.0 {
+                continue;
+            }
+            adjustments.entry(trade.connection_id).or_default().push(StatementAdjustment {
+                trade_id: trade.id,
+                symbol: trade.symbol.clone(),
+                closed_at: trade.closed_at,
+                previously_booked: booked.booked,
+                amount,
+            });
+            entries.push((trade.id, amount));
+        }
+
+        let balance = |balances: &[StatementBalance], id: Uuid| balances.iter().find(|b| b.connection_id == id).cloned();
+        let mut keys: Vec<Option<Uuid>> = inputs
+            .connections
+            .iter()
+            .filter(|c| c.created_at < end)
+            .map(|c| Some(c.id))
+            .collect();
+        let mut extra: Vec<Option<Uuid>> = lines
+            .keys()
+            .chain(adjustments.keys())
+            .filter(|key| !keys.contains(key))
+            .copied()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        // Connections that are gone sort by id, with trades that had no connection last
+        extra.sort_by_key(|key| (key.is_none(), *key));
+        keys.extend(extra);
+
+        let mut accounts = Vec::new();
+        for key in keys {
+            let trades = lines.remove(&key).unwrap_or_default();
+            let account_adjustments = adjustments.remove(&key).unwrap_or_default();
+            let (opening, ending) = match key {
+                Some(id) => (
+                    balance(&inputs.opening, id).or_else(|| balance(&inputs.first_in_period, id)),
+                    balance(&inputs.ending, id),
+                ),
+                None => (None, None),
+            };
+            if trades.is_empty() && account_adjustments.is_empty() && opening.is_none() && ending.is_none() {
+                continue;
+            }
+
+            let symbols = Self::symbol_totals(&trades);
+            let totals = StatementTotals {
+                trades: trades.len() as i64,
+                profit_loss: trades.iter().map(|t| t.profit_loss).sum(),
+                commission: trades.iter().map(|t| t.commission).sum(),
+                swap: trades.iter().map(|t| t.swap).sum(),
+                net: trades.iter().map(|t| t.net).sum(),
+                adjustments: account_adjustments.iter().map(|a| a.amount).sum(),
+            };
+            accounts.push(StatementAccount {
+                connection_id: key,
+                connection_name: key.and_then(|id| inputs.connections.iter().find(|c| c.id == id).map(|c| c.name.clone())),
+                currency: ending.as_ref().or(opening.as_ref()).map(|b| b.currency.clone()),
+                opening_balance: opening.map(|b| b.balance),
+                ending_balance: ending.map(|b| b.balance),
+                trades,
+                symbols,
+                adjustments: account_adjustments,
+                totals,
+            });
+        }
+
+        let statement = Statement {
+            year: period.year,
+            month: period.month,
+            period_start: start,
+            period_end: end,
+            is_final,
+            generated_at,
+            accounts,
+        };
+        (statement, entries)
+    }
+
+    fn line(trade: &StatementTrade, closed_at: DateTime<Utc>) -> StatementTradeLine {
+        StatementTradeLine {
+            trade_id: trade.id,
+            symbol: trade.symbol.clone(),
+            trade_type: trade.trade_type.clone(),
+            volume: trade.volume,
+            entry_price: trade.entry_price,
+            exit_price: trade.exit_price,
+            opened_at: trade.opened_at,
+            closed_at,
+            profit_loss: trade.profit_loss.unwrap_or(0.0),
+            commission: trade.commission.unwrap_or(0.0),
+            swap: trade.swap.unwrap_or(0.0),
+            net: trade.net(),
+        }
+    }
+
+    fn symbol_totals(trades: &[StatementTradeLine]) -> Vec<SymbolTotals> {
+        let mut by_symbol: BTreeMap<&str, SymbolTotals> = BTreeMap::new();
+        for trade in trades {
+            let totals = by_symbol
+                .entry(&trade.symbol)
+                .or_insert_with(|| SymbolTotals { symbol: trade.symbol.clone(), ..Default::default() });
+            totals.trades += 1;
+            totals.profit_loss += trade.profit_loss;
+            totals.commission += trade.commission;
+            totals.swap += trade.swap;
+            totals.net += trade.net;
+        }
+        by_symbol.into_values().collect()
+    }
+
+    // The printable document
+    pub fn render_html(statement: &Statement) -> Result<String> {
+        let period = StatementPeriod { year: statement.year, month: statement.month };
+        let status = if statement.is_final {
+            String::new()
+        } else {
+            r#"<p class="provisional">Provisional: this month is still in progress and its figures may change.</p>"#.to_string()
+        };
+        let accounts = if statement.accounts.is_empty() {
+            "<p>No trading activity in this period.</p>".to_string()
+        } else {
+            statement.accounts.iter().map(Self::render_account).collect::<Vec<_>>().join("\n")
+        };
+
+        render(
+            STATEMENT_DOCUMENT,
+            &[
+                ("period", &period.label()),
+                ("period_start", &statement.period_start.format("%Y-%m-%d").to_string()),
+                ("period_end", &(statement.period_end - chrono::Duration::days(1)).format("%Y-%m-%d").to_string()),
+                ("generated_at", &statement.generated_at.format("%Y-%m-%d %H:%M UTC").to_string()),
+                ("status", &status),
+                ("accounts", &accounts),
+            ],
+        )
+    }
+
+    fn render_account(account: &StatementAccount) -> String {
+        let amount = |value: f64| format!("{:.2}", money::round_amount(value));
+        let optional = |value: Option<f64>| value.map(amount).unwrap_or_else(|| "&ndash;".to_string());
+        let name = match (&account.connection_name, account.connection_id) {
+            (Some(name), _) => escape(name),
+            (None, Some(id)) => format!("Connection {}", id),
+            (None, None) => "Trades without a broker connection".to_string(),
+        };
+        let currency = account.currency.as_deref().map(escape).unwrap_or_default();
+
+        let mut html = format!(
+            r#"<section>
+    <h2>{} <span class="meta">{}</span></h2>
+    <table>
+        <tr><td>Opening balance</td><td class="num">{}</td></tr>
+        <tr><td>Closed trades</td><td class="num">{}</td></tr>
+        <tr><td>Profit/loss</td><td class="num">{}</td></tr>
+        <tr><td>Commission</td><td class="num">{}</td></tr>
+        <tr><td>Swap</td><td class="num">{}</td></tr>
+        <tr><td>Net profit/loss</td><td class="num">{}</td></tr>
+        <tr><td>Adjustments to earlier statements</td><td class="num">{}</td></tr>
+        <tr class="total"><td>Ending balance</td><td class="num">{}</td></tr>
+    </table>"#,
+            name,
+            currency,
+            optional(account.opening_balance),
+            account.totals.trades,
+            amount(account.totals.profit_loss),
+            amount(account.totals.commission),
+            amount(account.totals.swap),
+            amount(account.totals.net),
+            amount(account.totals.adjustments),
+            optional(account.ending_balance),
+        );
+
+        if !account.symbols.is_empty() {
+            html.push_str(
+                r#"
+    <h3>Totals per symbol</h3>
+    <table>
+        <tr><th>Symbol</th><th class="num">Trades</th><th class="num">Profit/loss</th><th class="num">Commission</th><th class="num">Swap</th><th class="num">Net</th></tr>"#,
+            );
+            for symbol in &account.symbols {
+                html.push_str(&format!(
+                    r#"
+        <tr><td>{}</td><td class="num">{}</td><td class="num">{}</td><td class="num">{}</td><td class="num">{}</td><td class="num">{}</td></tr>"#,
+                    escape(&symbol.symbol),
+                    symbol.trades,
+                    amount(symbol.profit_loss),
+                    amount(symbol.commission),
+                    amount(symbol.swap),
+                    amount(symbol.net),
+                ));
+            }
+            html.push_str(&format!(
+                r#"
+        <tr class="total"><td>Total</td><td class="num">{}</td><td class="num">{}</td><td class="num">{}</td><td class="num">{}</td><td class="num">{}</td></tr>
+    </table>"#,
+                account.totals.trades,
+                amount(account.totals.profit_loss),
+                amount(account.totals.commission),
+                amount(account.totals.swap),
+                amount(account.totals.net),
+            ));
+        }
+
+        if !account.trades.is_empty() {
+            html.push_str(
+                r#"
+    <h3>Closed trades</h3>
+    <table>
+        <tr><th>Closed</th><th>Symbol</th><th>Side</th><th class="num">Volume</th><th class="num">Entry</th><th class="num">Exit</th><th class="num">Profit/loss</th><th class="num">Commission</th><th class="num">Swap</th><th class="num">Net</th></tr>"#,
+            );
+            for trade in &account.trades {
+                html.push_str(&format!(
+                    r#"
+        <tr><td>{}</td><td>{}</td><td>{}</td><td class="num">{}</td><td class="num">{}</td><td class="num">{}</td><td class="num">{}</td><td class="num">{}</td><td class="num">{}</td><td class="num">{}</td></tr>"#,
+                    trade.closed_at.format("%Y-%m-%d %H:%M"),
+                    escape(&trade.symbol),
+                    escape(&trade.trade_type),
+                    trade.volume,
+                    money::format_price(trade.entry_price),
+                    trade.exit_price.map(money::format_price).unwrap_or_else(|| "&ndash;".to_string()),
+                    amount(trade.profit_loss),
+                    amount(trade.commission),
+                    amount(trade.swap),
+                    amount(trade.net),
+                ));
+            }
+            html.push_str("\n    </table>");
+        }
+
+        if !account.adjustments.is_empty() {
+            html.push_str(
+                r#"
+    <h3>Adjustments to earlier statements</h3>
+    <table>
+        <tr><th>Trade</th><th>Symbol</th><th>Closed</th><th class="num">Previously booked</th><th class="num">Adjustment</th></tr>"#,
+            );
+            for adjustment in &account.adjustments {
+                html.push_str(&format!(
+                    r#"
+        <tr><td>{}</td><td>{}</td><td>{}</td><td class="num">{}</td><td class="num">{}</td></tr>"#,
+                    adjustment.trade_id,
+                    escape(&adjustment.symbol),
+                    adjustment
+                        .closed_at
+                        .map(|closed_at| closed_at.format("%Y-%m-%d %H:%M").to_string())
+                        .unwrap_or_else(|| "&ndash;".to_string()),
+                    amount(adjustment.previously_booked),
+                    amount(adjustment.amount),
+                ));
+            }
+            html.push_str("\n    </table>");
+        }
+
+        html.push_str("\n</section>");
+        html
+    }
+
+    // The stored statement for the period, generating and storing it first when there is none.
+    // Once stored it never changes.
+    pub async fn issue(env: &dyn StatementEnv, user_id: Uuid, period: StatementPeriod, now: DateTime<Utc>) -> Result<StoredStatement> {
+        if let Some(stored) = env.find(user_id, period).await? {
+            return Ok(stored);
+        }
+
+        let inputs = env.inputs(user_id, period).await?;
+        let (statement, entries) = Self::assemble(period, &inputs, now, true);
+        let html = Self::render_html(&statement)?;
+        env.insert(user_id, &statement, &html, &entries).await
+    }
+
+    // A finished month is served from its stored copy; the month in progress is assembled afresh
+    pub async fn get(env: &dyn StatementEnv, user_id: Uuid, period: StatementPeriod, now: DateTime<Utc>) -> Result<(Statement, String)> {
+        if period.start() > now {
+            return Err(AppError::Validation(format!("No statement for {} yet", period.label())));
+        }
+        if now < period.end() {
+            let inputs = env.inputs(user_id, period).await?;
+            let (statement, _) = Self::assemble(period, &inputs, now, false);
+            let html = Self::render_html(&statement)?;
+            return Ok((statement, html));
+        }
+
+        let stored = Self::issue(env, user_id, period, now).await?;
+        let statement = serde_json::from_value(stored.content)
+            .map_err(|e| AppError::Internal(anyhow::anyhow!("Unreadable statement {}: {}", stored.id, e)))?;
+        Ok((statement, stored.html))
+    }
+
+    // Scheduler job: issues last month's statements and emails each user once. Runs hourly, so the
+    // statements go out within an hour of the month turning; returns how many were sent.
+    pub async fn generate_due(env: &dyn StatementEnv, now: DateTime<Utc>) -> Result<usize> {
+        let period = StatementPeriod::containing(now).previous();
+        let mut sent = 0;
+
+        for recipient in env.due_recipients(period).await? {
+            let stored = match Self::issue(env, recipient.user_id, period, now).await {
+                Ok(stored) => stored,
+                Err(e) => {
+                    tracing::warn!("Statement {} for user {} failed: {}", period.label(), recipient.user_id, e);
+                    continue;
+                }
+            };
+            if stored.notified_at.is_some() || !env.claim_notification(stored.id).await? {
+                continue;
+            }
+
+            match env.send(&recipient, period).await {
+                Ok(()) => sent += 1,
+                Err(e) => {
+                    tracing::warn!("Statement email to user {} failed: {}", recipient.user_id, e);
+                    env.release_notification(stored.id).await?;
+                }
+            }
+        }
+
+        Ok(sent)
+    }
+}
+
+fn escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::sync::Mutex;
+
+    // A stored statement with the (trade, amount) entries booked on it
+    type StoredWithEntries = (StoredStatement, Vec<(Uuid, f64)>);
+
+    #[derive(Default)]
+    struct FakeEnv {
+        trades: Mutex<Vec<StatementTrade>>,
+        connections: Vec<StatementConnection>,
+        balances: Vec<(Uuid, DateTime<Utc>, f64)>,
+        stored: Mutex<Vec<StoredWithEntries>>,
+        sent: Mutex<Vec<(String, StatementPeriod)>>,
+    }
+
+    impl FakeEnv {
+        fn balances(&self, matching: impl Fn(DateTime<Utc>) -> bool, latest: bool) -> Vec<StatementBalance> {
+            let mut picked: HashMap<Uuid, (DateTime<Utc>, f64)> = HashMap::new();
+            for (id, at, balance) in self.balances.iter().filter(|(_, at, _)| matching(*at)) {
+                let replace = picked.get(id).is_none_or(|(seen, _)| if latest { at > seen } else { at < seen });
+                if replace {
+                    picked.insert(*id, (*at, *balance));
+                }
+            }
+            picked
+                .into_iter()
+                .map(|(connection_id, (_, balance))| StatementBalance { connection_id, balance, currency: "USD".to_string() })
+                .collect()
+        }
+    }
+
+    #[async_trait]
+    impl StatementEnv for FakeEnv {
+        async fn find(&self, user_id: Uuid, period: StatementPeriod) -> Result<Option<StoredStatement>> {
+            Ok(self
+                .stored
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|(s, _)| s.user_id == user_id && s.period_start == period.first_day())
+                .map(|(s, _)| s.clone()))
+        }
+
+        async fn inputs(&self, user_id: Uuid, period: StatementPeriod) -> Result<StatementInputs> {
+            let stored = self.stored.lock().unwrap();
+            let first = stored.iter().map(|(s, _)| StatementPeriod::from_first_day(s.period_start)).min();
+            let since = first.map_or(period, |first| first.min(period)).start();
+            let (start, end) = (period.start(), period.end());
+
+            let trades = self.trades.lock().unwrap();
+            let mut booked: HashMap<Uuid, f64> = HashMap::new();
+            for (_, entries) in stored.iter().filter(|(s, _)| s.user_id == user_id) {
+                for (trade_id, amount) in entries {
+                    *booked.entry(*trade_id).or_default() += amount;
+                }
+            }
+
+            Ok(StatementInputs {
+                trades: trades
+                    .iter()
+                    .filter(|t| t.status == "closed" && !t.is_demo && t.closed_at.is_some_and(|c| c >= since && c < end))
+                    .cloned()
+                    .collect(),
+                booked: trades
+                    .iter()
+                    .filter_map(|t| booked.get(&t.id).map(|b| BookedTrade { trade: t.clone(), booked: *b }))
+                    .collect(),
+                connections: self.connections.clone(),
+                opening: self.balances(|at| at < start, true),
+                first_in_period: self.balances(|at| at >= start && at < end, false),
+                ending: self.balances(|at| at < end, true),
+            })
+        }
+
+        async fn insert(&self, user_id: Uuid, statement: &Statement, html: &str, entries: &[(Uuid, f64)]) -> Result<StoredStatement> {
+            let stored = StoredStatement {
+                id: Uuid::new_v4(),
+                user_id,
+                period_start: StatementPeriod { year: statement.year, month: statement.month }.first_day(),
+                content: serde_json::to_value(statement).unwrap(),
+                html: html.to_string(),
+                generated_at: statement.generated_at,
+                notified_at: None,
+            };
+            self.stored.lock().unwrap().push((stored.clone(), entries.to_vec()));
+            Ok(stored)
+        }
+
+        async fn due_recipients(&self, _period: StatementPeriod) -> Result<Vec<StatementRecipient>> {
+            Ok(vec![StatementRecipient { user_id: user(), email: "trader@example.com".to_string() }])
+        }
+
+        async fn claim_notification(&self, statement_id: Uuid) -> Result<bool> {
+            let mut stored = self.stored.lock().unwrap();
+            let statement = &mut stored.iter_mut().find(|(s, _)| s.id == statement_id).unwrap().0;
+            if statement.notified_at.is_some() {
+                return Ok(false);
+            }
+            statement.notified_at = Some(Utc::now());
+            Ok(true)
+        }
+
+        async fn release_notification(&self, statement_id: Uuid) -> Result<()> {
+            let mut stored = self.stored.lock().unwrap();
+            stored.iter_mut().find(|(s, _)| s.id == statement_id).unwrap().0.notified_at = None;
+            Ok(())
+        }
+
+        async fn send(&self, recipient: &StatementRecipient, period: StatementPeriod) -> Result<()> {
+            self.sent.lock().unwrap().push((recipient.email.clone(), period));
+            Ok(())
+        }
+    }
+
+    fn user() -> Uuid {
+        Uuid::from_u128(1)
+    }
+
+    fn connection() -> Uuid {
+        Uuid::from_u128(2)
+    }
+
+    fn at(month: u32, day: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, month, day, 12, 0, 0).unwrap()
+    }
+
+    fn trade(id: u128, symbol: &str, closed_at: DateTime<Utc>, profit: f64, commission: f64, swap: f64) -> StatementTrade {
+        StatementTrade {
+            id: Uuid::from_u128(id),
+            connection_id: Some(connection()),
+            symbol: symbol.to_string(),
+            trade_type: "buy".to_string(),
+            volume: 0.1,
+            entry_price: 1.1,
+            exit_price: Some(1.11),
+            profit_loss: Some(profit),
+            commission: Some(commission),
+            swap: Some(swap),
+            status: "closed".to_string(),
+            is_demo: false,
+            opened_at: closed_at - chrono::Duration::hours(2),
+            closed_at: Some(closed_at),
+        }
+    }
+
+    // March 2024 on one account: three EURUSD and one XAUUSD trade, plus a demo trade and one closed in April
+    fn seeded_env() -> FakeEnv {
+        let mut demo = trade(5, "EURUSD", at(3, 20), 500.0, 0.0, 0.0);
+        demo.is_demo = true;
+        FakeEnv {
+            trades: Mutex::new(vec![
+                trade(1, "EURUSD", at(3, 4), 120.0, -1.4, -0.3),
+                trade(2, "EURUSD", at(3, 11), -45.5, -1.4, 0.0),
+                trade(3, "XAUUSD", at(3, 15), 310.25, -3.5, -2.1),
+                trade(4, "EURUSD", at(3, 29), 12.0, -1.4, -0.6),
+                demo,
+                trade(6, "XAUUSD", at(4, 2), 80.0, -3.5, 0.0),
+            ]),
+            connections: vec![StatementConnection {
+                id: connection(),
+                name: "Main account".to_string(),
+                created_at: at(1, 1),
+            }],
+            balances: vec![
+                (connection(), Utc.with_ymd_and_hms(2024, 2, 29, 0, 0, 0).unwrap(), 10_000.0),
+                (connection(), Utc.with_ymd_and_hms(2024, 3, 31, 0, 0, 0).unwrap(), 10_386.05),
+                (connection(), Utc.with_ymd_and_hms(2024, 4, 30, 0, 0, 0).unwrap(), 10_462.55),
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_statement_totals_for_a_seeded_month() {
+        let env = seeded_env();
+        let march = StatementPeriod::new(2024, 3).unwrap();
+
+        let (statement, html) = StatementService::get(&env, user(), march, at(4, 5)).await.unwrap();
+        assert!(statement.is_final);
+        assert_eq!(statement.accounts.len(), 1);
+
+        let account = &statement.accounts[0];
+        assert_eq!(account.connection_name.as_deref(), Some("Main account"));
+        assert_eq!((account.opening_balance, account.ending_balance), (Some(10_000.0), Some(10_386.05)));
+        assert_eq!(account.trades.len(), 4);
+        assert_eq!(account.totals.trades, 4);
+        assert_eq!(money::round_amount(account.totals.profit_loss), 396.75);
+        assert_eq!(money::round_amount(account.totals.commission), -7.7);
+        assert_eq!(money::round_amount(account.totals.swap), -3.0);
+        assert_eq!(money::round_amount(account.totals.net), 386.05);
+        assert_eq!(account.totals.adjustments, 0.0);
+
+        let symbols: Vec<(&str, i64, f64)> =
+            account.symbols.iter().map(|s| (s.symbol.as_str(), s.trades, money::round_amount(s.net))).collect();
+        assert_eq!(symbols, vec![("EURUSD", 3, 81.4), ("XAUUSD", 1, 304.65)]);
+
+        assert!(html.contains("Monthly statement &mdash; March 2024"));
+        assert!(html.contains("<td>Net profit/loss</td><td class=\"num\">386.05</td>"));
+        assert!(html.contains("@media print"));
+    }
+
+    #[tokio::test]
+    async fn test_issued_statement_is_immutable_and_corrections_carry_to_the_next_month() {
+        let env = seeded_env();
+        let march = StatementPeriod::new(2024, 3).unwrap();
+        let (issued, issued_html) = StatementService::get(&env, user(), march, at(4, 5)).await.unwrap();
+
+        // The broker corrects trade 2 and a late close for March arrives
+        {
+            let mut trades = env.trades.lock().unwrap();
+            trades[1].profit_loss = Some(-40.5);
+            trades.push(trade(7, "GBPUSD", at(3, 30), 25.0, -1.0, 0.0));
+        }
+
+        let (again, again_html) = StatementService::get(&env, user(), march, at(5, 1)).await.unwrap();
+        assert_eq!(again, issued);
+        assert_eq!(again_html, issued_html);
+
+        let (april, _) = StatementService::get(&env, user(), march.next(), at(5, 1)).await.unwrap();
+        let account = &april.accounts[0];
+        assert_eq!(account.opening_balance, Some(10_386.05));
+        assert_eq!(account.trades.iter().map(|t| t.trade_id).collect::<Vec<_>>(), vec![Uuid::from_u128(6)]);
+        let adjustments: Vec<(Uuid, f64, f64)> = account
+            .adjustments
+            .iter()
+            .map(|a| (a.trade_id, money::round_amount(a.previously_booked), money::round_amount(a.amount)))
+            .collect();
+        assert_eq!(adjustments, vec![(Uuid::from_u128(7), 0.0, 24.0), (Uuid::from_u128(2), -46.9, 5.0)]);
+        assert_eq!(money::round_amount(account.totals.adjustments), 29.0);
+
+        // Once April books them, May has nothing left to adjust
+        let (may, _) = StatementService::get(&env, user(), StatementPeriod::new(2024, 5).unwrap(), at(6, 1)).await.unwrap();
+        assert!(may.accounts.iter().all(|a| a.adjustments.is_empty()));
+    }
+
+    #[tokio::test]
+    async fn test_month_in_progress_is_provisional_and_not_stored() {
+        let env = seeded_env();
+        let april = StatementPeriod::new(2024, 4).unwrap();
+
+        let (statement, html) = StatementService::get(&env, user(), april, at(4, 10)).await.unwrap();
+        assert!(!statement.is_final);
+        assert!(html.contains("Provisional"));
+        assert!(env.stored.lock().unwrap().is_empty());
+
+        let future = StatementService::get(&env, user(), april.next(), at(4, 10)).await;
+        assert!(matches!(future, Err(AppError::Validation(_))));
+        assert!(StatementPeriod::new(2024, 13).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_scheduled_run_issues_last_month_and_notifies_once() {
+        let env = seeded_env();
+
+        assert_eq!(StatementService::generate_due(&env, at(4, 1)).await.unwrap(), 1);
+        assert_eq!(StatementService::generate_due(&env, at(4, 1)).await.unwrap(), 0);
+
+        let stored = env.stored.lock().unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].0.period_start, NaiveDate::from_ymd_opt(2024, 3, 1).unwrap());
+        assert_eq!(*env.sent.lock().unwrap(), vec![("trader@example.com".to_string(), StatementPeriod { year: 2024, month: 3 })]);
+    }
+}
@@ -0,0 +1,633 @@
+use chrono::{DateTime, Duration, Utc};
+use futures_util::stream::{self, StreamExt};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+use crate::{
+    errors::{AppError, Result},
+    models::{SubscriptionPlan, TradingRobot, UpdateTradingRobotRequest},
+    services::{
+        backtest_engine::{BacktestEngine, BacktestMetrics, BacktestRules},
+        backtest_progress::{BacktestEventSink, BacktestJobRegistry, BacktestProgressReporter},
+        job_service::JobHandle,
+        robot_journal::RobotJournalStore,
+        task_supervisor::{TaskClass, TaskSupervisor},
+        PlanService, RobotJournal,
+    },
+};
+
+// risk_config settings a grid may sweep
+pub const OPTIMIZABLE_PARAMETERS: [&str; 3] = ["stop_loss_pips", "take_profit_pips", "min_confidence"];
+// Backtests of one job running at the same time
+const OPTIMIZATION_PARALLELISM: usize = 4;
+const MAX_OPTIMIZATION_DAYS: i64 = 365;
+const FINISHED_JOB_RETENTION_HOURS: i64 = 24;
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct OptimizeRobotRequest {
+    // Values to try per setting, e.g. {"stop_loss_pips": [10, 20, 30], "min_confidence": [0.6, 0.7]}
+    pub parameters: BTreeMap<String, Vec<f64>>,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OptimizationStatus {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct OptimizationResult {
+    // 1 is the best
+    pub rank: usize,
+    pub parameters: BTreeMap<String, f64>,
+    #[serde(flatten)]
+    pub metrics: BacktestMetrics,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct OptimizationJob {
+    pub job_id: Uuid,
+    pub robot_id: Uuid,
+    #[serde(skip)]
+    pub user_id: Uuid,
+    pub status: OptimizationStatus,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub combinations: usize,
+    pub completed_combinations: usize,
+    // Best first, once the job has completed
+    pub results: Vec<OptimizationResult>,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    // When the best combination was written to the robot's risk_config
+    pub applied_at: Option<DateTime<Utc>>,
+}
+
+// Every combination of the grid's values, in a stable order
+pub fn expand_grid(parameters: &BTreeMap<String, Vec<f64>>) -> Result<Vec<BTreeMap<String, f64>>> {
+    if parameters.is_empty() {
+        return Err(AppError::Validation("At least one parameter is needed to optimize".to_string()));
+    }
+    for (name, values) in parameters {
+        if !OPTIMIZABLE_PARAMETERS.contains(&name.as_str()) {
+            return Err(AppError::Validation(format!(
+                "{} cannot be optimized; expected one of {}",
+                name,
+                OPTIMIZABLE_PARAMETERS.join(", ")
+            )));
+        }
+        if values.is_empty() || values.iter().any(|v| !v.is_finite()) {
+            return Err(AppError::Validation(format!("{} needs at least one finite value", name)));
+        }
+    }
+
+    let mut combinations = vec![BTreeMap::new()];
+    for (name, values) in parameters {
+        combinations = combinations
+            .into_iter()
+            .flat_map(|combination| {
+                values.iter().map(move |value| {
+                    let mut next = combination.clone();
+                    next.insert(name.clone(), *value);
+                    next
+                })
+            })
+            .collect();
+    }
+    Ok(combinations)
+}
+
+// Whole numbers are stored as integers, the way risk configs are usually written
+fn config_value(value: f64) -> serde_json::Value {
+    if value.fract() == 0.0 && value.abs() < i64::MAX as f64 {
+        serde_json::json!(value as i64)
+    } else {
+        serde_json::json!(value)
+    }
+}
+
+fn with_parameters(risk_config: &serde_json::Value, parameters: &BTreeMap<String, f64>) -> serde_json::Value {
+    let mut config = risk_config.as_object().cloned().unwrap_or_default();
+    for (name, value) in parameters {
+        config.insert(name.clone(), config_value(*value));
+    }
+    serde_json::Value::Object(config)
+}
+
+// Highest net profit first; shallower drawdown, then grid order, break ties
+fn rank(mut scored: Vec<(usize, BTreeMap<String, f64>, BacktestMetrics)>) -> Vec<OptimizationResult> {
+    scored.sort_by(|a, b| {
+        b.2.net_profit
+            .total_cmp(&a.2.net_profit)
+            .then(a.2.max_drawdown.total_cmp(&b.2.max_drawdown))
+            .then(a.0.cmp(&b.0))
+    });
+    scored
+        .into_iter()
+        .enumerate()
+        .map(|(i, (_, parameters, metrics))| OptimizationResult { rank: i + 1, parameters, metrics })
+        .collect()
+}
+
+// Grid searches over backtests. Jobs live in memory; progress goes out as backtest events.
+pub struct StrategyOptimizer {
+    engine: Arc<dyn BacktestEngine>,
+    progress: Arc<BacktestJobRegistry>,
+    sink: Arc<dyn BacktestEventSink>,
+    jobs: Mutex<HashMap<Uuid, OptimizationJob>>,
+}
+
+impl StrategyOptimizer {
+    pub fn new(engine: Arc<dyn BacktestEngine>, progress: Arc<BacktestJobRegistry>, sink: Arc<dyn BacktestEventSink>) -> Self {
+        StrategyOptimizer { engine, progress, sink, jobs: Mutex::new(HashMap::new()) }
+    }
+
+    // Only the owner sees a job
+    pub fn job(&self, user_id: Uuid, job_id: Uuid) -> Option<OptimizationJob> {
+        self.jobs.lock().unwrap().get(&job_id).filter(|job| job.user_id == user_id).cloned()
+    }
+
+    fn update(&self, job_id: Uuid, apply: impl FnOnce(&mut OptimizationJob)) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(&job_id) {
+            apply(job);
+        }
+    }
+
+    // Validates the request against the plan; checked before a job is registered for it
+    pub fn validate(
+        robot: &TradingRobot,
+        plan: &SubscriptionPlan,
+        request: &OptimizeRobotRequest,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<BTreeMap<String, f64>>> {
+        if request.end <= request.start {
+            return Err(AppError::Validation("end must be after start".to_string()));
+        }
+        if request.end - request.start > Duration::days(MAX_OPTIMIZATION_DAYS) {
+            return Err(AppError::Validation(format!(
+                "An optimization covers at most {} days",
+                MAX_OPTIMIZATION_DAYS
+            )));
+        }
+        PlanService::check_backtest_range(plan, request.start, now)?;
+        // Checked before expanding so an oversized grid is never built
+        let count = request.parameters.values().fold(1usize, |n, values| n.saturating_mul(values.len()));
+        PlanService::check_optimization_limit(plan, count)?;
+        let combinations = expand_grid(&request.parameters)?;
+        for parameters in &combinations {
+            BacktestRules::from_risk_config(&with_parameters(&robot.risk_config, parameters))
+                .map_err(AppError::Validation)?;
+        }
+        Ok(combinations)
+    }
+
+    // Registers the job under the id of its entry in the jobs table
+    pub fn prepare(
+        &self,
+        robot: &TradingRobot,
+        request: &OptimizeRobotRequest,
+        combinations: usize,
+        job_id: Uuid,
+        now: DateTime<Utc>,
+    ) -> OptimizationJob {
+        let job = OptimizationJob {
+            job_id,
+            robot_id: robot.id,
+            user_id: robot.user_id,
+            status: OptimizationStatus::Running,
+            start: request.start,
+            end: request.end,
+            combinations,
+            completed_combinations: 0,
+            results: Vec::new(),
+            error: None,
+            created_at: now,
+            finished_at: None,
+            applied_at: None,
+        };
+
+        let mut jobs = self.jobs.lock().unwrap();
+        let cutoff = now - Duration::hours(FINISHED_JOB_RETENTION_HOURS);
+        jobs.retain(|_, job| !matches!(job.finished_at, Some(at) if at <= cutoff));
+        jobs.insert(job.job_id, job.clone());
+        job
+    }
+
+    // Backtests every combination, a few at a time, and stores the ranking. Progress is
+    // checkpointed after every combination; a cancel stops the job between backtests.
+    pub async fn run(&self, robot: &TradingRobot, job: &OptimizationJob, combinations: Vec<BTreeMap<String, f64>>, handle: JobHandle) {
+        if let Err(e) = handle.start().await {
+            tracing::warn!("Failed to mark optimization {} as started: {}", job.job_id, e);
+        }
+        let total = combinations.len();
+        let mut reporter = BacktestProgressReporter::start(
+            job.job_id,
+            job.user_id,
+            combinations.len() as u64,
+            self.progress.clone(),
+            self.sink.clone(),
+        )
+        .with_interval(1);
+
+        let mut backtests = stream::iter(combinations.into_iter().enumerate())
+            .map(|(index, parameters)| async move {
+                let risk_config = with_parameters(&robot.risk_config, &parameters);
+                let metrics = self.engine.run(robot, &risk_config, job.start, job.end).await;
+                (index, parameters, metrics)
+            })
+            .buffer_unordered(OPTIMIZATION_PARALLELISM);
+
+        let mut scored = Vec::new();
+        let mut trades = 0;
+        loop {
+            let next = tokio::select! {
+                biased;
+                _ = handle.cancelled() => None,
+                next = backtests.next() => Some(next),
+            };
+            let Some(next) = next else {
+                drop(backtests);
+                self.update(job.job_id, |job| {
+                    job.status = OptimizationStatus::Cancelled;
+                    job.finished_at = Some(Utc::now());
+                });
+                reporter.fail("The optimization was cancelled").await;
+                if let Err(e) = handle.acknowledge_cancel().await {
+                    tracing::warn!("Failed to record the cancel of optimization {}: {}", job.job_id, e);
+                }
+                return;
+            };
+            let Some((index, parameters, metrics)) = next else { break };
+            let metrics = match metrics {
+                Ok(metrics) => metrics,
+                Err(e) => {
+                    let error = format!("Backtest of {:?} failed: {}", parameters, e);
+                    self.update(job.job_id, |job| {
+                        job.status = OptimizationStatus::Failed;
+                        job.error = Some(error.clone());
+                        job.finished_at = Some(Utc::now());
+                    });
+                    reporter.fail(&error).await;
+                    if let Err(e) = handle.fail(&error).await {
+                        tracing::warn!("Failed to record the failure of optimization {}: {}", job.job_id, e);
+                    }
+                    return;
+                }
+            };
+            trades += metrics.total_trades as u64;
+            scored.push((index, parameters, metrics));
+
+            let done = scored.len();
+            self.update(job.job_id, |job| job.completed_combinations = done);
+            let checkpoint = serde_json::json!({ "completed_combinations": done, "combinations": total });
+            if let Err(e) = handle.checkpoint(done as f64 * 100.0 / total as f64, checkpoint).await {
+                tracing::warn!("Failed to checkpoint optimization {}: {}", job.job_id, e);
+            }
+            // Equity here is the best net profit found so far, in pips
+            let best = scored.iter().map(|(_, _, m)| m.net_profit).fold(f64::MIN, f64::max);
+            reporter.candle(done as u64, trades, best).await;
+        }
+
+        let results = rank(scored);
+        self.update(job.job_id, |job| {
+            job.status = OptimizationStatus::Completed;
+            job.results = results;
+            job.finished_at = Some(Utc::now());
+        });
+        reporter.complete(job.job_id).await;
+        let result_ref = format!("/api/v1/optimizations/{}", job.job_id);
+        if let Err(e) = handle.complete(Some(result_ref)).await {
+            tracing::warn!("Failed to record the completion of optimization {}: {}", job.job_id, e);
+        }
+    }
+
+    // Runs a validated grid in the background; the job counts against the user's limit until
+    // the handle is finished
+    pub fn start(
+        self: &Arc<Self>,
+        robot: TradingRobot,
+        request: &OptimizeRobotRequest,
+        combinations: Vec<BTreeMap<String, f64>>,
+        handle: JobHandle,
+    ) -> OptimizationJob {
+        let job = self.prepare(&robot, request, combinations.len(), handle.job_id(), Utc::now());
+
+        let optimizer = self.clone();
+        let queued = job.clone();
+        let work = async move {
+            optimizer.run(&robot, &queued, combinations, handle).await;
+        };
+        let optimizer = self.clone();
+        let job_id = job.job_id;
+        let on_panic = move || async move {
+            optimizer.update(job_id, |job| {
+                job.status = OptimizationStatus::Failed;
+                job.error = Some("The optimization crashed".to_string());
+                job.finished_at = Some(Utc::now());
+            });
+        };
+        TaskSupervisor::new().spawn_owned(format!("optimization:{}", job_id), TaskClass::Background, work, on_panic);
+        job
+    }
+
+    // Writes the best combination into the robot's risk_config through the journaled update
+    pub async fn apply_best(
+        &self,
+        store: &dyn RobotJournalStore,
+        robot: &TradingRobot,
+        actor_id: Uuid,
+        job_id: Uuid,
+        now: DateTime<Utc>,
+    ) -> Result<TradingRobot> {
+        let job = self
+            .job(robot.user_id, job_id)
+            .filter(|job| job.robot_id == robot.id)
+            .ok_or_else(|| AppError::NotFound("Optimization not found".to_string()))?;
+        if job.status != OptimizationStatus::Completed {
+            return Err(AppError::Unprocessable("The optimization has not completed".to_string()));
+        }
+        let best = job
+            .results
+            .first()
+            .ok_or_else(|| AppError::Unprocessable("The optimization has no results".to_string()))?;
+
+        let risk_config = best.parameters.iter().map(|(name, value)| (name.clone(), config_value(*value))).collect();
+        let request = UpdateTradingRobotRequest { risk_config: Some(risk_config), ..Default::default() };
+        let (updated, _) = RobotJournal::update(store, robot, actor_id, request, None, now).await?;
+
+        self.update(job_id, |job| job.applied_at = Some(now));
+        Ok(updated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::RobotChange;
+    use crate::services::backtest_engine::{Candle, CandleBacktestEngine, CandleSource};
+    use crate::services::websocket_manager::WebSocketMessage;
+    use crate::models::Subscription;
+    use crate::models::{JOB_CANCELLED, JOB_COMPLETED};
+    use crate::services::backtest_engine::BacktestEngine;
+    use crate::services::job_service::{JobClass, MemoryJobStore};
+    use crate::services::JobService;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use async_trait::async_trait;
+    use serde_json::json;
+
+    // Four candles repeated: a full-bodied bullish candle, a 15 pip dip after the entry,
+    // a rally 30 pips above it and a flat hour
+    struct SyntheticCandles {
+        cycles: usize,
+    }
+
+    #[async_trait]
+    impl CandleSource for SyntheticCandles {
+        async fn candles(&self, _symbol: &str, _start: DateTime<Utc>, _end: DateTime<Utc>) -> Result<Vec<Candle>> {
+            let candle = |open, high, low, close| Candle { open, high, low, close };
+            Ok((0..self.cycles)
+                .flat_map(|_| {
+                    [
+                        candle(1.1000, 1.1010, 1.1000, 1.1010),
+                        candle(1.1010, 1.1010, 1.0995, 1.1005),
+                        candle(1.1005, 1.1040, 1.1005, 1.1020),
+                        candle(1.1000, 1.1000, 1.1000, 1.1000),
+                    ]
+                })
+                .collect())
+        }
+    }
+
+    #[derive(Default)]
+    struct RecordingSink {
+        sent: Mutex<Vec<WebSocketMessage>>,
+    }
+
+    #[async_trait]
+    impl BacktestEventSink for RecordingSink {
+        async fn publish(&self, _user_id: Uuid, message: WebSocketMessage) {
+            self.sent.lock().unwrap().push(message);
+        }
+    }
+
+    #[derive(Default)]
+    struct FakeJournal {
+        saved: Mutex<Vec<TradingRobot>>,
+    }
+
+    #[async_trait]
+    impl RobotJournalStore for FakeJournal {
+        async fn save(&self, robot: &TradingRobot, _change: Option<&RobotChange>) -> Result<()> {
+            self.saved.lock().unwrap().push(robot.clone());
+            Ok(())
+        }
+
+        async fn changes_since(&self, _user_id: Uuid, _start: DateTime<Utc>) -> Result<Vec<RobotChange>> {
+            Ok(Vec::new())
+        }
+    }
+
+    fn robot() -> TradingRobot {
+        let mut robot = TradingRobot::new(Uuid::new_v4(), "Breakout".to_string(), "breakout".to_string());
+        robot.risk_config = json!({ "max_risk_per_trade": 0.01, "stop_loss_pips": 15, "symbols": ["EURUSD"] });
+        robot
+    }
+
+    fn request(parameters: serde_json::Value) -> OptimizeRobotRequest {
+        let end = Utc::now();
+        OptimizeRobotRequest {
+            parameters: serde_json::from_value(parameters).unwrap(),
+            start: end - Duration::days(30),
+            end,
+        }
+    }
+
+    async fn register(user_id: Uuid) -> (Arc<MemoryJobStore>, JobHandle) {
+        let store = Arc::new(MemoryJobStore::default());
+        let jobs = Arc::new(JobService::new(store.clone()));
+        (store, jobs.register_for_plan(user_id, "pro", JobClass::Backtest).await.unwrap())
+    }
+
+    fn optimizer(sink: Arc<RecordingSink>) -> StrategyOptimizer {
+        let engine = CandleBacktestEngine::new(Arc::new(SyntheticCandles { cycles: 3 }));
+        StrategyOptimizer::new(Arc::new(engine), Arc::new(BacktestJobRegistry::new()), sink)
+    }
+
+    #[tokio::test]
+    async fn test_grid_is_ranked_by_net_profit_and_progress_is_streamed() {
+        let sink = Arc::new(RecordingSink::default());
+        let optimizer = optimizer(sink.clone());
+        let robot = robot();
+        let (store, handle) = register(robot.user_id).await;
+        let job_id = handle.job_id();
+        let plan = Subscription::plan_details("pro");
+
+        let request = request(json!({ "stop_loss_pips": [10, 20], "take_profit_pips": [20, 40] }));
+        let combinations = StrategyOptimizer::validate(&robot, &plan, &request, Utc::now()).unwrap();
+        let job = optimizer.prepare(&robot, &request, combinations.len(), job_id, Utc::now());
+        assert_eq!(job.combinations, 4);
+        optimizer.run(&robot, &job, combinations, handle).await;
+
+        let stored = store.job(job_id);
+        assert_eq!((stored.status.as_str(), stored.progress), (JOB_COMPLETED, 100.0));
+        assert_eq!(stored.result_ref, Some(format!("/api/v1/optimizations/{}", job_id)));
+
+        let job = optimizer.job(robot.user_id, job_id).unwrap();
+        assert_eq!((job.status, job.completed_combinations), (OptimizationStatus::Completed, 4));
+        let ranking: Vec<_> = job
+            .results
+            .iter()
+            .map(|r| (r.rank, r.parameters["stop_loss_pips"], r.parameters["take_profit_pips"], r.metrics.net_profit.round()))
+            .collect();
+        // A 10 pip stop is always hit by the dip; a 40 pip target is never reached and the
+        // single trade is closed 10 pips down when the series ends
+        assert_eq!(
+            ranking,
+            vec![(1, 20.0, 20.0, 60.0), (2, 20.0, 40.0, -10.0), (3, 10.0, 20.0, -30.0), (4, 10.0, 40.0, -30.0)]
+        );
+        let best = &job.results[0].metrics;
+        assert_eq!((best.total_trades, best.max_drawdown, best.profit_factor), (3, 0.0, None));
+        assert_eq!(job.results[2].metrics.profit_factor, Some(0.0));
+
+        let sent = sink.sent.lock().unwrap();
+        let progress: Vec<_> = sent
+            .iter()
+            .filter(|m| m.message_type == "backtest_progress")
+            .map(|m| m.data["candles_processed"].as_u64().unwrap())
+            .collect();
+        assert_eq!(progress, vec![1, 2, 3, 4]);
+        assert_eq!(sent.last().unwrap().message_type, "backtest_complete");
+        assert_eq!(sent.last().unwrap().data["job_id"], json!(job.job_id));
+
+        // Other users cannot see the job
+        assert!(optimizer.job(Uuid::new_v4(), job.job_id).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_apply_best_updates_the_risk_config_through_the_journal() {
+        let optimizer = optimizer(Arc::new(RecordingSink::default()));
+        let robot = robot();
+        let (_, handle) = register(robot.user_id).await;
+        let plan = Subscription::plan_details("pro");
+        let store = FakeJournal::default();
+
+        let request = request(json!({ "stop_loss_pips": [10, 20], "take_profit_pips": [20, 40] }));
+        let combinations = StrategyOptimizer::validate(&robot, &plan, &request, Utc::now()).unwrap();
+        let job = optimizer.prepare(&robot, &request, combinations.len(), handle.job_id(), Utc::now());
+
+        // Nothing to apply until the job has finished
+        let err = optimizer.apply_best(&store, &robot, robot.user_id, job.job_id, Utc::now()).await.unwrap_err();
+        assert!(matches!(err, AppError::Unprocessable(_)));
+
+        optimizer.run(&robot, &job, combinations, handle).await;
+        let now = Utc::now();
+        let updated = optimizer.apply_best(&store, &robot, robot.user_id, job.job_id, now).await.unwrap();
+
+        assert_eq!(
+            updated.risk_config,
+            json!({ "max_risk_per_trade": 0.01, "stop_loss_pips": 20, "take_profit_pips": 20, "symbols": ["EURUSD"] })
+        );
+        assert_eq!(store.saved.lock().unwrap().len(), 1);
+        assert_eq!(optimizer.job(robot.user_id, job.job_id).unwrap().applied_at, Some(now));
+
+        // Only to the robot the job optimized
+        let other = TradingRobot { id: Uuid::new_v4(), ..robot.clone() };
+        let err = optimizer.apply_best(&store, &other, robot.user_id, job.job_id, now).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+    }
+
+    #[tokio::test]
+    async fn test_grid_is_validated_and_capped_by_plan() {
+        let robot = robot();
+        let essential = Subscription::plan_details("essential");
+        let prepare = |parameters| StrategyOptimizer::validate(&robot, &essential, &request(parameters), Utc::now());
+
+        // 3 x 4 = 12 combinations against a cap of 9
+        let err = prepare(json!({ "stop_loss_pips": [10, 20, 30], "min_confidence": [0.5, 0.6, 0.7, 0.8] })).unwrap_err();
+        assert!(matches!(err, AppError::Forbidden(msg) if msg.contains("this grid has 12")));
+        assert!(prepare(json!({ "stop_loss_pips": [10, 20, 30], "min_confidence": [0.6, 0.7, 0.8] })).is_ok());
+
+        assert!(matches!(prepare(json!({ "max_risk_per_trade": [0.01] })), Err(AppError::Validation(_))));
+        assert!(matches!(prepare(json!({ "min_confidence": [1.5] })), Err(AppError::Validation(_))));
+        assert!(matches!(prepare(json!({ "stop_loss_pips": [] })), Err(AppError::Validation(_))));
+        assert!(matches!(prepare(json!({})), Err(AppError::Validation(_))));
+    }
+
+    #[tokio::test]
+    async fn test_range_beyond_the_plan_history_is_refused() {
+        let robot = robot();
+        let end = Utc::now() - Duration::days(30);
+        let old = OptimizeRobotRequest { start: end - Duration::days(30), end, ..request(json!({ "stop_loss_pips": [10] })) };
+
+        let err = StrategyOptimizer::validate(&robot, &Subscription::plan_details("free"), &old, Utc::now()).unwrap_err();
+        assert!(matches!(err, AppError::PlanLimit(msg) if msg.contains("30 days")));
+        assert!(StrategyOptimizer::validate(&robot, &Subscription::plan_details("elite"), &old, Utc::now()).is_ok());
+    }
+
+    // Finishes the first two backtests it is given and never returns from the rest
+    #[derive(Default)]
+    struct StallingEngine {
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl BacktestEngine for StallingEngine {
+        async fn run(&self, _robot: &TradingRobot, _risk_config: &serde_json::Value, _start: DateTime<Utc>, _end: DateTime<Utc>) -> Result<BacktestMetrics> {
+            if self.calls.fetch_add(1, Ordering::SeqCst) >= 2 {
+                std::future::pending::<()>().await;
+            }
+            Ok(BacktestMetrics::from_trades(&[5.0]))
+        }
+    }
+
+    async fn wait_for(store: &MemoryJobStore, job_id: Uuid, done: impl Fn(&crate::models::Job) -> bool) {
+        let waiting = async {
+            while !done(&store.job(job_id)) {
+                tokio::task::yield_now().await;
+            }
+        };
+        tokio::time::timeout(std::time::Duration::from_secs(5), waiting).await.expect("job state not reached");
+    }
+
+    #[tokio::test]
+    async fn test_cancel_stops_a_running_grid_at_its_checkpoint() {
+        let sink = Arc::new(RecordingSink::default());
+        let optimizer = Arc::new(StrategyOptimizer::new(
+            Arc::new(StallingEngine::default()),
+            Arc::new(BacktestJobRegistry::new()),
+            sink.clone(),
+        ));
+        let robot = robot();
+        let store = Arc::new(MemoryJobStore::default());
+        let jobs = Arc::new(JobService::new(store.clone()));
+        let handle = jobs.register_for_plan(robot.user_id, "pro", JobClass::Backtest).await.unwrap();
+        let job_id = handle.job_id();
+
+        let request = request(json!({ "stop_loss_pips": [10, 20, 30], "take_profit_pips": [20, 40] }));
+        let combinations = StrategyOptimizer::validate(&robot, &Subscription::plan_details("pro"), &request, Utc::now()).unwrap();
+        optimizer.start(robot.clone(), &request, combinations, handle);
+
+        wait_for(&store, job_id, |job| job.checkpoint["completed_combinations"] == 2).await;
+        jobs.cancel(robot.user_id, job_id, Utc::now()).await.unwrap();
+        wait_for(&store, job_id, |job| job.status == JOB_CANCELLED).await;
+
+        let stored = store.job(job_id);
+        assert_eq!(stored.checkpoint, json!({ "completed_combinations": 2, "combinations": 6 }));
+        assert!((stored.progress - 100.0 / 3.0).abs() < 1e-9);
+        assert!(stored.cancel_requested_at.is_some() && stored.finished_at.is_some());
+
+        let job = optimizer.job(robot.user_id, job_id).unwrap();
+        assert_eq!((job.status, job.completed_combinations), (OptimizationStatus::Cancelled, 2));
+        assert!(job.results.is_empty());
+        assert_eq!(sink.sent.lock().unwrap().last().unwrap().message_type, "backtest_failed");
+    }
+}
@@ -2,7 +2,13 @@ use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
-use crate::errors::{AppError, Result};
+use crate::{
+    errors::{AppError, Result},
+    telemetry,
+};
+
+// Secret keys with this prefix never reach Stripe; calls return canned data
+pub const MOCK_STRIPE_SECRET_KEY: &str = "sk_test_mock";
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct StripeCustomer {
@@ -20,6 +26,45 @@ pub struct StripeSubscription {
     pub current_period_end: i64,
 }
 
+// A hosted payment page for one subscription; the customer is sent to `url`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StripeCheckoutSession {
+    pub id: String,
+    pub url: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckoutSessionParams {
+    pub price_id: String,
+    pub customer_email: String,
+    // Our user id, echoed back on the completed session
+    pub client_reference_id: String,
+    pub plan_name: String,
+    pub success_url: String,
+    pub cancel_url: String,
+}
+
+impl CheckoutSessionParams {
+    // Form fields of POST /v1/checkout/sessions
+    pub fn form(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("mode", "subscription".to_string()),
+            ("line_items[0][price]", self.price_id.clone()),
+            ("line_items[0][quantity]", "1".to_string()),
+            ("customer_email", self.customer_email.clone()),
+            ("client_reference_id", self.client_reference_id.clone()),
+            ("metadata[plan_name]", self.plan_name.clone()),
+            ("success_url", self.success_url.clone()),
+            ("cancel_url", self.cancel_url.clone()),
+        ]
+    }
+}
+
+const STRIPE_API_URL: &str = "https://api.stripe.com";
+
+// Where mock checkout sessions claim to live; nothing answers there
+pub const MOCK_CHECKOUT_URL: &str = "https://checkout.stripe.test/pay";
+
 #[derive(Debug, Serialize, Deserialize)]
 struct StripeApiCustomer {
     id: String,
@@ -39,6 +84,7 @@ struct StripeApiSubscription {
 pub struct StripeService {
     secret_key: String,
     client: Client,
+    api_url: String,
 }
 
 impl StripeService {
@@ -46,12 +92,26 @@ impl StripeService {
         StripeService {
             secret_key,
             client: Client::new(),
+            api_url: STRIPE_API_URL.to_string(),
         }
     }
 
+    // Points the client at a stand-in for the Stripe API
+    pub fn with_api_url(mut self, api_url: &str) -> Self {
+        self.api_url = api_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    async fn send(&self, operation: &'static str, request: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+        let request = request.header("Authorization", format!("Bearer {}", self.secret_key));
+        telemetry::send(operation, request)
+            .await
+            .map_err(|e| AppError::External(format!("Stripe API error: {}", e)))
+    }
+
     pub async fn create_customer(&self, email: &str, name: Option<&str>) -> Result<StripeCustomer> {
         // For now, return mock data. In production, implement actual Stripe API calls
-        if self.secret_key.starts_with("sk_test_mock") {
+        if self.secret_key.starts_with(MOCK_STRIPE_SECRET_KEY) {
             return Ok(StripeCustomer {
                 id: format!("cus_{}", uuid::Uuid::new_v4().to_string().replace("-", "")),
                 email: email.to_string(),
@@ -66,14 +126,8 @@ impl StripeService {
             params.insert("name", name);
         }
 
-        let response = self
-            .client
-            .post("https://api.stripe.com/v1/customers")
-            .header("Authorization", format!("Bearer {}", self.secret_key))
-            .form(&params)
-            .send()
-            .await
-            .map_err(|e| AppError::External(format!("Stripe API error: {}", e)))?;
+        let request = self.client.post(format!("{}/v1/customers", self.api_url)).form(&params);
+        let response = self.send("stripe.create_customer", request).await?;
 
         if !response.status().is_success() {
             return Err(AppError::External(format!(
@@ -101,7 +155,7 @@ impl StripeService {
         payment_method_id: &str,
     ) -> Result<StripeSubscription> {
         // For now, return mock data. In production, implement actual Stripe API calls
-        if self.secret_key.starts_with("sk_test_mock") {
+        if self.secret_key.starts_with(MOCK_STRIPE_SECRET_KEY) {
             return Ok(StripeSubscription {
                 id: format!("sub_{}", uuid::Uuid::new_v4().to_string().replace("-", "")),
                 customer_id: customer_id.to_string(),
@@ -117,14 +171,8 @@ impl StripeService {
         params.insert("items[0][price]", price_id);
         params.insert("default_payment_method", payment_method_id);
 
-        let response = self
-            .client
-            .post("https://api.stripe.com/v1/subscriptions")
-            .header("Authorization", format!("Bearer {}", self.secret_key))
-            .form(&params)
-            .send()
-            .await
-            .map_err(|e| AppError::External(format!("Stripe API error: {}", e)))?;
+        let request = self.client.post(format!("{}/v1/subscriptions", self.api_url)).form(&params);
+        let response = self.send("stripe.create_subscription", request).await?;
 
         if !response.status().is_success() {
             return Err(AppError::External(format!(
@@ -147,24 +195,42 @@ impl StripeService {
         })
     }
 
+    pub fn is_mock(&self) -> bool {
+        self.secret_key.starts_with(MOCK_STRIPE_SECRET_KEY)
+    }
+
+    pub async fn create_checkout_session(&self, params: &CheckoutSessionParams) -> Result<StripeCheckoutSession> {
+        if self.is_mock() {
+            let id = format!("cs_test_{}", uuid::Uuid::new_v4().to_string().replace("-", ""));
+            return Ok(StripeCheckoutSession { url: format!("{}/{}", MOCK_CHECKOUT_URL, id), id });
+        }
+
+        let request = self.client.post(format!("{}/v1/checkout/sessions", self.api_url)).form(&params.form());
+        let response = self.send("stripe.create_checkout_session", request).await?;
+
+        if !response.status().is_success() {
+            return Err(AppError::External(format!(
+                "Stripe API error: {}",
+                response.status()
+            )));
+        }
+
+        response
+            .json()
+            .await
+            .map_err(|e| AppError::External(format!("Failed to parse Stripe response: {}", e)))
+    }
+
     pub async fn cancel_subscription(&self, subscription_id: &str) -> Result<()> {
         // For now, just log. In production, implement actual Stripe API calls
-        if self.secret_key.starts_with("sk_test_mock") {
+        if self.secret_key.starts_with(MOCK_STRIPE_SECRET_KEY) {
             tracing::info!("Mock: Cancelling Stripe subscription: {}", subscription_id);
             return Ok(());
         }
 
         // Actual Stripe API implementation would go here
-        let response = self
-            .client
-            .delete(&format!(
-                "https://api.stripe.com/v1/subscriptions/{}",
-                subscription_id
-            ))
-            .header("Authorization", format!("Bearer {}", self.secret_key))
-            .send()
-            .await
-            .map_err(|e| AppError::External(format!("Stripe API error: {}", e)))?;
+        let request = self.client.delete(format!("{}/v1/subscriptions/{}", self.api_url, subscription_id));
+        let response = self.send("stripe.cancel_subscription", request).await?;
 
         if !response.status().is_success() {
             return Err(AppError::External(format!(
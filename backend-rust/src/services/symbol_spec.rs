@@ -0,0 +1,113 @@
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::services::backtest_engine::pip_size;
+
+const FOREX_CONTRACT_SIZE: f64 = 100_000.0;
+const MIN_LOT: f64 = 0.01;
+const MAX_LOT: f64 = 100.0;
+const LOT_STEP: f64 = 0.01;
+// Lots are snapped to this many decimals after stepping, so 7 steps of 0.01 are 0.07
+const LOT_DECIMALS: i32 = 8;
+
+// Contract terms of a symbol the way MT5 brokers usually set them: orders go out in steps of
+// `lot_step` between `min_lot` and `max_lot`
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct SymbolSpec {
+    pub symbol: String,
+    pub base_currency: String,
+    pub quote_currency: String,
+    // Units of the base currency in one lot
+    pub contract_size: f64,
+    pub pip_size: f64,
+    pub min_lot: f64,
+    pub max_lot: f64,
+    pub lot_step: f64,
+}
+
+impl SymbolSpec {
+    // Currency pairs and spot metals, e.g. EURUSD, USDJPY, XAUUSD; None for anything else
+    pub fn for_symbol(symbol: &str) -> Option<SymbolSpec> {
+        let symbol = symbol.trim().to_ascii_uppercase();
+        if symbol.len() != 6 || !symbol.chars().all(|c| c.is_ascii_alphabetic()) {
+            return None;
+        }
+        let (base, quote) = symbol.split_at(3);
+        let (contract_size, pip) = match base {
+            "XAU" => (100.0, 0.1),
+            "XAG" => (5_000.0, 0.01),
+            _ => (FOREX_CONTRACT_SIZE, pip_size(&symbol)),
+        };
+        Some(SymbolSpec {
+            base_currency: base.to_string(),
+            quote_currency: quote.to_string(),
+            symbol,
+            contract_size,
+            pip_size: pip,
+            min_lot: MIN_LOT,
+            max_lot: MAX_LOT,
+            lot_step: LOT_STEP,
+        })
+    }
+
+    // An amount in the quote currency, in the account currency at `price`. None when neither
+    // side of the pair is the account currency.
+    fn in_account_currency(&self, amount: f64, price: f64, account_currency: &str) -> Option<f64> {
+        let account_currency = account_currency.trim().to_ascii_uppercase();
+        if self.quote_currency == account_currency {
+            Some(amount)
+        } else if self.base_currency == account_currency && price > 0.0 {
+            Some(amount / price)
+        } else {
+            None
+        }
+    }
+
+    // What one pip on one lot is worth in the account currency
+    pub fn pip_value(&self, price: f64, account_currency: &str) -> Option<f64> {
+        self.in_account_currency(self.contract_size * self.pip_size, price, account_currency)
+    }
+
+    // Margin the broker holds for a position of `lots` opened at `price`
+    pub fn margin(&self, lots: f64, price: f64, leverage: f64, account_currency: &str) -> Option<f64> {
+        self.in_account_currency(lots * self.contract_size * price / leverage, price, account_currency)
+    }
+
+    // The volume the broker accepts: rounded down to the lot step, then held within the lot limits
+    pub fn round_lots(&self, lots: f64) -> f64 {
+        let steps = (lots / self.lot_step + 1e-9).floor();
+        crate::money::round(steps * self.lot_step, LOT_DECIMALS, crate::money::Rounding::HalfUp)
+            .clamp(self.min_lot, self.max_lot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pairs_and_metals_have_specs() {
+        let eurusd = SymbolSpec::for_symbol("eurusd").unwrap();
+        assert_eq!((eurusd.base_currency.as_str(), eurusd.quote_currency.as_str()), ("EUR", "USD"));
+        assert_eq!(eurusd.pip_value(1.1, "USD"), Some(10.0));
+        // A USD account on a USD-based pair converts at the price
+        let usdjpy = SymbolSpec::for_symbol("USDJPY").unwrap();
+        assert!((usdjpy.pip_value(125.0, "USD").unwrap() - 8.0).abs() < 1e-9);
+        assert_eq!(usdjpy.pip_value(125.0, "EUR"), None);
+        let gold = SymbolSpec::for_symbol("XAUUSD").unwrap();
+        assert_eq!(gold.pip_value(2000.0, "USD"), Some(10.0));
+        assert!((gold.margin(0.1, 2000.0, 100.0, "USD").unwrap() - 200.0).abs() < 1e-9);
+
+        assert!(SymbolSpec::for_symbol("US30").is_none());
+        assert!(SymbolSpec::for_symbol("EURUSD.m").is_none());
+    }
+
+    #[test]
+    fn test_lots_round_down_to_the_step_within_the_limits() {
+        let spec = SymbolSpec::for_symbol("EURUSD").unwrap();
+        assert_eq!(spec.round_lots(0.079), 0.07);
+        assert_eq!(spec.round_lots(0.07), 0.07);
+        assert_eq!(spec.round_lots(0.004), 0.01);
+        assert_eq!(spec.round_lots(250.0), 100.0);
+    }
+}
@@ -0,0 +1,441 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use sqlx::PgPool;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use crate::{
+    errors::Result,
+    models::{
+        AdminSetting, BrokerMaintenance, BrokerMaintenanceScope, Incident, IncidentResponse, MaintenanceNotice, OutboxEmail,
+        BROKER_MAINTENANCE_SETTING, MAINTENANCE_SETTING,
+    },
+    services::{
+        task_supervisor::{task_panic_counts, TaskClass},
+        Mt5Service, WebSocketManager,
+    },
+};
+
+pub const STATUS_CHECK_SECONDS: u64 = 30;
+pub const PUBLIC_STATUS_CACHE_CONTROL: &str = "public, max-age=15";
+// Resolved incidents stay on the status page this long
+const RECENT_INCIDENT_DAYS: i64 = 7;
+const MAX_PUBLIC_INCIDENTS: i64 = 10;
+// How long the oldest pending email may wait before the queue is degraded, then down
+const EMAIL_DELAYED_MINUTES: i64 = 15;
+const EMAIL_STALLED_MINUTES: i64 = 60;
+
+// Worst last, so the overall state is the maximum
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ComponentState {
+    Operational,
+    Degraded,
+    Down,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Component {
+    Api,
+    Database,
+    // MT5 sessions across every user's broker connections
+    BrokerBridge,
+    Websocket,
+    EmailQueue,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct ComponentStatus {
+    pub component: Component,
+    pub state: ComponentState,
+    // When the component entered this state
+    pub since: DateTime<Utc>,
+}
+
+// Raw measurements behind the component states; never part of the public payload
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HealthReadings {
+    pub database: bool,
+    pub broker_sessions: usize,
+    pub broker_connected: usize,
+    // Totals since startup; only their growth between checks counts
+    pub websocket_dropped_messages: u64,
+    pub websocket_panics: u64,
+    pub oldest_pending_email_at: Option<DateTime<Utc>>,
+}
+
+#[async_trait]
+pub trait HealthSource: Send + Sync {
+    async fn readings(&self) -> HealthReadings;
+}
+
+pub struct LiveHealthSource {
+    pool: PgPool,
+    mt5: Arc<Mt5Service>,
+    websocket: Arc<WebSocketManager>,
+}
+
+impl LiveHealthSource {
+    pub fn new(pool: PgPool, mt5: Arc<Mt5Service>, websocket: Arc<WebSocketManager>) -> Self {
+        LiveHealthSource { pool, mt5, websocket }
+    }
+}
+
+#[async_trait]
+impl HealthSource for LiveHealthSource {
+    async fn readings(&self) -> HealthReadings {
+        let database = sqlx::query("SELECT 1").execute(&self.pool).await.is_ok();
+        let (broker_sessions, broker_connected) = self.mt5.session_counts();
+        let websocket_dropped_messages = self.websocket.connection_metrics().await.iter().map(|m| m.dropped_messages).sum();
+        let websocket_panics = task_panic_counts()
+            .into_iter()
+            .find(|c| c.task_class == TaskClass::WebSocket)
+            .map(|c| c.count)
+            .unwrap_or(0);
+        // With the database down the queue cannot be read; its state then follows the database
+        let oldest_pending_email_at = match database {
+            true => OutboxEmail::health(&self.pool).await.ok().and_then(|h| h.oldest_pending_at),
+            false => None,
+        };
+
+        HealthReadings {
+            database,
+            broker_sessions,
+            broker_connected,
+            websocket_dropped_messages,
+            websocket_panics,
+            oldest_pending_email_at,
+        }
+    }
+}
+
+struct MonitorState {
+    components: BTreeMap<Component, ComponentStatus>,
+    previous: Option<HealthReadings>,
+}
+
+// Coarse component states, refreshed by a scheduler job
+pub struct SystemMonitor {
+    state: Mutex<MonitorState>,
+}
+
+impl SystemMonitor {
+    // Everything counts as operational until the first check says otherwise
+    pub fn new(now: DateTime<Utc>) -> Self {
+        let components = [Component::Api, Component::Database, Component::BrokerBridge, Component::Websocket, Component::EmailQueue]
+            .into_iter()
+            .map(|component| (component, ComponentStatus { component, state: ComponentState::Operational, since: now }))
+            .collect();
+        SystemMonitor { state: Mutex::new(MonitorState { components, previous: None }) }
+    }
+
+    pub fn derive(readings: &HealthReadings, previous: Option<&HealthReadings>, now: DateTime<Utc>) -> BTreeMap<Component, ComponentState> {
+        use ComponentState::*;
+        let mut states = BTreeMap::new();
+
+        states.insert(Component::Api, if readings.database { Operational } else { Degraded });
+        states.insert(Component::Database, if readings.database { Operational } else { Down });
+
+        let broker = match (readings.broker_sessions, readings.broker_connected) {
+            (0, _) => Operational,
+            (sessions, connected) if connected >= sessions => Operational,
+            (_, 0) => Down,
+            _ => Degraded,
+        };
+        states.insert(Component::BrokerBridge, broker);
+
+        let grew = |value: fn(&HealthReadings) -> u64| previous.is_some_and(|p| value(readings) > value(p));
+        let websocket = if grew(|r| r.websocket_panics) || grew(|r| r.websocket_dropped_messages) { Degraded } else { Operational };
+        states.insert(Component::Websocket, websocket);
+
+        let email = match readings.oldest_pending_email_at.map(|at| now - at) {
+            _ if !readings.database => Down,
+            Some(waited) if waited >= Duration::minutes(EMAIL_STALLED_MINUTES) => Down,
+            Some(waited) if waited >= Duration::minutes(EMAIL_DELAYED_MINUTES) => Degraded,
+            _ => Operational,
+        };
+        states.insert(Component::EmailQueue, email);
+        states
+    }
+
+    pub fn observe(&self, readings: HealthReadings, now: DateTime<Utc>) {
+        let mut state = self.state.lock().unwrap();
+        for (component, next) in Self::derive(&readings, state.previous.as_ref(), now) {
+            let status = state
+                .components
+                .entry(component)
+                .or_insert(ComponentStatus { component, state: next, since: now });
+            if status.state != next {
+                tracing::warn!("Status of {:?} changed from {:?} to {:?}", component, status.state, next);
+                *status = ComponentStatus { component, state: next, since: now };
+            }
+        }
+        state.previous = Some(readings);
+    }
+
+    pub async fn check(&self, source: &dyn HealthSource, now: DateTime<Utc>) -> Result<()> {
+        let readings = source.readings().await;
+        self.observe(readings, now);
+        Ok(())
+    }
+
+    pub fn components(&self) -> Vec<ComponentStatus> {
+        self.state.lock().unwrap().components.values().cloned().collect()
+    }
+}
+
+#[async_trait]
+pub trait StatusStore: Send + Sync {
+    async fn maintenance(&self) -> Result<MaintenanceNotice>;
+    async fn broker_maintenance(&self) -> Result<BrokerMaintenance>;
+    async fn recent_incidents(&self, resolved_since: DateTime<Utc>, limit: i64) -> Result<Vec<Incident>>;
+}
+
+pub struct PgStatusStore {
+    pool: PgPool,
+}
+
+impl PgStatusStore {
+    pub fn new(pool: PgPool) -> Self {
+        PgStatusStore { pool }
+    }
+}
+
+#[async_trait]
+impl StatusStore for PgStatusStore {
+    async fn maintenance(&self) -> Result<MaintenanceNotice> {
+        AdminSetting::get(&self.pool, MAINTENANCE_SETTING).await
+    }
+
+    async fn broker_maintenance(&self) -> Result<BrokerMaintenance> {
+        AdminSetting::get(&self.pool, BROKER_MAINTENANCE_SETTING).await
+    }
+
+    async fn recent_incidents(&self, resolved_since: DateTime<Utc>, limit: i64) -> Result<Vec<Incident>> {
+        Incident::find_recent(&self.pool, resolved_since, limit).await
+    }
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct PublicStatus {
+    // The worst component state
+    pub status: ComponentState,
+    pub components: Vec<ComponentStatus>,
+    // Only while a maintenance notice is active
+    pub maintenance: Option<MaintenanceNotice>,
+    // Broker types whose order placement is paused for maintenance
+    pub broker_maintenance: Vec<BrokerMaintenanceScope>,
+    // Open incidents and those resolved in the last 7 days, newest first
+    pub incidents: Vec<IncidentResponse>,
+    pub generated_at: DateTime<Utc>,
+}
+
+pub struct StatusPage;
+
+impl StatusPage {
+    // With the database down the notice and incidents cannot be read; the component states
+    // are still served, as they are what the page is for
+    pub async fn public(monitor: &SystemMonitor, store: &dyn StatusStore, now: DateTime<Utc>) -> PublicStatus {
+        let mut components = monitor.components();
+
+        let broker_maintenance = match store.broker_maintenance().await {
+            Ok(maintenance) => maintenance.active(now),
+            Err(e) => {
+                tracing::warn!("Status page without broker maintenance: {}", e);
+                Vec::new()
+            }
+        };
+        // Some order placement is refused while a broker type is under maintenance
+        if let Some(since) = broker_maintenance.iter().map(|s| s.since).min() {
+            if let Some(bridge) = components.iter_mut().find(|c| c.component == Component::BrokerBridge) {
+                if bridge.state < ComponentState::Degraded {
+                    bridge.state = ComponentState::Degraded;
+                    bridge.since = since;
+                }
+            }
+        }
+        let status = components.iter().map(|c| c.state).max().unwrap_or(ComponentState::Operational);
+
+        let maintenance = match store.maintenance().await {
+            Ok(notice) => Some(notice).filter(|n| n.is_active(now)),
+            Err(e) => {
+                tracing::warn!("Status page without the maintenance notice: {}", e);
+                None
+            }
+        };
+        let incidents = match store.recent_incidents(now - Duration::days(RECENT_INCIDENT_DAYS), MAX_PUBLIC_INCIDENTS).await {
+            Ok(incidents) => incidents.into_iter().map(IncidentResponse::from).collect(),
+            Err(e) => {
+                tracing::warn!("Status page without incidents: {}", e);
+                Vec::new()
+            }
+        };
+
+        PublicStatus { status, components, maintenance, broker_maintenance, incidents, generated_at: now }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::AppError;
+    use crate::models::BrokerMaintenanceRequest;
+    use chrono::TimeZone;
+    use uuid::Uuid;
+
+    fn at(minute: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 12, 22, 9, 0, 0).unwrap() + Duration::minutes(minute)
+    }
+
+    fn healthy() -> HealthReadings {
+        HealthReadings { database: true, broker_sessions: 4, broker_connected: 4, ..HealthReadings::default() }
+    }
+
+    fn state_of(monitor: &SystemMonitor, component: Component) -> ComponentStatus {
+        monitor.components().into_iter().find(|c| c.component == component).unwrap()
+    }
+
+    #[derive(Default)]
+    struct FakeStore {
+        maintenance: MaintenanceNotice,
+        broker_maintenance: BrokerMaintenance,
+        incidents: Vec<Incident>,
+        unreachable: bool,
+    }
+
+    #[async_trait]
+    impl StatusStore for FakeStore {
+        async fn maintenance(&self) -> Result<MaintenanceNotice> {
+            if self.unreachable {
+                return Err(AppError::Unavailable("database down".to_string()));
+            }
+            Ok(self.maintenance.clone())
+        }
+
+        async fn broker_maintenance(&self) -> Result<BrokerMaintenance> {
+            if self.unreachable {
+                return Err(AppError::Unavailable("database down".to_string()));
+            }
+            Ok(self.broker_maintenance.clone())
+        }
+
+        async fn recent_incidents(&self, resolved_since: DateTime<Utc>, _limit: i64) -> Result<Vec<Incident>> {
+            if self.unreachable {
+                return Err(AppError::Unavailable("database down".to_string()));
+            }
+            Ok(self
+                .incidents
+                .iter()
+                .filter(|i| i.resolved_at.is_none_or(|resolved_at| resolved_at >= resolved_since))
+                .cloned()
+                .collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_degraded_component_shows_on_the_status_page_and_recovers() {
+        let monitor = SystemMonitor::new(at(0));
+        monitor.observe(healthy(), at(1));
+        let store = FakeStore::default();
+        assert_eq!(StatusPage::public(&monitor, &store, at(1)).await.status, ComponentState::Operational);
+
+        // One of four broker sessions lost
+        monitor.observe(HealthReadings { broker_connected: 3, ..healthy() }, at(2));
+        let page = StatusPage::public(&monitor, &store, at(2)).await;
+        assert_eq!(page.status, ComponentState::Degraded);
+        assert_eq!(state_of(&monitor, Component::BrokerBridge), ComponentStatus {
+            component: Component::BrokerBridge,
+            state: ComponentState::Degraded,
+            since: at(2),
+        });
+        // Unchanged components keep their original timestamp
+        assert_eq!(state_of(&monitor, Component::Database).since, at(0));
+
+        monitor.observe(HealthReadings { database: false, broker_connected: 0, ..healthy() }, at(3));
+        let page = StatusPage::public(&monitor, &FakeStore { unreachable: true, ..FakeStore::default() }, at(3)).await;
+        assert_eq!(page.status, ComponentState::Down);
+        assert_eq!(state_of(&monitor, Component::Database).state, ComponentState::Down);
+        assert_eq!(state_of(&monitor, Component::Api).state, ComponentState::Degraded);
+        assert_eq!(state_of(&monitor, Component::EmailQueue).state, ComponentState::Down);
+        // Losing the last session is a change of its own
+        assert_eq!(state_of(&monitor, Component::BrokerBridge), ComponentStatus {
+            component: Component::BrokerBridge,
+            state: ComponentState::Down,
+            since: at(3),
+        });
+
+        monitor.observe(healthy(), at(4));
+        assert_eq!(StatusPage::public(&monitor, &store, at(4)).await.status, ComponentState::Operational);
+        assert_eq!(state_of(&monitor, Component::Database).since, at(4));
+    }
+
+    #[test]
+    fn test_websocket_and_email_states_follow_growth_and_queue_age() {
+        let previous = healthy();
+        let states = SystemMonitor::derive(&HealthReadings { websocket_dropped_messages: 5, ..healthy() }, Some(&previous), at(0));
+        assert_eq!(states[&Component::Websocket], ComponentState::Degraded);
+        // The first check has nothing to compare against
+        assert_eq!(SystemMonitor::derive(&HealthReadings { websocket_panics: 2, ..healthy() }, None, at(0))[&Component::Websocket], ComponentState::Operational);
+
+        let waiting = |minutes: i64| HealthReadings { oldest_pending_email_at: Some(at(0) - Duration::minutes(minutes)), ..healthy() };
+        assert_eq!(SystemMonitor::derive(&waiting(5), None, at(0))[&Component::EmailQueue], ComponentState::Operational);
+        assert_eq!(SystemMonitor::derive(&waiting(20), None, at(0))[&Component::EmailQueue], ComponentState::Degraded);
+        assert_eq!(SystemMonitor::derive(&waiting(90), None, at(0))[&Component::EmailQueue], ComponentState::Down);
+
+        // No broker sessions at all is not an outage
+        let idle = HealthReadings { broker_sessions: 0, broker_connected: 0, ..healthy() };
+        assert_eq!(SystemMonitor::derive(&idle, None, at(0))[&Component::BrokerBridge], ComponentState::Operational);
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_message_and_incidents_are_public_but_metrics_are_not() {
+        let monitor = SystemMonitor::new(at(0));
+        monitor.observe(HealthReadings { broker_connected: 1, websocket_dropped_messages: 4242, ..healthy() }, at(1));
+        let open = Incident::new("Broker bridge delays".to_string(), "investigating".to_string(), "Looking into it".to_string(), Uuid::new_v4(), at(-30));
+        let mut old = Incident::new("Old outage".to_string(), "resolved".to_string(), "Fixed".to_string(), Uuid::new_v4(), at(-60 * 24 * 10));
+        old.resolved_at = Some(at(-60 * 24 * 10));
+        let store = FakeStore {
+            maintenance: MaintenanceNotice { message: Some("Database upgrade tonight 22:00 UTC".to_string()), until: Some(at(60)) },
+            incidents: vec![open.clone(), old],
+            ..FakeStore::default()
+        };
+
+        let page = StatusPage::public(&monitor, &store, at(1)).await;
+        assert_eq!(page.maintenance.unwrap().message.as_deref(), Some("Database upgrade tonight 22:00 UTC"));
+        assert_eq!(page.incidents.iter().map(|i| i.id).collect::<Vec<_>>(), vec![open.id]);
+
+        let json = serde_json::to_string(&StatusPage::public(&monitor, &store, at(1)).await).unwrap();
+        assert!(!json.contains("4242"));
+        assert!(!json.contains("created_by"));
+
+        // Once `until` has passed the notice is gone
+        assert!(StatusPage::public(&monitor, &store, at(61)).await.maintenance.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_broker_maintenance_degrades_the_bridge_while_active() {
+        let monitor = SystemMonitor::new(at(0));
+        monitor.observe(healthy(), at(1));
+        let mut store = FakeStore::default();
+        store.broker_maintenance.set(
+            "mt5",
+            BrokerMaintenanceRequest { message: "MT5 server upgrade".to_string(), until: Some(at(60)) },
+            at(5),
+        );
+
+        let page = StatusPage::public(&monitor, &store, at(10)).await;
+        assert_eq!(page.status, ComponentState::Degraded);
+        assert_eq!(page.broker_maintenance.iter().map(|s| s.broker_type.as_str()).collect::<Vec<_>>(), vec!["MT5"]);
+        let bridge = page.components.iter().find(|c| c.component == Component::BrokerBridge).unwrap();
+        assert_eq!((bridge.state, bridge.since), (ComponentState::Degraded, at(5)));
+        // The monitor's own view is untouched
+        assert_eq!(state_of(&monitor, Component::BrokerBridge).state, ComponentState::Operational);
+
+        let page = StatusPage::public(&monitor, &store, at(61)).await;
+        assert_eq!(page.status, ComponentState::Operational);
+        assert!(page.broker_maintenance.is_empty());
+    }
+}
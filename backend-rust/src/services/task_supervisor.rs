@@ -0,0 +1,351 @@
+use async_trait::async_trait;
+use futures_util::FutureExt;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex, Once, OnceLock};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+use crate::services::NotificationService;
+
+const RESTART_BASE_DELAY: Duration = Duration::from_secs(1);
+const RESTART_MAX_DELAY: Duration = Duration::from_secs(300);
+
+tokio::task_local! {
+    static TASK_NAME: String;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskClass {
+    // Periodic jobs, market data included; restarted after a panic
+    SchedulerJob,
+    // Trading loops: the admin is emailed and the robot is marked as errored
+    RobotRunner,
+    WebSocket,
+    Background,
+}
+
+impl TaskClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TaskClass::SchedulerJob => "scheduler_job",
+            TaskClass::RobotRunner => "robot_runner",
+            TaskClass::WebSocket => "websocket",
+            TaskClass::Background => "background",
+        }
+    }
+
+    pub fn is_critical(&self) -> bool {
+        matches!(self, TaskClass::RobotRunner)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct TaskPanicCount {
+    pub task_class: TaskClass,
+    pub count: u64,
+}
+
+fn panic_counter() -> &'static Mutex<BTreeMap<TaskClass, u64>> {
+    static COUNTER: OnceLock<Mutex<BTreeMap<TaskClass, u64>>> = OnceLock::new();
+    COUNTER.get_or_init(|| Mutex::new(BTreeMap::new()))
+}
+
+fn record_panic(class: TaskClass) {
+    *panic_counter().lock().unwrap().entry(class).or_insert(0) += 1;
+}
+
+// Panics caught in supervised tasks since startup, per task class
+pub fn task_panic_counts() -> Vec<TaskPanicCount> {
+    panic_counter()
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(task_class, count)| TaskPanicCount { task_class: *task_class, count: *count })
+        .collect()
+}
+
+// Prometheus text exposition of the counters above
+pub fn render_prometheus() -> String {
+    let mut out = String::from(
+        "# HELP task_panics_total Panics caught in supervised background tasks.\n# TYPE task_panics_total counter\n",
+    );
+    for TaskPanicCount { task_class, count } in task_panic_counts() {
+        out.push_str(&format!("task_panics_total{{class=\"{}\"}} {}\n", task_class.as_str(), count));
+    }
+    out
+}
+
+// Logs every panic with the supervised task it happened in and a backtrace. Replaces the
+// default hook, which would only print to stderr.
+pub fn install_panic_hook() {
+    static INSTALLED: Once = Once::new();
+    INSTALLED.call_once(|| {
+        std::panic::set_hook(Box::new(|info| {
+            let task = TASK_NAME.try_with(|name| name.clone()).unwrap_or_else(|_| "unsupervised".to_string());
+            let location = info.location().map(|l| l.to_string()).unwrap_or_default();
+            let backtrace = std::backtrace::Backtrace::force_capture();
+            tracing::error!(task = %task, "Panic in {} at {}: {}\n{}", task, location, info, backtrace);
+        }));
+    });
+}
+
+#[async_trait]
+pub trait PanicAlerts: Send + Sync {
+    async fn task_panicked(&self, task: &str, message: &str);
+}
+
+pub struct AdminPanicAlerts {
+    notifications: Arc<NotificationService>,
+    admin_email: String,
+}
+
+impl AdminPanicAlerts {
+    pub fn new(notifications: Arc<NotificationService>, admin_email: String) -> Self {
+        AdminPanicAlerts { notifications, admin_email }
+    }
+}
+
+#[async_trait]
+impl PanicAlerts for AdminPanicAlerts {
+    async fn task_panicked(&self, task: &str, message: &str) {
+        let alert = format!("Background task {} panicked: {}", task, message);
+        if let Err(e) = self.notifications.send_system_alert(&self.admin_email, &alert).await {
+            tracing::error!("Could not alert the admin about {}: {}", task, e);
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "non-string panic payload".to_string())
+}
+
+// Delay before restart number `restarts` (1-based): 1s, 2s, 4s, ... capped at 5 minutes
+pub fn restart_delay(restarts: u32) -> Duration {
+    let exponent = restarts.saturating_sub(1).min(16);
+    (RESTART_BASE_DELAY * 2u32.pow(exponent)).min(RESTART_MAX_DELAY)
+}
+
+// Spawns tasks that cannot vanish silently: a panic is logged, counted, and depending on the
+// task class alerts the admin or restarts the task. Aborting the returned handle still
+// cancels the task, since the panic is caught inside it rather than in a separate task.
+#[derive(Clone, Default)]
+pub struct TaskSupervisor {
+    alerts: Option<Arc<dyn PanicAlerts>>,
+}
+
+impl TaskSupervisor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Critical task classes are reported here
+    pub fn with_alerts(mut self, alerts: Arc<dyn PanicAlerts>) -> Self {
+        self.alerts = Some(alerts);
+        self
+    }
+
+    pub fn spawn_supervised<F>(&self, name: impl Into<String>, class: TaskClass, fut: F) -> JoinHandle<()>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.spawn_owned(name, class, fut, || async {})
+    }
+
+    // For tasks that stand for some resource: `on_panic` runs after a panic so the resource is
+    // not left looking alive without its task
+    pub fn spawn_owned<F, H, HF>(&self, name: impl Into<String>, class: TaskClass, fut: F, on_panic: H) -> JoinHandle<()>
+    where
+        F: Future<Output = ()> + Send + 'static,
+        H: FnOnce() -> HF + Send + 'static,
+        HF: Future<Output = ()> + Send + 'static,
+    {
+        let name = name.into();
+        let supervisor = self.clone();
+        tokio::spawn(async move {
+            if let Err(message) = run_caught(&name, fut).await {
+                supervisor.panicked(&name, class, &message).await;
+                on_panic().await;
+            }
+        })
+    }
+
+    // Runs `factory()` again after every panic, waiting longer after each consecutive one.
+    // Returning normally ends the task for good.
+    pub fn spawn_restartable<F, Fut>(&self, name: impl Into<String>, class: TaskClass, factory: F) -> JoinHandle<()>
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let name = name.into();
+        let supervisor = self.clone();
+        tokio::spawn(async move {
+            let mut restarts = 0;
+            loop {
+                let started = tokio::time::Instant::now();
+                let Err(message) = run_caught(&name, factory()).await else {
+                    return;
+                };
+                supervisor.panicked(&name, class, &message).await;
+
+                // A run that lasted longer than the longest wait was healthy; start over at 1s
+                if started.elapsed() >= RESTART_MAX_DELAY {
+                    restarts = 0;
+                }
+                restarts += 1;
+                let delay = restart_delay(restarts);
+                tracing::warn!("Restarting {} in {:?} (restart {})", name, delay, restarts);
+                tokio::time::sleep(delay).await;
+            }
+        })
+    }
+
+    async fn panicked(&self, name: &str, class: TaskClass, message: &str) {
+        record_panic(class);
+        tracing::error!(task = %name, class = class.as_str(), "Supervised task {} panicked: {}", name, message);
+        if class.is_critical() {
+            if let Some(alerts) = &self.alerts {
+                alerts.task_panicked(name, message).await;
+            }
+        }
+    }
+}
+
+async fn run_caught<F>(name: &str, fut: F) -> Result<(), String>
+where
+    F: Future<Output = ()> + Send,
+{
+    TASK_NAME
+        .scope(name.to_string(), AssertUnwindSafe(fut).catch_unwind())
+        .await
+        .map_err(|payload| panic_message(payload.as_ref()))
+}
+
+// Supervised task without alerts or restarts, for places that have no supervisor at hand
+pub fn spawn_supervised<F>(name: impl Into<String>, class: TaskClass, fut: F) -> JoinHandle<()>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    TaskSupervisor::new().spawn_supervised(name, class, fut)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct RecordedAlerts {
+        alerts: Mutex<Vec<(String, String)>>,
+    }
+
+    #[async_trait]
+    impl PanicAlerts for RecordedAlerts {
+        async fn task_panicked(&self, task: &str, message: &str) {
+            self.alerts.lock().unwrap().push((task.to_string(), message.to_string()));
+        }
+    }
+
+    fn panics(class: TaskClass) -> u64 {
+        task_panic_counts().into_iter().find(|c| c.task_class == class).map(|c| c.count).unwrap_or(0)
+    }
+
+    #[test]
+    fn test_restart_delay_doubles_up_to_five_minutes() {
+        let delays: Vec<u64> = (1..=11).map(|r| restart_delay(r).as_secs()).collect();
+        assert_eq!(delays, vec![1, 2, 4, 8, 16, 32, 64, 128, 256, 300, 300]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_restartable_task_comes_back_after_panics_with_backoff() {
+        let alerts = Arc::new(RecordedAlerts::default());
+        let supervisor = TaskSupervisor::new().with_alerts(alerts.clone());
+        let runs = Arc::new(AtomicUsize::new(0));
+        let before = panics(TaskClass::SchedulerJob);
+
+        let counter = runs.clone();
+        let handle = supervisor.spawn_restartable("job:flaky", TaskClass::SchedulerJob, move || {
+            let counter = counter.clone();
+            async move {
+                if counter.fetch_add(1, Ordering::SeqCst) < 2 {
+                    panic!("deliberate");
+                }
+                // The third run stays up
+                std::future::pending::<()>().await;
+            }
+        });
+
+        // Panics at once, restarts after 1s, panics again, restarts 2s later
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+
+        // Other tests may panic in the same class concurrently
+        assert!(panics(TaskClass::SchedulerJob) >= before + 2);
+        // Not a critical class
+        assert!(alerts.alerts.lock().unwrap().is_empty());
+
+        // Aborting still reaches the running task
+        handle.abort();
+        assert!(handle.await.unwrap_err().is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn test_critical_task_alerts_and_marks_its_owner_without_restarting() {
+        let alerts = Arc::new(RecordedAlerts::default());
+        let supervisor = TaskSupervisor::new().with_alerts(alerts.clone());
+        let robot_status = Arc::new(Mutex::new("active".to_string()));
+        let runs = Arc::new(AtomicUsize::new(0));
+        let before = panics(TaskClass::RobotRunner);
+
+        let (status, counter) = (robot_status.clone(), runs.clone());
+        supervisor
+            .spawn_owned(
+                "robot:42",
+                TaskClass::RobotRunner,
+                async move {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    let positions: Vec<u32> = Vec::new();
+                    let _ = positions[3];
+                },
+                move || async move { *status.lock().unwrap() = "error".to_string() },
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert_eq!(*robot_status.lock().unwrap(), "error");
+        assert!(panics(TaskClass::RobotRunner) > before);
+
+        let alerts = alerts.alerts.lock().unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].0, "robot:42");
+        assert!(alerts[0].1.contains("index out of bounds"), "{}", alerts[0].1);
+        assert!(render_prometheus().contains("task_panics_total{class=\"robot_runner\"}"));
+    }
+
+    #[tokio::test]
+    async fn test_task_that_finishes_normally_is_left_alone() {
+        let marked = Arc::new(AtomicUsize::new(0));
+        let hook = marked.clone();
+        TaskSupervisor::new()
+            .spawn_owned("robot:done", TaskClass::RobotRunner, async {}, move || async move {
+                hook.fetch_add(1, Ordering::SeqCst);
+            })
+            .await
+            .unwrap();
+        assert_eq!(marked.load(Ordering::SeqCst), 0);
+    }
+}
@@ -0,0 +1,212 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use redis::AsyncCommands;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+use crate::errors::{AppError, Result};
+use crate::services::auth_service::Claims;
+
+// Signed-out tokens and per-user token versions. A token is rejected once its jti is denylisted,
+// or once its `ver` is below the user's current version.
+#[async_trait]
+pub trait TokenRevocations: Send + Sync {
+    // Denylists `jti` for `ttl_seconds`, after which the token has expired anyway
+    async fn revoke(&self, jti: &str, ttl_seconds: u64) -> Result<()>;
+    // Whether `jti` is denylisted, and the user's current token version
+    async fn lookup(&self, jti: &str, user_id: Uuid) -> Result<(bool, u32)>;
+    // The version new tokens of the user are issued with
+    async fn version(&self, user_id: Uuid) -> Result<u32>;
+    // Moves the user to the next version and returns it
+    async fn bump_version(&self, user_id: Uuid) -> Result<u32>;
+}
+
+pub async fn check(revocations: &dyn TokenRevocations, claims: &Claims, user_id: Uuid) -> Result<()> {
+    let (revoked, version) = revocations.lookup(&claims.jti, user_id).await?;
+    if revoked || claims.ver < version {
+        return Err(AppError::Auth("Token has been revoked".to_string()));
+    }
+    Ok(())
+}
+
+// The version a bump moves to. It is at least the current time in seconds, so it stays above every
+// version handed out before, even when the stored one has expired and reads as 0 again.
+pub fn next_version(current: Option<u32>, now: DateTime<Utc>) -> u32 {
+    let clock = u32::try_from(now.timestamp()).unwrap_or(u32::MAX);
+    current.map_or(clock, |version| version.saturating_add(1).max(clock))
+}
+
+// Single-instance revocations; they are lost when the process restarts
+#[derive(Default)]
+pub struct MemoryTokenRevocations {
+    revoked: Mutex<HashMap<String, DateTime<Utc>>>,
+    versions: Mutex<HashMap<Uuid, u32>>,
+}
+
+impl MemoryTokenRevocations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl TokenRevocations for MemoryTokenRevocations {
+    async fn revoke(&self, jti: &str, ttl_seconds: u64) -> Result<()> {
+        let now = Utc::now();
+        let mut revoked = self.revoked.lock().unwrap();
+        revoked.retain(|_, until| *until > now);
+        revoked.insert(jti.to_string(), now + Duration::seconds(ttl_seconds as i64));
+        Ok(())
+    }
+
+    async fn lookup(&self, jti: &str, user_id: Uuid) -> Result<(bool, u32)> {
+        let revoked = self.revoked.lock().unwrap().get(jti).is_some_and(|until| *until > Utc::now());
+        Ok((revoked, self.version(user_id).await?))
+    }
+
+    async fn version(&self, user_id: Uuid) -> Result<u32> {
+        Ok(self.versions.lock().unwrap().get(&user_id).copied().unwrap_or(0))
+    }
+
+    async fn bump_version(&self, user_id: Uuid) -> Result<u32> {
+        let mut versions = self.versions.lock().unwrap();
+        let version = next_version(versions.get(&user_id).copied(), Utc::now());
+        versions.insert(user_id, version);
+        Ok(version)
+    }
+}
+
+// Shared across instances. The version key expires one token lifetime after its last bump; the
+// next bump starts from the clock (see `next_version`), so tokens issued meanwhile are still caught.
+pub struct RedisTokenRevocations {
+    client: redis::Client,
+    token_lifetime: Duration,
+}
+
+impl RedisTokenRevocations {
+    pub fn new(redis_url: &str, token_lifetime: Duration) -> Result<Self> {
+        let client = redis::Client::open(redis_url)?;
+        Ok(RedisTokenRevocations { client, token_lifetime })
+    }
+
+    fn revoked_key(jti: &str) -> String {
+        format!("revoked_token:{}", jti)
+    }
+
+    fn version_key(user_id: Uuid) -> String {
+        format!("token_version:{}", user_id)
+    }
+}
+
+#[async_trait]
+impl TokenRevocations for RedisTokenRevocations {
+    async fn revoke(&self, jti: &str, ttl_seconds: u64) -> Result<()> {
+        if ttl_seconds == 0 {
+            return Ok(());
+        }
+        let mut conn = self.client.get_multiplexed_tokio_connection().await?;
+        redis::cmd("SET")
+            .arg(Self::revoked_key(jti))
+            .arg(1)
+            .arg("EX")
+            .arg(ttl_seconds)
+            .query_async::<_, ()>(&mut conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn lookup(&self, jti: &str, user_id: Uuid) -> Result<(bool, u32)> {
+        let mut conn = self.client.get_multiplexed_tokio_connection().await?;
+        let (revoked, version): (bool, Option<u32>) = redis::pipe()
+            .exists(Self::revoked_key(jti))
+            .get(Self::version_key(user_id))
+            .query_async(&mut conn)
+            .await?;
+        Ok((revoked, version.unwrap_or(0)))
+    }
+
+    async fn version(&self, user_id: Uuid) -> Result<u32> {
+        let mut conn = self.client.get_multiplexed_tokio_connection().await?;
+        let version: Option<u32> = conn.get(Self::version_key(user_id)).await?;
+        Ok(version.unwrap_or(0))
+    }
+
+    async fn bump_version(&self, user_id: Uuid) -> Result<u32> {
+        // `next_version` in one script, so concurrent bumps cannot land on the same version
+        let script = redis::Script::new(
+            r"
+            local current = redis.call('GET', KEYS[1])
+            local version = tonumber(ARGV[1])
+            if current then
+                version = math.max(tonumber(current) + 1, version)
+            end
+            redis.call('SET', KEYS[1], version, 'EX', ARGV[2])
+            return version
+            ",
+        );
+        let clock = u32::try_from(Utc::now().timestamp()).unwrap_or(u32::MAX);
+        let mut conn = self.client.get_multiplexed_tokio_connection().await?;
+        let version: u32 = script
+            .key(Self::version_key(user_id))
+            .arg(clock)
+            .arg(self.token_lifetime.num_seconds())
+            .invoke_async(&mut conn)
+            .await?;
+        Ok(version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn claims(jti: &str, ver: u32) -> Claims {
+        Claims { sub: String::new(), exp: 0, iat: 0, iss: String::new(), aud: String::new(), jti: jti.to_string(), ver, impersonator: None }
+    }
+
+    #[tokio::test]
+    async fn test_revoked_token_is_rejected_and_others_are_not() {
+        let revocations = MemoryTokenRevocations::new();
+        let user_id = Uuid::new_v4();
+        revocations.revoke("a", 60).await.unwrap();
+
+        assert!(check(&revocations, &claims("a", 0), user_id).await.is_err());
+        assert!(check(&revocations, &claims("b", 0), user_id).await.is_ok());
+        // A revocation that has run out no longer applies
+        revocations.revoke("c", 0).await.unwrap();
+        assert!(check(&revocations, &claims("c", 0), user_id).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_bumping_the_version_rejects_earlier_tokens_of_that_user_only() {
+        let revocations = MemoryTokenRevocations::new();
+        let (user_id, other_user) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let version = revocations.bump_version(user_id).await.unwrap();
+
+        assert!(check(&revocations, &claims("a", 0), user_id).await.is_err());
+        assert!(check(&revocations, &claims("b", version), user_id).await.is_ok());
+        assert!(check(&revocations, &claims("c", 0), other_user).await.is_ok());
+        assert_eq!(revocations.version(user_id).await.unwrap(), version);
+        assert!(revocations.bump_version(user_id).await.unwrap() > version);
+    }
+
+    #[tokio::test]
+    async fn test_a_bump_after_the_version_expired_still_rejects_tokens_issued_before_it() {
+        let bumped_at = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let first = next_version(None, bumped_at);
+        // A token issued right after the first bump carries `first`; a token lifetime later the
+        // stored version expires and the next bump starts again from nothing
+        let second = next_version(None, bumped_at + Duration::hours(24));
+        let revocations = MemoryTokenRevocations::new();
+        let user_id = Uuid::new_v4();
+        revocations.versions.lock().unwrap().insert(user_id, second);
+
+        assert!(check(&revocations, &claims("a", first), user_id).await.is_err());
+        assert!(check(&revocations, &claims("b", second), user_id).await.is_ok());
+        // Bumps within the same second still move on
+        assert_eq!(next_version(Some(first), bumped_at), first + 1);
+    }
+}
@@ -0,0 +1,358 @@
+use async_trait::async_trait;
+use futures_util::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use sqlx::PgPool;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{
+    errors::{AppError, Result},
+    models::{Trade, TradingRobot, TradingSession},
+    services::{
+        event_bus::{DomainEvent, EventPublisher},
+        Mt5Service,
+    },
+};
+
+pub const MAX_BATCH_CLOSE: usize = 50;
+const CLOSE_CONCURRENCY: usize = 5;
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct CloseBatchRequest {
+    pub trade_ids: Vec<Uuid>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum TradeCloseOutcome {
+    Closed {
+        #[serde(serialize_with = "crate::money::serialize_price")]
+        exit_price: f64,
+        #[serde(serialize_with = "crate::money::serialize_amount")]
+        profit_loss: f64,
+    },
+    Skipped { reason: String },
+    Failed { error: String },
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct TradeCloseResult {
+    pub trade_id: Uuid,
+    #[serde(flatten)]
+    pub outcome: TradeCloseOutcome,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct CloseBatchResponse {
+    pub results: Vec<TradeCloseResult>,
+    pub closed: usize,
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+// Closes a position at the broker and reports the exit price
+#[async_trait]
+pub trait PositionCloser: Send + Sync {
+    async fn close_position(&self, trade: &Trade) -> Result<f64>;
+}
+
+// Persists closes and the per-robot rollups that follow them
+#[async_trait]
+pub trait ClosedTradeStore: Send + Sync {
+    async fn mark_closed(&self, trade: &Trade, exit_price: f64, profit_loss: f64) -> Result<bool>;
+    async fn refresh_robot(&self, robot_id: Uuid) -> Result<()>;
+}
+
+pub struct Mt5PositionCloser {
+    mt5: Arc<Mt5Service>,
+    connection_id: String,
+}
+
+impl Mt5PositionCloser {
+    pub fn new(mt5: Arc<Mt5Service>, connection_id: String) -> Self {
+        Mt5PositionCloser { mt5, connection_id }
+    }
+}
+
+#[async_trait]
+impl PositionCloser for Mt5PositionCloser {
+    async fn close_position(&self, trade: &Trade) -> Result<f64> {
+        let quote = self.mt5.get_market_data(&self.connection_id, &trade.symbol).await?;
+
+        // Trades opened without a broker ticket only exist locally
+        if let Some(ticket) = trade.broker_trade_id.as_deref().and_then(|t| t.parse::<i64>().ok()) {
+            self.mt5.close_position(&self.connection_id, ticket).await?;
+        }
+
+        // A long position is closed by selling at the bid, a short one by buying at the ask
+        Ok(if trade.trade_type == "sell" { quote.ask } else { quote.bid })
+    }
+}
+
+pub struct PgClosedTradeStore {
+    pool: PgPool,
+}
+
+impl PgClosedTradeStore {
+    pub fn new(pool: PgPool) -> Self {
+        PgClosedTradeStore { pool }
+    }
+}
+
+#[async_trait]
+impl ClosedTradeStore for PgClosedTradeStore {
+    async fn mark_closed(&self, trade: &Trade, exit_price: f64, profit_loss: f64) -> Result<bool> {
+        Ok(Trade::close_open_trade(&self.pool, trade.id, trade.user_id, exit_price, profit_loss).await?)
+    }
+
+    async fn refresh_robot(&self, robot_id: Uuid) -> Result<()> {
+        TradingRobot::refresh_performance(&self.pool, robot_id).await?;
+        TradingSession::refresh_active_for_robot(&self.pool, robot_id).await?;
+        Ok(())
+    }
+}
+
+pub struct TradeCloseService;
+
+impl TradeCloseService {
+    // Drops duplicates while keeping the caller's order
+    pub fn normalize_ids(ids: &[Uuid]) -> Result<Vec<Uuid>> {
+        let mut seen = HashSet::new();
+        let unique: Vec<Uuid> = ids.iter().copied().filter(|id| seen.insert(*id)).collect();
+
+        if unique.is_empty() {
+            return Err(AppError::Validation("At least one trade id is required".to_string()));
+        }
+        if unique.len() > MAX_BATCH_CLOSE {
+            return Err(AppError::Validation(format!(
+                "At most {} trades can be closed at once",
+                MAX_BATCH_CLOSE
+            )));
+        }
+        Ok(unique)
+    }
+
+    // `owned` holds the requested trades that belong to the caller; anything else is reported
+    // as not found. Individual failures never abort the batch. Each closed trade is published
+    // as TradeClosed once stored.
+    pub async fn close_batch(
+        ids: &[Uuid],
+        owned: Vec<Trade>,
+        closer: &dyn PositionCloser,
+        store: &dyn ClosedTradeStore,
+        events: &dyn EventPublisher,
+    ) -> Vec<TradeCloseResult> {
+        let mut by_id: HashMap<Uuid, Trade> = owned.into_iter().map(|t| (t.id, t)).collect();
+        let mut outcomes: HashMap<Uuid, TradeCloseOutcome> = HashMap::new();
+        let mut candidates = Vec::new();
+
+        for id in ids {
+            match by_id.remove(id) {
+                None => {
+                    outcomes.insert(*id, TradeCloseOutcome::Failed { error: "Trade not found".to_string() });
+                }
+                Some(trade) if trade.status != "open" => {
+                    outcomes.insert(*id, TradeCloseOutcome::Skipped { reason: format!("Trade is {}", trade.status) });
+                }
+                Some(trade) => candidates.push(trade),
+            }
+        }
+
+        let closed_trades: Vec<(Trade, TradeCloseOutcome)> = stream::iter(candidates)
+            .map(|trade| async move {
+                let outcome = match closer.close_position(&trade).await {
+                    Ok(exit_price) => {
+                        let profit_loss = trade.calculate_profit_loss(exit_price);
+                        match store.mark_closed(&trade, exit_price, profit_loss).await {
+                            Ok(true) => TradeCloseOutcome::Closed { exit_price, profit_loss },
+                            Ok(false) => TradeCloseOutcome::Skipped { reason: "Trade was closed concurrently".to_string() },
+                            Err(e) => TradeCloseOutcome::Failed { error: e.to_string() },
+                        }
+                    }
+                    Err(e) => TradeCloseOutcome::Failed { error: e.to_string() },
+                };
+                (trade, outcome)
+            })
+            .buffer_unordered(CLOSE_CONCURRENCY)
+            .collect()
+            .await;
+
+        let mut closed = Vec::new();
+        for (trade, outcome) in closed_trades {
+            if let TradeCloseOutcome::Closed { exit_price, profit_loss } = &outcome {
+                closed.push(Trade {
+                    exit_price: Some(*exit_price),
+                    profit_loss: Some(*profit_loss),
+                    status: "closed".to_string(),
+                    ..trade.clone()
+                });
+            }
+            outcomes.insert(trade.id, outcome);
+        }
+
+        // One rollup per affected robot instead of one per closed trade
+        let robots: HashSet<Uuid> = closed.iter().map(|t| t.robot_id).collect();
+        for robot_id in robots {
+            if let Err(e) = store.refresh_robot(robot_id).await {
+                tracing::warn!("Failed to refresh rollups for robot {}: {}", robot_id, e);
+            }
+        }
+
+        for trade in closed {
+            events.publish(DomainEvent::TradeClosed { trade: Box::new(trade) });
+        }
+
+        ids.iter()
+            .map(|id| TradeCloseResult {
+                trade_id: *id,
+                outcome: outcomes.remove(id).expect("every requested id has an outcome"),
+            })
+            .collect()
+    }
+
+    pub fn summarize(results: Vec<TradeCloseResult>) -> CloseBatchResponse {
+        let count = |f: fn(&TradeCloseOutcome) -> bool| results.iter().filter(|r| f(&r.outcome)).count();
+        CloseBatchResponse {
+            closed: count(|o| matches!(o, TradeCloseOutcome::Closed { .. })),
+            skipped: count(|o| matches!(o, TradeCloseOutcome::Skipped { .. })),
+            failed: count(|o| matches!(o, TradeCloseOutcome::Failed { .. })),
+            results,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    struct FixedPriceCloser {
+        fail_symbol: &'static str,
+    }
+
+    #[async_trait]
+    impl PositionCloser for FixedPriceCloser {
+        async fn close_position(&self, trade: &Trade) -> Result<f64> {
+            if trade.symbol == self.fail_symbol {
+                return Err(AppError::Mt5("Market closed".to_string()));
+            }
+            Ok(1.2000)
+        }
+    }
+
+    #[derive(Default)]
+    struct RecordingStore {
+        closed: Mutex<Vec<Uuid>>,
+        refreshed: Mutex<Vec<Uuid>>,
+    }
+
+    #[async_trait]
+    impl ClosedTradeStore for RecordingStore {
+        async fn mark_closed(&self, trade: &Trade, _exit_price: f64, _profit_loss: f64) -> Result<bool> {
+            self.closed.lock().unwrap().push(trade.id);
+            Ok(true)
+        }
+
+        async fn refresh_robot(&self, robot_id: Uuid) -> Result<()> {
+            self.refreshed.lock().unwrap().push(robot_id);
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct RecordingPublisher {
+        events: Mutex<Vec<DomainEvent>>,
+    }
+
+    impl EventPublisher for RecordingPublisher {
+        fn publish(&self, event: DomainEvent) {
+            self.events.lock().unwrap().push(event);
+        }
+    }
+
+    fn trade(user_id: Uuid, robot_id: Uuid, symbol: &str, status: &str) -> Trade {
+        Trade {
+            status: status.to_string(),
+            ..Trade::new(user_id, robot_id, symbol.to_string(), "buy".to_string(), 1.0, 1.1000, None, None, None, None)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mixed_batch_reports_per_trade_outcomes() {
+        let user_id = Uuid::new_v4();
+        let robot_a = Uuid::new_v4();
+        let robot_b = Uuid::new_v4();
+
+        let open_a1 = trade(user_id, robot_a, "EURUSD", "open");
+        let open_a2 = trade(user_id, robot_a, "GBPUSD", "open");
+        let open_b = trade(user_id, robot_b, "EURUSD", "open");
+        let already_closed = trade(user_id, robot_b, "EURUSD", "closed");
+        let broker_fails = trade(user_id, robot_b, "XAUUSD", "open");
+        // Belongs to someone else, so the ownership-scoped lookup never returns it
+        let not_owned = Uuid::new_v4();
+
+        let ids = vec![open_a1.id, already_closed.id, not_owned, open_a2.id, broker_fails.id, open_b.id];
+        let owned = vec![open_a1.clone(), open_a2.clone(), open_b.clone(), already_closed.clone(), broker_fails.clone()];
+
+        let store = RecordingStore::default();
+        let events = RecordingPublisher::default();
+        let results = TradeCloseService::close_batch(
+            &ids,
+            owned,
+            &FixedPriceCloser { fail_symbol: "XAUUSD" },
+            &store,
+            &events,
+        )
+        .await;
+
+        let outcome_of = |id: Uuid| results.iter().find(|r| r.trade_id == id).unwrap().outcome.clone();
+        assert_eq!(results.iter().map(|r| r.trade_id).collect::<Vec<_>>(), ids);
+        assert!(matches!(outcome_of(open_a1.id), TradeCloseOutcome::Closed { exit_price, .. } if exit_price == 1.2000));
+        assert!(matches!(outcome_of(open_a2.id), TradeCloseOutcome::Closed { .. }));
+        assert!(matches!(outcome_of(open_b.id), TradeCloseOutcome::Closed { .. }));
+        assert_eq!(outcome_of(already_closed.id), TradeCloseOutcome::Skipped { reason: "Trade is closed".to_string() });
+        assert!(matches!(outcome_of(not_owned), TradeCloseOutcome::Failed { .. }));
+        assert!(matches!(outcome_of(broker_fails.id), TradeCloseOutcome::Failed { .. }));
+
+        // Only the closed trades are announced, carrying their exit price and result
+        let mut published: Vec<Uuid> = events
+            .events
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|event| match event {
+                DomainEvent::TradeClosed { trade } => {
+                    assert_eq!((trade.status.as_str(), trade.exit_price), ("closed", Some(1.2000)));
+                    trade.id
+                }
+                other => panic!("unexpected event {}", other.name()),
+            })
+            .collect();
+        published.sort();
+        let mut expected_closed = vec![open_a1.id, open_a2.id, open_b.id];
+        expected_closed.sort();
+        assert_eq!(published, expected_closed);
+
+        // Three trades closed across two robots: exactly one rollup each
+        let mut refreshed = store.refreshed.lock().unwrap().clone();
+        refreshed.sort();
+        let mut expected = vec![robot_a, robot_b];
+        expected.sort();
+        assert_eq!(refreshed, expected);
+
+        let summary = TradeCloseService::summarize(results);
+        assert_eq!((summary.closed, summary.skipped, summary.failed), (3, 1, 2));
+    }
+
+    #[test]
+    fn test_batch_size_is_bounded_and_deduplicated() {
+        let id = Uuid::new_v4();
+        assert_eq!(TradeCloseService::normalize_ids(&[id, id]).unwrap(), vec![id]);
+        assert!(TradeCloseService::normalize_ids(&[]).is_err());
+
+        let too_many: Vec<Uuid> = (0..=MAX_BATCH_CLOSE).map(|_| Uuid::new_v4()).collect();
+        assert!(TradeCloseService::normalize_ids(&too_many).is_err());
+    }
+}
@@ -0,0 +1,34 @@
+use sqlx::PgPool;
+
+use crate::{errors::Result, models::TradeDailyFact};
+
+// Users whose facts are rebuilt per transaction
+pub const BACKFILL_BATCH_USERS: i64 = 100;
+pub const BACKFILL_RETRY_SECONDS: u64 = 600;
+
+pub struct TradeFactsBackfill;
+
+impl TradeFactsBackfill {
+    // Fills trade_daily_facts from the trades closed before it existed. Progress is saved after
+    // every batch, so a restart carries on where the last run stopped; once complete it is a no-op.
+    pub async fn run(pool: &PgPool) -> Result<usize> {
+        let mut progress = TradeDailyFact::backfill_progress(pool).await?;
+        if progress.completed_at.is_some() {
+            return Ok(0);
+        }
+
+        let mut users = 0;
+        loop {
+            let batch = TradeDailyFact::users_after(pool, progress.last_user_id, BACKFILL_BATCH_USERS).await?;
+            if batch.is_empty() {
+                TradeDailyFact::save_backfill_progress(pool, progress.last_user_id, true).await?;
+                tracing::info!("Trade facts backfill complete: {} user(s) rebuilt", users);
+                return Ok(users);
+            }
+            TradeDailyFact::rebuild_users(pool, &batch).await?;
+            users += batch.len();
+            progress.last_user_id = batch.last().copied();
+            TradeDailyFact::save_backfill_progress(pool, progress.last_user_id, false).await?;
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{
+    errors::{AppError, Result},
+    models::{SubmitTradeReviewRequest, Trade, TradeReview, EMOTION_TAGS, REVIEW_EDIT_DAYS},
+};
+
+#[async_trait]
+pub trait TradeJournalStore: Send + Sync {
+    // Returns whether a new prompt was created
+    async fn create_prompt(&self, trade_id: Uuid, user_id: Uuid) -> Result<bool>;
+    async fn find(&self, user_id: Uuid, trade_id: Uuid) -> Result<Option<TradeReview>>;
+    // The user's trade, if there is one with this id
+    async fn trade(&self, user_id: Uuid, trade_id: Uuid) -> Result<Option<Trade>>;
+    async fn save(&self, review: &TradeReview) -> Result<()>;
+}
+
+pub struct PgTradeJournalStore {
+    pool: PgPool,
+}
+
+impl PgTradeJournalStore {
+    pub fn new(pool: PgPool) -> Self {
+        PgTradeJournalStore { pool }
+    }
+}
+
+#[async_trait]
+impl TradeJournalStore for PgTradeJournalStore {
+    async fn create_prompt(&self, trade_id: Uuid, user_id: Uuid) -> Result<bool> {
+        TradeReview::create_prompt(&self.pool, trade_id, user_id).await
+    }
+
+    async fn find(&self, user_id: Uuid, trade_id: Uuid) -> Result<Option<TradeReview>> {
+        TradeReview::find_by_trade_id(&self.pool, user_id, trade_id).await
+    }
+
+    async fn trade(&self, user_id: Uuid, trade_id: Uuid) -> Result<Option<Trade>> {
+        Trade::find_by_id(&self.pool, trade_id, user_id).await
+    }
+
+    async fn save(&self, review: &TradeReview) -> Result<()> {
+        TradeReview::save(&self.pool, review).await
+    }
+}
+
+pub struct TradeJournal;
+
+impl TradeJournal {
+    // Asks for a journal entry once a trade has closed. Returns whether a new prompt was created.
+    pub async fn prompt(store: &dyn TradeJournalStore, trade: &Trade) -> Result<bool> {
+        if trade.status != "closed" {
+            return Ok(false);
+        }
+        store.create_prompt(trade.id, trade.user_id).await
+    }
+
+    // Known tags, deduplicated and in EMOTION_TAGS order
+    pub fn emotion_tags(tags: &[String]) -> Result<Vec<String>> {
+        let tags: Vec<String> = tags.iter().map(|t| t.trim().to_lowercase()).collect();
+        if let Some(unknown) = tags.iter().find(|t| !EMOTION_TAGS.contains(&t.as_str())) {
+            return Err(AppError::Validation(format!(
+                "Unknown emotion tag '{}', expected one of {}",
+                unknown,
+                EMOTION_TAGS.join(", ")
+            )));
+        }
+        Ok(EMOTION_TAGS.iter().filter(|t| tags.iter().any(|tag| tag == *t)).map(|t| t.to_string()).collect())
+    }
+
+    // Creates or edits the trade's journal entry. Closed trades without a prompt (e.g. closed
+    // before journaling existed) can be reviewed too. Entries lock REVIEW_EDIT_DAYS after
+    // their first submission.
+    pub async fn submit(
+        store: &dyn TradeJournalStore,
+        user_id: Uuid,
+        trade_id: Uuid,
+        request: SubmitTradeReviewRequest,
+        now: DateTime<Utc>,
+    ) -> Result<TradeReview> {
+        request.validate().map_err(|e| AppError::Validation(e.to_string()))?;
+        let emotion_tags = Self::emotion_tags(&request.emotion_tags)?;
+
+        let review = match store.find(user_id, trade_id).await? {
+            Some(review) => review,
+            None => {
+                let trade = store
+                    .trade(user_id, trade_id)
+                    .await?
+                    .ok_or_else(|| AppError::NotFound("Trade not found".to_string()))?;
+                if trade.status != "closed" {
+                    return Err(AppError::Validation("Only closed trades can be reviewed".to_string()));
+                }
+                TradeReview::pending(trade_id, user_id, now)
+            }
+        };
+        if review.is_locked(now) {
+            return Err(AppError::Forbidden(format!(
+                "Journal entries can only be edited for {} days after they are submitted",
+                REVIEW_EDIT_DAYS
+            )));
+        }
+
+        let review = TradeReview {
+            followed_plan: Some(request.followed_plan),
+            execution_rating: request.execution_rating,
+            emotion_tags,
+            notes: request.notes.map(|n| n.trim().to_string()).filter(|n| !n.is_empty()),
+            reviewed_at: review.reviewed_at.or(Some(now)),
+            updated_at: now,
+            ..review
+        };
+        store.save(&review).await?;
+        Ok(review)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct MemoryStore {
+        trades: Vec<Trade>,
+        reviews: Mutex<HashMap<Uuid, TradeReview>>,
+    }
+
+    #[async_trait]
+    impl TradeJournalStore for MemoryStore {
+        async fn create_prompt(&self, trade_id: Uuid, user_id: Uuid) -> Result<bool> {
+            let mut reviews = self.reviews.lock().unwrap();
+            if reviews.contains_key(&trade_id) {
+                return Ok(false);
+            }
+            reviews.insert(trade_id, TradeReview::pending(trade_id, user_id, Utc::now()));
+            Ok(true)
+        }
+
+        async fn find(&self, user_id: Uuid, trade_id: Uuid) -> Result<Option<TradeReview>> {
+            Ok(self.reviews.lock().unwrap().get(&trade_id).filter(|r| r.user_id == user_id).cloned())
+        }
+
+        async fn trade(&self, user_id: Uuid, trade_id: Uuid) -> Result<Option<Trade>> {
+            Ok(self.trades.iter().find(|t| t.id == trade_id && t.user_id == user_id).cloned())
+        }
+
+        async fn save(&self, review: &TradeReview) -> Result<()> {
+            self.reviews.lock().unwrap().insert(review.trade_id, review.clone());
+            Ok(())
+        }
+    }
+
+    fn trade(status: &str) -> Trade {
+        Trade {
+            status: status.to_string(),
+            ..Trade::new(Uuid::new_v4(), Uuid::new_v4(), "EURUSD".to_string(), "buy".to_string(), 1.0, 1.1000, None, None, None, None)
+        }
+    }
+
+    fn request(followed_plan: bool, tags: &[&str]) -> SubmitTradeReviewRequest {
+        SubmitTradeReviewRequest {
+            followed_plan,
+            execution_rating: Some(4),
+            emotion_tags: tags.iter().map(|t| t.to_string()).collect(),
+            notes: Some("Entered early ".to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_closing_a_trade_creates_one_pending_prompt() {
+        let store = MemoryStore::default();
+        let closed = trade("closed");
+
+        assert!(TradeJournal::prompt(&store, &closed).await.unwrap());
+        // A redelivered close event does not prompt twice
+        assert!(!TradeJournal::prompt(&store, &closed).await.unwrap());
+        assert!(!TradeJournal::prompt(&store, &trade("open")).await.unwrap());
+
+        let reviews = store.reviews.lock().unwrap();
+        assert_eq!(reviews.len(), 1);
+        let prompt = &reviews[&closed.id];
+        assert_eq!((prompt.user_id, prompt.reviewed_at), (closed.user_id, None));
+    }
+
+    #[tokio::test]
+    async fn test_review_is_editable_for_seven_days_then_locked() {
+        let closed = trade("closed");
+        let store = MemoryStore { trades: vec![closed.clone()], ..Default::default() };
+        let submitted = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+
+        let review = TradeJournal::submit(&store, closed.user_id, closed.id, request(true, &["FOMO", "calm", "fomo"]), submitted)
+            .await
+            .unwrap();
+        assert_eq!(review.emotion_tags, vec!["calm", "fomo"]);
+        assert_eq!(review.notes.as_deref(), Some("Entered early"));
+        assert_eq!(review.reviewed_at, Some(submitted));
+
+        // Edits keep the first submission time, so they do not extend the window
+        let edited = submitted + Duration::days(6);
+        let review = TradeJournal::submit(&store, closed.user_id, closed.id, request(false, &[]), edited).await.unwrap();
+        assert_eq!((review.followed_plan, review.reviewed_at, review.updated_at), (Some(false), Some(submitted), edited));
+
+        let locked = submitted + Duration::days(REVIEW_EDIT_DAYS);
+        let error = TradeJournal::submit(&store, closed.user_id, closed.id, request(true, &[]), locked).await.unwrap_err();
+        assert!(matches!(error, AppError::Forbidden(_)));
+        assert_eq!(store.reviews.lock().unwrap()[&closed.id].followed_plan, Some(false));
+    }
+
+    #[tokio::test]
+    async fn test_only_closed_trades_with_known_tags_are_reviewed() {
+        let open = trade("open");
+        let closed = trade("closed");
+        let store = MemoryStore { trades: vec![open.clone(), closed.clone()], ..Default::default() };
+        let now = Utc::now();
+
+        let error = TradeJournal::submit(&store, open.user_id, open.id, request(true, &[]), now).await.unwrap_err();
+        assert!(matches!(error, AppError::Validation(_)));
+        let error = TradeJournal::submit(&store, closed.user_id, closed.id, request(true, &["angry"]), now).await.unwrap_err();
+        assert!(matches!(error, AppError::Validation(_)));
+        let error = TradeJournal::submit(&store, Uuid::new_v4(), closed.id, request(true, &[]), now).await.unwrap_err();
+        assert!(matches!(error, AppError::NotFound(_)));
+        assert!(store.reviews.lock().unwrap().is_empty());
+    }
+}
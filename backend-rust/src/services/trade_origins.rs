@@ -0,0 +1,127 @@
+use axum::http::HeaderMap;
+use serde_json::{json, Map, Value};
+use std::net::IpAddr;
+
+use crate::services::bridge_events::BRIDGE_TOKEN_HEADER;
+
+// Bodies above this are stored as a truncated prefix
+pub const MAX_ORIGIN_PAYLOAD_BYTES: usize = 16 * 1024;
+pub const DEFAULT_ORIGIN_RETENTION_DAYS: i64 = 90;
+
+// Credentials and session material, never stored
+const SENSITIVE_HEADERS: &[&str] = &[
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+    "x-api-key",
+    "x-webhook-secret",
+    "stripe-signature",
+    BRIDGE_TOKEN_HEADER,
+];
+
+// The inbound request as it will be stored: its headers minus credentials, and its body as
+// received when it fits under `max_bytes`
+pub fn capture(headers: &HeaderMap, body: &Value, max_bytes: usize) -> Value {
+    let mut kept = Map::new();
+    for (name, value) in headers {
+        if SENSITIVE_HEADERS.iter().any(|sensitive| name.as_str().eq_ignore_ascii_case(sensitive)) {
+            continue;
+        }
+        if let Ok(value) = value.to_str() {
+            kept.insert(name.as_str().to_string(), json!(value));
+        }
+    }
+
+    let raw = body.to_string();
+    let body = if raw.len() <= max_bytes {
+        body.clone()
+    } else {
+        let mut end = max_bytes;
+        while !raw.is_char_boundary(end) {
+            end -= 1;
+        }
+        json!({ "truncated": true, "bytes": raw.len(), "prefix": &raw[..end] })
+    };
+    json!({ "headers": kept, "body": body })
+}
+
+// The client address as reported by the proxies in front of the server. Each proxy appends the
+// peer it saw to X-Forwarded-For, so entries are read from the right: our own proxies sit on
+// private addresses and are skipped, and the first address past them is the client. Anything
+// further left was sent by the client itself and can change on every request.
+pub fn source_ip(headers: &HeaderMap) -> Option<String> {
+    let forwarded = headers.get("x-forwarded-for").and_then(|v| v.to_str().ok()).and_then(|v| {
+        let hops: Vec<&str> = v.split(',').map(str::trim).filter(|hop| !hop.is_empty()).collect();
+        hops.iter().rev().find(|hop| !is_internal_hop(hop)).or(hops.first()).copied()
+    });
+    forwarded
+        .or_else(|| headers.get("x-real-ip").and_then(|v| v.to_str().ok()))
+        .map(str::trim)
+        .filter(|ip| !ip.is_empty())
+        .map(String::from)
+}
+
+// A proxy of ours: loopback, private or link-local
+fn is_internal_hop(hop: &str) -> bool {
+    match hop.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => ip.is_loopback() || ip.is_private() || ip.is_link_local(),
+        Ok(IpAddr::V6(ip)) => ip.is_loopback() || (ip.segments()[0] & 0xfe00) == 0xfc00,
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_capture_strips_credentials_and_keeps_the_body() {
+        let headers = headers(&[
+            ("authorization", "Bearer secret"),
+            ("cookie", "session=abc"),
+            ("x-bridge-token", "brt_123"),
+            ("content-type", "application/json"),
+            ("user-agent", "TradingView/1.0"),
+        ]);
+        let body = json!({ "ticker": "EURUSD", "action": "buy" });
+
+        let captured = capture(&headers, &body, MAX_ORIGIN_PAYLOAD_BYTES);
+        assert_eq!(captured["body"], body);
+        assert_eq!(
+            captured["headers"],
+            json!({ "content-type": "application/json", "user-agent": "TradingView/1.0" })
+        );
+    }
+
+    #[test]
+    fn test_capture_truncates_large_bodies() {
+        let body = json!({ "note": "é".repeat(100) });
+        let captured = capture(&HeaderMap::new(), &body, 50);
+        assert_eq!(captured["body"]["truncated"], true);
+        assert_eq!(captured["body"]["bytes"], body.to_string().len());
+        // Cut back to a character boundary
+        assert_eq!(captured["body"]["prefix"].as_str().unwrap().len(), 49);
+    }
+
+    #[test]
+    fn test_source_ip_takes_the_address_our_proxies_saw() {
+        let ip = |forwarded: &str| source_ip(&headers(&[("x-forwarded-for", forwarded)]));
+        assert_eq!(ip("203.0.113.7, 10.0.0.1"), Some("203.0.113.7".to_string()));
+        // The client wrote the entries left of it, so changing them changes nothing
+        assert_eq!(ip("1.2.3.4, 203.0.113.7, 10.0.0.1"), Some("203.0.113.7".to_string()));
+        assert_eq!(ip("10.9.9.9, 203.0.113.7"), Some("203.0.113.7".to_string()));
+        assert_eq!(ip("192.168.1.5, 127.0.0.1"), Some("192.168.1.5".to_string()));
+        assert_eq!(source_ip(&headers(&[("x-real-ip", "198.51.100.2")])), Some("198.51.100.2".to_string()));
+        assert_eq!(source_ip(&HeaderMap::new()), None);
+    }
+}
@@ -0,0 +1,138 @@
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use schemars::JsonSchema;
+use serde::Serialize;
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::{
+    errors::{AppError, Result},
+    models::{Trade, TradeCursor, TradeResponse},
+};
+
+pub const DEFAULT_PAGE_SIZE: i64 = 50;
+pub const MAX_PAGE_SIZE: i64 = 100;
+// Keeps cursor signatures apart from anything else signed with the same secret
+const CURSOR_CONTEXT: &[u8] = b"trade-cursor:";
+// Microseconds since the epoch and the trade id
+const CURSOR_PAYLOAD_BYTES: usize = 8 + 16;
+const CURSOR_SIGNATURE_BYTES: usize = 16;
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct TradePage {
+    pub trades: Vec<TradeResponse>,
+    // Pass as `after` for the next page; null on the last one
+    pub after: Option<String>,
+}
+
+// Keyset pages over a user's trades. Cursors are hex of the page's last (created_at, id) and an
+// HMAC of it, so a client can hand one back but not make one up.
+pub struct TradePages;
+
+impl TradePages {
+    pub fn encode(cursor: &TradeCursor, secret: &str) -> String {
+        let mut bytes = Self::payload(cursor).to_vec();
+        bytes.extend_from_slice(&Self::signature(&bytes, secret)[..CURSOR_SIGNATURE_BYTES]);
+        hex::encode(bytes)
+    }
+
+    pub fn decode(cursor: &str, secret: &str) -> Result<TradeCursor> {
+        let invalid = || AppError::Validation("Invalid cursor; use the `after` value of the previous page".to_string());
+        let bytes = hex::decode(cursor.trim()).map_err(|_| invalid())?;
+        if bytes.len() != CURSOR_PAYLOAD_BYTES + CURSOR_SIGNATURE_BYTES {
+            return Err(invalid());
+        }
+        let (payload, signature) = bytes.split_at(CURSOR_PAYLOAD_BYTES);
+        let mut mac = Self::mac(secret);
+        mac.update(payload);
+        mac.verify_truncated_left(signature).map_err(|_| invalid())?;
+
+        let micros = i64::from_be_bytes(payload[..8].try_into().map_err(|_| invalid())?);
+        let created_at = DateTime::<Utc>::from_timestamp_micros(micros).ok_or_else(invalid)?;
+        let id = Uuid::from_slice(&payload[8..]).map_err(|_| invalid())?;
+        Ok(TradeCursor { created_at, id })
+    }
+
+    // Trades are fetched one past the limit: when that extra one is there, another page follows
+    pub fn page(mut trades: Vec<Trade>, limit: i64, secret: &str) -> TradePage {
+        let more = trades.len() as i64 > limit;
+        trades.truncate(limit.max(0) as usize);
+        let after = match trades.last() {
+            Some(last) if more => Some(Self::encode(&TradeCursor::of(last), secret)),
+            _ => None,
+        };
+        TradePage { trades: trades.into_iter().map(TradeResponse::from).collect(), after }
+    }
+
+    fn payload(cursor: &TradeCursor) -> [u8; CURSOR_PAYLOAD_BYTES] {
+        let mut payload = [0u8; CURSOR_PAYLOAD_BYTES];
+        payload[..8].copy_from_slice(&cursor.created_at.timestamp_micros().to_be_bytes());
+        payload[8..].copy_from_slice(cursor.id.as_bytes());
+        payload
+    }
+
+    fn signature(payload: &[u8], secret: &str) -> Vec<u8> {
+        let mut mac = Self::mac(secret);
+        mac.update(payload);
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    fn mac(secret: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+        mac.update(CURSOR_CONTEXT);
+        mac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    const SECRET: &str = "test-secret";
+
+    fn cursor() -> TradeCursor {
+        TradeCursor {
+            created_at: Utc.with_ymd_and_hms(2024, 3, 4, 12, 0, 0).unwrap() + chrono::Duration::microseconds(123_456),
+            id: Uuid::new_v4(),
+        }
+    }
+
+    #[test]
+    fn test_cursor_round_trips_to_the_microsecond() {
+        let cursor = cursor();
+        let encoded = TradePages::encode(&cursor, SECRET);
+        assert_eq!(TradePages::decode(&encoded, SECRET).unwrap(), cursor);
+    }
+
+    #[test]
+    fn test_tampered_or_foreign_cursors_are_refused() {
+        let encoded = TradePages::encode(&cursor(), SECRET);
+        // One bit of the timestamp flipped
+        let mut bytes = hex::decode(&encoded).unwrap();
+        bytes[7] ^= 1;
+        for bad in [hex::encode(bytes), encoded[..encoded.len() - 2].to_string(), "not-hex".to_string()] {
+            assert!(matches!(TradePages::decode(&bad, SECRET), Err(AppError::Validation(_))), "{}", bad);
+        }
+        assert!(TradePages::decode(&encoded, "another-secret").is_err());
+    }
+
+    #[test]
+    fn test_only_a_full_page_has_a_cursor() {
+        let trades: Vec<Trade> = (0..3)
+            .map(|_| Trade {
+                // As stored: Postgres keeps microseconds
+                created_at: cursor().created_at,
+                ..Trade::new(Uuid::new_v4(), Uuid::new_v4(), "EURUSD".to_string(), "buy".to_string(), 1.0, 1.1, None, None, None, None)
+            })
+            .collect();
+        let last_shown = TradeCursor::of(&trades[1]);
+
+        let page = TradePages::page(trades.clone(), 2, SECRET);
+        assert_eq!(page.trades.len(), 2);
+        assert_eq!(TradePages::decode(page.after.as_deref().unwrap(), SECRET).unwrap(), last_shown);
+
+        let last = TradePages::page(trades, 3, SECRET);
+        assert_eq!((last.trades.len(), last.after), (3, None));
+    }
+}
@@ -0,0 +1,113 @@
+use schemars::JsonSchema;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::models::{PositionLeg, Trade, TradeResponse};
+
+// One position as the user thinks of it: the original trade and the partial closes split off it
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct TradePosition {
+    pub position_id: Uuid,
+    pub symbol: String,
+    pub trade_type: String,
+    // open while any leg is still open
+    pub status: String,
+    pub total_volume: f64,
+    #[serde(serialize_with = "crate::money::serialize_price")]
+    pub average_entry_price: f64,
+    #[serde(serialize_with = "crate::money::serialize_amount")]
+    pub realized_profit_loss: f64,
+    pub remaining_volume: f64,
+    // The parent first, then its children oldest first
+    pub trades: Vec<TradeResponse>,
+}
+
+pub struct TradePositions;
+
+impl TradePositions {
+    fn summarize(position_id: Uuid, legs: Vec<Trade>) -> TradePosition {
+        let total_volume: f64 = legs.iter().map(|t| t.volume).sum();
+        let average_entry_price = if total_volume > 0.0 {
+            legs.iter().map(|t| t.volume * t.entry_price).sum::<f64>() / total_volume
+        } else {
+            legs[0].entry_price
+        };
+        let realized_profit_loss = legs.iter().filter(|t| t.status == "closed").filter_map(|t| t.profit_loss).sum();
+        let remaining_volume: f64 = legs.iter().filter(|t| t.status == "open").map(|t| t.volume).sum();
+
+        TradePosition {
+            position_id,
+            symbol: legs[0].symbol.clone(),
+            trade_type: legs[0].trade_type.clone(),
+            status: if remaining_volume > 0.0 { "open" } else { "closed" }.to_string(),
+            total_volume,
+            average_entry_price,
+            realized_profit_loss,
+            remaining_volume,
+            trades: legs.into_iter().map(TradeResponse::from).collect(),
+        }
+    }
+
+    // Folds legs, as ordered by Trade::find_position_legs, into positions
+    pub fn group(legs: Vec<PositionLeg>) -> Vec<TradePosition> {
+        let mut positions = Vec::new();
+        let mut current: Option<(Uuid, Vec<Trade>)> = None;
+
+        for leg in legs {
+            let position_id = leg.parent_trade_id.unwrap_or(leg.trade.id);
+            match current.as_mut() {
+                Some((id, trades)) if *id == position_id => trades.push(leg.trade),
+                _ => {
+                    if let Some((id, trades)) = current.replace((position_id, vec![leg.trade])) {
+                        positions.push(Self::summarize(id, trades));
+                    }
+                }
+            }
+        }
+        if let Some((id, trades)) = current {
+            positions.push(Self::summarize(id, trades));
+        }
+        positions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leg(parent: Option<&Trade>, volume: f64, entry: f64, status: &str, profit_loss: Option<f64>) -> PositionLeg {
+        let mut trade = Trade::new(Uuid::nil(), Uuid::nil(), "EURUSD".to_string(), "buy".to_string(), volume, entry, None, None, None, None);
+        trade.status = status.to_string();
+        trade.profit_loss = profit_loss;
+        PositionLeg { trade, parent_trade_id: parent.map(|p| p.id) }
+    }
+
+    #[test]
+    fn test_partial_closes_nest_under_their_parent() {
+        // 0.5 lots still open after closing 0.3 and 0.2 lots of a 1.0 lot position
+        let parent = leg(None, 0.5, 1.1000, "open", None);
+        let first_close = leg(Some(&parent.trade), 0.3, 1.1000, "closed", Some(45.0));
+        let second_close = leg(Some(&parent.trade), 0.2, 1.1010, "closed", Some(-12.5));
+        let other = leg(None, 0.1, 1.2000, "closed", Some(3.0));
+        let parent_id = parent.trade.id;
+
+        let positions = TradePositions::group(vec![parent, first_close, second_close, other]);
+
+        assert_eq!(positions.len(), 2);
+        let position = &positions[0];
+        assert_eq!(position.position_id, parent_id);
+        assert_eq!(position.trades.len(), 3);
+        assert_eq!(position.trades[0].id, parent_id);
+        assert!((position.total_volume - 1.0).abs() < 1e-9);
+        assert!((position.average_entry_price - 1.1002).abs() < 1e-9);
+        assert!((position.realized_profit_loss - 32.5).abs() < 1e-9);
+        assert!((position.remaining_volume - 0.5).abs() < 1e-9);
+        assert_eq!(position.status, "open");
+
+        // A trade that was never split is a position of its own, with its row unchanged
+        let single = &positions[1];
+        assert_eq!((single.trades.len(), single.status.as_str(), single.remaining_volume), (1, "closed", 0.0));
+        assert_eq!(single.position_id, single.trades[0].id);
+        assert_eq!((single.average_entry_price, single.realized_profit_loss), (1.2000, 3.0));
+    }
+}
@@ -0,0 +1,287 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+
+use crate::{
+    errors::{AppError, Result},
+    models::{StopManagement, Trade},
+    services::{mt5_service::Mt5Order, order_drain::OrderDrain, Mt5Service},
+};
+
+// Current prices and order entry on the connection the original robot is bound to
+#[async_trait]
+pub trait ReentryBroker: Send + Sync {
+    // (bid, ask), or None when the broker no longer offers the symbol
+    async fn quote(&self, symbol: &str) -> Result<Option<(f64, f64)>>;
+    // Places the order and stores the trade with its broker ticket
+    async fn place_order(&self, trade: Trade, order: &Mt5Order) -> Result<Trade>;
+}
+
+pub struct Mt5ReentryBroker {
+    mt5: Arc<Mt5Service>,
+    connection_id: String,
+    orders: Arc<OrderDrain>,
+    created_via: String,
+}
+
+impl Mt5ReentryBroker {
+    pub fn new(mt5: Arc<Mt5Service>, connection_id: String, orders: Arc<OrderDrain>, created_via: &str) -> Self {
+        Mt5ReentryBroker { mt5, connection_id, orders, created_via: created_via.to_string() }
+    }
+}
+
+#[async_trait]
+impl ReentryBroker for Mt5ReentryBroker {
+    async fn quote(&self, symbol: &str) -> Result<Option<(f64, f64)>> {
+        let market = self.mt5.get_market_data(&self.connection_id, symbol).await?;
+        // Delisted symbols come back without a tradable price
+        if market.bid > 0.0 && market.ask > 0.0 {
+            Ok(Some((market.bid, market.ask)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn place_order(&self, trade: Trade, order: &Mt5Order) -> Result<Trade> {
+        self.orders
+            .place(trade, &self.created_via, self.mt5.place_order(&self.connection_id, order))
+            .await
+    }
+}
+
+// Stop loss and take profit for a new entry price
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReentryLevels {
+    pub stop_loss: Option<f64>,
+    pub take_profit: Option<f64>,
+}
+
+pub struct TradeReentry;
+
+impl TradeReentry {
+    fn is_buy(trade_type: &str) -> Result<bool> {
+        match trade_type.to_ascii_lowercase().as_str() {
+            "buy" => Ok(true),
+            "sell" => Ok(false),
+            other => Err(AppError::Unprocessable(format!("Cannot re-enter a '{}' trade", other))),
+        }
+    }
+
+    // Longs fill at the ask, shorts at the bid
+    pub fn entry_price(trade_type: &str, bid: f64, ask: f64) -> Result<f64> {
+        Ok(if Self::is_buy(trade_type)? { ask } else { bid })
+    }
+
+    // Keeps the original SL/TP the same distance from the new entry, on the same side
+    pub fn recompute_levels(original: &Trade, entry_price: f64) -> ReentryLevels {
+        let shift = |level: Option<f64>| level.map(|price| entry_price + (price - original.entry_price));
+        ReentryLevels {
+            stop_loss: shift(original.stop_loss),
+            take_profit: shift(original.take_profit),
+        }
+    }
+
+    // Levels must be on the protective side of the entry and further away than the current spread,
+    // otherwise the broker rejects the order or it is stopped out on the first tick
+    pub fn validate_levels(trade_type: &str, entry_price: f64, levels: &ReentryLevels, spread: f64) -> Result<()> {
+        let is_buy = Self::is_buy(trade_type)?;
+        let checks = [
+            ("stop loss", levels.stop_loss, if is_buy { -1.0 } else { 1.0 }),
+            ("take profit", levels.take_profit, if is_buy { 1.0 } else { -1.0 }),
+        ];
+
+        for (name, level, side) in checks {
+            let Some(level) = level else { continue };
+            let distance = (level - entry_price) * side;
+            if distance <= 0.0 {
+                return Err(AppError::Unprocessable(format!(
+                    "The original {} is on the wrong side of the entry price",
+                    name
+                )));
+            }
+            if distance <= spread {
+                return Err(AppError::Unprocessable(format!(
+                    "The {} distance is inside the current spread of {}",
+                    name, spread
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn validate_volume(volume: f64) -> Result<()> {
+        if volume.is_finite() && volume > 0.0 {
+            Ok(())
+        } else {
+            Err(AppError::Unprocessable(format!("Invalid volume {}", volume)))
+        }
+    }
+
+    // Opens a fresh market order mirroring `original` at the current price.
+    // The returned trade is stored; it carries the broker ticket and the link back.
+    pub async fn reenter(
+        original: &Trade,
+        broker: &dyn ReentryBroker,
+        is_demo: bool,
+        stop_management: StopManagement,
+        now: DateTime<Utc>,
+    ) -> Result<Trade> {
+        Self::validate_volume(original.volume)?;
+
+        let (bid, ask) = broker.quote(&original.symbol).await?.ok_or_else(|| {
+            AppError::Unprocessable(format!("{} is no longer offered by the broker", original.symbol))
+        })?;
+        let entry_price = Self::entry_price(&original.trade_type, bid, ask)?;
+        let levels = Self::recompute_levels(original, entry_price);
+        Self::validate_levels(&original.trade_type, entry_price, &levels, ask - bid)?;
+
+        let mut trade = Trade::new(
+            original.user_id,
+            original.robot_id,
+            original.symbol.clone(),
+            original.trade_type.clone(),
+            original.volume,
+            entry_price,
+            levels.stop_loss,
+            levels.take_profit,
+            None,
+            None,
+        );
+        trade.is_demo = is_demo;
+        trade.stop_management = stop_management.as_str().to_string();
+        trade.reentered_from = Some(original.id);
+        trade.opened_at = now;
+        trade.created_at = now;
+        trade.updated_at = now;
+
+        let order = Mt5Order::for_trade(&trade, stop_management);
+        broker.place_order(trade, &order).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use uuid::Uuid;
+
+    struct FakeBroker {
+        quote: Option<(f64, f64)>,
+        orders: Mutex<Vec<Mt5Order>>,
+    }
+
+    impl FakeBroker {
+        fn quoting(bid: f64, ask: f64) -> Self {
+            FakeBroker { quote: Some((bid, ask)), orders: Mutex::new(Vec::new()) }
+        }
+    }
+
+    #[async_trait]
+    impl ReentryBroker for FakeBroker {
+        async fn quote(&self, _symbol: &str) -> Result<Option<(f64, f64)>> {
+            Ok(self.quote)
+        }
+
+        async fn place_order(&self, mut trade: Trade, order: &Mt5Order) -> Result<Trade> {
+            let mut orders = self.orders.lock().unwrap();
+            orders.push(order.clone());
+            trade.broker_trade_id = Some((9000 + orders.len() as i64).to_string());
+            Ok(trade)
+        }
+    }
+
+    fn closed(trade_type: &str, entry: f64, stop_loss: f64, take_profit: f64) -> Trade {
+        let mut trade = Trade::new(
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            "EURUSD".to_string(),
+            trade_type.to_string(),
+            0.3,
+            entry,
+            Some(stop_loss),
+            Some(take_profit),
+            Some(0.8),
+            Some("breakout".to_string()),
+        );
+        trade.status = "closed".to_string();
+        trade.exit_price = Some(take_profit);
+        trade
+    }
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.expect("level should be set");
+        assert!((actual - expected).abs() < 1e-9, "{} != {}", actual, expected);
+    }
+
+    #[tokio::test]
+    async fn test_buy_reentry_keeps_pip_distances_from_the_ask() {
+        // 50 pips of risk, 100 pips of target
+        let original = closed("buy", 1.0800, 1.0750, 1.0900);
+        let broker = FakeBroker::quoting(1.1000, 1.1002);
+
+        let trade = TradeReentry::reenter(&original, &broker, false, StopManagement::Broker, Utc::now()).await.unwrap();
+
+        assert_eq!(trade.entry_price, 1.1002);
+        assert_close(trade.stop_loss, 1.0952);
+        assert_close(trade.take_profit, 1.1102);
+        assert_eq!((trade.status.as_str(), trade.volume), ("open", 0.3));
+
+        let orders = broker.orders.lock().unwrap();
+        assert_eq!(orders[0].order_type, "BUY");
+        assert_eq!(orders[0].stop_loss, trade.stop_loss);
+        assert_eq!(trade.broker_trade_id.as_deref(), Some("9001"));
+    }
+
+    #[tokio::test]
+    async fn test_sell_reentry_keeps_pip_distances_from_the_bid() {
+        let original = closed("sell", 1.2500, 1.2530, 1.2440);
+        let broker = FakeBroker::quoting(1.1000, 1.1002);
+
+        let trade = TradeReentry::reenter(&original, &broker, true, StopManagement::Platform, Utc::now()).await.unwrap();
+
+        assert_eq!(trade.entry_price, 1.1000);
+        assert_close(trade.stop_loss, 1.1030);
+        assert_close(trade.take_profit, 1.0940);
+        assert!(trade.is_demo);
+
+        // Platform-managed stops are not sent with the order
+        let orders = broker.orders.lock().unwrap();
+        assert_eq!((orders[0].order_type.as_str(), orders[0].stop_loss), ("SELL", None));
+    }
+
+    #[tokio::test]
+    async fn test_reentry_links_back_to_the_original() {
+        let original = closed("buy", 1.0800, 1.0750, 1.0900);
+        let broker = FakeBroker::quoting(1.1000, 1.1002);
+
+        let trade = TradeReentry::reenter(&original, &broker, false, StopManagement::Broker, Utc::now()).await.unwrap();
+        assert_ne!(trade.id, original.id);
+        assert_eq!(trade.reentered_from, Some(original.id));
+        assert_eq!((trade.user_id, trade.robot_id), (original.user_id, original.robot_id));
+        assert_eq!((trade.exit_price, trade.ai_reasoning.as_deref()), (None, None));
+
+        let response: crate::models::TradeResponse = trade.into();
+        assert_eq!(response.reentered_from, Some(original.id));
+    }
+
+    #[tokio::test]
+    async fn test_stale_symbol_is_unprocessable_and_places_nothing() {
+        let original = closed("buy", 1.0800, 1.0750, 1.0900);
+        let broker = FakeBroker { quote: None, orders: Mutex::new(Vec::new()) };
+
+        let err = TradeReentry::reenter(&original, &broker, false, StopManagement::Broker, Utc::now()).await.unwrap_err();
+        assert!(matches!(&err, AppError::Unprocessable(msg) if msg.contains("EURUSD is no longer offered")));
+        assert!(broker.orders.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_levels_inside_the_spread_are_rejected() {
+        let levels = ReentryLevels { stop_loss: Some(1.0999), take_profit: Some(1.1100) };
+        let err = TradeReentry::validate_levels("buy", 1.1002, &levels, 0.0005).unwrap_err();
+        assert!(matches!(err, AppError::Unprocessable(msg) if msg.contains("stop loss distance")));
+
+        let wrong_side = ReentryLevels { stop_loss: Some(1.1050), take_profit: None };
+        assert!(TradeReentry::validate_levels("buy", 1.1002, &wrong_side, 0.0002).is_err());
+        assert!(TradeReentry::validate_levels("sell", 1.1000, &wrong_side, 0.0002).is_ok());
+    }
+}
@@ -0,0 +1,177 @@
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::{
+    errors::{AppError, Result},
+    models::{Trade, TradeResponse},
+};
+
+// Shorter patterns have too few trigrams for the index to narrow anything down
+pub const MIN_QUERY_CHARS: usize = 3;
+const MAX_QUERY_CHARS: usize = 100;
+// Characters of context kept on each side of the first match
+const SNIPPET_CONTEXT_CHARS: usize = 40;
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct TradeSearchResult {
+    pub trade: TradeResponse,
+    // HTML-escaped excerpt of ai_reasoning with every match wrapped in <mark>; null when
+    // the match is in another field
+    pub reasoning_snippet: Option<String>,
+}
+
+pub struct TradeSearch;
+
+impl TradeSearch {
+    pub fn normalize_query(raw: &str) -> Result<String> {
+        let query = raw.trim();
+        let chars = query.chars().count();
+        if chars < MIN_QUERY_CHARS {
+            return Err(AppError::Validation(format!(
+                "Search needs at least {} characters",
+                MIN_QUERY_CHARS
+            )));
+        }
+        if chars > MAX_QUERY_CHARS {
+            return Err(AppError::Validation(format!("Search is limited to {} characters", MAX_QUERY_CHARS)));
+        }
+        Ok(query.to_string())
+    }
+
+    // ILIKE pattern matching the query literally anywhere in the text
+    pub fn like_pattern(query: &str) -> String {
+        let mut pattern = String::with_capacity(query.len() + 2);
+        pattern.push('%');
+        for c in query.chars() {
+            if matches!(c, '%' | '_' | '\\') {
+                pattern.push('\\');
+            }
+            pattern.push(c);
+        }
+        pattern.push('%');
+        pattern
+    }
+
+    pub fn result(trade: Trade, query: &str) -> TradeSearchResult {
+        let reasoning_snippet = trade.ai_reasoning.as_deref().and_then(|text| Self::snippet(text, query));
+        TradeSearchResult { trade: trade.into(), reasoning_snippet }
+    }
+
+    pub fn snippet(text: &str, query: &str) -> Option<String> {
+        let text: Vec<char> = text.chars().collect();
+        let query: Vec<char> = query.chars().collect();
+        let matches = Self::find_all(&text, &query);
+        let first = *matches.first()?;
+
+        let start = first.saturating_sub(SNIPPET_CONTEXT_CHARS);
+        let end = (first + query.len() + SNIPPET_CONTEXT_CHARS).min(text.len());
+
+        let mut snippet = String::new();
+        if start > 0 {
+            snippet.push('…');
+        }
+        let mut at = start;
+        for &found in matches.iter().filter(|&&m| m >= start && m + query.len() <= end) {
+            push_escaped(&mut snippet, &text[at..found]);
+            snippet.push_str("<mark>");
+            push_escaped(&mut snippet, &text[found..found + query.len()]);
+            snippet.push_str("</mark>");
+            at = found + query.len();
+        }
+        push_escaped(&mut snippet, &text[at..end]);
+        if end < text.len() {
+            snippet.push('…');
+        }
+        Some(snippet)
+    }
+
+    // Char offsets of non-overlapping, case-insensitive matches
+    fn find_all(text: &[char], query: &[char]) -> Vec<usize> {
+        let mut found = Vec::new();
+        if query.is_empty() {
+            return found;
+        }
+        let mut i = 0;
+        while i + query.len() <= text.len() {
+            let hit = text[i..i + query.len()]
+                .iter()
+                .zip(query)
+                .all(|(a, b)| a.to_lowercase().eq(b.to_lowercase()));
+            if hit {
+                found.push(i);
+                i += query.len();
+            } else {
+                i += 1;
+            }
+        }
+        found
+    }
+}
+
+fn push_escaped(out: &mut String, chars: &[char]) {
+    for &c in chars {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            _ => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::TRADE_SEARCH_DOCUMENT;
+
+    #[test]
+    fn test_snippet_highlights_matches_within_the_window() {
+        let reasoning = "Bullish divergence on H4; Divergence on H1 too";
+
+        assert_eq!(
+            TradeSearch::snippet(reasoning, "DIVERGENCE").unwrap(),
+            "Bullish <mark>divergence</mark> on H4; <mark>Divergence</mark> on H1 too"
+        );
+        assert_eq!(TradeSearch::snippet(reasoning, "ichimoku"), None);
+    }
+
+    #[test]
+    fn test_long_reasoning_is_cut_around_the_first_match_and_escaped() {
+        let reasoning = format!("{}price broke <resistance> & held{}", "a".repeat(60), "b".repeat(60));
+
+        let snippet = TradeSearch::snippet(&reasoning, "<resistance>").unwrap();
+
+        assert_eq!(
+            snippet,
+            format!(
+                "…{}price broke <mark>&lt;resistance&gt;</mark> & held{}…",
+                "a".repeat(28),
+                "b".repeat(33)
+            )
+            .replace(" & ", " &amp; ")
+        );
+    }
+
+    #[test]
+    fn test_snippet_offsets_are_char_based() {
+        // Multi-byte characters before the match must not split a UTF-8 sequence
+        let snippet = TradeSearch::snippet("Preço rompeu a média móvel, divergência clara", "divergência").unwrap();
+        assert_eq!(snippet, "Preço rompeu a média móvel, <mark>divergência</mark> clara");
+    }
+
+    #[test]
+    fn test_short_queries_are_rejected_and_wildcards_are_literal() {
+        assert!(matches!(TradeSearch::normalize_query(" ab "), Err(AppError::Validation(_))));
+        assert_eq!(TradeSearch::normalize_query("  12345 ").unwrap(), "12345");
+        assert_eq!(TradeSearch::like_pattern("50%_off\\"), "%50\\%\\_off\\\\%");
+    }
+
+    #[test]
+    fn test_search_expression_matches_the_index() {
+        // Postgres only uses idx_trades_search when the query repeats the indexed expression
+        let migration = include_str!("../../migrations/20231217000001_trade_search.sql");
+        let indexed = migration.split("gin_trgm_ops").next().unwrap();
+        assert!(indexed.trim_end().ends_with(TRADE_SEARCH_DOCUMENT), "{}", indexed);
+    }
+}
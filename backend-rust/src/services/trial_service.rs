@@ -0,0 +1,152 @@
+use chrono::{DateTime, Duration, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    errors::{AppError, Result},
+    models::{Subscription, TrialSubscription},
+    services::NotificationService,
+};
+
+pub const TRIAL_PLAN: &str = "pro";
+pub const TRIAL_DAYS: i64 = 14;
+const REMINDER_DAYS: [i32; 2] = [3, 1];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrialAction {
+    Nothing,
+    Remind(i32),
+    Expire,
+}
+
+pub struct TrialService;
+
+impl TrialService {
+    pub fn check_eligible(trial_used: bool, current_plan: &str) -> Result<()> {
+        if trial_used {
+            return Err(AppError::Validation("The free trial has already been used".to_string()));
+        }
+        if current_plan != "free" {
+            return Err(AppError::Validation("Trials are only available on the free plan".to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn trial_end(now: DateTime<Utc>) -> DateTime<Utc> {
+        now + Duration::days(TRIAL_DAYS)
+    }
+
+    // What the scheduler should do with a trial right now. Only the most urgent reminder
+    // that hasn't gone out yet is sent, so a late run never sends both.
+    pub fn next_action(trial: &TrialSubscription, now: DateTime<Utc>) -> TrialAction {
+        if trial.status != "trialing" {
+            return TrialAction::Nothing;
+        }
+        if trial.trial_end <= now {
+            return TrialAction::Expire;
+        }
+
+        let remaining = trial.trial_end - now;
+        let already_sent = trial.trial_reminder_days.unwrap_or(i32::MAX);
+        REMINDER_DAYS
+            .iter()
+            .rev()
+            .find(|days| remaining <= Duration::days(**days as i64) && **days < already_sent)
+            .map(|days| TrialAction::Remind(*days))
+            .unwrap_or(TrialAction::Nothing)
+    }
+
+    pub async fn start_trial(pool: &PgPool, user_id: Uuid, current_plan: &str) -> Result<Subscription> {
+        // trial_used is checked again atomically inside start_trial
+        Self::check_eligible(false, current_plan)?;
+
+        Subscription::start_trial(pool, user_id, TRIAL_PLAN, Self::trial_end(Utc::now()))
+            .await?
+            .ok_or_else(|| AppError::Validation("The free trial has already been used".to_string()))
+    }
+
+    // Scheduler job: expire finished trials and send the T-3 / T-1 reminders
+    pub async fn process_trials(pool: &PgPool, notifications: &NotificationService) -> Result<()> {
+        let now = Utc::now();
+        let horizon = now + Duration::days(REMINDER_DAYS[0] as i64);
+
+        for trial in Subscription::find_trials_ending_before(pool, horizon).await? {
+            match Self::next_action(&trial, now) {
+                TrialAction::Nothing => {}
+                TrialAction::Remind(days_left) => {
+                    notifications.send_trial_reminder_email(&trial.email, days_left).await?;
+                    Subscription::mark_trial_reminder(pool, trial.id, days_left).await?;
+                }
+                TrialAction::Expire => {
+                    if Subscription::expire_trial(pool, trial.id, trial.user_id).await? {
+                        tracing::info!("Trial {} for user {} expired, downgraded to free", trial.id, trial.user_id);
+                        notifications.send_trial_expired_email(&trial.email).await?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn trial(trial_end: DateTime<Utc>, status: &str, reminder: Option<i32>) -> TrialSubscription {
+        TrialSubscription {
+            id: Uuid::new_v4(),
+            user_id: Uuid::new_v4(),
+            email: "trader@example.com".to_string(),
+            status: status.to_string(),
+            trial_end,
+            trial_reminder_days: reminder,
+        }
+    }
+
+    #[test]
+    fn test_trial_can_only_be_used_once() {
+        assert!(TrialService::check_eligible(false, "free").is_ok());
+        assert!(matches!(
+            TrialService::check_eligible(true, "free"),
+            Err(AppError::Validation(_))
+        ));
+        assert!(TrialService::check_eligible(false, "essential").is_err());
+    }
+
+    #[test]
+    fn test_trial_lasts_fourteen_days() {
+        let now = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+        assert_eq!(TrialService::trial_end(now), Utc.with_ymd_and_hms(2024, 3, 15, 12, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn test_reminders_at_three_days_then_one_day() {
+        let end = Utc.with_ymd_and_hms(2024, 3, 15, 12, 0, 0).unwrap();
+
+        assert_eq!(TrialService::next_action(&trial(end, "trialing", None), end - Duration::days(4)), TrialAction::Nothing);
+        assert_eq!(TrialService::next_action(&trial(end, "trialing", None), end - Duration::days(3)), TrialAction::Remind(3));
+        assert_eq!(TrialService::next_action(&trial(end, "trialing", Some(3)), end - Duration::days(2)), TrialAction::Nothing);
+        assert_eq!(TrialService::next_action(&trial(end, "trialing", Some(3)), end - Duration::hours(20)), TrialAction::Remind(1));
+        assert_eq!(TrialService::next_action(&trial(end, "trialing", Some(1)), end - Duration::hours(2)), TrialAction::Nothing);
+
+        // A missed T-3 run does not send a stale reminder alongside T-1
+        assert_eq!(TrialService::next_action(&trial(end, "trialing", None), end - Duration::hours(12)), TrialAction::Remind(1));
+    }
+
+    #[test]
+    fn test_expired_trial_is_downgraded() {
+        let end = Utc.with_ymd_and_hms(2024, 3, 15, 12, 0, 0).unwrap();
+        assert_eq!(TrialService::next_action(&trial(end, "trialing", Some(1)), end), TrialAction::Expire);
+        assert_eq!(TrialService::next_action(&trial(end, "trialing", None), end + Duration::days(2)), TrialAction::Expire);
+    }
+
+    #[test]
+    fn test_converted_trial_is_left_alone() {
+        let end = Utc.with_ymd_and_hms(2024, 3, 15, 12, 0, 0).unwrap();
+        assert_eq!(TrialService::next_action(&trial(end, "converted", None), end + Duration::days(1)), TrialAction::Nothing);
+        assert_eq!(TrialService::next_action(&trial(end, "converted", None), end - Duration::days(2)), TrialAction::Nothing);
+    }
+}
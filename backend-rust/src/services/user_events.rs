@@ -0,0 +1,204 @@
+use async_trait::async_trait;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use uuid::Uuid;
+
+use crate::errors::{AppError, Result};
+use crate::services::websocket_manager::WebSocketMessage;
+
+// Events kept per user for clients resuming after a reconnect
+pub const USER_EVENT_RING_SIZE: usize = 500;
+
+// Idle users' rings are dropped after a week; their clients simply resync
+const REDIS_EVENT_TTL_SECONDS: i64 = 7 * 24 * 3600;
+
+#[derive(Debug, Clone)]
+pub enum EventReplay {
+    // Everything after the requested sequence, oldest first
+    Events(Vec<WebSocketMessage>),
+    // The client is missing events the ring no longer holds, or knows of sequences this
+    // server never issued
+    Gap { latest_seq: u64 },
+}
+
+// Per-user sequenced history of the events pushed over WebSocket
+#[async_trait]
+pub trait UserEventLog: Send + Sync {
+    // Stores `message` as the user's next event and returns its sequence number, starting at 1
+    async fn append(&self, user_id: Uuid, message: &WebSocketMessage) -> Result<u64>;
+    async fn since(&self, user_id: Uuid, from_seq: u64) -> Result<EventReplay>;
+}
+
+// `retained` holds the newest events in order, each with its seq set
+fn replay_from(latest_seq: u64, retained: Vec<WebSocketMessage>, from_seq: u64) -> EventReplay {
+    if from_seq > latest_seq {
+        return EventReplay::Gap { latest_seq };
+    }
+    let oldest_seq = retained.first().and_then(|m| m.seq).unwrap_or(latest_seq + 1);
+    if from_seq < latest_seq && oldest_seq > from_seq + 1 {
+        return EventReplay::Gap { latest_seq };
+    }
+    EventReplay::Events(retained.into_iter().filter(|m| m.seq.is_some_and(|seq| seq > from_seq)).collect())
+}
+
+// Single-instance log; sequences restart when the process does
+pub struct MemoryUserEventLog {
+    capacity: usize,
+    users: Mutex<HashMap<Uuid, (u64, VecDeque<WebSocketMessage>)>>,
+}
+
+impl MemoryUserEventLog {
+    pub fn new() -> Self {
+        Self::with_capacity(USER_EVENT_RING_SIZE)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        MemoryUserEventLog { capacity, users: Mutex::new(HashMap::new()) }
+    }
+}
+
+impl Default for MemoryUserEventLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl UserEventLog for MemoryUserEventLog {
+    async fn append(&self, user_id: Uuid, message: &WebSocketMessage) -> Result<u64> {
+        let mut users = self.users.lock().unwrap();
+        let (latest_seq, ring) = users.entry(user_id).or_default();
+        *latest_seq += 1;
+        ring.push_back(WebSocketMessage { seq: Some(*latest_seq), ..message.clone() });
+        while ring.len() > self.capacity {
+            ring.pop_front();
+        }
+        Ok(*latest_seq)
+    }
+
+    async fn since(&self, user_id: Uuid, from_seq: u64) -> Result<EventReplay> {
+        let users = self.users.lock().unwrap();
+        let Some((latest_seq, ring)) = users.get(&user_id) else {
+            return Ok(replay_from(0, Vec::new(), from_seq));
+        };
+        Ok(replay_from(*latest_seq, ring.iter().cloned().collect(), from_seq))
+    }
+}
+
+// Shared across instances: a counter and a capped list of "<seq>:<json>" entries per user
+pub struct RedisUserEventLog {
+    client: redis::Client,
+    capacity: usize,
+}
+
+impl RedisUserEventLog {
+    pub fn new(redis_url: &str) -> Result<Self> {
+        let client = redis::Client::open(redis_url)?;
+        Ok(RedisUserEventLog { client, capacity: USER_EVENT_RING_SIZE })
+    }
+
+    fn keys(user_id: Uuid) -> (String, String) {
+        (format!("user_events:{}", user_id), format!("user_events:{}:seq", user_id))
+    }
+}
+
+#[async_trait]
+impl UserEventLog for RedisUserEventLog {
+    async fn append(&self, user_id: Uuid, message: &WebSocketMessage) -> Result<u64> {
+        // Numbering and pushing in one script keeps the list in sequence order
+        let script = redis::Script::new(
+            r"
+            local seq = redis.call('INCR', KEYS[2])
+            redis.call('RPUSH', KEYS[1], seq .. ':' .. ARGV[1])
+            redis.call('LTRIM', KEYS[1], -tonumber(ARGV[2]), -1)
+            redis.call('EXPIRE', KEYS[1], ARGV[3])
+            redis.call('EXPIRE', KEYS[2], ARGV[3])
+            return seq
+            ",
+        );
+        let raw = serde_json::to_string(message).map_err(|e| AppError::Internal(e.into()))?;
+        let (events_key, seq_key) = Self::keys(user_id);
+        let mut conn = self.client.get_multiplexed_tokio_connection().await?;
+        let seq: u64 = script
+            .key(events_key)
+            .key(seq_key)
+            .arg(raw)
+            .arg(self.capacity)
+            .arg(REDIS_EVENT_TTL_SECONDS)
+            .invoke_async(&mut conn)
+            .await?;
+        Ok(seq)
+    }
+
+    async fn since(&self, user_id: Uuid, from_seq: u64) -> Result<EventReplay> {
+        let (events_key, seq_key) = Self::keys(user_id);
+        let mut conn = self.client.get_multiplexed_tokio_connection().await?;
+        let (latest_seq, entries): (Option<u64>, Vec<String>) = redis::pipe()
+            .atomic()
+            .get(seq_key)
+            .lrange(events_key, 0, -1)
+            .query_async(&mut conn)
+            .await?;
+
+        let retained = entries
+            .iter()
+            .filter_map(|entry| {
+                let (seq, json) = entry.split_once(':')?;
+                let message: WebSocketMessage = serde_json::from_str(json).ok()?;
+                Some(WebSocketMessage { seq: Some(seq.parse().ok()?), ..message })
+            })
+            .collect();
+        Ok(replay_from(latest_seq.unwrap_or(0), retained, from_seq))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(n: u64) -> WebSocketMessage {
+        WebSocketMessage {
+            message_type: "trade_update".to_string(),
+            data: serde_json::json!({ "n": n }),
+            timestamp: chrono::Utc::now(),
+            seq: None,
+        }
+    }
+
+    fn seqs(replay: EventReplay) -> Vec<u64> {
+        match replay {
+            EventReplay::Events(events) => events.into_iter().map(|m| m.seq.unwrap()).collect(),
+            EventReplay::Gap { latest_seq } => panic!("unexpected gap at {}", latest_seq),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sequences_are_per_user_and_replay_starts_after_from_seq() {
+        let log = MemoryUserEventLog::new();
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        for n in 1..=5 {
+            assert_eq!(log.append(alice, &event(n)).await.unwrap(), n);
+        }
+        assert_eq!(log.append(bob, &event(1)).await.unwrap(), 1);
+
+        assert_eq!(seqs(log.since(alice, 2).await.unwrap()), vec![3, 4, 5]);
+        assert_eq!(seqs(log.since(alice, 5).await.unwrap()), Vec::<u64>::new());
+        assert_eq!(seqs(log.since(bob, 0).await.unwrap()), vec![1]);
+        // A user without events is caught up at 0
+        assert_eq!(seqs(log.since(Uuid::new_v4(), 0).await.unwrap()), Vec::<u64>::new());
+    }
+
+    #[tokio::test]
+    async fn test_gap_beyond_the_ring_or_unknown_sequence_needs_a_resync() {
+        let log = MemoryUserEventLog::with_capacity(3);
+        let user_id = Uuid::new_v4();
+        for n in 1..=6 {
+            log.append(user_id, &event(n)).await.unwrap();
+        }
+
+        // 4..=6 are retained, so resuming from 3 is still gapless
+        assert_eq!(seqs(log.since(user_id, 3).await.unwrap()), vec![4, 5, 6]);
+        assert!(matches!(log.since(user_id, 2).await.unwrap(), EventReplay::Gap { latest_seq: 6 }));
+        assert!(matches!(log.since(user_id, 9).await.unwrap(), EventReplay::Gap { latest_seq: 6 }));
+    }
+}
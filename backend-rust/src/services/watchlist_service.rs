@@ -0,0 +1,102 @@
+use crate::{
+    errors::{AppError, Result},
+    models::SubscriptionPlan,
+    services::PlanService,
+};
+
+const MAX_SYMBOL_LEN: usize = 20;
+
+pub struct WatchlistService;
+
+impl WatchlistService {
+    // Upper-cased broker symbol such as EURUSD, XAUUSD or US30.cash
+    pub fn normalize_symbol(raw: &str) -> Result<String> {
+        let symbol = raw.trim().to_uppercase();
+        let valid = !symbol.is_empty()
+            && symbol.len() <= MAX_SYMBOL_LEN
+            && symbol.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-' | '#'));
+        if !valid {
+            return Err(AppError::Validation(format!("Invalid symbol: {:?}", raw)));
+        }
+        Ok(symbol)
+    }
+
+    // Validates a full ordered list as sent to PUT /api/v1/watchlist
+    pub fn normalize(plan: &SubscriptionPlan, raw: &[String]) -> Result<Vec<String>> {
+        let mut symbols = Vec::with_capacity(raw.len());
+        for raw_symbol in raw {
+            let symbol = Self::normalize_symbol(raw_symbol)?;
+            if symbols.contains(&symbol) {
+                return Err(AppError::Validation(format!("{} is listed twice", symbol)));
+            }
+            symbols.push(symbol);
+        }
+        PlanService::check_watchlist_limit(plan, symbols.len())?;
+        Ok(symbols)
+    }
+
+    // Appends a symbol; adding one already on the list leaves the list as it is
+    pub fn add(plan: &SubscriptionPlan, mut symbols: Vec<String>, raw: &str) -> Result<Vec<String>> {
+        let symbol = Self::normalize_symbol(raw)?;
+        if !symbols.contains(&symbol) {
+            symbols.push(symbol);
+            PlanService::check_watchlist_limit(plan, symbols.len())?;
+        }
+        Ok(symbols)
+    }
+
+    pub fn remove(mut symbols: Vec<String>, raw: &str) -> Result<Vec<String>> {
+        let symbol = Self::normalize_symbol(raw)?;
+        let before = symbols.len();
+        symbols.retain(|s| *s != symbol);
+        if symbols.len() == before {
+            return Err(AppError::NotFound(format!("{} is not on your watchlist", symbol)));
+        }
+        Ok(symbols)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Subscription;
+
+    fn symbols(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_full_list_is_normalized_and_capped_by_plan() {
+        let essential = Subscription::plan_details("essential");
+
+        assert_eq!(
+            WatchlistService::normalize(&essential, &symbols(&[" xauusd", "EURUSD", "us30.cash"])).unwrap(),
+            symbols(&["XAUUSD", "EURUSD", "US30.CASH"])
+        );
+
+        let duplicate = WatchlistService::normalize(&essential, &symbols(&["EURUSD", "eurusd"])).unwrap_err();
+        assert!(matches!(duplicate, AppError::Validation(_)), "{}", duplicate);
+        let invalid = WatchlistService::normalize(&essential, &symbols(&["EUR USD"])).unwrap_err();
+        assert!(matches!(invalid, AppError::Validation(_)), "{}", invalid);
+
+        let eleven: Vec<String> = (0..11).map(|i| format!("SYM{}", i)).collect();
+        let err = WatchlistService::normalize(&essential, &eleven).unwrap_err();
+        assert!(matches!(err, AppError::Forbidden(_)), "{}", err);
+        assert!(WatchlistService::normalize(&Subscription::plan_details("elite"), &eleven).is_ok());
+    }
+
+    #[test]
+    fn test_add_and_remove_keep_the_order() {
+        let free = Subscription::plan_details("free");
+        let list = symbols(&["EURUSD", "GBPUSD"]);
+
+        let list = WatchlistService::add(&free, list, "usdjpy").unwrap();
+        assert_eq!(list, symbols(&["EURUSD", "GBPUSD", "USDJPY"]));
+        // Re-adding is a no-op rather than a duplicate
+        assert_eq!(WatchlistService::add(&free, list.clone(), "EURUSD").unwrap(), list);
+
+        let list = WatchlistService::remove(list, "gbpusd").unwrap();
+        assert_eq!(list, symbols(&["EURUSD", "USDJPY"]));
+        assert!(matches!(WatchlistService::remove(list, "XAUUSD"), Err(AppError::NotFound(_))));
+    }
+}
@@ -1,18 +1,53 @@
-use axum::extract::ws::{Message, WebSocket};
-use futures_util::{SinkExt, StreamExt};
+use axum::extract::ws::{close_code, CloseFrame, Message, WebSocket};
+use futures_util::{Sink, SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::sync::{broadcast, RwLock};
+use std::time::Duration;
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+use tokio::sync::{broadcast, mpsc, watch, RwLock};
+use tokio::time::Instant;
 use uuid::Uuid;
 
 use crate::errors::Result;
+use crate::services::backtest_progress::BacktestJobRegistry;
+use crate::services::market_data_streamer::PresenceListener;
+use crate::services::task_supervisor::{TaskClass, TaskSupervisor};
+use crate::services::user_events::{EventReplay, MemoryUserEventLog, UserEventLog};
+use crate::services::ws_protocol::{self, ClientCapabilities, EventType, ProtocolState};
+use crate::services::ws_shedding::{ChannelPressure, ShedPolicy, WebSocketChannelMetrics, WebSocketShedCount};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct WebSocketMessage {
     pub message_type: String,
     pub data: serde_json::Value,
     pub timestamp: chrono::DateTime<chrono::Utc>,
+    // Per-user event number, set on trade and robot events so a reconnecting client can resume
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+}
+
+pub const DEFAULT_USER_CHANNEL_CAPACITY: usize = 100;
+pub const DEFAULT_GLOBAL_CHANNEL_CAPACITY: usize = 1000;
+pub const DEFAULT_BATCH_WINDOW: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Default)]
+pub struct ConnectionStats {
+    dropped_messages: AtomicU64,
+    conflated_market_data: AtomicU64,
+    resyncs_sent: AtomicU64,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct WebSocketConnectionMetrics {
+    pub connection_id: String,
+    pub user_id: Uuid,
+    pub dropped_messages: u64,
+    pub conflated_market_data: u64,
+    pub resyncs_sent: u64,
+    pub channel: WebSocketChannelMetrics,
 }
 
 #[derive(Debug, Clone)]
@@ -20,35 +55,395 @@ pub struct WebSocketConnection {
     pub user_id: Uuid,
     pub connection_id: String,
     pub sender: broadcast::Sender<WebSocketMessage>,
+    pub stats: Arc<ConnectionStats>,
+    pub pressure: Arc<ChannelPressure>,
 }
 
 pub struct WebSocketManager {
     connections: Arc<RwLock<HashMap<String, WebSocketConnection>>>,
     global_sender: broadcast::Sender<WebSocketMessage>,
+    global_pressure: ChannelPressure,
+    user_channel_capacity: usize,
+    global_channel_capacity: usize,
+    shedding: ShedPolicy,
+    batch_window: Duration,
+    backtests: Arc<BacktestJobRegistry>,
+    presence: Option<Arc<dyn PresenceListener>>,
+    events: Arc<dyn UserEventLog>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ClientMessage {
+    #[serde(rename = "type")]
+    message_type: String,
+    job_id: Option<Uuid>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResumeRequest {
+    action: String,
+    from_seq: u64,
+}
+
+// `{"action": "resume", "from_seq": N}` asks for every user event after N
+fn parse_resume(text: &str) -> Option<u64> {
+    let request: ResumeRequest = serde_json::from_str(text).ok()?;
+    (request.action == "resume").then_some(request.from_seq)
+}
+
+// Replies owed to a client message, if any
+fn handle_client_message(backtests: &BacktestJobRegistry, user_id: Uuid, text: &str) -> Option<WebSocketMessage> {
+    let message: ClientMessage = serde_json::from_str(text).ok()?;
+    match message.message_type.as_str() {
+        "subscribe_backtest" => backtests.snapshot(user_id, message.job_id?),
+        _ => None,
+    }
+}
+
+// Messages waiting to be written to one client. Market data is conflated so a slow
+// client gets the latest tick per symbol instead of a backlog of stale ones.
+#[derive(Default)]
+struct OutgoingBatch {
+    messages: Vec<WebSocketMessage>,
+    market_data_slots: HashMap<String, usize>,
+    resync_queued: bool,
+}
+
+impl OutgoingBatch {
+    fn push(&mut self, message: WebSocketMessage, stats: &ConnectionStats) {
+        if message.message_type == "market_data" {
+            if let Some(symbol) = message.data.get("symbol").and_then(|s| s.as_str()) {
+                if let Some(&slot) = self.market_data_slots.get(symbol) {
+                    self.messages[slot] = message;
+                    stats.conflated_market_data.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+                self.market_data_slots.insert(symbol.to_string(), self.messages.len());
+            }
+        }
+        self.messages.push(message);
+    }
+
+    // Returns false once the channel is closed
+    fn receive(&mut self, received: std::result::Result<WebSocketMessage, RecvError>, stats: &ConnectionStats) -> bool {
+        match received {
+            Ok(message) => self.push(message, stats),
+            Err(RecvError::Lagged(skipped)) => self.lagged(skipped, stats),
+            Err(RecvError::Closed) => return false,
+        }
+        true
+    }
+
+    // The client missed messages it cannot recover from the stream; tell it to refetch state
+    fn lagged(&mut self, skipped: u64, stats: &ConnectionStats) {
+        stats.dropped_messages.fetch_add(skipped, Ordering::Relaxed);
+        self.resync(serde_json::json!({ "dropped_messages": skipped }), stats);
+    }
+
+    fn resync(&mut self, data: serde_json::Value, stats: &ConnectionStats) {
+        if self.resync_queued {
+            return;
+        }
+        self.resync_queued = true;
+        stats.resyncs_sent.fetch_add(1, Ordering::Relaxed);
+        self.messages.push(WebSocketMessage {
+            message_type: "resync_required".to_string(),
+            data,
+            timestamp: chrono::Utc::now(),
+            seq: None,
+        });
+    }
+
+    fn take(&mut self) -> Vec<WebSocketMessage> {
+        self.market_data_slots.clear();
+        self.resync_queued = false;
+        std::mem::take(&mut self.messages)
+    }
+}
+
+// Returns false once the channel is closed
+fn drain_ready(
+    receiver: &mut broadcast::Receiver<WebSocketMessage>,
+    batch: &mut OutgoingBatch,
+    stats: &ConnectionStats,
+) -> bool {
+    loop {
+        match receiver.try_recv() {
+            Ok(message) => batch.push(message, stats),
+            Err(TryRecvError::Lagged(skipped)) => batch.lagged(skipped, stats),
+            Err(TryRecvError::Empty) => return true,
+            Err(TryRecvError::Closed) => return false,
+        }
+    }
+}
+
+// Throttles bursts of batchable events per type. The first event of a type goes out at once;
+// more of that type within the window are held and leave together in one batch when it ends.
+// Order holds within a type, and every item keeps its seq.
+struct BurstBatcher {
+    window: Duration,
+    types: HashMap<EventType, Burst>,
+}
+
+struct Burst {
+    last_sent: Instant,
+    pending: Vec<WebSocketMessage>,
+}
+
+impl BurstBatcher {
+    fn new(window: Duration) -> Self {
+        BurstBatcher { window, types: HashMap::new() }
+    }
+
+    // The message if it goes out now, None if it was held for the next batch
+    fn offer(&mut self, message: WebSocketMessage, now: Instant) -> Option<WebSocketMessage> {
+        let event = EventType::parse(&message.message_type).filter(|e| ws_protocol::BATCHED_EVENTS.contains(e));
+        let Some(event) = event.filter(|_| !self.window.is_zero()) else {
+            return Some(message);
+        };
+        match self.types.get_mut(&event) {
+            Some(burst) if !burst.pending.is_empty() || now < burst.last_sent + self.window => {
+                burst.pending.push(message);
+                None
+            }
+            _ => {
+                self.types.insert(event, Burst { last_sent: now, pending: Vec::new() });
+                Some(message)
+            }
+        }
+    }
+
+    // When the earliest held batch is due
+    fn next_flush(&self) -> Option<Instant> {
+        self.types
+            .values()
+            .filter(|burst| !burst.pending.is_empty())
+            .map(|burst| burst.last_sent + self.window)
+            .min()
+    }
+
+    // Batches whose window has ended; a lone held event goes out as itself
+    fn flush_due(&mut self, now: Instant) -> Vec<WebSocketMessage> {
+        let mut due = Vec::new();
+        for (event, burst) in self.types.iter_mut() {
+            if burst.pending.is_empty() || now < burst.last_sent + self.window {
+                continue;
+            }
+            burst.last_sent = now;
+            let mut items = std::mem::take(&mut burst.pending);
+            if items.len() == 1 {
+                due.extend(items.pop());
+            } else {
+                due.push(ws_protocol::batch(*event, items));
+            }
+        }
+        due
+    }
+}
+
+// Sleeps until `deadline`, or forever without one
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+// Replays a user's missed events when their client asks to resume
+struct EventReplayer {
+    user_id: Uuid,
+    log: Arc<dyn UserEventLog>,
+    requests: mpsc::UnboundedReceiver<u64>,
+}
+
+impl EventReplayer {
+    async fn resume(&self, from_seq: u64, batch: &mut OutgoingBatch, stats: &ConnectionStats) {
+        let latest_seq = match self.log.since(self.user_id, from_seq).await {
+            Ok(EventReplay::Events(events)) => {
+                for event in events {
+                    batch.push(event, stats);
+                }
+                return;
+            }
+            Ok(EventReplay::Gap { latest_seq }) => Some(latest_seq),
+            Err(e) => {
+                tracing::warn!("Failed to replay events for user {} from {}: {}", self.user_id, from_seq, e);
+                None
+            }
+        };
+        batch.resync(serde_json::json!({ "from_seq": from_seq, "latest_seq": latest_seq }), stats);
+    }
+}
+
+async fn remove_connection(
+    connections: &RwLock<HashMap<String, WebSocketConnection>>,
+    connection_id: &str,
+    presence: Option<Arc<dyn PresenceListener>>,
+) {
+    let removed = connections.write().await.remove(connection_id);
+    if let (Some(removed), Some(presence)) = (removed, presence) {
+        presence.user_disconnected(removed.user_id).await;
+    }
+}
+
+// Forwards connection and global messages to the client, shaped for its negotiated protocol
+// version, until either channel closes, the client goes away or its handshake is rejected.
+// Lagging never ends the connection.
+async fn pump_outgoing<S>(
+    mut sink: S,
+    mut receiver: broadcast::Receiver<WebSocketMessage>,
+    mut global_receiver: broadcast::Receiver<WebSocketMessage>,
+    mut protocol: watch::Receiver<ProtocolState>,
+    stats: Arc<ConnectionStats>,
+    mut replay: EventReplayer,
+    batch_window: Duration,
+) where
+    S: Sink<Message> + Unpin,
+{
+    let mut batch = OutgoingBatch::default();
+    let mut bursts = BurstBatcher::new(batch_window);
+    // Highest user event sequence the client has been sent
+    let mut delivered_seq = 0;
+
+    loop {
+        tokio::select! {
+            // A resume goes first so the replay precedes live events that arrived alongside it
+            biased;
+            Some(from_seq) = replay.requests.recv() => {
+                delivered_seq = from_seq;
+                replay.resume(from_seq, &mut batch, &stats).await;
+            }
+            msg = receiver.recv() => if !batch.receive(msg, &stats) {
+                return;
+            },
+            msg = global_receiver.recv() => if !batch.receive(msg, &stats) {
+                return;
+            },
+            changed = protocol.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+                let state = protocol.borrow_and_update().clone();
+                if let ProtocolState::Rejected(reason) = state {
+                    let frame = CloseFrame {
+                        code: close_code::PROTOCOL,
+                        reason: reason.into(),
+                    };
+                    let _ = sink.send(Message::Close(Some(frame))).await;
+                    return;
+                }
+                continue;
+            }
+            _ = sleep_until(bursts.next_flush()) => {}
+        }
+
+        // Everything that piled up while the last write was in flight goes out as one batch
+        let connection_open = drain_ready(&mut receiver, &mut batch, &stats);
+        let global_open = drain_ready(&mut global_receiver, &mut batch, &stats);
+
+        let capabilities = match &*protocol.borrow() {
+            ProtocolState::Active(capabilities) => capabilities.clone(),
+            ProtocolState::Rejected(_) => return,
+        };
+        let now = Instant::now();
+        let mut outgoing = Vec::new();
+        for message in batch.take() {
+            // Live events a replay already covered
+            if let Some(seq) = message.seq {
+                if seq <= delivered_seq {
+                    continue;
+                }
+                delivered_seq = seq;
+            }
+            let Some(message) = ws_protocol::convert(message, &capabilities) else {
+                continue;
+            };
+            // Clients from before batch envelopes get every event alone
+            if capabilities.version < ws_protocol::BATCH_VERSION {
+                outgoing.push(message);
+            } else {
+                outgoing.extend(bursts.offer(message, now));
+            }
+        }
+        outgoing.extend(bursts.flush_due(now));
+
+        for message in outgoing {
+            let json = serde_json::to_string(&message).unwrap_or_default();
+            if sink.send(Message::Text(json)).await.is_err() {
+                return;
+            }
+        }
+
+        if !connection_open || !global_open {
+            return;
+        }
+    }
 }
 
 impl WebSocketManager {
     pub fn new() -> Self {
-        let (global_sender, _) = broadcast::channel(1000);
-        
+        Self::with_capacities(DEFAULT_USER_CHANNEL_CAPACITY, DEFAULT_GLOBAL_CHANNEL_CAPACITY)
+    }
+
+    pub fn with_capacities(user_channel_capacity: usize, global_channel_capacity: usize) -> Self {
+        let (global_sender, _) = broadcast::channel(global_channel_capacity);
+
         WebSocketManager {
             connections: Arc::new(RwLock::new(HashMap::new())),
             global_sender,
+            global_pressure: ChannelPressure::default(),
+            user_channel_capacity,
+            global_channel_capacity,
+            shedding: ShedPolicy::default(),
+            batch_window: DEFAULT_BATCH_WINDOW,
+            backtests: Arc::new(BacktestJobRegistry::new()),
+            presence: None,
+            events: Arc::new(MemoryUserEventLog::new()),
         }
     }
 
+    // Told about every connection that opens and closes, e.g. to stream a user's watchlist
+    pub fn with_presence(mut self, presence: Arc<dyn PresenceListener>) -> Self {
+        self.presence = Some(presence);
+        self
+    }
+
+    // How long bursts of robot_status and trade_update are held to go out as one batch; zero
+    // sends every event alone
+    pub fn with_batch_window(mut self, batch_window: Duration) -> Self {
+        self.batch_window = batch_window;
+        self
+    }
+
+    // When and how channels shed low-priority messages under pressure
+    pub fn with_shedding(mut self, shedding: ShedPolicy) -> Self {
+        self.shedding = shedding;
+        self
+    }
+
+    // Numbers user events so reconnecting clients can resume; in-memory by default
+    pub fn with_event_log(mut self, events: Arc<dyn UserEventLog>) -> Self {
+        self.events = events;
+        self
+    }
+
     pub async fn add_connection(
         &self,
         user_id: Uuid,
         websocket: WebSocket,
     ) -> Result<()> {
         let connection_id = Uuid::new_v4().to_string();
-        let (sender, mut receiver) = broadcast::channel(100);
-        
+        let (sender, receiver) = broadcast::channel(self.user_channel_capacity);
+        let stats = Arc::new(ConnectionStats::default());
+        // Clients that never send a hello get the v1 protocol
+        let (protocol_sender, protocol) = watch::channel(ProtocolState::Active(ClientCapabilities::legacy()));
+
         let connection = WebSocketConnection {
             user_id,
             connection_id: connection_id.clone(),
             sender: sender.clone(),
+            stats: stats.clone(),
+            pressure: Arc::new(ChannelPressure::default()),
         };
 
         // Add connection to the manager
@@ -56,24 +451,54 @@ impl WebSocketManager {
             let mut connections = self.connections.write().await;
             connections.insert(connection_id.clone(), connection);
         }
+        if let Some(presence) = &self.presence {
+            presence.user_connected(user_id).await;
+        }
 
         // Subscribe to global messages
-        let mut global_receiver = self.global_sender.subscribe();
+        let global_receiver = self.global_sender.subscribe();
 
         // Handle WebSocket connection
-        let (mut ws_sender, mut ws_receiver) = websocket.split();
-        let connections_clone = self.connections.clone();
+        let (ws_sender, mut ws_receiver) = websocket.split();
+        let supervisor = TaskSupervisor::new();
+
+        // Remove the connection when either task ends, panics included; whichever task removes
+        // it reports the disconnect
+        let disconnect = {
+            let connections = self.connections.clone();
+            let connection_id = connection_id.clone();
+            let presence = self.presence.clone();
+            move || async move { remove_connection(&connections, &connection_id, presence).await }
+        };
 
         // Spawn task to handle incoming messages from client
-        let connections_for_incoming = connections_clone.clone();
-        let connection_id_for_incoming = connection_id.clone();
-        tokio::spawn(async move {
+        let backtests = self.backtests.clone();
+        let (resume_sender, resume_requests) = mpsc::unbounded_channel();
+        let disconnect_incoming = disconnect.clone();
+        let incoming = async move {
+            let mut first_message = true;
             while let Some(msg) = ws_receiver.next().await {
                 match msg {
                     Ok(Message::Text(text)) => {
                         tracing::debug!("Received WebSocket message: {}", text);
-                        // Handle incoming message from client
-                        // TODO: Parse and process client messages
+                        // Only the first message can negotiate the protocol
+                        if std::mem::take(&mut first_message) {
+                            if let Some(state) = ws_protocol::negotiate(&text) {
+                                let rejected = matches!(state, ProtocolState::Rejected(_));
+                                let _ = protocol_sender.send(state);
+                                if rejected {
+                                    break;
+                                }
+                                continue;
+                            }
+                        }
+                        if let Some(from_seq) = parse_resume(&text) {
+                            let _ = resume_sender.send(from_seq);
+                            continue;
+                        }
+                        if let Some(reply) = handle_client_message(&backtests, user_id, &text) {
+                            let _ = sender.send(reply);
+                        }
                     }
                     Ok(Message::Close(_)) => {
                         tracing::info!("WebSocket connection closed by client");
@@ -87,47 +512,25 @@ impl WebSocketManager {
                 }
             }
 
-            // Remove connection when client disconnects
-            let mut connections = connections_for_incoming.write().await;
-            connections.remove(&connection_id_for_incoming);
-        });
+            disconnect_incoming().await;
+        };
+        let name = format!("websocket:{}:incoming", connection_id);
+        supervisor.spawn_owned(name, TaskClass::WebSocket, incoming, disconnect.clone());
 
         // Spawn task to handle outgoing messages to client
-        let connection_id_for_outgoing = connection_id.clone();
-        tokio::spawn(async move {
-            loop {
-                tokio::select! {
-                    // Handle connection-specific messages
-                    msg = receiver.recv() => {
-                        match msg {
-                            Ok(message) => {
-                                let json = serde_json::to_string(&message).unwrap_or_default();
-                                if ws_sender.send(Message::Text(json)).await.is_err() {
-                                    break;
-                                }
-                            }
-                            Err(_) => break,
-                        }
-                    }
-                    // Handle global messages
-                    msg = global_receiver.recv() => {
-                        match msg {
-                            Ok(message) => {
-                                let json = serde_json::to_string(&message).unwrap_or_default();
-                                if ws_sender.send(Message::Text(json)).await.is_err() {
-                                    break;
-                                }
-                            }
-                            Err(_) => break,
-                        }
-                    }
-                }
-            }
-
-            // Remove connection when sender task ends
-            let mut connections = connections_clone.write().await;
-            connections.remove(&connection_id_for_outgoing);
-        });
+        let disconnect_outgoing = disconnect.clone();
+        let batch_window = self.batch_window;
+        let replay = EventReplayer {
+            user_id,
+            log: self.events.clone(),
+            requests: resume_requests,
+        };
+        let outgoing = async move {
+            pump_outgoing(ws_sender, receiver, global_receiver, protocol, stats, replay, batch_window).await;
+            disconnect_outgoing().await;
+        };
+        let name = format!("websocket:{}:outgoing", connection_id);
+        supervisor.spawn_owned(name, TaskClass::WebSocket, outgoing, disconnect);
 
         tracing::info!("WebSocket connection established for user {}", user_id);
         Ok(())
@@ -138,15 +541,31 @@ impl WebSocketManager {
         
         for connection in connections.values() {
             if connection.user_id == user_id {
-                let _ = connection.sender.send(message.clone());
+                self.shedding.send(
+                    &connection.connection_id,
+                    &connection.sender,
+                    self.user_channel_capacity,
+                    &connection.pressure,
+                    message.clone(),
+                );
             }
         }
         
         Ok(())
     }
 
+    // Numbers the event in the user's log before pushing it. A failing log does not hold back
+    // live delivery; the event just goes out without a sequence.
+    pub async fn send_user_event(&self, user_id: Uuid, mut message: WebSocketMessage) -> Result<()> {
+        match self.events.append(user_id, &message).await {
+            Ok(seq) => message.seq = Some(seq),
+            Err(e) => tracing::warn!("Failed to record {} event for user {}: {}", message.message_type, user_id, e),
+        }
+        self.send_to_user(user_id, message).await
+    }
+
     pub async fn send_to_all(&self, message: WebSocketMessage) -> Result<()> {
-        let _ = self.global_sender.send(message);
+        self.shedding.send("global", &self.global_sender, self.global_channel_capacity, &self.global_pressure, message);
         Ok(())
     }
 
@@ -155,9 +574,33 @@ impl WebSocketManager {
             message_type: "trade_update".to_string(),
             data: trade_data,
             timestamp: chrono::Utc::now(),
+            seq: None,
         };
 
-        self.send_to_user(user_id, message).await
+        self.send_user_event(user_id, message).await
+    }
+
+    // A broker fill; v1 clients receive it as a trade_update
+    pub async fn broadcast_order_filled(&self, user_id: Uuid, fill_data: serde_json::Value) -> Result<()> {
+        let message = WebSocketMessage {
+            message_type: "order_filled".to_string(),
+            data: fill_data,
+            timestamp: chrono::Utc::now(),
+            seq: None,
+        };
+
+        self.send_user_event(user_id, message).await
+    }
+
+    pub async fn broadcast_trade_closed(&self, user_id: Uuid, trade_data: serde_json::Value) -> Result<()> {
+        let message = WebSocketMessage {
+            message_type: "trade_closed".to_string(),
+            data: trade_data,
+            timestamp: chrono::Utc::now(),
+            seq: None,
+        };
+
+        self.send_user_event(user_id, message).await
     }
 
     pub async fn broadcast_robot_status(&self, user_id: Uuid, robot_data: serde_json::Value) -> Result<()> {
@@ -165,9 +608,21 @@ impl WebSocketManager {
             message_type: "robot_status".to_string(),
             data: robot_data,
             timestamp: chrono::Utc::now(),
+            seq: None,
         };
 
-        self.send_to_user(user_id, message).await
+        self.send_user_event(user_id, message).await
+    }
+
+    pub async fn broadcast_journal_prompt(&self, user_id: Uuid, prompt_data: serde_json::Value) -> Result<()> {
+        let message = WebSocketMessage {
+            message_type: "journal_prompt".to_string(),
+            data: prompt_data,
+            timestamp: chrono::Utc::now(),
+            seq: None,
+        };
+
+        self.send_user_event(user_id, message).await
     }
 
     pub async fn broadcast_market_data(&self, market_data: serde_json::Value) -> Result<()> {
@@ -175,6 +630,7 @@ impl WebSocketManager {
             message_type: "market_data".to_string(),
             data: market_data,
             timestamp: chrono::Utc::now(),
+            seq: None,
         };
 
         self.send_to_all(message).await
@@ -185,16 +641,47 @@ impl WebSocketManager {
             message_type: "system_notification".to_string(),
             data: notification,
             timestamp: chrono::Utc::now(),
+            seq: None,
         };
 
         self.send_to_all(message).await
     }
 
+    pub fn backtests(&self) -> Arc<BacktestJobRegistry> {
+        self.backtests.clone()
+    }
+
     pub async fn get_connection_count(&self) -> usize {
         let connections = self.connections.read().await;
         connections.len()
     }
 
+    pub async fn connection_metrics(&self) -> Vec<WebSocketConnectionMetrics> {
+        let connections = self.connections.read().await;
+        let mut metrics: Vec<WebSocketConnectionMetrics> = connections
+            .values()
+            .map(|conn| WebSocketConnectionMetrics {
+                connection_id: conn.connection_id.clone(),
+                user_id: conn.user_id,
+                dropped_messages: conn.stats.dropped_messages.load(Ordering::Relaxed),
+                conflated_market_data: conn.stats.conflated_market_data.load(Ordering::Relaxed),
+                resyncs_sent: conn.stats.resyncs_sent.load(Ordering::Relaxed),
+                channel: conn.pressure.metrics(conn.sender.len(), self.user_channel_capacity),
+            })
+            .collect();
+        metrics.sort_by(|a, b| a.connection_id.cmp(&b.connection_id));
+        metrics
+    }
+
+    pub fn global_channel_metrics(&self) -> WebSocketChannelMetrics {
+        self.global_pressure.metrics(self.global_sender.len(), self.global_channel_capacity)
+    }
+
+    // Messages shed since startup, by type
+    pub fn shed_counts(&self) -> Vec<WebSocketShedCount> {
+        self.shedding.shed_counts()
+    }
+
     pub async fn get_user_connections(&self, user_id: Uuid) -> Vec<String> {
         let connections = self.connections.read().await;
         connections
@@ -214,6 +701,460 @@ impl Default for WebSocketManager {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::services::ws_shedding::SheddingAlerts;
+    use std::pin::Pin;
+    use std::sync::Mutex;
+    use std::task::{Context, Poll};
+
+    #[derive(Clone, Default)]
+    struct RecordingSink {
+        sent: Arc<Mutex<Vec<WebSocketMessage>>>,
+        close_reason: Arc<Mutex<Option<String>>>,
+    }
+
+    impl RecordingSink {
+        fn messages(&self) -> Vec<WebSocketMessage> {
+            self.sent.lock().unwrap().clone()
+        }
+    }
+
+    impl Sink<Message> for RecordingSink {
+        type Error = ();
+
+        fn poll_ready(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::result::Result<(), ()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn start_send(self: Pin<&mut Self>, item: Message) -> std::result::Result<(), ()> {
+            match item {
+                Message::Text(text) => self.sent.lock().unwrap().push(serde_json::from_str(&text).unwrap()),
+                Message::Close(Some(frame)) => *self.close_reason.lock().unwrap() = Some(frame.reason.to_string()),
+                _ => {}
+            }
+            Ok(())
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::result::Result<(), ()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::result::Result<(), ()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn message(message_type: &str, data: serde_json::Value) -> WebSocketMessage {
+        WebSocketMessage {
+            message_type: message_type.to_string(),
+            data,
+            timestamp: chrono::Utc::now(),
+            seq: None,
+        }
+    }
+
+    // A client that never asks to resume
+    fn no_replay() -> EventReplayer {
+        let (_, requests) = mpsc::unbounded_channel();
+        EventReplayer {
+            user_id: Uuid::new_v4(),
+            log: Arc::new(MemoryUserEventLog::new()),
+            requests,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_slow_consumer_survives_lag_with_resync_and_conflated_ticks() {
+        let (sender, receiver) = broadcast::channel(4);
+        let (global_sender, global_receiver) = broadcast::channel(100);
+        let stats = Arc::new(ConnectionStats::default());
+        let sink = RecordingSink::default();
+
+        // The client has not been read from yet: its own channel overflows and ticks pile up
+        for i in 0..10 {
+            sender.send(message("trade_update", serde_json::json!({ "seq": i }))).unwrap();
+        }
+        for price in 1..=20 {
+            global_sender
+                .send(message("market_data", serde_json::json!({ "symbol": "EURUSD", "price": price })))
+                .unwrap();
+        }
+        global_sender
+            .send(message("market_data", serde_json::json!({ "symbol": "GBPUSD", "price": 7 })))
+            .unwrap();
+
+        let (_protocol_sender, protocol) = watch::channel(ProtocolState::Active(ClientCapabilities::legacy()));
+        let pump = tokio::spawn(pump_outgoing(sink.clone(), receiver, global_receiver, protocol, stats.clone(), no_replay(), DEFAULT_BATCH_WINDOW));
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+        let sent = sink.messages();
+        assert_eq!(sent.iter().filter(|m| m.message_type == "resync_required").count(), 1);
+        assert_eq!(sent.iter().filter(|m| m.message_type == "trade_update").count(), 4);
+
+        let ticks: Vec<_> = sent.iter().filter(|m| m.message_type == "market_data").collect();
+        assert_eq!(ticks.len(), 2);
+        assert!(ticks.iter().any(|m| m.data["symbol"] == "EURUSD" && m.data["price"] == 20));
+        assert!(ticks.iter().any(|m| m.data["symbol"] == "GBPUSD" && m.data["price"] == 7));
+
+        assert_eq!(stats.dropped_messages.load(Ordering::Relaxed), 6);
+        assert_eq!(stats.resyncs_sent.load(Ordering::Relaxed), 1);
+        assert_eq!(stats.conflated_market_data.load(Ordering::Relaxed), 19);
+
+        // The connection is still alive and keeps delivering
+        assert!(!pump.is_finished());
+        sender.send(message("robot_status", serde_json::json!({}))).unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert_eq!(sink.messages().last().unwrap().message_type, "robot_status");
+
+        drop(sender);
+        pump.await.unwrap();
+    }
+
+    // Connects a client that sends `hello` first and returns its sink, sender and pump
+    fn connect(hello: &str) -> (RecordingSink, broadcast::Sender<WebSocketMessage>, tokio::task::JoinHandle<()>) {
+        let (sender, receiver) = broadcast::channel(100);
+        let (global_sender, global_receiver) = broadcast::channel(100);
+        let sink = RecordingSink::default();
+        let (protocol_sender, protocol) = watch::channel(ProtocolState::Active(ClientCapabilities::legacy()));
+        let pump = tokio::spawn(pump_outgoing(
+            sink.clone(),
+            receiver,
+            global_receiver,
+            protocol,
+            Arc::new(ConnectionStats::default()),
+            no_replay(),
+            DEFAULT_BATCH_WINDOW,
+        ));
+        protocol_sender.send(ws_protocol::negotiate(hello).unwrap()).unwrap();
+        // Both senders live as long as the pump
+        tokio::spawn(async move {
+            protocol_sender.closed().await;
+            drop(global_sender);
+        });
+        (sink, sender, pump)
+    }
+
+    #[tokio::test]
+    async fn test_v1_and_v2_clients_get_the_same_trade_close_in_their_own_shape() {
+        let (v1, v1_sender, v1_pump) = connect(r#"{"type": "hello", "version": 1}"#);
+        let (v2, v2_sender, v2_pump) = connect(r#"{"type": "hello", "version": 2, "events": ["order_filled", "trade_closed"]}"#);
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+
+        let fill = message(
+            "order_filled",
+            serde_json::json!({ "trade_id": "t-1", "ticket": 42, "price": 1.0947, "volume": 0.1, "closes_position": true }),
+        );
+        let closed = message("trade_closed", serde_json::json!({ "id": "t-1", "status": "closed", "exit_price": 1.0947 }));
+        for sender in [&v1_sender, &v2_sender] {
+            sender.send(fill.clone()).unwrap();
+            sender.send(closed.clone()).unwrap();
+            sender.send(message("robot_status", serde_json::json!({}))).unwrap();
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+        let v1_types: Vec<_> = v1.messages().into_iter().map(|m| m.message_type).collect();
+        assert_eq!(v1_types, vec!["trade_update", "trade_closed", "robot_status"]);
+        let update = &v1.messages()[0];
+        assert_eq!(update.data, serde_json::json!({ "id": "t-1", "status": "closed", "price": 1.0947, "volume": 0.1 }));
+
+        // v2 declared the events it wants, so robot_status is filtered out
+        let v2_messages = v2.messages();
+        let v2_types: Vec<_> = v2_messages.iter().map(|m| m.message_type.as_str()).collect();
+        assert_eq!(v2_types, vec!["order_filled", "trade_closed"]);
+        assert_eq!(v2_messages[0].data, fill.data);
+        assert_eq!(v2_messages[1].data, closed.data);
+
+        drop((v1_sender, v2_sender));
+        v1_pump.await.unwrap();
+        v2_pump.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_robot_status_burst_is_batched_in_order() {
+        let (sink, sender, pump) = connect(r#"{"type": "hello", "version": 2}"#);
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+
+        for n in 0..20u64 {
+            let mut status = message("robot_status", serde_json::json!({ "n": n }));
+            status.seq = Some(n + 1);
+            sender.send(status).unwrap();
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        }
+        tokio::time::sleep(DEFAULT_BATCH_WINDOW * 2).await;
+
+        let frames = sink.messages();
+        assert!(frames.len() >= 2 && frames.len() <= 4, "{} frames", frames.len());
+        // The first event of the burst is not held back
+        assert_eq!(frames[0].message_type, "robot_status");
+
+        let mut items = Vec::new();
+        for frame in frames {
+            if frame.message_type == "batch" {
+                let envelope: ws_protocol::BatchEnvelope = serde_json::from_value(frame.data).unwrap();
+                assert_eq!(envelope.event, "robot_status");
+                items.extend(envelope.items);
+            } else {
+                items.push(frame);
+            }
+        }
+        let order: Vec<_> = items.iter().map(|m| m.data["n"].as_u64().unwrap()).collect();
+        assert_eq!(order, (0..20).collect::<Vec<_>>());
+        assert_eq!(seqs(&items), (1..=20).collect::<Vec<_>>());
+
+        drop(sender);
+        pump.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_isolated_event_is_not_held_for_the_window() {
+        let (sink, sender, pump) = connect(r#"{"type": "hello", "version": 2}"#);
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+
+        sender.send(message("trade_update", serde_json::json!({ "id": "t-1" }))).unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        assert_eq!(sink.messages().len(), 1);
+
+        // Once the window has passed a lone event is sent alone again, without an envelope
+        tokio::time::sleep(DEFAULT_BATCH_WINDOW).await;
+        sender.send(message("trade_update", serde_json::json!({ "id": "t-2" }))).unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        let types: Vec<_> = sink.messages().into_iter().map(|m| m.message_type).collect();
+        assert_eq!(types, vec!["trade_update", "trade_update"]);
+
+        drop(sender);
+        pump.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_major_version_is_closed_with_a_reason() {
+        let (sink, sender, pump) = connect(r#"{"type": "hello", "version": 3}"#);
+        pump.await.unwrap();
+
+        let reason = sink.close_reason.lock().unwrap().clone().unwrap();
+        assert!(reason.contains("Unsupported protocol version 3"));
+        assert!(sender.send(message("trade_update", serde_json::json!({}))).is_err());
+        assert!(sink.messages().is_empty());
+    }
+
+    struct Attached {
+        sink: RecordingSink,
+        resume: mpsc::UnboundedSender<u64>,
+        connection_id: String,
+        pump: tokio::task::JoinHandle<()>,
+    }
+
+    // Registers a v1 client of `user_id` with the manager, as add_connection does
+    async fn attach(manager: &WebSocketManager, user_id: Uuid) -> Attached {
+        let (sender, receiver) = broadcast::channel(100);
+        let connection_id = Uuid::new_v4().to_string();
+        let connection = WebSocketConnection {
+            user_id,
+            connection_id: connection_id.clone(),
+            sender,
+            stats: Arc::new(ConnectionStats::default()),
+            pressure: Arc::new(ChannelPressure::default()),
+        };
+        manager.connections.write().await.insert(connection_id.clone(), connection);
+
+        let (resume, requests) = mpsc::unbounded_channel();
+        let replay = EventReplayer { user_id, log: manager.events.clone(), requests };
+        let (protocol_sender, protocol) = watch::channel(ProtocolState::Active(ClientCapabilities::legacy()));
+        let sink = RecordingSink::default();
+        let pump = tokio::spawn(pump_outgoing(
+            sink.clone(),
+            receiver,
+            manager.global_sender.subscribe(),
+            protocol,
+            Arc::new(ConnectionStats::default()),
+            replay,
+            manager.batch_window,
+        ));
+        tokio::spawn(async move { protocol_sender.closed().await });
+        Attached { sink, resume, connection_id, pump }
+    }
+
+    async fn detach(manager: &WebSocketManager, client: Attached) -> Vec<WebSocketMessage> {
+        remove_connection(&manager.connections, &client.connection_id, None).await;
+        client.pump.await.unwrap();
+        client.sink.messages()
+    }
+
+    fn seqs(messages: &[WebSocketMessage]) -> Vec<u64> {
+        messages.iter().filter_map(|m| m.seq).collect()
+    }
+
+    #[tokio::test]
+    async fn test_resume_replays_missed_events_in_order_before_live_ones() {
+        let manager = WebSocketManager::new();
+        let user_id = Uuid::new_v4();
+
+        let first = attach(&manager, user_id).await;
+        for n in 1..=2 {
+            manager.broadcast_trade_update(user_id, serde_json::json!({ "n": n })).await.unwrap();
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        let seen = detach(&manager, first).await;
+        assert_eq!(seqs(&seen), vec![1, 2]);
+
+        // Missed while disconnected
+        manager.broadcast_robot_status(user_id, serde_json::json!({ "status": "paused" })).await.unwrap();
+        manager.broadcast_trade_closed(user_id, serde_json::json!({ "id": "t-1" })).await.unwrap();
+        manager.broadcast_trade_update(user_id, serde_json::json!({ "n": 5 })).await.unwrap();
+
+        // A live event lands together with the resume request
+        let second = attach(&manager, user_id).await;
+        second.resume.send(seen.last().and_then(|m| m.seq).unwrap()).unwrap();
+        manager.broadcast_trade_update(user_id, serde_json::json!({ "n": 6 })).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        manager.broadcast_trade_update(user_id, serde_json::json!({ "n": 7 })).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+
+        let resumed = detach(&manager, second).await;
+        assert_eq!(seqs(&resumed), vec![3, 4, 5, 6, 7]);
+        let types: Vec<_> = resumed.iter().map(|m| m.message_type.as_str()).collect();
+        assert_eq!(types, vec!["robot_status", "trade_closed", "trade_update", "trade_update", "trade_update"]);
+        assert_eq!(resumed[2].data["n"], 5);
+    }
+
+    #[tokio::test]
+    async fn test_resume_past_the_ring_asks_for_a_resync() {
+        let manager = WebSocketManager::new().with_event_log(Arc::new(MemoryUserEventLog::with_capacity(3)));
+        let user_id = Uuid::new_v4();
+        for n in 1..=10 {
+            manager.broadcast_trade_update(user_id, serde_json::json!({ "n": n })).await.unwrap();
+        }
+
+        let client = attach(&manager, user_id).await;
+        client.resume.send(2).unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        manager.broadcast_trade_update(user_id, serde_json::json!({ "n": 11 })).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+
+        let messages = detach(&manager, client).await;
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].message_type, "resync_required");
+        assert_eq!(messages[0].data, serde_json::json!({ "from_seq": 2, "latest_seq": 10 }));
+        // Live delivery carries on from there
+        assert_eq!(messages[1].seq, Some(11));
+    }
+
+    #[test]
+    fn test_resume_request_is_parsed() {
+        assert_eq!(parse_resume(r#"{"action": "resume", "from_seq": 42}"#), Some(42));
+        assert_eq!(parse_resume(r#"{"action": "pause", "from_seq": 42}"#), None);
+        assert_eq!(parse_resume(r#"{"type": "subscribe_backtest", "job_id": null}"#), None);
+    }
+
+    #[test]
+    fn test_market_data_without_symbol_is_not_conflated() {
+        let stats = ConnectionStats::default();
+        let mut batch = OutgoingBatch::default();
+        batch.push(message("market_data", serde_json::json!({ "price": 1 })), &stats);
+        batch.push(message("market_data", serde_json::json!({ "price": 2 })), &stats);
+
+        assert_eq!(batch.take().len(), 2);
+        assert_eq!(stats.conflated_market_data.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_backtest_replies_with_catch_up() {
+        use crate::services::backtest_progress::{BacktestEventSink, BacktestProgressReporter};
+
+        let manager = Arc::new(WebSocketManager::new());
+        let user_id = Uuid::new_v4();
+        let job_id = Uuid::new_v4();
+
+        let sink: Arc<dyn BacktestEventSink> = manager.clone();
+        let mut reporter = BacktestProgressReporter::start(job_id, user_id, 500, manager.backtests(), sink);
+        reporter.candle(1, 0, 10_000.0).await;
+
+        let request = serde_json::json!({ "type": "subscribe_backtest", "job_id": job_id }).to_string();
+        let reply = handle_client_message(&manager.backtests(), user_id, &request).unwrap();
+        assert_eq!(reply.message_type, "backtest_progress");
+        assert_eq!(reply.data["job_id"], serde_json::json!(job_id));
+
+        assert!(handle_client_message(&manager.backtests(), user_id, "not json").is_none());
+        let unknown = serde_json::json!({ "type": "subscribe_backtest", "job_id": Uuid::new_v4() }).to_string();
+        assert!(handle_client_message(&manager.backtests(), user_id, &unknown).is_none());
+    }
+
+    #[derive(Default)]
+    struct RecordedShedAlerts(Mutex<Vec<(String, usize)>>);
+
+    #[async_trait::async_trait]
+    impl SheddingAlerts for RecordedShedAlerts {
+        async fn shedding_started(&self, channel: &str, queue_depth: usize, _estimated_bytes: u64) {
+            self.0.lock().unwrap().push((channel.to_string(), queue_depth));
+        }
+    }
+
+    fn drain_types(receiver: &mut broadcast::Receiver<WebSocketMessage>) -> Vec<String> {
+        let mut types = Vec::new();
+        while let Ok(message) = receiver.try_recv() {
+            types.push(message.message_type);
+        }
+        types
+    }
+
+    #[tokio::test]
+    async fn test_market_data_flood_is_shed_while_notifications_still_deliver() {
+        let alerts = Arc::new(RecordedShedAlerts::default());
+        let manager =
+            WebSocketManager::with_capacities(10, 10).with_shedding(ShedPolicy::new(50).with_alerts(alerts.clone()));
+
+        // A client that is not reading: nothing leaves either of its channels
+        let mut global = manager.global_sender.subscribe();
+        let user_id = Uuid::new_v4();
+        let (sender, mut own) = broadcast::channel(10);
+        let connection = WebSocketConnection {
+            user_id,
+            connection_id: "slow".to_string(),
+            sender,
+            stats: Arc::new(ConnectionStats::default()),
+            pressure: Arc::new(ChannelPressure::default()),
+        };
+        manager.connections.write().await.insert("slow".to_string(), connection);
+
+        for price in 0..500 {
+            manager.broadcast_market_data(serde_json::json!({ "symbol": "EURUSD", "price": price })).await.unwrap();
+            let quotes = message("watchlist_quotes", serde_json::json!({ "quotes": [{ "symbol": "EURUSD", "bid": price }] }));
+            manager.send_to_user(user_id, quotes).await.unwrap();
+        }
+        manager.broadcast_system_notification(serde_json::json!({ "message": "Maintenance at 22:00" })).await.unwrap();
+        manager.broadcast_journal_prompt(user_id, serde_json::json!({ "trade_id": Uuid::nil() })).await.unwrap();
+
+        // What is still queued is counted in bytes until the client reads it
+        assert!(manager.global_channel_metrics().estimated_bytes > 0);
+
+        // Market data stops at the watermark; quotes go on until the next step; notifications always get in
+        let mut expected_global = vec!["market_data".to_string(); 5];
+        expected_global.push("system_notification".to_string());
+        assert_eq!(drain_types(&mut global), expected_global);
+        let mut expected_own = vec!["watchlist_quotes".to_string(); 8];
+        expected_own.push("journal_prompt".to_string());
+        assert_eq!(drain_types(&mut own), expected_own);
+
+        let global_metrics = manager.global_channel_metrics();
+        assert!(global_metrics.shedding);
+        assert_eq!((global_metrics.shed_messages, global_metrics.capacity), (495, 10));
+        assert_eq!(global_metrics.estimated_bytes, 0);
+        assert_eq!(manager.connection_metrics().await[0].channel.shed_messages, 492);
+        let counts: Vec<(String, u64)> = manager.shed_counts().into_iter().map(|c| (c.message_type, c.count)).collect();
+        assert_eq!(
+            counts,
+            vec![("market_data".to_string(), 495), ("watchlist_quotes".to_string(), 492), ("backtest_progress".to_string(), 0)]
+        );
+
+        // Both channels started shedding, but one alert covers the storm
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while alerts.0.lock().unwrap().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(*alerts.0.lock().unwrap(), vec![("global".to_string(), 5)]);
+    }
 
     #[tokio::test]
     async fn test_websocket_manager_creation() {
@@ -228,6 +1169,7 @@ mod tests {
             message_type: "test".to_string(),
             data: serde_json::json!({"test": "data"}),
             timestamp: chrono::Utc::now(),
+            seq: None,
         };
 
         let result = manager.send_to_all(message).await;
@@ -0,0 +1,273 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashSet;
+
+use crate::services::websocket_manager::WebSocketMessage;
+
+// Current protocol major version. Clients that never send a hello are treated as v1.
+pub const VERSION: u32 = 2;
+pub const LEGACY_VERSION: u32 = 1;
+// Oldest version that receives bursts as batch envelopes; older clients get every event alone
+pub const BATCH_VERSION: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventType {
+    TradeUpdate,
+    TradeClosed,
+    OrderFilled,
+    RobotStatus,
+    MarketData,
+    WatchlistQuotes,
+    SystemNotification,
+    ResyncRequired,
+    BacktestProgress,
+    BacktestComplete,
+    BacktestFailed,
+    // A closed trade is waiting for its journal entry
+    JournalPrompt,
+    // Envelope around several events of one type
+    Batch,
+}
+
+// Events a burst of is coalesced into one batch
+pub const BATCHED_EVENTS: [EventType; 2] = [EventType::RobotStatus, EventType::TradeUpdate];
+
+// `data` of a batch message: {"event": "robot_status", "items": [<message>, ...]}. Items are
+// complete messages in the order they happened, each with its own seq.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BatchEnvelope {
+    pub event: String,
+    pub items: Vec<WebSocketMessage>,
+}
+
+impl EventType {
+    pub fn parse(raw: &str) -> Option<EventType> {
+        match raw {
+            "trade_update" => Some(EventType::TradeUpdate),
+            "trade_closed" => Some(EventType::TradeClosed),
+            "order_filled" => Some(EventType::OrderFilled),
+            "robot_status" => Some(EventType::RobotStatus),
+            "market_data" => Some(EventType::MarketData),
+            "watchlist_quotes" => Some(EventType::WatchlistQuotes),
+            "system_notification" => Some(EventType::SystemNotification),
+            "resync_required" => Some(EventType::ResyncRequired),
+            "backtest_progress" => Some(EventType::BacktestProgress),
+            "backtest_complete" => Some(EventType::BacktestComplete),
+            "backtest_failed" => Some(EventType::BacktestFailed),
+            "journal_prompt" => Some(EventType::JournalPrompt),
+            "batch" => Some(EventType::Batch),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventType::TradeUpdate => "trade_update",
+            EventType::TradeClosed => "trade_closed",
+            EventType::OrderFilled => "order_filled",
+            EventType::RobotStatus => "robot_status",
+            EventType::MarketData => "market_data",
+            EventType::WatchlistQuotes => "watchlist_quotes",
+            EventType::SystemNotification => "system_notification",
+            EventType::ResyncRequired => "resync_required",
+            EventType::BacktestProgress => "backtest_progress",
+            EventType::BacktestComplete => "backtest_complete",
+            EventType::BacktestFailed => "backtest_failed",
+            EventType::JournalPrompt => "journal_prompt",
+            EventType::Batch => "batch",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientCapabilities {
+    pub version: u32,
+    // None accepts every event the version knows about
+    pub events: Option<HashSet<String>>,
+}
+
+impl ClientCapabilities {
+    pub fn legacy() -> Self {
+        ClientCapabilities {
+            version: LEGACY_VERSION,
+            events: None,
+        }
+    }
+
+    fn accepts(&self, message_type: &str) -> bool {
+        self.events.as_ref().is_none_or(|events| events.contains(message_type))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProtocolState {
+    Active(ClientCapabilities),
+    // The connection is closed with this reason
+    Rejected(String),
+}
+
+#[derive(Debug, Deserialize)]
+struct Hello {
+    #[serde(rename = "type")]
+    message_type: String,
+    version: u32,
+    events: Option<Vec<String>>,
+}
+
+// The handshake: {"type": "hello", "version": 2, "events": ["order_filled", ...]}.
+// Returns None when the message is not a hello at all.
+pub fn negotiate(text: &str) -> Option<ProtocolState> {
+    let hello: Hello = serde_json::from_str(text).ok()?;
+    if hello.message_type != "hello" {
+        return None;
+    }
+
+    if hello.version < LEGACY_VERSION || hello.version > VERSION {
+        return Some(ProtocolState::Rejected(format!(
+            "Unsupported protocol version {}, supported versions are {} to {}",
+            hello.version, LEGACY_VERSION, VERSION
+        )));
+    }
+
+    Some(ProtocolState::Active(ClientCapabilities {
+        version: hello.version,
+        events: hello.events.map(|events| events.into_iter().collect()),
+    }))
+}
+
+// Shapes a server event for one client. Every event type has to decide what older
+// clients get, so adding a variant does not compile until that decision is made.
+pub fn convert(message: WebSocketMessage, capabilities: &ClientCapabilities) -> Option<WebSocketMessage> {
+    let Some(event) = EventType::parse(&message.message_type) else {
+        tracing::debug!("Dropping WebSocket event of unknown type {}", message.message_type);
+        return None;
+    };
+
+    let converted = match event {
+        EventType::OrderFilled if capabilities.version < 2 => order_fill_as_trade_update(message),
+        EventType::OrderFilled
+        | EventType::TradeUpdate
+        | EventType::TradeClosed
+        | EventType::RobotStatus
+        | EventType::MarketData
+        | EventType::WatchlistQuotes
+        | EventType::SystemNotification
+        | EventType::ResyncRequired
+        | EventType::BacktestProgress
+        | EventType::BacktestComplete
+        | EventType::BacktestFailed
+        | EventType::JournalPrompt
+        | EventType::Batch => message,
+    };
+
+    // resync_required is a control message every client must get
+    let control = converted.message_type == EventType::ResyncRequired.as_str();
+    (control || capabilities.accepts(&converted.message_type)).then_some(converted)
+}
+
+// Wraps already converted events of one type; the envelope's timestamp is the newest item's
+pub fn batch(event: EventType, items: Vec<WebSocketMessage>) -> WebSocketMessage {
+    let timestamp = items.last().map(|m| m.timestamp).unwrap_or_else(chrono::Utc::now);
+    let envelope = BatchEnvelope { event: event.as_str().to_string(), items };
+    WebSocketMessage {
+        message_type: EventType::Batch.as_str().to_string(),
+        data: serde_json::to_value(envelope).unwrap_or_default(),
+        timestamp,
+        seq: None,
+    }
+}
+
+// v1 clients only know trade_update, which carries the trade's id and status
+fn order_fill_as_trade_update(message: WebSocketMessage) -> WebSocketMessage {
+    let data = &message.data;
+    let status = if data["closes_position"].as_bool().unwrap_or(false) {
+        "closed"
+    } else {
+        "open"
+    };
+
+    WebSocketMessage {
+        message_type: EventType::TradeUpdate.as_str().to_string(),
+        data: json!({
+            "id": data["trade_id"],
+            "status": status,
+            "price": data["price"],
+            "volume": data["volume"],
+        }),
+        timestamp: message.timestamp,
+        seq: message.seq,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fill() -> WebSocketMessage {
+        WebSocketMessage {
+            message_type: "order_filled".to_string(),
+            data: json!({ "trade_id": "t-1", "ticket": 42, "price": 1.1, "volume": 0.5, "closes_position": true }),
+            timestamp: chrono::Utc::now(),
+            seq: None,
+        }
+    }
+
+    #[test]
+    fn test_event_names_round_trip() {
+        for name in [
+            "trade_update",
+            "trade_closed",
+            "order_filled",
+            "robot_status",
+            "market_data",
+            "watchlist_quotes",
+            "system_notification",
+            "resync_required",
+            "backtest_progress",
+            "backtest_complete",
+            "backtest_failed",
+            "journal_prompt",
+            "batch",
+        ] {
+            assert_eq!(EventType::parse(name).unwrap().as_str(), name);
+        }
+        assert_eq!(EventType::parse("order_rejected"), None);
+    }
+
+    #[test]
+    fn test_handshake_accepts_known_versions_only() {
+        assert_eq!(negotiate(r#"{"type": "subscribe_backtest", "job_id": null}"#), None);
+        assert_eq!(negotiate("not json"), None);
+
+        let Some(ProtocolState::Active(caps)) = negotiate(r#"{"type": "hello", "version": 2, "events": ["trade_closed"]}"#) else {
+            panic!("v2 hello should be accepted");
+        };
+        assert_eq!(caps.version, 2);
+        assert!(caps.accepts("trade_closed"));
+        assert!(!caps.accepts("market_data"));
+
+        assert!(matches!(negotiate(r#"{"type": "hello", "version": 3}"#), Some(ProtocolState::Rejected(_))));
+        assert!(matches!(negotiate(r#"{"type": "hello", "version": 0}"#), Some(ProtocolState::Rejected(_))));
+    }
+
+    #[test]
+    fn test_declared_events_filter_but_resync_always_passes() {
+        let caps = ClientCapabilities {
+            version: 2,
+            events: Some(["trade_closed".to_string()].into_iter().collect()),
+        };
+        assert!(convert(fill(), &caps).is_none());
+
+        let resync = WebSocketMessage {
+            message_type: "resync_required".to_string(),
+            data: json!({}),
+            timestamp: chrono::Utc::now(),
+            seq: None,
+        };
+        assert!(convert(resync, &caps).is_some());
+
+        let unknown = WebSocketMessage { message_type: "test".to_string(), ..fill() };
+        assert!(convert(unknown, &ClientCapabilities::legacy()).is_none());
+    }
+}
@@ -0,0 +1,221 @@
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::time::Instant;
+
+use crate::services::task_supervisor::{spawn_supervised, TaskClass};
+use crate::services::websocket_manager::WebSocketMessage;
+use crate::services::NotificationService;
+
+pub const DEFAULT_SHED_WATERMARK_PERCENT: u8 = 80;
+
+// Message classes a backed-up channel drops at the sender, lowest priority first. Each can be
+// recovered from the next one sent (or a backtest's catch-up), unlike notifications and trade
+// and robot events, which are never shed.
+pub const SHED_ORDER: [&str; 3] = ["market_data", "watchlist_quotes", "backtest_progress"];
+
+// Shedding that keeps starting and stopping around the watermark alerts at most this often
+const ALERT_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+#[async_trait]
+pub trait SheddingAlerts: Send + Sync {
+    async fn shedding_started(&self, channel: &str, queue_depth: usize, estimated_bytes: u64);
+}
+
+pub struct AdminSheddingAlerts {
+    notifications: Arc<NotificationService>,
+    admin_email: String,
+}
+
+impl AdminSheddingAlerts {
+    pub fn new(notifications: Arc<NotificationService>, admin_email: String) -> Self {
+        AdminSheddingAlerts { notifications, admin_email }
+    }
+}
+
+#[async_trait]
+impl SheddingAlerts for AdminSheddingAlerts {
+    async fn shedding_started(&self, channel: &str, queue_depth: usize, estimated_bytes: u64) {
+        let alert = format!(
+            "WebSocket channel {} passed its high-watermark with {} queued messages (~{} KB); market data is being shed",
+            channel,
+            queue_depth,
+            estimated_bytes / 1024
+        );
+        if let Err(e) = self.notifications.send_system_alert(&self.admin_email, &alert).await {
+            tracing::error!("Could not alert the admin about WebSocket shedding: {}", e);
+        }
+    }
+}
+
+// Sender-side state of one broadcast channel
+#[derive(Debug, Default)]
+pub struct ChannelPressure {
+    shedding: AtomicBool,
+    shed_messages: AtomicU64,
+    // Running average of the serialized size of sent messages, for the memory estimate
+    avg_message_bytes: AtomicU64,
+}
+
+impl ChannelPressure {
+    fn record_size(&self, message: &WebSocketMessage) {
+        let size = (message.message_type.len() + serde_json::to_string(&message.data).map(|s| s.len()).unwrap_or(0)) as u64;
+        let avg = self.avg_message_bytes.load(Ordering::Relaxed);
+        let avg = if avg == 0 { size } else { (avg * 7 + size) / 8 };
+        self.avg_message_bytes.store(avg, Ordering::Relaxed);
+    }
+
+    pub fn metrics(&self, queue_depth: usize, capacity: usize) -> WebSocketChannelMetrics {
+        WebSocketChannelMetrics {
+            queue_depth,
+            capacity,
+            estimated_bytes: queue_depth as u64 * self.avg_message_bytes.load(Ordering::Relaxed),
+            shedding: self.shedding.load(Ordering::Relaxed),
+            shed_messages: self.shed_messages.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// Queue depth counts messages not yet taken by every receiver, so one slow client keeps it high
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct WebSocketChannelMetrics {
+    pub queue_depth: usize,
+    pub capacity: usize,
+    pub estimated_bytes: u64,
+    pub shedding: bool,
+    pub shed_messages: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct WebSocketShedCount {
+    pub message_type: String,
+    pub count: u64,
+}
+
+// Decides at send time whether a message goes into a channel. Past the watermark the lowest
+// class in SHED_ORDER is dropped, and each further step towards a full channel drops the
+// next one, so a storm of market data cannot fill the buffers ahead of notifications.
+pub struct ShedPolicy {
+    watermark_percent: u8,
+    shed_counts: [AtomicU64; SHED_ORDER.len()],
+    alerts: Option<Arc<dyn SheddingAlerts>>,
+    last_alert: Mutex<Option<Instant>>,
+}
+
+impl ShedPolicy {
+    pub fn new(watermark_percent: u8) -> Self {
+        ShedPolicy {
+            watermark_percent: watermark_percent.clamp(1, 100),
+            shed_counts: Default::default(),
+            alerts: None,
+            last_alert: Mutex::new(None),
+        }
+    }
+
+    pub fn with_alerts(mut self, alerts: Arc<dyn SheddingAlerts>) -> Self {
+        self.alerts = Some(alerts);
+        self
+    }
+
+    fn watermark(&self, capacity: usize) -> usize {
+        (capacity * self.watermark_percent as usize / 100).max(1)
+    }
+
+    // How many classes of SHED_ORDER are shed at this depth
+    pub fn shed_classes(&self, queue_depth: usize, capacity: usize) -> usize {
+        let watermark = self.watermark(capacity);
+        if queue_depth < watermark {
+            return 0;
+        }
+        let headroom = capacity.saturating_sub(watermark).max(1);
+        let escalation = (queue_depth - watermark) * (SHED_ORDER.len() - 1) / headroom;
+        (1 + escalation).min(SHED_ORDER.len())
+    }
+
+    pub fn send(
+        &self,
+        channel: &str,
+        sender: &broadcast::Sender<WebSocketMessage>,
+        capacity: usize,
+        pressure: &ChannelPressure,
+        message: WebSocketMessage,
+    ) {
+        let queue_depth = sender.len();
+        let shed_classes = self.shed_classes(queue_depth, capacity);
+        let was_shedding = pressure.shedding.swap(shed_classes > 0, Ordering::Relaxed);
+        if shed_classes > 0 && !was_shedding {
+            let estimated_bytes = pressure.metrics(queue_depth, capacity).estimated_bytes;
+            tracing::warn!(
+                "WebSocket channel {} is shedding with {} of {} messages queued (~{} bytes)",
+                channel,
+                queue_depth,
+                capacity,
+                estimated_bytes
+            );
+            self.alert(channel, queue_depth, estimated_bytes);
+        }
+
+        if let Some(class) = SHED_ORDER[..shed_classes].iter().position(|c| *c == message.message_type) {
+            pressure.shed_messages.fetch_add(1, Ordering::Relaxed);
+            self.shed_counts[class].fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        pressure.record_size(&message);
+        let _ = sender.send(message);
+    }
+
+    fn alert(&self, channel: &str, queue_depth: usize, estimated_bytes: u64) {
+        let Some(alerts) = self.alerts.clone() else {
+            return;
+        };
+        {
+            let mut last_alert = self.last_alert.lock().unwrap();
+            let now = Instant::now();
+            if last_alert.is_some_and(|at| now < at + ALERT_INTERVAL) {
+                return;
+            }
+            *last_alert = Some(now);
+        }
+        let channel = channel.to_string();
+        spawn_supervised("websocket:shedding_alert", TaskClass::Background, async move {
+            alerts.shedding_started(&channel, queue_depth, estimated_bytes).await;
+        });
+    }
+
+    pub fn shed_counts(&self) -> Vec<WebSocketShedCount> {
+        SHED_ORDER
+            .iter()
+            .zip(&self.shed_counts)
+            .map(|(message_type, count)| WebSocketShedCount {
+                message_type: message_type.to_string(),
+                count: count.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+}
+
+impl Default for ShedPolicy {
+    fn default() -> Self {
+        Self::new(DEFAULT_SHED_WATERMARK_PERCENT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classes_are_shed_in_order_as_the_channel_fills() {
+        let policy = ShedPolicy::new(50);
+        let levels: Vec<usize> = (0..=10).map(|depth| policy.shed_classes(depth, 10)).collect();
+        assert_eq!(levels, vec![0, 0, 0, 0, 0, 1, 1, 1, 2, 2, 3]);
+
+        // A watermark at full capacity only sheds market data, and only once the channel is full
+        let policy = ShedPolicy::new(100);
+        assert_eq!((policy.shed_classes(9, 10), policy.shed_classes(10, 10)), (0, 1));
+    }
+}
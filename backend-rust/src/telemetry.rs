@@ -0,0 +1,264 @@
+use axum::{
+    extract::{MatchedPath, Request},
+    http::HeaderMap,
+    middleware::Next,
+    response::Response,
+};
+use opentelemetry::{
+    global,
+    propagation::{Extractor, Injector},
+    trace::{Span as _, SpanBuilder, SpanKind},
+    KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    runtime,
+    trace::{Sampler, Tracer, TracerProvider},
+    Resource,
+};
+use serde::Deserialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime};
+use tower_http::trace::{DefaultMakeSpan, MakeSpan};
+use tracing::{field::Visit, Instrument, Span, Subscriber};
+use tracing_opentelemetry::{OpenTelemetrySpanExt, OtelData, PreSampledTracer};
+use tracing_subscriber::{
+    filter::{filter_fn, Targets},
+    layer::{Context, Layer},
+    registry::LookupSpan,
+};
+
+pub const DEFAULT_SERVICE_NAME: &str = "trading-saas-backend";
+// Where sqlx reports each statement it ran, with how long it took
+const SQLX_QUERY_TARGET: &str = "sqlx::query";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TelemetryConfig {
+    // OTLP/HTTP collector base URL, e.g. http://otel-collector:4318; traces are only exported when set
+    pub otlp_endpoint: Option<String>,
+    pub service_name: String,
+    // Share of new traces kept, 0.0 to 1.0; requests arriving with a traceparent follow the caller's decision
+    pub sample_ratio: f64,
+}
+
+// Set once a tracer is installed; everything else here is skipped on this one branch until then
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// The exporting provider, or None when no endpoint is configured. Keep it to flush on shutdown.
+pub fn provider(config: &TelemetryConfig) -> anyhow::Result<Option<TracerProvider>> {
+    let Some(endpoint) = config.otlp_endpoint.as_deref() else {
+        return Ok(None);
+    };
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+        .build()?;
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(config.sample_ratio))))
+        .with_resource(Resource::new([KeyValue::new("service.name", config.service_name.clone())]))
+        .build();
+    Ok(Some(provider))
+}
+
+// Layers for the subscriber registry: spans go to `tracer`, and each statement sqlx reports
+// becomes a child span of whatever span ran it. None adds nothing.
+pub fn layer<S>(tracer: Option<Tracer>) -> Option<Box<dyn Layer<S> + Send + Sync>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a> + Send + Sync,
+{
+    let tracer = tracer?;
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    ENABLED.store(true, Ordering::Relaxed);
+
+    let spans = Targets::new()
+        .with_target("trading_saas_backend", tracing::Level::INFO)
+        .with_target("tower_http", tracing::Level::DEBUG);
+    let queries = spans.clone();
+    let otel = tracing_opentelemetry::layer().with_tracer(tracer.clone()).with_filter(spans);
+    // Sees the spans the OpenTelemetry layer sees, to find the parent, and the sqlx events
+    let statements = QuerySpans { tracer }.with_filter(filter_fn(move |metadata| {
+        metadata.target() == SQLX_QUERY_TARGET || (metadata.is_span() && queries.would_enable(metadata.target(), metadata.level()))
+    }));
+    Some(Box::new(otel.and_then(statements)))
+}
+
+// Turns sqlx's per-statement event into a client span after the fact, backdated by the elapsed
+// time sqlx measured. Statements run outside any traced span are left out.
+struct QuerySpans {
+    tracer: Tracer,
+}
+
+impl<S> Layer<S> for QuerySpans
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_event(&self, event: &tracing::Event<'_>, ctx: Context<'_, S>) {
+        if event.metadata().target() != SQLX_QUERY_TARGET {
+            return;
+        }
+        let Some(span) = ctx.event_span(event) else {
+            return;
+        };
+        let parent_cx = {
+            let mut extensions = span.extensions_mut();
+            let Some(data) = extensions.get_mut::<OtelData>() else {
+                return;
+            };
+            self.tracer.sampled_context(data)
+        };
+
+        let mut fields = QueryFields::default();
+        event.record(&mut fields);
+        let end = SystemTime::now();
+        let start = end.checked_sub(fields.elapsed).unwrap_or(end);
+        let mut attributes = vec![KeyValue::new("db.system", "postgresql")];
+        if let Some(statement) = fields.statement {
+            attributes.push(KeyValue::new("db.statement", statement));
+        }
+        attributes.push(KeyValue::new("db.rows_affected", fields.rows_affected as i64));
+        attributes.push(KeyValue::new("db.rows_returned", fields.rows_returned as i64));
+
+        let mut query_span = SpanBuilder::from_name(fields.summary.unwrap_or_else(|| "db.query".to_string()))
+            .with_kind(SpanKind::Client)
+            .with_start_time(start)
+            .with_attributes(attributes)
+            .start_with_context(&self.tracer, &parent_cx);
+        query_span.end_with_timestamp(end);
+    }
+}
+
+#[derive(Default)]
+struct QueryFields {
+    summary: Option<String>,
+    statement: Option<String>,
+    rows_affected: u64,
+    rows_returned: u64,
+    elapsed: Duration,
+}
+
+impl Visit for QueryFields {
+    fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+        match field.name() {
+            "summary" => self.summary = Some(value.to_string()),
+            "db.statement" => self.statement = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    fn record_u64(&mut self, field: &tracing::field::Field, value: u64) {
+        match field.name() {
+            "rows_affected" => self.rows_affected = value,
+            "rows_returned" => self.rows_returned = value,
+            _ => {}
+        }
+    }
+
+    fn record_f64(&mut self, field: &tracing::field::Field, value: f64) {
+        if field.name() == "elapsed_secs" {
+            self.elapsed = Duration::try_from_secs_f64(value).unwrap_or_default();
+        }
+    }
+
+    fn record_debug(&mut self, _field: &tracing::field::Field, _value: &dyn std::fmt::Debug) {}
+}
+
+// The TraceLayer's request span. With tracing on it is named after the matched route and
+// continues the caller's trace when the request carries a traceparent.
+#[derive(Debug, Clone, Default)]
+pub struct HttpSpan;
+
+impl<B> MakeSpan<B> for HttpSpan {
+    fn make_span(&mut self, request: &axum::http::Request<B>) -> Span {
+        if !enabled() {
+            return DefaultMakeSpan::new().make_span(request);
+        }
+        let route = request
+            .extensions()
+            .get::<MatchedPath>()
+            .map(|path| path.as_str())
+            .unwrap_or_else(|| request.uri().path());
+        let span = tracing::info_span!(
+            "request",
+            otel.name = %format!("{} {}", request.method(), route),
+            otel.kind = "server",
+            http.method = %request.method(),
+            http.route = route,
+            http.target = %request.uri(),
+        );
+        let parent = global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(request.headers())));
+        span.set_parent(parent);
+        span
+    }
+}
+
+// Innermost middleware: a span for the handler itself, so time spent in the outer layers
+// (auth, CORS) shows up between the request and the handler
+pub async fn handler_span(request: Request, next: Next) -> Response {
+    if !enabled() {
+        return next.run(request).await;
+    }
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| request.uri().path().to_string());
+    let span = tracing::info_span!("handler", otel.name = %format!("handler {}", route), http.route = %route);
+    next.run(request).instrument(span).await
+}
+
+// Sends an outbound call in a client span named `operation`, with that span's traceparent so
+// the callee joins the trace
+pub async fn send(operation: &'static str, request: reqwest::RequestBuilder) -> reqwest::Result<reqwest::Response> {
+    if !enabled() {
+        return request.send().await;
+    }
+    let span = tracing::info_span!("outbound", otel.name = operation, otel.kind = "client");
+    let mut headers = reqwest::header::HeaderMap::new();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&span.context(), &mut HeaderInjector(&mut headers))
+    });
+    request.headers(headers).send().instrument(span).await
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+struct HeaderInjector<'a>(&'a mut reqwest::header::HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            reqwest::header::HeaderName::from_bytes(key.as_bytes()),
+            reqwest::header::HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_endpoint_means_no_provider() {
+        let config = TelemetryConfig { otlp_endpoint: None, service_name: DEFAULT_SERVICE_NAME.to_string(), sample_ratio: 1.0 };
+        assert!(provider(&config).unwrap().is_none());
+        assert!(layer::<tracing_subscriber::Registry>(None).is_none());
+    }
+}
@@ -0,0 +1,111 @@
+// The 401/403/404 matrix: who may reach which routes, independent of what the handlers do
+use axum::http::{Method, StatusCode};
+use chrono::Duration;
+use serde_json::json;
+use sqlx::PgPool;
+use trading_saas_backend::services::auth_service::{AuthService, TokenSettings};
+use uuid::Uuid;
+
+use crate::common::{RobotBuilder, TestApp, UserBuilder};
+
+const USER_ROUTES: &[(Method, &str)] = &[
+    (Method::GET, "/api/v1/auth/me"),
+    (Method::GET, "/api/v1/robots"),
+    (Method::GET, "/api/v1/trades"),
+    (Method::GET, "/api/v1/brokers"),
+    (Method::GET, "/api/v1/dashboard"),
+    (Method::GET, "/api/v1/statements/2024/3"),
+    (Method::GET, "/api/v1/jobs"),
+];
+
+const ADMIN_ROUTES: &[(Method, &str)] = &[
+    (Method::GET, "/api/v1/admin/users"),
+    (Method::GET, "/api/v1/admin/stats"),
+    (Method::GET, "/api/v1/admin/health"),
+    (Method::GET, "/api/v1/admin/settings/runtime"),
+    (Method::GET, "/api/v1/admin/settings/maintenance/brokers"),
+    (Method::GET, "/api/v1/admin/templates"),
+];
+
+#[sqlx::test]
+async fn test_protected_routes_need_a_valid_token(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let deleted = UserBuilder::new().create(app.pool()).await;
+    sqlx::query("DELETE FROM users WHERE id = $1").bind(deleted.id).execute(app.pool()).await.unwrap();
+    let user = UserBuilder::new().create(app.pool()).await;
+    let settings = app.state.config.token_settings();
+    let tokens = [
+        "not-a-jwt".to_string(),
+        AuthService::create_token(Uuid::new_v4(), 0, &TokenSettings { secret: "some-other-secret", ..settings.clone() }).unwrap(),
+        AuthService::create_token(deleted.id, 0, &settings).unwrap(),
+        AuthService::create_token(user.id, 0, &TokenSettings { lifetime: Duration::minutes(-5), ..settings.clone() }).unwrap(),
+        AuthService::create_token(user.id, 0, &TokenSettings { issuer: "someone-else", ..settings.clone() }).unwrap(),
+        AuthService::create_token(user.id, 0, &TokenSettings { audience: "another-api", ..settings.clone() }).unwrap(),
+    ];
+
+    for (method, path) in USER_ROUTES.iter().chain(ADMIN_ROUTES) {
+        let clients = std::iter::once(app.anonymous()).chain(tokens.iter().map(|token| app.with_token(token)));
+        for client in clients {
+            let response = client.send(method.clone(), path, None).await;
+            assert_eq!(response.status, StatusCode::UNAUTHORIZED, "{} {}", method, path);
+            assert_eq!(response.body["status"], 401);
+        }
+    }
+}
+
+#[sqlx::test]
+async fn test_admin_routes_refuse_other_users(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().plan("elite").create(app.pool()).await;
+    let admin = UserBuilder::new().admin().create(app.pool()).await;
+
+    for (method, path) in ADMIN_ROUTES {
+        let response = app.client_as(&user).send(method.clone(), path, None).await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN, "{} {}", method, path);
+        let response = app.client_as(&admin).send(method.clone(), path, None).await;
+        assert_eq!(response.status, StatusCode::OK, "{} {}: {}", method, path, response.body);
+    }
+    for (method, path) in USER_ROUTES {
+        let response = app.client_as(&user).send(method.clone(), path, None).await;
+        assert_eq!(response.status, StatusCode::OK, "{} {}: {}", method, path, response.body);
+    }
+}
+
+#[sqlx::test]
+async fn test_users_cannot_read_each_other(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().create(app.pool()).await;
+    let other = UserBuilder::new().create(app.pool()).await;
+    let admin = UserBuilder::new().admin().create(app.pool()).await;
+
+    let path = format!("/api/v1/users/{}", other.id);
+    assert_eq!(app.client_as(&user).get(&path).await.status, StatusCode::FORBIDDEN);
+    assert_eq!(app.client_as(&other).get(&path).await.status, StatusCode::OK);
+    assert_eq!(app.client_as(&admin).get(&path).await.status, StatusCode::OK);
+    let missing = format!("/api/v1/users/{}", Uuid::new_v4());
+    assert_eq!(app.client_as(&admin).get(&missing).await.status, StatusCode::NOT_FOUND);
+}
+
+#[sqlx::test]
+async fn test_records_of_other_users_are_not_found(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let owner = UserBuilder::new().plan("pro").create(app.pool()).await;
+    let other = UserBuilder::new().plan("pro").create(app.pool()).await;
+    let robot = RobotBuilder::new(&owner).create(app.pool()).await;
+    let client = app.client_as(&other);
+
+    // Someone else's id answers exactly like one that does not exist
+    for id in [robot.id, Uuid::new_v4()] {
+        let cases = [
+            (Method::PATCH, format!("/api/v1/robots/{}", id), Some(json!({ "notes": "x" }))),
+            (Method::GET, format!("/api/v1/robots/{}/changes", id), None),
+            (Method::GET, format!("/api/v1/robots/{}/preflight", id), None),
+            (Method::POST, format!("/api/v1/robots/{}/stop", id), Some(json!({}))),
+            (Method::POST, format!("/api/v1/brokers/{}/test", id), Some(json!({}))),
+        ];
+        for (method, path, body) in cases {
+            let response = client.send(method.clone(), &path, body).await;
+            assert_eq!(response.status, StatusCode::NOT_FOUND, "{} {}: {}", method, path, response.body);
+        }
+    }
+}
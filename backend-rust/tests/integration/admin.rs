@@ -0,0 +1,318 @@
+use axum::http::StatusCode;
+use serde_json::json;
+use sqlx::PgPool;
+
+use trading_saas_backend::models::{AuditEntry, AuthEvent, User};
+
+use crate::common::{BrokerBuilder, RobotBuilder, TestApp, UserBuilder, TEST_PASSWORD};
+
+#[sqlx::test]
+async fn test_template_edit_preview_and_activation(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let admin = UserBuilder::new().admin().create(app.pool()).await;
+    let client = app.client_as(&admin);
+
+    // The migration seeds the built-ins as active version 1
+    let seeded = app.state.templates.render("welcome", "en", &[("name", "Ana")]).await.unwrap();
+    assert_eq!(seeded.version, Some(1));
+    assert!(seeded.body.contains("Welcome to Trading SaaS Platform, Ana!"));
+
+    let typo = client
+        .post("/api/v1/admin/templates/welcome", json!({ "subject": "Hi {{nmae}}", "body": "<p>Hi</p>" }))
+        .await
+        .expect(StatusCode::BAD_REQUEST);
+    assert!(typo["error"].as_str().unwrap().contains("{{nmae}}"));
+
+    let saved = client
+        .post("/api/v1/admin/templates/welcome", json!({ "subject": "Hi {{name}}", "body": "<p>Welcome aboard, {{name}}</p>" }))
+        .await
+        .expect(StatusCode::CREATED);
+    assert_eq!((saved["version"].as_i64(), saved["is_active"].as_bool()), (Some(2), Some(false)));
+
+    let preview = client
+        .post("/api/v1/admin/templates/welcome/preview", json!({ "version": 2 }))
+        .await
+        .expect(StatusCode::OK);
+    assert_eq!(preview["subject"], "Hi Ana");
+    assert_eq!(preview["body"], "<p>Welcome aboard, Ana</p>");
+    // Previewing sent nothing and activated nothing
+    let outbox: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM email_outbox").fetch_one(app.pool()).await.unwrap();
+    assert_eq!(outbox, 0);
+    assert_eq!(app.state.templates.render("welcome", "en", &[("name", "Bo")]).await.unwrap().version, Some(1));
+
+    client
+        .post("/api/v1/admin/templates/welcome/activate", json!({ "version": 2 }))
+        .await
+        .expect(StatusCode::OK);
+    let sent = app.state.templates.render("welcome", "en", &[("name", "Bo")]).await.unwrap();
+    assert_eq!((sent.version, sent.subject.as_str()), (Some(2), "Hi Bo"));
+
+    let versions = client.get("/api/v1/admin/templates").await.expect(StatusCode::OK);
+    let welcome: Vec<(i64, bool)> = versions
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|t| t["key"] == "welcome")
+        .map(|t| (t["version"].as_i64().unwrap(), t["is_active"].as_bool().unwrap()))
+        .collect();
+    assert_eq!(welcome, vec![(2, true), (1, false)]);
+
+    client
+        .post("/api/v1/admin/templates/welcome/activate", json!({ "version": 7 }))
+        .await
+        .expect(StatusCode::NOT_FOUND);
+}
+
+#[sqlx::test]
+async fn test_support_reads_and_admins_change_roles(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let admin = UserBuilder::new().admin().create(app.pool()).await;
+    let support = UserBuilder::new().support().create(app.pool()).await;
+    let user = UserBuilder::new().create(app.pool()).await;
+    let path = format!("/api/v1/admin/users/{}/role", user.id);
+
+    let staff = app.client_as(&support);
+    staff.get("/api/v1/admin/users").await.expect(StatusCode::OK);
+    staff.get("/api/v1/admin/stats").await.expect(StatusCode::OK);
+    assert_eq!(staff.get("/api/v1/admin/templates").await.status, StatusCode::FORBIDDEN);
+    assert_eq!(staff.put(&path, json!({ "role": "support" })).await.status, StatusCode::FORBIDDEN);
+    let flag = json!({ "enabled": true });
+    assert_eq!(staff.put("/api/v1/admin/feature-flags/beta", flag).await.status, StatusCode::FORBIDDEN);
+
+    let client = app.client_as(&admin);
+    let updated = client.put(&path, json!({ "role": "support" })).await.expect(StatusCode::OK);
+    assert_eq!((updated["role"].as_str(), updated["is_superuser"].as_bool()), (Some("support"), Some(false)));
+    let me = app.client_as(&user).get("/api/v1/auth/me").await.expect(StatusCode::OK);
+    assert_eq!(me["role"], "support");
+    app.client_as(&user).get("/api/v1/admin/users").await.expect(StatusCode::OK);
+
+    let promoted = client.put(&path, json!({ "role": "admin" })).await.expect(StatusCode::OK);
+    assert_eq!(promoted["is_superuser"], true);
+    assert_eq!(client.put(&path, json!({ "role": "owner" })).await.status, StatusCode::UNPROCESSABLE_ENTITY);
+    let own = format!("/api/v1/admin/users/{}/role", admin.id);
+    assert_eq!(client.put(&own, json!({ "role": "user" })).await.status, StatusCode::FORBIDDEN);
+    let missing = format!("/api/v1/admin/users/{}/role", uuid::Uuid::new_v4());
+    assert_eq!(client.put(&missing, json!({ "role": "user" })).await.status, StatusCode::NOT_FOUND);
+}
+
+#[sqlx::test]
+async fn test_support_filters_security_events(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let support = UserBuilder::new().support().create(app.pool()).await;
+    let user = UserBuilder::new().create(app.pool()).await;
+    let now = chrono::Utc::now();
+    let events = [
+        AuthEvent::new(Some(user.id), "login", "failure", None, Some("203.0.113.9".to_string()), now).with_reason("invalid_password"),
+        AuthEvent::new(None, "login", "failure", None, Some("203.0.113.9".to_string()), now).with_reason("unknown_email"),
+        AuthEvent::new(Some(user.id), "login", "success", Some("curl/8.0"), None, now),
+        AuthEvent::new(Some(support.id), "password_changed", "success", None, Some("203.0.113.9".to_string()), now),
+    ];
+    for event in &events {
+        AuthEvent::create(app.pool(), event).await.unwrap();
+    }
+
+    let staff = app.client_as(&support);
+    let failures = staff
+        .get("/api/v1/admin/security-events?outcome=failure&ip_address=203.0.113.9")
+        .await
+        .expect(StatusCode::OK);
+    assert_eq!(failures.as_array().unwrap().len(), 2);
+    let own = staff.get(&format!("/api/v1/admin/security-events?user_id={}", user.id)).await.expect(StatusCode::OK);
+    assert_eq!(own.as_array().unwrap().len(), 2);
+    let changes = staff.get("/api/v1/admin/security-events?event_type=password_changed").await.expect(StatusCode::OK);
+    assert_eq!(changes[0]["user_id"], support.id.to_string());
+    assert_eq!(staff.get("/api/v1/admin/security-events?event_type=signin").await.status, StatusCode::BAD_REQUEST);
+    assert_eq!(app.client_as(&user).get("/api/v1/admin/security-events").await.status, StatusCode::FORBIDDEN);
+}
+
+#[sqlx::test]
+async fn test_impersonation_is_audited_and_kept_away_from_credentials(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let admin = UserBuilder::new().admin().create(app.pool()).await;
+    let support = UserBuilder::new().support().create(app.pool()).await;
+    let user = UserBuilder::new().create(app.pool()).await;
+    let connection = BrokerBuilder::new(&user).create(&app).await;
+    let path = format!("/api/v1/admin/users/{}/impersonate", user.id);
+
+    assert_eq!(app.client_as(&support).post(&path, json!({})).await.status, StatusCode::FORBIDDEN);
+    let other_staff = format!("/api/v1/admin/users/{}/impersonate", support.id);
+    assert_eq!(app.client_as(&admin).post(&other_staff, json!({})).await.status, StatusCode::FORBIDDEN);
+
+    let started = app.client_as(&admin).post(&path, json!({})).await.expect(StatusCode::CREATED);
+    assert_eq!(started["user"]["id"], user.id.to_string());
+    let audited = AuditEntry::find_by_target(app.pool(), "user", user.id).await.unwrap();
+    assert_eq!((audited.len(), audited[0].action.as_str()), (1, "user.impersonate"));
+    assert_eq!((audited[0].actor_type.as_str(), audited[0].actor.clone()), ("admin", admin.id.to_string()));
+
+    // Reading works as the user; credentials and trading do not
+    let as_user = app.with_token(started["token"].as_str().unwrap());
+    let me = as_user.get("/api/v1/auth/me").await.expect(StatusCode::OK);
+    assert_eq!(me["id"], user.id.to_string());
+    as_user.get("/api/v1/brokers").await.expect(StatusCode::OK);
+    let credentials = format!("/api/v1/brokers/{}/credentials", connection.id);
+    let update = as_user.put(&credentials, json!({ "api_key": "k", "api_secret": "s" })).await;
+    assert_eq!(update.status, StatusCode::FORBIDDEN);
+    let bridge = format!("/api/v1/brokers/{}/bridge-token", connection.id);
+    assert_eq!(as_user.post(&bridge, json!({})).await.status, StatusCode::FORBIDDEN);
+    let password = json!({ "current_password": "x", "new_password": "y" });
+    assert_eq!(as_user.put("/api/v1/auth/password", password).await.status, StatusCode::FORBIDDEN);
+    assert_eq!(as_user.get("/api/v1/admin/users").await.status, StatusCode::FORBIDDEN);
+}
+
+#[sqlx::test]
+async fn test_deactivation_stops_robots_and_signs_the_user_out(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let admin = UserBuilder::new().admin().create(app.pool()).await;
+    let other_admin = UserBuilder::new().admin().create(app.pool()).await;
+    let support = UserBuilder::new().support().create(app.pool()).await;
+    let user = UserBuilder::new().create(app.pool()).await;
+    let running = RobotBuilder::new(&user).create(app.pool()).await;
+    let idle = RobotBuilder::new(&user).create(app.pool()).await;
+    sqlx::query("UPDATE trading_robots SET status = 'active' WHERE id = $1").bind(running.id).execute(app.pool()).await.unwrap();
+    let login = json!({ "email": user.email, "password": TEST_PASSWORD });
+    let token = app.anonymous().post("/api/v1/auth/login", login.clone()).await.expect(StatusCode::OK)["token"].clone();
+    let as_user = app.with_token(token.as_str().unwrap());
+    let deactivate = |id: uuid::Uuid| format!("/api/v1/admin/users/{}/deactivate", id);
+
+    assert_eq!(app.client_as(&support).post(&deactivate(user.id), json!({})).await.status, StatusCode::FORBIDDEN);
+    assert_eq!(app.client_as(&admin).post(&deactivate(admin.id), json!({})).await.status, StatusCode::FORBIDDEN);
+    assert_eq!(app.client_as(&admin).post(&deactivate(other_admin.id), json!({})).await.status, StatusCode::FORBIDDEN);
+
+    let body = app.client_as(&admin).post(&deactivate(user.id), json!({})).await.expect(StatusCode::OK);
+    assert_eq!(body["is_active"], false);
+    let status = |id: uuid::Uuid| sqlx::query_scalar::<_, String>("SELECT status FROM trading_robots WHERE id = $1").bind(id);
+    assert_eq!(status(running.id).fetch_one(app.pool()).await.unwrap(), "stopped");
+    assert_eq!(status(idle.id).fetch_one(app.pool()).await.unwrap(), idle.status);
+    assert_eq!(as_user.get("/api/v1/auth/me").await.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(app.anonymous().post("/api/v1/auth/login", login.clone()).await.status, StatusCode::UNAUTHORIZED);
+    let audited = AuditEntry::find_by_target(app.pool(), "user", user.id).await.unwrap();
+    assert_eq!((audited.len(), audited[0].action.as_str()), (1, "user.deactivate"));
+    assert_eq!(audited[0].details["robots_stopped"], json!([running.id]));
+
+    // Reactivating lets the user sign in again; the old token and the stopped robot stay as they are
+    let path = format!("/api/v1/admin/users/{}/activate", user.id);
+    assert_eq!(app.client_as(&admin).post(&path, json!({})).await.expect(StatusCode::OK)["is_active"], true);
+    app.anonymous().post("/api/v1/auth/login", login).await.expect(StatusCode::OK);
+    assert_eq!(as_user.get("/api/v1/auth/me").await.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(status(running.id).fetch_one(app.pool()).await.unwrap(), "stopped");
+    let missing = format!("/api/v1/admin/users/{}/activate", uuid::Uuid::new_v4());
+    assert_eq!(app.client_as(&admin).post(&missing, json!({})).await.status, StatusCode::NOT_FOUND);
+}
+
+#[sqlx::test]
+async fn test_admin_user_search_filters_in_sql_and_counts_matches(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let admin = UserBuilder::new().admin().email("root@example.com").create(app.pool()).await;
+    let ana = UserBuilder::new().email("ana.souza@example.com").plan("pro").create(app.pool()).await;
+    UserBuilder::new().email("anabel@example.com").inactive().create(app.pool()).await;
+    UserBuilder::new().email("bo@example.com").plan("pro").create(app.pool()).await;
+    // Literal, so an underscore does not match any one character
+    UserBuilder::new().email("an_x@example.com").create(app.pool()).await;
+    sqlx::query("UPDATE users SET created_at = '2024-01-10T00:00:00Z' WHERE id = $1").bind(ana.id).execute(app.pool()).await.unwrap();
+    let client = app.client_as(&admin);
+    let emails = |body: &serde_json::Value| -> Vec<String> {
+        body["users"].as_array().unwrap().iter().map(|u| u["email"].as_str().unwrap().to_string()).collect()
+    };
+
+    let body = client.get("/api/v1/admin/users?email=ANA&sort=email&limit=1").await.expect(StatusCode::OK);
+    assert_eq!((emails(&body), body["total"].as_i64()), (vec!["ana.souza@example.com".to_string()], Some(2)));
+    let body = client.get("/api/v1/admin/users?email=ana&is_active=true&subscription_plan=pro").await.expect(StatusCode::OK);
+    assert_eq!(emails(&body), ["ana.souza@example.com"]);
+    let body = client.get("/api/v1/admin/users?email=n_&sort=email").await.expect(StatusCode::OK);
+    assert_eq!(emails(&body), ["an_x@example.com"]);
+
+    let body = client
+        .get("/api/v1/admin/users?created_after=2024-01-01T00:00:00Z&created_before=2024-02-01T00:00:00Z")
+        .await
+        .expect(StatusCode::OK);
+    assert_eq!((emails(&body), body["total"].as_i64()), (vec!["ana.souza@example.com".to_string()], Some(1)));
+    let backwards = client.get("/api/v1/admin/users?created_after=2024-02-01T00:00:00Z&created_before=2024-01-01T00:00:00Z").await;
+    assert_eq!(backwards.status, StatusCode::BAD_REQUEST);
+}
+
+#[sqlx::test]
+async fn test_admin_user_list_shows_deleted_accounts_only_when_asked(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let admin = UserBuilder::new().admin().create(app.pool()).await;
+    let gone = UserBuilder::new().create(app.pool()).await;
+    User::anonymize(app.pool(), gone.id, chrono::Utc::now()).await.unwrap();
+    let client = app.client_as(&admin);
+
+    let body = client.get("/api/v1/admin/users").await.expect(StatusCode::OK);
+    assert_eq!((body["users"].as_array().unwrap().len(), body["total"].as_i64()), (1, Some(1)));
+    let body = client.get("/api/v1/admin/users?include_deleted=true").await.expect(StatusCode::OK);
+    assert_eq!(body["total"].as_i64(), Some(2));
+    let deleted = body["users"].as_array().unwrap().iter().find(|u| u["id"] == json!(gone.id)).unwrap();
+    assert!(deleted["deleted_at"].is_string());
+}
+
+#[sqlx::test]
+async fn test_admins_set_a_plan_manually_and_it_is_audited(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let admin = UserBuilder::new().admin().create(app.pool()).await;
+    let support = UserBuilder::new().support().create(app.pool()).await;
+    let user = UserBuilder::new().plan("pro").create(app.pool()).await;
+    RobotBuilder::new(&user).create(app.pool()).await;
+    RobotBuilder::new(&user).create(app.pool()).await;
+    sqlx::query("UPDATE trading_robots SET status = 'active' WHERE user_id = $1").bind(user.id).execute(app.pool()).await.unwrap();
+    let path = format!("/api/v1/admin/users/{}/plan", user.id);
+    let client = app.client_as(&admin);
+
+    assert_eq!(app.client_as(&support).put(&path, json!({ "plan_name": "elite" })).await.status, StatusCode::FORBIDDEN);
+    assert_eq!(client.put(&path, json!({ "plan_name": "platinum" })).await.status, StatusCode::BAD_REQUEST);
+
+    let body = client.put(&path, json!({ "plan_name": "Essential", "reason": "support ticket 42" })).await.expect(StatusCode::OK);
+    assert_eq!((body["user"]["subscription_plan"].as_str(), body["subscription"]["manual"].as_bool()), (Some("essential"), Some(true)));
+    assert_eq!(body["subscription"]["plan_details"]["max_robots"], 1);
+    let limited = body["robots_limited"].as_array().unwrap();
+    assert_eq!(limited.len(), 1);
+    let statuses: Vec<String> = sqlx::query_scalar("SELECT status FROM trading_robots WHERE user_id = $1 ORDER BY status")
+        .bind(user.id)
+        .fetch_all(app.pool())
+        .await
+        .unwrap();
+    assert_eq!(statuses, ["active", "plan_limited"]);
+
+    // A second change updates the same subscription instead of stacking another one
+    let body = client.put(&path, json!({ "plan_name": "elite" })).await.expect(StatusCode::OK);
+    assert_eq!(body["subscription"]["plan_details"]["max_robots"], -1);
+    let rows: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM subscriptions WHERE user_id = $1").bind(user.id).fetch_one(app.pool()).await.unwrap();
+    assert_eq!(rows, 1);
+    let audited = AuditEntry::find_by_target(app.pool(), "user", user.id).await.unwrap();
+    let plans: Vec<_> = audited.iter().filter(|e| e.action == "user.plan").map(|e| e.details["to"].clone()).collect();
+    assert_eq!(plans.len(), 2);
+    assert!(plans.contains(&json!("essential")) && plans.contains(&json!("elite")));
+    assert!(audited.iter().any(|e| e.details["reason"] == "support ticket 42" && e.actor == admin.id.to_string()));
+}
+
+#[sqlx::test]
+async fn test_demoting_an_admin_needs_a_second_admin(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let (first, second, target) = (
+        UserBuilder::new().admin().create(app.pool()).await,
+        UserBuilder::new().admin().create(app.pool()).await,
+        UserBuilder::new().admin().create(app.pool()).await,
+    );
+    let path = format!("/api/v1/admin/users/{}/role", target.id);
+    let deactivate = format!("/api/v1/admin/users/{}/deactivate", target.id);
+
+    // Asking again alone does not count as a second admin
+    for _ in 0..2 {
+        let pending = app.client_as(&first).put(&path, json!({ "role": "user" })).await.expect(StatusCode::ACCEPTED);
+        assert_eq!(pending["role"], "admin");
+    }
+    assert_eq!(app.client_as(&first).post(&deactivate, json!({})).await.status, StatusCode::FORBIDDEN);
+    // A different role is a different request
+    app.client_as(&second).put(&path, json!({ "role": "support" })).await.expect(StatusCode::ACCEPTED);
+
+    let demoted = app.client_as(&second).put(&path, json!({ "role": "user" })).await.expect(StatusCode::OK);
+    assert_eq!((demoted["role"].as_str(), demoted["is_superuser"].as_bool()), (Some("user"), Some(false)));
+    app.client_as(&first).post(&deactivate, json!({})).await.expect(StatusCode::OK);
+    let audited = AuditEntry::find_by_target(app.pool(), "user", target.id).await.unwrap();
+    let actions: Vec<_> = audited.iter().map(|e| e.action.as_str()).collect();
+    assert_eq!(actions, ["user.demote_request", "user.demote_request", "user.demote_request", "user.role", "user.deactivate"]);
+
+    // Once applied, the old requests do not carry over to a later demotion
+    app.client_as(&first).put(&path, json!({ "role": "admin" })).await.expect(StatusCode::OK);
+    app.client_as(&second).put(&path, json!({ "role": "user" })).await.expect(StatusCode::ACCEPTED);
+}
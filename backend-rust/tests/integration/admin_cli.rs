@@ -0,0 +1,132 @@
+use axum::http::StatusCode;
+use serde_json::Value;
+use sqlx::PgPool;
+use std::sync::Arc;
+
+use trading_saas_backend::{
+    admin_cli::{AdminCli, Invocation},
+    errors::{AppError, Result},
+    models::{AuditEntry, Trade, TradingRobot, User, ACTOR_CLI},
+    services::NotificationService,
+};
+
+use crate::common::{BrokerBuilder, RobotBuilder, TestApp, TradeBuilder, UserBuilder};
+
+const OPERATOR: &str = "ops-alice";
+
+// Parses the command line as the binary does and runs it against the test database
+async fn run(app: &TestApp, line: &str) -> Result<Value> {
+    let args: Vec<String> = line.split_whitespace().map(String::from).collect();
+    let invocation = Invocation::parse(&args).expect("valid command line");
+    let notifications = Arc::new(NotificationService::new(None, None, None));
+    AdminCli::new(app.pool().clone(), app.state.mt5.clone(), notifications, OPERATOR)
+        .run(&invocation.command)
+        .await
+}
+
+async fn audited(app: &TestApp, target_type: &str, target_id: uuid::Uuid) -> Vec<String> {
+    let entries = AuditEntry::find_by_target(app.pool(), target_type, target_id).await.unwrap();
+    assert!(entries.iter().all(|e| e.actor_type == ACTOR_CLI && e.actor == OPERATOR));
+    entries.into_iter().map(|e| e.action).collect()
+}
+
+#[sqlx::test]
+async fn test_user_promote_and_deactivate(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().email("trader@example.com").create(app.pool()).await;
+
+    let promoted = run(&app, "user promote trader@example.com").await.unwrap();
+    assert_eq!(promoted["is_superuser"], true);
+    let deactivated = run(&app, &format!("--json user deactivate {}", user.id)).await.unwrap();
+    assert_eq!(deactivated["is_active"], false);
+
+    let stored = User::find_by_id(app.pool(), user.id).await.unwrap().unwrap();
+    assert!(stored.is_superuser && !stored.is_active);
+    // The account's existing tokens stop working on the next request
+    app.client_as(&user).get("/api/v1/trades").await.expect(StatusCode::UNAUTHORIZED);
+
+    assert_eq!(audited(&app, "user", user.id).await, ["user.promote", "user.deactivate"]);
+    let entry = &AuditEntry::find_by_target(app.pool(), "user", user.id).await.unwrap()[0];
+    assert_eq!((entry.details["email"].as_str(), entry.details["previous_role"].as_str()), (Some("trader@example.com"), Some("user")));
+
+    assert!(matches!(run(&app, "user promote nobody@example.com").await, Err(AppError::NotFound(_))));
+}
+
+#[sqlx::test]
+async fn test_robot_stop_and_reset_error(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().create(app.pool()).await;
+    let running = RobotBuilder::new(&user).name("Running").create(app.pool()).await;
+    let crashed = RobotBuilder::new(&user).name("Crashed").create(app.pool()).await;
+    TradingRobot::update_status(app.pool(), running.id, user.id, "active").await.unwrap();
+    TradingRobot::update_status(app.pool(), crashed.id, user.id, "error").await.unwrap();
+
+    let stopped = run(&app, &format!("robot stop {}", running.id)).await.unwrap();
+    assert_eq!(stopped["status"], "stopped");
+    let reset = run(&app, &format!("robot reset-error {}", crashed.id)).await.unwrap();
+    assert_eq!(reset["status"], "stopped");
+
+    // Only a robot in error can be reset, and a refused reset leaves no entry
+    assert!(matches!(run(&app, &format!("robot reset-error {}", running.id)).await, Err(AppError::Validation(_))));
+    assert_eq!(audited(&app, "robot", running.id).await, ["robot.stop"]);
+    assert_eq!(audited(&app, "robot", crashed.id).await, ["robot.reset_error"]);
+    let entry = &AuditEntry::find_by_target(app.pool(), "robot", running.id).await.unwrap()[0];
+    assert_eq!(entry.details["previous_status"], "active");
+}
+
+#[sqlx::test]
+async fn test_trade_show_and_close(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().create(app.pool()).await;
+    let connection = BrokerBuilder::new(&user).create(&app).await;
+    let robot = RobotBuilder::new(&user).connection(&connection).create(app.pool()).await;
+    let trade = TradeBuilder::new(&robot).ticket("4242").create(app.pool()).await;
+
+    let shown = run(&app, &format!("trade show {}", trade.id)).await.unwrap();
+    assert_eq!((shown["status"].as_str(), shown["corrections"].as_array().map(Vec::len)), (Some("open"), Some(0)));
+
+    let closed = run(&app, &format!("trade close {}", trade.id)).await.unwrap();
+    assert_eq!(closed["status"], "closed");
+    let stored = Trade::find_by_id(app.pool(), trade.id, user.id).await.unwrap().unwrap();
+    assert_eq!(stored.status, "closed");
+    // A long closes at the broker's bid
+    assert_eq!(stored.exit_price, Some(1.1000));
+    assert_eq!(app.broker.closed(), vec![4242]);
+
+    // Closing it again is reported, not repeated
+    let again = run(&app, &format!("trade close {}", trade.id)).await.unwrap();
+    assert_eq!(again["status"], "skipped");
+
+    assert_eq!(audited(&app, "trade", trade.id).await, ["trade.show", "trade.close", "trade.close"]);
+    let entry = &AuditEntry::find_by_target(app.pool(), "trade", trade.id).await.unwrap()[1];
+    assert_eq!(entry.details["result"]["status"], "closed");
+}
+
+#[sqlx::test]
+async fn test_reconcile_and_stats(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().email("recon@example.com").create(app.pool()).await;
+    let connection = BrokerBuilder::new(&user).create(&app).await;
+    let connected = RobotBuilder::new(&user).name("Connected").connection(&connection).create(app.pool()).await;
+    let detached = RobotBuilder::new(&user).name("Detached").create(app.pool()).await;
+    RobotBuilder::new(&user).name("Idle").create(app.pool()).await;
+    // The broker reports no positions, so the ticketed trade is an issue
+    TradeBuilder::new(&connected).ticket("777").create(app.pool()).await;
+    TradeBuilder::new(&detached).create(app.pool()).await;
+
+    let report = run(&app, "reconcile --user recon@example.com").await.unwrap();
+    let row = |name: &str| report.as_array().unwrap().iter().find(|r| r["robot"] == name).cloned().unwrap();
+    assert_eq!((row("Connected")["open_trades"].as_u64(), row("Connected")["issues"].as_u64()), (Some(1), Some(1)));
+    assert!(row("Detached")["issues"].is_null());
+    assert!(row("Detached")["error"].as_str().unwrap().contains("no broker connection"));
+    assert_eq!((row("Idle")["open_trades"].as_u64(), row("Idle")["issues"].as_u64()), (Some(0), Some(0)));
+
+    assert_eq!(audited(&app, "user", user.id).await, ["reconcile"]);
+    let entry = &AuditEntry::find_by_target(app.pool(), "user", user.id).await.unwrap()[0];
+    assert_eq!((entry.details["robots"].as_u64(), entry.details["issues"].as_u64(), entry.details["failed"].as_u64()), (Some(3), Some(1), Some(1)));
+
+    let stats = run(&app, "stats").await.unwrap();
+    assert_eq!((stats["users"].as_i64(), stats["robots"].as_i64(), stats["open_trades"].as_i64()), (Some(1), Some(3), Some(2)));
+    let latest = &AuditEntry::recent(app.pool(), 1).await.unwrap()[0];
+    assert_eq!((latest.action.as_str(), latest.target_type.as_str(), latest.target_id), ("stats", "platform", None));
+}
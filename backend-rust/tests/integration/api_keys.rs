@@ -0,0 +1,65 @@
+use axum::http::StatusCode;
+use serde_json::json;
+use sqlx::PgPool;
+
+use crate::common::{BrokerBuilder, TestApp, UserBuilder};
+
+#[sqlx::test]
+async fn test_keys_are_held_to_their_scopes(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().plan("pro").create(app.pool()).await;
+    let connection = BrokerBuilder::new(&user).create(&app).await;
+    let client = app.client_as(&user);
+
+    let read = client.post("/api/v1/api-keys", json!({ "label": "Reporting" })).await.expect(StatusCode::CREATED);
+    assert_eq!(read["scopes"], json!(["read"]));
+    let trade = client
+        .post("/api/v1/api-keys", json!({ "label": "Bot", "scopes": ["trade"] }))
+        .await
+        .expect(StatusCode::CREATED);
+    assert_eq!(trade["scopes"], json!(["read", "trade"]));
+    let (read_key, trade_key) = (read["key"].as_str().unwrap(), trade["key"].as_str().unwrap());
+    assert!(read_key.starts_with(read["prefix"].as_str().unwrap()));
+
+    let reader = app.anonymous().header("x-api-key", read_key);
+    reader.get("/api/v1/trades").await.expect(StatusCode::OK);
+    let robot = json!({ "name": "EURUSD trend", "strategy": "trend_following", "broker_connection_id": connection.id });
+    assert_eq!(reader.post("/api/v1/robots", robot.clone()).await.status, StatusCode::FORBIDDEN);
+
+    let trader = app.anonymous().header("x-api-key", trade_key);
+    trader.post("/api/v1/robots", robot).await.expect(StatusCode::OK);
+    // Trading is all a key can change; the account and the keys themselves need a signed-in user
+    let password = json!({ "current_password": "x", "new_password": "y" });
+    assert_eq!(trader.put("/api/v1/auth/password", password).await.status, StatusCode::FORBIDDEN);
+    assert_eq!(trader.get("/api/v1/api-keys").await.status, StatusCode::FORBIDDEN);
+    assert_eq!(trader.post("/api/v1/api-keys", json!({ "label": "More" })).await.status, StatusCode::FORBIDDEN);
+
+    // The key itself is never shown again
+    let keys = client.get("/api/v1/api-keys").await.expect(StatusCode::OK);
+    assert_eq!(keys.as_array().unwrap().len(), 2);
+    assert!(keys.as_array().unwrap().iter().all(|k| k.get("key").is_none() && !k["last_used_at"].is_null()));
+}
+
+#[sqlx::test]
+async fn test_revoked_and_unknown_keys_are_refused(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().create(app.pool()).await;
+    let other = UserBuilder::new().create(app.pool()).await;
+    let client = app.client_as(&user);
+
+    let response = client.post("/api/v1/api-keys", json!({ "label": "Bot", "scopes": ["withdraw"] })).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+
+    let created = client.post("/api/v1/api-keys", json!({ "label": "Bot" })).await.expect(StatusCode::CREATED);
+    let path = format!("/api/v1/api-keys/{}", created["id"].as_str().unwrap());
+    let keyed = app.anonymous().header("x-api-key", created["key"].as_str().unwrap());
+    keyed.get("/api/v1/auth/me").await.expect(StatusCode::OK);
+
+    assert_eq!(app.client_as(&other).delete(&path).await.status, StatusCode::NOT_FOUND);
+    client.delete(&path).await.expect(StatusCode::NO_CONTENT);
+    assert_eq!(keyed.get("/api/v1/auth/me").await.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(client.get("/api/v1/api-keys").await.expect(StatusCode::OK), json!([]));
+
+    let forged = app.anonymous().header("x-api-key", "tsk_0000000000000000");
+    assert_eq!(forged.get("/api/v1/auth/me").await.status, StatusCode::UNAUTHORIZED);
+}
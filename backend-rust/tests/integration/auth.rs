@@ -0,0 +1,509 @@
+use axum::http::StatusCode;
+use chrono::{Duration, Utc};
+use serde_json::json;
+use sqlx::PgPool;
+
+use trading_saas_backend::models::{LinkedAccount, PasswordReset, User, UserSession};
+
+use crate::common::{TestApp, UserBuilder, TEST_PASSWORD};
+
+#[sqlx::test]
+async fn test_register_then_use_the_token(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+
+    let body = app
+        .anonymous()
+        .post("/api/v1/auth/register", json!({ "email": "new@example.com", "password": "long-enough" }))
+        .await
+        .expect(StatusCode::OK);
+    assert_eq!(body["user"]["email"], "new@example.com");
+    assert_eq!(body["user"]["subscription_plan"], "free");
+
+    let token = body["token"].as_str().unwrap();
+    let me = app.with_token(token).get("/api/v1/auth/me").await.expect(StatusCode::OK);
+    assert_eq!(me["email"], "new@example.com");
+}
+
+#[sqlx::test]
+async fn test_register_rejects_a_taken_email(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    UserBuilder::new().email("taken@example.com").create(app.pool()).await;
+
+    let response = app
+        .anonymous()
+        .post("/api/v1/auth/register", json!({ "email": "taken@example.com", "password": "long-enough" }))
+        .await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+}
+
+#[sqlx::test]
+async fn test_register_lists_every_password_rule_it_broke(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+
+    let policy = app.anonymous().get("/api/v1/auth/password-policy").await.expect(StatusCode::OK);
+    assert_eq!(policy["min_length"], 8);
+    assert_eq!(policy["reject_email_local_part"], true);
+
+    let response = app
+        .anonymous()
+        .post("/api/v1/auth/register", json!({ "email": "monkey@example.com", "password": "monkey" }))
+        .await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    let rules: Vec<_> = response.body["fields"].as_array().unwrap().iter().map(|f| (f["field"].clone(), f["rule"].clone())).collect();
+    assert_eq!(
+        rules,
+        vec![
+            (json!("password"), json!("min_length")),
+            (json!("password"), json!("email_local_part")),
+            (json!("password"), json!("common_password")),
+        ]
+    );
+    assert_eq!(
+        app.anonymous().post("/api/v1/auth/login", json!({ "email": "monkey@example.com", "password": "monkey" })).await.status,
+        StatusCode::UNAUTHORIZED,
+    );
+}
+
+#[sqlx::test]
+async fn test_change_password_applies_the_policy(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().create(app.pool()).await;
+    let client = app.client_as(&user);
+
+    let wrong = client
+        .put("/api/v1/auth/password", json!({ "current_password": "not-the-password", "new_password": "a-new-long-one" }))
+        .await;
+    assert_eq!(wrong.status, StatusCode::BAD_REQUEST);
+    let common = client
+        .put("/api/v1/auth/password", json!({ "current_password": TEST_PASSWORD, "new_password": "iloveyou" }))
+        .await;
+    assert_eq!(common.body["fields"][0]["rule"], "common_password");
+
+    client
+        .put("/api/v1/auth/password", json!({ "current_password": TEST_PASSWORD, "new_password": "a-new-long-one" }))
+        .await
+        .expect(StatusCode::NO_CONTENT);
+    let login = app
+        .anonymous()
+        .post("/api/v1/auth/login", json!({ "email": user.email, "password": "a-new-long-one" }))
+        .await;
+    assert_eq!(login.status, StatusCode::OK);
+}
+
+#[sqlx::test]
+async fn test_change_password_signs_out_other_devices(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().create(app.pool()).await;
+    let (phone, laptop) = (login(&app, &user.email).await, login(&app, &user.email).await);
+
+    let wrong = app
+        .with_token(&laptop)
+        .post("/api/v1/auth/change-password", json!({ "current_password": "not-the-password", "new_password": "a-new-long-one" }))
+        .await;
+    assert_eq!(wrong.status, StatusCode::BAD_REQUEST);
+    app.with_token(&phone).get("/api/v1/auth/me").await.expect(StatusCode::OK);
+
+    let body = app
+        .with_token(&laptop)
+        .post("/api/v1/auth/change-password", json!({ "current_password": TEST_PASSWORD, "new_password": "a-new-long-one" }))
+        .await
+        .expect(StatusCode::OK);
+    assert_eq!(app.with_token(&phone).get("/api/v1/auth/me").await.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(app.with_token(&laptop).get("/api/v1/auth/me").await.status, StatusCode::UNAUTHORIZED);
+    // The device that made the change carries on with the token it was given
+    app.with_token(body["token"].as_str().unwrap()).get("/api/v1/auth/me").await.expect(StatusCode::OK);
+
+    let old = app
+        .anonymous()
+        .post("/api/v1/auth/login", json!({ "email": user.email, "password": TEST_PASSWORD }))
+        .await;
+    assert_eq!(old.status, StatusCode::UNAUTHORIZED);
+    let stored = User::find_by_id(app.pool(), user.id).await.unwrap().unwrap();
+    assert!(stored.updated_at > user.updated_at);
+}
+
+#[sqlx::test]
+async fn test_login(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().create(app.pool()).await;
+
+    let body = app
+        .anonymous()
+        .post("/api/v1/auth/login", json!({ "email": user.email, "password": TEST_PASSWORD }))
+        .await
+        .expect(StatusCode::OK);
+    assert_eq!(body["user"]["id"], user.id.to_string());
+    assert!(body["token"].is_string());
+
+    let wrong = app
+        .anonymous()
+        .post("/api/v1/auth/login", json!({ "email": user.email, "password": "not-the-password" }))
+        .await;
+    assert_eq!(wrong.status, StatusCode::UNAUTHORIZED);
+}
+
+#[sqlx::test]
+async fn test_disabled_account_cannot_log_in_or_use_a_token(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().inactive().create(app.pool()).await;
+
+    let login = app
+        .anonymous()
+        .post("/api/v1/auth/login", json!({ "email": user.email, "password": TEST_PASSWORD }))
+        .await;
+    assert_eq!(login.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(app.client_as(&user).get("/api/v1/auth/me").await.status, StatusCode::UNAUTHORIZED);
+}
+
+#[sqlx::test]
+async fn test_repeated_wrong_passwords_are_throttled(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().create(app.pool()).await;
+    let other = UserBuilder::new().create(app.pool()).await;
+    let wrong = json!({ "email": user.email, "password": "not-the-password" });
+
+    for _ in 0..5 {
+        app.anonymous()
+            .header("x-forwarded-for", "203.0.113.7")
+            .post("/api/v1/auth/login", wrong.clone())
+            .await
+            .expect(StatusCode::UNAUTHORIZED);
+    }
+
+    // Even the right password is refused until the window passes
+    let refused = app
+        .anonymous()
+        .post("/api/v1/auth/login", json!({ "email": user.email, "password": TEST_PASSWORD }))
+        .await
+        .expect(StatusCode::TOO_MANY_REQUESTS);
+    assert!(refused["retry_after_seconds"].as_u64().unwrap() > 0);
+    // Another account behind the same address can still sign in
+    app.anonymous()
+        .header("x-forwarded-for", "203.0.113.7")
+        .post("/api/v1/auth/login", json!({ "email": other.email, "password": TEST_PASSWORD }))
+        .await
+        .expect(StatusCode::OK);
+}
+
+#[sqlx::test]
+async fn test_successful_login_resets_failed_attempts(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().create(app.pool()).await;
+    let wrong = json!({ "email": user.email, "password": "not-the-password" });
+
+    for _ in 0..4 {
+        app.anonymous().post("/api/v1/auth/login", wrong.clone()).await.expect(StatusCode::UNAUTHORIZED);
+    }
+    login(&app, &user.email).await;
+    for _ in 0..4 {
+        app.anonymous().post("/api/v1/auth/login", wrong.clone()).await.expect(StatusCode::UNAUTHORIZED);
+    }
+    login(&app, &user.email).await;
+}
+
+async fn login(app: &TestApp, email: &str) -> String {
+    let body = app
+        .anonymous()
+        .post("/api/v1/auth/login", json!({ "email": email, "password": TEST_PASSWORD }))
+        .await
+        .expect(StatusCode::OK);
+    body["token"].as_str().unwrap().to_string()
+}
+
+#[sqlx::test]
+async fn test_logout_signs_out_only_that_token(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().create(app.pool()).await;
+    let (phone, laptop) = (login(&app, &user.email).await, login(&app, &user.email).await);
+
+    app.with_token(&phone).post("/api/v1/auth/logout", json!({})).await.expect(StatusCode::NO_CONTENT);
+
+    assert_eq!(app.with_token(&phone).get("/api/v1/auth/me").await.status, StatusCode::UNAUTHORIZED);
+    app.with_token(&laptop).get("/api/v1/auth/me").await.expect(StatusCode::OK);
+}
+
+#[sqlx::test]
+async fn test_logout_all_signs_out_every_earlier_token(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().create(app.pool()).await;
+    let other = UserBuilder::new().create(app.pool()).await;
+    let (phone, laptop) = (login(&app, &user.email).await, login(&app, &user.email).await);
+    let others = login(&app, &other.email).await;
+
+    app.with_token(&phone).post("/api/v1/auth/logout-all", json!({})).await.expect(StatusCode::NO_CONTENT);
+
+    assert_eq!(app.with_token(&phone).get("/api/v1/auth/me").await.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(app.with_token(&laptop).get("/api/v1/auth/me").await.status, StatusCode::UNAUTHORIZED);
+    app.with_token(&others).get("/api/v1/auth/me").await.expect(StatusCode::OK);
+    // Signing in again issues a token at the new version
+    let fresh = login(&app, &user.email).await;
+    app.with_token(&fresh).get("/api/v1/auth/me").await.expect(StatusCode::OK);
+}
+
+#[sqlx::test]
+async fn test_sessions_are_listed_and_revoked_remotely(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().create(app.pool()).await;
+    let other = UserBuilder::new().create(app.pool()).await;
+    let credentials = json!({ "email": user.email, "password": TEST_PASSWORD });
+    let phone = app
+        .anonymous()
+        .header("user-agent", "TradingApp/2.1 (iPhone)")
+        .header("x-forwarded-for", "203.0.113.7, 10.0.0.1")
+        .post("/api/v1/auth/login", credentials)
+        .await
+        .expect(StatusCode::OK)["token"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let laptop = login(&app, &user.email).await;
+
+    let sessions = app.with_token(&laptop).get("/api/v1/auth/sessions").await.expect(StatusCode::OK);
+    let sessions = sessions.as_array().unwrap();
+    assert_eq!(sessions.len(), 2);
+    let on_phone = sessions.iter().find(|s| s["user_agent"] == "TradingApp/2.1 (iPhone)").unwrap();
+    assert_eq!((on_phone["ip_address"].as_str(), on_phone["current"].as_bool()), (Some("203.0.113.7"), Some(false)));
+    assert_eq!(sessions.iter().filter(|s| s["current"] == true).count(), 1);
+
+    // Another user's session is not found, and the phone is refused on its very next request
+    let path = format!("/api/v1/auth/sessions/{}", on_phone["id"].as_str().unwrap());
+    assert_eq!(app.client_as(&other).delete(&path).await.status, StatusCode::NOT_FOUND);
+    app.with_token(&phone).get("/api/v1/auth/me").await.expect(StatusCode::OK);
+    app.with_token(&laptop).delete(&path).await.expect(StatusCode::NO_CONTENT);
+    assert_eq!(app.with_token(&phone).get("/api/v1/auth/me").await.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(app.with_token(&laptop).delete(&path).await.status, StatusCode::NOT_FOUND);
+    let remaining = app.with_token(&laptop).get("/api/v1/auth/sessions").await.expect(StatusCode::OK);
+    assert_eq!(remaining.as_array().unwrap().len(), 1);
+
+    // Signing out everywhere ends every listed session
+    app.with_token(&laptop).post("/api/v1/auth/logout-all", json!({})).await.expect(StatusCode::NO_CONTENT);
+    let fresh = login(&app, &user.email).await;
+    let sessions = app.with_token(&fresh).get("/api/v1/auth/sessions").await.expect(StatusCode::OK);
+    assert_eq!(sessions.as_array().unwrap().len(), 1);
+    assert_eq!(sessions[0]["current"], true);
+}
+
+#[sqlx::test]
+async fn test_sessions_keep_token_versions_past_i32_max(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().create(app.pool()).await;
+    let now = Utc::now();
+    let version = i32::MAX as u32 + 10;
+    let session = UserSession::new(uuid::Uuid::new_v4(), user.id, version, None, None, now, now + Duration::hours(1));
+    UserSession::create(app.pool(), &session).await.unwrap();
+
+    let active = UserSession::find_active(app.pool(), user.id, version, now).await.unwrap();
+    assert_eq!(active.iter().map(|s| (s.id, s.token_version)).collect::<Vec<_>>(), vec![(session.id, i64::from(version))]);
+    assert!(UserSession::find_active(app.pool(), user.id, version + 1, now).await.unwrap().is_empty());
+}
+
+// Auth events are written in the background; waits until the user has `count` of them
+async fn security_events(app: &TestApp, token: &str, count: usize) -> Vec<serde_json::Value> {
+    for _ in 0..50 {
+        let events = app.with_token(token).get("/api/v1/users/me/security-events").await.expect(StatusCode::OK);
+        let events = events.as_array().unwrap().clone();
+        if events.len() >= count {
+            return events;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    panic!("expected {} security events", count);
+}
+
+#[sqlx::test]
+async fn test_sign_ins_and_their_failures_show_in_the_security_history(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().create(app.pool()).await;
+    let other = UserBuilder::new().create(app.pool()).await;
+    app.anonymous()
+        .header("x-forwarded-for", "198.51.100.4")
+        .post("/api/v1/auth/login", json!({ "email": user.email, "password": "not-the-password" }))
+        .await;
+    let token = app
+        .anonymous()
+        .header("user-agent", "TradingApp/2.1 (iPhone)")
+        .post("/api/v1/auth/login", json!({ "email": user.email, "password": TEST_PASSWORD }))
+        .await
+        .expect(StatusCode::OK)["token"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    app.with_token(&token).post("/api/v1/auth/logout-all", json!({})).await.expect(StatusCode::NO_CONTENT);
+
+    let fresh = login(&app, &user.email).await;
+    let events = security_events(&app, &fresh, 4).await;
+    let kinds: Vec<(&str, &str)> =
+        events.iter().map(|e| (e["event_type"].as_str().unwrap(), e["outcome"].as_str().unwrap())).collect();
+    assert_eq!(kinds, [("login", "success"), ("logout_all", "success"), ("login", "success"), ("login", "failure")]);
+    assert_eq!((events[3]["reason"].as_str(), events[3]["ip_address"].as_str()), (Some("invalid_password"), Some("198.51.100.4")));
+    assert_eq!(events[2]["user_agent"], "TradingApp/2.1 (iPhone)");
+
+    // The signed-out token is turned away, and that is recorded too
+    assert_eq!(app.with_token(&token).get("/api/v1/auth/me").await.status, StatusCode::UNAUTHORIZED);
+    let events = security_events(&app, &fresh, 5).await;
+    assert_eq!((events[0]["event_type"].as_str(), events[0]["reason"].as_str()), (Some("token_rejected"), Some("token_revoked")));
+
+    // Each user sees only their own history
+    assert!(app.client_as(&other).get("/api/v1/users/me/security-events").await.expect(StatusCode::OK)[0].is_null());
+}
+
+async fn reset_count(app: &TestApp) -> i64 {
+    sqlx::query_scalar("SELECT COUNT(*) FROM password_resets").fetch_one(app.pool()).await.unwrap()
+}
+
+#[sqlx::test]
+async fn test_password_reset_request_does_not_reveal_accounts(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().email("known@example.com").create(app.pool()).await;
+    let disabled = UserBuilder::new().inactive().create(app.pool()).await;
+
+    for email in ["nobody@example.com", disabled.email.as_str()] {
+        app.anonymous()
+            .post("/api/v1/auth/password-reset/request", json!({ "email": email }))
+            .await
+            .expect(StatusCode::ACCEPTED);
+    }
+    assert_eq!(reset_count(&app).await, 0);
+
+    app.anonymous()
+        .post("/api/v1/auth/password-reset/request", json!({ "email": " known@example.com " }))
+        .await
+        .expect(StatusCode::ACCEPTED);
+    let user_id: uuid::Uuid = sqlx::query_scalar("SELECT user_id FROM password_resets").fetch_one(app.pool()).await.unwrap();
+    assert_eq!(user_id, user.id);
+}
+
+#[sqlx::test]
+async fn test_password_reset_sets_the_password_once_and_signs_out(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().create(app.pool()).await;
+    let session = login(&app, &user.email).await;
+    let token = PasswordReset::create(app.pool(), user.id, Utc::now()).await.unwrap();
+
+    let weak = app
+        .anonymous()
+        .post("/api/v1/auth/password-reset/confirm", json!({ "token": token, "new_password": "iloveyou" }))
+        .await;
+    assert_eq!(weak.body["fields"][0]["rule"], "common_password");
+
+    app.anonymous()
+        .post("/api/v1/auth/password-reset/confirm", json!({ "token": token, "new_password": "a-new-long-one" }))
+        .await
+        .expect(StatusCode::NO_CONTENT);
+    assert_eq!(app.with_token(&session).get("/api/v1/auth/me").await.status, StatusCode::UNAUTHORIZED);
+    app.anonymous()
+        .post("/api/v1/auth/login", json!({ "email": user.email, "password": "a-new-long-one" }))
+        .await
+        .expect(StatusCode::OK);
+
+    let again = app
+        .anonymous()
+        .post("/api/v1/auth/password-reset/confirm", json!({ "token": token, "new_password": "yet-another-one" }))
+        .await;
+    assert_eq!(again.status, StatusCode::BAD_REQUEST);
+    assert!(again.body["error"].as_str().unwrap().contains("already been used"));
+}
+
+#[sqlx::test]
+async fn test_password_reset_refuses_expired_and_unknown_tokens(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().create(app.pool()).await;
+    let token = PasswordReset::create(app.pool(), user.id, Utc::now() - Duration::hours(2)).await.unwrap();
+
+    let expired = app
+        .anonymous()
+        .post("/api/v1/auth/password-reset/confirm", json!({ "token": token, "new_password": "a-new-long-one" }))
+        .await;
+    assert_eq!(expired.status, StatusCode::BAD_REQUEST);
+    assert!(expired.body["error"].as_str().unwrap().contains("expired"));
+    let unknown = app
+        .anonymous()
+        .post("/api/v1/auth/password-reset/confirm", json!({ "token": "not-a-token", "new_password": "a-new-long-one" }))
+        .await;
+    assert_eq!(unknown.status, StatusCode::BAD_REQUEST);
+
+    // The old password still works
+    login(&app, &user.email).await;
+}
+
+#[sqlx::test]
+async fn test_google_sign_in_links_the_account_registered_with_the_email(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().email("trader@example.com").create(app.pool()).await;
+
+    let token = app.google_token("google-1", "trader@example.com", |_| {});
+    let body = app.anonymous().post("/api/v1/auth/google", json!({ "token": token })).await.expect(StatusCode::OK);
+    assert_eq!(body["user"]["id"], user.id.to_string());
+    let linked = LinkedAccount::find_by_user(app.pool(), user.id).await.unwrap();
+    assert_eq!((linked[0].provider.as_str(), linked[0].external_id.as_str()), ("google", "google-1"));
+
+    // Later sign-ins follow the Google account, even after its email changes
+    let token = app.google_token("google-1", "renamed@example.com", |_| {});
+    let body = app.anonymous().post("/api/v1/auth/google", json!({ "token": token })).await.expect(StatusCode::OK);
+    assert_eq!(body["user"]["id"], user.id.to_string());
+
+    // Another Google account claiming the same address does not get in
+    let token = app.google_token("google-2", "trader@example.com", |_| {});
+    let response = app.anonymous().post("/api/v1/auth/google", json!({ "token": token })).await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+
+    // The password still works alongside Google
+    app.anonymous()
+        .post("/api/v1/auth/login", json!({ "email": "trader@example.com", "password": TEST_PASSWORD }))
+        .await
+        .expect(StatusCode::OK);
+}
+
+#[sqlx::test]
+async fn test_google_sign_in_refuses_forged_foreign_and_unverified_tokens(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let admin = UserBuilder::new().email("admin@gmail.com").admin().create(app.pool()).await;
+
+    let refused = [
+        // What the old mock mode turned into admin@gmail.com
+        "mock_google_token_admin".to_string(),
+        app.google_token("google-1", "admin@gmail.com", |info| info.aud = "another-app.apps.googleusercontent.com".to_string()),
+        app.google_token("google-1", "admin@gmail.com", |info| info.exp = Utc::now().timestamp() - 60),
+        app.google_token("google-1", "admin@gmail.com", |info| info.email_verified = false),
+    ];
+    for token in refused {
+        let response = app.anonymous().post("/api/v1/auth/google", json!({ "token": token })).await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED, "{}", token);
+    }
+    assert!(LinkedAccount::find_by_user(app.pool(), admin.id).await.unwrap().is_empty());
+
+    // A new address gets a new account, linked from the start
+    let token = app.google_token("google-3", "newcomer@gmail.com", |_| {});
+    let body = app.anonymous().post("/api/v1/auth/google", json!({ "token": token })).await.expect(StatusCode::OK);
+    assert_eq!(body["user"]["email"], "newcomer@gmail.com");
+    let newcomer = LinkedAccount::find_user_id(app.pool(), "google", "google-3").await.unwrap().unwrap();
+    assert_eq!(body["user"]["id"], newcomer.to_string());
+}
+
+#[sqlx::test]
+async fn test_every_provider_with_the_same_email_reaches_one_user(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+
+    let token = app.google_token("google-1", "trader@example.com", |_| {});
+    let google = app.anonymous().post("/api/v1/auth/oauth/google", json!({ "token": token })).await.expect(StatusCode::OK);
+    let token = app.github_token(583231, "trader@example.com");
+    let github = app.anonymous().post("/api/v1/auth/oauth/github", json!({ "token": token })).await.expect(StatusCode::OK);
+    assert_eq!(github["user"]["id"], google["user"]["id"]);
+    let user_id: uuid::Uuid = google["user"]["id"].as_str().unwrap().parse().unwrap();
+    let providers: Vec<String> =
+        LinkedAccount::find_by_user(app.pool(), user_id).await.unwrap().into_iter().map(|a| a.provider).collect();
+    assert_eq!(providers, ["google", "github"]);
+
+    // The GitHub account keeps signing in as that user after its email changes
+    let token = app.github_token(583231, "renamed@example.com");
+    let again = app.anonymous().post("/api/v1/auth/oauth/github", json!({ "token": token })).await.expect(StatusCode::OK);
+    assert_eq!(again["user"]["id"], google["user"]["id"]);
+
+    let forged = app.anonymous().post("/api/v1/auth/oauth/github", json!({ "token": "gho_forged" })).await;
+    assert_eq!(forged.status, StatusCode::UNAUTHORIZED);
+    // Apple is not configured in tests, and unknown providers are not found either
+    for provider in ["apple", "myspace"] {
+        let path = format!("/api/v1/auth/oauth/{}", provider);
+        assert_eq!(app.anonymous().post(&path, json!({ "token": "x" })).await.status, StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,140 @@
+use axum::http::StatusCode;
+use serde_json::json;
+use sqlx::PgPool;
+use trading_saas_backend::models::Trade;
+
+use crate::common::{BrokerBuilder, RobotBuilder, TestApp, TradeBuilder, UserBuilder};
+
+#[sqlx::test]
+async fn test_create_broker_tested_against_the_broker(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().create(app.pool()).await;
+    let client = app.client_as(&user);
+
+    let created = client
+        .post(
+            "/api/v1/brokers",
+            json!({
+                "name": "Demo",
+                "broker_type": "mt5",
+                "api_key": "key",
+                "api_secret": "secret",
+                "server": "Demo-Server",
+                "login": "555001",
+                "is_demo": true,
+                "test_on_create": true
+            }),
+        )
+        .await
+        .expect(StatusCode::OK);
+    assert_eq!(created["login"], "555001");
+    assert!(created["account_info"].is_object());
+    // Credentials never leave the server
+    assert!(created.get("api_secret").is_none());
+
+    let brokers = client.get("/api/v1/brokers").await.expect(StatusCode::OK);
+    assert_eq!(brokers.as_array().unwrap().len(), 1);
+}
+
+#[sqlx::test]
+async fn test_connection_the_broker_refuses_is_not_saved(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().create(app.pool()).await;
+    let client = app.client_as(&user);
+    app.broker.refuse_login("555002");
+
+    let refused = client
+        .post(
+            "/api/v1/brokers",
+            json!({
+                "name": "Demo",
+                "broker_type": "mt5",
+                "api_key": "key",
+                "api_secret": "secret",
+                "server": "Demo-Server",
+                "login": "555002",
+                "is_demo": true,
+                "test_on_create": true
+            }),
+        )
+        .await
+        .expect(StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(refused["error"].as_str().unwrap().contains("Invalid account 555002"));
+    assert_eq!(client.get("/api/v1/brokers").await.expect(StatusCode::OK), json!([]));
+}
+
+#[sqlx::test]
+async fn test_duplicate_account_is_refused(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().create(app.pool()).await;
+    let existing = BrokerBuilder::new(&user).login("777").create(&app).await;
+
+    let response = app
+        .client_as(&user)
+        .post(
+            "/api/v1/brokers",
+            json!({
+                "name": "Again",
+                "broker_type": "mt5",
+                "api_key": "key",
+                "api_secret": "secret",
+                "server": "Demo-Server",
+                "login": "777",
+                "is_demo": true
+            }),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::CONFLICT);
+    assert_eq!(response.body["existing_connection_id"], existing.id.to_string());
+}
+
+#[sqlx::test]
+async fn test_connection_test_is_recorded(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().create(app.pool()).await;
+    let connection = BrokerBuilder::new(&user).create(&app).await;
+    let client = app.client_as(&user);
+
+    let result = client.post(&format!("/api/v1/brokers/{}/test", connection.id), json!({})).await.expect(StatusCode::OK);
+    assert_eq!(result["success"], true);
+
+    let brokers = client.get("/api/v1/brokers").await.expect(StatusCode::OK);
+    assert_eq!(brokers[0]["last_test_status"], "success");
+}
+
+#[sqlx::test]
+async fn test_bridge_pushes_a_close_once(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().create(app.pool()).await;
+    let connection = BrokerBuilder::new(&user).create(&app).await;
+    let robot = RobotBuilder::new(&user).connection(&connection).create(app.pool()).await;
+    let trade = TradeBuilder::new(&robot).ticket("880001").create(app.pool()).await;
+
+    let issued = app
+        .client_as(&user)
+        .post(&format!("/api/v1/brokers/{}/bridge-token", connection.id), json!({}))
+        .await
+        .expect(StatusCode::OK);
+    let bridge = app.anonymous().header("x-bridge-token", issued["token"].as_str().unwrap());
+    let close = json!({
+        "type": "position_closed",
+        "deal_id": 990001,
+        "ticket": 880001,
+        "price": 1.1050,
+        "profit": 5.0,
+        "commission": -0.7,
+        "swap": -0.1
+    });
+
+    assert_eq!(bridge.post("/api/v1/bridge/events", close.clone()).await.expect(StatusCode::OK)["status"], "applied");
+    assert_eq!(bridge.post("/api/v1/bridge/events", close.clone()).await.expect(StatusCode::OK)["status"], "duplicate");
+
+    let stored = Trade::find_by_ids(app.pool(), user.id, &[trade.id]).await.unwrap().remove(0);
+    assert_eq!(stored.status, "closed");
+    assert_eq!((stored.exit_price, stored.profit_loss, stored.commission), (Some(1.1050), Some(5.0), Some(-0.7)));
+
+    // Another token, or none, is refused
+    let stranger = app.anonymous().header("x-bridge-token", "not-a-token");
+    assert_eq!(stranger.post("/api/v1/bridge/events", close.clone()).await.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(app.anonymous().post("/api/v1/bridge/events", close).await.status, StatusCode::UNAUTHORIZED);
+}
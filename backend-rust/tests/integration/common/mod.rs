@@ -0,0 +1,649 @@
+// Harness for the integration tests. Each test gets its own database from #[sqlx::test], created
+// and migrated from DATABASE_URL and dropped afterwards, and talks to the real router through
+// `TestClient`. Broker calls go to `MockBroker`, so no test leaves the machine.
+#![allow(dead_code)]
+
+use async_trait::async_trait;
+use axum::{
+    body::{to_bytes, Body},
+    http::{header, HeaderMap, Method, Request, StatusCode},
+    Router,
+};
+use serde_json::Value;
+use sqlx::PgPool;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use tower::Service;
+use uuid::Uuid;
+
+use trading_saas_backend::{
+    config::Config,
+    create_app,
+    database::Database,
+    errors::{AppError, Result},
+    models::{
+        user::CreateUserRequest, AccountInfo, BrokerConnection, CreateTradingRobotRequest, Role, Trade, TradingRobot, User,
+    },
+    services::{
+        activation_nudges::PgNudgeEnv,
+        auth_service::AuthService,
+        backtest_engine::{CandleBacktestEngine, Mt5CandleSource},
+        broker_throttle::BrokerThrottle,
+        cooldown_service::PgCooldownEnv,
+        credential_vault::{KeyRing, PgCredentialStore},
+        feature_flags::PgFlagSource,
+        job_service::PgJobStore,
+        market_data_streamer::PgWatchlistStore,
+        message_templates::PgTemplateStore,
+        migration_coordinator::{embedded_versions, PgMigrationTarget, SchemaGate},
+        mt5_service::{BrokerClient, Mt5MarketData, Mt5Order, Mt5Position},
+        order_drain::PgOrderStore,
+        password_policy::PasswordChecker,
+        token_revocation::MemoryTokenRevocations,
+        login_throttle::MemoryLoginAttempts,
+        google_identity::{GoogleTokenInfo, GoogleVerifier, MemoryGoogleTokens},
+        github_identity::{GitHubAccount, GitHubEmail, GitHubVerifier, MemoryGitHubTokens},
+        oauth::OAuthProviders,
+        quote_service::{BrokerQuotes, PlatformQuoteCache, PlatformQuotes, QuoteSource},
+        runtime_settings::PgRuntimeSettingsSource,
+        broker_maintenance::PgBrokerMaintenanceEnv,
+        plan_downgrade::PgPlanDowngradeEnv,
+        statements::PgStatementEnv,
+        system_status::SystemMonitor,
+        BrokerMaintenanceService, CacheService, CredentialVault, EventBus, FeatureFlags, JobService, LoginThrottle, MarketDataStreamer, MessageTemplates,
+        Mt5Service, NotificationService, OrderDrain, PlanDowngradeService, PublicStatsService, QuoteService, RobotRunnerRegistry,
+        RuntimeConfig, StrategyOptimizer, StripeService, TradeFactsBackfill, WebSocketManager,
+    },
+    AppState,
+};
+
+const MAX_BODY_BYTES: usize = 16 * 1024 * 1024;
+
+pub const TEST_PASSWORD: &str = "correct-horse-battery";
+pub const GOOGLE_CLIENT_ID: &str = "test-client.apps.googleusercontent.com";
+
+// The app as main.rs wires it, minus background jobs and with the test profile's config
+pub struct TestApp {
+    pub state: AppState,
+    router: Router,
+    // What Google would answer for the ID tokens the test hands out
+    pub google_tokens: Arc<MemoryGoogleTokens>,
+    // And GitHub for its access tokens
+    pub github_tokens: Arc<MemoryGitHubTokens>,
+    // What every broker connection talks to
+    pub broker: Arc<MockBroker>,
+}
+
+impl TestApp {
+    pub async fn new(pool: PgPool) -> TestApp {
+        let config = Arc::new(Config::for_tests());
+        let db = Database::from_pool(pool.clone());
+        // Opening a Redis client does not connect, and nothing on these paths reaches Redis
+        let cache = CacheService::new(&config.redis_url).expect("redis url");
+
+        // Nothing to fill on a fresh database; this marks the facts ready as in production
+        TradeFactsBackfill::run(&pool).await.expect("trade facts backfill");
+
+        let broker_throttle = Arc::new(BrokerThrottle::default());
+        let credentials = Arc::new(CredentialVault::new(
+            KeyRing::new(&config.encryption_key_id, &config.encryption_keys).expect("test key ring"),
+            Arc::new(PgCredentialStore::new(pool.clone())),
+        ));
+        let broker = Arc::new(MockBroker::default());
+        let mt5 = Arc::new(
+            Mt5Service::with_throttle(broker_throttle.clone())
+                .with_credentials(credentials.clone())
+                .with_client(broker.clone()),
+        );
+        let templates = Arc::new(MessageTemplates::new(Arc::new(PgTemplateStore::new(pool.clone()))));
+        let notifications = Arc::new(
+            NotificationService::new(config.smtp_host.clone(), config.smtp_user.clone(), config.smtp_password.clone())
+                .with_templates(templates.clone()),
+        );
+        let websocket = Arc::new(WebSocketManager::new());
+        let quote_cache = Arc::new(PlatformQuoteCache::new());
+        let google_tokens = Arc::new(MemoryGoogleTokens::new());
+        let github_tokens = Arc::new(MemoryGitHubTokens::new());
+        let quotes = Arc::new(QuoteService::new(vec![
+            (QuoteSource::Broker, Arc::new(BrokerQuotes::new(pool.clone(), mt5.clone()))),
+            (QuoteSource::Platform, Arc::new(PlatformQuotes::new(quote_cache.clone(), mt5.clone()))),
+        ]));
+
+        let state = AppState {
+            db,
+            config: config.clone(),
+            cache: cache.clone(),
+            broker_throttle: broker_throttle.clone(),
+            mt5: mt5.clone(),
+            credentials,
+            orders: Arc::new(OrderDrain::new(Arc::new(PgOrderStore::new(pool.clone())))),
+            websocket: websocket.clone(),
+            runners: Arc::new(RobotRunnerRegistry::new()),
+            cooldowns: Arc::new(PgCooldownEnv::new(pool.clone(), notifications.clone())),
+            nudges: Arc::new(PgNudgeEnv::new(pool.clone(), notifications.clone(), &config.public_base_url)),
+            statements: Arc::new(PgStatementEnv::new(pool.clone(), notifications.clone())),
+            broker_maintenance: Arc::new(BrokerMaintenanceService::new(Arc::new(PgBrokerMaintenanceEnv::new(
+                pool.clone(),
+                notifications.clone(),
+            )))),
+            plan_downgrades: Arc::new(PlanDowngradeService::new(Arc::new(PgPlanDowngradeEnv::new(
+                pool.clone(),
+                notifications.clone(),
+            )))),
+            jobs: Arc::new(JobService::new(Arc::new(PgJobStore::new(pool.clone())))),
+            passwords: Arc::new(PasswordChecker::new(config.password_policy.clone())),
+            token_revocations: Arc::new(MemoryTokenRevocations::new()),
+            login_throttle: Arc::new(LoginThrottle::new(
+                Arc::new(MemoryLoginAttempts::new()),
+                config.login_throttle.clone(),
+                notifications,
+            )),
+            oauth: Arc::new(
+                OAuthProviders::new()
+                    .with_provider(Arc::new(GoogleVerifier::new(google_tokens.clone(), Some(GOOGLE_CLIENT_ID.to_string()))))
+                    .with_provider(Arc::new(GitHubVerifier::new(github_tokens.clone()))),
+            ),
+            events: Arc::new(EventBus::new()),
+            feature_flags: Arc::new(FeatureFlags::new(Arc::new(PgFlagSource::new(pool.clone())))),
+            public_stats: Arc::new(PublicStatsService::new(Arc::new(cache), config.public_stats_round_to)),
+            schema: Arc::new(SchemaGate::new(Arc::new(PgMigrationTarget::new(pool.clone())), embedded_versions())),
+            market_data: Arc::new(
+                MarketDataStreamer::new(Arc::new(PgWatchlistStore::new(pool.clone()))).with_quote_cache(quote_cache),
+            ),
+            optimizer: Arc::new(StrategyOptimizer::new(
+                Arc::new(CandleBacktestEngine::new(Arc::new(Mt5CandleSource::new(mt5)))),
+                websocket.backtests(),
+                websocket,
+            )),
+            system_monitor: Arc::new(SystemMonitor::new(chrono::Utc::now())),
+            stripe: Arc::new(StripeService::new(config.stripe_secret_key.clone())),
+            quotes,
+            runtime: Arc::new(
+                RuntimeConfig::new(Arc::new(PgRuntimeSettingsSource::new(pool))).with_throttle(broker_throttle),
+            ),
+            templates,
+        };
+        let router = create_app(state.clone()).expect("router");
+        TestApp { state, router, google_tokens, github_tokens, broker }
+    }
+
+    // An ID token Google signed for this app, verified email and all; adjust the claims to test refusals
+    pub fn google_token(&self, google_id: &str, email: &str, adjust: impl FnOnce(&mut GoogleTokenInfo)) -> String {
+        let mut info = GoogleTokenInfo {
+            iss: "https://accounts.google.com".to_string(),
+            aud: GOOGLE_CLIENT_ID.to_string(),
+            sub: google_id.to_string(),
+            exp: (chrono::Utc::now() + chrono::Duration::hours(1)).timestamp(),
+            email: Some(email.to_string()),
+            email_verified: true,
+            name: Some("Google User".to_string()),
+            picture: None,
+        };
+        adjust(&mut info);
+        let token = format!("google-id-token-{}", Uuid::new_v4());
+        self.google_tokens.issue(&token, info);
+        token
+    }
+
+    // An access token of this app's GitHub OAuth client, for an account whose primary email is verified
+    pub fn github_token(&self, github_id: i64, email: &str) -> String {
+        let account = GitHubAccount { id: github_id, login: format!("user{}", github_id), name: None, avatar_url: None };
+        let emails = vec![GitHubEmail { email: email.to_string(), primary: true, verified: true }];
+        let token = format!("gho_{}", Uuid::new_v4().simple());
+        self.github_tokens.issue(&token, account, emails);
+        token
+    }
+
+    pub fn pool(&self) -> &PgPool {
+        self.state.db.pool()
+    }
+
+    // Sends no Authorization header
+    pub fn anonymous(&self) -> TestClient {
+        TestClient { router: self.router.clone(), token: None, headers: Vec::new() }
+    }
+
+    pub fn client_as(&self, user: &User) -> TestClient {
+        let token = AuthService::create_token(user.id, 0, &self.state.config.token_settings()).expect("token");
+        self.with_token(&token)
+    }
+
+    pub fn with_token(&self, token: &str) -> TestClient {
+        TestClient { router: self.router.clone(), token: Some(token.to_string()), headers: Vec::new() }
+    }
+}
+
+pub struct TestClient {
+    router: Router,
+    token: Option<String>,
+    headers: Vec<(&'static str, String)>,
+}
+
+#[derive(Debug)]
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    // Null for an empty body
+    pub body: Value,
+}
+
+impl TestResponse {
+    // Fails the test with the body when the status differs, which says more than the code alone
+    pub fn expect(self, status: StatusCode) -> Value {
+        assert_eq!(self.status, status, "unexpected status, body: {}", self.body);
+        self.body
+    }
+}
+
+impl TestClient {
+    // Sent with every request, e.g. a bridge token
+    pub fn header(mut self, name: &'static str, value: &str) -> Self {
+        self.headers.push((name, value.to_string()));
+        self
+    }
+
+    pub async fn get(&self, path: &str) -> TestResponse {
+        self.send(Method::GET, path, None).await
+    }
+
+    pub async fn post(&self, path: &str, body: Value) -> TestResponse {
+        self.send(Method::POST, path, Some(body)).await
+    }
+
+    pub async fn put(&self, path: &str, body: Value) -> TestResponse {
+        self.send(Method::PUT, path, Some(body)).await
+    }
+
+    pub async fn patch(&self, path: &str, body: Value) -> TestResponse {
+        self.send(Method::PATCH, path, Some(body)).await
+    }
+
+    pub async fn delete(&self, path: &str) -> TestResponse {
+        self.send(Method::DELETE, path, None).await
+    }
+
+    pub async fn send(&self, method: Method, path: &str, body: Option<Value>) -> TestResponse {
+        let mut request = Request::builder().method(method).uri(path);
+        if let Some(token) = &self.token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        for (name, value) in &self.headers {
+            request = request.header(*name, value);
+        }
+        let request = match body {
+            Some(body) => request
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string())),
+            None => request.body(Body::empty()),
+        }
+        .expect("request");
+
+        // The router is always ready, so it is called without polling first
+        let response = self.router.clone().call(request).await.expect("infallible router");
+        let status = response.status();
+        let headers = response.headers().clone();
+        let bytes = to_bytes(response.into_body(), MAX_BODY_BYTES).await.expect("body");
+        let body = if bytes.is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_slice(&bytes).unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into_owned()))
+        };
+        TestResponse { status, headers, body }
+    }
+}
+
+// A broker that accepts every login but the refused ones, fills every order with the next ticket,
+// quotes every symbol at 1.1000 / 1.1002 and holds only the positions a test opened on it. Orders
+// and closes are recorded for the test to check.
+#[derive(Default)]
+pub struct MockBroker {
+    refused_logins: Mutex<HashSet<String>>,
+    positions: Mutex<HashMap<String, Vec<Mt5Position>>>,
+    orders: Mutex<Vec<(String, Mt5Order)>>,
+    closed: Mutex<Vec<i64>>,
+    last_ticket: Mutex<i64>,
+}
+
+pub const MOCK_BID: f64 = 1.1000;
+pub const MOCK_ASK: f64 = 1.1002;
+
+impl MockBroker {
+    pub fn refuse_login(&self, login: &str) {
+        self.refused_logins.lock().unwrap().insert(login.to_string());
+    }
+
+    // An open long of `volume` lots on the connection, as the broker reports it
+    pub fn open_position(&self, connection: &BrokerConnection, ticket: i64, symbol: &str, volume: f64) {
+        self.positions.lock().unwrap().entry(connection.id.to_string()).or_default().push(Mt5Position {
+            ticket,
+            symbol: symbol.to_string(),
+            position_type: "BUY".to_string(),
+            volume,
+            price_open: MOCK_BID,
+            price_current: MOCK_BID,
+            profit: 0.0,
+            swap: 0.0,
+            commission: 0.0,
+        });
+    }
+
+    // (connection id, order), in the order they were placed
+    pub fn orders(&self) -> Vec<(String, Mt5Order)> {
+        self.orders.lock().unwrap().clone()
+    }
+
+    pub fn closed(&self) -> Vec<i64> {
+        self.closed.lock().unwrap().clone()
+    }
+
+    fn check_login(&self, login: &str) -> Result<()> {
+        if self.refused_logins.lock().unwrap().contains(login) {
+            return Err(AppError::Mt5(format!("Invalid account {}", login)));
+        }
+        Ok(())
+    }
+
+    fn account(login: &str) -> AccountInfo {
+        AccountInfo {
+            account_number: login.to_string(),
+            balance: 10_000.0,
+            equity: 10_000.0,
+            margin: 0.0,
+            free_margin: 10_000.0,
+            currency: "USD".to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl BrokerClient for MockBroker {
+    async fn login(&self, _connection_id: &str, login: &str, _password: &str, _server: &str) -> Result<()> {
+        self.check_login(login)
+    }
+
+    async fn test_login(&self, connection: &BrokerConnection) -> Result<AccountInfo> {
+        let login = connection.login.as_deref().unwrap_or_default();
+        self.check_login(login)?;
+        Ok(Self::account(login))
+    }
+
+    async fn account_info(&self, connection_id: &str) -> Result<AccountInfo> {
+        Ok(Self::account(connection_id))
+    }
+
+    async fn place_order(&self, connection_id: &str, order: &Mt5Order) -> Result<i64> {
+        self.orders.lock().unwrap().push((connection_id.to_string(), order.clone()));
+        let mut ticket = self.last_ticket.lock().unwrap();
+        *ticket += 1;
+        Ok(*ticket)
+    }
+
+    async fn close_position(&self, connection_id: &str, ticket: i64) -> Result<()> {
+        if let Some(positions) = self.positions.lock().unwrap().get_mut(connection_id) {
+            positions.retain(|p| p.ticket != ticket);
+        }
+        self.closed.lock().unwrap().push(ticket);
+        Ok(())
+    }
+
+    async fn modify_order(&self, _connection_id: &str, _ticket: i64, _stop_loss: Option<f64>, _take_profit: Option<f64>) -> Result<()> {
+        Ok(())
+    }
+
+    async fn positions(&self, connection_id: &str) -> Result<Vec<Mt5Position>> {
+        let positions = self.positions.lock().unwrap();
+        Ok(positions.get(connection_id).cloned().unwrap_or_default())
+    }
+
+    async fn market_data(&self, _connection_id: &str, symbol: &str) -> Result<Mt5MarketData> {
+        Ok(Mt5MarketData {
+            symbol: symbol.to_string(),
+            bid: MOCK_BID,
+            ask: MOCK_ASK,
+            last: MOCK_BID,
+            volume: 0.0,
+            time: chrono::Utc::now(),
+        })
+    }
+
+    async fn history(&self, _connection_id: &str, _symbol: &str, _timeframe: &str, count: i32) -> Result<Vec<[f64; 5]>> {
+        Ok(vec![[MOCK_BID, MOCK_ASK, MOCK_BID, MOCK_BID, 0.0]; count.max(0) as usize])
+    }
+}
+
+// Seeded rows go through the models, as the handlers would write them
+
+pub struct UserBuilder {
+    email: String,
+    plan: String,
+    role: Role,
+    active: bool,
+}
+
+impl UserBuilder {
+    pub fn new() -> Self {
+        UserBuilder {
+            email: format!("user-{}@example.com", Uuid::new_v4().simple()),
+            plan: "free".to_string(),
+            role: Role::User,
+            active: true,
+        }
+    }
+
+    pub fn email(mut self, email: &str) -> Self {
+        self.email = email.to_string();
+        self
+    }
+
+    pub fn plan(mut self, plan: &str) -> Self {
+        self.plan = plan.to_string();
+        self
+    }
+
+    pub fn admin(mut self) -> Self {
+        self.role = Role::Admin;
+        self
+    }
+
+    pub fn support(mut self) -> Self {
+        self.role = Role::Support;
+        self
+    }
+
+    pub fn inactive(mut self) -> Self {
+        self.active = false;
+        self
+    }
+
+    pub async fn create(self, pool: &PgPool) -> User {
+        let user = User::create(pool, CreateUserRequest { email: self.email, password: TEST_PASSWORD.to_string() })
+            .await
+            .expect("user");
+        sqlx::query("UPDATE users SET subscription_plan = $2, role = $3, is_superuser = $4, is_active = $5 WHERE id = $1")
+            .bind(user.id)
+            .bind(&self.plan)
+            .bind(self.role.as_str())
+            .bind(self.role == Role::Admin)
+            .bind(self.active)
+            .execute(pool)
+            .await
+            .expect("user flags");
+        User::find_by_id(pool, user.id).await.expect("user").expect("user row")
+    }
+}
+
+pub struct RobotBuilder {
+    user_id: Uuid,
+    name: String,
+    strategy: String,
+    broker_connection_id: Option<Uuid>,
+}
+
+impl RobotBuilder {
+    pub fn new(user: &User) -> Self {
+        RobotBuilder {
+            user_id: user.id,
+            name: "Test robot".to_string(),
+            strategy: "trend_following".to_string(),
+            broker_connection_id: None,
+        }
+    }
+
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    pub fn strategy(mut self, strategy: &str) -> Self {
+        self.strategy = strategy.to_string();
+        self
+    }
+
+    pub fn connection(mut self, connection: &BrokerConnection) -> Self {
+        self.broker_connection_id = Some(connection.id);
+        self
+    }
+
+    pub async fn create(self, pool: &PgPool) -> TradingRobot {
+        let request = CreateTradingRobotRequest {
+            name: self.name,
+            strategy: self.strategy,
+            risk_config: None,
+            broker_connection_id: self.broker_connection_id,
+        };
+        TradingRobot::create(pool, self.user_id, request, "test").await.expect("robot")
+    }
+}
+
+pub struct TradeBuilder {
+    trade: Trade,
+}
+
+impl TradeBuilder {
+    pub fn new(robot: &TradingRobot) -> Self {
+        TradeBuilder {
+            trade: Trade::new(robot.user_id, robot.id, "EURUSD".to_string(), "buy".to_string(), 0.1, 1.1000, None, None, None, None),
+        }
+    }
+
+    pub fn symbol(mut self, symbol: &str) -> Self {
+        self.trade.symbol = symbol.to_string();
+        self
+    }
+
+    pub fn opened_at(mut self, opened_at: chrono::DateTime<chrono::Utc>) -> Self {
+        self.trade.opened_at = opened_at;
+        self
+    }
+
+    pub fn created_at(mut self, created_at: chrono::DateTime<chrono::Utc>) -> Self {
+        self.trade.created_at = created_at;
+        self
+    }
+
+    pub fn volume(mut self, volume: f64) -> Self {
+        self.trade.volume = volume;
+        self
+    }
+
+    pub fn entry_price(mut self, entry_price: f64) -> Self {
+        self.trade.entry_price = entry_price;
+        self
+    }
+
+    pub fn sell(mut self) -> Self {
+        self.trade.trade_type = "sell".to_string();
+        self
+    }
+
+    pub fn stop_loss(mut self, stop_loss: f64) -> Self {
+        self.trade.stop_loss = Some(stop_loss);
+        self
+    }
+
+    pub fn ai_confidence(mut self, confidence: f64) -> Self {
+        self.trade.ai_confidence = Some(confidence);
+        self
+    }
+
+    pub fn ticket(mut self, ticket: &str) -> Self {
+        self.trade.broker_trade_id = Some(ticket.to_string());
+        self
+    }
+
+    pub fn closed(mut self, exit_price: f64, profit_loss: f64) -> Self {
+        self.trade.status = "closed".to_string();
+        self.trade.exit_price = Some(exit_price);
+        self.trade.profit_loss = Some(profit_loss);
+        self.trade.closed_at = Some(chrono::Utc::now());
+        self
+    }
+
+    pub fn closed_at(mut self, closed_at: chrono::DateTime<chrono::Utc>) -> Self {
+        self.trade.opened_at = closed_at - chrono::Duration::hours(1);
+        self.trade.closed_at = Some(closed_at);
+        self
+    }
+
+    pub fn fees(mut self, commission: f64, swap: f64) -> Self {
+        self.trade.commission = Some(commission);
+        self.trade.swap = Some(swap);
+        self
+    }
+
+    pub async fn create(self, pool: &PgPool) -> Trade {
+        Trade::insert(pool, &self.trade, "test").await.expect("trade");
+        self.trade
+    }
+}
+
+pub struct BrokerBuilder {
+    user_id: Uuid,
+    name: String,
+    login: String,
+    broker_type: String,
+    is_demo: bool,
+}
+
+impl BrokerBuilder {
+    pub fn new(user: &User) -> Self {
+        BrokerBuilder {
+            user_id: user.id,
+            name: "Demo account".to_string(),
+            login: "12345678".to_string(),
+            broker_type: "mt5".to_string(),
+            is_demo: true,
+        }
+    }
+
+    pub fn live(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self.is_demo = false;
+        self
+    }
+
+    pub fn login(mut self, login: &str) -> Self {
+        self.login = login.to_string();
+        self
+    }
+
+    pub fn broker_type(mut self, broker_type: &str) -> Self {
+        self.broker_type = broker_type.to_string();
+        self
+    }
+
+    // Sealed with the app's key ring, as stored connections always are
+    pub async fn create(self, app: &TestApp) -> BrokerConnection {
+        let connection = BrokerConnection::new(
+            self.user_id,
+            self.name,
+            self.broker_type,
+            "api-key".to_string(),
+            "api-secret".to_string(),
+            Some("Demo-Server".to_string()),
+            Some(self.login),
+            self.is_demo,
+        );
+        BrokerConnection::create(app.pool(), app.state.credentials.seal(connection)).await.expect("broker connection")
+    }
+}
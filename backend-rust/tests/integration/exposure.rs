@@ -0,0 +1,99 @@
+use axum::http::StatusCode;
+use chrono::Utc;
+use serde_json::Value;
+use sqlx::PgPool;
+use trading_saas_backend::models::{AccountInfo, AccountSnapshot, BrokerConnection, SnapshotGranularity};
+
+use crate::common::{BrokerBuilder, RobotBuilder, TestApp, TradeBuilder, UserBuilder};
+
+// The mock broker quotes every symbol at 1.1000 / 1.1002, so EURUSD's mid is 1.1001
+const EURUSD_MID: f64 = 1.1001;
+
+fn connection<'a>(exposure: &'a Value, connection: &BrokerConnection) -> &'a Value {
+    exposure["connections"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|c| c["connection_id"] == connection.id.to_string())
+        .expect("connection in the exposure")
+}
+
+fn assert_close(actual: &Value, expected: f64) {
+    let actual = actual.as_f64().expect("a number");
+    assert!((actual - expected).abs() < 0.011, "{} != {}", actual, expected);
+}
+
+#[sqlx::test]
+async fn test_offsetting_positions_net_across_accounts_in_different_currencies(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().plan("pro").create(app.pool()).await;
+    let usd = BrokerBuilder::new(&user).live("Main account").login("111").create(&app).await;
+    let eur = BrokerBuilder::new(&user).live("Euro account").login("222").create(&app).await;
+    for connection in [&usd, &eur] {
+        app.state.mt5.connect(connection).await.expect("session");
+    }
+    let info = AccountInfo {
+        account_number: "222".to_string(),
+        balance: 5_000.0,
+        equity: 5_000.0,
+        margin: 0.0,
+        free_margin: 5_000.0,
+        currency: "EUR".to_string(),
+    };
+    let snapshot = AccountSnapshot::new(&eur, &info, SnapshotGranularity::Hour, Utc::now());
+    AccountSnapshot::upsert(app.pool(), &snapshot).await.unwrap();
+
+    let on_usd = RobotBuilder::new(&user).connection(&usd).create(app.pool()).await;
+    let on_eur = RobotBuilder::new(&user).connection(&eur).create(app.pool()).await;
+    let unassigned = RobotBuilder::new(&user).create(app.pool()).await;
+    // Long 2 lots from 1.0950: 50 pips on 200,000 EUR is 1000 USD
+    TradeBuilder::new(&on_usd).volume(2.0).entry_price(1.0950).create(app.pool()).await;
+    // Short 0.5 lots from 1.1050, closed at the ask: 240 USD, which the euro account holds in EUR
+    TradeBuilder::new(&on_eur).sell().volume(0.5).entry_price(1.1050).create(app.pool()).await;
+    TradeBuilder::new(&on_usd).volume(1.0).closed(1.1100, 500.0).create(app.pool()).await;
+    TradeBuilder::new(&unassigned).volume(3.0).create(app.pool()).await;
+    let client = app.client_as(&user);
+
+    let exposure = client.get("/api/v1/exposure/by-connection").await.expect(StatusCode::OK);
+    assert_eq!(exposure["connections"].as_array().unwrap().len(), 2);
+    let main = connection(&exposure, &usd);
+    assert_eq!((main["account_currency"].as_str(), main["indicative"].as_bool()), (Some("USD"), Some(false)));
+    assert_eq!(main["symbols"][0]["net_volume"].as_f64(), Some(2.0));
+    assert_eq!(main["symbols"][0]["direction"], "long");
+    assert_close(&main["floating_profit_loss"], 1000.0);
+    assert_eq!(main["conversions"].as_array().unwrap().len(), 0);
+
+    let euro = connection(&exposure, &eur);
+    assert_eq!(euro["account_currency"], "EUR");
+    assert_eq!((euro["symbols"][0]["net_volume"].as_f64(), euro["symbols"][0]["direction"].as_str()), (Some(-0.5), Some("short")));
+    assert_close(&euro["floating_profit_loss"], 240.0 / EURUSD_MID);
+    let conversion = &euro["conversions"][0];
+    assert_eq!((conversion["from"].as_str(), conversion["to"].as_str(), conversion["symbol"].as_str()), (Some("USD"), Some("EUR"), Some("EURUSD")));
+    assert!((conversion["rate"].as_f64().unwrap() - 1.0 / EURUSD_MID).abs() < 1e-9);
+
+    // Netted: long 1.5 lots overall, P/L combined in USD since the accounts differ
+    let net = client.get("/api/v1/exposure/net").await.expect(StatusCode::OK);
+    assert_eq!((net["currency"].as_str(), net["mixed_currencies"].as_bool()), (Some("USD"), Some(true)));
+    let eurusd = &net["symbols"][0];
+    assert_eq!(net["symbols"].as_array().unwrap().len(), 1);
+    assert_eq!((eurusd["symbol"].as_str(), eurusd["direction"].as_str()), (Some("EURUSD"), Some("long")));
+    assert_eq!(
+        (eurusd["long_volume"].as_f64(), eurusd["short_volume"].as_f64(), eurusd["net_volume"].as_f64()),
+        (Some(2.0), Some(0.5), Some(1.5))
+    );
+    assert_eq!(eurusd["average_entry_price"].as_f64(), Some(1.095));
+    assert_eq!(eurusd["open_trades"], 2);
+    assert_close(&eurusd["floating_profit_loss"], 1240.0);
+    assert_close(&net["floating_profit_loss"], 1240.0);
+    assert_eq!(eurusd["connections"].as_array().unwrap().len(), 2);
+    let conversion = &net["conversions"][0];
+    assert_eq!((conversion["from"].as_str(), conversion["to"].as_str()), (Some("EUR"), Some("USD")));
+    assert!((conversion["rate"].as_f64().unwrap() - EURUSD_MID).abs() < 1e-9);
+
+    let in_euros = client.get("/api/v1/exposure/net?currency=eur").await.expect(StatusCode::OK);
+    assert_eq!(in_euros["currency"], "EUR");
+    assert_close(&in_euros["floating_profit_loss"], 1240.0 / EURUSD_MID);
+
+    let response = client.get("/api/v1/exposure/net?currency=euros").await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,91 @@
+use axum::http::StatusCode;
+use chrono::{Duration, Utc};
+use serde_json::json;
+use sqlx::PgPool;
+use trading_saas_backend::{models::Job, services::job_service::JobClass};
+
+use crate::common::{TestApp, UserBuilder};
+
+#[sqlx::test]
+async fn test_cancelled_job_reports_how_far_it_got(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().plan("pro").create(app.pool()).await;
+    let client = app.client_as(&user);
+    let handle = app.state.jobs.register_for_plan(user.id, "pro", JobClass::Import).await.unwrap();
+    let job_id = handle.job_id();
+
+    // A mocked import: checkpoints each batch it is fed, stops when cancelled
+    let (batch, mut batches) = tokio::sync::mpsc::channel::<()>(1);
+    let (saved, mut checkpoints) = tokio::sync::mpsc::channel::<u64>(1);
+    let worker = tokio::spawn(async move {
+        handle.start().await.unwrap();
+        let mut rows = 0;
+        loop {
+            tokio::select! {
+                _ = handle.cancelled() => break,
+                Some(()) = batches.recv() => {
+                    rows += 500;
+                    handle.checkpoint(rows as f64 / 50.0, json!({ "rows_imported": rows, "rows_total": 5000 })).await.unwrap();
+                    saved.send(rows).await.unwrap();
+                }
+            }
+        }
+        handle.acknowledge_cancel().await.unwrap();
+    });
+    for _ in 0..2 {
+        batch.send(()).await.unwrap();
+        checkpoints.recv().await.unwrap();
+    }
+
+    let running = client.get(&format!("/api/v1/jobs/{}", job_id)).await.expect(StatusCode::OK);
+    assert_eq!((running["status"].as_str(), running["kind"].as_str()), (Some("running"), Some("import")));
+
+    // Accepted, but cancelled only once the worker acknowledges
+    let requested = client.post(&format!("/api/v1/jobs/{}/cancel", job_id), json!({})).await.expect(StatusCode::ACCEPTED);
+    assert!(requested["cancel_requested_at"].is_string());
+    worker.await.unwrap();
+
+    let cancelled = client.get(&format!("/api/v1/jobs/{}", job_id)).await.expect(StatusCode::OK);
+    assert_eq!(cancelled["status"], "cancelled");
+    assert_eq!(cancelled["progress"], 20.0);
+    assert_eq!(cancelled["checkpoint"], json!({ "rows_imported": 1000, "rows_total": 5000 }));
+    assert!(cancelled["finished_at"].is_string());
+
+    let response = client.post(&format!("/api/v1/jobs/{}/cancel", job_id), json!({})).await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+    let jobs = client.get("/api/v1/jobs").await.expect(StatusCode::OK);
+    assert_eq!(jobs.as_array().unwrap().len(), 1);
+
+    // Other users can neither see nor cancel it
+    let other = UserBuilder::new().plan("pro").create(app.pool()).await;
+    let other_client = app.client_as(&other);
+    assert_eq!(other_client.get(&format!("/api/v1/jobs/{}", job_id)).await.status, StatusCode::NOT_FOUND);
+    assert_eq!(other_client.post(&format!("/api/v1/jobs/{}/cancel", job_id), json!({})).await.status, StatusCode::NOT_FOUND);
+    assert_eq!(other_client.get("/api/v1/jobs").await.expect(StatusCode::OK), json!([]));
+}
+
+#[sqlx::test]
+async fn test_concurrency_limit_counts_active_jobs_in_the_table(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().plan("free").create(app.pool()).await;
+
+    let first = app.state.jobs.register_for_plan(user.id, "free", JobClass::Backtest).await.unwrap();
+    let err = app.state.jobs.register_for_plan(user.id, "free", JobClass::Backtest).await.err().expect("over the cap");
+    assert!(matches!(err, trading_saas_backend::errors::AppError::JobLimit { ref running_job_ids, .. } if running_job_ids == &vec![first.job_id()]));
+    let first_id = first.job_id();
+    first.complete(None).await.unwrap();
+
+    // A job whose worker stopped checkpointing is swept and frees the slot
+    let stuck = app.state.jobs.register_for_plan(user.id, "free", JobClass::Backtest).await.unwrap();
+    let stuck_id = stuck.job_id();
+    std::mem::forget(stuck);
+    assert!(app.state.jobs.register_for_plan(user.id, "free", JobClass::Backtest).await.is_err());
+    let now = Utc::now();
+    assert_eq!(Job::fail_stale(app.pool(), now + Duration::minutes(1), now).await.unwrap(), 1);
+    assert!(app.state.jobs.register_for_plan(user.id, "free", JobClass::Backtest).await.is_ok());
+
+    let stuck = Job::find(app.pool(), user.id, stuck_id).await.unwrap().unwrap();
+    assert_eq!((stuck.status.as_str(), stuck.error.is_some()), ("failed", true));
+    let first = Job::find(app.pool(), user.id, first_id).await.unwrap().unwrap();
+    assert_eq!((first.status.as_str(), first.progress), ("completed", 100.0));
+}
@@ -0,0 +1,19 @@
+// End-to-end tests through the router, one database per test; see common for the harness
+mod common;
+
+mod access;
+mod admin;
+mod admin_cli;
+mod api_keys;
+mod auth;
+mod brokers;
+mod exposure;
+mod jobs;
+mod migrations;
+mod robots;
+mod statements;
+mod subscriptions;
+mod telemetry;
+mod tools;
+mod trades;
+mod users;
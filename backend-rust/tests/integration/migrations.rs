@@ -0,0 +1,38 @@
+use sqlx::PgPool;
+use std::time::Duration;
+use trading_saas_backend::services::{
+    migration_coordinator::{embedded_scripts, embedded_versions, MigrationCoordinator, MigrationTarget, PgMigrationTarget, SchemaVersion},
+    migration_lint::{MigrationGuard, MigrationScript},
+};
+
+#[sqlx::test]
+async fn test_strict_mode_refuses_a_pending_column_drop(pool: PgPool) {
+    let target = PgMigrationTarget::new(pool.clone());
+    // A release one migration ahead of the database, and that migration drops a column
+    let mut expected = embedded_versions();
+    expected.push(SchemaVersion { version: 29991231000001, checksum: vec![0] });
+    let mut scripts = embedded_scripts();
+    scripts.push(MigrationScript { version: 29991231000001, sql: "ALTER TABLE trades DROP COLUMN swap;".to_string() });
+    let guard = MigrationGuard::new(scripts).with_strict(true);
+
+    let flagged = MigrationCoordinator::pending_destructive(&target, &expected, &guard).await.unwrap();
+    assert_eq!(flagged.len(), 1);
+    assert_eq!(flagged[0].version, 29991231000001);
+
+    let err = MigrationCoordinator::run(&target, &expected, &guard, Duration::from_secs(1), Duration::from_millis(10))
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("--allow-destructive"), "{}", err);
+
+    let column: Option<String> = sqlx::query_scalar(
+        "SELECT column_name::TEXT FROM information_schema.columns WHERE table_name = 'trades' AND column_name = 'swap'",
+    )
+    .fetch_optional(&pool)
+    .await
+    .unwrap();
+    assert_eq!(column.as_deref(), Some("swap"));
+
+    // The advisory lock was released, so the next run can take it
+    assert!(target.try_lock().await.unwrap());
+    target.unlock().await.unwrap();
+}
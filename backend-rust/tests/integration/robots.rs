@@ -0,0 +1,429 @@
+use axum::http::StatusCode;
+use chrono::{Duration, Utc};
+use serde_json::json;
+use sqlx::PgPool;
+use trading_saas_backend::{
+    models::{TradeAiDecision, DECISION_FALLBACK, DECISION_MODEL},
+    services::plan_service::{PgPlanLimiter, PlanLimiter},
+};
+
+use crate::common::{BrokerBuilder, RobotBuilder, TradeBuilder, TestApp, UserBuilder};
+
+#[sqlx::test]
+async fn test_create_and_list_robots(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().plan("pro").create(app.pool()).await;
+    let connection = BrokerBuilder::new(&user).create(&app).await;
+    let client = app.client_as(&user);
+
+    let created = client
+        .post(
+            "/api/v1/robots",
+            json!({ "name": "EURUSD trend", "strategy": "trend_following", "broker_connection_id": connection.id }),
+        )
+        .await
+        .expect(StatusCode::OK);
+    assert_eq!(created["name"], "EURUSD trend");
+    assert_eq!(created["status"], "inactive");
+    // Settings left out come from the platform defaults
+    assert_eq!(created["risk_config"]["stop_loss_pips"], 20);
+
+    let robots = client.get("/api/v1/robots").await.expect(StatusCode::OK);
+    assert_eq!(robots.as_array().unwrap().len(), 1);
+    assert_eq!(robots[0]["id"], created["id"]);
+}
+
+#[sqlx::test]
+async fn test_plan_limits_robot_creation(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().plan("free").create(app.pool()).await;
+
+    let response = app
+        .client_as(&user)
+        .post("/api/v1/robots", json!({ "name": "Not allowed", "strategy": "trend_following" }))
+        .await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+}
+
+#[sqlx::test]
+async fn test_backtest_beyond_the_plan_history_is_refused(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().plan("free").create(app.pool()).await;
+    let robot = RobotBuilder::new(&user).create(app.pool()).await;
+    let end = Utc::now() - Duration::days(60);
+
+    let response = app
+        .client_as(&user)
+        .post(
+            &format!("/api/v1/robots/{}/optimize", robot.id),
+            json!({ "parameters": { "stop_loss_pips": [10, 20] }, "start": end - Duration::days(30), "end": end }),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+    assert_eq!(response.body["code"], "plan_limit");
+}
+
+#[sqlx::test]
+async fn test_evaluation_interval_follows_the_plan(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().plan("essential").create(app.pool()).await;
+    let robot = RobotBuilder::new(&user).create(app.pool()).await;
+    let client = app.client_as(&user);
+    let robot_path = format!("/api/v1/robots/{}", robot.id);
+
+    let response = client.patch(&robot_path, json!({ "risk_config": { "evaluation_interval_seconds": 10 } })).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    assert_eq!(response.body["code"], "plan_limit");
+
+    client
+        .patch(&robot_path, json!({ "risk_config": { "evaluation_interval_seconds": 45 } }))
+        .await
+        .expect(StatusCode::OK);
+    let signals = client.get(&format!("{}/signals", robot_path)).await.expect(StatusCode::OK);
+    assert_eq!(signals["effective_interval"], 45);
+
+    // A downgrade is picked up on the runner's next cycle; the stored 45s is raised to Free's minute
+    let limiter = PgPlanLimiter::new(app.pool().clone());
+    assert_eq!(limiter.evaluation_interval(robot.id).await.unwrap(), std::time::Duration::from_secs(45));
+    sqlx::query("UPDATE users SET subscription_plan = 'free' WHERE id = $1").bind(user.id).execute(app.pool()).await.unwrap();
+    assert_eq!(limiter.evaluation_interval(robot.id).await.unwrap(), std::time::Duration::from_secs(60));
+}
+
+#[sqlx::test]
+async fn test_update_robot_is_journaled(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().plan("pro").create(app.pool()).await;
+    let robot = RobotBuilder::new(&user).create(app.pool()).await;
+    let client = app.client_as(&user);
+
+    let updated = client
+        .patch(&format!("/api/v1/robots/{}", robot.id), json!({ "strategy": "mean_reversion" }))
+        .await
+        .expect(StatusCode::OK);
+    assert_eq!(updated["strategy"], "mean_reversion");
+
+    let changes = client.get(&format!("/api/v1/robots/{}/changes", robot.id)).await.expect(StatusCode::OK);
+    assert!(changes.to_string().contains("mean_reversion"), "no change recorded: {}", changes);
+}
+
+#[sqlx::test]
+async fn test_robots_are_private_to_their_owner(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let owner = UserBuilder::new().plan("pro").create(app.pool()).await;
+    let other = UserBuilder::new().plan("pro").create(app.pool()).await;
+    let robot = RobotBuilder::new(&owner).create(app.pool()).await;
+    let client = app.client_as(&other);
+
+    assert_eq!(client.get("/api/v1/robots").await.expect(StatusCode::OK), json!([]));
+    let response = client.patch(&format!("/api/v1/robots/{}", robot.id), json!({ "notes": "mine now" })).await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+}
+
+#[sqlx::test]
+async fn test_ignored_risk_fields_must_be_acknowledged(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().plan("pro").create(app.pool()).await;
+    let robot = RobotBuilder::new(&user).create(app.pool()).await;
+    let client = app.client_as(&user);
+    let robot_path = format!("/api/v1/robots/{}", robot.id);
+
+    // A legacy config missing a default only warns
+    client.patch(&robot_path, json!({ "risk_config": { "take_profit_pips": null } })).await.expect(StatusCode::OK);
+    let preflight = client.get(&format!("{}/preflight", robot_path)).await.expect(StatusCode::OK);
+    assert_eq!(preflight["config_warnings"], json!({ "ignored_fields": [], "defaulted_fields": ["take_profit_pips"] }));
+    assert_eq!(preflight["requires_acknowledgement"], false);
+
+    // A setting from a newer runner is not enforced by this one
+    client
+        .patch(&robot_path, json!({ "risk_config": { "trailing_stop": { "pips": 15 } } }))
+        .await
+        .expect(StatusCode::OK);
+    let preflight = client.get(&format!("{}/preflight", robot_path)).await.expect(StatusCode::OK);
+    assert_eq!(
+        preflight["config_warnings"],
+        json!({ "ignored_fields": ["trailing_stop"], "defaulted_fields": ["take_profit_pips"] })
+    );
+    assert_eq!(preflight["requires_acknowledgement"], true);
+    let signals = client.get(&format!("{}/signals", robot_path)).await.expect(StatusCode::OK);
+    assert_eq!(signals["config_warnings"], preflight["config_warnings"]);
+
+    let response = client.post(&format!("{}/start", robot_path), json!({})).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    assert!(response.body.to_string().contains("trailing_stop"), "{}", response.body);
+
+    let started = client
+        .post(&format!("{}/start?acknowledge_warnings=true", robot_path), json!({}))
+        .await
+        .expect(StatusCode::OK);
+    assert_eq!(started["status"], "active");
+    client.post(&format!("{}/stop", robot_path), json!({})).await.expect(StatusCode::OK);
+}
+
+#[sqlx::test]
+async fn test_broker_maintenance_pauses_only_its_broker_type(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let admin = UserBuilder::new().admin().create(app.pool()).await;
+    let user = UserBuilder::new().plan("pro").create(app.pool()).await;
+    let mt5 = BrokerBuilder::new(&user).create(&app).await;
+    let paper = BrokerBuilder::new(&user).broker_type("paper").login("87654321").create(&app).await;
+    let mt5_robot = RobotBuilder::new(&user).name("MT5 robot").connection(&mt5).create(app.pool()).await;
+    let paper_robot = RobotBuilder::new(&user).name("Paper robot").connection(&paper).create(app.pool()).await;
+    let client = app.client_as(&user);
+    for robot in [&mt5_robot, &paper_robot] {
+        client.post(&format!("/api/v1/robots/{}/start", robot.id), json!({})).await.expect(StatusCode::OK);
+    }
+    let status_of = |robot_id: uuid::Uuid| {
+        let pool = app.pool().clone();
+        async move {
+            sqlx::query_scalar::<_, String>("SELECT status FROM trading_robots WHERE id = $1")
+                .bind(robot_id)
+                .fetch_one(&pool)
+                .await
+                .unwrap()
+        }
+    };
+
+    let maintenance = app
+        .client_as(&admin)
+        .put(
+            "/api/v1/admin/settings/maintenance/brokers/mt5",
+            json!({ "message": "MT5 server upgrade", "until": Utc::now() + Duration::hours(1) }),
+        )
+        .await
+        .expect(StatusCode::OK);
+    assert_eq!(maintenance["scopes"][0]["broker_type"], "MT5");
+    assert_eq!(status_of(mt5_robot.id).await, "paused_broker");
+    assert_eq!(status_of(paper_robot.id).await, "active");
+
+    let status = app.anonymous().get("/api/v1/public/status").await.expect(StatusCode::OK);
+    assert_eq!(status["broker_maintenance"][0]["message"], "MT5 server upgrade");
+    assert_eq!(status["status"], "degraded");
+
+    // Orders through an MT5 connection are refused while the scope is active
+    let trade = TradeBuilder::new(&mt5_robot).closed(1.1, 5.0).create(app.pool()).await;
+    let refused = client.post(&format!("/api/v1/trades/{}/reenter", trade.id), json!({})).await;
+    assert_eq!(refused.status, StatusCode::SERVICE_UNAVAILABLE);
+    assert!(refused.body["error"].as_str().unwrap().contains("MT5 order placement is paused"));
+
+    // Restarting during the window leaves the robot paused
+    client.post(&format!("/api/v1/robots/{}/start", mt5_robot.id), json!({})).await.expect(StatusCode::OK);
+    assert_eq!(status_of(mt5_robot.id).await, "paused_broker");
+
+    app.client_as(&admin)
+        .delete("/api/v1/admin/settings/maintenance/brokers/MT5")
+        .await
+        .expect(StatusCode::OK);
+    assert_eq!(status_of(mt5_robot.id).await, "active");
+    assert_eq!(status_of(paper_robot.id).await, "active");
+    app.client_as(&admin)
+        .delete("/api/v1/admin/settings/maintenance/brokers/MT5")
+        .await
+        .expect(StatusCode::NOT_FOUND);
+
+    for robot in [&mt5_robot, &paper_robot] {
+        client.post(&format!("/api/v1/robots/{}/stop", robot.id), json!({})).await.expect(StatusCode::OK);
+    }
+}
+
+fn assert_close(actual: &serde_json::Value, expected: f64) {
+    let actual = actual.as_f64().unwrap_or_else(|| panic!("not a number: {}", actual));
+    assert!((actual - expected).abs() < 1e-6, "{} != {}", actual, expected);
+}
+
+#[sqlx::test]
+async fn test_ai_quality_reports_model_decisions(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().plan("pro").create(app.pool()).await;
+    let robot = RobotBuilder::new(&user).create(app.pool()).await;
+
+    // (confidence, sell, stop loss, exit, profit, what the fallback said); buys and sells enter at 1.1000
+    let history = [
+        (0.55, false, Some(1.0950), 1.1050, 50.0, "buy"),
+        (0.58, false, Some(1.0950), 1.0950, -50.0, "hold"),
+        (0.72, true, Some(1.1050), 1.0900, 100.0, "buy"),
+        (0.74, false, Some(1.0950), 1.1100, 100.0, "buy"),
+        (0.76, true, None, 1.1020, -20.0, "sell"),
+    ];
+    for (confidence, sell, stop_loss, exit, profit, fallback) in history {
+        let mut builder = TradeBuilder::new(&robot).ai_confidence(confidence).closed(exit, profit);
+        if sell {
+            builder = builder.sell();
+        }
+        if let Some(stop_loss) = stop_loss {
+            builder = builder.stop_loss(stop_loss);
+        }
+        let trade = builder.create(app.pool()).await;
+        let decision = TradeAiDecision::new(trade.id, DECISION_MODEL, trade.opened_at).with_fallback_signal(fallback);
+        TradeAiDecision::record(app.pool(), &decision).await.unwrap();
+    }
+    // Counted as a decision but left out of the model's metrics
+    let fallback = TradeBuilder::new(&robot).ai_confidence(0.65).closed(1.1010, 10.0).create(app.pool()).await;
+    TradeAiDecision::record(app.pool(), &TradeAiDecision::new(fallback.id, DECISION_FALLBACK, fallback.opened_at)).await.unwrap();
+    // Closed before the period
+    let old = TradeBuilder::new(&robot)
+        .ai_confidence(0.95)
+        .closed(1.0900, -100.0)
+        .closed_at(Utc::now() - Duration::days(200))
+        .create(app.pool())
+        .await;
+    TradeAiDecision::record(app.pool(), &TradeAiDecision::new(old.id, DECISION_MODEL, old.opened_at)).await.unwrap();
+
+    let report = app
+        .client_as(&user)
+        .get(&format!("/api/v1/robots/{}/ai-quality?days=30", robot.id))
+        .await
+        .expect(StatusCode::OK);
+
+    assert_eq!(report["status"], "model");
+    assert_eq!(report["decisions"], 6);
+    assert_eq!(report["model_decisions"], 5);
+
+    let calibration = report["calibration"].as_array().unwrap();
+    assert_eq!(calibration.len(), 10);
+    assert_eq!(calibration[5]["trades"], 2);
+    assert_close(&calibration[5]["predicted"], 0.565);
+    assert_close(&calibration[5]["win_rate"], 0.5);
+    assert_eq!(calibration[7]["trades"], 3);
+    assert_close(&calibration[7]["predicted"], 0.74);
+    assert_close(&calibration[7]["win_rate"], 2.0 / 3.0);
+    assert_eq!(calibration[9]["trades"], 0);
+    assert!(calibration[9]["win_rate"].is_null());
+
+    assert_eq!(
+        report["hit_rate_by_signal"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| (s["signal"].as_str().unwrap(), s["trades"].as_i64().unwrap(), s["wins"].as_i64().unwrap()))
+            .collect::<Vec<_>>(),
+        vec![("buy", 3, 2), ("sell", 2, 1)]
+    );
+
+    // Five decisions make five single-trade deciles
+    let deciles = report["r_by_confidence_decile"].as_array().unwrap();
+    assert_eq!(deciles.len(), 5);
+    for (decile, (confidence, r)) in deciles.iter().zip([(0.55, 1.0), (0.58, -1.0), (0.72, 2.0), (0.74, 2.0)]) {
+        assert_close(&decile["min_confidence"], confidence);
+        assert_close(&decile["average_r"], r);
+    }
+    assert_eq!(deciles[4]["trades_with_r"], 0);
+    assert!(deciles[4]["average_r"].is_null());
+
+    let overrides = &report["overrides"];
+    assert_eq!(overrides["compared"], 5);
+    assert_eq!(overrides["overridden"], 2);
+    assert_close(&overrides["share"], 0.4);
+    assert_close(&overrides["overridden_avg_profit"], 25.0);
+    assert_close(&overrides["agreed_avg_profit"], 130.0 / 3.0);
+    assert_close(&overrides["outcome_delta"], 25.0 - 130.0 / 3.0);
+}
+
+#[sqlx::test]
+async fn test_ai_quality_says_when_only_the_fallback_decided(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().plan("pro").create(app.pool()).await;
+    let robot = RobotBuilder::new(&user).create(app.pool()).await;
+    let client = app.client_as(&user);
+    let path = format!("/api/v1/robots/{}/ai-quality", robot.id);
+
+    assert_eq!(client.get(&path).await.expect(StatusCode::OK)["status"], "no_decisions");
+
+    let trade = TradeBuilder::new(&robot).ai_confidence(0.75).closed(1.1050, 50.0).create(app.pool()).await;
+    TradeAiDecision::record(app.pool(), &TradeAiDecision::new(trade.id, DECISION_FALLBACK, trade.opened_at)).await.unwrap();
+
+    let report = client.get(&path).await.expect(StatusCode::OK);
+    assert_eq!(report["status"], "fallback_only");
+    assert_eq!(report["decisions"], 1);
+    assert!(report["message"].as_str().unwrap().contains("fallback"));
+    assert!(report["calibration"].is_null() && report["overrides"].is_null());
+
+    let other = UserBuilder::new().plan("pro").create(app.pool()).await;
+    assert_eq!(app.client_as(&other).get(&path).await.status, StatusCode::NOT_FOUND);
+}
+
+#[sqlx::test]
+async fn test_missing_performance_counters_come_from_the_trades(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().plan("pro").create(app.pool()).await;
+    let legacy = RobotBuilder::new(&user).name("Legacy").create(app.pool()).await;
+    let partial = RobotBuilder::new(&user).name("Partial").create(app.pool()).await;
+    let complete = RobotBuilder::new(&user).name("Complete").create(app.pool()).await;
+    for robot in [&legacy, &partial, &complete] {
+        for profit in [10.0, 5.0, -4.0, -1.0] {
+            TradeBuilder::new(robot).closed(1.1, profit).create(app.pool()).await;
+        }
+        TradeBuilder::new(robot).create(app.pool()).await;
+    }
+    // What the migration leaves of a NULL row, a row missing a key, and one whose counters are trusted
+    let set_metrics = |id: uuid::Uuid, metrics: serde_json::Value, total_trades: i32| {
+        sqlx::query("UPDATE trading_robots SET performance_metrics = $2, total_trades = $3 WHERE id = $1")
+            .bind(id)
+            .bind(metrics)
+            .bind(total_trades)
+            .execute(app.pool())
+    };
+    set_metrics(legacy.id, json!({}), 0).await.unwrap();
+    set_metrics(partial.id, json!({ "total_profit": 10.0 }), 0).await.unwrap();
+    set_metrics(complete.id, json!({ "total_profit": 7.5, "winning_trades": 1 }), 2).await.unwrap();
+
+    let robots = app.client_as(&user).get("/api/v1/robots").await.expect(StatusCode::OK);
+    let robot = |name: &str| robots.as_array().unwrap().iter().find(|r| r["name"] == name).cloned().unwrap();
+    for name in ["Legacy", "Partial"] {
+        let counted = robot(name);
+        assert_eq!(
+            (counted["total_trades"].as_i64(), counted["winning_trades"].as_i64(), counted["win_rate"].as_f64()),
+            (Some(4), Some(2), Some(50.0))
+        );
+        assert_eq!(counted["total_profit"].as_f64(), Some(10.0));
+    }
+    let stored = robot("Complete");
+    assert_eq!(
+        (stored["total_trades"].as_i64(), stored["winning_trades"].as_i64(), stored["win_rate"].as_f64()),
+        (Some(2), Some(1), Some(50.0))
+    );
+    assert_eq!(stored["total_profit"].as_f64(), Some(7.5));
+
+    // Only the response is filled in; the row is left for refresh_performance to rewrite
+    let metrics: serde_json::Value = sqlx::query_scalar("SELECT performance_metrics FROM trading_robots WHERE id = $1")
+        .bind(legacy.id)
+        .fetch_one(app.pool())
+        .await
+        .unwrap();
+    assert_eq!(metrics, json!({}));
+}
+
+#[sqlx::test]
+async fn test_lists_answer_envelope_with_a_page_and_the_total(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().plan("pro").create(app.pool()).await;
+    BrokerBuilder::new(&user).create(&app).await;
+    let mut robots = Vec::new();
+    for name in ["First", "Second", "Third"] {
+        robots.push(RobotBuilder::new(&user).name(name).create(app.pool()).await);
+    }
+    for _ in 0..3 {
+        TradeBuilder::new(&robots[0]).create(app.pool()).await;
+    }
+    let client = app.client_as(&user);
+
+    let first = client.get("/api/v1/robots?envelope=true&limit=2").await.expect(StatusCode::OK);
+    assert_eq!((first["total"].as_i64(), first["limit"].as_i64(), first["offset"].as_i64()), (Some(3), Some(2), Some(0)));
+    let second = client.get("/api/v1/robots?envelope=true&limit=2&offset=2").await.expect(StatusCode::OK);
+    let mut names: Vec<_> = first["items"].as_array().unwrap().iter().chain(second["items"].as_array().unwrap()).map(|r| r["name"].clone()).collect();
+    names.sort_by_key(|name| name.to_string());
+    assert_eq!(names, vec![json!("First"), json!("Second"), json!("Third")]);
+
+    // v1 clients still get every robot as a bare array
+    let all = client.get("/api/v1/robots?limit=1").await.expect(StatusCode::OK);
+    assert_eq!(all.as_array().unwrap().len(), 3);
+
+    let brokers = client.get("/api/v1/brokers?envelope=true").await.expect(StatusCode::OK);
+    assert_eq!((brokers["items"].as_array().unwrap().len(), brokers["total"].as_i64()), (1, Some(1)));
+
+    let trades = client.get("/api/v1/trades?envelope=true&limit=1&offset=1").await;
+    assert!(!trades.headers.contains_key("deprecation"));
+    let trades = trades.expect(StatusCode::OK);
+    assert_eq!((trades["items"].as_array().unwrap().len(), trades["total"].as_i64()), (1, Some(3)));
+    let positions = client.get("/api/v1/trades?envelope=true&group_by=position&limit=2").await.expect(StatusCode::OK);
+    assert_eq!((positions["items"].as_array().unwrap().len(), positions["total"].as_i64()), (2, Some(3)));
+}
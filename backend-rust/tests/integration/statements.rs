@@ -0,0 +1,105 @@
+use axum::http::StatusCode;
+use chrono::{TimeZone, Utc};
+use sqlx::PgPool;
+
+use trading_saas_backend::models::{AccountInfo, AccountSnapshot, SnapshotGranularity};
+
+use crate::common::{BrokerBuilder, RobotBuilder, TestApp, TradeBuilder, UserBuilder};
+
+#[sqlx::test]
+async fn test_statement_totals_and_immutability(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().plan("pro").create(app.pool()).await;
+    let connection = BrokerBuilder::new(&user).live("Main account").create(&app).await;
+    let robot = RobotBuilder::new(&user).connection(&connection).create(app.pool()).await;
+    let march = |day, hour| Utc.with_ymd_and_hms(2024, 3, day, hour, 0, 0).unwrap();
+
+    TradeBuilder::new(&robot).closed(1.1050, 120.0).closed_at(march(4, 10)).fees(-1.4, -0.3).create(app.pool()).await;
+    let corrected = TradeBuilder::new(&robot)
+        .closed(1.0980, -45.5)
+        .closed_at(march(11, 15))
+        .fees(-1.4, 0.0)
+        .create(app.pool())
+        .await;
+    TradeBuilder::new(&robot)
+        .symbol("XAUUSD")
+        .closed(2170.0, 310.25)
+        .closed_at(march(15, 9))
+        .fees(-3.5, -2.1)
+        .create(app.pool())
+        .await;
+    // Closed in April, so not on the March statement
+    TradeBuilder::new(&robot)
+        .closed(1.0850, 80.0)
+        .closed_at(Utc.with_ymd_and_hms(2024, 4, 2, 8, 0, 0).unwrap())
+        .create(app.pool())
+        .await;
+
+    for (captured_at, balance) in [(march(1, 0) - chrono::Duration::days(1), 10_000.0), (march(31, 0), 10_376.35)] {
+        let info = AccountInfo {
+            account_number: "12345678".to_string(),
+            balance,
+            equity: balance,
+            margin: 0.0,
+            free_margin: balance,
+            currency: "USD".to_string(),
+        };
+        let snapshot = AccountSnapshot::new(&connection, &info, SnapshotGranularity::Day, captured_at);
+        AccountSnapshot::upsert(app.pool(), &snapshot).await.unwrap();
+    }
+
+    let client = app.client_as(&user);
+    let statement = client.get("/api/v1/statements/2024/3").await.expect(StatusCode::OK);
+    assert_eq!(statement["is_final"], true);
+    let account = &statement["accounts"][0];
+    assert_eq!(account["connection_name"], "Main account");
+    assert_eq!((account["opening_balance"].as_f64(), account["ending_balance"].as_f64()), (Some(10_000.0), Some(10_376.35)));
+    assert_eq!(account["totals"]["trades"], 3);
+    assert_eq!(account["totals"]["profit_loss"].as_f64(), Some(384.75));
+    assert_eq!(account["totals"]["commission"].as_f64(), Some(-6.3));
+    assert_eq!(account["totals"]["swap"].as_f64(), Some(-2.4));
+    assert_eq!(account["totals"]["net"].as_f64(), Some(376.05));
+    let symbols: Vec<(&str, i64)> = account["symbols"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| (s["symbol"].as_str().unwrap(), s["trades"].as_i64().unwrap()))
+        .collect();
+    assert_eq!(symbols, vec![("EURUSD", 2), ("XAUUSD", 1)]);
+
+    // A correction after the statement was issued leaves it as it was
+    sqlx::query("UPDATE trades SET profit_loss = -40.5 WHERE id = $1").bind(corrected.id).execute(app.pool()).await.unwrap();
+    let again = client.get("/api/v1/statements/2024/3").await.expect(StatusCode::OK);
+    assert_eq!(again, statement);
+
+    // and is booked on April's statement instead
+    let april = client.get("/api/v1/statements/2024/4").await.expect(StatusCode::OK);
+    let account = &april["accounts"][0];
+    assert_eq!(account["opening_balance"].as_f64(), Some(10_376.35));
+    assert_eq!(account["totals"]["trades"], 1);
+    assert_eq!(account["adjustments"][0]["trade_id"], corrected.id.to_string());
+    assert_eq!(account["adjustments"][0]["amount"].as_f64(), Some(5.0));
+    assert_eq!(account["totals"]["adjustments"].as_f64(), Some(5.0));
+
+    let html = client.get("/api/v1/statements/2024/3?format=html").await.expect(StatusCode::OK);
+    assert!(html.as_str().unwrap().contains("Monthly statement &mdash; March 2024"));
+}
+
+#[sqlx::test]
+async fn test_statements_are_private_and_validated(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().create(app.pool()).await;
+    let other = UserBuilder::new().email("other@example.com").create(app.pool()).await;
+    let robot = RobotBuilder::new(&other).create(app.pool()).await;
+    TradeBuilder::new(&robot)
+        .closed(1.1050, 50.0)
+        .closed_at(Utc.with_ymd_and_hms(2024, 3, 4, 10, 0, 0).unwrap())
+        .create(app.pool())
+        .await;
+
+    let statement = app.client_as(&user).get("/api/v1/statements/2024/3").await.expect(StatusCode::OK);
+    assert_eq!(statement["accounts"], serde_json::json!([]));
+
+    app.client_as(&user).get("/api/v1/statements/2024/13").await.expect(StatusCode::BAD_REQUEST);
+    app.client_as(&user).get("/api/v1/statements/2024/3?format=pdf").await.expect(StatusCode::BAD_REQUEST);
+}
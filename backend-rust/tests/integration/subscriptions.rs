@@ -0,0 +1,123 @@
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use trading_saas_backend::models::TradingRobot;
+use uuid::Uuid;
+
+use crate::common::{RobotBuilder, TestApp, UserBuilder};
+
+fn status_of(robots: &Value, id: Uuid) -> String {
+    let robot = robots.as_array().unwrap().iter().find(|r| r["id"] == json!(id)).expect("robot listed");
+    robot["status"].as_str().unwrap().to_string()
+}
+
+async fn plan_limit_logs(app: &TestApp, robot_id: Uuid) -> i64 {
+    sqlx::query_scalar("SELECT COUNT(*) FROM robot_logs WHERE robot_id = $1 AND message LIKE 'Stopped: the % plan allows%'")
+        .bind(robot_id)
+        .fetch_one(app.pool())
+        .await
+        .unwrap()
+}
+
+#[sqlx::test]
+async fn test_downgrade_keeps_the_chosen_robots(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().plan("pro").create(app.pool()).await;
+    let first = RobotBuilder::new(&user).name("First").create(app.pool()).await;
+    let second = RobotBuilder::new(&user).name("Second").create(app.pool()).await;
+    let third = RobotBuilder::new(&user).name("Third").create(app.pool()).await;
+    for robot in [&first, &second] {
+        TradingRobot::update_status(app.pool(), robot.id, user.id, "active").await.unwrap();
+    }
+    let client = app.client_as(&user);
+
+    // Three robots do not fit the Essential plan, so the user has to say which one stays
+    let refused = client
+        .post("/api/v1/subscriptions", json!({ "plan_name": "essential", "payment_method_id": "pm_test" }))
+        .await
+        .expect(StatusCode::BAD_REQUEST);
+    assert!(refused["error"].as_str().unwrap().contains("keep_robot_ids"));
+    assert_eq!(refused["code"], "plan_limit");
+
+    client
+        .post(
+            "/api/v1/subscriptions",
+            json!({ "plan_name": "essential", "payment_method_id": "pm_test", "keep_robot_ids": [second.id] }),
+        )
+        .await
+        .expect(StatusCode::OK);
+
+    let robots = client.get("/api/v1/robots").await.expect(StatusCode::OK);
+    assert_eq!(status_of(&robots, second.id), "active");
+    assert_eq!(status_of(&robots, first.id), "plan_limited");
+    assert_eq!(status_of(&robots, third.id), "plan_limited");
+    assert_eq!(plan_limit_logs(&app, first.id).await, 1);
+    assert_eq!(plan_limit_logs(&app, second.id).await, 0);
+
+    let response = client.post(&format!("/api/v1/robots/{}/start", first.id), json!({})).await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+}
+
+#[sqlx::test]
+async fn test_checkout_downgrade_parks_the_robots_not_running(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().plan("pro").create(app.pool()).await;
+    let idle = RobotBuilder::new(&user).name("Idle").create(app.pool()).await;
+    let running = RobotBuilder::new(&user).name("Running").create(app.pool()).await;
+    TradingRobot::update_status(app.pool(), running.id, user.id, "active").await.unwrap();
+    let client = app.client_as(&user);
+
+    let session = client
+        .post("/api/v1/subscriptions/checkout-session", json!({ "plan_name": "essential" }))
+        .await
+        .expect(StatusCode::OK);
+    let session_id = session["session_id"].as_str().unwrap();
+    let completed = client
+        .post(&format!("/api/v1/subscriptions/checkout-session/{}/complete", session_id), json!({}))
+        .await
+        .expect(StatusCode::OK);
+    assert_eq!(completed["activated"], true);
+
+    // Nobody to ask on this path: the robot that is not running is the one parked
+    let robots = client.get("/api/v1/robots").await.expect(StatusCode::OK);
+    assert_eq!(status_of(&robots, running.id), "active");
+    assert_eq!(status_of(&robots, idle.id), "plan_limited");
+    assert_eq!(plan_limit_logs(&app, idle.id).await, 1);
+}
+
+#[sqlx::test]
+async fn test_upgrade_reactivates_parked_robots(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().plan("pro").create(app.pool()).await;
+    let kept = RobotBuilder::new(&user).name("Kept").create(app.pool()).await;
+    let was_running = RobotBuilder::new(&user).name("Was running").create(app.pool()).await;
+    let was_stopped = RobotBuilder::new(&user).name("Was stopped").create(app.pool()).await;
+    for robot in [&kept, &was_running] {
+        TradingRobot::update_status(app.pool(), robot.id, user.id, "active").await.unwrap();
+    }
+    let client = app.client_as(&user);
+    client
+        .post(
+            "/api/v1/subscriptions",
+            json!({ "plan_name": "essential", "payment_method_id": "pm_test", "keep_robot_ids": [kept.id] }),
+        )
+        .await
+        .expect(StatusCode::OK);
+
+    // Essential is full with the kept robot
+    let response = client.post("/api/v1/robots/reactivate", json!({})).await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+
+    client
+        .post("/api/v1/subscriptions", json!({ "plan_name": "pro", "payment_method_id": "pm_test" }))
+        .await
+        .expect(StatusCode::OK);
+    let reactivated = client.post("/api/v1/robots/reactivate", json!({})).await.expect(StatusCode::OK);
+    assert_eq!(reactivated.as_array().unwrap().len(), 2);
+    assert_eq!(status_of(&reactivated, was_running.id), "active");
+    assert_eq!(status_of(&reactivated, was_stopped.id), "stopped");
+
+    // Nothing left to bring back
+    let again = client.post("/api/v1/robots/reactivate", json!({})).await.expect(StatusCode::OK);
+    assert!(again.as_array().unwrap().is_empty());
+}
@@ -0,0 +1,113 @@
+use axum::{http::HeaderMap, http::StatusCode, routing::post, Json, Router};
+use opentelemetry::trace::{SpanKind, TracerProvider as _};
+use opentelemetry_sdk::{export::trace::SpanData, testing::trace::InMemorySpanExporter, trace::TracerProvider};
+use serde_json::json;
+use sqlx::PgPool;
+use std::sync::{Arc, Mutex};
+use tracing_subscriber::layer::SubscriberExt;
+use trading_saas_backend::{services::StripeService, telemetry};
+
+use crate::common::{RobotBuilder, TestApp, UserBuilder};
+
+// Spans of this thread go to memory until the guard drops; the test runtimes are single-threaded
+fn trace_to_memory() -> (InMemorySpanExporter, TracerProvider, tracing::subscriber::DefaultGuard) {
+    let exporter = InMemorySpanExporter::default();
+    let provider = TracerProvider::builder().with_simple_exporter(exporter.clone()).build();
+    let subscriber = tracing_subscriber::registry().with(telemetry::layer(Some(provider.tracer("test"))));
+    let guard = tracing::subscriber::set_default(subscriber);
+    (exporter, provider, guard)
+}
+
+fn finished_spans(exporter: &InMemorySpanExporter, provider: &TracerProvider) -> Vec<SpanData> {
+    provider.force_flush();
+    exporter.get_finished_spans().unwrap()
+}
+
+fn span_named<'a>(spans: &'a [SpanData], name: &str) -> &'a SpanData {
+    spans
+        .iter()
+        .find(|span| span.name == name)
+        .unwrap_or_else(|| panic!("no span {} in {:?}", name, spans.iter().map(|s| &s.name).collect::<Vec<_>>()))
+}
+
+#[sqlx::test]
+async fn test_request_trace_nests_handler_and_queries(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().plan("pro").create(app.pool()).await;
+    RobotBuilder::new(&user).create(app.pool()).await;
+    let (exporter, provider, _guard) = trace_to_memory();
+
+    app.client_as(&user).get("/api/v1/robots").await.expect(StatusCode::OK);
+
+    let spans = finished_spans(&exporter, &provider);
+    let request = span_named(&spans, "GET /api/v1/robots");
+    let handler = span_named(&spans, "handler /api/v1/robots");
+    assert_eq!(request.span_kind, SpanKind::Server);
+    assert_eq!(handler.parent_span_id, request.span_context.span_id());
+    assert_eq!(handler.span_context.trace_id(), request.span_context.trace_id());
+
+    let queries: Vec<&SpanData> = spans
+        .iter()
+        .filter(|span| span.span_kind == SpanKind::Client && span.parent_span_id == handler.span_context.span_id())
+        .collect();
+    let statement = |span: &SpanData| {
+        span.attributes.iter().find(|kv| kv.key.as_str() == "db.statement").map(|kv| kv.value.as_str().to_string())
+    };
+    assert!(
+        queries.iter().any(|span| statement(span).is_some_and(|sql| sql.contains("trading_robots"))),
+        "no robots query under the handler"
+    );
+    for query in &queries {
+        assert_eq!(query.span_context.trace_id(), request.span_context.trace_id());
+        assert!(query.start_time <= query.end_time);
+    }
+}
+
+#[sqlx::test]
+async fn test_incoming_traceparent_is_continued(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let (exporter, provider, _guard) = trace_to_memory();
+
+    app.anonymous()
+        .header("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")
+        .get("/health")
+        .await
+        .expect(StatusCode::OK);
+
+    let spans = finished_spans(&exporter, &provider);
+    let request = span_named(&spans, "GET /health");
+    assert_eq!(request.span_context.trace_id().to_string(), "4bf92f3577b34da6a3ce929d0e0e4736");
+    assert_eq!(request.parent_span_id.to_string(), "00f067aa0ba902b7");
+}
+
+#[tokio::test]
+async fn test_outbound_stripe_call_carries_traceparent() {
+    let (exporter, provider, _guard) = trace_to_memory();
+
+    // Stands in for the Stripe API and keeps the traceparent it was sent
+    let received = Arc::new(Mutex::new(None));
+    let seen = received.clone();
+    let stub = Router::new().route(
+        "/v1/customers",
+        post(move |headers: HeaderMap| async move {
+            *seen.lock().unwrap() = headers.get("traceparent").map(|value| value.to_str().unwrap().to_string());
+            Json(json!({ "id": "cus_test", "email": "trader@example.com", "name": null }))
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, stub).await.unwrap() });
+
+    let stripe = StripeService::new("sk_test_stub".to_string()).with_api_url(&format!("http://{}", address));
+    let customer = stripe.create_customer("trader@example.com", None).await.unwrap();
+    assert_eq!(customer.id, "cus_test");
+
+    let spans = finished_spans(&exporter, &provider);
+    let call = span_named(&spans, "stripe.create_customer");
+    assert_eq!(call.span_kind, SpanKind::Client);
+    let traceparent = received.lock().unwrap().clone().expect("traceparent sent");
+    assert_eq!(
+        traceparent,
+        format!("00-{}-{}-01", call.span_context.trace_id(), call.span_context.span_id())
+    );
+}
@@ -0,0 +1,68 @@
+use axum::http::StatusCode;
+use serde_json::json;
+use sqlx::PgPool;
+use trading_saas_backend::{
+    models::TradingRobot,
+    services::{symbol_spec::SymbolSpec, AllocationService},
+};
+
+use crate::common::{BrokerBuilder, TestApp, UserBuilder};
+
+#[sqlx::test]
+async fn test_risk_calculator_sizes_like_the_runner_on_the_broker_balance(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().create(app.pool()).await;
+    let connection = BrokerBuilder::new(&user).create(&app).await;
+
+    let result = app
+        .client_as(&user)
+        .post(
+            "/api/v1/tools/risk-calculator",
+            json!({ "symbol": "eurusd", "risk_percent": 1, "stop_loss_pips": 20, "price": 1.1, "max_open_positions": 2 }),
+        )
+        .await
+        .expect(StatusCode::OK);
+
+    // The bridge reports a 10,000 USD balance for the connection
+    assert_eq!(result["broker_connection_id"], json!(connection.id));
+    assert_eq!(result["account_balance"], 10_000.0);
+    let mut robot = TradingRobot::new(user.id, "robot".to_string(), "trend".to_string());
+    robot.risk_config = json!({ "max_risk_per_trade": 0.01, "stop_loss_pips": 20 });
+    let runner = AllocationService::volume(&robot, 10_000.0, &SymbolSpec::for_symbol("EURUSD").unwrap(), 10.0);
+    assert_eq!(result["position_size"], runner);
+    assert_eq!(result["worst_case_loss_per_trade"], 100.0);
+    assert_eq!(result["daily_loss_limit"], 500.0);
+    assert_eq!(result["worst_case_daily_loss"], 700.0);
+    assert_eq!(result["warnings"], json!([]));
+}
+
+#[sqlx::test]
+async fn test_risk_calculator_warns_when_the_minimum_lot_risks_more(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().create(app.pool()).await;
+    let client = app.client_as(&user);
+
+    let result = client
+        .post(
+            "/api/v1/tools/risk-calculator",
+            json!({ "symbol": "EURUSD", "risk_percent": 0.5, "stop_loss_pips": 50, "account_balance": 500, "price": 1.1 }),
+        )
+        .await
+        .expect(StatusCode::OK);
+    assert_eq!(result["position_size"], 0.01);
+    assert_eq!(result["risk_percent"], 1.0);
+    assert!(result["warnings"][0].as_str().unwrap().contains("minimum of 0.01 lots"));
+
+    // No balance given and no broker connected to read one from
+    let response = client
+        .post("/api/v1/tools/risk-calculator", json!({ "symbol": "EURUSD", "risk_percent": 1, "stop_loss_pips": 20, "price": 1.1 }))
+        .await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    let response = client
+        .post(
+            "/api/v1/tools/risk-calculator",
+            json!({ "symbol": "US30", "risk_percent": 1, "stop_loss_pips": 20, "account_balance": 500, "price": 1.1 }),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+}
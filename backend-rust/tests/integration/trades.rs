@@ -0,0 +1,426 @@
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use chrono::{Duration, SecondsFormat, Utc};
+use serde_json::json;
+use sqlx::PgPool;
+use std::time::Instant;
+use trading_saas_backend::{
+    models::{Trade, TradeAmendment, TradeCorrection, TradeDailyFact, TradeFilter, TradeOrigin, CORRECTION_LATE_FILL, ORIGIN_WEBHOOK},
+    services::{trade_origins, TradeFactsBackfill},
+};
+
+use crate::common::{BrokerBuilder, RobotBuilder, TestApp, TestClient, TradeBuilder, UserBuilder};
+
+#[sqlx::test]
+async fn test_list_trades(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().plan("pro").create(app.pool()).await;
+    let robot = RobotBuilder::new(&user).create(app.pool()).await;
+    let open = TradeBuilder::new(&robot).create(app.pool()).await;
+    let closed = TradeBuilder::new(&robot).symbol("GBPUSD").closed(1.2050, 50.0).create(app.pool()).await;
+
+    let page = app.client_as(&user).get("/api/v1/trades").await.expect(StatusCode::OK);
+    assert!(page["after"].is_null());
+    let mut ids: Vec<String> = page["trades"].as_array().unwrap().iter().map(|t| t["id"].as_str().unwrap().to_string()).collect();
+    ids.sort();
+    let mut expected = vec![open.id.to_string(), closed.id.to_string()];
+    expected.sort();
+    assert_eq!(ids, expected);
+
+    // Every trade is a position of its own until one is split
+    let positions = app.client_as(&user).get("/api/v1/trades?group_by=position").await.expect(StatusCode::OK);
+    assert_eq!(positions.as_array().unwrap().len(), 2);
+}
+
+#[sqlx::test]
+async fn test_reentry_places_an_order_with_the_broker(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().plan("pro").create(app.pool()).await;
+    let connection = BrokerBuilder::new(&user).create(&app).await;
+    let robot = RobotBuilder::new(&user).connection(&connection).create(app.pool()).await;
+    let original = TradeBuilder::new(&robot).volume(0.3).closed(1.1050, 15.0).create(app.pool()).await;
+
+    let reentered =
+        app.client_as(&user).post(&format!("/api/v1/trades/{}/reenter", original.id), json!({})).await.expect(StatusCode::OK);
+    assert_eq!(reentered["reentered_from"], original.id.to_string());
+
+    let orders = app.broker.orders();
+    assert_eq!(orders.len(), 1);
+    let (placed_on, order) = &orders[0];
+    assert_eq!(placed_on, &connection.id.to_string());
+    assert_eq!((order.symbol.as_str(), order.order_type.as_str(), order.volume), ("EURUSD", "BUY", 0.3));
+}
+
+#[sqlx::test]
+async fn test_statistics_count_closed_trades(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().plan("pro").create(app.pool()).await;
+    let robot = RobotBuilder::new(&user).create(app.pool()).await;
+    TradeBuilder::new(&robot).closed(1.1050, 50.0).create(app.pool()).await;
+    TradeBuilder::new(&robot).closed(1.0980, -20.0).create(app.pool()).await;
+    TradeBuilder::new(&robot).create(app.pool()).await;
+
+    let statistics = app.client_as(&user).get("/api/v1/trades/statistics").await.expect(StatusCode::OK);
+    assert_eq!(statistics["total_trades"], 2);
+    assert_eq!(statistics["winning_trades"], 1);
+}
+
+#[sqlx::test]
+async fn test_statistics_are_cut_to_the_plan_history(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    for (plan, total, truncated) in [("free", 1, true), ("elite", 2, false)] {
+        let user = UserBuilder::new().plan(plan).create(app.pool()).await;
+        let robot = RobotBuilder::new(&user).create(app.pool()).await;
+        TradeBuilder::new(&robot).closed(1.1050, 50.0).create(app.pool()).await;
+        TradeBuilder::new(&robot)
+            .opened_at(Utc::now() - Duration::days(400))
+            .closed(1.0980, -20.0)
+            .create(app.pool())
+            .await;
+
+        let statistics = app.client_as(&user).get("/api/v1/trades/statistics").await.expect(StatusCode::OK);
+        assert_eq!((statistics["total_trades"].as_i64(), statistics["truncated"].as_bool()), (Some(total), Some(truncated)), "{}", plan);
+    }
+}
+
+#[sqlx::test]
+async fn test_trades_are_private_to_their_owner(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let owner = UserBuilder::new().plan("pro").create(app.pool()).await;
+    let other = UserBuilder::new().plan("pro").create(app.pool()).await;
+    let robot = RobotBuilder::new(&owner).create(app.pool()).await;
+    TradeBuilder::new(&robot).create(app.pool()).await;
+
+    let page = app.client_as(&other).get("/api/v1/trades").await.expect(StatusCode::OK);
+    assert_eq!(page["trades"].as_array().unwrap().len(), 0);
+}
+
+fn trade_ids(page: &serde_json::Value) -> Vec<String> {
+    page["trades"].as_array().unwrap().iter().map(|t| t["id"].as_str().unwrap().to_string()).collect()
+}
+
+// Follows `after` from `page` to the last page, collecting the trade ids on the way
+async fn follow_pages(client: &TestClient, query: &str, mut page: serde_json::Value) -> Vec<String> {
+    let mut ids = Vec::new();
+    loop {
+        ids.extend(trade_ids(&page));
+        let Some(after) = page["after"].as_str() else {
+            return ids;
+        };
+        page = client.get(&format!("/api/v1/trades?{}&after={}", query, after)).await.expect(StatusCode::OK);
+    }
+}
+
+#[sqlx::test]
+async fn test_trade_pages_hold_still_while_trades_are_inserted(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().plan("pro").create(app.pool()).await;
+    let robot = RobotBuilder::new(&user).create(app.pool()).await;
+    let start = Utc::now() - Duration::hours(1);
+    let mut existing = Vec::new();
+    for second in [0, 1, 2, 2, 2, 3, 4] {
+        // Three trades share an instant; the id keeps them in a fixed order
+        let trade = TradeBuilder::new(&robot).created_at(start + Duration::seconds(second)).create(app.pool()).await;
+        existing.push(trade);
+    }
+    existing.sort_by_key(|t| std::cmp::Reverse((t.created_at, t.id)));
+    let expected: Vec<String> = existing.iter().map(|t| t.id.to_string()).collect();
+    let client = app.client_as(&user);
+
+    let first = client.get("/api/v1/trades?limit=3").await.expect(StatusCode::OK);
+    for _ in 0..2 {
+        TradeBuilder::new(&robot).create(app.pool()).await;
+    }
+    assert_eq!(follow_pages(&client, "limit=3", first).await, expected);
+
+    // Offset paging shifts by the rows inserted in between, and is flagged as deprecated
+    let first = client.get("/api/v1/trades?limit=3&offset=0").await;
+    assert_eq!(first.headers.get("deprecation").map(|v| v.to_str().unwrap()), Some("true"));
+    TradeBuilder::new(&robot).create(app.pool()).await;
+    let second = client.get("/api/v1/trades?limit=3&offset=3").await.expect(StatusCode::OK);
+    assert!(trade_ids(&first.body).contains(&trade_ids(&second)[0]));
+    let keyset = client.get("/api/v1/trades?limit=3").await;
+    assert!(!keyset.headers.contains_key("deprecation"));
+}
+
+#[sqlx::test]
+async fn test_trade_cursor_combines_with_filters(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().plan("pro").create(app.pool()).await;
+    let trend = RobotBuilder::new(&user).name("Trend").create(app.pool()).await;
+    let scalper = RobotBuilder::new(&user).name("Scalper").create(app.pool()).await;
+    let start = Utc::now() - Duration::hours(1);
+    let mut expected = Vec::new();
+    for minute in 0..9 {
+        let (robot, symbol) = match minute % 3 {
+            0 => (&trend, "GBPUSD"),
+            1 => (&trend, "EURUSD"),
+            _ => (&scalper, "GBPUSD"),
+        };
+        let trade = TradeBuilder::new(robot)
+            .symbol(symbol)
+            .created_at(start + Duration::minutes(minute))
+            .create(app.pool())
+            .await;
+        if robot.id == trend.id && symbol == "GBPUSD" {
+            expected.push(trade.id.to_string());
+        }
+    }
+    expected.reverse();
+    let client = app.client_as(&user);
+
+    let query = format!("limit=2&symbols=GBPUSD&robot_ids={}", trend.id);
+    let first = client.get(&format!("/api/v1/trades?{}", query)).await.expect(StatusCode::OK);
+    assert_eq!(follow_pages(&client, &query, first).await, expected);
+
+    // A cursor from one filter carries on in another: it only marks a point in the list
+    let first = client.get("/api/v1/trades?limit=2").await.expect(StatusCode::OK);
+    let after = first["after"].as_str().unwrap().to_string();
+    let gbp = client.get(&format!("/api/v1/trades?symbols=GBPUSD&after={}", after)).await.expect(StatusCode::OK);
+    assert_eq!(trade_ids(&gbp).len(), 5);
+
+    let mut tampered = after.clone();
+    tampered.replace_range(0..1, if after.starts_with('0') { "1" } else { "0" });
+    for bad in [format!("after={}", tampered), format!("after={}&offset=2", after), "after=abc".to_string()] {
+        let response = client.get(&format!("/api/v1/trades?{}", bad)).await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST, "{}", bad);
+    }
+}
+
+#[sqlx::test]
+async fn test_dashboard_recent_trades_continue_in_the_list(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().plan("pro").create(app.pool()).await;
+    let robot = RobotBuilder::new(&user).create(app.pool()).await;
+    let start = Utc::now() - Duration::hours(1);
+    for minute in 0..12 {
+        TradeBuilder::new(&robot).created_at(start + Duration::minutes(minute)).create(app.pool()).await;
+    }
+    let client = app.client_as(&user);
+
+    let dashboard = client.get("/api/v1/dashboard").await.expect(StatusCode::OK);
+    assert_eq!(dashboard["recent_trades"].as_array().unwrap().len(), 10);
+    let after = dashboard["recent_trades_after"].as_str().unwrap();
+    let rest = client.get(&format!("/api/v1/trades?after={}", after)).await.expect(StatusCode::OK);
+    let listed = client.get("/api/v1/trades?limit=100").await.expect(StatusCode::OK);
+    assert_eq!(trade_ids(&rest), trade_ids(&listed)[10..].to_vec());
+}
+
+#[sqlx::test]
+async fn test_trade_traces_back_to_its_webhook_payload_until_pruned(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let owner = UserBuilder::new().plan("pro").create(app.pool()).await;
+    let admin = UserBuilder::new().admin().create(app.pool()).await;
+    let robot = RobotBuilder::new(&owner).create(app.pool()).await;
+    let trade = TradeBuilder::new(&robot).create(app.pool()).await;
+    let recent = TradeBuilder::new(&robot).create(app.pool()).await;
+
+    // The alert as a webhook handler receives it
+    let alert = json!({ "ticker": "EURUSD", "strategy": { "order_action": "buy", "order_contracts": 0.1 }, "time": "2024-03-04T12:00:00Z" });
+    let mut headers = HeaderMap::new();
+    headers.insert("authorization", HeaderValue::from_static("Bearer alert-secret"));
+    headers.insert("content-type", HeaderValue::from_static("application/json"));
+    headers.insert("x-forwarded-for", HeaderValue::from_static("52.89.214.238, 10.0.0.4"));
+    let received_at = Utc::now() - Duration::days(100);
+    for (trade_id, received_at) in [(trade.id, received_at), (recent.id, Utc::now())] {
+        let origin = TradeOrigin::new(trade_id, ORIGIN_WEBHOOK, Some("alert-7731".to_string()), received_at).with_payload(
+            trade_origins::capture(&headers, &alert, trade_origins::MAX_ORIGIN_PAYLOAD_BYTES),
+            trade_origins::source_ip(&headers),
+        );
+        TradeOrigin::record(app.pool(), &origin).await.unwrap();
+    }
+
+    let path = format!("/api/v1/trades/{}/origin", trade.id);
+    let origin = app.client_as(&owner).get(&path).await.expect(StatusCode::OK);
+    assert_eq!((origin["origin_type"].as_str(), origin["reference"].as_str()), (Some("webhook"), Some("alert-7731")));
+    assert_eq!(origin["payload"]["body"], alert);
+    assert_eq!(origin["payload"]["headers"], json!({ "content-type": "application/json", "x-forwarded-for": "52.89.214.238, 10.0.0.4" }));
+    assert_eq!(origin["source_ip"], "52.89.214.238");
+
+    let other = UserBuilder::new().plan("pro").create(app.pool()).await;
+    assert_eq!(app.client_as(&other).get(&path).await.status, StatusCode::NOT_FOUND);
+    let admin_path = format!("/api/v1/admin/trades/{}/origin", trade.id);
+    assert_eq!(app.client_as(&admin).get(&admin_path).await.expect(StatusCode::OK), origin);
+
+    // Retention clears the payload and keeps what caused the trade
+    let now = Utc::now();
+    assert_eq!(TradeOrigin::prune_payloads(app.pool(), now - Duration::days(90), now).await.unwrap(), 1);
+    let stub = app.client_as(&owner).get(&path).await.expect(StatusCode::OK);
+    assert_eq!((stub["origin_type"].as_str(), stub["reference"].as_str()), (Some("webhook"), Some("alert-7731")));
+    assert!(stub["payload"].is_null() && stub["source_ip"].is_null());
+    assert!(stub["payload_pruned_at"].is_string());
+    let kept = TradeOrigin::find(app.pool(), recent.id).await.unwrap().unwrap();
+    assert_eq!(kept.payload.unwrap()["body"], alert);
+}
+
+#[sqlx::test]
+async fn test_statistics_from_daily_facts_match_the_trades(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().plan("elite").create(app.pool()).await;
+    let robot = RobotBuilder::new(&user).create(app.pool()).await;
+    let today = Utc::now().date_naive().and_hms_opt(0, 0, 0).unwrap().and_utc();
+    for (days, hours, profit) in [(5, 2, 40.0), (5, 23, -15.0), (3, 12, 25.5), (1, 6, -10.0), (1, 18, 12.0)] {
+        TradeBuilder::new(&robot)
+            .closed(1.1050, profit)
+            .closed_at(today - Duration::days(days) + Duration::hours(hours))
+            .create(app.pool())
+            .await;
+    }
+    let open = TradeBuilder::new(&robot).symbol("GBPUSD").create(app.pool()).await;
+    assert!(Trade::close_open_trade(app.pool(), open.id, user.id, 1.2650, 7.0).await.unwrap());
+
+    // The first and last days of this window are only partially covered
+    let from = today - Duration::days(5) + Duration::hours(12);
+    let to = today - Duration::days(1) + Duration::hours(12);
+    let partial = TradeFilter { from: Some(from), to: Some(to), ..Default::default() };
+    let statistics = Trade::get_filtered_statistics(app.pool(), user.id, &partial, None).await.unwrap();
+    assert_eq!((statistics.total_trades, statistics.winning_trades), (3, 1));
+    assert!((statistics.total_profit - 0.5).abs() < 1e-9);
+
+    let windows = [
+        TradeFilter::default(),
+        partial,
+        TradeFilter { from: Some(today - Duration::days(3)), ..Default::default() },
+        TradeFilter { symbols: vec!["GBPUSD".to_string()], ..Default::default() },
+    ];
+    for filter in &windows {
+        let facts = TradeDailyFact::closed_totals(app.pool(), user.id, filter, Utc::now()).await.unwrap();
+        let raw = TradeDailyFact::closed_totals_from_trades(app.pool(), user.id, filter, Utc::now(), None).await.unwrap();
+        assert_eq!((facts.trades, facts.wins), (raw.trades, raw.wins), "{:?}", filter);
+        assert!((facts.net_profit - raw.net_profit).abs() < 1e-9, "{:?}", filter);
+    }
+
+    // Sparklines group by the day a trade closed
+    let days: Vec<(i64, f64)> = Trade::get_daily_summaries(app.pool(), user.id, today - Duration::days(6), None)
+        .await
+        .unwrap()
+        .iter()
+        .map(|day| (day.trades, day.profit))
+        .collect();
+    assert_eq!(days, vec![(2, 25.0), (1, 25.5), (2, 2.0), (1, 7.0)]);
+}
+
+#[sqlx::test]
+async fn test_backfill_fills_the_facts_of_existing_trades(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().plan("pro").create(app.pool()).await;
+    let robot = RobotBuilder::new(&user).create(app.pool()).await;
+    TradeBuilder::new(&robot).closed(1.1050, 50.0).create(app.pool()).await;
+    TradeBuilder::new(&robot).closed(1.0980, -20.0).create(app.pool()).await;
+
+    // As deployed: trades closed before the table existed, backfill not run yet
+    sqlx::query("DELETE FROM trade_daily_facts").execute(app.pool()).await.unwrap();
+    sqlx::query("UPDATE trade_facts_backfill SET last_user_id = NULL, completed_at = NULL").execute(app.pool()).await.unwrap();
+
+    let statistics = app.client_as(&user).get("/api/v1/trades/statistics").await.expect(StatusCode::OK);
+    assert_eq!((statistics["total_trades"].as_i64(), statistics["winning_trades"].as_i64()), (Some(2), Some(1)));
+
+    assert!(TradeFactsBackfill::run(app.pool()).await.unwrap() >= 1);
+    assert!(TradeDailyFact::is_ready(app.pool()).await.unwrap());
+    assert_eq!(TradeFactsBackfill::run(app.pool()).await.unwrap(), 0);
+
+    let facts = TradeDailyFact::find_by_robot_ids(app.pool(), &[robot.id]).await.unwrap();
+    assert_eq!(facts.iter().map(|fact| fact.trades).sum::<i32>(), 2);
+    let statistics = app.client_as(&user).get("/api/v1/trades/statistics").await.expect(StatusCode::OK);
+    assert_eq!((statistics["total_trades"].as_i64(), statistics["total_profit"].as_f64()), (Some(2), Some(30.0)));
+}
+
+#[sqlx::test]
+async fn test_as_of_reports_ignore_later_corrections_and_trades(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().plan("elite").create(app.pool()).await;
+    let robot = RobotBuilder::new(&user).create(app.pool()).await;
+    let disputed = TradeBuilder::new(&robot).closed(1.1050, 50.0).create(app.pool()).await;
+    TradeBuilder::new(&robot).closed(1.0980, -20.0).create(app.pool()).await;
+
+    let as_of = Utc::now();
+    tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    // Afterwards support corrects the disputed profit, and an import brings in an older trade
+    let amendment = TradeAmendment { profit_loss: Some(35.0), ..Default::default() };
+    TradeCorrection::record(app.pool(), disputed.id, &amendment, "support").await.unwrap().unwrap();
+    TradeBuilder::new(&robot).closed(1.1010, 10.0).closed_at(as_of - Duration::days(2)).create(app.pool()).await;
+
+    let client = app.client_as(&user);
+    let as_of = as_of.to_rfc3339_opts(SecondsFormat::Micros, true);
+    let current = client.get("/api/v1/trades/statistics").await.expect(StatusCode::OK);
+    assert_eq!((current["total_trades"].as_i64(), current["total_profit"].as_f64()), (Some(3), Some(25.0)));
+    let then = client.get(&format!("/api/v1/trades/statistics?as_of={}", as_of)).await.expect(StatusCode::OK);
+    assert_eq!((then["total_trades"].as_i64(), then["total_profit"].as_f64()), (Some(2), Some(30.0)));
+    // Asking again later gives the same answer
+    let again = client.get(&format!("/api/v1/trades/statistics?as_of={}", as_of)).await.expect(StatusCode::OK);
+    assert_eq!(again, then);
+
+    let total = |sparklines: &serde_json::Value| sparklines["profit"].as_array().unwrap().iter().map(|p| p.as_f64().unwrap()).sum::<f64>();
+    let current = client.get("/api/v1/dashboard/sparklines").await.expect(StatusCode::OK);
+    assert_eq!(total(&current), 25.0);
+    let then = client.get(&format!("/api/v1/dashboard/sparklines?as_of={}", as_of)).await.expect(StatusCode::OK);
+    assert_eq!(total(&then), 30.0);
+
+    let future = (Utc::now() + Duration::days(1)).to_rfc3339_opts(SecondsFormat::Secs, true);
+    let response = client.get(&format!("/api/v1/trades/statistics?as_of={}", future)).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+}
+
+#[sqlx::test]
+async fn test_a_fill_after_the_close_is_recorded_as_a_correction(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().create(app.pool()).await;
+    let robot = RobotBuilder::new(&user).create(app.pool()).await;
+    let trade = TradeBuilder::new(&robot).closed(1.1050, 50.0).create(app.pool()).await;
+
+    assert!(Trade::record_fill(app.pool(), trade.id, 1.1003).await.unwrap());
+
+    // The trade keeps what it was closed with; the fill is a correction on top
+    let stored = Trade::find_by_id(app.pool(), trade.id, user.id).await.unwrap().unwrap();
+    assert_eq!(stored.entry_price, 1.1000);
+    let corrections = TradeCorrection::find_by_trade_id(app.pool(), trade.id).await.unwrap();
+    assert_eq!(corrections.len(), 1);
+    assert_eq!((corrections[0].entry_price, corrections[0].profit_loss), (1.1003, Some(50.0)));
+    assert_eq!(corrections[0].reason, CORRECTION_LATE_FILL);
+
+    // Corrections are never edited in place
+    let edit = sqlx::query("UPDATE trade_corrections SET profit_loss = 0").execute(app.pool()).await;
+    assert!(edit.is_err());
+}
+
+// Timing comparison on a large history; slow, run with `cargo test -- --ignored`
+#[sqlx::test]
+#[ignore]
+async fn test_statistics_from_daily_facts_beat_the_trades_table(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().plan("elite").create(app.pool()).await;
+    let robot = RobotBuilder::new(&user).create(app.pool()).await;
+    sqlx::query(
+        r#"
+        INSERT INTO trades (id, user_id, robot_id, symbol, trade_type, volume, entry_price, exit_price, status, profit_loss, opened_at, closed_at, created_via)
+        SELECT uuid_generate_v4(), $1, $2, (ARRAY['EURUSD', 'GBPUSD', 'USDJPY'])[1 + n % 3], 'buy', 0.1, 1.1, 1.1, 'closed',
+            (n % 7) - 3, NOW() - (n * 5 + 3) * INTERVAL '1 minute', NOW() - n * 5 * INTERVAL '1 minute', 'test'
+        FROM generate_series(1, 100000) n
+        "#,
+    )
+    .bind(user.id)
+    .bind(robot.id)
+    .execute(app.pool())
+    .await
+    .unwrap();
+    TradeDailyFact::rebuild_users(app.pool(), &[user.id]).await.unwrap();
+    sqlx::query("ANALYZE trades").execute(app.pool()).await.unwrap();
+    sqlx::query("ANALYZE trade_daily_facts").execute(app.pool()).await.unwrap();
+
+    let filter = TradeFilter { from: Some(Utc::now() - Duration::days(300)), ..Default::default() };
+    let now = Utc::now();
+    let (mut raw_time, mut facts_time) = (std::time::Duration::ZERO, std::time::Duration::ZERO);
+    for _ in 0..5 {
+        let started = Instant::now();
+        let raw = TradeDailyFact::closed_totals_from_trades(app.pool(), user.id, &filter, now, None).await.unwrap();
+        raw_time += started.elapsed();
+
+        let started = Instant::now();
+        let facts = TradeDailyFact::closed_totals(app.pool(), user.id, &filter, now).await.unwrap();
+        facts_time += started.elapsed();
+
+        assert_eq!((facts.trades, facts.wins), (raw.trades, raw.wins));
+        assert!((facts.net_profit - raw.net_profit).abs() < 1e-6);
+    }
+    println!("statistics over 100k trades: trades table {:?}, daily facts {:?}", raw_time / 5, facts_time / 5);
+    assert!(facts_time * 3 < raw_time, "facts {:?} vs trades {:?}", facts_time, raw_time);
+}
@@ -0,0 +1,166 @@
+use axum::http::{Method, StatusCode};
+use serde_json::json;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use chrono::{Duration, Utc};
+use trading_saas_backend::{
+    models::{AuditEntry, BrokerConnection, TradingRobot, User},
+    services::DeletionPurge,
+};
+
+use crate::common::{BrokerBuilder, RobotBuilder, TestApp, TradeBuilder, UserBuilder, TEST_PASSWORD};
+
+#[sqlx::test]
+async fn test_account_deletion_anonymizes_the_user_and_keeps_trades(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().create(app.pool()).await;
+    let connection = BrokerBuilder::new(&user).create(&app).await;
+    let robot = RobotBuilder::new(&user).connection(&connection).create(app.pool()).await;
+    let closed = TradeBuilder::new(&robot).closed(1.1, 25.0).create(app.pool()).await;
+    let open = TradeBuilder::new(&robot).create(app.pool()).await;
+    sqlx::query("UPDATE trading_robots SET status = 'active' WHERE id = $1").bind(robot.id).execute(app.pool()).await.unwrap();
+    let client = app.client_as(&user);
+    let delete = |password: &str| client.send(Method::DELETE, "/api/v1/users/me", Some(json!({ "password": password })));
+
+    assert_eq!(delete("not-the-password").await.status, StatusCode::BAD_REQUEST);
+    let blocked = delete(TEST_PASSWORD).await;
+    assert_eq!(blocked.status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(blocked.body["error"].as_str().unwrap().contains("1 running robot(s), 1 open trade(s)"));
+
+    client.post(&format!("/api/v1/robots/{}/stop", robot.id), json!({})).await.expect(StatusCode::OK);
+    sqlx::query("UPDATE trades SET status = 'closed', exit_price = 1.2, closed_at = NOW() WHERE id = $1")
+        .bind(open.id)
+        .execute(app.pool())
+        .await
+        .unwrap();
+    delete(TEST_PASSWORD).await.expect(StatusCode::NO_CONTENT);
+
+    assert!(User::find_by_id(app.pool(), user.id).await.unwrap().is_none());
+    let stored = User::find_by_id_including_deleted(app.pool(), user.id).await.unwrap().unwrap();
+    assert_eq!(stored.email, User::deleted_email(user.id));
+    assert!(!stored.is_active && stored.password_hash.is_empty());
+    assert!(BrokerConnection::find_by_id(app.pool(), connection.id, user.id).await.unwrap().is_none());
+    let kept: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM trades WHERE user_id = $1 AND id = ANY($2)")
+        .bind(user.id)
+        .bind(vec![closed.id, open.id])
+        .fetch_one(app.pool())
+        .await
+        .unwrap();
+    assert_eq!(kept, 2);
+    let audited = AuditEntry::find_by_target(app.pool(), "user", user.id).await.unwrap();
+    assert_eq!((audited[0].action.as_str(), audited[0].actor_type.as_str()), ("user.delete", "user"));
+    assert_eq!(audited[0].details["broker_connections_deleted"], 1);
+
+    // The purge leaves an account that has trades, and the trades with it
+    let purged = DeletionPurge::purge(app.pool(), Utc::now() + Duration::seconds(1)).await.unwrap();
+    assert_eq!(purged.users, 0);
+    let kept: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM trades WHERE user_id = $1").bind(user.id).fetch_one(app.pool()).await.unwrap();
+    assert_eq!(kept, 2);
+
+    // Nobody can sign in as the account again, with the old address or an empty password
+    assert_eq!(client.get("/api/v1/auth/me").await.status, StatusCode::UNAUTHORIZED);
+    for (email, password) in [(user.email.as_str(), TEST_PASSWORD), (stored.email.as_str(), "")] {
+        let login = app.anonymous().post("/api/v1/auth/login", json!({ "email": email, "password": password })).await;
+        assert_eq!(login.status, StatusCode::UNAUTHORIZED);
+    }
+}
+
+#[sqlx::test]
+async fn test_deleted_robots_and_connections_stay_hidden_until_purged(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let user = UserBuilder::new().plan("pro").create(app.pool()).await;
+    let connection = BrokerBuilder::new(&user).create(&app).await;
+    let robot = RobotBuilder::new(&user).connection(&connection).create(app.pool()).await;
+    let trade = TradeBuilder::new(&robot).create(app.pool()).await;
+    let unused = RobotBuilder::new(&user).create(app.pool()).await;
+    sqlx::query("UPDATE trading_robots SET status = 'active' WHERE id = $1").bind(robot.id).execute(app.pool()).await.unwrap();
+    let client = app.client_as(&user);
+    let robot_path = format!("/api/v1/robots/{}", robot.id);
+    let broker_path = format!("/api/v1/brokers/{}", connection.id);
+
+    // Running robots and open trades keep both in place
+    assert_eq!(client.delete(&broker_path).await.status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(client.delete(&robot_path).await.status, StatusCode::UNPROCESSABLE_ENTITY);
+    client.post(&format!("{}/stop", robot_path), json!({})).await.expect(StatusCode::OK);
+    let open = client.delete(&robot_path).await;
+    assert_eq!(open.status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(open.body["error"].as_str().unwrap().contains("1 open trade(s)"));
+
+    // Once no robot runs on it the connection goes, and the robot is left without one
+    client.delete(&broker_path).await.expect(StatusCode::NO_CONTENT);
+    assert_eq!(client.get("/api/v1/brokers").await.expect(StatusCode::OK), json!([]));
+    let stored = TradingRobot::find_by_id(app.pool(), robot.id, user.id).await.unwrap().unwrap();
+    assert_eq!(stored.broker_connection_id, None);
+    let erased: (String, bool) = sqlx::query_as("SELECT api_key, deleted_at IS NOT NULL FROM broker_connections WHERE id = $1")
+        .bind(connection.id)
+        .fetch_one(app.pool())
+        .await
+        .unwrap();
+    assert_eq!(erased, (String::new(), true));
+    // The same account can be connected again
+    BrokerBuilder::new(&user).create(&app).await;
+
+    sqlx::query("UPDATE trades SET status = 'closed', exit_price = 1.2, closed_at = NOW() WHERE id = $1")
+        .bind(trade.id)
+        .execute(app.pool())
+        .await
+        .unwrap();
+    client.delete(&robot_path).await.expect(StatusCode::NO_CONTENT);
+    client.delete(&format!("/api/v1/robots/{}", unused.id)).await.expect(StatusCode::NO_CONTENT);
+    assert_eq!(client.delete(&robot_path).await.status, StatusCode::NOT_FOUND);
+    assert_eq!(TradingRobot::owner_id(app.pool(), robot.id).await.unwrap(), None);
+    assert!(TradingRobot::existing_ids(app.pool(), user.id, &[robot.id]).await.unwrap().is_empty());
+    assert_eq!(client.get("/api/v1/robots").await.expect(StatusCode::OK), json!([]));
+    assert_eq!(client.get("/api/v1/trades").await.expect(StatusCode::OK)["trades"].as_array().unwrap().len(), 1);
+
+    // Nothing is purged inside the retention window; past it, only the robot without trades goes
+    let purged = DeletionPurge::purge(app.pool(), Utc::now() - Duration::days(1)).await.unwrap();
+    assert_eq!(purged.total(), 0);
+    let purged = DeletionPurge::purge(app.pool(), Utc::now() + Duration::seconds(1)).await.unwrap();
+    assert_eq!((purged.robots, purged.broker_connections, purged.users), (1, 1, 0));
+    let robots: Vec<Uuid> = sqlx::query_scalar("SELECT id FROM trading_robots WHERE user_id = $1")
+        .bind(user.id)
+        .fetch_all(app.pool())
+        .await
+        .unwrap();
+    assert_eq!(robots, vec![robot.id]);
+    assert_eq!(client.get("/api/v1/trades").await.expect(StatusCode::OK)["trades"].as_array().unwrap().len(), 1);
+}
+
+#[sqlx::test]
+async fn test_profile_is_filled_from_google_and_edited_by_the_user(pool: PgPool) {
+    let app = TestApp::new(pool).await;
+    let picture = "https://lh3.googleusercontent.com/a/ana";
+    let google = |name: &'static str| {
+        app.google_token("google-ana", "ana@example.com", move |info| {
+            info.name = Some(name.to_string());
+            info.picture = Some(picture.to_string());
+        })
+    };
+    let login = app.anonymous().post("/api/v1/auth/google", json!({ "token": google("Ana Souza") })).await.expect(StatusCode::OK);
+    assert_eq!((login["user"]["full_name"].as_str(), login["user"]["avatar_url"].as_str()), (Some("Ana Souza"), Some(picture)));
+    assert!(login["user"]["timezone"].is_null());
+
+    let client = app.with_token(login["token"].as_str().unwrap());
+    let updated = client
+        .patch("/api/v1/users/me", json!({ "full_name": "Ana S.", "timezone": "America/Sao_Paulo", "locale": "pt-BR" }))
+        .await
+        .expect(StatusCode::OK);
+    assert_eq!(
+        (updated["full_name"].as_str(), updated["avatar_url"].as_str(), updated["timezone"].as_str(), updated["locale"].as_str()),
+        (Some("Ana S."), Some(picture), Some("America/Sao_Paulo"), Some("pt-BR"))
+    );
+
+    // Invalid values change nothing; an empty string clears the avatar
+    for refused in [json!({ "full_name": "Ana", "timezone": "Brasilia" }), json!({ "locale": "brazilian portuguese" })] {
+        assert_eq!(client.patch("/api/v1/users/me", refused).await.status, StatusCode::BAD_REQUEST);
+    }
+    client.patch("/api/v1/users/me", json!({ "avatar_url": "" })).await.expect(StatusCode::OK);
+
+    // A later sign-in fills in the cleared avatar again but keeps the name the user chose
+    let login = app.anonymous().post("/api/v1/auth/google", json!({ "token": google("Ana Souza") })).await.expect(StatusCode::OK);
+    assert_eq!((login["user"]["full_name"].as_str(), login["user"]["avatar_url"].as_str()), (Some("Ana S."), Some(picture)));
+    let me = client.get("/api/v1/auth/me").await.expect(StatusCode::OK);
+    assert_eq!((me["timezone"].as_str(), me["locale"].as_str()), (Some("America/Sao_Paulo"), Some("pt-BR")));
+}